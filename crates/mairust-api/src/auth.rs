@@ -1,16 +1,16 @@
 //! Authentication module
 
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
     extract::{Request, State},
     http::StatusCode,
     middleware::Next,
     response::Response,
 };
+use mairust_common::config::{ReplicationConfig, TrackingConfig};
 use mairust_common::types::{TenantId, UserId};
-use mairust_storage::repository::api_keys::ApiKey;
-use mairust_storage::{ApiKeyRepository, ApiKeyRepositoryTrait, DatabasePool};
-use sha2::{Digest, Sha256};
+use mairust_core::SessionRegistry;
+use mairust_storage::repository::api_keys::{key_prefix, ApiKey};
+use mairust_storage::{ApiKeyRepository, ApiKeyRepositoryTrait, DatabasePool, FileStorage};
 use std::sync::Arc;
 use tracing::{debug, error, warn};
 use uuid::Uuid;
@@ -19,6 +19,16 @@ use uuid::Uuid;
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: DatabasePool,
+    /// Message file storage of this server
+    pub file_storage: Arc<dyn FileStorage>,
+    /// Live protocol sessions of this process
+    pub sessions: SessionRegistry,
+    /// Warm standby replication of this server
+    pub replication: ReplicationConfig,
+    /// Click tracking of campaign mail
+    pub tracking: TrackingConfig,
+    /// Host name the CNAME of tracking domains must point at
+    pub tracking_cname_target: String,
 }
 
 /// Authenticated context extracted from API key
@@ -67,45 +77,9 @@ pub fn extract_api_key(req: &Request) -> Option<&str> {
     None
 }
 
-/// Extract the prefix from an API key (first 8 characters)
-fn extract_key_prefix(api_key: &str) -> Option<&str> {
-    if api_key.len() >= 8 {
-        Some(&api_key[..8])
-    } else {
-        None
-    }
-}
-
-/// Hash an API key for comparison
-fn hash_api_key(api_key: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(api_key.as_bytes());
-    let result = hasher.finalize();
-    hex::encode(result)
-}
-
-/// Verify an API key against a stored hash.
-///
-/// Supports both modern Argon2 hashes (`$argon2...`) and legacy SHA-256 hex hashes
-/// for backward compatibility during migration.
-fn verify_api_key(api_key: &str, stored_hash: &str) -> bool {
-    if stored_hash.starts_with("$argon2") {
-        return PasswordHash::new(stored_hash)
-            .ok()
-            .and_then(|parsed_hash| {
-                Argon2::default()
-                    .verify_password(api_key.as_bytes(), &parsed_hash)
-                    .ok()
-            })
-            .is_some();
-    }
-
-    hash_api_key(api_key) == stored_hash
-}
-
 /// Validate an API key against the database
 async fn validate_api_key(db_pool: &DatabasePool, api_key: &str) -> Result<ApiKey, StatusCode> {
-    let prefix = extract_key_prefix(api_key).ok_or_else(|| {
+    let prefix = key_prefix(api_key).ok_or_else(|| {
         warn!("API key too short");
         StatusCode::UNAUTHORIZED
     })?;
@@ -124,7 +98,7 @@ async fn validate_api_key(db_pool: &DatabasePool, api_key: &str) -> Result<ApiKe
     }
 
     for candidate in candidates {
-        if verify_api_key(api_key, &candidate.key_hash) {
+        if candidate.verify(api_key) {
             // Check expiration
             if candidate.is_expired() {
                 warn!("API key {} has expired", candidate.id);
@@ -152,38 +126,6 @@ async fn validate_api_key(db_pool: &DatabasePool, api_key: &str) -> Result<ApiKe
     Err(StatusCode::UNAUTHORIZED)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::verify_api_key;
-    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
-    use argon2::Argon2;
-    use sha2::{Digest, Sha256};
-
-    #[test]
-    fn verifies_legacy_sha256_hash() {
-        let api_key = "mk_test_legacy_key";
-        let mut hasher = Sha256::new();
-        hasher.update(api_key.as_bytes());
-        let legacy_hash = hex::encode(hasher.finalize());
-
-        assert!(verify_api_key(api_key, &legacy_hash));
-        assert!(!verify_api_key("wrong_key", &legacy_hash));
-    }
-
-    #[test]
-    fn verifies_argon2_hash() {
-        let api_key = "mk_test_argon2_key";
-        let salt = SaltString::generate(&mut OsRng);
-        let hash = Argon2::default()
-            .hash_password(api_key.as_bytes(), &salt)
-            .expect("argon2 hash generation should succeed")
-            .to_string();
-
-        assert!(verify_api_key(api_key, &hash));
-        assert!(!verify_api_key("wrong_key", &hash));
-    }
-}
-
 /// Authentication middleware
 pub async fn auth_middleware(
     State(state): State<Arc<AppState>>,
@@ -0,0 +1,79 @@
+//! API error responses
+//!
+//! Handlers return [`ApiError`], built from the shared [`Error`], so every
+//! error reaches clients with the same JSON body: a machine-readable code,
+//! a message and the context fields of the error.
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mairust_common::Error;
+
+/// Error returned by API handlers
+#[derive(Debug)]
+pub struct ApiError {
+    error: Error,
+    status: StatusCode,
+}
+
+/// Result type of API handlers
+pub type ApiResult<T> = Result<T, ApiError>;
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        let status = StatusCode::from_u16(error.status_code())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        Self { error, status }
+    }
+}
+
+/// Bare status codes, as returned by the access checks in [`crate::auth`]
+///
+/// The status is kept as it is; the error only gives the body.
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        let message = status.canonical_reason().unwrap_or("Error").to_string();
+        let error = match status {
+            StatusCode::UNAUTHORIZED => Error::Auth(message),
+            StatusCode::FORBIDDEN => Error::PermissionDenied(message),
+            StatusCode::NOT_FOUND => Error::NotFound(message),
+            StatusCode::CONFLICT => Error::Conflict(message),
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimitExceeded,
+            StatusCode::SERVICE_UNAVAILABLE => Error::Unavailable(message),
+            status if status.is_client_error() => Error::Validation(message),
+            _ => Error::Internal(message),
+        };
+        Self { error, status }
+    }
+}
+
+impl ApiError {
+    /// Answer with `status` instead of the status of the error's kind, for
+    /// endpoints documented with a more specific status (e.g. 413)
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// HTTP status of the response
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let body = self.error.to_response();
+        let retry_after = body.context.retry_after_secs;
+        let mut response = (status, Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
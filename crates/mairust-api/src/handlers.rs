@@ -2,18 +2,32 @@
 
 pub mod admin;
 pub mod campaigns;
+pub mod config_bundles;
+pub mod contacts;
+pub mod dkim_keys;
 pub mod domains;
 pub mod domain_aliases;
+pub mod domain_reports;
 pub mod domain_settings;
 pub mod health;
 pub mod hooks;
+pub mod inbound_routes;
+pub mod mailbox_filters;
+pub mod mailbox_provisioning;
 pub mod mailboxes;
+pub mod message_shares;
 pub mod messages;
+pub mod metrics;
 pub mod policies;
+pub mod protected_names;
+pub mod quarantine;
 pub mod recipient_lists;
 pub mod search;
+pub mod secure_messages;
 pub mod send;
+pub mod spam_rules;
 pub mod tenants;
+pub mod tracking_domains;
 pub mod users;
 
 pub use health::*;
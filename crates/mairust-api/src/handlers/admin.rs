@@ -5,16 +5,43 @@
 
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     Extension, Json,
 };
 use chrono::{DateTime, Duration, Utc};
+use mairust_common::Error;
+use mairust_core::journal::{retry_failed_exports, JOURNAL_QUEUE};
+use mairust_core::replication::{checksum, CHECKSUM_HEADER};
+use mairust_core::reprocess::{
+    MessageReprocessor, ReprocessOptions, ReprocessSelection, ReprocessStep, ReprocessSummary,
+    MAX_BATCH_SIZE as MAX_REPROCESS_BATCH,
+};
+use mairust_core::spam::{normalize_domain, normalize_network, ReputationScore};
+use mairust_core::{
+    HookManager, MaintenanceStatus, Replication, ReplicationStatus, SessionFilter, SessionInfo,
+};
+use mairust_storage::db::{db_error, storage_error};
+use mairust_storage::repository::bad_domains::{BadDomain, CreateBadDomain};
+use mairust_storage::repository::dnsbl_allowlist::{
+    CreateDnsblAllowlistEntry, DnsblAllowlistEntry,
+};
+use mairust_storage::repository::maintenance::{CreateMaintenanceWindow, MaintenanceWindow};
+use mairust_storage::repository::JobRepository;
+use mairust_storage::{
+    AuditLogRepository, BadDomainRepository, DnsblAllowlistRepository, MaintenanceRepository,
+    NewAuditLog, ReplicationRepository, SenderReputationRepository,
+};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::auth::{require_scope, require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::search::create_indexer;
+use crate::handlers::messages::read_message;
+use crate::handlers::send::{get_queue_stats, QueueStatusResponse};
 
 // ============================================================================
 // System Statistics
@@ -38,7 +65,7 @@ pub struct SystemStatsResponse {
 pub async fn get_system_stats(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
-) -> Result<Json<SystemStatsResponse>, StatusCode> {
+) -> ApiResult<Json<SystemStatsResponse>> {
     require_scope(&auth, "admin:system")?;
 
     // Query aggregate statistics
@@ -49,7 +76,7 @@ pub async fn get_system_stats(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            db_error(e).or_internal("Failed to get system stats")
         })?;
 
     let active_tenant_count: (i64,) =
@@ -58,7 +85,7 @@ pub async fn get_system_stats(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get system stats")
             })?;
 
     let user_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users")
@@ -66,7 +93,7 @@ pub async fn get_system_stats(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            db_error(e).or_internal("Failed to get system stats")
         })?;
 
     let mailbox_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM mailboxes")
@@ -74,7 +101,7 @@ pub async fn get_system_stats(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            db_error(e).or_internal("Failed to get system stats")
         })?;
 
     let message_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages")
@@ -82,7 +109,7 @@ pub async fn get_system_stats(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            db_error(e).or_internal("Failed to get system stats")
         })?;
 
     let storage_sum: (Option<i64>,) =
@@ -91,7 +118,7 @@ pub async fn get_system_stats(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get system stats")
             })?;
 
     let now = Utc::now();
@@ -105,7 +132,7 @@ pub async fn get_system_stats(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get system stats")
             })?;
 
     let messages_7d: (i64,) =
@@ -115,7 +142,7 @@ pub async fn get_system_stats(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get system stats")
             })?;
 
     Ok(Json(SystemStatsResponse {
@@ -131,6 +158,59 @@ pub async fn get_system_stats(
     }))
 }
 
+/// Get delivery queue status across all tenants (super admin only)
+pub async fn get_system_queue_status(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<QueueStatusResponse>> {
+    require_scope(&auth, "admin:system")?;
+
+    let stats = get_queue_stats(&state.db_pool, None).await.map_err(|e| {
+        error!("Database error while getting queue stats: {}", e);
+        storage_error(e).or_internal("Failed to get queue stats")
+    })?;
+
+    Ok(Json(stats))
+}
+
+/// Get the backlog of journal reports awaiting export (super admin only)
+pub async fn get_journal_status(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<QueueStatusResponse>> {
+    require_scope(&auth, "admin:system")?;
+
+    let stats = JobRepository::new(state.db_pool.clone())
+        .queue_stats(JOURNAL_QUEUE, None)
+        .await
+        .map_err(|e| {
+            error!("Database error while getting journal stats: {}", e);
+            storage_error(e).or_internal("Failed to get journal stats")
+        })?;
+
+    Ok(Json(stats.into()))
+}
+
+/// Requeue journal exports that ran out of attempts (super admin only)
+pub async fn retry_journal_exports(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_scope(&auth, "admin:system")?;
+
+    let requeued = retry_failed_exports(&state.db_pool).await.map_err(|e| {
+        error!("Database error while requeueing journal exports: {}", e);
+        storage_error(e).or_internal("Failed to requeue journal exports")
+    })?;
+
+    info!(
+        "{} journal exports requeued by API key {}",
+        requeued, auth.api_key_id
+    );
+
+    Ok(Json(serde_json::json!({ "requeued": requeued })))
+}
+
 // ============================================================================
 // Tenant Usage Report
 // ============================================================================
@@ -166,45 +246,43 @@ pub async fn get_tenant_usage(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<TenantUsageResponse>, StatusCode> {
+) -> ApiResult<Json<TenantUsageResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let pool = state.db_pool.pool();
 
     // Get tenant info
-    let tenant: Option<(String, String, serde_json::Value)> = sqlx::query_as(
-        "SELECT name, plan, settings FROM tenants WHERE id = $1",
-    )
-    .bind(tenant_id)
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let (tenant_name, plan, settings) = tenant.ok_or(StatusCode::NOT_FOUND)?;
-
-    // Get counts
-    let user_count: (i64,) =
-        sqlx::query_as("SELECT COUNT(*) FROM users WHERE tenant_id = $1")
+    let tenant: Option<(String, String, serde_json::Value)> =
+        sqlx::query_as("SELECT name, plan, settings FROM tenants WHERE id = $1")
             .bind(tenant_id)
-            .fetch_one(pool)
+            .fetch_optional(pool)
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get tenant usage")
             })?;
 
-    let domain_count: (i64,) =
-        sqlx::query_as("SELECT COUNT(*) FROM domains WHERE tenant_id = $1")
-            .bind(tenant_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| {
-                error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+    let (tenant_name, plan, settings) =
+        tenant.ok_or_else(|| Error::NotFound("Tenant not found".to_string()))?;
+
+    // Get counts
+    let user_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE tenant_id = $1")
+        .bind(tenant_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            db_error(e).or_internal("Failed to get tenant usage")
+        })?;
+
+    let domain_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM domains WHERE tenant_id = $1")
+        .bind(tenant_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            db_error(e).or_internal("Failed to get tenant usage")
+        })?;
 
     let mailbox_count: (i64,) =
         sqlx::query_as("SELECT COUNT(*) FROM mailboxes WHERE tenant_id = $1")
@@ -213,7 +291,7 @@ pub async fn get_tenant_usage(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get tenant usage")
             })?;
 
     let message_count: (i64,) =
@@ -223,7 +301,7 @@ pub async fn get_tenant_usage(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get tenant usage")
             })?;
 
     let storage_sum: (Option<i64>,) =
@@ -233,36 +311,34 @@ pub async fn get_tenant_usage(
             .await
             .map_err(|e| {
                 error!("Database error: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                db_error(e).or_internal("Failed to get tenant usage")
             })?;
 
     let now = Utc::now();
     let yesterday = now - Duration::hours(24);
     let last_week = now - Duration::days(7);
 
-    let messages_24h: (i64,) = sqlx::query_as(
-        "SELECT COUNT(*) FROM messages WHERE tenant_id = $1 AND received_at >= $2",
-    )
-    .bind(tenant_id)
-    .bind(yesterday)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let messages_24h: (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM messages WHERE tenant_id = $1 AND received_at >= $2")
+            .bind(tenant_id)
+            .bind(yesterday)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| {
+                error!("Database error: {}", e);
+                db_error(e).or_internal("Failed to get tenant usage")
+            })?;
 
-    let messages_7d: (i64,) = sqlx::query_as(
-        "SELECT COUNT(*) FROM messages WHERE tenant_id = $1 AND received_at >= $2",
-    )
-    .bind(tenant_id)
-    .bind(last_week)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let messages_7d: (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM messages WHERE tenant_id = $1 AND received_at >= $2")
+            .bind(tenant_id)
+            .bind(last_week)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| {
+                error!("Database error: {}", e);
+                db_error(e).or_internal("Failed to get tenant usage")
+            })?;
 
     // Parse limits from settings or use plan defaults
     let limits = parse_tenant_limits(&plan, &settings);
@@ -357,10 +433,12 @@ pub struct AuditLogQuery {
     pub actor_id: Option<String>,
     /// Filter by target type
     pub target_type: Option<String>,
+    /// Filter by target ID
+    pub target_id: Option<String>,
     /// Start date filter
-    pub from: Option<String>,
+    pub from: Option<DateTime<Utc>>,
     /// End date filter
-    pub to: Option<String>,
+    pub to: Option<DateTime<Utc>>,
     /// Pagination offset
     pub offset: Option<i64>,
     /// Pagination limit (max 100)
@@ -382,65 +460,70 @@ pub async fn list_audit_logs(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Query(query): Query<AuditLogQuery>,
-) -> Result<Json<AuditLogListResponse>, StatusCode> {
+) -> ApiResult<Json<AuditLogListResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let pool = state.db_pool.pool();
     let limit = query.limit.unwrap_or(50).min(100);
     let offset = query.offset.unwrap_or(0);
 
-    // Build query with filters
-    let mut sql = String::from(
-        "SELECT id, tenant_id, actor_type, actor_id, event_type, target_type, target_id, details, ip_address, created_at
-         FROM audit_logs WHERE tenant_id = $1"
-    );
-    let mut param_count = 1;
-
-    if query.event_type.is_some() {
-        param_count += 1;
-        sql.push_str(&format!(" AND event_type = ${}", param_count));
-    }
-
-    if query.actor_id.is_some() {
-        param_count += 1;
-        sql.push_str(&format!(" AND actor_id = ${}", param_count));
-    }
-
-    if query.target_type.is_some() {
-        param_count += 1;
-        sql.push_str(&format!(" AND target_type = ${}", param_count));
-    }
-
-    sql.push_str(" ORDER BY created_at DESC LIMIT $");
-    param_count += 1;
-    sql.push_str(&param_count.to_string());
-    sql.push_str(" OFFSET $");
-    param_count += 1;
-    sql.push_str(&param_count.to_string());
-
-    // For now, use simpler query
     let logs: Vec<AuditLogEntry> = sqlx::query_as(
-        "SELECT id, tenant_id, actor_type, actor_id, event_type, target_type, target_id, details, ip_address, created_at
-         FROM audit_logs WHERE tenant_id = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3"
+        r#"
+        SELECT id, tenant_id, actor_type, actor_id, event_type, target_type, target_id, details,
+               ip_address, created_at
+        FROM audit_logs
+        WHERE tenant_id = $1
+          AND ($2::TEXT IS NULL OR event_type = $2)
+          AND ($3::TEXT IS NULL OR actor_id = $3)
+          AND ($4::TEXT IS NULL OR target_type = $4)
+          AND ($5::TEXT IS NULL OR target_id = $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR created_at >= $6)
+          AND ($7::TIMESTAMPTZ IS NULL OR created_at < $7)
+        ORDER BY created_at DESC
+        LIMIT $8 OFFSET $9
+        "#,
     )
     .bind(tenant_id)
+    .bind(&query.event_type)
+    .bind(&query.actor_id)
+    .bind(&query.target_type)
+    .bind(&query.target_id)
+    .bind(query.from)
+    .bind(query.to)
     .bind(limit)
     .bind(offset)
     .fetch_all(pool)
     .await
     .map_err(|e| {
         error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        db_error(e).or_internal("Failed to list audit logs")
     })?;
 
-    let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM audit_logs WHERE tenant_id = $1")
-        .bind(tenant_id)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let total: (i64,) = sqlx::query_as(
+        r#"
+        SELECT COUNT(*) FROM audit_logs
+        WHERE tenant_id = $1
+          AND ($2::TEXT IS NULL OR event_type = $2)
+          AND ($3::TEXT IS NULL OR actor_id = $3)
+          AND ($4::TEXT IS NULL OR target_type = $4)
+          AND ($5::TEXT IS NULL OR target_id = $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR created_at >= $6)
+          AND ($7::TIMESTAMPTZ IS NULL OR created_at < $7)
+        "#,
+    )
+    .bind(tenant_id)
+    .bind(&query.event_type)
+    .bind(&query.actor_id)
+    .bind(&query.target_type)
+    .bind(&query.target_id)
+    .bind(query.from)
+    .bind(query.to)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        error!("Database error: {}", e);
+        db_error(e).or_internal("Failed to list audit logs")
+    })?;
 
     Ok(Json(AuditLogListResponse {
         logs,
@@ -470,7 +553,7 @@ pub async fn list_all_tenants_summary(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Query(query): Query<PaginationQuery>,
-) -> Result<Json<TenantListResponse>, StatusCode> {
+) -> ApiResult<Json<TenantListResponse>> {
     require_scope(&auth, "admin:system")?;
 
     let pool = state.db_pool.pool();
@@ -486,7 +569,7 @@ pub async fn list_all_tenants_summary(
     .await
     .map_err(|e| {
         error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        db_error(e).or_internal("Failed to list tenants")
     })?;
 
     let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM tenants")
@@ -494,7 +577,7 @@ pub async fn list_all_tenants_summary(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            db_error(e).or_internal("Failed to list tenants")
         })?;
 
     Ok(Json(TenantListResponse {
@@ -521,3 +604,1105 @@ pub struct TenantListResponse {
     pub limit: i64,
 }
 
+// ============================================================================
+// Global Search (Super Admin)
+// ============================================================================
+
+/// Maximum number of hits returned per source
+const GLOBAL_SEARCH_MAX_LIMIT: i64 = 200;
+
+/// Query parameters for the cross-tenant search
+#[derive(Debug, Clone, Deserialize)]
+pub struct GlobalSearchQuery {
+    /// Envelope sender or recipient address (case-insensitive)
+    pub address: Option<String>,
+    /// Message-ID header, with or without angle brackets, or a MaiRust message ID
+    pub message_id: Option<String>,
+    /// Delivery queue (job) ID
+    pub queue_id: Option<Uuid>,
+    /// Only include entries at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only include entries before this time
+    pub to: Option<DateTime<Utc>>,
+    /// Maximum hits per source (default 50, max 200)
+    pub limit: Option<i64>,
+}
+
+/// A received message matching a global search
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct GlobalMessageHit {
+    pub id: Uuid,
+    pub tenant_id: Uuid,
+    pub tenant_name: String,
+    pub mailbox_id: Uuid,
+    pub message_id_header: Option<String>,
+    pub subject: Option<String>,
+    pub from_address: Option<String>,
+    pub envelope_from: Option<String>,
+    pub to_addresses: serde_json::Value,
+    pub received_at: DateTime<Utc>,
+}
+
+/// A delivery job matching a global search
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct GlobalDeliveryHit {
+    pub queue_id: Uuid,
+    pub tenant_id: Option<Uuid>,
+    pub message_id: Option<String>,
+    pub message_id_header: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<serde_json::Value>,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Global search response
+#[derive(Debug, Clone, Serialize)]
+pub struct GlobalSearchResponse {
+    pub messages: Vec<GlobalMessageHit>,
+    pub deliveries: Vec<GlobalDeliveryHit>,
+    pub limit: i64,
+}
+
+/// Search received messages and delivery jobs across all tenants (super admin only)
+///
+/// Requires both the `admin:system` and `admin:search` scopes. Criteria are
+/// combined with AND; every search is recorded in the audit log and the
+/// request fails if it cannot be.
+pub async fn global_search(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<GlobalSearchQuery>,
+) -> ApiResult<Json<GlobalSearchResponse>> {
+    require_scope(&auth, "admin:system")?;
+    require_scope(&auth, "admin:search")?;
+
+    let address = query
+        .address
+        .as_deref()
+        .map(|a| a.trim().to_lowercase())
+        .filter(|a| !a.is_empty());
+    let message_id = query
+        .message_id
+        .as_deref()
+        .map(normalize_message_id)
+        .filter(|m| !m.is_empty());
+
+    if address.is_none() && message_id.is_none() && query.queue_id.is_none() {
+        return Err(Error::Validation(
+            "One of address, message_id and queue_id is required".to_string(),
+        )
+        .into());
+    }
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            return Err(Error::Validation("from must be before to".to_string())
+                .with_field("from")
+                .into());
+        }
+    }
+
+    let pool = state.db_pool.pool();
+    let limit = query.limit.unwrap_or(50).clamp(1, GLOBAL_SEARCH_MAX_LIMIT);
+    let bracketed_message_id = message_id.as_ref().map(|m| format!("<{}>", m));
+    let internal_id = message_id.as_deref().and_then(|m| m.parse::<Uuid>().ok());
+
+    // Received messages have no queue ID
+    let messages: Vec<GlobalMessageHit> = if query.queue_id.is_none() {
+        sqlx::query_as(
+            r#"
+            SELECT m.id, m.tenant_id, t.name AS tenant_name, m.mailbox_id, m.message_id_header,
+                   m.subject, m.from_address, m.metadata->>'envelope_from' AS envelope_from,
+                   m.to_addresses, m.received_at
+            FROM messages m
+            JOIN tenants t ON t.id = m.tenant_id
+            WHERE ($1::text IS NULL
+                   OR LOWER(m.metadata->>'envelope_from') = $1
+                   OR EXISTS (
+                       SELECT 1 FROM jsonb_array_elements(m.to_addresses) a
+                       WHERE LOWER(CONCAT(a->>'local', '@', a->>'domain')) = $1
+                   ))
+              AND ($2::text IS NULL OR m.message_id_header IN ($2, $3) OR m.id = $4)
+              AND ($5::timestamptz IS NULL OR m.received_at >= $5)
+              AND ($6::timestamptz IS NULL OR m.received_at < $6)
+            ORDER BY m.received_at DESC
+            LIMIT $7
+            "#,
+        )
+        .bind(&address)
+        .bind(&message_id)
+        .bind(&bracketed_message_id)
+        .bind(internal_id)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            error!("Database error while searching messages: {}", e);
+            db_error(e).or_internal("Failed to search messages")
+        })?
+    } else {
+        Vec::new()
+    };
+
+    let deliveries: Vec<GlobalDeliveryHit> = sqlx::query_as(
+        r#"
+        SELECT id AS queue_id, tenant_id, payload->>'message_id' AS message_id,
+               payload->>'message_id_header' AS message_id_header,
+               payload->>'from' AS "from", payload->'to' AS "to",
+               status, attempts, last_error, created_at, completed_at
+        FROM jobs
+        WHERE queue = 'delivery'
+          AND ($1::text IS NULL
+               OR LOWER(payload->>'from') = $1
+               OR EXISTS (
+                   SELECT 1 FROM jsonb_array_elements_text(payload->'to') r
+                   WHERE LOWER(r) = $1
+               ))
+          AND ($2::text IS NULL
+               OR payload->>'message_id_header' IN ($2, $3)
+               OR payload->>'message_id' = $2)
+          AND ($4::uuid IS NULL OR id = $4)
+          AND ($5::timestamptz IS NULL OR created_at >= $5)
+          AND ($6::timestamptz IS NULL OR created_at < $6)
+        ORDER BY created_at DESC
+        LIMIT $7
+        "#,
+    )
+    .bind(&address)
+    .bind(&message_id)
+    .bind(&bracketed_message_id)
+    .bind(query.queue_id)
+    .bind(query.from)
+    .bind(query.to)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        error!("Database error while searching delivery jobs: {}", e);
+        db_error(e).or_internal("Failed to search delivery jobs")
+    })?;
+
+    // Cross-tenant access must always leave a trail
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: None,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: "admin.global_search".to_string(),
+            target_type: None,
+            target_id: None,
+            details: serde_json::json!({
+                "address": address,
+                "message_id": message_id,
+                "queue_id": query.queue_id,
+                "from": query.from,
+                "to": query.to,
+                "message_hits": messages.len(),
+                "delivery_hits": deliveries.len(),
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for global search: {}", e);
+            storage_error(e).or_internal("Failed to record audit log for global search")
+        })?;
+
+    info!(
+        "Global search by API key {}: {} messages, {} deliveries",
+        auth.api_key_id,
+        messages.len(),
+        deliveries.len()
+    );
+
+    Ok(Json(GlobalSearchResponse {
+        messages,
+        deliveries,
+        limit,
+    }))
+}
+
+/// Strip whitespace and angle brackets from a Message-ID
+fn normalize_message_id(message_id: &str) -> String {
+    message_id
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim()
+        .to_string()
+}
+
+
+// ============================================================================
+// Message Reprocessing
+// ============================================================================
+
+/// Request to re-run the inbound pipeline for stored messages
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReprocessRequest {
+    /// Explicit message IDs to reprocess
+    #[serde(default)]
+    pub message_ids: Vec<Uuid>,
+    /// Restrict a date-range run to a mailbox
+    pub mailbox_id: Option<Uuid>,
+    /// Date range start (received_at >= from)
+    pub from: Option<DateTime<Utc>>,
+    /// Date range end (received_at < to)
+    pub to: Option<DateTime<Utc>>,
+    /// Maximum number of messages for a date-range run (max 1000)
+    pub limit: Option<i64>,
+    /// Pipeline stages to run (defaults to all)
+    pub steps: Option<Vec<ReprocessStep>>,
+    /// Evaluate without saving results or calling hooks
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Request to reprocess messages across tenants (super admin)
+#[derive(Debug, Clone, Deserialize)]
+pub struct SystemReprocessRequest {
+    /// Restrict to a single tenant
+    pub tenant_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub request: ReprocessRequest,
+}
+
+/// Re-run the processing pipeline for messages of a tenant
+pub async fn reprocess_tenant_messages(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(request): Json<ReprocessRequest>,
+) -> ApiResult<Json<ReprocessSummary>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    run_reprocess(&state, Some(tenant_id), request).await
+}
+
+/// Re-run the processing pipeline for messages in any tenant (super admin only)
+pub async fn reprocess_system_messages(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<SystemReprocessRequest>,
+) -> ApiResult<Json<ReprocessSummary>> {
+    require_scope(&auth, "admin:system")?;
+
+    run_reprocess(&state, request.tenant_id, request.request).await
+}
+
+async fn run_reprocess(
+    state: &AppState,
+    tenant_id: Option<Uuid>,
+    request: ReprocessRequest,
+) -> ApiResult<Json<ReprocessSummary>> {
+    // Either explicit messages or a bounded date range is required
+    if request.message_ids.is_empty() && request.from.is_none() {
+        return Err(Error::Validation("message_ids or from is required".to_string()).into());
+    }
+    if request.message_ids.len() > MAX_REPROCESS_BATCH as usize {
+        return Err(Error::Validation(format!(
+            "at most {} message_ids can be reprocessed at once",
+            MAX_REPROCESS_BATCH
+        ))
+        .with_field("message_ids")
+        .into());
+    }
+    if let (Some(from), Some(to)) = (request.from, request.to) {
+        if from >= to {
+            return Err(Error::Validation("from must be before to".to_string())
+                .with_field("from")
+                .into());
+        }
+    }
+
+    let hook_manager = Arc::new(HookManager::new(state.db_pool.clone()));
+    let mut reprocessor = MessageReprocessor::new(
+        state.db_pool.clone(),
+        state.file_storage.clone(),
+        hook_manager,
+    );
+    if let Some(indexer) = create_indexer() {
+        reprocessor = reprocessor.with_indexer(indexer);
+    }
+
+    let selection = ReprocessSelection {
+        tenant_id,
+        message_ids: request.message_ids,
+        mailbox_id: request.mailbox_id,
+        from: request.from,
+        to: request.to,
+        limit: request.limit,
+    };
+    let options = ReprocessOptions {
+        steps: request.steps.unwrap_or_else(ReprocessStep::all),
+        dry_run: request.dry_run,
+    };
+
+    let summary = reprocessor
+        .reprocess(&selection, &options)
+        .await
+        .map_err(|e| {
+            error!("Database error while reprocessing messages: {}", e);
+            storage_error(e).or_internal("Failed to reprocess messages")
+        })?;
+
+    info!(
+        "Reprocessed {} messages ({} failed, dry_run: {})",
+        summary.processed, summary.failed, summary.dry_run
+    );
+
+    Ok(Json(summary))
+}
+
+// ============================================================================
+// Maintenance Mode
+// ============================================================================
+
+/// Message shown when a window is scheduled without one
+const DEFAULT_MAINTENANCE_MESSAGE: &str = "System maintenance in progress";
+
+/// Maintenance state and the windows that have not ended
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceResponse {
+    /// Whether the system is read-only now
+    pub active: bool,
+    pub message: Option<String>,
+    /// When the active maintenance ends, if known
+    pub until: Option<DateTime<Utc>>,
+    pub retry_after_secs: Option<i64>,
+    /// Active and upcoming windows
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+/// Request to schedule a maintenance window
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleMaintenanceRequest {
+    /// Start of the window (defaults to now)
+    pub starts_at: Option<DateTime<Utc>>,
+    /// End of the window (open until ended if omitted)
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Message shown to clients
+    pub message: Option<String>,
+}
+
+/// Get maintenance state (super admin only)
+pub async fn get_maintenance(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<MaintenanceResponse>> {
+    require_scope(&auth, "admin:system")?;
+
+    maintenance_response(&state).await.map(Json)
+}
+
+/// Schedule a maintenance window, starting now unless `starts_at` is given (super admin only)
+pub async fn schedule_maintenance(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<ScheduleMaintenanceRequest>,
+) -> ApiResult<(StatusCode, Json<MaintenanceWindow>)> {
+    require_scope(&auth, "admin:system")?;
+
+    let now = Utc::now();
+    let starts_at = request.starts_at.unwrap_or(now).max(now);
+    if let Some(ends_at) = request.ends_at {
+        if ends_at <= starts_at {
+            return Err(
+                Error::Validation("ends_at must be after starts_at".to_string())
+                    .with_field("ends_at")
+                    .into(),
+            );
+        }
+    }
+    let message = request
+        .message
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| DEFAULT_MAINTENANCE_MESSAGE.to_string());
+    // The message ends up in SMTP replies and IMAP responses
+    if message.len() > 200 || message.chars().any(char::is_control) {
+        return Err(Error::Validation(
+            "Message must be at most 200 characters without control characters".to_string(),
+        )
+        .with_field("message")
+        .into());
+    }
+
+    let window = MaintenanceRepository::new(state.db_pool.clone())
+        .create(CreateMaintenanceWindow {
+            starts_at,
+            ends_at: request.ends_at,
+            message,
+            created_by: Some(auth.api_key_id.to_string()),
+        })
+        .await
+        .map_err(|e| {
+            error!("Database error while scheduling maintenance: {}", e);
+            storage_error(e).or_internal("Failed to schedule maintenance")
+        })?;
+
+    record_maintenance_event(
+        &state,
+        &auth,
+        "admin.maintenance_scheduled",
+        Some(window.id),
+        serde_json::json!({
+            "starts_at": window.starts_at,
+            "ends_at": window.ends_at,
+            "message": window.message,
+        }),
+    )
+    .await?;
+
+    info!(
+        "Maintenance window {} scheduled from {} by API key {}",
+        window.id, window.starts_at, auth.api_key_id
+    );
+
+    Ok((StatusCode::CREATED, Json(window)))
+}
+
+/// End the active maintenance windows now (super admin only)
+pub async fn end_maintenance(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<MaintenanceResponse>> {
+    require_scope(&auth, "admin:system")?;
+
+    let ended = MaintenanceRepository::new(state.db_pool.clone())
+        .end_active()
+        .await
+        .map_err(|e| {
+            error!("Database error while ending maintenance: {}", e);
+            storage_error(e).or_internal("Failed to end maintenance")
+        })?;
+
+    if ended > 0 {
+        record_maintenance_event(
+            &state,
+            &auth,
+            "admin.maintenance_ended",
+            None,
+            serde_json::json!({ "windows": ended }),
+        )
+        .await?;
+        info!(
+            "Maintenance ended by API key {} ({} windows)",
+            auth.api_key_id, ended
+        );
+    }
+
+    maintenance_response(&state).await.map(Json)
+}
+
+/// Cancel a maintenance window that has not started (super admin only)
+pub async fn cancel_maintenance_window(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(window_id): Path<Uuid>,
+) -> ApiResult<StatusCode> {
+    require_scope(&auth, "admin:system")?;
+
+    let cancelled = MaintenanceRepository::new(state.db_pool.clone())
+        .cancel(window_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while cancelling maintenance window: {}", e);
+            storage_error(e).or_internal("Failed to cancel maintenance window")
+        })?;
+    if !cancelled {
+        // Unknown, or already started (use the end endpoint instead)
+        return Err(Error::NotFound("Maintenance window not found".to_string()).into());
+    }
+
+    record_maintenance_event(
+        &state,
+        &auth,
+        "admin.maintenance_cancelled",
+        Some(window_id),
+        serde_json::json!({}),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn maintenance_response(state: &AppState) -> ApiResult<MaintenanceResponse> {
+    let windows = MaintenanceRepository::new(state.db_pool.clone())
+        .list_open()
+        .await
+        .map_err(|e| {
+            error!("Database error while loading maintenance windows: {}", e);
+            storage_error(e).or_internal("Failed to load maintenance windows")
+        })?;
+
+    let now = Utc::now();
+    let status = MaintenanceStatus::from_windows(&windows, now);
+
+    Ok(MaintenanceResponse {
+        active: status.is_some(),
+        retry_after_secs: status.as_ref().map(|s| s.retry_after_secs(now)),
+        until: status.as_ref().and_then(|s| s.until),
+        message: status.map(|s| s.message),
+        windows,
+    })
+}
+
+/// Maintenance changes affect every tenant, so they always leave a trail
+async fn record_maintenance_event(
+    state: &AppState,
+    auth: &AuthContext,
+    event_type: &str,
+    window_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> ApiResult<()> {
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: None,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: event_type.to_string(),
+            target_type: window_id.map(|_| "maintenance_window".to_string()),
+            target_id: window_id.map(|id| id.to_string()),
+            details,
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for {}: {}", event_type, e);
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+
+    Ok(())
+}
+
+/// Request to exempt a client network from DNS blocklist lookups
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateDnsblAllowlistRequest {
+    /// IP address or CIDR range
+    pub network: String,
+    pub description: Option<String>,
+}
+
+/// List the networks exempt from DNS blocklist lookups (super admin only)
+pub async fn list_dnsbl_allowlist(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<Vec<DnsblAllowlistEntry>>> {
+    require_scope(&auth, "admin:system")?;
+
+    let entries = DnsblAllowlistRepository::new(state.db_pool.clone())
+        .list()
+        .await
+        .map_err(|e| {
+            error!("Database error while listing the DNSBL allowlist: {}", e);
+            storage_error(e).or_internal("Failed to list the DNSBL allowlist")
+        })?;
+
+    Ok(Json(entries))
+}
+
+/// Exempt a client network from DNS blocklist lookups (super admin only)
+pub async fn create_dnsbl_allowlist_entry(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<CreateDnsblAllowlistRequest>,
+) -> ApiResult<(StatusCode, Json<DnsblAllowlistEntry>)> {
+    require_scope(&auth, "admin:system")?;
+
+    let network = normalize_network(&request.network).ok_or_else(|| {
+        Error::Validation(format!("Invalid network {}", request.network)).with_field("network")
+    })?;
+    let description = request
+        .description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+
+    let entry = DnsblAllowlistRepository::new(state.db_pool.clone())
+        .create(CreateDnsblAllowlistEntry {
+            network,
+            description,
+            created_by: Some(auth.api_key_id.to_string()),
+        })
+        .await
+        .map_err(|e| {
+            error!("Database error while adding to the DNSBL allowlist: {}", e);
+            storage_error(e).or_internal("Failed to add to the DNSBL allowlist")
+        })?
+        .ok_or_else(|| Error::Conflict("Network is already allowlisted".to_string()))?;
+
+    record_dnsbl_allowlist_event(&state, &auth, "admin.dnsbl_allowlist_added", &entry).await?;
+    info!(
+        "{} added to the DNSBL allowlist by API key {}",
+        entry.network, auth.api_key_id
+    );
+
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+/// Remove a network from the DNSBL allowlist (super admin only)
+pub async fn delete_dnsbl_allowlist_entry(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(entry_id): Path<Uuid>,
+) -> ApiResult<StatusCode> {
+    require_scope(&auth, "admin:system")?;
+
+    let entry = DnsblAllowlistRepository::new(state.db_pool.clone())
+        .delete(entry_id)
+        .await
+        .map_err(|e| {
+            error!(
+                "Database error while removing from the DNSBL allowlist: {}",
+                e
+            );
+            storage_error(e).or_internal("Failed to remove from the DNSBL allowlist")
+        })?
+        .ok_or_else(|| Error::NotFound("Allowlist entry not found".to_string()))?;
+
+    record_dnsbl_allowlist_event(&state, &auth, "admin.dnsbl_allowlist_removed", &entry).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Allowlisted clients skip a spam check on every tenant, so changes leave
+/// a trail
+async fn record_dnsbl_allowlist_event(
+    state: &AppState,
+    auth: &AuthContext,
+    event_type: &str,
+    entry: &DnsblAllowlistEntry,
+) -> ApiResult<()> {
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: None,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: event_type.to_string(),
+            target_type: Some("dnsbl_allowlist".to_string()),
+            target_id: Some(entry.id.to_string()),
+            details: serde_json::json!({
+                "network": entry.network,
+                "description": entry.description,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for {}: {}", event_type, e);
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+
+    Ok(())
+}
+
+/// Request to add a domain to the local URI blocklist
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateBadDomainRequest {
+    /// Domain; its subdomains are covered too
+    pub domain: String,
+    /// Score of a link to the domain; the configured one when omitted
+    pub score: Option<f64>,
+    pub description: Option<String>,
+}
+
+/// List the domains whose links mark messages as spam (super admin only)
+pub async fn list_bad_domains(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<Vec<BadDomain>>> {
+    require_scope(&auth, "admin:system")?;
+
+    let entries = BadDomainRepository::new(state.db_pool.clone())
+        .list()
+        .await
+        .map_err(|e| {
+            error!("Database error while listing bad domains: {}", e);
+            storage_error(e).or_internal("Failed to list bad domains")
+        })?;
+
+    Ok(Json(entries))
+}
+
+/// Add a domain whose links mark messages as spam (super admin only)
+pub async fn create_bad_domain(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<CreateBadDomainRequest>,
+) -> ApiResult<(StatusCode, Json<BadDomain>)> {
+    require_scope(&auth, "admin:system")?;
+
+    let domain = normalize_domain(&request.domain).ok_or_else(|| {
+        Error::Validation(format!("Invalid domain {}", request.domain)).with_field("domain")
+    })?;
+    if request.score.is_some_and(|score| !score.is_finite()) {
+        return Err(Error::Validation("score must be finite".to_string())
+            .with_field("score")
+            .into());
+    }
+    let description = request
+        .description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+
+    let entry = BadDomainRepository::new(state.db_pool.clone())
+        .create(CreateBadDomain {
+            domain,
+            score: request.score,
+            description,
+            created_by: Some(auth.api_key_id.to_string()),
+        })
+        .await
+        .map_err(|e| {
+            error!("Database error while adding a bad domain: {}", e);
+            storage_error(e).or_internal("Failed to add a bad domain")
+        })?
+        .ok_or_else(|| Error::Conflict("Domain is already listed".to_string()))?;
+
+    record_bad_domain_event(&state, &auth, "admin.bad_domain_added", &entry).await?;
+    info!(
+        "{} added to the bad domains by API key {}",
+        entry.domain, auth.api_key_id
+    );
+
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+/// Remove a domain from the bad domains (super admin only)
+pub async fn delete_bad_domain(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(entry_id): Path<Uuid>,
+) -> ApiResult<StatusCode> {
+    require_scope(&auth, "admin:system")?;
+
+    let entry = BadDomainRepository::new(state.db_pool.clone())
+        .delete(entry_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while removing a bad domain: {}", e);
+            storage_error(e).or_internal("Failed to remove a bad domain")
+        })?
+        .ok_or_else(|| Error::NotFound("Bad domain not found".to_string()))?;
+
+    record_bad_domain_event(&state, &auth, "admin.bad_domain_removed", &entry).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Bad domains score the mail of every tenant, so changes leave a trail
+async fn record_bad_domain_event(
+    state: &AppState,
+    auth: &AuthContext,
+    event_type: &str,
+    entry: &BadDomain,
+) -> ApiResult<()> {
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: None,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: event_type.to_string(),
+            target_type: Some("bad_domain".to_string()),
+            target_id: Some(entry.id.to_string()),
+            details: serde_json::json!({
+                "domain": entry.domain,
+                "score": entry.score,
+                "description": entry.description,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for {}: {}", event_type, e);
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+
+    Ok(())
+}
+
+// ============================================================================
+// Sender Reputation
+// ============================================================================
+
+/// Query parameters for listing sender reputations
+#[derive(Debug, Clone, Deserialize)]
+pub struct SenderReputationQuery {
+    /// Pagination offset
+    pub offset: Option<i64>,
+    /// Pagination limit (default 100, max 1000)
+    pub limit: Option<i64>,
+}
+
+/// List the reputations of sender domains, most recently seen first (super
+/// admin only)
+pub async fn list_sender_reputations(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<SenderReputationQuery>,
+) -> ApiResult<Json<Vec<ReputationScore>>> {
+    require_scope(&auth, "admin:system")?;
+
+    let records = SenderReputationRepository::new(state.db_pool.clone())
+        .list(
+            query.limit.unwrap_or(100).clamp(1, 1000),
+            query.offset.unwrap_or(0).max(0),
+        )
+        .await
+        .map_err(|e| {
+            error!("Database error while listing sender reputations: {}", e);
+            storage_error(e).or_internal("Failed to list sender reputations")
+        })?;
+
+    let now = Utc::now();
+    Ok(Json(
+        records
+            .iter()
+            .map(|record| ReputationScore::of(record, now))
+            .collect(),
+    ))
+}
+
+/// Get the reputation of a sender domain (super admin only)
+pub async fn get_sender_reputation(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(domain): Path<String>,
+) -> ApiResult<Json<ReputationScore>> {
+    require_scope(&auth, "admin:system")?;
+
+    let record = SenderReputationRepository::new(state.db_pool.clone())
+        .get(&domain)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching a sender reputation: {}", e);
+            storage_error(e).or_internal("Failed to fetch a sender reputation")
+        })?
+        .ok_or_else(|| Error::NotFound("Sender reputation not found".to_string()))?;
+
+    Ok(Json(ReputationScore::of(&record, Utc::now())))
+}
+
+/// Forget the reputation of a sender domain, which starts again as clean
+/// (super admin only)
+pub async fn reset_sender_reputation(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(domain): Path<String>,
+) -> ApiResult<StatusCode> {
+    require_scope(&auth, "admin:system")?;
+
+    let record = SenderReputationRepository::new(state.db_pool.clone())
+        .reset(&domain)
+        .await
+        .map_err(|e| {
+            error!("Database error while resetting a sender reputation: {}", e);
+            storage_error(e).or_internal("Failed to reset a sender reputation")
+        })?
+        .ok_or_else(|| Error::NotFound("Sender reputation not found".to_string()))?;
+
+    // Reputations affect the mail of every tenant, so resets leave a trail
+    let reputation = ReputationScore::of(&record, Utc::now());
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: None,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: "admin.sender_reputation_reset".to_string(),
+            target_type: Some("sender_domain".to_string()),
+            target_id: Some(record.domain.clone()),
+            details: serde_json::json!({
+                "score": reputation.score,
+                "total_messages": reputation.total_messages,
+                "total_spam": reputation.total_spam,
+                "total_errors": reputation.total_errors,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to record audit log for admin.sender_reputation_reset: {}",
+                e
+            );
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+    info!(
+        "Reputation of {} reset by API key {}",
+        record.domain, auth.api_key_id
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ============================================================================
+// Live Sessions
+// ============================================================================
+
+/// Sessions terminated by a request
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminateSessionsResponse {
+    pub terminated: usize,
+}
+
+/// List the SMTP, IMAP, POP3 and ManageSieve sessions served by this process (super admin only)
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Query(filter): Query<SessionFilter>,
+) -> ApiResult<Json<Vec<SessionInfo>>> {
+    require_scope(&auth, "admin:system")?;
+
+    Ok(Json(state.sessions.list(&filter)))
+}
+
+/// Terminate a session, closing its connection (super admin only)
+pub async fn terminate_session(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(session_id): Path<Uuid>,
+) -> ApiResult<StatusCode> {
+    require_scope(&auth, "admin:system")?;
+
+    if !state.sessions.terminate(session_id) {
+        return Err(Error::NotFound("Session not found".to_string()).into());
+    }
+
+    record_session_event(
+        &state,
+        &auth,
+        Some(session_id),
+        serde_json::json!({ "terminated": 1 }),
+    )
+    .await?;
+    info!(
+        "Session {} terminated by API key {}",
+        session_id, auth.api_key_id
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Terminate every session of a user, client address, tenant or protocol
+/// (super admin only)
+///
+/// An empty filter is refused rather than closing every connection.
+pub async fn terminate_sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(filter): Json<SessionFilter>,
+) -> ApiResult<Json<TerminateSessionsResponse>> {
+    require_scope(&auth, "admin:system")?;
+
+    if filter.is_empty() {
+        return Err(Error::Validation("At least one filter is required".to_string()).into());
+    }
+
+    let terminated = state.sessions.terminate_matching(&filter);
+    if terminated > 0 {
+        record_session_event(
+            &state,
+            &auth,
+            None,
+            serde_json::json!({ "filter": filter, "terminated": terminated }),
+        )
+        .await?;
+        info!(
+            "{} sessions terminated by API key {}",
+            terminated, auth.api_key_id
+        );
+    }
+
+    Ok(Json(TerminateSessionsResponse { terminated }))
+}
+
+/// Terminated sessions cut off users without warning, so they leave a trail
+async fn record_session_event(
+    state: &AppState,
+    auth: &AuthContext,
+    session_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> ApiResult<()> {
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: None,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: "admin.sessions_terminated".to_string(),
+            target_type: session_id.map(|_| "session".to_string()),
+            target_id: session_id.map(|id| id.to_string()),
+            details,
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for terminated sessions: {}", e);
+            storage_error(e).or_internal("Failed to record audit log for terminated sessions")
+        })?;
+
+    Ok(())
+}
+
+// ============================================================================
+// Replication
+// ============================================================================
+
+/// Replication state of this server, with its lag (super admin only)
+pub async fn get_replication_status(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> ApiResult<Json<ReplicationStatus>> {
+    require_scope(&auth, "admin:system")?;
+
+    let status = Replication::new(state.db_pool.clone(), state.replication.clone())
+        .status()
+        .await
+        .map_err(|e| {
+            error!("Failed to read replication status: {}", e);
+            storage_error(e).or_internal("Failed to read replication status")
+        })?;
+
+    Ok(Json(status))
+}
+
+/// A message file, with its SHA-256 checksum, for standbys to copy
+/// (super admin only)
+///
+/// Only files of stored messages are served.
+pub async fn get_replication_file(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(storage_path): Path<String>,
+) -> ApiResult<Response> {
+    require_scope(&auth, "admin:system")?;
+
+    let referenced = ReplicationRepository::new(state.db_pool.clone())
+        .is_message_file(&storage_path)
+        .await
+        .map_err(|e| {
+            error!("Failed to look up message file: {}", e);
+            storage_error(e).or_internal("Failed to look up message file")
+        })?;
+    if !referenced {
+        return Err(Error::NotFound("Message file not found".to_string()).into());
+    }
+
+    let data = read_message(&state, &storage_path).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "message/rfc822".to_string()),
+            (
+                header::HeaderName::from_static(CHECKSUM_HEADER),
+                checksum(&data),
+            ),
+        ],
+        data,
+    )
+        .into_response())
+}
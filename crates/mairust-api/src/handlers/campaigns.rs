@@ -6,6 +6,9 @@ use axum::{
     Extension, Json,
 };
 use chrono::{DateTime, Utc};
+use mairust_common::Error;
+use mairust_core::{CampaignManager, ClickTracker};
+use mairust_storage::db::db_error;
 use mairust_storage::models::{
     Campaign, CampaignStats, CampaignStatus, CreateCampaign, UpdateCampaign,
 };
@@ -16,13 +19,7 @@ use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
-
-/// Error response
-#[derive(Debug, Clone, Serialize)]
-pub struct ErrorResponse {
-    pub error: String,
-    pub message: String,
-}
+use crate::error::ApiResult;
 
 /// Query parameters for listing campaigns
 #[derive(Debug, Deserialize)]
@@ -148,16 +145,8 @@ pub async fn list_campaigns(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Query(query): Query<ListCampaignsQuery>,
-) -> Result<Json<CampaignListResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignListResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = CampaignRepository::new(state.db_pool.pool().clone());
 
@@ -168,13 +157,7 @@ pub async fn list_campaigns(
         .await
         .map_err(|e| {
             error!("Failed to list campaigns: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to list campaigns".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to list campaigns")
         })?;
 
     let total = repo
@@ -200,46 +183,22 @@ pub async fn create_campaign(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<CreateCampaignRequest>,
-) -> Result<(StatusCode, Json<CampaignResponse>), (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<(StatusCode, Json<CampaignResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Validate input
     if input.name.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Campaign name is required".to_string(),
-            }),
-        ));
+        return Err(Error::Validation("Campaign name is required".to_string()).into());
     }
 
     if input.subject.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Subject is required".to_string(),
-            }),
-        ));
+        return Err(Error::Validation("Subject is required".to_string()).into());
     }
 
     if input.html_body.is_none() && input.text_body.is_none() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Either html_body or text_body is required".to_string(),
-            }),
-        ));
+        return Err(
+            Error::Validation("Either html_body or text_body is required".to_string()).into(),
+        );
     }
 
     let repo = CampaignRepository::new(state.db_pool.pool().clone());
@@ -264,13 +223,7 @@ pub async fn create_campaign(
 
     let campaign = repo.create(create_input).await.map_err(|e| {
         error!("Failed to create campaign: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to create campaign".to_string(),
-            }),
-        )
+        db_error(e).or_internal("Failed to create campaign")
     })?;
 
     info!("Created campaign {} for tenant {}", campaign.id, tenant_id);
@@ -285,16 +238,8 @@ pub async fn get_campaign(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = CampaignRepository::new(state.db_pool.pool().clone());
 
@@ -303,23 +248,9 @@ pub async fn get_campaign(
         .await
         .map_err(|e| {
             error!("Failed to get campaign: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to get campaign".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to get campaign")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Campaign not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Campaign not found".to_string()))?;
 
     Ok(Json(CampaignResponse::from(campaign)))
 }
@@ -332,16 +263,8 @@ pub async fn update_campaign(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<UpdateCampaignRequest>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = CampaignRepository::new(state.db_pool.pool().clone());
 
@@ -367,23 +290,9 @@ pub async fn update_campaign(
         .await
         .map_err(|e| {
             error!("Failed to update campaign: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to update campaign".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to update campaign")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Campaign not found or not in draft status".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Campaign not found or not in draft status".to_string()))?;
 
     info!("Updated campaign {}", campaign_id);
 
@@ -397,41 +306,34 @@ pub async fn delete_campaign(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = CampaignRepository::new(state.db_pool.pool().clone());
 
     let deleted = repo.delete(campaign_id, tenant_id).await.map_err(|e| {
         error!("Failed to delete campaign: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to delete campaign".to_string(),
-            }),
-        )
+        db_error(e).or_internal("Failed to delete campaign")
     })?;
 
     if deleted {
         info!("Deleted campaign {}", campaign_id);
         Ok(StatusCode::NO_CONTENT)
     } else {
-        Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not_found".to_string(),
-                message: "Campaign not found or not in draft status".to_string(),
-            }),
-        ))
+        Err(Error::NotFound("Campaign not found or not in draft status".to_string()).into())
+    }
+}
+
+/// Campaign manager of the handlers, rewriting links when click tracking
+/// is enabled
+fn campaign_manager(state: &AppState) -> CampaignManager {
+    let manager = CampaignManager::new(
+        state.db_pool.clone(),
+        "https://mail.example.com/unsubscribe".to_string(), // TODO: Get from config
+    );
+    match ClickTracker::from_config(&state.tracking) {
+        Some(tracker) => manager.with_click_tracking(tracker, state.tracking.default_url.clone()),
+        None => manager,
     }
 }
 
@@ -443,50 +345,18 @@ pub async fn schedule_campaign(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<ScheduleCampaignRequest>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Create campaign manager
-    let campaign_manager = mairust_core::CampaignManager::new(
-        state.db_pool.clone(),
-        "https://mail.example.com/unsubscribe".to_string(), // TODO: Get from config
-    );
+    let campaign_manager = campaign_manager(&state);
 
     let campaign = campaign_manager
         .schedule_campaign(tenant_id, campaign_id, input.scheduled_at)
         .await
         .map_err(|e| {
             error!("Failed to schedule campaign: {}", e);
-            let (status, message) = match e {
-                mairust_core::CampaignError::NotFound => {
-                    (StatusCode::NOT_FOUND, "Campaign not found")
-                }
-                mairust_core::CampaignError::NotDraft => {
-                    (StatusCode::BAD_REQUEST, "Campaign is not in draft status")
-                }
-                mairust_core::CampaignError::NoRecipientList => {
-                    (StatusCode::BAD_REQUEST, "Campaign has no recipient list")
-                }
-                mairust_core::CampaignError::EmptyRecipientList => {
-                    (StatusCode::BAD_REQUEST, "Recipient list is empty")
-                }
-                _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to schedule campaign"),
-            };
-            (
-                status,
-                Json(ErrorResponse {
-                    error: "schedule_error".to_string(),
-                    message: message.to_string(),
-                }),
-            )
+            Error::from(e)
         })?;
 
     info!("Scheduled campaign {} for tenant {}", campaign_id, tenant_id);
@@ -501,34 +371,17 @@ pub async fn send_campaign(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
-    let campaign_manager = mairust_core::CampaignManager::new(
-        state.db_pool.clone(),
-        "https://mail.example.com/unsubscribe".to_string(),
-    );
+    let campaign_manager = campaign_manager(&state);
 
     let campaign = campaign_manager
         .start_campaign(tenant_id, campaign_id)
         .await
         .map_err(|e| {
             error!("Failed to start campaign: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "send_error".to_string(),
-                    message: e.to_string(),
-                }),
-            )
+            Error::Validation(e.to_string())
         })?;
 
     info!("Started campaign {} for tenant {}", campaign_id, tenant_id);
@@ -543,34 +396,17 @@ pub async fn pause_campaign(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
-    let campaign_manager = mairust_core::CampaignManager::new(
-        state.db_pool.clone(),
-        "https://mail.example.com/unsubscribe".to_string(),
-    );
+    let campaign_manager = campaign_manager(&state);
 
     let campaign = campaign_manager
         .pause_campaign(tenant_id, campaign_id)
         .await
         .map_err(|e| {
             error!("Failed to pause campaign: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "pause_error".to_string(),
-                    message: e.to_string(),
-                }),
-            )
+            Error::Validation(e.to_string())
         })?;
 
     info!("Paused campaign {}", campaign_id);
@@ -585,34 +421,17 @@ pub async fn resume_campaign(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
-    let campaign_manager = mairust_core::CampaignManager::new(
-        state.db_pool.clone(),
-        "https://mail.example.com/unsubscribe".to_string(),
-    );
+    let campaign_manager = campaign_manager(&state);
 
     let campaign = campaign_manager
         .resume_campaign(tenant_id, campaign_id)
         .await
         .map_err(|e| {
             error!("Failed to resume campaign: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "resume_error".to_string(),
-                    message: e.to_string(),
-                }),
-            )
+            Error::Validation(e.to_string())
         })?;
 
     info!("Resumed campaign {}", campaign_id);
@@ -627,34 +446,17 @@ pub async fn cancel_campaign(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<CampaignResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
-    let campaign_manager = mairust_core::CampaignManager::new(
-        state.db_pool.clone(),
-        "https://mail.example.com/unsubscribe".to_string(),
-    );
+    let campaign_manager = campaign_manager(&state);
 
     let campaign = campaign_manager
         .cancel_campaign(tenant_id, campaign_id)
         .await
         .map_err(|e| {
             error!("Failed to cancel campaign: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "cancel_error".to_string(),
-                    message: e.to_string(),
-                }),
-            )
+            Error::Validation(e.to_string())
         })?;
 
     info!("Cancelled campaign {}", campaign_id);
@@ -669,34 +471,17 @@ pub async fn get_campaign_stats(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, campaign_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<CampaignStats>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<CampaignStats>> {
+    require_tenant_access(&auth, tenant_id)?;
 
-    let campaign_manager = mairust_core::CampaignManager::new(
-        state.db_pool.clone(),
-        "https://mail.example.com/unsubscribe".to_string(),
-    );
+    let campaign_manager = campaign_manager(&state);
 
     let stats = campaign_manager
         .get_campaign_stats(tenant_id, campaign_id)
         .await
         .map_err(|e| {
             error!("Failed to get campaign stats: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to get campaign statistics".to_string(),
-                }),
-            )
+            Error::from(e).or_internal("Failed to get campaign statistics")
         })?;
 
     Ok(Json(stats))
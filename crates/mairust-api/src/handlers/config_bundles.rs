@@ -0,0 +1,126 @@
+//! Configuration bundle handlers
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::{
+    BundleError, BundleFormat, ConfigBundle, ConfigBundleManager, ImportOptions, ImportReport,
+};
+use mairust_storage::db::db_error;
+use mairust_storage::PolicyActor;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Query parameters for exporting a bundle
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportBundleQuery {
+    #[serde(default)]
+    pub format: BundleFormat,
+}
+
+/// Query parameters for importing a bundle
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportBundleQuery {
+    /// Body format; taken from Content-Type when omitted
+    pub format: Option<BundleFormat>,
+    /// Report the planned changes without applying them
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Delete entries missing from the bundle
+    #[serde(default)]
+    pub prune: bool,
+}
+
+/// Export a tenant's policies, hooks and domain settings
+pub async fn export_bundle(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<ExportBundleQuery>,
+) -> ApiResult<Response> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let bundle = ConfigBundleManager::new(state.db_pool.clone())
+        .export(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while exporting config bundle: {}", e);
+            match e {
+                BundleError::Database(e) => {
+                    db_error(e).or_internal("Failed to export config bundle")
+                }
+                _ => Error::Internal("Failed to export config bundle".to_string()),
+            }
+        })?;
+
+    let body = bundle.render(query.format).map_err(|e| {
+        error!("Failed to render config bundle: {}", e);
+        Error::Internal("Failed to render config bundle".to_string())
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, query.format.content_type())], body).into_response())
+}
+
+/// Import a bundle, upserting entries by name
+pub async fn import_bundle(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<ImportBundleQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> ApiResult<Json<ImportReport>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let format = query
+        .format
+        .or_else(|| {
+            headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(BundleFormat::from_content_type)
+        })
+        .unwrap_or_default();
+
+    let bundle = ConfigBundle::parse(&body, format).map_err(|e| {
+        warn!("Rejected config bundle for tenant {}: {}", tenant_id, e);
+        Error::Validation(e.to_string())
+    })?;
+
+    let options = ImportOptions {
+        dry_run: query.dry_run,
+        prune: query.prune,
+    };
+    let report = ConfigBundleManager::new(state.db_pool.clone())
+        .with_actor(PolicyActor::api_key(auth.api_key_id))
+        .import(tenant_id, &bundle, options)
+        .await
+        .map_err(|e| match e {
+            BundleError::Database(e) => {
+                error!("Database error while importing config bundle: {}", e);
+                db_error(e).or_internal("Failed to import config bundle")
+            }
+            e => {
+                warn!("Rejected config bundle for tenant {}: {}", tenant_id, e);
+                Error::Validation(e.to_string())
+            }
+        })?;
+
+    if !report.dry_run {
+        info!(
+            "Config bundle imported for tenant {} by API key {}",
+            tenant_id, auth.api_key_id
+        );
+    }
+
+    Ok(Json(report))
+}
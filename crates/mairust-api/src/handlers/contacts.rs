@@ -0,0 +1,94 @@
+//! Contact handlers
+
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_storage::db::storage_error;
+use mairust_storage::{Correspondent, CorrespondentRepository, UserRepository};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Query parameters for contact suggestions
+#[derive(Debug, Clone, Deserialize)]
+pub struct SuggestContactsQuery {
+    /// Prefix of the address or display name
+    #[serde(default)]
+    pub q: String,
+    /// User whose correspondents to search (required for tenant-level keys)
+    pub user_id: Option<Uuid>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    10
+}
+
+/// Suggest recipients for the composer from recent correspondents
+pub async fn suggest_contacts(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<SuggestContactsQuery>,
+) -> ApiResult<Json<Vec<Correspondent>>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+
+    // User keys only see their own correspondents
+    let user_id = match (auth.user_id, query.user_id) {
+        (Some(own), Some(requested)) if own != requested => {
+            warn!(
+                "API key of user {} requested correspondents of user {}",
+                own, requested
+            );
+            return Err(Error::PermissionDenied(
+                "API key can only access its own contacts".to_string(),
+            )
+            .into());
+        }
+        (Some(own), _) => own,
+        (None, Some(requested)) => requested,
+        (None, None) => {
+            return Err(Error::Validation("user_id is required".to_string())
+                .with_field("user_id")
+                .into())
+        }
+    };
+
+    UserRepository::new(state.db_pool.clone())
+        .find_by_id(user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching user: {}", e);
+            e.or_internal("Failed to get user")
+        })?
+        .filter(|u| u.tenant_id == tenant_id)
+        .ok_or_else(|| {
+            warn!(
+                "User {} not found or not owned by tenant {}",
+                user_id, tenant_id
+            );
+            Error::NotFound("User not found".to_string())
+        })?;
+
+    if query.q.trim().is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+
+    let suggestions = CorrespondentRepository::new(state.db_pool.clone())
+        .suggest(tenant_id, user_id, &query.q, query.limit)
+        .await
+        .map_err(|e| {
+            error!("Database error while suggesting contacts: {}", e);
+            storage_error(e).or_internal("Failed to suggest contacts")
+        })?;
+
+    Ok(Json(suggestions))
+}
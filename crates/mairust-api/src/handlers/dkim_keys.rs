@@ -0,0 +1,327 @@
+//! DKIM key handlers
+//!
+//! The signing keys of a domain. A new key is pending until its activation
+//! date and, with DNS checks on, until its record is published; the
+//! rotation worker then activates it and retires the key it replaces.
+//! Keys can also be activated and retired by hand, for example to roll
+//! back to the previous key.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use mairust_common::Error;
+use mairust_core::email_auth::dkim::DkimSigningConfig;
+use mairust_core::email_auth::dkim_rotation::{
+    dns_record, generate_private_key, rotation_selector,
+};
+use mairust_core::email_auth::{DkimSigner, SigningAlgorithm};
+use mairust_storage::db::storage_error;
+use mairust_storage::{
+    CreateDkimKey, DkimKey, DkimKeyRepository, Domain, DomainRepository, DomainRepositoryTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::domains::{
+    extract_public_key_from_pem, is_valid_dkim_selector, TxtRecord, DKIM_KEY_BITS,
+};
+
+/// Request body for adding a DKIM key
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateDkimKeyRequest {
+    /// `rsa-sha256` (default) or `ed25519-sha256`
+    pub algorithm: Option<String>,
+    /// Selector; a dated one is chosen when missing
+    pub selector: Option<String>,
+    /// PKCS#8 PEM private key; generated when missing
+    pub private_key: Option<String>,
+    /// When to activate the key (default: as soon as its record is found)
+    pub activate_at: Option<DateTime<Utc>>,
+    /// Activate the key at once, without waiting for its record
+    #[serde(default)]
+    pub activate: bool,
+}
+
+/// A DKIM key with the DNS record it needs
+#[derive(Debug, Clone, Serialize)]
+pub struct DkimKeyResponse {
+    #[serde(flatten)]
+    pub key: DkimKey,
+    /// `None` when the stored private key cannot be read
+    pub dns_record: Option<TxtRecord>,
+}
+
+/// List the DKIM keys of a domain
+pub async fn list_dkim_keys(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<Vec<DkimKeyResponse>>> {
+    require_tenant_access(&auth, tenant_id)?;
+    let domain = tenant_domain(&state, tenant_id, domain_id).await?;
+    Ok(Json(dkim_key_records(&state, &domain).await?))
+}
+
+/// Add a DKIM key to a domain
+///
+/// The key is generated unless one is given. It starts out pending, so
+/// that its DNS record can be published before mail is signed with it.
+pub async fn create_dkim_key(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<CreateDkimKeyRequest>,
+) -> ApiResult<(StatusCode, Json<DkimKeyResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let algorithm = match input.algorithm.as_deref() {
+        None => SigningAlgorithm::RsaSha256,
+        Some(name) => SigningAlgorithm::parse(name.trim()).ok_or_else(|| {
+            warn!("Unknown DKIM algorithm: {}", name);
+            Error::Validation(format!("Unknown DKIM algorithm: {}", name)).with_field("algorithm")
+        })?,
+    };
+    if let Some(selector) = &input.selector {
+        if !is_valid_dkim_selector(selector) {
+            warn!("Invalid DKIM selector format: {}", selector);
+            return Err(Error::Validation("Invalid DKIM selector".to_string())
+                .with_field("selector")
+                .into());
+        }
+    }
+    if let Some(private_key) = &input.private_key {
+        check_private_key(algorithm, private_key).map_err(|e| {
+            warn!("Invalid DKIM private key: {}", e);
+            Error::Validation(format!("Invalid private key: {}", e)).with_field("private_key")
+        })?;
+    }
+
+    let domain = tenant_domain(&state, tenant_id, domain_id).await?;
+    if !domain.verified {
+        warn!(
+            "Cannot add a DKIM key to unverified domain: {}",
+            domain.name
+        );
+        return Err(ApiError::from(Error::Conflict("Domain is not verified".to_string()))
+            .with_status(StatusCode::PRECONDITION_FAILED));
+    }
+
+    let repo = DkimKeyRepository::new(state.db_pool.clone());
+    let now = Utc::now();
+    let selector = match input.selector {
+        Some(selector) => {
+            if repo
+                .selector_exists(domain_id, &selector)
+                .await
+                .map_err(db_error)?
+            {
+                warn!("DKIM selector {} of {} is taken", selector, domain.name);
+                return Err(Error::Conflict("DKIM selector is taken".to_string())
+                    .with_field("selector")
+                    .into());
+            }
+            selector
+        }
+        None => {
+            let mut attempt = 0;
+            loop {
+                let selector = rotation_selector(algorithm, now, attempt);
+                if !repo
+                    .selector_exists(domain_id, &selector)
+                    .await
+                    .map_err(db_error)?
+                {
+                    break selector;
+                }
+                attempt += 1;
+            }
+        }
+    };
+
+    let private_key = match input.private_key {
+        Some(private_key) => private_key,
+        None => generate_key(algorithm).await?,
+    };
+
+    let key = repo
+        .create(CreateDkimKey {
+            domain_id,
+            selector,
+            algorithm: algorithm.as_str().to_string(),
+            private_key,
+            active: input.activate,
+            activate_at: input.activate_at.unwrap_or(now),
+        })
+        .await
+        .map_err(db_error)?;
+    info!(
+        "Added {} DKIM key {} to domain {}",
+        key.algorithm, key.selector, domain.name
+    );
+
+    Ok((StatusCode::CREATED, Json(key_response(&domain, key))))
+}
+
+/// Sign with a key now, retiring the domain's active key of its algorithm
+pub async fn activate_dkim_key(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id, key_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> ApiResult<Json<DkimKeyResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+    let domain = tenant_domain(&state, tenant_id, domain_id).await?;
+    let repo = DkimKeyRepository::new(state.db_pool.clone());
+    domain_key(&repo, domain_id, key_id).await?;
+
+    let key = repo.activate(domain_id, key_id).await.map_err(db_error)?;
+    info!(
+        "Activated DKIM key {} of domain {}",
+        key.selector, domain.name
+    );
+    Ok(Json(key_response(&domain, key)))
+}
+
+/// Stop signing with a key; its record is still listed until the key is
+/// deleted
+pub async fn retire_dkim_key(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id, key_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> ApiResult<Json<DkimKeyResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+    let domain = tenant_domain(&state, tenant_id, domain_id).await?;
+    let repo = DkimKeyRepository::new(state.db_pool.clone());
+    domain_key(&repo, domain_id, key_id).await?;
+
+    repo.retire(domain_id, key_id).await.map_err(db_error)?;
+    let key = domain_key(&repo, domain_id, key_id).await?;
+    info!(
+        "Retired DKIM key {} of domain {}",
+        key.selector, domain.name
+    );
+    Ok(Json(key_response(&domain, key)))
+}
+
+/// Delete a key that is not active
+pub async fn delete_dkim_key(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id, key_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+    tenant_domain(&state, tenant_id, domain_id).await?;
+    let repo = DkimKeyRepository::new(state.db_pool.clone());
+    let key = domain_key(&repo, domain_id, key_id).await?;
+    if key.is_active() {
+        warn!("Refusing to delete active DKIM key {}", key.selector);
+        return Err(Error::Conflict("Cannot delete the active DKIM key".to_string()).into());
+    }
+
+    repo.delete(domain_id, key_id).await.map_err(db_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The DKIM keys of a domain with their records, for the domain's DNS
+/// records
+pub(crate) async fn dkim_key_records(
+    state: &AppState,
+    domain: &Domain,
+) -> ApiResult<Vec<DkimKeyResponse>> {
+    let keys = DkimKeyRepository::new(state.db_pool.clone())
+        .list(domain.id)
+        .await
+        .map_err(db_error)?;
+    Ok(keys
+        .into_iter()
+        .map(|key| key_response(domain, key))
+        .collect())
+}
+
+fn key_response(domain: &Domain, key: DkimKey) -> DkimKeyResponse {
+    let record = SigningAlgorithm::parse(&key.algorithm)
+        .ok_or_else(|| anyhow::anyhow!("unknown algorithm {}", key.algorithm))
+        .and_then(|algorithm| dns_record(algorithm, &key.private_key));
+    let dns_record = match record {
+        Ok(value) => Some(TxtRecord {
+            host: format!("{}._domainkey.{}", key.selector, domain.name),
+            value,
+        }),
+        Err(e) => {
+            warn!(
+                "Failed to build DKIM record {} of {}: {}",
+                key.selector, domain.name, e
+            );
+            None
+        }
+    };
+    DkimKeyResponse { key, dns_record }
+}
+
+/// Generate a private key; key generation takes a while, so it is kept
+/// off the async workers
+async fn generate_key(algorithm: SigningAlgorithm) -> ApiResult<String> {
+    let key = tokio::task::spawn_blocking(move || generate_private_key(algorithm, DKIM_KEY_BITS))
+        .await
+        .map_err(|e| {
+            error!("DKIM key generation task failed: {}", e);
+            Error::Internal("Failed to generate DKIM key".to_string())
+        })?
+        .map_err(|e| {
+            error!("{}", e);
+            Error::Internal("Failed to generate DKIM key".to_string())
+        })?;
+    Ok(key)
+}
+
+/// Check that mail can be signed with a private key
+fn check_private_key(algorithm: SigningAlgorithm, private_key: &str) -> Result<(), String> {
+    if algorithm == SigningAlgorithm::RsaSha256 {
+        extract_public_key_from_pem(private_key)?;
+    }
+    DkimSigner::new(DkimSigningConfig {
+        private_key_pem: private_key.trim().to_string(),
+        algorithm,
+        ..DkimSigningConfig::default()
+    })
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+async fn tenant_domain(state: &AppState, tenant_id: Uuid, domain_id: Uuid) -> ApiResult<Domain> {
+    let domain = DomainRepository::new(state.db_pool.clone())
+        .get(tenant_id, domain_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching domain: {}", e);
+            e.or_internal("Failed to get domain")
+        })?
+        .ok_or_else(|| {
+            warn!("Domain {} not found for tenant {}", domain_id, tenant_id);
+            Error::NotFound("Domain not found".to_string())
+        })?;
+    Ok(domain)
+}
+
+async fn domain_key(repo: &DkimKeyRepository, domain_id: Uuid, key_id: Uuid) -> ApiResult<DkimKey> {
+    let key = repo
+        .get(domain_id, key_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            warn!("DKIM key {} not found for domain {}", key_id, domain_id);
+            Error::NotFound("DKIM key not found".to_string())
+        })?;
+    Ok(key)
+}
+
+fn db_error(e: anyhow::Error) -> Error {
+    error!("Database error while handling DKIM keys: {}", e);
+    storage_error(e).or_internal("Failed to access DKIM keys")
+}
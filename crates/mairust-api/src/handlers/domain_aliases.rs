@@ -5,6 +5,7 @@ use axum::{
     http::StatusCode,
     Extension, Json,
 };
+use mairust_common::Error;
 use mairust_storage::{CreateDomainAlias, DomainAlias, DomainAliasRepository, DomainAliasRepositoryTrait, DomainRepository, DomainRepositoryTrait};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -12,6 +13,7 @@ use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
 
 /// Request body for creating a domain alias
 #[derive(Debug, Clone, Deserialize)]
@@ -33,7 +35,7 @@ pub async fn list_domain_aliases(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<Vec<DomainAliasResponse>>, StatusCode> {
+) -> ApiResult<Json<Vec<DomainAliasResponse>>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let alias_repo = DomainAliasRepository::new(state.db_pool.clone());
@@ -41,7 +43,7 @@ pub async fn list_domain_aliases(
 
     let aliases = alias_repo.list(tenant_id).await.map_err(|e| {
         error!("Database error while listing domain aliases: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to list domain aliases")
     })?;
 
     let mut responses = Vec::with_capacity(aliases.len());
@@ -66,7 +68,7 @@ pub async fn get_domain_alias(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, alias_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<DomainAliasResponse>, StatusCode> {
+) -> ApiResult<Json<DomainAliasResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let alias_repo = DomainAliasRepository::new(state.db_pool.clone());
@@ -77,11 +79,14 @@ pub async fn get_domain_alias(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain alias: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain alias")
         })?
         .ok_or_else(|| {
-            warn!("Domain alias {} not found for tenant {}", alias_id, tenant_id);
-            StatusCode::NOT_FOUND
+            warn!(
+                "Domain alias {} not found for tenant {}",
+                alias_id, tenant_id
+            );
+            Error::NotFound("Domain alias not found".to_string())
         })?;
 
     let primary_domain_name = domain_repo
@@ -103,13 +108,17 @@ pub async fn create_domain_alias(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<CreateDomainAliasRequest>,
-) -> Result<(StatusCode, Json<DomainAliasResponse>), StatusCode> {
+) -> ApiResult<(StatusCode, Json<DomainAliasResponse>)> {
     require_tenant_access(&auth, tenant_id)?;
 
     // Validate alias domain name
     if !is_valid_domain_name(&input.alias_domain) {
         warn!("Invalid alias domain name format: {}", input.alias_domain);
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(
+            Error::Validation(format!("Invalid domain name {}", input.alias_domain))
+                .with_field("alias_domain")
+                .into(),
+        );
     }
 
     let alias_repo = DomainAliasRepository::new(state.db_pool.clone());
@@ -121,25 +130,26 @@ pub async fn create_domain_alias(
         .await
         .map_err(|e| {
             error!("Database error while fetching primary domain: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch primary domain")
         })?
         .ok_or_else(|| {
             warn!(
                 "Primary domain {} not found for tenant {}",
                 input.primary_domain_id, tenant_id
             );
-            StatusCode::BAD_REQUEST
+            Error::Validation("Primary domain not found".to_string())
+                .with_field("primary_domain_id")
         })?;
 
     // Check if alias domain already exists as a domain (global uniqueness check)
     if let Ok(Some(_)) = domain_repo.find_by_name(&input.alias_domain).await {
         warn!("Domain {} already exists", input.alias_domain);
-        return Err(StatusCode::CONFLICT);
+        return Err(Error::Conflict("Domain already exists".to_string()).into());
     }
 
     if let Ok(Some(_)) = alias_repo.get_by_alias_domain(&input.alias_domain).await {
         warn!("Domain alias {} already exists", input.alias_domain);
-        return Err(StatusCode::CONFLICT);
+        return Err(Error::Conflict("Domain alias already exists".to_string()).into());
     }
 
     let create_input = CreateDomainAlias {
@@ -150,7 +160,7 @@ pub async fn create_domain_alias(
 
     let alias = alias_repo.create(create_input).await.map_err(|e| {
         error!("Database error while creating domain alias: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to create domain alias")
     })?;
 
     info!(
@@ -172,7 +182,7 @@ pub async fn enable_domain_alias(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, alias_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = DomainAliasRepository::new(state.db_pool.clone());
@@ -183,13 +193,13 @@ pub async fn enable_domain_alias(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain alias: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain alias")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Domain alias not found".to_string()))?;
 
     repo.enable(alias_id).await.map_err(|e| {
         error!("Database error while enabling domain alias: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to enable domain alias")
     })?;
 
     info!("Enabled domain alias {}", alias_id);
@@ -201,7 +211,7 @@ pub async fn disable_domain_alias(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, alias_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = DomainAliasRepository::new(state.db_pool.clone());
@@ -212,13 +222,13 @@ pub async fn disable_domain_alias(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain alias: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain alias")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Domain alias not found".to_string()))?;
 
     repo.disable(alias_id).await.map_err(|e| {
         error!("Database error while disabling domain alias: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to disable domain alias")
     })?;
 
     info!("Disabled domain alias {}", alias_id);
@@ -230,7 +240,7 @@ pub async fn delete_domain_alias(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, alias_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = DomainAliasRepository::new(state.db_pool.clone());
@@ -241,13 +251,13 @@ pub async fn delete_domain_alias(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain alias: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain alias")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Domain alias not found".to_string()))?;
 
     repo.delete(alias_id).await.map_err(|e| {
         error!("Database error while deleting domain alias: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to delete domain alias")
     })?;
 
     info!("Deleted domain alias {}", alias.alias_domain);
@@ -0,0 +1,150 @@
+//! Received DMARC and TLS-RPT report handlers
+//!
+//! Reports other mail systems sent about a domain to its report addresses,
+//! for charting who sends mail as the domain and whether TLS to it works.
+
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::Error;
+use mairust_storage::db::storage_error;
+use mairust_storage::repository::received_reports::{
+    DmarcSourceSummary, ReceivedDmarcRecord, ReceivedDmarcReport, ReceivedTlsReport,
+};
+use mairust_storage::{DomainRepository, DomainRepositoryTrait, ReceivedReportRepository};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Query parameters for listing reports
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReportQuery {
+    /// Reports whose period ended in the last `days` days (default 30, max
+    /// 366)
+    pub days: Option<i64>,
+}
+
+impl ReportQuery {
+    fn since(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::days(self.days.unwrap_or(30).clamp(1, 366))
+    }
+}
+
+/// DMARC reports of a domain, with the mail of each source summed
+#[derive(Debug, Clone, Serialize)]
+pub struct DmarcReportsResponse {
+    pub since: DateTime<Utc>,
+    pub reports: Vec<ReceivedDmarcReport>,
+    /// Sources sending mail as the domain, most failing first
+    pub sources: Vec<DmarcSourceSummary>,
+}
+
+/// A DMARC report with its rows
+#[derive(Debug, Clone, Serialize)]
+pub struct DmarcReportDetail {
+    #[serde(flatten)]
+    pub report: ReceivedDmarcReport,
+    pub records: Vec<ReceivedDmarcRecord>,
+}
+
+/// List the DMARC aggregate reports received about a domain
+pub async fn list_dmarc_reports(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<ReportQuery>,
+) -> ApiResult<Json<DmarcReportsResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_domain(&state, tenant_id, domain_id).await?;
+
+    let since = query.since();
+    let repository = ReceivedReportRepository::new(state.db_pool.clone());
+    let reports = repository
+        .list_dmarc_reports(domain_id, since)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing DMARC reports: {}", e);
+            storage_error(e).or_internal("Failed to list DMARC reports")
+        })?;
+    let sources = repository
+        .dmarc_sources(domain_id, since)
+        .await
+        .map_err(|e| {
+            error!("Database error while summing DMARC sources: {}", e);
+            storage_error(e).or_internal("Failed to sum DMARC sources")
+        })?;
+
+    Ok(Json(DmarcReportsResponse {
+        since,
+        reports,
+        sources,
+    }))
+}
+
+/// Get a DMARC aggregate report with its rows
+pub async fn get_dmarc_report(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id, report_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> ApiResult<Json<DmarcReportDetail>> {
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_domain(&state, tenant_id, domain_id).await?;
+
+    let (report, records) = ReceivedReportRepository::new(state.db_pool.clone())
+        .get_dmarc_report(domain_id, report_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching DMARC report: {}", e);
+            storage_error(e).or_internal("Failed to get DMARC report")
+        })?
+        .ok_or_else(|| Error::NotFound("DMARC report not found".to_string()))?;
+
+    Ok(Json(DmarcReportDetail { report, records }))
+}
+
+/// List the TLS-RPT reports received about a domain, one entry per policy
+pub async fn list_tls_reports(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<ReportQuery>,
+) -> ApiResult<Json<Vec<ReceivedTlsReport>>> {
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_domain(&state, tenant_id, domain_id).await?;
+
+    let reports = ReceivedReportRepository::new(state.db_pool.clone())
+        .list_tls_reports(domain_id, query.since())
+        .await
+        .map_err(|e| {
+            error!("Database error while listing TLS reports: {}", e);
+            storage_error(e).or_internal("Failed to list TLS reports")
+        })?;
+
+    Ok(Json(reports))
+}
+
+/// Verify a domain exists and belongs to the tenant
+async fn require_tenant_domain(
+    state: &AppState,
+    tenant_id: Uuid,
+    domain_id: Uuid,
+) -> ApiResult<()> {
+    DomainRepository::new(state.db_pool.clone())
+        .get(tenant_id, domain_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching domain: {}", e);
+            e.or_internal("Failed to get domain")
+        })?
+        .ok_or_else(|| {
+            warn!("Domain {} not found for tenant {}", domain_id, tenant_id);
+            Error::NotFound("Domain not found".to_string())
+        })?;
+    Ok(())
+}
@@ -2,9 +2,10 @@
 
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
     Extension, Json,
 };
+use mairust_common::Error;
+use mairust_core::subdomains::SigningDomain;
 use mairust_storage::{
     DomainRepository, DomainRepositoryTrait, DomainSettings, DomainSettingsRepository,
     DomainSettingsRepositoryTrait, UpdateDomainSettings,
@@ -15,6 +16,8 @@ use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::domains::{is_valid_dkim_selector, is_valid_domain_name};
 
 /// Request body for updating domain settings
 #[derive(Debug, Clone, Deserialize)]
@@ -44,7 +47,7 @@ pub async fn get_domain_settings(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<DomainSettingsResponse>, StatusCode> {
+) -> ApiResult<Json<DomainSettingsResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let domain_repo = DomainRepository::new(state.db_pool.clone());
@@ -56,16 +59,16 @@ pub async fn get_domain_settings(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain")
         })?
         .ok_or_else(|| {
             warn!("Domain {} not found for tenant {}", domain_id, tenant_id);
-            StatusCode::NOT_FOUND
+            Error::NotFound("Domain not found".to_string())
         })?;
 
     let settings = settings_repo.get_or_create(domain_id).await.map_err(|e| {
         error!("Database error while fetching domain settings: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to fetch domain settings")
     })?;
 
     Ok(Json(DomainSettingsResponse {
@@ -80,7 +83,7 @@ pub async fn update_domain_settings(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<UpdateDomainSettingsRequest>,
-) -> Result<Json<DomainSettingsResponse>, StatusCode> {
+) -> ApiResult<Json<DomainSettingsResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let domain_repo = DomainRepository::new(state.db_pool.clone());
@@ -92,18 +95,20 @@ pub async fn update_domain_settings(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain")
         })?
         .ok_or_else(|| {
             warn!("Domain {} not found for tenant {}", domain_id, tenant_id);
-            StatusCode::NOT_FOUND
+            Error::NotFound("Domain not found".to_string())
         })?;
 
     // Validate SPF policy if provided
     if let Some(ref policy) = input.spf_policy {
         if !is_valid_spf_policy(policy) {
             warn!("Invalid SPF policy: {}", policy);
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(Error::Validation(format!("Invalid SPF policy {}", policy))
+                .with_field("spf_policy")
+                .into());
         }
     }
 
@@ -111,7 +116,43 @@ pub async fn update_domain_settings(
     if let Some(ref policy) = input.dmarc_policy {
         if !is_valid_dmarc_policy(policy) {
             warn!("Invalid DMARC policy: {}", policy);
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(
+                Error::Validation(format!("Invalid DMARC policy {}", policy))
+                    .with_field("dmarc_policy")
+                    .into(),
+            );
+        }
+    }
+
+    // Validate the subdomain policy if provided
+    if let Some(subdomains) = input
+        .extra_settings
+        .as_ref()
+        .and_then(|e| e.get("subdomains"))
+    {
+        if let Err(reason) = validate_subdomain_policy(subdomains, &domain.name) {
+            warn!("Invalid subdomain policy for {}: {}", domain.name, reason);
+            return Err(
+                Error::Validation(format!("Invalid subdomain policy: {}", reason))
+                    .with_field("extra_settings.subdomains")
+                    .into(),
+            );
+        }
+    }
+
+    // Validate the report addresses if provided
+    if let Some(addresses) = input
+        .extra_settings
+        .as_ref()
+        .and_then(|e| e.get("report_addresses"))
+    {
+        if let Err(reason) = validate_report_addresses(addresses) {
+            warn!("Invalid report addresses for {}: {}", domain.name, reason);
+            return Err(
+                Error::Validation(format!("Invalid report addresses: {}", reason))
+                    .with_field("extra_settings.report_addresses")
+                    .into(),
+            );
         }
     }
 
@@ -133,7 +174,7 @@ pub async fn update_domain_settings(
         .await
         .map_err(|e| {
             error!("Database error while updating domain settings: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to update domain settings")
         })?;
 
     info!("Updated settings for domain {}", domain.name);
@@ -150,7 +191,7 @@ pub async fn enable_catch_all(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<CatchAllRequest>,
-) -> Result<Json<DomainSettingsResponse>, StatusCode> {
+) -> ApiResult<Json<DomainSettingsResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let domain_repo = DomainRepository::new(state.db_pool.clone());
@@ -162,9 +203,9 @@ pub async fn enable_catch_all(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Domain not found".to_string()))?;
 
     let update = UpdateDomainSettings {
         catch_all_enabled: Some(true),
@@ -181,7 +222,7 @@ pub async fn enable_catch_all(
 
     let settings = settings_repo.update(domain_id, update).await.map_err(|e| {
         error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to enable catch-all")
     })?;
 
     info!("Enabled catch-all for domain {}", domain.name);
@@ -197,7 +238,7 @@ pub async fn disable_catch_all(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, domain_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<DomainSettingsResponse>, StatusCode> {
+) -> ApiResult<Json<DomainSettingsResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let domain_repo = DomainRepository::new(state.db_pool.clone());
@@ -209,9 +250,9 @@ pub async fn disable_catch_all(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Domain not found".to_string()))?;
 
     let update = UpdateDomainSettings {
         catch_all_enabled: Some(false),
@@ -228,7 +269,7 @@ pub async fn disable_catch_all(
 
     let settings = settings_repo.update(domain_id, update).await.map_err(|e| {
         error!("Database error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to disable catch-all")
     })?;
 
     info!("Disabled catch-all for domain {}", domain.name);
@@ -253,6 +294,53 @@ fn is_valid_spf_policy(policy: &str) -> bool {
     )
 }
 
+/// Validate the `subdomains` entry of extra settings for a domain
+fn validate_subdomain_policy(policy: &serde_json::Value, domain: &str) -> Result<(), String> {
+    let policy = policy.as_object().ok_or("must be an object")?;
+    for flag in ["sign", "accept_mail"] {
+        if policy.get(flag).is_some_and(|v| !v.is_boolean()) {
+            return Err(format!("{} must be a boolean", flag));
+        }
+    }
+    if let Some(signing_domain) = policy.get("signing_domain") {
+        if signing_domain
+            .as_str()
+            .and_then(SigningDomain::from_setting)
+            .is_none()
+        {
+            return Err("signing_domain must be \"parent\" or \"subdomain\"".to_string());
+        }
+    }
+    let Some(selectors) = policy.get("selectors") else {
+        return Ok(());
+    };
+    let selectors = selectors.as_object().ok_or("selectors must be an object")?;
+    let suffix = format!(".{}", domain.to_lowercase());
+    for (subdomain, selector) in selectors {
+        let subdomain = subdomain.to_lowercase();
+        if !is_valid_domain_name(&subdomain) || !subdomain.ends_with(&suffix) {
+            return Err(format!("{} is not a subdomain of {}", subdomain, domain));
+        }
+        if !selector.as_str().is_some_and(is_valid_dkim_selector) {
+            return Err(format!("invalid selector for {}", subdomain));
+        }
+    }
+    Ok(())
+}
+
+/// Validate `report_addresses`: local parts whose mail is parsed as DMARC
+/// and TLS-RPT reports
+fn validate_report_addresses(addresses: &serde_json::Value) -> Result<(), String> {
+    let addresses = addresses.as_array().ok_or("must be an array")?;
+    for address in addresses {
+        let local = address.as_str().ok_or("addresses must be strings")?;
+        if local.trim().is_empty() || local.contains(['@', ' ']) {
+            return Err(format!("{} is not a local part", local));
+        }
+    }
+    Ok(())
+}
+
 /// Validate DMARC policy values
 fn is_valid_dmarc_policy(policy: &str) -> bool {
     matches!(
@@ -1,6 +1,7 @@
 //! Health check handlers
 
 use axum::{extract::State, http::StatusCode, Json};
+use mairust_core::Replication;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use utoipa::ToSchema;
@@ -28,6 +29,27 @@ pub struct DetailedHealthResponse {
 pub struct HealthChecks {
     /// Database health status
     pub database: ComponentHealth,
+    /// Replication status, when replication is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replication: Option<ReplicationHealth>,
+}
+
+/// Replication health of a server
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReplicationHealth {
+    /// Replication status (healthy/unhealthy); a standby is unhealthy
+    /// when it lags too far behind its primary
+    pub status: String,
+    /// Role of this server (primary/standby)
+    pub role: String,
+    /// Seconds a standby is behind its primary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag_secs: Option<f64>,
+    /// Message files a standby has yet to copy or delete
+    pub files_pending: i64,
+    /// Error message if the status could not be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 /// Individual component health status
@@ -120,7 +142,38 @@ pub async fn health_detailed(State(state): State<Arc<AppState>>) -> Json<Detaile
         },
     };
 
-    let overall_status = if db_status.status == "healthy" {
+    let replication_status = if state.replication.enabled {
+        let status = Replication::new(state.db_pool.clone(), state.replication.clone())
+            .status()
+            .await;
+        Some(match status {
+            Ok(status) => ReplicationHealth {
+                status: if status.is_lagging() {
+                    "unhealthy"
+                } else {
+                    "healthy"
+                }
+                .to_string(),
+                role: status.role.as_str().to_string(),
+                lag_secs: status.lag_secs,
+                files_pending: status.files.pending,
+                error: None,
+            },
+            Err(e) => ReplicationHealth {
+                status: "unhealthy".to_string(),
+                role: "unknown".to_string(),
+                lag_secs: None,
+                files_pending: 0,
+                error: Some(e.to_string()),
+            },
+        })
+    } else {
+        None
+    };
+
+    let overall_status = if db_status.status == "healthy"
+        && !matches!(&replication_status, Some(replication) if replication.status != "healthy")
+    {
         "healthy"
     } else {
         "unhealthy"
@@ -130,6 +183,7 @@ pub async fn health_detailed(State(state): State<Arc<AppState>>) -> Json<Detaile
         status: overall_status.to_string(),
         checks: HealthChecks {
             database: db_status,
+            replication: replication_status,
         },
     })
 }
@@ -6,6 +6,7 @@ use axum::{
     Extension, Json,
 };
 use mairust_common::types::HookType;
+use mairust_common::Error;
 use mairust_storage::repository::hooks::CreateHook;
 use mairust_storage::{Hook, HookRepository, HookRepositoryTrait};
 use serde::{Deserialize, Serialize};
@@ -14,6 +15,7 @@ use tracing::{error, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
 
 /// Query parameters for listing hooks
 #[derive(Debug, Clone, Deserialize)]
@@ -88,24 +90,24 @@ pub async fn list_hooks(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Query(query): Query<ListHooksQuery>,
-) -> Result<Json<Vec<HookResponse>>, StatusCode> {
+) -> ApiResult<Json<Vec<HookResponse>>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = HookRepository::new(state.db_pool.clone());
 
     // Always filter by tenant - never return hooks from other tenants
-    let hooks = repo
-        .list(Some(tenant_id))
-        .await
-        .map_err(|e| {
-            error!("Database error while listing hooks: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let hooks = repo.list(Some(tenant_id)).await.map_err(|e| {
+        error!("Database error while listing hooks: {}", e);
+        e.or_internal("Failed to list hooks")
+    })?;
 
     // Apply additional filters in-memory if needed
     let filtered_hooks: Vec<Hook> = if let Some(hook_type_str) = query.hook_type {
-        let hook_type = parse_hook_type(&hook_type_str).ok_or(StatusCode::BAD_REQUEST)?;
+        let hook_type = parse_hook_type(&hook_type_str).ok_or_else(|| {
+            Error::Validation(format!("Invalid hook type {}", hook_type_str))
+                .with_field("hook_type")
+        })?;
 
         hooks
             .into_iter()
@@ -128,7 +130,7 @@ pub async fn get_hook(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, hook_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<HookResponse>, StatusCode> {
+) -> ApiResult<Json<HookResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -139,17 +141,14 @@ pub async fn get_hook(
         .await
         .map_err(|e| {
             error!("Database error while fetching hook: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch hook")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Hook not found".to_string()))?;
 
     // Verify hook belongs to the requested tenant
     if hook.tenant_id != Some(tenant_id) {
-        warn!(
-            "Hook {} does not belong to tenant {}",
-            hook_id, tenant_id
-        );
-        return Err(StatusCode::NOT_FOUND);
+        warn!("Hook {} does not belong to tenant {}", hook_id, tenant_id);
+        return Err(Error::NotFound("Hook not found".to_string()).into());
     }
 
     Ok(Json(hook.into()))
@@ -161,13 +160,15 @@ pub async fn create_hook(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<CreateHookRequest>,
-) -> Result<(StatusCode, Json<HookResponse>), StatusCode> {
+) -> ApiResult<(StatusCode, Json<HookResponse>)> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = HookRepository::new(state.db_pool.clone());
 
-    let hook_type = parse_hook_type(&input.hook_type).ok_or(StatusCode::BAD_REQUEST)?;
+    let hook_type = parse_hook_type(&input.hook_type).ok_or_else(|| {
+        Error::Validation(format!("Invalid hook type {}", input.hook_type)).with_field("hook_type")
+    })?;
 
     let create_input = CreateHook {
         tenant_id: Some(tenant_id),
@@ -184,7 +185,7 @@ pub async fn create_hook(
 
     let hook = repo.create(create_input).await.map_err(|e| {
         error!("Database error while creating hook: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to create hook")
     })?;
 
     Ok((StatusCode::CREATED, Json(hook.into())))
@@ -195,7 +196,7 @@ pub async fn enable_hook(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, hook_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<HookResponse>, StatusCode> {
+) -> ApiResult<Json<HookResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -207,22 +208,19 @@ pub async fn enable_hook(
         .await
         .map_err(|e| {
             error!("Database error while fetching hook: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch hook")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Hook not found".to_string()))?;
 
     // Verify hook belongs to the requested tenant
     if hook.tenant_id != Some(tenant_id) {
-        warn!(
-            "Hook {} does not belong to tenant {}",
-            hook_id, tenant_id
-        );
-        return Err(StatusCode::NOT_FOUND);
+        warn!("Hook {} does not belong to tenant {}", hook_id, tenant_id);
+        return Err(Error::NotFound("Hook not found".to_string()).into());
     }
 
     repo.enable(hook_id).await.map_err(|e| {
         error!("Database error while enabling hook: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to enable hook")
     })?;
 
     let hook = repo
@@ -230,9 +228,9 @@ pub async fn enable_hook(
         .await
         .map_err(|e| {
             error!("Database error while fetching hook: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch hook")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Hook not found".to_string()))?;
 
     Ok(Json(hook.into()))
 }
@@ -242,7 +240,7 @@ pub async fn disable_hook(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, hook_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<HookResponse>, StatusCode> {
+) -> ApiResult<Json<HookResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -254,22 +252,19 @@ pub async fn disable_hook(
         .await
         .map_err(|e| {
             error!("Database error while fetching hook: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch hook")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Hook not found".to_string()))?;
 
     // Verify hook belongs to the requested tenant
     if hook.tenant_id != Some(tenant_id) {
-        warn!(
-            "Hook {} does not belong to tenant {}",
-            hook_id, tenant_id
-        );
-        return Err(StatusCode::NOT_FOUND);
+        warn!("Hook {} does not belong to tenant {}", hook_id, tenant_id);
+        return Err(Error::NotFound("Hook not found".to_string()).into());
     }
 
     repo.disable(hook_id).await.map_err(|e| {
         error!("Database error while disabling hook: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to disable hook")
     })?;
 
     let hook = repo
@@ -277,9 +272,9 @@ pub async fn disable_hook(
         .await
         .map_err(|e| {
             error!("Database error while fetching hook: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch hook")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Hook not found".to_string()))?;
 
     Ok(Json(hook.into()))
 }
@@ -289,7 +284,7 @@ pub async fn delete_hook(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, hook_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -301,22 +296,19 @@ pub async fn delete_hook(
         .await
         .map_err(|e| {
             error!("Database error while fetching hook: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch hook")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Hook not found".to_string()))?;
 
     // Verify hook belongs to the requested tenant
     if hook.tenant_id != Some(tenant_id) {
-        warn!(
-            "Hook {} does not belong to tenant {}",
-            hook_id, tenant_id
-        );
-        return Err(StatusCode::NOT_FOUND);
+        warn!("Hook {} does not belong to tenant {}", hook_id, tenant_id);
+        return Err(Error::NotFound("Hook not found".to_string()).into());
     }
 
     repo.delete(hook_id).await.map_err(|e| {
         error!("Database error while deleting hook: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to delete hook")
     })?;
 
     Ok(StatusCode::NO_CONTENT)
@@ -0,0 +1,360 @@
+//! Inbound route handlers
+//!
+//! Routes post messages for an address or domain to a webhook. Attachment
+//! links in the posted JSON are served by `download_attachment`, which is
+//! authorized by the link signature instead of an API key. Attachments are
+//! rescanned before they are served when rescanning is enabled.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::hooks::validate_webhook_url;
+use mairust_core::inbound::{attachment, inbound_storage_path, verify_attachment_signature};
+use mairust_core::spam::rescan::received_at_from_id;
+use mairust_core::spam::AttachmentRescanner;
+use mairust_storage::db::storage_error;
+use mairust_storage::repository::inbound_routes::{CreateInboundRoute, InboundRoute};
+use mairust_storage::{DomainRepository, InboundRouteRepository};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::messages::read_message;
+
+/// Request body for creating an inbound route
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateInboundRouteRequest {
+    /// `user@example.com`, or `*@example.com` / `example.com` for a whole domain
+    pub address: String,
+    pub webhook_url: String,
+    /// Also deliver matching messages to the recipient's mailbox
+    #[serde(default = "default_store_message")]
+    pub store_message: bool,
+}
+
+fn default_store_message() -> bool {
+    true
+}
+
+/// Inbound route with its matched address
+#[derive(Debug, Clone, Serialize)]
+pub struct InboundRouteResponse {
+    #[serde(flatten)]
+    pub route: InboundRoute,
+    pub address: String,
+}
+
+impl From<InboundRoute> for InboundRouteResponse {
+    fn from(route: InboundRoute) -> Self {
+        Self {
+            address: route.address(),
+            route,
+        }
+    }
+}
+
+/// Query parameters of a signed attachment link
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttachmentLinkQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+/// Split a route address into local part (`None` for the whole domain) and domain
+fn parse_route_address(address: &str) -> Option<(Option<String>, String)> {
+    let address = address.trim().to_lowercase();
+    let (local, domain) = match address.rsplit_once('@') {
+        Some((local, domain)) => (local.to_string(), domain.to_string()),
+        None => ("*".to_string(), address),
+    };
+    if domain.is_empty() || local.is_empty() || local.contains(char::is_whitespace) {
+        return None;
+    }
+    let local = (local != "*").then_some(local);
+    Some((local, domain))
+}
+
+/// List inbound routes for a tenant
+pub async fn list_inbound_routes(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+) -> ApiResult<Json<Vec<InboundRouteResponse>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let routes = InboundRouteRepository::new(state.db_pool.clone())
+        .list(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing inbound routes: {}", e);
+            storage_error(e).or_internal("Failed to list inbound routes")
+        })?;
+
+    Ok(Json(routes.into_iter().map(Into::into).collect()))
+}
+
+/// Get an inbound route by ID
+pub async fn get_inbound_route(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, route_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<InboundRouteResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let route = InboundRouteRepository::new(state.db_pool.clone())
+        .get(tenant_id, route_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching inbound route: {}", e);
+            storage_error(e).or_internal("Failed to get inbound route")
+        })?
+        .ok_or_else(|| Error::NotFound("Inbound route not found".to_string()))?;
+
+    Ok(Json(route.into()))
+}
+
+/// Create an inbound route
+pub async fn create_inbound_route(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<CreateInboundRouteRequest>,
+) -> ApiResult<(StatusCode, Json<InboundRouteResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let (local_part, domain_name) = parse_route_address(&input.address).ok_or_else(|| {
+        warn!("Invalid inbound route address: {}", input.address);
+        Error::Validation("Invalid route address".to_string()).with_field("address")
+    })?;
+
+    if let Err(e) = validate_webhook_url(&input.webhook_url) {
+        warn!("Rejected inbound route webhook URL: {}", e);
+        return Err(Error::Validation(format!("Invalid webhook URL: {}", e))
+            .with_field("webhook_url")
+            .into());
+    }
+
+    // The domain must be one of the tenant's
+    let domain = DomainRepository::new(state.db_pool.clone())
+        .find_by_name(&domain_name)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching domain: {}", e);
+            e.or_internal("Failed to get domain")
+        })?
+        .filter(|d| d.tenant_id == tenant_id)
+        .ok_or_else(|| {
+            warn!("Domain {} not found for tenant {}", domain_name, tenant_id);
+            Error::Validation(format!("Unknown domain: {}", domain_name)).with_field("address")
+        })?;
+
+    let repo = InboundRouteRepository::new(state.db_pool.clone());
+    let existing = repo.list(tenant_id).await.map_err(|e| {
+        error!("Database error while listing inbound routes: {}", e);
+        storage_error(e).or_internal("Failed to list inbound routes")
+    })?;
+    if existing
+        .iter()
+        .any(|r| r.domain_id == domain.id && r.local_part == local_part)
+    {
+        warn!("Inbound route for {} already exists", input.address);
+        return Err(Error::Conflict("Inbound route already exists".to_string())
+            .with_field("address")
+            .into());
+    }
+
+    let route = repo
+        .create(CreateInboundRoute {
+            tenant_id,
+            domain_id: domain.id,
+            local_part,
+            webhook_url: input.webhook_url,
+            store_message: input.store_message,
+        })
+        .await
+        .map_err(|e| {
+            error!("Database error while creating inbound route: {}", e);
+            storage_error(e).or_internal("Failed to create inbound route")
+        })?;
+
+    info!(
+        "Created inbound route {} for {} (tenant {})",
+        route.id,
+        route.address(),
+        tenant_id
+    );
+
+    Ok((StatusCode::CREATED, Json(route.into())))
+}
+
+/// Enable an inbound route
+pub async fn enable_inbound_route(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, route_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<InboundRouteResponse>> {
+    set_route_enabled(&state, &auth, tenant_id, route_id, true).await
+}
+
+/// Disable an inbound route
+pub async fn disable_inbound_route(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, route_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<InboundRouteResponse>> {
+    set_route_enabled(&state, &auth, tenant_id, route_id, false).await
+}
+
+async fn set_route_enabled(
+    state: &AppState,
+    auth: &AuthContext,
+    tenant_id: Uuid,
+    route_id: Uuid,
+    enabled: bool,
+) -> ApiResult<Json<InboundRouteResponse>> {
+    require_tenant_access(auth, tenant_id)?;
+
+    let repo = InboundRouteRepository::new(state.db_pool.clone());
+    let updated = repo
+        .set_enabled(tenant_id, route_id, enabled)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating inbound route: {}", e);
+            storage_error(e).or_internal("Failed to update inbound route")
+        })?;
+    if !updated {
+        return Err(Error::NotFound("Inbound route not found".to_string()).into());
+    }
+
+    let route = repo
+        .get(tenant_id, route_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching inbound route: {}", e);
+            storage_error(e).or_internal("Failed to get inbound route")
+        })?
+        .ok_or_else(|| Error::NotFound("Inbound route not found".to_string()))?;
+
+    Ok(Json(route.into()))
+}
+
+/// Delete an inbound route
+pub async fn delete_inbound_route(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, route_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let deleted = InboundRouteRepository::new(state.db_pool.clone())
+        .delete(tenant_id, route_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while deleting inbound route: {}", e);
+            storage_error(e).or_internal("Failed to delete inbound route")
+        })?;
+
+    if deleted {
+        info!("Deleted inbound route {} (tenant {})", route_id, tenant_id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::NotFound("Inbound route not found".to_string()).into())
+    }
+}
+
+/// Download an attachment through a signed link from a webhook payload
+pub async fn download_attachment(
+    State(state): State<Arc<AppState>>,
+    Path((route_id, message_id, index)): Path<(Uuid, Uuid, usize)>,
+    Query(query): Query<AttachmentLinkQuery>,
+) -> ApiResult<Response> {
+    let route = InboundRouteRepository::new(state.db_pool.clone())
+        .get_by_id(route_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching inbound route: {}", e);
+            storage_error(e).or_internal("Failed to get inbound route")
+        })?
+        .ok_or_else(|| Error::NotFound("Inbound route not found".to_string()))?;
+
+    if !verify_attachment_signature(
+        &route.secret,
+        route.id,
+        message_id,
+        index,
+        query.expires,
+        &query.signature,
+        chrono::Utc::now(),
+    ) {
+        warn!(
+            "Invalid or expired attachment link for message {} (route {})",
+            message_id, route_id
+        );
+        return Err(
+            Error::PermissionDenied("Invalid or expired attachment link".to_string()).into(),
+        );
+    }
+
+    serve_attachment(
+        &state,
+        route.tenant_id,
+        &inbound_storage_path(route.tenant_id, route.id, message_id),
+        received_at_from_id(message_id),
+        index,
+    )
+    .await
+}
+
+/// Serve attachment `index` of a stored message
+///
+/// The message is rescanned first when rescanning on download is enabled;
+/// a flagged message is refused with 403 and an explanation.
+pub(crate) async fn serve_attachment(
+    state: &AppState,
+    tenant_id: Uuid,
+    storage_path: &str,
+    received_at: Option<chrono::DateTime<chrono::Utc>>,
+    index: usize,
+) -> ApiResult<Response> {
+    let raw = read_message(state, storage_path).await?;
+
+    let (content_type, filename, contents) = attachment(&raw, index)
+        .ok_or_else(|| Error::NotFound("Attachment not found".to_string()))?;
+
+    let verdict = AttachmentRescanner::from_env(state.db_pool.clone())
+        .check(tenant_id, storage_path, received_at, &raw)
+        .await;
+    if let Some(reason) = verdict.reason() {
+        warn!(
+            "Refusing download of attachment {} of {}",
+            index, storage_path
+        );
+        return Err(Error::PermissionDenied(reason.to_string())
+            .with_resource("attachment")
+            .into());
+    }
+
+    let disposition = match filename {
+        Some(name) => format!(
+            "attachment; filename=\"{}\"",
+            name.replace(['"', '\\', '\r', '\n'], "_")
+        ),
+        None => "attachment".to_string(),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        contents,
+    )
+        .into_response())
+}
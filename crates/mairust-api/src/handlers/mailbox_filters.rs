@@ -0,0 +1,221 @@
+//! Mailbox filter handlers
+//!
+//! Simple server-side rules for users who do not write Sieve. Conditions
+//! use the policy condition format, restricted to the sender, recipients,
+//! subject and headers; actions are `move_to_folder`, `tag`, `mark_read`,
+//! `forward` and `delete`. Filters run when mail is delivered.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::filters::{parse_actions, parse_conditions};
+use mairust_storage::db::storage_error;
+use mairust_storage::{CreateMailboxFilter, MailboxFilter, MailboxFilterRepository};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::mailboxes::fetch_mailbox;
+
+/// Most filters a mailbox may have
+const MAX_FILTERS: usize = 100;
+
+/// Request body for creating or replacing a filter
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailboxFilterRequest {
+    pub name: String,
+    /// Filters run in ascending position; new filters go last by default
+    pub position: Option<i32>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_conditions")]
+    pub conditions: serde_json::Value,
+    pub actions: serde_json::Value,
+    /// Skip later filters when this one matches
+    #[serde(default)]
+    pub stop_processing: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_conditions() -> serde_json::Value {
+    serde_json::json!([])
+}
+
+impl MailboxFilterRequest {
+    /// Check the request and turn it into repository input
+    fn validate(self) -> Result<CreateMailboxFilter, Error> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.len() > 255 {
+            warn!("Invalid filter name: {:?}", self.name);
+            return Err(
+                Error::Validation("Name must be 1 to 255 characters".to_string())
+                    .with_field("name"),
+            );
+        }
+        if let Err(e) = parse_conditions(&self.conditions) {
+            warn!("Invalid filter conditions: {}", e);
+            return Err(Error::Validation(e.to_string()).with_field("conditions"));
+        }
+        if let Err(e) = parse_actions(&self.actions) {
+            warn!("Invalid filter actions: {}", e);
+            return Err(Error::Validation(e.to_string()).with_field("actions"));
+        }
+
+        Ok(CreateMailboxFilter {
+            name,
+            position: self.position,
+            enabled: self.enabled,
+            conditions: self.conditions,
+            actions: self.actions,
+            stop_processing: self.stop_processing,
+        })
+    }
+}
+
+/// List the filters of a mailbox, in the order they run
+pub async fn list_filters(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<Vec<MailboxFilter>>> {
+    require_tenant_access(&auth, tenant_id)?;
+    fetch_mailbox(&state, tenant_id, mailbox_id).await?;
+
+    let filters = MailboxFilterRepository::new(state.db_pool.clone())
+        .list(tenant_id, mailbox_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing mailbox filters: {}", e);
+            storage_error(e).or_internal("Failed to list mailbox filters")
+        })?;
+
+    Ok(Json(filters))
+}
+
+/// Get a filter of a mailbox
+pub async fn get_filter(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id, filter_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> ApiResult<Json<MailboxFilter>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let filter = MailboxFilterRepository::new(state.db_pool.clone())
+        .get(tenant_id, mailbox_id, filter_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching mailbox filter: {}", e);
+            storage_error(e).or_internal("Failed to get mailbox filter")
+        })?
+        .ok_or_else(|| Error::NotFound("Mailbox filter not found".to_string()))?;
+
+    Ok(Json(filter))
+}
+
+/// Create a filter for a mailbox
+pub async fn create_filter(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<MailboxFilterRequest>,
+) -> ApiResult<(StatusCode, Json<MailboxFilter>)> {
+    require_tenant_access(&auth, tenant_id)?;
+    let input = input.validate()?;
+    fetch_mailbox(&state, tenant_id, mailbox_id).await?;
+
+    let repo = MailboxFilterRepository::new(state.db_pool.clone());
+    let existing = repo.list(tenant_id, mailbox_id).await.map_err(|e| {
+        error!("Database error while listing mailbox filters: {}", e);
+        storage_error(e).or_internal("Failed to list mailbox filters")
+    })?;
+    if existing.len() >= MAX_FILTERS {
+        warn!(
+            "Mailbox {} already has {} filters",
+            mailbox_id,
+            existing.len()
+        );
+        return Err(Error::Conflict(format!(
+            "A mailbox can have at most {} filters",
+            MAX_FILTERS
+        ))
+        .into());
+    }
+
+    let filter = repo
+        .create(tenant_id, mailbox_id, input)
+        .await
+        .map_err(|e| {
+            error!("Database error while creating mailbox filter: {}", e);
+            storage_error(e).or_internal("Failed to create mailbox filter")
+        })?;
+
+    info!(
+        "Created filter {} '{}' for mailbox {} (tenant {})",
+        filter.id, filter.name, mailbox_id, tenant_id
+    );
+
+    Ok((StatusCode::CREATED, Json(filter)))
+}
+
+/// Replace a filter of a mailbox
+pub async fn update_filter(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id, filter_id)): Path<(Uuid, Uuid, Uuid)>,
+    Json(input): Json<MailboxFilterRequest>,
+) -> ApiResult<Json<MailboxFilter>> {
+    require_tenant_access(&auth, tenant_id)?;
+    let input = input.validate()?;
+
+    let filter = MailboxFilterRepository::new(state.db_pool.clone())
+        .update(tenant_id, mailbox_id, filter_id, input)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating mailbox filter: {}", e);
+            storage_error(e).or_internal("Failed to update mailbox filter")
+        })?
+        .ok_or_else(|| Error::NotFound("Mailbox filter not found".to_string()))?;
+
+    info!(
+        "Updated filter {} of mailbox {} (tenant {})",
+        filter_id, mailbox_id, tenant_id
+    );
+
+    Ok(Json(filter))
+}
+
+/// Delete a filter of a mailbox
+pub async fn delete_filter(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id, filter_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let deleted = MailboxFilterRepository::new(state.db_pool.clone())
+        .delete(tenant_id, mailbox_id, filter_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while deleting mailbox filter: {}", e);
+            storage_error(e).or_internal("Failed to delete mailbox filter")
+        })?;
+
+    if deleted {
+        info!(
+            "Deleted filter {} of mailbox {} (tenant {})",
+            filter_id, mailbox_id, tenant_id
+        );
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::NotFound("Mailbox filter not found".to_string()).into())
+    }
+}
@@ -0,0 +1,257 @@
+//! Bulk mailbox provisioning handlers
+//!
+//! A batch of users with mailboxes is submitted as JSON or CSV and created
+//! in the background; the batch is then followed until every row has a
+//! result. See `mairust_core::provisioning` for how rows are provisioned.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::provisioning::{
+    parse_csv, BulkMailboxRow, BulkProvisioner, GeneratedPassword, PasswordMode, ProvisioningError,
+};
+use mairust_storage::db::{db_error, storage_error};
+use mairust_storage::{
+    AuditLogRepository, NewAuditLog, ProvisioningBatch, ProvisioningRepository, ProvisioningRow,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Query parameters of a bulk submission; for JSON bodies, fields of the
+/// body take precedence
+#[derive(Debug, Clone, Deserialize)]
+pub struct BulkProvisionQuery {
+    pub password_mode: Option<PasswordMode>,
+    pub idempotency_key: Option<String>,
+}
+
+/// JSON body of a bulk submission
+#[derive(Debug, Clone, Deserialize)]
+pub struct BulkProvisionRequest {
+    /// What users without a password get (default `generate`)
+    pub password_mode: Option<PasswordMode>,
+    /// Submitting again with the same key returns the first batch
+    pub idempotency_key: Option<String>,
+    pub rows: Vec<BulkMailboxRow>,
+}
+
+/// A submitted batch
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkProvisionResponse {
+    pub batch: ProvisioningBatch,
+    /// Generated passwords; only returned when the batch is created
+    pub passwords: Vec<GeneratedPassword>,
+}
+
+/// Query parameters for listing batches
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchListQuery {
+    /// Pagination offset
+    pub offset: Option<i64>,
+    /// Pagination limit (default 50, max 200)
+    pub limit: Option<i64>,
+}
+
+/// Query parameters for the rows of a batch
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchRowsQuery {
+    /// Only rows with this status (`pending`, `created`, `exists`, `failed`)
+    pub status: Option<String>,
+    /// Pagination offset
+    pub offset: Option<i64>,
+    /// Pagination limit (default 1000, max 5000)
+    pub limit: Option<i64>,
+}
+
+/// A batch with the results of its rows
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchDetail {
+    pub batch: ProvisioningBatch,
+    pub rows: Vec<ProvisioningRow>,
+}
+
+/// Submit users with mailboxes to create in the background
+///
+/// POST /api/v1/tenants/:tenant_id/mailboxes/bulk
+///
+/// The body is JSON, or CSV with a header line when sent as `text/csv`.
+/// Returns 202 with the new batch, or 200 with the earlier batch of the
+/// same idempotency key.
+pub async fn submit_bulk_mailboxes(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<BulkProvisionQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> ApiResult<(StatusCode, Json<BulkProvisionResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let is_csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("text/csv"));
+
+    let (rows, password_mode, idempotency_key) = if is_csv {
+        let rows = parse_csv(&body).map_err(|e| {
+            warn!("Rejected bulk mailbox CSV for tenant {}: {}", tenant_id, e);
+            Error::Validation(e.to_string())
+        })?;
+        (rows, query.password_mode, query.idempotency_key)
+    } else {
+        let request: BulkProvisionRequest = serde_json::from_str(&body).map_err(|e| {
+            warn!(
+                "Rejected bulk mailbox request for tenant {}: {}",
+                tenant_id, e
+            );
+            Error::Validation(format!("Invalid request body: {}", e))
+        })?;
+        (
+            request.rows,
+            request.password_mode.or(query.password_mode),
+            request.idempotency_key.or(query.idempotency_key),
+        )
+    };
+
+    let submission = BulkProvisioner::new(state.db_pool.clone())
+        .submit(
+            tenant_id,
+            &rows,
+            password_mode.unwrap_or_default(),
+            idempotency_key.as_deref(),
+        )
+        .await
+        .map_err(|e| match e {
+            ProvisioningError::Invalid(reason) => {
+                warn!(
+                    "Rejected bulk mailbox batch for tenant {}: {}",
+                    tenant_id, reason
+                );
+                Error::Validation(reason)
+            }
+            ProvisioningError::Database(e) => {
+                error!("Failed to submit bulk mailbox batch: {}", e);
+                db_error(e).or_internal("Failed to submit bulk mailbox batch")
+            }
+            e => {
+                error!("Failed to submit bulk mailbox batch: {}", e);
+                Error::Internal("Failed to submit bulk mailbox batch".to_string())
+            }
+        })?;
+
+    if !submission.created {
+        return Ok((
+            StatusCode::OK,
+            Json(BulkProvisionResponse {
+                batch: submission.batch,
+                passwords: Vec::new(),
+            }),
+        ));
+    }
+
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: Some(tenant_id),
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: "mailboxes.bulk_submitted".to_string(),
+            target_type: Some("provisioning_batch".to_string()),
+            target_id: Some(submission.batch.id.to_string()),
+            details: serde_json::json!({
+                "total_rows": submission.batch.total_rows,
+                "password_mode": submission.batch.password_mode,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to record audit log for mailboxes.bulk_submitted: {}",
+                e
+            );
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+    info!(
+        "Bulk mailbox batch {} with {} rows submitted for tenant {} by API key {}",
+        submission.batch.id, submission.batch.total_rows, tenant_id, auth.api_key_id
+    );
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(BulkProvisionResponse {
+            batch: submission.batch,
+            passwords: submission.passwords,
+        }),
+    ))
+}
+
+/// List the bulk provisioning batches of a tenant, newest first
+///
+/// GET /api/v1/tenants/:tenant_id/mailboxes/bulk
+pub async fn list_bulk_batches(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<BatchListQuery>,
+) -> ApiResult<Json<Vec<ProvisioningBatch>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let batches = ProvisioningRepository::new(state.db_pool.clone())
+        .list(
+            tenant_id,
+            query.limit.unwrap_or(50).clamp(1, 200),
+            query.offset.unwrap_or(0).max(0),
+        )
+        .await
+        .map_err(|e| {
+            error!("Database error while listing provisioning batches: {}", e);
+            storage_error(e).or_internal("Failed to list provisioning batches")
+        })?;
+
+    Ok(Json(batches))
+}
+
+/// Get a bulk provisioning batch with the results of its rows
+///
+/// GET /api/v1/tenants/:tenant_id/mailboxes/bulk/:batch_id
+pub async fn get_bulk_batch(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, batch_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<BatchRowsQuery>,
+) -> ApiResult<Json<BatchDetail>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let repository = ProvisioningRepository::new(state.db_pool.clone());
+    let batch = repository
+        .get(tenant_id, batch_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching provisioning batch: {}", e);
+            storage_error(e).or_internal("Failed to get provisioning batch")
+        })?
+        .ok_or_else(|| Error::NotFound("Provisioning batch not found".to_string()))?;
+    let rows = repository
+        .rows(
+            batch.id,
+            query.status.as_deref(),
+            query.limit.unwrap_or(1000).clamp(1, 5000),
+            query.offset.unwrap_or(0).max(0),
+        )
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching provisioning rows: {}", e);
+            storage_error(e).or_internal("Failed to get provisioning rows")
+        })?;
+
+    Ok(Json(BatchDetail { batch, rows }))
+}
@@ -5,16 +5,24 @@ use axum::{
     http::StatusCode,
     Extension, Json,
 };
+use mairust_common::Error;
+use mairust_core::folders::{FolderProvisioner, SpecialUse};
+use mairust_storage::db::storage_error;
+use mairust_storage::repository::metadata::{
+    is_private_entry, normalize_entry, METADATA_MAX_VALUE_SIZE,
+};
 use mairust_storage::{
-    CreateMailbox, DomainRepository, DomainRepositoryTrait, Mailbox, MailboxRepository,
-    MailboxRepositoryTrait, UserRepository,
+    CreateMailbox, DomainRepository, DomainRepositoryTrait, Mailbox, MailboxCounters,
+    MailboxRepository, MailboxRepositoryTrait, MetadataRepository, UserRepository,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use tracing::{error, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::{ApiError, ApiResult};
 
 /// Query parameters for listing mailboxes
 #[derive(Debug, Clone, Deserialize)]
@@ -47,6 +55,32 @@ pub struct UpdateQuotaRequest {
     pub quota_bytes: Option<i64>,
 }
 
+/// Request to update mailbox metadata; a `null` value removes the entry
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateMailboxMetadataRequest {
+    pub entries: BTreeMap<String, Option<String>>,
+}
+
+/// Mailbox metadata (RFC 5464 entries shared with IMAP)
+#[derive(Debug, Clone, Serialize)]
+pub struct MailboxMetadataResponse {
+    pub mailbox_id: Uuid,
+    pub entries: BTreeMap<String, String>,
+}
+
+/// Folder of a mailbox owner with its special use
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderResponse {
+    pub id: Uuid,
+    /// Full folder name, levels separated by `/`
+    pub name: String,
+    /// Full name of the parent folder, `None` at the top level
+    pub parent: Option<String>,
+    pub special_use: Option<SpecialUse>,
+    /// IMAP LIST attribute (e.g. `\Sent`)
+    pub attribute: Option<&'static str>,
+}
+
 /// Mailbox with usage stats
 #[derive(Debug, Clone, Serialize)]
 pub struct MailboxResponse {
@@ -78,7 +112,7 @@ pub async fn list_mailboxes(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Query(query): Query<ListMailboxesQuery>,
-) -> Result<Json<Vec<MailboxResponse>>, StatusCode> {
+) -> ApiResult<Json<Vec<MailboxResponse>>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -92,20 +126,20 @@ pub async fn list_mailboxes(
             .await
             .map_err(|e| {
                 error!("Database error while fetching domain: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                e.or_internal("Failed to fetch domain")
             })?
             .ok_or_else(|| {
                 warn!(
                     "Domain {} not found or not owned by tenant {}",
                     domain_id, tenant_id
                 );
-                StatusCode::NOT_FOUND
+                Error::NotFound("Domain not found".to_string())
             })?;
 
         // list_by_domain returns mailboxes for a domain; filter by tenant in-memory
         let all_mailboxes = repo.list_by_domain(domain_id).await.map_err(|e| {
             error!("Database error while listing mailboxes: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to list mailboxes")
         })?;
 
         // Ensure only tenant's mailboxes are returned
@@ -121,7 +155,7 @@ pub async fn list_mailboxes(
             .await
             .map_err(|e| {
                 error!("Database error while fetching user: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                e.or_internal("Failed to fetch user")
             })?
             .filter(|u| u.tenant_id == tenant_id)
             .ok_or_else(|| {
@@ -129,13 +163,13 @@ pub async fn list_mailboxes(
                     "User {} not found or not owned by tenant {}",
                     user_id, tenant_id
                 );
-                StatusCode::NOT_FOUND
+                Error::NotFound("User not found".to_string())
             })?;
 
         // list_by_user returns mailboxes for a user; filter by tenant in-memory
         let all_mailboxes = repo.list_by_user(user_id).await.map_err(|e| {
             error!("Database error while listing mailboxes: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to list mailboxes")
         })?;
 
         // Ensure only tenant's mailboxes are returned
@@ -148,7 +182,7 @@ pub async fn list_mailboxes(
             .await
             .map_err(|e| {
                 error!("Database error while listing mailboxes: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                e.or_internal("Failed to list mailboxes")
             })?
     };
 
@@ -162,7 +196,7 @@ pub async fn get_mailbox(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<MailboxResponse>, StatusCode> {
+) -> ApiResult<Json<MailboxResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -173,26 +207,56 @@ pub async fn get_mailbox(
         .await
         .map_err(|e| {
             error!("Database error while fetching mailbox: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch mailbox")
         })?
         .ok_or_else(|| {
             warn!(
                 "Mailbox {} not found or not owned by tenant {}",
                 mailbox_id, tenant_id
             );
-            StatusCode::NOT_FOUND
+            Error::NotFound("Mailbox not found".to_string())
         })?;
 
     Ok(Json(mailbox.into()))
 }
 
+/// Get a mailbox's unread and total message counts
+///
+/// Reads the counters maintained on the mailbox row, so it is cheap enough
+/// to poll for the web UI unread badge.
+pub async fn get_unread_count(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<MailboxCounters>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+
+    let counters = MailboxRepository::new(state.db_pool.clone())
+        .counters(tenant_id, mailbox_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching mailbox counters: {}", e);
+            e.or_internal("Failed to fetch mailbox counters")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Mailbox {} not found or not owned by tenant {}",
+                mailbox_id, tenant_id
+            );
+            Error::NotFound("Mailbox not found".to_string())
+        })?;
+
+    Ok(Json(counters))
+}
+
 /// Create a new mailbox
 pub async fn create_mailbox(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<CreateMailboxRequest>,
-) -> Result<(StatusCode, Json<MailboxResponse>), StatusCode> {
+) -> ApiResult<(StatusCode, Json<MailboxResponse>)> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -203,14 +267,14 @@ pub async fn create_mailbox(
         .await
         .map_err(|e| {
             error!("Database error while fetching domain: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch domain")
         })?
         .ok_or_else(|| {
             warn!(
                 "Domain {} not found or not owned by tenant {}",
                 input.domain_id, tenant_id
             );
-            StatusCode::BAD_REQUEST
+            Error::Validation("Domain not found".to_string()).with_field("domain_id")
         })?;
 
     // Verify user belongs to tenant (if provided)
@@ -221,7 +285,7 @@ pub async fn create_mailbox(
             .await
             .map_err(|e| {
                 error!("Database error while fetching user: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                e.or_internal("Failed to fetch user")
             })?
             .filter(|u| u.tenant_id == tenant_id)
             .ok_or_else(|| {
@@ -229,19 +293,22 @@ pub async fn create_mailbox(
                     "User {} not found or not owned by tenant {}",
                     user_id, tenant_id
                 );
-                StatusCode::BAD_REQUEST
+                Error::Validation("User not found".to_string()).with_field("user_id")
             })?;
     }
 
     let repo = MailboxRepository::new(state.db_pool.clone());
 
     // Check if address already exists (global uniqueness - email addresses are globally unique)
-    if let Ok(Some(_)) = repo.find_by_address_for_tenant(tenant_id, &input.address).await {
-        return Err(StatusCode::CONFLICT);
+    if let Ok(Some(_)) = repo
+        .find_by_address_for_tenant(tenant_id, &input.address)
+        .await
+    {
+        return Err(Error::Conflict("Address already exists".to_string()).into());
     }
     // Also check globally to prevent duplicate addresses across tenants
     if let Ok(Some(_)) = repo.get_by_address(&input.address).await {
-        return Err(StatusCode::CONFLICT);
+        return Err(Error::Conflict("Address already exists".to_string()).into());
     }
 
     let create_input = CreateMailbox {
@@ -255,19 +322,91 @@ pub async fn create_mailbox(
 
     let mailbox = repo.create(create_input).await.map_err(|e| {
         error!("Database error while creating mailbox: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to create mailbox")
     })?;
 
+    // The mailbox is usable without its folders, so a failure is only logged
+    if let Err(e) = FolderProvisioner::new(state.db_pool.clone())
+        .provision(&mailbox)
+        .await
+    {
+        warn!(
+            "Failed to provision default folders for mailbox {}: {}",
+            mailbox.id, e
+        );
+    }
+
     Ok((StatusCode::CREATED, Json(mailbox.into())))
 }
 
+/// List the folders of a mailbox's owner
+///
+/// Special uses come from provisioning or are recognised from localized
+/// names, matching the attributes IMAP LIST reports.
+pub async fn list_folders(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<Vec<FolderResponse>>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+
+    let repo = MailboxRepository::new(state.db_pool.clone());
+    let mailbox = repo
+        .get(tenant_id, mailbox_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching mailbox: {}", e);
+            e.or_internal("Failed to fetch mailbox")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Mailbox {} not found or not owned by tenant {}",
+                mailbox_id, tenant_id
+            );
+            Error::NotFound("Mailbox not found".to_string())
+        })?;
+
+    let user_id = match mailbox.user_id {
+        Some(user_id) => user_id,
+        None => return Ok(Json(Vec::new())),
+    };
+
+    let mailboxes = repo.list_by_user(user_id).await.map_err(|e| {
+        error!("Database error while listing folders: {}", e);
+        e.or_internal("Failed to list folders")
+    })?;
+
+    // Folders are the owner's mailbox rows with a folder path
+    let mut folders: Vec<FolderResponse> = mailboxes
+        .into_iter()
+        .filter(|m| m.tenant_id == tenant_id)
+        .filter_map(|m| {
+            let name = m.folder_path?;
+            let special_use = SpecialUse::of_folder(m.special_use.as_deref(), &name);
+            Some(FolderResponse {
+                id: m.id,
+                name,
+                parent: m.parent_path,
+                special_use,
+                attribute: special_use.map(|use_| use_.attribute()),
+            })
+        })
+        .collect();
+
+    // Parents come before their subfolders
+    folders.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Json(folders))
+}
+
 /// Update mailbox quota
 pub async fn update_mailbox_quota(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<UpdateQuotaRequest>,
-) -> Result<Json<MailboxResponse>, StatusCode> {
+) -> ApiResult<Json<MailboxResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -279,21 +418,21 @@ pub async fn update_mailbox_quota(
         .await
         .map_err(|e| {
             error!("Database error while fetching mailbox: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch mailbox")
         })?
         .ok_or_else(|| {
             warn!(
                 "Mailbox {} not found or not owned by tenant {}",
                 mailbox_id, tenant_id
             );
-            StatusCode::NOT_FOUND
+            Error::NotFound("Mailbox not found".to_string())
         })?;
 
     repo.update_quota(mailbox_id, input.quota_bytes)
         .await
         .map_err(|e| {
             error!("Database error while updating mailbox quota: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to update mailbox quota")
         })?;
 
     let mailbox = repo
@@ -301,9 +440,9 @@ pub async fn update_mailbox_quota(
         .await
         .map_err(|e| {
             error!("Database error while fetching mailbox: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch mailbox")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Mailbox not found".to_string()))?;
 
     Ok(Json(mailbox.into()))
 }
@@ -313,7 +452,7 @@ pub async fn delete_mailbox(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -325,20 +464,143 @@ pub async fn delete_mailbox(
         .await
         .map_err(|e| {
             error!("Database error while fetching mailbox: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch mailbox")
         })?
         .ok_or_else(|| {
             warn!(
                 "Mailbox {} not found or not owned by tenant {}",
                 mailbox_id, tenant_id
             );
-            StatusCode::NOT_FOUND
+            Error::NotFound("Mailbox not found".to_string())
         })?;
 
     repo.delete(mailbox_id).await.map_err(|e| {
         error!("Database error while deleting mailbox: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to delete mailbox")
     })?;
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Get mailbox metadata (folder settings)
+///
+/// Returns shared entries and the private entries of the API key's user,
+/// or of the mailbox owner for tenant-level keys.
+pub async fn get_mailbox_metadata(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<MailboxMetadataResponse>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+
+    let mailbox = fetch_mailbox(&state, tenant_id, mailbox_id).await?;
+    let user_id = auth.user_id.or(mailbox.user_id);
+
+    let entries = MetadataRepository::new(state.db_pool.clone())
+        .list(tenant_id, user_id, Some(mailbox_id))
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching mailbox metadata: {}", e);
+            storage_error(e).or_internal("Failed to fetch mailbox metadata")
+        })?;
+
+    Ok(Json(MailboxMetadataResponse {
+        mailbox_id,
+        entries: entries.into_iter().map(|e| (e.entry, e.value)).collect(),
+    }))
+}
+
+/// Set or remove mailbox metadata entries
+pub async fn update_mailbox_metadata(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, mailbox_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<UpdateMailboxMetadataRequest>,
+) -> ApiResult<Json<MailboxMetadataResponse>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+
+    let mailbox = fetch_mailbox(&state, tenant_id, mailbox_id).await?;
+    let user_id = auth.user_id.or(mailbox.user_id);
+
+    let mut entries = Vec::with_capacity(input.entries.len());
+    for (entry, value) in input.entries {
+        let entry = normalize_entry(&entry).ok_or_else(|| {
+            warn!("Invalid metadata entry name: {}", entry);
+            Error::Validation(format!("Invalid metadata entry name {}", entry))
+                .with_field("entries")
+        })?;
+        if is_private_entry(&entry) && user_id.is_none() {
+            warn!("Private metadata entry {} requires a user", entry);
+            return Err(Error::Validation(format!(
+                "Private metadata entry {} requires a user",
+                entry
+            ))
+            .with_field("entries")
+            .into());
+        }
+        if value.as_ref().is_some_and(|v| v.len() > METADATA_MAX_VALUE_SIZE) {
+            return Err(ApiError::from(
+                Error::Validation(format!(
+                    "Metadata values are limited to {} bytes",
+                    METADATA_MAX_VALUE_SIZE
+                ))
+                .with_field("entries"),
+            )
+            .with_status(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        entries.push((entry, value));
+    }
+
+    let repo = MetadataRepository::new(state.db_pool.clone());
+    let stored = repo
+        .set_entries(tenant_id, user_id, Some(mailbox_id), &entries)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating mailbox metadata: {}", e);
+            storage_error(e).or_internal("Failed to update mailbox metadata")
+        })?;
+    if !stored {
+        warn!("Too many metadata entries for mailbox {}", mailbox_id);
+        return Err(
+            ApiError::from(Error::Validation("Too many metadata entries".to_string()))
+                .with_status(StatusCode::UNPROCESSABLE_ENTITY),
+        );
+    }
+
+    let entries = repo
+        .list(tenant_id, user_id, Some(mailbox_id))
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching mailbox metadata: {}", e);
+            storage_error(e).or_internal("Failed to fetch mailbox metadata")
+        })?;
+
+    Ok(Json(MailboxMetadataResponse {
+        mailbox_id,
+        entries: entries.into_iter().map(|e| (e.entry, e.value)).collect(),
+    }))
+}
+
+/// Fetch a mailbox owned by the tenant
+pub(crate) async fn fetch_mailbox(
+    state: &AppState,
+    tenant_id: Uuid,
+    mailbox_id: Uuid,
+) -> ApiResult<Mailbox> {
+    MailboxRepository::new(state.db_pool.clone())
+        .get(tenant_id, mailbox_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching mailbox: {}", e);
+            e.or_internal("Failed to fetch mailbox")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Mailbox {} not found or not owned by tenant {}",
+                mailbox_id, tenant_id
+            );
+            Error::NotFound("Mailbox not found".to_string()).into()
+        })
+}
@@ -0,0 +1,166 @@
+//! Message share handlers
+//!
+//! Read-only share links for single messages. The link's token is returned
+//! once, when the share is created; the page itself is served by the web UI
+//! at `/share/{token}`.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::share::share_lifetime;
+use mairust_storage::db::storage_error;
+use mairust_storage::{
+    AuditLogRepository, CreateMessageShare, MessageShare, MessageShareRepository, NewAuditLog,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::messages::find_message;
+
+/// Request body for sharing a message
+#[derive(Debug, Deserialize)]
+pub struct CreateShareRequest {
+    /// Lifetime of the link in hours (default 72, at most 720)
+    pub expires_in_hours: Option<i64>,
+}
+
+/// A new share with its link
+#[derive(Debug, Serialize)]
+pub struct CreatedShare {
+    #[serde(flatten)]
+    pub share: MessageShare,
+    /// Token of the link; it cannot be retrieved again
+    pub token: String,
+    /// Path of the shared page on the web UI
+    pub path: String,
+}
+
+/// Share a message through a read-only link
+///
+/// POST /api/v1/messages/:id/shares
+pub async fn create_share(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(message_id): Path<Uuid>,
+    Json(input): Json<CreateShareRequest>,
+) -> ApiResult<(StatusCode, Json<CreatedShare>)> {
+    let lifetime = share_lifetime(input.expires_in_hours).ok_or_else(|| {
+        warn!("Invalid share lifetime: {:?} hours", input.expires_in_hours);
+        Error::Validation("Lifetime must be 1 to 720 hours".to_string())
+            .with_field("expires_in_hours")
+    })?;
+    let message = find_message(&state, &auth, message_id).await?;
+
+    let (share, token) = MessageShareRepository::new(state.db_pool.clone())
+        .create(&CreateMessageShare {
+            tenant_id: auth.tenant_id,
+            message_id: message.id,
+            created_by: auth.user_id,
+            expires_at: chrono::Utc::now() + lifetime,
+        })
+        .await
+        .map_err(|e| {
+            error!("Database error while sharing message: {}", e);
+            storage_error(e).or_internal("Failed to share message")
+        })?;
+
+    record_share_event(&state, &auth, "message_share.created", &share).await?;
+    info!(
+        "Message {} shared until {} (tenant {})",
+        message.id, share.expires_at, auth.tenant_id
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedShare {
+            share,
+            path: format!("/share/{}", token),
+            token,
+        }),
+    ))
+}
+
+/// List the share links of a message, including expired and revoked ones
+///
+/// GET /api/v1/messages/:id/shares
+pub async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(message_id): Path<Uuid>,
+) -> ApiResult<Json<Vec<MessageShare>>> {
+    let message = find_message(&state, &auth, message_id).await?;
+
+    let shares = MessageShareRepository::new(state.db_pool.clone())
+        .list_for_message(auth.tenant_id, message.id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing message shares: {}", e);
+            storage_error(e).or_internal("Failed to list message shares")
+        })?;
+
+    Ok(Json(shares))
+}
+
+/// Revoke a share link
+///
+/// DELETE /api/v1/messages/:id/shares/:share_id
+pub async fn revoke_share(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((message_id, share_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    let share = MessageShareRepository::new(state.db_pool.clone())
+        .revoke(auth.tenant_id, message_id, share_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while revoking message share: {}", e);
+            storage_error(e).or_internal("Failed to revoke message share")
+        })?
+        .ok_or_else(|| Error::NotFound("Message share not found".to_string()))?;
+
+    record_share_event(&state, &auth, "message_share.revoked", &share).await?;
+    info!(
+        "Revoked share {} of message {} (tenant {})",
+        share.id, message_id, auth.tenant_id
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Shares make a message readable without signing in, so they leave a trail
+async fn record_share_event(
+    state: &AppState,
+    auth: &AuthContext,
+    event_type: &str,
+    share: &MessageShare,
+) -> ApiResult<()> {
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: Some(auth.tenant_id),
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: event_type.to_string(),
+            target_type: Some("message_share".to_string()),
+            target_id: Some(share.id.to_string()),
+            details: serde_json::json!({
+                "message_id": share.message_id,
+                "expires_at": share.expires_at,
+                "view_count": share.view_count,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for {}: {}", event_type, e);
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+
+    Ok(())
+}
@@ -2,17 +2,25 @@
 
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     Extension, Json,
 };
+use mairust_common::Error;
+use mairust_core::print::{export_filename, PrintableMessage};
+use mairust_core::spam::{JunkFiler, NotSpamOutcome, SpamFilter};
 use mairust_storage::repository::messages::MessageRepository as MessageRepositoryTrait;
-use mairust_storage::{MailboxRepository, MailboxRepositoryTrait, Message, MessageRepository};
+use mairust_storage::{
+    FileStorage, MailboxRepository, MailboxRepositoryTrait, Message, MessageRepository,
+};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::{error, warn};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::inbound_routes::serve_attachment;
 
 /// List messages query parameters
 #[derive(Debug, Deserialize)]
@@ -36,6 +44,8 @@ pub struct MessageSummary {
     pub id: Uuid,
     pub subject: Option<String>,
     pub from_address: Option<String>,
+    /// Body text on one line
+    pub snippet: Option<String>,
     pub received_at: chrono::DateTime<chrono::Utc>,
     pub seen: bool,
     pub flagged: bool,
@@ -48,6 +58,7 @@ impl From<Message> for MessageSummary {
             id: msg.id,
             subject: msg.subject,
             from_address: msg.from_address,
+            snippet: msg.snippet,
             received_at: msg.received_at,
             seen: msg.seen,
             flagged: msg.flagged,
@@ -125,6 +136,161 @@ pub async fn get_message(
     Ok(Json(message))
 }
 
+/// Download attachment `index` of a message
+///
+/// GET /api/v1/messages/:id/attachments/:index
+pub async fn download_attachment(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((message_id, index)): Path<(Uuid, usize)>,
+) -> ApiResult<Response> {
+    let repo = MessageRepository::new(state.db_pool.clone());
+
+    let message = MessageRepositoryTrait::get(&repo, auth.tenant_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching message: {}", e);
+            e.or_internal("Failed to get message")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Message {} not found or not owned by tenant {}",
+                message_id, auth.tenant_id
+            );
+            Error::NotFound("Message not found".to_string())
+        })?;
+
+    serve_attachment(
+        &state,
+        message.tenant_id,
+        &message.storage_path,
+        Some(message.received_at),
+        index,
+    )
+    .await
+}
+
+/// Content-Security-Policy of print views: inline styles only, no scripts
+/// and no remote content
+const PRINT_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'";
+
+/// Print-friendly HTML view of a message
+///
+/// GET /api/v1/messages/:id/print
+pub async fn print_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(message_id): Path<Uuid>,
+) -> ApiResult<Response> {
+    let printable = printable_message(&state, &auth, message_id).await?.1;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CONTENT_SECURITY_POLICY, PRINT_CSP),
+        ],
+        printable.to_html(),
+    )
+        .into_response())
+}
+
+/// Format of a downloaded message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// The message as stored (RFC 5322)
+    #[default]
+    Eml,
+    /// Printed headers, body text and attachment list
+    Pdf,
+}
+
+/// Export query parameters
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Download a message as an EML file or a PDF
+///
+/// GET /api/v1/messages/:id/export?format=eml|pdf
+pub async fn export_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(message_id): Path<Uuid>,
+    Query(query): Query<ExportQuery>,
+) -> ApiResult<Response> {
+    let (message, content_type, filename, contents) = match query.format {
+        ExportFormat::Eml => {
+            let message = find_message(&state, &auth, message_id).await?;
+            let raw = read_message(&state, &message.storage_path).await?;
+            let filename = export_filename(message.subject.as_deref(), "eml");
+            (message, "message/rfc822", filename, raw)
+        }
+        ExportFormat::Pdf => {
+            let (message, printable) = printable_message(&state, &auth, message_id).await?;
+            let filename = export_filename(message.subject.as_deref(), "pdf");
+            (message, "application/pdf", filename, printable.to_pdf())
+        }
+    };
+
+    info!(
+        "Message {} exported as {:?} by tenant {}",
+        message.id, query.format, auth.tenant_id
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        contents,
+    )
+        .into_response())
+}
+
+/// Message of the tenant with the parts of it that are printed
+async fn printable_message(
+    state: &AppState,
+    auth: &AuthContext,
+    message_id: Uuid,
+) -> ApiResult<(Message, PrintableMessage)> {
+    let message = find_message(state, auth, message_id).await?;
+    let raw = read_message(state, &message.storage_path).await?;
+    let printable = PrintableMessage::of_raw(&raw).ok_or_else(|| {
+        warn!("Message {} could not be parsed for printing", message_id);
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    Ok((message, printable))
+}
+
+/// Message of the authenticated tenant, 404 when there is none
+pub(crate) async fn find_message(
+    state: &AppState,
+    auth: &AuthContext,
+    message_id: Uuid,
+) -> Result<Message, StatusCode> {
+    let repo = MessageRepository::new(state.db_pool.clone());
+
+    MessageRepositoryTrait::get(&repo, auth.tenant_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching message: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Message {} not found or not owned by tenant {}",
+                message_id, auth.tenant_id
+            );
+            StatusCode::NOT_FOUND
+        })
+}
+
 /// Update message flags
 #[derive(Debug, Deserialize)]
 pub struct UpdateFlagsRequest {
@@ -209,3 +375,146 @@ pub async fn delete_message(
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Result of a "not spam" verdict
+#[derive(Debug, Serialize)]
+pub struct NotSpamResponse {
+    #[serde(flatten)]
+    pub outcome: NotSpamOutcome,
+    /// Whether the spam filter was trained with the verdict
+    pub learned: bool,
+}
+
+/// Mark a message as not spam
+///
+/// Moves a message filed into the Junk folder back to its original mailbox,
+/// removes the `spam` tag and trains the spam filter when rspamd is configured.
+pub async fn mark_not_spam(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(message_id): Path<Uuid>,
+) -> Result<Json<NotSpamResponse>, StatusCode> {
+    let outcome = JunkFiler::new(state.db_pool.clone())
+        .mark_not_spam(auth.tenant_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while marking message as not spam: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Message {} not found or not owned by tenant {}",
+                message_id, auth.tenant_id
+            );
+            StatusCode::NOT_FOUND
+        })?;
+
+    // Learning is best effort; the verdict itself is already recorded
+    let learned = match learn_ham(state.file_storage.as_ref(), &outcome.storage_path).await {
+        Ok(learned) => learned,
+        Err(e) => {
+            warn!("Failed to train spam filter with message {}: {}", message_id, e);
+            false
+        }
+    };
+
+    info!(
+        "Message {} marked as not spam (moved: {}, learned: {})",
+        message_id, outcome.moved, learned
+    );
+
+    Ok(Json(NotSpamResponse { outcome, learned }))
+}
+
+/// Train the spam filter with a message the user marked as ham
+async fn learn_ham(file_storage: &dyn FileStorage, storage_path: &str) -> anyhow::Result<bool> {
+    let spam_filter = SpamFilter::from_env();
+    if !spam_filter.has_rspamd() {
+        return Ok(false);
+    }
+
+    let raw = file_storage.read(storage_path).await?;
+    spam_filter.learn(&raw, false).await
+}
+
+/// Raw message from message storage
+///
+/// A file that cannot be read is not found, except the file of a tenant
+/// kept in another region, which is refused with [`Error::Residency`].
+pub(crate) async fn read_message(state: &AppState, storage_path: &str) -> ApiResult<Vec<u8>> {
+    state
+        .file_storage
+        .read(storage_path)
+        .await
+        .map_err(|e| match e {
+            Error::Residency(_) => e.into(),
+            e => {
+                warn!("Failed to read message file {}: {}", storage_path, e);
+                Error::NotFound("Message file not found".to_string()).into()
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mairust_common::config::{
+        DatabaseConfig, RegionConfig, ReplicationConfig, ResidencyConfig, StorageConfig,
+        TrackingConfig,
+    };
+    use mairust_core::SessionRegistry;
+    use mairust_storage::{DatabasePool, LocalStorage, Residency, ResidentStorage};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_read_message_of_other_region() {
+        let root = std::env::temp_dir().join(format!("mairust-residency-{}", Uuid::new_v4()));
+        let storage = |name: &str| StorageConfig {
+            path: root.join(name),
+            ..StorageConfig::default()
+        };
+        let db_pool = DatabasePool::connect_lazy(&DatabaseConfig {
+            backend: "postgres".to_string(),
+            url: Some("postgres://localhost/mairust".to_string()),
+            path: None,
+            max_connections: 1,
+            min_connections: 0,
+        })
+        .unwrap();
+        let residency = Arc::new(Residency::new(
+            db_pool.clone(),
+            ResidencyConfig {
+                region: Some("eu".to_string()),
+                regions: HashMap::from([
+                    ("eu".to_string(), RegionConfig { storage: storage("eu") }),
+                    ("us".to_string(), RegionConfig { storage: storage("us") }),
+                ]),
+            },
+        ));
+
+        // The file is there, but its tenant is kept in the other region
+        let local = LocalStorage::new(&storage("default")).unwrap();
+        let (tenant, mailbox, message) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::now_v7());
+        let path = local.message_path(tenant, mailbox, message);
+        local.store(&path, b"Subject: test\r\n\r\nbody\r\n").await.unwrap();
+        residency.set_region(tenant, Some("us".to_string()));
+
+        let state = AppState {
+            db_pool,
+            file_storage: Arc::new(ResidentStorage::new(Box::new(local), residency).unwrap()),
+            sessions: SessionRegistry::new(),
+            replication: ReplicationConfig::default(),
+            tracking: TrackingConfig::default(),
+            tracking_cname_target: "mail.example.com".to_string(),
+        };
+        let error = read_message(&state, &path).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::MISDIRECTED_REQUEST);
+        let response = error.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("WRONG_REGION"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
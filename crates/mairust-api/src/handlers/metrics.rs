@@ -0,0 +1,32 @@
+//! Prometheus metrics handler
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use mairust_core::Replication;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::auth::AppState;
+
+/// Metrics in the Prometheus text format
+///
+/// GET /metrics
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Result<Response, StatusCode> {
+    let status = Replication::new(state.db_pool.clone(), state.replication.clone())
+        .status()
+        .await
+        .map_err(|e| {
+            error!("Failed to read replication status for metrics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        status.metrics(Utc::now()),
+    )
+        .into_response())
+}
@@ -1,17 +1,26 @@
 //! Policy handlers
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Extension, Json,
 };
-use mairust_storage::{CreatePolicyRule, PolicyRepository, PolicyRepositoryTrait, PolicyRule};
+use chrono::{DateTime, Utc};
+use mairust_common::Error;
+use mairust_core::policy::{PolicyRateLimit, PolicySimulator, SimulationOptions, SimulationReport};
+use mairust_storage::db::storage_error;
+use mairust_storage::{
+    policy_changes, AuditLogRepository, CreatePolicyRule, NewAuditLog, PolicyAction,
+    PolicyActionType, PolicyActor, PolicyCondition, PolicyHistoryRepository, PolicyMatchFilter,
+    PolicyMatchRecord, PolicyRepository, PolicyRepositoryTrait, PolicyRule, PolicyVersion,
+};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
 
 /// Request body for creating a policy rule
 #[derive(Debug, Clone, Deserialize)]
@@ -36,6 +45,16 @@ pub struct UpdatePolicyRuleRequest {
     pub actions: Option<serde_json::Value>,
 }
 
+/// Request body for simulating an unsaved policy rule against recent mail
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulatePolicyRequest {
+    pub policy: CreatePolicyRuleRequest,
+    /// Days of mail to replay (default 7, at most 90)
+    pub days: Option<i64>,
+    /// Existing policy the proposed one would replace
+    pub replaces: Option<Uuid>,
+}
+
 /// Response for policy rule
 #[derive(Debug, Clone, Serialize)]
 pub struct PolicyRuleResponse {
@@ -43,19 +62,50 @@ pub struct PolicyRuleResponse {
     pub rule: PolicyRule,
 }
 
+/// Query parameters for policy matches
+#[derive(Debug, Clone, Deserialize)]
+pub struct PolicyMatchQuery {
+    /// Filter by policy
+    pub policy_id: Option<Uuid>,
+    /// Filter by Message-ID header, with or without angle brackets
+    pub message_id: Option<String>,
+    /// Filter by envelope sender
+    pub sender: Option<String>,
+    /// Filter by envelope recipient
+    pub recipient: Option<String>,
+    /// Filter by outcome: rejected, deferred, quarantined or accepted
+    pub outcome: Option<String>,
+    /// Start date filter
+    pub from: Option<DateTime<Utc>>,
+    /// End date filter
+    pub to: Option<DateTime<Utc>>,
+    /// Pagination offset
+    pub offset: Option<i64>,
+    /// Pagination limit (max 100)
+    pub limit: Option<i64>,
+}
+
+/// Policy match list response
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyMatchListResponse {
+    pub matches: Vec<PolicyMatchRecord>,
+    pub offset: i64,
+    pub limit: i64,
+}
+
 /// List all policy rules for a tenant
 pub async fn list_policies(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<Vec<PolicyRule>>, StatusCode> {
+) -> ApiResult<Json<Vec<PolicyRule>>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
 
     let policies = repo.list_by_tenant(tenant_id).await.map_err(|e| {
         error!("Database error while listing policies: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to list policies")
     })?;
 
     Ok(Json(policies))
@@ -66,15 +116,18 @@ pub async fn list_effective_policies(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, domain_id)): Path<(Uuid, Option<Uuid>)>,
-) -> Result<Json<Vec<PolicyRule>>, StatusCode> {
+) -> ApiResult<Json<Vec<PolicyRule>>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
 
-    let policies = repo.list_effective(tenant_id, domain_id).await.map_err(|e| {
-        error!("Database error while listing effective policies: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let policies = repo
+        .list_effective(tenant_id, domain_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing effective policies: {}", e);
+            e.or_internal("Failed to list effective policies")
+        })?;
 
     Ok(Json(policies))
 }
@@ -84,7 +137,7 @@ pub async fn get_policy(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, policy_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<PolicyRuleResponse>, StatusCode> {
+) -> ApiResult<Json<PolicyRuleResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
@@ -94,16 +147,16 @@ pub async fn get_policy(
         .await
         .map_err(|e| {
             error!("Database error while fetching policy: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch policy")
         })?
         .ok_or_else(|| {
             warn!("Policy {} not found", policy_id);
-            StatusCode::NOT_FOUND
+            Error::NotFound("Policy not found".to_string())
         })?;
 
     // Verify policy belongs to this tenant
     if policy.tenant_id != Some(tenant_id) && policy.tenant_id.is_some() {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(Error::PermissionDenied("Policy belongs to another tenant".to_string()).into());
     }
 
     Ok(Json(PolicyRuleResponse { rule: policy }))
@@ -115,24 +168,32 @@ pub async fn create_policy(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<CreatePolicyRuleRequest>,
-) -> Result<(StatusCode, Json<PolicyRuleResponse>), StatusCode> {
+) -> ApiResult<(StatusCode, Json<PolicyRuleResponse>)> {
     require_tenant_access(&auth, tenant_id)?;
 
     // Validate policy type
     if !is_valid_policy_type(&input.policy_type) {
         warn!("Invalid policy type: {}", input.policy_type);
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(
+            Error::Validation(format!("Invalid policy type {}", input.policy_type))
+                .with_field("policy_type")
+                .into(),
+        );
     }
 
     // Validate conditions and actions format
     if let Err(e) = validate_conditions(&input.conditions) {
         warn!("Invalid conditions: {}", e);
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(Error::Validation(format!("Invalid conditions: {}", e))
+            .with_field("conditions")
+            .into());
     }
 
     if let Err(e) = validate_actions(&input.actions) {
         warn!("Invalid actions: {}", e);
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(Error::Validation(format!("Invalid actions: {}", e))
+            .with_field("actions")
+            .into());
     }
 
     let repo = PolicyRepository::new(state.db_pool.clone());
@@ -150,12 +211,95 @@ pub async fn create_policy(
 
     let policy = repo.create(create_input).await.map_err(|e| {
         error!("Database error while creating policy: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to create policy")
     })?;
 
+    record_policy_change(&state, &auth, "created", None, &policy).await?;
     info!("Created policy rule: {}", policy.name);
 
-    Ok((StatusCode::CREATED, Json(PolicyRuleResponse { rule: policy })))
+    Ok((
+        StatusCode::CREATED,
+        Json(PolicyRuleResponse { rule: policy }),
+    ))
+}
+
+/// Replay recent mail through a proposed policy rule without saving it
+pub async fn simulate_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<SimulatePolicyRequest>,
+) -> ApiResult<Json<SimulationReport>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let policy = input.policy;
+    if !is_valid_policy_type(&policy.policy_type) {
+        warn!("Invalid policy type: {}", policy.policy_type);
+        return Err(
+            Error::Validation(format!("Invalid policy type {}", policy.policy_type))
+                .with_field("policy.policy_type")
+                .into(),
+        );
+    }
+    if let Err(e) = validate_conditions(&policy.conditions) {
+        warn!("Invalid conditions: {}", e);
+        return Err(Error::Validation(format!("Invalid conditions: {}", e))
+            .with_field("policy.conditions")
+            .into());
+    }
+    if let Err(e) = validate_actions(&policy.actions) {
+        warn!("Invalid actions: {}", e);
+        return Err(Error::Validation(format!("Invalid actions: {}", e))
+            .with_field("policy.actions")
+            .into());
+    }
+
+    // Only the tenant's own policies can be replaced
+    if let Some(replaces) = input.replaces {
+        let existing = PolicyRepository::new(state.db_pool.clone())
+            .get(replaces)
+            .await
+            .map_err(|e| {
+                error!("Database error: {}", e);
+                e.or_internal("Failed to fetch policy")
+            })?
+            .ok_or_else(|| Error::NotFound("Policy not found".to_string()))?;
+        if existing.tenant_id != Some(tenant_id) {
+            return Err(
+                Error::PermissionDenied("Policy belongs to another tenant".to_string()).into(),
+            );
+        }
+    }
+
+    let now = Utc::now();
+    let proposed = PolicyRule {
+        id: Uuid::now_v7(),
+        tenant_id: Some(tenant_id),
+        domain_id: policy.domain_id,
+        name: policy.name,
+        description: policy.description,
+        policy_type: policy.policy_type.to_lowercase(),
+        priority: policy.priority.unwrap_or(100),
+        enabled: true,
+        conditions: policy.conditions,
+        actions: policy.actions,
+        created_at: now,
+        updated_at: now,
+    };
+    let options = SimulationOptions {
+        days: input.days,
+        replaces: input.replaces,
+    };
+
+    let report = PolicySimulator::new(state.db_pool.clone())
+        .simulate(tenant_id, &proposed, &options)
+        .await
+        .map_err(|e| {
+            error!("Policy simulation failed: {}", e);
+            storage_error(e).or_internal("Failed to simulate policy")
+        })?;
+
+    Ok(Json(report))
 }
 
 /// Update a policy rule
@@ -164,7 +308,7 @@ pub async fn update_policy(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, policy_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<UpdatePolicyRuleRequest>,
-) -> Result<Json<PolicyRuleResponse>, StatusCode> {
+) -> ApiResult<Json<PolicyRuleResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
@@ -175,19 +319,23 @@ pub async fn update_policy(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch policy")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Policy not found".to_string()))?;
 
     // Verify ownership
     if existing.tenant_id != Some(tenant_id) {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(Error::PermissionDenied("Policy belongs to another tenant".to_string()).into());
     }
 
     // Validate if policy type is being updated
     if let Some(ref policy_type) = input.policy_type {
         if !is_valid_policy_type(policy_type) {
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(
+                Error::Validation(format!("Invalid policy type {}", policy_type))
+                    .with_field("policy_type")
+                    .into(),
+            );
         }
     }
 
@@ -195,7 +343,9 @@ pub async fn update_policy(
     if let Some(ref conditions) = input.conditions {
         if let Err(e) = validate_conditions(conditions) {
             warn!("Invalid conditions: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(Error::Validation(format!("Invalid conditions: {}", e))
+                .with_field("conditions")
+                .into());
         }
     }
 
@@ -203,26 +353,33 @@ pub async fn update_policy(
     if let Some(ref actions) = input.actions {
         if let Err(e) = validate_actions(actions) {
             warn!("Invalid actions: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(Error::Validation(format!("Invalid actions: {}", e))
+                .with_field("actions")
+                .into());
         }
     }
 
     let update_input = CreatePolicyRule {
         tenant_id: existing.tenant_id,
         domain_id: existing.domain_id,
-        name: input.name.unwrap_or(existing.name),
-        description: input.description.or(existing.description),
-        policy_type: input.policy_type.unwrap_or(existing.policy_type),
+        name: input.name.unwrap_or_else(|| existing.name.clone()),
+        description: input.description.or_else(|| existing.description.clone()),
+        policy_type: input
+            .policy_type
+            .unwrap_or_else(|| existing.policy_type.clone()),
         priority: input.priority.unwrap_or(existing.priority),
-        conditions: input.conditions.unwrap_or(existing.conditions),
-        actions: input.actions.unwrap_or(existing.actions),
+        conditions: input
+            .conditions
+            .unwrap_or_else(|| existing.conditions.clone()),
+        actions: input.actions.unwrap_or_else(|| existing.actions.clone()),
     };
 
     let policy = repo.update(policy_id, update_input).await.map_err(|e| {
         error!("Database error while updating policy: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to update policy")
     })?;
 
+    record_policy_change(&state, &auth, "updated", Some(&existing), &policy).await?;
     info!("Updated policy rule: {}", policy.name);
 
     Ok(Json(PolicyRuleResponse { rule: policy }))
@@ -233,7 +390,7 @@ pub async fn enable_policy(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, policy_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
@@ -244,19 +401,25 @@ pub async fn enable_policy(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch policy")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Policy not found".to_string()))?;
 
     if policy.tenant_id != Some(tenant_id) {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(Error::PermissionDenied("Policy belongs to another tenant".to_string()).into());
     }
 
     repo.enable(policy_id).await.map_err(|e| {
         error!("Database error while enabling policy: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to enable policy")
     })?;
 
+    let changed = PolicyRule {
+        enabled: true,
+        updated_at: Utc::now(),
+        ..policy.clone()
+    };
+    record_policy_change(&state, &auth, "enabled", Some(&policy), &changed).await?;
     info!("Enabled policy: {}", policy.name);
     Ok(StatusCode::OK)
 }
@@ -266,7 +429,7 @@ pub async fn disable_policy(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, policy_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
@@ -277,19 +440,25 @@ pub async fn disable_policy(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch policy")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Policy not found".to_string()))?;
 
     if policy.tenant_id != Some(tenant_id) {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(Error::PermissionDenied("Policy belongs to another tenant".to_string()).into());
     }
 
     repo.disable(policy_id).await.map_err(|e| {
         error!("Database error while disabling policy: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to disable policy")
     })?;
 
+    let changed = PolicyRule {
+        enabled: false,
+        updated_at: Utc::now(),
+        ..policy.clone()
+    };
+    record_policy_change(&state, &auth, "disabled", Some(&policy), &changed).await?;
     info!("Disabled policy: {}", policy.name);
     Ok(StatusCode::OK)
 }
@@ -299,7 +468,7 @@ pub async fn delete_policy(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, policy_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = PolicyRepository::new(state.db_pool.clone());
@@ -310,23 +479,140 @@ pub async fn delete_policy(
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            e.or_internal("Failed to fetch policy")
         })?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .ok_or_else(|| Error::NotFound("Policy not found".to_string()))?;
 
     if policy.tenant_id != Some(tenant_id) {
-        return Err(StatusCode::FORBIDDEN);
+        return Err(Error::PermissionDenied("Policy belongs to another tenant".to_string()).into());
     }
 
     repo.delete(policy_id).await.map_err(|e| {
         error!("Database error while deleting policy: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        e.or_internal("Failed to delete policy")
     })?;
 
+    record_policy_change(&state, &auth, "deleted", None, &policy).await?;
     info!("Deleted policy: {}", policy.name);
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// List the versions of a policy rule, newest first
+///
+/// Versions remain after the policy is deleted.
+pub async fn list_policy_versions(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, policy_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<Vec<PolicyVersion>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let versions = PolicyHistoryRepository::new(state.db_pool.clone())
+        .versions(tenant_id, policy_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing policy versions: {}", e);
+            storage_error(e).or_internal("Failed to list policy versions")
+        })?;
+    if versions.is_empty() {
+        return Err(Error::NotFound("Policy not found".to_string()).into());
+    }
+
+    Ok(Json(versions))
+}
+
+/// List the inbound policies that matched received messages, newest first
+pub async fn list_policy_matches(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<PolicyMatchQuery>,
+) -> ApiResult<Json<PolicyMatchListResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let filter = PolicyMatchFilter {
+        policy_id: query.policy_id,
+        message_id: query.message_id.map(|id| {
+            id.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        }),
+        sender: query.sender,
+        recipient: query.recipient,
+        outcome: query.outcome,
+        since: query.from,
+        until: query.to,
+    };
+
+    let matches = PolicyHistoryRepository::new(state.db_pool.clone())
+        .matches(tenant_id, &filter, limit, offset)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing policy matches: {}", e);
+            storage_error(e).or_internal("Failed to list policy matches")
+        })?;
+
+    Ok(Json(PolicyMatchListResponse {
+        matches,
+        offset,
+        limit,
+    }))
+}
+
+/// Record a change of a policy rule as a new version and in the audit log
+///
+/// `previous` is the policy before an update; the audit entry lists the
+/// fields that changed.
+async fn record_policy_change(
+    state: &AppState,
+    auth: &AuthContext,
+    change: &str,
+    previous: Option<&PolicyRule>,
+    policy: &PolicyRule,
+) -> ApiResult<()> {
+    let event_type = format!("policy.{}", change);
+    let version = PolicyHistoryRepository::new(state.db_pool.clone())
+        .record_version(change, policy, &PolicyActor::api_key(auth.api_key_id))
+        .await
+        .map_err(|e| {
+            error!("Failed to record version of policy {}: {}", policy.id, e);
+            storage_error(e).or_internal("Failed to record policy change")
+        })?;
+    let changes = match previous {
+        Some(previous) => policy_changes(
+            &serde_json::to_value(previous).unwrap_or_default(),
+            &version.policy,
+        ),
+        None => serde_json::json!({}),
+    };
+
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: policy.tenant_id,
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: event_type.clone(),
+            target_type: Some("policy".to_string()),
+            target_id: Some(policy.id.to_string()),
+            details: serde_json::json!({
+                "name": policy.name,
+                "version": version.version,
+                "changes": changes,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to record audit log for {}: {}", event_type, e);
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+
+    Ok(())
+}
+
 /// Validate policy type
 fn is_valid_policy_type(policy_type: &str) -> bool {
     matches!(
@@ -335,40 +621,58 @@ fn is_valid_policy_type(policy_type: &str) -> bool {
     )
 }
 
-/// Validate conditions format
+/// Validate conditions against the policy condition schema
+///
+/// The engine skips policies whose conditions it cannot read, so every
+/// condition, also inside `all_of`, `any_of` and `none_of` groups, has to
+/// parse.
 fn validate_conditions(conditions: &serde_json::Value) -> Result<(), String> {
-    if !conditions.is_array() {
-        return Err("Conditions must be an array".to_string());
-    }
+    let arr = conditions
+        .as_array()
+        .ok_or_else(|| "Conditions must be an array".to_string())?;
 
-    let arr = conditions.as_array().unwrap();
     for (i, condition) in arr.iter().enumerate() {
-        if !condition.is_object() {
-            return Err(format!("Condition {} must be an object", i));
-        }
+        validate_condition(condition, &i.to_string())?;
+    }
 
-        let obj = condition.as_object().unwrap();
-        if !obj.contains_key("condition_type") {
-            return Err(format!("Condition {} missing condition_type", i));
-        }
-        if !obj.contains_key("operator") {
-            return Err(format!("Condition {} missing operator", i));
+    Ok(())
+}
+
+/// Validate a condition or condition group; `path` names it in errors
+fn validate_condition(condition: &serde_json::Value, path: &str) -> Result<(), String> {
+    let obj = condition
+        .as_object()
+        .ok_or_else(|| format!("Condition {} must be an object", path))?;
+
+    if let Some((group, nodes)) = obj
+        .iter()
+        .find(|(key, _)| matches!(key.as_str(), "all_of" | "any_of" | "none_of"))
+    {
+        if obj.len() != 1 {
+            return Err(format!(
+                "Condition {}: {} must be the only key",
+                path, group
+            ));
         }
-        if !obj.contains_key("value") {
-            return Err(format!("Condition {} missing value", i));
+        let nodes = nodes
+            .as_array()
+            .ok_or_else(|| format!("Condition {}: {} must be an array", path, group))?;
+        for (i, node) in nodes.iter().enumerate() {
+            validate_condition(node, &format!("{}.{}[{}]", path, group, i))?;
         }
+        return Ok(());
     }
 
+    serde_json::from_value::<PolicyCondition>(condition.clone())
+        .map_err(|e| format!("Condition {}: {}", path, e))?;
     Ok(())
 }
 
-/// Validate actions format
+/// Validate actions against the policy action schema
 fn validate_actions(actions: &serde_json::Value) -> Result<(), String> {
-    if !actions.is_array() {
-        return Err("Actions must be an array".to_string());
-    }
-
-    let arr = actions.as_array().unwrap();
+    let arr = actions
+        .as_array()
+        .ok_or_else(|| "Actions must be an array".to_string())?;
     if arr.is_empty() {
         return Err("At least one action is required".to_string());
     }
@@ -377,10 +681,12 @@ fn validate_actions(actions: &serde_json::Value) -> Result<(), String> {
         if !action.is_object() {
             return Err(format!("Action {} must be an object", i));
         }
-
-        let obj = action.as_object().unwrap();
-        if !obj.contains_key("action_type") {
-            return Err(format!("Action {} missing action_type", i));
+        let action = serde_json::from_value::<PolicyAction>(action.clone())
+            .map_err(|e| format!("Action {}: {}", i, e))?;
+        if matches!(action.action_type, PolicyActionType::RateLimit)
+            && PolicyRateLimit::from_parameters(Uuid::nil(), &action.parameters).is_none()
+        {
+            return Err(format!("Action {}: invalid rate_limit parameters", i));
         }
     }
 
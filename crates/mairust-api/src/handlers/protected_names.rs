@@ -0,0 +1,130 @@
+//! Protected display name handlers
+//!
+//! Names a tenant protects from display-name impersonation. The check is
+//! turned on with the `display_name_protection` tenant setting.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::types::EmailAddress;
+use mairust_common::Error;
+use mairust_storage::db::storage_error;
+use mairust_storage::repository::protected_names::{CreateProtectedName, ProtectedName};
+use mairust_storage::ProtectedNameRepository;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Longest protected display name
+const MAX_DISPLAY_NAME_LEN: usize = 255;
+
+/// Request body for protecting a display name
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateProtectedNameRequest {
+    pub display_name: String,
+    /// The person's own address, allowed to use the name
+    pub email: Option<String>,
+}
+
+/// List the protected display names of a tenant
+pub async fn list_protected_names(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+) -> ApiResult<Json<Vec<ProtectedName>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let names = ProtectedNameRepository::new(state.db_pool.clone())
+        .list(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing protected names: {}", e);
+            storage_error(e).or_internal("Failed to list protected names")
+        })?;
+
+    Ok(Json(names))
+}
+
+/// Protect a display name
+pub async fn create_protected_name(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<CreateProtectedNameRequest>,
+) -> ApiResult<(StatusCode, Json<ProtectedName>)> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let display_name = input.display_name.trim();
+    if display_name.is_empty()
+        || display_name.len() > MAX_DISPLAY_NAME_LEN
+        || !display_name.chars().any(char::is_alphanumeric)
+    {
+        warn!("Invalid protected display name: {:?}", input.display_name);
+        return Err(Error::Validation("Invalid display name".to_string())
+            .with_field("display_name")
+            .into());
+    }
+    if let Some(email) = &input.email {
+        if EmailAddress::parse(email.trim()).is_none() {
+            warn!("Invalid protected name address: {}", email);
+            return Err(
+                Error::Validation(format!("Invalid email address {}", email))
+                    .with_field("email")
+                    .into(),
+            );
+        }
+    }
+
+    let name = ProtectedNameRepository::new(state.db_pool.clone())
+        .create(CreateProtectedName {
+            tenant_id,
+            display_name: display_name.to_string(),
+            email: input.email,
+        })
+        .await
+        .map_err(|e| {
+            error!("Database error while creating protected name: {}", e);
+            storage_error(e).or_internal("Failed to create protected name")
+        })?
+        .ok_or_else(|| {
+            warn!("Display name {} is already protected", display_name);
+            Error::Conflict("Display name is already protected".to_string())
+        })?;
+
+    info!(
+        "Protected display name {} (tenant {})",
+        name.display_name, tenant_id
+    );
+
+    Ok((StatusCode::CREATED, Json(name)))
+}
+
+/// Stop protecting a display name
+pub async fn delete_protected_name(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, name_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let deleted = ProtectedNameRepository::new(state.db_pool.clone())
+        .delete(tenant_id, name_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while deleting protected name: {}", e);
+            storage_error(e).or_internal("Failed to delete protected name")
+        })?;
+
+    if deleted {
+        info!("Deleted protected name {} (tenant {})", name_id, tenant_id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::NotFound("Protected name not found".to_string()).into())
+    }
+}
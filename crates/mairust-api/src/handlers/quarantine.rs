@@ -0,0 +1,224 @@
+//! Quarantine handlers
+//!
+//! Messages held by the virus scan, the spam filter or an inbound policy
+//! are kept out of their mailbox until an administrator releases them into
+//! it or deletes them.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::print::{PrintableMessage, PrintedAttachment};
+use mairust_storage::db::storage_error;
+use mairust_storage::{QuarantineRepository, QuarantinedMessage};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::messages::read_message;
+
+/// Query parameters for listing quarantined messages
+#[derive(Debug, Deserialize)]
+pub struct ListQuarantineQuery {
+    /// Only messages addressed to this user's mailboxes
+    pub user_id: Option<Uuid>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+fn default_limit() -> i64 {
+    50
+}
+
+/// Attachment of a previewed message
+#[derive(Debug, Serialize)]
+pub struct AttachmentPreview {
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub size: usize,
+}
+
+impl From<PrintedAttachment> for AttachmentPreview {
+    fn from(attachment: PrintedAttachment) -> Self {
+        Self {
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size: attachment.size,
+        }
+    }
+}
+
+/// A quarantined message with its headers and body text
+#[derive(Debug, Serialize)]
+pub struct QuarantinePreview {
+    #[serde(flatten)]
+    pub message: QuarantinedMessage,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    /// Date header in RFC 822 form
+    pub date: Option<String>,
+    /// Body text; empty when the message has none
+    pub body: String,
+    /// Attachments are listed, never served
+    pub attachments: Vec<AttachmentPreview>,
+}
+
+/// Result of releasing a message
+#[derive(Debug, Serialize)]
+pub struct ReleaseResponse {
+    pub id: Uuid,
+    /// Mailbox the message was released into
+    pub mailbox_id: Uuid,
+}
+
+/// List the quarantined messages of a tenant, newest first
+pub async fn list_quarantine(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<ListQuarantineQuery>,
+) -> ApiResult<Json<Vec<QuarantinedMessage>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let limit = query.limit.clamp(1, 200);
+    let offset = query.offset.max(0);
+    let messages = QuarantineRepository::new(state.db_pool.clone())
+        .list(tenant_id, query.user_id, limit, offset)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing quarantined messages: {}", e);
+            storage_error(e).or_internal("Failed to list quarantined messages")
+        })?;
+
+    Ok(Json(messages))
+}
+
+/// Preview a quarantined message
+///
+/// The body is returned as text; attachments are only described, so that
+/// nothing held for a virus can be downloaded through the API.
+pub async fn get_quarantined_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, message_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<QuarantinePreview>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let message = find_quarantined(&state, tenant_id, message_id).await?;
+    let raw = read_message(&state, &message.storage_path).await?;
+    let printable = PrintableMessage::of_raw(&raw).ok_or_else(|| {
+        warn!("Quarantined message {} could not be parsed", message_id);
+        ApiError::from(Error::Validation(
+            "The quarantined message could not be parsed".to_string(),
+        ))
+        .with_status(StatusCode::UNPROCESSABLE_ENTITY)
+    })?;
+
+    Ok(Json(QuarantinePreview {
+        message,
+        to: printable.to,
+        cc: printable.cc,
+        date: printable.date,
+        body: printable.body,
+        attachments: printable.attachments.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Release a quarantined message into the mailbox it was addressed to
+pub async fn release_quarantined_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, message_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<ReleaseResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let mailbox_id = QuarantineRepository::new(state.db_pool.clone())
+        .release(tenant_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while releasing quarantined message: {}", e);
+            storage_error(e).or_internal("Failed to release quarantined message")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Quarantined message {} not found in tenant {}",
+                message_id, tenant_id
+            );
+            Error::NotFound("Quarantined message not found".to_string())
+        })?;
+
+    info!(
+        "Quarantined message {} released into mailbox {}",
+        message_id, mailbox_id
+    );
+
+    Ok(Json(ReleaseResponse {
+        id: message_id,
+        mailbox_id,
+    }))
+}
+
+/// Delete a quarantined message
+pub async fn delete_quarantined_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, message_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let storage_path = QuarantineRepository::new(state.db_pool.clone())
+        .delete(tenant_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while deleting quarantined message: {}", e);
+            storage_error(e).or_internal("Failed to delete quarantined message")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Quarantined message {} not found in tenant {}",
+                message_id, tenant_id
+            );
+            Error::NotFound("Quarantined message not found".to_string())
+        })?;
+
+    // The message is gone either way; a file left behind is only logged
+    if let Err(e) = state.file_storage.delete(&storage_path).await {
+        warn!(
+            "Failed to remove the file of quarantined message {}: {}",
+            message_id, e
+        );
+    }
+    info!("Quarantined message {} deleted", message_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Quarantined message of the tenant, 404 when there is none
+async fn find_quarantined(
+    state: &AppState,
+    tenant_id: Uuid,
+    message_id: Uuid,
+) -> ApiResult<QuarantinedMessage> {
+    let message = QuarantineRepository::new(state.db_pool.clone())
+        .get(tenant_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching quarantined message: {}", e);
+            storage_error(e).or_internal("Failed to get quarantined message")
+        })?
+        .ok_or_else(|| {
+            warn!(
+                "Quarantined message {} not found in tenant {}",
+                message_id, tenant_id
+            );
+            Error::NotFound("Quarantined message not found".to_string())
+        })?;
+    Ok(message)
+}
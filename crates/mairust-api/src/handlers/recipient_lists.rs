@@ -6,6 +6,8 @@ use axum::{
     Extension, Json,
 };
 use chrono::{DateTime, Utc};
+use mairust_common::Error;
+use mairust_storage::db::db_error;
 use mairust_storage::models::{
     CreateRecipient, CreateRecipientList, Recipient, RecipientList, RecipientStatus,
     UpdateRecipient, UpdateRecipientList,
@@ -17,13 +19,7 @@ use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
-
-/// Error response
-#[derive(Debug, Clone, Serialize)]
-pub struct ErrorResponse {
-    pub error: String,
-    pub message: String,
-}
+use crate::error::ApiResult;
 
 /// Query parameters for listing
 #[derive(Debug, Deserialize)]
@@ -176,16 +172,8 @@ pub async fn list_recipient_lists(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Query(query): Query<ListQuery>,
-) -> Result<Json<RecipientListsResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<RecipientListsResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = RecipientListRepository::new(state.db_pool.pool().clone());
 
@@ -194,13 +182,7 @@ pub async fn list_recipient_lists(
         .await
         .map_err(|e| {
             error!("Failed to list recipient lists: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to list recipient lists".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to list recipient lists")
         })?;
 
     let total = repo.count_by_tenant(tenant_id).await.unwrap_or(0);
@@ -223,25 +205,11 @@ pub async fn create_recipient_list(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<CreateRecipientListRequest>,
-) -> Result<(StatusCode, Json<RecipientListResponse>), (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<(StatusCode, Json<RecipientListResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
 
     if input.name.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Name is required".to_string(),
-            }),
-        ));
+        return Err(Error::Validation("Name is required".to_string()).into());
     }
 
     let repo = RecipientListRepository::new(state.db_pool.pool().clone());
@@ -255,13 +223,7 @@ pub async fn create_recipient_list(
 
     let list = repo.create(create_input).await.map_err(|e| {
         error!("Failed to create recipient list: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to create recipient list".to_string(),
-            }),
-        )
+        db_error(e).or_internal("Failed to create recipient list")
     })?;
 
     info!("Created recipient list {} for tenant {}", list.id, tenant_id);
@@ -276,16 +238,8 @@ pub async fn get_recipient_list(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<RecipientListResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<RecipientListResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = RecipientListRepository::new(state.db_pool.pool().clone());
 
@@ -294,23 +248,9 @@ pub async fn get_recipient_list(
         .await
         .map_err(|e| {
             error!("Failed to get recipient list: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to get recipient list".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to get recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     Ok(Json(RecipientListResponse::from(list)))
 }
@@ -323,16 +263,8 @@ pub async fn update_recipient_list(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<UpdateRecipientListRequest>,
-) -> Result<Json<RecipientListResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<RecipientListResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = RecipientListRepository::new(state.db_pool.pool().clone());
 
@@ -347,23 +279,9 @@ pub async fn update_recipient_list(
         .await
         .map_err(|e| {
             error!("Failed to update recipient list: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to update recipient list".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to update recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     info!("Updated recipient list {}", list_id);
 
@@ -377,41 +295,21 @@ pub async fn delete_recipient_list(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
 
     let repo = RecipientListRepository::new(state.db_pool.pool().clone());
 
     let deleted = repo.delete(list_id, tenant_id).await.map_err(|e| {
         error!("Failed to delete recipient list: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to delete recipient list".to_string(),
-            }),
-        )
+        db_error(e).or_internal("Failed to delete recipient list")
     })?;
 
     if deleted {
         info!("Deleted recipient list {}", list_id);
         Ok(StatusCode::NO_CONTENT)
     } else {
-        Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not_found".to_string(),
-                message: "Recipient list not found".to_string(),
-            }),
-        ))
+        Err(Error::NotFound("Recipient list not found".to_string()).into())
     }
 }
 
@@ -427,16 +325,8 @@ pub async fn list_recipients(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id)): Path<(Uuid, Uuid)>,
     Query(query): Query<ListQuery>,
-) -> Result<Json<RecipientsResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<RecipientsResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Verify list belongs to tenant
     let list_repo = RecipientListRepository::new(state.db_pool.pool().clone());
@@ -445,23 +335,9 @@ pub async fn list_recipients(
         .await
         .map_err(|e| {
             error!("Failed to verify recipient list: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify recipient list".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to verify recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     let repo = RecipientRepository::new(state.db_pool.pool().clone());
 
@@ -474,13 +350,7 @@ pub async fn list_recipients(
         .await
         .map_err(|e| {
             error!("Failed to list recipients: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to list recipients".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to list recipients")
         })?;
 
     let total = repo.count_by_list(list_id, status).await.unwrap_or(0);
@@ -503,26 +373,12 @@ pub async fn add_recipient(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<AddRecipientRequest>,
-) -> Result<(StatusCode, Json<RecipientResponse>), (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<(StatusCode, Json<RecipientResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Validate email
     if !input.email.contains('@') {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Invalid email address".to_string(),
-            }),
-        ));
+        return Err(Error::Validation("Invalid email address".to_string()).into());
     }
 
     // Verify list belongs to tenant
@@ -532,23 +388,9 @@ pub async fn add_recipient(
         .await
         .map_err(|e| {
             error!("Failed to verify recipient list: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify recipient list".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to verify recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     let repo = RecipientRepository::new(state.db_pool.pool().clone());
 
@@ -560,19 +402,14 @@ pub async fn add_recipient(
     };
 
     let recipient = repo.create(create_input).await.map_err(|e| {
-        let message = if e.to_string().contains("unique") {
-            "Email already exists in this list"
-        } else {
-            "Failed to add recipient"
-        };
         error!("Failed to add recipient: {}", e);
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "add_error".to_string(),
-                message: message.to_string(),
-            }),
-        )
+        match db_error(e).inner() {
+            Error::Conflict(_) => {
+                Error::Validation("Email already exists in this list".to_string())
+                    .with_field("email")
+            }
+            _ => Error::Validation("Failed to add recipient".to_string()),
+        }
     })?;
 
     info!("Added recipient {} to list {}", recipient.id, list_id);
@@ -587,62 +424,30 @@ pub async fn get_recipient(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id, recipient_id)): Path<(Uuid, Uuid, Uuid)>,
-) -> Result<Json<RecipientResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<RecipientResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Verify list belongs to tenant
     let list_repo = RecipientListRepository::new(state.db_pool.pool().clone());
     list_repo
         .get_by_tenant(tenant_id, list_id)
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify recipient list".to_string(),
-                }),
-            )
+        .map_err(|e| {
+            error!("Database error while verifying recipient list: {}", e);
+            db_error(e).or_internal("Failed to verify recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     let repo = RecipientRepository::new(state.db_pool.pool().clone());
 
-    let recipient = repo.get(recipient_id).await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to get recipient".to_string(),
-            }),
-        )
+    let recipient = repo.get(recipient_id).await.map_err(|e| {
+        error!("Database error while getting recipient: {}", e);
+        db_error(e).or_internal("Failed to get recipient")
     })?;
 
     match recipient {
         Some(r) if r.recipient_list_id == list_id => Ok(Json(RecipientResponse::from(r))),
-        _ => Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not_found".to_string(),
-                message: "Recipient not found".to_string(),
-            }),
-        )),
+        _ => Err(Error::NotFound("Recipient not found".to_string()).into()),
     }
 }
 
@@ -654,62 +459,30 @@ pub async fn update_recipient(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id, recipient_id)): Path<(Uuid, Uuid, Uuid)>,
     Json(input): Json<UpdateRecipientRequest>,
-) -> Result<Json<RecipientResponse>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<RecipientResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Verify list belongs to tenant
     let list_repo = RecipientListRepository::new(state.db_pool.pool().clone());
     list_repo
         .get_by_tenant(tenant_id, list_id)
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify recipient list".to_string(),
-                }),
-            )
+        .map_err(|e| {
+            error!("Database error while verifying recipient list: {}", e);
+            db_error(e).or_internal("Failed to verify recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     let repo = RecipientRepository::new(state.db_pool.pool().clone());
 
     // Verify recipient belongs to list
-    let existing = repo.get(recipient_id).await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to get recipient".to_string(),
-            }),
-        )
+    let existing = repo.get(recipient_id).await.map_err(|e| {
+        error!("Database error while getting recipient: {}", e);
+        db_error(e).or_internal("Failed to get recipient")
     })?;
 
     if existing.is_none() || existing.as_ref().map(|r| r.recipient_list_id) != Some(list_id) {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not_found".to_string(),
-                message: "Recipient not found".to_string(),
-            }),
-        ));
+        return Err(Error::NotFound("Recipient not found".to_string()).into());
     }
 
     let status = input
@@ -727,23 +500,9 @@ pub async fn update_recipient(
         .await
         .map_err(|e| {
             error!("Failed to update recipient: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to update recipient".to_string(),
-                }),
-            )
+            db_error(e).or_internal("Failed to update recipient")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient not found".to_string()))?;
 
     info!("Updated recipient {}", recipient_id);
 
@@ -757,86 +516,42 @@ pub async fn delete_recipient(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id, recipient_id)): Path<(Uuid, Uuid, Uuid)>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
 
     // Verify list belongs to tenant
     let list_repo = RecipientListRepository::new(state.db_pool.pool().clone());
     list_repo
         .get_by_tenant(tenant_id, list_id)
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify recipient list".to_string(),
-                }),
-            )
+        .map_err(|e| {
+            error!("Database error while verifying recipient list: {}", e);
+            db_error(e).or_internal("Failed to verify recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     let repo = RecipientRepository::new(state.db_pool.pool().clone());
 
     // Verify recipient belongs to list
-    let existing = repo.get(recipient_id).await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to get recipient".to_string(),
-            }),
-        )
+    let existing = repo.get(recipient_id).await.map_err(|e| {
+        error!("Database error while getting recipient: {}", e);
+        db_error(e).or_internal("Failed to get recipient")
     })?;
 
     if existing.is_none() || existing.as_ref().map(|r| r.recipient_list_id) != Some(list_id) {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not_found".to_string(),
-                message: "Recipient not found".to_string(),
-            }),
-        ));
+        return Err(Error::NotFound("Recipient not found".to_string()).into());
     }
 
     let deleted = repo.delete(recipient_id).await.map_err(|e| {
         error!("Failed to delete recipient: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "internal_error".to_string(),
-                message: "Failed to delete recipient".to_string(),
-            }),
-        )
+        db_error(e).or_internal("Failed to delete recipient")
     })?;
 
     if deleted {
         info!("Deleted recipient {}", recipient_id);
         Ok(StatusCode::NO_CONTENT)
     } else {
-        Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not_found".to_string(),
-                message: "Recipient not found".to_string(),
-            }),
-        ))
+        Err(Error::NotFound("Recipient not found".to_string()).into())
     }
 }
 
@@ -848,25 +563,11 @@ pub async fn import_recipients(
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, list_id)): Path<(Uuid, Uuid)>,
     Json(input): Json<ImportRecipientsRequest>,
-) -> Result<Json<ImportResult>, (StatusCode, Json<ErrorResponse>)> {
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+) -> ApiResult<Json<ImportResult>> {
+    require_tenant_access(&auth, tenant_id)?;
 
     if input.recipients.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "No recipients provided".to_string(),
-            }),
-        ));
+        return Err(Error::Validation("No recipients provided".to_string()).into());
     }
 
     // Verify list belongs to tenant
@@ -874,24 +575,11 @@ pub async fn import_recipients(
     list_repo
         .get_by_tenant(tenant_id, list_id)
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify recipient list".to_string(),
-                }),
-            )
+        .map_err(|e| {
+            error!("Database error while verifying recipient list: {}", e);
+            db_error(e).or_internal("Failed to verify recipient list")
         })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "not_found".to_string(),
-                    message: "Recipient list not found".to_string(),
-                }),
-            )
-        })?;
+        .ok_or_else(|| Error::NotFound("Recipient list not found".to_string()))?;
 
     let repo = RecipientRepository::new(state.db_pool.pool().clone());
 
@@ -907,19 +595,10 @@ pub async fn import_recipients(
 
     let skipped = total - recipients.len();
 
-    let imported = repo
-        .create_batch(list_id, recipients)
-        .await
-        .map_err(|e| {
-            error!("Failed to import recipients: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "import_error".to_string(),
-                    message: "Failed to import recipients".to_string(),
-                }),
-            )
-        })?;
+    let imported = repo.create_batch(list_id, recipients).await.map_err(|e| {
+        error!("Failed to import recipients: {}", e);
+        db_error(e).or_internal("Failed to import recipients")
+    })?;
 
     info!(
         "Imported {} recipients to list {} ({} skipped)",
@@ -2,10 +2,10 @@
 
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
     Extension, Json,
 };
 use chrono::{DateTime, Utc};
+use mairust_common::Error;
 use mairust_core::search::{
     client::{MeilisearchClient, MeilisearchConfig as CoreMeilisearchConfig},
     indexer::{MessageIndexer, MessageSearchHit, SearchOptions},
@@ -16,6 +16,7 @@ use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
 
 /// Search query parameters
 #[derive(Debug, Clone, Deserialize)]
@@ -63,7 +64,7 @@ pub struct IndexStatusResponse {
 }
 
 /// Get indexer from config (helper function)
-fn create_indexer() -> Option<MessageIndexer> {
+pub(crate) fn create_indexer() -> Option<MessageIndexer> {
     // Read Meilisearch config from environment or default
     let url = std::env::var("MEILISEARCH_URL").unwrap_or_else(|_| "http://localhost:7700".to_string());
     let api_key = std::env::var("MEILISEARCH_API_KEY").ok();
@@ -94,18 +95,18 @@ pub async fn search_messages(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Query(query): Query<SearchQuery>,
-) -> Result<Json<SearchResponse>, StatusCode> {
+) -> ApiResult<Json<SearchResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let indexer = create_indexer().ok_or_else(|| {
         warn!("Search not enabled");
-        StatusCode::SERVICE_UNAVAILABLE
+        Error::Unavailable("Search is not enabled".to_string())
     })?;
 
     // Check if search service is available
     if !indexer.is_available().await {
         error!("Meilisearch service unavailable");
-        return Err(StatusCode::SERVICE_UNAVAILABLE);
+        return Err(Error::Unavailable("Search service is unavailable".to_string()).into());
     }
 
     // Parse date filters
@@ -122,7 +123,9 @@ pub async fn search_messages(
     });
 
     // Parse tags
-    let tags = query.tags.map(|t| t.split(',').map(|s| s.trim().to_string()).collect());
+    let tags = query
+        .tags
+        .map(|t| t.split(',').map(|s| s.trim().to_string()).collect());
 
     // Limit the maximum results
     let limit = query.limit.map(|l| l.min(100)).unwrap_or(20);
@@ -144,7 +147,7 @@ pub async fn search_messages(
 
     let result = indexer.search(options).await.map_err(|e| {
         error!("Search error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        Error::Internal("Failed to search messages".to_string())
     })?;
 
     info!(
@@ -169,7 +172,7 @@ pub async fn search_status(
     State(_state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<IndexStatusResponse>, StatusCode> {
+) -> ApiResult<Json<IndexStatusResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     let indexer = match create_indexer() {
@@ -212,17 +215,22 @@ pub async fn reindex_messages(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(request): Json<ReindexRequest>,
-) -> Result<Json<ReindexResponse>, StatusCode> {
+) -> ApiResult<Json<ReindexResponse>> {
     require_tenant_access(&auth, tenant_id)?;
 
     if request.message_ids.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(
+            Error::Validation("message_ids must not be empty".to_string())
+                .with_field("message_ids")
+                .into(),
+        );
     }
 
-    let indexer = create_indexer().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let indexer =
+        create_indexer().ok_or_else(|| Error::Unavailable("Search is not enabled".to_string()))?;
 
     if !indexer.is_available().await {
-        return Err(StatusCode::SERVICE_UNAVAILABLE);
+        return Err(Error::Unavailable("Search service is unavailable".to_string()).into());
     }
 
     // In a real implementation, we would fetch message data from the database
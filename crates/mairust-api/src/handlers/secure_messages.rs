@@ -0,0 +1,149 @@
+//! Secure message handlers
+//!
+//! Secure messages are sent through the send endpoint with `secure` set;
+//! these handlers let the sender follow them: when the links were used,
+//! wrong passcodes, and revoking a link before it expires. The messages
+//! themselves are read on the web UI at `/secure/{token}`.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_storage::db::storage_error;
+use mairust_storage::{
+    AuditLogRepository, NewAuditLog, SecureMessage, SecureMessageAccess, SecureMessageRepository,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Query parameters for listing secure messages
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecureMessageQuery {
+    /// Pagination offset
+    pub offset: Option<i64>,
+    /// Pagination limit (default 50, max 200)
+    pub limit: Option<i64>,
+}
+
+/// A secure message with its access log
+#[derive(Debug, Clone, Serialize)]
+pub struct SecureMessageDetail {
+    #[serde(flatten)]
+    pub message: SecureMessage,
+    pub access_log: Vec<SecureMessageAccess>,
+}
+
+/// List the secure messages of a tenant, newest first
+///
+/// GET /api/v1/tenants/:tenant_id/secure-messages
+pub async fn list_secure_messages(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Query(query): Query<SecureMessageQuery>,
+) -> ApiResult<Json<Vec<SecureMessage>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let messages = SecureMessageRepository::new(state.db_pool.clone())
+        .list(
+            tenant_id,
+            query.limit.unwrap_or(50).clamp(1, 200),
+            query.offset.unwrap_or(0).max(0),
+        )
+        .await
+        .map_err(|e| {
+            error!("Database error while listing secure messages: {}", e);
+            storage_error(e).or_internal("Failed to list secure messages")
+        })?;
+
+    Ok(Json(messages))
+}
+
+/// Get a secure message with its access log
+///
+/// GET /api/v1/tenants/:tenant_id/secure-messages/:id
+pub async fn get_secure_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<SecureMessageDetail>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let repository = SecureMessageRepository::new(state.db_pool.clone());
+    let message = repository
+        .get(tenant_id, id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching secure message: {}", e);
+            storage_error(e).or_internal("Failed to get secure message")
+        })?
+        .ok_or_else(|| Error::NotFound("Secure message not found".to_string()))?;
+    let access_log = repository.access_log(message.id).await.map_err(|e| {
+        error!(
+            "Database error while fetching secure message access log: {}",
+            e
+        );
+        storage_error(e).or_internal("Failed to get secure message access log")
+    })?;
+
+    Ok(Json(SecureMessageDetail {
+        message,
+        access_log,
+    }))
+}
+
+/// Revoke the link of a secure message
+///
+/// DELETE /api/v1/tenants/:tenant_id/secure-messages/:id
+pub async fn revoke_secure_message(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let message = SecureMessageRepository::new(state.db_pool.clone())
+        .revoke(tenant_id, id)
+        .await
+        .map_err(|e| {
+            error!("Database error while revoking secure message: {}", e);
+            storage_error(e).or_internal("Failed to revoke secure message")
+        })?
+        .ok_or_else(|| Error::NotFound("Secure message not found".to_string()))?;
+
+    AuditLogRepository::new(state.db_pool.clone())
+        .record(NewAuditLog {
+            tenant_id: Some(tenant_id),
+            actor_type: "api_key".to_string(),
+            actor_id: Some(auth.api_key_id.to_string()),
+            event_type: "secure_message.revoked".to_string(),
+            target_type: Some("secure_message".to_string()),
+            target_id: Some(message.id.to_string()),
+            details: serde_json::json!({
+                "recipient": message.recipient,
+                "open_count": message.open_count,
+            }),
+            ip_address: None,
+        })
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to record audit log for secure_message.revoked: {}",
+                e
+            );
+            storage_error(e).or_internal("Failed to record audit log")
+        })?;
+    info!(
+        "Revoked secure message {} to {} (tenant {})",
+        message.id, message.recipient, tenant_id
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -7,13 +7,26 @@ use axum::{
 };
 use base64::Engine;
 use chrono::Utc;
-use mairust_storage::{DatabasePool, MailboxRepository, MailboxRepositoryTrait};
+use mairust_common::types::DeliveryLane;
+use mairust_common::Error;
+use mairust_core::queue::{DeliveryPreflight, DnsCache, PreflightReport, PreflightRequest};
+use mairust_core::secure_message::{
+    hash_passcode, notification_text, secure_message_lifetime, secure_message_url,
+    validate_passcode, web_base_url, SecureMessageKey,
+};
+use mairust_core::DkimKeyring;
+use mairust_storage::db::{db_error, storage_error};
+use mairust_storage::{
+    CorrespondentRepository, CreateSecureMessage, DatabasePool, JobRepository, MailboxRepository,
+    QueueCount, QueueFailure, QueueStats, SecureMessageRepository, TenantQueueLatency,
+};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::{error, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
 
 /// Maximum total message size (10MB)
 const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
@@ -67,6 +80,25 @@ pub struct SendEmailRequest {
     pub scheduled_at: Option<chrono::DateTime<Utc>>,
     /// Custom Message-ID (generated if not provided)
     pub message_id: Option<String>,
+    /// Only relay over verified TLS (RFC 8689 REQUIRETLS)
+    #[serde(default)]
+    pub require_tls: bool,
+    /// Queue lane: `transactional` (default), `campaign` or `bulk`
+    #[serde(default)]
+    pub lane: DeliveryLane,
+    /// Send as a secure message: recipients are sent a link to read it on
+    /// the web UI instead of the message itself
+    pub secure: Option<SecureOptions>,
+}
+
+/// Options of a secure message
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecureOptions {
+    /// Passcode the sender shares with the recipients some other way; when
+    /// not set, recipients have a one-time code emailed to them
+    pub passcode: Option<String>,
+    /// Lifetime of the links in hours (default 168, at most 720)
+    pub expires_in_hours: Option<i64>,
 }
 
 /// Response after queuing an email
@@ -82,6 +114,9 @@ pub struct SendEmailResponse {
     pub scheduled_at: Option<chrono::DateTime<Utc>>,
     /// Queue position (if queued)
     pub queue_id: Option<Uuid>,
+    /// Secure messages stored for the recipients, one each
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub secure_message_ids: Vec<Uuid>,
 }
 
 /// Validate email address format
@@ -110,129 +145,96 @@ pub async fn send_email(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(input): Json<SendEmailRequest>,
-) -> Result<(StatusCode, Json<SendEmailResponse>), (StatusCode, Json<ErrorResponse>)> {
+) -> ApiResult<(StatusCode, Json<SendEmailResponse>)> {
     // Verify the authenticated user has access to this tenant
-    require_tenant_access(&auth, tenant_id).map_err(|status| {
-        (
-            status,
-            Json(ErrorResponse {
-                error: "forbidden".to_string(),
-                message: "Not authorized for this tenant".to_string(),
-            }),
-        )
-    })?;
+    require_tenant_access(&auth, tenant_id)?;
 
     // Validate input
     if input.to.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "At least one recipient is required".to_string(),
-            }),
-        ));
+        return Err(
+            Error::Validation("At least one recipient is required".to_string())
+                .with_field("to")
+                .into(),
+        );
     }
 
     // Validate recipient count
     let total_recipients = input.to.len() + input.cc.len() + input.bcc.len();
     if total_recipients > MAX_RECIPIENTS {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: format!(
-                    "Too many recipients: {} (max {})",
-                    total_recipients, MAX_RECIPIENTS
-                ),
-            }),
-        ));
+        return Err(Error::Validation(format!(
+            "Too many recipients: {} (max {})",
+            total_recipients, MAX_RECIPIENTS
+        ))
+        .into());
     }
 
     // Validate attachment count
     if input.attachments.len() > MAX_ATTACHMENTS {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: format!(
-                    "Too many attachments: {} (max {})",
-                    input.attachments.len(),
-                    MAX_ATTACHMENTS
-                ),
-            }),
-        ));
+        return Err(Error::Validation(format!(
+            "Too many attachments: {} (max {})",
+            input.attachments.len(),
+            MAX_ATTACHMENTS
+        ))
+        .with_field("attachments")
+        .into());
     }
 
     // Validate attachments
     for (i, attachment) in input.attachments.iter().enumerate() {
         // Validate base64 content
         if let Err(e) = validate_attachment(attachment) {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "validation_error".to_string(),
-                    message: format!("Invalid attachment {}: {}", i + 1, e),
-                }),
-            ));
+            return Err(
+                Error::Validation(format!("Invalid attachment {}: {}", i + 1, e))
+                    .with_field("attachments")
+                    .into(),
+            );
         }
     }
 
     if input.text.is_none() && input.html.is_none() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Either text or html body is required".to_string(),
-            }),
-        ));
+        return Err(Error::Validation("Either text or html body is required".to_string()).into());
+    }
+
+    if input.secure.is_some() && !input.attachments.is_empty() {
+        return Err(
+            Error::Validation("Secure messages cannot have attachments".to_string())
+                .with_field("attachments")
+                .into(),
+        );
     }
 
     // Validate sender email format
     if !is_valid_email(&input.from) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: "Invalid sender email address".to_string(),
-            }),
-        ));
+        return Err(
+            Error::Validation("Invalid sender email address".to_string())
+                .with_field("from")
+                .into(),
+        );
     }
 
     // Validate all recipient email formats
     for recipient in input.to.iter().chain(input.cc.iter()).chain(input.bcc.iter()) {
         if !is_valid_email(recipient) {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "validation_error".to_string(),
-                    message: format!("Invalid recipient email address: {}", recipient),
-                }),
-            ));
+            return Err(Error::Validation(format!(
+                "Invalid recipient email address: {}",
+                recipient
+            ))
+            .into());
         }
     }
 
     // Verify sender mailbox belongs to tenant (tenant-scoped query)
     let mailbox_repo = MailboxRepository::new(state.db_pool.clone());
-    let _sender_mailbox = mailbox_repo
+    let sender_mailbox = mailbox_repo
         .find_by_address_for_tenant(tenant_id, &input.from.to_lowercase())
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "internal_error".to_string(),
-                    message: "Failed to verify sender mailbox".to_string(),
-                }),
-            )
+        .map_err(|e| {
+            error!("Database error while verifying sender mailbox: {}", e);
+            e.or_internal("Failed to verify sender mailbox")
         })?
         .ok_or_else(|| {
-            (
-                StatusCode::FORBIDDEN,
-                Json(ErrorResponse {
-                    error: "forbidden".to_string(),
-                    message: "Sender address not found or not authorized for this tenant"
-                        .to_string(),
-                }),
+            Error::PermissionDenied(
+                "Sender address not found or not authorized for this tenant".to_string(),
             )
         })?;
 
@@ -252,37 +254,59 @@ pub async fn send_email(
     // Calculate body size and validate
     let body_size = raw_message.len();
     if body_size > MAX_MESSAGE_SIZE {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "validation_error".to_string(),
-                message: format!(
-                    "Message too large: {} bytes (max {} bytes)",
-                    body_size, MAX_MESSAGE_SIZE
-                ),
-            }),
-        ));
+        return Err(Error::Validation(format!(
+            "Message too large: {} bytes (max {} bytes)",
+            body_size, MAX_MESSAGE_SIZE
+        ))
+        .into());
     }
 
-    // Store message in queue
-    let queue_id = enqueue_message(
-        &state.db_pool,
-        message_id,
-        tenant_id,
-        &input,
-        &raw_message,
-        body_size as i64,
-    )
-    .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "queue_error".to_string(),
-                message: format!("Failed to queue message: {}", e),
-            }),
-        )
-    })?;
+    // Store message in queue; a secure message is kept sealed and its
+    // recipients are sent links instead
+    let (queue_id, secure_message_ids) = match input.secure {
+        Some(ref options) => {
+            let ids = queue_secure_messages(
+                &state.db_pool,
+                tenant_id,
+                auth.user_id,
+                &input,
+                options,
+                &raw_message,
+            )
+            .await?;
+            (None, ids)
+        }
+        None => {
+            let queue_id = enqueue_message(
+                &state.db_pool,
+                message_id,
+                &message_id_header,
+                tenant_id,
+                &input,
+                &raw_message,
+                body_size as i64,
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to queue message: {}", e);
+                Error::Internal("Failed to queue message".to_string())
+            })?;
+            (Some(queue_id), Vec::new())
+        }
+    };
+
+    // Remember the recipients for the sender's composer autocomplete
+    if let Some(user_id) = sender_mailbox.user_id {
+        let correspondents = CorrespondentRepository::new(state.db_pool.clone());
+        for recipient in input.to.iter().chain(input.cc.iter()).chain(input.bcc.iter()) {
+            if let Err(e) = correspondents
+                .record(tenant_id, user_id, recipient, None)
+                .await
+            {
+                warn!("Failed to record correspondent {}: {}", recipient, e);
+            }
+        }
+    }
 
     Ok((
         StatusCode::ACCEPTED,
@@ -291,26 +315,135 @@ pub async fn send_email(
             status: "queued".to_string(),
             recipients_count,
             scheduled_at: input.scheduled_at,
-            queue_id: Some(queue_id),
+            queue_id,
+            secure_message_ids,
         }),
     ))
 }
 
-/// Error response
-#[derive(Debug, Clone, Serialize)]
-pub struct ErrorResponse {
-    pub error: String,
-    pub message: String,
+/// Store the message sealed for each recipient and queue their
+/// notifications, returning the ids of the secure messages
+async fn queue_secure_messages(
+    db_pool: &DatabasePool,
+    tenant_id: Uuid,
+    created_by: Option<Uuid>,
+    input: &SendEmailRequest,
+    options: &SecureOptions,
+    raw_message: &[u8],
+) -> ApiResult<Vec<Uuid>> {
+    let key = SecureMessageKey::from_env()
+        .map_err(|e| {
+            error!("Invalid secure message key: {}", e);
+            storage_error(e).or_internal("Secure messages are misconfigured")
+        })?
+        .ok_or_else(|| {
+            Error::Unavailable("Secure messages are not enabled on this server".to_string())
+        })?;
+    let lifetime = secure_message_lifetime(options.expires_in_hours).ok_or_else(|| {
+        Error::Validation(format!(
+            "Invalid secure message lifetime: {:?} hours",
+            options.expires_in_hours
+        ))
+        .with_field("secure.expires_in_hours")
+    })?;
+    let passcode_hash = match &options.passcode {
+        Some(passcode) => {
+            validate_passcode(passcode)
+                .map_err(|e| Error::Validation(e.to_string()).with_field("secure.passcode"))?;
+            Some(hash_passcode(passcode).map_err(|e| Error::Internal(e.to_string()))?)
+        }
+        None => None,
+    };
+    let expires_at = input.scheduled_at.unwrap_or_else(Utc::now) + lifetime;
+    let base_url = web_base_url();
+
+    let repository = SecureMessageRepository::new(db_pool.clone());
+    let mut ids = Vec::new();
+    for recipient in input
+        .to
+        .iter()
+        .chain(input.cc.iter())
+        .chain(input.bcc.iter())
+    {
+        let id = Uuid::now_v7();
+        let sealed_message = key.seal(id, raw_message).map_err(|e| {
+            error!("Failed to seal secure message: {}", e);
+            Error::Internal("Failed to store secure message".to_string())
+        })?;
+        let (secure_message, token) = repository
+            .create(&CreateSecureMessage {
+                id,
+                tenant_id,
+                sender: input.from.clone(),
+                recipient: recipient.clone(),
+                sealed_message,
+                passcode_hash: passcode_hash.clone(),
+                created_by,
+                expires_at,
+            })
+            .await
+            .map_err(|e| {
+                error!("Failed to store secure message: {}", e);
+                storage_error(e).or_internal("Failed to store secure message")
+            })?;
+
+        let notification = SendEmailRequest {
+            from: input.from.clone(),
+            to: vec![recipient.clone()],
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            subject: Some(format!("Secure message from {}", input.from)),
+            text: Some(notification_text(
+                &input.from,
+                &secure_message_url(&base_url, &token),
+                expires_at,
+                secure_message.uses_emailed_codes(),
+            )),
+            html: None,
+            headers: std::collections::HashMap::new(),
+            attachments: Vec::new(),
+            reply_to: input.reply_to.clone(),
+            scheduled_at: input.scheduled_at,
+            message_id: None,
+            require_tls: input.require_tls,
+            lane: input.lane,
+            secure: None,
+        };
+        let notification_id = Uuid::now_v7();
+        let domain = extract_domain(&input.from).unwrap_or("localhost");
+        let message_id_header = format!(
+            "<{}.{}@{}>",
+            notification_id,
+            Utc::now().timestamp_millis(),
+            domain
+        );
+        let raw_notification = build_message(&notification, &message_id_header)?;
+        enqueue_message(
+            db_pool,
+            notification_id,
+            &message_id_header,
+            tenant_id,
+            &notification,
+            &raw_notification,
+            raw_notification.len() as i64,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to queue secure message notification: {}", e);
+            Error::Internal("Failed to queue message".to_string())
+        })?;
+
+        ids.push(secure_message.id);
+    }
+
+    Ok(ids)
 }
 
 /// Build RFC 5322 compliant message
 ///
 /// NOTE: This is a simplified implementation. In production, consider using
 /// a proper MIME library like `lettre` for robust email construction.
-fn build_message(
-    input: &SendEmailRequest,
-    message_id_header: &str,
-) -> Result<Vec<u8>, (StatusCode, Json<ErrorResponse>)> {
+fn build_message(input: &SendEmailRequest, message_id_header: &str) -> ApiResult<Vec<u8>> {
     let mut message = String::new();
 
     // Required headers (sanitized to prevent header injection)
@@ -351,13 +484,9 @@ fn build_message(
     for (name, value) in &input.headers {
         // Only allow safe header names (alphanumeric and hyphen)
         if !is_safe_header_name(name) {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "validation_error".to_string(),
-                    message: format!("Invalid header name: {}", name),
-                }),
-            ));
+            return Err(Error::Validation(format!("Invalid header name: {}", name))
+                .with_field("headers")
+                .into());
         }
         // Don't allow overriding critical headers
         let lower_name = name.to_lowercase();
@@ -451,6 +580,7 @@ fn build_message(
 async fn enqueue_message(
     db_pool: &DatabasePool,
     message_id: Uuid,
+    message_id_header: &str,
     tenant_id: Uuid,
     input: &SendEmailRequest,
     raw_message: &[u8],
@@ -465,22 +595,33 @@ async fn enqueue_message(
     all_recipients.extend(input.cc.clone());
     all_recipients.extend(input.bcc.clone());
 
+    // Sign once, so that every attempt sends the same signature
+    let signed = DkimKeyring::new(db_pool.clone())
+        .try_sign(tenant_id, raw_message)
+        .await;
+    let raw_message = signed.as_deref().unwrap_or(raw_message);
+
     // Create delivery job payload
     let payload = serde_json::json!({
         "message_id": message_id,
+        "message_id_header": message_id_header,
         "tenant_id": tenant_id,
         "from": input.from,
         "to": all_recipients,
         "subject": input.subject,
         "body_size": body_size,
         "raw_message_base64": base64::engine::general_purpose::STANDARD.encode(raw_message),
+        "require_tls": input.require_tls,
+        "dkim_signed": signed.is_some(),
+        "lane": input.lane,
     });
 
     // Insert job into queue
     sqlx::query(
         r#"
-        INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at,
+                          tenant_id, lane)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         "#,
     )
     .bind(job_id)
@@ -491,6 +632,8 @@ async fn enqueue_message(
     .bind(5i32)
     .bind(scheduled_at)
     .bind(now)
+    .bind(tenant_id)
+    .bind(input.lane.as_str())
     .execute(db_pool.pool())
     .await
     .map_err(|e| e.to_string())?;
@@ -505,15 +648,15 @@ pub async fn get_send_queue(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<QueueStatusResponse>, StatusCode> {
+) -> ApiResult<Json<QueueStatusResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
-    let stats = get_queue_stats(&state.db_pool, tenant_id)
+    let stats = get_queue_stats(&state.db_pool, Some(tenant_id))
         .await
         .map_err(|e| {
             error!("Database error while getting queue stats: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            storage_error(e).or_internal("Failed to get queue stats")
         })?;
 
     Ok(Json(stats))
@@ -526,60 +669,55 @@ pub struct QueueStatusResponse {
     pub processing: i64,
     pub completed: i64,
     pub failed: i64,
+    /// Deliveries that exceeded their queue lifetime
+    pub dead_letter: i64,
+    /// Counts by status, lane and priority
+    pub by_priority: Vec<QueueCount>,
+    /// Creation time of the oldest pending job
+    pub oldest_pending_at: Option<chrono::DateTime<Utc>>,
+    /// Age of the oldest pending job in seconds
+    pub oldest_pending_age_secs: Option<i64>,
+    /// Deliveries completed in the last minute
+    pub throughput_per_minute: i64,
+    /// Deliveries completed in the last hour
+    pub completed_last_hour: i64,
+    /// Most recent failed deliveries
+    pub recent_failures: Vec<QueueFailure>,
+    /// Queue latency per tenant, longest waiting tenants first
+    pub tenant_latency: Vec<TenantQueueLatency>,
 }
 
-/// Get queue statistics
-async fn get_queue_stats(db_pool: &DatabasePool, tenant_id: Uuid) -> Result<QueueStatusResponse, String> {
-    let pool = db_pool.pool();
-    let tenant_id_str = tenant_id.to_string();
-
-    // Filter by tenant_id from the job payload using JSONB query
-    let pending: (i64,) = sqlx::query_as(
-        r#"SELECT COUNT(*) FROM jobs
-           WHERE status = 'pending' AND queue = 'delivery'
-           AND payload->>'tenant_id' = $1"#,
-    )
-    .bind(&tenant_id_str)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| e.to_string())?;
-
-    let processing: (i64,) = sqlx::query_as(
-        r#"SELECT COUNT(*) FROM jobs
-           WHERE status = 'processing' AND queue = 'delivery'
-           AND payload->>'tenant_id' = $1"#,
-    )
-    .bind(&tenant_id_str)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| e.to_string())?;
-
-    let completed: (i64,) = sqlx::query_as(
-        r#"SELECT COUNT(*) FROM jobs
-           WHERE status = 'completed' AND queue = 'delivery'
-           AND payload->>'tenant_id' = $1"#,
-    )
-    .bind(&tenant_id_str)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| e.to_string())?;
+impl From<QueueStats> for QueueStatusResponse {
+    fn from(stats: QueueStats) -> Self {
+        Self {
+            pending: stats.total("pending"),
+            processing: stats.total("processing"),
+            completed: stats.total("completed"),
+            failed: stats.total("failed"),
+            dead_letter: stats.total("dead_letter"),
+            oldest_pending_age_secs: stats
+                .oldest_pending_at
+                .map(|t| (Utc::now() - t).num_seconds().max(0)),
+            oldest_pending_at: stats.oldest_pending_at,
+            throughput_per_minute: stats.completed_last_minute,
+            completed_last_hour: stats.completed_last_hour,
+            by_priority: stats.counts,
+            recent_failures: stats.recent_failures,
+            tenant_latency: stats.tenant_latency,
+        }
+    }
+}
 
-    let failed: (i64,) = sqlx::query_as(
-        r#"SELECT COUNT(*) FROM jobs
-           WHERE status = 'failed' AND queue = 'delivery'
-           AND payload->>'tenant_id' = $1"#,
-    )
-    .bind(&tenant_id_str)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| e.to_string())?;
+/// Get delivery queue statistics, for one tenant or system-wide
+pub(crate) async fn get_queue_stats(
+    db_pool: &DatabasePool,
+    tenant_id: Option<Uuid>,
+) -> anyhow::Result<QueueStatusResponse> {
+    let stats = JobRepository::new(db_pool.clone())
+        .queue_stats("delivery", tenant_id)
+        .await?;
 
-    Ok(QueueStatusResponse {
-        pending: pending.0,
-        processing: processing.0,
-        completed: completed.0,
-        failed: failed.0,
-    })
+    Ok(stats.into())
 }
 
 /// Get status of a specific queued message
@@ -589,7 +727,7 @@ pub async fn get_message_status(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, message_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<MessageStatusResponse>, StatusCode> {
+) -> ApiResult<Json<MessageStatusResponse>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -611,12 +749,13 @@ pub async fn get_message_status(
     .await
     .map_err(|e| {
         error!("Database error while fetching message status: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        db_error(e).or_internal("Failed to fetch message status")
     })?;
 
     match job {
         Some((status, attempts, last_error, scheduled_at)) => Ok(Json(MessageStatusResponse {
             message_id,
+            next_attempt_at: (status == "pending").then_some(scheduled_at),
             status,
             attempts,
             last_error,
@@ -627,7 +766,7 @@ pub async fn get_message_status(
                 "Message {} not found or not owned by tenant {}",
                 message_id, tenant_id
             );
-            Err(StatusCode::NOT_FOUND)
+            Err(Error::NotFound("Message not found".to_string()).into())
         }
     }
 }
@@ -640,6 +779,39 @@ pub struct MessageStatusResponse {
     pub attempts: i32,
     pub last_error: Option<String>,
     pub scheduled_at: Option<chrono::DateTime<Utc>>,
+    /// Time of the next delivery attempt, while the message is queued
+    pub next_attempt_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// Report what would happen to a message to a recipient, without sending
+///
+/// POST /api/v1/tenants/:tenant_id/send/preflight
+pub async fn preflight(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(request): Json<PreflightRequest>,
+) -> ApiResult<Json<PreflightReport>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    if !is_valid_email(request.recipient.trim()) {
+        return Err(Error::Validation("Invalid recipient address".to_string())
+            .with_field("recipient")
+            .into());
+    }
+
+    let hostname =
+        std::env::var("MAIRUST_HOSTNAME").unwrap_or_else(|_| "mail.example.com".to_string());
+    let dns = Arc::new(DnsCache::new(Default::default()));
+    let report = DeliveryPreflight::new(state.db_pool.clone(), dns, hostname)
+        .check(tenant_id, &request)
+        .await
+        .map_err(|e| {
+            error!("Preflight check for tenant {} failed: {}", tenant_id, e);
+            storage_error(e).or_internal("Failed to run preflight checks")
+        })?;
+
+    Ok(Json(report))
 }
 
 /// Validate an attachment
@@ -0,0 +1,250 @@
+//! Spam rule handlers
+//!
+//! Rules a tenant adds to the rule-based spam filter. `scope` is the rule
+//! type: `header`, `body`, `from`, `subject` and `url` match `pattern` as a
+//! regular expression, `has_header` and `missing_header` name a header and
+//! `body_length` gives the longest body that matches. A rule named like a
+//! built-in rule (such as `SUBJECT_ALL_CAPS`) replaces it. Changes apply to
+//! received mail within a minute.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::spam::{RuleType, SpamRule};
+use mairust_storage::db::storage_error;
+use mairust_storage::{CreateSpamRule, SpamRuleRecord, SpamRuleRepository};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+
+/// Most spam rules a tenant may have
+const MAX_RULES: usize = 200;
+
+/// Request body for creating or replacing a spam rule
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpamRuleRequest {
+    /// Rule name, reported as the symbol of a match
+    pub name: String,
+    pub description: Option<String>,
+    pub scope: String,
+    pub pattern: String,
+    /// Score added when the rule matches; negative scores lower it
+    pub score: f64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl SpamRuleRequest {
+    /// Check the request and turn it into repository input
+    fn validate(self) -> Result<CreateSpamRule, Error> {
+        let name = self.name.trim().to_string();
+        if name.is_empty()
+            || name.len() > 100
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            warn!("Invalid spam rule name: {:?}", self.name);
+            return Err(Error::Validation(
+                "Name must be 1 to 100 letters, digits, '_' or '-'".to_string(),
+            )
+            .with_field("name"));
+        }
+        let Some(rule_type) = RuleType::parse(&self.scope) else {
+            warn!("Invalid spam rule scope: {:?}", self.scope);
+            return Err(
+                Error::Validation(format!("Unknown scope: {}", self.scope)).with_field("scope")
+            );
+        };
+        if !self.score.is_finite() || self.score.abs() > 100.0 {
+            warn!("Invalid spam rule score: {}", self.score);
+            return Err(
+                Error::Validation("Score must be between -100 and 100".to_string())
+                    .with_field("score"),
+            );
+        }
+
+        let rule = SpamRule {
+            name,
+            description: self.description.clone().unwrap_or_default(),
+            rule_type,
+            pattern: self.pattern,
+            score: self.score,
+            enabled: self.enabled,
+        };
+        if let Err(e) = rule.validate() {
+            warn!("Invalid spam rule {}: {}", rule.name, e);
+            return Err(Error::Validation(e.to_string()).with_field("pattern"));
+        }
+
+        Ok(CreateSpamRule {
+            name: rule.name,
+            description: self.description.filter(|d| !d.trim().is_empty()),
+            scope: rule.rule_type.as_str().to_string(),
+            pattern: rule.pattern,
+            score: rule.score,
+            enabled: rule.enabled,
+        })
+    }
+}
+
+/// List the spam rules of a tenant, by name
+pub async fn list_spam_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+) -> ApiResult<Json<Vec<SpamRuleRecord>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let rules = SpamRuleRepository::new(state.db_pool.clone())
+        .list(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing spam rules: {}", e);
+            storage_error(e).or_internal("Failed to list spam rules")
+        })?;
+
+    Ok(Json(rules))
+}
+
+/// Get a spam rule of a tenant
+pub async fn get_spam_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, rule_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<SpamRuleRecord>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let rule = SpamRuleRepository::new(state.db_pool.clone())
+        .get(tenant_id, rule_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching spam rule: {}", e);
+            storage_error(e).or_internal("Failed to get spam rule")
+        })?
+        .ok_or_else(|| Error::NotFound("Spam rule not found".to_string()))?;
+
+    Ok(Json(rule))
+}
+
+/// Create a spam rule for a tenant
+pub async fn create_spam_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<SpamRuleRequest>,
+) -> ApiResult<(StatusCode, Json<SpamRuleRecord>)> {
+    require_tenant_access(&auth, tenant_id)?;
+    let input = input.validate()?;
+
+    let repo = SpamRuleRepository::new(state.db_pool.clone());
+    let existing = repo.list(tenant_id).await.map_err(|e| {
+        error!("Database error while listing spam rules: {}", e);
+        storage_error(e).or_internal("Failed to list spam rules")
+    })?;
+    if existing.len() >= MAX_RULES {
+        warn!(
+            "Tenant {} already has {} spam rules",
+            tenant_id,
+            existing.len()
+        );
+        return Err(Error::Conflict(format!(
+            "A tenant can have at most {} spam rules",
+            MAX_RULES
+        ))
+        .into());
+    }
+
+    let rule = repo
+        .create(tenant_id, input)
+        .await
+        .map_err(|e| {
+            error!("Database error while creating spam rule: {}", e);
+            storage_error(e).or_internal("Failed to create spam rule")
+        })?
+        .ok_or_else(|| {
+            warn!("Tenant {} already has a spam rule of that name", tenant_id);
+            Error::Conflict("A spam rule of that name already exists".to_string())
+                .with_field("name")
+        })?;
+
+    info!(
+        "Created spam rule {} '{}' (tenant {})",
+        rule.id, rule.name, tenant_id
+    );
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+/// Replace a spam rule of a tenant
+pub async fn update_spam_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, rule_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<SpamRuleRequest>,
+) -> ApiResult<Json<SpamRuleRecord>> {
+    require_tenant_access(&auth, tenant_id)?;
+    let input = input.validate()?;
+
+    let repo = SpamRuleRepository::new(state.db_pool.clone());
+    repo.get(tenant_id, rule_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching spam rule: {}", e);
+            storage_error(e).or_internal("Failed to get spam rule")
+        })?
+        .ok_or_else(|| Error::NotFound("Spam rule not found".to_string()))?;
+
+    // The rule exists, so nothing updated means the name is taken
+    let rule = repo
+        .update(tenant_id, rule_id, input)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating spam rule: {}", e);
+            storage_error(e).or_internal("Failed to update spam rule")
+        })?
+        .ok_or_else(|| {
+            warn!("Tenant {} already has a spam rule of that name", tenant_id);
+            Error::Conflict("A spam rule of that name already exists".to_string())
+                .with_field("name")
+        })?;
+
+    info!("Updated spam rule {} (tenant {})", rule_id, tenant_id);
+
+    Ok(Json(rule))
+}
+
+/// Delete a spam rule of a tenant
+pub async fn delete_spam_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, rule_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let deleted = SpamRuleRepository::new(state.db_pool.clone())
+        .delete(tenant_id, rule_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while deleting spam rule: {}", e);
+            storage_error(e).or_internal("Failed to delete spam rule")
+        })?;
+
+    if deleted {
+        info!("Deleted spam rule {} (tenant {})", rule_id, tenant_id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::NotFound("Spam rule not found".to_string()).into())
+    }
+}
@@ -5,27 +5,65 @@ use axum::{
     http::StatusCode,
     Extension, Json,
 };
-use mairust_storage::{CreateTenant, Tenant, TenantRepository};
+use mairust_common::config::is_valid_region_name;
+use mairust_common::types::ServicesEnabled;
+use mairust_common::Error;
+use mairust_core::bundle::ImportReport;
+use mairust_core::onboarding::{BootstrapRequest, DkimKey, OnboardingError, TenantBootstrapper};
+use mairust_storage::db::db_error;
+use mairust_storage::{CreateTenant, Domain, Mailbox, Tenant, TenantRepository, User};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::warn;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_scope, require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::domains::{
+    generate_dkim_key, generate_dns_records, is_valid_dkim_selector, is_valid_domain_name,
+    DnsRecords,
+};
+
+/// DKIM selector of bootstrapped domains unless one is given
+const DEFAULT_DKIM_SELECTOR: &str = "mairust";
+
+/// Request body for bootstrapping a tenant
+#[derive(Debug, Clone, Deserialize)]
+pub struct BootstrapTenantRequest {
+    #[serde(flatten)]
+    pub tenant: BootstrapRequest,
+    pub dkim_selector: Option<String>,
+}
+
+/// A bootstrapped tenant with the DNS records to publish and its API key
+#[derive(Debug, Clone, Serialize)]
+pub struct BootstrapTenantResponse {
+    pub tenant: Tenant,
+    pub domain: Domain,
+    pub admin: User,
+    pub mailbox: Mailbox,
+    pub folders: Vec<String>,
+    pub config: Option<ImportReport>,
+    pub dns_records: DnsRecords,
+    pub api_key_id: Uuid,
+    /// Shown once; only a hash is stored
+    pub api_key: String,
+}
 
 /// List all tenants (admin only - requires 'admin:tenants' scope)
 pub async fn list_tenants(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
-) -> Result<Json<Vec<Tenant>>, StatusCode> {
+) -> ApiResult<Json<Vec<Tenant>>> {
     // Require admin scope for listing all tenants
     require_scope(&auth, "admin:tenants")?;
 
     let repo = TenantRepository::new(state.db_pool.clone());
 
-    let tenants = repo
-        .find_all()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tenants = repo.find_all().await.map_err(|e| {
+        error!("Database error while listing tenants: {}", e);
+        e.or_internal("Failed to list tenants")
+    })?;
 
     Ok(Json(tenants))
 }
@@ -35,7 +73,7 @@ pub async fn get_tenant(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<Tenant>, StatusCode> {
+) -> ApiResult<Json<Tenant>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -44,8 +82,11 @@ pub async fn get_tenant(
     let tenant = repo
         .find_by_id(tenant_id)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .map_err(|e| {
+            error!("Database error while fetching tenant: {}", e);
+            e.or_internal("Failed to fetch tenant")
+        })?
+        .ok_or_else(|| Error::NotFound("Tenant not found".to_string()))?;
 
     Ok(Json(tenant))
 }
@@ -55,26 +96,251 @@ pub async fn create_tenant(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Json(input): Json<CreateTenant>,
-) -> Result<(StatusCode, Json<Tenant>), StatusCode> {
+) -> ApiResult<(StatusCode, Json<Tenant>)> {
     // Require admin scope for creating tenants
     require_scope(&auth, "admin:tenants")?;
 
     let repo = TenantRepository::new(state.db_pool.clone());
 
-    let tenant = repo
-        .create(&input)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tenant = repo.create(&input).await.map_err(|e| {
+        error!("Database error while creating tenant: {}", e);
+        e.or_internal("Failed to create tenant")
+    })?;
 
     Ok((StatusCode::CREATED, Json(tenant)))
 }
 
+/// Bootstrap a tenant (admin only - requires 'admin:tenants' scope)
+///
+/// Creates the tenant, its first domain with a new DKIM key, the admin user
+/// with a mailbox and default folders, default domain settings, an optional
+/// starter configuration bundle and an API key, all in one transaction.
+pub async fn bootstrap_tenant(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(input): Json<BootstrapTenantRequest>,
+) -> ApiResult<(StatusCode, Json<BootstrapTenantResponse>)> {
+    require_scope(&auth, "admin:tenants")?;
+
+    if !is_valid_domain_name(input.tenant.domain.trim()) {
+        warn!("Invalid domain name format: {}", input.tenant.domain);
+        return Err(
+            Error::Validation(format!("Invalid domain name {}", input.tenant.domain))
+                .with_field("tenant.domain")
+                .into(),
+        );
+    }
+    let selector = input
+        .dkim_selector
+        .unwrap_or_else(|| DEFAULT_DKIM_SELECTOR.to_string());
+    if !is_valid_dkim_selector(&selector) {
+        warn!("Invalid DKIM selector format: {}", selector);
+        return Err(
+            Error::Validation(format!("Invalid DKIM selector {}", selector))
+                .with_field("dkim_selector")
+                .into(),
+        );
+    }
+
+    // Key generation takes a while; keep it off the async workers
+    let private_key = tokio::task::spawn_blocking(generate_dkim_key)
+        .await
+        .map_err(|e| {
+            error!("DKIM key generation task failed: {}", e);
+            Error::Internal("Failed to bootstrap tenant".to_string())
+        })?
+        .map_err(|e| {
+            error!("{}", e);
+            Error::Internal("Failed to bootstrap tenant".to_string())
+        })?;
+
+    let result = TenantBootstrapper::new(state.db_pool.clone())
+        .bootstrap(
+            &input.tenant,
+            DkimKey {
+                selector,
+                private_key,
+            },
+        )
+        .await
+        .map_err(|e| match e {
+            OnboardingError::Invalid(_) | OnboardingError::Bundle(_) => {
+                warn!("Rejected tenant bootstrap: {}", e);
+                Error::Validation(e.to_string())
+            }
+            OnboardingError::Conflict(_) => {
+                warn!("Rejected tenant bootstrap: {}", e);
+                Error::Conflict(e.to_string())
+            }
+            OnboardingError::Database(e) => {
+                error!("Failed to bootstrap tenant: {}", e);
+                db_error(e).or_internal("Failed to bootstrap tenant")
+            }
+            OnboardingError::Password(_) => {
+                error!("Failed to bootstrap tenant: {}", e);
+                Error::Internal("Failed to bootstrap tenant".to_string())
+            }
+        })?;
+
+    let dns_records = generate_dns_records(&result.domain);
+    Ok((
+        StatusCode::CREATED,
+        Json(BootstrapTenantResponse {
+            tenant: result.tenant,
+            domain: result.domain,
+            admin: result.admin,
+            mailbox: result.mailbox,
+            folders: result.folders,
+            config: result.config,
+            dns_records,
+            api_key_id: result.api_key_id,
+            api_key: result.api_key,
+        }),
+    ))
+}
+
+/// Get the mail access protocols a tenant's users may use
+pub async fn get_tenant_services(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+) -> ApiResult<Json<ServicesEnabled>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let tenant = TenantRepository::new(state.db_pool.clone())
+        .find_by_id(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching tenant: {}", e);
+            e.or_internal("Failed to fetch tenant")
+        })?
+        .ok_or_else(|| Error::NotFound("Tenant not found".to_string()))?;
+
+    Ok(Json(
+        tenant
+            .settings
+            .get("services_enabled")
+            .map(ServicesEnabled::from_json)
+            .unwrap_or_default(),
+    ))
+}
+
+/// Switch IMAP and POP3 on or off for a tenant
+///
+/// A protocol switched off for the tenant's plan stays off.
+pub async fn set_tenant_services(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<ServicesEnabled>,
+) -> ApiResult<Json<ServicesEnabled>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let repo = TenantRepository::new(state.db_pool.clone());
+    repo.find_by_id(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching tenant: {}", e);
+            e.or_internal("Failed to fetch tenant")
+        })?
+        .ok_or_else(|| Error::NotFound("Tenant not found".to_string()))?;
+
+    repo.set_services_enabled(tenant_id, &input)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating tenant services: {}", e);
+            e.or_internal("Failed to update tenant services")
+        })?;
+
+    info!(
+        "Services of tenant {}: imap={}, pop3={}",
+        tenant_id, input.imap, input.pop3
+    );
+    Ok(Json(input))
+}
+
+/// Region a tenant's data is pinned to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantResidency {
+    /// Region name from `residency.regions`, or `null` when not pinned
+    pub data_region: Option<String>,
+}
+
+/// Get the region a tenant's data is pinned to
+pub async fn get_tenant_residency(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+) -> ApiResult<Json<TenantResidency>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let repo = TenantRepository::new(state.db_pool.clone());
+    repo.find_by_id(tenant_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Tenant not found".to_string()))?;
+
+    Ok(Json(TenantResidency {
+        data_region: repo.data_region(tenant_id).await?,
+    }))
+}
+
+/// Pin a tenant's data to a region (admin only - requires 'admin:tenants'
+/// scope)
+///
+/// Stored files are not moved, so the region can only be changed while the
+/// tenant has no messages.
+pub async fn set_tenant_residency(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<TenantResidency>,
+) -> ApiResult<Json<TenantResidency>> {
+    require_scope(&auth, "admin:tenants")?;
+    require_tenant_access(&auth, tenant_id)?;
+
+    if let Some(region) = &input.data_region {
+        if !is_valid_region_name(region) {
+            return Err(Error::Validation(format!(
+                "Invalid region name '{}': use lowercase letters, digits and hyphens",
+                region
+            ))
+            .with_field("data_region")
+            .into());
+        }
+    }
+
+    let repo = TenantRepository::new(state.db_pool.clone());
+    repo.find_by_id(tenant_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Tenant not found".to_string()))?;
+
+    if repo.data_region(tenant_id).await? == input.data_region {
+        return Ok(Json(input));
+    }
+    if repo.has_messages(tenant_id).await? {
+        return Err(Error::Conflict(
+            "The region of a tenant with stored messages cannot be changed".to_string(),
+        )
+        .into());
+    }
+
+    repo.set_data_region(tenant_id, input.data_region.as_deref())
+        .await?;
+
+    info!(
+        "Data of tenant {} pinned to region {}",
+        tenant_id,
+        input.data_region.as_deref().unwrap_or("(none)")
+    );
+    Ok(Json(input))
+}
+
 /// Delete a tenant (admin only - requires 'admin:tenants' scope)
 pub async fn delete_tenant(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     // Require admin scope for deleting tenants
     require_scope(&auth, "admin:tenants")?;
 
@@ -87,15 +353,19 @@ pub async fn delete_tenant(
     let _ = repo
         .find_by_id(tenant_id)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("Database error while fetching tenant: {}", e);
+            e.or_internal("Failed to fetch tenant")
+        })?
         .ok_or_else(|| {
             warn!("Tenant {} not found", tenant_id);
-            StatusCode::NOT_FOUND
+            Error::NotFound("Tenant not found".to_string())
         })?;
 
-    repo.delete(tenant_id)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    repo.delete(tenant_id).await.map_err(|e| {
+        error!("Database error while deleting tenant: {}", e);
+        e.or_internal("Failed to delete tenant")
+    })?;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,221 @@
+//! Tracking domain handlers
+//!
+//! Domains a tenant serves the tracked links of its campaigns from. A
+//! tracking domain must share its organizational domain with a verified,
+//! DKIM-signing domain of the tenant, so that the links align with the
+//! sender. It is verified once its CNAME points at the server, and then
+//! gets a certificate over ACME.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use mairust_common::Error;
+use mairust_core::tracking::{is_aligned, CnameChecker};
+use mairust_storage::db::storage_error;
+use mairust_storage::repository::{
+    DomainRepository, DomainRepositoryTrait, TrackingDomain, TrackingDomainRepository,
+    TrackingDomainStatus,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::ApiResult;
+use crate::handlers::domains::is_valid_domain_name;
+
+/// Request body for adding a tracking domain
+#[derive(Debug, Deserialize)]
+pub struct CreateTrackingDomainRequest {
+    pub domain: String,
+}
+
+/// A tracking domain with the CNAME it needs
+#[derive(Debug, Serialize)]
+pub struct TrackingDomainResponse {
+    #[serde(flatten)]
+    pub domain: TrackingDomain,
+    /// Host name the domain's CNAME must point at
+    pub cname_target: String,
+}
+
+impl TrackingDomainResponse {
+    fn new(state: &AppState, domain: TrackingDomain) -> Self {
+        Self {
+            domain,
+            cname_target: state.tracking_cname_target.clone(),
+        }
+    }
+}
+
+/// List the tracking domains of a tenant
+///
+/// GET /api/v1/tenants/:tenant_id/tracking-domains
+pub async fn list_tracking_domains(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+) -> ApiResult<Json<Vec<TrackingDomainResponse>>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let domains = TrackingDomainRepository::new(state.db_pool.clone())
+        .list(tenant_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing tracking domains: {}", e);
+            storage_error(e).or_internal("Failed to list tracking domains")
+        })?;
+
+    Ok(Json(
+        domains
+            .into_iter()
+            .map(|domain| TrackingDomainResponse::new(&state, domain))
+            .collect(),
+    ))
+}
+
+/// Add a tracking domain
+///
+/// POST /api/v1/tenants/:tenant_id/tracking-domains
+pub async fn create_tracking_domain(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(tenant_id): Path<Uuid>,
+    Json(input): Json<CreateTrackingDomainRequest>,
+) -> ApiResult<(StatusCode, Json<TrackingDomainResponse>)> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let name = input.domain.trim().trim_end_matches('.').to_lowercase();
+    if !is_valid_domain_name(&name) || !name.contains('.') {
+        return Err(Error::Validation(format!("Invalid domain name '{}'", input.domain))
+            .with_field("domain")
+            .into());
+    }
+
+    // Links must align with a domain the tenant sends signed mail from
+    let domains = DomainRepository::new(state.db_pool.clone())
+        .list(tenant_id)
+        .await?;
+    if !domains.iter().any(|domain| {
+        domain.verified
+            && domain.dkim_selector.is_some()
+            && domain.dkim_private_key.is_some()
+            && is_aligned(&name, &domain.name)
+    }) {
+        return Err(Error::Validation(format!(
+            "{} does not share its organizational domain with a verified, DKIM-signing domain of the tenant",
+            name
+        ))
+        .with_field("domain")
+        .into());
+    }
+
+    let domain = TrackingDomainRepository::new(state.db_pool.clone())
+        .create(tenant_id, &name)
+        .await
+        .map_err(|e| {
+            error!("Database error while creating tracking domain: {}", e);
+            storage_error(e).or_internal("Failed to create tracking domain")
+        })?
+        .ok_or_else(|| Error::Conflict(format!("Tracking domain {} already exists", name)))?;
+
+    info!("Added tracking domain {} (tenant {})", domain.domain, tenant_id);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(TrackingDomainResponse::new(&state, domain)),
+    ))
+}
+
+/// Get a tracking domain
+///
+/// GET /api/v1/tenants/:tenant_id/tracking-domains/:id
+pub async fn get_tracking_domain(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<TrackingDomainResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let domain = find_tracking_domain(&state, tenant_id, id).await?;
+    Ok(Json(TrackingDomainResponse::new(&state, domain)))
+}
+
+/// Check the CNAME of a pending tracking domain now instead of waiting for
+/// the next check
+///
+/// POST /api/v1/tenants/:tenant_id/tracking-domains/:id/verify
+pub async fn verify_tracking_domain(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<TrackingDomainResponse>> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let domain = find_tracking_domain(&state, tenant_id, id).await?;
+    if domain.status != TrackingDomainStatus::Pending.as_str() {
+        return Ok(Json(TrackingDomainResponse::new(&state, domain)));
+    }
+
+    let repo = TrackingDomainRepository::new(state.db_pool.clone());
+    let checked = match CnameChecker::new(&state.tracking_cname_target)
+        .check(&domain.domain)
+        .await
+    {
+        Ok(()) => {
+            info!("Tracking domain {} verified", domain.domain);
+            repo.mark_verified(domain.id).await
+        }
+        Err(e) => repo.record_check_error(domain.id, &e).await,
+    };
+    checked.map_err(|e| {
+        error!("Database error while verifying tracking domain: {}", e);
+        storage_error(e).or_internal("Failed to verify tracking domain")
+    })?;
+
+    let domain = find_tracking_domain(&state, tenant_id, id).await?;
+    Ok(Json(TrackingDomainResponse::new(&state, domain)))
+}
+
+/// Remove a tracking domain
+///
+/// DELETE /api/v1/tenants/:tenant_id/tracking-domains/:id
+pub async fn delete_tracking_domain(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<StatusCode> {
+    require_tenant_access(&auth, tenant_id)?;
+
+    let deleted = TrackingDomainRepository::new(state.db_pool.clone())
+        .delete(tenant_id, id)
+        .await
+        .map_err(|e| {
+            error!("Database error while deleting tracking domain: {}", e);
+            storage_error(e).or_internal("Failed to delete tracking domain")
+        })?;
+    if !deleted {
+        return Err(Error::NotFound("Tracking domain not found".to_string()).into());
+    }
+
+    info!("Deleted tracking domain {} (tenant {})", id, tenant_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn find_tracking_domain(
+    state: &AppState,
+    tenant_id: Uuid,
+    id: Uuid,
+) -> ApiResult<TrackingDomain> {
+    TrackingDomainRepository::new(state.db_pool.clone())
+        .get(tenant_id, id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching tracking domain: {}", e);
+            storage_error(e).or_internal("Failed to fetch tracking domain")
+        })?
+        .ok_or_else(|| Error::NotFound("Tracking domain not found".to_string()).into())
+}
@@ -1,32 +1,60 @@
 //! User handlers
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Extension, Json,
 };
-use mairust_storage::{CreateUser, User, UserRepository};
+use mairust_common::types::ServicesEnabled;
+use mairust_common::Error;
+use mairust_core::sent_archive::ArchiveTarget;
+use mairust_storage::db::storage_error;
+use mairust_storage::{
+    CreateUser, NotificationSettings, SecurityEvent, SecurityEventRepository,
+    UpdateNotificationSettings, UpdateUserSettings, User, UserRepository, UserSettings,
+    UserSettingsRepository,
+};
+use serde::Deserialize;
 use std::sync::Arc;
-use tracing::warn;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::auth::{require_tenant_access, AppState, AuthContext};
+use crate::error::{ApiError, ApiResult};
+
+/// Shortest APOP secret accepted
+const MIN_APOP_SECRET_LEN: usize = 8;
+
+/// APOP secret update
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetApopSecretRequest {
+    /// New secret; `None` disables APOP for the user
+    pub secret: Option<String>,
+}
+
+/// Pagination of a user's security events
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecurityEventQuery {
+    pub offset: Option<i64>,
+    /// Page size (max 100)
+    pub limit: Option<i64>,
+}
 
 /// List users in a tenant
 pub async fn list_users(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
-) -> Result<Json<Vec<User>>, StatusCode> {
+) -> ApiResult<Json<Vec<User>>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
     let repo = UserRepository::new(state.db_pool.clone());
 
-    let users = repo
-        .find_by_tenant(tenant_id)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let users = repo.find_by_tenant(tenant_id).await.map_err(|e| {
+        error!("Database error while listing users: {}", e);
+        e.or_internal("Failed to list users")
+    })?;
 
     Ok(Json(users))
 }
@@ -36,7 +64,7 @@ pub async fn get_user(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<User>, StatusCode> {
+) -> ApiResult<Json<User>> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -45,14 +73,17 @@ pub async fn get_user(
     let user = repo
         .find_by_id(user_id)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("Database error while fetching user: {}", e);
+            e.or_internal("Failed to fetch user")
+        })?
         .filter(|u| u.tenant_id == tenant_id)
         .ok_or_else(|| {
             warn!(
                 "User {} not found or not owned by tenant {}",
                 user_id, tenant_id
             );
-            StatusCode::NOT_FOUND
+            Error::NotFound("User not found".to_string())
         })?;
 
     Ok(Json(user))
@@ -64,7 +95,7 @@ pub async fn create_user(
     Extension(auth): Extension<AuthContext>,
     Path(tenant_id): Path<Uuid>,
     Json(mut input): Json<CreateUser>,
-) -> Result<(StatusCode, Json<User>), StatusCode> {
+) -> ApiResult<(StatusCode, Json<User>)> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -73,10 +104,10 @@ pub async fn create_user(
 
     let repo = UserRepository::new(state.db_pool.clone());
 
-    let user = repo
-        .create(&input)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user = repo.create(&input).await.map_err(|e| {
+        error!("Database error while creating user: {}", e);
+        e.or_internal("Failed to create user")
+    })?;
 
     Ok((StatusCode::CREATED, Json(user)))
 }
@@ -86,7 +117,7 @@ pub async fn delete_user(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthContext>,
     Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
+) -> ApiResult<StatusCode> {
     // Verify the authenticated user has access to this tenant
     require_tenant_access(&auth, tenant_id)?;
 
@@ -96,19 +127,269 @@ pub async fn delete_user(
     let _ = repo
         .find_by_id(user_id)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("Database error while fetching user: {}", e);
+            e.or_internal("Failed to fetch user")
+        })?
         .filter(|u| u.tenant_id == tenant_id)
         .ok_or_else(|| {
             warn!(
                 "User {} not found or not owned by tenant {}",
                 user_id, tenant_id
             );
-            StatusCode::NOT_FOUND
+            Error::NotFound("User not found".to_string())
+        })?;
+
+    repo.delete(user_id).await.map_err(|e| {
+        error!("Database error while deleting user: {}", e);
+        e.or_internal("Failed to delete user")
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get a user's settings
+pub async fn get_user_settings(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<UserSettings>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    let settings = UserSettingsRepository::new(state.db_pool.clone())
+        .get(tenant_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching user settings: {}", e);
+            storage_error(e).or_internal("Failed to fetch user settings")
+        })?;
+
+    Ok(Json(settings))
+}
+
+/// Update a user's settings
+pub async fn update_user_settings(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+    Json(mut input): Json<UpdateUserSettings>,
+) -> ApiResult<Json<UserSettings>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    // Store the archive as it is used; the Sent folder cannot be one
+    if let Some(archive) = input
+        .sent_archive
+        .as_deref()
+        .filter(|a| !a.trim().is_empty())
+    {
+        let target = ArchiveTarget::parse(archive).ok_or_else(|| {
+            ApiError::from(
+                Error::Validation(format!("Invalid sent archive {}", archive))
+                    .with_field("sent_archive"),
+            )
+            .with_status(StatusCode::UNPROCESSABLE_ENTITY)
+        })?;
+        input.sent_archive = Some(match target {
+            ArchiveTarget::Folder(path) => path,
+            ArchiveTarget::Address(address) => address,
+        });
+    }
+
+    let settings = UserSettingsRepository::new(state.db_pool.clone())
+        .update(tenant_id, user_id, &input)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating user settings: {}", e);
+            storage_error(e).or_internal("Failed to update user settings")
+        })?;
+
+    Ok(Json(settings))
+}
+
+/// Get a user's webmail notification preferences
+pub async fn get_notification_settings(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<NotificationSettings>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    let settings = UserSettingsRepository::new(state.db_pool.clone())
+        .get(tenant_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching notification settings: {}", e);
+            storage_error(e).or_internal("Failed to fetch notification settings")
+        })?;
+
+    Ok(Json(settings.into()))
+}
+
+/// Update a user's webmail notification preferences
+pub async fn update_notification_settings(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<UpdateNotificationSettings>,
+) -> ApiResult<Json<NotificationSettings>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    let settings = UserSettingsRepository::new(state.db_pool.clone())
+        .update(tenant_id, user_id, &input.into())
+        .await
+        .map_err(|e| {
+            error!("Database error while updating notification settings: {}", e);
+            storage_error(e).or_internal("Failed to update notification settings")
         })?;
 
-    repo.delete(user_id)
+    Ok(Json(settings.into()))
+}
+
+/// Set or clear a user's POP3 APOP secret
+pub async fn set_apop_secret(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<SetApopSecretRequest>,
+) -> ApiResult<StatusCode> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    if input
+        .secret
+        .as_deref()
+        .is_some_and(|secret| secret.chars().count() < MIN_APOP_SECRET_LEN)
+    {
+        return Err(ApiError::from(
+            Error::Validation(format!(
+                "APOP secret must have at least {} characters",
+                MIN_APOP_SECRET_LEN
+            ))
+            .with_field("secret"),
+        )
+        .with_status(StatusCode::UNPROCESSABLE_ENTITY));
+    }
+
+    UserRepository::new(state.db_pool.clone())
+        .set_apop_secret(user_id, input.secret.as_deref())
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|e| {
+            error!("Database error while updating APOP secret: {}", e);
+            e.or_internal("Failed to update APOP secret")
+        })?;
 
+    info!(
+        "APOP secret {} for user {}",
+        if input.secret.is_some() {
+            "set"
+        } else {
+            "cleared"
+        },
+        user_id
+    );
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Get the mail access protocols a user may use
+pub async fn get_user_services(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<ServicesEnabled>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    let services = UserRepository::new(state.db_pool.clone())
+        .services_enabled(user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while loading user services: {}", e);
+            e.or_internal("Failed to load user services")
+        })?;
+
+    Ok(Json(services))
+}
+
+/// Switch IMAP and POP3 on or off for a user
+///
+/// With both off the user is restricted to webmail and the API. A protocol
+/// switched off for the tenant or its plan stays off.
+pub async fn set_user_services(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<ServicesEnabled>,
+) -> ApiResult<Json<ServicesEnabled>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    UserRepository::new(state.db_pool.clone())
+        .set_services_enabled(user_id, &input)
+        .await
+        .map_err(|e| {
+            error!("Database error while updating user services: {}", e);
+            e.or_internal("Failed to update user services")
+        })?;
+
+    info!(
+        "Services of user {}: imap={}, pop3={}",
+        user_id, input.imap, input.pop3
+    );
+    Ok(Json(input))
+}
+
+/// List a user's security events, newest first
+pub async fn list_security_events(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+    Path((tenant_id, user_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<SecurityEventQuery>,
+) -> ApiResult<Json<Vec<SecurityEvent>>> {
+    // Verify the authenticated user has access to this tenant
+    require_tenant_access(&auth, tenant_id)?;
+    require_tenant_user(&state, tenant_id, user_id).await?;
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let events = SecurityEventRepository::new(state.db_pool.clone())
+        .list(tenant_id, user_id, limit, offset)
+        .await
+        .map_err(|e| {
+            error!("Database error while listing security events: {}", e);
+            storage_error(e).or_internal("Failed to list security events")
+        })?;
+
+    Ok(Json(events))
+}
+
+/// Verify a user exists and belongs to the tenant
+async fn require_tenant_user(state: &AppState, tenant_id: Uuid, user_id: Uuid) -> ApiResult<()> {
+    UserRepository::new(state.db_pool.clone())
+        .find_by_id(user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error while fetching user: {}", e);
+            e.or_internal("Failed to fetch user")
+        })?
+        .filter(|u| u.tenant_id == tenant_id)
+        .ok_or_else(|| {
+            warn!(
+                "User {} not found or not owned by tenant {}",
+                user_id, tenant_id
+            );
+            Error::NotFound("User not found".to_string())
+        })?;
+    Ok(())
+}
@@ -3,10 +3,16 @@
 //! This crate provides the REST API server for MaiRust,
 //! including authentication, message management, and admin endpoints.
 
+// The OpenAPI document is one large `json!` invocation
+#![recursion_limit = "4096"]
+
 pub mod auth;
+pub mod error;
 pub mod handlers;
+pub mod maintenance;
 pub mod openapi;
 pub mod routes;
 
+pub use error::{ApiError, ApiResult};
 pub use openapi::create_openapi_routes;
 pub use routes::create_router;
@@ -0,0 +1,114 @@
+//! Maintenance mode middleware
+//!
+//! While a maintenance window is active, requests that change data are
+//! answered with 503 and a `Retry-After` header. Reads continue.
+//!
+//! A replication standby likewise refuses requests that change data until
+//! it is promoted.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use mairust_core::{MaintenanceStatus, Replication, ReplicationRole};
+use mairust_storage::MaintenanceRepository;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::auth::AppState;
+
+/// Whether a request method only reads
+fn is_read(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Refuse mutations while a maintenance window is active
+pub async fn maintenance_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_read(request.method()) {
+        return next.run(request).await;
+    }
+
+    // Fail open: a request that needs the database fails on its own
+    let windows = match MaintenanceRepository::new(state.db_pool.clone())
+        .list_open()
+        .await
+    {
+        Ok(windows) => windows,
+        Err(e) => {
+            error!("Failed to load maintenance windows: {}", e);
+            return next.run(request).await;
+        }
+    };
+
+    let now = Utc::now();
+    let Some(status) = MaintenanceStatus::from_windows(&windows, now) else {
+        return next.run(request).await;
+    };
+
+    info!(
+        "Refusing {} {} during maintenance",
+        request.method(),
+        request.uri().path()
+    );
+    let retry_after = status.retry_after_secs(now);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(json!({
+            "error": "maintenance",
+            "message": status.message,
+            "until": status.until,
+            "retry_after": retry_after,
+        })),
+    )
+        .into_response()
+}
+
+/// Refuse mutations on a replication standby
+pub async fn standby_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.replication.enabled || is_read(request.method()) {
+        return next.run(request).await;
+    }
+
+    // Fail open, as above
+    let role = match Replication::new(state.db_pool.clone(), state.replication.clone())
+        .role()
+        .await
+    {
+        Ok(role) => role,
+        Err(e) => {
+            error!("Failed to read replication role: {}", e);
+            return next.run(request).await;
+        }
+    };
+    if role != ReplicationRole::Standby {
+        return next.run(request).await;
+    }
+
+    info!(
+        "Refusing {} {} on a standby",
+        request.method(),
+        request.uri().path()
+    );
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({
+            "error": "standby",
+            "message": "This server is a replication standby; changes are made on the primary",
+        })),
+    )
+        .into_response()
+}
@@ -52,12 +52,21 @@ fn get_openapi_spec() -> serde_json::Value {
         "tags": [
             {"name": "health", "description": "Health check endpoints"},
             {"name": "tenants", "description": "Tenant management (admin only)"},
+            {"name": "admin", "description": "System administration (super admin only)"},
             {"name": "users", "description": "User management"},
             {"name": "domains", "description": "Domain management"},
             {"name": "mailboxes", "description": "Mailbox management"},
             {"name": "messages", "description": "Message operations"},
             {"name": "hooks", "description": "Hook/plugin management"},
-            {"name": "send", "description": "Email sending"}
+            {"name": "inbound-routes", "description": "Inbound messages posted to webhooks"},
+            {"name": "protected-names", "description": "Display names protected from impersonation"},
+            {"name": "tracking-domains", "description": "Domains of tracked campaign links"},
+            {"name": "quarantine", "description": "Messages held back from their mailbox"},
+            {"name": "spam-rules", "description": "Rules of the rule-based spam filter"},
+            {"name": "policies", "description": "Mail flow rules"},
+            {"name": "send", "description": "Email sending"},
+            {"name": "contacts", "description": "Recipient autocomplete"},
+            {"name": "config-bundles", "description": "Declarative export and import of tenant configuration"}
         ],
         "paths": {
             // Health endpoints
@@ -117,6 +126,22 @@ fn get_openapi_spec() -> serde_json::Value {
                     }
                 }
             },
+            "/metrics": {
+                "get": {
+                    "tags": ["health"],
+                    "summary": "Prometheus metrics",
+                    "description": "Replication metrics in the Prometheus text format: mairust_replication_lag_seconds, mairust_replication_files_pending and others. Served at /metrics, outside /api/v1.",
+                    "operationId": "metrics",
+                    "responses": {
+                        "200": {
+                            "description": "Metrics",
+                            "content": {
+                                "text/plain": {"schema": {"type": "string"}}
+                            }
+                        }
+                    }
+                }
+            },
             // Tenant endpoints
             "/admin/tenants": {
                 "get": {
@@ -163,6 +188,35 @@ fn get_openapi_spec() -> serde_json::Value {
                     }
                 }
             },
+            "/admin/tenants/bootstrap": {
+                "post": {
+                    "tags": ["tenants"],
+                    "summary": "Bootstrap a tenant",
+                    "description": "Creates a tenant, its first domain with a new DKIM key, an admin user with a mailbox and default folders, default domain settings, an optional starter configuration bundle and an API key in one transaction.",
+                    "operationId": "bootstrapTenant",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/BootstrapTenantRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Tenant bootstrapped",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/BootstrapTenantResponse"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid request or configuration bundle"},
+                        "409": {"description": "Slug, domain or admin address already exists"}
+                    }
+                }
+            },
             "/admin/tenants/{id}": {
                 "get": {
                     "tags": ["tenants"],
@@ -191,497 +245,3919 @@ fn get_openapi_spec() -> serde_json::Value {
                     }
                 }
             },
-            // User endpoints
-            "/tenants/{tenant_id}/users": {
+            "/admin/tenants/{id}/services": {
                 "get": {
-                    "tags": ["users"],
-                    "summary": "List users",
-                    "operationId": "listUsers",
+                    "tags": ["tenants"],
+                    "summary": "Get the mail protocols a tenant's users may use",
+                    "operationId": "getTenantServices",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "responses": {
-                        "200": {"description": "List of users"}
+                        "200": {
+                            "description": "Enabled protocols",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ServicesEnabled"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Tenant not found"}
                     }
                 },
-                "post": {
-                    "tags": ["users"],
-                    "summary": "Create a user",
-                    "operationId": "createUser",
+                "put": {
+                    "tags": ["tenants"],
+                    "summary": "Switch IMAP and POP3 on or off for a tenant's users",
+                    "description": "IMAP and POP3 logins of the tenant's users are refused with a message naming the organization when the protocol is off. Protocols switched off for the tenant's plan in the server configuration stay off.",
+                    "operationId": "setTenantServices",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "requestBody": {
                         "required": true,
                         "content": {
                             "application/json": {
-                                "schema": {"$ref": "#/components/schemas/CreateUserRequest"}
+                                "schema": {"$ref": "#/components/schemas/ServicesEnabled"}
                             }
                         }
                     },
                     "responses": {
-                        "201": {"description": "User created"}
+                        "200": {
+                            "description": "Updated protocols",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ServicesEnabled"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Tenant not found"}
                     }
                 }
             },
-            // Domain endpoints
-            "/tenants/{tenant_id}/domains": {
+            "/admin/tenants/{id}/residency": {
                 "get": {
-                    "tags": ["domains"],
-                    "summary": "List domains",
-                    "operationId": "listDomains",
+                    "tags": ["tenants"],
+                    "summary": "Get the region a tenant's data is pinned to",
+                    "operationId": "getTenantResidency",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "responses": {
                         "200": {
-                            "description": "List of domains",
+                            "description": "Data region",
                             "content": {
                                 "application/json": {
-                                    "schema": {
-                                        "type": "array",
-                                        "items": {"$ref": "#/components/schemas/Domain"}
-                                    }
+                                    "schema": {"$ref": "#/components/schemas/TenantResidency"}
                                 }
                             }
-                        }
+                        },
+                        "404": {"description": "Tenant not found"}
                     }
                 },
-                "post": {
-                    "tags": ["domains"],
-                    "summary": "Create a domain",
-                    "operationId": "createDomain",
+                "put": {
+                    "tags": ["tenants"],
+                    "summary": "Pin a tenant's data to a region",
+                    "description": "Message files of a pinned tenant are stored in the region's storage, and servers of other regions refuse to access them (HTTP 421 WRONG_REGION, SMTP 451 4.3.2). Requires the admin:tenants scope. The region can only be changed while the tenant has no messages.",
+                    "operationId": "setTenantResidency",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "requestBody": {
                         "required": true,
                         "content": {
                             "application/json": {
-                                "schema": {"$ref": "#/components/schemas/CreateDomainRequest"}
+                                "schema": {"$ref": "#/components/schemas/TenantResidency"}
                             }
                         }
                     },
                     "responses": {
-                        "201": {"description": "Domain created"}
+                        "200": {
+                            "description": "Updated data region",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/TenantResidency"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Tenant not found"},
+                        "409": {"description": "The tenant has stored messages"},
+                        "400": {"description": "Invalid region name"}
                     }
                 }
             },
-            "/tenants/{tenant_id}/domains/{domain_id}": {
+            // User endpoints
+            "/admin/system/search": {
                 "get": {
-                    "tags": ["domains"],
-                    "summary": "Get a domain",
-                    "operationId": "getDomain",
+                    "tags": ["admin"],
+                    "summary": "Search messages and deliveries across tenants",
+                    "description": "Finds received messages and delivery jobs by envelope address, Message-ID or queue ID. Requires the admin:system and admin:search scopes; every search is audit logged.",
+                    "operationId": "globalSearch",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "address", "in": "query", "schema": {"type": "string", "format": "email"}, "description": "Envelope sender or recipient"},
+                        {"name": "message_id", "in": "query", "schema": {"type": "string"}, "description": "Message-ID header or MaiRust message ID"},
+                        {"name": "queue_id", "in": "query", "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "from", "in": "query", "schema": {"type": "string", "format": "date-time"}},
+                        {"name": "to", "in": "query", "schema": {"type": "string", "format": "date-time"}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50, "maximum": 200}}
                     ],
                     "responses": {
-                        "200": {"description": "Domain details with DNS records"}
+                        "200": {
+                            "description": "Matching messages and delivery jobs",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/GlobalSearchResponse"}
+                                }
+                            }
+                        },
+                        "400": {"description": "No search criteria given"},
+                        "403": {"description": "Missing admin scope"}
                     }
-                },
-                "delete": {
-                    "tags": ["domains"],
-                    "summary": "Delete a domain",
-                    "operationId": "deleteDomain",
+                }
+            },
+            "/admin/system/journal": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "Get the journal export backlog",
+                    "description": "Counts of journal reports waiting for export to the archive, the oldest waiting report and recent export failures.",
+                    "operationId": "getJournalStatus",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
-                    ],
                     "responses": {
-                        "204": {"description": "Domain deleted"}
+                        "200": {
+                            "description": "Journal export queue statistics",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/QueueStatusResponse"}
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            "/tenants/{tenant_id}/domains/{domain_id}/verify": {
+            "/admin/system/journal/retry": {
                 "post": {
-                    "tags": ["domains"],
-                    "summary": "Verify domain DNS",
-                    "operationId": "verifyDomain",
+                    "tags": ["admin"],
+                    "summary": "Retry failed journal exports",
+                    "description": "Requeues journal reports whose export ran out of attempts, for example after an archive outage. The journaled copies are kept until export succeeds.",
+                    "operationId": "retryJournalExports",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
-                    ],
                     "responses": {
-                        "200": {"description": "Verification result"}
+                        "200": {
+                            "description": "Number of requeued reports",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "object",
+                                        "properties": {"requeued": {"type": "integer"}}
+                                    }
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            // Mailbox endpoints
-            "/tenants/{tenant_id}/mailboxes": {
+            "/admin/system/maintenance": {
                 "get": {
-                    "tags": ["mailboxes"],
-                    "summary": "List mailboxes",
-                    "operationId": "listMailboxes",
+                    "tags": ["admin"],
+                    "summary": "Get maintenance mode",
+                    "description": "While a maintenance window is active the system is read-only: SMTP answers 421, IMAP refuses changes with an ALERT, POP3 refuses deletions and API requests other than GET return 503 with Retry-After.",
+                    "operationId": "getMaintenance",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50}},
-                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}}
-                    ],
                     "responses": {
                         "200": {
-                            "description": "List of mailboxes",
+                            "description": "Maintenance state with active and upcoming windows",
                             "content": {
                                 "application/json": {
-                                    "schema": {
-                                        "type": "array",
-                                        "items": {"$ref": "#/components/schemas/Mailbox"}
-                                    }
+                                    "schema": {"$ref": "#/components/schemas/MaintenanceResponse"}
                                 }
                             }
-                        }
+                        },
+                        "403": {"description": "Missing admin scope"}
                     }
-                },
+                }
+            },
+            "/admin/system/maintenance/windows": {
                 "post": {
-                    "tags": ["mailboxes"],
-                    "summary": "Create a mailbox",
-                    "operationId": "createMailbox",
+                    "tags": ["admin"],
+                    "summary": "Schedule a maintenance window",
+                    "description": "Starts maintenance now unless starts_at is given. A window without ends_at lasts until maintenance is ended.",
+                    "operationId": "scheduleMaintenance",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
-                    ],
                     "requestBody": {
                         "required": true,
                         "content": {
                             "application/json": {
-                                "schema": {"$ref": "#/components/schemas/CreateMailboxRequest"}
+                                "schema": {"$ref": "#/components/schemas/ScheduleMaintenanceRequest"}
                             }
                         }
                     },
                     "responses": {
-                        "201": {"description": "Mailbox created"},
-                        "409": {"description": "Address already exists"}
+                        "201": {
+                            "description": "Window scheduled",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MaintenanceWindow"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Window ends before it starts or invalid message"},
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            // Hook endpoints
-            "/tenants/{tenant_id}/hooks": {
-                "get": {
-                    "tags": ["hooks"],
-                    "summary": "List hooks",
-                    "operationId": "listHooks",
+            "/admin/system/maintenance/windows/{window_id}": {
+                "delete": {
+                    "tags": ["admin"],
+                    "summary": "Cancel an upcoming maintenance window",
+                    "operationId": "cancelMaintenanceWindow",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "hook_type", "in": "query", "schema": {"type": "string"}},
-                        {"name": "enabled_only", "in": "query", "schema": {"type": "boolean"}}
+                        {"name": "window_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "responses": {
-                        "200": {"description": "List of hooks"}
+                        "204": {"description": "Window cancelled"},
+                        "404": {"description": "Window not found or already started"}
                     }
-                },
+                }
+            },
+            "/admin/system/maintenance/end": {
                 "post": {
-                    "tags": ["hooks"],
-                    "summary": "Create a hook",
-                    "operationId": "createHook",
+                    "tags": ["admin"],
+                    "summary": "End maintenance now",
+                    "description": "Ends the active windows. Upcoming windows are kept.",
+                    "operationId": "endMaintenance",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
-                    ],
-                    "requestBody": {
-                        "required": true,
-                        "content": {
-                            "application/json": {
-                                "schema": {"$ref": "#/components/schemas/CreateHookRequest"}
-                            }
-                        }
-                    },
                     "responses": {
-                        "201": {"description": "Hook created"}
+                        "200": {
+                            "description": "Maintenance state after ending",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MaintenanceResponse"}
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            "/tenants/{tenant_id}/hooks/{hook_id}/enable": {
-                "post": {
-                    "tags": ["hooks"],
-                    "summary": "Enable a hook",
-                    "operationId": "enableHook",
+            "/admin/system/sessions": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "List live sessions",
+                    "description": "SMTP, submission, IMAP, POP3 and ManageSieve sessions served by the server process answering the request, oldest first. Byte counts are of the decrypted traffic.",
+                    "operationId": "listSessions",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "hook_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "protocol", "in": "query", "schema": {"type": "string", "enum": ["smtp", "submission", "lmtp", "imap", "pop3", "managesieve"]}},
+                        {"name": "user", "in": "query", "schema": {"type": "string"}, "description": "Authenticated user, case-insensitive"},
+                        {"name": "ip", "in": "query", "schema": {"type": "string"}, "description": "Client IP address"},
+                        {"name": "tenant_id", "in": "query", "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "responses": {
-                        "200": {"description": "Hook enabled"}
+                        "200": {
+                            "description": "Live sessions",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/SessionInfo"}}
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            "/tenants/{tenant_id}/hooks/{hook_id}/disable": {
-                "post": {
-                    "tags": ["hooks"],
-                    "summary": "Disable a hook",
-                    "operationId": "disableHook",
+            "/admin/system/sessions/{session_id}": {
+                "delete": {
+                    "tags": ["admin"],
+                    "summary": "Terminate a session",
+                    "description": "Closes the session's connection at once, whatever it is doing.",
+                    "operationId": "terminateSession",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "hook_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "session_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "responses": {
-                        "200": {"description": "Hook disabled"}
+                        "204": {"description": "Session terminated"},
+                        "404": {"description": "Session not found or already closed"}
                     }
                 }
             },
-            // Send endpoint
-            "/tenants/{tenant_id}/send": {
+            "/admin/system/sessions/terminate": {
                 "post": {
-                    "tags": ["send"],
-                    "summary": "Send an email",
-                    "description": "Queue an email for delivery. The sender address must be a verified mailbox belonging to the tenant.",
-                    "operationId": "sendEmail",
+                    "tags": ["admin"],
+                    "summary": "Terminate matching sessions",
+                    "description": "Terminates every session matching all the given fields, e.g. all sessions of a user or a client address.",
+                    "operationId": "terminateSessions",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
-                    ],
                     "requestBody": {
                         "required": true,
                         "content": {
                             "application/json": {
-                                "schema": {"$ref": "#/components/schemas/SendEmailRequest"}
+                                "schema": {"$ref": "#/components/schemas/SessionFilter"}
                             }
                         }
                     },
                     "responses": {
-                        "202": {
-                            "description": "Email queued for delivery",
+                        "200": {
+                            "description": "Number of sessions terminated",
                             "content": {
                                 "application/json": {
-                                    "schema": {"$ref": "#/components/schemas/SendEmailResponse"}
+                                    "schema": {
+                                        "type": "object",
+                                        "properties": {"terminated": {"type": "integer"}}
+                                    }
                                 }
                             }
                         },
-                        "400": {"description": "Invalid request"},
-                        "403": {"description": "Forbidden - sender not authorized"}
+                        "400": {"description": "Empty filter"},
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            "/tenants/{tenant_id}/send/queue": {
+            "/admin/system/dnsbl-allowlist": {
                 "get": {
-                    "tags": ["send"],
-                    "summary": "Get send queue status",
-                    "operationId": "getSendQueue",
+                    "tags": ["admin"],
+                    "summary": "List the DNSBL allowlist",
+                    "description": "Client networks that are never looked up in DNS blocklists.",
+                    "operationId": "listDnsblAllowlist",
                     "security": [{"api_key": []}, {"bearer": []}],
-                    "parameters": [
-                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    "responses": {
+                        "200": {
+                            "description": "Allowlisted networks",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/DnsblAllowlistEntry"}}
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"}
+                    }
+                },
+                "post": {
+                    "tags": ["admin"],
+                    "summary": "Add a network to the DNSBL allowlist",
+                    "description": "Clients in the network skip DNS blocklist lookups, and with them the blocklist scores and refusals. Takes effect within a minute.",
+                    "operationId": "createDnsblAllowlistEntry",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateDnsblAllowlistRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Network allowlisted",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/DnsblAllowlistEntry"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Not an IP address or CIDR range"},
+                        "403": {"description": "Missing admin scope"},
+                        "409": {"description": "Network already allowlisted"}
+                    }
+                }
+            },
+            "/admin/system/dnsbl-allowlist/{entry_id}": {
+                "delete": {
+                    "tags": ["admin"],
+                    "summary": "Remove a network from the DNSBL allowlist",
+                    "operationId": "deleteDnsblAllowlistEntry",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "entry_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
+                    "responses": {
+                        "204": {"description": "Network removed"},
+                        "404": {"description": "Entry not found"}
+                    }
+                }
+            },
+            "/admin/system/bad-domains": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "List bad domains",
+                    "description": "Local URI blocklist: domains whose links add to the spam score of received messages.",
+                    "operationId": "listBadDomains",
+                    "security": [{"api_key": []}, {"bearer": []}],
                     "responses": {
                         "200": {
-                            "description": "Queue status",
+                            "description": "Bad domains",
                             "content": {
                                 "application/json": {
-                                    "schema": {"$ref": "#/components/schemas/QueueStatusResponse"}
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/BadDomain"}}
                                 }
                             }
+                        },
+                        "403": {"description": "Missing admin scope"}
+                    }
+                },
+                "post": {
+                    "tags": ["admin"],
+                    "summary": "Add a bad domain",
+                    "description": "Links to the domain or its subdomains add the entry's score to the spam score of messages from unauthenticated clients when URIBL checks are enabled. Takes effect within a minute.",
+                    "operationId": "createBadDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateBadDomainRequest"}
+                            }
                         }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Domain added",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/BadDomain"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid domain or score"},
+                        "403": {"description": "Missing admin scope"},
+                        "409": {"description": "Domain already listed"}
                     }
                 }
             },
-            // Messages endpoint
-            "/messages": {
+            "/admin/system/bad-domains/{entry_id}": {
+                "delete": {
+                    "tags": ["admin"],
+                    "summary": "Remove a bad domain",
+                    "operationId": "deleteBadDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "entry_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Domain removed"},
+                        "404": {"description": "Entry not found"}
+                    }
+                }
+            },
+            "/admin/system/sender-reputation": {
                 "get": {
-                    "tags": ["messages"],
-                    "summary": "List messages",
-                    "operationId": "listMessages",
+                    "tags": ["admin"],
+                    "summary": "List sender domain reputations",
+                    "description": "Local reputations of the envelope sender domains of unauthenticated clients, most recently seen first. Counts are decayed to the time of the request.",
+                    "operationId": "listSenderReputations",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "mailbox_id", "in": "query", "required": true, "schema": {"type": "string", "format": "uuid"}},
-                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50}},
-                        {"name": "cursor", "in": "query", "schema": {"type": "string"}}
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 100, "maximum": 1000}}
                     ],
                     "responses": {
                         "200": {
-                            "description": "List of messages",
+                            "description": "Sender domain reputations",
                             "content": {
                                 "application/json": {
-                                    "schema": {"$ref": "#/components/schemas/MessageListResponse"}
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/SenderReputation"}}
                                 }
                             }
-                        }
+                        },
+                        "403": {"description": "Missing admin scope"}
                     }
                 }
             },
-            "/messages/{id}": {
+            "/admin/system/sender-reputation/{domain}": {
                 "get": {
-                    "tags": ["messages"],
-                    "summary": "Get a message",
-                    "operationId": "getMessage",
+                    "tags": ["admin"],
+                    "summary": "Get a sender domain reputation",
+                    "operationId": "getSenderReputation",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "domain", "in": "path", "required": true, "schema": {"type": "string"}}
                     ],
                     "responses": {
-                        "200": {"description": "Message details"},
-                        "404": {"description": "Message not found"}
+                        "200": {
+                            "description": "Reputation of the domain",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/SenderReputation"}
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"},
+                        "404": {"description": "No mail seen from the domain"}
                     }
                 },
                 "delete": {
-                    "tags": ["messages"],
-                    "summary": "Delete a message",
-                    "operationId": "deleteMessage",
+                    "tags": ["admin"],
+                    "summary": "Reset a sender domain reputation",
+                    "description": "Forgets the domain's counts, so that its reputation starts again as clean.",
+                    "operationId": "resetSenderReputation",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "domain", "in": "path", "required": true, "schema": {"type": "string"}}
                     ],
                     "responses": {
-                        "204": {"description": "Message deleted"}
+                        "204": {"description": "Reputation reset"},
+                        "403": {"description": "Missing admin scope"},
+                        "404": {"description": "No mail seen from the domain"}
                     }
                 }
             },
-            "/messages/{id}/flags": {
+            "/admin/system/replication": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "Get the replication status",
+                    "description": "Role of this server, the standby's lag behind its primary and its message files still to copy, or the primary's subscribers.",
+                    "operationId": "getReplicationStatus",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "responses": {
+                        "200": {
+                            "description": "Replication status",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ReplicationStatus"}
+                                }
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"}
+                    }
+                }
+            },
+            "/admin/system/replication/files/{path}": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "Download a message file",
+                    "description": "Used by standbys to copy message files. The X-Checksum-SHA256 header holds the file's SHA-256 checksum (hex).",
+                    "operationId": "getReplicationFile",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "path", "in": "path", "required": true, "schema": {"type": "string"}, "description": "Storage path of the message"}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The message file",
+                            "content": {
+                                "message/rfc822": {"schema": {"type": "string", "format": "binary"}}
+                            }
+                        },
+                        "403": {"description": "Missing admin scope"},
+                        "404": {"description": "No message is stored in the file"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/users": {
+                "get": {
+                    "tags": ["users"],
+                    "summary": "List users",
+                    "operationId": "listUsers",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "List of users"}
+                    }
+                },
+                "post": {
+                    "tags": ["users"],
+                    "summary": "Create a user",
+                    "operationId": "createUser",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateUserRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {"description": "User created"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/users/{user_id}/settings": {
+                "get": {
+                    "tags": ["users"],
+                    "summary": "Get user settings",
+                    "operationId": "getUserSettings",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "User settings",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/UserSettings"}
+                                }
+                            }
+                        }
+                    }
+                },
                 "patch": {
-                    "tags": ["messages"],
-                    "summary": "Update message flags",
-                    "operationId": "updateMessageFlags",
+                    "tags": ["users"],
+                    "summary": "Update user settings",
+                    "operationId": "updateUserSettings",
                     "security": [{"api_key": []}, {"bearer": []}],
                     "parameters": [
-                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
                     ],
                     "requestBody": {
                         "required": true,
                         "content": {
                             "application/json": {
-                                "schema": {"$ref": "#/components/schemas/UpdateFlagsRequest"}
+                                "schema": {
+                                    "type": "object",
+                                    "properties": {
+                                        "junk_filing": {"type": "boolean"},
+                                        "desktop_notifications": {"type": "boolean"},
+                                        "notification_sound": {"type": "boolean"},
+                                        "digest_frequency": {"type": "string", "enum": ["never", "hourly", "daily", "weekly"]},
+                                        "sent_archive": {"type": "string", "description": "Folder or address that receives a copy of every message sent; an empty string removes it"},
+                                        "subaddress_filing": {"type": "boolean"},
+                                        "quarantine_digest": {"type": "boolean"}
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {"description": "Updated user settings"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/users/{user_id}/settings/notifications": {
+                "get": {
+                    "tags": ["users"],
+                    "summary": "Get webmail notification preferences",
+                    "operationId": "getNotificationSettings",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Notification preferences",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/NotificationSettings"}
+                                }
+                            }
+                        }
+                    }
+                },
+                "patch": {
+                    "tags": ["users"],
+                    "summary": "Update webmail notification preferences",
+                    "operationId": "updateNotificationSettings",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/NotificationSettings"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Updated notification preferences",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/NotificationSettings"}
+                                }
+                            }
+                        },
+                        "422": {"description": "Invalid digest frequency"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/users/{user_id}/apop-secret": {
+                "put": {
+                    "tags": ["users"],
+                    "summary": "Set or clear the POP3 APOP secret",
+                    "description": "APOP logins compare an MD5 digest of this secret, so it is stored as given and should differ from the account password. A null secret disables APOP for the user.",
+                    "operationId": "setApopSecret",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": {
+                                        "secret": {"type": "string", "nullable": true, "minLength": 8}
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "204": {"description": "APOP secret updated"},
+                        "404": {"description": "User not found"},
+                        "422": {"description": "Secret too short"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/users/{user_id}/services": {
+                "get": {
+                    "tags": ["users"],
+                    "summary": "Get the mail protocols a user may use",
+                    "operationId": "getUserServices",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Enabled protocols",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ServicesEnabled"}
+                                }
+                            }
+                        },
+                        "404": {"description": "User not found"}
+                    }
+                },
+                "put": {
+                    "tags": ["users"],
+                    "summary": "Switch IMAP and POP3 on or off for a user",
+                    "description": "With both protocols off the user can only use webmail and the API. Protocols switched off for the tenant or its plan stay off.",
+                    "operationId": "setUserServices",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/ServicesEnabled"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Updated protocols",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ServicesEnabled"}
+                                }
+                            }
+                        },
+                        "404": {"description": "User not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/users/{user_id}/security-events": {
+                "get": {
+                    "tags": ["users"],
+                    "summary": "List security events of a user",
+                    "description": "Logins blocked by the brute-force protection and logins from networks the user has not logged in from before, newest first. Whether users are also emailed about them is set per tenant with the notify_login_blocked and notify_new_login_location tenant settings.",
+                    "operationId": "listSecurityEvents",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50, "maximum": 100}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Security events",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/SecurityEvent"}}
+                                }
+                            }
+                        },
+                        "404": {"description": "User not found"}
+                    }
+                }
+            },
+            // Domain endpoints
+            "/tenants/{tenant_id}/domains": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "List domains",
+                    "operationId": "listDomains",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "List of domains",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/Domain"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["domains"],
+                    "summary": "Create a domain",
+                    "operationId": "createDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateDomainRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {"description": "Domain created"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "Get a domain",
+                    "operationId": "getDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Domain details with DNS records; dns_records.dkim_keys lists every DKIM key of the domain with the TXT record it needs"}
+                    }
+                },
+                "delete": {
+                    "tags": ["domains"],
+                    "summary": "Delete a domain",
+                    "operationId": "deleteDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Domain deleted"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/verify": {
+                "post": {
+                    "tags": ["domains"],
+                    "summary": "Verify domain DNS",
+                    "operationId": "verifyDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Verification result with the DNS records to publish; dns_records.subdomains lists the wildcard MX and extra DKIM records needed when the domain settings (extra_settings.subdomains) cover subdomains, dns_records.mta_sts the _mta-sts TXT record when they have an MTA-STS policy (extra_settings.mta_sts), which the web UI serves at https://mta-sts.{domain}/.well-known/mta-sts.txt, and dns_records.bimi the BIMI TXT record when they have a logo (extra_settings.bimi)"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/dkim/keys": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "List the domain's DKIM keys",
+                    "description": "Pending, active and retired DKIM keys with the TXT record each needs. Mail is signed with every active key: with an RSA and an Ed25519 key active, messages carry both signatures.",
+                    "operationId": "listDkimKeys",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "DKIM keys, newest first",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/DkimKey"}}
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["domains"],
+                    "summary": "Add a DKIM key",
+                    "description": "Adds a key, generated unless private_key is given. The key is pending until activate_at and, when smtp.dkim_rotation.check_dns is set, until its TXT record is published; then it is activated and the domain's active key of the same algorithm is retired. The domain must be verified.",
+                    "operationId": "createDkimKey",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": {
+                                        "algorithm": {"type": "string", "enum": ["rsa-sha256", "ed25519-sha256"], "default": "rsa-sha256"},
+                                        "selector": {"type": "string", "description": "Defaults to a dated selector such as rsa20261016"},
+                                        "private_key": {"type": "string", "description": "PKCS#8 PEM private key"},
+                                        "activate_at": {"type": "string", "format": "date-time", "description": "Defaults to now"},
+                                        "activate": {"type": "boolean", "default": false, "description": "Activate at once, without waiting for the TXT record"}
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Key added",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/DkimKey"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Unknown algorithm, invalid selector or unusable private key"},
+                        "409": {"description": "The domain already has a key under the selector"},
+                        "412": {"description": "Domain not verified"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/dkim/keys/{key_id}": {
+                "delete": {
+                    "tags": ["domains"],
+                    "summary": "Delete a DKIM key",
+                    "operationId": "deleteDkimKey",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "key_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Key deleted"},
+                        "409": {"description": "The key is active; retire it first"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/dkim/keys/{key_id}/activate": {
+                "post": {
+                    "tags": ["domains"],
+                    "summary": "Activate a DKIM key",
+                    "description": "Signs with the key from now on and retires the domain's active key of the same algorithm, for example to roll back a rotation.",
+                    "operationId": "activateDkimKey",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "key_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Activated key",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/DkimKey"}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/dkim/keys/{key_id}/retire": {
+                "post": {
+                    "tags": ["domains"],
+                    "summary": "Retire a DKIM key",
+                    "description": "Stops signing with the key. Its record stays listed until the key is deleted, which the rotation worker does after smtp.dkim_rotation.retain_days.",
+                    "operationId": "retireDkimKey",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "key_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Retired key",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/DkimKey"}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/bimi": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "Check the domain's BIMI setup",
+                    "description": "The BIMI TXT record to publish for the logo in the domain settings (extra_settings.bimi: selector, location and authority URLs), and what receivers find: the published record, whether the DMARC policy is enforced (quarantine or reject at pct=100, which receivers require before showing a logo), whether the logo is a valid SVG Tiny PS image, and whether the Verified Mark Certificate is valid for the domain. VMCs are validated against the roots in MAIRUST_BIMI_VMC_ROOTS.",
+                    "operationId": "getDomainBimi",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Record to publish and check results",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/BimiStatus"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Domain not found, or its settings have no logo"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/dmarc-reports": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "List received DMARC aggregate reports",
+                    "description": "DMARC aggregate reports other mail systems sent to the domain's report addresses (domain settings extra_settings.report_addresses), with the messages of each source address summed across them. Sources whose mail passed neither aligned DKIM nor aligned SPF are sending mail as the domain without authorization.",
+                    "operationId": "listDmarcReports",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "days", "in": "query", "description": "Reports whose period ended in the last days", "schema": {"type": "integer", "default": 30, "maximum": 366}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Reports and sources",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/DmarcReports"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Domain not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/dmarc-reports/{report_id}": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "Get a received DMARC aggregate report",
+                    "operationId": "getDmarcReport",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "report_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The report with its rows",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "allOf": [
+                                            {"$ref": "#/components/schemas/ReceivedDmarcReport"},
+                                            {
+                                                "type": "object",
+                                                "properties": {
+                                                    "records": {"type": "array", "items": {"$ref": "#/components/schemas/ReceivedDmarcRecord"}}
+                                                }
+                                            }
+                                        ]
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "Domain or report not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/domains/{domain_id}/tls-reports": {
+                "get": {
+                    "tags": ["domains"],
+                    "summary": "List received TLS-RPT reports",
+                    "description": "TLS-RPT reports (RFC 8460) other mail systems sent to the domain's report addresses, one entry per policy of a report, newest first.",
+                    "operationId": "listTlsReports",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "domain_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "days", "in": "query", "description": "Reports whose period ended in the last days", "schema": {"type": "integer", "default": 30, "maximum": 366}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "TLS report policies",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/ReceivedTlsReport"}}
+                                }
+                            }
+                        },
+                        "404": {"description": "Domain not found"}
+                    }
+                }
+            },
+            // Mailbox endpoints
+            "/tenants/{tenant_id}/mailboxes": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "List mailboxes",
+                    "operationId": "listMailboxes",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "List of mailboxes",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/Mailbox"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["mailboxes"],
+                    "summary": "Create a mailbox",
+                    "description": "When the mailbox has an owner, the tenant's default folders (settings.default_folders) are created for them, named in settings.default_locale.",
+                    "operationId": "createMailbox",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateMailboxRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {"description": "Mailbox created"},
+                        "409": {"description": "Address already exists"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/bulk": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "List bulk provisioning batches",
+                    "operationId": "listBulkMailboxBatches",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50, "maximum": 200}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Batches, newest first",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/ProvisioningBatch"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["mailboxes"],
+                    "summary": "Create users with mailboxes in bulk",
+                    "description": "Queues up to 5000 users with mailboxes, created in the background one row at a time; follow the batch to get each row's result. Rows whose mailbox already exists in the tenant are reported as `exists` and left untouched, so submitting the same rows again is safe. Users without a password get a generated one (`password_mode=generate`, returned only in this response) or an invite to choose one, emailed to `invite_email` (`password_mode=invite`). The body is JSON, or CSV with a header line naming the row fields when sent as `text/csv`, with the options as query parameters.",
+                    "operationId": "submitBulkMailboxes",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "password_mode", "in": "query", "schema": {"type": "string", "enum": ["generate", "invite"], "default": "generate"}},
+                        {"name": "idempotency_key", "in": "query", "description": "Submitting again with the same key returns the first batch", "schema": {"type": "string", "maxLength": 255}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "required": ["rows"],
+                                    "properties": {
+                                        "password_mode": {"type": "string", "enum": ["generate", "invite"], "default": "generate"},
+                                        "idempotency_key": {"type": "string", "maxLength": 255},
+                                        "rows": {
+                                            "type": "array",
+                                            "maxItems": 5000,
+                                            "items": {"$ref": "#/components/schemas/BulkMailboxRow"}
+                                        }
+                                    }
+                                }
+                            },
+                            "text/csv": {
+                                "schema": {"type": "string"},
+                                "example": "email,name,role,quota_bytes,invite_email\nalice@example.com,Alice,tenant_admin,,alice@home.example\n"
+                            }
+                        }
+                    },
+                    "responses": {
+                        "202": {
+                            "description": "Batch queued",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/BulkProvisionResponse"}
+                                }
+                            }
+                        },
+                        "200": {
+                            "description": "The earlier batch of the idempotency key, without passwords",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/BulkProvisionResponse"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Malformed body, no rows or too many rows"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/bulk/{batch_id}": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "Get a bulk provisioning batch with its row results",
+                    "operationId": "getBulkMailboxBatch",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "batch_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "status", "in": "query", "schema": {"type": "string", "enum": ["pending", "created", "exists", "failed"]}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 1000, "maximum": 5000}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The batch and its rows in submitted order",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "object",
+                                        "properties": {
+                                            "batch": {"$ref": "#/components/schemas/ProvisioningBatch"},
+                                            "rows": {
+                                                "type": "array",
+                                                "items": {"$ref": "#/components/schemas/ProvisioningRow"}
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "Batch not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/{mailbox_id}/unread-count": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "Get unread message count",
+                    "description": "Reads counters maintained on the mailbox, cheap enough to poll for an unread badge",
+                    "operationId": "getMailboxUnreadCount",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Mailbox counters",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MailboxCounters"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Mailbox not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/{mailbox_id}/folders": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "List folders of the mailbox owner",
+                    "description": "Folders with their special use (sent, drafts, trash, junk, archive), taken from provisioning or recognised from localized names. IMAP LIST reports the same attributes.",
+                    "operationId": "listMailboxFolders",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Folders",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/Folder"}
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "Mailbox not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/{mailbox_id}/metadata": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "Get mailbox metadata",
+                    "description": "Folder settings stored as RFC 5464 metadata entries, shared with IMAP GETMETADATA/SETMETADATA.",
+                    "operationId": "getMailboxMetadata",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Mailbox metadata",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MailboxMetadata"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Mailbox not found"}
+                    }
+                },
+                "put": {
+                    "tags": ["mailboxes"],
+                    "summary": "Set or remove mailbox metadata entries",
+                    "operationId": "updateMailboxMetadata",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/UpdateMailboxMetadataRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {"description": "Updated mailbox metadata"},
+                        "400": {"description": "Invalid entry name"},
+                        "413": {"description": "Value too large"},
+                        "422": {"description": "Too many entries"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/{mailbox_id}/filters": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "List mailbox filters",
+                    "description": "Server-side filter rules for users who do not write Sieve, in the order they run when mail is delivered.",
+                    "operationId": "listMailboxFilters",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Filters",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/MailboxFilter"}
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "Mailbox not found"}
+                    }
+                },
+                "post": {
+                    "tags": ["mailboxes"],
+                    "summary": "Create a mailbox filter",
+                    "operationId": "createMailboxFilter",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/MailboxFilterRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Filter created",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MailboxFilter"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid name, conditions or actions"},
+                        "404": {"description": "Mailbox not found"},
+                        "409": {"description": "The mailbox has 100 filters already"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/mailboxes/{mailbox_id}/filters/{filter_id}": {
+                "get": {
+                    "tags": ["mailboxes"],
+                    "summary": "Get a mailbox filter",
+                    "operationId": "getMailboxFilter",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "filter_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Filter",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MailboxFilter"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Filter not found"}
+                    }
+                },
+                "put": {
+                    "tags": ["mailboxes"],
+                    "summary": "Replace a mailbox filter",
+                    "description": "The position is kept when none is given.",
+                    "operationId": "updateMailboxFilter",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "filter_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/MailboxFilterRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Filter updated",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MailboxFilter"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid name, conditions or actions"},
+                        "404": {"description": "Filter not found"}
+                    }
+                },
+                "delete": {
+                    "tags": ["mailboxes"],
+                    "summary": "Delete a mailbox filter",
+                    "operationId": "deleteMailboxFilter",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "mailbox_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "filter_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Filter deleted"},
+                        "404": {"description": "Filter not found"}
+                    }
+                }
+            },
+            // Hook endpoints
+            "/tenants/{tenant_id}/hooks": {
+                "get": {
+                    "tags": ["hooks"],
+                    "summary": "List hooks",
+                    "operationId": "listHooks",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "hook_type", "in": "query", "schema": {"type": "string"}},
+                        {"name": "enabled_only", "in": "query", "schema": {"type": "boolean"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "List of hooks"}
+                    }
+                },
+                "post": {
+                    "tags": ["hooks"],
+                    "summary": "Create a hook",
+                    "operationId": "createHook",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateHookRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {"description": "Hook created"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/hooks/{hook_id}/enable": {
+                "post": {
+                    "tags": ["hooks"],
+                    "summary": "Enable a hook",
+                    "operationId": "enableHook",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "hook_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Hook enabled"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/hooks/{hook_id}/disable": {
+                "post": {
+                    "tags": ["hooks"],
+                    "summary": "Disable a hook",
+                    "operationId": "disableHook",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "hook_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Hook disabled"}
+                    }
+                }
+            },
+            // Inbound route endpoints
+            "/tenants/{tenant_id}/inbound-routes": {
+                "get": {
+                    "tags": ["inbound-routes"],
+                    "summary": "List inbound routes",
+                    "operationId": "listInboundRoutes",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "List of inbound routes",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/InboundRoute"}}
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["inbound-routes"],
+                    "summary": "Create an inbound route",
+                    "operationId": "createInboundRoute",
+                    "description": "Messages received for the address, or for every address of the domain, are parsed to JSON and posted to the webhook. Posts carry an `X-Webhook-Signature: sha256=<hex>` HMAC of the body keyed with the route secret and are retried with backoff until the webhook answers 2xx.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateInboundRouteRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Inbound route created",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/InboundRoute"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid address, unknown domain or disallowed webhook URL"},
+                        "409": {"description": "A route for the address already exists"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/inbound-routes/{route_id}": {
+                "get": {
+                    "tags": ["inbound-routes"],
+                    "summary": "Get an inbound route",
+                    "operationId": "getInboundRoute",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "route_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Inbound route"},
+                        "404": {"description": "Route not found"}
+                    }
+                },
+                "delete": {
+                    "tags": ["inbound-routes"],
+                    "summary": "Delete an inbound route",
+                    "operationId": "deleteInboundRoute",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "route_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Inbound route deleted"},
+                        "404": {"description": "Route not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/inbound-routes/{route_id}/enable": {
+                "post": {
+                    "tags": ["inbound-routes"],
+                    "summary": "Enable an inbound route",
+                    "operationId": "enableInboundRoute",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "route_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Inbound route enabled"},
+                        "404": {"description": "Route not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/inbound-routes/{route_id}/disable": {
+                "post": {
+                    "tags": ["inbound-routes"],
+                    "summary": "Disable an inbound route",
+                    "operationId": "disableInboundRoute",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "route_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Inbound route disabled"},
+                        "404": {"description": "Route not found"}
+                    }
+                }
+            },
+            "/inbound/attachments/{route_id}/{message_id}/{index}": {
+                "get": {
+                    "tags": ["inbound-routes"],
+                    "summary": "Download an inbound attachment",
+                    "operationId": "downloadInboundAttachment",
+                    "description": "Target of the signed attachment URLs in webhook payloads. Needs no API key; links expire after 7 days.",
+                    "parameters": [
+                        {"name": "route_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "message_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "index", "in": "path", "required": true, "schema": {"type": "integer", "minimum": 0}},
+                        {"name": "expires", "in": "query", "required": true, "schema": {"type": "integer"}, "description": "Unix time the link expires"},
+                        {"name": "signature", "in": "query", "required": true, "schema": {"type": "string"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Attachment contents"},
+                        "403": {"description": "Invalid or expired link, or the message was flagged when rescanned"},
+                        "404": {"description": "Attachment not found"}
+                    }
+                }
+            },
+            // Config bundle endpoints
+            "/tenants/{tenant_id}/protected-names": {
+                "get": {
+                    "tags": ["protected-names"],
+                    "summary": "List protected display names",
+                    "operationId": "listProtectedNames",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "List of protected display names",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/ProtectedName"}}
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["protected-names"],
+                    "summary": "Protect a display name",
+                    "operationId": "createProtectedName",
+                    "description": "When the tenant setting `display_name_protection` is `warn` or `tag`, inbound mail whose From display name shows a protected name (or a user's name, unless `display_name_protect_users` is false) but comes from outside the tenant's domains gets a `display_name_impersonation` entry in `metadata.warnings`. In `tag` mode it is also tagged `impersonation`.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateProtectedNameRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Display name protected",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ProtectedName"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid name or address"},
+                        "409": {"description": "The name is already protected"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/protected-names/{name_id}": {
+                "delete": {
+                    "tags": ["protected-names"],
+                    "summary": "Stop protecting a display name",
+                    "operationId": "deleteProtectedName",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "name_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Protected name deleted"},
+                        "404": {"description": "Protected name not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/quarantine": {
+                "get": {
+                    "tags": ["quarantine"],
+                    "summary": "List quarantined messages",
+                    "operationId": "listQuarantine",
+                    "description": "Messages held by the virus scan, the spam filter or a policy, newest first.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "user_id", "in": "query", "description": "Only messages addressed to this user's mailboxes", "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50, "maximum": 200}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Quarantined messages",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/QuarantinedMessage"}}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/quarantine/{message_id}": {
+                "get": {
+                    "tags": ["quarantine"],
+                    "summary": "Preview a quarantined message",
+                    "operationId": "getQuarantinedMessage",
+                    "description": "Returns the header fields and body text. Attachments are described but never served.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "message_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Message preview",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/QuarantinePreview"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Quarantined message not found"},
+                        "422": {"description": "The message could not be parsed"}
+                    }
+                },
+                "delete": {
+                    "tags": ["quarantine"],
+                    "summary": "Delete a quarantined message",
+                    "operationId": "deleteQuarantinedMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "message_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Message deleted"},
+                        "404": {"description": "Quarantined message not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/quarantine/{message_id}/release": {
+                "post": {
+                    "tags": ["quarantine"],
+                    "summary": "Release a quarantined message",
+                    "operationId": "releaseQuarantinedMessage",
+                    "description": "Moves the message into the mailbox it was addressed to.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "message_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Message released",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "object",
+                                        "properties": {
+                                            "id": {"type": "string", "format": "uuid"},
+                                            "mailbox_id": {"type": "string", "format": "uuid"}
+                                        }
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "Quarantined message not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/spam-rules": {
+                "get": {
+                    "tags": ["spam-rules"],
+                    "summary": "List spam rules",
+                    "operationId": "listSpamRules",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Spam rules of the tenant, by name",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/SpamRule"}}
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["spam-rules"],
+                    "summary": "Create a spam rule",
+                    "operationId": "createSpamRule",
+                    "description": "Adds a rule to the rule-based spam filter for the tenant's recipients. A rule named like a built-in rule (such as SUBJECT_ALL_CAPS) replaces it; a disabled one turns it off. Changes apply to received mail within a minute.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/SpamRuleRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Spam rule created",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/SpamRule"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid name, scope, pattern or score"},
+                        "409": {"description": "A rule of that name exists, or the tenant has 200 rules already"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/spam-rules/{rule_id}": {
+                "get": {
+                    "tags": ["spam-rules"],
+                    "summary": "Get a spam rule",
+                    "operationId": "getSpamRule",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "rule_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Spam rule",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/SpamRule"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Spam rule not found"}
+                    }
+                },
+                "put": {
+                    "tags": ["spam-rules"],
+                    "summary": "Replace a spam rule",
+                    "operationId": "updateSpamRule",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "rule_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/SpamRuleRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Spam rule replaced",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/SpamRule"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid name, scope, pattern or score"},
+                        "404": {"description": "Spam rule not found"},
+                        "409": {"description": "Another rule has that name"}
+                    }
+                },
+                "delete": {
+                    "tags": ["spam-rules"],
+                    "summary": "Delete a spam rule",
+                    "operationId": "deleteSpamRule",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "rule_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Spam rule deleted"},
+                        "404": {"description": "Spam rule not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies": {
+                "get": {
+                    "tags": ["policies"],
+                    "summary": "List policies",
+                    "operationId": "listPolicies",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Policies of the tenant",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/PolicyRule"}}
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["policies"],
+                    "summary": "Create a policy",
+                    "operationId": "createPolicy",
+                    "description": "Adds a mail flow rule. Every condition and action is checked against the PolicyCondition and PolicyAction schemas; the engine skips policies whose conditions it cannot read. Conditions can be grouped with `all_of`, `any_of` and `none_of`, which nest: `[{\"any_of\": [A, B]}, C]` matches when A or B matches, and C does.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/PolicyRuleRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Policy created",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/PolicyRule"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid policy type, or conditions or actions that do not match the PolicyCondition and PolicyAction schemas"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies/{policy_id}": {
+                "get": {
+                    "tags": ["policies"],
+                    "summary": "Get a policy",
+                    "operationId": "getPolicy",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Policy",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/PolicyRule"}
+                                }
+                            }
+                        },
+                        "403": {"description": "The policy belongs to another tenant"},
+                        "404": {"description": "Policy not found"}
+                    }
+                },
+                "put": {
+                    "tags": ["policies"],
+                    "summary": "Update a policy",
+                    "operationId": "updatePolicy",
+                    "description": "Fields left out keep their value.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/PolicyRuleUpdate"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Policy updated",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/PolicyRule"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid policy type, or conditions or actions that do not match the PolicyCondition and PolicyAction schemas"},
+                        "403": {"description": "The policy belongs to another tenant"},
+                        "404": {"description": "Policy not found"}
+                    }
+                },
+                "delete": {
+                    "tags": ["policies"],
+                    "summary": "Delete a policy",
+                    "operationId": "deletePolicy",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Policy deleted"},
+                        "403": {"description": "The policy belongs to another tenant"},
+                        "404": {"description": "Policy not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies/{policy_id}/enable": {
+                "post": {
+                    "tags": ["policies"],
+                    "summary": "Enable a policy",
+                    "operationId": "enablePolicy",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Policy enabled"},
+                        "403": {"description": "The policy belongs to another tenant"},
+                        "404": {"description": "Policy not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies/{policy_id}/disable": {
+                "post": {
+                    "tags": ["policies"],
+                    "summary": "Disable a policy",
+                    "operationId": "disablePolicy",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Policy disabled"},
+                        "403": {"description": "The policy belongs to another tenant"},
+                        "404": {"description": "Policy not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/tracking-domains": {
+                "get": {
+                    "tags": ["tracking-domains"],
+                    "summary": "List tracking domains",
+                    "operationId": "listTrackingDomains",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "List of tracking domains",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/TrackingDomain"}}
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "tags": ["tracking-domains"],
+                    "summary": "Add a tracking domain",
+                    "operationId": "createTrackingDomain",
+                    "description": "When `[tracking]` is enabled, links in the HTML of campaign mail are rewritten to tracked links. Campaigns sent from a domain with the same organizational domain as an active tracking domain link to it over HTTPS, so that the links align with the SPF and DKIM authenticated sender. The domain must share its organizational domain with a verified domain of the tenant that has a DKIM key. It is verified once it has a CNAME to `cname_target`, then gets a certificate over ACME and becomes `active`.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateTrackingDomainRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Tracking domain added, pending its CNAME",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/TrackingDomain"}
+                                }
+                            }
+                        },
+                        "409": {"description": "The domain is already a tracking domain"},
+                        "400": {"description": "Invalid domain, or not aligned with a verified DKIM-signing domain of the tenant"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/tracking-domains/{id}": {
+                "get": {
+                    "tags": ["tracking-domains"],
+                    "summary": "Get a tracking domain",
+                    "operationId": "getTrackingDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The tracking domain",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/TrackingDomain"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Tracking domain not found"}
+                    }
+                },
+                "delete": {
+                    "tags": ["tracking-domains"],
+                    "summary": "Remove a tracking domain",
+                    "operationId": "deleteTrackingDomain",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Tracking domain removed"},
+                        "404": {"description": "Tracking domain not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/tracking-domains/{id}/verify": {
+                "post": {
+                    "tags": ["tracking-domains"],
+                    "summary": "Check the CNAME of a tracking domain",
+                    "operationId": "verifyTrackingDomain",
+                    "description": "Checks the CNAME of a pending domain now instead of at the next periodic check. A failed check is described in `last_error`.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The tracking domain after the check",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/TrackingDomain"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Tracking domain not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies/simulate": {
+                "post": {
+                    "tags": ["policies"],
+                    "summary": "Simulate a mail flow rule",
+                    "operationId": "simulatePolicy",
+                    "description": "Replays the tenant's recent stored mail through the current policies with and without the proposed rule, without saving it. Mail rejected when it was received was never stored and is not replayed. Conditions that stored messages cannot answer are listed in `warnings`.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/SimulatePolicyRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "What the rule would have done",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/PolicySimulationReport"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid rule"},
+                        "403": {"description": "The replaced policy belongs to another tenant"},
+                        "404": {"description": "Replaced policy not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies/{policy_id}/versions": {
+                "get": {
+                    "tags": ["policies"],
+                    "summary": "List policy versions",
+                    "operationId": "listPolicyVersions",
+                    "description": "Every change of the policy, newest first: who made it, when, the policy after it and the fields it changed. Versions remain after the policy is deleted.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Policy versions",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/PolicyVersion"}
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "The tenant has no policy with this ID"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/policies/matches": {
+                "get": {
+                    "tags": ["policies"],
+                    "summary": "List policy matches",
+                    "operationId": "listPolicyMatches",
+                    "description": "Inbound policies that matched received messages, newest first, with the actions of the policy and what happened to the message, including messages that were refused and never stored.",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "policy_id", "in": "query", "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "message_id", "in": "query", "schema": {"type": "string"}, "description": "Message-ID header, with or without angle brackets"},
+                        {"name": "sender", "in": "query", "schema": {"type": "string"}, "description": "Envelope sender"},
+                        {"name": "recipient", "in": "query", "schema": {"type": "string"}, "description": "Envelope recipient"},
+                        {"name": "outcome", "in": "query", "schema": {"type": "string", "enum": ["rejected", "deferred", "quarantined", "accepted"]}},
+                        {"name": "from", "in": "query", "schema": {"type": "string", "format": "date-time"}},
+                        {"name": "to", "in": "query", "schema": {"type": "string", "format": "date-time"}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50, "maximum": 100}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Policy matches",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/PolicyMatchList"}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/config-bundle": {
+                "get": {
+                    "tags": ["config-bundles"],
+                    "summary": "Export configuration bundle",
+                    "description": "Exports the tenant's policies, hooks and domain settings. Entries reference domains and mailboxes by name so the bundle can be imported into another environment.",
+                    "operationId": "exportConfigBundle",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "format", "in": "query", "schema": {"type": "string", "enum": ["json", "yaml"], "default": "json"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Configuration bundle",
+                            "content": {
+                                "application/json": {"schema": {"$ref": "#/components/schemas/ConfigBundle"}},
+                                "application/yaml": {"schema": {"$ref": "#/components/schemas/ConfigBundle"}}
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/config-bundle/import": {
+                "post": {
+                    "tags": ["config-bundles"],
+                    "summary": "Import configuration bundle",
+                    "description": "Upserts policies, hooks and domain settings by name in a single transaction. Importing the same bundle again changes nothing.",
+                    "operationId": "importConfigBundle",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "format", "in": "query", "schema": {"type": "string", "enum": ["json", "yaml"]}, "description": "Defaults to the Content-Type of the body, then JSON"},
+                        {"name": "dry_run", "in": "query", "schema": {"type": "boolean", "default": false}, "description": "Report the planned changes without applying them"},
+                        {"name": "prune", "in": "query", "schema": {"type": "boolean", "default": false}, "description": "Delete policies and hooks missing from the bundle and reset settings of domains it does not list"}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {"schema": {"$ref": "#/components/schemas/ConfigBundle"}},
+                            "application/yaml": {"schema": {"$ref": "#/components/schemas/ConfigBundle"}}
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Applied (or planned) changes",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/ImportReport"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid bundle, or it references an unknown domain or mailbox"}
+                    }
+                }
+            },
+            // Contact endpoints
+            "/tenants/{tenant_id}/contacts/suggest": {
+                "get": {
+                    "tags": ["contacts"],
+                    "summary": "Suggest recipients",
+                    "description": "Matches addresses the user recently sent to or received from, ranked by frequency and recency",
+                    "operationId": "suggestContacts",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "q", "in": "query", "required": true, "schema": {"type": "string"}, "description": "Prefix of the address or of a word in the display name"},
+                        {"name": "user_id", "in": "query", "schema": {"type": "string", "format": "uuid"}, "description": "Required for tenant-level API keys"},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 10, "maximum": 50}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Suggested correspondents",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": {"$ref": "#/components/schemas/Correspondent"}
+                                    }
+                                }
+                            }
+                        },
+                        "400": {"description": "No user given for a tenant-level key"}
+                    }
+                }
+            },
+            // Send endpoint
+            "/tenants/{tenant_id}/send": {
+                "post": {
+                    "tags": ["send"],
+                    "summary": "Send an email",
+                    "description": "Queue an email for delivery. The sender address must be a verified mailbox belonging to the tenant. With `secure` set, the message is stored encrypted and each recipient is sent a link to read it on the web UI after entering a passcode instead.",
+                    "operationId": "sendEmail",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/SendEmailRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "202": {
+                            "description": "Email queued for delivery",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/SendEmailResponse"}
+                                }
+                            }
+                        },
+                        "400": {
+                            "description": "Invalid request",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/Error"}
+                                }
+                            }
+                        },
+                        "403": {
+                            "description": "Forbidden - sender not authorized",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/Error"}
+                                }
+                            }
+                        },
+                        "503": {
+                            "description": "Secure messages are not enabled on this server",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/Error"}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/secure-messages": {
+                "get": {
+                    "tags": ["send"],
+                    "summary": "List secure messages",
+                    "description": "Secure messages sent by the tenant, one per recipient. Includes expired, revoked and locked messages.",
+                    "operationId": "listSecureMessages",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "default": 0}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "minimum": 1, "maximum": 200, "default": 50}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Secure messages, newest first",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/SecureMessage"}}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/secure-messages/{id}": {
+                "get": {
+                    "tags": ["send"],
+                    "summary": "Get a secure message with its access log",
+                    "operationId": "getSecureMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Secure message",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/SecureMessageDetail"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Secure message not found"}
+                    }
+                },
+                "delete": {
+                    "tags": ["send"],
+                    "summary": "Revoke a secure message",
+                    "description": "The link stops working immediately. Revocation is audit logged.",
+                    "operationId": "revokeSecureMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Secure message revoked"},
+                        "404": {"description": "Secure message not found"}
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/send/queue": {
+                "get": {
+                    "tags": ["send"],
+                    "summary": "Get send queue status",
+                    "operationId": "getSendQueue",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Queue status",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/QueueStatusResponse"}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/tenants/{tenant_id}/send/preflight": {
+                "post": {
+                    "tags": ["send"],
+                    "summary": "Check delivery to a recipient",
+                    "description": "Report what would happen to a message to the recipient without sending anything: MX resolution, MTA-STS and DANE records, suppressions, the tenant's sending quota and the outbound route.",
+                    "operationId": "preflightSend",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "tenant_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/PreflightRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Preflight report",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/PreflightReport"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Invalid recipient address"}
+                    }
+                }
+            },
+            // Messages endpoint
+            "/messages": {
+                "get": {
+                    "tags": ["messages"],
+                    "summary": "List messages",
+                    "operationId": "listMessages",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "mailbox_id", "in": "query", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 50}},
+                        {"name": "cursor", "in": "query", "schema": {"type": "string"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "List of messages",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/MessageListResponse"}
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/messages/{id}": {
+                "get": {
+                    "tags": ["messages"],
+                    "summary": "Get a message",
+                    "operationId": "getMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Message details"},
+                        "404": {"description": "Message not found"}
+                    }
+                },
+                "delete": {
+                    "tags": ["messages"],
+                    "summary": "Delete a message",
+                    "operationId": "deleteMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Message deleted"}
+                    }
+                }
+            },
+            "/messages/{id}/attachments/{index}": {
+                "get": {
+                    "tags": ["messages"],
+                    "summary": "Download an attachment",
+                    "description": "When rescanning on download is enabled, a message whose last scan is older than the rescan interval is checked again by the antivirus and URL reputation checks first. Attachments of a flagged message are refused with 403 and an explanation.",
+                    "operationId": "downloadMessageAttachment",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "index", "in": "path", "required": true, "schema": {"type": "integer", "minimum": 0}}
+                    ],
+                    "responses": {
+                        "200": {"description": "Attachment contents"},
+                        "403": {
+                            "description": "The message was flagged when rescanned; `message` gives the reason",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/Error"}
+                                }
+                            }
+                        },
+                        "404": {"description": "Message or attachment not found"}
+                    }
+                }
+            },
+            "/messages/{id}/print": {
+                "get": {
+                    "tags": ["messages"],
+                    "summary": "Print view of a message",
+                    "description": "A self-contained HTML page with the message's From, To, Cc, Date and Subject, its body as text and the list of its attachments. HTML bodies are converted to text; the page loads no remote content and runs no scripts.",
+                    "operationId": "printMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Print view",
+                            "content": {"text/html": {"schema": {"type": "string"}}}
+                        },
+                        "404": {"description": "Message not found"},
+                        "422": {"description": "The stored message could not be parsed"}
+                    }
+                }
+            },
+            "/messages/{id}/export": {
+                "get": {
+                    "tags": ["messages"],
+                    "summary": "Download a message",
+                    "description": "`eml` returns the message as stored. `pdf` returns the print view as an A4 PDF; its fonts only cover Latin-1, so other characters are printed as `?`.",
+                    "operationId": "exportMessage",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "format", "in": "query", "required": false, "schema": {"type": "string", "enum": ["eml", "pdf"], "default": "eml"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The message as an attachment named after its subject",
+                            "content": {
+                                "message/rfc822": {"schema": {"type": "string", "format": "binary"}},
+                                "application/pdf": {"schema": {"type": "string", "format": "binary"}}
+                            }
+                        },
+                        "404": {"description": "Message not found"},
+                        "422": {"description": "The stored message could not be parsed"}
+                    }
+                }
+            },
+            "/messages/{id}/shares": {
+                "get": {
+                    "tags": ["messages"],
+                    "summary": "List share links of a message",
+                    "description": "Includes expired and revoked links. Tokens are not returned.",
+                    "operationId": "listMessageShares",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Share links, newest first",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"type": "array", "items": {"$ref": "#/components/schemas/MessageShare"}}
+                                }
+                            }
+                        },
+                        "404": {"description": "Message not found"}
+                    }
+                },
+                "post": {
+                    "tags": ["messages"],
+                    "summary": "Share a message through a read-only link",
+                    "description": "Anyone with the link can read the message, with its body as sanitized HTML, and download its attachments until the link expires or is revoked. The web UI serves the page at the returned `path`. The token is only returned here. Creation is audit logged.",
+                    "operationId": "createMessageShare",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/CreateMessageShareRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Link created",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/CreatedMessageShare"}
+                                }
+                            }
+                        },
+                        "400": {"description": "Lifetime out of range"},
+                        "404": {"description": "Message not found"}
+                    }
+                }
+            },
+            "/messages/{id}/shares/{share_id}": {
+                "delete": {
+                    "tags": ["messages"],
+                    "summary": "Revoke a share link",
+                    "description": "The link stops working immediately. Revocation is audit logged.",
+                    "operationId": "revokeMessageShare",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}},
+                        {"name": "share_id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "204": {"description": "Link revoked"},
+                        "404": {"description": "Share not found"}
+                    }
+                }
+            },
+            "/messages/{id}/flags": {
+                "patch": {
+                    "tags": ["messages"],
+                    "summary": "Update message flags",
+                    "operationId": "updateMessageFlags",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/UpdateFlagsRequest"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "204": {"description": "Flags updated"}
+                    }
+                }
+            },
+            "/messages/{id}/not-spam": {
+                "post": {
+                    "tags": ["messages"],
+                    "summary": "Mark a message as not spam",
+                    "description": "Moves a message filed into the Junk folder back to its original mailbox, removes the spam tag and trains rspamd when configured.",
+                    "operationId": "markNotSpam",
+                    "security": [{"api_key": []}, {"bearer": []}],
+                    "parameters": [
+                        {"name": "id", "in": "path", "required": true, "schema": {"type": "string", "format": "uuid"}}
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Verdict recorded",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "object",
+                                        "properties": {
+                                            "message_id": {"type": "string", "format": "uuid"},
+                                            "mailbox_id": {"type": "string", "format": "uuid"},
+                                            "moved": {"type": "boolean"},
+                                            "learned": {"type": "boolean"}
+                                        }
+                                    }
+                                }
+                            }
+                        },
+                        "404": {"description": "Message not found"}
+                    }
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": {
+                "api_key": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "X-API-Key"
+                },
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer"
+                }
+            },
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["error", "message"],
+                    "properties": {
+                        "error": {"type": "string", "description": "Machine-readable error code", "example": "VALIDATION_ERROR"},
+                        "message": {"type": "string", "description": "Human-readable description"},
+                        "field": {"type": "string", "description": "Request field the error concerns"},
+                        "resource": {"type": "string", "description": "Kind of resource the error concerns"},
+                        "retry_after_secs": {"type": "integer", "description": "Seconds after which the request may be retried"}
+                    }
+                },
+                "HealthResponse": {
+                    "type": "object",
+                    "properties": {
+                        "status": {"type": "string", "example": "healthy"}
+                    }
+                },
+                "DetailedHealthResponse": {
+                    "type": "object",
+                    "properties": {
+                        "status": {"type": "string"},
+                        "checks": {
+                            "type": "object",
+                            "properties": {
+                                "database": {"$ref": "#/components/schemas/ComponentHealth"},
+                                "replication": {"$ref": "#/components/schemas/ReplicationHealth"}
+                            }
+                        }
+                    }
+                },
+                "ComponentHealth": {
+                    "type": "object",
+                    "properties": {
+                        "status": {"type": "string"},
+                        "latency_ms": {"type": "integer"},
+                        "error": {"type": "string"}
+                    }
+                },
+                "ReplicationHealth": {
+                    "type": "object",
+                    "properties": {
+                        "status": {"type": "string", "description": "unhealthy when a standby lags more than max_lag_secs"},
+                        "role": {"type": "string", "enum": ["primary", "standby"]},
+                        "lag_secs": {"type": "number"},
+                        "files_pending": {"type": "integer", "format": "int64"},
+                        "error": {"type": "string"}
+                    }
+                },
+                "Tenant": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "name": {"type": "string"},
+                        "slug": {"type": "string"},
+                        "status": {"type": "string"},
+                        "plan": {"type": "string"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "CreateTenantRequest": {
+                    "type": "object",
+                    "required": ["name", "slug"],
+                    "properties": {
+                        "name": {"type": "string"},
+                        "slug": {"type": "string"},
+                        "plan": {"type": "string", "default": "free"}
+                    }
+                },
+                "BootstrapTenantRequest": {
+                    "type": "object",
+                    "required": ["name", "slug", "domain", "admin_email", "admin_password"],
+                    "properties": {
+                        "name": {"type": "string"},
+                        "slug": {"type": "string"},
+                        "plan": {"type": "string", "default": "free"},
+                        "settings": {"type": "object"},
+                        "domain": {"type": "string", "description": "First domain of the tenant"},
+                        "admin_email": {"type": "string", "format": "email", "description": "Admin address at the domain"},
+                        "admin_password": {"type": "string", "minLength": 8},
+                        "admin_name": {"type": "string"},
+                        "dkim_selector": {"type": "string", "default": "mairust"},
+                        "config": {"type": "object", "description": "Configuration bundle with policies, hooks and domain settings to start from"}
+                    }
+                },
+                "BootstrapTenantResponse": {
+                    "type": "object",
+                    "properties": {
+                        "tenant": {"$ref": "#/components/schemas/Tenant"},
+                        "domain": {"type": "object"},
+                        "admin": {"type": "object"},
+                        "mailbox": {"type": "object"},
+                        "folders": {"type": "array", "items": {"type": "string"}},
+                        "config": {"type": "object", "nullable": true, "description": "Import report of the starter bundle"},
+                        "dns_records": {"type": "object", "description": "MX, SPF, DKIM and DMARC records to publish"},
+                        "api_key_id": {"type": "string", "format": "uuid"},
+                        "api_key": {"type": "string", "description": "Shown once; only a hash is stored"}
+                    }
+                },
+                "CreateUserRequest": {
+                    "type": "object",
+                    "required": ["email", "password"],
+                    "properties": {
+                        "email": {"type": "string", "format": "email"},
+                        "password": {"type": "string", "minLength": 8},
+                        "name": {"type": "string"},
+                        "role": {"type": "string", "enum": ["admin", "user"], "default": "user"}
+                    }
+                },
+                "Domain": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "name": {"type": "string"},
+                        "verified": {"type": "boolean"},
+                        "dkim_selector": {"type": "string"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "CreateDomainRequest": {
+                    "type": "object",
+                    "required": ["name"],
+                    "properties": {
+                        "name": {"type": "string", "example": "example.com"}
+                    }
+                },
+                "Mailbox": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "domain_id": {"type": "string", "format": "uuid"},
+                        "address": {"type": "string"},
+                        "display_name": {"type": "string"},
+                        "quota_bytes": {"type": "integer"},
+                        "used_bytes": {"type": "integer"},
+                        "usage_percent": {"type": "number"},
+                        "special_use": {"type": "string", "nullable": true, "description": "Role of a provisioned folder"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "CreateMailboxRequest": {
+                    "type": "object",
+                    "required": ["domain_id", "address"],
+                    "properties": {
+                        "domain_id": {"type": "string", "format": "uuid"},
+                        "user_id": {"type": "string", "format": "uuid"},
+                        "address": {"type": "string", "example": "user@example.com"},
+                        "display_name": {"type": "string"},
+                        "quota_bytes": {"type": "integer"}
+                    }
+                },
+                "GlobalSearchResponse": {
+                    "type": "object",
+                    "properties": {
+                        "messages": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "id": {"type": "string", "format": "uuid"},
+                                    "tenant_id": {"type": "string", "format": "uuid"},
+                                    "tenant_name": {"type": "string"},
+                                    "mailbox_id": {"type": "string", "format": "uuid"},
+                                    "message_id_header": {"type": "string"},
+                                    "subject": {"type": "string"},
+                                    "from_address": {"type": "string"},
+                                    "envelope_from": {"type": "string"},
+                                    "to_addresses": {"type": "array", "items": {"type": "object"}},
+                                    "received_at": {"type": "string", "format": "date-time"}
+                                }
+                            }
+                        },
+                        "deliveries": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "queue_id": {"type": "string", "format": "uuid"},
+                                    "tenant_id": {"type": "string", "format": "uuid"},
+                                    "message_id": {"type": "string"},
+                                    "message_id_header": {"type": "string"},
+                                    "from": {"type": "string"},
+                                    "to": {"type": "array", "items": {"type": "string"}},
+                                    "status": {"type": "string"},
+                                    "attempts": {"type": "integer"},
+                                    "last_error": {"type": "string"},
+                                    "created_at": {"type": "string", "format": "date-time"},
+                                    "completed_at": {"type": "string", "format": "date-time"}
+                                }
+                            }
+                        },
+                        "limit": {"type": "integer"}
+                    }
+                },
+                "ConfigBundle": {
+                    "type": "object",
+                    "properties": {
+                        "version": {"type": "integer", "example": 1},
+                        "policies": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["name", "policy_type"],
+                                "properties": {
+                                    "name": {"type": "string"},
+                                    "domain": {"type": "string", "description": "Domain the policy is limited to; tenant-wide when omitted"},
+                                    "description": {"type": "string"},
+                                    "policy_type": {"type": "string", "enum": ["inbound", "outbound", "both"]},
+                                    "priority": {"type": "integer", "default": 100},
+                                    "enabled": {"type": "boolean", "default": true},
+                                    "conditions": {"type": "array", "items": {"type": "object"}},
+                                    "actions": {"type": "array", "items": {"type": "object"}}
+                                }
+                            }
+                        },
+                        "hooks": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["name", "hook_type", "plugin_id"],
+                                "properties": {
+                                    "name": {"type": "string"},
+                                    "hook_type": {"type": "string", "enum": ["pre_receive", "post_receive", "pre_send", "pre_delivery"]},
+                                    "plugin_id": {"type": "string"},
+                                    "enabled": {"type": "boolean", "default": true},
+                                    "priority": {"type": "integer", "default": 100},
+                                    "timeout_ms": {"type": "integer", "default": 2000},
+                                    "on_timeout": {"type": "string", "enum": ["allow", "reject", "tempfail"], "default": "tempfail"},
+                                    "on_error": {"type": "string", "enum": ["allow", "reject", "tempfail"], "default": "allow"},
+                                    "filter_config": {"type": "object"},
+                                    "config": {"type": "object"}
+                                }
+                            }
+                        },
+                        "domain_settings": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["domain"],
+                                "properties": {
+                                    "domain": {"type": "string"},
+                                    "catch_all_enabled": {"type": "boolean"},
+                                    "catch_all_mailbox": {"type": "string", "format": "email"},
+                                    "max_message_size": {"type": "integer"},
+                                    "max_recipients": {"type": "integer"},
+                                    "rate_limit_per_hour": {"type": "integer"},
+                                    "require_tls_inbound": {"type": "boolean"},
+                                    "require_tls_outbound": {"type": "boolean"},
+                                    "spf_policy": {"type": "string", "default": "neutral"},
+                                    "dmarc_policy": {"type": "string", "default": "none"},
+                                    "extra_settings": {"type": "object"}
+                                }
+                            }
+                        }
+                    }
+                },
+                "ImportReport": {
+                    "type": "object",
+                    "properties": {
+                        "dry_run": {"type": "boolean"},
+                        "created": {"type": "integer"},
+                        "updated": {"type": "integer"},
+                        "deleted": {"type": "integer"},
+                        "unchanged": {"type": "integer"},
+                        "changes": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "kind": {"type": "string", "enum": ["policy", "hook", "domain_settings"]},
+                                    "name": {"type": "string"},
+                                    "action": {"type": "string", "enum": ["create", "update", "delete", "unchanged"]},
+                                    "fields": {"type": "array", "items": {"type": "string"}, "description": "Fields that differ, for updates"}
+                                }
+                            }
+                        }
+                    }
+                },
+                "Correspondent": {
+                    "type": "object",
+                    "properties": {
+                        "address": {"type": "string", "format": "email"},
+                        "display_name": {"type": "string", "nullable": true},
+                        "frequency": {"type": "integer", "description": "Messages exchanged with the address"},
+                        "last_used_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "UserSettings": {
+                    "type": "object",
+                    "properties": {
+                        "user_id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "junk_filing": {"type": "boolean", "description": "Deliver messages flagged by the spam filter to the Junk folder"},
+                        "desktop_notifications": {"type": "boolean"},
+                        "notification_sound": {"type": "boolean"},
+                        "digest_frequency": {"type": "string", "enum": ["never", "hourly", "daily", "weekly"]},
+                        "sent_archive": {"type": "string", "nullable": true, "description": "Folder or address that receives a copy of every message sent"},
+                        "subaddress_filing": {"type": "boolean", "description": "File mail for user+tag@domain into the user's folder named after the tag, when there is one"},
+                        "quarantine_digest": {"type": "boolean", "description": "Email a daily digest of the messages held in quarantine for the user's mailboxes"},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "updated_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "NotificationSettings": {
+                    "type": "object",
+                    "properties": {
+                        "desktop_notifications": {"type": "boolean", "description": "Show desktop notifications for new mail"},
+                        "notification_sound": {"type": "boolean", "description": "Play a sound with new mail notifications"},
+                        "digest_frequency": {"type": "string", "enum": ["never", "hourly", "daily", "weekly"], "description": "How often to email a digest of unread messages"}
+                    }
+                },
+                "ServicesEnabled": {
+                    "type": "object",
+                    "properties": {
+                        "imap": {"type": "boolean", "default": true, "description": "Allow IMAP logins"},
+                        "pop3": {"type": "boolean", "default": true, "description": "Allow POP3 logins"}
+                    }
+                },
+                "TenantResidency": {
+                    "type": "object",
+                    "properties": {
+                        "data_region": {"type": "string", "nullable": true, "description": "Region from the server's residency.regions, or null when the tenant is not pinned", "example": "eu-west-1"}
+                    }
+                },
+                "SecurityEvent": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "user_id": {"type": "string", "format": "uuid"},
+                        "event_type": {"type": "string", "enum": ["login_blocked", "new_login_location"]},
+                        "ip_address": {"type": "string", "nullable": true},
+                        "protocol": {"type": "string", "enum": ["imap", "pop3"], "nullable": true},
+                        "details": {"type": "object"},
+                        "notified": {"type": "boolean", "description": "A notification email was delivered to the user"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "ReceivedDmarcReport": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "domain_id": {"type": "string", "format": "uuid"},
+                        "org_name": {"type": "string", "description": "Organization that sent the report"},
+                        "email": {"type": "string", "nullable": true},
+                        "report_id": {"type": "string", "description": "Reporter's identifier of the report"},
+                        "begin_at": {"type": "string", "format": "date-time"},
+                        "end_at": {"type": "string", "format": "date-time"},
+                        "policy": {"type": "object", "description": "Policy the reporter found published (domain, p, sp, pct, adkim, aspf)"},
+                        "message_count": {"type": "integer", "format": "int64"},
+                        "fail_count": {"type": "integer", "format": "int64", "description": "Messages that passed neither aligned DKIM nor aligned SPF"},
+                        "received_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "ReceivedDmarcRecord": {
+                    "type": "object",
+                    "properties": {
+                        "source_ip": {"type": "string"},
+                        "message_count": {"type": "integer", "format": "int64"},
+                        "disposition": {"type": "string", "enum": ["none", "quarantine", "reject"]},
+                        "dkim": {"type": "string", "description": "Aligned DKIM result"},
+                        "spf": {"type": "string", "description": "Aligned SPF result"},
+                        "reasons": {"type": "array", "items": {"type": "object"}, "description": "Policy overrides, with type and comment"},
+                        "header_from": {"type": "string"},
+                        "envelope_from": {"type": "string", "nullable": true},
+                        "dkim_results": {"type": "array", "items": {"type": "object"}},
+                        "spf_results": {"type": "array", "items": {"type": "object"}}
+                    }
+                },
+                "DkimKey": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "domain_id": {"type": "string", "format": "uuid"},
+                        "selector": {"type": "string", "example": "ed20261016"},
+                        "algorithm": {"type": "string", "enum": ["rsa-sha256", "ed25519-sha256"]},
+                        "status": {"type": "string", "enum": ["pending", "active", "retired"]},
+                        "activate_at": {"type": "string", "format": "date-time"},
+                        "activated_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "retired_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "dns_record": {
+                            "type": "object",
+                            "nullable": true,
+                            "properties": {
+                                "host": {"type": "string", "example": "ed20261016._domainkey.example.com"},
+                                "value": {"type": "string", "example": "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="}
+                            }
+                        }
+                    }
+                },
+                "BimiStatus": {
+                    "type": "object",
+                    "properties": {
+                        "record": {
+                            "type": "object",
+                            "properties": {
+                                "host": {"type": "string", "example": "default._bimi.example.com"},
+                                "value": {"type": "string", "example": "v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem"}
+                            }
+                        },
+                        "ready": {"type": "boolean", "description": "Receivers can show the logo"},
+                        "published": {"type": "string", "nullable": true, "description": "Record published at the selector"},
+                        "published_matches": {"type": "boolean"},
+                        "dmarc_policy": {"type": "string", "nullable": true, "enum": ["none", "quarantine", "reject", null]},
+                        "dmarc_enforced": {"type": "boolean"},
+                        "logo_error": {"type": "string", "nullable": true},
+                        "authority_validated": {"type": "boolean"},
+                        "authority_error": {"type": "string", "nullable": true}
+                    }
+                },
+                "DmarcReports": {
+                    "type": "object",
+                    "properties": {
+                        "since": {"type": "string", "format": "date-time"},
+                        "reports": {"type": "array", "items": {"$ref": "#/components/schemas/ReceivedDmarcReport"}},
+                        "sources": {
+                            "type": "array",
+                            "description": "Sources sending mail as the domain, most failing first",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "source_ip": {"type": "string"},
+                                    "message_count": {"type": "integer", "format": "int64"},
+                                    "pass_count": {"type": "integer", "format": "int64"},
+                                    "fail_count": {"type": "integer", "format": "int64"},
+                                    "dispositions": {"type": "array", "items": {"type": "string"}, "description": "Dispositions receivers applied to the failing messages"},
+                                    "reporters": {"type": "array", "items": {"type": "string"}},
+                                    "last_seen_at": {"type": "string", "format": "date-time"}
+                                }
+                            }
+                        }
+                    }
+                },
+                "ReceivedTlsReport": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "domain_id": {"type": "string", "format": "uuid"},
+                        "org_name": {"type": "string"},
+                        "contact_info": {"type": "string", "nullable": true},
+                        "report_id": {"type": "string"},
+                        "begin_at": {"type": "string", "format": "date-time"},
+                        "end_at": {"type": "string", "format": "date-time"},
+                        "policy_type": {"type": "string", "enum": ["sts", "tlsa", "no-policy-found"]},
+                        "policy_domain": {"type": "string"},
+                        "policy_strings": {"type": "array", "items": {"type": "string"}},
+                        "mx_hosts": {"type": "array", "items": {}},
+                        "successful_sessions": {"type": "integer", "format": "int64"},
+                        "failed_sessions": {"type": "integer", "format": "int64"},
+                        "failure_details": {"type": "array", "items": {"type": "object"}, "description": "failure-details of the report, as sent"},
+                        "received_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "MailboxCounters": {
+                    "type": "object",
+                    "properties": {
+                        "mailbox_id": {"type": "string", "format": "uuid"},
+                        "message_count": {"type": "integer", "format": "int64", "description": "All messages, including those flagged \\Deleted"},
+                        "unread_count": {"type": "integer", "format": "int64", "description": "Unseen messages not flagged \\Deleted"}
+                    }
+                },
+                "Folder": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "name": {"type": "string", "example": "Archive/2024", "description": "Full folder name, levels separated by /"},
+                        "parent": {"type": "string", "example": "Archive", "nullable": true},
+                        "special_use": {"type": "string", "enum": ["sent", "drafts", "trash", "junk", "archive"], "nullable": true},
+                        "attribute": {"type": "string", "example": "\\Sent", "nullable": true}
+                    }
+                },
+                "MailboxMetadata": {
+                    "type": "object",
+                    "properties": {
+                        "mailbox_id": {"type": "string", "format": "uuid"},
+                        "entries": {
+                            "type": "object",
+                            "additionalProperties": {"type": "string"},
+                            "example": {"/private/color": "#3b82f6", "/shared/comment": "Team inbox"}
+                        }
+                    }
+                },
+                "UpdateMailboxMetadataRequest": {
+                    "type": "object",
+                    "required": ["entries"],
+                    "properties": {
+                        "entries": {
+                            "type": "object",
+                            "description": "Entries under /private/ or /shared/; null removes an entry",
+                            "additionalProperties": {"type": "string", "nullable": true}
+                        }
+                    }
+                },
+                "MailboxFilter": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "mailbox_id": {"type": "string", "format": "uuid"},
+                        "name": {"type": "string"},
+                        "position": {"type": "integer", "description": "Filters run in ascending position"},
+                        "enabled": {"type": "boolean"},
+                        "conditions": {"type": "array", "items": {"type": "object"}},
+                        "actions": {"type": "array", "items": {"$ref": "#/components/schemas/FilterAction"}},
+                        "stop_processing": {"type": "boolean"},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "updated_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "MailboxFilterRequest": {
+                    "type": "object",
+                    "required": ["name", "actions"],
+                    "properties": {
+                        "name": {"type": "string", "example": "Receipts"},
+                        "position": {"type": "integer", "description": "Defaults to after the existing filters"},
+                        "enabled": {"type": "boolean", "default": true},
+                        "conditions": {
+                            "type": "array",
+                            "description": "Policy conditions, all of which must match. Filters may use sender_address and sender_domain (the From header), recipient_address, recipient_domain, subject_contains, header_exists and header_value, also inside all_of, any_of and none_of groups. No conditions matches every message.",
+                            "items": {"type": "object"},
+                            "example": [{"condition_type": "sender_domain", "operator": "eq", "value": "shop.example"}]
+                        },
+                        "actions": {
+                            "type": "array",
+                            "minItems": 1,
+                            "items": {"$ref": "#/components/schemas/FilterAction"}
+                        },
+                        "stop_processing": {"type": "boolean", "default": false, "description": "Skip later filters when this one matches"}
+                    }
+                },
+                "FilterAction": {
+                    "type": "object",
+                    "required": ["action_type"],
+                    "description": "The first folder chosen wins and is ignored for mail filed as junk. Spam is never forwarded. A deleted message is forwarded first.",
+                    "properties": {
+                        "action_type": {"type": "string", "enum": ["move_to_folder", "tag", "mark_read", "forward", "delete"]},
+                        "folder": {"type": "string", "description": "Folder for move_to_folder; it is not created when missing", "example": "Receipts"},
+                        "tag": {"type": "string", "description": "Tag for tag"},
+                        "address": {"type": "string", "format": "email", "description": "Address for forward"}
+                    }
+                },
+                "CreateHookRequest": {
+                    "type": "object",
+                    "required": ["name", "hook_type", "plugin_id"],
+                    "properties": {
+                        "name": {"type": "string"},
+                        "hook_type": {"type": "string", "enum": ["pre_receive", "post_receive", "pre_send", "pre_delivery"]},
+                        "plugin_id": {"type": "string"},
+                        "priority": {"type": "integer", "default": 100},
+                        "timeout_ms": {"type": "integer", "default": 5000},
+                        "on_timeout": {"type": "string", "enum": ["continue", "reject", "tempfail"], "default": "continue"},
+                        "on_error": {"type": "string", "enum": ["continue", "reject", "tempfail"], "default": "continue"},
+                        "filter_config": {"type": "object"},
+                        "config": {"type": "object"}
+                    }
+                },
+                "MaintenanceWindow": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "starts_at": {"type": "string", "format": "date-time"},
+                        "ends_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "message": {"type": "string"},
+                        "created_by": {"type": "string", "nullable": true, "description": "API key that scheduled the window"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "MaintenanceResponse": {
+                    "type": "object",
+                    "properties": {
+                        "active": {"type": "boolean"},
+                        "message": {"type": "string", "nullable": true},
+                        "until": {"type": "string", "format": "date-time", "nullable": true, "description": "End of the active maintenance, if known"},
+                        "retry_after_secs": {"type": "integer", "nullable": true},
+                        "windows": {"type": "array", "items": {"$ref": "#/components/schemas/MaintenanceWindow"}}
+                    }
+                },
+                "ScheduleMaintenanceRequest": {
+                    "type": "object",
+                    "properties": {
+                        "starts_at": {"type": "string", "format": "date-time", "description": "Defaults to now"},
+                        "ends_at": {"type": "string", "format": "date-time", "description": "Open until ended if omitted"},
+                        "message": {"type": "string", "maxLength": 200, "default": "System maintenance in progress"}
+                    }
+                },
+                "SessionInfo": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "protocol": {"type": "string", "enum": ["smtp", "submission", "lmtp", "imap", "pop3", "managesieve"]},
+                        "peer_ip": {"type": "string"},
+                        "peer_port": {"type": "integer"},
+                        "user": {"type": "string", "nullable": true, "description": "Authenticated user"},
+                        "tenant_id": {"type": "string", "format": "uuid", "nullable": true},
+                        "state": {"type": "string", "example": "authenticated", "description": "Protocol state"},
+                        "started_at": {"type": "string", "format": "date-time"},
+                        "duration_secs": {"type": "integer"},
+                        "bytes_in": {"type": "integer", "description": "Bytes received from the client"},
+                        "bytes_out": {"type": "integer", "description": "Bytes sent to the client"}
+                    }
+                },
+                "SessionFilter": {
+                    "type": "object",
+                    "description": "Sessions matching all the given fields; at least one is required",
+                    "properties": {
+                        "protocol": {"type": "string", "enum": ["smtp", "submission", "lmtp", "imap", "pop3", "managesieve"]},
+                        "user": {"type": "string", "description": "Authenticated user, case-insensitive"},
+                        "ip": {"type": "string", "description": "Client IP address"},
+                        "tenant_id": {"type": "string", "format": "uuid"}
+                    }
+                },
+                "DnsblAllowlistEntry": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "network": {"type": "string", "example": "192.0.2.0/24", "description": "CIDR range; single addresses are /32 or /128"},
+                        "description": {"type": "string", "nullable": true},
+                        "created_by": {"type": "string", "nullable": true, "description": "API key that added the entry"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "BadDomain": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "domain": {"type": "string", "example": "spam.example", "description": "Also covers its subdomains"},
+                        "score": {"type": "number", "nullable": true, "description": "Score of a link to the domain; the configured bad_domain_score when null"},
+                        "description": {"type": "string", "nullable": true},
+                        "created_by": {"type": "string", "nullable": true, "description": "API key that added the entry"},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "SenderReputation": {
+                    "type": "object",
+                    "properties": {
+                        "domain": {"type": "string", "example": "example.com"},
+                        "score": {"type": "number", "example": 87.5, "description": "From 0 (only spam and refused recipients) to 100 (clean)"},
+                        "messages": {"type": "number", "description": "Messages, decayed"},
+                        "spam": {"type": "number", "description": "Messages flagged as spam, decayed"},
+                        "errors": {"type": "number", "description": "Refused recipients, decayed"},
+                        "total_messages": {"type": "integer", "format": "int64"},
+                        "total_spam": {"type": "integer", "format": "int64"},
+                        "total_errors": {"type": "integer", "format": "int64"},
+                        "first_seen_at": {"type": "string", "format": "date-time"},
+                        "last_seen_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "ReplicationStatus": {
+                    "type": "object",
+                    "properties": {
+                        "enabled": {"type": "boolean"},
+                        "role": {"type": "string", "enum": ["primary", "standby"]},
+                        "heartbeat_at": {"type": "string", "format": "date-time", "nullable": true, "description": "Last heartbeat of the primary in this database"},
+                        "lag_secs": {"type": "number", "nullable": true, "description": "Seconds a standby is behind its primary"},
+                        "max_lag_secs": {"type": "integer"},
+                        "files": {
+                            "type": "object",
+                            "properties": {
+                                "pending": {"type": "integer", "format": "int64"},
+                                "failing": {"type": "integer", "format": "int64"},
+                                "oldest_queued_at": {"type": "string", "format": "date-time", "nullable": true}
+                            }
+                        },
+                        "subscribers": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "name": {"type": "string"},
+                                    "replay_lag_secs": {"type": "number", "nullable": true}
+                                }
                             }
                         }
-                    },
-                    "responses": {
-                        "204": {"description": "Flags updated"}
                     }
-                }
-            }
-        },
-        "components": {
-            "securitySchemes": {
-                "api_key": {
-                    "type": "apiKey",
-                    "in": "header",
-                    "name": "X-API-Key"
                 },
-                "bearer": {
-                    "type": "http",
-                    "scheme": "bearer"
-                }
-            },
-            "schemas": {
-                "HealthResponse": {
+                "CreateDnsblAllowlistRequest": {
                     "type": "object",
+                    "required": ["network"],
                     "properties": {
-                        "status": {"type": "string", "example": "healthy"}
+                        "network": {"type": "string", "example": "192.0.2.0/24", "description": "IP address or CIDR range; host bits are cleared"},
+                        "description": {"type": "string"}
                     }
                 },
-                "DetailedHealthResponse": {
+                "CreateBadDomainRequest": {
                     "type": "object",
+                    "required": ["domain"],
                     "properties": {
-                        "status": {"type": "string"},
-                        "checks": {
+                        "domain": {"type": "string", "example": "spam.example"},
+                        "score": {"type": "number"},
+                        "description": {"type": "string"}
+                    }
+                },
+                "CreateInboundRouteRequest": {
+                    "type": "object",
+                    "required": ["address", "webhook_url"],
+                    "properties": {
+                        "address": {"type": "string", "example": "orders@example.com", "description": "Address to route, or `*@example.com` / `example.com` for every address of the domain"},
+                        "webhook_url": {"type": "string", "format": "uri"},
+                        "store_message": {"type": "boolean", "default": true, "description": "Also deliver matching messages to the recipient's mailbox"}
+                    }
+                },
+                "CreateProtectedNameRequest": {
+                    "type": "object",
+                    "required": ["display_name"],
+                    "properties": {
+                        "display_name": {"type": "string", "example": "John Doe"},
+                        "email": {"type": "string", "format": "email", "description": "The person's own address, which may use the name even outside the tenant's domains"}
+                    }
+                },
+                "MessageShare": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "message_id": {"type": "string", "format": "uuid"},
+                        "created_by": {"type": "string", "format": "uuid", "nullable": true},
+                        "expires_at": {"type": "string", "format": "date-time"},
+                        "revoked_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "view_count": {"type": "integer", "description": "Times the shared page was opened"},
+                        "last_viewed_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "CreateMessageShareRequest": {
+                    "type": "object",
+                    "properties": {
+                        "expires_in_hours": {"type": "integer", "minimum": 1, "maximum": 720, "default": 72}
+                    }
+                },
+                "CreatedMessageShare": {
+                    "allOf": [
+                        {"$ref": "#/components/schemas/MessageShare"},
+                        {
                             "type": "object",
                             "properties": {
-                                "database": {"$ref": "#/components/schemas/ComponentHealth"}
+                                "token": {"type": "string", "description": "Token of the link; it cannot be retrieved again"},
+                                "path": {"type": "string", "example": "/share/3f2a...", "description": "Path of the shared page on the web UI"}
                             }
                         }
+                    ]
+                },
+                "PolicyCondition": {
+                    "type": "object",
+                    "required": ["condition_type", "operator", "value"],
+                    "properties": {
+                        "condition_type": {"type": "string", "enum": ["sender_domain", "sender_address", "recipient_domain", "recipient_address", "subject_contains", "header_exists", "header_value", "message_size", "attachment_type", "spam_score", "client_ip", "time_of_day", "sender_reputation", "country", "asn", "spf_result", "dkim_result", "dmarc_result", "dmarc_policy"], "description": "country compares the ISO 3166-1 code of the client's country and asn its autonomous system number (64500 or \"AS64500\"), both from the server's GeoIP databases, with eq, ne or in. spf_result, dkim_result and dmarc_result compare the Authentication-Results value of the check (pass, fail, softfail, none, ...); dmarc_policy is the published policy (none, quarantine or reject) of a sender domain whose DMARC check failed. A DMARC failure overridden by a trusted ARC chain has no dmarc_result."},
+                        "operator": {"type": "string", "description": "Comparison, such as eq, contains, regex, in, gt or between"},
+                        "value": {"description": "Value to compare with"},
+                        "negate": {"type": "boolean", "default": false}
                     }
                 },
-                "ComponentHealth": {
+                "PolicyAction": {
                     "type": "object",
+                    "required": ["action_type"],
                     "properties": {
-                        "status": {"type": "string"},
-                        "latency_ms": {"type": "integer"},
-                        "error": {"type": "string"}
+                        "action_type": {"type": "string", "enum": ["allow", "reject", "tempfail", "quarantine", "tag", "redirect", "add_header", "modify_subject", "rate_limit", "require_tls", "strip_attachments", "replace_attachments"]},
+                        "parameters": {"type": "object", "description": "Parameters of the action. rate_limit takes limit (messages per window), window (seconds, default 3600), per (sender, sender_domain or client_ip; default sender) and message; received mail over the limit is deferred with 450 until the window ends."}
                     }
                 },
-                "Tenant": {
+                "PolicyConditionGroup": {
+                    "type": "object",
+                    "description": "Group of conditions and groups, with a single key: all_of (every entry matches), any_of (at least one does) or none_of (none does)",
+                    "minProperties": 1,
+                    "maxProperties": 1,
+                    "properties": {
+                        "all_of": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/PolicyCondition"}, {"$ref": "#/components/schemas/PolicyConditionGroup"}]}},
+                        "any_of": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/PolicyCondition"}, {"$ref": "#/components/schemas/PolicyConditionGroup"}]}},
+                        "none_of": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/PolicyCondition"}, {"$ref": "#/components/schemas/PolicyConditionGroup"}]}}
+                    }
+                },
+                "PolicyRule": {
                     "type": "object",
                     "properties": {
                         "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid", "nullable": true},
+                        "domain_id": {"type": "string", "format": "uuid", "nullable": true},
                         "name": {"type": "string"},
-                        "slug": {"type": "string"},
-                        "status": {"type": "string"},
-                        "plan": {"type": "string"},
-                        "created_at": {"type": "string", "format": "date-time"}
+                        "description": {"type": "string", "nullable": true},
+                        "policy_type": {"type": "string", "enum": ["inbound", "outbound", "both"]},
+                        "priority": {"type": "integer", "description": "Policies with a higher priority apply first"},
+                        "enabled": {"type": "boolean"},
+                        "conditions": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/PolicyCondition"}, {"$ref": "#/components/schemas/PolicyConditionGroup"}]}},
+                        "actions": {"type": "array", "items": {"$ref": "#/components/schemas/PolicyAction"}},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "updated_at": {"type": "string", "format": "date-time"}
                     }
                 },
-                "CreateTenantRequest": {
+                "PolicyRuleRequest": {
                     "type": "object",
-                    "required": ["name", "slug"],
+                    "required": ["name", "policy_type", "conditions", "actions"],
                     "properties": {
                         "name": {"type": "string"},
-                        "slug": {"type": "string"},
-                        "plan": {"type": "string", "default": "free"}
+                        "description": {"type": "string", "nullable": true},
+                        "policy_type": {"type": "string", "enum": ["inbound", "outbound", "both"]},
+                        "priority": {"type": "integer", "default": 100},
+                        "domain_id": {"type": "string", "format": "uuid", "nullable": true, "description": "Domain the policy is limited to; tenant-wide when omitted"},
+                        "conditions": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/PolicyCondition"}, {"$ref": "#/components/schemas/PolicyConditionGroup"}]}, "description": "All entries have to match"},
+                        "actions": {"type": "array", "minItems": 1, "items": {"$ref": "#/components/schemas/PolicyAction"}}
                     }
                 },
-                "CreateUserRequest": {
+                "PolicyRuleUpdate": {
                     "type": "object",
-                    "required": ["email", "password"],
                     "properties": {
-                        "email": {"type": "string", "format": "email"},
-                        "password": {"type": "string", "minLength": 8},
                         "name": {"type": "string"},
-                        "role": {"type": "string", "enum": ["admin", "user"], "default": "user"}
+                        "description": {"type": "string", "nullable": true},
+                        "policy_type": {"type": "string", "enum": ["inbound", "outbound", "both"]},
+                        "priority": {"type": "integer"},
+                        "conditions": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/PolicyCondition"}, {"$ref": "#/components/schemas/PolicyConditionGroup"}]}},
+                        "actions": {"type": "array", "minItems": 1, "items": {"$ref": "#/components/schemas/PolicyAction"}}
                     }
                 },
-                "Domain": {
+                "SimulatePolicyRequest": {
+                    "type": "object",
+                    "required": ["policy"],
+                    "properties": {
+                        "policy": {"type": "object", "description": "The proposed rule, as accepted when creating a policy"},
+                        "days": {"type": "integer", "minimum": 1, "maximum": 90, "default": 7, "description": "Days of mail to replay"},
+                        "replaces": {"type": "string", "format": "uuid", "nullable": true, "description": "Existing policy the proposed one would replace"}
+                    }
+                },
+                "SimulatedOutcome": {
+                    "type": "object",
+                    "properties": {
+                        "reject": {"type": "boolean"},
+                        "smtp_code": {"type": "integer", "nullable": true},
+                        "quarantine": {"type": "boolean"},
+                        "tags": {"type": "array", "items": {"type": "string"}}
+                    }
+                },
+                "PolicySimulationReport": {
+                    "type": "object",
+                    "properties": {
+                        "from": {"type": "string", "format": "date-time"},
+                        "to": {"type": "string", "format": "date-time"},
+                        "examined": {"type": "integer", "description": "Stored messages replayed"},
+                        "truncated": {"type": "boolean", "description": "More messages were received in the period than could be replayed (10000)"},
+                        "matched": {"type": "integer", "description": "Messages the proposed rule matches"},
+                        "rejected": {"type": "integer", "description": "Messages that would be rejected and were not"},
+                        "quarantined": {"type": "integer", "description": "Messages that would be quarantined and were not"},
+                        "tagged": {"type": "integer", "description": "Messages that would get tags they did not get"},
+                        "tags": {"type": "object", "additionalProperties": {"type": "integer"}, "description": "Messages per added tag"},
+                        "no_longer_rejected": {"type": "integer", "description": "Messages the proposed rule no longer rejects"},
+                        "samples": {
+                            "type": "array",
+                            "description": "Affected messages, the most recent first (at most 100)",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "message_id": {"type": "string", "format": "uuid"},
+                                    "received_at": {"type": "string", "format": "date-time"},
+                                    "from_address": {"type": "string", "nullable": true},
+                                    "subject": {"type": "string", "nullable": true},
+                                    "before": {"$ref": "#/components/schemas/SimulatedOutcome"},
+                                    "after": {"$ref": "#/components/schemas/SimulatedOutcome"}
+                                }
+                            }
+                        },
+                        "warnings": {"type": "array", "items": {"type": "string"}, "description": "Conditions that cannot be evaluated from stored metadata"}
+                    }
+                },
+                "PolicyVersion": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "policy_id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid", "nullable": true},
+                        "version": {"type": "integer", "description": "From 1"},
+                        "change": {"type": "string", "enum": ["created", "updated", "enabled", "disabled", "deleted"]},
+                        "policy": {"$ref": "#/components/schemas/PolicyRule"},
+                        "actor_type": {"type": "string", "description": "`api_key`, or `system` for changes made on the server"},
+                        "actor_id": {"type": "string", "nullable": true},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "changes": {
+                            "type": "object",
+                            "description": "Fields changed since the previous version",
+                            "additionalProperties": {
+                                "type": "object",
+                                "properties": {"from": {}, "to": {}}
+                            }
+                        }
+                    }
+                },
+                "PolicyMatch": {
                     "type": "object",
                     "properties": {
                         "id": {"type": "string", "format": "uuid"},
                         "tenant_id": {"type": "string", "format": "uuid"},
-                        "name": {"type": "string"},
-                        "verified": {"type": "boolean"},
-                        "dkim_selector": {"type": "string"},
+                        "policy_id": {"type": "string", "format": "uuid"},
+                        "policy_name": {"type": "string"},
+                        "policy_version": {"type": "integer", "nullable": true, "description": "Version of the policy when it matched"},
+                        "message_id": {"type": "string", "nullable": true, "description": "Message-ID header"},
+                        "sender": {"type": "string", "nullable": true},
+                        "recipients": {"type": "array", "items": {"type": "string"}},
+                        "client_ip": {"type": "string", "nullable": true},
+                        "actions": {"type": "array", "items": {"type": "string"}, "description": "Action types of the policy"},
+                        "outcome": {"type": "string", "enum": ["rejected", "deferred", "quarantined", "accepted"]},
+                        "smtp_reply": {"type": "string", "nullable": true, "description": "Reply given when the message was refused"},
                         "created_at": {"type": "string", "format": "date-time"}
                     }
                 },
-                "CreateDomainRequest": {
+                "PolicyMatchList": {
                     "type": "object",
-                    "required": ["name"],
                     "properties": {
-                        "name": {"type": "string", "example": "example.com"}
+                        "matches": {"type": "array", "items": {"$ref": "#/components/schemas/PolicyMatch"}},
+                        "offset": {"type": "integer"},
+                        "limit": {"type": "integer"}
                     }
                 },
-                "Mailbox": {
+                "ProtectedName": {
                     "type": "object",
                     "properties": {
                         "id": {"type": "string", "format": "uuid"},
                         "tenant_id": {"type": "string", "format": "uuid"},
-                        "domain_id": {"type": "string", "format": "uuid"},
-                        "address": {"type": "string"},
                         "display_name": {"type": "string"},
-                        "quota_bytes": {"type": "integer"},
-                        "used_bytes": {"type": "integer"},
-                        "usage_percent": {"type": "number"},
+                        "email": {"type": "string", "format": "email", "nullable": true},
                         "created_at": {"type": "string", "format": "date-time"}
                     }
                 },
-                "CreateMailboxRequest": {
+                "QuarantinedMessage": {
                     "type": "object",
-                    "required": ["domain_id", "address"],
                     "properties": {
-                        "domain_id": {"type": "string", "format": "uuid"},
-                        "user_id": {"type": "string", "format": "uuid"},
-                        "address": {"type": "string", "example": "user@example.com"},
-                        "display_name": {"type": "string"},
-                        "quota_bytes": {"type": "integer"}
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "mailbox_id": {"type": "string", "format": "uuid", "description": "Mailbox the message was addressed to"},
+                        "address": {"type": "string"},
+                        "user_id": {"type": "string", "format": "uuid", "nullable": true},
+                        "subject": {"type": "string", "nullable": true},
+                        "from_address": {"type": "string", "nullable": true},
+                        "snippet": {"type": "string", "nullable": true},
+                        "body_size": {"type": "integer"},
+                        "has_attachments": {"type": "boolean"},
+                        "spam_score": {"type": "number", "nullable": true},
+                        "reason": {"type": "string", "enum": ["virus", "spam", "policy"], "nullable": true},
+                        "detail": {"type": "string", "nullable": true, "description": "Virus name, spam score or policy name"},
+                        "received_at": {"type": "string", "format": "date-time"}
                     }
                 },
-                "CreateHookRequest": {
+                "QuarantinePreview": {
+                    "allOf": [
+                        {"$ref": "#/components/schemas/QuarantinedMessage"},
+                        {
+                            "type": "object",
+                            "properties": {
+                                "to": {"type": "array", "items": {"type": "string"}},
+                                "cc": {"type": "array", "items": {"type": "string"}},
+                                "date": {"type": "string", "nullable": true},
+                                "body": {"type": "string"},
+                                "attachments": {
+                                    "type": "array",
+                                    "items": {
+                                        "type": "object",
+                                        "properties": {
+                                            "filename": {"type": "string", "nullable": true},
+                                            "content_type": {"type": "string"},
+                                            "size": {"type": "integer"}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    ]
+                },
+                "SpamRule": {
                     "type": "object",
-                    "required": ["name", "hook_type", "plugin_id"],
                     "properties": {
-                        "name": {"type": "string"},
-                        "hook_type": {"type": "string", "enum": ["pre_receive", "post_receive", "pre_send", "pre_delivery"]},
-                        "plugin_id": {"type": "string"},
-                        "priority": {"type": "integer", "default": 100},
-                        "timeout_ms": {"type": "integer", "default": 5000},
-                        "on_timeout": {"type": "string", "enum": ["continue", "reject", "tempfail"], "default": "continue"},
-                        "on_error": {"type": "string", "enum": ["continue", "reject", "tempfail"], "default": "continue"},
-                        "filter_config": {"type": "object"},
-                        "config": {"type": "object"}
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "name": {"type": "string", "description": "Reported as the symbol of a match"},
+                        "description": {"type": "string", "nullable": true},
+                        "scope": {"type": "string", "enum": ["header", "body", "from", "subject", "url", "has_header", "missing_header", "body_length"]},
+                        "pattern": {"type": "string"},
+                        "score": {"type": "number"},
+                        "enabled": {"type": "boolean"},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "updated_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "SpamRuleRequest": {
+                    "type": "object",
+                    "required": ["name", "scope", "pattern", "score"],
+                    "properties": {
+                        "name": {"type": "string", "maxLength": 100, "description": "Letters, digits, underscores and hyphens"},
+                        "description": {"type": "string", "nullable": true},
+                        "scope": {"type": "string", "enum": ["header", "body", "from", "subject", "url", "has_header", "missing_header", "body_length"]},
+                        "pattern": {"type": "string", "description": "A regular expression for header, body, from, subject and url rules; a header name for has_header and missing_header; the longest matching body in bytes for body_length"},
+                        "score": {"type": "number", "minimum": -100, "maximum": 100, "description": "Added when the rule matches; negative scores lower the spam score"},
+                        "enabled": {"type": "boolean", "default": true}
+                    }
+                },
+                "TrackingDomain": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "domain": {"type": "string", "example": "links.example.com"},
+                        "status": {"type": "string", "enum": ["pending", "verified", "active", "failed"]},
+                        "verified_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "certificate_issued_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "last_error": {"type": "string", "nullable": true, "description": "Why the last CNAME check or certificate order failed"},
+                        "cname_target": {"type": "string", "description": "Host name the domain's CNAME must point at"},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "updated_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "CreateTrackingDomainRequest": {
+                    "type": "object",
+                    "required": ["domain"],
+                    "properties": {
+                        "domain": {"type": "string", "example": "links.example.com"}
+                    }
+                },
+                "InboundRoute": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "domain_id": {"type": "string", "format": "uuid"},
+                        "domain": {"type": "string"},
+                        "local_part": {"type": "string", "nullable": true},
+                        "address": {"type": "string", "example": "orders@example.com"},
+                        "webhook_url": {"type": "string", "format": "uri"},
+                        "secret": {"type": "string", "description": "Key for the payload and attachment link signatures"},
+                        "store_message": {"type": "boolean"},
+                        "enabled": {"type": "boolean"},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "updated_at": {"type": "string", "format": "date-time"}
                     }
                 },
                 "SendEmailRequest": {
@@ -697,10 +4173,19 @@ fn get_openapi_spec() -> serde_json::Value {
                         "html": {"type": "string", "description": "HTML body"},
                         "reply_to": {"type": "string", "format": "email"},
                         "scheduled_at": {"type": "string", "format": "date-time"},
+                        "lane": {"type": "string", "enum": ["transactional", "campaign", "bulk"], "default": "transactional", "description": "Queue lane; transactional mail is delivered before campaign and bulk mail"},
                         "headers": {"type": "object", "additionalProperties": {"type": "string"}},
                         "attachments": {
                             "type": "array",
                             "items": {"$ref": "#/components/schemas/Attachment"}
+                        },
+                        "secure": {
+                            "type": "object",
+                            "description": "Send as a secure message; it cannot have attachments",
+                            "properties": {
+                                "passcode": {"type": "string", "minLength": 8, "description": "Passcode shared with the recipients some other way; when not set, recipients have a one-time code emailed to them"},
+                                "expires_in_hours": {"type": "integer", "minimum": 1, "maximum": 720, "default": 168}
+                            }
                         }
                     }
                 },
@@ -720,7 +4205,182 @@ fn get_openapi_spec() -> serde_json::Value {
                         "status": {"type": "string", "example": "queued"},
                         "recipients_count": {"type": "integer"},
                         "scheduled_at": {"type": "string", "format": "date-time"},
-                        "queue_id": {"type": "string", "format": "uuid"}
+                        "queue_id": {"type": "string", "format": "uuid"},
+                        "secure_message_ids": {"type": "array", "items": {"type": "string", "format": "uuid"}, "description": "Secure messages stored for the recipients, one each"}
+                    }
+                },
+                "SecureMessage": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "sender": {"type": "string", "format": "email"},
+                        "recipient": {"type": "string", "format": "email"},
+                        "code_sent_at": {"type": "string", "format": "date-time", "nullable": true, "description": "When a one-time code was last emailed"},
+                        "failed_attempts": {"type": "integer", "description": "Wrong passcodes since the last open; the link locks at 5"},
+                        "created_by": {"type": "string", "format": "uuid", "nullable": true},
+                        "expires_at": {"type": "string", "format": "date-time"},
+                        "revoked_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "open_count": {"type": "integer"},
+                        "last_opened_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "created_at": {"type": "string", "format": "date-time"}
+                    }
+                },
+                "SecureMessageDetail": {
+                    "allOf": [
+                        {"$ref": "#/components/schemas/SecureMessage"},
+                        {
+                            "type": "object",
+                            "properties": {
+                                "access_log": {
+                                    "type": "array",
+                                    "items": {
+                                        "type": "object",
+                                        "properties": {
+                                            "id": {"type": "string", "format": "uuid"},
+                                            "secure_message_id": {"type": "string", "format": "uuid"},
+                                            "event": {"type": "string", "enum": ["code_sent", "opened", "failed", "locked"]},
+                                            "ip_address": {"type": "string", "nullable": true},
+                                            "user_agent": {"type": "string", "nullable": true},
+                                            "created_at": {"type": "string", "format": "date-time"}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    ]
+                },
+                "BulkMailboxRow": {
+                    "type": "object",
+                    "required": ["email"],
+                    "properties": {
+                        "email": {"type": "string", "format": "email"},
+                        "name": {"type": "string"},
+                        "password": {"type": "string", "minLength": 8},
+                        "role": {"type": "string", "enum": ["user", "domain_admin", "tenant_admin"], "default": "user"},
+                        "quota_bytes": {"type": "integer", "format": "int64"},
+                        "invite_email": {"type": "string", "format": "email", "description": "Where to email the invite of a user without a password, in invite mode"}
+                    }
+                },
+                "ProvisioningBatch": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "tenant_id": {"type": "string", "format": "uuid"},
+                        "idempotency_key": {"type": "string", "nullable": true},
+                        "password_mode": {"type": "string", "enum": ["generate", "invite"]},
+                        "status": {"type": "string", "enum": ["pending", "running", "completed"]},
+                        "total_rows": {"type": "integer"},
+                        "created_count": {"type": "integer"},
+                        "existing_count": {"type": "integer"},
+                        "failed_count": {"type": "integer"},
+                        "created_at": {"type": "string", "format": "date-time"},
+                        "started_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "completed_at": {"type": "string", "format": "date-time", "nullable": true}
+                    }
+                },
+                "ProvisioningRow": {
+                    "type": "object",
+                    "properties": {
+                        "batch_id": {"type": "string", "format": "uuid"},
+                        "row_number": {"type": "integer", "description": "Position in the submitted batch, from 1"},
+                        "email": {"type": "string"},
+                        "name": {"type": "string", "nullable": true},
+                        "role": {"type": "string"},
+                        "quota_bytes": {"type": "integer", "format": "int64", "nullable": true},
+                        "invite_email": {"type": "string", "nullable": true},
+                        "status": {"type": "string", "enum": ["pending", "created", "exists", "failed"]},
+                        "user_id": {"type": "string", "format": "uuid", "nullable": true},
+                        "mailbox_id": {"type": "string", "format": "uuid", "nullable": true},
+                        "error": {"type": "string", "nullable": true},
+                        "processed_at": {"type": "string", "format": "date-time", "nullable": true}
+                    }
+                },
+                "BulkProvisionResponse": {
+                    "type": "object",
+                    "properties": {
+                        "batch": {"$ref": "#/components/schemas/ProvisioningBatch"},
+                        "passwords": {
+                            "type": "array",
+                            "description": "Generated passwords; only returned when the batch is created",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "row_number": {"type": "integer"},
+                                    "email": {"type": "string"},
+                                    "password": {"type": "string"}
+                                }
+                            }
+                        }
+                    }
+                },
+                "PreflightRequest": {
+                    "type": "object",
+                    "required": ["recipient"],
+                    "properties": {
+                        "recipient": {"type": "string", "format": "email"},
+                        "require_tls": {"type": "boolean", "default": false, "description": "Check as for a message sent with REQUIRETLS"}
+                    }
+                },
+                "PreflightReport": {
+                    "type": "object",
+                    "properties": {
+                        "recipient": {"type": "string", "format": "email"},
+                        "domain": {"type": "string"},
+                        "deliverable": {"type": "boolean", "description": "The message would be handed to an MX host"},
+                        "problems": {"type": "array", "items": {"type": "string"}},
+                        "local_mailbox": {"type": "boolean"},
+                        "mx": {
+                            "type": "object",
+                            "properties": {
+                                "hosts": {"type": "array", "items": {"type": "string"}},
+                                "implicit": {"type": "boolean", "description": "No MX record; the domain itself is used"},
+                                "null_mx": {"type": "boolean"},
+                                "error": {"type": "string", "nullable": true}
+                            }
+                        },
+                        "tls": {
+                            "type": "object",
+                            "properties": {
+                                "required": {"type": "boolean"},
+                                "mta_sts": {"type": "string", "nullable": true},
+                                "tlsa": {"type": "object", "additionalProperties": {"type": "array", "items": {"type": "string"}}}
+                            }
+                        },
+                        "suppression": {
+                            "type": "object",
+                            "properties": {
+                                "unsubscribed": {"type": "boolean"},
+                                "unsubscribe_source": {"type": "string", "nullable": true},
+                                "unsubscribed_at": {"type": "string", "format": "date-time", "nullable": true},
+                                "list_statuses": {"type": "array", "items": {"type": "string", "enum": ["bounced", "complained"]}}
+                            }
+                        },
+                        "rate_limit": {
+                            "type": "object",
+                            "properties": {
+                                "enabled": {"type": "boolean"},
+                                "per_minute": {"type": "integer"},
+                                "per_hour": {"type": "integer"},
+                                "per_day": {"type": "integer"},
+                                "remaining": {
+                                    "type": "object",
+                                    "properties": {
+                                        "per_minute": {"type": "integer"},
+                                        "per_hour": {"type": "integer"},
+                                        "per_day": {"type": "integer"}
+                                    }
+                                }
+                            }
+                        },
+                        "route": {
+                            "type": "object",
+                            "properties": {
+                                "method": {"type": "string", "example": "mx"},
+                                "helo": {"type": "string"},
+                                "port": {"type": "integer", "example": 25}
+                            }
+                        }
                     }
                 },
                 "QueueStatusResponse": {
@@ -729,7 +4389,55 @@ fn get_openapi_spec() -> serde_json::Value {
                         "pending": {"type": "integer"},
                         "processing": {"type": "integer"},
                         "completed": {"type": "integer"},
-                        "failed": {"type": "integer"}
+                        "failed": {"type": "integer"},
+                        "dead_letter": {"type": "integer", "description": "Deliveries that exceeded their queue lifetime"},
+                        "by_priority": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "status": {"type": "string"},
+                                    "lane": {"type": "string", "enum": ["transactional", "campaign", "bulk"]},
+                                    "priority": {"type": "integer"},
+                                    "count": {"type": "integer"}
+                                }
+                            }
+                        },
+                        "oldest_pending_at": {"type": "string", "format": "date-time", "nullable": true},
+                        "oldest_pending_age_secs": {"type": "integer", "nullable": true},
+                        "throughput_per_minute": {"type": "integer"},
+                        "completed_last_hour": {"type": "integer"},
+                        "recent_failures": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "job_id": {"type": "string", "format": "uuid"},
+                                    "status": {"type": "string", "enum": ["failed", "dead_letter"]},
+                                    "tenant_id": {"type": "string", "format": "uuid", "nullable": true},
+                                    "message_id": {"type": "string", "nullable": true},
+                                    "attempts": {"type": "integer"},
+                                    "last_error": {"type": "string", "nullable": true},
+                                    "failed_at": {"type": "string", "format": "date-time", "nullable": true}
+                                }
+                            }
+                        },
+                        "tenant_latency": {
+                            "type": "array",
+                            "description": "Queue latency per tenant, longest waiting tenants first",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "tenant_id": {"type": "string", "format": "uuid", "nullable": true},
+                                    "pending": {"type": "integer"},
+                                    "processing": {"type": "integer"},
+                                    "oldest_pending_at": {"type": "string", "format": "date-time", "nullable": true},
+                                    "started_last_hour": {"type": "integer"},
+                                    "avg_wait_secs": {"type": "number", "nullable": true},
+                                    "max_wait_secs": {"type": "number", "nullable": true}
+                                }
+                            }
+                        }
                     }
                 },
                 "MessageListResponse": {
@@ -749,6 +4457,7 @@ fn get_openapi_spec() -> serde_json::Value {
                         "id": {"type": "string", "format": "uuid"},
                         "subject": {"type": "string"},
                         "from_address": {"type": "string"},
+                        "snippet": {"type": "string", "nullable": true, "description": "Body text on one line, at most 200 bytes"},
                         "received_at": {"type": "string", "format": "date-time"},
                         "seen": {"type": "boolean"},
                         "flagged": {"type": "boolean"},
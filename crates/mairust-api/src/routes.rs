@@ -5,20 +5,44 @@ use axum::{
     routing::{delete, get, patch, post, put},
     Router,
 };
-use mairust_storage::DatabasePool;
+use mairust_common::config::{ReplicationConfig, TrackingConfig};
+use mairust_core::SessionRegistry;
+use mairust_storage::{DatabasePool, FileStorage};
 use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 
 use crate::auth::{auth_middleware, AppState};
 use crate::handlers::{
-    admin, campaigns, domain_aliases, domain_settings, domains, health, hooks, mailboxes, messages,
-    policies, recipient_lists, search, send, tenants, users,
+    admin, campaigns, config_bundles, contacts, dkim_keys, domain_aliases, domain_reports,
+    domain_settings, domains, health, hooks, inbound_routes, mailbox_filters, mailbox_provisioning,
+    mailboxes, message_shares, messages, metrics, policies, protected_names, quarantine,
+    recipient_lists, search, secure_messages, send, spam_rules, tenants, tracking_domains, users,
 };
+use crate::maintenance::{maintenance_middleware, standby_middleware};
 use crate::openapi::create_openapi_routes;
 
 /// Create the API router
-pub fn create_router(db_pool: DatabasePool) -> Router {
-    let state = Arc::new(AppState { db_pool });
+///
+/// `file_storage` holds the message files of this server; `sessions` holds
+/// the protocol sessions served by this process;
+/// `hostname` is the CNAME target of tracking domains when `tracking` has
+/// none.
+pub fn create_router(
+    db_pool: DatabasePool,
+    file_storage: Arc<dyn FileStorage>,
+    sessions: SessionRegistry,
+    replication: ReplicationConfig,
+    tracking: TrackingConfig,
+    hostname: &str,
+) -> Router {
+    let state = Arc::new(AppState {
+        db_pool,
+        file_storage,
+        sessions,
+        replication,
+        tracking_cname_target: tracking.cname_target(hostname).to_string(),
+        tracking,
+    });
 
     // Health check routes (no auth required)
     let health_routes = Router::new()
@@ -32,22 +56,47 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
     let message_routes = Router::new()
         .route("/", get(messages::list_messages))
         .route("/:id", get(messages::get_message))
+        .route("/:id/attachments/:index", get(messages::download_attachment))
+        .route("/:id/print", get(messages::print_message))
+        .route("/:id/export", get(messages::export_message))
+        .route(
+            "/:id/shares",
+            get(message_shares::list_shares).post(message_shares::create_share),
+        )
+        .route(
+            "/:id/shares/:share_id",
+            delete(message_shares::revoke_share),
+        )
         .route("/:id/flags", patch(messages::update_message_flags))
+        .route("/:id/not-spam", post(messages::mark_not_spam))
         .route("/:id", delete(messages::delete_message));
 
     // Tenant routes (admin)
     let tenant_routes = Router::new()
         .route("/", get(tenants::list_tenants))
         .route("/", post(tenants::create_tenant))
+        .route("/bootstrap", post(tenants::bootstrap_tenant))
         .route("/:id", get(tenants::get_tenant))
-        .route("/:id", delete(tenants::delete_tenant));
+        .route("/:id", delete(tenants::delete_tenant))
+        .route("/:id/services", get(tenants::get_tenant_services))
+        .route("/:id/services", put(tenants::set_tenant_services))
+        .route("/:id/residency", get(tenants::get_tenant_residency))
+        .route("/:id/residency", put(tenants::set_tenant_residency));
 
     // User routes
     let user_routes = Router::new()
         .route("/", get(users::list_users))
         .route("/", post(users::create_user))
         .route("/:id", get(users::get_user))
-        .route("/:id", delete(users::delete_user));
+        .route("/:id", delete(users::delete_user))
+        .route("/:id/settings", get(users::get_user_settings))
+        .route("/:id/settings", patch(users::update_user_settings))
+        .route("/:id/settings/notifications", get(users::get_notification_settings))
+        .route("/:id/settings/notifications", patch(users::update_notification_settings))
+        .route("/:id/apop-secret", put(users::set_apop_secret))
+        .route("/:id/services", get(users::get_user_services))
+        .route("/:id/services", put(users::set_user_services))
+        .route("/:id/security-events", get(users::list_security_events));
 
     // Domain routes
     let domain_routes = Router::new()
@@ -56,15 +105,53 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
         .route("/:domain_id", get(domains::get_domain))
         .route("/:domain_id", delete(domains::delete_domain))
         .route("/:domain_id/verify", post(domains::verify_domain))
-        .route("/:domain_id/dkim", post(domains::set_dkim));
+        .route("/:domain_id/dkim", post(domains::set_dkim))
+        .route("/:domain_id/dkim/keys", get(dkim_keys::list_dkim_keys))
+        .route("/:domain_id/dkim/keys", post(dkim_keys::create_dkim_key))
+        .route(
+            "/:domain_id/dkim/keys/:key_id",
+            delete(dkim_keys::delete_dkim_key),
+        )
+        .route(
+            "/:domain_id/dkim/keys/:key_id/activate",
+            post(dkim_keys::activate_dkim_key),
+        )
+        .route(
+            "/:domain_id/dkim/keys/:key_id/retire",
+            post(dkim_keys::retire_dkim_key),
+        )
+        .route("/:domain_id/bimi", get(domains::get_bimi))
+        .route("/:domain_id/dmarc-reports", get(domain_reports::list_dmarc_reports))
+        .route(
+            "/:domain_id/dmarc-reports/:report_id",
+            get(domain_reports::get_dmarc_report),
+        )
+        .route("/:domain_id/tls-reports", get(domain_reports::list_tls_reports));
 
     // Mailbox routes
     let mailbox_routes = Router::new()
         .route("/", get(mailboxes::list_mailboxes))
         .route("/", post(mailboxes::create_mailbox))
+        .route("/bulk", get(mailbox_provisioning::list_bulk_batches))
+        .route("/bulk", post(mailbox_provisioning::submit_bulk_mailboxes))
+        .route("/bulk/:batch_id", get(mailbox_provisioning::get_bulk_batch))
         .route("/:mailbox_id", get(mailboxes::get_mailbox))
         .route("/:mailbox_id", delete(mailboxes::delete_mailbox))
-        .route("/:mailbox_id/quota", patch(mailboxes::update_mailbox_quota));
+        .route("/:mailbox_id/quota", patch(mailboxes::update_mailbox_quota))
+        .route("/:mailbox_id/unread-count", get(mailboxes::get_unread_count))
+        .route("/:mailbox_id/folders", get(mailboxes::list_folders))
+        .route("/:mailbox_id/metadata", get(mailboxes::get_mailbox_metadata))
+        .route("/:mailbox_id/metadata", put(mailboxes::update_mailbox_metadata))
+        .route(
+            "/:mailbox_id/filters",
+            get(mailbox_filters::list_filters).post(mailbox_filters::create_filter),
+        )
+        .route(
+            "/:mailbox_id/filters/:filter_id",
+            get(mailbox_filters::get_filter)
+                .put(mailbox_filters::update_filter)
+                .delete(mailbox_filters::delete_filter),
+        );
 
     // Hook routes
     let hook_routes = Router::new()
@@ -75,12 +162,28 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
         .route("/:hook_id/enable", post(hooks::enable_hook))
         .route("/:hook_id/disable", post(hooks::disable_hook));
 
+    // Inbound route routes
+    let inbound_route_routes = Router::new()
+        .route("/", get(inbound_routes::list_inbound_routes))
+        .route("/", post(inbound_routes::create_inbound_route))
+        .route("/:route_id", get(inbound_routes::get_inbound_route))
+        .route("/:route_id", delete(inbound_routes::delete_inbound_route))
+        .route("/:route_id/enable", post(inbound_routes::enable_inbound_route))
+        .route("/:route_id/disable", post(inbound_routes::disable_inbound_route));
+
     // Send routes
     let send_routes = Router::new()
         .route("/", post(send::send_email))
         .route("/queue", get(send::get_send_queue))
+        .route("/preflight", post(send::preflight))
         .route("/:message_id/status", get(send::get_message_status));
 
+    // Secure message routes
+    let secure_message_routes = Router::new()
+        .route("/", get(secure_messages::list_secure_messages))
+        .route("/:id", get(secure_messages::get_secure_message))
+        .route("/:id", delete(secure_messages::revoke_secure_message));
+
     // Domain alias routes
     let domain_alias_routes = Router::new()
         .route("/", get(domain_aliases::list_domain_aliases))
@@ -101,11 +204,43 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
     let policy_routes = Router::new()
         .route("/", get(policies::list_policies))
         .route("/", post(policies::create_policy))
+        .route("/simulate", post(policies::simulate_policy))
+        .route("/matches", get(policies::list_policy_matches))
         .route("/:policy_id", get(policies::get_policy))
         .route("/:policy_id", put(policies::update_policy))
         .route("/:policy_id", delete(policies::delete_policy))
         .route("/:policy_id/enable", post(policies::enable_policy))
-        .route("/:policy_id/disable", post(policies::disable_policy));
+        .route("/:policy_id/disable", post(policies::disable_policy))
+        .route("/:policy_id/versions", get(policies::list_policy_versions));
+
+    // Protected display name routes
+    let protected_name_routes = Router::new()
+        .route("/", get(protected_names::list_protected_names))
+        .route("/", post(protected_names::create_protected_name))
+        .route("/:name_id", delete(protected_names::delete_protected_name));
+
+    // Quarantine routes
+    let quarantine_routes = Router::new()
+        .route("/", get(quarantine::list_quarantine))
+        .route("/:message_id", get(quarantine::get_quarantined_message))
+        .route("/:message_id", delete(quarantine::delete_quarantined_message))
+        .route(
+            "/:message_id/release",
+            post(quarantine::release_quarantined_message),
+        );
+
+    // Spam rule routes
+    let spam_rule_routes = Router::new()
+        .route("/", get(spam_rules::list_spam_rules))
+        .route("/", post(spam_rules::create_spam_rule))
+        .route("/:rule_id", get(spam_rules::get_spam_rule))
+        .route("/:rule_id", put(spam_rules::update_spam_rule))
+        .route("/:rule_id", delete(spam_rules::delete_spam_rule));
+
+    // Config bundle routes
+    let config_bundle_routes = Router::new()
+        .route("/", get(config_bundles::export_bundle))
+        .route("/import", post(config_bundles::import_bundle));
 
     // Search routes
     let search_routes = Router::new()
@@ -113,6 +248,9 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
         .route("/status", get(search::search_status))
         .route("/reindex", post(search::reindex_messages));
 
+    // Contact routes
+    let contact_routes = Router::new().route("/suggest", get(contacts::suggest_contacts));
+
     // Campaign routes
     let campaign_routes = Router::new()
         .route("/", get(campaigns::list_campaigns))
@@ -141,15 +279,75 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
         .route("/:list_id/recipients/:recipient_id", put(recipient_lists::update_recipient))
         .route("/:list_id/recipients/:recipient_id", delete(recipient_lists::delete_recipient));
 
+    // Tracking domain routes
+    let tracking_domain_routes = Router::new()
+        .route(
+            "/",
+            get(tracking_domains::list_tracking_domains)
+                .post(tracking_domains::create_tracking_domain),
+        )
+        .route(
+            "/:id",
+            get(tracking_domains::get_tracking_domain)
+                .delete(tracking_domains::delete_tracking_domain),
+        )
+        .route("/:id/verify", post(tracking_domains::verify_tracking_domain));
+
     // Admin dashboard routes (super admin)
     let admin_system_routes = Router::new()
         .route("/stats", get(admin::get_system_stats))
-        .route("/tenants", get(admin::list_all_tenants_summary));
+        .route("/queue", get(admin::get_system_queue_status))
+        .route("/journal", get(admin::get_journal_status))
+        .route("/journal/retry", post(admin::retry_journal_exports))
+        .route("/tenants", get(admin::list_all_tenants_summary))
+        .route("/search", get(admin::global_search))
+        .route("/reprocess", post(admin::reprocess_system_messages))
+        .route(
+            "/dnsbl-allowlist",
+            get(admin::list_dnsbl_allowlist).post(admin::create_dnsbl_allowlist_entry),
+        )
+        .route(
+            "/dnsbl-allowlist/:entry_id",
+            delete(admin::delete_dnsbl_allowlist_entry),
+        )
+        .route(
+            "/bad-domains",
+            get(admin::list_bad_domains).post(admin::create_bad_domain),
+        )
+        .route("/bad-domains/:entry_id", delete(admin::delete_bad_domain))
+        .route("/sender-reputation", get(admin::list_sender_reputations))
+        .route(
+            "/sender-reputation/:domain",
+            get(admin::get_sender_reputation).delete(admin::reset_sender_reputation),
+        )
+        .route("/replication", get(admin::get_replication_status))
+        .route("/replication/files/*path", get(admin::get_replication_file));
+
+    // Maintenance mode routes (super admin), reachable during maintenance
+    let maintenance_routes = Router::new()
+        .route("/", get(admin::get_maintenance))
+        .route("/windows", post(admin::schedule_maintenance))
+        .route("/windows/:window_id", delete(admin::cancel_maintenance_window))
+        .route("/end", post(admin::end_maintenance));
+
+    // Live session routes (super admin), reachable during maintenance
+    let session_routes = Router::new()
+        .route("/", get(admin::list_sessions))
+        .route("/terminate", post(admin::terminate_sessions))
+        .route("/:session_id", delete(admin::terminate_session));
 
     // Tenant admin routes
     let tenant_admin_routes = Router::new()
         .route("/usage", get(admin::get_tenant_usage))
-        .route("/audit-logs", get(admin::list_audit_logs));
+        .route("/audit-logs", get(admin::list_audit_logs))
+        .route("/reprocess", post(admin::reprocess_tenant_messages));
+
+    // Signed attachment links from inbound webhook payloads, authorized by
+    // the link signature instead of an API key
+    let inbound_public_routes = Router::new().route(
+        "/attachments/:route_id/:message_id/:index",
+        get(inbound_routes::download_attachment),
+    );
 
     // API v1 routes with authentication
     let api_v1 = Router::new()
@@ -163,15 +361,41 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
         .nest("/tenants/:tenant_id/domain-aliases", domain_alias_routes)
         .nest("/tenants/:tenant_id/mailboxes", mailbox_routes)
         .nest("/tenants/:tenant_id/hooks", hook_routes)
+        .nest("/tenants/:tenant_id/inbound-routes", inbound_route_routes)
         .nest("/tenants/:tenant_id/policies", policy_routes)
+        .nest("/tenants/:tenant_id/protected-names", protected_name_routes)
+        .nest("/tenants/:tenant_id/quarantine", quarantine_routes)
+        .nest("/tenants/:tenant_id/spam-rules", spam_rule_routes)
+        .nest("/tenants/:tenant_id/config-bundle", config_bundle_routes)
         .nest("/tenants/:tenant_id/search", search_routes)
         .nest("/tenants/:tenant_id/send", send_routes)
+        .nest("/tenants/:tenant_id/secure-messages", secure_message_routes)
+        .nest("/tenants/:tenant_id/contacts", contact_routes)
         .nest("/tenants/:tenant_id/campaigns", campaign_routes)
         .nest("/tenants/:tenant_id/recipient-lists", recipient_list_routes)
+        .nest("/tenants/:tenant_id/tracking-domains", tracking_domain_routes)
+        // Mutations above are refused during maintenance
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance_middleware,
+        ))
+        .nest("/admin/system/maintenance", maintenance_routes)
+        .nest("/admin/system/sessions", session_routes)
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ))
+        .nest("/inbound", inbound_public_routes)
+        // A standby only changes through replication
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            standby_middleware,
+        ))
+        .with_state(state.clone());
+
+    // Prometheus metrics (no auth required)
+    let metrics_routes = Router::new()
+        .route("/", get(metrics::metrics))
         .with_state(state.clone());
 
     // OpenAPI documentation routes
@@ -180,6 +404,7 @@ pub fn create_router(db_pool: DatabasePool) -> Router {
     // Combine all routes
     Router::new()
         .nest("/health", health_routes)
+        .nest("/metrics", metrics_routes)
         .nest("/api/v1", api_v1)
         .merge(openapi_routes)
         .layer(TraceLayer::new_for_http())
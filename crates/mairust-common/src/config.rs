@@ -1,7 +1,10 @@
 //! Configuration for MaiRust
 
+use crate::types::{DeliveryLane, ServicesEnabled};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use uuid::Uuid;
 
 /// Main configuration structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,10 +20,18 @@ pub struct Config {
     #[serde(default)]
     pub storage: StorageConfig,
 
+    /// Regions tenant data can be pinned to
+    #[serde(default)]
+    pub residency: ResidencyConfig,
+
     /// SMTP configuration
     #[serde(default)]
     pub smtp: SmtpConfig,
 
+    /// LMTP configuration
+    #[serde(default)]
+    pub lmtp: LmtpConfig,
+
     /// API configuration
     #[serde(default)]
     pub api: ApiConfig,
@@ -44,13 +55,41 @@ pub struct Config {
     #[serde(default)]
     pub pop3: Pop3Config,
 
+    /// ManageSieve configuration
+    #[serde(default)]
+    pub managesieve: ManageSieveConfig,
+
+    /// IMAP and POP3 access by tenant plan
+    #[serde(default)]
+    pub services: ServicesConfig,
+
+    /// Outbound delivery queue configuration
+    #[serde(default)]
+    pub queue: QueueConfig,
+
+    /// Envelope journaling to an external archive
+    #[serde(default)]
+    pub journal: JournalConfig,
+
+    /// Warm standby replication
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+
     /// Web UI configuration
     #[serde(default)]
     pub web: WebConfig,
 
+    /// Tracked links of campaign mail and tenant tracking domains
+    #[serde(default)]
+    pub tracking: TrackingConfig,
+
     /// Plugin configuration
     #[serde(default)]
     pub plugins: PluginConfig,
+
+    /// Network listeners (`[[listener]]`); replace the fixed listeners when set
+    #[serde(default, rename = "listener")]
+    pub listeners: Vec<ListenerConfig>,
 }
 
 /// Server configuration
@@ -63,6 +102,11 @@ pub struct ServerConfig {
     /// Bind address
     #[serde(default = "default_bind_address")]
     pub bind_address: String,
+
+    /// Load balancers allowed to send a PROXY protocol header, as addresses
+    /// or CIDR ranges (e.g. "10.0.0.0/8")
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
 }
 
 impl Default for ServerConfig {
@@ -70,10 +114,29 @@ impl Default for ServerConfig {
         Self {
             hostname: default_hostname(),
             bind_address: default_bind_address(),
+            trusted_proxies: Vec::new(),
         }
     }
 }
 
+/// Check an address or CIDR range of `server.trusted_proxies`
+fn validate_network(network: &str) -> Result<(), String> {
+    let (address, prefix) = match network.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (network, None),
+    };
+    let address: std::net::IpAddr = address
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not an IP address or CIDR range", network))?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    match prefix.map(|p| p.trim().parse::<u8>()) {
+        None => Ok(()),
+        Some(Ok(prefix)) if prefix <= max_prefix => Ok(()),
+        Some(_) => Err(format!("'{}' has an invalid prefix length", network)),
+    }
+}
+
 fn default_hostname() -> String {
     "localhost".to_string()
 }
@@ -129,6 +192,10 @@ pub struct StorageConfig {
 
     /// S3 configuration
     pub s3: Option<S3Config>,
+
+    /// Directory layout of new message files
+    #[serde(default)]
+    pub layout: StorageLayoutConfig,
 }
 
 impl Default for StorageConfig {
@@ -137,6 +204,7 @@ impl Default for StorageConfig {
             backend: default_storage_backend(),
             path: default_storage_path(),
             s3: None,
+            layout: StorageLayoutConfig::default(),
         }
     }
 }
@@ -149,6 +217,68 @@ fn default_storage_path() -> PathBuf {
     PathBuf::from("/var/lib/mairust/mail")
 }
 
+/// How message files are spread over directories
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageLayoutScheme {
+    /// `tenant/mailbox/message.eml`
+    #[default]
+    Flat,
+    /// `tenant/mailbox/ab/cd/message.eml`, fanned out by a hash of the
+    /// message ID
+    Hashed,
+}
+
+/// Storage path layout
+///
+/// Only new files follow the layout; the path of every message is stored
+/// with it, so files written under an earlier layout stay readable until
+/// `mairust storage relocate` moves them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageLayoutConfig {
+    /// Layout scheme: "flat" or "hashed"
+    #[serde(default)]
+    pub scheme: StorageLayoutScheme,
+
+    /// Directory levels of two hex digits each (hashed scheme)
+    #[serde(default = "default_storage_fanout")]
+    pub fanout: u8,
+
+    /// Generation directory (`g<n>/`) new files are written under; 0 for
+    /// none
+    #[serde(default)]
+    pub generation: u32,
+}
+
+impl Default for StorageLayoutConfig {
+    fn default() -> Self {
+        Self {
+            scheme: StorageLayoutScheme::default(),
+            fanout: default_storage_fanout(),
+            generation: 0,
+        }
+    }
+}
+
+impl StorageLayoutConfig {
+    /// Highest supported fan-out depth
+    pub const MAX_FANOUT: u8 = 4;
+
+    /// Check the fan-out depth
+    pub fn validate(&self) -> Result<(), String> {
+        if self.scheme == StorageLayoutScheme::Hashed
+            && !(1..=Self::MAX_FANOUT).contains(&self.fanout)
+        {
+            return Err(format!("fanout must be between 1 and {}", Self::MAX_FANOUT));
+        }
+        Ok(())
+    }
+}
+
+fn default_storage_fanout() -> u8 {
+    2
+}
+
 /// S3 configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct S3Config {
@@ -176,6 +306,63 @@ fn default_multipart_threshold() -> u32 {
     8
 }
 
+/// Data residency of a multi-region deployment
+///
+/// A tenant pinned to a region (see the tenant's `data_region` setting)
+/// has its message files in that region's storage, and only servers of
+/// that region may access them. Tenants without a region use `storage`.
+/// With no regions configured, residency is not enforced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResidencyConfig {
+    /// Region this server runs in; must be one of `regions`
+    #[serde(default)]
+    pub region: Option<String>,
+
+    /// Storage of each region, e.g. `[residency.regions.eu.storage]`
+    #[serde(default)]
+    pub regions: HashMap<String, RegionConfig>,
+}
+
+impl ResidencyConfig {
+    /// Whether tenants can be pinned to regions
+    pub fn is_enabled(&self) -> bool {
+        !self.regions.is_empty()
+    }
+
+    /// Check that the local region is configured
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        for name in self.regions.keys() {
+            if !is_valid_region_name(name) {
+                return Err(format!("'{}' is not a valid region name", name));
+            }
+        }
+        match &self.region {
+            Some(region) if self.regions.contains_key(region) => Ok(()),
+            Some(region) => Err(format!("region '{}' is not in residency.regions", region)),
+            None => Err("region must be set when regions are configured".to_string()),
+        }
+    }
+}
+
+/// A region of a multi-region deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionConfig {
+    /// Storage of the message files of tenants pinned to the region
+    pub storage: StorageConfig,
+}
+
+/// Whether `name` can name a region: 1 to 32 lowercase letters, digits and
+/// hyphens (e.g. `eu-west-1`)
+pub fn is_valid_region_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
 /// SMTP configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SmtpConfig {
@@ -198,6 +385,11 @@ pub struct SmtpConfig {
     /// Maximum message size in bytes
     pub max_message_size: Option<usize>,
 
+    /// Maximum length of a line of message data in bytes, not counting the
+    /// CRLF (RFC 5321 section 4.5.3.1.6)
+    #[serde(default = "default_max_line_length")]
+    pub max_line_length: usize,
+
     /// Maximum recipients per message
     #[serde(default = "default_max_recipients")]
     pub max_recipients: usize,
@@ -218,6 +410,226 @@ pub struct SmtpConfig {
     /// Require TLS for authentication
     #[serde(default = "default_require_tls_for_auth")]
     pub require_tls_for_auth: bool,
+
+    /// Text following the hostname in the 220 greeting
+    #[serde(default = "default_smtp_banner")]
+    pub banner: String,
+
+    /// Hostname/banner overrides for specific listeners
+    #[serde(default)]
+    pub listener_identities: Vec<SmtpListenerIdentity>,
+
+    /// Outbound source IP pools with their own HELO names
+    #[serde(default)]
+    pub outbound_pools: Vec<OutboundIpPool>,
+
+    /// Let authenticated clients relay mail to remote domains
+    #[serde(default)]
+    pub allow_relay: bool,
+
+    /// Greylisting of unauthenticated senders
+    #[serde(default)]
+    pub greylisting: GreylistConfig,
+
+    /// Connection, message and recipient rates per client
+    #[serde(default)]
+    pub rate_limits: SmtpRateLimitConfig,
+
+    /// DNS blocklist lookups of unauthenticated clients
+    #[serde(default)]
+    pub dnsbl: DnsblConfig,
+
+    /// URI blocklist lookups of the links in received messages
+    #[serde(default)]
+    pub uribl: UriblConfig,
+
+    /// Local reputation of the domains mail is received from
+    #[serde(default)]
+    pub sender_reputation: SenderReputationConfig,
+
+    /// Characters that start a subaddress (`user+tag@domain`); each one is
+    /// a delimiter, and an empty string turns subaddressing off
+    #[serde(default = "default_recipient_delimiter")]
+    pub recipient_delimiter: String,
+
+    /// Margin over a mailbox quota, in percent of the quota, within which
+    /// delivery is deferred (452) instead of rejected (552); 0 rejects as
+    /// soon as the quota is exceeded
+    #[serde(default = "default_quota_grace_percent")]
+    pub quota_grace_percent: u32,
+
+    /// What to do when an authenticated user sends from an address (MAIL
+    /// FROM or the From header) that is not one of their mailboxes
+    #[serde(default)]
+    pub sender_policy: SenderPolicy,
+
+    /// ARC verification of forwarded mail
+    #[serde(default)]
+    pub arc: ArcConfig,
+
+    /// Sender rewriting of forwarded mail
+    #[serde(default)]
+    pub srs: SrsConfig,
+
+    /// Signing of the VERP return paths of scheduled mail
+    #[serde(default)]
+    pub verp: VerpConfig,
+
+    /// DMARC aggregate reports about received mail
+    #[serde(default)]
+    pub dmarc_reports: DmarcReportConfig,
+
+    /// BIMI brand indicators of received mail
+    #[serde(default)]
+    pub bimi: BimiConfig,
+
+    /// Rotation of the domains' DKIM signing keys
+    #[serde(default)]
+    pub dkim_rotation: DkimRotationConfig,
+
+    /// SPF checks of received mail
+    #[serde(default)]
+    pub spf: SpfConfig,
+
+    /// External filters spoken to with the milter protocol, in order
+    #[serde(default)]
+    pub milters: Vec<MilterConfig>,
+
+    /// Virus scanning of received mail with clamd
+    #[serde(default)]
+    pub antivirus: AntivirusConfig,
+
+    /// Country and autonomous system of clients, for policies
+    #[serde(default)]
+    pub geoip: GeoIpConfig,
+}
+
+/// Handling of authenticated senders using addresses they do not own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderPolicy {
+    /// Refuse the sender (553) or the message (550)
+    #[default]
+    Reject,
+    /// Replace the address with the user's own
+    Rewrite,
+    /// Accept any address
+    Allow,
+}
+
+/// Identity presented by a specific SMTP listener
+///
+/// An identity applies when every field that is set matches the connection:
+/// `service` is "smtp" or "submission", `local_ip` is the address the client
+/// connected to. The most specific match wins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmtpListenerIdentity {
+    /// Service name ("smtp" or "submission")
+    pub service: Option<String>,
+
+    /// Local IP address the connection was accepted on
+    pub local_ip: Option<String>,
+
+    /// Hostname used in the banner, EHLO response and Received headers
+    pub hostname: String,
+
+    /// Greeting text (defaults to `smtp.banner`)
+    pub banner: Option<String>,
+}
+
+impl SmtpListenerIdentity {
+    /// Number of matching fields, or None if any set field does not match
+    fn specificity(&self, service: &str, local_ip: Option<&str>) -> Option<u8> {
+        let mut score = 0;
+        if let Some(ref s) = self.service {
+            if !s.eq_ignore_ascii_case(service) {
+                return None;
+            }
+            score += 1;
+        }
+        if let Some(ref ip) = self.local_ip {
+            if Some(ip.as_str()) != local_ip {
+                return None;
+            }
+            score += 2;
+        }
+        Some(score)
+    }
+}
+
+/// Pool of outbound source IPs sharing a HELO identity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboundIpPool {
+    /// Pool name
+    pub name: String,
+
+    /// Local source addresses used for outbound connections
+    #[serde(default)]
+    pub source_ips: Vec<String>,
+
+    /// Hostname sent in EHLO/HELO from this pool
+    pub helo_hostname: String,
+
+    /// Sender domains routed through this pool
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+impl SmtpConfig {
+    /// Resolve the hostname and banner for a listener connection
+    pub fn listener_identity(&self, service: &str, local_ip: Option<&str>) -> (String, String) {
+        let identity = self
+            .listener_identities
+            .iter()
+            .filter_map(|i| i.specificity(service, local_ip).map(|score| (score, i)))
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, i)| i);
+
+        match identity {
+            Some(i) => (
+                i.hostname.clone(),
+                i.banner.clone().unwrap_or_else(|| self.banner.clone()),
+            ),
+            None => (self.hostname.clone(), self.banner.clone()),
+        }
+    }
+
+    /// Find the outbound pool for a sender domain
+    pub fn outbound_pool(&self, sender_domain: &str) -> Option<&OutboundIpPool> {
+        self.outbound_pools.iter().find(|p| {
+            p.domains
+                .iter()
+                .any(|d| d.eq_ignore_ascii_case(sender_domain))
+        })
+    }
+
+    /// Hostname to announce in EHLO when sending for a sender domain
+    pub fn outbound_helo(&self, sender_domain: &str) -> &str {
+        self.outbound_pool(sender_domain)
+            .map(|p| p.helo_hostname.as_str())
+            .unwrap_or(&self.hostname)
+    }
+
+    /// SMTP (`port`) and Submission (`submission_port`) listeners used when
+    /// no `[[listener]]` is configured
+    pub fn default_listeners(&self) -> Vec<ListenerConfig> {
+        vec![
+            ListenerConfig {
+                auth_required: self.auth_required,
+                ..ListenerConfig::new(
+                    ListenerProtocol::Smtp,
+                    format!("{}:{}", self.host, self.port),
+                )
+            },
+            ListenerConfig {
+                auth_required: Some(true),
+                ..ListenerConfig::new(
+                    ListenerProtocol::Submission,
+                    format!("{}:{}", self.host, self.submission_port),
+                )
+            },
+        ]
+    }
 }
 
 impl Default for SmtpConfig {
@@ -228,257 +640,1819 @@ impl Default for SmtpConfig {
             port: default_smtp_port(),
             submission_port: default_submission_port(),
             max_message_size: Some(default_max_message_size()),
+            max_line_length: default_max_line_length(),
             max_recipients: default_max_recipients(),
             max_connections: Some(100),
             connection_timeout_secs: default_connection_timeout(),
             tls_enabled: Some(true),
             auth_required: Some(false),
             require_tls_for_auth: default_require_tls_for_auth(),
+            banner: default_smtp_banner(),
+            listener_identities: Vec::new(),
+            outbound_pools: Vec::new(),
+            allow_relay: false,
+            greylisting: GreylistConfig::default(),
+            rate_limits: SmtpRateLimitConfig::default(),
+            dnsbl: DnsblConfig::default(),
+            uribl: UriblConfig::default(),
+            sender_reputation: SenderReputationConfig::default(),
+            recipient_delimiter: default_recipient_delimiter(),
+            quota_grace_percent: default_quota_grace_percent(),
+            sender_policy: SenderPolicy::default(),
+            arc: ArcConfig::default(),
+            srs: SrsConfig::default(),
+            verp: VerpConfig::default(),
+            dmarc_reports: DmarcReportConfig::default(),
+            bimi: BimiConfig::default(),
+            dkim_rotation: DkimRotationConfig::default(),
+            spf: SpfConfig::default(),
+            milters: Vec::new(),
+            antivirus: AntivirusConfig::default(),
+            geoip: GeoIpConfig::default(),
         }
     }
 }
 
-fn default_smtp_host() -> String {
-    "0.0.0.0".to_string()
-}
+/// Greylisting of unauthenticated SMTP clients
+///
+/// The first delivery attempt of a (client network, sender, recipient)
+/// triplet is deferred with a 451; a retry after `delay_secs` and within
+/// `retry_window_secs` is accepted and the triplet remembered. Clients that
+/// passed `auto_whitelist_after` times are no longer greylisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GreylistConfig {
+    /// Greylist clients that fail SPF or have no prior history
+    #[serde(default)]
+    pub enabled: bool,
 
-fn default_smtp_port() -> u16 {
-    25
+    /// Time a new triplet is deferred, in seconds
+    #[serde(default = "default_greylist_delay_secs")]
+    pub delay_secs: u64,
+
+    /// Time after the first attempt within which a retry is accepted, in
+    /// seconds; later attempts start over
+    #[serde(default = "default_greylist_retry_window_secs")]
+    pub retry_window_secs: u64,
+
+    /// How long a passed triplet and a whitelisted client are remembered
+    /// after their last message, in seconds
+    #[serde(default = "default_greylist_expiry_secs")]
+    pub expiry_secs: u64,
+
+    /// Passed triplets after which a client network is whitelisted (0
+    /// disables auto-whitelisting)
+    #[serde(default = "default_greylist_auto_whitelist_after")]
+    pub auto_whitelist_after: u32,
 }
 
-fn default_submission_port() -> u16 {
-    587
+impl Default for GreylistConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay_secs: default_greylist_delay_secs(),
+            retry_window_secs: default_greylist_retry_window_secs(),
+            expiry_secs: default_greylist_expiry_secs(),
+            auto_whitelist_after: default_greylist_auto_whitelist_after(),
+        }
+    }
 }
 
-fn default_max_message_size() -> usize {
-    25 * 1024 * 1024 // 25 MB
+fn default_greylist_delay_secs() -> u64 {
+    300
 }
 
-fn default_max_recipients() -> usize {
-    100
+fn default_greylist_retry_window_secs() -> u64 {
+    4 * 3600
 }
 
-fn default_connection_timeout() -> u64 {
-    300
+fn default_greylist_expiry_secs() -> u64 {
+    35 * 86400
 }
 
-fn default_require_tls_for_auth() -> bool {
-    true
+fn default_greylist_auto_whitelist_after() -> u32 {
+    5
 }
 
-/// API configuration
+/// SMTP rate limits
+///
+/// Limits are per minute and enforced with token buckets, so a client may
+/// use a minute's allowance in a burst. Connections are counted per client
+/// IP address; messages and recipients per authenticated user, or per
+/// client IP address for unauthenticated sessions. Messages of
+/// unauthenticated sessions also count against their envelope sender's
+/// domain. 0 disables a limit.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ApiConfig {
-    /// API port
-    #[serde(default = "default_api_port")]
-    pub port: u16,
+pub struct SmtpRateLimitConfig {
+    /// Enforce the limits
+    #[serde(default)]
+    pub enabled: bool,
 
-    /// Enable Swagger UI
-    #[serde(default = "default_enable_swagger")]
-    pub enable_swagger: bool,
+    /// Connections per minute from one IP address
+    #[serde(default = "default_smtp_connections_per_ip")]
+    pub connections_per_ip: u32,
 
-    /// CORS allowed origins
+    /// Messages per minute from one IP address
+    #[serde(default = "default_smtp_messages_per_ip")]
+    pub messages_per_ip: u32,
+
+    /// Recipients per minute from one IP address
+    #[serde(default = "default_smtp_recipients_per_ip")]
+    pub recipients_per_ip: u32,
+
+    /// Messages per minute from one authenticated user
+    #[serde(default = "default_smtp_messages_per_user")]
+    pub messages_per_user: u32,
+
+    /// Recipients per minute from one authenticated user
+    #[serde(default = "default_smtp_recipients_per_user")]
+    pub recipients_per_user: u32,
+
+    /// Messages per minute from one sender domain, across client addresses
     #[serde(default)]
-    pub cors_origins: Vec<String>,
+    pub messages_per_sender_domain: u32,
 }
 
-impl Default for ApiConfig {
+impl Default for SmtpRateLimitConfig {
     fn default() -> Self {
         Self {
-            port: default_api_port(),
-            enable_swagger: default_enable_swagger(),
-            cors_origins: Vec::new(),
+            enabled: false,
+            connections_per_ip: default_smtp_connections_per_ip(),
+            messages_per_ip: default_smtp_messages_per_ip(),
+            recipients_per_ip: default_smtp_recipients_per_ip(),
+            messages_per_user: default_smtp_messages_per_user(),
+            recipients_per_user: default_smtp_recipients_per_user(),
+            messages_per_sender_domain: 0,
         }
     }
 }
 
-fn default_api_port() -> u16 {
-    8080
-}
-
-fn default_enable_swagger() -> bool {
-    true
+fn default_smtp_connections_per_ip() -> u32 {
+    60
 }
 
-/// Logging configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LoggingConfig {
-    /// Log level
-    #[serde(default = "default_log_level")]
-    pub level: String,
-
-    /// Log format: "json" or "text"
-    #[serde(default = "default_log_format")]
-    pub format: String,
+fn default_smtp_messages_per_ip() -> u32 {
+    30
 }
 
-impl Default for LoggingConfig {
-    fn default() -> Self {
-        Self {
-            level: default_log_level(),
-            format: default_log_format(),
-        }
-    }
+fn default_smtp_recipients_per_ip() -> u32 {
+    300
 }
 
-fn default_log_level() -> String {
-    "info".to_string()
+fn default_smtp_messages_per_user() -> u32 {
+    100
 }
 
-fn default_log_format() -> String {
-    "json".to_string()
+fn default_smtp_recipients_per_user() -> u32 {
+    1000
 }
 
-/// TLS configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TlsConfig {
-    /// Path to certificate file
-    pub cert_path: PathBuf,
-
-    /// Path to private key file
-    pub key_path: PathBuf,
+/// ARC (RFC 8617) verification of forwarded mail
+///
+/// Mail forwarded by mailing lists and forwarding services often fails
+/// DMARC. Such a failure is ignored when the message has a valid ARC chain
+/// whose latest seal was added by one of the trusted sealers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArcConfig {
+    /// Domains whose ARC seals (d= of ARC-Seal) are trusted
+    #[serde(default)]
+    pub trusted_sealers: Vec<String>,
 }
 
-/// Meilisearch configuration
+/// Sender Rewriting Scheme (SRS) for forwarded mail
+///
+/// Forwarded mail is sent with an envelope sender at `domain` that encodes
+/// the original sender, so that it passes SPF at the destination. Bounces
+/// to such addresses are returned to the original sender. The domain's MX
+/// must point at this server and its SPF record must allow it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MeilisearchConfig {
-    /// Enable Meilisearch integration
+pub struct SrsConfig {
+    /// Rewrite the envelope sender of forwarded mail
     #[serde(default)]
     pub enabled: bool,
 
-    /// Meilisearch server URL
-    #[serde(default = "default_meilisearch_url")]
-    pub url: String,
-
-    /// API key for authentication
-    pub api_key: Option<String>,
+    /// Domain of rewritten addresses (default: the SMTP hostname)
+    pub domain: Option<String>,
 
-    /// Request timeout in seconds
-    #[serde(default = "default_meilisearch_timeout")]
-    pub timeout_secs: u64,
+    /// Keys of the address hashes. The first one hashes new addresses and
+    /// all are accepted, so a key is rotated by adding the new one first.
+    #[serde(default)]
+    pub secrets: Vec<String>,
 
-    /// Index name for messages
-    #[serde(default = "default_messages_index")]
-    pub messages_index: String,
+    /// Days a rewritten address accepts bounces
+    #[serde(default = "default_srs_max_age_days")]
+    pub max_age_days: u32,
 }
 
-impl Default for MeilisearchConfig {
+impl Default for SrsConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            url: default_meilisearch_url(),
-            api_key: None,
-            timeout_secs: default_meilisearch_timeout(),
-            messages_index: default_messages_index(),
+            domain: None,
+            secrets: Vec::new(),
+            max_age_days: default_srs_max_age_days(),
         }
     }
 }
 
-fn default_meilisearch_url() -> String {
-    "http://localhost:7700".to_string()
+fn default_srs_max_age_days() -> u32 {
+    21
 }
 
-fn default_meilisearch_timeout() -> u64 {
-    30
+/// VERP return paths of scheduled mail
+///
+/// The return path `bounce+<message id>-<tag>@<sender domain>` carries an
+/// HMAC tag of the message ID, so that bounces for a message cannot be
+/// forged by someone who only knows its ID. Without a secret, scheduled
+/// mail is sent without VERP and bounces are not processed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerpConfig {
+    /// Keys of the return path tags. The first one tags new return paths
+    /// and all are accepted, so a key is rotated by adding the new one first.
+    #[serde(default)]
+    pub secrets: Vec<String>,
+}
+
+/// DMARC aggregate (RUA) reports about received mail
+///
+/// The DMARC results of received mail are counted per source IP. Once a
+/// day, every domain whose DMARC record has `rua=` addresses is sent an XML
+/// report of the previous UTC day.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DmarcReportConfig {
+    /// Count results and send reports
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Reporting organization (default: the SMTP hostname)
+    pub org_name: Option<String>,
+
+    /// Sender of the reports (default: `noreply-dmarc@<hostname>`)
+    pub from_address: Option<String>,
+
+    /// Additional contact information given in the reports
+    pub contact_info: Option<String>,
+
+    /// Packaging of the XML report
+    #[serde(default)]
+    pub compression: DmarcReportCompression,
+}
+
+/// Packaging of DMARC aggregate reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmarcReportCompression {
+    /// `.xml.gz`, which every report receiver accepts
+    #[default]
+    Gzip,
+    /// `.zip`
+    Zip,
+}
+
+/// BIMI brand indicators of received mail
+///
+/// Mail that passes DMARC under a quarantine or reject policy gets the logo
+/// its domain publishes in a BIMI record, in the BIMI-Location and
+/// BIMI-Indicator headers. Verified Mark Certificates (VMCs) are validated
+/// against the root certificates in `vmc_roots`; without roots, logos are
+/// shown unverified unless `require_vmc` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BimiConfig {
+    /// Look up logos
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// PEM file with the roots VMCs are validated against
+    pub vmc_roots: Option<String>,
+
+    /// Only show logos with a valid VMC
+    #[serde(default)]
+    pub require_vmc: bool,
+
+    /// Seconds a domain's logo is cached
+    #[serde(default = "default_bimi_cache_secs")]
+    pub cache_secs: u64,
+}
+
+impl Default for BimiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            vmc_roots: None,
+            require_vmc: false,
+            cache_secs: default_bimi_cache_secs(),
+        }
+    }
+}
+
+fn default_bimi_cache_secs() -> u64 {
+    3600
+}
+
+/// Rotation of DKIM signing keys
+///
+/// Every domain that signs gets a new key for each of `algorithms` once its
+/// newest key of that algorithm is `interval_days` old, or when it has
+/// none; listing `ed25519-sha256` next to `rsa-sha256` starts dual signing.
+/// New keys are pending for `publish_days` so that their DNS records can be
+/// published, and with `check_dns` are only activated once the record is
+/// found. Keys replaced by a new one are deleted after `retain_days`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DkimRotationConfig {
+    /// Generate new keys; pending keys are activated either way
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Age in days after which a key is replaced
+    #[serde(default = "default_dkim_rotation_interval_days")]
+    pub interval_days: u32,
+
+    /// Days a new key is pending before it is activated
+    #[serde(default = "default_dkim_rotation_publish_days")]
+    pub publish_days: u32,
+
+    /// Days a replaced key is kept, so that signatures made with it
+    /// still verify
+    #[serde(default = "default_dkim_rotation_retain_days")]
+    pub retain_days: u32,
+
+    /// Algorithms every domain signs with: `rsa-sha256`, `ed25519-sha256`
+    #[serde(default = "default_dkim_rotation_algorithms")]
+    pub algorithms: Vec<String>,
+
+    /// Size of generated RSA keys, in bits
+    #[serde(default = "default_dkim_rotation_rsa_bits")]
+    pub rsa_bits: usize,
+
+    /// Only activate a key once its DNS record is published
+    #[serde(default = "default_dkim_rotation_check_dns")]
+    pub check_dns: bool,
+}
+
+impl Default for DkimRotationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_days: default_dkim_rotation_interval_days(),
+            publish_days: default_dkim_rotation_publish_days(),
+            retain_days: default_dkim_rotation_retain_days(),
+            algorithms: default_dkim_rotation_algorithms(),
+            rsa_bits: default_dkim_rotation_rsa_bits(),
+            check_dns: default_dkim_rotation_check_dns(),
+        }
+    }
+}
+
+fn default_dkim_rotation_interval_days() -> u32 {
+    180
+}
+
+fn default_dkim_rotation_publish_days() -> u32 {
+    7
+}
+
+fn default_dkim_rotation_retain_days() -> u32 {
+    7
+}
+
+fn default_dkim_rotation_algorithms() -> Vec<String> {
+    vec!["rsa-sha256".to_string()]
+}
+
+fn default_dkim_rotation_rsa_bits() -> usize {
+    2048
+}
+
+fn default_dkim_rotation_check_dns() -> bool {
+    true
+}
+
+/// SPF checks of received mail
+///
+/// Every message from an unauthenticated client is checked and the result
+/// recorded in its Authentication-Results header. With `reject_fail`, MAIL
+/// FROM is refused when the sender's policy fails the client, with the
+/// explanation the domain publishes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpfConfig {
+    /// Refuse senders whose SPF policy fails the client (550 5.7.23)
+    #[serde(default)]
+    pub reject_fail: bool,
+}
+
+/// An external filter spoken to with the Sendmail milter protocol (e.g.
+/// rspamd's proxy or clamav-milter)
+///
+/// Milters are asked in order at connect, HELO, MAIL, RCPT, DATA and at
+/// the end of the message; the first one to refuse decides. A milter that
+/// cannot be reached or misbehaves is skipped when `fail_open`, and defers
+/// the command with 451 otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MilterConfig {
+    /// Name used in logs
+    pub name: String,
+
+    /// "host:port", or "unix:/path/to/socket"
+    pub address: String,
+
+    /// Go on without the milter when it fails
+    #[serde(default = "default_milter_fail_open")]
+    pub fail_open: bool,
+
+    /// Seconds to wait for the connection
+    #[serde(default = "default_milter_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// Seconds to wait for each reply
+    #[serde(default = "default_milter_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl MilterConfig {
+    /// Check the address
+    pub fn validate(&self) -> Result<(), String> {
+        validate_socket_address(&self.address)
+    }
+}
+
+/// Check a "host:port" or "unix:/path" address
+fn validate_socket_address(address: &str) -> Result<(), String> {
+    if let Some(path) = address.strip_prefix("unix:") {
+        if !path.starts_with('/') {
+            return Err(format!("socket path '{}' is not absolute", path));
+        }
+        return Ok(());
+    }
+    let port = address
+        .rsplit_once(':')
+        .map(|(_, port)| port.parse::<u16>());
+    if !matches!(port, Some(Ok(_))) {
+        return Err(format!("address '{}' has no valid port", address));
+    }
+    Ok(())
+}
+
+fn default_milter_fail_open() -> bool {
+    true
+}
+
+fn default_milter_connect_timeout_secs() -> u64 {
+    5
+}
+
+fn default_milter_timeout_secs() -> u64 {
+    30
+}
+
+/// Virus scanning of received mail with clamd
+///
+/// After DATA, each message is streamed to clamd once, before it is
+/// delivered. Infected messages are rejected, quarantined or tagged as
+/// `action` says; tenants can turn scanning off or choose their own action
+/// in their settings. A failed scan lets the message through when
+/// `fail_open`, and defers it with 451 otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AntivirusConfig {
+    /// Scan received mail
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// clamd's "host:port", or "unix:/path/to/clamd.ctl"
+    #[serde(default = "default_antivirus_address")]
+    pub address: String,
+
+    /// What to do with infected messages
+    #[serde(default)]
+    pub action: AntivirusAction,
+
+    /// Deliver messages unscanned when the scan fails
+    #[serde(default = "default_antivirus_fail_open")]
+    pub fail_open: bool,
+
+    /// Seconds a scan may take
+    #[serde(default = "default_antivirus_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// Largest message scanned, in bytes; larger ones are delivered
+    /// unscanned (keep it within clamd's StreamMaxLength)
+    #[serde(default = "default_antivirus_max_size")]
+    pub max_size: usize,
+}
+
+impl Default for AntivirusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: default_antivirus_address(),
+            action: AntivirusAction::default(),
+            fail_open: default_antivirus_fail_open(),
+            timeout_secs: default_antivirus_timeout_secs(),
+            max_size: default_antivirus_max_size(),
+        }
+    }
+}
+
+impl AntivirusConfig {
+    /// Check the address
+    pub fn validate(&self) -> Result<(), String> {
+        validate_socket_address(&self.address)
+    }
+}
+
+/// What happens to an infected message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AntivirusAction {
+    /// Refuse the message (554)
+    #[default]
+    Reject,
+    /// Hold the message out of the recipient's mailbox
+    Quarantine,
+    /// Deliver the message with the `virus` tag
+    Tag,
+}
+
+fn default_antivirus_address() -> String {
+    "127.0.0.1:3310".to_string()
+}
+
+fn default_antivirus_fail_open() -> bool {
+    true
+}
+
+fn default_antivirus_timeout_secs() -> u64 {
+    30
+}
+
+fn default_antivirus_max_size() -> usize {
+    25 * 1024 * 1024
+}
+
+/// GeoIP lookups of SMTP clients
+///
+/// The databases are in the MaxMind DB format, such as GeoLite2-Country (or
+/// -City) and GeoLite2-ASN. They are read at startup, so a restart picks up
+/// updated files. The country and autonomous system of a client are matched
+/// by the `country` and `asn` policy conditions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeoIpConfig {
+    /// Database with the country of addresses (`country.iso_code`)
+    #[serde(default)]
+    pub country_database: Option<PathBuf>,
+
+    /// Database with the autonomous system of addresses
+    /// (`autonomous_system_number`)
+    #[serde(default)]
+    pub asn_database: Option<PathBuf>,
+}
+
+/// DNS blocklist (DNSBL/RBL) lookups of unauthenticated SMTP clients
+///
+/// The client address is looked up in every list when the client connects.
+/// The scores of the lists it is on add up; the sum is added to the spam
+/// score of its messages, and MAIL FROM is refused once it reaches
+/// `reject_score`. Addresses in the DNSBL allowlist are never looked up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsblConfig {
+    /// Look up clients
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Blocklists to look clients up in
+    #[serde(default = "default_dnsbl_lists")]
+    pub lists: Vec<DnsblListConfig>,
+
+    /// Total score at which MAIL FROM is refused (0 only adds to the spam
+    /// score)
+    #[serde(default)]
+    pub reject_score: f64,
+
+    /// Seconds lookup results are cached
+    #[serde(default = "default_dnsbl_cache_secs")]
+    pub cache_secs: u64,
+
+    /// Seconds to wait for a blocklist to answer
+    #[serde(default = "default_dnsbl_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for DnsblConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lists: default_dnsbl_lists(),
+            reject_score: 0.0,
+            cache_secs: default_dnsbl_cache_secs(),
+            timeout_secs: default_dnsbl_timeout_secs(),
+        }
+    }
+}
+
+/// A DNS blocklist
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsblListConfig {
+    /// DNS zone of the list (e.g. "zen.spamhaus.org")
+    pub zone: String,
+
+    /// Score added when a client (or, in a URI blocklist, a link) is listed
+    #[serde(default = "default_dnsbl_score")]
+    pub score: f64,
+
+    /// Answers that count as a listing (e.g. "127.0.0.2"); any 127.0.0.0/8
+    /// answer when empty
+    #[serde(default)]
+    pub codes: Vec<String>,
+}
+
+fn default_dnsbl_lists() -> Vec<DnsblListConfig> {
+    vec![DnsblListConfig {
+        zone: "zen.spamhaus.org".to_string(),
+        score: default_dnsbl_score(),
+        codes: Vec::new(),
+    }]
+}
+
+fn default_dnsbl_score() -> f64 {
+    5.0
+}
+
+fn default_dnsbl_cache_secs() -> u64 {
+    900
+}
+
+fn default_dnsbl_timeout_secs() -> u64 {
+    3
+}
+
+/// URI blocklist (URIBL/SURBL) lookups of the links in received messages
+///
+/// The domains and addresses that links of a message point to are looked
+/// up in every list and in the local bad-domain table. Each list or table
+/// entry a message has a link on adds its score once to the spam score of
+/// the message. Mail of authenticated senders is not checked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UriblConfig {
+    /// Look up links
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Blocklists to look link domains up in
+    #[serde(default = "default_uribl_lists")]
+    pub lists: Vec<DnsblListConfig>,
+
+    /// Score of bad-domain table entries without their own score
+    #[serde(default = "default_dnsbl_score")]
+    pub bad_domain_score: f64,
+
+    /// Most link domains looked up per message
+    #[serde(default = "default_uribl_max_domains")]
+    pub max_domains: usize,
+
+    /// Seconds lookup results are cached
+    #[serde(default = "default_dnsbl_cache_secs")]
+    pub cache_secs: u64,
+
+    /// Seconds to wait for a blocklist to answer
+    #[serde(default = "default_dnsbl_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for UriblConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lists: default_uribl_lists(),
+            bad_domain_score: default_dnsbl_score(),
+            max_domains: default_uribl_max_domains(),
+            cache_secs: default_dnsbl_cache_secs(),
+            timeout_secs: default_dnsbl_timeout_secs(),
+        }
+    }
+}
+
+fn default_uribl_lists() -> Vec<DnsblListConfig> {
+    vec![DnsblListConfig {
+        zone: "multi.surbl.org".to_string(),
+        score: default_dnsbl_score(),
+        codes: Vec::new(),
+    }]
+}
+
+fn default_uribl_max_domains() -> usize {
+    20
+}
+
+/// Local reputation of sender domains
+///
+/// Messages, spam and refused recipients of unauthenticated sessions are
+/// counted per envelope sender domain. The counts decay by half every
+/// `half_life_hours`, so a domain recovers once its mail gets better. The
+/// reputation is a score from 0 (only spam and errors) to 100 (clean).
+/// Below 50 it adds up to `spam_weight` to the spam score of the domain's
+/// messages; below `reject_below` MAIL FROM is deferred, once the domain
+/// has sent `min_messages`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SenderReputationConfig {
+    /// Track sender domains
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Hours after which counts have decayed to half
+    #[serde(default = "default_reputation_half_life_hours")]
+    pub half_life_hours: f64,
+
+    /// Spam score added to messages of a domain with a reputation of 0
+    #[serde(default = "default_reputation_spam_weight")]
+    pub spam_weight: f64,
+
+    /// Reputation below which MAIL FROM is deferred (0 never defers)
+    #[serde(default)]
+    pub reject_below: f64,
+
+    /// Messages and refused recipients, after decay, a domain needs before
+    /// it is deferred
+    #[serde(default = "default_reputation_min_messages")]
+    pub min_messages: f64,
+}
+
+impl Default for SenderReputationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            half_life_hours: default_reputation_half_life_hours(),
+            spam_weight: default_reputation_spam_weight(),
+            reject_below: 0.0,
+            min_messages: default_reputation_min_messages(),
+        }
+    }
+}
+
+fn default_reputation_half_life_hours() -> f64 {
+    24.0
+}
+
+fn default_reputation_spam_weight() -> f64 {
+    3.0
+}
+
+fn default_reputation_min_messages() -> f64 {
+    20.0
+}
+
+fn default_smtp_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+fn default_submission_port() -> u16 {
+    587
+}
+
+fn default_max_message_size() -> usize {
+    25 * 1024 * 1024 // 25 MB
+}
+
+fn default_max_line_length() -> usize {
+    998
+}
+
+fn default_max_recipients() -> usize {
+    100
+}
+
+fn default_connection_timeout() -> u64 {
+    300
+}
+
+fn default_require_tls_for_auth() -> bool {
+    true
+}
+
+fn default_smtp_banner() -> String {
+    "ESMTP MaiRust".to_string()
+}
+
+fn default_recipient_delimiter() -> String {
+    "+".to_string()
+}
+
+fn default_quota_grace_percent() -> u32 {
+    10
+}
+
+/// API configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiConfig {
+    /// API port
+    #[serde(default = "default_api_port")]
+    pub port: u16,
+
+    /// Enable Swagger UI
+    #[serde(default = "default_enable_swagger")]
+    pub enable_swagger: bool,
+
+    /// CORS allowed origins
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+
+    /// Public base URL of the API, used in links handed to external
+    /// services (e.g. `https://mail.example.com`)
+    #[serde(default)]
+    pub public_url: Option<String>,
+}
+
+impl ApiConfig {
+    /// Public base URL, derived from the hostname and port when not set
+    pub fn public_base_url(&self, hostname: &str) -> String {
+        match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{}", hostname, self.port),
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            port: default_api_port(),
+            enable_swagger: default_enable_swagger(),
+            cors_origins: Vec::new(),
+            public_url: None,
+        }
+    }
+}
+
+fn default_api_port() -> u16 {
+    8080
+}
+
+fn default_enable_swagger() -> bool {
+    true
+}
+
+/// Logging configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Log level
+    #[serde(default = "default_log_level")]
+    pub level: String,
+
+    /// Log format: "json" or "text"
+    #[serde(default = "default_log_format")]
+    pub format: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            format: default_log_format(),
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_format() -> String {
+    "json".to_string()
+}
+
+/// TLS configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Path to certificate file
+    pub cert_path: PathBuf,
+
+    /// Path to private key file
+    pub key_path: PathBuf,
+}
+
+/// Meilisearch configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeilisearchConfig {
+    /// Enable Meilisearch integration
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Meilisearch server URL
+    #[serde(default = "default_meilisearch_url")]
+    pub url: String,
+
+    /// API key for authentication
+    pub api_key: Option<String>,
+
+    /// Request timeout in seconds
+    #[serde(default = "default_meilisearch_timeout")]
+    pub timeout_secs: u64,
+
+    /// Index name for messages
+    #[serde(default = "default_messages_index")]
+    pub messages_index: String,
+}
+
+impl Default for MeilisearchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: default_meilisearch_url(),
+            api_key: None,
+            timeout_secs: default_meilisearch_timeout(),
+            messages_index: default_messages_index(),
+        }
+    }
+}
+
+fn default_meilisearch_url() -> String {
+    "http://localhost:7700".to_string()
+}
+
+fn default_meilisearch_timeout() -> u64 {
+    30
+}
+
+fn default_messages_index() -> String {
+    "messages".to_string()
+}
+
+/// IMAP server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImapConfig {
+    /// Enable IMAP server
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// IMAP server bind address
+    #[serde(default = "default_imap_bind")]
+    pub bind: String,
+
+    /// Enable STARTTLS
+    #[serde(default)]
+    pub starttls: bool,
+
+    /// Implicit TLS (IMAPS) bind address, e.g. "0.0.0.0:993"
+    #[serde(default)]
+    pub tls_bind: Option<String>,
+
+    /// Session timeout in minutes
+    #[serde(default = "default_imap_timeout")]
+    pub timeout_minutes: i64,
+
+    /// Maximum concurrent connections
+    #[serde(default = "default_imap_max_connections")]
+    pub max_connections: usize,
+
+    /// Per-IP connection and authentication limits
+    #[serde(default)]
+    pub limits: ConnectionLimitConfig,
+}
+
+impl Default for ImapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_imap_bind(),
+            starttls: false,
+            tls_bind: None,
+            timeout_minutes: default_imap_timeout(),
+            max_connections: default_imap_max_connections(),
+            limits: ConnectionLimitConfig::default(),
+        }
+    }
+}
+
+fn default_imap_bind() -> String {
+    "0.0.0.0:143".to_string()
+}
+
+fn default_imap_timeout() -> i64 {
+    30
+}
+
+fn default_imap_max_connections() -> usize {
+    1000
+}
+
+/// POP3 server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pop3Config {
+    /// Enable POP3 server
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// POP3 server bind address
+    #[serde(default = "default_pop3_bind")]
+    pub bind: String,
+
+    /// Enable STARTTLS
+    #[serde(default)]
+    pub starttls: bool,
+
+    /// Implicit TLS (POP3S) bind address, e.g. "0.0.0.0:995"
+    #[serde(default)]
+    pub tls_bind: Option<String>,
+
+    /// Refuse USER/PASS and AUTH until the connection is encrypted
+    #[serde(default = "default_require_tls_for_auth")]
+    pub require_tls_for_auth: bool,
+
+    /// Offer APOP to users with an APOP secret
+    #[serde(default)]
+    pub apop: bool,
+
+    /// Session timeout in minutes
+    #[serde(default = "default_pop3_timeout")]
+    pub timeout_minutes: i64,
+
+    /// Maximum concurrent connections
+    #[serde(default = "default_pop3_max_connections")]
+    pub max_connections: usize,
+
+    /// Per-IP connection and authentication limits
+    #[serde(default)]
+    pub limits: ConnectionLimitConfig,
+}
+
+impl Default for Pop3Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_pop3_bind(),
+            starttls: false,
+            tls_bind: None,
+            require_tls_for_auth: default_require_tls_for_auth(),
+            apop: false,
+            timeout_minutes: default_pop3_timeout(),
+            max_connections: default_pop3_max_connections(),
+            limits: ConnectionLimitConfig::default(),
+        }
+    }
+}
+
+fn default_pop3_bind() -> String {
+    "0.0.0.0:110".to_string()
+}
+
+fn default_pop3_timeout() -> i64 {
+    10
+}
+
+fn default_pop3_max_connections() -> usize {
+    500
+}
+
+/// LMTP server configuration (RFC 2033)
+///
+/// Takes local delivery from an MTA in front of MaiRust (e.g. Postfix with
+/// `mailbox_transport = lmtp:inet:host:24`). Each recipient gets its own
+/// reply after DATA. The MTA has already screened the client, so greylisting,
+/// rate limits and blocklists do not apply; bind to a loopback or private
+/// address only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LmtpConfig {
+    /// Enable LMTP server
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// LMTP server bind address
+    #[serde(default = "default_lmtp_bind")]
+    pub bind: String,
+
+    /// Enable STARTTLS
+    #[serde(default)]
+    pub starttls: bool,
+}
+
+impl Default for LmtpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_lmtp_bind(),
+            starttls: false,
+        }
+    }
+}
+
+fn default_lmtp_bind() -> String {
+    "127.0.0.1:24".to_string()
+}
+
+/// ManageSieve server configuration (RFC 5804)
+///
+/// Users log in with their IMAP credentials to upload and activate Sieve
+/// scripts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManageSieveConfig {
+    /// Enable ManageSieve server
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// ManageSieve server bind address
+    #[serde(default = "default_managesieve_bind")]
+    pub bind: String,
+
+    /// Enable STARTTLS
+    #[serde(default)]
+    pub starttls: bool,
+
+    /// Refuse AUTHENTICATE until the connection is encrypted
+    #[serde(default = "default_require_tls_for_auth")]
+    pub require_tls_for_auth: bool,
+
+    /// Session timeout in minutes
+    #[serde(default = "default_managesieve_timeout")]
+    pub timeout_minutes: i64,
+
+    /// Maximum concurrent connections
+    #[serde(default = "default_managesieve_max_connections")]
+    pub max_connections: usize,
+
+    /// Scripts a user may store
+    #[serde(default = "default_managesieve_max_scripts")]
+    pub max_scripts: usize,
+
+    /// Largest script in bytes
+    #[serde(default = "default_managesieve_max_script_size")]
+    pub max_script_size: usize,
+
+    /// Per-IP connection and authentication limits
+    #[serde(default)]
+    pub limits: ConnectionLimitConfig,
+}
+
+impl Default for ManageSieveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_managesieve_bind(),
+            starttls: false,
+            require_tls_for_auth: default_require_tls_for_auth(),
+            timeout_minutes: default_managesieve_timeout(),
+            max_connections: default_managesieve_max_connections(),
+            max_scripts: default_managesieve_max_scripts(),
+            max_script_size: default_managesieve_max_script_size(),
+            limits: ConnectionLimitConfig::default(),
+        }
+    }
+}
+
+fn default_managesieve_bind() -> String {
+    "0.0.0.0:4190".to_string()
+}
+
+fn default_managesieve_timeout() -> i64 {
+    10
+}
+
+fn default_managesieve_max_connections() -> usize {
+    100
+}
+
+fn default_managesieve_max_scripts() -> usize {
+    16
+}
+
+fn default_managesieve_max_script_size() -> usize {
+    64 * 1024
+}
+
+/// IMAP and POP3 access by tenant plan
+///
+/// Tenants on a plan listed here can only use the protocols the plan
+/// enables; tenant and user settings can switch off more of them but not
+/// switch these back on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServicesConfig {
+    /// Protocols of each plan, e.g. `[services.plans.free] pop3 = false`
+    #[serde(default)]
+    pub plans: HashMap<String, ServicesEnabled>,
+}
+
+/// Per-IP limits of the IMAP and POP3 servers
+///
+/// An address that opens too many connections within the window, or fails
+/// authentication too often, is banned for `ban_secs`. A limit of 0 is
+/// disabled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionLimitConfig {
+    /// Maximum concurrent connections from one IP address
+    #[serde(default = "default_limit_connections_per_ip")]
+    pub max_connections_per_ip: usize,
+
+    /// Maximum connections opened by one IP address within the window
+    #[serde(default = "default_limit_attempts_per_ip")]
+    pub max_attempts_per_ip: usize,
+
+    /// Failed logins from one IP address within the window that lead to a ban
+    #[serde(default = "default_limit_auth_failures_per_ip")]
+    pub max_auth_failures_per_ip: usize,
+
+    /// Window over which attempts and failed logins are counted, in seconds
+    #[serde(default = "default_limit_window_secs")]
+    pub window_secs: u64,
+
+    /// How long an IP address that exceeded a limit is refused, in seconds
+    #[serde(default = "default_limit_ban_secs")]
+    pub ban_secs: u64,
+}
+
+impl Default for ConnectionLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_connections_per_ip: default_limit_connections_per_ip(),
+            max_attempts_per_ip: default_limit_attempts_per_ip(),
+            max_auth_failures_per_ip: default_limit_auth_failures_per_ip(),
+            window_secs: default_limit_window_secs(),
+            ban_secs: default_limit_ban_secs(),
+        }
+    }
+}
+
+fn default_limit_connections_per_ip() -> usize {
+    20
+}
+
+fn default_limit_attempts_per_ip() -> usize {
+    100
+}
+
+fn default_limit_auth_failures_per_ip() -> usize {
+    10
+}
+
+fn default_limit_window_secs() -> u64 {
+    300
+}
+
+fn default_limit_ban_secs() -> u64 {
+    900
+}
+
+/// Outbound delivery queue configuration
+///
+/// Deliveries are dispatched lane by lane, and round-robin over tenants
+/// within a lane. Each turn a tenant gets as many jobs as its weight,
+/// limited by its concurrency cap, so one tenant's campaign cannot starve
+/// the mail of the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueConfig {
+    /// Maximum deliveries in progress at once
+    #[serde(default = "default_queue_max_concurrent")]
+    pub max_concurrent_deliveries: usize,
+
+    /// Maximum deliveries in progress for one tenant
+    #[serde(default = "default_queue_tenant_concurrency")]
+    pub tenant_concurrency: usize,
+
+    /// Maximum connections in progress to one destination domain
+    #[serde(default = "default_queue_domain_concurrency")]
+    pub domain_concurrency: usize,
+
+    /// Scheduling weight of tenants without an override
+    #[serde(default = "default_queue_tenant_weight")]
+    pub default_weight: u32,
+
+    /// Per-tenant weight and concurrency overrides (`[[queue.tenants]]`)
+    #[serde(default)]
+    pub tenants: Vec<TenantQueueConfig>,
+
+    /// DNS cache of destination domains (`[queue.dns]`)
+    #[serde(default)]
+    pub dns: DnsPrefetchConfig,
+
+    /// Concurrency budgets of the delivery lanes (`[queue.lanes]`)
+    #[serde(default)]
+    pub lanes: QueueLanesConfig,
+
+    /// Seconds to wait before each retry of a deferred delivery; the last
+    /// interval repeats until the message expires
+    #[serde(default = "default_queue_retry_schedule_secs")]
+    pub retry_schedule_secs: Vec<u64>,
+
+    /// Random spread of each retry interval, in percent either way
+    #[serde(default = "default_queue_retry_jitter_percent")]
+    pub retry_jitter_percent: u32,
+
+    /// Hours a message is retried before it is bounced and moved to the
+    /// dead-letter state
+    #[serde(default = "default_queue_max_lifetime_hours")]
+    pub max_lifetime_hours: u64,
+
+    /// Longest wait between scans of the queue, in seconds. Deliveries are
+    /// dispatched when they are queued or come due; the scan catches any
+    /// wakeup that was missed.
+    #[serde(default = "default_queue_poll_interval_secs")]
+    pub poll_interval_secs: u64,
 }
 
-fn default_messages_index() -> String {
-    "messages".to_string()
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_deliveries: default_queue_max_concurrent(),
+            tenant_concurrency: default_queue_tenant_concurrency(),
+            domain_concurrency: default_queue_domain_concurrency(),
+            default_weight: default_queue_tenant_weight(),
+            tenants: Vec::new(),
+            dns: DnsPrefetchConfig::default(),
+            lanes: QueueLanesConfig::default(),
+            retry_schedule_secs: default_queue_retry_schedule_secs(),
+            retry_jitter_percent: default_queue_retry_jitter_percent(),
+            max_lifetime_hours: default_queue_max_lifetime_hours(),
+            poll_interval_secs: default_queue_poll_interval_secs(),
+        }
+    }
 }
 
-/// IMAP server configuration
+impl QueueConfig {
+    fn tenant(&self, tenant_id: Option<Uuid>) -> Option<&TenantQueueConfig> {
+        let tenant_id = tenant_id?;
+        self.tenants.iter().find(|t| t.tenant_id == tenant_id)
+    }
+
+    /// Scheduling weight of a tenant (at least 1)
+    pub fn tenant_weight(&self, tenant_id: Option<Uuid>) -> u32 {
+        self.tenant(tenant_id)
+            .and_then(|t| t.weight)
+            .unwrap_or(self.default_weight)
+            .max(1)
+    }
+
+    /// Maximum deliveries in progress for a tenant (at least 1)
+    pub fn tenant_concurrency(&self, tenant_id: Option<Uuid>) -> usize {
+        self.tenant(tenant_id)
+            .and_then(|t| t.max_concurrent)
+            .unwrap_or(self.tenant_concurrency)
+            .max(1)
+    }
+
+    /// Maximum deliveries in progress in a lane (at least 1, at most
+    /// `max_concurrent_deliveries`)
+    pub fn lane_concurrency(&self, lane: DeliveryLane) -> usize {
+        let budget = match lane {
+            DeliveryLane::Transactional => self.lanes.transactional,
+            DeliveryLane::Campaign => self.lanes.campaign,
+            DeliveryLane::Bulk => self.lanes.bulk,
+        };
+        budget.min(self.max_concurrent_deliveries).max(1)
+    }
+}
+
+/// Concurrency budgets of the delivery lanes
+///
+/// Transactional mail is dispatched before campaign mail, and campaign mail
+/// before bulk mail. Keeping the campaign and bulk budgets below
+/// `max_concurrent_deliveries` leaves slots free for transactional mail
+/// however large a campaign is.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImapConfig {
-    /// Enable IMAP server
+pub struct QueueLanesConfig {
+    /// Maximum transactional deliveries in progress
+    #[serde(default = "default_queue_lane_transactional")]
+    pub transactional: usize,
+
+    /// Maximum campaign deliveries in progress
+    #[serde(default = "default_queue_lane_campaign")]
+    pub campaign: usize,
+
+    /// Maximum bulk deliveries in progress
+    #[serde(default = "default_queue_lane_bulk")]
+    pub bulk: usize,
+}
+
+impl Default for QueueLanesConfig {
+    fn default() -> Self {
+        Self {
+            transactional: default_queue_lane_transactional(),
+            campaign: default_queue_lane_campaign(),
+            bulk: default_queue_lane_bulk(),
+        }
+    }
+}
+
+/// Queue scheduling overrides for one tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantQueueConfig {
+    /// Tenant ID
+    pub tenant_id: Uuid,
+
+    /// Jobs dispatched for the tenant per round-robin turn
+    #[serde(default)]
+    pub weight: Option<u32>,
+
+    /// Maximum deliveries in progress for the tenant
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+}
+
+fn default_queue_max_concurrent() -> usize {
+    20
+}
+
+fn default_queue_tenant_concurrency() -> usize {
+    5
+}
+
+fn default_queue_domain_concurrency() -> usize {
+    3
+}
+
+fn default_queue_tenant_weight() -> u32 {
+    1
+}
+
+fn default_queue_lane_transactional() -> usize {
+    20
+}
+
+fn default_queue_lane_campaign() -> usize {
+    10
+}
+
+fn default_queue_lane_bulk() -> usize {
+    5
+}
+
+fn default_queue_retry_schedule_secs() -> Vec<u64> {
+    vec![60, 300, 900, 3600, 14400]
+}
+
+fn default_queue_retry_jitter_percent() -> u32 {
+    10
+}
+
+fn default_queue_max_lifetime_hours() -> u64 {
+    120
+}
+
+fn default_queue_poll_interval_secs() -> u64 {
+    60
+}
+
+/// DNS cache of outbound destination domains
+///
+/// MX, TLSA and MTA-STS records of the destinations are cached for their
+/// TTL. The most frequent destinations are refreshed shortly before their
+/// records expire, so deliveries to them never wait for a cold lookup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsPrefetchConfig {
+    /// Refresh the records of frequent destinations in the background
+    #[serde(default = "default_dns_prefetch")]
+    pub prefetch: bool,
+
+    /// Number of most frequent destination domains kept warm
+    #[serde(default = "default_dns_prefetch_domains")]
+    pub prefetch_domains: usize,
+
+    /// Refresh records this many seconds before they expire
+    #[serde(default = "default_dns_refresh_ahead_secs")]
+    pub refresh_ahead_secs: u64,
+
+    /// How often the prefetcher runs, in seconds
+    #[serde(default = "default_dns_prefetch_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Shortest time records are cached, whatever their TTL
+    #[serde(default = "default_dns_min_ttl_secs")]
+    pub min_ttl_secs: u64,
+
+    /// Longest time records are cached, whatever their TTL
+    #[serde(default = "default_dns_max_ttl_secs")]
+    pub max_ttl_secs: u64,
+}
+
+impl Default for DnsPrefetchConfig {
+    fn default() -> Self {
+        Self {
+            prefetch: default_dns_prefetch(),
+            prefetch_domains: default_dns_prefetch_domains(),
+            refresh_ahead_secs: default_dns_refresh_ahead_secs(),
+            interval_secs: default_dns_prefetch_interval_secs(),
+            min_ttl_secs: default_dns_min_ttl_secs(),
+            max_ttl_secs: default_dns_max_ttl_secs(),
+        }
+    }
+}
+
+fn default_dns_prefetch() -> bool {
+    true
+}
+
+fn default_dns_prefetch_domains() -> usize {
+    200
+}
+
+fn default_dns_refresh_ahead_secs() -> u64 {
+    60
+}
+
+fn default_dns_prefetch_interval_secs() -> u64 {
+    15
+}
+
+fn default_dns_min_ttl_secs() -> u64 {
+    30
+}
+
+fn default_dns_max_ttl_secs() -> u64 {
+    86400
+}
+
+/// Where journal reports are exported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalTarget {
+    /// Sent by SMTP to a journal mailbox
+    #[default]
+    Smtp,
+    /// Uploaded as `.eml` objects to an S3 bucket
+    S3,
+}
+
+/// Envelope journaling configuration
+///
+/// A copy of every inbound message and outbound delivery is wrapped in an
+/// envelope journal report (recipients and verdicts) and exported to an
+/// external archive for compliance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalConfig {
+    /// Enable journaling
     #[serde(default)]
     pub enabled: bool,
 
-    /// IMAP server bind address
-    #[serde(default = "default_imap_bind")]
-    pub bind: String,
+    /// Journal messages received for local recipients
+    #[serde(default = "default_journal_direction")]
+    pub inbound: bool,
 
-    /// Enable STARTTLS
+    /// Journal messages delivered to remote recipients
+    #[serde(default = "default_journal_direction")]
+    pub outbound: bool,
+
+    /// Archive the reports are exported to
     #[serde(default)]
-    pub starttls: bool,
+    pub target: JournalTarget,
 
-    /// Session timeout in minutes
-    #[serde(default = "default_imap_timeout")]
-    pub timeout_minutes: i64,
+    /// SMTP archive host
+    #[serde(default)]
+    pub smtp_host: Option<String>,
 
-    /// Maximum concurrent connections
-    #[serde(default = "default_imap_max_connections")]
-    pub max_connections: usize,
+    /// SMTP archive port
+    #[serde(default = "default_journal_smtp_port")]
+    pub smtp_port: u16,
+
+    /// Journal mailbox the reports are sent to
+    #[serde(default)]
+    pub recipient: Option<String>,
+
+    /// Envelope sender of the reports (default: journal@<server hostname>)
+    #[serde(default)]
+    pub sender: Option<String>,
+
+    /// S3 archive bucket
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+
+    /// Key prefix of the reports in the bucket
+    #[serde(default = "default_journal_s3_prefix")]
+    pub s3_prefix: String,
+
+    /// Export attempts before a report is marked failed
+    #[serde(default = "default_journal_max_attempts")]
+    pub max_attempts: i32,
+
+    /// Alert when more reports than this are waiting
+    #[serde(default = "default_journal_backlog_alert")]
+    pub backlog_alert_threshold: i64,
+
+    /// Alert when the oldest waiting report is older than this (minutes)
+    #[serde(default = "default_journal_backlog_alert_minutes")]
+    pub backlog_alert_minutes: i64,
+
+    /// Webhook notified of failed exports and backlog alerts
+    #[serde(default)]
+    pub alert_webhook_url: Option<String>,
+
+    /// Minimum minutes between two alerts of the same kind
+    #[serde(default = "default_journal_alert_interval")]
+    pub alert_interval_minutes: u64,
 }
 
-impl Default for ImapConfig {
+impl Default for JournalConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            bind: default_imap_bind(),
-            starttls: false,
-            timeout_minutes: default_imap_timeout(),
-            max_connections: default_imap_max_connections(),
+            inbound: default_journal_direction(),
+            outbound: default_journal_direction(),
+            target: JournalTarget::default(),
+            smtp_host: None,
+            smtp_port: default_journal_smtp_port(),
+            recipient: None,
+            sender: None,
+            s3: None,
+            s3_prefix: default_journal_s3_prefix(),
+            max_attempts: default_journal_max_attempts(),
+            backlog_alert_threshold: default_journal_backlog_alert(),
+            backlog_alert_minutes: default_journal_backlog_alert_minutes(),
+            alert_webhook_url: None,
+            alert_interval_minutes: default_journal_alert_interval(),
         }
     }
 }
 
-fn default_imap_bind() -> String {
-    "0.0.0.0:143".to_string()
+impl JournalConfig {
+    /// Whether inbound messages are journaled
+    pub fn journals_inbound(&self) -> bool {
+        self.enabled && self.inbound
+    }
+
+    /// Whether outbound deliveries are journaled
+    pub fn journals_outbound(&self) -> bool {
+        self.enabled && self.outbound
+    }
+
+    /// Check that the selected archive is configured
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        match self.target {
+            JournalTarget::Smtp => {
+                if self.smtp_host.as_deref().unwrap_or_default().is_empty() {
+                    return Err("smtp_host is required for the smtp target".to_string());
+                }
+                if !self.recipient.as_deref().is_some_and(|r| r.contains('@')) {
+                    return Err("recipient must be an email address".to_string());
+                }
+            }
+            JournalTarget::S3 => {
+                if self.s3.is_none() {
+                    return Err("[journal.s3] is required for the s3 target".to_string());
+                }
+            }
+        }
+        if self.max_attempts < 1 {
+            return Err("max_attempts must be at least 1".to_string());
+        }
+        Ok(())
+    }
 }
 
-fn default_imap_timeout() -> i64 {
-    30
+fn default_journal_direction() -> bool {
+    true
 }
 
-fn default_imap_max_connections() -> usize {
+fn default_journal_smtp_port() -> u16 {
+    25
+}
+
+fn default_journal_s3_prefix() -> String {
+    "journal/".to_string()
+}
+
+fn default_journal_max_attempts() -> i32 {
+    48
+}
+
+fn default_journal_backlog_alert() -> i64 {
     1000
 }
 
-/// POP3 server configuration
+fn default_journal_backlog_alert_minutes() -> i64 {
+    60
+}
+
+fn default_journal_alert_interval() -> u64 {
+    60
+}
+
+/// Warm standby replication configuration
+///
+/// A standby server follows a primary through PostgreSQL logical
+/// replication (`mairust replication publish` on the primary,
+/// `mairust replication subscribe` on the standby) and copies the message
+/// files it replicates from the primary's API. It serves nothing but the
+/// API until `mairust replication promote` makes it a primary.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Pop3Config {
-    /// Enable POP3 server
+pub struct ReplicationConfig {
+    /// Enable replication (heartbeats on the primary, file sync on the
+    /// standby)
     #[serde(default)]
     pub enabled: bool,
 
-    /// POP3 server bind address
-    #[serde(default = "default_pop3_bind")]
-    pub bind: String,
+    /// Publication of the primary's tables
+    #[serde(default = "default_replication_publication")]
+    pub publication: String,
 
-    /// Enable STARTTLS
+    /// Subscription of the standby to the publication
+    #[serde(default = "default_replication_subscription")]
+    pub subscription: String,
+
+    /// Seconds between heartbeats of the primary, from which the standby's
+    /// lag is measured
+    #[serde(default = "default_replication_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+
+    /// Lag (seconds) above which the standby reports itself unhealthy
+    #[serde(default = "default_replication_max_lag_secs")]
+    pub max_lag_secs: u64,
+
+    /// API of the primary the standby copies message files from, e.g.
+    /// "https://mail.example.com:8080"
     #[serde(default)]
-    pub starttls: bool,
+    pub primary_api_url: Option<String>,
 
-    /// Session timeout in minutes
-    #[serde(default = "default_pop3_timeout")]
-    pub timeout_minutes: i64,
+    /// API key with the `admin:system` scope on the primary
+    #[serde(default)]
+    pub primary_api_key: Option<String>,
 
-    /// Maximum concurrent connections
-    #[serde(default = "default_pop3_max_connections")]
-    pub max_connections: usize,
+    /// Seconds between two file sync runs of the standby
+    #[serde(default = "default_replication_sync_interval_secs")]
+    pub sync_interval_secs: u64,
+
+    /// Messages looked at per batch of a file sync run
+    #[serde(default = "default_replication_sync_batch_size")]
+    pub sync_batch_size: i64,
+
+    /// Compare the checksums of files the standby already has with the
+    /// primary's when it starts, and copy them again when they differ
+    #[serde(default)]
+    pub verify_existing: bool,
 }
 
-impl Default for Pop3Config {
+impl Default for ReplicationConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            bind: default_pop3_bind(),
-            starttls: false,
-            timeout_minutes: default_pop3_timeout(),
-            max_connections: default_pop3_max_connections(),
+            publication: default_replication_publication(),
+            subscription: default_replication_subscription(),
+            heartbeat_secs: default_replication_heartbeat_secs(),
+            max_lag_secs: default_replication_max_lag_secs(),
+            primary_api_url: None,
+            primary_api_key: None,
+            sync_interval_secs: default_replication_sync_interval_secs(),
+            sync_batch_size: default_replication_sync_batch_size(),
+            verify_existing: false,
         }
     }
 }
 
-fn default_pop3_bind() -> String {
-    "0.0.0.0:110".to_string()
+impl ReplicationConfig {
+    /// Check the publication and subscription names and the intervals
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        for (setting, name) in [
+            ("publication", &self.publication),
+            ("subscription", &self.subscription),
+        ] {
+            let valid = name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+                && name.len() <= 63
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if !valid {
+                return Err(format!(
+                    "{} must be a lowercase identifier, not '{}'",
+                    setting, name
+                ));
+            }
+        }
+        if self.heartbeat_secs == 0 || self.sync_interval_secs == 0 {
+            return Err("heartbeat_secs and sync_interval_secs must be at least 1".to_string());
+        }
+        if self.sync_batch_size < 1 {
+            return Err("sync_batch_size must be at least 1".to_string());
+        }
+        Ok(())
+    }
 }
 
-fn default_pop3_timeout() -> i64 {
+fn default_replication_publication() -> String {
+    "mairust".to_string()
+}
+
+fn default_replication_subscription() -> String {
+    "mairust_standby".to_string()
+}
+
+fn default_replication_heartbeat_secs() -> u64 {
     10
 }
 
-fn default_pop3_max_connections() -> usize {
+fn default_replication_max_lag_secs() -> u64 {
+    300
+}
+
+fn default_replication_sync_interval_secs() -> u64 {
+    30
+}
+
+fn default_replication_sync_batch_size() -> i64 {
     500
 }
 
@@ -497,28 +2471,147 @@ pub struct WebConfig {
     #[serde(default = "default_web_api_url")]
     pub api_url: String,
 
-    /// Enable debug mode
-    #[serde(default)]
-    pub debug: bool,
+    /// Enable debug mode
+    #[serde(default)]
+    pub debug: bool,
+
+    /// Path that receives TLS-RPT reports on the MTA-STS policy hosts
+    /// (`mta-sts.<domain>`)
+    #[serde(default = "default_web_tls_report_path")]
+    pub tls_report_path: String,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_web_bind(),
+            api_url: default_web_api_url(),
+            debug: false,
+            tls_report_path: default_web_tls_report_path(),
+        }
+    }
+}
+
+fn default_web_bind() -> String {
+    "0.0.0.0:8081".to_string()
+}
+
+fn default_web_api_url() -> String {
+    "/api/v1".to_string()
+}
+
+fn default_web_tls_report_path() -> String {
+    "/tlsrpt".to_string()
+}
+
+/// Tracked links of campaign mail
+///
+/// Links in the HTML of campaign mail are rewritten to `<base>/c/<token>`
+/// on the web server, which counts the click and redirects to the original
+/// link. A tenant can add tracking domains (e.g. `links.example.com`) with a
+/// CNAME to `cname_target`. Campaigns sent from a domain with the same
+/// organizational domain use them for their links, so the links match the
+/// SPF and DKIM aligned sender. Tracking domains are served over HTTPS on
+/// `https_bind` with certificates requested over ACME; the HTTP-01
+/// challenges are answered by the web server, which must be reachable on
+/// port 80 of the tracking domains.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackingConfig {
+    /// Rewrite the links of campaign mail
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Keys of the link signatures. The first one signs new links and all
+    /// are accepted, so a key is rotated by adding the new one first.
+    #[serde(default)]
+    pub secrets: Vec<String>,
+
+    /// Base URL of the links of tenants without an active tracking domain
+    /// (e.g. "https://mail.example.com"); their links are not rewritten
+    /// when unset
+    pub default_url: Option<String>,
+
+    /// Host name the CNAME of tracking domains must point at (default: the
+    /// server hostname)
+    pub cname_target: Option<String>,
+
+    /// HTTPS listener of tracking domains (e.g. "0.0.0.0:443")
+    pub https_bind: Option<String>,
+
+    /// Certificates of tracking domains
+    #[serde(default)]
+    pub acme: AcmeConfig,
+}
+
+impl TrackingConfig {
+    /// Host name tracking domains must have a CNAME to
+    pub fn cname_target<'a>(&'a self, hostname: &'a str) -> &'a str {
+        self.cname_target.as_deref().unwrap_or(hostname)
+    }
+
+    /// Check that links can be signed and certificates served
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.secrets.iter().all(|s| s.is_empty()) {
+            return Err("secrets are required to sign tracked links".to_string());
+        }
+        if self.acme.enabled {
+            if self.https_bind.is_none() {
+                return Err("https_bind is required to serve ACME certificates".to_string());
+            }
+            if self.acme.renew_after_days < 1 {
+                return Err("acme.renew_after_days must be at least 1".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ACME client of tracking domain certificates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    /// Request certificates of verified tracking domains
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Directory of the certificate authority
+    #[serde(default = "default_acme_directory_url")]
+    pub directory_url: String,
+
+    /// Contact address of the ACME account
+    pub contact_email: Option<String>,
+
+    /// Days after which a certificate is renewed
+    #[serde(default = "default_acme_renew_after_days")]
+    pub renew_after_days: i64,
+
+    /// Seconds between checks of tracking domains
+    #[serde(default = "default_acme_check_interval")]
+    pub check_interval_secs: u64,
 }
 
-impl Default for WebConfig {
+impl Default for AcmeConfig {
     fn default() -> Self {
         Self {
             enabled: false,
-            bind: default_web_bind(),
-            api_url: default_web_api_url(),
-            debug: false,
+            directory_url: default_acme_directory_url(),
+            contact_email: None,
+            renew_after_days: default_acme_renew_after_days(),
+            check_interval_secs: default_acme_check_interval(),
         }
     }
 }
 
-fn default_web_bind() -> String {
-    "0.0.0.0:8081".to_string()
+fn default_acme_directory_url() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
 }
 
-fn default_web_api_url() -> String {
-    "/api/v1".to_string()
+fn default_acme_renew_after_days() -> i64 {
+    60
+}
+
+fn default_acme_check_interval() -> u64 {
+    3600
 }
 
 /// Plugin system configuration
@@ -572,7 +2665,253 @@ fn default_enable_categorizer() -> bool {
     true
 }
 
+/// Protocol served by a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerProtocol {
+    /// Inbound mail reception
+    Smtp,
+    /// Mail submission by authenticated clients
+    Submission,
+    /// Local delivery from another MTA
+    Lmtp,
+    /// IMAP mailbox access
+    Imap,
+    /// POP3 mailbox access
+    Pop3,
+    /// ManageSieve script management
+    ManageSieve,
+    /// REST API
+    Api,
+}
+
+impl ListenerProtocol {
+    /// Name used in configuration and logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListenerProtocol::Smtp => "smtp",
+            ListenerProtocol::Submission => "submission",
+            ListenerProtocol::Lmtp => "lmtp",
+            ListenerProtocol::Imap => "imap",
+            ListenerProtocol::Pop3 => "pop3",
+            ListenerProtocol::ManageSieve => "managesieve",
+            ListenerProtocol::Api => "api",
+        }
+    }
+
+    /// Whether the protocol is served by the SMTP server
+    pub fn is_smtp(&self) -> bool {
+        matches!(
+            self,
+            ListenerProtocol::Smtp | ListenerProtocol::Submission | ListenerProtocol::Lmtp
+        )
+    }
+}
+
+/// How a listener negotiates TLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerTls {
+    /// Plaintext only
+    None,
+    /// Plaintext with an in-band upgrade (STARTTLS/STLS)
+    Starttls,
+    /// TLS handshake on connect (e.g. 465, 993)
+    Implicit,
+}
+
+/// A network listener
+///
+/// When at least one `[[listener]]` is configured, the listeners replace the
+/// fixed ports of `[smtp]`, `[lmtp]`, `[imap]`, `[pop3]`, `[managesieve]` and
+/// `[api]`, so the same protocol can be offered on several addresses with
+/// different policies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    /// Name used in logs (defaults to "protocol bind")
+    pub name: Option<String>,
+
+    /// Protocol served on this listener
+    pub protocol: ListenerProtocol,
+
+    /// Bind address and port, e.g. "0.0.0.0:465"
+    pub bind: String,
+
+    /// TLS mode (defaults to "starttls", or "none" for the API)
+    pub tls: Option<ListenerTls>,
+
+    /// Expect a PROXY protocol header (v1 or v2) from a load balancer in
+    /// `server.trusted_proxies`; other peers are refused
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// Require AUTH before MAIL FROM (SMTP protocols only; submission
+    /// defaults to true, smtp to `smtp.auth_required`)
+    pub auth_required: Option<bool>,
+
+    /// Let authenticated clients relay to remote domains (SMTP protocols
+    /// only; defaults to `smtp.allow_relay`)
+    pub relay: Option<bool>,
+}
+
+impl ListenerConfig {
+    /// Listener with default policies for a protocol
+    pub fn new(protocol: ListenerProtocol, bind: impl Into<String>) -> Self {
+        Self {
+            name: None,
+            protocol,
+            bind: bind.into(),
+            tls: None,
+            proxy_protocol: false,
+            auth_required: None,
+            relay: None,
+        }
+    }
+
+    /// Effective TLS mode
+    pub fn tls_mode(&self) -> ListenerTls {
+        match (self.tls, self.protocol) {
+            (Some(tls), _) => tls,
+            (None, ListenerProtocol::Api) => ListenerTls::None,
+            (None, _) => ListenerTls::Starttls,
+        }
+    }
+
+    /// Name used in logs
+    pub fn label(&self) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => format!("{} {}", self.protocol.as_str(), self.bind),
+        }
+    }
+
+    /// Check that the listener's options apply to its protocol
+    fn validate(&self) -> Result<(), String> {
+        let port = self
+            .bind
+            .rsplit_once(':')
+            .map(|(_, port)| port.parse::<u16>());
+        if !matches!(port, Some(Ok(_))) {
+            return Err(format!("bind address '{}' has no valid port", self.bind));
+        }
+        let smtp_options = matches!(
+            self.protocol,
+            ListenerProtocol::Smtp | ListenerProtocol::Submission
+        );
+        if !smtp_options && (self.auth_required.is_some() || self.relay.is_some()) {
+            return Err(format!(
+                "auth_required and relay only apply to smtp and submission listeners, not {}",
+                self.protocol.as_str()
+            ));
+        }
+        if self.proxy_protocol && self.protocol == ListenerProtocol::Api {
+            return Err("API listeners do not support the PROXY protocol".into());
+        }
+        match (self.protocol, self.tls_mode()) {
+            (ListenerProtocol::Api, ListenerTls::None) => Ok(()),
+            (ListenerProtocol::Api, _) => {
+                Err("API listeners do not support TLS; terminate TLS in a reverse proxy".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 impl Config {
+    /// Listeners to start: the `[[listener]]` entries, or the fixed
+    /// listeners derived from the per-protocol sections when none are set
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+
+        let mut listeners = self.smtp.default_listeners();
+        if self.lmtp.enabled {
+            listeners.push(ListenerConfig {
+                tls: Some(if self.lmtp.starttls {
+                    ListenerTls::Starttls
+                } else {
+                    ListenerTls::None
+                }),
+                ..ListenerConfig::new(ListenerProtocol::Lmtp, self.lmtp.bind.clone())
+            });
+        }
+        if self.imap.enabled {
+            listeners.push(ListenerConfig {
+                tls: Some(if self.imap.starttls {
+                    ListenerTls::Starttls
+                } else {
+                    ListenerTls::None
+                }),
+                ..ListenerConfig::new(ListenerProtocol::Imap, self.imap.bind.clone())
+            });
+            if let Some(ref tls_bind) = self.imap.tls_bind {
+                listeners.push(ListenerConfig {
+                    tls: Some(ListenerTls::Implicit),
+                    ..ListenerConfig::new(ListenerProtocol::Imap, tls_bind.clone())
+                });
+            }
+        }
+        if self.pop3.enabled {
+            listeners.push(ListenerConfig {
+                tls: Some(if self.pop3.starttls {
+                    ListenerTls::Starttls
+                } else {
+                    ListenerTls::None
+                }),
+                ..ListenerConfig::new(ListenerProtocol::Pop3, self.pop3.bind.clone())
+            });
+            if let Some(ref tls_bind) = self.pop3.tls_bind {
+                listeners.push(ListenerConfig {
+                    tls: Some(ListenerTls::Implicit),
+                    ..ListenerConfig::new(ListenerProtocol::Pop3, tls_bind.clone())
+                });
+            }
+        }
+        if self.managesieve.enabled {
+            listeners.push(ListenerConfig {
+                tls: Some(if self.managesieve.starttls {
+                    ListenerTls::Starttls
+                } else {
+                    ListenerTls::None
+                }),
+                ..ListenerConfig::new(ListenerProtocol::ManageSieve, self.managesieve.bind.clone())
+            });
+        }
+        listeners.push(ListenerConfig::new(
+            ListenerProtocol::Api,
+            format!("0.0.0.0:{}", self.api.port),
+        ));
+        listeners
+    }
+
+    /// Validate the `[[listener]]` entries
+    pub fn validate_listeners(&self) -> crate::Result<()> {
+        let mut binds = std::collections::HashSet::new();
+        for listener in &self.listeners {
+            listener.validate().map_err(|e| {
+                crate::Error::Config(format!("Invalid listener '{}': {}", listener.label(), e))
+            })?;
+            if !binds.insert(listener.bind.as_str()) {
+                return Err(crate::Error::Config(format!(
+                    "Duplicate listener bind address '{}'",
+                    listener.bind
+                )));
+            }
+            if listener.proxy_protocol && self.server.trusted_proxies.is_empty() {
+                return Err(crate::Error::Config(format!(
+                    "Listener '{}' expects PROXY protocol headers but server.trusted_proxies is empty",
+                    listener.label()
+                )));
+            }
+        }
+        for network in &self.server.trusted_proxies {
+            validate_network(network)
+                .map_err(|e| crate::Error::Config(format!("Invalid trusted proxy: {}", e)))?;
+        }
+        Ok(())
+    }
+
     /// Load configuration from file
     pub fn from_file(path: &std::path::Path) -> crate::Result<Self> {
         let content = std::fs::read_to_string(path)
@@ -580,29 +2919,45 @@ impl Config {
 
         let config: Config = toml::from_str(&content)
             .map_err(|e| crate::Error::Config(format!("Failed to parse config: {}", e)))?;
+        config.validate()?;
 
         Ok(config)
     }
 
-    /// Load configuration from environment and file
-    pub fn load() -> crate::Result<Self> {
-        // Try to load from default locations
-        let paths = [
-            std::path::PathBuf::from("./config.yaml"),
-            std::path::PathBuf::from("./config.toml"),
-            std::path::PathBuf::from("/etc/mairust/config.yaml"),
-            std::path::PathBuf::from("/etc/mairust/config.toml"),
-        ];
-
-        for path in paths {
-            if path.exists() {
-                return Self::from_file(&path);
-            }
+    /// Check the settings that cannot be checked while parsing
+    pub fn validate(&self) -> crate::Result<()> {
+        self.validate_listeners()?;
+        self.journal
+            .validate()
+            .map_err(|e| crate::Error::Config(format!("Invalid journal configuration: {}", e)))?;
+        self.storage.layout.validate().map_err(|e| {
+            crate::Error::Config(format!("Invalid storage layout configuration: {}", e))
+        })?;
+        self.residency
+            .validate()
+            .map_err(|e| crate::Error::Config(format!("Invalid residency configuration: {}", e)))?;
+        self.replication.validate().map_err(|e| {
+            crate::Error::Config(format!("Invalid replication configuration: {}", e))
+        })?;
+        for milter in &self.smtp.milters {
+            milter.validate().map_err(|e| {
+                crate::Error::Config(format!("Invalid milter '{}': {}", milter.name, e))
+            })?;
         }
+        self.smtp
+            .antivirus
+            .validate()
+            .map_err(|e| crate::Error::Config(format!("Invalid antivirus configuration: {}", e)))?;
+        self.tracking
+            .validate()
+            .map_err(|e| crate::Error::Config(format!("Invalid tracking configuration: {}", e)))?;
+        Ok(())
+    }
 
-        Err(crate::Error::Config(
-            "No configuration file found".to_string(),
-        ))
+    /// Load configuration from the file, profile and environment variables
+    /// (see [`ConfigLoader`](crate::config_loader::ConfigLoader))
+    pub fn load() -> crate::Result<Self> {
+        crate::config_loader::ConfigLoader::from_env().load()
     }
 }
 
@@ -645,4 +3000,844 @@ submission_port = 587
         assert_eq!(config.database.backend, "postgres");
         assert_eq!(config.smtp.port, 25);
     }
+
+    #[test]
+    fn test_smtp_listener_identity() {
+        let toml = r#"
+hostname = "mx.example.com"
+
+[[listener_identities]]
+service = "submission"
+hostname = "submit.example.com"
+
+[[listener_identities]]
+local_ip = "192.0.2.10"
+hostname = "mx1.tenant-a.example"
+banner = "ESMTP ready"
+
+[[outbound_pools]]
+name = "tenant-a"
+source_ips = ["192.0.2.10"]
+helo_hostname = "out1.tenant-a.example"
+domains = ["tenant-a.example"]
+"#;
+
+        let smtp: SmtpConfig = toml::from_str(toml).unwrap();
+        assert_eq!(
+            smtp.listener_identity("smtp", None),
+            ("mx.example.com".to_string(), "ESMTP MaiRust".to_string())
+        );
+        assert_eq!(
+            smtp.listener_identity("submission", Some("198.51.100.1")).0,
+            "submit.example.com"
+        );
+        assert_eq!(
+            smtp.listener_identity("submission", Some("192.0.2.10")),
+            ("mx1.tenant-a.example".to_string(), "ESMTP ready".to_string())
+        );
+        assert_eq!(smtp.outbound_helo("Tenant-A.example"), "out1.tenant-a.example");
+        assert_eq!(smtp.outbound_helo("other.example"), "mx.example.com");
+    }
+
+    #[test]
+    fn test_default_listeners() {
+        let toml = r#"
+[database]
+url = "postgres://localhost/mairust"
+
+[imap]
+enabled = true
+starttls = true
+tls_bind = "0.0.0.0:993"
+
+[pop3]
+enabled = true
+tls_bind = "0.0.0.0:995"
+
+[managesieve]
+enabled = true
+starttls = true
+"#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        let listeners = config.listeners();
+        let summary: Vec<_> = listeners
+            .iter()
+            .map(|l| (l.protocol, l.bind.as_str(), l.tls_mode()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ListenerProtocol::Smtp, "0.0.0.0:25", ListenerTls::Starttls),
+                (
+                    ListenerProtocol::Submission,
+                    "0.0.0.0:587",
+                    ListenerTls::Starttls
+                ),
+                (ListenerProtocol::Imap, "0.0.0.0:143", ListenerTls::Starttls),
+                (ListenerProtocol::Imap, "0.0.0.0:993", ListenerTls::Implicit),
+                (ListenerProtocol::Pop3, "0.0.0.0:110", ListenerTls::None),
+                (ListenerProtocol::Pop3, "0.0.0.0:995", ListenerTls::Implicit),
+                (
+                    ListenerProtocol::ManageSieve,
+                    "0.0.0.0:4190",
+                    ListenerTls::Starttls
+                ),
+                (ListenerProtocol::Api, "0.0.0.0:8080", ListenerTls::None),
+            ]
+        );
+        assert_eq!(listeners[0].auth_required, Some(false));
+        assert_eq!(listeners[1].auth_required, Some(true));
+    }
+
+    #[test]
+    fn test_parse_listeners() {
+        let toml = r#"
+[server]
+trusted_proxies = ["10.0.0.0/8", "fd00::1"]
+
+[database]
+url = "postgres://localhost/mairust"
+
+[[listener]]
+protocol = "submission"
+bind = "0.0.0.0:587"
+
+[[listener]]
+name = "partner-relay"
+protocol = "submission"
+bind = "10.0.0.5:2525"
+tls = "none"
+auth_required = false
+relay = true
+
+[[listener]]
+protocol = "smtp"
+bind = "0.0.0.0:465"
+tls = "implicit"
+proxy_protocol = true
+"#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate_listeners().unwrap();
+        let listeners = config.listeners();
+        assert_eq!(listeners.len(), 3);
+        assert_eq!(listeners[0].label(), "submission 0.0.0.0:587");
+        assert_eq!(listeners[0].tls_mode(), ListenerTls::Starttls);
+        assert_eq!(listeners[1].label(), "partner-relay");
+        assert_eq!(listeners[1].tls_mode(), ListenerTls::None);
+        assert_eq!(listeners[1].relay, Some(true));
+        assert_eq!(listeners[2].tls_mode(), ListenerTls::Implicit);
+        assert!(listeners[2].proxy_protocol);
+    }
+
+    #[test]
+    fn test_validate_listeners() {
+        let invalid = [
+            ListenerConfig::new(ListenerProtocol::Smtp, "0.0.0.0"),
+            ListenerConfig {
+                relay: Some(true),
+                ..ListenerConfig::new(ListenerProtocol::Imap, "0.0.0.0:143")
+            },
+            ListenerConfig {
+                tls: Some(ListenerTls::Implicit),
+                ..ListenerConfig::new(ListenerProtocol::Api, "0.0.0.0:8443")
+            },
+            ListenerConfig {
+                proxy_protocol: true,
+                ..ListenerConfig::new(ListenerProtocol::Api, "0.0.0.0:8080")
+            },
+        ];
+        for listener in invalid {
+            assert!(listener.validate().is_err(), "{:?}", listener);
+        }
+
+        let mut config: Config = toml::from_str(
+            r#"
+[database]
+url = "postgres://localhost/mairust"
+"#,
+        )
+        .unwrap();
+        config.listeners = vec![
+            ListenerConfig::new(ListenerProtocol::Smtp, "0.0.0.0:25"),
+            ListenerConfig::new(ListenerProtocol::Submission, "0.0.0.0:25"),
+        ];
+        assert!(config.validate_listeners().is_err());
+
+        // PROXY protocol listeners need trusted proxies
+        config.listeners = vec![ListenerConfig {
+            proxy_protocol: true,
+            ..ListenerConfig::new(ListenerProtocol::Imap, "0.0.0.0:143")
+        }];
+        assert!(config.validate_listeners().is_err());
+        config.server.trusted_proxies = vec!["10.0.0.0/33".to_string()];
+        assert!(config.validate_listeners().is_err());
+        config.server.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+        config.validate_listeners().unwrap();
+    }
+
+    #[test]
+    fn test_api_public_base_url() {
+        let mut api = ApiConfig::default();
+        assert_eq!(
+            api.public_base_url("mail.example.com"),
+            "http://mail.example.com:8080"
+        );
+
+        api.public_url = Some("https://mail.example.com/".to_string());
+        assert_eq!(api.public_base_url("localhost"), "https://mail.example.com");
+    }
+
+    #[test]
+    fn test_greylist_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.greylisting.enabled);
+        assert_eq!(smtp.greylisting.delay_secs, 300);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[greylisting]
+enabled = true
+delay_secs = 60
+auto_whitelist_after = 0
+"#,
+        )
+        .unwrap();
+        assert!(smtp.greylisting.enabled);
+        assert_eq!(smtp.greylisting.delay_secs, 60);
+        assert_eq!(smtp.greylisting.retry_window_secs, 4 * 3600);
+        assert_eq!(smtp.greylisting.auto_whitelist_after, 0);
+    }
+
+    #[test]
+    fn test_smtp_rate_limit_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.rate_limits.enabled);
+        assert_eq!(smtp.rate_limits.connections_per_ip, 60);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[rate_limits]
+enabled = true
+messages_per_user = 20
+recipients_per_ip = 0
+"#,
+        )
+        .unwrap();
+        assert!(smtp.rate_limits.enabled);
+        assert_eq!(smtp.rate_limits.messages_per_user, 20);
+        assert_eq!(smtp.rate_limits.recipients_per_ip, 0);
+        assert_eq!(smtp.rate_limits.messages_per_ip, 30);
+    }
+
+    #[test]
+    fn test_dnsbl_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.dnsbl.enabled);
+        assert_eq!(smtp.dnsbl.lists.len(), 1);
+        assert_eq!(smtp.dnsbl.lists[0].zone, "zen.spamhaus.org");
+        assert_eq!(smtp.dnsbl.reject_score, 0.0);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[dnsbl]
+enabled = true
+reject_score = 10.0
+
+[[dnsbl.lists]]
+zone = "zen.spamhaus.org"
+score = 10.0
+codes = ["127.0.0.2", "127.0.0.4"]
+
+[[dnsbl.lists]]
+zone = "bl.spamcop.net"
+"#,
+        )
+        .unwrap();
+        assert!(smtp.dnsbl.enabled);
+        assert_eq!(smtp.dnsbl.reject_score, 10.0);
+        assert_eq!(smtp.dnsbl.lists.len(), 2);
+        assert_eq!(smtp.dnsbl.lists[0].codes, vec!["127.0.0.2", "127.0.0.4"]);
+        assert_eq!(smtp.dnsbl.lists[1].score, 5.0);
+        assert!(smtp.dnsbl.lists[1].codes.is_empty());
+        assert_eq!(smtp.dnsbl.cache_secs, 900);
+    }
+
+    #[test]
+    fn test_uribl_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.uribl.enabled);
+        assert_eq!(smtp.uribl.lists.len(), 1);
+        assert_eq!(smtp.uribl.lists[0].zone, "multi.surbl.org");
+        assert_eq!(smtp.uribl.bad_domain_score, 5.0);
+        assert_eq!(smtp.uribl.max_domains, 20);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[uribl]
+enabled = true
+bad_domain_score = 8.0
+
+[[uribl.lists]]
+zone = "multi.uribl.com"
+score = 4.0
+codes = ["127.0.0.2"]
+"#,
+        )
+        .unwrap();
+        assert!(smtp.uribl.enabled);
+        assert_eq!(smtp.uribl.bad_domain_score, 8.0);
+        assert_eq!(smtp.uribl.lists.len(), 1);
+        assert_eq!(smtp.uribl.lists[0].score, 4.0);
+        assert_eq!(smtp.uribl.lists[0].codes, vec!["127.0.0.2"]);
+        assert_eq!(smtp.uribl.timeout_secs, 3);
+    }
+
+    #[test]
+    fn test_arc_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(smtp.arc.trusted_sealers.is_empty());
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[arc]
+trusted_sealers = ["google.com", "lists.example.org"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            smtp.arc.trusted_sealers,
+            vec!["google.com", "lists.example.org"]
+        );
+    }
+
+    #[test]
+    fn test_srs_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.srs.enabled);
+        assert_eq!(smtp.srs.max_age_days, 21);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[srs]
+enabled = true
+domain = "srs.example.com"
+secrets = ["new", "old"]
+"#,
+        )
+        .unwrap();
+        assert!(smtp.srs.enabled);
+        assert_eq!(smtp.srs.domain.as_deref(), Some("srs.example.com"));
+        assert_eq!(smtp.srs.secrets, vec!["new", "old"]);
+        assert_eq!(smtp.srs.max_age_days, 21);
+    }
+
+    #[test]
+    fn test_verp_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(smtp.verp.secrets.is_empty());
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[verp]
+secrets = ["new", "old"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(smtp.verp.secrets, vec!["new", "old"]);
+    }
+
+    #[test]
+    fn test_tracking_config() {
+        let tracking: TrackingConfig = toml::from_str("").unwrap();
+        assert!(!tracking.enabled);
+        assert!(!tracking.acme.enabled);
+        assert_eq!(tracking.acme.renew_after_days, 60);
+        assert_eq!(tracking.cname_target("mail.example.com"), "mail.example.com");
+        assert!(tracking.validate().is_ok());
+
+        let mut tracking: TrackingConfig = toml::from_str(
+            r#"
+enabled = true
+default_url = "https://mail.example.com"
+cname_target = "links.mail.example.com"
+
+[acme]
+enabled = true
+contact_email = "postmaster@example.com"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tracking.cname_target("mail.example.com"),
+            "links.mail.example.com"
+        );
+        assert!(tracking.validate().unwrap_err().contains("secrets"));
+        tracking.secrets = vec!["secret".to_string()];
+        assert!(tracking.validate().unwrap_err().contains("https_bind"));
+        tracking.https_bind = Some("0.0.0.0:443".to_string());
+        assert!(tracking.validate().is_ok());
+    }
+
+    #[test]
+    fn test_dmarc_report_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.dmarc_reports.enabled);
+        assert_eq!(smtp.dmarc_reports.compression, DmarcReportCompression::Gzip);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[dmarc_reports]
+enabled = true
+org_name = "Example Mail"
+from_address = "dmarc@example.com"
+compression = "zip"
+"#,
+        )
+        .unwrap();
+        assert!(smtp.dmarc_reports.enabled);
+        assert_eq!(smtp.dmarc_reports.org_name.as_deref(), Some("Example Mail"));
+        assert_eq!(
+            smtp.dmarc_reports.from_address.as_deref(),
+            Some("dmarc@example.com")
+        );
+        assert!(smtp.dmarc_reports.contact_info.is_none());
+        assert_eq!(smtp.dmarc_reports.compression, DmarcReportCompression::Zip);
+    }
+
+    #[test]
+    fn test_bimi_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.bimi.enabled);
+        assert!(smtp.bimi.vmc_roots.is_none());
+        assert_eq!(smtp.bimi.cache_secs, 3600);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[bimi]
+enabled = true
+vmc_roots = "/etc/mairust/vmc-roots.pem"
+require_vmc = true
+"#,
+        )
+        .unwrap();
+        assert!(smtp.bimi.enabled);
+        assert_eq!(
+            smtp.bimi.vmc_roots.as_deref(),
+            Some("/etc/mairust/vmc-roots.pem")
+        );
+        assert!(smtp.bimi.require_vmc);
+        assert_eq!(smtp.bimi.cache_secs, 3600);
+    }
+
+    #[test]
+    fn test_dkim_rotation_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.dkim_rotation.enabled);
+        assert_eq!(smtp.dkim_rotation.interval_days, 180);
+        assert_eq!(smtp.dkim_rotation.algorithms, vec!["rsa-sha256"]);
+        assert!(smtp.dkim_rotation.check_dns);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[dkim_rotation]
+enabled = true
+interval_days = 90
+algorithms = ["rsa-sha256", "ed25519-sha256"]
+check_dns = false
+"#,
+        )
+        .unwrap();
+        assert!(smtp.dkim_rotation.enabled);
+        assert_eq!(smtp.dkim_rotation.interval_days, 90);
+        assert_eq!(smtp.dkim_rotation.publish_days, 7);
+        assert_eq!(
+            smtp.dkim_rotation.algorithms,
+            vec!["rsa-sha256", "ed25519-sha256"]
+        );
+        assert_eq!(smtp.dkim_rotation.rsa_bits, 2048);
+        assert!(!smtp.dkim_rotation.check_dns);
+    }
+
+    #[test]
+    fn test_spf_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.spf.reject_fail);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[spf]
+reject_fail = true
+"#,
+        )
+        .unwrap();
+        assert!(smtp.spf.reject_fail);
+    }
+
+    #[test]
+    fn test_milter_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(smtp.milters.is_empty());
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[[milters]]
+name = "rspamd"
+address = "127.0.0.1:11332"
+
+[[milters]]
+name = "clamav"
+address = "unix:/run/clamav/clamav-milter.ctl"
+fail_open = false
+timeout_secs = 60
+"#,
+        )
+        .unwrap();
+        assert_eq!(smtp.milters.len(), 2);
+        assert!(smtp.milters[0].fail_open);
+        assert_eq!(smtp.milters[0].connect_timeout_secs, 5);
+        assert_eq!(smtp.milters[0].timeout_secs, 30);
+        assert!(!smtp.milters[1].fail_open);
+        assert_eq!(smtp.milters[1].timeout_secs, 60);
+        assert!(smtp.milters.iter().all(|m| m.validate().is_ok()));
+
+        let mut milter = smtp.milters[0].clone();
+        milter.address = "localhost".to_string();
+        assert!(milter.validate().is_err());
+        milter.address = "unix:clamav.ctl".to_string();
+        assert!(milter.validate().is_err());
+    }
+
+    #[test]
+    fn test_antivirus_config() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert!(!smtp.antivirus.enabled);
+        assert_eq!(smtp.antivirus.address, "127.0.0.1:3310");
+        assert_eq!(smtp.antivirus.action, AntivirusAction::Reject);
+        assert!(smtp.antivirus.fail_open);
+        assert_eq!(smtp.antivirus.timeout_secs, 30);
+        assert_eq!(smtp.antivirus.max_size, 25 * 1024 * 1024);
+
+        let smtp: SmtpConfig = toml::from_str(
+            r#"
+[antivirus]
+enabled = true
+address = "unix:/run/clamav/clamd.ctl"
+action = "quarantine"
+fail_open = false
+"#,
+        )
+        .unwrap();
+        assert!(smtp.antivirus.enabled);
+        assert_eq!(smtp.antivirus.action, AntivirusAction::Quarantine);
+        assert!(!smtp.antivirus.fail_open);
+        assert!(smtp.antivirus.validate().is_ok());
+
+        let mut antivirus = smtp.antivirus.clone();
+        antivirus.address = "clamd".to_string();
+        assert!(antivirus.validate().is_err());
+    }
+
+    #[test]
+    fn test_recipient_delimiter() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert_eq!(smtp.recipient_delimiter, "+");
+
+        let smtp: SmtpConfig = toml::from_str("recipient_delimiter = \"\"").unwrap();
+        assert_eq!(smtp.recipient_delimiter, "");
+    }
+
+    #[test]
+    fn test_services_plans() {
+        let toml = r#"
+[plans.free]
+pop3 = false
+"#;
+        let services: ServicesConfig = toml::from_str(toml).unwrap();
+        let free = services.plans["free"];
+        assert!(free.imap);
+        assert!(!free.pop3);
+        assert!(ServicesConfig::default().plans.is_empty());
+    }
+
+    #[test]
+    fn test_residency_config() {
+        let residency = ResidencyConfig::default();
+        assert!(!residency.is_enabled());
+        assert!(residency.validate().is_ok());
+
+        let toml = r#"
+region = "eu"
+
+[regions.eu.storage]
+path = "/var/lib/mairust/eu"
+
+[regions.us.storage]
+path = "/var/lib/mairust/us"
+"#;
+        let mut residency: ResidencyConfig = toml::from_str(toml).unwrap();
+        assert!(residency.is_enabled());
+        assert!(residency.validate().is_ok());
+        assert_eq!(
+            residency.regions["us"].storage.path,
+            PathBuf::from("/var/lib/mairust/us")
+        );
+
+        residency.region = Some("ap".to_string());
+        assert!(residency.validate().is_err());
+        residency.region = None;
+        assert!(residency.validate().is_err());
+
+        assert!(is_valid_region_name("eu-west-1"));
+        assert!(!is_valid_region_name("EU"));
+        assert!(!is_valid_region_name(""));
+        assert!(!is_valid_region_name("../eu"));
+    }
+
+    #[test]
+    fn test_max_line_length() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert_eq!(smtp.max_line_length, 998);
+
+        let smtp: SmtpConfig = toml::from_str("max_line_length = 4096").unwrap();
+        assert_eq!(smtp.max_line_length, 4096);
+    }
+
+    #[test]
+    fn test_quota_grace_percent() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert_eq!(smtp.quota_grace_percent, 10);
+
+        let smtp: SmtpConfig = toml::from_str("quota_grace_percent = 0").unwrap();
+        assert_eq!(smtp.quota_grace_percent, 0);
+    }
+
+    #[test]
+    fn test_sender_policy() {
+        let smtp: SmtpConfig = toml::from_str("").unwrap();
+        assert_eq!(smtp.sender_policy, SenderPolicy::Reject);
+
+        let smtp: SmtpConfig = toml::from_str("sender_policy = \"rewrite\"").unwrap();
+        assert_eq!(smtp.sender_policy, SenderPolicy::Rewrite);
+        assert!(toml::from_str::<SmtpConfig>("sender_policy = \"drop\"").is_err());
+    }
+
+    #[test]
+    fn test_queue_tenant_overrides() {
+        let toml = r#"
+tenant_concurrency = 4
+
+[[tenants]]
+tenant_id = "00000000-0000-0000-0000-000000000001"
+weight = 3
+
+[[tenants]]
+tenant_id = "00000000-0000-0000-0000-000000000002"
+max_concurrent = 0
+"#;
+
+        let queue: QueueConfig = toml::from_str(toml).unwrap();
+        let first = Some(Uuid::from_u128(1));
+        let second = Some(Uuid::from_u128(2));
+
+        assert_eq!(queue.max_concurrent_deliveries, 20);
+        assert_eq!(queue.tenant_weight(first), 3);
+        assert_eq!(queue.tenant_concurrency(first), 4);
+        assert_eq!(queue.tenant_weight(second), 1);
+        assert_eq!(queue.tenant_concurrency(second), 1);
+        assert_eq!(queue.tenant_weight(None), 1);
+        assert_eq!(queue.tenant_concurrency(None), 4);
+        assert_eq!(queue.domain_concurrency, 3);
+        assert!(queue.dns.prefetch);
+        assert_eq!(queue.dns.prefetch_domains, 200);
+    }
+
+    #[test]
+    fn test_queue_dns_prefetch() {
+        let toml = r#"
+[dns]
+prefetch = false
+prefetch_domains = 50
+refresh_ahead_secs = 120
+"#;
+
+        let queue: QueueConfig = toml::from_str(toml).unwrap();
+        assert!(!queue.dns.prefetch);
+        assert_eq!(queue.dns.prefetch_domains, 50);
+        assert_eq!(queue.dns.refresh_ahead_secs, 120);
+        assert_eq!(queue.dns.min_ttl_secs, 30);
+    }
+
+    #[test]
+    fn test_queue_lanes() {
+        let toml = r#"
+max_concurrent_deliveries = 8
+
+[lanes]
+campaign = 4
+bulk = 0
+"#;
+
+        let queue: QueueConfig = toml::from_str(toml).unwrap();
+        assert_eq!(queue.lane_concurrency(DeliveryLane::Transactional), 8);
+        assert_eq!(queue.lane_concurrency(DeliveryLane::Campaign), 4);
+        assert_eq!(queue.lane_concurrency(DeliveryLane::Bulk), 1);
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let toml = r#"
+enabled = true
+
+[limits]
+max_connections_per_ip = 5
+ban_secs = 60
+"#;
+
+        let imap: ImapConfig = toml::from_str(toml).unwrap();
+        assert_eq!(imap.max_connections, 1000);
+        assert_eq!(imap.limits.max_connections_per_ip, 5);
+        assert_eq!(imap.limits.ban_secs, 60);
+        assert_eq!(imap.limits.max_auth_failures_per_ip, 10);
+        assert_eq!(imap.limits.window_secs, 300);
+
+        let pop3 = Pop3Config::default();
+        assert_eq!(pop3.limits.max_attempts_per_ip, 100);
+        assert!(pop3.require_tls_for_auth);
+        assert!(!pop3.apop);
+    }
+
+    #[test]
+    fn test_lmtp_config() {
+        let lmtp = LmtpConfig::default();
+        assert!(!lmtp.enabled);
+        assert_eq!(lmtp.bind, "127.0.0.1:24");
+
+        let toml = r#"
+[database]
+url = "postgres://localhost/mairust"
+
+[lmtp]
+enabled = true
+bind = "10.0.0.5:2424"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let lmtp = config
+            .listeners()
+            .into_iter()
+            .find(|l| l.protocol == ListenerProtocol::Lmtp)
+            .unwrap();
+        assert_eq!(lmtp.bind, "10.0.0.5:2424");
+        assert_eq!(lmtp.tls_mode(), ListenerTls::None);
+        assert!(lmtp.protocol.is_smtp());
+
+        // LMTP clients never authenticate or relay
+        let listener = ListenerConfig {
+            relay: Some(true),
+            ..ListenerConfig::new(ListenerProtocol::Lmtp, "127.0.0.1:24")
+        };
+        assert!(listener.validate().is_err());
+    }
+
+    #[test]
+    fn test_managesieve_config() {
+        let managesieve = ManageSieveConfig::default();
+        assert!(!managesieve.enabled);
+        assert_eq!(managesieve.bind, "0.0.0.0:4190");
+        assert!(managesieve.require_tls_for_auth);
+
+        let toml = r#"
+enabled = true
+max_scripts = 4
+max_script_size = 1024
+"#;
+        let managesieve: ManageSieveConfig = toml::from_str(toml).unwrap();
+        assert!(managesieve.enabled);
+        assert_eq!(managesieve.max_scripts, 4);
+        assert_eq!(managesieve.max_script_size, 1024);
+        assert_eq!(managesieve.timeout_minutes, 10);
+    }
+
+    #[test]
+    fn test_journal_config() {
+        let journal = JournalConfig::default();
+        assert!(!journal.journals_inbound());
+        assert!(journal.validate().is_ok());
+
+        let toml = r#"
+enabled = true
+target = "smtp"
+smtp_host = "archive.example.com"
+recipient = "journal@archive.example.com"
+outbound = false
+"#;
+        let journal: JournalConfig = toml::from_str(toml).unwrap();
+        assert!(journal.journals_inbound());
+        assert!(!journal.journals_outbound());
+        assert_eq!(journal.smtp_port, 25);
+        assert!(journal.validate().is_ok());
+
+        let toml = r#"
+enabled = true
+target = "s3"
+"#;
+        let journal: JournalConfig = toml::from_str(toml).unwrap();
+        assert_eq!(journal.target, JournalTarget::S3);
+        assert!(journal.validate().is_err());
+    }
+
+    #[test]
+    fn test_storage_layout_config() {
+        let storage = StorageConfig::default();
+        assert_eq!(storage.layout.scheme, StorageLayoutScheme::Flat);
+        assert!(storage.layout.validate().is_ok());
+
+        let toml = r#"
+path = "/srv/mail"
+
+[layout]
+scheme = "hashed"
+generation = 3
+"#;
+        let storage: StorageConfig = toml::from_str(toml).unwrap();
+        assert_eq!(storage.layout.scheme, StorageLayoutScheme::Hashed);
+        assert_eq!(storage.layout.fanout, 2);
+        assert_eq!(storage.layout.generation, 3);
+        assert!(storage.layout.validate().is_ok());
+
+        let layout = StorageLayoutConfig {
+            scheme: StorageLayoutScheme::Hashed,
+            fanout: 5,
+            generation: 0,
+        };
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn test_replication_config() {
+        let replication = ReplicationConfig::default();
+        assert!(!replication.enabled);
+        assert_eq!(replication.subscription, "mairust_standby");
+        assert!(replication.validate().is_ok());
+
+        let toml = r#"
+enabled = true
+primary_api_url = "https://mail.example.com:8080"
+primary_api_key = "mr_key"
+heartbeat_secs = 5
+"#;
+        let mut replication: ReplicationConfig = toml::from_str(toml).unwrap();
+        assert!(replication.validate().is_ok());
+        assert_eq!(replication.heartbeat_secs, 5);
+        assert_eq!(replication.publication, "mairust");
+
+        replication.subscription = "standby; DROP TABLE users".to_string();
+        assert!(replication.validate().is_err());
+        replication.subscription = "1standby".to_string();
+        assert!(replication.validate().is_err());
+        replication.subscription = "dr_standby".to_string();
+        replication.sync_interval_secs = 0;
+        assert!(replication.validate().is_err());
+    }
 }
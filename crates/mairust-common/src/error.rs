@@ -1,5 +1,6 @@
 //! Error types for MaiRust
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Main error type for MaiRust
@@ -29,9 +30,22 @@ pub enum Error {
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
     #[error("Rate limit exceeded")]
     RateLimitExceeded,
 
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
+    /// Data of a tenant pinned to another region
+    #[error("Wrong region: {0}")]
+    Residency(String),
+
     #[error("Plugin error: {0}")]
     Plugin(String),
 
@@ -41,6 +55,22 @@ pub enum Error {
     #[error("Internal error: {0}")]
     Internal(String),
 
+    /// A query the database refused (a constraint violation) or found no
+    /// row for
+    ///
+    /// Answered like the wrapped error over HTTP, where the request caused
+    /// it. In SMTP it is a temporary failure: mail must not be rejected
+    /// because of the server's own data.
+    #[error("{0}")]
+    DatabaseRefused(Box<Error>),
+
+    /// An error with details about what it concerns
+    #[error("{error}")]
+    WithContext {
+        error: Box<Error>,
+        context: ErrorContext,
+    },
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -48,8 +78,38 @@ pub enum Error {
 /// Result type alias for MaiRust
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Details about what an error concerns, returned to clients
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorContext {
+    /// Request field that is invalid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Kind of resource concerned (e.g. `mailbox`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+    /// Seconds after which the request may be retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Machine-readable error returned to API clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    /// Error code (e.g. `VALIDATION_ERROR`)
+    pub error: String,
+    /// Human-readable description
+    pub message: String,
+    #[serde(flatten)]
+    pub context: ErrorContext,
+}
+
 impl Error {
     /// Returns the HTTP status code for this error
+    ///
+    /// Validation errors answer 400, as the handlers did for invalid input
+    /// before they returned this error, so existing clients keep working
+    /// (docs/decisions/003-api.md). Endpoints documented with 422 set it
+    /// with `ApiError::with_status`.
     pub fn status_code(&self) -> u16 {
         match self {
             Error::Config(_) => 500,
@@ -57,13 +117,19 @@ impl Error {
             Error::Storage(_) => 500,
             Error::Smtp(_) => 500,
             Error::Auth(_) => 401,
-            Error::Validation(_) => 422,
+            Error::Validation(_) => 400,
             Error::NotFound(_) => 404,
             Error::PermissionDenied(_) => 403,
+            Error::Conflict(_) => 409,
+            Error::QuotaExceeded(_) => 507,
             Error::RateLimitExceeded => 429,
+            Error::Unavailable(_) => 503,
+            Error::Residency(_) => 421,
             Error::Plugin(_) => 500,
             Error::Hook(_) => 500,
             Error::Internal(_) => 500,
+            Error::DatabaseRefused(error) => error.status_code(),
+            Error::WithContext { error, .. } => error.status_code(),
             Error::Other(_) => 500,
         }
     }
@@ -79,11 +145,264 @@ impl Error {
             Error::Validation(_) => "VALIDATION_ERROR",
             Error::NotFound(_) => "NOT_FOUND",
             Error::PermissionDenied(_) => "FORBIDDEN",
+            Error::Conflict(_) => "CONFLICT",
+            Error::QuotaExceeded(_) => "QUOTA_EXCEEDED",
             Error::RateLimitExceeded => "RATE_LIMITED",
+            Error::Unavailable(_) => "UNAVAILABLE",
+            Error::Residency(_) => "WRONG_REGION",
             Error::Plugin(_) => "PLUGIN_ERROR",
             Error::Hook(_) => "HOOK_ERROR",
             Error::Internal(_) => "INTERNAL_ERROR",
+            Error::DatabaseRefused(error) => error.code(),
+            Error::WithContext { error, .. } => error.code(),
             Error::Other(_) => "INTERNAL_ERROR",
         }
     }
+
+    /// Returns the SMTP reply code and text for this error
+    ///
+    /// Server-side failures, including every error raised by the database,
+    /// are temporary (4xx) so the client retries; their details are not
+    /// sent.
+    pub fn smtp_reply(&self) -> (u16, String) {
+        let (code, status) = match self {
+            Error::Auth(_) => (535, "5.7.8"),
+            Error::Validation(_) => (501, "5.5.4"),
+            Error::NotFound(_) => (550, "5.1.1"),
+            Error::PermissionDenied(_) => (550, "5.7.1"),
+            Error::QuotaExceeded(_) => (552, "5.2.2"),
+            Error::RateLimitExceeded => (450, "4.7.1"),
+            Error::Residency(_) => (451, "4.3.2"),
+            Error::WithContext { error, .. } => return error.smtp_reply(),
+            _ => return (451, "4.3.0 Temporary error".to_string()),
+        };
+        (code, format!("{} {}", status, self.public_message()))
+    }
+
+    /// Returns the IMAP response code for this error (RFC 5530)
+    ///
+    /// As in SMTP, errors raised by the database are reported as the server
+    /// being unavailable, whatever the query ran into.
+    pub fn imap_code(&self) -> &'static str {
+        match self {
+            Error::Auth(_) => "AUTHENTICATIONFAILED",
+            Error::Validation(_) => "CANNOT",
+            Error::NotFound(_) => "NONEXISTENT",
+            Error::PermissionDenied(_) => "NOPERM",
+            Error::Conflict(_) => "ALREADYEXISTS",
+            Error::QuotaExceeded(_) => "OVERQUOTA",
+            Error::RateLimitExceeded => "LIMIT",
+            Error::WithContext { error, .. } => error.imap_code(),
+            _ => "UNAVAILABLE",
+        }
+    }
+
+    /// Returns the POP3 response code for this error (RFC 2449, RFC 3206)
+    ///
+    /// POP3 only distinguishes authentication failures and permanent from
+    /// temporary system errors; client mistakes have no code.
+    pub fn pop3_code(&self) -> Option<&'static str> {
+        match self {
+            Error::Auth(_) => Some("AUTH"),
+            Error::PermissionDenied(_) | Error::QuotaExceeded(_) => Some("SYS/PERM"),
+            Error::Validation(_) | Error::NotFound(_) | Error::Conflict(_) => None,
+            Error::WithContext { error, .. } => error.pop3_code(),
+            _ => Some("SYS/TEMP"),
+        }
+    }
+
+    /// Description that can be shown to clients
+    ///
+    /// Failures of the server's own components (database, storage, ...)
+    /// only give their kind, as their details may reveal internals.
+    pub fn public_message(&self) -> String {
+        match self {
+            Error::Auth(message)
+            | Error::Validation(message)
+            | Error::NotFound(message)
+            | Error::PermissionDenied(message)
+            | Error::Conflict(message)
+            | Error::QuotaExceeded(message)
+            | Error::Unavailable(message)
+            | Error::Residency(message)
+            | Error::Internal(message) => message.clone(),
+            Error::RateLimitExceeded => "Rate limit exceeded".to_string(),
+            Error::Config(_) => "Configuration error".to_string(),
+            Error::Database(_) => "Database error".to_string(),
+            Error::Storage(_) => "Storage error".to_string(),
+            Error::DatabaseRefused(error) => error.public_message(),
+            Error::WithContext { error, .. } => error.public_message(),
+            Error::Smtp(_) | Error::Plugin(_) | Error::Hook(_) | Error::Other(_) => {
+                "Internal error".to_string()
+            }
+        }
+    }
+
+    /// The error under any context or database wrapping
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::DatabaseRefused(error) | Error::WithContext { error, .. } => error.inner(),
+            error => error,
+        }
+    }
+
+    /// Details added with the `with_*` methods
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Name the request field the error concerns
+    pub fn with_field(self, field: impl Into<String>) -> Self {
+        self.map_context(|context| context.field = Some(field.into()))
+    }
+
+    /// Name the kind of resource the error concerns
+    pub fn with_resource(self, resource: impl Into<String>) -> Self {
+        self.map_context(|context| context.resource = Some(resource.into()))
+    }
+
+    /// Tell clients when to retry
+    pub fn with_retry_after(self, secs: u64) -> Self {
+        self.map_context(|context| context.retry_after_secs = Some(secs))
+    }
+
+    /// Keep errors about the request, such as a duplicate, a missing row or
+    /// an exhausted pool, and replace server failures with
+    /// `Internal(message)`, so clients get a message about what failed
+    /// instead of the database's
+    pub fn or_internal(self, message: impl Into<String>) -> Self {
+        if self.status_code() == 500 {
+            Error::Internal(message.into())
+        } else {
+            self
+        }
+    }
+
+    /// Body of the API response for this error
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            error: self.code().to_string(),
+            message: self.public_message(),
+            context: self.context().cloned().unwrap_or_default(),
+        }
+    }
+
+    fn map_context(self, update: impl FnOnce(&mut ErrorContext)) -> Self {
+        let (error, mut context) = match self {
+            Error::WithContext { error, context } => (error, context),
+            error => (Box::new(error), ErrorContext::default()),
+        };
+        update(&mut context);
+        Error::WithContext { error, context }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_mappings() {
+        let error = Error::Validation("Invalid sender".to_string());
+        assert_eq!(error.status_code(), 400);
+        assert_eq!(error.code(), "VALIDATION_ERROR");
+        assert_eq!(
+            error.smtp_reply(),
+            (501, "5.5.4 Invalid sender".to_string())
+        );
+
+        let error = Error::Database("connection refused to 10.0.0.5".to_string());
+        assert_eq!(error.status_code(), 500);
+        assert_eq!(error.public_message(), "Database error");
+        assert_eq!(
+            error.smtp_reply(),
+            (451, "4.3.0 Temporary error".to_string())
+        );
+
+        let error = Error::QuotaExceeded("Mailbox full".to_string());
+        assert_eq!(error.smtp_reply(), (552, "5.2.2 Mailbox full".to_string()));
+
+        let error =
+            Error::DatabaseRefused(Box::new(Error::NotFound("Resource not found".to_string())));
+        assert_eq!(error.status_code(), 404);
+        assert_eq!(error.code(), "NOT_FOUND");
+        assert_eq!(
+            error.smtp_reply(),
+            (451, "4.3.0 Temporary error".to_string())
+        );
+
+        let error = Error::Residency("Tenant data is stored in region eu".to_string());
+        assert_eq!(error.status_code(), 421);
+        assert_eq!(error.code(), "WRONG_REGION");
+        assert_eq!(
+            error.smtp_reply(),
+            (451, "4.3.2 Tenant data is stored in region eu".to_string())
+        );
+    }
+
+    #[test]
+    fn test_or_internal() {
+        let error = Error::Database("connection reset".to_string()).or_internal("Failed to list");
+        assert_eq!(error.status_code(), 500);
+        assert_eq!(error.public_message(), "Failed to list");
+
+        let error = Error::DatabaseRefused(Box::new(Error::Conflict("Exists".to_string())))
+            .or_internal("Failed to create");
+        assert_eq!(error.status_code(), 409);
+        assert_eq!(error.public_message(), "Exists");
+
+        let error = Error::Unavailable("Busy".to_string()).or_internal("Failed to list");
+        assert_eq!(error.status_code(), 503);
+    }
+
+    #[test]
+    fn test_mailbox_protocol_codes() {
+        let error = Error::PermissionDenied("Read-only mailbox".to_string());
+        assert_eq!(error.imap_code(), "NOPERM");
+        assert_eq!(error.pop3_code(), Some("SYS/PERM"));
+
+        let error = Error::Auth("Invalid credentials".to_string()).with_resource("user");
+        assert_eq!(error.imap_code(), "AUTHENTICATIONFAILED");
+        assert_eq!(error.pop3_code(), Some("AUTH"));
+
+        let error =
+            Error::DatabaseRefused(Box::new(Error::Conflict("Mailbox exists".to_string())));
+        assert_eq!(error.imap_code(), "UNAVAILABLE");
+        assert_eq!(error.pop3_code(), Some("SYS/TEMP"));
+
+        let error = Error::NotFound("No such message".to_string());
+        assert_eq!(error.imap_code(), "NONEXISTENT");
+        assert_eq!(error.pop3_code(), None);
+    }
+
+    #[test]
+    fn test_error_context() {
+        let error = Error::Validation("Invalid address".to_string())
+            .with_field("to")
+            .with_resource("message");
+        assert_eq!(error.status_code(), 400);
+        assert_eq!(error.code(), "VALIDATION_ERROR");
+        assert_eq!(error.to_string(), "Validation error: Invalid address");
+
+        let response = serde_json::to_value(error.to_response()).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "error": "VALIDATION_ERROR",
+                "message": "Invalid address",
+                "field": "to",
+                "resource": "message",
+            })
+        );
+
+        let response = Error::RateLimitExceeded.with_retry_after(60).to_response();
+        assert_eq!(response.context.retry_after_secs, Some(60));
+        assert_eq!(
+            serde_json::to_value(Error::NotFound("x".to_string()).to_response()).unwrap(),
+            serde_json::json!({"error": "NOT_FOUND", "message": "x"})
+        );
+    }
 }
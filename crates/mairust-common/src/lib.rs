@@ -4,8 +4,10 @@
 //! shared across all MaiRust components.
 
 pub mod config;
+pub mod config_loader;
 pub mod error;
 pub mod types;
 
 pub use config::Config;
-pub use error::{Error, Result};
+pub use config_loader::ConfigLoader;
+pub use error::{Error, ErrorContext, ErrorResponse, Result};
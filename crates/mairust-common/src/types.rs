@@ -103,6 +103,23 @@ pub struct Envelope {
 
     /// HELO/EHLO hostname
     pub helo: Option<String>,
+
+    /// REQUIRETLS requested on MAIL FROM (RFC 8689)
+    #[serde(default)]
+    pub require_tls: bool,
+
+    /// Message size declared with SIZE on MAIL FROM (RFC 1870)
+    #[serde(default)]
+    pub size: Option<u64>,
+
+    /// Remote recipients accepted for relay (not included in `to`)
+    #[serde(default)]
+    pub relay_to: Vec<EmailAddress>,
+
+    /// Original senders of messages to SRS addresses, which are returned
+    /// to them (not included in `to`)
+    #[serde(default)]
+    pub srs_to: Vec<EmailAddress>,
 }
 
 /// Message headers
@@ -231,6 +248,117 @@ pub enum UserRole {
     User,
 }
 
+/// Priority lane of an outbound delivery
+///
+/// Lanes are drained in the order transactional, campaign, bulk, each
+/// within its own concurrency budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryLane {
+    /// Mail someone is waiting for, such as password resets
+    #[default]
+    Transactional,
+    /// Newsletters and marketing campaigns
+    Campaign,
+    /// Mail nobody is waiting for, such as reports and digests
+    Bulk,
+}
+
+impl DeliveryLane {
+    /// Lanes in the order they are drained
+    pub const ALL: [DeliveryLane; 3] = [
+        DeliveryLane::Transactional,
+        DeliveryLane::Campaign,
+        DeliveryLane::Bulk,
+    ];
+
+    /// Name of the lane, as stored in the job queue
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryLane::Transactional => "transactional",
+            DeliveryLane::Campaign => "campaign",
+            DeliveryLane::Bulk => "bulk",
+        }
+    }
+
+    /// Lane of a stored name; unknown names are transactional
+    pub fn from_name(name: &str) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|lane| lane.as_str() == name)
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for DeliveryLane {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Mail access protocol that can be switched off for a tenant or user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailService {
+    Imap,
+    Pop3,
+}
+
+impl MailService {
+    /// Protocol name shown to clients
+    pub fn name(self) -> &'static str {
+        match self {
+            MailService::Imap => "IMAP",
+            MailService::Pop3 => "POP3",
+        }
+    }
+}
+
+/// Mail access protocols a plan, tenant or user may use
+///
+/// Stored as `services_enabled`, e.g. `{"imap": true, "pop3": false}`; a
+/// protocol that is not listed is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServicesEnabled {
+    #[serde(default = "service_enabled_default")]
+    pub imap: bool,
+    #[serde(default = "service_enabled_default")]
+    pub pop3: bool,
+}
+
+fn service_enabled_default() -> bool {
+    true
+}
+
+impl Default for ServicesEnabled {
+    fn default() -> Self {
+        Self {
+            imap: true,
+            pop3: true,
+        }
+    }
+}
+
+impl ServicesEnabled {
+    /// Read a `services_enabled` object; values that are not booleans are
+    /// ignored
+    pub fn from_json(value: &serde_json::Value) -> Self {
+        let flag = |name: &str| value.get(name).and_then(|v| v.as_bool()).unwrap_or(true);
+        Self {
+            imap: flag("imap"),
+            pop3: flag("pop3"),
+        }
+    }
+
+    /// Whether a protocol is enabled
+    pub fn allows(&self, service: MailService) -> bool {
+        match service {
+            MailService::Imap => self.imap,
+            MailService::Pop3 => self.pop3,
+        }
+    }
+}
+
 /// Pagination cursor
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaginationCursor {
@@ -277,6 +405,29 @@ mod tests {
         assert!(EmailAddress::parse("user@").is_none());
     }
 
+    #[test]
+    fn test_services_enabled_from_json() {
+        assert_eq!(
+            ServicesEnabled::from_json(&serde_json::json!({})),
+            ServicesEnabled::default()
+        );
+        let services = ServicesEnabled::from_json(&serde_json::json!({"pop3": false, "imap": 1}));
+        assert!(services.allows(MailService::Imap));
+        assert!(!services.allows(MailService::Pop3));
+    }
+
+    #[test]
+    fn test_delivery_lane_names() {
+        for lane in DeliveryLane::ALL {
+            assert_eq!(DeliveryLane::from_name(lane.as_str()), lane);
+            assert_eq!(
+                serde_json::to_value(lane).unwrap(),
+                serde_json::json!(lane.to_string())
+            );
+        }
+        assert_eq!(DeliveryLane::from_name(""), DeliveryLane::Transactional);
+    }
+
     #[test]
     fn test_hook_type_display() {
         assert_eq!(HookType::PreReceive.to_string(), "pre_receive");
@@ -0,0 +1,358 @@
+//! Antivirus scanning of received mail with clamd
+//!
+//! After DATA, a message for tenants that scan their mail is streamed to
+//! clamd once (`INSTREAM`), over TCP or a unix socket. What happens to an
+//! infected message is the tenant's action, or the server's:
+//! - `reject`: the message is refused with 554 5.7.1
+//! - `quarantine`: the message is held in the recipient's quarantine
+//! - `tag`: the message is delivered with the `virus` tag
+//!
+//! Scan verdicts are recorded in `metadata.antivirus` of delivered
+//! messages. A scan that fails lets the message through when the scanner
+//! fails open, and defers it with 451 otherwise; messages over the size
+//! limit are not scanned.
+//!
+//! Tenant settings:
+//! - `antivirus`: `false` turns scanning off for the tenant's mailboxes
+//! - `antivirus_action`: `"reject"`, `"quarantine"` or `"tag"`; the
+//!   server's `smtp.antivirus.action` when unset
+
+use anyhow::{anyhow, bail, Result};
+use mairust_common::config::{AntivirusAction, AntivirusConfig};
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::TenantRepository;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// Tag added to infected messages in `tag` mode
+pub const VIRUS_TAG: &str = "virus";
+
+/// Largest chunk streamed to clamd at once
+const STREAM_CHUNK: usize = 65536;
+
+/// Longest reply read from clamd
+const MAX_REPLY: usize = 4096;
+
+/// Result of scanning a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    /// No virus found
+    Clean,
+    /// clamd found the named virus
+    Infected(String),
+    /// The message was not scanned, for the given reason
+    Unscanned(String),
+}
+
+impl ScanVerdict {
+    /// Whether a virus was found
+    pub fn is_infected(&self) -> bool {
+        matches!(self, ScanVerdict::Infected(_))
+    }
+
+    /// Verdict recorded in the message metadata (`metadata.antivirus`)
+    pub fn metadata(&self, action: AntivirusAction) -> serde_json::Value {
+        match self {
+            ScanVerdict::Clean => serde_json::json!({ "result": "clean" }),
+            ScanVerdict::Infected(virus) => serde_json::json!({
+                "result": "infected",
+                "virus": virus,
+                "action": action_name(action),
+            }),
+            ScanVerdict::Unscanned(reason) => serde_json::json!({
+                "result": "unscanned",
+                "reason": reason,
+            }),
+        }
+    }
+}
+
+/// A message refused after DATA by the virus scan
+#[derive(Debug, thiserror::Error)]
+pub enum AntivirusRejection {
+    /// A virus was found and a recipient's tenant rejects infected mail
+    #[error("message infected with {0}")]
+    Infected(String),
+    /// The scan failed and the scanner fails closed
+    #[error("virus scan failed: {0}")]
+    ScanFailed(String),
+}
+
+impl AntivirusRejection {
+    /// SMTP reply for the refused message
+    pub fn reply(&self) -> (u16, String) {
+        match self {
+            AntivirusRejection::Infected(virus) => (
+                554,
+                format!("5.7.1 Message rejected: virus found ({})", virus),
+            ),
+            AntivirusRejection::ScanFailed(_) => (
+                451,
+                "4.7.1 Message could not be scanned, try again later".to_string(),
+            ),
+        }
+    }
+}
+
+/// Name of an action in metadata and tenant settings
+fn action_name(action: AntivirusAction) -> &'static str {
+    match action {
+        AntivirusAction::Reject => "reject",
+        AntivirusAction::Quarantine => "quarantine",
+        AntivirusAction::Tag => "tag",
+    }
+}
+
+/// Read `antivirus` and `antivirus_action` from tenant settings; `None`
+/// when the tenant has scanning off
+pub fn tenant_action(
+    settings: &serde_json::Value,
+    default: AntivirusAction,
+) -> Option<AntivirusAction> {
+    if settings.get("antivirus").and_then(|v| v.as_bool()) == Some(false) {
+        return None;
+    }
+    let action = match settings.get("antivirus_action").and_then(|v| v.as_str()) {
+        Some("reject") => AntivirusAction::Reject,
+        Some("quarantine") => AntivirusAction::Quarantine,
+        Some("tag") => AntivirusAction::Tag,
+        _ => default,
+    };
+    Some(action)
+}
+
+/// Parse clamd's reply to `INSTREAM`
+///
+/// `stream: OK` is clean and `stream: <virus> FOUND` infected; anything
+/// else, such as `INSTREAM size limit exceeded. ERROR`, is an error.
+fn parse_reply(reply: &[u8]) -> Result<ScanVerdict> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\r', '\n']).trim();
+    let result = reply.strip_prefix("stream:").map(str::trim);
+    match result {
+        Some("OK") => Ok(ScanVerdict::Clean),
+        Some(result) if result.ends_with(" FOUND") => Ok(ScanVerdict::Infected(
+            result.trim_end_matches(" FOUND").trim().to_string(),
+        )),
+        _ => bail!("unexpected reply from clamd: {}", reply),
+    }
+}
+
+/// Stream a message to clamd and read its verdict
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    data: &[u8],
+) -> Result<ScanVerdict> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in data.chunks(STREAM_CHUNK) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buf[..n]);
+        if reply.contains(&0) || reply.len() > MAX_REPLY {
+            break;
+        }
+    }
+    parse_reply(&reply)
+}
+
+/// Scans received mail with clamd
+pub struct Antivirus {
+    db_pool: DatabasePool,
+    config: AntivirusConfig,
+}
+
+impl Antivirus {
+    /// Create a scanner
+    pub fn new(db_pool: DatabasePool, config: AntivirusConfig) -> Self {
+        Self { db_pool, config }
+    }
+
+    /// Whether mail is scanned
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Action on infected mail for a tenant's mailboxes; `None` when the
+    /// tenant has scanning off
+    ///
+    /// The server's action applies when the tenant cannot be loaded.
+    pub async fn tenant_action(&self, tenant_id: TenantId) -> Option<AntivirusAction> {
+        match TenantRepository::new(self.db_pool.clone())
+            .find_by_id(tenant_id)
+            .await
+        {
+            Ok(Some(tenant)) => tenant_action(&tenant.settings, self.config.action),
+            Ok(None) => Some(self.config.action),
+            Err(e) => {
+                warn!(
+                    "Failed to load the antivirus settings of tenant {}: {}",
+                    tenant_id, e
+                );
+                Some(self.config.action)
+            }
+        }
+    }
+
+    /// Scan a message
+    ///
+    /// A failed scan is `Unscanned` when the scanner fails open, and an
+    /// `AntivirusRejection::ScanFailed` error otherwise.
+    pub async fn scan(&self, data: &[u8]) -> Result<ScanVerdict> {
+        if data.len() > self.config.max_size {
+            info!(
+                "Not scanning a message of {} bytes, over the antivirus size limit",
+                data.len()
+            );
+            return Ok(ScanVerdict::Unscanned("too large".to_string()));
+        }
+
+        match self.scan_with_clamd(data).await {
+            Ok(verdict) => Ok(verdict),
+            Err(e) if self.config.fail_open => {
+                warn!("Virus scan failed, delivering unscanned: {}", e);
+                Ok(ScanVerdict::Unscanned("scan failed".to_string()))
+            }
+            Err(e) => {
+                warn!("Virus scan failed, deferring the message: {}", e);
+                Err(AntivirusRejection::ScanFailed(e.to_string()).into())
+            }
+        }
+    }
+
+    /// Connect to clamd and stream the message to it
+    async fn scan_with_clamd(&self, data: &[u8]) -> Result<ScanVerdict> {
+        let timeout = Duration::from_secs(self.config.timeout_secs.max(1));
+        let scan = async {
+            match self.config.address.strip_prefix("unix:") {
+                #[cfg(unix)]
+                Some(path) => {
+                    let mut stream = tokio::net::UnixStream::connect(path).await?;
+                    instream(&mut stream, data).await
+                }
+                #[cfg(not(unix))]
+                Some(_) => bail!("unix sockets are not supported on this platform"),
+                None => {
+                    let mut stream = TcpStream::connect(&self.config.address).await?;
+                    instream(&mut stream, data).await
+                }
+            }
+        };
+        tokio::time::timeout(timeout, scan)
+            .await
+            .map_err(|_| anyhow!("clamd timed out"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply(b"stream: OK\0").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply(b"stream: Eicar-Test-Signature FOUND\0").unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+        assert_eq!(parse_reply(b"stream: OK\n").unwrap(), ScanVerdict::Clean);
+        assert!(parse_reply(b"INSTREAM size limit exceeded. ERROR\0").is_err());
+        assert!(parse_reply(b"").is_err());
+    }
+
+    #[test]
+    fn test_tenant_action() {
+        let default = AntivirusAction::Reject;
+        assert_eq!(
+            tenant_action(&serde_json::json!({}), default),
+            Some(AntivirusAction::Reject)
+        );
+        assert_eq!(
+            tenant_action(&serde_json::json!({ "antivirus": false }), default),
+            None
+        );
+        assert_eq!(
+            tenant_action(
+                &serde_json::json!({ "antivirus": true, "antivirus_action": "tag" }),
+                default
+            ),
+            Some(AntivirusAction::Tag)
+        );
+        assert_eq!(
+            tenant_action(
+                &serde_json::json!({ "antivirus_action": "quarantine" }),
+                default
+            ),
+            Some(AntivirusAction::Quarantine)
+        );
+        assert_eq!(
+            tenant_action(&serde_json::json!({ "antivirus_action": "drop" }), default),
+            Some(AntivirusAction::Reject)
+        );
+    }
+
+    #[test]
+    fn test_verdict_metadata() {
+        let infected = ScanVerdict::Infected("Eicar-Test-Signature".to_string());
+        assert!(infected.is_infected());
+        let metadata = infected.metadata(AntivirusAction::Tag);
+        assert_eq!(metadata["result"], "infected");
+        assert_eq!(metadata["virus"], "Eicar-Test-Signature");
+        assert_eq!(metadata["action"], "tag");
+        assert_eq!(
+            ScanVerdict::Clean.metadata(AntivirusAction::Reject)["result"],
+            "clean"
+        );
+        assert_eq!(
+            AntivirusRejection::Infected("Eicar-Test-Signature".to_string())
+                .reply()
+                .0,
+            554
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instream() {
+        let (mut client, mut server) = tokio::io::duplex(1 << 20);
+        let data = vec![b'x'; STREAM_CHUNK + 10];
+        let clamd = tokio::spawn(async move {
+            let mut command = [0u8; 10];
+            server.read_exact(&mut command).await.unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+            let mut received = 0;
+            loop {
+                let len = server.read_u32().await.unwrap() as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; len];
+                server.read_exact(&mut chunk).await.unwrap();
+                received += len;
+            }
+            server
+                .write_all(b"stream: Test-Virus FOUND\0")
+                .await
+                .unwrap();
+            received
+        });
+
+        let verdict = instream(&mut client, &data).await.unwrap();
+        assert_eq!(verdict, ScanVerdict::Infected("Test-Virus".to_string()));
+        assert_eq!(clamd.await.unwrap(), data.len());
+    }
+}
@@ -0,0 +1,395 @@
+//! Attachment policy enforcement
+//!
+//! Inbound policies see the attachments of a message through the
+//! `attachment_type` condition: each attachment contributes its content
+//! type (`application/pdf`) and, when its file name has one, its extension
+//! with a leading dot (`.exe`). Two actions then take dangerous files out of
+//! the copy delivered to the tenant's mailboxes:
+//!
+//! - `strip_attachments` removes them
+//! - `replace_attachments` puts a short text file in their place that tells
+//!   the recipient what was removed
+//!
+//! Both take an optional `types` parameter, a list of extensions
+//! (`.docm`) and content types (`application/x-msdownload`,
+//! `application/*`); without it, [`DANGEROUS_EXTENSIONS`] are removed.
+//! `replace_attachments` also takes a `notice`, in which `{{filename}}` is
+//! replaced by the name of the removed file. Removed files are listed in
+//! the `X-MaiRust-Attachments-Removed` header.
+//!
+//! Only attachments of the message itself are removed, not those of
+//! messages attached to it.
+
+use crate::inbound::content_type;
+use base64::Engine;
+use mail_parser::{MessageParser, MimeHeaders};
+use serde::Serialize;
+
+/// Extensions removed when an action has no `types`: executables,
+/// scripts, shortcuts, disk images and macro-enabled Office documents
+pub const DANGEROUS_EXTENSIONS: &[&str] = &[
+    ".bat", ".cmd", ".com", ".cpl", ".exe", ".hta", ".iso", ".img", ".jar", ".js", ".jse", ".lnk",
+    ".msi", ".msp", ".pif", ".ps1", ".reg", ".scr", ".vbe", ".vbs", ".wsf", ".wsh", ".docm",
+    ".dotm", ".xlsm", ".xltm", ".xlam", ".pptm", ".potm", ".ppsm", ".ppam", ".sldm",
+];
+
+/// Header listing the removed attachments
+pub const REMOVED_HEADER: &str = "X-MaiRust-Attachments-Removed";
+
+/// Notice of a `replace_attachments` action without one
+pub const DEFAULT_NOTICE: &str = "The attachment {{filename}} was removed from this message \
+because files of this type can harm your computer. If you expected it, ask the sender to \
+share it another way.";
+
+/// What happens to a removed attachment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentDisposition {
+    /// Take it out of the message
+    Strip,
+    /// Put a text file with a notice in its place
+    Replace,
+}
+
+/// Attachments a policy removes, and how
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttachmentRemoval {
+    pub disposition: AttachmentDisposition,
+    /// Lowercase extensions (with the dot) and content types
+    pub types: Vec<String>,
+    /// Text of the replacement file
+    pub notice: String,
+}
+
+impl AttachmentRemoval {
+    /// Read the parameters of a `strip_attachments` or
+    /// `replace_attachments` action
+    pub fn from_parameters(disposition: AttachmentDisposition, params: &serde_json::Value) -> Self {
+        let types: Vec<String> = params
+            .get("types")
+            .and_then(|v| v.as_array())
+            .map(|types| {
+                types
+                    .iter()
+                    .filter_map(|t| t.as_str())
+                    .map(|t| t.trim().to_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let types = if types.is_empty() {
+            DANGEROUS_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+        } else {
+            types
+        };
+        let notice = params
+            .get("notice")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .unwrap_or(DEFAULT_NOTICE)
+            .to_string();
+        Self {
+            disposition,
+            types,
+            notice,
+        }
+    }
+
+    /// Whether an attachment is removed
+    pub fn matches(&self, filename: Option<&str>, content_type: &str) -> bool {
+        let extension = filename.and_then(extension_of);
+        let content_type = content_type.to_lowercase();
+        self.types.iter().any(|t| {
+            if t.starts_with('.') {
+                extension.as_deref() == Some(t.as_str())
+            } else if let Some(prefix) = t.strip_suffix("/*") {
+                content_type.split('/').next() == Some(prefix)
+            } else {
+                content_type == *t
+            }
+        })
+    }
+}
+
+/// Lowercase extension of a file name with its dot, e.g. `.exe`
+fn extension_of(filename: &str) -> Option<String> {
+    let filename = filename.trim().trim_end_matches(['.', ' ']);
+    let (stem, extension) = filename.rsplit_once('.')?;
+    if stem.is_empty() || extension.is_empty() || extension.contains(['/', '\\']) {
+        return None;
+    }
+    Some(format!(".{}", extension.to_lowercase()))
+}
+
+/// Attachment types of a message for the `attachment_type` condition:
+/// the content type of each attachment and the extension of its file name
+pub fn attachment_types(raw: &[u8]) -> Vec<String> {
+    let Some(parsed) = MessageParser::default().parse(raw) else {
+        return Vec::new();
+    };
+    let mut types: Vec<String> = Vec::new();
+    for part in parsed.attachments() {
+        let found = std::iter::once(content_type(part))
+            .chain(part.attachment_name().and_then(extension_of));
+        for t in found {
+            if !types.contains(&t) {
+                types.push(t);
+            }
+        }
+    }
+    types
+}
+
+/// A message with attachments removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedAttachments {
+    pub raw: Vec<u8>,
+    /// Names of the removed files (`unnamed` for those without one)
+    pub filenames: Vec<String>,
+}
+
+/// Remove the attachments matching `removal` from a message
+///
+/// `None` when none match.
+pub fn remove_attachments(raw: &[u8], removal: &AttachmentRemoval) -> Option<RemovedAttachments> {
+    let parsed = MessageParser::default().parse(raw)?;
+
+    // (start, end, replacement) of each removed part, in order
+    let mut edits: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+    let mut filenames = Vec::new();
+    for &id in &parsed.attachments {
+        let Some(part) = parsed.parts.get(id) else {
+            continue;
+        };
+        // A message that is nothing but the attachment is left alone
+        if part.offset_header == 0 {
+            continue;
+        }
+        let filename = part.attachment_name();
+        if !removal.matches(filename, &content_type(part)) {
+            continue;
+        }
+        let filename = filename.unwrap_or("unnamed").to_string();
+        let edit = match removal.disposition {
+            AttachmentDisposition::Strip => {
+                let start = delimiter_start(raw, part.offset_header);
+                (start, part.offset_end, Vec::new())
+            }
+            AttachmentDisposition::Replace => (
+                part.offset_header,
+                part.offset_end,
+                placeholder_part(&filename, &removal.notice),
+            ),
+        };
+        edits.push(edit);
+        filenames.push(filename);
+    }
+    if edits.is_empty() {
+        return None;
+    }
+    edits.sort_by_key(|(start, _, _)| *start);
+
+    let header = format!(
+        "{}: {}\r\n",
+        REMOVED_HEADER,
+        filenames
+            .iter()
+            .map(|name| header_safe(name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut result = Vec::with_capacity(raw.len() + header.len());
+    result.extend_from_slice(header.as_bytes());
+    let mut pos = 0;
+    for (start, end, replacement) in edits {
+        if start < pos {
+            continue;
+        }
+        result.extend_from_slice(&raw[pos..start]);
+        result.extend_from_slice(&replacement);
+        pos = end;
+    }
+    result.extend_from_slice(&raw[pos..]);
+    Some(RemovedAttachments {
+        raw: result,
+        filenames,
+    })
+}
+
+/// Start of the boundary delimiter before a part, with the line break that
+/// belongs to it (RFC 2046, section 5.1.1)
+fn delimiter_start(raw: &[u8], offset_header: usize) -> usize {
+    // The delimiter line ends right before the part's headers
+    let before = raw[..offset_header]
+        .strip_suffix(b"\n")
+        .unwrap_or(&raw[..offset_header]);
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |pos| pos + 1);
+    if raw[..line_start].ends_with(b"\r\n") {
+        line_start - 2
+    } else if raw[..line_start].ends_with(b"\n") {
+        line_start - 1
+    } else {
+        line_start
+    }
+}
+
+/// Text part put in the place of a removed attachment
+fn placeholder_part(filename: &str, notice: &str) -> Vec<u8> {
+    let name = format!("{}.txt", header_safe(filename));
+    let notice = notice.replace("{{filename}}", filename);
+    let mut part = format!(
+        "Content-Type: text/plain; charset=utf-8; name=\"{name}\"\r\n\
+         Content-Disposition: attachment; filename=\"{name}\"\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n"
+    )
+    .into_bytes();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(notice.as_bytes());
+    let lines: Vec<&[u8]> = encoded.as_bytes().chunks(76).collect();
+    part.extend_from_slice(&lines.join(&b"\r\n"[..]));
+    part
+}
+
+/// A file name as it can go into a quoted header parameter; other than
+/// printable ASCII, quotes and backslashes become `_`
+fn header_safe(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| {
+            if c == ' ' || (c.is_ascii_graphic() && !matches!(c, '"' | '\\')) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "From: sender@example.com\r\n\
+        To: user@example.org\r\n\
+        Subject: Invoice\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: multipart/mixed; boundary=\"XX\"\r\n\
+        \r\n\
+        --XX\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        Please see the attached files.\r\n\
+        --XX\r\n\
+        Content-Type: application/octet-stream; name=\"invoice.exe\"\r\n\
+        Content-Disposition: attachment; filename=\"invoice.exe\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        TVqQAAMAAAAEAAAA\r\n\
+        --XX\r\n\
+        Content-Type: application/pdf; name=\"invoice.pdf\"\r\n\
+        Content-Disposition: attachment; filename=\"invoice.pdf\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        JVBERi0xLjQK\r\n\
+        --XX--\r\n";
+
+    fn removal(disposition: AttachmentDisposition) -> AttachmentRemoval {
+        AttachmentRemoval::from_parameters(disposition, &serde_json::json!({}))
+    }
+
+    fn attachment_names(raw: &[u8]) -> Vec<String> {
+        let parsed = MessageParser::default().parse(raw).unwrap();
+        parsed
+            .attachments()
+            .map(|part| part.attachment_name().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_attachment_types() {
+        assert_eq!(
+            attachment_types(MESSAGE.as_bytes()),
+            vec![
+                "application/octet-stream",
+                ".exe",
+                "application/pdf",
+                ".pdf"
+            ]
+        );
+        assert!(attachment_types(b"Subject: Hi\r\n\r\nNo attachments").is_empty());
+    }
+
+    #[test]
+    fn test_extension_of() {
+        assert_eq!(extension_of("Invoice.EXE").as_deref(), Some(".exe"));
+        assert_eq!(extension_of("report.pdf.exe. ").as_deref(), Some(".exe"));
+        assert_eq!(extension_of("README"), None);
+        assert_eq!(extension_of(".profile"), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let default = removal(AttachmentDisposition::Strip);
+        assert!(default.matches(Some("invoice.exe"), "application/octet-stream"));
+        assert!(default.matches(Some("Budget.XLSM"), "application/vnd.ms-excel"));
+        assert!(!default.matches(Some("invoice.pdf"), "application/pdf"));
+        assert!(!default.matches(None, "application/x-msdownload"));
+
+        let custom = AttachmentRemoval::from_parameters(
+            AttachmentDisposition::Strip,
+            &serde_json::json!({"types": ["application/x-msdownload", "Audio/*", ".ZIP"]}),
+        );
+        assert!(custom.matches(None, "application/x-msdownload"));
+        assert!(custom.matches(Some("voicemail.wav"), "audio/wav"));
+        assert!(custom.matches(Some("files.zip"), "application/zip"));
+        assert!(!custom.matches(Some("invoice.exe"), "application/octet-stream"));
+    }
+
+    #[test]
+    fn test_strip_attachments() {
+        let removed =
+            remove_attachments(MESSAGE.as_bytes(), &removal(AttachmentDisposition::Strip)).unwrap();
+        assert_eq!(removed.filenames, vec!["invoice.exe"]);
+
+        let text = String::from_utf8(removed.raw.clone()).unwrap();
+        assert!(text.starts_with("X-MaiRust-Attachments-Removed: invoice.exe\r\n"));
+        assert!(!text.contains("TVqQ"));
+        assert!(text
+            .contains("Please see the attached files.\r\n--XX\r\nContent-Type: application/pdf"));
+        assert_eq!(attachment_names(&removed.raw), vec!["invoice.pdf"]);
+    }
+
+    #[test]
+    fn test_replace_attachments() {
+        let removed =
+            remove_attachments(MESSAGE.as_bytes(), &removal(AttachmentDisposition::Replace))
+                .unwrap();
+        assert_eq!(
+            attachment_names(&removed.raw),
+            vec!["invoice.exe.txt", "invoice.pdf"]
+        );
+
+        let parsed = MessageParser::default().parse(&removed.raw[..]).unwrap();
+        let placeholder = parsed.attachment(0).unwrap();
+        assert_eq!(
+            placeholder.text_contents().unwrap(),
+            DEFAULT_NOTICE.replace("{{filename}}", "invoice.exe")
+        );
+    }
+
+    #[test]
+    fn test_nothing_to_remove() {
+        let removal = AttachmentRemoval::from_parameters(
+            AttachmentDisposition::Strip,
+            &serde_json::json!({"types": [".zip"]}),
+        );
+        assert_eq!(remove_attachments(MESSAGE.as_bytes(), &removal), None);
+        assert_eq!(
+            remove_attachments(b"Subject: Hi\r\n\r\nHello", &removal),
+            None
+        );
+    }
+}
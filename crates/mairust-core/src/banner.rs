@@ -0,0 +1,585 @@
+//! External-sender and first-contact banners
+//!
+//! Tenants can have a warning banner put at the top of inbound mail from
+//! outside their domains, and a different one on the first message a user
+//! gets from an address. The banner goes into the text and HTML bodies of
+//! the copy delivered to each mailbox; the message as received (journal,
+//! DKIM verification) is left as it was. Signed and encrypted messages are
+//! never changed.
+//!
+//! Tenant settings (`sender_banners`):
+//!
+//! ```json
+//! {
+//!   "external": {"text": "...", "html": "..."},
+//!   "first_contact": true,
+//!   "exclude": ["partner.example", "billing@vendor.example"]
+//! }
+//! ```
+//!
+//! A banner is either `true`, for the default text, or a template with
+//! `text` and optionally `html` (made from the text when missing). Templates
+//! may use `{{sender}}`, `{{sender_name}}` and `{{sender_domain}}`; values
+//! put into HTML are escaped. Senders matching an exclusion (an address, a
+//! domain or its subdomains) never get a banner.
+
+use crate::impersonation::{domain_of, is_tenant_domain};
+use anyhow::Result;
+use base64::Engine;
+use mail_parser::{MessageParser, MimeHeaders, PartType};
+use mairust_common::types::{TenantId, UserId};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::{CorrespondentRepository, TenantRepository};
+use std::collections::HashMap;
+
+/// Text of a `true` external-sender banner
+pub const DEFAULT_EXTERNAL_BANNER: &str = "CAUTION: This message was sent from outside \
+your organization ({{sender}}). Do not open links or attachments unless you recognize the \
+sender and know the content is safe.";
+
+/// Text of a `true` first-contact banner
+pub const DEFAULT_FIRST_CONTACT_BANNER: &str = "You don't usually get email from {{sender}}. \
+Be careful with links, attachments and requests for payments or passwords.";
+
+/// A banner template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BannerTemplate {
+    pub text: String,
+    /// HTML version; made from the text when `None`
+    pub html: Option<String>,
+}
+
+impl BannerTemplate {
+    /// Read a banner setting: `true` or `{"text": ..., "html": ...}`
+    fn from_setting(value: &serde_json::Value, default_text: &str) -> Option<Self> {
+        let non_empty = |key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        match value {
+            serde_json::Value::Bool(true) => Some(Self {
+                text: default_text.to_string(),
+                html: None,
+            }),
+            serde_json::Value::Object(_) => Some(Self {
+                text: non_empty("text").unwrap_or_else(|| default_text.to_string()),
+                html: non_empty("html"),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Fill in the template for a sender
+    pub fn render(&self, sender_address: &str, sender_name: Option<&str>) -> Banner {
+        let sender_name = sender_name
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .unwrap_or(sender_address);
+        let sender_domain = domain_of(sender_address).unwrap_or_default();
+        let fill = |template: &str, escape: fn(&str) -> String| {
+            template
+                .replace("{{sender}}", &escape(sender_address))
+                .replace("{{sender_name}}", &escape(sender_name))
+                .replace("{{sender_domain}}", &escape(&sender_domain))
+        };
+
+        let text = fill(&self.text, str::to_string);
+        let html = match &self.html {
+            Some(html) => fill(html, escape_html),
+            None => format!(
+                "<div style=\"border:1px solid #c9a13b;background:#fff4ce;color:#1f1f1f;\
+                 padding:8px 12px;margin:0 0 12px 0;font-family:sans-serif;font-size:13px;\">{}</div>",
+                escape_html(&text)
+            ),
+        };
+        Banner { text, html }
+    }
+}
+
+/// A banner filled in for a sender
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Banner {
+    pub text: String,
+    pub html: String,
+}
+
+/// Banner settings of a tenant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderBanners {
+    /// Banner for mail from outside the tenant's domains
+    pub external: Option<BannerTemplate>,
+    /// Banner for the first message a user gets from an outside address
+    pub first_contact: Option<BannerTemplate>,
+    /// Addresses and domains that never get a banner, lowercased
+    pub exclude: Vec<String>,
+}
+
+impl SenderBanners {
+    /// Read `sender_banners` from tenant settings; `None` when no banner is
+    /// configured
+    pub fn from_tenant_settings(settings: &serde_json::Value) -> Option<Self> {
+        let banners = settings.get("sender_banners")?;
+        let external = banners
+            .get("external")
+            .and_then(|v| BannerTemplate::from_setting(v, DEFAULT_EXTERNAL_BANNER));
+        let first_contact = banners
+            .get("first_contact")
+            .and_then(|v| BannerTemplate::from_setting(v, DEFAULT_FIRST_CONTACT_BANNER));
+        if external.is_none() && first_contact.is_none() {
+            return None;
+        }
+        let exclude = banners
+            .get("exclude")
+            .and_then(|v| v.as_array())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|e| e.as_str())
+                    .map(|e| e.trim().trim_start_matches('@').to_lowercase())
+                    .filter(|e| !e.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            external,
+            first_contact,
+            exclude,
+        })
+    }
+
+    /// Whether an address is exempt from banners
+    pub fn is_excluded(&self, address: &str) -> bool {
+        let address = address.trim().to_lowercase();
+        let domain = domain_of(&address).unwrap_or_default();
+        self.exclude.iter().any(|entry| {
+            if entry.contains('@') {
+                *entry == address
+            } else {
+                domain == *entry || domain.ends_with(&format!(".{}", entry))
+            }
+        })
+    }
+
+    /// Banner for a message from an outside sender; the first-contact
+    /// banner takes the place of the external one when both apply
+    pub fn template(&self, first_contact: bool) -> Option<&BannerTemplate> {
+        if first_contact {
+            self.first_contact.as_ref().or(self.external.as_ref())
+        } else {
+            self.external.as_ref()
+        }
+    }
+}
+
+/// Escape text for HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether a content type is signed or encrypted content
+fn is_protected(ctype: &str, subtype: Option<&str>) -> bool {
+    let subtype = subtype.unwrap_or_default();
+    (ctype.eq_ignore_ascii_case("multipart")
+        && (subtype.eq_ignore_ascii_case("signed") || subtype.eq_ignore_ascii_case("encrypted")))
+        || (ctype.eq_ignore_ascii_case("application")
+            && (subtype.eq_ignore_ascii_case("pkcs7-mime")
+                || subtype.eq_ignore_ascii_case("x-pkcs7-mime")))
+}
+
+/// Put a banner at the top of the text and HTML bodies of a message
+///
+/// The changed body parts are re-encoded as UTF-8 base64; everything else
+/// is copied as it is. `None` when the message has no body text to put the
+/// banner in, or is signed or encrypted.
+pub fn add_banner(raw: &[u8], banner: &Banner) -> Option<Vec<u8>> {
+    let parsed = MessageParser::default().parse(raw)?;
+    let protected = parsed.parts.iter().any(|part| {
+        part.content_type()
+            .is_some_and(|ct| is_protected(ct.ctype(), ct.subtype()))
+    });
+    if protected {
+        return None;
+    }
+
+    let mut part_ids: Vec<usize> = parsed
+        .text_body
+        .first()
+        .into_iter()
+        .chain(parsed.html_body.first())
+        .copied()
+        .collect();
+    part_ids.sort_unstable();
+    part_ids.dedup();
+
+    // (start, end, replacement) of each changed part
+    let mut edits: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+    for id in part_ids {
+        let Some(part) = parsed.parts.get(id) else {
+            continue;
+        };
+        let (subtype, body) = match &part.body {
+            PartType::Text(text) => ("plain", format!("{}\r\n\r\n{}", banner.text, text)),
+            PartType::Html(html) => ("html", insert_html_banner(html, &banner.html)),
+            _ => continue,
+        };
+        let mut replacement = rewrite_headers(
+            &raw[part.offset_header..part.offset_body],
+            subtype,
+            part.offset_header == 0,
+        );
+        replacement.extend_from_slice(&encode_base64_lines(body.as_bytes()));
+        edits.push((part.offset_header, part.offset_end, replacement));
+    }
+    if edits.is_empty() {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(raw.len() + 1024);
+    let mut pos = 0;
+    for (start, end, replacement) in edits {
+        result.extend_from_slice(&raw[pos..start]);
+        result.extend_from_slice(&replacement);
+        pos = end;
+    }
+    result.extend_from_slice(&raw[pos..]);
+    Some(result)
+}
+
+/// Insert the HTML banner right after the `<body>` tag, or at the start
+/// when there is none
+fn insert_html_banner(html: &str, banner: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let at = lower
+        .find("<body")
+        .and_then(|start| lower[start..].find('>').map(|end| start + end + 1))
+        .unwrap_or(0);
+    format!("{}{}{}", &html[..at], banner, &html[at..])
+}
+
+/// Header block of a changed part: its headers without the old content
+/// type and transfer encoding, and new ones for UTF-8 base64 text
+///
+/// The header block of the message itself also gets a MIME-Version when it
+/// has none.
+fn rewrite_headers(block: &[u8], subtype: &str, top_level: bool) -> Vec<u8> {
+    let text = String::from_utf8_lossy(block);
+    let mut kept: Vec<&str> = Vec::new();
+    let mut skipping = false;
+    let mut has_mime_version = false;
+    for line in text.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']).is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if !skipping {
+                kept.push(line);
+            }
+            continue;
+        }
+        let name = line.split(':').next().unwrap_or_default().trim();
+        skipping = name.eq_ignore_ascii_case("content-type")
+            || name.eq_ignore_ascii_case("content-transfer-encoding");
+        has_mime_version |= name.eq_ignore_ascii_case("mime-version");
+        if !skipping {
+            kept.push(line);
+        }
+    }
+
+    let mut headers = String::with_capacity(block.len() + 96);
+    for line in kept {
+        headers.push_str(line.trim_end_matches(['\r', '\n']));
+        headers.push_str("\r\n");
+    }
+    if top_level && !has_mime_version {
+        headers.push_str("MIME-Version: 1.0\r\n");
+    }
+    headers.push_str(&format!(
+        "Content-Type: text/{}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+        subtype
+    ));
+    headers.into_bytes()
+}
+
+/// Base64 in lines of 76 characters
+fn encode_base64_lines(data: &[u8]) -> Vec<u8> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+    let mut lines = Vec::with_capacity(encoded.len() + encoded.len() / 38 + 2);
+    for chunk in encoded.as_bytes().chunks(76) {
+        lines.extend_from_slice(chunk);
+        lines.extend_from_slice(b"\r\n");
+    }
+    lines
+}
+
+/// Decides on and adds the banners of inbound messages
+///
+/// Tenant settings and the tenant's domains are looked up once per
+/// message, however many of the tenant's mailboxes it goes to.
+pub struct BannerStamper {
+    db_pool: DatabasePool,
+    /// Banner settings of each tenant; `None` when the tenant has none or
+    /// the sender is inside its domains
+    tenants: HashMap<TenantId, Option<SenderBanners>>,
+}
+
+impl BannerStamper {
+    /// Create a stamper for one message
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self {
+            db_pool,
+            tenants: HashMap::new(),
+        }
+    }
+
+    /// The message with its banner for a mailbox of `tenant_id` owned by
+    /// `user_id`, or `None` when it gets no banner
+    ///
+    /// `from_trusted` is false when the From domain failed DMARC; such a
+    /// message gets the banner even when it claims a tenant domain.
+    pub async fn stamp(
+        &mut self,
+        tenant_id: TenantId,
+        user_id: Option<UserId>,
+        from_address: &str,
+        from_name: Option<&str>,
+        from_trusted: bool,
+        raw: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        if !self.tenants.contains_key(&tenant_id) {
+            let banners = self
+                .tenant_banners(tenant_id, from_address, from_trusted)
+                .await?;
+            self.tenants.insert(tenant_id, banners);
+        }
+        let Some(banners) = &self.tenants[&tenant_id] else {
+            return Ok(None);
+        };
+
+        let first_contact = match (banners.first_contact.is_some(), user_id) {
+            (true, Some(user_id)) => {
+                !CorrespondentRepository::new(self.db_pool.clone())
+                    .exists(user_id, from_address)
+                    .await?
+            }
+            _ => false,
+        };
+        Ok(banners
+            .template(first_contact)
+            .and_then(|template| add_banner(raw, &template.render(from_address, from_name))))
+    }
+
+    /// Banner settings applying to a sender, `None` when none do
+    async fn tenant_banners(
+        &self,
+        tenant_id: TenantId,
+        from_address: &str,
+        from_trusted: bool,
+    ) -> Result<Option<SenderBanners>> {
+        let Some(banners) = TenantRepository::new(self.db_pool.clone())
+            .find_by_id(tenant_id)
+            .await?
+            .and_then(|tenant| SenderBanners::from_tenant_settings(&tenant.settings))
+        else {
+            return Ok(None);
+        };
+        if banners.is_excluded(from_address) {
+            return Ok(None);
+        }
+        let Some(domain) = domain_of(from_address) else {
+            return Ok(None);
+        };
+        if from_trusted && is_tenant_domain(&self.db_pool, tenant_id, &domain).await? {
+            return Ok(None);
+        }
+        Ok(Some(banners))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banner() -> Banner {
+        Banner {
+            text: "EXTERNAL".to_string(),
+            html: "<div>EXTERNAL</div>".to_string(),
+        }
+    }
+
+    fn body_of(raw: &[u8], html: bool) -> String {
+        let parsed = MessageParser::default().parse(raw).unwrap();
+        if html {
+            parsed.body_html(0).unwrap().to_string()
+        } else {
+            parsed.body_text(0).unwrap().to_string()
+        }
+    }
+
+    #[test]
+    fn test_from_tenant_settings() {
+        assert_eq!(
+            SenderBanners::from_tenant_settings(&serde_json::json!({})),
+            None
+        );
+        assert_eq!(
+            SenderBanners::from_tenant_settings(
+                &serde_json::json!({"sender_banners": {"external": false}})
+            ),
+            None
+        );
+
+        let banners = SenderBanners::from_tenant_settings(&serde_json::json!({
+            "sender_banners": {
+                "external": true,
+                "first_contact": {"text": "First mail from {{sender}}"},
+                "exclude": ["Partner.example", "@vendor.example", "alerts@saas.example"]
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            banners.external.as_ref().unwrap().text,
+            DEFAULT_EXTERNAL_BANNER
+        );
+        assert_eq!(
+            banners.first_contact.as_ref().unwrap().text,
+            "First mail from {{sender}}"
+        );
+        assert_eq!(
+            banners.exclude,
+            vec!["partner.example", "vendor.example", "alerts@saas.example"]
+        );
+
+        assert_eq!(banners.template(false), banners.external.as_ref());
+        assert_eq!(banners.template(true), banners.first_contact.as_ref());
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let banners = SenderBanners {
+            external: None,
+            first_contact: None,
+            exclude: vec![
+                "partner.example".to_string(),
+                "alerts@saas.example".to_string(),
+            ],
+        };
+        assert!(banners.is_excluded("bob@partner.example"));
+        assert!(banners.is_excluded("bob@mail.Partner.example"));
+        assert!(banners.is_excluded("Alerts@saas.example"));
+        assert!(!banners.is_excluded("billing@saas.example"));
+        assert!(!banners.is_excluded("bob@notpartner.example"));
+    }
+
+    #[test]
+    fn test_render() {
+        let template = BannerTemplate {
+            text: "From {{sender_name}} <{{sender}}> at {{sender_domain}}".to_string(),
+            html: Some("<b>{{sender_name}}</b>".to_string()),
+        };
+        let banner = template.render("eve@evil.example", Some("Eve <CEO>"));
+        assert_eq!(
+            banner.text,
+            "From Eve <CEO> <eve@evil.example> at evil.example"
+        );
+        assert_eq!(banner.html, "<b>Eve &lt;CEO&gt;</b>");
+
+        let template = BannerTemplate {
+            text: "Mail from {{sender_name}} & co".to_string(),
+            html: None,
+        };
+        let banner = template.render("eve@evil.example", None);
+        assert_eq!(banner.text, "Mail from eve@evil.example & co");
+        assert!(banner
+            .html
+            .contains(">Mail from eve@evil.example &amp; co</div>"));
+    }
+
+    #[test]
+    fn test_add_banner_to_plain_message() {
+        let raw = b"From: a@evil.example\r\nSubject: Invoice\r\nContent-Type: text/plain; charset=iso-8859-1\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\r\nPay caf=E9 bill\r\n";
+        let stamped = add_banner(raw, &banner()).unwrap();
+        let text = String::from_utf8_lossy(&stamped);
+        assert!(text.starts_with("From: a@evil.example\r\nSubject: Invoice\r\n"));
+        assert!(text.contains("MIME-Version: 1.0\r\n"));
+        assert!(text.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(!text.contains("quoted-printable"));
+        assert_eq!(
+            body_of(&stamped, false),
+            "EXTERNAL\r\n\r\nPay café bill\r\n"
+        );
+    }
+
+    #[test]
+    fn test_add_banner_to_alternative_message() {
+        let raw = b"From: a@evil.example\r\nMIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"m\"\r\n\r\n\
+--m\r\nContent-Type: multipart/alternative;\r\n boundary=\"a\"\r\n\r\n\
+--a\r\nContent-Type: text/plain\r\n\r\nHello\r\n\
+--a\r\nContent-Type: text/html\r\n\r\n<html><BODY class=\"x\"><p>Hello</p></body></html>\r\n\
+--a--\r\n\
+--m\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=\"a.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQK\r\n\
+--m--\r\n";
+        let stamped = add_banner(raw, &banner()).unwrap();
+        assert_eq!(body_of(&stamped, false), "EXTERNAL\r\n\r\nHello");
+        assert_eq!(
+            body_of(&stamped, true),
+            "<html><BODY class=\"x\"><div>EXTERNAL</div><p>Hello</p></body></html>"
+        );
+
+        let parsed = MessageParser::default().parse(&stamped[..]).unwrap();
+        assert_eq!(parsed.attachment_count(), 1);
+        assert_eq!(
+            parsed.attachment(0).unwrap().contents(),
+            b"%PDF-1.4\n".as_slice()
+        );
+        // Only the message's own header block gets a MIME-Version
+        assert_eq!(
+            String::from_utf8_lossy(&stamped)
+                .matches("MIME-Version")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_messages_left_alone() {
+        let signed = b"From: a@evil.example\r\n\
+Content-Type: multipart/signed; protocol=\"application/pkcs7-signature\"; boundary=\"s\"\r\n\r\n\
+--s\r\nContent-Type: text/plain\r\n\r\nSigned\r\n\
+--s\r\nContent-Type: application/pkcs7-signature\r\n\r\nMIIB\r\n\
+--s--\r\n";
+        assert_eq!(add_banner(signed, &banner()), None);
+
+        let image = b"From: a@evil.example\r\nContent-Type: image/png\r\n\r\niVBORw0KGgo=\r\n";
+        assert_eq!(add_banner(image, &banner()), None);
+    }
+
+    #[test]
+    fn test_insert_html_banner() {
+        assert_eq!(
+            insert_html_banner("<p>Hi</p>", "<b>!</b>"),
+            "<b>!</b><p>Hi</p>"
+        );
+        assert_eq!(
+            insert_html_banner("<html><body>Hi</body></html>", "<b>!</b>"),
+            "<html><body><b>!</b>Hi</body></html>"
+        );
+    }
+}
@@ -0,0 +1,625 @@
+//! Bundle export and import against the database
+
+use super::model::{
+    changed_fields, policy_key, ChangeAction, ConfigBundle, DomainSettingsEntry, HookEntry,
+    ImportReport, PolicyEntry,
+};
+use mairust_common::types::{DomainId, TenantId};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{DomainSettings, Hook, PolicyRule};
+use mairust_storage::repository::{record_policy_version, PolicyActor};
+use sqlx::PgConnection;
+use std::collections::HashMap;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+/// Bundle import/export errors
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("Invalid bundle: {0}")]
+    Invalid(String),
+
+    #[error("Domain '{0}' does not exist in this tenant")]
+    UnknownDomain(String),
+
+    #[error("Mailbox '{0}' does not exist in this tenant")]
+    UnknownMailbox(String),
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Options for importing a bundle
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// Plan the changes and roll them back instead of committing
+    pub dry_run: bool,
+    /// Delete policies and hooks missing from the bundle and reset the
+    /// settings of domains it does not mention
+    pub prune: bool,
+}
+
+/// Exports and imports tenant configuration bundles
+pub struct ConfigBundleManager {
+    db_pool: DatabasePool,
+    actor: PolicyActor,
+}
+
+impl ConfigBundleManager {
+    /// Create a new bundle manager
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self {
+            db_pool,
+            actor: PolicyActor::system(),
+        }
+    }
+
+    /// Record imported policy changes as made by `actor` instead of the
+    /// server
+    pub fn with_actor(mut self, actor: PolicyActor) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// Export a tenant's policies, hooks and domain settings
+    pub async fn export(&self, tenant_id: TenantId) -> Result<ConfigBundle, BundleError> {
+        let mut conn = self.db_pool.pool().acquire().await?;
+        let domains = load_domains(&mut conn, tenant_id).await?;
+
+        Ok(ConfigBundle {
+            policies: load_policies(&mut conn, tenant_id, &domains)
+                .await?
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect(),
+            hooks: load_hooks(&mut conn, tenant_id)
+                .await?
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect(),
+            domain_settings: load_domain_settings(&mut conn, tenant_id, &domains).await?,
+            ..Default::default()
+        })
+    }
+
+    /// Import a bundle, upserting entries by name
+    ///
+    /// All changes run in one transaction, so a failing entry leaves the
+    /// tenant untouched. Importing the same bundle twice reports every
+    /// entry as unchanged the second time.
+    pub async fn import(
+        &self,
+        tenant_id: TenantId,
+        bundle: &ConfigBundle,
+        options: ImportOptions,
+    ) -> Result<ImportReport, BundleError> {
+        bundle
+            .validate()
+            .map_err(|e| BundleError::Invalid(e.to_string()))?;
+
+        let mut tx = self.db_pool.pool().begin().await?;
+        let report = apply_bundle(&mut tx, tenant_id, bundle, options, &self.actor).await?;
+
+        if options.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+            info!(
+                "Imported config bundle for tenant {}: {} created, {} updated, {} deleted",
+                tenant_id, report.created, report.updated, report.deleted
+            );
+        }
+
+        Ok(report)
+    }
+}
+
+/// Apply a validated bundle on a connection inside the caller's transaction
+///
+/// Policy changes are versioned as made by `actor`.
+pub(crate) async fn apply_bundle(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+    bundle: &ConfigBundle,
+    options: ImportOptions,
+    actor: &PolicyActor,
+) -> Result<ImportReport, BundleError> {
+    let mut report = ImportReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    let domains = load_domains(&mut *conn, tenant_id).await?;
+    let domain_ids: HashMap<String, DomainId> = domains
+        .iter()
+        .map(|(id, name)| (name.clone(), *id))
+        .collect();
+
+    import_policies(
+        &mut *conn,
+        tenant_id,
+        bundle,
+        &domains,
+        &domain_ids,
+        options,
+        actor,
+        &mut report,
+    )
+    .await?;
+    import_hooks(&mut *conn, tenant_id, bundle, options, &mut report).await?;
+    import_domain_settings(
+        &mut *conn,
+        tenant_id,
+        bundle,
+        &domains,
+        &domain_ids,
+        options,
+        &mut report,
+    )
+    .await?;
+
+    Ok(report)
+}
+
+/// Domain names of a tenant, lowercased, by ID
+async fn load_domains(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+) -> Result<HashMap<DomainId, String>, sqlx::Error> {
+    let rows: Vec<(DomainId, String)> =
+        sqlx::query_as("SELECT id, name FROM domains WHERE tenant_id = $1")
+            .bind(tenant_id)
+            .fetch_all(conn)
+            .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, name)| (id, name.to_lowercase()))
+        .collect())
+}
+
+async fn load_policies(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+    domains: &HashMap<DomainId, String>,
+) -> Result<Vec<(Uuid, PolicyEntry)>, sqlx::Error> {
+    let rules = sqlx::query_as::<_, PolicyRule>(
+        "SELECT * FROM policy_rules WHERE tenant_id = $1 ORDER BY priority ASC, name ASC",
+    )
+    .bind(tenant_id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rules
+        .into_iter()
+        .map(|rule| {
+            let entry = PolicyEntry {
+                name: rule.name,
+                domain: rule.domain_id.and_then(|id| domains.get(&id).cloned()),
+                description: rule.description,
+                policy_type: rule.policy_type,
+                priority: rule.priority,
+                enabled: rule.enabled,
+                conditions: rule.conditions,
+                actions: rule.actions,
+            };
+            (rule.id, entry)
+        })
+        .collect())
+}
+
+async fn load_hooks(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+) -> Result<Vec<(Uuid, HookEntry)>, sqlx::Error> {
+    let hooks = sqlx::query_as::<_, Hook>(
+        "SELECT * FROM hooks WHERE tenant_id = $1 ORDER BY priority ASC, name ASC",
+    )
+    .bind(tenant_id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(hooks
+        .into_iter()
+        .map(|hook| {
+            let entry = HookEntry {
+                name: hook.name,
+                hook_type: hook.hook_type,
+                plugin_id: hook.plugin_id,
+                enabled: hook.enabled,
+                priority: hook.priority,
+                timeout_ms: hook.timeout_ms,
+                on_timeout: hook.on_timeout,
+                on_error: hook.on_error,
+                filter_config: hook.filter_config,
+                config: hook.config,
+            };
+            (hook.id, entry)
+        })
+        .collect())
+}
+
+async fn load_domain_settings(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+    domains: &HashMap<DomainId, String>,
+) -> Result<Vec<DomainSettingsEntry>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, DomainSettings>(
+        r#"
+        SELECT ds.* FROM domain_settings ds
+        JOIN domains d ON d.id = ds.domain_id
+        WHERE d.tenant_id = $1
+        ORDER BY d.name ASC
+        "#,
+    )
+    .bind(tenant_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut entries = Vec::with_capacity(rows.len());
+    for settings in rows {
+        let catch_all_mailbox = match settings.catch_all_mailbox_id {
+            Some(mailbox_id) => {
+                sqlx::query_scalar::<_, String>("SELECT address FROM mailboxes WHERE id = $1")
+                    .bind(mailbox_id)
+                    .fetch_optional(&mut *conn)
+                    .await?
+                    .map(|address| address.to_lowercase())
+            }
+            None => None,
+        };
+
+        entries.push(DomainSettingsEntry {
+            domain: domains
+                .get(&settings.domain_id)
+                .cloned()
+                .unwrap_or_default(),
+            catch_all_enabled: settings.catch_all_enabled,
+            catch_all_mailbox,
+            max_message_size: settings.max_message_size,
+            max_recipients: settings.max_recipients,
+            rate_limit_per_hour: settings.rate_limit_per_hour,
+            require_tls_inbound: settings.require_tls_inbound,
+            require_tls_outbound: settings.require_tls_outbound,
+            spf_policy: settings.spf_policy,
+            dmarc_policy: settings.dmarc_policy,
+            extra_settings: settings.extra_settings,
+        });
+    }
+    Ok(entries)
+}
+
+/// Report name of a policy, qualified by its domain
+fn policy_display_name(entry: &PolicyEntry) -> String {
+    match &entry.domain {
+        Some(domain) => format!("{}/{}", domain, entry.name),
+        None => entry.name.clone(),
+    }
+}
+
+fn resolve_domain(
+    domain_ids: &HashMap<String, DomainId>,
+    domain: &str,
+) -> Result<DomainId, BundleError> {
+    domain_ids
+        .get(domain)
+        .copied()
+        .ok_or_else(|| BundleError::UnknownDomain(domain.to_string()))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn import_policies(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+    bundle: &ConfigBundle,
+    domains: &HashMap<DomainId, String>,
+    domain_ids: &HashMap<String, DomainId>,
+    options: ImportOptions,
+    actor: &PolicyActor,
+    report: &mut ImportReport,
+) -> Result<(), BundleError> {
+    let mut current = load_policies(conn, tenant_id, domains).await?;
+
+    for desired in &bundle.policies {
+        let mut desired = desired.clone();
+        desired.domain = desired.domain.map(|domain| domain.to_lowercase());
+        let domain_id = match &desired.domain {
+            Some(domain) => Some(resolve_domain(domain_ids, domain)?),
+            None => None,
+        };
+        let key = policy_key(desired.domain.as_deref(), &desired.name);
+        let existing = current
+            .iter()
+            .position(|(_, entry)| policy_key(entry.domain.as_deref(), &entry.name) == key)
+            .map(|pos| current.remove(pos));
+
+        match existing {
+            None => {
+                let rule = sqlx::query_as::<_, PolicyRule>(
+                    r#"
+                    INSERT INTO policy_rules (
+                        id, tenant_id, domain_id, name, description, policy_type,
+                        priority, enabled, conditions, actions
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                    RETURNING *
+                    "#,
+                )
+                .bind(Uuid::now_v7())
+                .bind(tenant_id)
+                .bind(domain_id)
+                .bind(&desired.name)
+                .bind(&desired.description)
+                .bind(&desired.policy_type)
+                .bind(desired.priority)
+                .bind(desired.enabled)
+                .bind(&desired.conditions)
+                .bind(&desired.actions)
+                .fetch_one(&mut *conn)
+                .await?;
+                record_policy_version(&mut *conn, "created", &rule, actor).await?;
+                report.push(
+                    "policy",
+                    policy_display_name(&desired),
+                    ChangeAction::Create,
+                    Vec::new(),
+                );
+            }
+            Some((id, entry)) => {
+                let fields = changed_fields(&entry, &desired);
+                if fields.is_empty() {
+                    report.push(
+                        "policy",
+                        policy_display_name(&desired),
+                        ChangeAction::Unchanged,
+                        fields,
+                    );
+                    continue;
+                }
+                let rule = sqlx::query_as::<_, PolicyRule>(
+                    r#"
+                    UPDATE policy_rules SET
+                        description = $2, policy_type = $3, priority = $4, enabled = $5,
+                        conditions = $6, actions = $7, updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING *
+                    "#,
+                )
+                .bind(id)
+                .bind(&desired.description)
+                .bind(&desired.policy_type)
+                .bind(desired.priority)
+                .bind(desired.enabled)
+                .bind(&desired.conditions)
+                .bind(&desired.actions)
+                .fetch_one(&mut *conn)
+                .await?;
+                record_policy_version(&mut *conn, "updated", &rule, actor).await?;
+                report.push(
+                    "policy",
+                    policy_display_name(&desired),
+                    ChangeAction::Update,
+                    fields,
+                );
+            }
+        }
+    }
+
+    if options.prune {
+        for (id, entry) in current {
+            let rule = sqlx::query_as::<_, PolicyRule>(
+                "DELETE FROM policy_rules WHERE id = $1 RETURNING *",
+            )
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await?;
+            record_policy_version(&mut *conn, "deleted", &rule, actor).await?;
+            report.push(
+                "policy",
+                policy_display_name(&entry),
+                ChangeAction::Delete,
+                Vec::new(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn import_hooks(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+    bundle: &ConfigBundle,
+    options: ImportOptions,
+    report: &mut ImportReport,
+) -> Result<(), BundleError> {
+    let mut current = load_hooks(conn, tenant_id).await?;
+
+    for desired in &bundle.hooks {
+        let existing = current
+            .iter()
+            .position(|(_, entry)| entry.name == desired.name)
+            .map(|pos| current.remove(pos));
+
+        match existing {
+            None => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO hooks (
+                        id, tenant_id, name, hook_type, plugin_id, enabled, priority,
+                        timeout_ms, on_timeout, on_error, filter_config, config
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                    "#,
+                )
+                .bind(Uuid::now_v7())
+                .bind(tenant_id)
+                .bind(&desired.name)
+                .bind(&desired.hook_type)
+                .bind(&desired.plugin_id)
+                .bind(desired.enabled)
+                .bind(desired.priority)
+                .bind(desired.timeout_ms)
+                .bind(&desired.on_timeout)
+                .bind(&desired.on_error)
+                .bind(&desired.filter_config)
+                .bind(&desired.config)
+                .execute(&mut *conn)
+                .await?;
+                report.push("hook", &desired.name, ChangeAction::Create, Vec::new());
+            }
+            Some((id, entry)) => {
+                let fields = changed_fields(&entry, desired);
+                if fields.is_empty() {
+                    report.push("hook", &desired.name, ChangeAction::Unchanged, fields);
+                    continue;
+                }
+                sqlx::query(
+                    r#"
+                    UPDATE hooks SET
+                        hook_type = $2, plugin_id = $3, enabled = $4, priority = $5,
+                        timeout_ms = $6, on_timeout = $7, on_error = $8,
+                        filter_config = $9, config = $10, updated_at = NOW()
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(id)
+                .bind(&desired.hook_type)
+                .bind(&desired.plugin_id)
+                .bind(desired.enabled)
+                .bind(desired.priority)
+                .bind(desired.timeout_ms)
+                .bind(&desired.on_timeout)
+                .bind(&desired.on_error)
+                .bind(&desired.filter_config)
+                .bind(&desired.config)
+                .execute(&mut *conn)
+                .await?;
+                report.push("hook", &desired.name, ChangeAction::Update, fields);
+            }
+        }
+    }
+
+    if options.prune {
+        for (id, entry) in current {
+            sqlx::query("DELETE FROM hooks WHERE id = $1")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            report.push("hook", entry.name, ChangeAction::Delete, Vec::new());
+        }
+    }
+
+    Ok(())
+}
+
+async fn import_domain_settings(
+    conn: &mut PgConnection,
+    tenant_id: TenantId,
+    bundle: &ConfigBundle,
+    domains: &HashMap<DomainId, String>,
+    domain_ids: &HashMap<String, DomainId>,
+    options: ImportOptions,
+    report: &mut ImportReport,
+) -> Result<(), BundleError> {
+    let mut current = load_domain_settings(conn, tenant_id, domains).await?;
+
+    for desired in &bundle.domain_settings {
+        let mut desired = desired.clone();
+        desired.domain = desired.domain.to_lowercase();
+        desired.catch_all_mailbox = desired
+            .catch_all_mailbox
+            .map(|address| address.to_lowercase());
+        let domain_id = resolve_domain(domain_ids, &desired.domain)?;
+
+        let existing = current
+            .iter()
+            .position(|entry| entry.domain == desired.domain)
+            .map(|pos| current.remove(pos));
+        let (action, fields) = match &existing {
+            None => (ChangeAction::Create, Vec::new()),
+            Some(entry) => {
+                let fields = changed_fields(entry, &desired);
+                if fields.is_empty() {
+                    report.push(
+                        "domain_settings",
+                        &desired.domain,
+                        ChangeAction::Unchanged,
+                        fields,
+                    );
+                    continue;
+                }
+                (ChangeAction::Update, fields)
+            }
+        };
+
+        let catch_all_mailbox_id = match &desired.catch_all_mailbox {
+            Some(address) => Some(
+                sqlx::query_scalar::<_, Uuid>(
+                    "SELECT id FROM mailboxes WHERE tenant_id = $1 AND LOWER(address) = $2",
+                )
+                .bind(tenant_id)
+                .bind(address)
+                .fetch_optional(&mut *conn)
+                .await?
+                .ok_or_else(|| BundleError::UnknownMailbox(address.clone()))?,
+            ),
+            None => None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO domain_settings (
+                domain_id, catch_all_enabled, catch_all_mailbox_id, max_message_size,
+                max_recipients, rate_limit_per_hour, require_tls_inbound,
+                require_tls_outbound, spf_policy, dmarc_policy, extra_settings
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (domain_id) DO UPDATE SET
+                catch_all_enabled = EXCLUDED.catch_all_enabled,
+                catch_all_mailbox_id = EXCLUDED.catch_all_mailbox_id,
+                max_message_size = EXCLUDED.max_message_size,
+                max_recipients = EXCLUDED.max_recipients,
+                rate_limit_per_hour = EXCLUDED.rate_limit_per_hour,
+                require_tls_inbound = EXCLUDED.require_tls_inbound,
+                require_tls_outbound = EXCLUDED.require_tls_outbound,
+                spf_policy = EXCLUDED.spf_policy,
+                dmarc_policy = EXCLUDED.dmarc_policy,
+                extra_settings = EXCLUDED.extra_settings,
+                updated_at = NOW()
+            "#,
+        )
+        .bind(domain_id)
+        .bind(desired.catch_all_enabled)
+        .bind(catch_all_mailbox_id)
+        .bind(desired.max_message_size)
+        .bind(desired.max_recipients)
+        .bind(desired.rate_limit_per_hour)
+        .bind(desired.require_tls_inbound)
+        .bind(desired.require_tls_outbound)
+        .bind(&desired.spf_policy)
+        .bind(&desired.dmarc_policy)
+        .bind(&desired.extra_settings)
+        .execute(&mut *conn)
+        .await?;
+        report.push("domain_settings", &desired.domain, action, fields);
+    }
+
+    if options.prune {
+        for entry in current {
+            let domain_id = resolve_domain(domain_ids, &entry.domain)?;
+            sqlx::query("DELETE FROM domain_settings WHERE domain_id = $1")
+                .bind(domain_id)
+                .execute(&mut *conn)
+                .await?;
+            report.push(
+                "domain_settings",
+                entry.domain,
+                ChangeAction::Delete,
+                Vec::new(),
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+//! Configuration bundles
+//!
+//! Exports a tenant's policies, hooks and domain settings as a single
+//! JSON or YAML document and imports it again, so configuration can be
+//! kept in version control and promoted between environments.
+//!
+//! Imports are idempotent upserts keyed by name; a dry run reports the
+//! planned changes without applying them. Spam rules are built in rather
+//! than stored per tenant, so they are not part of a bundle.
+
+mod manager;
+mod model;
+mod yaml;
+
+pub(crate) use manager::apply_bundle;
+pub use manager::{BundleError, ConfigBundleManager, ImportOptions};
+pub use model::{
+    BundleChange, BundleFormat, ChangeAction, ConfigBundle, DomainSettingsEntry, HookEntry,
+    ImportReport, PolicyEntry, BUNDLE_VERSION,
+};
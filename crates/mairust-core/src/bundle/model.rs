@@ -0,0 +1,466 @@
+//! Configuration bundle document
+//!
+//! Entries are keyed by name rather than by ID so a bundle exported from
+//! one environment can be imported into another: policies by domain and
+//! name, hooks by name and domain settings by domain name.
+
+use super::yaml;
+use anyhow::{anyhow, Result};
+use mairust_storage::models::{PolicyAction, PolicyConditionNode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Current bundle format version
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Hook types accepted in a bundle
+const HOOK_TYPES: &[&str] = &["pre_receive", "post_receive", "pre_send", "pre_delivery"];
+
+/// Hook failure behaviours accepted in a bundle
+const HOOK_FAILURE_ACTIONS: &[&str] = &["allow", "reject", "tempfail"];
+
+/// Policy directions accepted in a bundle
+const POLICY_TYPES: &[&str] = &["inbound", "outbound", "both"];
+
+/// Serialization format of a bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl BundleFormat {
+    /// Guess the format from a file name, defaulting to JSON
+    pub fn from_path(path: &str) -> Self {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".yaml") || lower.ends_with(".yml") {
+            Self::Yaml
+        } else {
+            Self::Json
+        }
+    }
+
+    /// Format named by a MIME type, e.g. a Content-Type header
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next()?.trim().to_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(Self::Json),
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+                Some(Self::Yaml)
+            }
+            _ => None,
+        }
+    }
+
+    /// MIME type of the format
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Yaml => "application/yaml",
+        }
+    }
+}
+
+/// A tenant's declarative configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigBundle {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub policies: Vec<PolicyEntry>,
+    #[serde(default)]
+    pub hooks: Vec<HookEntry>,
+    #[serde(default)]
+    pub domain_settings: Vec<DomainSettingsEntry>,
+}
+
+impl Default for ConfigBundle {
+    fn default() -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            policies: Vec::new(),
+            hooks: Vec::new(),
+            domain_settings: Vec::new(),
+        }
+    }
+}
+
+/// A policy rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyEntry {
+    pub name: String,
+    /// Domain the policy is limited to; tenant-wide when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub policy_type: String,
+    #[serde(default = "default_priority")]
+    pub priority: i32,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "empty_array")]
+    pub conditions: Value,
+    #[serde(default = "empty_array")]
+    pub actions: Value,
+}
+
+/// A hook bound to a plugin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HookEntry {
+    pub name: String,
+    pub hook_type: String,
+    pub plugin_id: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_priority")]
+    pub priority: i32,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: i32,
+    #[serde(default = "default_on_timeout")]
+    pub on_timeout: String,
+    #[serde(default = "default_on_error")]
+    pub on_error: String,
+    #[serde(default = "empty_object")]
+    pub filter_config: Value,
+    #[serde(default = "empty_object")]
+    pub config: Value,
+}
+
+/// Settings of one domain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DomainSettingsEntry {
+    pub domain: String,
+    #[serde(default)]
+    pub catch_all_enabled: bool,
+    /// Address of the mailbox receiving catch-all mail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catch_all_mailbox: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_message_size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_recipients: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_per_hour: Option<i32>,
+    #[serde(default)]
+    pub require_tls_inbound: bool,
+    #[serde(default)]
+    pub require_tls_outbound: bool,
+    #[serde(default = "default_spf_policy")]
+    pub spf_policy: String,
+    #[serde(default = "default_dmarc_policy")]
+    pub dmarc_policy: String,
+    #[serde(default = "empty_object")]
+    pub extra_settings: Value,
+}
+
+fn default_version() -> u32 {
+    BUNDLE_VERSION
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_priority() -> i32 {
+    100
+}
+
+fn default_timeout_ms() -> i32 {
+    2000
+}
+
+fn default_on_timeout() -> String {
+    "tempfail".to_string()
+}
+
+fn default_on_error() -> String {
+    "allow".to_string()
+}
+
+fn default_spf_policy() -> String {
+    "neutral".to_string()
+}
+
+fn default_dmarc_policy() -> String {
+    "none".to_string()
+}
+
+fn empty_array() -> Value {
+    Value::Array(Vec::new())
+}
+
+fn empty_object() -> Value {
+    Value::Object(serde_json::Map::new())
+}
+
+impl ConfigBundle {
+    /// Parse a bundle document
+    pub fn parse(input: &str, format: BundleFormat) -> Result<Self> {
+        let bundle = match format {
+            BundleFormat::Json => serde_json::from_str(input)?,
+            BundleFormat::Yaml => serde_json::from_value(yaml::parse(input)?)?,
+        };
+        Ok(bundle)
+    }
+
+    /// Serialize the bundle
+    pub fn render(&self, format: BundleFormat) -> Result<String> {
+        let rendered = match format {
+            BundleFormat::Json => serde_json::to_string_pretty(self)?,
+            BundleFormat::Yaml => yaml::render(&serde_json::to_value(self)?),
+        };
+        Ok(rendered)
+    }
+
+    /// Check the bundle for errors that would make an import fail halfway
+    pub fn validate(&self) -> Result<()> {
+        if self.version > BUNDLE_VERSION {
+            return Err(anyhow!(
+                "Unsupported bundle version {} (max {})",
+                self.version,
+                BUNDLE_VERSION
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        for policy in &self.policies {
+            if policy.name.trim().is_empty() {
+                return Err(anyhow!("Policy without a name"));
+            }
+            if !seen.insert(policy_key(policy.domain.as_deref(), &policy.name)) {
+                return Err(anyhow!("Duplicate policy '{}'", policy.name));
+            }
+            if !POLICY_TYPES.contains(&policy.policy_type.as_str()) {
+                return Err(anyhow!(
+                    "Policy '{}' has invalid policy_type '{}'",
+                    policy.name,
+                    policy.policy_type
+                ));
+            }
+            serde_json::from_value::<Vec<PolicyConditionNode>>(policy.conditions.clone())
+                .map_err(|e| anyhow!("Policy '{}' has invalid conditions: {}", policy.name, e))?;
+            serde_json::from_value::<Vec<PolicyAction>>(policy.actions.clone())
+                .map_err(|e| anyhow!("Policy '{}' has invalid actions: {}", policy.name, e))?;
+        }
+
+        let mut seen = HashSet::new();
+        for hook in &self.hooks {
+            if hook.name.trim().is_empty() {
+                return Err(anyhow!("Hook without a name"));
+            }
+            if !seen.insert(hook.name.as_str()) {
+                return Err(anyhow!("Duplicate hook '{}'", hook.name));
+            }
+            if !HOOK_TYPES.contains(&hook.hook_type.as_str()) {
+                return Err(anyhow!(
+                    "Hook '{}' has invalid hook_type '{}'",
+                    hook.name,
+                    hook.hook_type
+                ));
+            }
+            for action in [&hook.on_timeout, &hook.on_error] {
+                if !HOOK_FAILURE_ACTIONS.contains(&action.as_str()) {
+                    return Err(anyhow!(
+                        "Hook '{}' has invalid failure action '{}'",
+                        hook.name,
+                        action
+                    ));
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for settings in &self.domain_settings {
+            if !seen.insert(settings.domain.to_lowercase()) {
+                return Err(anyhow!(
+                    "Duplicate settings for domain '{}'",
+                    settings.domain
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Identity of a policy within a tenant
+pub(crate) fn policy_key(domain: Option<&str>, name: &str) -> String {
+    format!("{}/{}", domain.unwrap_or("").to_lowercase(), name)
+}
+
+/// What an import does to one entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+    Unchanged,
+}
+
+/// Planned or applied change to one entry
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleChange {
+    /// `policy`, `hook` or `domain_settings`
+    pub kind: &'static str,
+    pub name: String,
+    pub action: ChangeAction,
+    /// Fields that differ, for updates
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+}
+
+/// Result of importing a bundle
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    /// Whether the changes were only planned, not applied
+    pub dry_run: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    pub changes: Vec<BundleChange>,
+}
+
+impl ImportReport {
+    /// Record a change
+    pub(crate) fn push(
+        &mut self,
+        kind: &'static str,
+        name: impl Into<String>,
+        action: ChangeAction,
+        fields: Vec<String>,
+    ) {
+        match action {
+            ChangeAction::Create => self.created += 1,
+            ChangeAction::Update => self.updated += 1,
+            ChangeAction::Delete => self.deleted += 1,
+            ChangeAction::Unchanged => self.unchanged += 1,
+        }
+        self.changes.push(BundleChange {
+            kind,
+            name: name.into(),
+            action,
+            fields,
+        });
+    }
+}
+
+/// Top-level fields that differ between two entries
+pub(crate) fn changed_fields<T: Serialize>(current: &T, desired: &T) -> Vec<String> {
+    let current = serde_json::to_value(current).unwrap_or(Value::Null);
+    let desired = serde_json::to_value(desired).unwrap_or(Value::Null);
+    let (Some(current), Some(desired)) = (current.as_object(), desired.as_object()) else {
+        return Vec::new();
+    };
+
+    let mut fields: Vec<String> = current
+        .keys()
+        .chain(desired.keys())
+        .filter(|key| current.get(*key) != desired.get(*key))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(name: &str) -> HookEntry {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "hook_type": "pre_receive",
+            "plugin_id": "spam-check",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_defaults_applied() {
+        let hook = hook("spam");
+        assert!(hook.enabled);
+        assert_eq!(hook.priority, 100);
+        assert_eq!(hook.on_timeout, "tempfail");
+        assert_eq!(hook.config, serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut bundle = ConfigBundle {
+            hooks: vec![hook("spam"), hook("archive")],
+            ..Default::default()
+        };
+        assert!(bundle.validate().is_ok());
+
+        bundle.hooks.push(hook("spam"));
+        assert!(bundle.validate().is_err());
+
+        let bundle = ConfigBundle {
+            policies: vec![serde_json::from_value(serde_json::json!({
+                "name": "block",
+                "policy_type": "inbound",
+                "conditions": [{"condition_type": "no_such_condition", "operator": "eq", "value": 1}],
+            }))
+            .unwrap()],
+            ..Default::default()
+        };
+        assert!(bundle.validate().is_err());
+
+        let bundle = ConfigBundle {
+            version: BUNDLE_VERSION + 1,
+            ..Default::default()
+        };
+        assert!(bundle.validate().is_err());
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let current = hook("spam");
+        let mut desired = current.clone();
+        assert!(changed_fields(&current, &desired).is_empty());
+
+        desired.priority = 10;
+        desired.enabled = false;
+        assert_eq!(
+            changed_fields(&current, &desired),
+            vec!["enabled", "priority"]
+        );
+    }
+
+    #[test]
+    fn test_format_detection() {
+        assert_eq!(BundleFormat::from_path("prod.yml"), BundleFormat::Yaml);
+        assert_eq!(BundleFormat::from_path("prod.json"), BundleFormat::Json);
+        assert_eq!(
+            BundleFormat::from_content_type("application/yaml; charset=utf-8"),
+            Some(BundleFormat::Yaml)
+        );
+        assert_eq!(BundleFormat::from_content_type("text/plain"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bundle = ConfigBundle {
+            hooks: vec![hook("spam")],
+            domain_settings: vec![serde_json::from_value(serde_json::json!({
+                "domain": "example.com",
+                "catch_all_enabled": true,
+                "catch_all_mailbox": "postmaster@example.com",
+            }))
+            .unwrap()],
+            ..Default::default()
+        };
+        let json = bundle.render(BundleFormat::Json).unwrap();
+        assert_eq!(
+            ConfigBundle::parse(&json, BundleFormat::Json).unwrap(),
+            bundle
+        );
+    }
+}
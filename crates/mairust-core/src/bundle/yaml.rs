@@ -0,0 +1,659 @@
+//! Minimal YAML support for configuration bundles
+//!
+//! Covers the block style written by [`render`] plus what is commonly
+//! hand-edited: block mappings and sequences, single-line flow collections,
+//! quoted and plain scalars, and comments. Anchors, aliases, tags, block
+//! scalars (`|`, `>`) and multi-document streams are rejected.
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Number, Value};
+
+/// Characters that may not start a plain scalar
+const INDICATORS: &str = "-?:,[]{}#&*!|>'\"%@`";
+
+/// A significant (non-blank, non-comment) input line
+struct Line {
+    number: usize,
+    indent: usize,
+    text: String,
+}
+
+/// Parse a YAML document into a JSON value
+pub fn parse(input: &str) -> Result<Value> {
+    let mut lines = Vec::new();
+    let mut document_started = false;
+    for (index, raw) in input.lines().enumerate() {
+        let number = index + 1;
+        let content = strip_comment(raw).trim_end();
+        let text = content.trim_start_matches(' ');
+        if text.is_empty() {
+            continue;
+        }
+        if text.starts_with('\t') {
+            bail!("line {}: tabs are not allowed for indentation", number);
+        }
+        let indent = content.len() - text.len();
+        if indent == 0 && (text == "---" || text.starts_with("--- ")) {
+            if document_started || !lines.is_empty() {
+                bail!("line {}: multiple documents are not supported", number);
+            }
+            document_started = true;
+            continue;
+        }
+        lines.push(Line {
+            number,
+            indent,
+            text: text.to_string(),
+        });
+    }
+
+    let Some(first) = lines.first() else {
+        return Ok(Value::Null);
+    };
+    let indent = first.indent;
+    let mut parser = Parser { lines, pos: 0 };
+    let value = parser.block(indent)?;
+    if let Some(line) = parser.lines.get(parser.pos) {
+        bail!("line {}: unexpected content", line.number);
+    }
+    Ok(value)
+}
+
+/// Render a JSON value as a block-style YAML document
+pub fn render(value: &Value) -> String {
+    let mut out = String::new();
+    match value {
+        Value::Object(map) if !map.is_empty() => write_mapping(&mut out, map, 0),
+        Value::Array(items) if !items.is_empty() => write_sequence(&mut out, items, 0),
+        other => {
+            out.push_str(&inline(other));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Parse the node starting at the current line
+    fn block(&mut self, indent: usize) -> Result<Value> {
+        let line = &self.lines[self.pos];
+        if is_sequence_item(&line.text) {
+            self.sequence(indent)
+        } else if split_key(&line.text, line.number)?.is_some() {
+            self.mapping(indent)
+        } else {
+            let value = inline_value(&line.text, line.number)?;
+            self.pos += 1;
+            Ok(value)
+        }
+    }
+
+    /// Parse the value nested below a `key:` or `-` without inline content
+    fn nested(&mut self, parent_indent: usize) -> Result<Value> {
+        match self.lines.get(self.pos) {
+            Some(next) if next.indent > parent_indent => {
+                let indent = next.indent;
+                self.block(indent)
+            }
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.indent < indent || !is_sequence_item(&line.text) {
+                break;
+            }
+            if line.indent > indent {
+                bail!("line {}: unexpected indentation", line.number);
+            }
+
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.nested(indent)?);
+            } else {
+                // Re-read the item content as if it started its own line,
+                // so "- key: value" opens a mapping at the content column
+                let offset = line.text.len() - rest.len();
+                let rest = rest.to_string();
+                let line = &mut self.lines[self.pos];
+                line.indent += offset;
+                line.text = rest;
+                let inner = line.indent;
+                items.push(self.block(inner)?);
+            }
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value> {
+        let mut map = Map::new();
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.indent < indent {
+                break;
+            }
+            let number = line.number;
+            if line.indent > indent {
+                bail!("line {}: unexpected indentation", number);
+            }
+            let Some((key, rest)) = split_key(&line.text, number)? else {
+                bail!("line {}: expected a mapping key", number);
+            };
+            if map.contains_key(&key) {
+                bail!("line {}: duplicate key '{}'", number, key);
+            }
+            self.pos += 1;
+
+            let value = if rest.is_empty() {
+                match self.lines.get(self.pos) {
+                    // Sequences may sit at the same indentation as their key
+                    Some(next) if next.indent == indent && is_sequence_item(&next.text) => {
+                        self.sequence(indent)?
+                    }
+                    _ => self.nested(indent)?,
+                }
+            } else {
+                inline_value(&rest, number)?
+            };
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Remove a trailing comment, ignoring `#` inside quoted scalars
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if q == '"' && c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '#' if prev.is_whitespace() => return &line[..i],
+                '"' | '\'' if prev.is_whitespace() || "[{,:".contains(prev) => quote = Some(c),
+                _ => {}
+            },
+        }
+        prev = c;
+    }
+    line
+}
+
+/// Split `key: value` into its parts; `None` when the line is not a key
+fn split_key(text: &str, number: usize) -> Result<Option<(String, String)>> {
+    if text.starts_with('"') || text.starts_with('\'') {
+        let (key, consumed) = quoted(text, number)?;
+        let rest = text[consumed..].trim_start();
+        return Ok(match rest.strip_prefix(':') {
+            Some(value) if value.is_empty() || value.starts_with(' ') => {
+                Some((key, value.trim().to_string()))
+            }
+            _ => None,
+        });
+    }
+    if text.starts_with('[') || text.starts_with('{') {
+        return Ok(None);
+    }
+
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b':' && (i + 1 == bytes.len() || bytes[i + 1] == b' ') {
+            let key = text[..i].trim_end();
+            if key.is_empty() {
+                bail!("line {}: empty mapping key", number);
+            }
+            return Ok(Some((key.to_string(), text[i + 1..].trim().to_string())));
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a value written on a single line
+fn inline_value(text: &str, number: usize) -> Result<Value> {
+    let text = text.trim();
+    match text.chars().next() {
+        Some('[') | Some('{') => {
+            let mut flow = Flow {
+                text,
+                pos: 0,
+                number,
+            };
+            let value = flow.value()?;
+            flow.skip_spaces();
+            if flow.pos != text.len() {
+                bail!(
+                    "line {}: unexpected characters after flow collection",
+                    number
+                );
+            }
+            Ok(value)
+        }
+        Some('"') | Some('\'') => {
+            let (value, consumed) = quoted(text, number)?;
+            if consumed != text.len() {
+                bail!("line {}: unexpected characters after quoted string", number);
+            }
+            Ok(Value::String(value))
+        }
+        Some('|') | Some('>') => bail!(
+            "line {}: block scalars are not supported, use a quoted string",
+            number
+        ),
+        Some('&') | Some('*') | Some('!') => bail!(
+            "line {}: anchors, aliases and tags are not supported",
+            number
+        ),
+        _ => Ok(plain(text)),
+    }
+}
+
+/// Resolve a plain (unquoted) scalar
+fn plain(text: &str) -> Value {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Value::Null,
+        "true" | "True" | "TRUE" => return Value::Bool(true),
+        "false" | "False" | "FALSE" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(n) = text.parse::<i64>() {
+        return Value::from(n);
+    }
+    let numeric = text.bytes().any(|b| b.is_ascii_digit())
+        && text
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E'));
+    if numeric {
+        if let Some(n) = text.parse::<f64>().ok().and_then(Number::from_f64) {
+            return Value::Number(n);
+        }
+    }
+    Value::String(text.to_string())
+}
+
+/// Parse a quoted scalar at the start of `text`, returning the string and
+/// the number of bytes consumed
+fn quoted(text: &str, number: usize) -> Result<(String, usize)> {
+    let mut chars = text.char_indices();
+    let Some((_, quote)) = chars.next() else {
+        bail!("line {}: expected a quoted string", number);
+    };
+    let mut out = String::new();
+
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            // '' is an escaped quote inside single-quoted scalars
+            if quote == '\'' && text[i + 1..].starts_with('\'') {
+                chars.next();
+                out.push('\'');
+                continue;
+            }
+            return Ok((out, i + 1));
+        }
+        if quote == '"' && c == '\\' {
+            let Some((_, escape)) = chars.next() else {
+                break;
+            };
+            match escape {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                '0' => out.push('\0'),
+                '"' | '\\' | '/' | '\'' => out.push(escape),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| anyhow!("line {}: invalid \\u escape", number))?;
+                    out.push(c);
+                }
+                other => bail!("line {}: unknown escape '\\{}'", number, other),
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    bail!("line {}: unterminated quoted string", number)
+}
+
+/// Single-line flow collection (`[a, b]`, `{k: v}`)
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+    number: usize,
+}
+
+impl Flow<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.rest().starts_with(' ') {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_spaces();
+        match self.rest().chars().next() {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.value()?);
+                    if !self.eat(',') && !self.rest().trim_start().starts_with(']') {
+                        bail!("line {}: expected ',' or ']'", self.number);
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut map = Map::new();
+                while !self.eat('}') {
+                    let key = self.key()?;
+                    if !self.eat(':') {
+                        bail!("line {}: expected ':' after '{}'", self.number, key);
+                    }
+                    let value = self.value()?;
+                    map.insert(key, value);
+                    if !self.eat(',') && !self.rest().trim_start().starts_with('}') {
+                        bail!("line {}: expected ',' or '}}'", self.number);
+                    }
+                }
+                Ok(Value::Object(map))
+            }
+            Some('"') | Some('\'') => {
+                let (value, consumed) = quoted(self.rest(), self.number)?;
+                self.pos += consumed;
+                Ok(Value::String(value))
+            }
+            Some(_) => {
+                let rest = self.rest();
+                let end = rest.find([',', ']', '}']).unwrap_or(rest.len());
+                let value = plain(rest[..end].trim());
+                self.pos += end;
+                Ok(value)
+            }
+            None => bail!("line {}: unterminated flow collection", self.number),
+        }
+    }
+
+    fn key(&mut self) -> Result<String> {
+        self.skip_spaces();
+        if self.rest().starts_with('"') || self.rest().starts_with('\'') {
+            let (key, consumed) = quoted(self.rest(), self.number)?;
+            self.pos += consumed;
+            return Ok(key);
+        }
+        let rest = self.rest();
+        let end = rest.find([':', ',', '}']).unwrap_or(rest.len());
+        let key = rest[..end].trim().to_string();
+        if key.is_empty() {
+            bail!("line {}: empty mapping key", self.number);
+        }
+        self.pos += end;
+        Ok(key)
+    }
+}
+
+fn write_indent(out: &mut String, indent: usize) {
+    out.push_str(&" ".repeat(indent));
+}
+
+fn write_mapping(out: &mut String, map: &Map<String, Value>, indent: usize) {
+    for (key, value) in map {
+        write_indent(out, indent);
+        out.push_str(&scalar(key));
+        out.push(':');
+        write_value(out, value, indent);
+    }
+}
+
+fn write_sequence(out: &mut String, items: &[Value], indent: usize) {
+    for item in items {
+        write_indent(out, indent);
+        out.push('-');
+        match item {
+            Value::Object(map) if !map.is_empty() => {
+                // The first key shares the line with the dash
+                let mut nested = String::new();
+                write_mapping(&mut nested, map, indent + 2);
+                out.push(' ');
+                out.push_str(&nested[indent + 2..]);
+            }
+            other => write_value(out, other, indent),
+        }
+    }
+}
+
+/// Write a value following `key:` or `-`
+fn write_value(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            out.push('\n');
+            write_mapping(out, map, indent + 2);
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push('\n');
+            write_sequence(out, items, indent + 2);
+        }
+        other => {
+            out.push(' ');
+            out.push_str(&inline(other));
+            out.push('\n');
+        }
+    }
+}
+
+/// Render a value on a single line
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => scalar(s),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(inline).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(map) => {
+            let entries: Vec<String> = map
+                .iter()
+                .map(|(k, v)| format!("{}: {}", scalar(k), inline(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+/// Render a string, quoting it when it would not read back as the same string
+fn scalar(s: &str) -> String {
+    let needs_quotes = s.is_empty()
+        || s != s.trim()
+        || !matches!(plain(s), Value::String(_))
+        || s.starts_with(|c: char| INDICATORS.contains(c))
+        || s.ends_with(':')
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.contains(|c: char| c.is_control() || ",[]{}".contains(c));
+    if !needs_quotes {
+        return s.to_string();
+    }
+
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"---
+# Production configuration
+version: 1
+policies:
+- name: block-exe   # inline comment
+  policy_type: inbound
+  priority: 10
+  conditions:
+    - condition_type: attachment_type
+      operator: in
+      value: [exe, "scr", 'bat']
+  actions: [{action_type: reject, message: "Executables are not allowed"}]
+hooks:
+  - name: "spam: check"
+    hook_type: pre_receive
+    enabled: false
+    timeout_ms: 2500
+    config: {}
+    filter_config:
+      threshold: 0.5
+      note: it's fine # really
+      empty: ~
+"#;
+
+    #[test]
+    fn test_parse_document() {
+        let value = parse(DOCUMENT).unwrap();
+        assert_eq!(value.get("version").and_then(Value::as_i64), Some(1));
+
+        let policies = value.get("policies").and_then(Value::as_array).unwrap();
+        assert_eq!(policies.len(), 1);
+        let policy = &policies[0];
+        assert_eq!(
+            policy.get("name").and_then(Value::as_str),
+            Some("block-exe")
+        );
+        assert_eq!(policy.get("priority").and_then(Value::as_i64), Some(10));
+        let condition = &policy.get("conditions").and_then(Value::as_array).unwrap()[0];
+        let extensions = condition.get("value").and_then(Value::as_array).unwrap();
+        let extensions: Vec<&str> = extensions.iter().filter_map(Value::as_str).collect();
+        assert_eq!(extensions, vec!["exe", "scr", "bat"]);
+        let action = &policy.get("actions").and_then(Value::as_array).unwrap()[0];
+        assert_eq!(
+            action.get("message").and_then(Value::as_str),
+            Some("Executables are not allowed")
+        );
+
+        let hook = &value.get("hooks").and_then(Value::as_array).unwrap()[0];
+        assert_eq!(
+            hook.get("name").and_then(Value::as_str),
+            Some("spam: check")
+        );
+        assert_eq!(hook.get("enabled").and_then(Value::as_bool), Some(false));
+        assert_eq!(hook.get("config"), Some(&Value::Object(Map::new())));
+        let filter = hook.get("filter_config").unwrap();
+        assert_eq!(
+            filter.get("note").and_then(Value::as_str),
+            Some("it's fine")
+        );
+        assert!(filter.get("empty").unwrap().is_null());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = parse(DOCUMENT).unwrap();
+        let rendered = render(&value);
+        assert_eq!(parse(&rendered).unwrap(), value);
+    }
+
+    #[test]
+    fn test_render_quotes_ambiguous_strings() {
+        for s in [
+            "",
+            "true",
+            "null",
+            "42",
+            "1.5",
+            "a: b",
+            "- item",
+            " padded",
+            "x #y",
+            "line\nbreak",
+        ] {
+            let rendered = scalar(s);
+            assert!(
+                rendered.starts_with('"'),
+                "{:?} rendered as {}",
+                s,
+                rendered
+            );
+            assert_eq!(
+                inline_value(&rendered, 1).unwrap(),
+                Value::String(s.to_string())
+            );
+        }
+        assert_eq!(scalar("pre_receive"), "pre_receive");
+        assert_eq!(
+            scalar("https://example.com/hook"),
+            "https://example.com/hook"
+        );
+    }
+
+    #[test]
+    fn test_render_layout() {
+        let value = parse("hooks:\n- name: a\n  config: {}\nversion: 1\n").unwrap();
+        assert_eq!(
+            render(&value),
+            "hooks:\n  - config: {}\n    name: a\nversion: 1\n"
+        );
+    }
+
+    #[test]
+    fn test_rejects_unsupported() {
+        assert!(parse("a:\n\tb: 1").is_err());
+        assert!(parse("a: &anchor 1").is_err());
+        assert!(parse("a: |\n  text").is_err());
+        assert!(parse("a: 1\na: 2").is_err());
+        assert!(parse("a: 1\n---\nb: 2").is_err());
+        assert!(parse("a: [1, 2").is_err());
+        assert!(parse("a: \"open").is_err());
+    }
+}
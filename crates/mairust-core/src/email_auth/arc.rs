@@ -0,0 +1,567 @@
+//! ARC (Authenticated Received Chain) sealing and verification
+//!
+//! Implements RFC 8617. A server that forwards a message adds an ARC set on
+//! top of it: the authentication results it saw (ARC-Authentication-Results),
+//! a DKIM-like signature of the message (ARC-Message-Signature) and a seal
+//! over all the ARC sets (ARC-Seal). A receiver whose DMARC check fails
+//! because of the forwarding can then trust the results recorded by a
+//! forwarder it knows.
+
+use super::dkim::{
+    canonical_header, compute_body_hash, fold_base64, fold_list, header_fields,
+    parse_canonicalization, parse_dkim_tags, parse_rsa_private_key, parse_rsa_public_key,
+    signature_header_data, signed_header_data, strip_dkim_signature_value, Canonicalization,
+    DkimSigningConfig, DkimVerifier,
+};
+use super::signing::SigningIdentity;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rsa::pkcs1v15::{Signature as RsaSignature, SigningKey, VerifyingKey};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::RsaPrivateKey;
+use sha2::Sha256;
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+/// Most ARC sets a message may have (RFC 8617 section 4.2.1)
+pub const MAX_INSTANCES: u32 = 50;
+
+const AAR: &str = "arc-authentication-results";
+const AMS: &str = "arc-message-signature";
+const SEAL: &str = "arc-seal";
+
+/// ARC chain validation result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArcResult {
+    /// No ARC sets
+    #[default]
+    None,
+    /// The chain and the latest message signature are valid
+    Pass,
+    /// The chain is broken or a signature does not verify
+    Fail,
+}
+
+impl ArcResult {
+    /// Convert to header value for Authentication-Results and cv=
+    pub fn as_header_value(&self) -> &'static str {
+        match self {
+            ArcResult::None => "none",
+            ArcResult::Pass => "pass",
+            ArcResult::Fail => "fail",
+        }
+    }
+}
+
+/// Result of verifying the ARC chain of a message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArcVerification {
+    pub result: ArcResult,
+    /// Domains of the ARC-Seals (d= tags), oldest first
+    pub sealers: Vec<String>,
+}
+
+impl ArcVerification {
+    /// Domain of the latest seal, which handed the message to us
+    pub fn latest_sealer(&self) -> Option<&str> {
+        self.sealers.last().map(String::as_str)
+    }
+
+    /// Whether the chain passes and its latest seal is by one of `domains`
+    pub fn is_trusted(&self, domains: &[String]) -> bool {
+        self.result == ArcResult::Pass
+            && self.latest_sealer().is_some_and(|sealer| {
+                domains
+                    .iter()
+                    .any(|domain| domain.trim_end_matches('.').eq_ignore_ascii_case(sealer))
+            })
+    }
+}
+
+/// One ARC set: the raw header fields of an instance
+#[derive(Debug, Clone, Copy)]
+struct ArcSet<'a> {
+    results: &'a [u8],
+    signature: &'a [u8],
+    seal: &'a [u8],
+}
+
+/// Value of a raw header field, after its colon
+fn field_value(raw: &[u8]) -> String {
+    let colon = raw.iter().position(|&b| b == b':').unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[(colon + 1).min(raw.len())..]).into_owned()
+}
+
+/// Instance (i= tag) of an ARC header field
+///
+/// The tag comes first in ARC-Authentication-Results, whose other parts
+/// are not tags.
+fn instance(name: &str, raw: &[u8]) -> Option<u32> {
+    let value = field_value(raw);
+    let tags = if name == AAR {
+        parse_dkim_tags(value.split(';').next().unwrap_or_default()).ok()?
+    } else {
+        parse_dkim_tags(&value).ok()?
+    };
+    tags.get("i")?.parse().ok()
+}
+
+/// The ARC sets of a message, by instance from 1
+///
+/// Fails when the sets are not numbered 1 to N with exactly one header
+/// field of each kind (RFC 8617 section 5.2).
+fn arc_sets<'a>(fields: &[(String, &'a [u8])]) -> Result<Vec<ArcSet<'a>>> {
+    let mut instances: HashMap<u32, [Option<&'a [u8]>; 3]> = HashMap::new();
+    for (name, raw) in fields {
+        let slot = match name.as_str() {
+            AAR => 0,
+            AMS => 1,
+            SEAL => 2,
+            _ => continue,
+        };
+        let i = instance(name, raw)
+            .filter(|i| (1..=MAX_INSTANCES).contains(i))
+            .ok_or_else(|| anyhow!("Invalid ARC instance in {}", name))?;
+        let set = instances.entry(i).or_default();
+        if set[slot].replace(raw).is_some() {
+            return Err(anyhow!("Duplicate {} for instance {}", name, i));
+        }
+    }
+
+    (1..=instances.len() as u32)
+        .map(|i| match instances.get(&i) {
+            Some([Some(results), Some(signature), Some(seal)]) => Ok(ArcSet {
+                results,
+                signature,
+                seal,
+            }),
+            _ => Err(anyhow!("Incomplete or missing ARC set {}", i)),
+        })
+        .collect()
+}
+
+/// Data signed by the ARC-Seal of the last of `sets`, with `seal_value`
+/// as its value without the b= value (RFC 8617 section 5.1.1)
+fn seal_data(sets: &[ArcSet<'_>], seal_value: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (n, set) in sets.iter().enumerate() {
+        data.extend(canonical_header(set.results, Canonicalization::Relaxed));
+        data.extend(canonical_header(set.signature, Canonicalization::Relaxed));
+        if n + 1 < sets.len() {
+            data.extend(canonical_header(set.seal, Canonicalization::Relaxed));
+        }
+    }
+    data.extend(signature_header_data(
+        "ARC-Seal",
+        seal_value,
+        Canonicalization::Relaxed,
+    ));
+    data
+}
+
+/// Check an RSA-SHA256 signature (a base64 b= value, possibly folded)
+/// against a base64 public key
+fn check_signature(public_key: &str, data: &[u8], signature_b64: &str) -> Result<bool> {
+    let public_key = parse_rsa_public_key(public_key)?;
+    let signature: String = signature_b64.split_whitespace().collect();
+    let signature = BASE64.decode(signature)?;
+    let signature = RsaSignature::try_from(signature.as_slice())?;
+    Ok(VerifyingKey::<Sha256>::new(public_key)
+        .verify(data, &signature)
+        .is_ok())
+}
+
+/// Public keys by selector and domain
+pub(crate) type Keys = HashMap<(String, String), String>;
+
+/// Verify the ARC chain of a message with the given public keys
+pub(crate) fn verify_chain(message: &[u8], keys: &Keys) -> ArcVerification {
+    let (fields, body) = header_fields(message);
+    let sets = match arc_sets(&fields) {
+        Ok(sets) => sets,
+        Err(e) => {
+            debug!("Invalid ARC chain: {}", e);
+            return ArcVerification {
+                result: ArcResult::Fail,
+                sealers: Vec::new(),
+            };
+        }
+    };
+    if sets.is_empty() {
+        return ArcVerification::default();
+    }
+
+    let mut sealers = Vec::with_capacity(sets.len());
+    let mut result = ArcResult::Pass;
+    let key = |tags: &HashMap<String, String>| {
+        let selector = tags.get("s")?;
+        let domain = tags.get("d")?;
+        keys.get(&(selector.clone(), domain.to_lowercase()))
+    };
+
+    // Every seal, newest first
+    for n in (0..sets.len()).rev() {
+        let value = field_value(sets[n].seal);
+        let tags = parse_dkim_tags(&value).unwrap_or_default();
+        sealers.push(tags.get("d").cloned().unwrap_or_default().to_lowercase());
+        if result == ArcResult::Fail {
+            continue;
+        }
+        let expected_cv = if n == 0 { "none" } else { "pass" };
+        let valid = tags.get("cv").map(String::as_str) == Some(expected_cv)
+            && tags.get("a").map(String::as_str) == Some("rsa-sha256")
+            && match (
+                key(&tags),
+                tags.get("b"),
+                strip_dkim_signature_value(&value),
+            ) {
+                (Some(public_key), Some(b), Ok(stripped)) => {
+                    let data = seal_data(&sets[..=n], stripped.trim_start());
+                    check_signature(public_key, &data, b).unwrap_or(false)
+                }
+                _ => false,
+            };
+        if !valid {
+            debug!("ARC-Seal {} does not verify", n + 1);
+            result = ArcResult::Fail;
+        }
+    }
+    sealers.reverse();
+
+    // The latest message signature
+    if result == ArcResult::Pass {
+        let latest = sets[sets.len() - 1];
+        let value = field_value(latest.signature);
+        let tags = parse_dkim_tags(&value).unwrap_or_default();
+        let (header_canon, body_canon) =
+            parse_canonicalization(tags.get("c").map_or("simple/simple", String::as_str));
+        let signed_headers: Vec<String> = tags
+            .get("h")
+            .map(|h| {
+                h.split(':')
+                    .map(|name| name.trim().to_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let body_hash: String = tags
+            .get("bh")
+            .map(|bh| bh.split_whitespace().collect())
+            .unwrap_or_default();
+
+        let valid = tags.get("a").map(String::as_str) == Some("rsa-sha256")
+            && !signed_headers.is_empty()
+            && !signed_headers.iter().any(|name| name == SEAL)
+            && compute_body_hash(body, body_canon) == body_hash
+            && match (
+                key(&tags),
+                tags.get("b"),
+                strip_dkim_signature_value(&value),
+            ) {
+                (Some(public_key), Some(b), Ok(stripped)) => {
+                    let mut data = signed_header_data(&fields, &signed_headers, header_canon);
+                    data.extend(signature_header_data(
+                        "ARC-Message-Signature",
+                        stripped.trim_start(),
+                        header_canon,
+                    ));
+                    check_signature(public_key, &data, b).unwrap_or(false)
+                }
+                _ => false,
+            };
+        if !valid {
+            debug!("ARC-Message-Signature {} does not verify", sets.len());
+            result = ArcResult::Fail;
+        }
+    }
+
+    ArcVerification { result, sealers }
+}
+
+/// ARC verifier for incoming mail
+pub struct ArcVerifier {
+    dkim: DkimVerifier,
+}
+
+impl ArcVerifier {
+    /// Create a new ARC verifier
+    pub async fn new() -> Result<Self> {
+        Ok(Self {
+            dkim: DkimVerifier::new().await?,
+        })
+    }
+
+    /// Verify the ARC chain of a message
+    ///
+    /// A key that cannot be fetched fails the chain.
+    pub async fn verify(&self, message: &[u8]) -> ArcVerification {
+        let (fields, _) = header_fields(message);
+        let mut keys = Keys::new();
+        for (name, raw) in &fields {
+            if name != AMS && name != SEAL {
+                continue;
+            }
+            let tags = parse_dkim_tags(&field_value(raw)).unwrap_or_default();
+            let (Some(selector), Some(domain)) = (tags.get("s"), tags.get("d")) else {
+                continue;
+            };
+            let id = (selector.clone(), domain.to_lowercase());
+            if keys.contains_key(&id) {
+                continue;
+            }
+            let dns_name = format!("{}._domainkey.{}", selector, domain);
+            match self.dkim.fetch_public_key(&dns_name).await {
+                Ok(Some(key)) => {
+                    keys.insert(id, key);
+                }
+                Ok(None) => debug!("No ARC public key found for {}", dns_name),
+                Err(e) => warn!("Failed to fetch ARC public key {}: {}", dns_name, e),
+            }
+        }
+        verify_chain(message, &keys)
+    }
+}
+
+/// ARC sealer for forwarded mail
+pub struct ArcSealer {
+    domain: String,
+    selector: String,
+    signing_key: SigningKey<Sha256>,
+}
+
+impl ArcSealer {
+    /// Create a sealer with a domain's signing identity
+    pub fn new(identity: &SigningIdentity) -> Result<Self> {
+        let private_key = parse_rsa_private_key(&identity.private_key_pem)?;
+        Ok(Self::from_private_key(
+            &identity.domain,
+            &identity.selector,
+            private_key,
+        ))
+    }
+
+    /// Create a sealer from an RSA private key directly
+    pub fn from_private_key(domain: &str, selector: &str, private_key: RsaPrivateKey) -> Self {
+        Self {
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            signing_key: SigningKey::<Sha256>::new(private_key),
+        }
+    }
+
+    /// The ARC set to add on top of a forwarded message
+    ///
+    /// `auth_results` is the Authentication-Results value of this server
+    /// (authserv-id and results) and `chain` the result of verifying the
+    /// message's ARC chain when it arrived. The three header fields are
+    /// returned folded, each with its CRLF. `None` when the chain cannot be
+    /// extended: its sets are malformed, it already has [`MAX_INSTANCES`]
+    /// sets, or it was sealed as failed.
+    pub fn seal(
+        &self,
+        message: &[u8],
+        auth_results: &str,
+        chain: ArcResult,
+    ) -> Result<Option<String>> {
+        let (fields, body) = header_fields(message);
+        if fields.is_empty() {
+            return Err(anyhow!("Message has no header fields"));
+        }
+        let Ok(sets) = arc_sets(&fields) else {
+            return Ok(None);
+        };
+        let sealed_failed = sets.iter().any(|set| {
+            parse_dkim_tags(&field_value(set.seal))
+                .is_ok_and(|tags| tags.get("cv").is_some_and(|cv| cv == "fail"))
+        });
+        if sets.len() as u32 >= MAX_INSTANCES || sealed_failed {
+            return Ok(None);
+        }
+        let i = sets.len() as u32 + 1;
+        let cv = match (i, chain) {
+            (1, _) => ArcResult::None,
+            (_, ArcResult::Pass) => ArcResult::Pass,
+            _ => ArcResult::Fail,
+        };
+        let timestamp = chrono::Utc::now().timestamp();
+
+        let results = format!("ARC-Authentication-Results: i={}; {}", i, auth_results);
+
+        // Message signature over the headers a DKIM signature covers
+        let mut signed_headers: Vec<String> = Vec::new();
+        for name in DkimSigningConfig::default()
+            .headers_to_sign
+            .iter()
+            .chain(&["dkim-signature".to_string()])
+        {
+            let count = fields.iter().filter(|(field, _)| field == name).count();
+            signed_headers.resize(signed_headers.len() + count, name.clone());
+        }
+        let mut signature_value = format!(
+            "i={}; a=rsa-sha256; c=relaxed/relaxed;\r\n\td={}; s={}; t={};\r\n\th={};\r\n\tbh={};\r\n\tb=",
+            i,
+            self.domain,
+            self.selector,
+            timestamp,
+            fold_list(&signed_headers),
+            compute_body_hash(body, Canonicalization::Relaxed)
+        );
+        let mut data = signed_header_data(&fields, &signed_headers, Canonicalization::Relaxed);
+        data.extend(signature_header_data(
+            "ARC-Message-Signature",
+            &signature_value,
+            Canonicalization::Relaxed,
+        ));
+        signature_value.push_str(&self.sign(&data));
+        let signature = format!("ARC-Message-Signature: {}", signature_value);
+
+        // Seal over the earlier sets and this one
+        let mut seal_value = format!(
+            "i={}; a=rsa-sha256; t={}; cv={};\r\n\td={}; s={};\r\n\tb=",
+            i,
+            timestamp,
+            cv.as_header_value(),
+            self.domain,
+            self.selector
+        );
+        let mut chain_sets = sets;
+        chain_sets.push(ArcSet {
+            results: results.as_bytes(),
+            signature: signature.as_bytes(),
+            seal: &[],
+        });
+        let data = seal_data(&chain_sets, &seal_value);
+        seal_value.push_str(&self.sign(&data));
+
+        Ok(Some(format!(
+            "ARC-Seal: {}\r\n{}\r\n{}\r\n",
+            seal_value, signature, results
+        )))
+    }
+
+    /// Base64 RSA-SHA256 signature of `data`, folded
+    fn sign(&self, data: &[u8]) -> String {
+        let signature = self.signing_key.sign(data);
+        fold_base64(&BASE64.encode(signature.to_bytes().as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::EncodePublicKey;
+    use rsa::RsaPublicKey;
+
+    fn key_pair() -> (RsaPrivateKey, String) {
+        let private_key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+        let public_key = RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .unwrap();
+        (private_key, BASE64.encode(public_key.as_bytes()))
+    }
+
+    fn sealed(sealer: &ArcSealer, message: &[u8], chain: ArcResult) -> Vec<u8> {
+        let headers = sealer
+            .seal(message, "mx.example.net; spf=pass dkim=pass", chain)
+            .unwrap()
+            .unwrap();
+        let mut sealed = headers.into_bytes();
+        sealed.extend_from_slice(message);
+        sealed
+    }
+
+    #[test]
+    fn test_arc_result_header_value() {
+        assert_eq!(ArcResult::None.as_header_value(), "none");
+        assert_eq!(ArcResult::Pass.as_header_value(), "pass");
+        assert_eq!(ArcResult::Fail.as_header_value(), "fail");
+    }
+
+    #[test]
+    fn test_seal_and_verify() {
+        let (first_key, first_public) = key_pair();
+        let (second_key, second_public) = key_pair();
+        let keys = Keys::from([
+            (
+                ("arc".to_string(), "lists.example.org".to_string()),
+                first_public,
+            ),
+            (
+                ("fwd".to_string(), "example.net".to_string()),
+                second_public,
+            ),
+        ]);
+        let first = ArcSealer::from_private_key("lists.example.org", "arc", first_key);
+        let second = ArcSealer::from_private_key("example.net", "fwd", second_key);
+
+        let message = b"From: Alice <alice@example.com>\r\nTo: list@lists.example.org\r\nSubject: Hello\r\n\r\nHi  all\r\n";
+        assert_eq!(verify_chain(message, &keys), ArcVerification::default());
+
+        let once = sealed(&first, message, ArcResult::None);
+        assert!(once.starts_with(b"ARC-Seal: i=1; a=rsa-sha256; t="));
+        assert!(String::from_utf8_lossy(&once)
+            .lines()
+            .all(|line| line.len() <= 78));
+        let verification = verify_chain(&once, &keys);
+        assert_eq!(verification.result, ArcResult::Pass);
+        assert_eq!(verification.sealers, ["lists.example.org"]);
+
+        let twice = sealed(&second, &once, verification.result);
+        let verification = verify_chain(&twice, &keys);
+        assert_eq!(verification.result, ArcResult::Pass);
+        assert_eq!(verification.sealers, ["lists.example.org", "example.net"]);
+        assert!(verification.is_trusted(&["Example.NET".to_string()]));
+        assert!(!verification.is_trusted(&["lists.example.org".to_string()]));
+
+        // Changing the body breaks the latest message signature
+        let mut modified = twice.clone();
+        modified.extend_from_slice(b"P.S.\r\n");
+        assert_eq!(verify_chain(&modified, &keys).result, ArcResult::Fail);
+
+        // Removing a set breaks the chain
+        let (fields, _) = header_fields(&twice);
+        let without_first: Vec<u8> = fields
+            .iter()
+            .filter(|(name, raw)| !(name.starts_with("arc-") && instance(name, raw) == Some(1)))
+            .flat_map(|(_, raw)| [*raw, &b"\r\n"[..]].concat())
+            .chain(b"\r\nHi  all\r\n".iter().copied())
+            .collect();
+        assert_eq!(verify_chain(&without_first, &keys).result, ArcResult::Fail);
+
+        // Unknown keys fail
+        assert_eq!(verify_chain(&twice, &Keys::new()).result, ArcResult::Fail);
+    }
+
+    #[test]
+    fn test_seal_failed_chain() {
+        let (key, _) = key_pair();
+        let sealer = ArcSealer::from_private_key("example.net", "fwd", key);
+        let message = b"From: alice@example.com\r\nSubject: Hi\r\n\r\nHi\r\n";
+
+        let once = sealed(&sealer, message, ArcResult::None);
+        let failed = sealed(&sealer, &once, ArcResult::Fail);
+        assert!(failed.starts_with(b"ARC-Seal: i=2; a=rsa-sha256; t="));
+        assert!(String::from_utf8_lossy(&failed).contains("cv=fail;"));
+
+        // A chain sealed as failed is not extended
+        assert!(sealer
+            .seal(&failed, "mx.example.net; arc=fail", ArcResult::Fail)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_arc_sets() {
+        let message = b"ARC-Seal: i=1; cv=none; b=x\r\nARC-Message-Signature: i=1; b=y\r\nARC-Authentication-Results: i=1; mx.example.net; spf=pass\r\nFrom: a@example.com\r\n\r\n";
+        let (fields, _) = header_fields(message);
+        assert_eq!(arc_sets(&fields).unwrap().len(), 1);
+
+        let duplicate = [&b"ARC-Seal: i=1; cv=none; b=z\r\n"[..], message].concat();
+        let (fields, _) = header_fields(&duplicate);
+        assert!(arc_sets(&fields).is_err());
+
+        let gap = b"ARC-Seal: i=2; cv=pass; b=x\r\nARC-Message-Signature: i=2; b=y\r\nARC-Authentication-Results: i=2; mx; spf=pass\r\n\r\n";
+        let (fields, _) = header_fields(gap);
+        assert!(arc_sets(&fields).is_err());
+    }
+}
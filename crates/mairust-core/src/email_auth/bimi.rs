@@ -0,0 +1,1188 @@
+//! BIMI (Brand Indicators for Message Identification)
+//!
+//! A domain publishes the logo to show with its mail in a TXT record at
+//! `<selector>._bimi.<domain>`:
+//!
+//! ```text
+//! v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem
+//! ```
+//!
+//! The logo of a received message is only looked up when the message
+//! passed DMARC under a quarantine or reject policy that applies to all of
+//! the domain's mail: nobody else can then send as the domain. The logo
+//! (`l=`) must be an SVG Tiny PS image. The Verified Mark Certificate
+//! (`a=`) ties the logo to the domain; it is validated against the
+//! configured roots, and the logo it embeds is shown instead of `l=`.
+//!
+//! The result is passed on in the BIMI-Location header, and the logo
+//! itself, base64-encoded, in BIMI-Indicator. Those headers are only ever
+//! this server's: the ones a message arrives with are removed.
+
+use super::dkim::{fold_base64, header_fields, parse_dkim_tags};
+use super::dmarc::{get_organizational_domain, DmarcEvaluation, DmarcPolicy, DmarcRecord};
+use super::{DmarcResult, DmarcVerifier};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use flate2::read::GzDecoder;
+use mairust_common::config::BimiConfig;
+use reqwest::{Client, Url};
+use rustls_pemfile::certs;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+use webpki::types::{CertificateDer, ServerName, TrustAnchor, UnixTime};
+use webpki::{anchor_from_trusted_cert, EndEntityCert, KeyUsage, ALL_VERIFICATION_ALGS};
+
+/// Header with the BIMI result of a message
+pub const LOCATION_HEADER: &str = "BIMI-Location";
+
+/// Header with the base64-encoded logo of a message
+pub const INDICATOR_HEADER: &str = "BIMI-Indicator";
+
+/// Selector of messages that do not name one
+const DEFAULT_SELECTOR: &str = "default";
+
+/// Largest logo accepted
+const MAX_INDICATOR_SIZE: usize = 32 * 1024;
+
+/// Largest VMC file accepted
+const MAX_VMC_SIZE: usize = 64 * 1024;
+
+/// Timeout of logo and VMC downloads
+const FETCH_TIMEOUT_SECS: u64 = 10;
+
+/// How long results of failed lookups are cached
+const FAILURE_CACHE: Duration = Duration::from_secs(300);
+
+/// Cached results above which expired ones are dropped
+const MAX_CACHED: usize = 10_000;
+
+/// id-kp-BIMI (1.3.6.1.5.5.7.3.31), the extended key usage of VMCs
+const BIMI_EKU: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x1f];
+
+/// id-pe-logotype (1.3.6.1.5.5.7.1.12), the extension VMCs embed their logo
+/// in (RFC 3709)
+const LOGOTYPE_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x0c];
+
+/// DER tag of the IA5String holding the logo's data URI
+const IA5_STRING_TAG: u8 = 0x16;
+
+/// Start of the logo's data URI
+const SVG_DATA_URI: &[u8] = b"data:image/svg+xml;base64,";
+
+/// Namespace of SVG elements
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+/// Elements SVG Tiny PS leaves out: scripts, animation, and anything that
+/// shows content from elsewhere
+const FORBIDDEN_ELEMENTS: &[&str] = &[
+    "script",
+    "foreignObject",
+    "image",
+    "a",
+    "animate",
+    "animateColor",
+    "animateMotion",
+    "animateTransform",
+    "set",
+    "video",
+    "audio",
+    "iframe",
+];
+
+/// BIMI result of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BimiResult {
+    /// The logo was found and is valid
+    Pass,
+    /// The domain has no BIMI record
+    None,
+    /// The domain publishes no logo
+    Declined,
+    /// The message does not qualify (DMARC not enforced)
+    Skipped,
+    /// The record, logo or VMC is invalid
+    Fail,
+    /// The lookup failed and may succeed later
+    TempError,
+}
+
+impl BimiResult {
+    /// Value in the Authentication-Results header
+    pub fn as_header_value(&self) -> &'static str {
+        match self {
+            BimiResult::Pass => "pass",
+            BimiResult::None => "none",
+            BimiResult::Declined => "declined",
+            BimiResult::Skipped => "skipped",
+            BimiResult::Fail => "fail",
+            BimiResult::TempError => "temperror",
+        }
+    }
+}
+
+/// Parsed BIMI record
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BimiRecord {
+    /// HTTPS URL of the SVG logo (l=)
+    pub location: Option<String>,
+    /// HTTPS URL of the VMC (a=)
+    pub authority: Option<String>,
+}
+
+impl BimiRecord {
+    /// Parse the value of a BIMI TXT record
+    pub fn parse(txt: &str) -> Result<Self> {
+        let mut tags = txt.split(';').map(str::trim).filter(|tag| !tag.is_empty());
+        // v=BIMI1 comes first
+        match tags.next().and_then(|tag| tag.split_once('=')) {
+            Some((name, value))
+                if name.trim().eq_ignore_ascii_case("v") && value.trim() == "BIMI1" => {}
+            _ => bail!("Not a BIMI1 record"),
+        }
+
+        let mut record = Self::default();
+        for tag in tags {
+            let Some((name, value)) = tag.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_lowercase().as_str() {
+                "l" => record.location = parse_uri(value)?,
+                "a" if value.eq_ignore_ascii_case("self") => record.authority = None,
+                "a" => record.authority = parse_uri(value)?,
+                _ => {}
+            }
+        }
+        Ok(record)
+    }
+
+    /// Whether the domain declines to show a logo (empty `l=` and `a=`)
+    pub fn is_declined(&self) -> bool {
+        self.location.is_none() && self.authority.is_none()
+    }
+
+    /// Value of the TXT record
+    pub fn to_txt(&self) -> String {
+        format!(
+            "v=BIMI1; l={}; a={}",
+            self.location.as_deref().unwrap_or_default(),
+            self.authority.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// URI of an `l=` or `a=` tag; empty means none
+fn parse_uri(value: &str) -> Result<Option<String>> {
+    // Early drafts allowed a list; only the first URI is used
+    let uri = value.split(',').next().unwrap_or_default().trim();
+    if uri.is_empty() {
+        return Ok(None);
+    }
+    let url = Url::parse(uri).map_err(|_| anyhow!("Invalid BIMI URI: {}", uri))?;
+    if url.scheme() != "https" {
+        bail!("BIMI URI is not HTTPS: {}", uri);
+    }
+    Ok(Some(uri.to_string()))
+}
+
+/// Lowercased selector, if it is a valid DNS label sequence
+fn normalize_selector(selector: &str) -> Option<String> {
+    let selector = selector.trim().to_lowercase();
+    let valid = !selector.is_empty()
+        && selector.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    valid.then_some(selector)
+}
+
+/// Whether a DMARC record found at `policy_domain` enforces its policy on
+/// all of `domain`'s mail
+pub fn policy_enforced(record: &DmarcRecord, policy_domain: &str, domain: &str) -> bool {
+    let policy = if policy_domain.eq_ignore_ascii_case(domain) {
+        record.policy
+    } else {
+        // The organizational domain itself must be enforced too
+        if record.policy == DmarcPolicy::None {
+            return false;
+        }
+        record.subdomain_policy.unwrap_or(record.policy)
+    };
+    record.percentage >= 100 && policy != DmarcPolicy::None
+}
+
+/// Whether a message's DMARC result lets its logo be shown
+pub fn dmarc_enforced(dmarc: &DmarcEvaluation, from_domain: &str) -> bool {
+    dmarc.result == DmarcResult::Pass
+        && matches!(
+            (&dmarc.policy_domain, &dmarc.record),
+            (Some(policy_domain), Some(record)) if policy_enforced(record, policy_domain, from_domain)
+        )
+}
+
+/// Value of a raw header field, unfolded
+fn field_value(raw: &[u8]) -> String {
+    let value = raw
+        .iter()
+        .position(|&b| b == b':')
+        .map(|colon| &raw[colon + 1..])
+        .unwrap_or_default();
+    String::from_utf8_lossy(value)
+        .replace("\r\n", "")
+        .replace('\n', "")
+}
+
+/// Selector of a message: that of its BIMI-Selector header when a DKIM
+/// signature aligned with `from_domain` covers the header, otherwise
+/// `default`
+pub fn message_selector(message: &[u8], from_domain: &str) -> String {
+    let (fields, _) = header_fields(message);
+    let from_org = get_organizational_domain(&from_domain.to_lowercase());
+    let signed = fields
+        .iter()
+        .filter(|(name, _)| name == "dkim-signature")
+        .any(|(_, raw)| {
+            let Ok(tags) = parse_dkim_tags(&field_value(raw)) else {
+                return false;
+            };
+            let covers = tags.get("h").is_some_and(|names| {
+                names
+                    .split(':')
+                    .any(|name| name.trim().eq_ignore_ascii_case("bimi-selector"))
+            });
+            let aligned = tags.get("d").is_some_and(|domain| {
+                from_org.is_some() && get_organizational_domain(&domain.to_lowercase()) == from_org
+            });
+            covers && aligned
+        });
+    if !signed {
+        return DEFAULT_SELECTOR.to_string();
+    }
+
+    fields
+        .iter()
+        .find(|(name, _)| name == "bimi-selector")
+        .and_then(|(_, raw)| {
+            let tags = parse_dkim_tags(&field_value(raw)).ok()?;
+            if tags.get("v").map(String::as_str) != Some("BIMI1") {
+                return None;
+            }
+            normalize_selector(tags.get("s")?)
+        })
+        .unwrap_or_else(|| DEFAULT_SELECTOR.to_string())
+}
+
+/// The message without BIMI-Location and BIMI-Indicator headers
+pub fn strip_headers(message: &[u8]) -> Cow<'_, [u8]> {
+    let (fields, _) = header_fields(message);
+    let base = message.as_ptr() as usize;
+    let removed: Vec<(usize, usize)> = fields
+        .iter()
+        .filter(|(name, _)| name == "bimi-location" || name == "bimi-indicator")
+        .map(|(_, raw)| {
+            let start = raw.as_ptr() as usize - base;
+            let mut end = start + raw.len();
+            if message[end..].starts_with(b"\r\n") {
+                end += 2;
+            } else if message[end..].starts_with(b"\n") {
+                end += 1;
+            }
+            (start, end)
+        })
+        .collect();
+    if removed.is_empty() {
+        return Cow::Borrowed(message);
+    }
+
+    let mut kept = Vec::with_capacity(message.len());
+    let mut position = 0;
+    for (start, end) in removed {
+        kept.extend_from_slice(&message[position..start]);
+        position = end;
+    }
+    kept.extend_from_slice(&message[position..]);
+    Cow::Owned(kept)
+}
+
+/// Check that a logo is an SVG Tiny PS image that shows nothing from
+/// elsewhere
+pub fn check_svg(data: &[u8]) -> Result<()> {
+    if data.len() > MAX_INDICATOR_SIZE {
+        bail!("The logo is larger than {} bytes", MAX_INDICATOR_SIZE);
+    }
+    let text = std::str::from_utf8(data).map_err(|_| anyhow!("The logo is not UTF-8"))?;
+    let document = roxmltree::Document::parse(text)
+        .map_err(|e| anyhow!("The logo is not valid XML: {}", e))?;
+
+    let root = document.root_element();
+    if root.tag_name().name() != "svg" || root.tag_name().namespace() != Some(SVG_NAMESPACE) {
+        bail!("The logo is not an SVG image");
+    }
+    if root.attribute("baseProfile") != Some("tiny-ps") {
+        bail!("The logo is not SVG Tiny PS (baseProfile=\"tiny-ps\")");
+    }
+    if root.attribute("x").is_some() || root.attribute("y").is_some() {
+        bail!("The logo's svg element has x or y attributes");
+    }
+    if !root
+        .children()
+        .any(|node| node.is_element() && node.tag_name().name() == "title")
+    {
+        bail!("The logo has no title");
+    }
+
+    for node in root.descendants().filter(|node| node.is_element()) {
+        let name = node.tag_name().name();
+        if FORBIDDEN_ELEMENTS.contains(&name) {
+            bail!("The logo contains a {} element", name);
+        }
+        for attribute in node.attributes() {
+            if attribute.name() == "href" && !attribute.value().starts_with('#') {
+                bail!("The logo refers to {}", attribute.value());
+            }
+            if attribute.name().starts_with("on") {
+                bail!("The logo has event handlers");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Length of the IA5String whose content starts at `start`, read from its
+/// DER header
+fn ia5_string_len(der: &[u8], start: usize) -> Option<usize> {
+    if start >= 2 && der[start - 1] < 0x80 && der[start - 2] == IA5_STRING_TAG {
+        return Some(der[start - 1] as usize);
+    }
+    // Long form: 0x80 | number of length octets, then the length
+    for octets in 1..=3usize {
+        let header = start.checked_sub(octets + 2)?;
+        if der[header] == IA5_STRING_TAG && der[header + 1] == 0x80 | octets as u8 {
+            let len = der[header + 2..start]
+                .iter()
+                .fold(0usize, |len, &b| len << 8 | b as usize);
+            return Some(len);
+        }
+    }
+    None
+}
+
+/// SVG logo a VMC embeds as a data URI in its logotype extension,
+/// gunzipped
+pub fn logotype_svg(cert_der: &[u8]) -> Option<Vec<u8>> {
+    let extension = find(cert_der, LOGOTYPE_OID)?;
+    let start = extension + find(&cert_der[extension..], SVG_DATA_URI)?;
+    let len = ia5_string_len(cert_der, start)?;
+    let uri = cert_der.get(start..start + len)?;
+    let data = BASE64.decode(&uri[SVG_DATA_URI.len()..]).ok()?;
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Some(data);
+    }
+    let mut svg = Vec::new();
+    GzDecoder::new(data.as_slice())
+        .take(MAX_INDICATOR_SIZE as u64 + 1)
+        .read_to_end(&mut svg)
+        .ok()?;
+    Some(svg)
+}
+
+/// Position of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Validate a VMC, followed by its intermediates, for one of `domains`;
+/// returns the logo it embeds
+fn validate_vmc_chain(
+    chain: &[CertificateDer<'_>],
+    trust_anchors: &[TrustAnchor<'_>],
+    domains: &[&str],
+    now: UnixTime,
+) -> Result<Option<Vec<u8>>> {
+    let (vmc, intermediates) = chain
+        .split_first()
+        .ok_or_else(|| anyhow!("The VMC file has no certificate"))?;
+    let cert = EndEntityCert::try_from(vmc).map_err(|e| anyhow!("Invalid VMC: {}", e))?;
+    cert.verify_for_usage(
+        ALL_VERIFICATION_ALGS,
+        trust_anchors,
+        intermediates,
+        now,
+        KeyUsage::required(BIMI_EKU),
+        None,
+        None,
+    )
+    .map_err(|e| anyhow!("The VMC is not trusted: {}", e))?;
+
+    let valid_for = domains.iter().any(|domain| {
+        ServerName::try_from(*domain)
+            .is_ok_and(|name| cert.verify_is_valid_for_subject_name(&name).is_ok())
+    });
+    if !valid_for {
+        bail!("The VMC is not for {}", domains.join(" or "));
+    }
+    Ok(logotype_svg(vmc))
+}
+
+/// Root certificates in a PEM file
+fn load_trust_anchors(path: &str) -> Result<Vec<TrustAnchor<'static>>> {
+    let pem = std::fs::read(path)?;
+    let mut anchors = Vec::new();
+    for cert in certs(&mut pem.as_slice()) {
+        let cert = cert?;
+        let anchor = anchor_from_trusted_cert(&cert)
+            .map_err(|e| anyhow!("Invalid root certificate in {}: {}", path, e))?;
+        anchors.push(anchor.to_owned());
+    }
+    Ok(anchors)
+}
+
+/// BIMI result of a message
+#[derive(Debug, Clone)]
+pub struct BimiEvaluation {
+    pub result: BimiResult,
+    /// Why the result is not a pass
+    pub reason: Option<String>,
+    pub selector: String,
+    /// Domain the record was found at
+    pub domain: Option<String>,
+    pub record: Option<BimiRecord>,
+    /// The VMC was validated
+    pub authority_validated: bool,
+    /// The SVG logo
+    pub indicator: Option<Vec<u8>>,
+}
+
+impl BimiEvaluation {
+    fn new(result: BimiResult, selector: &str, reason: impl Into<String>) -> Self {
+        Self {
+            result,
+            reason: Some(reason.into()),
+            selector: selector.to_string(),
+            domain: None,
+            record: None,
+            authority_validated: false,
+            indicator: None,
+        }
+    }
+
+    /// Headers to add to the message, each ending with CRLF; empty unless
+    /// the result is a pass
+    pub fn headers(&self) -> String {
+        let (BimiResult::Pass, Some(record), Some(indicator)) =
+            (self.result, &self.record, &self.indicator)
+        else {
+            return String::new();
+        };
+        format!(
+            "{}: {}\r\n{}: {}\r\n",
+            LOCATION_HEADER,
+            record.to_txt(),
+            INDICATOR_HEADER,
+            fold_base64(&BASE64.encode(indicator))
+        )
+    }
+
+    /// Clause of the Authentication-Results header
+    pub fn to_auth_result(&self) -> String {
+        let mut clause = format!("bimi={}", self.result.as_header_value());
+        if self.result == BimiResult::Pass {
+            if let Some(domain) = &self.domain {
+                clause.push_str(&format!(" header.d={}", domain));
+            }
+            clause.push_str(&format!(" header.selector={}", self.selector));
+            match self.record.as_ref().and_then(|r| r.authority.as_ref()) {
+                Some(authority) if self.authority_validated => clause.push_str(&format!(
+                    " policy.authority=pass policy.authority-uri={}",
+                    authority
+                )),
+                _ => clause.push_str(" policy.authority=none"),
+            }
+        } else if let Some(reason) = &self.reason {
+            clause.push_str(&format!(" ({})", reason));
+        }
+        clause
+    }
+}
+
+/// BIMI record a hosted domain publishes, from its settings
+/// (`extra_settings.bimi`):
+///
+/// ```json
+/// {"selector": "default", "location": "https://example.com/logo.svg",
+///  "authority": "https://example.com/vmc.pem"}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BimiPublication {
+    pub selector: String,
+    pub record: BimiRecord,
+}
+
+impl BimiPublication {
+    /// Read `bimi` from domain settings; `None` when the domain has no
+    /// valid logo URL
+    pub fn from_domain_settings(extra_settings: &serde_json::Value) -> Option<Self> {
+        let settings = extra_settings.get("bimi")?.as_object()?;
+        let selector = settings
+            .get("selector")
+            .and_then(|v| v.as_str())
+            .and_then(normalize_selector)
+            .unwrap_or_else(|| DEFAULT_SELECTOR.to_string());
+        let uri = |name: &str| {
+            settings
+                .get(name)
+                .and_then(|v| v.as_str())
+                .and_then(|v| parse_uri(v).ok().flatten())
+        };
+        let record = BimiRecord {
+            location: uri("location"),
+            authority: uri("authority"),
+        };
+        record
+            .location
+            .is_some()
+            .then_some(Self { selector, record })
+    }
+
+    /// Name of the TXT record
+    pub fn host(&self, domain: &str) -> String {
+        format!("{}._bimi.{}", self.selector, domain)
+    }
+}
+
+/// Whether a hosted domain's BIMI setup works
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BimiPublicationCheck {
+    /// Record published at the selector
+    pub published: Option<String>,
+    /// The published record is the one the settings describe
+    pub published_matches: bool,
+    /// Policy the domain's DMARC record applies to its mail
+    pub dmarc_policy: Option<String>,
+    /// The DMARC policy lets receivers show the logo
+    pub dmarc_enforced: bool,
+    /// Why the logo cannot be used
+    pub logo_error: Option<String>,
+    /// The VMC was validated
+    pub authority_validated: bool,
+    /// Why the VMC cannot be used
+    pub authority_error: Option<String>,
+}
+
+impl BimiPublicationCheck {
+    /// Whether receivers can show the logo
+    pub fn is_ready(&self) -> bool {
+        self.published_matches
+            && self.dmarc_enforced
+            && self.logo_error.is_none()
+            && self.authority_error.is_none()
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    evaluation: Arc<BimiEvaluation>,
+    expires_at: Instant,
+}
+
+/// BIMI lookups, shared by the sessions of an SMTP server
+pub struct Bimi {
+    config: BimiConfig,
+    resolver: TokioAsyncResolver,
+    client: Client,
+    trust_anchors: Vec<TrustAnchor<'static>>,
+    cache: Mutex<HashMap<(String, String), CacheEntry>>,
+}
+
+impl Bimi {
+    /// Create a BIMI checker, reading the VMC roots
+    pub fn new(config: BimiConfig) -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            if config.enabled {
+                warn!("Failed to read the system resolver configuration: {}", e);
+            }
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+        let client = Client::builder()
+            .timeout(Duration::from_secs(FETCH_TIMEOUT_SECS))
+            .build()
+            .expect("Failed to create HTTP client");
+        let trust_anchors = match &config.vmc_roots {
+            Some(path) => load_trust_anchors(path).unwrap_or_else(|e| {
+                warn!("Failed to read the VMC roots from {}: {}", path, e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        Self {
+            config,
+            resolver,
+            client,
+            trust_anchors,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether logos are looked up
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// BIMI result of a message from `from_domain`, from the cache when
+    /// fresh
+    pub async fn evaluate(
+        &self,
+        message: &[u8],
+        from_domain: &str,
+        dmarc: &DmarcEvaluation,
+    ) -> Arc<BimiEvaluation> {
+        let selector = message_selector(message, from_domain);
+        if !dmarc_enforced(dmarc, from_domain) {
+            return Arc::new(BimiEvaluation::new(
+                BimiResult::Skipped,
+                &selector,
+                "DMARC policy not enforced",
+            ));
+        }
+
+        let key = (selector, from_domain.to_lowercase());
+        let now = Instant::now();
+        if let Some(entry) = self.cache.lock().unwrap().get(&key) {
+            if entry.expires_at > now {
+                return entry.evaluation.clone();
+            }
+        }
+
+        let evaluation = Arc::new(self.lookup(&key.0, &key.1).await);
+        match evaluation.result {
+            BimiResult::Pass => info!(
+                "BIMI logo of {} (selector {}) found",
+                key.1, evaluation.selector
+            ),
+            BimiResult::Fail | BimiResult::TempError => debug!(
+                "BIMI lookup of {} failed: {}",
+                key.1,
+                evaluation.reason.as_deref().unwrap_or_default()
+            ),
+            _ => {}
+        }
+
+        let ttl = Duration::from_secs(self.config.cache_secs);
+        let ttl = if evaluation.result == BimiResult::TempError {
+            ttl.min(FAILURE_CACHE)
+        } else {
+            ttl
+        };
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, entry| entry.expires_at > now);
+        }
+        cache.insert(
+            key,
+            CacheEntry {
+                evaluation: evaluation.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        evaluation
+    }
+
+    /// Look up the record, VMC and logo of `domain`
+    async fn lookup(&self, selector: &str, domain: &str) -> BimiEvaluation {
+        let (record_domain, txt) = match self.find_record(selector, domain).await {
+            Ok(Some(found)) => found,
+            Ok(None) => return BimiEvaluation::new(BimiResult::None, selector, "no record"),
+            Err(e) => return BimiEvaluation::new(BimiResult::TempError, selector, e.to_string()),
+        };
+        let record = match BimiRecord::parse(&txt) {
+            Ok(record) => record,
+            Err(e) => return BimiEvaluation::new(BimiResult::Fail, selector, e.to_string()),
+        };
+
+        let mut evaluation = BimiEvaluation::new(BimiResult::Fail, selector, "");
+        evaluation.domain = Some(record_domain.clone());
+        evaluation.record = Some(record.clone());
+        let fail = |mut evaluation: BimiEvaluation, result, reason: String| {
+            evaluation.result = result;
+            evaluation.reason = Some(reason);
+            evaluation
+        };
+        if record.is_declined() {
+            return fail(evaluation, BimiResult::Declined, "no logo published".into());
+        }
+
+        if let Some(authority) = &record.authority {
+            if self.trust_anchors.is_empty() {
+                debug!("No VMC roots; the VMC of {} is not validated", domain);
+            } else {
+                match self
+                    .validate_vmc(authority, &[domain, &record_domain])
+                    .await
+                {
+                    Ok(logo) => {
+                        evaluation.authority_validated = true;
+                        evaluation.indicator = logo;
+                    }
+                    Err(e) => return fail(evaluation, BimiResult::Fail, e.to_string()),
+                }
+            }
+        }
+        if self.config.require_vmc && !evaluation.authority_validated {
+            return fail(evaluation, BimiResult::Fail, "no valid VMC".into());
+        }
+
+        if evaluation.indicator.is_none() {
+            let Some(location) = &record.location else {
+                return fail(evaluation, BimiResult::Fail, "no logo".into());
+            };
+            match self.fetch(location, MAX_INDICATOR_SIZE).await {
+                Ok(logo) => evaluation.indicator = Some(logo),
+                Err(e) => return fail(evaluation, BimiResult::TempError, e.to_string()),
+            }
+        }
+        if let Err(e) = check_svg(evaluation.indicator.as_deref().unwrap_or_default()) {
+            return fail(evaluation, BimiResult::Fail, e.to_string());
+        }
+
+        evaluation.result = BimiResult::Pass;
+        evaluation.reason = None;
+        evaluation
+    }
+
+    /// BIMI record for `selector` at `domain` or, failing that, its
+    /// organizational domain, with the domain it was found at
+    async fn find_record(&self, selector: &str, domain: &str) -> Result<Option<(String, String)>> {
+        if let Some(txt) = self
+            .txt_record(&format!("{}._bimi.{}", selector, domain))
+            .await?
+        {
+            return Ok(Some((domain.to_string(), txt)));
+        }
+        match get_organizational_domain(domain) {
+            Some(org_domain) if org_domain != domain => Ok(self
+                .txt_record(&format!("{}._bimi.{}", selector, org_domain))
+                .await?
+                .map(|txt| (org_domain, txt))),
+            _ => Ok(None),
+        }
+    }
+
+    /// The BIMI TXT record at `name`
+    async fn txt_record(&self, name: &str) -> Result<Option<String>> {
+        match self.resolver.txt_lookup(format!("{}.", name)).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|record| {
+                    record
+                        .txt_data()
+                        .iter()
+                        .map(|d| String::from_utf8_lossy(d))
+                        .collect::<String>()
+                })
+                .find(|txt| txt.trim_start().to_lowercase().starts_with("v=bimi1"))),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(None),
+            Err(e) => Err(anyhow!("DNS lookup of {} failed: {}", name, e)),
+        }
+    }
+
+    /// Download `url`, refusing more than `limit` bytes
+    async fn fetch(&self, url: &str, limit: usize) -> Result<Vec<u8>> {
+        let mut response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            bail!("{} returned {}", url, response.status());
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            data.extend_from_slice(&chunk);
+            if data.len() > limit {
+                bail!("{} is larger than {} bytes", url, limit);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Download and validate the VMC at `authority` for one of `domains`;
+    /// returns the logo it embeds
+    async fn validate_vmc(&self, authority: &str, domains: &[&str]) -> Result<Option<Vec<u8>>> {
+        let pem = self.fetch(authority, MAX_VMC_SIZE).await?;
+        let chain = certs(&mut pem.as_slice()).collect::<std::io::Result<Vec<_>>>()?;
+        validate_vmc_chain(&chain, &self.trust_anchors, domains, UnixTime::now())
+    }
+
+    /// Check what receivers will find of a hosted domain's BIMI setup
+    pub async fn check_publication(
+        &self,
+        domain: &str,
+        publication: &BimiPublication,
+    ) -> BimiPublicationCheck {
+        let mut check = BimiPublicationCheck::default();
+
+        if let Ok(Some(txt)) = self.txt_record(&publication.host(domain)).await {
+            check.published_matches =
+                BimiRecord::parse(&txt).is_ok_and(|record| record == publication.record);
+            check.published = Some(txt);
+        }
+
+        if let Ok(verifier) = DmarcVerifier::new().await {
+            if let Ok(Some((policy_domain, record))) = verifier.lookup(domain).await {
+                let policy = if policy_domain == domain {
+                    record.policy
+                } else {
+                    record.subdomain_policy.unwrap_or(record.policy)
+                };
+                check.dmarc_policy = Some(policy.as_str().to_string());
+                check.dmarc_enforced = policy_enforced(&record, &policy_domain, domain);
+            }
+        }
+
+        let mut logo = None;
+        if let Some(authority) = &publication.record.authority {
+            if self.trust_anchors.is_empty() {
+                check.authority_error = Some("No VMC roots are configured".to_string());
+            } else {
+                match self.validate_vmc(authority, &[domain]).await {
+                    Ok(embedded) => {
+                        check.authority_validated = true;
+                        logo = embedded;
+                    }
+                    Err(e) => check.authority_error = Some(e.to_string()),
+                }
+            }
+        }
+        if logo.is_none() {
+            if let Some(location) = &publication.record.location {
+                match self.fetch(location, MAX_INDICATOR_SIZE).await {
+                    Ok(data) => logo = Some(data),
+                    Err(e) => check.logo_error = Some(e.to_string()),
+                }
+            }
+        }
+        if let Some(logo) = logo {
+            check.logo_error = check_svg(&logo).err().map(|e| e.to_string());
+        }
+        check
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const LOGO: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" version="1.2" baseProfile="tiny-ps" viewBox="0 0 64 64"><title>Example</title><circle cx="32" cy="32" r="30" fill="#0a5"/></svg>"##;
+
+    fn dmarc(
+        policy: DmarcPolicy,
+        subdomain_policy: Option<DmarcPolicy>,
+        percentage: u8,
+        policy_domain: &str,
+    ) -> DmarcEvaluation {
+        DmarcEvaluation {
+            result: DmarcResult::Pass,
+            policy_domain: Some(policy_domain.to_string()),
+            record: Some(DmarcRecord {
+                policy,
+                subdomain_policy,
+                percentage,
+                ..DmarcRecord::default()
+            }),
+            spf_aligned: true,
+            dkim_aligned: true,
+        }
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let record = BimiRecord::parse(
+            "v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem",
+        )
+        .unwrap();
+        assert_eq!(
+            record.location.as_deref(),
+            Some("https://example.com/logo.svg")
+        );
+        assert_eq!(
+            record.authority.as_deref(),
+            Some("https://example.com/vmc.pem")
+        );
+        assert_eq!(
+            record.to_txt(),
+            "v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem"
+        );
+
+        let record =
+            BimiRecord::parse("v=BIMI1;l=https://a.example/1.svg,https://a.example/2.svg;a=self")
+                .unwrap();
+        assert_eq!(record.location.as_deref(), Some("https://a.example/1.svg"));
+        assert!(record.authority.is_none());
+
+        assert!(BimiRecord::parse("v=BIMI1; l=; a=").unwrap().is_declined());
+        assert!(BimiRecord::parse("v=BIMI1;").unwrap().is_declined());
+
+        assert!(BimiRecord::parse("l=https://example.com/logo.svg; v=BIMI1").is_err());
+        assert!(BimiRecord::parse("v=BIMI2; l=https://example.com/logo.svg").is_err());
+        assert!(BimiRecord::parse("v=BIMI1; l=http://example.com/logo.svg").is_err());
+        assert!(BimiRecord::parse("v=BIMI1; a=not a url").is_err());
+    }
+
+    #[test]
+    fn test_dmarc_enforced() {
+        let domain = "example.com";
+        assert!(dmarc_enforced(
+            &dmarc(DmarcPolicy::Reject, None, 100, domain),
+            domain
+        ));
+        assert!(dmarc_enforced(
+            &dmarc(DmarcPolicy::Quarantine, None, 100, domain),
+            domain
+        ));
+        assert!(!dmarc_enforced(
+            &dmarc(DmarcPolicy::None, None, 100, domain),
+            domain
+        ));
+        assert!(!dmarc_enforced(
+            &dmarc(DmarcPolicy::Reject, None, 50, domain),
+            domain
+        ));
+
+        let mut failed = dmarc(DmarcPolicy::Reject, None, 100, domain);
+        failed.result = DmarcResult::Fail(DmarcPolicy::Reject);
+        assert!(!dmarc_enforced(&failed, domain));
+        assert!(!dmarc_enforced(
+            &DmarcEvaluation::without_record(DmarcResult::None),
+            domain
+        ));
+
+        // Subdomains get the sp= policy, and the organizational domain must
+        // be enforced itself
+        let sub = "mail.example.com";
+        assert!(dmarc_enforced(
+            &dmarc(DmarcPolicy::Reject, None, 100, domain),
+            sub
+        ));
+        assert!(!dmarc_enforced(
+            &dmarc(DmarcPolicy::Reject, Some(DmarcPolicy::None), 100, domain),
+            sub
+        ));
+        assert!(!dmarc_enforced(
+            &dmarc(DmarcPolicy::None, Some(DmarcPolicy::Reject), 100, domain),
+            sub
+        ));
+    }
+
+    #[test]
+    fn test_message_selector() {
+        let signed = b"DKIM-Signature: v=1; a=rsa-sha256; d=mail.example.com; s=k1;\r\n\th=from:to:subject:bimi-selector; bh=x; b=y\r\nBIMI-Selector: v=BIMI1; s=Brand\r\nFrom: news@example.com\r\n\r\nBody\r\n";
+        assert_eq!(message_selector(signed, "example.com"), "brand");
+
+        // Another domain's signature does not count
+        assert_eq!(message_selector(signed, "example.net"), "default");
+
+        let unsigned = b"DKIM-Signature: v=1; d=example.com; h=from:to; b=y\r\nBIMI-Selector: v=BIMI1; s=brand\r\n\r\n";
+        assert_eq!(message_selector(unsigned, "example.com"), "default");
+
+        let invalid = b"DKIM-Signature: v=1; d=example.com; h=bimi-selector; b=y\r\nBIMI-Selector: v=BIMI1; s=bad selector\r\n\r\n";
+        assert_eq!(message_selector(invalid, "example.com"), "default");
+    }
+
+    #[test]
+    fn test_strip_headers() {
+        let message = b"From: a@example.com\r\nBIMI-Location: v=BIMI1; l=https://evil.example/x.svg\r\nSubject: Hi\r\nbimi-indicator: PHN2Zz4=\r\n\tPHN2Zz4=\r\n\r\nBIMI-Location: kept in the body\r\n";
+        assert_eq!(
+            &*strip_headers(message),
+            &b"From: a@example.com\r\nSubject: Hi\r\n\r\nBIMI-Location: kept in the body\r\n"[..]
+        );
+
+        let clean = b"From: a@example.com\r\n\r\nBody\r\n";
+        assert!(matches!(strip_headers(clean), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_check_svg() {
+        assert!(check_svg(LOGO.as_bytes()).is_ok());
+
+        for (svg, error) in [
+            (LOGO.replace("tiny-ps", "tiny"), "not SVG Tiny PS"),
+            (LOGO.replace("<title>Example</title>", ""), "no title"),
+            (
+                LOGO.replace("<circle", "<script>alert(1)</script><circle"),
+                "script",
+            ),
+            (
+                LOGO.replace(
+                    "<circle",
+                    "<use href=\"https://tracker.example/x.svg#a\"/><circle",
+                ),
+                "refers to",
+            ),
+            (
+                LOGO.replace("<circle", "<circle onload=\"x()\""),
+                "event handlers",
+            ),
+            (
+                LOGO.replace("http://www.w3.org/2000/svg", "urn:other"),
+                "not an SVG",
+            ),
+            ("not xml".to_string(), "not valid XML"),
+        ] {
+            let e = check_svg(svg.as_bytes()).unwrap_err().to_string();
+            assert!(e.contains(error), "{}: {}", error, e);
+        }
+
+        let doctype = format!("<!DOCTYPE svg [<!ENTITY x \"y\">]>{}", LOGO);
+        assert!(check_svg(doctype.as_bytes()).is_err());
+        assert!(check_svg(&vec![b' '; MAX_INDICATOR_SIZE + 1]).is_err());
+    }
+
+    /// Fake certificate bytes with a logotype extension holding `uri`
+    fn certificate_with_logotype(uri: &[u8]) -> Vec<u8> {
+        let mut der = vec![0x30, 0x82, 0x01, 0x00, 0x06, 0x08];
+        der.extend_from_slice(LOGOTYPE_OID);
+        der.extend_from_slice(&[0x04, 0x82, 0x00, 0x10, 0x30, 0x10]);
+        der.push(IA5_STRING_TAG);
+        if uri.len() < 0x80 {
+            der.push(uri.len() as u8);
+        } else {
+            der.extend_from_slice(&[0x82, (uri.len() >> 8) as u8, uri.len() as u8]);
+        }
+        der.extend_from_slice(uri);
+        // Following DER that looks like base64
+        der.extend_from_slice(b"0AB=");
+        der
+    }
+
+    #[test]
+    fn test_logotype_svg() {
+        let mut uri = SVG_DATA_URI.to_vec();
+        uri.extend_from_slice(BASE64.encode(LOGO).as_bytes());
+        assert_eq!(
+            logotype_svg(&certificate_with_logotype(&uri)).as_deref(),
+            Some(LOGO.as_bytes())
+        );
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(LOGO.as_bytes()).unwrap();
+        let mut uri = SVG_DATA_URI.to_vec();
+        uri.extend_from_slice(BASE64.encode(gzip.finish().unwrap()).as_bytes());
+        assert_eq!(
+            logotype_svg(&certificate_with_logotype(&uri)).as_deref(),
+            Some(LOGO.as_bytes())
+        );
+
+        // Logos referenced by URL are not embedded
+        let referenced = certificate_with_logotype(b"https://example.com/logo.svg");
+        assert_eq!(logotype_svg(&referenced), None);
+        assert_eq!(logotype_svg(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn test_evaluation_headers() {
+        let mut evaluation = BimiEvaluation::new(BimiResult::Pass, "default", "");
+        evaluation.reason = None;
+        evaluation.domain = Some("example.com".to_string());
+        evaluation.record = Some(BimiRecord {
+            location: Some("https://example.com/logo.svg".to_string()),
+            authority: Some("https://example.com/vmc.pem".to_string()),
+        });
+        evaluation.authority_validated = true;
+        evaluation.indicator = Some(LOGO.as_bytes().to_vec());
+
+        let headers = evaluation.headers();
+        let mut lines = headers.split("\r\n");
+        assert_eq!(
+            lines.next(),
+            Some("BIMI-Location: v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem")
+        );
+        assert!(lines.next().unwrap().starts_with("BIMI-Indicator: PHN2Zy"));
+        assert!(lines.next().unwrap().starts_with('\t'));
+        assert!(headers.ends_with("\r\n"));
+        let encoded: String = headers
+            .split_once("BIMI-Indicator: ")
+            .unwrap()
+            .1
+            .split_whitespace()
+            .collect();
+        assert_eq!(BASE64.decode(encoded).unwrap(), LOGO.as_bytes());
+
+        assert_eq!(
+            evaluation.to_auth_result(),
+            "bimi=pass header.d=example.com header.selector=default policy.authority=pass policy.authority-uri=https://example.com/vmc.pem"
+        );
+        evaluation.authority_validated = false;
+        assert_eq!(
+            evaluation.to_auth_result(),
+            "bimi=pass header.d=example.com header.selector=default policy.authority=none"
+        );
+
+        let skipped =
+            BimiEvaluation::new(BimiResult::Skipped, "default", "DMARC policy not enforced");
+        assert_eq!(skipped.headers(), "");
+        assert_eq!(
+            skipped.to_auth_result(),
+            "bimi=skipped (DMARC policy not enforced)"
+        );
+    }
+
+    #[test]
+    fn test_publication_from_domain_settings() {
+        assert_eq!(
+            BimiPublication::from_domain_settings(&serde_json::json!({})),
+            None
+        );
+        assert_eq!(
+            BimiPublication::from_domain_settings(
+                &serde_json::json!({"bimi": {"location": "http://example.com/logo.svg"}})
+            ),
+            None
+        );
+
+        let publication = BimiPublication::from_domain_settings(&serde_json::json!({"bimi": {
+            "selector": "Brand",
+            "location": "https://example.com/logo.svg",
+            "authority": "https://example.com/vmc.pem"
+        }}))
+        .unwrap();
+        assert_eq!(publication.selector, "brand");
+        assert_eq!(publication.host("example.com"), "brand._bimi.example.com");
+        assert_eq!(
+            publication.record.to_txt(),
+            "v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem"
+        );
+
+        let publication = BimiPublication::from_domain_settings(
+            &serde_json::json!({"bimi": {"location": "https://example.com/logo.svg"}}),
+        )
+        .unwrap();
+        assert_eq!(publication.selector, "default");
+        assert!(publication.record.authority.is_none());
+    }
+
+    #[test]
+    fn test_validate_vmc_chain_without_certificate() {
+        let e = validate_vmc_chain(&[], &[], &["example.com"], UnixTime::now()).unwrap_err();
+        assert!(e.to_string().contains("no certificate"));
+        let e = validate_vmc_chain(
+            &[CertificateDer::from(b"garbage".to_vec())],
+            &[],
+            &["example.com"],
+            UnixTime::now(),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("Invalid VMC"));
+    }
+}
@@ -28,6 +28,17 @@ impl Default for DmarcPolicy {
     }
 }
 
+impl DmarcPolicy {
+    /// Tag value (p=, sp=) and aggregate report disposition
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DmarcPolicy::None => "none",
+            DmarcPolicy::Quarantine => "quarantine",
+            DmarcPolicy::Reject => "reject",
+        }
+    }
+}
+
 /// DMARC verification result
 #[derive(Debug, Clone, PartialEq)]
 pub enum DmarcResult {
@@ -121,6 +132,44 @@ impl Default for AlignmentMode {
     }
 }
 
+impl AlignmentMode {
+    /// Tag value (adkim=, aspf=)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlignmentMode::Strict => "s",
+            AlignmentMode::Relaxed => "r",
+        }
+    }
+}
+
+/// DMARC evaluation of a message, with the details aggregate reports need
+#[derive(Debug, Clone)]
+pub struct DmarcEvaluation {
+    pub result: DmarcResult,
+    /// Domain the record was published at: the From domain or its
+    /// organizational domain
+    pub policy_domain: Option<String>,
+    /// The record that was applied
+    pub record: Option<DmarcRecord>,
+    /// SPF passed for a domain aligned with the From domain
+    pub spf_aligned: bool,
+    /// DKIM passed for a domain aligned with the From domain
+    pub dkim_aligned: bool,
+}
+
+impl DmarcEvaluation {
+    /// Evaluation that found no usable record
+    pub fn without_record(result: DmarcResult) -> Self {
+        Self {
+            result,
+            policy_domain: None,
+            record: None,
+            spf_aligned: false,
+            dkim_aligned: false,
+        }
+    }
+}
+
 /// DMARC verifier
 pub struct DmarcVerifier {
     resolver: TokioAsyncResolver,
@@ -150,16 +199,39 @@ impl DmarcVerifier {
         spf_result: &SpfResult,
         dkim_result: &DkimResult,
     ) -> DmarcResult {
+        self.evaluate(
+            from_domain,
+            mail_from_domain,
+            dkim_domain,
+            spf_result,
+            dkim_result,
+        )
+        .await
+        .result
+    }
+
+    /// Verify DMARC for a message, keeping the record and the alignment
+    /// results
+    ///
+    /// Takes the same arguments as [`DmarcVerifier::verify`].
+    pub async fn evaluate(
+        &self,
+        from_domain: &str,
+        mail_from_domain: Option<&str>,
+        dkim_domain: Option<&str>,
+        spf_result: &SpfResult,
+        dkim_result: &DkimResult,
+    ) -> DmarcEvaluation {
         // Fetch DMARC record
-        let dmarc_record = match self.fetch_dmarc_record(from_domain).await {
-            Ok(Some(record)) => record,
+        let (policy_domain, dmarc_record) = match self.fetch_dmarc_record(from_domain).await {
+            Ok(Some(found)) => found,
             Ok(None) => {
                 debug!("No DMARC record found for {}", from_domain);
-                return DmarcResult::None;
+                return DmarcEvaluation::without_record(DmarcResult::None);
             }
             Err(e) => {
                 warn!("Failed to fetch DMARC record: {}", e);
-                return DmarcResult::TempError;
+                return DmarcEvaluation::without_record(DmarcResult::TempError);
             }
         };
 
@@ -188,7 +260,7 @@ impl DmarcVerifier {
         };
 
         // DMARC passes if either SPF or DKIM is aligned
-        if spf_aligned || dkim_aligned {
+        let result = if spf_aligned || dkim_aligned {
             debug!(
                 "DMARC pass for {}: SPF aligned={}, DKIM aligned={}",
                 from_domain, spf_aligned, dkim_aligned
@@ -200,14 +272,31 @@ impl DmarcVerifier {
                 from_domain, spf_aligned, dkim_aligned, dmarc_record.policy
             );
             DmarcResult::Fail(dmarc_record.policy)
+        };
+
+        DmarcEvaluation {
+            result,
+            policy_domain: Some(policy_domain),
+            record: Some(dmarc_record),
+            spf_aligned,
+            dkim_aligned,
         }
     }
 
-    /// Fetch DMARC record from DNS
+    /// DMARC record that applies to `domain`, with the domain it was found
+    /// at (the domain itself or its organizational domain)
+    pub async fn lookup(&self, domain: &str) -> Result<Option<(String, DmarcRecord)>> {
+        self.fetch_dmarc_record(domain).await
+    }
+
+    /// Fetch DMARC record from DNS, with the domain it was found at
+    #[allow(clippy::type_complexity)]
     fn fetch_dmarc_record<'a>(
         &'a self,
         domain: &'a str,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Option<DmarcRecord>>> + Send + 'a>> {
+    ) -> std::pin::Pin<
+        Box<dyn std::future::Future<Output = Result<Option<(String, DmarcRecord)>>> + Send + 'a>,
+    > {
         Box::pin(async move {
             let dmarc_domain = format!("_dmarc.{}", domain);
 
@@ -221,7 +310,7 @@ impl DmarcVerifier {
                             .collect::<String>();
 
                         if txt.starts_with("v=DMARC1") {
-                            return Ok(Some(parse_dmarc_record(&txt)?));
+                            return Ok(Some((domain.to_string(), parse_dmarc_record(&txt)?)));
                         }
                     }
                     // No DMARC record found, try organizational domain
@@ -373,7 +462,7 @@ fn check_alignment(from_domain: &str, auth_domain: &str, mode: AlignmentMode) ->
 }
 
 /// Get organizational domain (simplified - in production use PSL)
-fn get_organizational_domain(domain: &str) -> Option<String> {
+pub(crate) fn get_organizational_domain(domain: &str) -> Option<String> {
     let parts: Vec<&str> = domain.split('.').collect();
     if parts.len() >= 2 {
         // Simple heuristic: last two parts
@@ -0,0 +1,996 @@
+//! DMARC aggregate reports (RFC 7489, section 7.2)
+//!
+//! The DMARC result of every message received from outside is counted per
+//! UTC day, policy domain, source IP and result in
+//! `dmarc_report_records`, when the domain's record asks for aggregate
+//! reports (`rua=`). `DmarcReportWorker` sends each finished day's counts
+//! to the `rua=` addresses as a gzip- or zip-packaged XML report, through
+//! the outbound queue.
+//!
+//! Addresses outside the policy domain only get reports when the domain
+//! they are at publishes `<policy domain>._report._dmarc.<their domain>`
+//! (section 7.1).
+
+use super::dkim::DkimResult;
+use super::dmarc::{get_organizational_domain, DmarcPolicy, DmarcRecord};
+use super::spf::SpfResult;
+use crate::queue::{DeliveryJob, QueueManager};
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Compression, Crc};
+use mairust_common::config::{DmarcReportCompression, DmarcReportConfig, SmtpConfig};
+use mairust_common::types::DeliveryLane;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::TokioAsyncResolver;
+use uuid::Uuid;
+
+/// How often finished days are looked for, in seconds
+const REPORT_CHECK_SECS: u64 = 3600;
+
+/// Published DMARC policy, as given in a report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublishedPolicy {
+    pub domain: String,
+    pub adkim: String,
+    pub aspf: String,
+    pub p: String,
+    pub sp: String,
+    pub pct: u8,
+    /// Aggregate report URIs (rua=)
+    pub rua: String,
+}
+
+impl PublishedPolicy {
+    /// Policy of the record published at `domain`, or `None` when the
+    /// record asks for no aggregate reports
+    pub fn new(domain: &str, record: &DmarcRecord) -> Option<Self> {
+        let rua = record
+            .aggregate_report_uri
+            .as_deref()
+            .filter(|rua| !rua.trim().is_empty())?;
+        Some(Self {
+            domain: domain.to_lowercase(),
+            adkim: record.dkim_alignment.as_str().to_string(),
+            aspf: record.spf_alignment.as_str().to_string(),
+            p: record.policy.as_str().to_string(),
+            sp: record
+                .subdomain_policy
+                .unwrap_or(record.policy)
+                .as_str()
+                .to_string(),
+            pct: record.percentage,
+            rua: rua.to_string(),
+        })
+    }
+}
+
+/// Why the disposition of a message differs from the published policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyOverride {
+    /// RFC 7489 PolicyOverrideType, e.g. `trusted_forwarder`
+    pub reason: &'static str,
+    pub comment: String,
+}
+
+/// DMARC result of one received message
+#[derive(Debug, Clone)]
+pub struct DmarcObservation {
+    pub source_ip: IpAddr,
+    /// Domain of the From header
+    pub header_from: String,
+    /// Domain of MAIL FROM
+    pub envelope_from: Option<String>,
+    /// Domain of the DKIM signature (d=)
+    pub dkim_domain: Option<String>,
+    pub dkim_result: DkimResult,
+    /// Domain SPF was checked for
+    pub spf_domain: Option<String>,
+    pub spf_result: SpfResult,
+    pub dkim_aligned: bool,
+    pub spf_aligned: bool,
+    /// Policy that was applied to the message
+    pub disposition: DmarcPolicy,
+    pub policy_override: Option<PolicyOverride>,
+}
+
+/// Counts DMARC results for aggregate reports
+pub struct DmarcReportRecorder {
+    db_pool: DatabasePool,
+}
+
+impl DmarcReportRecorder {
+    /// Create a recorder
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Count a message received at `at`
+    pub async fn record(
+        &self,
+        policy: &PublishedPolicy,
+        observation: &DmarcObservation,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        let (reason, comment) = match &observation.policy_override {
+            Some(o) => (o.reason, o.comment.as_str()),
+            None => ("", ""),
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO dmarc_report_records
+                (report_date, policy_domain, source_ip, header_from, envelope_from, dkim_domain,
+                 dkim_result, spf_domain, spf_result, dkim_aligned, spf_aligned, disposition,
+                 override_reason, override_comment, message_count, policy, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, 1, $15, $16)
+            ON CONFLICT (report_date, policy_domain, source_ip, header_from, envelope_from,
+                         dkim_domain, dkim_result, spf_domain, spf_result, dkim_aligned,
+                         spf_aligned, disposition, override_reason, override_comment)
+            DO UPDATE SET
+                message_count = dmarc_report_records.message_count + 1,
+                policy = EXCLUDED.policy,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        )
+        .bind(at.date_naive())
+        .bind(&policy.domain)
+        .bind(observation.source_ip.to_string())
+        .bind(observation.header_from.to_lowercase())
+        .bind(lowercase_or_empty(&observation.envelope_from))
+        .bind(lowercase_or_empty(&observation.dkim_domain))
+        .bind(observation.dkim_result.as_header_value())
+        .bind(lowercase_or_empty(&observation.spf_domain))
+        .bind(observation.spf_result.as_header_value())
+        .bind(observation.dkim_aligned)
+        .bind(observation.spf_aligned)
+        .bind(observation.disposition.as_str())
+        .bind(reason)
+        .bind(comment)
+        .bind(serde_json::to_value(policy)?)
+        .bind(at)
+        .execute(self.db_pool.pool())
+        .await?;
+        Ok(())
+    }
+}
+
+fn lowercase_or_empty(value: &Option<String>) -> String {
+    value.as_deref().unwrap_or_default().to_lowercase()
+}
+
+/// Counted results of one source, as stored
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ReportRow {
+    pub source_ip: String,
+    pub header_from: String,
+    pub envelope_from: String,
+    pub dkim_domain: String,
+    pub dkim_result: String,
+    pub spf_domain: String,
+    pub spf_result: String,
+    pub dkim_aligned: bool,
+    pub spf_aligned: bool,
+    pub disposition: String,
+    pub override_reason: String,
+    pub override_comment: String,
+    pub message_count: i32,
+    pub policy: serde_json::Value,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Report metadata (`report_metadata`)
+#[derive(Debug, Clone)]
+pub struct ReportMetadata {
+    pub org_name: String,
+    pub email: String,
+    pub contact_info: Option<String>,
+    pub report_id: String,
+    pub begin: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Start and end of the report of a UTC day
+pub fn report_period(date: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let begin = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    (begin, begin + Duration::days(1) - Duration::seconds(1))
+}
+
+/// Render the XML of an aggregate report
+pub fn render_report(
+    metadata: &ReportMetadata,
+    policy: &PublishedPolicy,
+    rows: &[ReportRow],
+) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<feedback>\n");
+
+    xml.push_str("  <report_metadata>\n");
+    push_element(&mut xml, 4, "org_name", &metadata.org_name);
+    push_element(&mut xml, 4, "email", &metadata.email);
+    if let Some(info) = &metadata.contact_info {
+        push_element(&mut xml, 4, "extra_contact_info", info);
+    }
+    push_element(&mut xml, 4, "report_id", &metadata.report_id);
+    xml.push_str("    <date_range>\n");
+    push_element(
+        &mut xml,
+        6,
+        "begin",
+        &metadata.begin.timestamp().to_string(),
+    );
+    push_element(&mut xml, 6, "end", &metadata.end.timestamp().to_string());
+    xml.push_str("    </date_range>\n");
+    xml.push_str("  </report_metadata>\n");
+
+    xml.push_str("  <policy_published>\n");
+    push_element(&mut xml, 4, "domain", &policy.domain);
+    push_element(&mut xml, 4, "adkim", &policy.adkim);
+    push_element(&mut xml, 4, "aspf", &policy.aspf);
+    push_element(&mut xml, 4, "p", &policy.p);
+    push_element(&mut xml, 4, "sp", &policy.sp);
+    push_element(&mut xml, 4, "pct", &policy.pct.to_string());
+    xml.push_str("  </policy_published>\n");
+
+    for row in rows {
+        xml.push_str("  <record>\n");
+        xml.push_str("    <row>\n");
+        push_element(&mut xml, 6, "source_ip", &row.source_ip);
+        push_element(&mut xml, 6, "count", &row.message_count.to_string());
+        xml.push_str("      <policy_evaluated>\n");
+        push_element(&mut xml, 8, "disposition", &row.disposition);
+        push_element(&mut xml, 8, "dkim", pass_or_fail(row.dkim_aligned));
+        push_element(&mut xml, 8, "spf", pass_or_fail(row.spf_aligned));
+        if !row.override_reason.is_empty() {
+            xml.push_str("        <reason>\n");
+            push_element(&mut xml, 10, "type", &row.override_reason);
+            if !row.override_comment.is_empty() {
+                push_element(&mut xml, 10, "comment", &row.override_comment);
+            }
+            xml.push_str("        </reason>\n");
+        }
+        xml.push_str("      </policy_evaluated>\n");
+        xml.push_str("    </row>\n");
+
+        xml.push_str("    <identifiers>\n");
+        if !row.envelope_from.is_empty() {
+            push_element(&mut xml, 6, "envelope_from", &row.envelope_from);
+        }
+        push_element(&mut xml, 6, "header_from", &row.header_from);
+        xml.push_str("    </identifiers>\n");
+
+        xml.push_str("    <auth_results>\n");
+        if !row.dkim_domain.is_empty() {
+            xml.push_str("      <dkim>\n");
+            push_element(&mut xml, 8, "domain", &row.dkim_domain);
+            push_element(&mut xml, 8, "result", &row.dkim_result);
+            xml.push_str("      </dkim>\n");
+        }
+        xml.push_str("      <spf>\n");
+        push_element(&mut xml, 8, "domain", &row.spf_domain);
+        push_element(&mut xml, 8, "scope", "mfrom");
+        push_element(&mut xml, 8, "result", &row.spf_result);
+        xml.push_str("      </spf>\n");
+        xml.push_str("    </auth_results>\n");
+        xml.push_str("  </record>\n");
+    }
+
+    xml.push_str("</feedback>\n");
+    xml
+}
+
+fn pass_or_fail(aligned: bool) -> &'static str {
+    if aligned {
+        "pass"
+    } else {
+        "fail"
+    }
+}
+
+/// Append `<name>value</name>` on its own line
+fn push_element(xml: &mut String, indent: usize, name: &str, value: &str) {
+    let _ = writeln!(
+        xml,
+        "{:indent$}<{name}>{}</{name}>",
+        "",
+        escape_xml(value),
+        indent = indent,
+        name = name
+    );
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// File name of a report: `receiver!policy-domain!begin!end.<extension>`
+pub fn report_filename(
+    receiver: &str,
+    metadata: &ReportMetadata,
+    policy_domain: &str,
+    compression: DmarcReportCompression,
+) -> String {
+    format!(
+        "{}!{}!{}!{}.{}",
+        receiver,
+        policy_domain,
+        metadata.begin.timestamp(),
+        metadata.end.timestamp(),
+        match compression {
+            DmarcReportCompression::Gzip => "xml.gz",
+            DmarcReportCompression::Zip => "zip",
+        }
+    )
+}
+
+/// Package an XML report; `xml_name` is the name of the XML file in a zip
+/// archive
+pub fn package_report(
+    xml: &[u8],
+    xml_name: &str,
+    compression: DmarcReportCompression,
+    now: DateTime<Utc>,
+) -> Result<Vec<u8>> {
+    match compression {
+        DmarcReportCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(xml)?;
+            Ok(encoder.finish()?)
+        }
+        DmarcReportCompression::Zip => zip_single(xml, xml_name, now),
+    }
+}
+
+/// Zip archive of one deflated file
+fn zip_single(data: &[u8], name: &str, now: DateTime<Utc>) -> Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let deflated = encoder.finish()?;
+
+    let mut crc = Crc::new();
+    crc.update(data);
+    let crc = crc.sum();
+
+    let size = u32::try_from(data.len()).map_err(|_| anyhow!("Report too large to zip"))?;
+    let compressed =
+        u32::try_from(deflated.len()).map_err(|_| anyhow!("Report too large to zip"))?;
+    let name_len = u16::try_from(name.len()).map_err(|_| anyhow!("File name too long"))?;
+
+    // MS-DOS time and date
+    let dos_time = ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16;
+    let dos_date =
+        (((now.year() - 1980).max(0) as u32) << 9 | (now.month() << 5) | now.day()) as u16;
+
+    // Fields shared by the local header and the central directory entry:
+    // version needed, flags, method (deflate), time, date, CRC and sizes
+    let mut common = Vec::with_capacity(26);
+    common.extend_from_slice(&20u16.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+    common.extend_from_slice(&8u16.to_le_bytes());
+    common.extend_from_slice(&dos_time.to_le_bytes());
+    common.extend_from_slice(&dos_date.to_le_bytes());
+    common.extend_from_slice(&crc.to_le_bytes());
+    common.extend_from_slice(&compressed.to_le_bytes());
+    common.extend_from_slice(&size.to_le_bytes());
+    common.extend_from_slice(&name_len.to_le_bytes());
+    common.extend_from_slice(&0u16.to_le_bytes());
+
+    let mut zip = Vec::with_capacity(deflated.len() + 2 * name.len() + 100);
+    zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&common);
+    zip.extend_from_slice(name.as_bytes());
+    zip.extend_from_slice(&deflated);
+
+    let directory_offset = zip.len() as u32;
+    zip.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&20u16.to_le_bytes()); // version made by
+    zip.extend_from_slice(&common);
+    zip.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    zip.extend_from_slice(&0u16.to_le_bytes()); // disk number
+    zip.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+    zip.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+    zip.extend_from_slice(&0u32.to_le_bytes()); // local header offset
+    zip.extend_from_slice(name.as_bytes());
+    let directory_size = zip.len() as u32 - directory_offset;
+
+    zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // this disk
+    zip.extend_from_slice(&0u16.to_le_bytes()); // directory disk
+    zip.extend_from_slice(&1u16.to_le_bytes()); // entries on this disk
+    zip.extend_from_slice(&1u16.to_le_bytes()); // entries
+    zip.extend_from_slice(&directory_size.to_le_bytes());
+    zip.extend_from_slice(&directory_offset.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+    Ok(zip)
+}
+
+/// A `rua=` destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportUri {
+    pub address: String,
+    /// Largest report the destination accepts, in bytes
+    pub max_size: Option<u64>,
+}
+
+/// The mailto destinations of a `rua=` value
+/// (`mailto:a@example.com!10m,mailto:b@example.net`); other schemes are
+/// skipped
+pub fn parse_rua(rua: &str) -> Vec<ReportUri> {
+    rua.split(',')
+        .filter_map(|uri| {
+            let uri = uri.trim();
+            let scheme = uri.get(..7)?;
+            if !scheme.eq_ignore_ascii_case("mailto:") {
+                return None;
+            }
+            let (address, size) = match uri[7..].split_once('!') {
+                Some((address, size)) => (address, Some(size)),
+                None => (&uri[7..], None),
+            };
+            let address = address
+                .replace("%21", "!")
+                .replace("%2C", ",")
+                .replace("%2c", ",");
+            if !address.contains('@') {
+                return None;
+            }
+            Some(ReportUri {
+                address,
+                max_size: size.and_then(parse_size),
+            })
+        })
+        .collect()
+}
+
+/// Size limit of a report URI: a number with an optional k, m, g or t unit
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, shift) = match size.chars().last()?.to_ascii_lowercase() {
+        'k' => (&size[..size.len() - 1], 10),
+        'm' => (&size[..size.len() - 1], 20),
+        'g' => (&size[..size.len() - 1], 30),
+        't' => (&size[..size.len() - 1], 40),
+        _ => (size, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Whether reports for `policy_domain` may only go to an address at
+/// `destination` once the destination authorizes them
+pub fn needs_authorization(policy_domain: &str, destination: &str) -> bool {
+    let org = |domain: &str| {
+        let domain = domain.to_lowercase();
+        get_organizational_domain(&domain).unwrap_or(domain)
+    };
+    org(policy_domain) != org(destination)
+}
+
+/// The report email
+fn build_report_message(
+    from: &str,
+    to: &str,
+    metadata: &ReportMetadata,
+    policy_domain: &str,
+    filename: &str,
+    compression: DmarcReportCompression,
+    package: &[u8],
+) -> Vec<u8> {
+    let hostname = from.rsplit('@').next().unwrap_or_default();
+    let boundary = format!("=_dmarc_{}", Uuid::now_v7().simple());
+    let content_type = match compression {
+        DmarcReportCompression::Gzip => "application/gzip",
+        DmarcReportCompression::Zip => "application/zip",
+    };
+
+    let mut msg = String::new();
+    msg.push_str(&format!("From: <{}>\r\n", from));
+    msg.push_str(&format!("To: <{}>\r\n", to));
+    msg.push_str(&format!(
+        "Subject: Report Domain: {} Submitter: {} Report-ID: <{}>\r\n",
+        policy_domain, metadata.org_name, metadata.report_id
+    ));
+    msg.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+    msg.push_str(&format!(
+        "Message-ID: <{}@{}>\r\n",
+        Uuid::now_v7(),
+        hostname
+    ));
+    msg.push_str("Auto-Submitted: auto-generated\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+        boundary
+    ));
+
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+    msg.push_str(&format!(
+        "This is a DMARC aggregate report from {} for {}, covering {} to {} (UTC).\r\n\r\n",
+        metadata.org_name,
+        policy_domain,
+        metadata.begin.format("%Y-%m-%d %H:%M:%S"),
+        metadata.end.format("%Y-%m-%d %H:%M:%S")
+    ));
+
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str(&format!(
+        "Content-Type: {}; name=\"{}\"\r\n",
+        content_type, filename
+    ));
+    msg.push_str(&format!(
+        "Content-Disposition: attachment; filename=\"{}\"\r\n",
+        filename
+    ));
+    msg.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+    let encoded = base64::engine::general_purpose::STANDARD.encode(package);
+    for line in encoded.as_bytes().chunks(76) {
+        msg.push_str(&String::from_utf8_lossy(line));
+        msg.push_str("\r\n");
+    }
+    msg.push_str(&format!("--{}--\r\n", boundary));
+
+    msg.into_bytes()
+}
+
+/// Sends the aggregate reports of finished days
+pub struct DmarcReportWorker<S: FileStorage> {
+    db_pool: DatabasePool,
+    queue_manager: Arc<QueueManager<S>>,
+    config: DmarcReportConfig,
+    hostname: String,
+    resolver: TokioAsyncResolver,
+}
+
+impl<S: FileStorage + Send + Sync + 'static> DmarcReportWorker<S> {
+    /// Create a worker for the `smtp.dmarc_reports` configuration
+    pub fn new(
+        db_pool: DatabasePool,
+        queue_manager: Arc<QueueManager<S>>,
+        smtp_config: &SmtpConfig,
+    ) -> Self {
+        Self {
+            db_pool,
+            queue_manager,
+            config: smtp_config.dmarc_reports.clone(),
+            hostname: smtp_config.hostname.clone(),
+            resolver: TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()),
+        }
+    }
+
+    /// Send reports as days finish
+    pub async fn run(self) {
+        let mut ticker = interval(std::time::Duration::from_secs(REPORT_CHECK_SECS));
+        info!("DMARC aggregate report worker started");
+
+        loop {
+            ticker.tick().await;
+            match self.send_due_reports(Utc::now()).await {
+                Ok(0) => {}
+                Ok(sent) => info!("Sent {} DMARC aggregate reports", sent),
+                Err(e) => error!("Failed to send DMARC aggregate reports: {}", e),
+            }
+        }
+    }
+
+    /// Send the reports of the days before `now`; returns how many were
+    /// queued
+    pub async fn send_due_reports(&self, now: DateTime<Utc>) -> Result<usize> {
+        let due: Vec<(String, NaiveDate)> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT policy_domain, report_date FROM dmarc_report_records
+            WHERE report_date < $1
+            ORDER BY report_date, policy_domain
+            "#,
+        )
+        .bind(now.date_naive())
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        let mut sent = 0;
+        for (domain, date) in due {
+            match self.send_report(&domain, date, now).await {
+                Ok(queued) => sent += queued,
+                Err(e) => warn!(
+                    "Failed to send DMARC report for {} on {}: {}",
+                    domain, date, e
+                ),
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Send the report of a domain's day to its `rua=` addresses
+    async fn send_report(
+        &self,
+        domain: &str,
+        date: NaiveDate,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        // Claiming the rows reports each day once, even with several servers
+        let mut rows: Vec<ReportRow> = sqlx::query_as(
+            r#"
+            DELETE FROM dmarc_report_records
+            WHERE policy_domain = $1 AND report_date = $2
+            RETURNING source_ip, header_from, envelope_from, dkim_domain, dkim_result,
+                      spf_domain, spf_result, dkim_aligned, spf_aligned, disposition,
+                      override_reason, override_comment, message_count, policy, updated_at
+            "#,
+        )
+        .bind(domain)
+        .bind(date)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+        let Some(latest) = rows.iter().max_by_key(|row| row.updated_at) else {
+            return Ok(0);
+        };
+        let policy: PublishedPolicy = serde_json::from_value(latest.policy.clone())?;
+        rows.sort_by(|a, b| (&a.source_ip, &a.header_from).cmp(&(&b.source_ip, &b.header_from)));
+
+        let (begin, end) = report_period(date);
+        let metadata = ReportMetadata {
+            org_name: self
+                .config
+                .org_name
+                .clone()
+                .unwrap_or_else(|| self.hostname.clone()),
+            email: self.sender_address(),
+            contact_info: self.config.contact_info.clone(),
+            report_id: format!("{}.{}@{}", date.format("%Y%m%d"), domain, self.hostname),
+            begin,
+            end,
+        };
+        let xml = render_report(&metadata, &policy, &rows);
+        let compression = self.config.compression;
+        let filename = report_filename(&self.hostname, &metadata, domain, compression);
+        let xml_name = report_filename(
+            &self.hostname,
+            &metadata,
+            domain,
+            DmarcReportCompression::Gzip,
+        );
+        let xml_name = xml_name.trim_end_matches(".gz");
+        let package = package_report(xml.as_bytes(), xml_name, compression, now)?;
+
+        let mut queued = 0;
+        for uri in parse_rua(&policy.rua) {
+            if uri.max_size.is_some_and(|max| package.len() as u64 > max) {
+                warn!(
+                    "DMARC report for {} is larger than {} accepts",
+                    domain, uri.address
+                );
+                continue;
+            }
+            let destination = uri.address.rsplit('@').next().unwrap_or_default();
+            if needs_authorization(domain, destination)
+                && !self.authorized(domain, destination).await
+            {
+                warn!(
+                    "{} does not accept DMARC reports for {}",
+                    destination, domain
+                );
+                continue;
+            }
+
+            let message = build_report_message(
+                &metadata.email,
+                &uri.address,
+                &metadata,
+                domain,
+                &filename,
+                compression,
+                &package,
+            );
+            let job = DeliveryJob {
+                message_id: Uuid::now_v7(),
+                tenant_id: Uuid::nil(),
+                from: metadata.email.clone(),
+                to: vec![uri.address.clone()],
+                storage_path: String::new(),
+                raw_message_base64: Some(
+                    base64::engine::general_purpose::STANDARD.encode(&message),
+                ),
+                require_tls: false,
+                priority: 0,
+                lane: DeliveryLane::Bulk,
+                archived: true,
+                dkim_signed: false,
+                forwarded: false,
+                queued_at: None,
+            };
+            self.queue_manager.enqueue_delivery(job).await?;
+            debug!(
+                "Queued DMARC report {} to {}",
+                metadata.report_id, uri.address
+            );
+            queued += 1;
+        }
+        Ok(queued)
+    }
+
+    /// Sender of the reports
+    fn sender_address(&self) -> String {
+        self.config
+            .from_address
+            .clone()
+            .unwrap_or_else(|| format!("noreply-dmarc@{}", self.hostname))
+    }
+
+    /// Whether `destination` publishes that it accepts reports for
+    /// `policy_domain`
+    async fn authorized(&self, policy_domain: &str, destination: &str) -> bool {
+        let name = format!("{}._report._dmarc.{}", policy_domain, destination);
+        match self.resolver.txt_lookup(&name).await {
+            Ok(lookup) => lookup.iter().any(|record| {
+                let txt: String = record
+                    .txt_data()
+                    .iter()
+                    .map(|d| String::from_utf8_lossy(d))
+                    .collect();
+                txt.trim_start().starts_with("v=DMARC1")
+            }),
+            Err(e) => {
+                debug!("No DMARC report authorization at {}: {}", name, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use std::io::Read;
+
+    fn metadata() -> ReportMetadata {
+        let (begin, end) = report_period(NaiveDate::from_ymd_opt(2026, 10, 15).unwrap());
+        ReportMetadata {
+            org_name: "mx.example.net".to_string(),
+            email: "noreply-dmarc@mx.example.net".to_string(),
+            contact_info: None,
+            report_id: "20261015.example.com@mx.example.net".to_string(),
+            begin,
+            end,
+        }
+    }
+
+    fn policy() -> PublishedPolicy {
+        let record = DmarcRecord {
+            policy: DmarcPolicy::Reject,
+            aggregate_report_uri: Some("mailto:dmarc@example.com".to_string()),
+            ..DmarcRecord::default()
+        };
+        PublishedPolicy::new("Example.com", &record).unwrap()
+    }
+
+    fn row(source_ip: &str, count: i32) -> ReportRow {
+        ReportRow {
+            source_ip: source_ip.to_string(),
+            header_from: "example.com".to_string(),
+            envelope_from: "example.com".to_string(),
+            dkim_domain: "example.com".to_string(),
+            dkim_result: "pass".to_string(),
+            spf_domain: "example.com".to_string(),
+            spf_result: "fail".to_string(),
+            dkim_aligned: true,
+            spf_aligned: false,
+            disposition: "none".to_string(),
+            override_reason: String::new(),
+            override_comment: String::new(),
+            message_count: count,
+            policy: serde_json::Value::Null,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_published_policy() {
+        let policy = policy();
+        assert_eq!(policy.domain, "example.com");
+        assert_eq!(policy.p, "reject");
+        assert_eq!(policy.sp, "reject");
+        assert_eq!(policy.adkim, "r");
+        assert_eq!(policy.pct, 100);
+
+        // No reports without rua=
+        assert!(PublishedPolicy::new("example.com", &DmarcRecord::default()).is_none());
+    }
+
+    #[test]
+    fn test_report_period() {
+        let (begin, end) = report_period(NaiveDate::from_ymd_opt(2026, 10, 15).unwrap());
+        assert_eq!(begin, Utc.with_ymd_and_hms(2026, 10, 15, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2026, 10, 15, 23, 59, 59).unwrap());
+    }
+
+    #[test]
+    fn test_render_report() {
+        let mut forwarded = row("192.0.2.1", 3);
+        forwarded.override_reason = "trusted_forwarder".to_string();
+        forwarded.override_comment = "arc=pass <lists.example.org>".to_string();
+        forwarded.dkim_domain = String::new();
+
+        let xml = render_report(
+            &metadata(),
+            &policy(),
+            &[row("198.51.100.7", 12), forwarded],
+        );
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feedback>"));
+        assert!(xml.contains("<report_id>20261015.example.com@mx.example.net</report_id>"));
+        assert!(xml.contains(&format!("<begin>{}</begin>", metadata().begin.timestamp())));
+        assert!(xml.contains("<domain>example.com</domain>\n    <adkim>r</adkim>"));
+        assert!(xml.contains("<p>reject</p>"));
+        assert!(xml.contains("<source_ip>198.51.100.7</source_ip>\n      <count>12</count>"));
+        assert!(xml.contains("<dkim>pass</dkim>\n        <spf>fail</spf>"));
+        assert!(xml.contains("<type>trusted_forwarder</type>"));
+        assert!(xml.contains("<comment>arc=pass &lt;lists.example.org&gt;</comment>"));
+        assert_eq!(xml.matches("<record>").count(), 2);
+        // A message without a DKIM signature has no dkim result
+        assert_eq!(xml.matches("<dkim>\n").count(), 1);
+        assert!(!xml.contains("extra_contact_info"));
+        assert!(xml.ends_with("</feedback>\n"));
+    }
+
+    #[test]
+    fn test_report_filename() {
+        let metadata = metadata();
+        let name = report_filename(
+            "mx.example.net",
+            &metadata,
+            "example.com",
+            DmarcReportCompression::Gzip,
+        );
+        assert_eq!(
+            name,
+            format!(
+                "mx.example.net!example.com!{}!{}.xml.gz",
+                metadata.begin.timestamp(),
+                metadata.end.timestamp()
+            )
+        );
+        assert!(
+            report_filename("a", &metadata, "b", DmarcReportCompression::Zip).ends_with(".zip")
+        );
+    }
+
+    #[test]
+    fn test_gzip_package() {
+        let xml = render_report(&metadata(), &policy(), &[row("192.0.2.1", 1)]);
+        let package = package_report(
+            xml.as_bytes(),
+            "r.xml",
+            DmarcReportCompression::Gzip,
+            Utc::now(),
+        )
+        .unwrap();
+        let mut unpacked = String::new();
+        GzDecoder::new(package.as_slice())
+            .read_to_string(&mut unpacked)
+            .unwrap();
+        assert_eq!(unpacked, xml);
+    }
+
+    #[test]
+    fn test_zip_package() {
+        let xml = render_report(&metadata(), &policy(), &[row("192.0.2.1", 1)]);
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 1, 2, 4).unwrap();
+        let zip =
+            package_report(xml.as_bytes(), "r.xml", DmarcReportCompression::Zip, now).unwrap();
+
+        let u16_at = |at: usize| u16::from_le_bytes([zip[at], zip[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(zip[at..at + 4].try_into().unwrap());
+
+        // Local file header
+        assert_eq!(u32_at(0), 0x0403_4b50);
+        assert_eq!(u16_at(8), 8);
+        assert_eq!(u16_at(10), (1 << 11) | (2 << 5) | 2);
+        assert_eq!(u16_at(12), (46 << 9) | (10 << 5) | 16);
+        let compressed = u32_at(18) as usize;
+        assert_eq!(u32_at(22) as usize, xml.len());
+        assert_eq!(u16_at(26), 5);
+        assert_eq!(&zip[30..35], b"r.xml");
+
+        let mut unpacked = String::new();
+        DeflateDecoder::new(&zip[35..35 + compressed])
+            .read_to_string(&mut unpacked)
+            .unwrap();
+        assert_eq!(unpacked, xml);
+        let mut crc = Crc::new();
+        crc.update(xml.as_bytes());
+        assert_eq!(u32_at(14), crc.sum());
+
+        // Central directory and end record
+        let directory = 35 + compressed;
+        assert_eq!(u32_at(directory), 0x0201_4b50);
+        assert_eq!(&zip[directory + 46..directory + 51], b"r.xml");
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16_at(end + 10), 1);
+        assert_eq!(u32_at(end + 12) as usize, end - directory);
+        assert_eq!(u32_at(end + 16) as usize, directory);
+    }
+
+    #[test]
+    fn test_parse_rua() {
+        assert_eq!(
+            parse_rua("mailto:dmarc@example.com!10m, MAILTO:agg@reports.example.net"),
+            vec![
+                ReportUri {
+                    address: "dmarc@example.com".to_string(),
+                    max_size: Some(10 << 20),
+                },
+                ReportUri {
+                    address: "agg@reports.example.net".to_string(),
+                    max_size: None,
+                },
+            ]
+        );
+        assert_eq!(
+            parse_rua("https://example.com/dmarc,mailto:a@example.com!500"),
+            vec![ReportUri {
+                address: "a@example.com".to_string(),
+                max_size: Some(500),
+            }]
+        );
+        assert!(parse_rua("mailto:nobody").is_empty());
+        assert_eq!(parse_size("2k"), Some(2048));
+        assert_eq!(parse_size("x"), None);
+    }
+
+    #[test]
+    fn test_needs_authorization() {
+        assert!(!needs_authorization("example.com", "example.com"));
+        assert!(!needs_authorization(
+            "mail.example.com",
+            "Reports.Example.com"
+        ));
+        assert!(needs_authorization("example.com", "reports.example.net"));
+    }
+
+    #[test]
+    fn test_build_report_message() {
+        let metadata = metadata();
+        let message = build_report_message(
+            &metadata.email,
+            "dmarc@example.com",
+            &metadata,
+            "example.com",
+            "r.xml.gz",
+            DmarcReportCompression::Gzip,
+            b"package",
+        );
+        let message = String::from_utf8(message).unwrap();
+        assert!(message.contains("From: <noreply-dmarc@mx.example.net>\r\n"));
+        assert!(message.contains("To: <dmarc@example.com>\r\n"));
+        assert!(message.contains(
+            "Subject: Report Domain: example.com Submitter: mx.example.net \
+             Report-ID: <20261015.example.com@mx.example.net>\r\n"
+        ));
+        assert!(message.contains("Content-Type: application/gzip; name=\"r.xml.gz\"\r\n"));
+        assert!(message.contains("@mx.example.net>\r\n"));
+        assert!(message.contains("cGFja2FnZQ==\r\n"));
+    }
+}
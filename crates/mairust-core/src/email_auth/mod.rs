@@ -1,15 +1,31 @@
 //! Email Authentication Module
 //!
-//! Provides SPF, DKIM, and DMARC verification for incoming mail
-//! and DKIM signing for outgoing mail.
+//! Provides SPF, DKIM, DMARC and ARC verification for incoming mail, BIMI
+//! brand logos for mail that passes DMARC, DMARC aggregate reports to the
+//! domains that ask for them, DKIM signing for outgoing mail with rotated
+//! RSA and Ed25519 keys, and ARC sealing and sender rewriting (SRS) for
+//! forwarded mail.
 
+pub mod arc;
+pub mod bimi;
 pub mod dkim;
+pub mod dkim_rotation;
 pub mod dmarc;
+pub mod dmarc_report;
+pub mod signing;
 pub mod spf;
+pub mod srs;
 
-pub use dkim::{DkimResult, DkimSigner, DkimVerifier};
-pub use dmarc::{DmarcPolicy, DmarcResult, DmarcVerifier};
-pub use spf::{SpfResult, SpfVerifier};
+pub use arc::{ArcResult, ArcSealer, ArcVerification, ArcVerifier};
+pub use bimi::{Bimi, BimiEvaluation, BimiPublication, BimiResult};
+pub use dkim::{DkimResult, DkimSigner, DkimVerifier, SigningAlgorithm};
+pub use dkim_rotation::DkimRotationWorker;
+pub use dmarc::{DmarcEvaluation, DmarcPolicy, DmarcResult, DmarcVerifier};
+pub use dmarc_report::{DmarcReportRecorder, DmarcReportWorker};
+pub use signing::{DkimKeyring, SigningIdentity};
+pub use spf::{SpfEvaluation, SpfResult, SpfVerifier};
+pub use srs::{Srs, SrsError};
+use std::sync::Arc;
 
 /// Combined email authentication result
 #[derive(Debug, Clone)]
@@ -17,12 +33,45 @@ pub struct AuthenticationResult {
     pub spf: SpfResult,
     pub dkim: DkimResult,
     pub dmarc: DmarcResult,
+    pub arc: ArcVerification,
+    /// DMARC failed, but the message came through the valid ARC chain of a
+    /// trusted sealer, so the failure is ignored
+    pub dmarc_overridden: bool,
+    /// BIMI result, for mail whose logo was looked up
+    pub bimi: Option<Arc<BimiEvaluation>>,
 }
 
 impl AuthenticationResult {
     /// Create a new authentication result
     pub fn new(spf: SpfResult, dkim: DkimResult, dmarc: DmarcResult) -> Self {
-        Self { spf, dkim, dmarc }
+        Self {
+            spf,
+            dkim,
+            dmarc,
+            arc: ArcVerification::default(),
+            dmarc_overridden: false,
+            bimi: None,
+        }
+    }
+
+    /// Add the ARC result; a DMARC failure is overridden when the chain
+    /// passes and its latest seal is by one of `trusted_sealers`
+    pub fn with_arc(mut self, arc: ArcVerification, trusted_sealers: &[String]) -> Self {
+        self.dmarc_overridden =
+            matches!(self.dmarc, DmarcResult::Fail(_)) && arc.is_trusted(trusted_sealers);
+        self.arc = arc;
+        self
+    }
+
+    /// Add the BIMI result
+    pub fn with_bimi(mut self, bimi: Option<Arc<BimiEvaluation>>) -> Self {
+        self.bimi = bimi;
+        self
+    }
+
+    /// Whether DMARC failed, and the failure was not overridden by ARC
+    pub fn dmarc_failed(&self) -> bool {
+        matches!(self.dmarc, DmarcResult::Fail(_)) && !self.dmarc_overridden
     }
 
     /// Check if the message should be accepted based on authentication results
@@ -33,19 +82,31 @@ impl AuthenticationResult {
             SpfResult::Pass | SpfResult::SoftFail | SpfResult::Neutral | SpfResult::None
         );
 
-        let dmarc_ok = !matches!(self.dmarc, DmarcResult::Fail(DmarcPolicy::Reject));
+        let dmarc_ok =
+            !matches!(self.dmarc, DmarcResult::Fail(DmarcPolicy::Reject)) || self.dmarc_overridden;
 
         spf_ok && dmarc_ok
     }
 
     /// Generate Authentication-Results header value
     pub fn to_header(&self, hostname: &str) -> String {
-        format!(
-            "{}; spf={} dkim={} dmarc={}",
+        let dmarc_comment = match self.arc.latest_sealer() {
+            Some(sealer) if self.dmarc_overridden => format!(" (overridden by arc {})", sealer),
+            _ => String::new(),
+        };
+        let mut header = format!(
+            "{}; spf={} dkim={} dmarc={}{} arc={}",
             hostname,
             self.spf.as_header_value(),
             self.dkim.as_header_value(),
-            self.dmarc.as_header_value()
-        )
+            self.dmarc.as_header_value(),
+            dmarc_comment,
+            self.arc.result.as_header_value()
+        );
+        if let Some(bimi) = &self.bimi {
+            header.push(' ');
+            header.push_str(&bimi.to_auth_result());
+        }
+        header
     }
 }
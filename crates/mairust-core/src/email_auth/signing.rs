@@ -0,0 +1,362 @@
+//! DKIM signing of outgoing mail
+//!
+//! Mail is signed with the key of the tenant domain its From address is at.
+//! Mail from a subdomain is signed with the key of the nearest tenant domain
+//! above it, when that domain's subdomain policy allows it (see
+//! [`crate::subdomains`]). Mail from domains of other tenants is never
+//! signed.
+//!
+//! A domain signs with each of its active keys (see
+//! [`mairust_storage::DkimKeyRepository`]): with an RSA and an Ed25519 key
+//! both active, messages carry both signatures (RFC 8463), so that
+//! verifiers without Ed25519 support still find the RSA one. Domains with
+//! no managed keys sign with the key on the domain itself.
+
+use super::arc::{ArcResult, ArcSealer};
+use super::dkim::{DkimSigner, DkimSigningConfig, SigningAlgorithm};
+use crate::subdomains::find_parent_domain;
+use anyhow::Result;
+use mail_parser::MessageParser;
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::{DkimKeyRepository, DomainRepository};
+use mairust_storage::Domain;
+use tracing::{debug, warn};
+
+/// Domain, selector and key a message is signed with
+#[derive(Debug, Clone)]
+pub struct SigningIdentity {
+    /// Signing domain (d= tag)
+    pub domain: String,
+    /// Selector (s= tag)
+    pub selector: String,
+    /// Private key (PEM format)
+    pub private_key_pem: String,
+    /// Algorithm of the key
+    pub algorithm: SigningAlgorithm,
+}
+
+impl SigningIdentity {
+    /// The message with a DKIM-Signature header added on top
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        Ok(with_signature(message, &self.signature(message)?))
+    }
+
+    /// DKIM-Signature header value for a message
+    pub fn signature(&self, message: &[u8]) -> Result<String> {
+        let signer = DkimSigner::new(DkimSigningConfig {
+            domain: self.domain.clone(),
+            selector: self.selector.clone(),
+            private_key_pem: self.private_key_pem.clone(),
+            algorithm: self.algorithm,
+            ..DkimSigningConfig::default()
+        })?;
+        signer.sign(message)
+    }
+
+    /// The message with an ARC set added on top, for mail forwarded by
+    /// this domain, or `None` when its ARC chain cannot be extended
+    ///
+    /// `auth_results` is the Authentication-Results value the message got
+    /// on arrival and `chain` the result of verifying its ARC chain then.
+    pub fn arc_seal(
+        &self,
+        message: &[u8],
+        auth_results: &str,
+        chain: ArcResult,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(headers) = ArcSealer::new(self)?.seal(message, auth_results, chain)? else {
+            return Ok(None);
+        };
+        let mut sealed = Vec::with_capacity(headers.len() + message.len());
+        sealed.extend_from_slice(headers.as_bytes());
+        sealed.extend_from_slice(message);
+        Ok(Some(sealed))
+    }
+}
+
+/// Finds the signing identity of outgoing mail
+pub struct DkimKeyring {
+    db_pool: DatabasePool,
+}
+
+impl DkimKeyring {
+    /// Create a keyring
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// RSA signing identity for mail a tenant sends from `sender_domain`,
+    /// as ARC seals need, or `None` when the tenant has no RSA key for it
+    pub async fn identity(
+        &self,
+        tenant_id: TenantId,
+        sender_domain: &str,
+    ) -> Result<Option<SigningIdentity>> {
+        Ok(self
+            .identities(tenant_id, sender_domain)
+            .await?
+            .into_iter()
+            .find(|identity| identity.algorithm == SigningAlgorithm::RsaSha256))
+    }
+
+    /// Signing identities for mail a tenant sends from `sender_domain`,
+    /// Ed25519 ones first; empty when the tenant has no key for it
+    pub async fn identities(
+        &self,
+        tenant_id: TenantId,
+        sender_domain: &str,
+    ) -> Result<Vec<SigningIdentity>> {
+        let sender_domain = sender_domain.trim().trim_end_matches('.').to_lowercase();
+        if let Some(domain) = DomainRepository::new(self.db_pool.clone())
+            .find_by_name(&sender_domain)
+            .await?
+        {
+            if domain.tenant_id != tenant_id {
+                return Ok(Vec::new());
+            }
+            let name = domain.name.clone();
+            return Ok(self
+                .domain_keys(domain)
+                .await?
+                .into_iter()
+                .map(|(algorithm, selector, private_key_pem)| SigningIdentity {
+                    domain: name.clone(),
+                    selector,
+                    private_key_pem,
+                    algorithm,
+                })
+                .collect());
+        }
+
+        let Some((parent, policy)) = find_parent_domain(&self.db_pool, &sender_domain).await?
+        else {
+            return Ok(Vec::new());
+        };
+        if parent.tenant_id != tenant_id || !policy.sign {
+            return Ok(Vec::new());
+        }
+        // A selector set for the subdomain can only publish one key, the
+        // RSA one
+        let fixed_selector = policy.selectors.contains_key(&sender_domain);
+        let parent_name = parent.name.clone();
+        Ok(self
+            .domain_keys(parent)
+            .await?
+            .into_iter()
+            .filter(|(algorithm, _, _)| {
+                !fixed_selector || *algorithm == SigningAlgorithm::RsaSha256
+            })
+            .map(|(algorithm, selector, private_key_pem)| {
+                let (domain, selector) =
+                    policy.signing_tags(&parent_name, &selector, &sender_domain);
+                SigningIdentity {
+                    domain,
+                    selector,
+                    private_key_pem,
+                    algorithm,
+                }
+            })
+            .collect())
+    }
+
+    /// Algorithm, selector and private key of each active key of a domain,
+    /// Ed25519 ones first, or the key on the domain itself when it has no
+    /// managed keys
+    async fn domain_keys(&self, domain: Domain) -> Result<Vec<(SigningAlgorithm, String, String)>> {
+        let mut keys: Vec<_> = DkimKeyRepository::new(self.db_pool.clone())
+            .active(domain.id)
+            .await?
+            .into_iter()
+            .filter_map(|key| match SigningAlgorithm::parse(&key.algorithm) {
+                Some(algorithm) => Some((algorithm, key.selector, key.private_key)),
+                None => {
+                    warn!(
+                        "Unknown DKIM algorithm {} of selector {} of {}",
+                        key.algorithm, key.selector, domain.name
+                    );
+                    None
+                }
+            })
+            .collect();
+        if keys.is_empty() {
+            if let (Some(selector), Some(private_key)) =
+                (domain.dkim_selector, domain.dkim_private_key)
+            {
+                keys.push((SigningAlgorithm::RsaSha256, selector, private_key));
+            }
+        }
+        keys.sort_by_key(|(algorithm, _, _)| *algorithm != SigningAlgorithm::Ed25519Sha256);
+        Ok(keys)
+    }
+
+    /// The message signed for the domain of its From address, with one
+    /// signature per active key, or `None` when it is not signed
+    pub async fn sign(&self, tenant_id: TenantId, message: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(sender_domain) = from_domain(message) else {
+            return Ok(None);
+        };
+        let identities = self.identities(tenant_id, &sender_domain).await?;
+        if identities.is_empty() {
+            debug!("No DKIM key for mail from {}", sender_domain);
+            return Ok(None);
+        }
+        // Every signature covers the message as it was; the RSA one ends
+        // up last, next to the message
+        let mut signed = message.to_vec();
+        for identity in identities.iter().rev() {
+            signed = with_signature(&signed, &identity.signature(message)?);
+        }
+        Ok(Some(signed))
+    }
+
+    /// The message signed like [`Self::sign`], or `None` when it is not
+    /// signed; failures are logged, and the message goes out unsigned
+    pub async fn try_sign(&self, tenant_id: TenantId, message: &[u8]) -> Option<Vec<u8>> {
+        match self.sign(tenant_id, message).await {
+            Ok(signed) => signed,
+            Err(e) => {
+                warn!("Failed to DKIM-sign message: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The message with an ARC set added by `forwarder_domain`, for mail
+    /// the tenant forwards from that domain; `None` when the domain has no
+    /// RSA key or the chain cannot be extended. Failures are logged, and
+    /// the message is forwarded unsealed.
+    pub async fn try_arc_seal(
+        &self,
+        tenant_id: TenantId,
+        forwarder_domain: &str,
+        message: &[u8],
+        auth_results: &str,
+        chain: ArcResult,
+    ) -> Option<Vec<u8>> {
+        let identity = self.identity(tenant_id, forwarder_domain).await;
+        arc_seal_with(identity, message, auth_results, chain)
+    }
+}
+
+/// The message sealed with the identity the keyring found, as
+/// [`DkimKeyring::try_arc_seal`] returns it
+fn arc_seal_with(
+    identity: Result<Option<SigningIdentity>>,
+    message: &[u8],
+    auth_results: &str,
+    chain: ArcResult,
+) -> Option<Vec<u8>> {
+    let sealed = match identity {
+        Ok(Some(identity)) => identity.arc_seal(message, auth_results, chain),
+        Ok(None) => return None,
+        Err(e) => Err(e),
+    };
+    sealed.unwrap_or_else(|e| {
+        warn!("Failed to ARC-seal message: {}", e);
+        None
+    })
+}
+
+/// Domain of the first From address of a message
+fn from_domain(message: &[u8]) -> Option<String> {
+    let parsed = MessageParser::default().parse(message)?;
+    let address = parsed.from()?.first()?.address()?;
+    let (_, domain) = address.rsplit_once('@')?;
+    (!domain.is_empty()).then(|| domain.to_lowercase())
+}
+
+/// Put a DKIM-Signature header with `value` on top of a message
+fn with_signature(message: &[u8], value: &str) -> Vec<u8> {
+    let header = format!("DKIM-Signature: {}\r\n", value);
+    let mut signed = Vec::with_capacity(header.len() + message.len());
+    signed.extend_from_slice(header.as_bytes());
+    signed.extend_from_slice(message);
+    signed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_domain() {
+        let message = b"From: News <News@Mail.Example.com>\r\nTo: a@example.org\r\n\r\nHi\r\n";
+        assert_eq!(from_domain(message), Some("mail.example.com".to_string()));
+        assert_eq!(from_domain(b"To: a@example.org\r\n\r\nHi\r\n"), None);
+    }
+
+    #[test]
+    fn test_arc_seal_with() {
+        use crate::email_auth::arc::{verify_chain, Keys};
+        use base64::Engine;
+        use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+
+        let private_key = rsa::RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+        let public_key = rsa::RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .unwrap();
+        let identity = SigningIdentity {
+            domain: "example.net".to_string(),
+            selector: "fwd".to_string(),
+            private_key_pem: private_key
+                .to_pkcs8_pem(LineEnding::LF)
+                .unwrap()
+                .to_string(),
+            algorithm: SigningAlgorithm::RsaSha256,
+        };
+        let message = b"From: Alice <alice@example.com>\r\nTo: bob@example.net\r\n\r\nHello\r\n";
+        let auth_results = "mx.example.net; spf=pass dkim=pass";
+
+        // A key of the forwarding domain seals the message
+        let sealed = arc_seal_with(
+            Ok(Some(identity.clone())),
+            message,
+            auth_results,
+            ArcResult::None,
+        )
+        .unwrap();
+        assert!(sealed.ends_with(message));
+        let keys = Keys::from([(
+            ("fwd".to_string(), "example.net".to_string()),
+            base64::engine::general_purpose::STANDARD.encode(public_key.as_bytes()),
+        )]);
+        assert_eq!(verify_chain(&sealed, &keys).result, ArcResult::Pass);
+
+        // No key, a lookup that failed, or a key that cannot sign
+        assert!(arc_seal_with(Ok(None), message, auth_results, ArcResult::None).is_none());
+        assert!(arc_seal_with(
+            Err(anyhow::anyhow!("database unavailable")),
+            message,
+            auth_results,
+            ArcResult::None
+        )
+        .is_none());
+        let broken = SigningIdentity {
+            private_key_pem: "not a key".to_string(),
+            ..identity.clone()
+        };
+        assert!(arc_seal_with(Ok(Some(broken)), message, auth_results, ArcResult::None).is_none());
+
+        // A chain sealed as failed is not extended
+        let failed = arc_seal_with(
+            Ok(Some(identity.clone())),
+            &sealed,
+            auth_results,
+            ArcResult::Fail,
+        )
+        .unwrap();
+        assert!(
+            arc_seal_with(Ok(Some(identity)), &failed, auth_results, ArcResult::Fail).is_none()
+        );
+    }
+
+    #[test]
+    fn test_with_signature() {
+        let signed = with_signature(b"From: a@example.com\r\n\r\nHi\r\n", "v=1; d=example.com");
+        assert_eq!(
+            signed,
+            b"DKIM-Signature: v=1; d=example.com\r\nFrom: a@example.com\r\n\r\nHi\r\n"
+        );
+    }
+}
@@ -1,13 +1,30 @@
 //! SPF (Sender Policy Framework) verification
 //!
-//! Implements RFC 7208 - Sender Policy Framework (SPF) for Authorizing Use of Domains in Email
+//! Implements RFC 7208 - Sender Policy Framework (SPF) for Authorizing Use of Domains in Email:
+//! macro expansion (`%{s}`, `%{i}`, `%{d2r}`, ...), the `redirect=` and
+//! `exp=` modifiers, and the limits of 10 DNS-querying terms and 2 void
+//! lookups per check.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use std::net::IpAddr;
 use tracing::{debug, warn};
 use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
 use trust_dns_resolver::TokioAsyncResolver;
 
+/// DNS-querying terms (include, a, mx, ptr, exists, redirect) allowed per
+/// check (RFC 7208 section 4.6.4)
+const MAX_DNS_LOOKUPS: usize = 10;
+
+/// Lookups allowed to find no records per check
+const MAX_VOID_LOOKUPS: usize = 2;
+
+/// MX and PTR names looked at per mechanism
+const MAX_NAME_LOOKUPS: usize = 10;
+
+/// Longest explanation passed on to clients
+const MAX_EXPLANATION_LEN: usize = 256;
+
 /// SPF verification result
 #[derive(Debug, Clone, PartialEq)]
 pub enum SpfResult {
@@ -42,26 +59,56 @@ impl SpfResult {
     }
 }
 
+/// Result of an SPF check with what the domain says about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpfEvaluation {
+    pub result: SpfResult,
+    /// Domain whose policy was checked: the MAIL FROM domain, or the HELO
+    /// name for bounces
+    pub domain: Option<String>,
+    /// Explanation the domain publishes with `exp=`, for fail results
+    pub explanation: Option<String>,
+}
+
+impl SpfEvaluation {
+    fn none(domain: Option<String>) -> Self {
+        Self {
+            result: SpfResult::None,
+            domain,
+            explanation: None,
+        }
+    }
+
+    /// Reply text refusing mail that failed the check (RFC 7372)
+    pub fn reply(&self) -> String {
+        match (&self.explanation, &self.domain) {
+            (Some(explanation), _) => format!("5.7.23 {}", explanation),
+            (None, Some(domain)) => format!("5.7.23 SPF validation failed for {}", domain),
+            (None, None) => "5.7.23 SPF validation failed".to_string(),
+        }
+    }
+}
+
 /// SPF mechanism types
 #[derive(Debug, Clone)]
 enum SpfMechanism {
     All,
-    Include(String),
-    A(Option<String>),
-    Mx(Option<String>),
+    Include(MacroString),
+    A(Option<MacroString>, Cidr),
+    Mx(Option<MacroString>, Cidr),
     Ip4(ipnet::Ipv4Net),
     Ip6(ipnet::Ipv6Net),
-    Ptr(Option<String>),
-    Exists(String),
+    Ptr(Option<MacroString>),
+    Exists(MacroString),
 }
 
 /// SPF qualifier (prefix)
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SpfQualifier {
-    Pass,    // + (default)
-    Fail,    // -
+    Pass,     // + (default)
+    Fail,     // -
     SoftFail, // ~
-    Neutral, // ?
+    Neutral,  // ?
 }
 
 impl SpfQualifier {
@@ -82,10 +129,194 @@ struct SpfDirective {
     mechanism: SpfMechanism,
 }
 
+/// Parsed SPF record
+#[derive(Debug, Clone)]
+struct SpfRecord {
+    directives: Vec<SpfDirective>,
+    /// Domain whose policy applies when no mechanism matches
+    redirect: Option<MacroString>,
+    /// Domain whose TXT record explains fail results
+    exp: Option<MacroString>,
+}
+
+/// Prefix lengths of the `a` and `mx` mechanisms (`a/24//64`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cidr {
+    v4: u8,
+    v6: u8,
+}
+
+impl Default for Cidr {
+    fn default() -> Self {
+        Self { v4: 32, v6: 128 }
+    }
+}
+
+impl Cidr {
+    /// Whether `ip` is in the network of `address`
+    fn contains(self, address: IpAddr, ip: IpAddr) -> bool {
+        match (address, ip) {
+            (IpAddr::V4(address), IpAddr::V4(ip)) => ipnet::Ipv4Net::new(address, self.v4)
+                .map(|net| net.contains(&ip))
+                .unwrap_or(false),
+            (IpAddr::V6(address), IpAddr::V6(ip)) => ipnet::Ipv6Net::new(address, self.v6)
+                .map(|net| net.contains(&ip))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+/// A domain-spec or explanation with macros (RFC 7208 section 7)
+#[derive(Debug, Clone, PartialEq)]
+struct MacroString(Vec<MacroToken>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum MacroToken {
+    Literal(String),
+    Macro {
+        /// Macro letter, in lower case
+        letter: char,
+        /// Upper-case letter: URL-escape the value
+        escape: bool,
+        /// Keep this many right-hand parts
+        keep: Option<usize>,
+        reverse: bool,
+        /// Characters the value is split on; `.` when empty
+        delimiters: String,
+    },
+}
+
+/// What macros expand to during a check
+#[derive(Debug, Clone)]
+struct MacroContext {
+    /// `%{s}`: the sender address
+    sender: String,
+    /// `%{l}`: its local part
+    local: String,
+    /// `%{o}`: its domain
+    sender_domain: String,
+    /// `%{i}`, `%{c}` and `%{v}`: the client address
+    ip: IpAddr,
+    /// `%{h}`: the HELO name
+    helo: String,
+    /// `%{r}`: this host's name
+    receiver: String,
+    /// `%{t}`: seconds since the epoch
+    timestamp: i64,
+}
+
+impl MacroString {
+    /// Whether the string uses a macro letter
+    fn uses(&self, letter: char) -> bool {
+        self.0
+            .iter()
+            .any(|token| matches!(token, MacroToken::Macro { letter: l, .. } if *l == letter))
+    }
+
+    /// Expand the macros for the current `domain`; `validated` is the
+    /// client's validated name for `%{p}`
+    fn expand(&self, ctx: &MacroContext, domain: &str, validated: Option<&str>) -> String {
+        let mut out = String::new();
+        for token in &self.0 {
+            let (letter, escape, keep, reverse, delimiters) = match token {
+                MacroToken::Literal(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                MacroToken::Macro {
+                    letter,
+                    escape,
+                    keep,
+                    reverse,
+                    delimiters,
+                } => (*letter, *escape, *keep, *reverse, delimiters),
+            };
+            let value = match letter {
+                's' => ctx.sender.clone(),
+                'l' => ctx.local.clone(),
+                'o' => ctx.sender_domain.clone(),
+                'd' => domain.to_string(),
+                'i' => dotted_ip(ctx.ip),
+                'p' => validated.unwrap_or("unknown").to_string(),
+                'v' => match ctx.ip {
+                    IpAddr::V4(_) => "in-addr".to_string(),
+                    IpAddr::V6(_) => "ip6".to_string(),
+                },
+                'h' => ctx.helo.clone(),
+                'c' => ctx.ip.to_string(),
+                'r' => ctx.receiver.clone(),
+                't' => ctx.timestamp.to_string(),
+                _ => String::new(),
+            };
+
+            let mut parts: Vec<&str> = if delimiters.is_empty() {
+                value.split('.').collect()
+            } else {
+                value.split(|c| delimiters.contains(c)).collect()
+            };
+            if reverse {
+                parts.reverse();
+            }
+            if let Some(keep) = keep {
+                if parts.len() > keep {
+                    parts.drain(..parts.len() - keep);
+                }
+            }
+            let value = parts.join(".");
+            if escape {
+                out.push_str(&url_escape(&value));
+            } else {
+                out.push_str(&value);
+            }
+        }
+        out
+    }
+}
+
+/// Counts the DNS-querying terms and void lookups of a check
+struct Lookups {
+    terms: usize,
+    voids: usize,
+    max_terms: usize,
+    max_voids: usize,
+}
+
+impl Lookups {
+    fn count_term(&mut self) -> Result<(), SpfResult> {
+        self.terms += 1;
+        if self.terms > self.max_terms {
+            debug!("SPF check exceeded {} DNS lookups", self.max_terms);
+            return Err(SpfResult::PermError);
+        }
+        Ok(())
+    }
+
+    fn count_void(&mut self) -> Result<(), SpfResult> {
+        self.voids += 1;
+        if self.voids > self.max_voids {
+            debug!("SPF check exceeded {} void lookups", self.max_voids);
+            return Err(SpfResult::PermError);
+        }
+        Ok(())
+    }
+}
+
+type CheckFuture<'a> = std::pin::Pin<
+    Box<
+        dyn std::future::Future<
+                Output = std::result::Result<(SpfResult, Option<String>), SpfResult>,
+            > + Send
+            + 'a,
+    >,
+>;
+
 /// SPF verifier
 pub struct SpfVerifier {
     resolver: TokioAsyncResolver,
     max_dns_lookups: usize,
+    max_void_lookups: usize,
+    hostname: String,
 }
 
 impl SpfVerifier {
@@ -93,222 +324,420 @@ impl SpfVerifier {
     pub async fn new() -> Result<Self> {
         let resolver =
             TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
-        Ok(Self {
-            resolver,
-            max_dns_lookups: 10, // RFC 7208 limit
-        })
+        Ok(Self::with_resolver(resolver))
     }
 
     /// Create a new SPF verifier with custom resolver
     pub fn with_resolver(resolver: TokioAsyncResolver) -> Self {
         Self {
             resolver,
-            max_dns_lookups: 10,
+            max_dns_lookups: MAX_DNS_LOOKUPS,
+            max_void_lookups: MAX_VOID_LOOKUPS,
+            hostname: "unknown".to_string(),
         }
     }
 
+    /// Name of this host, which explanations can include with `%{r}`
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        self.hostname = hostname.to_string();
+        self
+    }
+
     /// Verify SPF for a given sender and connecting IP
     pub async fn verify(&self, mail_from: &str, client_ip: IpAddr) -> SpfResult {
-        // Extract domain from MAIL FROM
-        let domain = match extract_domain(mail_from) {
-            Some(d) => d,
-            None => {
-                debug!("Could not extract domain from MAIL FROM: {}", mail_from);
-                return SpfResult::None;
+        self.check(mail_from, None, client_ip).await.result
+    }
+
+    /// Check the sender's policy, with the explanation of a fail result
+    ///
+    /// Bounces, which have an empty MAIL FROM, are checked against the HELO
+    /// name (RFC 7208 section 2.4).
+    pub async fn check(
+        &self,
+        mail_from: &str,
+        helo: Option<&str>,
+        client_ip: IpAddr,
+    ) -> SpfEvaluation {
+        let helo = helo
+            .map(|helo| helo.trim().trim_end_matches('.').to_lowercase())
+            .filter(|helo| !helo.is_empty());
+        let address = mail_from
+            .trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let (local, domain) = if address.is_empty() {
+            match &helo {
+                Some(helo) => ("postmaster".to_string(), helo.clone()),
+                None => return SpfEvaluation::none(None),
+            }
+        } else {
+            match (address.rfind('@'), extract_domain(address)) {
+                (Some(at), Some(domain)) => {
+                    let local = match &address[..at] {
+                        "" => "postmaster",
+                        local => local,
+                    };
+                    (local.to_string(), domain)
+                }
+                _ => {
+                    debug!("Could not extract domain from MAIL FROM: {}", mail_from);
+                    return SpfEvaluation::none(None);
+                }
             }
         };
 
+        // IPv4 clients of dual-stack listeners are checked as IPv4
+        let client_ip = match client_ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(client_ip),
+            ip => ip,
+        };
+
         debug!("Checking SPF for domain {} from IP {}", domain, client_ip);
 
-        // Perform SPF check
-        match self.check_spf(&domain, client_ip, 0).await {
-            Ok(result) => result,
-            Err(e) => {
-                warn!("SPF check error for {}: {}", domain, e);
-                SpfResult::TempError
-            }
+        let ctx = MacroContext {
+            sender: format!("{}@{}", local, domain),
+            local,
+            sender_domain: domain.clone(),
+            ip: client_ip,
+            helo: helo.unwrap_or_else(|| "unknown".to_string()),
+            receiver: self.hostname.clone(),
+            timestamp: chrono::Utc::now().timestamp(),
+        };
+        let mut lookups = Lookups {
+            terms: 0,
+            voids: 0,
+            max_terms: self.max_dns_lookups,
+            max_voids: self.max_void_lookups,
+        };
+        let (result, explanation) = match self.check_host(&ctx, domain.clone(), &mut lookups).await
+        {
+            Ok(outcome) => outcome,
+            Err(result) => (result, None),
+        };
+        SpfEvaluation {
+            result,
+            domain: Some(domain),
+            explanation,
         }
     }
 
-    /// Recursive SPF check with depth tracking
-    fn check_spf<'a>(
+    /// Evaluate the policy of a domain (check_host(), RFC 7208 section 4)
+    ///
+    /// Temporary and permanent errors end the whole check, so they are
+    /// returned as errors.
+    fn check_host<'a>(
         &'a self,
-        domain: &'a str,
-        client_ip: IpAddr,
-        depth: usize,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<SpfResult>> + Send + 'a>> {
+        ctx: &'a MacroContext,
+        domain: String,
+        lookups: &'a mut Lookups,
+    ) -> CheckFuture<'a> {
         Box::pin(async move {
-        if depth >= self.max_dns_lookups {
-            return Ok(SpfResult::PermError);
-        }
-
-        // Query TXT records for the domain
-        let spf_record = match self.get_spf_record(domain).await {
-            Ok(Some(record)) => record,
-            Ok(None) => return Ok(SpfResult::None),
-            Err(e) => {
-                warn!("DNS lookup failed for {}: {}", domain, e);
-                return Ok(SpfResult::TempError);
+            if !is_valid_domain(&domain) {
+                debug!("Not checking SPF of malformed domain {}", domain);
+                return Ok((SpfResult::None, None));
             }
-        };
 
-        debug!("Found SPF record for {}: {}", domain, spf_record);
+            // Query TXT records for the domain
+            let spf_record = match self.get_spf_record(&domain).await? {
+                Some(record) => record,
+                None => return Ok((SpfResult::None, None)),
+            };
 
-        // Parse the SPF record
-        let directives = match parse_spf_record(&spf_record) {
-            Ok(d) => d,
-            Err(e) => {
+            debug!("Found SPF record for {}: {}", domain, spf_record);
+
+            // Parse the SPF record
+            let record = parse_spf_record(&spf_record).map_err(|e| {
                 warn!("Failed to parse SPF record for {}: {}", domain, e);
-                return Ok(SpfResult::PermError);
+                SpfResult::PermError
+            })?;
+
+            // Evaluate each directive
+            for directive in &record.directives {
+                if self
+                    .matches(&directive.mechanism, ctx, &domain, lookups)
+                    .await?
+                {
+                    let result = directive.qualifier.to_result();
+                    let explanation = match (&result, &record.exp) {
+                        (SpfResult::Fail, Some(exp)) => self.explanation(exp, ctx, &domain).await,
+                        _ => None,
+                    };
+                    return Ok((result, explanation));
+                }
             }
-        };
 
-        // Evaluate each directive
-        for directive in directives {
-            match self
-                .evaluate_directive(&directive, domain, client_ip, depth)
-                .await?
-            {
-                Some(result) => return Ok(result),
-                None => continue,
+            // Without a match, the redirect's policy applies
+            if let Some(redirect) = &record.redirect {
+                lookups.count_term()?;
+                let target = self.expand_domain(redirect, ctx, &domain).await;
+                return match self.check_host(ctx, target, lookups).await? {
+                    (SpfResult::None, _) => Err(SpfResult::PermError),
+                    outcome => Ok(outcome),
+                };
             }
-        }
 
-        // Default result if no mechanism matches
-        Ok(SpfResult::Neutral)
+            // Default result if no mechanism matches
+            Ok((SpfResult::Neutral, None))
         })
     }
 
-    /// Get SPF TXT record for a domain
-    async fn get_spf_record(&self, domain: &str) -> Result<Option<String>> {
-        let lookup = self.resolver.txt_lookup(domain).await?;
-
-        for record in lookup.iter() {
-            let txt = record
-                .txt_data()
-                .iter()
-                .map(|d| String::from_utf8_lossy(d))
-                .collect::<String>();
-
-            if txt.starts_with("v=spf1 ") || txt == "v=spf1" {
-                return Ok(Some(txt));
+    /// Get the SPF TXT record of a domain; more than one is an error
+    async fn get_spf_record(&self, domain: &str) -> std::result::Result<Option<String>, SpfResult> {
+        let lookup = match self.resolver.txt_lookup(fqdn(domain)).await {
+            Ok(lookup) => lookup,
+            Err(e) if is_void(&e) => return Ok(None),
+            Err(e) => {
+                warn!("DNS lookup failed for {}: {}", domain, e);
+                return Err(SpfResult::TempError);
             }
-        }
+        };
 
-        Ok(None)
+        let mut records = lookup
+            .iter()
+            .map(|record| txt_string(record.txt_data()))
+            .filter(|txt| is_spf_record(txt));
+        let record = records.next();
+        if records.next().is_some() {
+            warn!("{} publishes more than one SPF record", domain);
+            return Err(SpfResult::PermError);
+        }
+        Ok(record)
     }
 
-    /// Evaluate a single SPF directive
-    fn evaluate_directive<'a>(
-        &'a self,
-        directive: &'a SpfDirective,
-        domain: &'a str,
-        client_ip: IpAddr,
-        depth: usize,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Option<SpfResult>>> + Send + 'a>> {
-        Box::pin(async move {
-        let matches = match &directive.mechanism {
-            SpfMechanism::All => true,
+    /// Whether a mechanism matches the client
+    async fn matches(
+        &self,
+        mechanism: &SpfMechanism,
+        ctx: &MacroContext,
+        domain: &str,
+        lookups: &mut Lookups,
+    ) -> std::result::Result<bool, SpfResult> {
+        match mechanism {
+            SpfMechanism::All => Ok(true),
 
             SpfMechanism::Ip4(network) => {
-                if let IpAddr::V4(ip) = client_ip {
-                    network.contains(&ip)
-                } else {
-                    false
-                }
+                Ok(matches!(ctx.ip, IpAddr::V4(ip) if network.contains(&ip)))
             }
 
             SpfMechanism::Ip6(network) => {
-                if let IpAddr::V6(ip) = client_ip {
-                    network.contains(&ip)
-                } else {
-                    false
-                }
+                Ok(matches!(ctx.ip, IpAddr::V6(ip) if network.contains(&ip)))
             }
 
-            SpfMechanism::A(target) => {
-                let target_domain = target.as_deref().unwrap_or(domain);
-                self.check_a_record(target_domain, client_ip).await?
+            SpfMechanism::A(target, cidr) => {
+                lookups.count_term()?;
+                let target = self.target(target.as_ref(), ctx, domain).await;
+                let addresses = self.addresses(&target, ctx.ip, Some(lookups)).await?;
+                Ok(addresses
+                    .iter()
+                    .any(|&address| cidr.contains(address, ctx.ip)))
             }
 
-            SpfMechanism::Mx(target) => {
-                let target_domain = target.as_deref().unwrap_or(domain);
-                self.check_mx_record(target_domain, client_ip).await?
+            SpfMechanism::Mx(target, cidr) => {
+                lookups.count_term()?;
+                let target = self.target(target.as_ref(), ctx, domain).await;
+                let hosts: Vec<String> = match self.resolver.mx_lookup(fqdn(&target)).await {
+                    Ok(lookup) => lookup.iter().map(|mx| mx.exchange().to_string()).collect(),
+                    Err(e) if is_void(&e) => {
+                        lookups.count_void()?;
+                        Vec::new()
+                    }
+                    Err(e) => {
+                        warn!("MX lookup failed for {}: {}", target, e);
+                        return Err(SpfResult::TempError);
+                    }
+                };
+                if hosts.len() > MAX_NAME_LOOKUPS {
+                    debug!("{} has more than {} MX hosts", target, MAX_NAME_LOOKUPS);
+                    return Err(SpfResult::PermError);
+                }
+                for host in hosts {
+                    let addresses = self.addresses(&host, ctx.ip, None).await?;
+                    if addresses
+                        .iter()
+                        .any(|&address| cidr.contains(address, ctx.ip))
+                    {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
             }
 
             SpfMechanism::Include(included_domain) => {
-                let result = self
-                    .check_spf(included_domain, client_ip, depth + 1)
-                    .await?;
-                result == SpfResult::Pass
+                lookups.count_term()?;
+                let target = self.expand_domain(included_domain, ctx, domain).await;
+                match self.check_host(ctx, target, lookups).await? {
+                    (SpfResult::Pass, _) => Ok(true),
+                    (SpfResult::None, _) => Err(SpfResult::PermError),
+                    _ => Ok(false),
+                }
             }
 
-            SpfMechanism::Ptr(_) => {
-                // PTR mechanism is deprecated and computationally expensive
-                // We treat it as a non-match for security reasons
-                warn!("PTR mechanism used but not evaluated (deprecated)");
-                false
+            SpfMechanism::Ptr(target) => {
+                // Deprecated and slow, but still published
+                lookups.count_term()?;
+                let target = self.target(target.as_ref(), ctx, domain).await;
+                let names = self.validated_names(ctx.ip, Some(lookups)).await?;
+                Ok(names.iter().any(|name| is_subdomain(name, &target)))
             }
 
             SpfMechanism::Exists(macro_domain) => {
-                // Check if A record exists for the domain
-                self.check_exists(macro_domain).await?
+                // Any A record will do, whatever the client's address family
+                lookups.count_term()?;
+                let target = self.expand_domain(macro_domain, ctx, domain).await;
+                match self.resolver.ipv4_lookup(fqdn(&target)).await {
+                    Ok(lookup) => Ok(lookup.iter().next().is_some()),
+                    Err(e) if is_void(&e) => {
+                        lookups.count_void()?;
+                        Ok(false)
+                    }
+                    Err(e) => {
+                        warn!("A lookup failed for {}: {}", target, e);
+                        Err(SpfResult::TempError)
+                    }
+                }
             }
-        };
+        }
+    }
 
-        if matches {
-            Ok(Some(directive.qualifier.to_result()))
-        } else {
-            Ok(None)
+    /// Domain a mechanism checks: its own, or the current domain
+    async fn target(&self, spec: Option<&MacroString>, ctx: &MacroContext, domain: &str) -> String {
+        match spec {
+            Some(spec) => self.expand_domain(spec, ctx, domain).await,
+            None => domain.to_string(),
         }
-        })
     }
 
-    /// Check if client IP matches any A/AAAA record for domain
-    async fn check_a_record(&self, domain: &str, client_ip: IpAddr) -> Result<bool> {
-        match client_ip {
-            IpAddr::V4(ip) => {
-                if let Ok(lookup) = self.resolver.ipv4_lookup(domain).await {
-                    for record in lookup.iter() {
-                        // Convert A record to Ipv4Addr for comparison
-                        let record_ip: std::net::Ipv4Addr = (*record).into();
-                        if record_ip == ip {
-                            return Ok(true);
-                        }
-                    }
+    /// A or AAAA addresses of a name, whichever family the client's is;
+    /// lookups finding nothing count as void when `lookups` is given
+    async fn addresses(
+        &self,
+        name: &str,
+        client_ip: IpAddr,
+        lookups: Option<&mut Lookups>,
+    ) -> std::result::Result<Vec<IpAddr>, SpfResult> {
+        let found = match client_ip {
+            IpAddr::V4(_) => self
+                .resolver
+                .ipv4_lookup(fqdn(name))
+                .await
+                .map(|lookup| lookup.iter().map(|a| IpAddr::V4(a.0)).collect()),
+            IpAddr::V6(_) => self
+                .resolver
+                .ipv6_lookup(fqdn(name))
+                .await
+                .map(|lookup| lookup.iter().map(|aaaa| IpAddr::V6(aaaa.0)).collect()),
+        };
+        match found {
+            Ok(addresses) => Ok(addresses),
+            Err(e) if is_void(&e) => {
+                if let Some(lookups) = lookups {
+                    lookups.count_void()?;
                 }
+                Ok(Vec::new())
             }
-            IpAddr::V6(ip) => {
-                if let Ok(lookup) = self.resolver.ipv6_lookup(domain).await {
-                    for record in lookup.iter() {
-                        // Convert AAAA record to Ipv6Addr for comparison
-                        let record_ip: std::net::Ipv6Addr = (*record).into();
-                        if record_ip == ip {
-                            return Ok(true);
-                        }
-                    }
-                }
+            Err(e) => {
+                warn!("Address lookup failed for {}: {}", name, e);
+                Err(SpfResult::TempError)
             }
         }
-        Ok(false)
     }
 
-    /// Check if client IP matches any MX host's A/AAAA record
-    async fn check_mx_record(&self, domain: &str, client_ip: IpAddr) -> Result<bool> {
-        if let Ok(mx_lookup) = self.resolver.mx_lookup(domain).await {
-            for mx in mx_lookup.iter() {
-                let mx_host = mx.exchange().to_string();
-                if self.check_a_record(&mx_host, client_ip).await? {
-                    return Ok(true);
+    /// Names of the client's PTR records that resolve back to its address
+    /// (RFC 7208 section 5.5)
+    ///
+    /// Lookup failures give no names rather than an error.
+    async fn validated_names(
+        &self,
+        client_ip: IpAddr,
+        lookups: Option<&mut Lookups>,
+    ) -> std::result::Result<Vec<String>, SpfResult> {
+        let names: Vec<String> = match self.resolver.reverse_lookup(client_ip).await {
+            Ok(lookup) => lookup
+                .iter()
+                .take(MAX_NAME_LOOKUPS)
+                .map(|ptr| ptr.0.to_string().trim_end_matches('.').to_lowercase())
+                .collect(),
+            Err(e) => {
+                if let (true, Some(lookups)) = (is_void(&e), lookups) {
+                    lookups.count_void()?;
                 }
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut validated = Vec::new();
+        for name in names {
+            match self.addresses(&name, client_ip, None).await {
+                Ok(addresses) if addresses.contains(&client_ip) => validated.push(name),
+                _ => {}
             }
         }
-        Ok(false)
+        Ok(validated)
+    }
+
+    /// The client's validated name for `%{p}`, preferring one in `domain`
+    async fn validated_name(&self, client_ip: IpAddr, domain: &str) -> String {
+        let names = self
+            .validated_names(client_ip, None)
+            .await
+            .unwrap_or_default();
+        names
+            .iter()
+            .find(|name| is_subdomain(name, domain))
+            .or_else(|| names.first())
+            .cloned()
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Expand a macro string, looking up the client's name if it is used
+    async fn expand(&self, spec: &MacroString, ctx: &MacroContext, domain: &str) -> String {
+        let validated = match spec.uses('p') {
+            true => Some(self.validated_name(ctx.ip, domain).await),
+            false => None,
+        };
+        spec.expand(ctx, domain, validated.as_deref())
     }
 
-    /// Check if A record exists for domain (exists mechanism)
-    async fn check_exists(&self, domain: &str) -> Result<bool> {
-        Ok(self.resolver.ipv4_lookup(domain).await.is_ok())
+    /// Expand a domain-spec into a name that can be looked up
+    async fn expand_domain(&self, spec: &MacroString, ctx: &MacroContext, domain: &str) -> String {
+        truncate_domain(&self.expand(spec, ctx, domain).await)
+    }
+
+    /// Explanation of a fail result, from the TXT record `exp=` names
+    ///
+    /// Any problem with the record means no explanation (RFC 7208 section
+    /// 6.2). Text that is not printable ASCII is not passed on.
+    async fn explanation(
+        &self,
+        exp: &MacroString,
+        ctx: &MacroContext,
+        domain: &str,
+    ) -> Option<String> {
+        let target = self.expand_domain(exp, ctx, domain).await;
+        let lookup = match self.resolver.txt_lookup(fqdn(&target)).await {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                debug!("No SPF explanation at {}: {}", target, e);
+                return None;
+            }
+        };
+        let mut records = lookup.iter().map(|record| txt_string(record.txt_data()));
+        let text = records.next()?;
+        if records.next().is_some() {
+            debug!("More than one SPF explanation at {}", target);
+            return None;
+        }
+        let text = parse_macro_string(&text, true).ok()?;
+        let explanation = self.expand(&text, ctx, domain).await;
+        let explanation = explanation.trim();
+        if explanation.is_empty() || !explanation.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+            return None;
+        }
+        let mut end = explanation.len().min(MAX_EXPLANATION_LEN);
+        while !explanation.is_char_boundary(end) {
+            end -= 1;
+        }
+        Some(explanation[..end].to_string())
     }
 }
 
@@ -328,26 +757,189 @@ fn extract_domain(email: &str) -> Option<String> {
     None
 }
 
-/// Parse SPF record into directives
-fn parse_spf_record(record: &str) -> Result<Vec<SpfDirective>> {
-    let mut directives = Vec::new();
-
-    // Remove "v=spf1" prefix
-    let terms: &str = record
-        .strip_prefix("v=spf1")
-        .ok_or_else(|| anyhow!("Invalid SPF record: missing v=spf1"))?
-        .trim();
-
-    for term in terms.split_whitespace() {
-        // Skip modifiers (redirect, exp, etc.) for now
-        if term.contains('=') {
-            // Handle redirect modifier
-            if let Some(domain) = term.strip_prefix("redirect=") {
-                directives.push(SpfDirective {
-                    qualifier: SpfQualifier::Pass,
-                    mechanism: SpfMechanism::Include(domain.to_string()),
+/// Whether a TXT record is an SPF record (`v=spf1` alone or before a space)
+fn is_spf_record(txt: &str) -> bool {
+    match txt.get(..6) {
+        Some(version) if version.eq_ignore_ascii_case("v=spf1") => {
+            txt.len() == 6 || txt.as_bytes()[6] == b' '
+        }
+        _ => false,
+    }
+}
+
+/// The strings of a TXT record, concatenated without spaces
+fn txt_string(data: &[Box<[u8]>]) -> String {
+    data.iter().map(|d| String::from_utf8_lossy(d)).collect()
+}
+
+/// Whether a failed lookup found no records (NXDOMAIN or an empty answer)
+fn is_void(error: &ResolveError) -> bool {
+    matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+/// A name as an absolute name, so no search domain is appended
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Whether a domain can be checked: several labels of 1 to 63 characters
+fn is_valid_domain(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.');
+    domain.len() <= 253
+        && domain.contains('.')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+}
+
+/// Whether `name` is `domain` or a name below it
+fn is_subdomain(name: &str, domain: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    name == domain || name.ends_with(&format!(".{}", domain))
+}
+
+/// Drop leading labels of an expanded name longer than 253 characters
+fn truncate_domain(name: &str) -> String {
+    let mut name = name.trim_end_matches('.');
+    while name.len() > 253 {
+        match name.find('.') {
+            Some(pos) => name = &name[pos + 1..],
+            None => break,
+        }
+    }
+    name.to_string()
+}
+
+/// The client address as `%{i}` gives it: dotted nibbles for IPv6
+fn dotted_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0x0f])
+            .map(|nibble| format!("{:x}", nibble))
+            .collect::<Vec<_>>()
+            .join("."),
+    }
+}
+
+/// URL-escape a macro value, for upper-case macro letters
+fn url_escape(value: &str) -> String {
+    let mut out = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// Parse a macro string; `%{c}`, `%{r}` and `%{t}` are only allowed in
+/// explanations
+fn parse_macro_string(s: &str, explanation: bool) -> Result<MacroString> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => literal.push('%'),
+            Some('_') => literal.push(' '),
+            Some('-') => literal.push_str("%20"),
+            Some('{') => {
+                if !literal.is_empty() {
+                    tokens.push(MacroToken::Literal(std::mem::take(&mut literal)));
+                }
+                let letter = chars.next().unwrap_or('}');
+                let lower = letter.to_ascii_lowercase();
+                let allowed = matches!(lower, 's' | 'l' | 'o' | 'd' | 'i' | 'p' | 'h' | 'v')
+                    || (explanation && matches!(lower, 'c' | 'r' | 't'));
+                if !allowed {
+                    bail!("Invalid SPF macro letter {} in {}", letter, s);
+                }
+
+                let mut digits = String::new();
+                while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                    digits.push(digit);
+                }
+                let keep = match digits.as_str() {
+                    "" => None,
+                    digits => match digits.parse::<usize>()? {
+                        0 => bail!("Invalid SPF macro in {}", s),
+                        n => Some(n),
+                    },
+                };
+                let reverse = chars.next_if(|c| c.eq_ignore_ascii_case(&'r')).is_some();
+
+                let mut delimiters = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if ".-+,/_=".contains(c) => delimiters.push(c),
+                        _ => bail!("Invalid SPF macro in {}", s),
+                    }
+                }
+                tokens.push(MacroToken::Macro {
+                    letter: lower,
+                    escape: letter.is_ascii_uppercase(),
+                    keep,
+                    reverse,
+                    delimiters,
                 });
             }
+            _ => bail!("Invalid SPF macro in {}", s),
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(MacroToken::Literal(literal));
+    }
+    Ok(MacroString(tokens))
+}
+
+/// Parse a domain-spec
+fn parse_domain_spec(s: &str) -> Result<MacroString> {
+    if s.is_empty() {
+        bail!("Empty SPF domain");
+    }
+    parse_macro_string(s, false)
+}
+
+/// Parse SPF record into directives and modifiers
+///
+/// Any syntax error makes the whole record invalid (RFC 7208 section 4.6).
+fn parse_spf_record(record: &str) -> Result<SpfRecord> {
+    if !is_spf_record(record) {
+        bail!("Invalid SPF record: missing v=spf1");
+    }
+    let mut parsed = SpfRecord {
+        directives: Vec::new(),
+        redirect: None,
+        exp: None,
+    };
+
+    for term in record[6..].split_whitespace() {
+        if let Some((name, value)) = split_modifier(term) {
+            match name.to_ascii_lowercase().as_str() {
+                "redirect" if parsed.redirect.is_none() => {
+                    parsed.redirect = Some(parse_domain_spec(value)?);
+                }
+                "exp" if parsed.exp.is_none() => {
+                    parsed.exp = Some(parse_domain_spec(value)?);
+                }
+                "redirect" | "exp" => bail!("Duplicate SPF modifier: {}", name),
+                // Unknown modifiers are ignored, but must be well formed
+                _ => {
+                    parse_macro_string(value, false)?;
+                }
+            }
             continue;
         }
 
@@ -363,78 +955,154 @@ fn parse_spf_record(record: &str) -> Result<Vec<SpfDirective>> {
         // Parse mechanism
         let mechanism = parse_mechanism(mechanism_str)?;
 
-        directives.push(SpfDirective {
+        parsed.directives.push(SpfDirective {
             qualifier,
             mechanism,
         });
     }
 
-    Ok(directives)
+    Ok(parsed)
+}
+
+/// Split a modifier term into its name and value
+fn split_modifier(term: &str) -> Option<(&str, &str)> {
+    let (name, value) = term.split_once('=')?;
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    valid.then_some((name, value))
 }
 
 /// Parse a single SPF mechanism
 fn parse_mechanism(s: &str) -> Result<SpfMechanism> {
-    if s == "all" {
-        return Ok(SpfMechanism::All);
-    }
-
-    if s == "a" {
-        return Ok(SpfMechanism::A(None));
-    }
-
-    if let Some(domain) = s.strip_prefix("a:") {
-        return Ok(SpfMechanism::A(Some(domain.to_string())));
-    }
-
-    if s == "mx" {
-        return Ok(SpfMechanism::Mx(None));
-    }
-
-    if let Some(domain) = s.strip_prefix("mx:") {
-        return Ok(SpfMechanism::Mx(Some(domain.to_string())));
-    }
+    let name_end = s.find([':', '/']).unwrap_or(s.len());
+    let (name, rest) = s.split_at(name_end);
 
-    if let Some(network) = s.strip_prefix("ip4:") {
-        let net = if network.contains('/') {
-            network.parse()?
-        } else {
-            format!("{}/32", network).parse()?
-        };
-        return Ok(SpfMechanism::Ip4(net));
+    match name.to_ascii_lowercase().as_str() {
+        "all" if rest.is_empty() => Ok(SpfMechanism::All),
+        "include" => Ok(SpfMechanism::Include(required_domain(rest)?)),
+        "exists" => Ok(SpfMechanism::Exists(required_domain(rest)?)),
+        "a" => {
+            let (spec, cidr) = split_cidr(rest);
+            Ok(SpfMechanism::A(
+                optional_domain(spec)?,
+                parse_dual_cidr(cidr)?,
+            ))
+        }
+        "mx" => {
+            let (spec, cidr) = split_cidr(rest);
+            Ok(SpfMechanism::Mx(
+                optional_domain(spec)?,
+                parse_dual_cidr(cidr)?,
+            ))
+        }
+        "ptr" => Ok(SpfMechanism::Ptr(optional_domain(rest)?)),
+        "ip4" => {
+            let network = rest
+                .strip_prefix(':')
+                .ok_or_else(|| anyhow!("Missing ip4 network"))?;
+            let net = if network.contains('/') {
+                network.parse()?
+            } else {
+                format!("{}/32", network).parse()?
+            };
+            Ok(SpfMechanism::Ip4(net))
+        }
+        "ip6" => {
+            let network = rest
+                .strip_prefix(':')
+                .ok_or_else(|| anyhow!("Missing ip6 network"))?;
+            let net = if network.contains('/') {
+                network.parse()?
+            } else {
+                format!("{}/128", network).parse()?
+            };
+            Ok(SpfMechanism::Ip6(net))
+        }
+        _ => Err(anyhow!("Unknown SPF mechanism: {}", s)),
     }
+}
 
-    if let Some(network) = s.strip_prefix("ip6:") {
-        let net = if network.contains('/') {
-            network.parse()?
-        } else {
-            format!("{}/128", network).parse()?
-        };
-        return Ok(SpfMechanism::Ip6(net));
+/// The `:domain` a mechanism requires
+fn required_domain(rest: &str) -> Result<MacroString> {
+    match rest.strip_prefix(':') {
+        Some(spec) => parse_domain_spec(spec),
+        None => bail!("Missing SPF mechanism domain"),
     }
+}
 
-    if let Some(domain) = s.strip_prefix("include:") {
-        return Ok(SpfMechanism::Include(domain.to_string()));
+/// The `:domain` a mechanism may have
+fn optional_domain(rest: &str) -> Result<Option<MacroString>> {
+    match rest {
+        "" => Ok(None),
+        rest => required_domain(rest).map(Some),
     }
+}
 
-    if s == "ptr" {
-        return Ok(SpfMechanism::Ptr(None));
+/// Split `:domain/24//64` into the domain and the prefix lengths; slashes
+/// in macros do not count
+fn split_cidr(s: &str) -> (&str, &str) {
+    let mut in_macro = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => in_macro = true,
+            '}' => in_macro = false,
+            '/' if !in_macro => return s.split_at(i),
+            _ => {}
+        }
     }
+    (s, "")
+}
 
-    if let Some(domain) = s.strip_prefix("ptr:") {
-        return Ok(SpfMechanism::Ptr(Some(domain.to_string())));
+/// Parse `/24`, `//64` or `/24//64`
+fn parse_dual_cidr(s: &str) -> Result<Cidr> {
+    let (v4, v6) = match s.find("//") {
+        Some(pos) => (&s[..pos], Some(&s[pos + 2..])),
+        None => (s, None),
+    };
+    let mut cidr = Cidr::default();
+    if !v4.is_empty() {
+        let length = v4
+            .strip_prefix('/')
+            .ok_or_else(|| anyhow!("Invalid SPF prefix length: {}", s))?
+            .parse::<u8>()?;
+        if length > 32 {
+            bail!("Invalid SPF prefix length: {}", s);
+        }
+        cidr.v4 = length;
     }
-
-    if let Some(domain) = s.strip_prefix("exists:") {
-        return Ok(SpfMechanism::Exists(domain.to_string()));
+    if let Some(v6) = v6 {
+        let length = v6.parse::<u8>()?;
+        if length > 128 {
+            bail!("Invalid SPF prefix length: {}", s);
+        }
+        cidr.v6 = length;
     }
-
-    Err(anyhow!("Unknown SPF mechanism: {}", s))
+    Ok(cidr)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn context(ip: &str) -> MacroContext {
+        MacroContext {
+            sender: "strong-bad@email.example.com".to_string(),
+            local: "strong-bad".to_string(),
+            sender_domain: "email.example.com".to_string(),
+            ip: ip.parse().unwrap(),
+            helo: "mx.example.org".to_string(),
+            receiver: "mail.example.net".to_string(),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    fn expand(spec: &str, ip: &str) -> String {
+        parse_macro_string(spec, true)
+            .unwrap()
+            .expand(&context(ip), "email.example.com", None)
+    }
+
     #[test]
     fn test_extract_domain() {
         assert_eq!(
@@ -456,19 +1124,138 @@ mod tests {
     #[test]
     fn test_parse_spf_record() {
         let record = "v=spf1 ip4:192.168.1.0/24 include:_spf.google.com -all";
-        let directives = parse_spf_record(record).unwrap();
+        let directives = parse_spf_record(record).unwrap().directives;
 
         assert_eq!(directives.len(), 3);
+        assert!(matches!(directives[0].mechanism, SpfMechanism::Ip4(_)));
+        assert!(matches!(directives[1].mechanism, SpfMechanism::Include(_)));
+        assert!(matches!(directives[2].mechanism, SpfMechanism::All));
+        assert_eq!(directives[2].qualifier, SpfQualifier::Fail);
+    }
+
+    #[test]
+    fn test_parse_spf_modifiers() {
+        let record = parse_spf_record(
+            "v=spf1 a/24//64 mx:example.com//64 ?ptr redirect=_spf.example.com exp=explain.%{d}",
+        )
+        .unwrap();
+        assert_eq!(record.directives.len(), 3);
         assert!(matches!(
-            directives[0].mechanism,
-            SpfMechanism::Ip4(_)
+            record.directives[0].mechanism,
+            SpfMechanism::A(None, Cidr { v4: 24, v6: 64 })
         ));
         assert!(matches!(
-            directives[1].mechanism,
-            SpfMechanism::Include(_)
+            record.directives[1].mechanism,
+            SpfMechanism::Mx(Some(_), Cidr { v4: 32, v6: 64 })
         ));
-        assert!(matches!(directives[2].mechanism, SpfMechanism::All));
-        assert_eq!(directives[2].qualifier, SpfQualifier::Fail);
+        assert_eq!(record.directives[2].qualifier, SpfQualifier::Neutral);
+        assert!(record.redirect.is_some());
+        assert!(record.exp.as_ref().unwrap().uses('d'));
+
+        assert!(parse_spf_record("v=spf1 redirect=a.example redirect=b.example").is_err());
+        assert!(parse_spf_record("v=spf1 ip4:192.0.2.1/33").is_err());
+        assert!(parse_spf_record("v=spf1 include:").is_err());
+        assert!(parse_spf_record("v=spf1 exists:%{c}.example.com").is_err());
+        assert!(parse_spf_record("v=spf1 foo:bar").is_err());
+        assert!(parse_spf_record("v=spf10 -all").is_err());
+        assert!(parse_spf_record("V=SPF1 unknown=%{d} -all").is_ok());
+    }
+
+    #[test]
+    fn test_macro_expansion() {
+        // RFC 7208 section 7.4
+        let ip = "192.0.2.3";
+        assert_eq!(expand("%{s}", ip), "strong-bad@email.example.com");
+        assert_eq!(expand("%{o}", ip), "email.example.com");
+        assert_eq!(expand("%{d4}", ip), "email.example.com");
+        assert_eq!(expand("%{d2}", ip), "example.com");
+        assert_eq!(expand("%{d1}", ip), "com");
+        assert_eq!(expand("%{dr}", ip), "com.example.email");
+        assert_eq!(expand("%{d2r}", ip), "example.email");
+        assert_eq!(expand("%{l}", ip), "strong-bad");
+        assert_eq!(expand("%{l-}", ip), "strong.bad");
+        assert_eq!(expand("%{lr-}", ip), "bad.strong");
+        assert_eq!(expand("%{l1r-}", ip), "strong");
+        assert_eq!(
+            expand("%{ir}.%{v}._spf.%{d2}", ip),
+            "3.2.0.192.in-addr._spf.example.com"
+        );
+        assert_eq!(
+            expand("%{lr-}.lp.%{ir}.%{v}._spf.%{d2}", ip),
+            "bad.strong.lp.3.2.0.192.in-addr._spf.example.com"
+        );
+        assert_eq!(
+            expand("%{ir}.%{v}._spf.%{d2}", "2001:db8::cb01"),
+            "1.0.b.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6._spf.example.com"
+        );
+        assert_eq!(expand("%{S}", ip), "strong-bad%40email.example.com");
+        assert_eq!(expand("%%%_%-", ip), "% %20");
+        assert_eq!(
+            expand("%{c} is not one of %{d}'s servers (%{r}, %{t})", ip),
+            "192.0.2.3 is not one of email.example.com's servers (mail.example.net, 1700000000)"
+        );
+        assert_eq!(expand("%{p}", ip), "unknown");
+    }
+
+    #[test]
+    fn test_invalid_macros() {
+        assert!(parse_macro_string("%{x}", false).is_err());
+        assert!(parse_macro_string("%{d0}", false).is_err());
+        assert!(parse_macro_string("%{d", false).is_err());
+        assert!(parse_macro_string("%a", false).is_err());
+        assert!(parse_macro_string("%{c}", false).is_err());
+        assert!(parse_macro_string("%{c}", true).is_ok());
+    }
+
+    #[test]
+    fn test_domain_names() {
+        assert!(is_valid_domain("example.com"));
+        assert!(is_valid_domain("example.com."));
+        assert!(!is_valid_domain("localhost"));
+        assert!(!is_valid_domain("a..example.com"));
+        assert!(!is_valid_domain(&format!("{}.com", "a".repeat(64))));
+
+        let long = format!("{}.example.com", vec!["a".repeat(60); 5].join("."));
+        let truncated = truncate_domain(&long);
+        assert!(truncated.len() <= 253);
+        assert!(truncated.ends_with(".example.com"));
+
+        assert!(is_subdomain("mail.Example.com.", "example.com"));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr = parse_dual_cidr("/24//64").unwrap();
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(cidr.contains(address, "192.0.2.200".parse().unwrap()));
+        assert!(!cidr.contains(address, "192.0.3.1".parse().unwrap()));
+        assert!(!cidr.contains(address, "2001:db8::1".parse().unwrap()));
+        assert_eq!(
+            split_cidr(":%{d/}.example.com/24"),
+            (":%{d/}.example.com", "/24")
+        );
+    }
+
+    #[test]
+    fn test_evaluation_reply() {
+        let evaluation = SpfEvaluation {
+            result: SpfResult::Fail,
+            domain: Some("example.com".to_string()),
+            explanation: None,
+        };
+        assert_eq!(
+            evaluation.reply(),
+            "5.7.23 SPF validation failed for example.com"
+        );
+        let evaluation = SpfEvaluation {
+            explanation: Some("192.0.2.3 may not send for example.com".to_string()),
+            ..evaluation
+        };
+        assert_eq!(
+            evaluation.reply(),
+            "5.7.23 192.0.2.3 may not send for example.com"
+        );
     }
 
     #[test]
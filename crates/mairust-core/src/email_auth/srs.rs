@@ -0,0 +1,386 @@
+//! Sender Rewriting Scheme (SRS)
+//!
+//! A forwarded message that keeps its envelope sender fails SPF at the
+//! destination, because the forwarder is not a permitted sender of the
+//! original domain. SRS rewrites the sender to an address at the forwarding
+//! domain that encodes the original one. Bounces to that address are
+//! decoded and returned to the original sender.
+//!
+//! - `user@orig.example` becomes `SRS0=HHHH=TT=orig.example=user@<domain>`.
+//! - The SRS0 address of another forwarder is not nested again:
+//!   `SRS0=HHHH=TT=orig.example=user@first.example` becomes
+//!   `SRS1=HHHH=first.example==HHHH=TT=orig.example=user@<domain>`, and
+//!   bounces to it go back to the first forwarder.
+//!
+//! `HHHH` is a truncated HMAC-SHA256 of the rest of the address, so that
+//! addresses cannot be forged to relay mail through the server. `TT` is the
+//! day the address was made, so that addresses expire.
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use mairust_common::config::SrsConfig;
+use sha2::Sha256;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Characters of the address hash
+const HASH_LENGTH: usize = 4;
+
+/// Alphabet of the day stamp (base32)
+const STAMP_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Day stamps wrap around after this many days
+const STAMP_CYCLE: i64 = 1024;
+
+/// Errors decoding an SRS address
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SrsError {
+    #[error("Not an SRS address")]
+    NotSrs,
+
+    #[error("Malformed SRS address")]
+    Malformed,
+
+    #[error("Invalid SRS address hash")]
+    BadHash,
+
+    #[error("SRS address expired")]
+    Expired,
+}
+
+/// Rewrites envelope senders of forwarded mail and decodes bounces
+#[derive(Debug, Clone)]
+pub struct Srs {
+    domain: String,
+    secrets: Vec<String>,
+    max_age_days: u32,
+}
+
+impl Srs {
+    /// Create a rewriter; the first secret hashes new addresses, and every
+    /// secret is accepted when decoding
+    pub fn new(domain: impl Into<String>, secrets: Vec<String>, max_age_days: u32) -> Self {
+        Self {
+            domain: domain.into(),
+            secrets,
+            max_age_days,
+        }
+    }
+
+    /// Rewriter of the `smtp.srs` configuration, or `None` when SRS is
+    /// disabled or has no secret
+    pub fn from_config(config: &SrsConfig, hostname: &str) -> Option<Self> {
+        if !config.enabled || config.secrets.is_empty() {
+            return None;
+        }
+        let domain = config.domain.as_deref().unwrap_or(hostname);
+        Some(Self::new(
+            domain,
+            config.secrets.clone(),
+            config.max_age_days,
+        ))
+    }
+
+    /// Domain of rewritten addresses
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Envelope sender to forward a message from `sender` with
+    ///
+    /// The null sender and addresses already at the SRS domain are kept.
+    pub fn forward(&self, sender: &str, now: DateTime<Utc>) -> String {
+        let Some((local, host)) = sender.rsplit_once('@') else {
+            return sender.to_string();
+        };
+        if local.is_empty() || host.eq_ignore_ascii_case(&self.domain) {
+            return sender.to_string();
+        }
+
+        if let Some(rest) = strip_tag(local, "SRS0") {
+            // Bounces go back to the forwarder that made the SRS0 address
+            let hash = self.hash(&self.secrets[0], &[host, rest]);
+            return format!("SRS1={}={}={}@{}", hash, host, rest, self.domain);
+        }
+        if let Some(rest) = strip_tag(local, "SRS1") {
+            // Keep the first forwarder and its opaque part, with our hash
+            if let Some((_, first, opaque)) = split_srs1(rest) {
+                let hash = self.hash(&self.secrets[0], &[first, opaque]);
+                return format!("SRS1={}={}={}@{}", hash, first, opaque, self.domain);
+            }
+        }
+
+        let stamp = day_stamp(now);
+        let hash = self.hash(&self.secrets[0], &[&stamp, host, local]);
+        format!("SRS0={}={}={}={}@{}", hash, stamp, host, local, self.domain)
+    }
+
+    /// Address a message to the SRS address `address` goes back to
+    pub fn reverse(&self, address: &str, now: DateTime<Utc>) -> Result<String, SrsError> {
+        let (local, host) = address.rsplit_once('@').ok_or(SrsError::NotSrs)?;
+        if !host.eq_ignore_ascii_case(&self.domain) {
+            return Err(SrsError::NotSrs);
+        }
+
+        if let Some(rest) = strip_tag(local, "SRS0") {
+            let mut parts = rest[1..].splitn(4, '=');
+            let (Some(hash), Some(stamp), Some(host), Some(user)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(SrsError::Malformed);
+            };
+            if stamp.is_empty() || host.is_empty() || user.is_empty() {
+                return Err(SrsError::Malformed);
+            }
+            self.check_hash(hash, &[stamp, host, user])?;
+            let made = parse_day_stamp(stamp).ok_or(SrsError::Malformed)?;
+            let age = (day_number(now) - made).rem_euclid(STAMP_CYCLE);
+            if age > i64::from(self.max_age_days) {
+                return Err(SrsError::Expired);
+            }
+            return Ok(format!("{}@{}", user, host));
+        }
+
+        if let Some(rest) = strip_tag(local, "SRS1") {
+            let (hash, first, opaque) = split_srs1(rest).ok_or(SrsError::Malformed)?;
+            self.check_hash(hash, &[first, opaque])?;
+            return Ok(format!("SRS0{}@{}", opaque, first));
+        }
+
+        Err(SrsError::NotSrs)
+    }
+
+    /// Check a hash against every secret
+    ///
+    /// Intermediate servers may change the case of local parts, so the hash
+    /// is compared case-insensitively.
+    fn check_hash(&self, hash: &str, parts: &[&str]) -> Result<(), SrsError> {
+        if self
+            .secrets
+            .iter()
+            .any(|secret| self.hash(secret, parts).eq_ignore_ascii_case(hash))
+        {
+            Ok(())
+        } else {
+            Err(SrsError::BadHash)
+        }
+    }
+
+    /// Truncated HMAC of the lowercased parts of an address
+    fn hash(&self, secret: &str, parts: &[&str]) -> String {
+        let mut mac =
+            HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        for part in parts {
+            // Separated, so that `a=bc` and `ab=c` hash differently
+            mac.update(part.to_ascii_lowercase().as_bytes());
+            mac.update(b"=");
+        }
+        let digest = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+        digest[..HASH_LENGTH].to_string()
+    }
+}
+
+/// Whether a local part is an SRS address
+pub fn is_srs(local: &str) -> bool {
+    strip_tag(local, "SRS0").is_some() || strip_tag(local, "SRS1").is_some()
+}
+
+/// The rest of a local part after an SRS tag, starting with the separator
+fn strip_tag<'a>(local: &'a str, tag: &str) -> Option<&'a str> {
+    let prefix = local.get(..tag.len())?;
+    let rest = &local[tag.len()..];
+    (prefix.eq_ignore_ascii_case(tag) && rest.len() > 1 && rest.starts_with(['=', '+', '-']))
+        .then_some(rest)
+}
+
+/// Hash, first forwarder and opaque part of the rest of an SRS1 local part
+///
+/// The rest of `SRS1=HHHH=first.example==HHHH=TT=orig.example=user` gives
+/// `first.example` and `=HHHH=TT=orig.example=user`: the opaque part keeps
+/// the separator that followed `SRS0`.
+fn split_srs1(rest: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = rest[1..].splitn(3, '=');
+    let (hash, first, opaque) = (parts.next()?, parts.next()?, parts.next()?);
+    if first.is_empty() || opaque.is_empty() {
+        return None;
+    }
+    Some((hash, first, opaque))
+}
+
+/// Days since the Unix epoch
+fn day_number(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(86_400)
+}
+
+/// Two-character stamp of the current day
+fn day_stamp(now: DateTime<Utc>) -> String {
+    let day = day_number(now).rem_euclid(STAMP_CYCLE) as usize;
+    [STAMP_ALPHABET[day >> 5], STAMP_ALPHABET[day & 31]]
+        .iter()
+        .map(|&c| c as char)
+        .collect()
+}
+
+/// Day of a stamp, within the stamp cycle
+fn parse_day_stamp(stamp: &str) -> Option<i64> {
+    if stamp.len() != 2 {
+        return None;
+    }
+    stamp.bytes().try_fold(0i64, |day, c| {
+        let value = STAMP_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        Some(day * 32 + value as i64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn srs(domain: &str) -> Srs {
+        Srs::new(domain, vec!["secret".to_string()], 21)
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_forward_and_reverse() {
+        let srs = srs("fwd.example");
+        let rewritten = srs.forward("alice@orig.example", now());
+        assert!(rewritten.starts_with("SRS0="));
+        assert!(rewritten.ends_with("=orig.example=alice@fwd.example"));
+        assert_eq!(
+            srs.reverse(&rewritten, now()).unwrap(),
+            "alice@orig.example"
+        );
+
+        // Case changes on the way back do not matter
+        assert_eq!(
+            srs.reverse(&rewritten.to_uppercase(), now()).unwrap(),
+            "ALICE@ORIG.EXAMPLE"
+        );
+    }
+
+    #[test]
+    fn test_forward_keeps_own_and_null_senders() {
+        let srs = srs("fwd.example");
+        assert_eq!(srs.forward("", now()), "");
+        assert_eq!(srs.forward("bob@FWD.example", now()), "bob@FWD.example");
+        assert_eq!(srs.forward("no-domain", now()), "no-domain");
+    }
+
+    #[test]
+    fn test_second_hop() {
+        let first = srs("first.example");
+        let second = Srs::new("second.example", vec!["other".to_string()], 21);
+        let third = Srs::new("third.example", vec!["third".to_string()], 21);
+
+        let srs0 = first.forward("alice@orig.example", now());
+        let srs1 = second.forward(&srs0, now());
+        let opaque = srs0
+            .strip_prefix("SRS0")
+            .unwrap()
+            .strip_suffix("@first.example")
+            .unwrap();
+        assert!(srs1.starts_with("SRS1="));
+        assert!(srs1.ends_with(&format!("=first.example={}@second.example", opaque)));
+
+        // A bounce goes back to the first forwarder, which returns it
+        let back = second.reverse(&srs1, now()).unwrap();
+        assert_eq!(back, srs0);
+        assert_eq!(first.reverse(&back, now()).unwrap(), "alice@orig.example");
+
+        // A third hop keeps the first forwarder
+        let again = third.forward(&srs1, now());
+        assert!(again.ends_with(&format!("=first.example={}@third.example", opaque)));
+        assert_eq!(third.reverse(&again, now()).unwrap(), srs0);
+    }
+
+    #[test]
+    fn test_reverse_rejects_forgeries() {
+        let srs = srs("fwd.example");
+        let rewritten = srs.forward("alice@orig.example", now());
+
+        let forged = rewritten.replace("alice", "mallory");
+        assert_eq!(srs.reverse(&forged, now()), Err(SrsError::BadHash));
+
+        let other = Srs::new("fwd.example", vec!["another".to_string()], 21);
+        assert_eq!(other.reverse(&rewritten, now()), Err(SrsError::BadHash));
+
+        assert_eq!(
+            srs.reverse("alice@fwd.example", now()),
+            Err(SrsError::NotSrs)
+        );
+        assert_eq!(
+            srs.reverse(
+                &rewritten.replace("fwd.example", "elsewhere.example"),
+                now()
+            ),
+            Err(SrsError::NotSrs)
+        );
+        assert_eq!(
+            srs.reverse("SRS0=abcd=AA@fwd.example", now()),
+            Err(SrsError::Malformed)
+        );
+        assert_eq!(
+            srs.reverse("SRS1=abcd@fwd.example", now()),
+            Err(SrsError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let srs = srs("fwd.example");
+        let rewritten = srs.forward("alice@orig.example", now());
+        assert!(srs.reverse(&rewritten, now() + Duration::days(21)).is_ok());
+        assert_eq!(
+            srs.reverse(&rewritten, now() + Duration::days(22)),
+            Err(SrsError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_secret_rotation() {
+        let old = srs("fwd.example");
+        let rewritten = old.forward("alice@orig.example", now());
+        let rotated = Srs::new(
+            "fwd.example",
+            vec!["new".to_string(), "secret".to_string()],
+            21,
+        );
+        assert_eq!(
+            rotated.reverse(&rewritten, now()).unwrap(),
+            "alice@orig.example"
+        );
+        assert_ne!(rotated.forward("alice@orig.example", now()), rewritten);
+    }
+
+    #[test]
+    fn test_day_stamp() {
+        for day in [0, 1, 31, 32, 1023] {
+            let at = DateTime::<Utc>::from_timestamp(day * 86_400, 0).unwrap();
+            assert_eq!(parse_day_stamp(&day_stamp(at)), Some(day));
+        }
+        // The stamp wraps around
+        let at = DateTime::<Utc>::from_timestamp(1024 * 86_400, 0).unwrap();
+        assert_eq!(day_stamp(at), "AA");
+        assert_eq!(parse_day_stamp("aB"), Some(1));
+        assert_eq!(parse_day_stamp("A"), None);
+        assert_eq!(parse_day_stamp("A1"), None);
+    }
+
+    #[test]
+    fn test_is_srs() {
+        assert!(is_srs("SRS0=abcd=AA=orig.example=alice"));
+        assert!(is_srs("srs1+abcd=first.example==x"));
+        assert!(!is_srs("SRS0"));
+        assert!(!is_srs("SRS2=abcd"));
+        assert!(!is_srs("alice"));
+    }
+}
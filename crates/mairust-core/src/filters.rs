@@ -0,0 +1,325 @@
+//! Mailbox filters - simple server-side rules for users who do not write Sieve
+//!
+//! A filter has conditions on the sender, recipients, subject and headers
+//! of a message, evaluated like policy conditions, and actions that file,
+//! tag, mark read, forward or delete it. The enabled filters of the
+//! recipient's mailbox run in position order at delivery. A matching
+//! filter with `stop_processing` skips the filters after it.
+//!
+//! Actions of all matching filters are combined: the first folder chosen
+//! wins, tags and forwarding addresses add up, and a delete discards the
+//! message after it has been forwarded.
+
+use crate::folders::normalize_folder_path;
+use crate::policy::{PolicyContext, PolicyEngine};
+use anyhow::Result;
+use mairust_common::types::EmailAddress;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{Mailbox, PolicyConditionNode, PolicyConditionType};
+use mairust_storage::{MailboxFilter, MailboxFilterRepository};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Condition types a filter may use
+pub const CONDITION_TYPES: [PolicyConditionType; 7] = [
+    PolicyConditionType::SenderAddress,
+    PolicyConditionType::SenderDomain,
+    PolicyConditionType::RecipientAddress,
+    PolicyConditionType::RecipientDomain,
+    PolicyConditionType::SubjectContains,
+    PolicyConditionType::HeaderExists,
+    PolicyConditionType::HeaderValue,
+];
+
+/// Longest tag a filter may add
+const MAX_TAG_LEN: usize = 64;
+
+/// What a matching filter does with a message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action_type", rename_all = "snake_case")]
+pub enum FilterAction {
+    /// File into the owner's folder of this name
+    MoveToFolder { folder: String },
+    /// Add a tag
+    Tag { tag: String },
+    /// Mark as read
+    MarkRead,
+    /// Forward a copy to an address
+    Forward { address: String },
+    /// Discard the message instead of storing it
+    Delete,
+}
+
+/// Combined actions of the filters that matched a message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterOutcome {
+    /// Folder to file into
+    pub folder: Option<String>,
+    pub tags: Vec<String>,
+    pub mark_read: bool,
+    pub forward_to: Vec<String>,
+    pub delete: bool,
+    /// Filters that matched, in the order they ran
+    pub matched: Vec<Uuid>,
+}
+
+impl FilterOutcome {
+    /// Add an action of a matching filter
+    pub fn apply(&mut self, action: &FilterAction) {
+        match action {
+            FilterAction::MoveToFolder { folder } => {
+                if self.folder.is_none() {
+                    self.folder = Some(folder.clone());
+                }
+            }
+            FilterAction::Tag { tag } => {
+                if !self.tags.contains(tag) {
+                    self.tags.push(tag.clone());
+                }
+            }
+            FilterAction::MarkRead => self.mark_read = true,
+            FilterAction::Forward { address } => {
+                if !self
+                    .forward_to
+                    .iter()
+                    .any(|a| a.eq_ignore_ascii_case(address))
+                {
+                    self.forward_to.push(address.clone());
+                }
+            }
+            FilterAction::Delete => self.delete = true,
+        }
+    }
+}
+
+/// Parse and check the conditions of a filter
+pub fn parse_conditions(
+    conditions: &serde_json::Value,
+) -> Result<Vec<PolicyConditionNode>, String> {
+    let conditions: Vec<PolicyConditionNode> = serde_json::from_value(conditions.clone())
+        .map_err(|e| format!("Invalid conditions: {}", e))?;
+    for (i, condition) in conditions
+        .iter()
+        .flat_map(PolicyConditionNode::conditions)
+        .enumerate()
+    {
+        if !CONDITION_TYPES.contains(&condition.condition_type) {
+            return Err(format!(
+                "Condition {}: {:?} cannot be used in filters",
+                i, condition.condition_type
+            ));
+        }
+    }
+    Ok(conditions)
+}
+
+/// Parse and check the actions of a filter
+pub fn parse_actions(actions: &serde_json::Value) -> Result<Vec<FilterAction>, String> {
+    let actions: Vec<FilterAction> =
+        serde_json::from_value(actions.clone()).map_err(|e| format!("Invalid actions: {}", e))?;
+    if actions.is_empty() {
+        return Err("At least one action is required".to_string());
+    }
+    for (i, action) in actions.iter().enumerate() {
+        match action {
+            FilterAction::MoveToFolder { folder } if normalize_folder_path(folder).is_none() => {
+                return Err(format!("Action {}: invalid folder name", i));
+            }
+            FilterAction::Tag { tag }
+                if tag.trim().is_empty()
+                    || tag.len() > MAX_TAG_LEN
+                    || tag.chars().any(char::is_control) =>
+            {
+                return Err(format!("Action {}: invalid tag", i));
+            }
+            FilterAction::Forward { address }
+                if EmailAddress::parse(address).is_none()
+                    || address.contains(char::is_whitespace) =>
+            {
+                return Err(format!("Action {}: invalid forwarding address", i));
+            }
+            _ => {}
+        }
+    }
+    Ok(actions)
+}
+
+/// Runs the filters of a mailbox at delivery
+pub struct MailboxFilters {
+    db_pool: DatabasePool,
+    engine: PolicyEngine,
+}
+
+impl MailboxFilters {
+    /// Create a new filter runner
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self {
+            engine: PolicyEngine::new(db_pool.clone()),
+            db_pool,
+        }
+    }
+
+    /// Run the enabled filters of a mailbox against a message
+    pub async fn run(&self, mailbox: &Mailbox, context: &PolicyContext) -> Result<FilterOutcome> {
+        let filters = MailboxFilterRepository::new(self.db_pool.clone())
+            .list_enabled(mailbox.id)
+            .await?;
+        Ok(self.evaluate(&filters, context))
+    }
+
+    /// Run filters, in the given order, against a message
+    ///
+    /// Filters whose stored conditions or actions no longer parse are
+    /// skipped.
+    pub fn evaluate(&self, filters: &[MailboxFilter], context: &PolicyContext) -> FilterOutcome {
+        let mut outcome = FilterOutcome::default();
+        for filter in filters.iter().filter(|f| f.enabled) {
+            let (Ok(conditions), Ok(actions)) = (
+                parse_conditions(&filter.conditions),
+                parse_actions(&filter.actions),
+            ) else {
+                continue;
+            };
+            if !self.engine.evaluate_conditions(&conditions, context) {
+                continue;
+            }
+
+            outcome.matched.push(filter.id);
+            for action in &actions {
+                outcome.apply(action);
+            }
+            if filter.stop_processing {
+                break;
+            }
+        }
+        outcome
+    }
+
+    /// Folder of the mailbox's owner that a filter files into
+    ///
+    /// Names are matched ignoring case. `None` when the owner has no such
+    /// folder; folders are never created by a filter.
+    pub async fn folder(&self, mailbox: &Mailbox, name: &str) -> Result<Option<Uuid>> {
+        let (Some(user_id), Some(path)) = (mailbox.user_id, normalize_folder_path(name)) else {
+            return Ok(None);
+        };
+
+        let folder: Option<(Uuid,)> = sqlx::query_as(
+            "SELECT id FROM mailboxes
+             WHERE tenant_id = $1 AND user_id = $2 AND LOWER(folder_path) = LOWER($3)
+             ORDER BY folder_path = $3 DESC
+             LIMIT 1",
+        )
+        .bind(mailbox.tenant_id)
+        .bind(user_id)
+        .bind(&path)
+        .fetch_optional(self.db_pool.pool())
+        .await?;
+
+        Ok(folder.map(|(id,)| id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_conditions() {
+        let conditions = parse_conditions(&json!([
+            {"condition_type": "sender_domain", "operator": "eq", "value": "example.com"},
+            {"condition_type": "subject_contains", "operator": "contains", "value": "invoice",
+             "negate": true}
+        ]))
+        .unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert!(matches!(&conditions[1], PolicyConditionNode::Condition(c) if c.negate));
+
+        assert!(parse_conditions(&json!([])).unwrap().is_empty());
+        assert!(parse_conditions(&json!([
+            {"condition_type": "spam_score", "operator": "gt", "value": 5}
+        ]))
+        .is_err());
+        assert!(parse_conditions(&json!({"condition_type": "sender_domain"})).is_err());
+
+        // Groups are checked down to their conditions
+        assert!(parse_conditions(&json!([{"any_of": [
+            {"condition_type": "sender_domain", "operator": "eq", "value": "a.example"},
+            {"condition_type": "sender_domain", "operator": "eq", "value": "b.example"}
+        ]}]))
+        .is_ok());
+        assert!(parse_conditions(&json!([{"none_of": [
+            {"condition_type": "spam_score", "operator": "gt", "value": 5}
+        ]}]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_actions() {
+        let actions = parse_actions(&json!([
+            {"action_type": "move_to_folder", "folder": "Lists/Rust"},
+            {"action_type": "tag", "tag": "rust"},
+            {"action_type": "mark_read"},
+            {"action_type": "forward", "address": "me@example.org"},
+            {"action_type": "delete"}
+        ]))
+        .unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                FilterAction::MoveToFolder {
+                    folder: "Lists/Rust".to_string()
+                },
+                FilterAction::Tag {
+                    tag: "rust".to_string()
+                },
+                FilterAction::MarkRead,
+                FilterAction::Forward {
+                    address: "me@example.org".to_string()
+                },
+                FilterAction::Delete,
+            ]
+        );
+
+        assert!(parse_actions(&json!([])).is_err());
+        assert!(parse_actions(&json!([{"action_type": "reject"}])).is_err());
+        assert!(parse_actions(&json!([{"action_type": "move_to_folder", "folder": ""}])).is_err());
+        assert!(parse_actions(&json!([{"action_type": "tag", "tag": " "}])).is_err());
+        assert!(parse_actions(&json!([{"action_type": "forward", "address": "nobody"}])).is_err());
+    }
+
+    #[test]
+    fn test_outcome_combines_actions() {
+        let mut outcome = FilterOutcome::default();
+        for action in [
+            FilterAction::MoveToFolder {
+                folder: "Receipts".to_string(),
+            },
+            FilterAction::Tag {
+                tag: "shop".to_string(),
+            },
+            FilterAction::MoveToFolder {
+                folder: "Other".to_string(),
+            },
+            FilterAction::Tag {
+                tag: "shop".to_string(),
+            },
+            FilterAction::Forward {
+                address: "Me@example.org".to_string(),
+            },
+            FilterAction::Forward {
+                address: "me@example.org".to_string(),
+            },
+            FilterAction::MarkRead,
+        ] {
+            outcome.apply(&action);
+        }
+
+        assert_eq!(outcome.folder.as_deref(), Some("Receipts"));
+        assert_eq!(outcome.tags, vec!["shop".to_string()]);
+        assert_eq!(outcome.forward_to, vec!["Me@example.org".to_string()]);
+        assert!(outcome.mark_read);
+        assert!(!outcome.delete);
+    }
+}
@@ -0,0 +1,494 @@
+//! Default folders - localized folder provisioning and special-use mapping
+//!
+//! New mailboxes get the standard folder set (Sent, Drafts, Trash, Junk,
+//! Archive) named in the tenant's default locale. The same table maps a
+//! folder name in any supported locale back to its special-use attribute
+//! (RFC 6154), so IMAP LIST, junk filing and the web UI agree on which
+//! folder is "Sent" whether it is called "Sent", "送信済み" or "Gesendet".
+//!
+//! Folders form a hierarchy separated by `/` ("Archive/2024"), independent
+//! of the email addresses of mailboxes.
+//!
+//! Tenant settings:
+//! - `default_locale`: locale of provisioned folder names (default `en`)
+//! - `default_folders`: special uses to provision (default all; `[]` disables)
+
+use anyhow::Result;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Mailbox;
+use mairust_storage::TenantRepository;
+use serde::Serialize;
+use sqlx::PgConnection;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// Locale used when the tenant has none or it is not supported
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Separator of folder hierarchy levels
+pub const FOLDER_DELIMITER: char = '/';
+
+/// Special-use role of a folder (RFC 6154)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpecialUse {
+    Sent,
+    Drafts,
+    Trash,
+    Junk,
+    Archive,
+}
+
+/// Localized folder names, in `SpecialUse::ALL` order
+const LOCALIZED_NAMES: &[(&str, [&str; 5])] = &[
+    ("en", ["Sent", "Drafts", "Trash", "Junk", "Archive"]),
+    (
+        "ja",
+        ["送信済み", "下書き", "ゴミ箱", "迷惑メール", "アーカイブ"],
+    ),
+    (
+        "de",
+        ["Gesendet", "Entwürfe", "Papierkorb", "Spam", "Archiv"],
+    ),
+    (
+        "fr",
+        [
+            "Envoyés",
+            "Brouillons",
+            "Corbeille",
+            "Indésirables",
+            "Archives",
+        ],
+    ),
+    (
+        "es",
+        [
+            "Enviados",
+            "Borradores",
+            "Papelera",
+            "Correo no deseado",
+            "Archivo",
+        ],
+    ),
+    ("zh", ["已发送", "草稿", "已删除", "垃圾邮件", "归档"]),
+    (
+        "ko",
+        ["보낸편지함", "임시보관함", "휴지통", "스팸메일함", "보관함"],
+    ),
+];
+
+/// Names other clients commonly create for the same folders
+const ALIASES: &[(&str, SpecialUse)] = &[
+    ("Sent Items", SpecialUse::Sent),
+    ("Sent Messages", SpecialUse::Sent),
+    ("Sent Mail", SpecialUse::Sent),
+    ("Deleted Items", SpecialUse::Trash),
+    ("Deleted Messages", SpecialUse::Trash),
+    ("Spam", SpecialUse::Junk),
+    ("Junk E-mail", SpecialUse::Junk),
+    ("Junk Email", SpecialUse::Junk),
+    ("Bulk Mail", SpecialUse::Junk),
+    ("Archives", SpecialUse::Archive),
+];
+
+impl SpecialUse {
+    /// All special uses, in provisioning order
+    pub const ALL: [SpecialUse; 5] = [
+        SpecialUse::Sent,
+        SpecialUse::Drafts,
+        SpecialUse::Trash,
+        SpecialUse::Junk,
+        SpecialUse::Archive,
+    ];
+
+    /// Value stored in `mailboxes.special_use` and used in tenant settings
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpecialUse::Sent => "sent",
+            SpecialUse::Drafts => "drafts",
+            SpecialUse::Trash => "trash",
+            SpecialUse::Junk => "junk",
+            SpecialUse::Archive => "archive",
+        }
+    }
+
+    /// Parse a stored value or IMAP attribute (`sent`, `\Sent`)
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim_start_matches('\\');
+        Self::ALL
+            .into_iter()
+            .find(|use_| use_.as_str().eq_ignore_ascii_case(value))
+    }
+
+    /// IMAP LIST attribute
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            SpecialUse::Sent => "\\Sent",
+            SpecialUse::Drafts => "\\Drafts",
+            SpecialUse::Trash => "\\Trash",
+            SpecialUse::Junk => "\\Junk",
+            SpecialUse::Archive => "\\Archive",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Self::ALL.iter().position(|use_| use_ == self).unwrap_or(0)
+    }
+
+    /// Folder name in `locale`, falling back to English
+    pub fn localized_name(&self, locale: &str) -> &'static str {
+        localized_names(locale)[self.index()]
+    }
+
+    /// Special use of a folder known by name in any supported locale
+    pub fn from_folder_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        LOCALIZED_NAMES
+            .iter()
+            .find_map(|(_, names)| {
+                names
+                    .iter()
+                    .position(|n| n.to_lowercase() == name.to_lowercase())
+                    .map(|i| Self::ALL[i])
+            })
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+                    .map(|(_, use_)| *use_)
+            })
+    }
+
+    /// Special use of a folder: the stored role, else the name mapping
+    pub fn of_folder(special_use: Option<&str>, name: &str) -> Option<Self> {
+        special_use
+            .and_then(Self::parse)
+            .or_else(|| Self::from_folder_name(name))
+    }
+
+    /// Every name the folder is known by, across locales and aliases
+    pub fn folder_names(&self) -> Vec<&'static str> {
+        let localized = LOCALIZED_NAMES.iter().map(|(_, names)| names[self.index()]);
+        let aliases = ALIASES
+            .iter()
+            .filter(|(_, use_)| use_ == self)
+            .map(|(alias, _)| *alias);
+
+        let mut names = Vec::new();
+        for name in localized.chain(aliases) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+}
+
+/// Primary language subtag of a supported locale (`ja-JP` -> `ja`)
+pub fn normalize_locale(locale: &str) -> &'static str {
+    let language = locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    LOCALIZED_NAMES
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map(|(code, _)| *code)
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+fn localized_names(locale: &str) -> &'static [&'static str; 5] {
+    let locale = normalize_locale(locale);
+    LOCALIZED_NAMES
+        .iter()
+        .find(|(code, _)| *code == locale)
+        .map(|(_, names)| names)
+        .unwrap_or(&LOCALIZED_NAMES[0].1)
+}
+
+/// Folder provisioning settings of a tenant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderSettings {
+    /// Supported locale the folder names are taken from
+    pub locale: &'static str,
+    /// Folders created for new mailboxes
+    pub folders: Vec<SpecialUse>,
+}
+
+impl Default for FolderSettings {
+    fn default() -> Self {
+        Self {
+            locale: DEFAULT_LOCALE,
+            folders: SpecialUse::ALL.to_vec(),
+        }
+    }
+}
+
+impl FolderSettings {
+    /// Read `default_locale` and `default_folders` from tenant settings
+    ///
+    /// Unknown folder names are ignored.
+    pub fn from_tenant_settings(settings: &serde_json::Value) -> Self {
+        let locale = settings
+            .get("default_locale")
+            .and_then(|v| v.as_str())
+            .map(normalize_locale)
+            .unwrap_or(DEFAULT_LOCALE);
+
+        let folders = match settings.get("default_folders").and_then(|v| v.as_array()) {
+            Some(values) => {
+                let mut folders = Vec::new();
+                for use_ in values
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .filter_map(SpecialUse::parse)
+                {
+                    if !folders.contains(&use_) {
+                        folders.push(use_);
+                    }
+                }
+                folders
+            }
+            None => SpecialUse::ALL.to_vec(),
+        };
+
+        Self { locale, folders }
+    }
+
+    /// Folder names to provision with their special use
+    pub fn folder_names(&self) -> Vec<(SpecialUse, &'static str)> {
+        self.folders
+            .iter()
+            .map(|use_| (*use_, use_.localized_name(self.locale)))
+            .collect()
+    }
+}
+
+/// Validate a folder name given by a client
+///
+/// A trailing delimiter only announces that the folder will have children
+/// (RFC 3501, CREATE) and is dropped. Empty levels, wildcards and `@`, which
+/// marks email addresses, are refused.
+pub fn normalize_folder_path(name: &str) -> Option<String> {
+    let path = name.strip_suffix(FOLDER_DELIMITER).unwrap_or(name);
+    let valid = !path.is_empty()
+        && path
+            .split(FOLDER_DELIMITER)
+            .all(|level| !level.trim().is_empty())
+        && !path
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '@' | '%' | '*'));
+    valid.then(|| path.to_string())
+}
+
+/// Full name of a folder's parent, `None` at the top level
+pub fn parent_folder(path: &str) -> Option<&str> {
+    path.rsplit_once(FOLDER_DELIMITER).map(|(parent, _)| parent)
+}
+
+/// Full names of a folder's ancestors, from the top level down
+pub fn ancestor_folders(path: &str) -> Vec<&str> {
+    path.match_indices(FOLDER_DELIMITER)
+        .map(|(idx, _)| &path[..idx])
+        .collect()
+}
+
+/// Creates the default folders of new mailboxes
+pub struct FolderProvisioner {
+    db_pool: DatabasePool,
+}
+
+impl FolderProvisioner {
+    /// Create a new folder provisioner
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Create the tenant's default folders for the owner of `mailbox`
+    ///
+    /// Folders the user already has, by role or by name, are skipped, so
+    /// provisioning a second address of the same user adds nothing.
+    /// Returns the names of the folders created.
+    pub async fn provision(&self, mailbox: &Mailbox) -> Result<Vec<String>> {
+        let settings = TenantRepository::new(self.db_pool.clone())
+            .find_by_id(mailbox.tenant_id)
+            .await?
+            .map(|tenant| FolderSettings::from_tenant_settings(&tenant.settings))
+            .unwrap_or_default();
+
+        let mut conn = self.db_pool.pool().acquire().await?;
+        Ok(provision_folders(&mut conn, mailbox, &settings).await?)
+    }
+}
+
+/// Create the folders of `settings` for the owner of `mailbox` on a
+/// connection, so that provisioning can join a larger transaction
+pub(crate) async fn provision_folders(
+    conn: &mut PgConnection,
+    mailbox: &Mailbox,
+    settings: &FolderSettings,
+) -> sqlx::Result<Vec<String>> {
+    let user_id = match mailbox.user_id {
+        Some(user_id) => user_id,
+        None => return Ok(Vec::new()),
+    };
+
+    let existing: Vec<(String, Option<String>)> = sqlx::query_as(
+        "SELECT folder_path, special_use FROM mailboxes
+         WHERE tenant_id = $1 AND user_id = $2 AND folder_path IS NOT NULL",
+    )
+    .bind(mailbox.tenant_id)
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut created = Vec::new();
+    for (use_, name) in settings.folder_names() {
+        let present = existing.iter().any(|(path, special_use)| {
+            path.eq_ignore_ascii_case(name)
+                || SpecialUse::of_folder(special_use.as_deref(), path) == Some(use_)
+        });
+        if present {
+            debug!("User {} already has a {} folder", user_id, use_.as_str());
+            continue;
+        }
+
+        sqlx::query(
+            "INSERT INTO mailboxes (id, tenant_id, domain_id, user_id, address, folder_path, special_use, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $5, $6, NOW(), NOW())",
+        )
+        .bind(Uuid::now_v7())
+        .bind(mailbox.tenant_id)
+        .bind(mailbox.domain_id)
+        .bind(user_id)
+        .bind(name)
+        .bind(use_.as_str())
+        .execute(&mut *conn)
+        .await?;
+        created.push(name.to_string());
+    }
+
+    if !created.is_empty() {
+        info!(
+            "Provisioned folders {:?} ({}) for user {}",
+            created, settings.locale, user_id
+        );
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_localized_names() {
+        assert_eq!(SpecialUse::Sent.localized_name("en"), "Sent");
+        assert_eq!(SpecialUse::Sent.localized_name("ja-JP"), "送信済み");
+        assert_eq!(SpecialUse::Trash.localized_name("de_DE"), "Papierkorb");
+        assert_eq!(SpecialUse::Junk.localized_name("xx"), "Junk");
+        assert_eq!(normalize_locale("FR-ca"), "fr");
+        assert_eq!(normalize_locale(""), "en");
+    }
+
+    #[test]
+    fn test_from_folder_name() {
+        assert_eq!(SpecialUse::from_folder_name("sent"), Some(SpecialUse::Sent));
+        assert_eq!(
+            SpecialUse::from_folder_name("送信済み"),
+            Some(SpecialUse::Sent)
+        );
+        assert_eq!(
+            SpecialUse::from_folder_name("ENTWÜRFE"),
+            Some(SpecialUse::Drafts)
+        );
+        assert_eq!(
+            SpecialUse::from_folder_name("Deleted Items"),
+            Some(SpecialUse::Trash)
+        );
+        assert_eq!(
+            SpecialUse::from_folder_name("迷惑メール"),
+            Some(SpecialUse::Junk)
+        );
+        assert_eq!(SpecialUse::from_folder_name("Projects"), None);
+        assert_eq!(SpecialUse::from_folder_name("INBOX"), None);
+
+        // A stored role wins over the name
+        assert_eq!(
+            SpecialUse::of_folder(Some("archive"), "Old mail"),
+            Some(SpecialUse::Archive)
+        );
+        assert_eq!(SpecialUse::of_folder(None, "Spam"), Some(SpecialUse::Junk));
+    }
+
+    #[test]
+    fn test_parse_and_attribute() {
+        for use_ in SpecialUse::ALL {
+            assert_eq!(SpecialUse::parse(use_.as_str()), Some(use_));
+            assert_eq!(SpecialUse::parse(use_.attribute()), Some(use_));
+            assert!(use_.folder_names().contains(&use_.localized_name("ko")));
+        }
+        assert_eq!(SpecialUse::parse("\\Flagged"), None);
+    }
+
+    #[test]
+    fn test_folder_paths() {
+        assert_eq!(
+            normalize_folder_path("Archive"),
+            Some("Archive".to_string())
+        );
+        assert_eq!(
+            normalize_folder_path("Archive/2024/"),
+            Some("Archive/2024".to_string())
+        );
+        assert_eq!(
+            normalize_folder_path("Projekte/Übersicht"),
+            Some("Projekte/Übersicht".to_string())
+        );
+        for invalid in [
+            "",
+            "/",
+            "/Archive",
+            "Archive//2024",
+            "a/ /b",
+            "user@example.com",
+            "A*",
+        ] {
+            assert_eq!(normalize_folder_path(invalid), None, "{}", invalid);
+        }
+
+        assert_eq!(parent_folder("Archive/2024/Q1"), Some("Archive/2024"));
+        assert_eq!(parent_folder("Archive"), None);
+        assert_eq!(
+            ancestor_folders("Archive/2024/Q1"),
+            vec!["Archive", "Archive/2024"]
+        );
+        assert!(ancestor_folders("Archive").is_empty());
+    }
+
+    #[test]
+    fn test_folder_settings() {
+        assert_eq!(
+            FolderSettings::from_tenant_settings(&serde_json::json!({})),
+            FolderSettings::default()
+        );
+
+        let settings = FolderSettings::from_tenant_settings(&serde_json::json!({
+            "default_locale": "ja-JP",
+            "default_folders": ["sent", "trash", "sent", "bogus"],
+        }));
+        assert_eq!(settings.locale, "ja");
+        assert_eq!(
+            settings.folder_names(),
+            vec![
+                (SpecialUse::Sent, "送信済み"),
+                (SpecialUse::Trash, "ゴミ箱")
+            ]
+        );
+
+        let disabled =
+            FolderSettings::from_tenant_settings(&serde_json::json!({ "default_folders": [] }));
+        assert!(disabled.folder_names().is_empty());
+    }
+}
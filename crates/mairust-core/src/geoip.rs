@@ -0,0 +1,621 @@
+//! GeoIP lookups of client addresses
+//!
+//! A reader of MaxMind DB files (format version 2), the format of the
+//! GeoLite2 and GeoIP2 databases. A database is a binary search tree over
+//! the bits of the address, followed by a data section whose records are
+//! maps, arrays and scalars in a compact typed encoding, and by a metadata
+//! map after the `\xAB\xCD\xEFMaxMind.com` marker.
+//!
+//! The SMTP server looks up the country (`country.iso_code`) and the
+//! autonomous system (`autonomous_system_number`) of each client. Policies
+//! match them with the `country` and `asn` conditions, and delivered
+//! messages record them as `country` and `asn` in their metadata.
+
+use anyhow::{bail, Context, Result};
+use mairust_common::config::GeoIpConfig;
+use serde_json::Value;
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Marker before the metadata map
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+
+/// The metadata is within this many bytes of the end of a file
+const METADATA_MAX_SIZE: usize = 128 * 1024;
+
+/// Zero bytes between the search tree and the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+
+/// Deepest nesting of maps, arrays and pointers decoded
+const MAX_DEPTH: usize = 32;
+
+/// Data types of the data section
+const TYPE_POINTER: u8 = 1;
+const TYPE_STRING: u8 = 2;
+const TYPE_DOUBLE: u8 = 3;
+const TYPE_BYTES: u8 = 4;
+const TYPE_UINT16: u8 = 5;
+const TYPE_UINT32: u8 = 6;
+const TYPE_MAP: u8 = 7;
+const TYPE_INT32: u8 = 8;
+const TYPE_UINT64: u8 = 9;
+const TYPE_UINT128: u8 = 10;
+const TYPE_ARRAY: u8 = 11;
+const TYPE_BOOLEAN: u8 = 14;
+const TYPE_FLOAT: u8 = 15;
+
+/// A MaxMind DB file held in memory
+pub struct MmdbReader {
+    data: Vec<u8>,
+    node_count: usize,
+    /// Bits per record: 24, 28 or 32
+    record_size: usize,
+    ip_version: u16,
+    /// Start of the data section
+    data_start: usize,
+    /// Node of `::/96`, where IPv4 addresses start in an IPv6 tree
+    ipv4_start: usize,
+    database_type: String,
+}
+
+impl MmdbReader {
+    /// Read a database file
+    pub fn open(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_bytes(data)
+    }
+
+    /// Read a database from its bytes
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let search_from = data.len().saturating_sub(METADATA_MAX_SIZE);
+        let marker = data[search_from..]
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .map(|i| search_from + i)
+            .context("Not a MaxMind DB file: metadata not found")?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (metadata, _) = Decoder::new(&data[metadata_start..]).decode(0, 0)?;
+
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(|v| v.as_u64())
+                .with_context(|| format!("Metadata without {}", name))
+        };
+        let node_count = usize::try_from(field("node_count")?)?;
+        let record_size = usize::try_from(field("record_size")?)?;
+        let ip_version = u16::try_from(field("ip_version")?)?;
+        if !matches!(record_size, 24 | 28 | 32) {
+            bail!("Unsupported record size {}", record_size);
+        }
+        if !matches!(ip_version, 4 | 6) {
+            bail!("Unsupported IP version {}", ip_version);
+        }
+        let data_start = node_count
+            .checked_mul(record_size / 4)
+            .and_then(|tree_size| tree_size.checked_add(DATA_SECTION_SEPARATOR))
+            .filter(|start| *start <= marker)
+            .context("Search tree larger than the file")?;
+
+        let mut reader = Self {
+            node_count,
+            record_size,
+            ip_version,
+            data_start,
+            ipv4_start: 0,
+            database_type: metadata
+                .get("database_type")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            data: {
+                let mut data = data;
+                data.truncate(marker);
+                data
+            },
+        };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = reader.record(node, 0)?;
+            }
+            reader.ipv4_start = node;
+        }
+        Ok(reader)
+    }
+
+    /// Type of the database, such as `GeoLite2-Country`
+    pub fn database_type(&self) -> &str {
+        &self.database_type
+    }
+
+    /// Record of the network `ip` is in; `None` when the database has none
+    pub fn lookup(&self, ip: IpAddr) -> Result<Option<Value>> {
+        let (bits, bit_count) = match ip {
+            IpAddr::V4(ip) => (u128::from(u32::from(ip)), 32),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => (u128::from(u32::from(ip)), 32),
+                None if self.ip_version == 4 => return Ok(None),
+                None => (u128::from(ip), 128),
+            },
+        };
+
+        let mut node = if bit_count == 32 { self.ipv4_start } else { 0 };
+        for i in 0..bit_count {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bits >> (bit_count - 1 - i)) & 1;
+            node = self.record(node, bit as usize)?;
+        }
+
+        if node == self.node_count {
+            return Ok(None);
+        }
+        let offset = node
+            .checked_sub(self.node_count + DATA_SECTION_SEPARATOR)
+            .context("Search tree deeper than the address")?;
+        let (record, _) = Decoder::new(&self.data[self.data_start..]).decode(offset, 0)?;
+        Ok(Some(record))
+    }
+
+    /// Left (`side` 0) or right (`side` 1) record of a node
+    fn record(&self, node: usize, side: usize) -> Result<usize> {
+        let size = self.record_size / 4;
+        let start = node * size;
+        let bytes = self
+            .data
+            .get(start..start + size)
+            .context("Search tree truncated")?;
+        Ok(match (self.record_size, side) {
+            (24, 0) => be_uint(&bytes[..3]),
+            (24, _) => be_uint(&bytes[3..]),
+            (28, 0) => (usize::from(bytes[3] & 0xf0) << 20) | be_uint(&bytes[..3]),
+            (28, _) => (usize::from(bytes[3] & 0x0f) << 24) | be_uint(&bytes[4..]),
+            (_, 0) => be_uint(&bytes[..4]),
+            (_, _) => be_uint(&bytes[4..]),
+        })
+    }
+}
+
+/// Big-endian unsigned integer of up to 8 bytes
+fn be_uint(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | usize::from(*byte))
+}
+
+/// Decoder of the data and metadata sections; offsets and pointers are
+/// relative to the start of the section
+struct Decoder<'a> {
+    section: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(section: &'a [u8]) -> Self {
+        Self { section }
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        self.section
+            .get(offset..offset.saturating_add(len))
+            .context("Data section truncated")
+    }
+
+    /// Value at `offset`, and the offset after it
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize)> {
+        if depth > MAX_DEPTH {
+            bail!("Data nested too deeply");
+        }
+        let control = self.bytes(offset, 1)?[0];
+        let mut offset = offset + 1;
+        let mut kind = control >> 5;
+
+        if kind == TYPE_POINTER {
+            let len = usize::from((control >> 3) & 0x03) + 1;
+            let value = be_uint(self.bytes(offset, len)?);
+            let high = usize::from(control & 0x07);
+            let target = match len {
+                1 => (high << 8) | value,
+                2 => ((high << 16) | value) + 2048,
+                3 => ((high << 24) | value) + 526_336,
+                _ => value,
+            };
+            let (value, _) = self.decode(target, depth + 1)?;
+            return Ok((value, offset + len));
+        }
+        if kind == 0 {
+            kind = self.bytes(offset, 1)?[0].saturating_add(7);
+            offset += 1;
+        }
+
+        let (size, mut offset) = match control & 0x1f {
+            29 => (29 + be_uint(self.bytes(offset, 1)?), offset + 1),
+            30 => (285 + be_uint(self.bytes(offset, 2)?), offset + 2),
+            31 => (65_821 + be_uint(self.bytes(offset, 3)?), offset + 3),
+            size => (usize::from(size), offset),
+        };
+
+        let value = match kind {
+            TYPE_STRING => {
+                let text = std::str::from_utf8(self.bytes(offset, size)?)
+                    .context("Invalid UTF-8 in string")?;
+                Value::from(text)
+            }
+            TYPE_DOUBLE | TYPE_FLOAT => {
+                let bytes = self.bytes(offset, size)?;
+                let value = match size {
+                    8 => f64::from_be_bytes(bytes.try_into()?),
+                    4 => f64::from(f32::from_be_bytes(bytes.try_into()?)),
+                    _ => bail!("Invalid size {} of a floating point number", size),
+                };
+                Value::from(value)
+            }
+            TYPE_BYTES => Value::from(self.bytes(offset, size)?.to_vec()),
+            TYPE_UINT16 | TYPE_UINT32 | TYPE_UINT64 | TYPE_UINT128 => {
+                if size > 16 {
+                    bail!("Invalid size {} of an integer", size);
+                }
+                let value = self
+                    .bytes(offset, size)?
+                    .iter()
+                    .fold(0u128, |value, byte| (value << 8) | u128::from(*byte));
+                match u64::try_from(value) {
+                    Ok(value) => Value::from(value),
+                    Err(_) => Value::from(value.to_string()),
+                }
+            }
+            TYPE_INT32 => {
+                if size > 4 {
+                    bail!("Invalid size {} of an integer", size);
+                }
+                let value = be_uint(self.bytes(offset, size)?) as u32;
+                Value::from(value as i32)
+            }
+            TYPE_MAP => {
+                let mut map = serde_json::Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(offset, depth + 1)?;
+                    let Value::String(key) = key else {
+                        bail!("Map key is not a string");
+                    };
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    map.insert(key, value);
+                    offset = next;
+                }
+                return Ok((Value::Object(map), offset));
+            }
+            TYPE_ARRAY => {
+                let mut items = Vec::new();
+                for _ in 0..size {
+                    let (item, next) = self.decode(offset, depth + 1)?;
+                    items.push(item);
+                    offset = next;
+                }
+                return Ok((Value::Array(items), offset));
+            }
+            TYPE_BOOLEAN => return Ok((Value::Bool(size != 0), offset)),
+            kind => bail!("Unsupported data type {}", kind),
+        };
+        Ok((value, offset + size))
+    }
+}
+
+/// Country and autonomous system of an address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoIpInfo {
+    /// ISO 3166-1 code of the country, in upper case
+    pub country: Option<String>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+    /// Organization of the autonomous system
+    pub as_organization: Option<String>,
+}
+
+/// The GeoIP databases of the server
+#[derive(Default)]
+pub struct GeoIp {
+    country: Option<MmdbReader>,
+    asn: Option<MmdbReader>,
+}
+
+impl GeoIp {
+    /// Read the configured databases; one that cannot be read is left out
+    pub fn new(config: &GeoIpConfig) -> Self {
+        Self {
+            country: open_database(config.country_database.as_deref()),
+            asn: open_database(config.asn_database.as_deref()),
+        }
+    }
+
+    /// Whether any database is loaded
+    pub fn enabled(&self) -> bool {
+        self.country.is_some() || self.asn.is_some()
+    }
+
+    /// Country and autonomous system of an address
+    pub fn lookup(&self, ip: IpAddr) -> GeoIpInfo {
+        let country = lookup_record(&self.country, ip);
+        let asn = lookup_record(&self.asn, ip);
+        GeoIpInfo {
+            country: country.as_ref().and_then(country_code),
+            asn: asn
+                .as_ref()
+                .and_then(|record| record.get("autonomous_system_number"))
+                .and_then(|v| v.as_u64())
+                .and_then(|asn| u32::try_from(asn).ok()),
+            as_organization: asn
+                .as_ref()
+                .and_then(|record| record.get("autonomous_system_organization"))
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Read a database, logging why it cannot be
+fn open_database(path: Option<&Path>) -> Option<MmdbReader> {
+    let path = path?;
+    match MmdbReader::open(path) {
+        Ok(reader) => {
+            info!(
+                "Loaded GeoIP database {} ({})",
+                path.display(),
+                reader.database_type()
+            );
+            Some(reader)
+        }
+        Err(e) => {
+            warn!("Failed to load GeoIP database {}: {:#}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Record of an address in a database, if any
+fn lookup_record(database: &Option<MmdbReader>, ip: IpAddr) -> Option<Value> {
+    match database.as_ref()?.lookup(ip) {
+        Ok(record) => record,
+        Err(e) => {
+            warn!("GeoIP lookup of {} failed: {}", ip, e);
+            None
+        }
+    }
+}
+
+/// Country of a Country or City record; networks without a located country
+/// have the one they are registered in
+fn country_code(record: &Value) -> Option<String> {
+    ["country", "registered_country"]
+        .iter()
+        .find_map(|key| record.get(key)?.get("iso_code")?.as_str())
+        .map(str::to_ascii_uppercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::Ipv4Addr;
+
+    /// Append the control bytes of a value
+    fn control(out: &mut Vec<u8>, kind: u8, size: usize) {
+        let (bits, extra) = match size {
+            0..=28 => (size as u8, Vec::new()),
+            29..=284 => (29, vec![(size - 29) as u8]),
+            _ => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+        };
+        if kind < 8 {
+            out.push((kind << 5) | bits);
+        } else {
+            out.push(bits);
+            out.push(kind - 7);
+        }
+        out.extend(extra);
+    }
+
+    /// Append a value in the encoding of the data section
+    fn encode(value: &Value, out: &mut Vec<u8>) {
+        match value {
+            Value::String(text) => {
+                control(out, TYPE_STRING, text.len());
+                out.extend(text.as_bytes());
+            }
+            Value::Number(number) => {
+                let bytes = number.as_u64().unwrap().to_be_bytes();
+                let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+                control(out, TYPE_UINT32, 8 - skip);
+                out.extend(&bytes[skip..]);
+            }
+            Value::Bool(value) => control(out, TYPE_BOOLEAN, usize::from(*value)),
+            Value::Object(map) => {
+                control(out, TYPE_MAP, map.len());
+                for (key, value) in map {
+                    encode(&Value::from(key.as_str()), out);
+                    encode(value, out);
+                }
+            }
+            _ => panic!("unsupported value type in test fixture: {:?}", value),
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    enum Record {
+        Empty,
+        Node(usize),
+        Data(usize),
+    }
+
+    /// Database with 24-bit records mapping networks to records
+    fn database(ip_version: u16, networks: &[(IpAddr, usize, Value)]) -> Vec<u8> {
+        let mut nodes = vec![[Record::Empty; 2]];
+        let mut data = Vec::new();
+        for (ip, prefix, value) in networks {
+            let (bits, prefix) = match ip {
+                IpAddr::V4(ip) if ip_version == 6 => (u128::from(u32::from(*ip)), prefix + 96),
+                IpAddr::V4(ip) => (u128::from(u32::from(*ip)) << 96, *prefix),
+                IpAddr::V6(ip) => (u128::from(*ip), *prefix),
+            };
+            let offset = data.len();
+            encode(value, &mut data);
+            let mut node = 0;
+            for i in 0..prefix {
+                let bit = ((bits >> (127 - i)) & 1) as usize;
+                if i + 1 == prefix {
+                    nodes[node][bit] = Record::Data(offset);
+                    break;
+                }
+                node = match nodes[node][bit] {
+                    Record::Node(next) => next,
+                    _ => {
+                        nodes.push([Record::Empty; 2]);
+                        nodes[node][bit] = Record::Node(nodes.len() - 1);
+                        nodes.len() - 1
+                    }
+                };
+            }
+        }
+
+        let node_count = nodes.len();
+        let mut out = Vec::new();
+        for record in nodes.iter().flatten() {
+            let value = match record {
+                Record::Empty => node_count,
+                Record::Node(node) => *node,
+                Record::Data(offset) => node_count + DATA_SECTION_SEPARATOR + offset,
+            };
+            out.extend(&(value as u32).to_be_bytes()[1..]);
+        }
+        out.extend([0; DATA_SECTION_SEPARATOR]);
+        out.extend(data);
+        out.extend(METADATA_MARKER);
+        encode(
+            &json!({
+                "node_count": node_count,
+                "record_size": 24,
+                "ip_version": ip_version,
+                "database_type": "Test",
+            }),
+            &mut out,
+        );
+        out
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_country_lookup() {
+        let reader = MmdbReader::from_bytes(database(
+            4,
+            &[
+                (ip("192.0.2.0"), 24, json!({"country": {"iso_code": "nl"}})),
+                (
+                    ip("198.51.100.0"),
+                    25,
+                    json!({"registered_country": {"iso_code": "DE"}}),
+                ),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(reader.database_type(), "Test");
+        let geoip = GeoIp {
+            country: Some(reader),
+            asn: None,
+        };
+        assert!(geoip.enabled());
+
+        let country = |text| geoip.lookup(ip(text)).country;
+        assert_eq!(country("192.0.2.7").as_deref(), Some("NL"));
+        assert_eq!(country("::ffff:192.0.2.7").as_deref(), Some("NL"));
+        assert_eq!(country("198.51.100.1").as_deref(), Some("DE"));
+        assert_eq!(country("198.51.100.200"), None);
+        assert_eq!(country("203.0.113.1"), None);
+        assert_eq!(country("2001:db8::1"), None);
+        assert_eq!(geoip.lookup(ip("192.0.2.7")).asn, None);
+    }
+
+    #[test]
+    fn test_asn_lookup() {
+        let reader = MmdbReader::from_bytes(database(
+            6,
+            &[
+                (
+                    ip("2001:db8::"),
+                    32,
+                    json!({
+                        "autonomous_system_number": 64500,
+                        "autonomous_system_organization": "Example Networks",
+                    }),
+                ),
+                (
+                    ip("192.0.2.0"),
+                    24,
+                    json!({"autonomous_system_number": 4_200_000_000u32}),
+                ),
+            ],
+        ))
+        .unwrap();
+        let geoip = GeoIp {
+            country: None,
+            asn: Some(reader),
+        };
+
+        assert_eq!(
+            geoip.lookup(ip("2001:db8:1::25")),
+            GeoIpInfo {
+                country: None,
+                asn: Some(64500),
+                as_organization: Some("Example Networks".to_string()),
+            }
+        );
+        assert_eq!(geoip.lookup(ip("192.0.2.1")).asn, Some(4_200_000_000));
+        assert_eq!(geoip.lookup(ip("2001:db9::1")), GeoIpInfo::default());
+        assert_eq!(geoip.lookup(IpAddr::V4(Ipv4Addr::LOCALHOST)).asn, None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let long = "x".repeat(300);
+        let mut section = vec![0x43, b'a', b'b', b'c', 0x20, 0x00];
+        section.push(0x68);
+        section.extend(1.5f64.to_be_bytes());
+        section.extend([0x01, 0x07]);
+        section.extend([0x04, 0x01, 0xff, 0xff, 0xff, 0xfe]);
+        let start = section.len();
+        encode(&Value::from(long.as_str()), &mut section);
+
+        let decoder = Decoder::new(&section);
+        assert_eq!(decoder.decode(0, 0).unwrap(), (json!("abc"), 4));
+        assert_eq!(decoder.decode(4, 0).unwrap(), (json!("abc"), 6));
+        assert_eq!(decoder.decode(6, 0).unwrap(), (json!(1.5), 15));
+        assert_eq!(decoder.decode(15, 0).unwrap(), (json!(true), 17));
+        assert_eq!(decoder.decode(17, 0).unwrap(), (json!(-2), 23));
+        assert_eq!(
+            decoder.decode(start, 0).unwrap(),
+            (json!(long), section.len())
+        );
+        assert!(decoder.decode(section.len(), 0).is_err());
+
+        // A pointer to itself
+        assert!(Decoder::new(&[0x20, 0x00]).decode(0, 0).is_err());
+    }
+
+    #[test]
+    fn test_invalid_database() {
+        assert!(MmdbReader::from_bytes(b"not a database".to_vec()).is_err());
+
+        let mut truncated = METADATA_MARKER.to_vec();
+        encode(
+            &json!({"node_count": 1000, "record_size": 24, "ip_version": 4}),
+            &mut truncated,
+        );
+        assert!(MmdbReader::from_bytes(truncated).is_err());
+    }
+}
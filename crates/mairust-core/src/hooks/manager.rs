@@ -448,7 +448,7 @@ impl HookManager {
 ///
 /// Rejects URLs targeting private/internal IP ranges, loopback addresses,
 /// link-local addresses, and non-HTTP(S) schemes.
-fn validate_webhook_url(url_str: &str) -> Result<()> {
+pub fn validate_webhook_url(url_str: &str) -> Result<()> {
     let url = Url::parse(url_str)
         .map_err(|e| anyhow::anyhow!("Invalid webhook URL: {}", e))?;
 
@@ -2,4 +2,4 @@
 
 mod manager;
 
-pub use manager::HookManager;
+pub use manager::{validate_webhook_url, HookManager};
@@ -0,0 +1,154 @@
+//! IMAP access control lists (RFC 4314)
+//!
+//! Rights are the RFC 4314 letters `lrswipkxtea`. Mailboxes shared with a
+//! user appear under the `Shared/` namespace as `Shared/<owner>/<folder>`.
+
+use std::fmt;
+
+/// All rights in canonical order
+pub const ALL_RIGHTS: &str = "lrswipkxtea";
+
+/// Rights granted for a read-only share
+pub const READ_ONLY_RIGHTS: &str = "lrs";
+
+/// Rights granted for a read-write share (everything except administer)
+pub const READ_WRITE_RIGHTS: &str = "lrswipkxte";
+
+/// Prefix of the shared mailbox namespace
+pub const SHARED_PREFIX: &str = "Shared/";
+
+/// A set of ACL rights
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rights(u16);
+
+impl Rights {
+    /// No rights
+    pub fn none() -> Self {
+        Self(0)
+    }
+
+    /// Every right, as held by a mailbox owner
+    pub fn all() -> Self {
+        Self::parse(ALL_RIGHTS).unwrap_or_default()
+    }
+
+    /// Parse a rights string
+    ///
+    /// The obsolete RFC 2086 rights are mapped as described in RFC 4314
+    /// section 2.1.1: `c` grants `k` and `x`, `d` grants `x`, `t` and `e`.
+    pub fn parse(rights: &str) -> Option<Self> {
+        let mut bits = 0;
+        for right in rights.chars() {
+            bits |= match right {
+                'c' => Self::bit('k')? | Self::bit('x')?,
+                'd' => Self::bit('x')? | Self::bit('t')? | Self::bit('e')?,
+                right => Self::bit(right)?,
+            };
+        }
+        Some(Self(bits))
+    }
+
+    /// Apply a SETACL rights modification: `+rights`, `-rights` or `rights`
+    pub fn apply(self, modification: &str) -> Option<Self> {
+        if let Some(added) = modification.strip_prefix('+') {
+            Some(Self(self.0 | Self::parse(added)?.0))
+        } else if let Some(removed) = modification.strip_prefix('-') {
+            Some(Self(self.0 & !Self::parse(removed)?.0))
+        } else {
+            Self::parse(modification)
+        }
+    }
+
+    /// Whether a right is granted
+    pub fn has(self, right: char) -> bool {
+        Self::bit(right).is_some_and(|bit| self.0 & bit != 0)
+    }
+
+    /// Whether no rights are granted
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the mailbox can be modified with these rights
+    ///
+    /// Without any of `s`, `w`, `i`, `t` or `e` a mailbox is selected
+    /// read-only.
+    pub fn allows_write(self) -> bool {
+        "swite".chars().any(|right| self.has(right))
+    }
+
+    fn bit(right: char) -> Option<u16> {
+        ALL_RIGHTS.find(right).map(|pos| 1 << pos)
+    }
+}
+
+impl fmt::Display for Rights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for right in ALL_RIGHTS.chars().filter(|right| self.has(*right)) {
+            write!(f, "{}", right)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of a shared mailbox as seen by users it is shared with
+///
+/// The owner's own address is their inbox and is shown as `INBOX`.
+pub fn shared_mailbox_name(owner_email: &str, address: &str) -> String {
+    let folder = if address.eq_ignore_ascii_case(owner_email) {
+        "INBOX"
+    } else {
+        address
+    };
+    format!("{}{}/{}", SHARED_PREFIX, owner_email, folder)
+}
+
+/// Whether a mailbox name lies in the shared namespace
+pub fn is_shared_name(name: &str) -> bool {
+    name.get(..SHARED_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(SHARED_PREFIX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rights() {
+        assert_eq!(Rights::parse("rls").unwrap().to_string(), "lrs");
+        assert_eq!(Rights::parse("").unwrap(), Rights::none());
+        assert_eq!(Rights::parse("cd").unwrap().to_string(), "kxte");
+        assert_eq!(Rights::parse("lrz"), None);
+        assert_eq!(Rights::all().to_string(), ALL_RIGHTS);
+    }
+
+    #[test]
+    fn test_apply_modification() {
+        let rights = Rights::parse(READ_ONLY_RIGHTS).unwrap();
+        assert_eq!(rights.apply("+wi").unwrap().to_string(), "lrswi");
+        assert_eq!(rights.apply("-s").unwrap().to_string(), "lr");
+        assert_eq!(rights.apply("lr").unwrap().to_string(), "lr");
+        assert_eq!(rights.apply("+q"), None);
+    }
+
+    #[test]
+    fn test_allows_write() {
+        assert!(!Rights::parse("lr").unwrap().allows_write());
+        assert!(Rights::parse(READ_ONLY_RIGHTS).unwrap().allows_write());
+        assert!(Rights::parse(READ_WRITE_RIGHTS).unwrap().allows_write());
+    }
+
+    #[test]
+    fn test_shared_mailbox_name() {
+        assert_eq!(
+            shared_mailbox_name("alice@example.com", "alice@example.com"),
+            "Shared/alice@example.com/INBOX"
+        );
+        assert_eq!(
+            shared_mailbox_name("alice@example.com", "Projects"),
+            "Shared/alice@example.com/Projects"
+        );
+        assert!(is_shared_name("shared/alice@example.com/INBOX"));
+        assert!(!is_shared_name("Sent"));
+    }
+}
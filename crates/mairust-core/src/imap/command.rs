@@ -2,6 +2,7 @@
 //!
 //! Defines the IMAP commands supported by this server (read and write operations).
 
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
 /// IMAP command tag (client-provided identifier)
@@ -23,8 +24,9 @@ pub enum SequenceSet {
 impl SequenceSet {
     /// Parse a sequence set string
     pub fn parse(s: &str) -> Option<Self> {
+        // A lone `*` is the highest number in use, not every message
         if s == "*" {
-            return Some(SequenceSet::All);
+            return Some(SequenceSet::Single(u32::MAX));
         }
 
         // Check for comma-separated sets
@@ -133,23 +135,32 @@ impl FetchItem {
             "FAST" => Some(FetchItem::Fast),
             "FULL" => Some(FetchItem::Full),
             _ if s.starts_with("BODY.PEEK[") => {
-                let section = s.strip_prefix("BODY.PEEK[")?.strip_suffix(']')?.to_string();
-                Some(FetchItem::BodyPeek {
-                    section,
-                    partial: None,
-                })
+                let (section, partial) = Self::parse_section(s.strip_prefix("BODY.PEEK[")?)?;
+                Some(FetchItem::BodyPeek { section, partial })
             }
             _ if s.starts_with("BODY[") => {
-                let section = s.strip_prefix("BODY[")?.strip_suffix(']')?.to_string();
-                Some(FetchItem::BodySection {
-                    section,
-                    partial: None,
-                })
+                let (section, partial) = Self::parse_section(s.strip_prefix("BODY[")?)?;
+                Some(FetchItem::BodySection { section, partial })
             }
             _ => None,
         }
     }
 
+    /// Split `section]` or `section]<origin.count>` into the section and
+    /// the partial range
+    fn parse_section(s: &str) -> Option<(String, Option<(u32, u32)>)> {
+        let end = s.rfind(']')?;
+        let partial = match &s[end + 1..] {
+            "" => None,
+            suffix => {
+                let range = suffix.strip_prefix('<')?.strip_suffix('>')?;
+                let (origin, count) = range.split_once('.')?;
+                Some((origin.parse().ok()?, count.parse().ok()?))
+            }
+        };
+        Some((s[..end].to_string(), partial))
+    }
+
     /// Parse fetch items from a parenthesized list or single item
     pub fn parse_list(s: &str) -> Vec<Self> {
         let s = s.trim();
@@ -211,8 +222,8 @@ pub enum SearchCriteria {
     Answered,
     /// BCC header contains string
     Bcc(String),
-    /// Before date
-    Before(String),
+    /// Internal date earlier than the date
+    Before(NaiveDate),
     /// Body contains string
     Body(String),
     /// CC header contains string
@@ -225,8 +236,11 @@ pub enum SearchCriteria {
     Flagged,
     /// From header contains string
     From(String),
-    /// Messages with specific header value
+    /// Header field (name, string) contains the string; an empty string
+    /// matches every message with the field
     Header(String, String),
+    /// Messages with the keyword set
+    Keyword(String),
     /// Larger than size
     Larger(u32),
     /// New messages (Recent and not Seen)
@@ -235,16 +249,22 @@ pub enum SearchCriteria {
     Not(Box<SearchCriteria>),
     /// Old messages (not Recent)
     Old,
-    /// On specific date
-    On(String),
+    /// Internal date on the date
+    On(NaiveDate),
     /// Logical OR
     Or(Box<SearchCriteria>, Box<SearchCriteria>),
     /// Recent messages
     Recent,
     /// Seen messages
     Seen,
-    /// Since date
-    Since(String),
+    /// Sent (Date header) earlier than the date
+    SentBefore(NaiveDate),
+    /// Sent on the date
+    SentOn(NaiveDate),
+    /// Sent on or after the date
+    SentSince(NaiveDate),
+    /// Internal date on or after the date
+    Since(NaiveDate),
     /// Smaller than size
     Smaller(u32),
     /// Subject contains string
@@ -261,6 +281,8 @@ pub enum SearchCriteria {
     Undeleted,
     /// Undraft messages
     Undraft,
+    /// Messages without the keyword
+    Unkeyword(String),
     /// Unflagged messages
     Unflagged,
     /// Unseen messages
@@ -290,6 +312,25 @@ pub struct StoreFlags {
     pub flags: Vec<String>,
 }
 
+/// GETMETADATA DEPTH option (RFC 5464)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataDepth {
+    /// Only the requested entries
+    #[default]
+    Zero,
+    /// Requested entries and their immediate children
+    One,
+    /// Requested entries and all descendants
+    Infinity,
+}
+
+/// GETMETADATA options
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataOptions {
+    pub maxsize: Option<usize>,
+    pub depth: MetadataDepth,
+}
+
 /// IMAP Command
 #[derive(Debug, Clone)]
 pub enum ImapCommand {
@@ -384,6 +425,37 @@ pub enum ImapCommand {
     Idle,
     Done,
     Namespace,
+    Compress {
+        mechanism: String,
+    },
+    GetMetadata {
+        mailbox: String,
+        entries: Vec<String>,
+        options: MetadataOptions,
+    },
+    SetMetadata {
+        mailbox: String,
+        entries: Vec<(String, Option<String>)>,
+    },
+    SetAcl {
+        mailbox: String,
+        identifier: String,
+        rights: String,
+    },
+    DeleteAcl {
+        mailbox: String,
+        identifier: String,
+    },
+    GetAcl {
+        mailbox: String,
+    },
+    ListRights {
+        mailbox: String,
+        identifier: String,
+    },
+    MyRights {
+        mailbox: String,
+    },
 
     // UID variants are handled via uid flag in Fetch/Search/Store/Copy/Move
 
@@ -393,6 +465,46 @@ pub enum ImapCommand {
     },
 }
 
+impl ImapCommand {
+    /// Whether the command changes mailboxes, messages or their metadata
+    ///
+    /// These are refused while the system is read-only for maintenance.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            ImapCommand::Create { .. }
+                | ImapCommand::Delete { .. }
+                | ImapCommand::Rename { .. }
+                | ImapCommand::Subscribe { .. }
+                | ImapCommand::Unsubscribe { .. }
+                | ImapCommand::Append { .. }
+                | ImapCommand::Store { .. }
+                | ImapCommand::Copy { .. }
+                | ImapCommand::Move { .. }
+                | ImapCommand::Expunge
+                | ImapCommand::SetMetadata { .. }
+                | ImapCommand::SetAcl { .. }
+                | ImapCommand::DeleteAcl { .. }
+        )
+    }
+
+    /// Whether the command refers to messages by sequence number
+    ///
+    /// Expunges made by other sessions are not announced while such a
+    /// command runs, since that would renumber the messages it refers to
+    /// (RFC 3501, section 7.4.1).
+    pub fn uses_sequence_numbers(&self) -> bool {
+        matches!(
+            self,
+            ImapCommand::Fetch { uid: false, .. }
+                | ImapCommand::Store { uid: false, .. }
+                | ImapCommand::Copy { uid: false, .. }
+                | ImapCommand::Move { uid: false, .. }
+                | ImapCommand::Search { uid: false, .. }
+        )
+    }
+}
+
 /// Parsed IMAP command with tag
 #[derive(Debug, Clone)]
 pub struct TaggedCommand {
@@ -407,7 +519,7 @@ mod tests {
     #[test]
     fn test_sequence_set_parse() {
         assert_eq!(SequenceSet::parse("1"), Some(SequenceSet::Single(1)));
-        assert_eq!(SequenceSet::parse("*"), Some(SequenceSet::All));
+        assert_eq!(SequenceSet::parse("*"), Some(SequenceSet::Single(u32::MAX)));
         assert_eq!(SequenceSet::parse("1:5"), Some(SequenceSet::Range(1, 5)));
         assert_eq!(
             SequenceSet::parse("1:*"),
@@ -424,6 +536,38 @@ mod tests {
         assert!(!set.contains(6, 10));
     }
 
+    #[test]
+    fn test_is_write() {
+        assert!(ImapCommand::Expunge.is_write());
+        assert!(ImapCommand::Create {
+            mailbox: "Archive".to_string()
+        }
+        .is_write());
+        assert!(!ImapCommand::Select {
+            mailbox: "INBOX".to_string()
+        }
+        .is_write());
+        assert!(!ImapCommand::Fetch {
+            sequence: SequenceSet::All,
+            items: vec![FetchItem::Flags],
+            uid: false,
+        }
+        .is_write());
+    }
+
+    #[test]
+    fn test_uses_sequence_numbers() {
+        let fetch = |uid| ImapCommand::Fetch {
+            sequence: SequenceSet::Range(1, 10),
+            items: vec![FetchItem::Flags],
+            uid,
+        };
+        assert!(fetch(false).uses_sequence_numbers());
+        assert!(!fetch(true).uses_sequence_numbers());
+        assert!(!ImapCommand::Noop.uses_sequence_numbers());
+        assert!(!ImapCommand::Expunge.uses_sequence_numbers());
+    }
+
     #[test]
     fn test_fetch_item_parse() {
         assert_eq!(FetchItem::parse("FLAGS"), Some(FetchItem::Flags));
@@ -438,5 +582,46 @@ mod tests {
         assert_eq!(items[0], FetchItem::Flags);
         assert_eq!(items[1], FetchItem::Uid);
         assert_eq!(items[2], FetchItem::Rfc822Size);
+
+        let items = FetchItem::parse_list("(UID BODY.PEEK[1.HEADER.FIELDS (From Subject)])");
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[1],
+            FetchItem::BodyPeek {
+                section: "1.HEADER.FIELDS (FROM SUBJECT)".to_string(),
+                partial: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_fetch_item_partial() {
+        assert_eq!(
+            FetchItem::parse("BODY[]<0.1024>"),
+            Some(FetchItem::BodySection {
+                section: String::new(),
+                partial: Some((0, 1024)),
+            })
+        );
+        assert_eq!(
+            FetchItem::parse("body.peek[2]<65536.65536>"),
+            Some(FetchItem::BodyPeek {
+                section: "2".to_string(),
+                partial: Some((65536, 65536)),
+            })
+        );
+        assert_eq!(FetchItem::parse("BODY[]<10>"), None);
+        assert_eq!(FetchItem::parse("BODY[]<a.b>"), None);
+        assert_eq!(FetchItem::parse("BODY[TEXT"), None);
+
+        let items = FetchItem::parse_list("(UID BODY.PEEK[1]<0.512> FLAGS)");
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[1],
+            FetchItem::BodyPeek {
+                section: "1".to_string(),
+                partial: Some((0, 512)),
+            }
+        );
     }
 }
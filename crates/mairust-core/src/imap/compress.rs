@@ -0,0 +1,250 @@
+//! IMAP COMPRESS=DEFLATE (RFC 4978)
+//!
+//! Wraps the session streams in raw DEFLATE (no zlib header). The writer
+//! emits a sync flush whenever the session flushes, so every response
+//! reaches the client as soon as it is complete.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Size of the compressed input buffer and of output buffer growth
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Compressed output held before it is written to the connection
+const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+
+/// Reader that inflates a raw DEFLATE stream
+pub struct DeflateReader<R> {
+    inner: R,
+    decompress: Decompress,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+}
+
+impl<R> DeflateReader<R> {
+    /// Wrap `inner`; `buffered` holds compressed bytes already read from it
+    /// (e.g. pipelined after the COMPRESS command)
+    pub fn new(inner: R, buffered: &[u8]) -> Self {
+        let mut buf = buffered.to_vec();
+        let end = buf.len();
+        buf.resize(end.max(CHUNK_SIZE), 0);
+        Self {
+            inner,
+            decompress: Decompress::new(false),
+            buf,
+            start: 0,
+            end,
+            eof: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DeflateReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if out.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        loop {
+            if this.start < this.end || this.eof {
+                let before_in = this.decompress.total_in();
+                let before_out = this.decompress.total_out();
+                let status = this
+                    .decompress
+                    .decompress(
+                        &this.buf[this.start..this.end],
+                        out.initialize_unfilled(),
+                        FlushDecompress::None,
+                    )
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let consumed = (this.decompress.total_in() - before_in) as usize;
+                let produced = (this.decompress.total_out() - before_out) as usize;
+                this.start += consumed;
+                out.advance(produced);
+
+                if produced > 0 || status == Status::StreamEnd {
+                    return Poll::Ready(Ok(()));
+                }
+                if this.eof {
+                    return Poll::Ready(if this.start < this.end {
+                        Err(io::ErrorKind::UnexpectedEof.into())
+                    } else {
+                        Ok(())
+                    });
+                }
+                if consumed > 0 {
+                    continue;
+                }
+            }
+
+            // Keep unconsumed input and read more behind it
+            this.buf.copy_within(this.start..this.end, 0);
+            this.end -= this.start;
+            this.start = 0;
+            if this.end == this.buf.len() {
+                this.buf.resize(this.buf.len() + CHUNK_SIZE, 0);
+            }
+
+            let mut read_buf = ReadBuf::new(&mut this.buf[this.end..]);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+            let n = read_buf.filled().len();
+            if n == 0 {
+                this.eof = true;
+            }
+            this.end += n;
+        }
+    }
+}
+
+/// Writer that deflates everything written to it
+pub struct DeflateWriter<W> {
+    inner: W,
+    compress: Compress,
+    output: Vec<u8>,
+    written: usize,
+    needs_sync: bool,
+}
+
+impl<W> DeflateWriter<W> {
+    /// Wrap `inner`
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            compress: Compress::new(Compression::default(), false),
+            output: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+            needs_sync: false,
+        }
+    }
+
+    /// Compress `input` into the pending output
+    fn deflate(&mut self, mut input: &[u8], flush: FlushCompress) -> io::Result<()> {
+        loop {
+            if self.output.capacity() - self.output.len() < CHUNK_SIZE {
+                self.output.reserve(CHUNK_SIZE);
+            }
+            let before_in = self.compress.total_in();
+            self.compress
+                .compress_vec(input, &mut self.output, flush)
+                .map_err(io::Error::other)?;
+            input = &input[(self.compress.total_in() - before_in) as usize..];
+
+            // Done once the input is consumed and the output was not filled
+            if input.is_empty() && self.output.len() < self.output.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> DeflateWriter<W> {
+    /// Write the pending output to the connection
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.output[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for DeflateWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.output.len() >= MAX_PENDING_OUTPUT {
+            ready!(this.poll_drain(cx))?;
+        }
+        this.deflate(buf, FlushCompress::None)?;
+        this.needs_sync = true;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.needs_sync {
+            this.deflate(&[], FlushCompress::Sync)?;
+            this.needs_sync = false;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    #[tokio::test]
+    async fn test_deflate_round_trip() {
+        let (client, server) = tokio::io::duplex(256);
+        let mut writer = DeflateWriter::new(client);
+        let mut reader = BufReader::new(DeflateReader::new(server, &[]));
+
+        writer.write_all(b"a1 NOOP\r\n").await.unwrap();
+        writer.flush().await.unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "a1 NOOP\r\n");
+
+        // A large response crosses many small duplex writes
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let expected = body.clone();
+        let send = tokio::spawn(async move {
+            writer.write_all(&body).await.unwrap();
+            writer.flush().await.unwrap();
+        });
+        let mut received = vec![0u8; expected.len()];
+        reader.read_exact(&mut received).await.unwrap();
+        send.await.unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_deflate_reader_buffered_prefix() {
+        // Compressed bytes the line reader already consumed must not be lost
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut compressed = Vec::with_capacity(1024);
+        compress
+            .compress_vec(
+                b"a2 SELECT INBOX\r\na3 LOGOUT\r\n",
+                &mut compressed,
+                FlushCompress::Sync,
+            )
+            .unwrap();
+        let (prefix, rest) = compressed.split_at(5);
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(rest).await.unwrap();
+        drop(client);
+
+        let mut reader = BufReader::new(DeflateReader::new(server, prefix));
+        let mut text = String::new();
+        reader.read_to_string(&mut text).await.unwrap();
+        assert_eq!(text, "a2 SELECT INBOX\r\na3 LOGOUT\r\n");
+    }
+}
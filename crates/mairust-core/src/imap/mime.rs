@@ -0,0 +1,707 @@
+//! MIME structure of stored messages
+//!
+//! FETCH BODYSTRUCTURE and BODY[section] are answered from the stored
+//! message. Sections have to be exact octet ranges of the original, so the
+//! structure is computed over byte offsets into the raw message rather than
+//! over decoded parts.
+
+use super::response::ImapResponse;
+use std::ops::Range;
+
+/// Nesting depth beyond which entities are treated as leaf parts
+const MAX_DEPTH: usize = 32;
+
+/// Header field parameters as (lowercase name, value) pairs
+type Params = Vec<(String, String)>;
+
+/// A MIME entity: a message, a body part or an encapsulated message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePart {
+    /// Header, including the blank line that ends it
+    pub header: Range<usize>,
+    /// Body
+    pub body: Range<usize>,
+    /// Unfolded header fields
+    fields: Vec<(String, String)>,
+    /// Media type, lowercase
+    pub media_type: String,
+    /// Media subtype, lowercase
+    pub media_subtype: String,
+    /// Content-Type parameters
+    pub params: Params,
+    /// Children of a multipart entity
+    pub parts: Vec<MimePart>,
+    /// Encapsulated message of a message/rfc822 entity
+    pub message: Option<Box<MimePart>>,
+}
+
+impl MimePart {
+    /// Parse the structure of a raw message
+    pub fn parse(raw: &[u8]) -> Self {
+        Self::parse_entity(raw, 0..raw.len(), ("text", "plain"), 0)
+    }
+
+    fn parse_entity(
+        raw: &[u8],
+        range: Range<usize>,
+        default_type: (&str, &str),
+        depth: usize,
+    ) -> Self {
+        let (header, body) = split_header(raw, range);
+        let fields = parse_fields(&raw[header.clone()]);
+
+        let content_type = fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .and_then(|(_, value)| parse_content_type(value));
+        let (media_type, media_subtype, params) = content_type.unwrap_or_else(|| {
+            (
+                default_type.0.to_string(),
+                default_type.1.to_string(),
+                Vec::new(),
+            )
+        });
+
+        let mut part = Self {
+            header,
+            body,
+            fields,
+            media_type,
+            media_subtype,
+            params,
+            parts: Vec::new(),
+            message: None,
+        };
+
+        if depth >= MAX_DEPTH {
+            return part;
+        }
+
+        if part.media_type == "multipart" {
+            let child_default = if part.media_subtype == "digest" {
+                ("message", "rfc822")
+            } else {
+                ("text", "plain")
+            };
+            if let Some(boundary) = part.param("boundary") {
+                part.parts = split_multipart(raw, part.body.clone(), boundary)
+                    .into_iter()
+                    .map(|range| Self::parse_entity(raw, range, child_default, depth + 1))
+                    .collect();
+            }
+            // A multipart entity without parts cannot be described; present
+            // it as an opaque text part instead
+            if part.parts.is_empty() {
+                part.media_type = "text".to_string();
+                part.media_subtype = "plain".to_string();
+                part.params.clear();
+            }
+        } else if part.is_message() {
+            part.message = Some(Box::new(Self::parse_entity(
+                raw,
+                part.body.clone(),
+                ("text", "plain"),
+                depth + 1,
+            )));
+        }
+
+        part
+    }
+
+    /// Whether the entity is a multipart entity
+    pub fn is_multipart(&self) -> bool {
+        !self.parts.is_empty()
+    }
+
+    /// Whether the entity encapsulates a message
+    fn is_message(&self) -> bool {
+        self.media_type == "message" && matches!(self.media_subtype.as_str(), "rfc822" | "global")
+    }
+
+    /// First value of a header field
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+
+    /// Content-Type parameter
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// BODYSTRUCTURE (`extensible`) or BODY representation of the entity
+    pub fn body_structure(&self, raw: &[u8], extensible: bool) -> String {
+        if self.is_multipart() {
+            let mut out = String::from("(");
+            for part in &self.parts {
+                out.push_str(&part.body_structure(raw, extensible));
+            }
+            out.push(' ');
+            out.push_str(&nstring(Some(&self.media_subtype.to_uppercase())));
+            if extensible {
+                out.push(' ');
+                out.push_str(&param_list(&self.params));
+                out.push(' ');
+                out.push_str(&self.extension_fields());
+            }
+            out.push(')');
+            return out;
+        }
+
+        let mut params = self.params.clone();
+        if self.media_type == "text" && self.param("charset").is_none() {
+            params.push(("charset".to_string(), "us-ascii".to_string()));
+        }
+        let encoding = self
+            .field("Content-Transfer-Encoding")
+            .map(|e| e.to_uppercase())
+            .unwrap_or_else(|| "7BIT".to_string());
+        let body = &raw[self.body.clone()];
+
+        let mut out = format!(
+            "({} {} {} {} {} {} {}",
+            nstring(Some(&self.media_type.to_uppercase())),
+            nstring(Some(&self.media_subtype.to_uppercase())),
+            param_list(&params),
+            nstring(self.field("Content-ID")),
+            nstring(self.field("Content-Description")),
+            nstring(Some(&encoding)),
+            body.len()
+        );
+
+        if let Some(message) = &self.message {
+            out.push_str(&format!(
+                " {} {} {}",
+                message.envelope(),
+                message.body_structure(raw, extensible),
+                count_lines(body)
+            ));
+        } else if self.media_type == "text" {
+            out.push_str(&format!(" {}", count_lines(body)));
+        }
+
+        if extensible {
+            out.push(' ');
+            out.push_str(&nstring(self.field("Content-MD5")));
+            out.push(' ');
+            out.push_str(&self.extension_fields());
+        }
+        out.push(')');
+        out
+    }
+
+    /// Disposition, language and location extension fields
+    fn extension_fields(&self) -> String {
+        let disposition = self
+            .field("Content-Disposition")
+            .and_then(|value| {
+                let (kind, params) = split_params(value);
+                let kind = kind.trim();
+                (!kind.is_empty()).then(|| {
+                    format!(
+                        "({} {})",
+                        nstring(Some(&kind.to_uppercase())),
+                        param_list(&params)
+                    )
+                })
+            })
+            .unwrap_or_else(|| "NIL".to_string());
+
+        let languages: Vec<&str> = self
+            .field("Content-Language")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let language = match languages.as_slice() {
+            [] => "NIL".to_string(),
+            [language] => nstring(Some(language)),
+            languages => format!(
+                "({})",
+                languages
+                    .iter()
+                    .map(|l| nstring(Some(l)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        };
+
+        format!(
+            "{} {} {}",
+            disposition,
+            language,
+            nstring(self.field("Content-Location"))
+        )
+    }
+
+    /// ENVELOPE of an encapsulated message
+    fn envelope(&self) -> String {
+        ImapResponse::format_envelope(
+            self.field("Date"),
+            self.field("Subject"),
+            self.field("From"),
+            self.field("To"),
+            self.field("Cc"),
+            self.field("Message-ID"),
+        )
+    }
+
+    /// Contents of a BODY[section] fetch
+    ///
+    /// `section` is the text between the brackets, e.g. `1.2`, `HEADER`,
+    /// `2.HEADER.FIELDS (FROM TO)` or `1.MIME`. Returns `None` when the
+    /// section does not exist or is malformed.
+    pub fn section(&self, raw: &[u8], section: &str) -> Option<Vec<u8>> {
+        let (path, text) = parse_section_path(section.trim())?;
+
+        // Walk the part numbers (RFC 3501 section 6.4.5): the parts of a
+        // message/rfc822 part are those of the message it encapsulates, and
+        // a non-multipart message has a single part 1, its body
+        let mut part = self;
+        let mut is_message = true;
+        for number in &path {
+            let container = if is_message || part.is_multipart() {
+                part
+            } else {
+                part.message.as_deref()?
+            };
+            part = if container.is_multipart() {
+                container.parts.get(number - 1)?
+            } else if *number == 1 {
+                container
+            } else {
+                return None;
+            };
+            is_message = false;
+        }
+
+        let upper = text.to_uppercase();
+        if upper.is_empty() {
+            let range = if path.is_empty() {
+                part.header.start..part.body.end
+            } else {
+                part.body.clone()
+            };
+            return Some(raw[range].to_vec());
+        }
+        if upper == "MIME" {
+            return (!path.is_empty()).then(|| raw[part.header.clone()].to_vec());
+        }
+
+        // HEADER and TEXT apply to a message: the top-level one or the one
+        // encapsulated by a message/rfc822 part
+        let message = if path.is_empty() {
+            self
+        } else {
+            part.message.as_deref()?
+        };
+        if upper == "HEADER" {
+            return Some(raw[message.header.clone()].to_vec());
+        }
+        if upper == "TEXT" {
+            return Some(raw[message.body.clone()].to_vec());
+        }
+        let (exclude, list) = if let Some(list) = upper.strip_prefix("HEADER.FIELDS.NOT") {
+            (true, list)
+        } else if let Some(list) = upper.strip_prefix("HEADER.FIELDS") {
+            (false, list)
+        } else {
+            return None;
+        };
+        let names = parse_field_names(list)?;
+        Some(filter_header(&raw[message.header.clone()], &names, exclude))
+    }
+}
+
+/// Split a section into its part numbers and the remaining text specifier
+fn parse_section_path(section: &str) -> Option<(Vec<usize>, &str)> {
+    let mut path = Vec::new();
+    let mut rest = section;
+    loop {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            break;
+        }
+        let number: usize = rest[..digits].parse().ok()?;
+        if number == 0 {
+            return None;
+        }
+        path.push(number);
+        rest = &rest[digits..];
+        match rest.strip_prefix('.') {
+            Some(next) => rest = next,
+            None if rest.is_empty() => break,
+            None => return None,
+        }
+    }
+    Some((path, rest))
+}
+
+/// Parse the parenthesized field name list of HEADER.FIELDS
+fn parse_field_names(list: &str) -> Option<Vec<String>> {
+    let inner = list.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let names: Vec<String> = inner
+        .split_whitespace()
+        .map(|name| name.trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    (!names.is_empty()).then_some(names)
+}
+
+/// Header fields (with their original folding) kept or dropped by name,
+/// followed by the blank line that ends a header
+fn filter_header(header: &[u8], names: &[String], exclude: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut keep = false;
+    for line in header.split_inclusive(|&b| b == b'\n') {
+        if matches!(line, b"\r\n" | b"\n") {
+            break;
+        }
+        if !matches!(line.first(), Some(b' ' | b'\t')) {
+            let name = line
+                .iter()
+                .position(|&b| b == b':')
+                .map(|colon| String::from_utf8_lossy(&line[..colon]).trim().to_string());
+            keep = name
+                .is_some_and(|name| names.iter().any(|n| n.eq_ignore_ascii_case(&name)) != exclude);
+        }
+        if keep {
+            out.extend_from_slice(line);
+        }
+    }
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+/// Top-level header fields of a message, unfolded
+pub fn header_fields(raw: &[u8]) -> Vec<(String, String)> {
+    let (header, _) = split_header(raw, 0..raw.len());
+    parse_fields(&raw[header])
+}
+
+/// Split an entity into header (ending with the blank line) and body
+fn split_header(raw: &[u8], range: Range<usize>) -> (Range<usize>, Range<usize>) {
+    let mut pos = range.start;
+    while pos < range.end {
+        let line_end = raw[pos..range.end]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(range.end, |i| pos + i + 1);
+        if matches!(&raw[pos..line_end], b"\r\n" | b"\n") {
+            return (range.start..line_end, line_end..range.end);
+        }
+        pos = line_end;
+    }
+    (range.clone(), range.end..range.end)
+}
+
+/// Parse header fields, unfolding continuation lines
+fn parse_fields(header: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(header);
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    fields
+}
+
+/// Parse a Content-Type value into type, subtype and parameters
+fn parse_content_type(value: &str) -> Option<(String, String, Params)> {
+    let (media, params) = split_params(value);
+    let (media_type, media_subtype) = media.split_once('/')?;
+    let media_type = media_type.trim().to_ascii_lowercase();
+    let media_subtype = media_subtype.trim().to_ascii_lowercase();
+    if media_type.is_empty() || media_subtype.is_empty() {
+        return None;
+    }
+    Some((media_type, media_subtype, params))
+}
+
+/// Split a structured header value into its leading value and parameters
+fn split_params(value: &str) -> (String, Params) {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => {
+                current.push(c);
+                escaped = true;
+            }
+            '"' => {
+                current.push(c);
+                quoted = !quoted;
+            }
+            ';' if !quoted => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+
+    let mut segments = segments.into_iter();
+    let leading = segments.next().unwrap_or_default().trim().to_string();
+    let params = segments
+        .filter_map(|segment| {
+            let (name, value) = segment.split_once('=')?;
+            let name = name.trim().to_ascii_lowercase();
+            (!name.is_empty()).then(|| (name, unquote(value.trim())))
+        })
+        .collect();
+    (leading, params)
+}
+
+/// Remove the quotes and escapes of a quoted string
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => {
+            let mut out = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    if let Some(next) = chars.next() {
+                        out.push(next);
+                    }
+                } else {
+                    out.push(c);
+                }
+            }
+            out
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Ranges of the body parts between the boundary delimiters of a multipart body
+fn split_multipart(raw: &[u8], body: Range<usize>, boundary: &str) -> Vec<Range<usize>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut pos = body.start;
+
+    while pos < body.end {
+        let next = raw[pos..body.end]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(body.end, |i| pos + i + 1);
+        let line = raw[pos..next]
+            .strip_suffix(b"\n")
+            .unwrap_or(&raw[pos..next]);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if let Some(rest) = line.strip_prefix(delimiter.as_slice()) {
+            let closing = rest.starts_with(b"--");
+            let rest = if closing { &rest[2..] } else { rest };
+            if rest.iter().all(|b| matches!(b, b' ' | b'\t')) {
+                if let Some(start) = current.take() {
+                    // The line break before a delimiter belongs to the delimiter
+                    let end = if pos >= start + 2 && &raw[pos - 2..pos] == b"\r\n" {
+                        pos - 2
+                    } else if pos > start && raw[pos - 1] == b'\n' {
+                        pos - 1
+                    } else {
+                        pos
+                    };
+                    parts.push(start..end);
+                }
+                if closing {
+                    return parts;
+                }
+                current = Some(next);
+            }
+        }
+        pos = next;
+    }
+
+    if let Some(start) = current {
+        parts.push(start..body.end);
+    }
+    parts
+}
+
+/// Number of lines in a body
+fn count_lines(body: &[u8]) -> usize {
+    let newlines = body.iter().filter(|&&b| b == b'\n').count();
+    if body.last().is_some_and(|&b| b != b'\n') {
+        newlines + 1
+    } else {
+        newlines
+    }
+}
+
+/// Format a string or NIL
+fn nstring(value: Option<&str>) -> String {
+    value
+        .map(ImapResponse::format_string)
+        .unwrap_or_else(|| "NIL".to_string())
+}
+
+/// Format a parameter list, or NIL when empty
+fn param_list(params: &[(String, String)]) -> String {
+    if params.is_empty() {
+        return "NIL".to_string();
+    }
+    let items: Vec<String> = params
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{} {}",
+                nstring(Some(&name.to_uppercase())),
+                nstring(Some(value))
+            )
+        })
+        .collect();
+    format!("({})", items.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MULTIPART: &str = "From: alice@example.com\r\n\
+        Subject: Report\r\n\
+        Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+        \r\n\
+        preamble\r\n\
+        --outer\r\n\
+        Content-Type: multipart/alternative; boundary=inner\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        Hello\r\n\
+        world\r\n\
+        --inner\r\n\
+        Content-Type: text/html\r\n\
+        \r\n\
+        <p>Hello</p>\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: application/pdf; name=\"report.pdf\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+        \r\n\
+        JVBERi0=\r\n\
+        --outer\r\n\
+        Content-Type: message/rfc822\r\n\
+        \r\n\
+        From: bob@example.com\r\n\
+        Subject: Original\r\n\
+        \r\n\
+        Forwarded body\r\n\
+        --outer--\r\n";
+
+    #[test]
+    fn test_parse_multipart() {
+        let raw = MULTIPART.as_bytes();
+        let mime = MimePart::parse(raw);
+        assert_eq!(mime.media_type, "multipart");
+        assert_eq!(mime.parts.len(), 3);
+        assert_eq!(mime.parts[0].parts.len(), 2);
+        assert_eq!(mime.parts[0].parts[0].param("charset"), Some("utf-8"));
+        assert_eq!(&raw[mime.parts[0].parts[1].body.clone()], b"<p>Hello</p>");
+        assert_eq!(mime.parts[1].param("name"), Some("report.pdf"));
+        let message = mime.parts[2].message.as_ref().unwrap();
+        assert_eq!(message.field("Subject"), Some("Original"));
+    }
+
+    #[test]
+    fn test_sections() {
+        let raw = MULTIPART.as_bytes();
+        let mime = MimePart::parse(raw);
+        let section = |s: &str| mime.section(raw, s).map(|b| String::from_utf8(b).unwrap());
+
+        assert_eq!(section("").unwrap(), MULTIPART);
+        assert_eq!(section("1.1").unwrap(), "Hello\r\nworld");
+        assert_eq!(section("2").unwrap(), "JVBERi0=");
+        assert!(section("2.MIME")
+            .unwrap()
+            .starts_with("Content-Type: application/pdf"));
+        assert_eq!(
+            section("3.HEADER").unwrap(),
+            "From: bob@example.com\r\nSubject: Original\r\n\r\n"
+        );
+        assert_eq!(section("3.TEXT").unwrap(), "Forwarded body");
+        assert_eq!(section("3.1").unwrap(), "Forwarded body");
+        assert_eq!(
+            section("HEADER.FIELDS (SUBJECT FROM)").unwrap(),
+            "From: alice@example.com\r\nSubject: Report\r\n\r\n"
+        );
+        assert_eq!(
+            section("HEADER.FIELDS.NOT (CONTENT-TYPE FROM)").unwrap(),
+            "Subject: Report\r\n\r\n"
+        );
+        assert_eq!(section("4"), None);
+        assert_eq!(section("2.HEADER"), None);
+        assert_eq!(section("0"), None);
+    }
+
+    #[test]
+    fn test_single_part_sections() {
+        let raw = b"Subject: Hi\r\n\r\nBody text\r\n";
+        let mime = MimePart::parse(raw);
+        assert_eq!(mime.section(raw, "1").unwrap(), b"Body text\r\n");
+        assert_eq!(mime.section(raw, "TEXT").unwrap(), b"Body text\r\n");
+        assert_eq!(mime.section(raw, "HEADER").unwrap(), b"Subject: Hi\r\n\r\n");
+        assert_eq!(mime.section(raw, "2"), None);
+    }
+
+    #[test]
+    fn test_body_structure() {
+        let raw = MULTIPART.as_bytes();
+        let mime = MimePart::parse(raw);
+
+        let text = mime.parts[0].parts[0].body_structure(raw, false);
+        assert_eq!(
+            text,
+            "(\"TEXT\" \"PLAIN\" (\"CHARSET\" \"utf-8\") NIL NIL \"7BIT\" 12 2)"
+        );
+
+        let html = mime.parts[0].parts[1].body_structure(raw, true);
+        assert_eq!(
+            html,
+            "(\"TEXT\" \"HTML\" (\"CHARSET\" \"us-ascii\") NIL NIL \"7BIT\" 12 1 NIL NIL NIL NIL)"
+        );
+
+        let pdf = mime.parts[1].body_structure(raw, true);
+        assert_eq!(
+            pdf,
+            "(\"APPLICATION\" \"PDF\" (\"NAME\" \"report.pdf\") NIL NIL \"BASE64\" 8 NIL \
+             (\"ATTACHMENT\" (\"FILENAME\" \"report.pdf\")) NIL NIL)"
+        );
+
+        let structure = mime.body_structure(raw, true);
+        assert!(structure.starts_with("(((\"TEXT\" \"PLAIN\""));
+        assert!(structure.contains(" \"ALTERNATIVE\" (\"BOUNDARY\" \"inner\") NIL NIL NIL)"));
+        assert!(structure.contains("(\"MESSAGE\" \"RFC822\" NIL NIL NIL \"7BIT\" 58 ("));
+        assert!(structure.ends_with(" \"MIXED\" (\"BOUNDARY\" \"outer\") NIL NIL NIL)"));
+    }
+
+    #[test]
+    fn test_missing_boundary_is_leaf() {
+        let raw = b"Content-Type: multipart/mixed\r\n\r\nno parts here\r\n";
+        let mime = MimePart::parse(raw);
+        assert!(!mime.is_multipart());
+        assert_eq!(mime.media_type, "text");
+    }
+}
@@ -5,18 +5,27 @@
 //!
 //! Supported commands:
 //! - CAPABILITY, NOOP, LOGOUT
-//! - LOGIN, AUTHENTICATE (PLAIN)
+//! - LOGIN, AUTHENTICATE (PLAIN, LOGIN, OAUTHBEARER, XOAUTH2)
 //! - LIST, LSUB, SELECT, EXAMINE, STATUS
-//! - FETCH, SEARCH
+//! - FETCH (with MIME BODYSTRUCTURE and BODY[section]), SEARCH
 //! - CLOSE, CHECK
 //! - CREATE, DELETE, RENAME, SUBSCRIBE, UNSUBSCRIBE (mailbox management)
 //! - STORE, COPY, MOVE, EXPUNGE, APPEND (message operations)
-//! - IDLE, NAMESPACE (extensions)
+//! - IDLE, NAMESPACE, GETMETADATA, SETMETADATA, COMPRESS=DEFLATE (extensions)
+//! - SETACL, DELETEACL, GETACL, LISTRIGHTS, MYRIGHTS (shared mailboxes)
+//! - SPECIAL-USE folder attributes and modified UTF-7 mailbox names
+//! - Folder hierarchy with the "/" delimiter and CHILDREN attributes
 
+pub mod acl;
 pub mod command;
+pub mod compress;
+pub mod mime;
 pub mod parser;
 pub mod response;
+pub mod sasl;
+pub mod search;
 pub mod server;
 pub mod session;
+pub mod utf7;
 
 pub use server::{ImapConfig, ImapServer};
@@ -3,13 +3,43 @@
 //! Parses IMAP4 commands from client input.
 
 use super::command::{
-    FetchItem, ImapCommand, SearchCriteria, SequenceSet, StoreFlags, StoreOperation, TaggedCommand,
+    FetchItem, ImapCommand, MetadataDepth, MetadataOptions, SearchCriteria, SequenceSet,
+    StoreFlags, StoreOperation, TaggedCommand,
 };
+use super::utf7;
+use chrono::NaiveDate;
+use std::iter::Peekable;
+use std::vec::IntoIter;
 use tracing::debug;
 
 /// IMAP command parser
 pub struct ImapParser;
 
+/// Literal announced by a `{n}` or `{n+}` marker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiteralMarker {
+    /// Octets that follow the marker line
+    pub len: usize,
+    /// `{n}` waits for a continuation request; `{n+}` (LITERAL-) does not
+    pub synchronizing: bool,
+}
+
+/// Token of SEARCH arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SearchToken {
+    Open,
+    Close,
+    /// Atom or quoted string
+    Word(String),
+}
+
+type SearchTokens = Peekable<IntoIter<SearchToken>>;
+
+/// Quote a string for the line parser
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 impl ImapParser {
     /// Parse an IMAP command line
     pub fn parse(line: &str) -> Option<TaggedCommand> {
@@ -28,11 +58,114 @@ impl ImapParser {
         let rest = if parts.len() > 1 { parts[1].trim() } else { "" };
 
         // Parse command
-        let command = Self::parse_command(rest)?;
+        let mut command = Self::parse_command(rest)?;
+        Self::decode_mailbox_names(&mut command);
 
         Some(TaggedCommand { tag, command })
     }
 
+    /// Convert modified UTF-7 mailbox names to UTF-8
+    fn decode_mailbox_names(command: &mut ImapCommand) {
+        let names: Vec<&mut String> = match command {
+            ImapCommand::Select { mailbox }
+            | ImapCommand::Examine { mailbox }
+            | ImapCommand::Create { mailbox }
+            | ImapCommand::Delete { mailbox }
+            | ImapCommand::Subscribe { mailbox }
+            | ImapCommand::Unsubscribe { mailbox }
+            | ImapCommand::Status { mailbox, .. }
+            | ImapCommand::Append { mailbox, .. }
+            | ImapCommand::Copy { mailbox, .. }
+            | ImapCommand::Move { mailbox, .. }
+            | ImapCommand::GetMetadata { mailbox, .. }
+            | ImapCommand::SetMetadata { mailbox, .. }
+            | ImapCommand::SetAcl { mailbox, .. }
+            | ImapCommand::DeleteAcl { mailbox, .. }
+            | ImapCommand::GetAcl { mailbox }
+            | ImapCommand::ListRights { mailbox, .. }
+            | ImapCommand::MyRights { mailbox } => vec![mailbox],
+            ImapCommand::Rename {
+                old_mailbox,
+                new_mailbox,
+            } => vec![old_mailbox, new_mailbox],
+            ImapCommand::List { reference, pattern } | ImapCommand::Lsub { reference, pattern } => {
+                vec![reference, pattern]
+            }
+            _ => Vec::new(),
+        };
+        for name in names {
+            *name = utf7::decode(name);
+        }
+    }
+
+    /// Literal announced at the end of a command line (CRLF removed)
+    pub fn literal_marker(line: &[u8]) -> Option<LiteralMarker> {
+        let inner = line.strip_suffix(b"}")?;
+        let start = inner.iter().rposition(|&b| b == b'{')?;
+        let spec = &inner[start + 1..];
+        let (digits, synchronizing) = match spec.strip_suffix(b"+") {
+            Some(digits) => (digits, false),
+            None => (spec, true),
+        };
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let len = std::str::from_utf8(digits).ok()?.parse().ok()?;
+        Some(LiteralMarker { len, synchronizing })
+    }
+
+    /// Parse a complete command whose lines may end in literals
+    /// (`{n}\r\n` or `{n+}\r\n` followed by n octets)
+    ///
+    /// Literal arguments are handed to the line parser as quoted strings,
+    /// except the message literal of APPEND, which is kept as raw octets.
+    pub fn parse_bytes(input: &[u8]) -> Option<TaggedCommand> {
+        let mut text = String::new();
+        let mut literals = Vec::new();
+        let mut rest = input;
+
+        loop {
+            let (line, after) = match rest.iter().position(|&b| b == b'\n') {
+                Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+                None => (rest, &rest[rest.len()..]),
+            };
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+            let Some(marker) = Self::literal_marker(line) else {
+                text.push_str(&String::from_utf8_lossy(line));
+                break;
+            };
+            let start = line.iter().rposition(|&b| b == b'{').unwrap_or(0);
+            text.push_str(&String::from_utf8_lossy(&line[..start]));
+            if after.len() < marker.len {
+                return None;
+            }
+            literals.push((text.len(), &after[..marker.len]));
+            rest = &after[marker.len..];
+        }
+
+        // The last literal of APPEND is the message itself
+        let is_append = text
+            .split_whitespace()
+            .nth(1)
+            .map(|name| name.eq_ignore_ascii_case("APPEND"))
+            .unwrap_or(false);
+        let message = if is_append { literals.pop() } else { None };
+
+        // Splice the remaining literals back in as quoted strings
+        for (pos, literal) in literals.into_iter().rev() {
+            text.insert_str(pos, &quote(&String::from_utf8_lossy(literal)));
+        }
+
+        let mut cmd = Self::parse(&text)?;
+        if let (ImapCommand::Append { message: body, .. }, Some((_, literal))) =
+            (&mut cmd.command, message)
+        {
+            *body = literal.to_vec();
+        }
+        Some(cmd)
+    }
+
     /// Parse the command portion
     fn parse_command(input: &str) -> Option<ImapCommand> {
         let parts: Vec<&str> = input.splitn(2, ' ').collect();
@@ -90,6 +223,32 @@ impl ImapParser {
             "IDLE" => Some(ImapCommand::Idle),
             "DONE" => Some(ImapCommand::Done),
             "NAMESPACE" => Some(ImapCommand::Namespace),
+            "COMPRESS" => Some(ImapCommand::Compress {
+                mechanism: args.split_whitespace().next()?.to_uppercase(),
+            }),
+            "GETMETADATA" => Self::parse_getmetadata(args),
+            "SETMETADATA" => Self::parse_setmetadata(args),
+            "SETACL" => Self::parse_setacl(args),
+            "DELETEACL" => {
+                let (mailbox, identifier) = Self::parse_mailbox_identifier(args)?;
+                Some(ImapCommand::DeleteAcl {
+                    mailbox,
+                    identifier,
+                })
+            }
+            "GETACL" => Some(ImapCommand::GetAcl {
+                mailbox: Self::parse_astring(args)?.0,
+            }),
+            "LISTRIGHTS" => {
+                let (mailbox, identifier) = Self::parse_mailbox_identifier(args)?;
+                Some(ImapCommand::ListRights {
+                    mailbox,
+                    identifier,
+                })
+            }
+            "MYRIGHTS" => Some(ImapCommand::MyRights {
+                mailbox: Self::parse_astring(args)?.0,
+            }),
 
             _ => Some(ImapCommand::Unknown { command: cmd_name }),
         }
@@ -172,90 +331,144 @@ impl ImapParser {
         Some(ImapCommand::Search { criteria, uid })
     }
 
-    /// Parse search criteria
+    /// Parse search criteria: search keys that must all match, optionally
+    /// preceded by CHARSET
     fn parse_search_criteria(args: &str) -> Option<SearchCriteria> {
-        let args = args.trim();
-        if args.is_empty() {
-            return Some(SearchCriteria::All);
-        }
-
-        let args_upper = args.to_uppercase();
-
-        // Simple criteria
-        match args_upper.as_str() {
-            "ALL" => return Some(SearchCriteria::All),
-            "ANSWERED" => return Some(SearchCriteria::Answered),
-            "DELETED" => return Some(SearchCriteria::Deleted),
-            "DRAFT" => return Some(SearchCriteria::Draft),
-            "FLAGGED" => return Some(SearchCriteria::Flagged),
-            "NEW" => return Some(SearchCriteria::New),
-            "OLD" => return Some(SearchCriteria::Old),
-            "RECENT" => return Some(SearchCriteria::Recent),
-            "SEEN" => return Some(SearchCriteria::Seen),
-            "UNANSWERED" => return Some(SearchCriteria::Unanswered),
-            "UNDELETED" => return Some(SearchCriteria::Undeleted),
-            "UNDRAFT" => return Some(SearchCriteria::Undraft),
-            "UNFLAGGED" => return Some(SearchCriteria::Unflagged),
-            "UNSEEN" => return Some(SearchCriteria::Unseen),
-            _ => {}
-        }
-
-        // Check for criteria with arguments
-        let parts: Vec<&str> = args.splitn(2, ' ').collect();
-        let key = parts[0].to_uppercase();
-        let value = if parts.len() > 1 { parts[1] } else { "" };
+        let mut tokens = Self::search_tokens(args)?.into_iter().peekable();
 
-        match key.as_str() {
-            "BCC" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::Bcc(s))
-            }
-            "BEFORE" => Some(SearchCriteria::Before(value.to_string())),
-            "BODY" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::Body(s))
-            }
-            "CC" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::Cc(s))
-            }
-            "FROM" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::From(s))
-            }
-            "LARGER" => Some(SearchCriteria::Larger(value.parse().ok()?)),
-            "ON" => Some(SearchCriteria::On(value.to_string())),
-            "SINCE" => Some(SearchCriteria::Since(value.to_string())),
-            "SMALLER" => Some(SearchCriteria::Smaller(value.parse().ok()?)),
-            "SUBJECT" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::Subject(s))
-            }
-            "TEXT" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::Text(s))
+        if matches!(tokens.peek(), Some(SearchToken::Word(w)) if w.eq_ignore_ascii_case("CHARSET"))
+        {
+            tokens.next();
+            // Strings are matched as UTF-8, which covers US-ASCII
+            let charset = Self::search_arg(&mut tokens)?;
+            if !charset.eq_ignore_ascii_case("UTF-8") && !charset.eq_ignore_ascii_case("US-ASCII") {
+                return None;
             }
-            "TO" => {
-                let (s, _) = Self::parse_astring(value)?;
-                Some(SearchCriteria::To(s))
+        }
+
+        let mut keys = Vec::new();
+        while tokens.peek().is_some() {
+            keys.push(Self::parse_search_key(&mut tokens)?);
+        }
+        Some(Self::all_of(keys))
+    }
+
+    /// Parse one search key, including the keys it takes as arguments
+    fn parse_search_key(tokens: &mut SearchTokens) -> Option<SearchCriteria> {
+        let key = match tokens.next()? {
+            SearchToken::Word(word) => word,
+            SearchToken::Open => {
+                let mut keys = Vec::new();
+                while tokens.peek()? != &SearchToken::Close {
+                    keys.push(Self::parse_search_key(tokens)?);
+                }
+                tokens.next();
+                if keys.is_empty() {
+                    return None;
+                }
+                return Some(Self::all_of(keys));
             }
-            "NOT" => {
-                let inner = Self::parse_search_criteria(value)?;
-                Some(SearchCriteria::Not(Box::new(inner)))
+            SearchToken::Close => return None,
+        };
+
+        let criteria = match key.to_uppercase().as_str() {
+            "ALL" => SearchCriteria::All,
+            "ANSWERED" => SearchCriteria::Answered,
+            "DELETED" => SearchCriteria::Deleted,
+            "DRAFT" => SearchCriteria::Draft,
+            "FLAGGED" => SearchCriteria::Flagged,
+            "NEW" => SearchCriteria::New,
+            "OLD" => SearchCriteria::Old,
+            "RECENT" => SearchCriteria::Recent,
+            "SEEN" => SearchCriteria::Seen,
+            "UNANSWERED" => SearchCriteria::Unanswered,
+            "UNDELETED" => SearchCriteria::Undeleted,
+            "UNDRAFT" => SearchCriteria::Undraft,
+            "UNFLAGGED" => SearchCriteria::Unflagged,
+            "UNSEEN" => SearchCriteria::Unseen,
+            "BCC" => SearchCriteria::Bcc(Self::search_arg(tokens)?),
+            "BEFORE" => SearchCriteria::Before(Self::search_date(tokens)?),
+            "BODY" => SearchCriteria::Body(Self::search_arg(tokens)?),
+            "CC" => SearchCriteria::Cc(Self::search_arg(tokens)?),
+            "FROM" => SearchCriteria::From(Self::search_arg(tokens)?),
+            "HEADER" => {
+                let name = Self::search_arg(tokens)?;
+                SearchCriteria::Header(name, Self::search_arg(tokens)?)
             }
-            "UID" => {
-                let seq = SequenceSet::parse(value)?;
-                Some(SearchCriteria::Uid(seq))
+            "KEYWORD" => SearchCriteria::Keyword(Self::search_arg(tokens)?),
+            "LARGER" => SearchCriteria::Larger(Self::search_arg(tokens)?.parse().ok()?),
+            "NOT" => SearchCriteria::Not(Box::new(Self::parse_search_key(tokens)?)),
+            "ON" => SearchCriteria::On(Self::search_date(tokens)?),
+            "OR" => {
+                let a = Self::parse_search_key(tokens)?;
+                SearchCriteria::Or(Box::new(a), Box::new(Self::parse_search_key(tokens)?))
             }
-            _ => {
-                // Try to parse as sequence set
-                if let Some(seq) = SequenceSet::parse(&key) {
-                    Some(SearchCriteria::SequenceSet(seq))
-                } else {
-                    Some(SearchCriteria::All)
+            "SENTBEFORE" => SearchCriteria::SentBefore(Self::search_date(tokens)?),
+            "SENTON" => SearchCriteria::SentOn(Self::search_date(tokens)?),
+            "SENTSINCE" => SearchCriteria::SentSince(Self::search_date(tokens)?),
+            "SINCE" => SearchCriteria::Since(Self::search_date(tokens)?),
+            "SMALLER" => SearchCriteria::Smaller(Self::search_arg(tokens)?.parse().ok()?),
+            "SUBJECT" => SearchCriteria::Subject(Self::search_arg(tokens)?),
+            "TEXT" => SearchCriteria::Text(Self::search_arg(tokens)?),
+            "TO" => SearchCriteria::To(Self::search_arg(tokens)?),
+            "UID" => SearchCriteria::Uid(SequenceSet::parse(&Self::search_arg(tokens)?)?),
+            "UNKEYWORD" => SearchCriteria::Unkeyword(Self::search_arg(tokens)?),
+            // Anything else has to be a sequence set; unknown keys are refused
+            _ => SearchCriteria::SequenceSet(SequenceSet::parse(&key)?),
+        };
+        Some(criteria)
+    }
+
+    /// Keys that must all match
+    fn all_of(mut keys: Vec<SearchCriteria>) -> SearchCriteria {
+        match keys.len() {
+            0 => SearchCriteria::All,
+            1 => keys.remove(0),
+            _ => SearchCriteria::And(keys),
+        }
+    }
+
+    /// The string argument of a search key
+    fn search_arg(tokens: &mut SearchTokens) -> Option<String> {
+        match tokens.next()? {
+            SearchToken::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// The date argument of a search key (`1-Feb-1994`)
+    fn search_date(tokens: &mut SearchTokens) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&Self::search_arg(tokens)?, "%d-%b-%Y").ok()
+    }
+
+    /// Split SEARCH arguments into parentheses, atoms and quoted strings
+    fn search_tokens(args: &str) -> Option<Vec<SearchToken>> {
+        let mut tokens = Vec::new();
+        let mut rest = args.trim_start();
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '(' => {
+                    tokens.push(SearchToken::Open);
+                    rest = &rest[1..];
+                }
+                ')' => {
+                    tokens.push(SearchToken::Close);
+                    rest = &rest[1..];
+                }
+                '"' => {
+                    let (word, remaining) = Self::parse_astring(rest)?;
+                    tokens.push(SearchToken::Word(word));
+                    rest = remaining;
+                }
+                _ => {
+                    let end = rest.find([' ', '(', ')']).unwrap_or(rest.len());
+                    tokens.push(SearchToken::Word(rest[..end].to_string()));
+                    rest = &rest[end..];
                 }
             }
+            rest = rest.trim_start();
         }
+        Some(tokens)
     }
 
     /// Parse UID FETCH/SEARCH/STORE/COPY/MOVE commands
@@ -423,9 +636,7 @@ impl ImapParser {
             remaining = rest.trim();
         }
 
-        // The rest should be a literal marker like {1234}
-        // The actual message bytes will be handled by the server after sending continuation
-        // For now, we create the command with empty message - server will populate it
+        // The message literal is filled in by `parse_bytes`
         Some(ImapCommand::Append {
             mailbox,
             flags,
@@ -434,6 +645,122 @@ impl ImapParser {
         })
     }
 
+    /// Parse GETMETADATA command
+    fn parse_getmetadata(args: &str) -> Option<ImapCommand> {
+        // GETMETADATA [(MAXSIZE n DEPTH d)] mailbox (entry ...) | entry
+        let mut rest = args.trim();
+        let mut options = MetadataOptions::default();
+
+        if let Some(inner) = rest.strip_prefix('(') {
+            let end = inner.find(')')?;
+            let mut tokens = inner[..end].split_whitespace();
+            while let Some(name) = tokens.next() {
+                let value = tokens.next()?;
+                match name.to_uppercase().as_str() {
+                    "MAXSIZE" => options.maxsize = Some(value.parse().ok()?),
+                    "DEPTH" => {
+                        options.depth = match value.to_lowercase().as_str() {
+                            "0" => MetadataDepth::Zero,
+                            "1" => MetadataDepth::One,
+                            "infinity" => MetadataDepth::Infinity,
+                            _ => return None,
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            rest = inner[end + 1..].trim();
+        }
+
+        let (mailbox, rest) = Self::parse_astring(rest)?;
+        let rest = rest.trim();
+        let entries = match rest.strip_prefix('(') {
+            Some(list) => Self::parse_astring_list(list.strip_suffix(')')?)?,
+            None => vec![Self::parse_astring(rest)?.0],
+        };
+        if entries.is_empty() || entries.iter().any(|e| e.is_empty()) {
+            return None;
+        }
+
+        Some(ImapCommand::GetMetadata {
+            mailbox,
+            entries,
+            options,
+        })
+    }
+
+    /// Parse SETMETADATA command
+    fn parse_setmetadata(args: &str) -> Option<ImapCommand> {
+        // SETMETADATA mailbox (entry value entry NIL ...)
+        let (mailbox, rest) = Self::parse_astring(args)?;
+        let mut list = rest.trim().strip_prefix('(')?.strip_suffix(')')?.trim();
+
+        let mut entries = Vec::new();
+        while !list.is_empty() {
+            let (entry, rest) = Self::parse_astring(list)?;
+            let rest = rest.trim_start();
+            if rest.is_empty() || entry.is_empty() {
+                return None;
+            }
+
+            let is_nil = rest.get(..3).is_some_and(|v| v.eq_ignore_ascii_case("NIL"))
+                && (rest[3..].is_empty() || rest[3..].starts_with(' '));
+            let (value, rest) = if is_nil {
+                (None, &rest[3..])
+            } else {
+                let (value, rest) = Self::parse_astring(rest)?;
+                (Some(value), rest)
+            };
+
+            entries.push((entry, value));
+            list = rest.trim_start();
+        }
+        if entries.is_empty() {
+            return None;
+        }
+
+        Some(ImapCommand::SetMetadata { mailbox, entries })
+    }
+
+    /// Parse SETACL command
+    fn parse_setacl(args: &str) -> Option<ImapCommand> {
+        // SETACL mailbox identifier rights
+        let (mailbox, rest) = Self::parse_astring(args)?;
+        let (identifier, rest) = Self::parse_astring(rest)?;
+        let (rights, _) = Self::parse_astring(rest)?;
+        if identifier.is_empty() {
+            return None;
+        }
+
+        Some(ImapCommand::SetAcl {
+            mailbox,
+            identifier,
+            rights,
+        })
+    }
+
+    /// Parse the `mailbox identifier` arguments of DELETEACL and LISTRIGHTS
+    fn parse_mailbox_identifier(args: &str) -> Option<(String, String)> {
+        let (mailbox, rest) = Self::parse_astring(args)?;
+        let (identifier, _) = Self::parse_astring(rest)?;
+        if identifier.is_empty() {
+            return None;
+        }
+        Some((mailbox, identifier))
+    }
+
+    /// Parse a space-separated list of astrings (without the parentheses)
+    fn parse_astring_list(s: &str) -> Option<Vec<String>> {
+        let mut items = Vec::new();
+        let mut rest = s.trim();
+        while !rest.is_empty() {
+            let (item, remaining) = Self::parse_astring(rest)?;
+            items.push(item);
+            rest = remaining.trim_start();
+        }
+        Some(items)
+    }
+
     /// Parse mailbox name
     fn parse_mailbox(s: &str) -> String {
         let s = s.trim();
@@ -451,26 +778,25 @@ impl ImapParser {
 
         if s.starts_with('"') {
             // Quoted string
-            let mut chars = s.chars().skip(1);
             let mut result = String::new();
             let mut escaped = false;
-            let mut pos = 1;
+            let mut end = s.len();
 
-            for c in chars {
-                pos += 1;
+            for (i, c) in s.char_indices().skip(1) {
                 if escaped {
                     result.push(c);
                     escaped = false;
                 } else if c == '\\' {
                     escaped = true;
                 } else if c == '"' {
+                    end = i + 1;
                     break;
                 } else {
                     result.push(c);
                 }
             }
 
-            Some((result, &s[pos..]))
+            Some((result, &s[end..]))
         } else {
             // Atom (space-delimited)
             let end = s.find(' ').unwrap_or(s.len());
@@ -513,6 +839,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_compress() {
+        let cmd = ImapParser::parse("A010 COMPRESS deflate").unwrap();
+        if let ImapCommand::Compress { mechanism } = cmd.command {
+            assert_eq!(mechanism, "DEFLATE");
+        } else {
+            panic!("Expected COMPRESS command");
+        }
+        assert!(ImapParser::parse("A011 COMPRESS").is_none());
+    }
+
+    #[test]
+    fn test_literal_marker() {
+        assert_eq!(
+            ImapParser::literal_marker(b"A1 LOGIN {4}"),
+            Some(LiteralMarker {
+                len: 4,
+                synchronizing: true
+            })
+        );
+        assert_eq!(
+            ImapParser::literal_marker(b"A1 APPEND INBOX {310+}"),
+            Some(LiteralMarker {
+                len: 310,
+                synchronizing: false
+            })
+        );
+        assert_eq!(ImapParser::literal_marker(b"A1 LOGIN user pass"), None);
+        assert_eq!(ImapParser::literal_marker(b"A1 LOGIN {}"), None);
+        assert_eq!(ImapParser::literal_marker(b"A1 LOGIN {+}"), None);
+        assert_eq!(ImapParser::literal_marker(b"A1 LOGIN {x}"), None);
+    }
+
+    #[test]
+    fn test_parse_bytes_login_literals() {
+        let cmd = ImapParser::parse_bytes(b"A1 LOGIN {4}\r\nuser {9+}\r\np\"a\\s s w\r\n").unwrap();
+        assert_eq!(cmd.tag, "A1");
+        if let ImapCommand::Login { username, password } = cmd.command {
+            assert_eq!(username, "user");
+            assert_eq!(password, "p\"a\\s s w");
+        } else {
+            panic!("Expected LOGIN command");
+        }
+
+        // Truncated literal
+        assert!(ImapParser::parse_bytes(b"A2 LOGIN {10}\r\nuser").is_none());
+    }
+
+    #[test]
+    fn test_parse_bytes_append() {
+        let message = b"Subject: draft\r\n\r\n\xff body {3}\r\n";
+        let mut input = format!(
+            "A3 APPEND {{6}}\r\nDrafts (\\Draft) {{{}}}\r\n",
+            message.len()
+        )
+        .into_bytes();
+        input.extend_from_slice(message);
+        input.extend_from_slice(b"\r\n");
+
+        let cmd = ImapParser::parse_bytes(&input).unwrap();
+        if let ImapCommand::Append {
+            mailbox,
+            flags,
+            message: body,
+            ..
+        } = cmd.command
+        {
+            assert_eq!(mailbox, "Drafts");
+            assert_eq!(flags, vec!["\\Draft"]);
+            assert_eq!(body, message);
+        } else {
+            panic!("Expected APPEND command");
+        }
+
+        // Commands without literals parse as before
+        let cmd = ImapParser::parse_bytes(b"A4 SELECT INBOX\r\n").unwrap();
+        assert!(matches!(cmd.command, ImapCommand::Select { ref mailbox } if mailbox == "INBOX"));
+    }
+
     #[test]
     fn test_parse_select() {
         let cmd = ImapParser::parse("A003 SELECT INBOX").unwrap();
@@ -567,6 +972,132 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_search_keyword() {
+        let cmd = ImapParser::parse("A006 UID SEARCH KEYWORD $Phishing").unwrap();
+        if let ImapCommand::Search { criteria, uid } = cmd.command {
+            assert!(uid);
+            assert_eq!(criteria, SearchCriteria::Keyword("$Phishing".to_string()));
+        } else {
+            panic!("Expected SEARCH command");
+        }
+
+        let cmd = ImapParser::parse("A007 SEARCH UNKEYWORD $Junk").unwrap();
+        assert!(matches!(
+            cmd.command,
+            ImapCommand::Search { criteria: SearchCriteria::Unkeyword(ref k), .. } if k == "$Junk"
+        ));
+    }
+
+    /// Criteria of a SEARCH command line
+    fn search_criteria(line: &str) -> Option<SearchCriteria> {
+        match ImapParser::parse(line)?.command {
+            ImapCommand::Search { criteria, .. } => Some(criteria),
+            _ => panic!("Expected SEARCH command"),
+        }
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_parse_search_dates() {
+        assert_eq!(
+            search_criteria("A1 SEARCH BEFORE 1-Feb-1994"),
+            Some(SearchCriteria::Before(date(1994, 2, 1)))
+        );
+        assert_eq!(
+            search_criteria(r#"A1 SEARCH ON "07-JUL-2024""#),
+            Some(SearchCriteria::On(date(2024, 7, 7)))
+        );
+        assert_eq!(
+            search_criteria("A1 SEARCH SINCE 31-dec-2023"),
+            Some(SearchCriteria::Since(date(2023, 12, 31)))
+        );
+        assert_eq!(
+            search_criteria(
+                "A1 SEARCH SENTBEFORE 1-Mar-2024 SENTON 2-Mar-2024 SENTSINCE 3-Mar-2024"
+            ),
+            Some(SearchCriteria::And(vec![
+                SearchCriteria::SentBefore(date(2024, 3, 1)),
+                SearchCriteria::SentOn(date(2024, 3, 2)),
+                SearchCriteria::SentSince(date(2024, 3, 3)),
+            ]))
+        );
+        assert_eq!(search_criteria("A1 SEARCH SINCE 2024-03-01"), None);
+        assert_eq!(search_criteria("A1 SEARCH BEFORE 30-Feb-2024"), None);
+        assert_eq!(search_criteria("A1 SEARCH ON"), None);
+    }
+
+    #[test]
+    fn test_parse_search_header() {
+        assert_eq!(
+            search_criteria(r#"A1 SEARCH HEADER List-Id "dev team""#),
+            Some(SearchCriteria::Header(
+                "List-Id".to_string(),
+                "dev team".to_string()
+            ))
+        );
+        assert_eq!(
+            search_criteria(r#"A1 SEARCH HEADER X-Priority """#),
+            Some(SearchCriteria::Header(
+                "X-Priority".to_string(),
+                String::new()
+            ))
+        );
+        assert_eq!(search_criteria("A1 SEARCH HEADER X-Priority"), None);
+    }
+
+    #[test]
+    fn test_parse_search_uid_and_sequence() {
+        assert_eq!(
+            search_criteria("A1 UID SEARCH UID 5:*"),
+            Some(SearchCriteria::Uid(SequenceSet::Range(5, u32::MAX)))
+        );
+        assert_eq!(
+            search_criteria("A1 SEARCH UID *"),
+            Some(SearchCriteria::Uid(SequenceSet::Single(u32::MAX)))
+        );
+        assert_eq!(
+            search_criteria("A1 SEARCH 1:3,7 UNSEEN"),
+            Some(SearchCriteria::And(vec![
+                SearchCriteria::SequenceSet(SequenceSet::Multiple(vec![
+                    SequenceSet::Range(1, 3),
+                    SequenceSet::Single(7),
+                ])),
+                SearchCriteria::Unseen,
+            ]))
+        );
+        // Unknown keys are an error rather than matching everything
+        assert_eq!(search_criteria("A1 SEARCH BOGUS"), None);
+    }
+
+    #[test]
+    fn test_parse_search_combined() {
+        assert_eq!(
+            search_criteria(
+                r#"A1 SEARCH CHARSET UTF-8 OR (KEYWORD $Work UNSEEN) NOT FROM "bob" UNKEYWORD $Junk"#
+            ),
+            Some(SearchCriteria::And(vec![
+                SearchCriteria::Or(
+                    Box::new(SearchCriteria::And(vec![
+                        SearchCriteria::Keyword("$Work".to_string()),
+                        SearchCriteria::Unseen,
+                    ])),
+                    Box::new(SearchCriteria::Not(Box::new(SearchCriteria::From(
+                        "bob".to_string()
+                    )))),
+                ),
+                SearchCriteria::Unkeyword("$Junk".to_string()),
+            ]))
+        );
+        assert_eq!(search_criteria("A1 SEARCH"), Some(SearchCriteria::All));
+        assert_eq!(search_criteria("A1 SEARCH CHARSET KOI8-R ALL"), None);
+        assert_eq!(search_criteria("A1 SEARCH (SEEN"), None);
+        assert_eq!(search_criteria("A1 SEARCH ()"), None);
+    }
+
     #[test]
     fn test_parse_list() {
         let cmd = ImapParser::parse(r#"A007 LIST "" "*""#).unwrap();
@@ -577,4 +1108,123 @@ mod tests {
             panic!("Expected LIST command");
         }
     }
+
+    #[test]
+    fn test_parse_getmetadata() {
+        let cmd = ImapParser::parse(
+            r#"A008 GETMETADATA (MAXSIZE 1024 DEPTH infinity) "INBOX" (/private/comment /shared/vendor)"#,
+        )
+        .unwrap();
+        if let ImapCommand::GetMetadata {
+            mailbox,
+            entries,
+            options,
+        } = cmd.command
+        {
+            assert_eq!(mailbox, "INBOX");
+            assert_eq!(entries, vec!["/private/comment", "/shared/vendor"]);
+            assert_eq!(options.maxsize, Some(1024));
+            assert_eq!(options.depth, MetadataDepth::Infinity);
+        } else {
+            panic!("Expected GETMETADATA command");
+        }
+
+        let cmd = ImapParser::parse(r#"A009 GETMETADATA "" /shared/comment"#).unwrap();
+        if let ImapCommand::GetMetadata {
+            mailbox,
+            entries,
+            options,
+        } = cmd.command
+        {
+            assert_eq!(mailbox, "");
+            assert_eq!(entries, vec!["/shared/comment"]);
+            assert_eq!(options, MetadataOptions::default());
+        } else {
+            panic!("Expected GETMETADATA command");
+        }
+    }
+
+    #[test]
+    fn test_parse_setmetadata() {
+        let cmd = ImapParser::parse(
+            r#"A010 SETMETADATA INBOX (/private/comment "My \"own\" folder" /shared/color NIL)"#,
+        )
+        .unwrap();
+        if let ImapCommand::SetMetadata { mailbox, entries } = cmd.command {
+            assert_eq!(mailbox, "INBOX");
+            assert_eq!(
+                entries,
+                vec![
+                    (
+                        "/private/comment".to_string(),
+                        Some("My \"own\" folder".to_string())
+                    ),
+                    ("/shared/color".to_string(), None),
+                ]
+            );
+        } else {
+            panic!("Expected SETMETADATA command");
+        }
+
+        let cmd =
+            ImapParser::parse(r#"A011 SETMETADATA INBOX (/private/comment "日本語")"#).unwrap();
+        if let ImapCommand::SetMetadata { entries, .. } = cmd.command {
+            assert_eq!(entries[0].1.as_deref(), Some("日本語"));
+        } else {
+            panic!("Expected SETMETADATA command");
+        }
+    }
+
+    #[test]
+    fn test_parse_acl_commands() {
+        let cmd = ImapParser::parse(r#"A012 SETACL "Shared Folder" bob@example.com +lrs"#).unwrap();
+        if let ImapCommand::SetAcl {
+            mailbox,
+            identifier,
+            rights,
+        } = cmd.command
+        {
+            assert_eq!(mailbox, "Shared Folder");
+            assert_eq!(identifier, "bob@example.com");
+            assert_eq!(rights, "+lrs");
+        } else {
+            panic!("Expected SETACL command");
+        }
+
+        let cmd = ImapParser::parse("A013 DELETEACL INBOX bob@example.com").unwrap();
+        assert!(matches!(
+            cmd.command,
+            ImapCommand::DeleteAcl { ref identifier, .. } if identifier == "bob@example.com"
+        ));
+
+        let cmd = ImapParser::parse("A014 MYRIGHTS INBOX").unwrap();
+        assert!(matches!(cmd.command, ImapCommand::MyRights { ref mailbox } if mailbox == "INBOX"));
+
+        assert!(ImapParser::parse("A015 LISTRIGHTS INBOX").is_none());
+    }
+
+    #[test]
+    fn test_parse_utf7_mailbox_names() {
+        let cmd = ImapParser::parse("A016 SELECT &kAFP4W4IMH8-").unwrap();
+        assert!(
+            matches!(cmd.command, ImapCommand::Select { ref mailbox } if mailbox == "送信済み")
+        );
+
+        let cmd = ImapParser::parse(r#"A017 RENAME "Tom &- Jerry" Entw&APw-rfe"#).unwrap();
+        if let ImapCommand::Rename {
+            old_mailbox,
+            new_mailbox,
+        } = cmd.command
+        {
+            assert_eq!(old_mailbox, "Tom & Jerry");
+            assert_eq!(new_mailbox, "Entwürfe");
+        } else {
+            panic!("Expected RENAME command");
+        }
+
+        let cmd = ImapParser::parse("A018 UID COPY 1:3 &kAFP4W4IMH8-").unwrap();
+        assert!(
+            matches!(cmd.command, ImapCommand::Copy { ref mailbox, .. } if mailbox == "送信済み")
+        );
+    }
 }
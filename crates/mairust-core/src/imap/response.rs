@@ -2,6 +2,7 @@
 //!
 //! Generates IMAP4 response strings for client communication.
 
+use super::utf7;
 use chrono::{DateTime, Utc};
 
 /// IMAP response status
@@ -37,7 +38,16 @@ impl ImapResponse {
 
     /// Server greeting with optional STARTTLS capability
     pub fn greeting_with_starttls(starttls_enabled: bool) -> String {
-        let mut capabilities = vec!["IMAP4rev1", "LITERAL+", "SASL-IR", "LOGIN", "AUTH=PLAIN"];
+        let mut capabilities = vec![
+            "IMAP4rev1",
+            "LITERAL-",
+            "SASL-IR",
+            "LOGIN",
+            "AUTH=PLAIN",
+            "AUTH=LOGIN",
+            "AUTH=OAUTHBEARER",
+            "AUTH=XOAUTH2",
+        ];
         if starttls_enabled {
             capabilities.push("STARTTLS");
         }
@@ -63,6 +73,11 @@ impl ImapResponse {
         format!("{} BAD {}\r\n", tag, message)
     }
 
+    /// Untagged OK response with an ALERT the client must show to the user
+    pub fn alert(message: &str) -> String {
+        format!("* OK [ALERT] {}\r\n", message)
+    }
+
     /// Untagged BYE response
     pub fn bye(message: &str) -> String {
         format!("* BYE {}\r\n", message)
@@ -77,14 +92,23 @@ impl ImapResponse {
     pub fn capability_with_starttls(starttls_enabled: bool) -> String {
         let mut capabilities = vec![
             "IMAP4rev1",
-            "LITERAL+",
+            "LITERAL-",
             "SASL-IR",
             "LOGIN",
             "AUTH=PLAIN",
+            "AUTH=LOGIN",
+            "AUTH=OAUTHBEARER",
+            "AUTH=XOAUTH2",
             "IDLE",
             "NAMESPACE",
             "MOVE",
             "UIDPLUS",
+            "METADATA",
+            "ACL",
+            "RIGHTS=texk",
+            "COMPRESS=DEFLATE",
+            "SPECIAL-USE",
+            "CHILDREN",
         ];
         if starttls_enabled {
             capabilities.push("STARTTLS");
@@ -94,7 +118,7 @@ impl ImapResponse {
 
     /// Update CAPABILITY in greeting to include write operations
     pub fn greeting_full() -> String {
-        "* OK [CAPABILITY IMAP4rev1 LITERAL+ SASL-IR LOGIN AUTH=PLAIN IDLE MOVE UIDPLUS] MaiRust IMAP server ready\r\n".to_string()
+        "* OK [CAPABILITY IMAP4rev1 LITERAL- SASL-IR LOGIN AUTH=PLAIN AUTH=LOGIN AUTH=OAUTHBEARER AUTH=XOAUTH2 IDLE MOVE UIDPLUS METADATA ACL RIGHTS=texk COMPRESS=DEFLATE SPECIAL-USE CHILDREN] MaiRust IMAP server ready\r\n".to_string()
     }
 
     /// EXPUNGE response
@@ -114,15 +138,20 @@ impl ImapResponse {
     /// NAMESPACE response
     pub fn namespace() -> String {
         // Personal namespace, Other users namespace, Shared namespace
-        "* NAMESPACE ((\"\" \"/\")) NIL NIL\r\n".to_string()
+        "* NAMESPACE ((\"\" \"/\")) NIL ((\"Shared/\" \"/\"))\r\n".to_string()
     }
 
     /// LIST response for a mailbox
+    ///
+    /// Mailbox names in this and the other responses are UTF-8 and sent in
+    /// modified UTF-7.
     pub fn list(flags: &[&str], delimiter: &str, mailbox: &str) -> String {
         let flags_str = flags.join(" ");
         format!(
             "* LIST ({}) \"{}\" \"{}\"\r\n",
-            flags_str, delimiter, mailbox
+            flags_str,
+            delimiter,
+            Self::quote_string(&utf7::encode(mailbox))
         )
     }
 
@@ -131,7 +160,9 @@ impl ImapResponse {
         let flags_str = flags.join(" ");
         format!(
             "* LSUB ({}) \"{}\" \"{}\"\r\n",
-            flags_str, delimiter, mailbox
+            flags_str,
+            delimiter,
+            Self::quote_string(&utf7::encode(mailbox))
         )
     }
 
@@ -169,7 +200,11 @@ impl ImapResponse {
     /// STATUS response
     pub fn status(mailbox: &str, items: &[(String, u32)]) -> String {
         let items_str: Vec<String> = items.iter().map(|(k, v)| format!("{} {}", k, v)).collect();
-        format!("* STATUS \"{}\" ({})\r\n", mailbox, items_str.join(" "))
+        format!(
+            "* STATUS \"{}\" ({})\r\n",
+            Self::quote_string(&utf7::encode(mailbox)),
+            items_str.join(" ")
+        )
     }
 
     /// FETCH response
@@ -209,6 +244,7 @@ impl ImapResponse {
         flagged: bool,
         deleted: bool,
         draft: bool,
+        keywords: &[String],
     ) -> String {
         let mut flags = Vec::new();
         if seen {
@@ -226,6 +262,7 @@ impl ImapResponse {
         if draft {
             flags.push("\\Draft");
         }
+        flags.extend(keywords.iter().map(String::as_str));
         format!("({})", flags.join(" "))
     }
 
@@ -315,6 +352,78 @@ impl ImapResponse {
         None
     }
 
+    /// METADATA response (RFC 5464); `None` values are sent as NIL
+    pub fn metadata(mailbox: &str, entries: &[(String, Option<String>)]) -> String {
+        let items: Vec<String> = entries
+            .iter()
+            .map(|(entry, value)| {
+                let value = match value {
+                    Some(v) => Self::format_string(v),
+                    None => "NIL".to_string(),
+                };
+                format!("{} {}", Self::format_string(entry), value)
+            })
+            .collect();
+        format!(
+            "* METADATA \"{}\" ({})\r\n",
+            Self::quote_string(&utf7::encode(mailbox)),
+            items.join(" ")
+        )
+    }
+
+    /// ACL response (RFC 4314): identifier/rights pairs for a mailbox
+    pub fn acl(mailbox: &str, entries: &[(String, String)]) -> String {
+        let mut response = format!("* ACL {}", Self::format_string(&utf7::encode(mailbox)));
+        for (identifier, rights) in entries {
+            response.push_str(&format!(
+                " {} {}",
+                Self::format_string(identifier),
+                Self::format_string(rights)
+            ));
+        }
+        response.push_str("\r\n");
+        response
+    }
+
+    /// LISTRIGHTS response: required rights followed by optional right groups
+    pub fn listrights(
+        mailbox: &str,
+        identifier: &str,
+        required: &str,
+        optional: &[&str],
+    ) -> String {
+        let mut response = format!(
+            "* LISTRIGHTS {} {} {}",
+            Self::format_string(&utf7::encode(mailbox)),
+            Self::format_string(identifier),
+            Self::format_string(required)
+        );
+        for group in optional {
+            response.push(' ');
+            response.push_str(group);
+        }
+        response.push_str("\r\n");
+        response
+    }
+
+    /// MYRIGHTS response
+    pub fn myrights(mailbox: &str, rights: &str) -> String {
+        format!(
+            "* MYRIGHTS {} {}\r\n",
+            Self::format_string(&utf7::encode(mailbox)),
+            Self::format_string(rights)
+        )
+    }
+
+    /// Format a string as a quoted string, or as a literal when it cannot be quoted
+    pub(crate) fn format_string(s: &str) -> String {
+        if s.is_ascii() && !s.contains(['\r', '\n']) {
+            format!("\"{}\"", Self::quote_string(s))
+        } else {
+            format!("{{{}}}\r\n{}", s.len(), s)
+        }
+    }
+
     /// Quote a string for IMAP (escape backslash and quote)
     fn quote_string(s: &str) -> String {
         s.replace('\\', "\\\\").replace('"', "\\\"")
@@ -343,6 +452,7 @@ mod tests {
         let greeting = ImapResponse::greeting();
         assert!(greeting.starts_with("* OK"));
         assert!(greeting.contains("IMAP4rev1"));
+        assert!(greeting.contains("AUTH=OAUTHBEARER"));
     }
 
     #[test]
@@ -355,10 +465,22 @@ mod tests {
         assert_eq!(ImapResponse::no("A001", "Failed"), "A001 NO Failed\r\n");
     }
 
+    #[test]
+    fn test_alert() {
+        assert_eq!(
+            ImapResponse::alert("Read-only until 12:00"),
+            "* OK [ALERT] Read-only until 12:00\r\n"
+        );
+    }
+
     #[test]
     fn test_format_flags() {
-        let flags = ImapResponse::format_flags(true, false, true, false, false);
+        let flags = ImapResponse::format_flags(true, false, true, false, false, &[]);
         assert_eq!(flags, "(\\Seen \\Flagged)");
+
+        let keywords = vec!["$Forwarded".to_string(), "work".to_string()];
+        let flags = ImapResponse::format_flags(false, false, false, false, true, &keywords);
+        assert_eq!(flags, "(\\Draft $Forwarded work)");
     }
 
     #[test]
@@ -371,5 +493,48 @@ mod tests {
     fn test_list() {
         let list = ImapResponse::list(&["\\HasNoChildren"], "/", "INBOX");
         assert_eq!(list, "* LIST (\\HasNoChildren) \"/\" \"INBOX\"\r\n");
+
+        let list = ImapResponse::list(&["\\HasNoChildren", "\\Sent"], "/", "送信済み");
+        assert_eq!(
+            list,
+            "* LIST (\\HasNoChildren \\Sent) \"/\" \"&kAFP4W4IMH8-\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let entries = vec![
+            (
+                "/private/comment".to_string(),
+                Some("My \"inbox\"".to_string()),
+            ),
+            ("/shared/comment".to_string(), None),
+            ("/private/note".to_string(), Some("メモ".to_string())),
+        ];
+        assert_eq!(
+            ImapResponse::metadata("INBOX", &entries),
+            "* METADATA \"INBOX\" (\"/private/comment\" \"My \\\"inbox\\\"\" \
+             \"/shared/comment\" NIL \"/private/note\" {6}\r\nメモ)\r\n"
+        );
+    }
+
+    #[test]
+    fn test_acl() {
+        let entries = vec![
+            ("alice@example.com".to_string(), "lrswipkxtea".to_string()),
+            ("bob@example.com".to_string(), "lrs".to_string()),
+        ];
+        assert_eq!(
+            ImapResponse::acl("INBOX", &entries),
+            "* ACL \"INBOX\" \"alice@example.com\" \"lrswipkxtea\" \"bob@example.com\" \"lrs\"\r\n"
+        );
+        assert_eq!(
+            ImapResponse::myrights("INBOX", "lrs"),
+            "* MYRIGHTS \"INBOX\" \"lrs\"\r\n"
+        );
+        assert_eq!(
+            ImapResponse::listrights("INBOX", "bob@example.com", "", &["l", "r"]),
+            "* LISTRIGHTS \"INBOX\" \"bob@example.com\" \"\" l r\r\n"
+        );
     }
 }
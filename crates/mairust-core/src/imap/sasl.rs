@@ -0,0 +1,279 @@
+//! SASL mechanisms for IMAP AUTHENTICATE and POP3 AUTH
+//!
+//! Decodes the client responses for PLAIN (RFC 4616), LOGIN,
+//! OAUTHBEARER (RFC 7628) and Google's XOAUTH2. Both protocols frame
+//! challenges as `+ <base64>` lines and cancel an exchange with `*`.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// Base64 "Username:" challenge for the LOGIN mechanism
+pub const LOGIN_USERNAME_CHALLENGE: &str = "VXNlcm5hbWU6";
+
+/// Base64 "Password:" challenge for the LOGIN mechanism
+pub const LOGIN_PASSWORD_CHALLENGE: &str = "UGFzc3dvcmQ6";
+
+/// Supported SASL mechanisms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaslMechanism {
+    Plain,
+    Login,
+    OAuthBearer,
+    XOAuth2,
+}
+
+impl SaslMechanism {
+    /// Parse a mechanism name (case-insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "PLAIN" => Some(Self::Plain),
+            "LOGIN" => Some(Self::Login),
+            "OAUTHBEARER" => Some(Self::OAuthBearer),
+            "XOAUTH2" => Some(Self::XOAuth2),
+            _ => None,
+        }
+    }
+
+    /// Whether the mechanism carries a bearer token instead of a password
+    pub fn is_bearer(self) -> bool {
+        matches!(self, Self::OAuthBearer | Self::XOAuth2)
+    }
+}
+
+/// Credentials extracted from a SASL exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaslCredentials {
+    /// Username and password (PLAIN, LOGIN)
+    Password { username: String, password: String },
+    /// Bearer token with the user it claims to authenticate (OAUTHBEARER, XOAUTH2)
+    Bearer {
+        username: Option<String>,
+        token: String,
+    },
+}
+
+/// Decode a base64 client response line
+///
+/// `=` is the empty response used with SASL-IR (RFC 4959).
+pub fn decode_response(line: &str) -> Option<Vec<u8>> {
+    let line = line.trim();
+    if line == "=" {
+        return Some(Vec::new());
+    }
+    BASE64.decode(line).ok()
+}
+
+/// Encode a server challenge as a continuation request
+pub fn challenge(data: &str) -> String {
+    format!("+ {}\r\n", data)
+}
+
+/// Send a challenge and read the client response (`None` if cancelled with `*`)
+pub async fn exchange<R, W>(
+    reader: &mut R,
+    writer: &Arc<Mutex<W>>,
+    challenge: &str,
+    timeout: Duration,
+) -> Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    {
+        let mut w = writer.lock().await;
+        w.write_all(challenge.as_bytes()).await?;
+        w.flush().await?;
+    }
+
+    let mut line = String::new();
+    let read = tokio::time::timeout(timeout, reader.read_line(&mut line))
+        .await
+        .map_err(|_| anyhow!("SASL exchange timed out"))??;
+    if read == 0 {
+        return Err(anyhow!("Connection closed during SASL exchange"));
+    }
+
+    let response = line.trim_end_matches(['\r', '\n']);
+    if response == "*" {
+        return Ok(None);
+    }
+    Ok(Some(response.to_string()))
+}
+
+/// Parse a PLAIN response: `authzid NUL authcid NUL passwd`
+///
+/// Authorizing as a different identity is not supported, so a non-empty
+/// authzid must match the authcid.
+pub fn parse_plain(data: &[u8]) -> Option<SaslCredentials> {
+    let text = std::str::from_utf8(data).ok()?;
+    let mut parts = text.split('\0');
+    let authzid = parts.next()?;
+    let username = parts.next()?;
+    let password = parts.next()?;
+    if parts.next().is_some() || username.is_empty() {
+        return None;
+    }
+    if !authzid.is_empty() && authzid != username {
+        return None;
+    }
+    Some(SaslCredentials::Password {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// Parse an OAUTHBEARER response (RFC 7628)
+///
+/// Format: `n,a=user@example.com,^Ahost=...^Aauth=Bearer <token>^A^A`
+pub fn parse_oauthbearer(data: &[u8]) -> Option<SaslCredentials> {
+    let text = std::str::from_utf8(data).ok()?;
+    let (gs2_header, kvpairs) = text.split_once('\x01')?;
+
+    // GS2 header: channel binding flag, optional authzid, trailing comma
+    let mut gs2 = gs2_header.splitn(3, ',');
+    let cb_flag = gs2.next()?;
+    if !matches!(cb_flag, "n" | "y") {
+        return None;
+    }
+    let username = match gs2.next()? {
+        "" => None,
+        a => Some(unescape_saslname(a.strip_prefix("a=")?)?),
+    };
+
+    let token = bearer_token(kvpairs)?;
+    Some(SaslCredentials::Bearer { username, token })
+}
+
+/// Parse an XOAUTH2 response
+///
+/// Format: `user=user@example.com^Aauth=Bearer <token>^A^A`
+pub fn parse_xoauth2(data: &[u8]) -> Option<SaslCredentials> {
+    let text = std::str::from_utf8(data).ok()?;
+    let username = text
+        .split('\x01')
+        .find_map(|kv| kv.strip_prefix("user="))
+        .filter(|u| !u.is_empty())?;
+    let token = bearer_token(text)?;
+    Some(SaslCredentials::Bearer {
+        username: Some(username.to_string()),
+        token,
+    })
+}
+
+/// Error challenge sent before failing an OAuth exchange (RFC 7628 section 3.2.2)
+pub fn oauth_error_challenge() -> String {
+    let body = r#"{"status":"invalid_token","scope":"mail"}"#;
+    challenge(&BASE64.encode(body))
+}
+
+/// Extract the bearer token from `^A`-separated key/value pairs
+fn bearer_token(kvpairs: &str) -> Option<String> {
+    kvpairs
+        .split('\x01')
+        .find_map(|kv| kv.strip_prefix("auth="))
+        .and_then(|auth| {
+            let (scheme, token) = auth.split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+        })
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
+
+/// Decode `=2C` and `=3D` escapes in a GS2 saslname
+fn unescape_saslname(name: &str) -> Option<String> {
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(pos) = rest.find('=') {
+        out.push_str(&rest[..pos]);
+        match rest.get(pos..pos + 3)? {
+            "=2C" => out.push(','),
+            "=3D" => out.push('='),
+            _ => return None,
+        }
+        rest = &rest[pos + 3..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mechanism() {
+        assert_eq!(SaslMechanism::parse("plain"), Some(SaslMechanism::Plain));
+        assert_eq!(SaslMechanism::parse("XOAUTH2"), Some(SaslMechanism::XOAuth2));
+        assert_eq!(SaslMechanism::parse("CRAM-MD5"), None);
+        assert!(SaslMechanism::OAuthBearer.is_bearer());
+        assert!(!SaslMechanism::Login.is_bearer());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        assert_eq!(decode_response("=\r\n"), Some(Vec::new()));
+        assert_eq!(decode_response("dXNlcg==\r\n"), Some(b"user".to_vec()));
+        assert_eq!(decode_response("not base64!"), None);
+    }
+
+    #[test]
+    fn test_parse_plain() {
+        assert_eq!(
+            parse_plain(b"\0alice@example.com\0secret"),
+            Some(SaslCredentials::Password {
+                username: "alice@example.com".to_string(),
+                password: "secret".to_string(),
+            })
+        );
+        assert!(parse_plain(b"alice@example.com\0alice@example.com\0secret").is_some());
+        assert_eq!(parse_plain(b"bob@example.com\0alice@example.com\0secret"), None);
+        assert_eq!(parse_plain(b"\0alice@example.com"), None);
+    }
+
+    #[test]
+    fn test_parse_oauthbearer() {
+        let data = b"n,a=alice@example.com,\x01host=imap.example.com\x01port=993\x01auth=Bearer mk_token123\x01\x01";
+        assert_eq!(
+            parse_oauthbearer(data),
+            Some(SaslCredentials::Bearer {
+                username: Some("alice@example.com".to_string()),
+                token: "mk_token123".to_string(),
+            })
+        );
+
+        let data = b"n,,\x01auth=Bearer mk_token123\x01\x01";
+        assert_eq!(
+            parse_oauthbearer(data),
+            Some(SaslCredentials::Bearer {
+                username: None,
+                token: "mk_token123".to_string(),
+            })
+        );
+
+        assert_eq!(parse_oauthbearer(b"n,,\x01auth=Basic abc\x01\x01"), None);
+        assert_eq!(parse_oauthbearer(b"p=tls-unique,,\x01auth=Bearer t\x01\x01"), None);
+    }
+
+    #[test]
+    fn test_parse_xoauth2() {
+        let data = b"user=alice@example.com\x01auth=Bearer mk_token123\x01\x01";
+        assert_eq!(
+            parse_xoauth2(data),
+            Some(SaslCredentials::Bearer {
+                username: Some("alice@example.com".to_string()),
+                token: "mk_token123".to_string(),
+            })
+        );
+        assert_eq!(parse_xoauth2(b"auth=Bearer mk_token123\x01\x01"), None);
+    }
+
+    #[test]
+    fn test_unescape_saslname() {
+        assert_eq!(unescape_saslname("a=3Db=2Cc").as_deref(), Some("a=b,c"));
+        assert_eq!(unescape_saslname("bad=20"), None);
+    }
+}
@@ -0,0 +1,637 @@
+//! IMAP SEARCH evaluation
+//!
+//! Criteria are compiled to one SQL query over the selected mailbox, which
+//! also numbers the messages so sequence numbers come from the same
+//! snapshot as the matches. When full-text search is configured, BODY,
+//! TEXT, SUBJECT and FROM are answered by Meilisearch and enter the query
+//! as sets of message IDs; messages the index does not hold yet fall back
+//! to substring matching on the stored columns.
+//!
+//! Dates are compared without time and time zone (RFC 3501): the internal
+//! date as a UTC day, the sent date as the day written in the Date header.
+//! HEADER, CC and BCC match the header fields stored with each message.
+
+use super::command::SearchCriteria;
+use super::mime;
+use super::session::SelectedMailbox;
+use crate::search::MessageIndexer;
+use chrono::{DateTime, NaiveDate};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Internal date of a message as a day
+const INTERNAL_DATE: &str = "(received_at AT TIME ZONE 'UTC')::date";
+
+/// Sent date of a message as a day, the internal date without a Date header
+const SENT_DATE: &str = "COALESCE(sent_date, (received_at AT TIME ZONE 'UTC')::date)";
+
+/// Header fields stored with a message: lowercase field name to unfolded
+/// value, the values of repeated fields separated by newlines
+pub fn stored_headers(raw: &[u8]) -> serde_json::Value {
+    let mut headers = serde_json::Map::new();
+    for (name, value) in mime::header_fields(raw) {
+        let name = name.to_lowercase();
+        match headers.get_mut(&name) {
+            Some(serde_json::Value::String(existing)) => {
+                existing.push('\n');
+                existing.push_str(&value);
+            }
+            _ => {
+                headers.insert(name, serde_json::Value::String(value));
+            }
+        }
+    }
+    serde_json::Value::Object(headers)
+}
+
+/// Day of the Date header in the header's own time zone
+pub fn sent_date(raw: &[u8]) -> Option<NaiveDate> {
+    let (_, value) = mime::header_fields(raw)
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Date"))?;
+    // A trailing comment such as "(UTC)" is not part of the date
+    let value = match value.find('(') {
+        Some(idx) => value[..idx].trim(),
+        None => value.trim(),
+    };
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.date_naive())
+}
+
+/// Text criteria that can be delegated to the search index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextField {
+    Body,
+    Text,
+    Subject,
+    From,
+}
+
+impl TextField {
+    /// The delegable text criterion and its search string
+    fn of(criteria: &SearchCriteria) -> Option<(TextField, &str)> {
+        match criteria {
+            SearchCriteria::Body(s) => Some((TextField::Body, s)),
+            SearchCriteria::Text(s) => Some((TextField::Text, s)),
+            SearchCriteria::Subject(s) => Some((TextField::Subject, s)),
+            SearchCriteria::From(s) => Some((TextField::From, s)),
+            _ => None,
+        }
+    }
+
+    /// Index attributes searched for the criterion
+    ///
+    /// `body_preview` is included for documents indexed before the full
+    /// body was.
+    pub fn attributes(&self) -> &'static [&'static str] {
+        match self {
+            TextField::Body => &["body", "body_preview"],
+            TextField::Text => &[
+                "subject",
+                "from_address",
+                "to_addresses",
+                "body",
+                "body_preview",
+            ],
+            TextField::Subject => &["subject"],
+            TextField::From => &["from_address"],
+        }
+    }
+
+    /// Message columns matched by substring when the index is not used
+    fn columns(&self) -> &'static [&'static str] {
+        match self {
+            TextField::Body => &["body_preview"],
+            TextField::Text => &["subject", "from_address", "headers::text", "body_preview"],
+            TextField::Subject => &["subject"],
+            TextField::From => &["from_address"],
+        }
+    }
+}
+
+/// Text criteria of a search, without duplicates
+pub fn text_criteria(criteria: &SearchCriteria) -> Vec<(TextField, String)> {
+    fn collect(criteria: &SearchCriteria, out: &mut Vec<(TextField, String)>) {
+        match criteria {
+            SearchCriteria::Not(inner) => collect(inner, out),
+            SearchCriteria::And(list) => list.iter().for_each(|c| collect(c, out)),
+            SearchCriteria::Or(a, b) => {
+                collect(a, out);
+                collect(b, out);
+            }
+            other => {
+                if let Some((field, s)) = TextField::of(other) {
+                    let key = (field, s.to_string());
+                    if !out.contains(&key) {
+                        out.push(key);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    collect(criteria, &mut out);
+    out
+}
+
+/// Text criteria answered by the search index for one mailbox
+#[derive(Debug, Clone, Default)]
+pub struct IndexMatches {
+    /// Messages of the mailbox the index holds
+    indexed: Vec<Uuid>,
+    /// Matching messages per criterion
+    hits: HashMap<(TextField, String), Vec<Uuid>>,
+}
+
+impl IndexMatches {
+    /// Ask the index for every text criterion of `criteria`
+    ///
+    /// `limit` must exceed the number of messages in the mailbox; a search
+    /// that would be truncated fails instead.
+    pub async fn fetch(
+        indexer: &MessageIndexer,
+        tenant_id: Uuid,
+        mailbox_id: Uuid,
+        criteria: &SearchCriteria,
+        limit: u64,
+    ) -> Result<Self, String> {
+        let indexed = indexer
+            .find_message_ids(tenant_id, mailbox_id, "", &[], limit)
+            .await?;
+
+        let mut hits = HashMap::new();
+        for (field, s) in text_criteria(criteria) {
+            let ids = indexer
+                .find_message_ids(tenant_id, mailbox_id, &s, field.attributes(), limit)
+                .await?;
+            hits.insert((field, s), ids);
+        }
+
+        Ok(Self { indexed, hits })
+    }
+}
+
+/// Bind value of a compiled query
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlParam {
+    Text(String),
+    Int(i64),
+    Date(NaiveDate),
+    Ids(Vec<Uuid>),
+}
+
+/// Compiled SEARCH query
+///
+/// `$1` is the mailbox ID; `params` bind `$2` onwards. Rows are
+/// `(sequence number, uid)` in sequence order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub sql: String,
+    pub params: Vec<SqlParam>,
+}
+
+/// Compile search criteria, using `index` for text criteria when given
+///
+/// Sequence numbers and `*` are resolved against the messages of
+/// `selected` known to the client.
+pub fn compile(
+    criteria: &SearchCriteria,
+    index: Option<&IndexMatches>,
+    selected: &SelectedMailbox,
+) -> SearchQuery {
+    let mut compiler = Compiler {
+        index,
+        selected,
+        params: Vec::new(),
+        indexed_param: None,
+    };
+    let predicate = compiler.predicate(criteria);
+    SearchQuery {
+        sql: format!(
+            "SELECT seq, uid FROM (
+                 SELECT *, ROW_NUMBER() OVER (ORDER BY uid) AS seq
+                 FROM messages WHERE mailbox_id = $1
+             ) AS m
+             WHERE {}
+             ORDER BY seq",
+            predicate
+        ),
+        params: compiler.params,
+    }
+}
+
+struct Compiler<'a> {
+    index: Option<&'a IndexMatches>,
+    selected: &'a SelectedMailbox,
+    params: Vec<SqlParam>,
+    /// Placeholder of the indexed message IDs, bound once
+    indexed_param: Option<String>,
+}
+
+impl Compiler<'_> {
+    /// Add a bind value and return its placeholder
+    fn param(&mut self, param: SqlParam) -> String {
+        self.params.push(param);
+        format!("${}", self.params.len() + 1)
+    }
+
+    fn predicate(&mut self, criteria: &SearchCriteria) -> String {
+        match criteria {
+            SearchCriteria::All => "TRUE".to_string(),
+            SearchCriteria::Answered => "answered".to_string(),
+            SearchCriteria::Deleted => "deleted".to_string(),
+            SearchCriteria::Draft => "draft".to_string(),
+            SearchCriteria::Flagged => "flagged".to_string(),
+            SearchCriteria::Seen | SearchCriteria::Old => "seen".to_string(),
+            SearchCriteria::Unanswered => "NOT answered".to_string(),
+            SearchCriteria::Undeleted => "NOT deleted".to_string(),
+            SearchCriteria::Undraft => "NOT draft".to_string(),
+            SearchCriteria::Unflagged => "NOT flagged".to_string(),
+            SearchCriteria::Unseen | SearchCriteria::New => "NOT seen".to_string(),
+            SearchCriteria::Recent => "created_at > NOW() - INTERVAL '24 hours'".to_string(),
+            SearchCriteria::To(s) => {
+                let p = self.param(SqlParam::Text(like_pattern(s)));
+                format!(
+                    "EXISTS (SELECT 1 FROM jsonb_array_elements_text(to_addresses) AS a(value) WHERE a.value ILIKE {})",
+                    p
+                )
+            }
+            SearchCriteria::Keyword(k) => {
+                let p = self.param(SqlParam::Text(k.clone()));
+                format!("EXISTS (SELECT 1 FROM jsonb_array_elements_text(keywords) AS k(value) WHERE LOWER(k.value) = LOWER({}))", p)
+            }
+            SearchCriteria::Unkeyword(k) => {
+                let p = self.param(SqlParam::Text(k.clone()));
+                format!("NOT EXISTS (SELECT 1 FROM jsonb_array_elements_text(keywords) AS k(value) WHERE LOWER(k.value) = LOWER({}))", p)
+            }
+            SearchCriteria::Before(date) => self.date(INTERNAL_DATE, "<", date),
+            SearchCriteria::On(date) => self.date(INTERNAL_DATE, "=", date),
+            SearchCriteria::Since(date) => self.date(INTERNAL_DATE, ">=", date),
+            SearchCriteria::SentBefore(date) => self.date(SENT_DATE, "<", date),
+            SearchCriteria::SentOn(date) => self.date(SENT_DATE, "=", date),
+            SearchCriteria::SentSince(date) => self.date(SENT_DATE, ">=", date),
+            SearchCriteria::Header(name, s) => self.header(name, s),
+            SearchCriteria::Cc(s) => self.header("cc", s),
+            SearchCriteria::Bcc(s) => self.header("bcc", s),
+            SearchCriteria::Uid(set) => uid_in(&self.selected.uid_ranges(set, true)),
+            SearchCriteria::SequenceSet(set) => uid_in(&self.selected.uid_ranges(set, false)),
+            SearchCriteria::Larger(size) => {
+                let p = self.param(SqlParam::Int(*size as i64));
+                format!("body_size > {}", p)
+            }
+            SearchCriteria::Smaller(size) => {
+                let p = self.param(SqlParam::Int(*size as i64));
+                format!("body_size < {}", p)
+            }
+            SearchCriteria::Not(inner) => format!("NOT ({})", self.predicate(inner)),
+            SearchCriteria::And(list) if list.is_empty() => "TRUE".to_string(),
+            SearchCriteria::And(list) => {
+                let parts: Vec<String> = list.iter().map(|c| self.predicate(c)).collect();
+                format!("({})", parts.join(" AND "))
+            }
+            SearchCriteria::Or(a, b) => {
+                format!("({} OR {})", self.predicate(a), self.predicate(b))
+            }
+            SearchCriteria::Body(s) => self.text(TextField::Body, s),
+            SearchCriteria::Text(s) => self.text(TextField::Text, s),
+            SearchCriteria::Subject(s) => self.text(TextField::Subject, s),
+            SearchCriteria::From(s) => self.text(TextField::From, s),
+        }
+    }
+
+    /// Date criterion comparing the day `column` with `date`
+    fn date(&mut self, column: &str, op: &str, date: &NaiveDate) -> String {
+        let p = self.param(SqlParam::Date(*date));
+        format!("{} {} {}", column, op, p)
+    }
+
+    /// Header field criterion; an empty string asks for the field only
+    fn header(&mut self, name: &str, s: &str) -> String {
+        let name = self.param(SqlParam::Text(name.to_lowercase()));
+        if s.is_empty() {
+            return format!("headers -> {} IS NOT NULL", name);
+        }
+        let pattern = self.param(SqlParam::Text(like_pattern(s)));
+        format!("COALESCE(headers ->> {}, '') ILIKE {}", name, pattern)
+    }
+
+    /// Text criterion: index hits, or substring matching for messages the
+    /// index does not hold
+    fn text(&mut self, field: TextField, s: &str) -> String {
+        let pattern = self.param(SqlParam::Text(like_pattern(s)));
+        let local: Vec<String> = field
+            .columns()
+            .iter()
+            .map(|column| format!("COALESCE({}, '') ILIKE {}", column, pattern))
+            .collect();
+        let local = format!("({})", local.join(" OR "));
+
+        let Some(hits) = self
+            .index
+            .and_then(|index| index.hits.get(&(field, s.to_string())))
+            .cloned()
+        else {
+            return local;
+        };
+
+        let indexed = match self.indexed_param.clone() {
+            Some(p) => p,
+            None => {
+                let ids = self.index.map(|i| i.indexed.clone()).unwrap_or_default();
+                let p = self.param(SqlParam::Ids(ids));
+                self.indexed_param = Some(p.clone());
+                p
+            }
+        };
+        let hits = self.param(SqlParam::Ids(hits));
+        format!(
+            "(id = ANY({}) OR (id <> ALL({}) AND {}))",
+            hits, indexed, local
+        )
+    }
+}
+
+/// Messages whose UID is in one of `ranges`
+fn uid_in(ranges: &[(u32, u32)]) -> String {
+    if ranges.is_empty() {
+        return "FALSE".to_string();
+    }
+    let parts: Vec<String> = ranges
+        .iter()
+        .map(|(start, end)| format!("uid BETWEEN {} AND {}", start, end))
+        .collect();
+    format!("({})", parts.join(" OR "))
+}
+
+/// ILIKE pattern matching `s` anywhere
+fn like_pattern(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imap::command::SequenceSet;
+    use crate::imap::session::MessageState;
+
+    /// A mailbox holding the messages with UIDs 2, 3, 5 and 8
+    fn mailbox() -> SelectedMailbox {
+        let mut mailbox = SelectedMailbox::new(Uuid::new_v4(), "INBOX".to_string());
+        let messages: Vec<MessageState> = [2, 3, 5, 8]
+            .into_iter()
+            .map(|uid| MessageState {
+                uid,
+                seen: false,
+                recent: false,
+            })
+            .collect();
+        mailbox.update_with_messages(&messages);
+        mailbox
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// The outer WHERE clause of a compiled query
+    fn predicate(query: &SearchQuery) -> &str {
+        let outer = &query.sql[query.sql.find(") AS m").unwrap()..];
+        let rest = &outer[outer.find("WHERE ").unwrap() + 6..];
+        rest[..rest.rfind("ORDER BY").unwrap()].trim()
+    }
+
+    #[test]
+    fn test_compile_flags() {
+        let criteria = SearchCriteria::And(vec![
+            SearchCriteria::Unseen,
+            SearchCriteria::Not(Box::new(SearchCriteria::Deleted)),
+            SearchCriteria::Or(
+                Box::new(SearchCriteria::Flagged),
+                Box::new(SearchCriteria::Larger(1024)),
+            ),
+        ]);
+        let query = compile(&criteria, None, &mailbox());
+        assert_eq!(
+            predicate(&query),
+            "(NOT seen AND NOT (deleted) AND (flagged OR body_size > $2))"
+        );
+        assert_eq!(query.params, vec![SqlParam::Int(1024)]);
+    }
+
+    #[test]
+    fn test_compile_text_without_index() {
+        let query = compile(
+            &SearchCriteria::Body("50%_off".to_string()),
+            None,
+            &mailbox(),
+        );
+        assert_eq!(predicate(&query), "(COALESCE(body_preview, '') ILIKE $2)");
+        assert_eq!(
+            query.params,
+            vec![SqlParam::Text("%50\\%\\_off%".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_compile_text_with_index() {
+        let hit = Uuid::new_v4();
+        let indexed = vec![hit, Uuid::new_v4()];
+        let criteria = SearchCriteria::And(vec![
+            SearchCriteria::Seen,
+            SearchCriteria::Body("invoice".to_string()),
+            SearchCriteria::Subject("Q3".to_string()),
+        ]);
+        let mut index = IndexMatches {
+            indexed: indexed.clone(),
+            hits: HashMap::new(),
+        };
+        index
+            .hits
+            .insert((TextField::Body, "invoice".to_string()), vec![hit]);
+        index
+            .hits
+            .insert((TextField::Subject, "Q3".to_string()), Vec::new());
+
+        let query = compile(&criteria, Some(&index), &mailbox());
+        assert_eq!(
+            predicate(&query),
+            "(seen \
+             AND (id = ANY($4) OR (id <> ALL($3) AND (COALESCE(body_preview, '') ILIKE $2))) \
+             AND (id = ANY($6) OR (id <> ALL($3) AND (COALESCE(subject, '') ILIKE $5))))"
+        );
+        assert_eq!(query.params[1], SqlParam::Ids(indexed));
+        assert_eq!(query.params[2], SqlParam::Ids(vec![hit]));
+        assert_eq!(query.params.len(), 5);
+    }
+
+    #[test]
+    fn test_text_criteria() {
+        let criteria = SearchCriteria::Or(
+            Box::new(SearchCriteria::Text("a".to_string())),
+            Box::new(SearchCriteria::Not(Box::new(SearchCriteria::And(vec![
+                SearchCriteria::Text("a".to_string()),
+                SearchCriteria::From("bob".to_string()),
+                SearchCriteria::Seen,
+            ])))),
+        );
+        assert_eq!(
+            text_criteria(&criteria),
+            vec![
+                (TextField::Text, "a".to_string()),
+                (TextField::From, "bob".to_string())
+            ]
+        );
+        assert!(text_criteria(&SearchCriteria::All).is_empty());
+    }
+
+    #[test]
+    fn test_compile_internal_dates() {
+        let criteria = SearchCriteria::And(vec![
+            SearchCriteria::Since(date(2024, 1, 1)),
+            SearchCriteria::Before(date(2024, 2, 1)),
+            SearchCriteria::Not(Box::new(SearchCriteria::On(date(2024, 1, 15)))),
+        ]);
+        let query = compile(&criteria, None, &mailbox());
+        assert_eq!(
+            predicate(&query),
+            "((received_at AT TIME ZONE 'UTC')::date >= $2 \
+             AND (received_at AT TIME ZONE 'UTC')::date < $3 \
+             AND NOT ((received_at AT TIME ZONE 'UTC')::date = $4))"
+        );
+        assert_eq!(
+            query.params,
+            vec![
+                SqlParam::Date(date(2024, 1, 1)),
+                SqlParam::Date(date(2024, 2, 1)),
+                SqlParam::Date(date(2024, 1, 15)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_sent_dates() {
+        for (criteria, op) in [
+            (SearchCriteria::SentBefore(date(2024, 3, 1)), "<"),
+            (SearchCriteria::SentOn(date(2024, 3, 1)), "="),
+            (SearchCriteria::SentSince(date(2024, 3, 1)), ">="),
+        ] {
+            let query = compile(&criteria, None, &mailbox());
+            assert_eq!(
+                predicate(&query),
+                format!(
+                    "COALESCE(sent_date, (received_at AT TIME ZONE 'UTC')::date) {} $2",
+                    op
+                )
+            );
+            assert_eq!(query.params, vec![SqlParam::Date(date(2024, 3, 1))]);
+        }
+    }
+
+    #[test]
+    fn test_compile_headers() {
+        let query = compile(
+            &SearchCriteria::Header("List-ID".to_string(), "dev".to_string()),
+            None,
+            &mailbox(),
+        );
+        assert_eq!(predicate(&query), "COALESCE(headers ->> $2, '') ILIKE $3");
+        assert_eq!(
+            query.params,
+            vec![
+                SqlParam::Text("list-id".to_string()),
+                SqlParam::Text("%dev%".to_string())
+            ]
+        );
+
+        // An empty string matches every message with the field
+        let query = compile(
+            &SearchCriteria::Header("X-Priority".to_string(), String::new()),
+            None,
+            &mailbox(),
+        );
+        assert_eq!(predicate(&query), "headers -> $2 IS NOT NULL");
+
+        let query = compile(&SearchCriteria::Cc("carol".to_string()), None, &mailbox());
+        assert_eq!(predicate(&query), "COALESCE(headers ->> $2, '') ILIKE $3");
+        assert_eq!(query.params[0], SqlParam::Text("cc".to_string()));
+    }
+
+    #[test]
+    fn test_compile_keywords() {
+        let criteria = SearchCriteria::And(vec![
+            SearchCriteria::Keyword("$Work".to_string()),
+            SearchCriteria::Unkeyword("$Junk".to_string()),
+        ]);
+        let query = compile(&criteria, None, &mailbox());
+        assert_eq!(
+            predicate(&query),
+            "(EXISTS (SELECT 1 FROM jsonb_array_elements_text(keywords) AS k(value) WHERE LOWER(k.value) = LOWER($2)) \
+             AND NOT EXISTS (SELECT 1 FROM jsonb_array_elements_text(keywords) AS k(value) WHERE LOWER(k.value) = LOWER($3)))"
+        );
+        assert_eq!(
+            query.params,
+            vec![
+                SqlParam::Text("$Work".to_string()),
+                SqlParam::Text("$Junk".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_uid_and_sequence_sets() {
+        let uid =
+            |set| predicate(&compile(&SearchCriteria::Uid(set), None, &mailbox())).to_string();
+        assert_eq!(
+            uid(SequenceSet::Range(3, u32::MAX)),
+            "(uid BETWEEN 3 AND 8)"
+        );
+        // * is the highest UID even when it is below the range start
+        assert_eq!(
+            uid(SequenceSet::Range(20, u32::MAX)),
+            "(uid BETWEEN 8 AND 20)"
+        );
+        assert_eq!(
+            uid(SequenceSet::Multiple(vec![
+                SequenceSet::Single(2),
+                SequenceSet::Single(u32::MAX),
+            ])),
+            "(uid BETWEEN 2 AND 2 OR uid BETWEEN 8 AND 8)"
+        );
+
+        // Sequence numbers map to the UIDs known to the client
+        let query = compile(
+            &SearchCriteria::SequenceSet(SequenceSet::Range(2, 3)),
+            None,
+            &mailbox(),
+        );
+        assert_eq!(predicate(&query), "(uid BETWEEN 3 AND 5)");
+        let query = compile(
+            &SearchCriteria::SequenceSet(SequenceSet::Single(9)),
+            None,
+            &mailbox(),
+        );
+        assert_eq!(predicate(&query), "FALSE");
+    }
+
+    #[test]
+    fn test_stored_headers_and_sent_date() {
+        let raw = b"Received: from a\r\nReceived: from b\r\nSubject: Hello\r\n \
+                    world\r\nList-Id: <dev.example.com>\r\n\
+                    Date: Sun, 31 Mar 2024 23:30:00 -0500 (EST)\r\n\r\nDate: not a header\r\n";
+        let headers = stored_headers(raw);
+        assert_eq!(headers["received"], "from a\nfrom b");
+        assert_eq!(headers["subject"], "Hello world");
+        assert_eq!(headers["list-id"], "<dev.example.com>");
+
+        // The day in the header's own time zone
+        assert_eq!(sent_date(raw), Some(date(2024, 3, 31)));
+        assert_eq!(sent_date(b"Date: yesterday\r\n\r\n"), None);
+        assert_eq!(sent_date(b"Subject: x\r\n\r\nbody"), None);
+    }
+}
@@ -2,30 +2,75 @@
 //!
 //! Full-featured IMAP server implementation with read/write mail access.
 
+use super::acl::{self, Rights, ALL_RIGHTS};
 use super::command::{
-    FetchItem, ImapCommand, SearchCriteria, SequenceSet, StoreFlags, StoreOperation, TaggedCommand,
+    FetchItem, ImapCommand, MetadataDepth, MetadataOptions, SearchCriteria, SequenceSet,
+    StoreFlags, StoreOperation, TaggedCommand,
 };
+use super::compress::{DeflateReader, DeflateWriter};
+use super::mime::MimePart;
 use super::parser::ImapParser;
 use super::response::ImapResponse;
-use super::session::{ImapSession, SelectedMailbox, SessionState};
+use super::sasl::{self, SaslCredentials, SaslMechanism};
+use super::search::{self, IndexMatches, SqlParam};
+use super::session::{ImapSession, MessageState, SelectedMailbox, SessionState};
+use crate::folders::{self, SpecialUse, FOLDER_DELIMITER};
+use crate::limits::ConnectionLimiter;
+use crate::maintenance::{MaintenanceMode, MaintenanceStatus};
+use crate::preview::MessagePreview;
+use crate::proxy_protocol::TrustedProxies;
+use crate::search::{MeilisearchClient, MeilisearchConfig, MessageIndexer, MAX_TOTAL_HITS};
+use crate::security::SecurityNotifier;
+use crate::services::ServicePolicy;
+use crate::sessions::{ActiveSession, SessionProtocol, SessionRegistry};
 
 use anyhow::{anyhow, Result};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use mairust_common::config::TlsConfig;
+use mairust_common::config::{ConnectionLimitConfig, TlsConfig};
+use mairust_common::types::MailService;
 use mairust_storage::db::DatabasePool;
 use mairust_storage::models::Message;
-use mairust_storage::{FileStorage, LocalStorage};
+use mairust_storage::repository::api_keys::{key_prefix, ApiKey};
+use mairust_storage::repository::metadata::{
+    is_private_entry, normalize_entry, METADATA_MAX_VALUE_SIZE,
+};
+use mairust_storage::{
+    ApiKeyRepository, ApiKeyRepositoryTrait, MailboxAclRepository, MailboxSubscriptionRepository,
+    MetadataRepository,
+};
+use mairust_storage::{FileStorage, LocalStorage, Residency, StorageLayout};
 use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Timeout for the TLS handshake on the IMAPS listener
+const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 30;
+
+/// Largest command accepted, literals included (room for an APPEND message
+/// and its arguments)
+const MAX_COMMAND_SIZE: usize = ImapServer::MAX_APPEND_SIZE + 64 * 1024;
+
+/// Largest command line, literals excluded
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Largest non-synchronizing literal (LITERAL-, RFC 7888) and largest
+/// literal accepted before authentication
+const MAX_SMALL_LITERAL: usize = 4096;
+
+/// API key scope that allows a key to be used as an IMAP bearer token
+const IMAP_SCOPE: &str = "mail:imap";
+
 /// IMAP server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImapConfig {
@@ -35,6 +80,9 @@ pub struct ImapConfig {
     /// Enable STARTTLS
     #[serde(default)]
     pub starttls: bool,
+    /// Implicit TLS (IMAPS) listen address and port
+    #[serde(default)]
+    pub tls_bind: Option<String>,
     /// Session timeout in minutes
     #[serde(default = "default_timeout")]
     pub timeout_minutes: i64,
@@ -44,6 +92,36 @@ pub struct ImapConfig {
     /// Storage path for message files
     #[serde(default = "default_storage_path")]
     pub storage_path: PathBuf,
+    /// Layout of message files stored by APPEND (set at startup)
+    #[serde(skip)]
+    pub storage_layout: StorageLayout,
+    /// Full-text search used for SEARCH BODY, TEXT, SUBJECT and FROM
+    /// (set at startup from the Meilisearch configuration)
+    #[serde(skip)]
+    pub search: Option<MeilisearchConfig>,
+    /// Maintenance windows during which changes are refused
+    #[serde(skip)]
+    pub maintenance: MaintenanceMode,
+    /// Connection limits shared by all listeners (set at startup; created
+    /// from `max_connections` with the default per-IP limits when unset)
+    #[serde(skip)]
+    pub limiter: Option<ConnectionLimiter>,
+    /// Records blocked logins and logins from new networks (set at startup)
+    #[serde(skip)]
+    pub security: Option<SecurityNotifier>,
+    /// Load balancers whose PROXY header gives the client address (set at
+    /// startup for `proxy_protocol` listeners)
+    #[serde(skip)]
+    pub proxy: Option<TrustedProxies>,
+    /// IMAP access by plan, tenant and user (set at startup)
+    #[serde(skip)]
+    pub services: ServicePolicy,
+    /// Regions tenant data is pinned to (set at startup)
+    #[serde(skip)]
+    pub residency: Option<Arc<Residency>>,
+    /// Live sessions listed to administrators (set at startup)
+    #[serde(skip)]
+    pub sessions: SessionRegistry,
 }
 
 fn default_storage_path() -> PathBuf {
@@ -67,13 +145,67 @@ impl Default for ImapConfig {
         Self {
             bind: default_bind(),
             starttls: false,
+            tls_bind: None,
             timeout_minutes: default_timeout(),
             max_connections: default_max_connections(),
             storage_path: default_storage_path(),
+            storage_layout: StorageLayout::default(),
+            search: None,
+            maintenance: MaintenanceMode::default(),
+            limiter: None,
+            security: None,
+            proxy: None,
+            services: ServicePolicy::default(),
+            residency: None,
+            sessions: SessionRegistry::default(),
         }
     }
 }
 
+/// A mailbox the session user can open: one of their own or one shared with them
+struct AccessibleMailbox {
+    id: Uuid,
+    uid_validity: i64,
+    uid_next: i64,
+    /// Messages, maintained on the mailbox row
+    message_count: i64,
+    /// Messages without \Seen, maintained on the mailbox row
+    unseen_count: i64,
+    owner_id: Uuid,
+    rights: Rights,
+}
+
+/// How a session loop handed the connection back
+enum SessionExit {
+    /// The client logged out or the connection ended
+    Closed,
+    /// STARTTLS was accepted; the stream must be upgraded
+    StartTls,
+    /// COMPRESS was accepted; the streams must be wrapped
+    Compress,
+}
+
+/// Protection of the streams a session loop runs on
+#[derive(Debug, Clone, Copy)]
+struct StreamState {
+    /// STARTTLS can be offered
+    starttls: bool,
+    /// TLS is active
+    tls: bool,
+    /// COMPRESS=DEFLATE is active
+    compressed: bool,
+}
+
+/// Result of reading one command from the client
+enum CommandRead {
+    /// A complete command, literals included
+    Command(Vec<u8>),
+    /// An oversized synchronizing literal was refused before its data was sent
+    Rejected,
+    /// The connection ended or was closed for an oversized literal
+    Closed,
+}
+
 /// IMAP Server
 pub struct ImapServer {
     config: ImapConfig,
@@ -85,7 +217,7 @@ impl ImapServer {
     /// Create a new IMAP server
     pub fn new(config: ImapConfig, db_pool: DatabasePool) -> Self {
         Self {
-            config,
+            config: Self::with_limiter(config),
             db_pool,
             tls_acceptor: None,
         }
@@ -97,19 +229,30 @@ impl ImapServer {
                 |tls_config| match crate::smtp::create_tls_acceptor(tls_config) {
                     Ok(acceptor) => Some(Arc::new(acceptor)),
                     Err(e) => {
-                        warn!("Failed to initialize IMAP STARTTLS acceptor: {}", e);
+                        warn!("Failed to initialize IMAP TLS acceptor: {}", e);
                         None
                     }
                 },
             );
 
         Self {
-            config,
+            config: Self::with_limiter(config),
             db_pool,
             tls_acceptor,
         }
     }
 
+    /// Give the configuration a limiter of its own unless one is shared
+    fn with_limiter(mut config: ImapConfig) -> ImapConfig {
+        if config.limiter.is_none() {
+            config.limiter = Some(ConnectionLimiter::new(
+                config.max_connections,
+                ConnectionLimitConfig::default(),
+            ));
+        }
+        config
+    }
+
     /// Start the IMAP server
     pub async fn run(&self) -> Result<()> {
         let listener = TcpListener::bind(&self.config.bind).await?;
@@ -123,6 +266,56 @@ impl ImapServer {
             self.config.bind, tls_status
         );
 
+        let tls_listener = match (&self.config.tls_bind, &self.tls_acceptor) {
+            (Some(bind), Some(_)) => {
+                let listener = TcpListener::bind(bind).await?;
+                info!("IMAPS server listening on {} (implicit TLS)", bind);
+                Some(listener)
+            }
+            (Some(bind), None) => {
+                warn!("IMAPS listener on {} disabled: TLS is not configured", bind);
+                None
+            }
+            (None, _) => None,
+        };
+
+        match tls_listener {
+            Some(tls_listener) => {
+                tokio::try_join!(
+                    self.accept_loop(listener, false),
+                    self.accept_loop(tls_listener, true)
+                )?;
+            }
+            None => self.accept_loop(listener, false).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Start a single listener: plaintext (with STARTTLS when enabled) or
+    /// implicit TLS
+    pub async fn run_listener(&self, bind: &str, implicit_tls: bool) -> Result<()> {
+        if implicit_tls && self.tls_acceptor.is_none() {
+            return Err(anyhow!(
+                "IMAPS listener on {} requires TLS to be configured",
+                bind
+            ));
+        }
+
+        let listener = TcpListener::bind(bind).await?;
+        if implicit_tls {
+            info!("IMAPS server listening on {} (implicit TLS)", bind);
+        } else if self.config.starttls && self.tls_acceptor.is_some() {
+            info!("IMAP server listening on {} (STARTTLS enabled)", bind);
+        } else {
+            info!("IMAP server listening on {} (STARTTLS disabled)", bind);
+        }
+
+        self.accept_loop(listener, implicit_tls).await
+    }
+
+    /// Accept connections on a listener
+    async fn accept_loop(&self, listener: TcpListener, implicit_tls: bool) -> Result<()> {
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
@@ -131,11 +324,71 @@ impl ImapServer {
                     let tls_acceptor = self.tls_acceptor.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) =
-                            Self::handle_connection(stream, addr, db_pool, config, tls_acceptor)
-                                .await
-                        {
-                            error!("Connection error from {}: {}", addr, e);
+                        let mut stream = stream;
+                        // Behind a load balancer the PROXY header names the client
+                        let addr = match &config.proxy {
+                            Some(proxy) => match proxy.client_addr(&mut stream, addr).await {
+                                Ok(client_addr) => client_addr,
+                                Err(e) => {
+                                    warn!("Refusing IMAP connection: {}", e);
+                                    return;
+                                }
+                            },
+                            None => addr,
+                        };
+
+                        let limiter = config.limiter.as_ref();
+                        let _permit = match limiter.map(|l| l.try_acquire(addr.ip())).transpose() {
+                            Ok(permit) => permit,
+                            Err(refusal) => {
+                                warn!(
+                                    "Refusing IMAP connection from {}: {}",
+                                    addr,
+                                    refusal.reason()
+                                );
+                                if !implicit_tls {
+                                    // A new connection's send buffer has room for the line
+                                    let bye = ImapResponse::bye(&format!(
+                                        "[UNAVAILABLE] {}",
+                                        refusal.reason()
+                                    ));
+                                    let _ = stream.try_write(bye.as_bytes());
+                                }
+                                return;
+                            }
+                        };
+
+                        let registration = config.sessions.register(SessionProtocol::Imap, addr);
+                        let live = registration.session().clone();
+                        let result = live
+                            .run(async {
+                                if implicit_tls {
+                                    Self::handle_implicit_tls_connection(
+                                        stream,
+                                        addr,
+                                        db_pool,
+                                        config,
+                                        tls_acceptor,
+                                        live.clone(),
+                                    )
+                                    .await
+                                } else {
+                                    Self::handle_connection(
+                                        stream,
+                                        addr,
+                                        db_pool,
+                                        config,
+                                        tls_acceptor,
+                                        live.clone(),
+                                    )
+                                    .await
+                                }
+                            })
+                            .await;
+                        match result {
+                            Some(Err(e)) => error!("Connection error from {}: {}", addr, e),
+                            Some(Ok(())) => {}
+                            None => info!("Terminated IMAP session from {}", addr),
                         }
                     });
                 }
@@ -146,6 +399,30 @@ impl ImapServer {
         }
     }
 
+    /// Handle a connection on the implicit TLS (IMAPS) listener
+    async fn handle_implicit_tls_connection(
+        stream: TcpStream,
+        addr: SocketAddr,
+        db_pool: DatabasePool,
+        config: ImapConfig,
+        tls_acceptor: Option<Arc<TlsAcceptor>>,
+        live: Arc<ActiveSession>,
+    ) -> Result<()> {
+        let acceptor =
+            tls_acceptor.ok_or_else(|| anyhow!("IMAPS connection without configured acceptor"))?;
+
+        info!("New IMAPS connection from {}", addr);
+        let tls_stream = tokio::time::timeout(
+            std::time::Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS),
+            acceptor.accept(stream),
+        )
+        .await
+        .map_err(|_| anyhow!("TLS handshake timed out for {}", addr))??;
+
+        let session = Arc::new(Mutex::new(ImapSession::new()));
+        Self::handle_tls_connection(tls_stream, addr, db_pool, config, session, live, true).await
+    }
+
     /// Handle a single IMAP connection
     async fn handle_connection(
         stream: TcpStream,
@@ -153,83 +430,335 @@ impl ImapServer {
         db_pool: DatabasePool,
         config: ImapConfig,
         tls_acceptor: Option<Arc<TlsAcceptor>>,
+        live: Arc<ActiveSession>,
     ) -> Result<()> {
         info!("New IMAP connection from {}", addr);
 
         let (reader, writer) = stream.into_split();
-        let mut reader = BufReader::new(reader);
-        let writer = Arc::new(Mutex::new(writer));
+        let mut reader = BufReader::new(live.meter(reader));
+        let writer = Arc::new(Mutex::new(live.meter(writer)));
         let session = Arc::new(Mutex::new(ImapSession::new()));
+        let advertise_starttls = config.starttls && tls_acceptor.is_some();
 
         // Send greeting
         {
             let mut w = writer.lock().await;
-            let advertise_starttls = config.starttls && tls_acceptor.is_some();
             w.write_all(ImapResponse::greeting_with_starttls(advertise_starttls).as_bytes())
                 .await?;
             w.flush().await?;
         }
 
-        let mut line = String::new();
+        let state = StreamState {
+            starttls: advertise_starttls,
+            tls: false,
+            compressed: false,
+        };
+        let exit = Self::run_session(
+            &mut reader,
+            &writer,
+            addr,
+            &db_pool,
+            &config,
+            &session,
+            &live,
+            state,
+        )
+        .await?;
+
+        match exit {
+            SessionExit::Closed => {}
+            SessionExit::StartTls => {
+                let acceptor = tls_acceptor.ok_or_else(|| {
+                    anyhow!("IMAP STARTTLS requested without configured acceptor")
+                })?;
+
+                let read_half = reader.into_inner().into_inner();
+                let writer_half = Arc::try_unwrap(writer)
+                    .map_err(|_| anyhow!("Failed to unwrap IMAP writer during STARTTLS"))?
+                    .into_inner()
+                    .into_inner();
+                let tcp_stream = read_half
+                    .reunite(writer_half)
+                    .map_err(|_| anyhow!("Failed to reunite IMAP stream halves during STARTTLS"))?;
+                let tls_stream = acceptor.accept(tcp_stream).await?;
+                info!("IMAP STARTTLS negotiation completed for {}", addr);
+
+                return Self::handle_tls_connection(
+                    tls_stream, addr, db_pool, config, session, live, false,
+                )
+                .await;
+            }
+            SessionExit::Compress => {
+                Self::run_compressed(
+                    reader, writer, addr, &db_pool, &config, &session, &live, state,
+                )
+                .await?;
+            }
+        }
+
+        info!("IMAP connection closed for {}", addr);
+        Ok(())
+    }
+
+    async fn handle_tls_connection(
+        tls_stream: tokio_rustls::server::TlsStream<TcpStream>,
+        addr: SocketAddr,
+        db_pool: DatabasePool,
+        config: ImapConfig,
+        session: Arc<Mutex<ImapSession>>,
+        live: Arc<ActiveSession>,
+        send_greeting: bool,
+    ) -> Result<()> {
+        let (reader, writer) = tokio::io::split(live.meter(tls_stream));
+        let mut reader = BufReader::new(reader);
+        let writer = Arc::new(Mutex::new(writer));
+
+        // Implicit TLS connections get their greeting after the handshake
+        if send_greeting {
+            let mut w = writer.lock().await;
+            w.write_all(ImapResponse::greeting_with_starttls(false).as_bytes())
+                .await?;
+            w.flush().await?;
+        }
 
+        let state = StreamState {
+            starttls: false,
+            tls: true,
+            compressed: false,
+        };
+        let exit = Self::run_session(
+            &mut reader,
+            &writer,
+            addr,
+            &db_pool,
+            &config,
+            &session,
+            &live,
+            state,
+        )
+        .await?;
+        if let SessionExit::Compress = exit {
+            Self::run_compressed(
+                reader, writer, addr, &db_pool, &config, &session, &live, state,
+            )
+            .await?;
+        }
+
+        info!("IMAP TLS connection closed for {}", addr);
+        Ok(())
+    }
+
+    /// Continue a session over DEFLATE-wrapped streams after COMPRESS
+    #[allow(clippy::too_many_arguments)]
+    async fn run_compressed<R, W>(
+        reader: BufReader<R>,
+        writer: Arc<Mutex<W>>,
+        addr: SocketAddr,
+        db_pool: &DatabasePool,
+        config: &ImapConfig,
+        session: &Arc<Mutex<ImapSession>>,
+        live: &ActiveSession,
+        state: StreamState,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        // Anything pipelined after the COMPRESS command is already compressed
+        let buffered = reader.buffer().to_vec();
+        let mut reader = BufReader::new(DeflateReader::new(reader.into_inner(), &buffered));
+        let writer = Arc::try_unwrap(writer)
+            .map_err(|_| anyhow!("Failed to unwrap IMAP writer for COMPRESS"))?
+            .into_inner();
+        let writer = Arc::new(Mutex::new(DeflateWriter::new(writer)));
+        debug!("IMAP COMPRESS=DEFLATE active for {}", addr);
+
+        let state = StreamState {
+            starttls: false,
+            compressed: true,
+            ..state
+        };
+        Self::run_session(
+            &mut reader,
+            &writer,
+            addr,
+            db_pool,
+            config,
+            session,
+            live,
+            state,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Read and execute commands until the session ends or the connection
+    /// has to be upgraded (STARTTLS) or wrapped (COMPRESS)
+    #[allow(clippy::too_many_arguments)]
+    async fn run_session<R, W>(
+        reader: &mut BufReader<R>,
+        writer: &Arc<Mutex<W>>,
+        addr: SocketAddr,
+        db_pool: &DatabasePool,
+        config: &ImapConfig,
+        session: &Arc<Mutex<ImapSession>>,
+        live: &ActiveSession,
+        state: StreamState,
+    ) -> Result<SessionExit>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
         loop {
-            line.clear();
+            let authenticated = session.lock().await.is_authenticated();
 
-            // Read line with timeout
+            // Read command (and its literals) with timeout
             let read_result = tokio::time::timeout(
                 std::time::Duration::from_secs((config.timeout_minutes * 60) as u64),
-                reader.read_line(&mut line),
+                Self::read_command(reader, writer, authenticated),
             )
             .await;
 
             match read_result {
-                Ok(Ok(0)) => {
+                Ok(Ok(CommandRead::Closed)) => {
                     // Connection closed
                     info!("Connection closed by client {}", addr);
-                    break;
+                    return Ok(SessionExit::Closed);
                 }
-                Ok(Ok(_)) => {
-                    debug!("Received from {}: {}", addr, line.trim());
+                Ok(Ok(CommandRead::Rejected)) => continue,
+                Ok(Ok(CommandRead::Command(command))) => {
+                    let first_line = command.split(|&b| b == b'\n').next().unwrap_or_default();
+                    debug!(
+                        "Received from {}: {}",
+                        addr,
+                        String::from_utf8_lossy(first_line).trim()
+                    );
 
                     // Parse command
-                    let parsed = ImapParser::parse(&line);
-                    let mut do_starttls = false;
+                    let parsed = ImapParser::parse_bytes(&command);
+                    let maintenance = config.maintenance.status().await;
+                    let alert = Self::maintenance_alert(session, maintenance.as_ref()).await;
+
+                    // Announce what changed in the selected mailbox before the
+                    // command sees it, unless the command leaves the mailbox
+                    let updates = match &parsed {
+                        Some(cmd)
+                            if !matches!(
+                                cmd.command,
+                                ImapCommand::Select { .. }
+                                    | ImapCommand::Examine { .. }
+                                    | ImapCommand::Close
+                                    | ImapCommand::Logout
+                            ) =>
+                        {
+                            let allow_expunge = !cmd.command.uses_sequence_numbers();
+                            Self::sync_selected(session, db_pool, allow_expunge).await
+                        }
+                        _ => String::new(),
+                    };
+                    if !updates.is_empty() {
+                        writer.lock().await.write_all(updates.as_bytes()).await?;
+                    }
+
+                    // Failed logins count towards the per-IP ban
+                    let login_tag = match &parsed {
+                        Some(cmd)
+                            if matches!(
+                                cmd.command,
+                                ImapCommand::Login { .. } | ImapCommand::Authenticate { .. }
+                            ) =>
+                        {
+                            Some(cmd.tag.clone())
+                        }
+                        _ => None,
+                    };
+
+                    let mut exit = None;
                     let response = match parsed {
+                        Some(cmd) if cmd.command.is_write() && maintenance.is_some() => {
+                            let message = maintenance.map(|m| m.message).unwrap_or_default();
+                            ImapResponse::no(&cmd.tag, &format!("[UNAVAILABLE] {}", message))
+                        }
                         Some(cmd) => match cmd.command {
                             ImapCommand::StartTls => {
-                                let sess = session.lock().await;
-                                if sess.is_authenticated() {
+                                if state.tls {
+                                    ImapResponse::bad(&cmd.tag, "TLS already active")
+                                } else if session.lock().await.is_authenticated() {
                                     ImapResponse::bad(
                                         &cmd.tag,
                                         "STARTTLS not allowed after authentication",
                                     )
-                                } else if !config.starttls || tls_acceptor.is_none() {
+                                } else if !state.starttls {
                                     ImapResponse::bad(&cmd.tag, "STARTTLS not available")
+                                } else if !reader.buffer().is_empty() {
+                                    // Data pipelined after STARTTLS would be processed as if
+                                    // it had been sent over TLS (command injection)
+                                    warn!("Pipelined data after STARTTLS from {}", addr);
+                                    ImapResponse::bad(&cmd.tag, "STARTTLS must not be pipelined")
                                 } else {
-                                    do_starttls = true;
+                                    exit = Some(SessionExit::StartTls);
                                     ImapResponse::ok(&cmd.tag, "Begin TLS negotiation now")
                                 }
                             }
-                            ImapCommand::Capability => {
-                                let advertise_starttls = config.starttls && tls_acceptor.is_some();
-                                format!(
-                                    "{}{}",
-                                    ImapResponse::capability_with_starttls(advertise_starttls),
-                                    ImapResponse::ok(&cmd.tag, "CAPABILITY completed")
+                            ImapCommand::Compress { mechanism } => {
+                                if state.compressed {
+                                    ImapResponse::no(
+                                        &cmd.tag,
+                                        "[COMPRESSIONACTIVE] DEFLATE active via COMPRESS",
+                                    )
+                                } else if !session.lock().await.is_authenticated() {
+                                    ImapResponse::no(&cmd.tag, "Not authenticated")
+                                } else if mechanism != "DEFLATE" {
+                                    ImapResponse::bad(&cmd.tag, "Unsupported compression mechanism")
+                                } else {
+                                    exit = Some(SessionExit::Compress);
+                                    ImapResponse::ok(&cmd.tag, "DEFLATE active")
+                                }
+                            }
+                            ImapCommand::Authenticate {
+                                mechanism,
+                                initial_response,
+                            } => {
+                                Self::handle_authenticate(
+                                    &cmd.tag,
+                                    &mechanism,
+                                    initial_response,
+                                    reader,
+                                    writer,
+                                    session,
+                                    db_pool,
+                                    config,
+                                )
+                                .await?
+                            }
+                            ImapCommand::Fetch {
+                                sequence,
+                                items,
+                                uid,
+                            } => {
+                                Self::handle_fetch(
+                                    &cmd.tag,
+                                    &sequence,
+                                    &items,
+                                    uid,
+                                    session,
+                                    db_pool,
+                                    &Self::storage_path(session, config).await,
+                                    writer,
                                 )
+                                .await?
                             }
+                            ImapCommand::Capability => format!(
+                                "{}{}",
+                                ImapResponse::capability_with_starttls(state.starttls),
+                                ImapResponse::ok(&cmd.tag, "CAPABILITY completed")
+                            ),
                             other => {
                                 let tagged = TaggedCommand {
                                     tag: cmd.tag,
                                     command: other,
                                 };
-                                Self::handle_command(
-                                    tagged,
-                                    &session,
-                                    &db_pool,
-                                    &config.storage_path,
-                                )
-                                .await
+                                Self::handle_command(tagged, session, db_pool, config).await
                             }
                         },
                         None => "* BAD Invalid command\r\n".to_string(),
@@ -238,42 +767,51 @@ impl ImapServer {
                     // Send response
                     {
                         let mut w = writer.lock().await;
+                        if let Some(alert) = alert {
+                            w.write_all(alert.as_bytes()).await?;
+                        }
                         w.write_all(response.as_bytes()).await?;
                         w.flush().await?;
                     }
+                    Self::report_session(session, live).await;
 
-                    if do_starttls {
-                        let acceptor = tls_acceptor.clone().ok_or_else(|| {
-                            anyhow!("IMAP STARTTLS requested without configured acceptor")
-                        })?;
-
-                        let read_half = reader.into_inner();
-                        let writer_half = Arc::try_unwrap(writer)
-                            .map_err(|_| anyhow!("Failed to unwrap IMAP writer during STARTTLS"))?
-                            .into_inner();
-                        let tcp_stream = read_half.reunite(writer_half).map_err(|_| {
-                            anyhow!("Failed to reunite IMAP stream halves during STARTTLS")
-                        })?;
-                        let tls_stream = acceptor.accept(tcp_stream).await?;
-                        info!("IMAP STARTTLS negotiation completed for {}", addr);
-
-                        return Self::handle_tls_connection(
-                            tls_stream, addr, db_pool, config, session,
-                        )
-                        .await;
+                    if let Some(exit) = exit {
+                        return Ok(exit);
                     }
 
-                    // Check if we should close
-                    {
-                        let sess = session.lock().await;
-                        if sess.state == SessionState::Logout {
-                            break;
+                    if let Some(tag) = &login_tag {
+                        Self::report_login(session, db_pool, config, addr, tag, &response).await;
+                    }
+
+                    if let (Some(tag), Some(limiter)) = (login_tag, &config.limiter) {
+                        let failed = response.starts_with(&format!("{} NO ", tag))
+                            && !session.lock().await.is_authenticated();
+                        if failed && limiter.auth_failed(addr.ip()) {
+                            if let (Some(security), Some(username)) = (
+                                &config.security,
+                                session.lock().await.login_username.clone(),
+                            ) {
+                                security.login_blocked(db_pool, &username, addr.ip(), "imap");
+                            }
+                            let mut w = writer.lock().await;
+                            w.write_all(
+                                ImapResponse::bye("Too many failed logins, try again later")
+                                    .as_bytes(),
+                            )
+                            .await?;
+                            w.flush().await?;
+                            return Ok(SessionExit::Closed);
                         }
                     }
+
+                    // Check if we should close
+                    if session.lock().await.state == SessionState::Logout {
+                        return Ok(SessionExit::Closed);
+                    }
                 }
                 Ok(Err(e)) => {
                     error!("Read error from {}: {}", addr, e);
-                    break;
+                    return Ok(SessionExit::Closed);
                 }
                 Err(_) => {
                     // Timeout
@@ -281,140 +819,295 @@ impl ImapServer {
                     let mut w = writer.lock().await;
                     w.write_all(ImapResponse::bye("Connection timeout").as_bytes())
                         .await?;
-                    break;
+                    w.flush().await?;
+                    return Ok(SessionExit::Closed);
                 }
             }
         }
-
-        info!("IMAP connection closed for {}", addr);
-        Ok(())
     }
 
-    async fn handle_tls_connection(
-        tls_stream: tokio_rustls::server::TlsStream<TcpStream>,
+    /// Report a successful LOGIN or AUTHENTICATE to the security notifier
+    async fn report_login(
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+        config: &ImapConfig,
         addr: SocketAddr,
-        db_pool: DatabasePool,
-        config: ImapConfig,
-        session: Arc<Mutex<ImapSession>>,
-    ) -> Result<()> {
-        let (reader, writer) = tokio::io::split(tls_stream);
-        let mut reader = BufReader::new(reader);
-        let writer = Arc::new(Mutex::new(writer));
-        let mut line = String::new();
-
-        loop {
-            line.clear();
-            let read_result = tokio::time::timeout(
-                std::time::Duration::from_secs((config.timeout_minutes * 60) as u64),
-                reader.read_line(&mut line),
-            )
-            .await;
-
-            match read_result {
-                Ok(Ok(0)) => break,
-                Ok(Ok(_)) => {
-                    let response = match ImapParser::parse(&line) {
-                        Some(cmd) => match cmd.command {
-                            ImapCommand::StartTls => {
-                                ImapResponse::bad(&cmd.tag, "TLS already active")
-                            }
-                            ImapCommand::Capability => format!(
-                                "{}{}",
-                                ImapResponse::capability_with_starttls(false),
-                                ImapResponse::ok(&cmd.tag, "CAPABILITY completed")
-                            ),
-                            other => {
-                                let tagged = TaggedCommand {
-                                    tag: cmd.tag,
-                                    command: other,
-                                };
-                                Self::handle_command(
-                                    tagged,
-                                    &session,
-                                    &db_pool,
-                                    &config.storage_path,
-                                )
-                                .await
-                            }
-                        },
-                        None => "* BAD Invalid command\r\n".to_string(),
-                    };
+        tag: &str,
+        response: &str,
+    ) {
+        let Some(security) = &config.security else {
+            return;
+        };
+        if !response.starts_with(&format!("{} OK ", tag)) {
+            return;
+        }
+        let sess = session.lock().await;
+        if let (Some(user_id), Some(tenant_id)) = (sess.user_id, sess.tenant_id) {
+            security.login_succeeded(db_pool, user_id, tenant_id, addr.ip(), "imap");
+        }
+    }
 
-                    {
-                        let mut w = writer.lock().await;
-                        w.write_all(response.as_bytes()).await?;
-                        w.flush().await?;
-                    }
+    /// Show the session's state and user in the session registry
+    async fn report_session(session: &Arc<Mutex<ImapSession>>, live: &ActiveSession) {
+        let session = session.lock().await;
+        live.set_state(session.state.as_str());
+        if let Some(email) = &session.user_email {
+            live.set_user(email, session.tenant_id);
+        }
+    }
 
-                    let sess = session.lock().await;
-                    if sess.state == SessionState::Logout {
-                        break;
-                    }
-                }
-                Ok(Err(e)) => {
-                    error!("TLS read error from {}: {}", addr, e);
-                    break;
-                }
-                Err(_) => {
-                    warn!("TLS connection timeout for {}", addr);
-                    let mut w = writer.lock().await;
-                    w.write_all(ImapResponse::bye("Connection timeout").as_bytes())
-                        .await?;
-                    break;
-                }
+    /// The ALERT to send before the next response, once per maintenance window
+    async fn maintenance_alert(
+        session: &Arc<Mutex<ImapSession>>,
+        maintenance: Option<&MaintenanceStatus>,
+    ) -> Option<String> {
+        let mut sess = session.lock().await;
+        match maintenance {
+            Some(status) if !sess.maintenance_alerted => {
+                sess.maintenance_alerted = true;
+                Some(ImapResponse::alert(&format!(
+                    "{} (read-only, changes are refused)",
+                    status.message
+                )))
+            }
+            Some(_) => None,
+            None => {
+                sess.maintenance_alerted = false;
+                None
             }
         }
-
-        info!("IMAP TLS connection closed for {}", addr);
-        Ok(())
     }
 
-    /// Handle a parsed IMAP command
-    async fn handle_command(
-        cmd: TaggedCommand,
+    /// Bring the selected mailbox up to date with changes made elsewhere,
+    /// returning the untagged responses that announce them
+    ///
+    /// Only the counters of the mailbox row are read unless something
+    /// changed: every message added or moved in takes a UID from
+    /// `uid_next`, and every expunge lowers `message_count`. Expunges are
+    /// announced only when `allow_expunge` is set; until then expunged
+    /// messages keep their sequence numbers.
+    async fn sync_selected(
         session: &Arc<Mutex<ImapSession>>,
         db_pool: &DatabasePool,
-        storage_path: &PathBuf,
+        allow_expunge: bool,
     ) -> String {
-        let tag = &cmd.tag;
-
-        match cmd.command {
-            // Any state commands
-            ImapCommand::Capability => {
-                format!(
-                    "{}{}",
-                    ImapResponse::capability(),
-                    ImapResponse::ok(tag, "CAPABILITY completed")
-                )
+        let (mailbox_id, exists, last_uid, known_uid_next) = {
+            let sess = session.lock().await;
+            match &sess.selected_mailbox {
+                Some(selected) if sess.is_selected() => (
+                    selected.id,
+                    selected.exists,
+                    selected.last_uid(),
+                    selected.uid_next,
+                ),
+                _ => return String::new(),
             }
-            ImapCommand::Noop => {
-                session.lock().await.update_activity();
-                ImapResponse::ok(tag, "NOOP completed")
+        };
+
+        let pool = db_pool.pool();
+
+        let counters: Result<(i64, i64), _> =
+            sqlx::query_as("SELECT message_count, uid_next FROM mailboxes WHERE id = $1")
+                .bind(mailbox_id)
+                .fetch_one(pool)
+                .await;
+        let uid_next = match counters {
+            Ok((count, uid_next))
+                if count == i64::from(exists) && uid_next <= i64::from(known_uid_next) =>
+            {
+                return String::new();
             }
-            ImapCommand::Logout => {
-                session.lock().await.logout();
-                format!(
-                    "{}{}",
-                    ImapResponse::bye("Logging out"),
-                    ImapResponse::ok(tag, "LOGOUT completed")
-                )
+            Ok((_, uid_next)) => uid_next as u32,
+            Err(e) => {
+                warn!("Failed to check mailbox {} for changes: {}", mailbox_id, e);
+                return String::new();
             }
-            ImapCommand::StartTls => ImapResponse::bad(tag, "Use STARTTLS before authentication"),
+        };
 
-            // Authentication
-            ImapCommand::Login { username, password } => {
-                Self::handle_login(tag, &username, &password, session, db_pool).await
+        // Without expunges only the messages after the known ones are needed
+        let after_uid = if allow_expunge { 0 } else { last_uid };
+        let uids: Vec<i64> = match sqlx::query_scalar(
+            "SELECT uid FROM messages WHERE mailbox_id = $1 AND uid > $2 ORDER BY uid ASC",
+        )
+        .bind(mailbox_id)
+        .bind(i64::from(after_uid))
+        .fetch_all(pool)
+        .await
+        {
+            Ok(uids) => uids,
+            Err(e) => {
+                warn!("Failed to load UIDs of mailbox {}: {}", mailbox_id, e);
+                return String::new();
             }
-            ImapCommand::Authenticate {
-                mechanism,
-                initial_response,
-            } => {
-                // For now, only support PLAIN
-                if mechanism != "PLAIN" {
-                    return ImapResponse::no(tag, "Unsupported authentication mechanism");
+        };
+        let uids: Vec<u32> = uids.into_iter().map(|uid| uid as u32).collect();
+
+        let mut sess = session.lock().await;
+        let selected = match sess.selected_mailbox.as_mut() {
+            Some(selected) if selected.id == mailbox_id => selected,
+            _ => return String::new(),
+        };
+
+        let mut response = String::new();
+        if allow_expunge {
+            for seq in selected.expunge_missing(&uids) {
+                response.push_str(&ImapResponse::expunge(seq));
+            }
+        }
+        if selected.append_uids(&uids) {
+            response.push_str(&ImapResponse::exists(selected.exists));
+        }
+        // UIDs taken by messages already gone need not be looked for again
+        selected.uid_next = selected.uid_next.max(uid_next);
+        response
+    }
+
+    /// Read one command, including any literals
+    ///
+    /// Synchronizing literals (`{n}`) are acknowledged with a continuation
+    /// request before their data is read; LITERAL- literals (`{n+}`) follow
+    /// the line directly, so they and any literal sent before authentication
+    /// are limited to `MAX_SMALL_LITERAL` octets. Literal data is buffered as
+    /// it arrives, never ahead of it.
+    async fn read_command<R, W>(
+        reader: &mut BufReader<R>,
+        writer: &Arc<Mutex<W>>,
+        authenticated: bool,
+    ) -> std::io::Result<CommandRead>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut command = Vec::new();
+
+        loop {
+            let start = command.len();
+            let read = (&mut *reader)
+                .take(MAX_LINE_LENGTH as u64)
+                .read_until(b'\n', &mut command)
+                .await?;
+            if read == 0 {
+                return Ok(CommandRead::Closed);
+            }
+            if !command.ends_with(b"\n") {
+                if read < MAX_LINE_LENGTH || !skip_line(reader).await? {
+                    return Ok(CommandRead::Closed);
+                }
+                Self::reject_command(writer, &command, ImapResponse::bad, "Command line too long")
+                    .await?;
+                return Ok(CommandRead::Rejected);
+            }
+
+            let line = &command[start..];
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let Some(marker) = ImapParser::literal_marker(line) else {
+                return Ok(CommandRead::Command(command));
+            };
+
+            let limit = if authenticated && marker.synchronizing {
+                MAX_COMMAND_SIZE
+            } else {
+                MAX_SMALL_LITERAL
+            };
+            let too_large = match command.len().checked_add(marker.len) {
+                Some(total) => marker.len > limit || total > MAX_COMMAND_SIZE,
+                None => true,
+            };
+
+            if too_large {
+                if !marker.synchronizing {
+                    // The literal is already on its way and cannot be skipped
+                    // without reading it (RFC 7888 section 4)
+                    Self::reject_command(writer, &command, ImapResponse::bad, "Literal too large")
+                        .await?;
+                    let mut w = writer.lock().await;
+                    w.write_all(ImapResponse::bye("Literal too large").as_bytes())
+                        .await?;
+                    w.flush().await?;
+                    return Ok(CommandRead::Closed);
                 }
-                // TODO: Handle PLAIN authentication
-                ImapResponse::no(tag, "AUTHENTICATE not yet implemented")
+                Self::reject_command(writer, &command, ImapResponse::no, "Literal too large")
+                    .await?;
+                return Ok(CommandRead::Rejected);
+            }
+
+            if marker.synchronizing {
+                let mut w = writer.lock().await;
+                w.write_all(ImapResponse::continue_req().as_bytes()).await?;
+                w.flush().await?;
+            }
+
+            let len = command.len();
+            (&mut *reader)
+                .take(marker.len as u64)
+                .read_to_end(&mut command)
+                .await?;
+            if command.len() - len < marker.len {
+                return Ok(CommandRead::Closed);
+            }
+        }
+    }
+
+    /// Send a tagged `[TOOBIG]` rejection of a command that was not read
+    /// in full
+    async fn reject_command<W: AsyncWrite + Unpin>(
+        writer: &Arc<Mutex<W>>,
+        command: &[u8],
+        response: fn(&str, &str) -> String,
+        text: &str,
+    ) -> std::io::Result<()> {
+        let tag = command
+            .split(|b| b.is_ascii_whitespace())
+            .next()
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        let response = response(&tag, &format!("[TOOBIG] {}", text));
+        let mut w = writer.lock().await;
+        w.write_all(response.as_bytes()).await?;
+        w.flush().await
+    }
+
+    /// Handle a parsed IMAP command
+    async fn handle_command(
+        cmd: TaggedCommand,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+        config: &ImapConfig,
+    ) -> String {
+        let tag = &cmd.tag;
+        let storage_path = &Self::storage_path(session, config).await;
+
+        match cmd.command {
+            // Any state commands
+            ImapCommand::Capability => {
+                format!(
+                    "{}{}",
+                    ImapResponse::capability(),
+                    ImapResponse::ok(tag, "CAPABILITY completed")
+                )
+            }
+            ImapCommand::Noop => {
+                session.lock().await.update_activity();
+                ImapResponse::ok(tag, "NOOP completed")
+            }
+            ImapCommand::Logout => {
+                session.lock().await.logout();
+                format!(
+                    "{}{}",
+                    ImapResponse::bye("Logging out"),
+                    ImapResponse::ok(tag, "LOGOUT completed")
+                )
+            }
+            ImapCommand::StartTls => ImapResponse::bad(tag, "Use STARTTLS before authentication"),
+
+            // Authentication
+            ImapCommand::Login { username, password } => {
+                Self::handle_login(tag, &username, &password, session, db_pool, config).await
+            }
+            ImapCommand::Authenticate { .. } => {
+                // The SASL exchange needs the connection and is handled by the connection loop
+                ImapResponse::no(tag, "AUTHENTICATE not available")
             }
 
             // Authenticated state commands
@@ -428,8 +1121,7 @@ impl ImapServer {
                 Self::handle_list(tag, &reference, &pattern, session, db_pool).await
             }
             ImapCommand::Lsub { reference, pattern } => {
-                // LSUB returns subscribed mailboxes - for now, same as LIST
-                Self::handle_list(tag, &reference, &pattern, session, db_pool).await
+                Self::handle_lsub(tag, &reference, &pattern, session, db_pool).await
             }
             ImapCommand::Status { mailbox, items } => {
                 Self::handle_status(tag, &mailbox, &items, session, db_pool).await
@@ -453,16 +1145,25 @@ impl ImapServer {
                     ImapResponse::no(tag, "No mailbox selected")
                 }
             }
-            ImapCommand::Fetch {
-                sequence,
-                items,
-                uid,
-            } => {
-                Self::handle_fetch(tag, &sequence, &items, uid, session, db_pool, storage_path)
-                    .await
+            ImapCommand::Fetch { .. } => {
+                // Literals are streamed to the connection, so FETCH is handled by
+                // the connection loop
+                ImapResponse::no(tag, "FETCH not available")
+            }
+            ImapCommand::Compress { .. } => {
+                // Wrapping the streams needs the connection
+                ImapResponse::no(tag, "COMPRESS not available")
             }
             ImapCommand::Search { criteria, uid } => {
-                Self::handle_search(tag, &criteria, uid, session, db_pool).await
+                Self::handle_search(
+                    tag,
+                    &criteria,
+                    uid,
+                    session,
+                    db_pool,
+                    config.search.as_ref(),
+                )
+                .await
             }
 
             // Write operations - Mailbox management
@@ -515,6 +1216,7 @@ impl ImapServer {
                     session,
                     db_pool,
                     storage_path,
+                    &config.storage_layout,
                 )
                 .await
             }
@@ -533,6 +1235,35 @@ impl ImapServer {
                     ImapResponse::ok(tag, "NAMESPACE completed")
                 )
             }
+            ImapCommand::GetMetadata {
+                mailbox,
+                entries,
+                options,
+            } => {
+                Self::handle_getmetadata(tag, &mailbox, &entries, &options, session, db_pool).await
+            }
+            ImapCommand::SetMetadata { mailbox, entries } => {
+                Self::handle_setmetadata(tag, &mailbox, &entries, session, db_pool).await
+            }
+            ImapCommand::SetAcl {
+                mailbox,
+                identifier,
+                rights,
+            } => Self::handle_setacl(tag, &mailbox, &identifier, &rights, session, db_pool).await,
+            ImapCommand::DeleteAcl {
+                mailbox,
+                identifier,
+            } => Self::handle_deleteacl(tag, &mailbox, &identifier, session, db_pool).await,
+            ImapCommand::GetAcl { mailbox } => {
+                Self::handle_getacl(tag, &mailbox, session, db_pool).await
+            }
+            ImapCommand::ListRights {
+                mailbox,
+                identifier,
+            } => Self::handle_listrights(tag, &mailbox, &identifier, session, db_pool).await,
+            ImapCommand::MyRights { mailbox } => {
+                Self::handle_myrights(tag, &mailbox, session, db_pool).await
+            }
 
             ImapCommand::Unknown { command } => {
                 ImapResponse::bad(tag, &format!("Unknown command: {}", command))
@@ -547,7 +1278,204 @@ impl ImapServer {
         password: &str,
         session: &Arc<Mutex<ImapSession>>,
         db_pool: &DatabasePool,
+        config: &ImapConfig,
+    ) -> String {
+        session.lock().await.login_username = Some(username.to_string());
+        match Self::verify_password(username, password, db_pool).await {
+            Ok(user) => Self::open_session(tag, "LOGIN", user, session, db_pool, config).await,
+            Err(reason) => ImapResponse::no(tag, reason),
+        }
+    }
+
+    /// Authenticate the session of a user whose credentials were verified,
+    /// unless IMAP is switched off for them or their tenant
+    async fn open_session(
+        tag: &str,
+        command: &str,
+        (user_id, tenant_id, email): (Uuid, Uuid, String),
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+        config: &ImapConfig,
     ) -> String {
+        match config
+            .services
+            .check(db_pool, tenant_id, user_id, MailService::Imap)
+            .await
+        {
+            Ok(None) => {
+                let storage_path = match Self::pinned_storage_path(tenant_id, config).await {
+                    Ok(storage_path) => storage_path,
+                    Err(e @ mairust_common::Error::Residency(_)) => {
+                        info!("IMAP login of {} refused: {}", email, e);
+                        return ImapResponse::no(
+                            tag,
+                            &format!("[UNAVAILABLE] {}", e.public_message()),
+                        );
+                    }
+                    Err(e) => {
+                        error!("Failed to check the data region of {}: {}", email, e);
+                        return ImapResponse::no(
+                            tag,
+                            "[UNAVAILABLE] Temporary authentication failure",
+                        );
+                    }
+                };
+                let mut session = session.lock().await;
+                session.authenticate(user_id, tenant_id, email);
+                session.storage_path = storage_path;
+                ImapResponse::ok(tag, &format!("{} completed", command))
+            }
+            Ok(Some(refusal)) => {
+                info!("IMAP login of {} refused: {:?}", email, refusal);
+                ImapResponse::no(
+                    tag,
+                    &format!("[CONTACTADMIN] {}", refusal.message(MailService::Imap)),
+                )
+            }
+            Err(e) => {
+                error!("Failed to check IMAP access of {}: {}", email, e);
+                ImapResponse::no(tag, "[UNAVAILABLE] Temporary authentication failure")
+            }
+        }
+    }
+
+    /// Storage root of a tenant pinned to this server's region
+    ///
+    /// Fails when the tenant is pinned to another region.
+    async fn pinned_storage_path(
+        tenant_id: Uuid,
+        config: &ImapConfig,
+    ) -> mairust_common::Result<Option<PathBuf>> {
+        match &config.residency {
+            Some(residency) => Ok(residency
+                .storage_for(tenant_id)
+                .await?
+                .map(|storage| storage.path.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// Storage root of the session's message files
+    async fn storage_path(session: &Arc<Mutex<ImapSession>>, config: &ImapConfig) -> PathBuf {
+        session
+            .lock()
+            .await
+            .storage_path
+            .clone()
+            .unwrap_or_else(|| config.storage_path.clone())
+    }
+
+    /// Handle AUTHENTICATE command, running the SASL challenge/response exchange
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_authenticate<R, W>(
+        tag: &str,
+        mechanism: &str,
+        initial_response: Option<String>,
+        reader: &mut R,
+        writer: &Arc<Mutex<W>>,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+        config: &ImapConfig,
+    ) -> Result<String>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        if session.lock().await.is_authenticated() {
+            return Ok(ImapResponse::bad(tag, "Already authenticated"));
+        }
+
+        let mechanism = match SaslMechanism::parse(mechanism) {
+            Some(m) => m,
+            None => {
+                return Ok(ImapResponse::no(
+                    tag,
+                    "Unsupported authentication mechanism",
+                ))
+            }
+        };
+        let timeout = std::time::Duration::from_secs((config.timeout_minutes * 60) as u64);
+
+        // The first response is either sent inline (SASL-IR) or after an initial challenge
+        let first = match initial_response {
+            Some(response) => response,
+            None => {
+                let prompt = if mechanism == SaslMechanism::Login {
+                    sasl::LOGIN_USERNAME_CHALLENGE
+                } else {
+                    ""
+                };
+                match sasl::exchange(reader, writer, &sasl::challenge(prompt), timeout).await? {
+                    Some(response) => response,
+                    None => return Ok(ImapResponse::bad(tag, "AUTHENTICATE cancelled")),
+                }
+            }
+        };
+        let data = match sasl::decode_response(&first) {
+            Some(data) => data,
+            None => return Ok(ImapResponse::bad(tag, "Invalid base64 in SASL response")),
+        };
+
+        let credentials = match mechanism {
+            SaslMechanism::Plain => sasl::parse_plain(&data),
+            SaslMechanism::Login => {
+                let challenge = sasl::challenge(sasl::LOGIN_PASSWORD_CHALLENGE);
+                let password = match sasl::exchange(reader, writer, &challenge, timeout).await? {
+                    Some(response) => {
+                        sasl::decode_response(&response).and_then(|p| String::from_utf8(p).ok())
+                    }
+                    None => return Ok(ImapResponse::bad(tag, "AUTHENTICATE cancelled")),
+                };
+                String::from_utf8(data)
+                    .ok()
+                    .filter(|u| !u.is_empty())
+                    .zip(password)
+                    .map(|(username, password)| SaslCredentials::Password { username, password })
+            }
+            SaslMechanism::OAuthBearer => sasl::parse_oauthbearer(&data),
+            SaslMechanism::XOAuth2 => sasl::parse_xoauth2(&data),
+        };
+        let credentials = match credentials {
+            Some(credentials) => credentials,
+            None => return Ok(ImapResponse::bad(tag, "Malformed SASL response")),
+        };
+
+        session.lock().await.login_username = match &credentials {
+            SaslCredentials::Password { username, .. } => Some(username.clone()),
+            SaslCredentials::Bearer { username, .. } => username.clone(),
+        };
+        let result = match credentials {
+            SaslCredentials::Password { username, password } => {
+                Self::verify_password(&username, &password, db_pool).await
+            }
+            SaslCredentials::Bearer { username, token } => {
+                Self::verify_bearer_token(username.as_deref(), &token, db_pool).await
+            }
+        };
+
+        match result {
+            Ok(user) => {
+                Ok(Self::open_session(tag, "AUTHENTICATE", user, session, db_pool, config).await)
+            }
+            Err(reason) => {
+                if mechanism.is_bearer() {
+                    // OAuth failures are reported in a challenge the client must acknowledge
+                    sasl::exchange(reader, writer, &sasl::oauth_error_challenge(), timeout).await?;
+                }
+                Ok(ImapResponse::no(
+                    tag,
+                    &format!("[AUTHENTICATIONFAILED] {}", reason),
+                ))
+            }
+        }
+    }
+
+    /// Verify a username and password against the users table
+    async fn verify_password(
+        username: &str,
+        password: &str,
+        db_pool: &DatabasePool,
+    ) -> std::result::Result<(Uuid, Uuid, String), &'static str> {
         let pool = db_pool.pool();
 
         // Query user by email
@@ -560,31 +1488,154 @@ impl ImapServer {
         .ok()
         .flatten();
 
-        match user {
-            Some((user_id, tenant_id, email, password_hash, active)) => {
-                if !active {
-                    return ImapResponse::no(tag, "Account is disabled");
-                }
+        let (user_id, tenant_id, email, password_hash, active) =
+            user.ok_or("Invalid credentials")?;
+        if !active {
+            return Err("Account is disabled");
+        }
 
-                // Verify password using argon2
-                let password_valid = if let Ok(parsed_hash) = PasswordHash::new(&password_hash) {
-                    Argon2::default()
-                        .verify_password(password.as_bytes(), &parsed_hash)
-                        .is_ok()
-                } else {
-                    false
-                };
+        // Verify password using argon2
+        let password_valid = if let Ok(parsed_hash) = PasswordHash::new(&password_hash) {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed_hash)
+                .is_ok()
+        } else {
+            false
+        };
 
-                if password_valid {
-                    let mut sess = session.lock().await;
-                    sess.authenticate(user_id, tenant_id, email);
-                    ImapResponse::ok(tag, "LOGIN completed")
-                } else {
-                    ImapResponse::no(tag, "Invalid credentials")
-                }
+        if password_valid {
+            Ok((user_id, tenant_id, email))
+        } else {
+            Err("Invalid credentials")
+        }
+    }
+
+    /// Verify an OAuth bearer token against the API keys issued by the API
+    ///
+    /// The key must be bound to a user and carry the `mail:imap` scope, and
+    /// a username sent by the client must be that user's address.
+    async fn verify_bearer_token(
+        username: Option<&str>,
+        token: &str,
+        db_pool: &DatabasePool,
+    ) -> std::result::Result<(Uuid, Uuid, String), &'static str> {
+        let prefix = key_prefix(token).ok_or("Invalid token")?;
+        let repo = ApiKeyRepository::new(db_pool.clone());
+
+        let candidates = repo.find_by_prefix(prefix).await.map_err(|e| {
+            error!("Database error while looking up API key: {}", e);
+            "Temporary authentication failure"
+        })?;
+        let key = candidates
+            .into_iter()
+            .find(|k| k.verify(token))
+            .ok_or("Invalid token")?;
+        let user_id = Self::bearer_key_user(&key)?;
+
+        let user: Option<(Uuid, Uuid, String, bool)> = sqlx::query_as(
+            "SELECT id, tenant_id, email, active FROM users WHERE id = $1 AND tenant_id = $2",
+        )
+        .bind(user_id)
+        .bind(key.tenant_id)
+        .fetch_optional(db_pool.pool())
+        .await
+        .ok()
+        .flatten();
+
+        let (user_id, tenant_id, email, active) = user.ok_or("Invalid token")?;
+        if !active {
+            return Err("Account is disabled");
+        }
+        if let Some(username) = username {
+            if !username.eq_ignore_ascii_case(&email) {
+                warn!("API key {} presented for a different user", key.id);
+                return Err("Invalid token");
+            }
+        }
+
+        // Update last_used_at (fire and forget, don't fail auth on this)
+        let key_id = key.id;
+        tokio::spawn(async move {
+            if let Err(e) = repo.update_last_used(key_id).await {
+                error!("Failed to update API key last_used_at: {}", e);
             }
-            None => ImapResponse::no(tag, "Invalid credentials"),
+        });
+
+        Ok((user_id, tenant_id, email))
+    }
+
+    /// Check that an API key may be used for IMAP and return its user
+    fn bearer_key_user(key: &ApiKey) -> std::result::Result<Uuid, &'static str> {
+        if key.is_expired() {
+            return Err("Token expired");
+        }
+        if !key.has_scope(IMAP_SCOPE) {
+            warn!("API key {} lacks the {} scope", key.id, IMAP_SCOPE);
+            return Err("Token is not valid for IMAP");
         }
+        key.user_id.ok_or("Token is not bound to a user")
+    }
+
+    /// Resolve a mailbox name to a mailbox the user owns or has been granted access to
+    ///
+    /// Names under `Shared/` refer to mailboxes other users shared via ACLs;
+    /// all other names are looked up among the user's own mailboxes.
+    async fn resolve_mailbox(
+        mailbox_name: &str,
+        tenant_id: Uuid,
+        user_id: Uuid,
+        db_pool: &DatabasePool,
+    ) -> Result<Option<AccessibleMailbox>> {
+        if acl::is_shared_name(mailbox_name) {
+            let shared = MailboxAclRepository::new(db_pool.clone())
+                .shared_with(tenant_id, user_id)
+                .await?;
+            return Ok(shared
+                .into_iter()
+                .find(|m| acl::shared_mailbox_name(&m.owner_email, &m.address) == mailbox_name)
+                .map(|m| AccessibleMailbox {
+                    id: m.mailbox_id,
+                    uid_validity: m.uid_validity,
+                    uid_next: m.uid_next,
+                    message_count: m.message_count,
+                    unseen_count: m.unseen_count,
+                    owner_id: m.owner_id,
+                    rights: Rights::parse(&m.rights).unwrap_or_default(),
+                }));
+        }
+
+        // SECURITY: Must filter by user_id to prevent cross-user mailbox access
+        // INBOX is the user's address mailbox, never one of their folders
+        let query = if mailbox_name.to_uppercase() == "INBOX" {
+            sqlx::query_as::<_, (Uuid, i64, i64, i64, i64)>(
+                "SELECT id, uid_validity, uid_next, message_count, unseen_count FROM mailboxes
+                 WHERE tenant_id = $1 AND user_id = $2 AND quarantine_of IS NULL
+                 ORDER BY folder_path IS NOT NULL, created_at LIMIT 1",
+            )
+            .bind(tenant_id)
+            .bind(user_id)
+        } else {
+            sqlx::query_as::<_, (Uuid, i64, i64, i64, i64)>(
+                "SELECT id, uid_validity, uid_next, message_count, unseen_count FROM mailboxes
+                 WHERE tenant_id = $1 AND user_id = $2 AND folder_path = $3",
+            )
+            .bind(tenant_id)
+            .bind(user_id)
+            .bind(canonical_name(mailbox_name))
+        };
+
+        let mailbox = query.fetch_optional(db_pool.pool()).await?;
+        Ok(mailbox.map(
+            |(id, uid_validity, uid_next, message_count, unseen_count)| AccessibleMailbox {
+                id,
+                uid_validity,
+                uid_next,
+                message_count,
+                unseen_count,
+                owner_id: user_id,
+                rights: Rights::all(),
+            },
+        ))
     }
 
     /// Handle SELECT/EXAMINE command
@@ -613,58 +1664,60 @@ impl ImapServer {
 
         let pool = db_pool.pool();
 
-        // Find mailbox by address (INBOX is special)
-        let mailbox_query = if mailbox_name.to_uppercase() == "INBOX" {
-            // Get primary mailbox for user
-            sqlx::query_as::<_, (Uuid, String)>(
-                "SELECT id, address FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 LIMIT 1",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-        } else {
-            // SECURITY: Must filter by user_id to prevent cross-user mailbox access
-            sqlx::query_as::<_, (Uuid, String)>(
-                "SELECT id, address FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-            .bind(mailbox_name)
-        };
-
-        let mailbox_result = mailbox_query.fetch_optional(pool).await;
+        let mailbox_result = Self::resolve_mailbox(mailbox_name, tenant_id, user_id, db_pool).await;
 
         match mailbox_result {
-            Ok(Some((mailbox_id, mailbox_address))) => {
-                // Get messages for this mailbox
-                let messages: Vec<Message> = sqlx::query_as(
-                    "SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC",
+            Ok(Some(mailbox)) if !mailbox.rights.has('r') => {
+                ImapResponse::no(tag, "[NOPERM] Permission denied")
+            }
+            Ok(Some(mailbox)) => {
+                // Only the state needed for the sequence numbers and counts is
+                // loaded; messages created in the last 24 hours are recent
+                let messages: Vec<MessageState> = sqlx::query_as::<_, (i64, bool, bool)>(
+                    "SELECT uid, seen, created_at > NOW() - INTERVAL '24 hours'
+                     FROM messages WHERE mailbox_id = $1 ORDER BY uid ASC",
                 )
-                .bind(mailbox_id)
+                .bind(mailbox.id)
+                .fetch_all(pool)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(uid, seen, recent)| MessageState {
+                    uid: uid as u32,
+                    seen,
+                    recent,
+                })
+                .collect();
+                let in_use: Vec<String> = sqlx::query_scalar(
+                    "SELECT DISTINCT jsonb_array_elements_text(keywords)
+                     FROM messages WHERE mailbox_id = $1",
+                )
+                .bind(mailbox.id)
                 .fetch_all(pool)
                 .await
                 .unwrap_or_default();
 
-                let mut selected = SelectedMailbox::new(mailbox_id, mailbox_address.clone());
+                // Without any write right the mailbox can only be examined
+                let readonly = readonly || !mailbox.rights.allows_write();
+
+                let mut selected = SelectedMailbox::new(mailbox.id, mailbox_name.to_string());
                 selected.update_with_messages(&messages);
+                selected.uid_validity = mailbox.uid_validity as u32;
+                selected.uid_next = mailbox.uid_next as u32;
+                selected.rights = mailbox.rights;
 
                 let mut response = String::new();
 
-                // Send mailbox information
-                response.push_str(&ImapResponse::mailbox_flags(&[
-                    "\\Answered",
-                    "\\Flagged",
-                    "\\Deleted",
-                    "\\Seen",
-                    "\\Draft",
-                ]));
-                response.push_str(&ImapResponse::permanent_flags(&[
-                    "\\Answered",
-                    "\\Flagged",
-                    "\\Deleted",
-                    "\\Seen",
-                    "\\Draft",
-                    "\\*",
-                ]));
+                // Send mailbox information; keywords in use are announced with the
+                // system flags, and any other keyword may be created (\\*)
+                let keywords = mailbox_keywords(in_use);
+                let mut flags = vec!["\\Answered", "\\Flagged", "\\Deleted", "\\Seen", "\\Draft"];
+                flags.extend(keywords.iter().map(String::as_str));
+                response.push_str(&ImapResponse::mailbox_flags(&flags));
+                if !readonly && mailbox.rights.has('w') {
+                    flags.push("\\*");
+                }
+                response.push_str(&ImapResponse::permanent_flags(&flags));
                 response.push_str(&ImapResponse::exists(selected.exists));
                 response.push_str(&ImapResponse::recent(selected.recent));
 
@@ -694,7 +1747,7 @@ impl ImapServer {
             Ok(None) => ImapResponse::no(tag, "Mailbox not found"),
             Err(e) => {
                 error!("Database error in SELECT: {}", e);
-                ImapResponse::no(tag, "Internal server error")
+                failure(tag, e, "Internal server error")
             }
         }
     }
@@ -702,7 +1755,7 @@ impl ImapServer {
     /// Handle LIST command
     async fn handle_list(
         tag: &str,
-        _reference: &str,
+        reference: &str,
         pattern: &str,
         session: &Arc<Mutex<ImapSession>>,
         db_pool: &DatabasePool,
@@ -719,58 +1772,195 @@ impl ImapServer {
         let user_id = sess.user_id;
         drop(sess);
 
-        let pool = db_pool.pool();
+        // An empty pattern asks for the hierarchy delimiter
+        if pattern.is_empty() {
+            return format!(
+                "{}{}",
+                ImapResponse::list(&["\\Noselect"], "/", ""),
+                ImapResponse::ok(tag, "LIST completed")
+            );
+        }
+        let pattern = canonical_name(&format!("{}{}", reference, pattern));
 
-        // Get mailboxes for this tenant/user
-        let mailboxes: Vec<(String,)> = if let Some(uid) = user_id {
-            sqlx::query_as("SELECT address FROM mailboxes WHERE tenant_id = $1 AND user_id = $2")
-                .bind(tenant_id)
-                .bind(uid)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default()
-        } else {
-            sqlx::query_as("SELECT address FROM mailboxes WHERE tenant_id = $1")
-                .bind(tenant_id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default()
-        };
+        let names = Self::listed_mailboxes(tenant_id, user_id, db_pool)
+            .await
+            .into_iter()
+            .map(|(name, special_use, _)| (name, special_use))
+            .collect();
 
         let mut response = String::new();
+        for entry in list_entries(names) {
+            if !list_pattern_matches(&pattern, &entry.name) {
+                continue;
+            }
 
-        // Always include INBOX
-        response.push_str(&ImapResponse::list(&["\\HasNoChildren"], "/", "INBOX"));
-
-        // Filter mailboxes by pattern
-        for (address,) in mailboxes {
-            if pattern == "*"
-                || pattern == "%"
-                || address.contains(pattern.trim_matches('*').trim_matches('%'))
-            {
-                response.push_str(&ImapResponse::list(&["\\HasNoChildren"], "/", &address));
+            let mut attributes = Vec::new();
+            if entry.noselect {
+                attributes.push("\\Noselect");
             }
+            attributes.push(if entry.has_children {
+                "\\HasChildren"
+            } else {
+                "\\HasNoChildren"
+            });
+            attributes.extend(entry.special_use.map(|use_| use_.attribute()));
+            response.push_str(&ImapResponse::list(&attributes, "/", &entry.name));
         }
 
         response.push_str(&ImapResponse::ok(tag, "LIST completed"));
         response
     }
 
-    /// Handle STATUS command
-    async fn handle_status(
-        tag: &str,
-        mailbox_name: &str,
-        items: &[String],
-        session: &Arc<Mutex<ImapSession>>,
+    /// Mailboxes shown to a user by LIST and LSUB, with their special use
+    /// and ID
+    ///
+    /// INBOX always comes first, without an ID; mailboxes other users shared
+    /// with the user are listed under the Shared/ namespace.
+    async fn listed_mailboxes(
+        tenant_id: Uuid,
+        user_id: Option<Uuid>,
         db_pool: &DatabasePool,
-    ) -> String {
-        let sess = session.lock().await;
-        if !sess.is_authenticated() {
-            return ImapResponse::no(tag, "Not authenticated");
-        }
+    ) -> Vec<(String, Option<SpecialUse>, Option<Uuid>)> {
+        let pool = db_pool.pool();
 
-        let tenant_id = match sess.tenant_id {
-            Some(id) => id,
+        // Get folders for this tenant/user
+        let mailboxes: Vec<(Uuid, String, Option<String>)> = if let Some(uid) = user_id {
+            sqlx::query_as(
+                "SELECT id, folder_path, special_use FROM mailboxes
+                 WHERE tenant_id = $1 AND user_id = $2 AND folder_path IS NOT NULL",
+            )
+            .bind(tenant_id)
+            .bind(uid)
+            .fetch_all(pool)
+            .await
+            .unwrap_or_default()
+        } else {
+            sqlx::query_as(
+                "SELECT id, folder_path, special_use FROM mailboxes
+                 WHERE tenant_id = $1 AND folder_path IS NOT NULL",
+            )
+            .bind(tenant_id)
+            .fetch_all(pool)
+            .await
+            .unwrap_or_default()
+        };
+
+        // Always include INBOX; own folders carry their special-use attribute (RFC 6154)
+        let mut names = vec![("INBOX".to_string(), None, None)];
+        names.extend(mailboxes.into_iter().map(|(id, path, special_use)| {
+            let use_ = SpecialUse::of_folder(special_use.as_deref(), &path);
+            (path, use_, Some(id))
+        }));
+
+        // Mailboxes other users shared with this user, under the Shared/ namespace
+        if let Some(uid) = user_id {
+            match MailboxAclRepository::new(db_pool.clone())
+                .shared_with(tenant_id, uid)
+                .await
+            {
+                Ok(shared) => names.extend(
+                    shared
+                        .iter()
+                        .filter(|m| Rights::parse(&m.rights).unwrap_or_default().has('l'))
+                        .map(|m| {
+                            (
+                                acl::shared_mailbox_name(&m.owner_email, &m.address),
+                                None,
+                                Some(m.mailbox_id),
+                            )
+                        }),
+                ),
+                Err(e) => error!("Failed to list shared mailboxes: {}", e),
+            }
+        }
+
+        names
+    }
+
+    /// Handle LSUB command
+    ///
+    /// Lists the mailboxes the user has not unsubscribed from.
+    async fn handle_lsub(
+        tag: &str,
+        reference: &str,
+        pattern: &str,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let sess = session.lock().await;
+        if !sess.is_authenticated() {
+            return ImapResponse::no(tag, "Not authenticated");
+        }
+
+        let tenant_id = match sess.tenant_id {
+            Some(id) => id,
+            None => return ImapResponse::no(tag, "No tenant context"),
+        };
+        let user_id = sess.user_id;
+        drop(sess);
+
+        // An empty pattern asks for the hierarchy delimiter
+        if pattern.is_empty() {
+            return format!(
+                "{}{}",
+                ImapResponse::lsub(&["\\Noselect"], "/", ""),
+                ImapResponse::ok(tag, "LSUB completed")
+            );
+        }
+        let pattern = canonical_name(&format!("{}{}", reference, pattern));
+
+        let mut mailboxes = Self::listed_mailboxes(tenant_id, user_id, db_pool).await;
+
+        if let Some(uid) = user_id {
+            let unsubscribed = match MailboxSubscriptionRepository::new(db_pool.clone())
+                .unsubscribed(uid)
+                .await
+            {
+                Ok(unsubscribed) => unsubscribed,
+                Err(e) => {
+                    error!("Failed to load subscriptions: {}", e);
+                    return failure(tag, e, "Internal server error");
+                }
+            };
+
+            if !unsubscribed.is_empty() {
+                let inbox = Self::resolve_mailbox("INBOX", tenant_id, uid, db_pool)
+                    .await
+                    .ok()
+                    .flatten();
+                if let Some(entry) = mailboxes.first_mut() {
+                    entry.2 = inbox.map(|m| m.id);
+                }
+                mailboxes.retain(|(_, _, id)| !id.is_some_and(|id| unsubscribed.contains(&id)));
+            }
+        }
+
+        let subscribed: Vec<String> = mailboxes.into_iter().map(|(name, _, _)| name).collect();
+        let mut response = String::new();
+        for (name, noselect) in lsub_entries(&subscribed, &pattern) {
+            let attributes: &[&str] = if noselect { &["\\Noselect"] } else { &[] };
+            response.push_str(&ImapResponse::lsub(attributes, "/", &name));
+        }
+
+        response.push_str(&ImapResponse::ok(tag, "LSUB completed"));
+        response
+    }
+
+    /// Handle STATUS command
+    async fn handle_status(
+        tag: &str,
+        mailbox_name: &str,
+        items: &[String],
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let sess = session.lock().await;
+        if !sess.is_authenticated() {
+            return ImapResponse::no(tag, "Not authenticated");
+        }
+
+        let tenant_id = match sess.tenant_id {
+            Some(id) => id,
             None => return ImapResponse::no(tag, "No tenant context"),
         };
 
@@ -780,49 +1970,35 @@ impl ImapServer {
         };
         drop(sess);
 
-        let pool = db_pool.pool();
-
-        // Get mailbox
-        // SECURITY: Must filter by user_id to prevent cross-user mailbox access
-        let mailbox: Option<(Uuid,)> = sqlx::query_as(
-            "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
-        )
-        .bind(tenant_id)
-        .bind(user_id)
-        .bind(mailbox_name)
-        .fetch_optional(pool)
-        .await
-        .ok()
-        .flatten();
+        let mailbox = Self::resolve_mailbox(mailbox_name, tenant_id, user_id, db_pool)
+            .await
+            .ok()
+            .flatten();
 
         match mailbox {
-            Some((mailbox_id,)) => {
-                // Get message counts
-                let total: (i64,) =
-                    sqlx::query_as("SELECT COUNT(*) FROM messages WHERE mailbox_id = $1")
-                        .bind(mailbox_id)
-                        .fetch_one(pool)
-                        .await
-                        .unwrap_or((0,));
-
-                let unseen: (i64,) = sqlx::query_as(
-                    "SELECT COUNT(*) FROM messages WHERE mailbox_id = $1 AND seen = false",
-                )
-                .bind(mailbox_id)
-                .fetch_one(pool)
-                .await
-                .unwrap_or((0,));
-
+            Some(mailbox) if !mailbox.rights.has('r') => {
+                ImapResponse::no(tag, "[NOPERM] Permission denied")
+            }
+            // Counts come from the counters maintained on the mailbox row
+            Some(AccessibleMailbox {
+                uid_validity,
+                uid_next,
+                message_count,
+                unseen_count,
+                ..
+            }) => {
                 let mut status_items = Vec::new();
                 for item in items {
                     match item.to_uppercase().as_str() {
-                        "MESSAGES" => status_items.push(("MESSAGES".to_string(), total.0 as u32)),
-                        "UNSEEN" => status_items.push(("UNSEEN".to_string(), unseen.0 as u32)),
+                        "MESSAGES" => {
+                            status_items.push(("MESSAGES".to_string(), message_count as u32))
+                        }
+                        "UNSEEN" => status_items.push(("UNSEEN".to_string(), unseen_count as u32)),
                         "RECENT" => status_items.push(("RECENT".to_string(), 0)),
-                        "UIDNEXT" => {
-                            status_items.push(("UIDNEXT".to_string(), (total.0 + 1) as u32))
+                        "UIDNEXT" => status_items.push(("UIDNEXT".to_string(), uid_next as u32)),
+                        "UIDVALIDITY" => {
+                            status_items.push(("UIDVALIDITY".to_string(), uid_validity as u32))
                         }
-                        "UIDVALIDITY" => status_items.push(("UIDVALIDITY".to_string(), 1)),
                         _ => {}
                     }
                 }
@@ -838,7 +2014,12 @@ impl ImapServer {
     }
 
     /// Handle FETCH command
-    async fn handle_fetch(
+    ///
+    /// Untagged responses are written to the connection as they are built, so
+    /// that message literals can be streamed from storage in chunks; the tagged
+    /// completion is returned.
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_fetch<W>(
         tag: &str,
         sequence: &SequenceSet,
         items: &[FetchItem],
@@ -846,27 +2027,31 @@ impl ImapServer {
         session: &Arc<Mutex<ImapSession>>,
         db_pool: &DatabasePool,
         storage_path: &PathBuf,
-    ) -> String {
+        writer: &Arc<Mutex<W>>,
+    ) -> Result<String>
+    where
+        W: AsyncWrite + Unpin,
+    {
         let sess = session.lock().await;
         if !sess.is_selected() {
-            return ImapResponse::no(tag, "No mailbox selected");
+            return Ok(ImapResponse::no(tag, "No mailbox selected"));
         }
 
         let selected = match &sess.selected_mailbox {
             Some(s) => s.clone(),
-            None => return ImapResponse::no(tag, "No mailbox selected"),
+            None => return Ok(ImapResponse::no(tag, "No mailbox selected")),
         };
         drop(sess);
 
-        let pool = db_pool.pool();
-
-        // Get messages for the sequence set
-        let messages: Vec<Message> =
-            sqlx::query_as("SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC")
-                .bind(selected.id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
+        // Only the messages in the sequence set are loaded
+        let ranges = selected.uid_ranges(sequence, uid_mode);
+        let messages = match Self::messages_in(db_pool, selected.id, &ranges).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("FETCH in mailbox {} failed: {}", selected.id, e);
+                return Ok(failure(tag, e, "FETCH failed"));
+            }
+        };
 
         // Initialize storage for reading full message bodies
         let storage = match LocalStorage::from_path(storage_path) {
@@ -877,26 +2062,69 @@ impl ImapServer {
             }
         };
 
-        let mut response = String::new();
-        let max_seq = messages.len() as u32;
+        // The whole message is only loaded when its MIME structure is needed;
+        // BODY[] is streamed straight from the stored file
+        let needs_mime = items.iter().any(|item| match item {
+            FetchItem::BodyStructure | FetchItem::Body | FetchItem::Full => true,
+            FetchItem::BodySection { section, .. } | FetchItem::BodyPeek { section, .. } => {
+                !section.is_empty()
+            }
+            _ => false,
+        });
+        let needs_stored = !needs_mime
+            && items.iter().any(|item| {
+                matches!(
+                    item,
+                    FetchItem::BodySection { section, .. } | FetchItem::BodyPeek { section, .. }
+                        if section.is_empty()
+                )
+            });
 
-        for (idx, msg) in messages.iter().enumerate() {
-            let seq = (idx + 1) as u32;
-            let msg_uid = Self::message_id_to_uid(&msg.id);
+        for msg in &messages {
+            let msg_uid = Self::message_uid(msg);
 
-            // Check if this message is in the sequence set
-            let in_set = if uid_mode {
-                sequence.contains(msg_uid, msg_uid)
-            } else {
-                sequence.contains(seq, max_seq)
+            // Messages the client has not been told about yet are left out
+            let Some(seq) = selected.get_seq_by_uid(msg_uid) else {
+                continue;
             };
 
-            if !in_set {
-                continue;
-            }
+            // Read and parse the stored message once for structure and section items
+            let raw = match (&storage, needs_mime) {
+                (Some(storage), true) => match storage.read(&msg.storage_path).await {
+                    Ok(data) => Some(data),
+                    Err(e) => {
+                        warn!("Failed to read message from storage: {}", e);
+                        None
+                    }
+                },
+                _ => None,
+            };
+            let stored_size = match (&storage, needs_stored) {
+                (Some(storage), true) => match storage.size(&msg.storage_path).await {
+                    Ok(size) => Some(size),
+                    Err(e) => {
+                        warn!("Failed to stat message in storage: {}", e);
+                        None
+                    }
+                },
+                _ => None,
+            };
+            let mime = raw.as_deref().map(MimePart::parse);
+            let body_structure = |extensible: bool| match (&raw, &mime) {
+                (Some(raw), Some(mime)) => mime.body_structure(raw, extensible),
+                _ => {
+                    let lines = msg
+                        .body_preview
+                        .as_ref()
+                        .map(|p| p.lines().count() as u32)
+                        .unwrap_or(0);
+                    ImapResponse::format_body_structure_simple(msg.body_size as u64, lines)
+                }
+            };
 
             // Build FETCH response items
-            let mut fetch_items: Vec<(String, String)> = Vec::new();
+            let mut fetch_items: Vec<(String, FetchValue)> = Vec::new();
+            let keywords = msg.keywords_vec();
 
             for item in items {
                 match item {
@@ -907,18 +2135,23 @@ impl ImapServer {
                             msg.flagged,
                             msg.deleted,
                             msg.draft,
+                            &keywords,
                         );
-                        fetch_items.push(("FLAGS".to_string(), flags));
+                        fetch_items.push(("FLAGS".to_string(), FetchValue::Text(flags)));
                     }
                     FetchItem::Uid => {
-                        fetch_items.push(("UID".to_string(), msg_uid.to_string()));
+                        fetch_items
+                            .push(("UID".to_string(), FetchValue::Text(msg_uid.to_string())));
                     }
                     FetchItem::InternalDate => {
                         let date = ImapResponse::format_internal_date(&msg.received_at);
-                        fetch_items.push(("INTERNALDATE".to_string(), date));
+                        fetch_items.push(("INTERNALDATE".to_string(), FetchValue::Text(date)));
                     }
                     FetchItem::Rfc822Size => {
-                        fetch_items.push(("RFC822.SIZE".to_string(), msg.body_size.to_string()));
+                        fetch_items.push((
+                            "RFC822.SIZE".to_string(),
+                            FetchValue::Text(msg.body_size.to_string()),
+                        ));
                     }
                     FetchItem::Envelope => {
                         let headers = &msg.headers;
@@ -930,17 +2163,17 @@ impl ImapServer {
                             headers.get("cc").and_then(|v| v.as_str()),
                             msg.message_id_header.as_deref(),
                         );
-                        fetch_items.push(("ENVELOPE".to_string(), envelope));
+                        fetch_items.push(("ENVELOPE".to_string(), FetchValue::Text(envelope)));
+                    }
+                    FetchItem::BodyStructure => {
+                        fetch_items.push((
+                            "BODYSTRUCTURE".to_string(),
+                            FetchValue::Text(body_structure(true)),
+                        ));
                     }
-                    FetchItem::BodyStructure | FetchItem::Body => {
-                        let lines = msg
-                            .body_preview
-                            .as_ref()
-                            .map(|p| p.lines().count() as u32)
-                            .unwrap_or(0);
-                        let structure =
-                            ImapResponse::format_body_structure_simple(msg.body_size as u64, lines);
-                        fetch_items.push(("BODYSTRUCTURE".to_string(), structure));
+                    FetchItem::Body => {
+                        fetch_items
+                            .push(("BODY".to_string(), FetchValue::Text(body_structure(false))));
                     }
                     FetchItem::All => {
                         // FLAGS, INTERNALDATE, RFC822.SIZE, ENVELOPE
@@ -950,13 +2183,17 @@ impl ImapServer {
                             msg.flagged,
                             msg.deleted,
                             msg.draft,
+                            &keywords,
                         );
-                        fetch_items.push(("FLAGS".to_string(), flags));
+                        fetch_items.push(("FLAGS".to_string(), FetchValue::Text(flags)));
                         fetch_items.push((
                             "INTERNALDATE".to_string(),
-                            ImapResponse::format_internal_date(&msg.received_at),
+                            FetchValue::Text(ImapResponse::format_internal_date(&msg.received_at)),
+                        ));
+                        fetch_items.push((
+                            "RFC822.SIZE".to_string(),
+                            FetchValue::Text(msg.body_size.to_string()),
                         ));
-                        fetch_items.push(("RFC822.SIZE".to_string(), msg.body_size.to_string()));
                     }
                     FetchItem::Fast => {
                         // FLAGS, INTERNALDATE, RFC822.SIZE
@@ -966,13 +2203,17 @@ impl ImapServer {
                             msg.flagged,
                             msg.deleted,
                             msg.draft,
+                            &keywords,
                         );
-                        fetch_items.push(("FLAGS".to_string(), flags));
+                        fetch_items.push(("FLAGS".to_string(), FetchValue::Text(flags)));
                         fetch_items.push((
                             "INTERNALDATE".to_string(),
-                            ImapResponse::format_internal_date(&msg.received_at),
+                            FetchValue::Text(ImapResponse::format_internal_date(&msg.received_at)),
+                        ));
+                        fetch_items.push((
+                            "RFC822.SIZE".to_string(),
+                            FetchValue::Text(msg.body_size.to_string()),
                         ));
-                        fetch_items.push(("RFC822.SIZE".to_string(), msg.body_size.to_string()));
                     }
                     FetchItem::Full => {
                         // FLAGS, INTERNALDATE, RFC822.SIZE, ENVELOPE, BODY
@@ -982,64 +2223,58 @@ impl ImapServer {
                             msg.flagged,
                             msg.deleted,
                             msg.draft,
+                            &keywords,
                         );
-                        fetch_items.push(("FLAGS".to_string(), flags));
+                        fetch_items.push(("FLAGS".to_string(), FetchValue::Text(flags)));
                         fetch_items.push((
                             "INTERNALDATE".to_string(),
-                            ImapResponse::format_internal_date(&msg.received_at),
+                            FetchValue::Text(ImapResponse::format_internal_date(&msg.received_at)),
                         ));
-                        fetch_items.push(("RFC822.SIZE".to_string(), msg.body_size.to_string()));
-                        let lines = msg
-                            .body_preview
-                            .as_ref()
-                            .map(|p| p.lines().count() as u32)
-                            .unwrap_or(0);
                         fetch_items.push((
-                            "BODYSTRUCTURE".to_string(),
-                            ImapResponse::format_body_structure_simple(msg.body_size as u64, lines),
+                            "RFC822.SIZE".to_string(),
+                            FetchValue::Text(msg.body_size.to_string()),
                         ));
+                        fetch_items
+                            .push(("BODY".to_string(), FetchValue::Text(body_structure(false))));
                     }
-                    FetchItem::BodySection { section, .. }
-                    | FetchItem::BodyPeek { section, .. } => {
-                        // Read full message body from storage
-                        let body_key = format!("BODY[{}]", section);
-                        if let Some(ref storage) = storage {
-                            match storage.read(&msg.storage_path).await {
-                                Ok(data) => {
-                                    // Convert to UTF-8 (lossy) and use the converted string's
-                                    // byte length for the literal size to ensure consistency.
-                                    // This avoids mismatch when non-UTF-8 bytes are replaced.
-                                    let body = String::from_utf8_lossy(&data);
-                                    fetch_items.push((
-                                        body_key,
-                                        format!("{{{}}}\r\n{}", body.len(), body),
-                                    ));
-                                }
-                                Err(e) => {
-                                    // Fall back to body_preview if storage read fails
-                                    warn!("Failed to read message from storage: {}", e);
-                                    if let Some(preview) = &msg.body_preview {
-                                        fetch_items.push((
-                                            body_key,
-                                            format!("{{{}}}\r\n{}", preview.len(), preview),
-                                        ));
-                                    }
-                                }
+                    FetchItem::BodySection { section, partial }
+                    | FetchItem::BodyPeek { section, partial } => {
+                        // A partial response names only the origin octet
+                        let body_key = match partial {
+                            Some((origin, _)) => format!("BODY[{}]<{}>", section, origin),
+                            None => format!("BODY[{}]", section),
+                        };
+                        let value = if let (Some(raw), Some(mime)) = (&raw, &mime) {
+                            match mime.section(raw, section) {
+                                Some(data) => FetchValue::Literal(slice_partial(&data, *partial)),
+                                None => FetchValue::Text("NIL".to_string()),
                             }
+                        } else if let (true, Some(size)) = (section.is_empty(), stored_size) {
+                            let (offset, len) = partial_range(size, *partial);
+                            FetchValue::Stored { offset, len }
                         } else if let Some(preview) = &msg.body_preview {
-                            // No storage available, use preview
-                            fetch_items
-                                .push((body_key, format!("{{{}}}\r\n{}", preview.len(), preview)));
-                        }
+                            // Fall back to body_preview if the message could not be read
+                            FetchValue::Literal(slice_partial(preview.as_bytes(), *partial))
+                        } else {
+                            continue;
+                        };
+                        fetch_items.push((body_key, value));
                     }
                 }
             }
 
-            response.push_str(&ImapResponse::fetch(seq, &fetch_items));
+            let mut w = writer.lock().await;
+            write_fetch(
+                &mut *w,
+                seq,
+                &fetch_items,
+                storage.as_ref(),
+                &msg.storage_path,
+            )
+            .await?;
         }
 
-        response.push_str(&ImapResponse::ok(tag, "FETCH completed"));
-        response
+        Ok(ImapResponse::ok(tag, "FETCH completed"))
     }
 
     /// Handle SEARCH command
@@ -1049,6 +2284,7 @@ impl ImapServer {
         uid_mode: bool,
         session: &Arc<Mutex<ImapSession>>,
         db_pool: &DatabasePool,
+        search_config: Option<&MeilisearchConfig>,
     ) -> String {
         let sess = session.lock().await;
         if !sess.is_selected() {
@@ -1059,32 +2295,52 @@ impl ImapServer {
             Some(s) => s.clone(),
             None => return ImapResponse::no(tag, "No mailbox selected"),
         };
+        let tenant_id = match sess.tenant_id {
+            Some(id) => id,
+            None => return ImapResponse::no(tag, "No tenant context"),
+        };
         drop(sess);
 
         let pool = db_pool.pool();
 
-        // Get messages
-        let messages: Vec<Message> =
-            sqlx::query_as("SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC")
-                .bind(selected.id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
+        let index = match search_config {
+            Some(config) if !search::text_criteria(criteria).is_empty() => {
+                Self::index_matches(config, tenant_id, selected.id, criteria, db_pool).await
+            }
+            _ => None,
+        };
 
-        let mut results = Vec::new();
+        let compiled = search::compile(criteria, index.as_ref(), &selected);
+        let mut query = sqlx::query_as::<_, (i64, Option<i64>)>(&compiled.sql).bind(selected.id);
+        for param in compiled.params {
+            query = match param {
+                SqlParam::Text(value) => query.bind(value),
+                SqlParam::Int(value) => query.bind(value),
+                SqlParam::Date(value) => query.bind(value),
+                SqlParam::Ids(ids) => query.bind(ids),
+            };
+        }
 
-        for (idx, msg) in messages.iter().enumerate() {
-            let seq = (idx + 1) as u32;
-            let msg_uid = Self::message_id_to_uid(&msg.id);
+        let rows = match query.fetch_all(pool).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("SEARCH in mailbox {} failed: {}", selected.id, e);
+                return failure(tag, e, "SEARCH failed");
+            }
+        };
 
-            if Self::matches_criteria(msg, criteria) {
+        // Sequence numbers are the ones known to the client
+        let results: Vec<u32> = rows
+            .into_iter()
+            .filter_map(|(_, uid)| {
+                let uid = uid.unwrap_or_default() as u32;
                 if uid_mode {
-                    results.push(msg_uid);
+                    Some(uid)
                 } else {
-                    results.push(seq);
+                    selected.get_seq_by_uid(uid)
                 }
-            }
-        }
+            })
+            .collect();
 
         format!(
             "{}{}",
@@ -1093,69 +2349,77 @@ impl ImapServer {
         )
     }
 
-    /// Check if a message matches search criteria
-    fn matches_criteria(msg: &Message, criteria: &SearchCriteria) -> bool {
-        match criteria {
-            SearchCriteria::All => true,
-            SearchCriteria::Answered => msg.answered,
-            SearchCriteria::Deleted => msg.deleted,
-            SearchCriteria::Draft => msg.draft,
-            SearchCriteria::Flagged => msg.flagged,
-            SearchCriteria::Seen => msg.seen,
-            SearchCriteria::Unanswered => !msg.answered,
-            SearchCriteria::Undeleted => !msg.deleted,
-            SearchCriteria::Undraft => !msg.draft,
-            SearchCriteria::Unflagged => !msg.flagged,
-            SearchCriteria::Unseen => !msg.seen,
-            SearchCriteria::New => !msg.seen,
-            SearchCriteria::Old => msg.seen,
-            SearchCriteria::Recent => {
-                let age = chrono::Utc::now() - msg.created_at;
-                age.num_hours() < 24
-            }
-            SearchCriteria::From(s) => msg
-                .from_address
-                .as_ref()
-                .map(|f| f.to_lowercase().contains(&s.to_lowercase()))
-                .unwrap_or(false),
-            SearchCriteria::To(s) => {
-                if let Some(to) = msg.to_addresses.as_array() {
-                    to.iter().any(|addr| {
-                        addr.as_str()
-                            .map(|a| a.to_lowercase().contains(&s.to_lowercase()))
-                            .unwrap_or(false)
-                    })
-                } else {
-                    false
-                }
-            }
-            SearchCriteria::Subject(s) => msg
-                .subject
-                .as_ref()
-                .map(|subj| subj.to_lowercase().contains(&s.to_lowercase()))
-                .unwrap_or(false),
-            SearchCriteria::Body(s) | SearchCriteria::Text(s) => msg
-                .body_preview
-                .as_ref()
-                .map(|body| body.to_lowercase().contains(&s.to_lowercase()))
-                .unwrap_or(false),
-            SearchCriteria::Larger(size) => msg.body_size > (*size as i64),
-            SearchCriteria::Smaller(size) => msg.body_size < (*size as i64),
-            SearchCriteria::Not(inner) => !Self::matches_criteria(msg, inner),
-            SearchCriteria::And(criteria_list) => {
-                criteria_list.iter().all(|c| Self::matches_criteria(msg, c))
-            }
-            SearchCriteria::Or(a, b) => {
-                Self::matches_criteria(msg, a) || Self::matches_criteria(msg, b)
+    /// Answer the text criteria of a search from the search index
+    ///
+    /// Returns `None` when the index cannot give a complete answer, in which
+    /// case the criteria are matched against the stored columns instead.
+    async fn index_matches(
+        config: &MeilisearchConfig,
+        tenant_id: Uuid,
+        mailbox_id: Uuid,
+        criteria: &SearchCriteria,
+        db_pool: &DatabasePool,
+    ) -> Option<IndexMatches> {
+        let count: i64 = sqlx::query_scalar("SELECT message_count FROM mailboxes WHERE id = $1")
+            .bind(mailbox_id)
+            .fetch_one(db_pool.pool())
+            .await
+            .ok()?;
+        let limit = count as u64 + 1;
+        if limit > MAX_TOTAL_HITS {
+            debug!(
+                "Mailbox {} exceeds the search index result limit, matching SEARCH locally",
+                mailbox_id
+            );
+            return None;
+        }
+
+        let indexer = MessageIndexer::new(MeilisearchClient::new(config.clone()));
+        match IndexMatches::fetch(&indexer, tenant_id, mailbox_id, criteria, limit).await {
+            Ok(matches) => Some(matches),
+            Err(e) => {
+                warn!(
+                    "Search index unavailable for SEARCH, matching locally: {}",
+                    e
+                );
+                None
             }
-            _ => true, // Default to matching for unimplemented criteria
         }
     }
 
-    /// Convert message ID to UID
-    fn message_id_to_uid(id: &Uuid) -> u32 {
-        let bytes = id.as_bytes();
-        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    /// Persistent per-mailbox UID of a message
+    fn message_uid(msg: &Message) -> u32 {
+        msg.uid.unwrap_or_default() as u32
+    }
+
+    /// Messages of a mailbox with UIDs in the given ranges, in UID order
+    async fn messages_in(
+        db_pool: &DatabasePool,
+        mailbox_id: Uuid,
+        ranges: &[(u32, u32)],
+    ) -> Result<Vec<Message>> {
+        if ranges.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (firsts, lasts): (Vec<i64>, Vec<i64>) = ranges
+            .iter()
+            .map(|&(first, last)| (i64::from(first), i64::from(last)))
+            .unzip();
+        let messages = sqlx::query_as(
+            "SELECT m.* FROM messages m
+             JOIN UNNEST($2::BIGINT[], $3::BIGINT[]) AS r(first_uid, last_uid)
+               ON m.uid BETWEEN r.first_uid AND r.last_uid
+             WHERE m.mailbox_id = $1
+             ORDER BY m.uid ASC",
+        )
+        .bind(mailbox_id)
+        .bind(firsts)
+        .bind(lasts)
+        .fetch_all(db_pool.pool())
+        .await?;
+
+        Ok(messages)
     }
 
     // ========================================================================
@@ -1184,20 +2448,34 @@ impl ImapServer {
         };
         drop(sess);
 
+        if acl::is_shared_name(mailbox_name) {
+            return ImapResponse::no(tag, "[CANNOT] Shared/ is reserved for shared mailboxes");
+        }
+
+        let path = match folders::normalize_folder_path(&canonical_name(mailbox_name)) {
+            Some(path) if path == "INBOX" => {
+                return ImapResponse::no(tag, "[ALREADYEXISTS] Mailbox already exists")
+            }
+            Some(path) => path,
+            None => return ImapResponse::no(tag, "[CANNOT] Invalid mailbox name"),
+        };
+
         let pool = db_pool.pool();
 
-        // Check if mailbox already exists
-        let exists: Option<(Uuid,)> =
-            sqlx::query_as("SELECT id FROM mailboxes WHERE tenant_id = $1 AND address = $2")
-                .bind(tenant_id)
-                .bind(mailbox_name)
-                .fetch_optional(pool)
-                .await
-                .ok()
-                .flatten();
+        // Check if mailbox already exists (folder names are unique per user)
+        let exists: Option<(Uuid,)> = sqlx::query_as(
+            "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND folder_path = $3",
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(&path)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten();
 
         if exists.is_some() {
-            return ImapResponse::no(tag, "Mailbox already exists");
+            return ImapResponse::no(tag, "[ALREADYEXISTS] Mailbox already exists");
         }
 
         // Get user's domain
@@ -1217,28 +2495,17 @@ impl ImapServer {
             None => return ImapResponse::no(tag, "No domain found"),
         };
 
-        // Create the mailbox
-        let mailbox_id = Uuid::new_v4();
-        let result = sqlx::query(
-            "INSERT INTO mailboxes (id, tenant_id, domain_id, user_id, address, created_at, updated_at)
-             VALUES ($1, $2, $3, $4, $5, NOW(), NOW())",
-        )
-        .bind(mailbox_id)
-        .bind(tenant_id)
-        .bind(domain_id)
-        .bind(user_id)
-        .bind(mailbox_name)
-        .execute(pool)
-        .await;
+        // Superior names that do not exist yet are created too (RFC 3501, CREATE)
+        let result = create_folders(pool, tenant_id, domain_id, user_id, &path).await;
 
         match result {
-            Ok(_) => {
-                info!("Created mailbox {} for user {}", mailbox_name, user_id);
+            Ok(()) => {
+                info!("Created mailbox {} for user {}", path, user_id);
                 ImapResponse::ok(tag, "CREATE completed")
             }
             Err(e) => {
                 error!("Failed to create mailbox: {}", e);
-                ImapResponse::no(tag, "Failed to create mailbox")
+                failure(tag, e, "Failed to create mailbox")
             }
         }
     }
@@ -1271,14 +2538,32 @@ impl ImapServer {
         }
 
         let pool = db_pool.pool();
+        let path = canonical_name(mailbox_name);
+
+        // Folders with subfolders are kept, so that the subfolders keep a
+        // selectable parent
+        let has_children: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM mailboxes
+             WHERE tenant_id = $1 AND user_id = $2 AND parent_path = $3)",
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(&path)
+        .fetch_one(pool)
+        .await
+        .unwrap_or(false);
+
+        if has_children {
+            return ImapResponse::no(tag, "[INUSE] Mailbox has subfolders, delete them first");
+        }
 
         // Find and delete the mailbox
         let result = sqlx::query(
-            "DELETE FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
+            "DELETE FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND folder_path = $3",
         )
         .bind(tenant_id)
         .bind(user_id)
-        .bind(mailbox_name)
+        .bind(&path)
         .execute(pool)
         .await;
 
@@ -1290,7 +2575,7 @@ impl ImapServer {
             Ok(_) => ImapResponse::no(tag, "Mailbox not found"),
             Err(e) => {
                 error!("Failed to delete mailbox: {}", e);
-                ImapResponse::no(tag, "Failed to delete mailbox")
+                failure(tag, e, "Failed to delete mailbox")
             }
         }
     }
@@ -1323,32 +2608,61 @@ impl ImapServer {
             return ImapResponse::no(tag, "Cannot rename INBOX");
         }
 
+        if acl::is_shared_name(new_name) {
+            return ImapResponse::no(tag, "[CANNOT] Shared/ is reserved for shared mailboxes");
+        }
+
+        let old_path = canonical_name(old_name);
+        let new_path = match folders::normalize_folder_path(&canonical_name(new_name)) {
+            Some(path) if path == "INBOX" => {
+                return ImapResponse::no(tag, "[ALREADYEXISTS] Mailbox already exists")
+            }
+            Some(path) => path,
+            None => return ImapResponse::no(tag, "[CANNOT] Invalid mailbox name"),
+        };
+        if folders::ancestor_folders(&new_path).contains(&old_path.as_str()) {
+            return ImapResponse::no(tag, "[CANNOT] Cannot move a mailbox into itself");
+        }
+
         let pool = db_pool.pool();
 
-        // Rename the mailbox
-        let result = sqlx::query(
-            "UPDATE mailboxes SET address = $4, updated_at = NOW()
-             WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
-        )
-        .bind(tenant_id)
-        .bind(user_id)
-        .bind(old_name)
-        .bind(new_name)
-        .execute(pool)
-        .await;
+        let folder = |path: &str| {
+            sqlx::query_as::<_, (Uuid,)>(
+                "SELECT domain_id FROM mailboxes
+                 WHERE tenant_id = $1 AND user_id = $2 AND folder_path = $3",
+            )
+            .bind(tenant_id)
+            .bind(user_id)
+            .bind(path.to_string())
+            .fetch_optional(pool)
+        };
+
+        let domain_id = match folder(&old_path).await {
+            Ok(Some((domain_id,))) => domain_id,
+            Ok(None) => return ImapResponse::no(tag, "Mailbox not found"),
+            Err(e) => {
+                error!("Failed to find mailbox: {}", e);
+                return failure(tag, e, "Failed to rename mailbox");
+            }
+        };
+        if let Ok(Some(_)) = folder(&new_path).await {
+            return ImapResponse::no(tag, "[ALREADYEXISTS] Mailbox already exists");
+        }
+
+        // Subfolders move with the mailbox (RFC 3501, RENAME)
+        let result = rename_folder(pool, tenant_id, domain_id, user_id, &old_path, &new_path).await;
 
         match result {
-            Ok(r) if r.rows_affected() > 0 => {
+            Ok(renamed) => {
                 info!(
-                    "Renamed mailbox {} to {} for user {}",
-                    old_name, new_name, user_id
+                    "Renamed mailbox {} to {} for user {} ({} folders)",
+                    old_path, new_path, user_id, renamed
                 );
                 ImapResponse::ok(tag, "RENAME completed")
             }
-            Ok(_) => ImapResponse::no(tag, "Mailbox not found"),
             Err(e) => {
                 error!("Failed to rename mailbox: {}", e);
-                ImapResponse::no(tag, "Failed to rename mailbox")
+                failure(tag, e, "Failed to rename mailbox")
             }
         }
     }
@@ -1359,24 +2673,52 @@ impl ImapServer {
         mailbox_name: &str,
         subscribe: bool,
         session: &Arc<Mutex<ImapSession>>,
-        _db_pool: &DatabasePool,
+        db_pool: &DatabasePool,
     ) -> String {
         let sess = session.lock().await;
         if !sess.is_authenticated() {
             return ImapResponse::no(tag, "Not authenticated");
         }
+
+        let tenant_id = match sess.tenant_id {
+            Some(id) => id,
+            None => return ImapResponse::no(tag, "No tenant context"),
+        };
+
+        let user_id = match sess.user_id {
+            Some(id) => id,
+            None => return ImapResponse::no(tag, "No user context"),
+        };
         drop(sess);
 
-        // For now, we don't track subscriptions separately - all mailboxes are subscribed
-        // This could be implemented with a mailbox_subscriptions table
         let action = if subscribe {
             "SUBSCRIBE"
         } else {
             "UNSUBSCRIBE"
         };
-        debug!("{} to mailbox {}", action, mailbox_name);
 
-        ImapResponse::ok(tag, &format!("{} completed", action))
+        let mailbox = match Self::resolve_mailbox(mailbox_name, tenant_id, user_id, db_pool).await {
+            Ok(Some(mailbox)) => mailbox,
+            Ok(None) => return ImapResponse::no(tag, "[NONEXISTENT] Mailbox does not exist"),
+            Err(e) => {
+                error!("Failed to look up mailbox: {}", e);
+                return failure(tag, e, "Internal server error");
+            }
+        };
+
+        match MailboxSubscriptionRepository::new(db_pool.clone())
+            .set(user_id, mailbox.id, subscribe)
+            .await
+        {
+            Ok(()) => {
+                debug!("{} to mailbox {}", action, mailbox_name);
+                ImapResponse::ok(tag, &format!("{} completed", action))
+            }
+            Err(e) => {
+                error!("Failed to {} mailbox: {}", action, e);
+                failure(tag, e, "Internal server error")
+            }
+        }
     }
 
     // ========================================================================
@@ -1410,39 +2752,47 @@ impl ImapServer {
 
         let pool = db_pool.pool();
 
-        // Get messages for the sequence set
-        let messages: Vec<Message> =
-            sqlx::query_as("SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC")
-                .bind(selected.id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
+        // Only the messages in the sequence set are loaded
+        let ranges = selected.uid_ranges(sequence, uid_mode);
+        let messages = match Self::messages_in(db_pool, selected.id, &ranges).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("STORE in mailbox {} failed: {}", selected.id, e);
+                return failure(tag, e, "STORE failed");
+            }
+        };
 
         let mut response = String::new();
-        let max_seq = messages.len() as u32;
 
-        for (idx, msg) in messages.iter().enumerate() {
-            let seq = (idx + 1) as u32;
-            let msg_uid = Self::message_id_to_uid(&msg.id);
+        for msg in &messages {
+            let msg_uid = Self::message_uid(msg);
 
-            // Check if this message is in the sequence set
-            let in_set = if uid_mode {
-                sequence.contains(msg_uid, msg_uid)
-            } else {
-                sequence.contains(seq, max_seq)
+            // Messages the client has not been told about yet are left out
+            let Some(seq) = selected.get_seq_by_uid(msg_uid) else {
+                continue;
             };
 
-            if !in_set {
-                continue;
-            }
-
-            // Parse and apply flag changes
-            let (new_seen, new_answered, new_flagged, new_deleted, new_draft) =
+            // Parse and apply flag changes; changes the user has no right to are ignored
+            let (mut new_seen, mut new_answered, mut new_flagged, mut new_deleted, mut new_draft) =
                 Self::apply_flag_changes(msg, flags);
+            let mut new_keywords = apply_keyword_changes(msg.keywords_vec(), flags);
+            if !selected.rights.has('s') {
+                new_seen = msg.seen;
+            }
+            if !selected.rights.has('t') {
+                new_deleted = msg.deleted;
+            }
+            if !selected.rights.has('w') {
+                new_answered = msg.answered;
+                new_flagged = msg.flagged;
+                new_draft = msg.draft;
+                new_keywords = msg.keywords_vec();
+            }
 
             // Update the message in database
             let update_result = sqlx::query(
-                "UPDATE messages SET seen = $2, answered = $3, flagged = $4, deleted = $5, draft = $6
+                "UPDATE messages SET seen = $2, answered = $3, flagged = $4, deleted = $5, draft = $6,
+                 keywords = $7
                  WHERE id = $1",
             )
             .bind(msg.id)
@@ -1451,6 +2801,7 @@ impl ImapServer {
             .bind(new_flagged)
             .bind(new_deleted)
             .bind(new_draft)
+            .bind(serde_json::json!(new_keywords))
             .execute(pool)
             .await;
 
@@ -1467,6 +2818,7 @@ impl ImapServer {
                     new_flagged,
                     new_deleted,
                     new_draft,
+                    &new_keywords,
                 );
                 let mut fetch_items = vec![("FLAGS".to_string(), flags_str)];
                 if uid_mode {
@@ -1590,81 +2942,65 @@ impl ImapServer {
 
         let pool = db_pool.pool();
 
-        // Find destination mailbox (filtered by tenant_id AND user_id to prevent cross-user access)
-        let dest_mailbox_query = if dest_mailbox.to_uppercase() == "INBOX" {
-            sqlx::query_as::<_, (Uuid,)>(
-                "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 LIMIT 1",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-        } else {
-            sqlx::query_as::<_, (Uuid,)>(
-                "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-            .bind(dest_mailbox)
-        };
-
-        let dest_id = match dest_mailbox_query.fetch_optional(pool).await {
-            Ok(Some((id,))) => id,
+        // Find destination mailbox among the user's own and shared mailboxes
+        let dest = Self::resolve_mailbox(dest_mailbox, tenant_id, user_id, db_pool).await;
+        let (dest_id, dest_uid_validity) = match dest {
+            Ok(Some(mailbox)) if !mailbox.rights.has('i') => {
+                return ImapResponse::no(tag, "[NOPERM] Permission denied")
+            }
+            Ok(Some(mailbox)) => (mailbox.id, mailbox.uid_validity as u32),
             Ok(None) => {
                 return ImapResponse::no(tag, "[TRYCREATE] Destination mailbox does not exist")
             }
             Err(e) => {
                 error!("Failed to find destination mailbox: {}", e);
-                return ImapResponse::no(tag, "Failed to find destination mailbox");
+                return failure(tag, e, "Failed to find destination mailbox");
             }
         };
 
         // Get source messages
-        let messages: Vec<Message> =
-            sqlx::query_as("SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC")
-                .bind(selected.id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
+        let ranges = selected.uid_ranges(sequence, uid_mode);
+        let messages = match Self::messages_in(db_pool, selected.id, &ranges).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("COPY from mailbox {} failed: {}", selected.id, e);
+                return failure(tag, e, "COPY failed");
+            }
+        };
 
-        let max_seq = messages.len() as u32;
         let mut source_uids = Vec::new();
         let mut dest_uids = Vec::new();
 
-        for (idx, msg) in messages.iter().enumerate() {
-            let seq = (idx + 1) as u32;
-            let msg_uid = Self::message_id_to_uid(&msg.id);
-
-            let in_set = if uid_mode {
-                sequence.contains(msg_uid, msg_uid)
-            } else {
-                sequence.contains(seq, max_seq)
-            };
+        for msg in &messages {
+            let msg_uid = Self::message_uid(msg);
 
-            if !in_set {
+            if selected.get_seq_by_uid(msg_uid).is_none() {
                 continue;
             }
 
-            // Copy the message
+            // Copy the message; the destination UID is allocated on insert
             let new_id = Uuid::new_v4();
-            let new_uid = Self::message_id_to_uid(&new_id);
 
-            let copy_result = sqlx::query(
+            let copy_result = sqlx::query_as::<_, (i64,)>(
                 "INSERT INTO messages (id, tenant_id, mailbox_id, message_id_header, subject,
                  from_address, to_addresses, cc_addresses, headers, body_preview, body_size,
                  has_attachments, storage_path, seen, answered, flagged, deleted, draft,
-                 spam_score, tags, metadata, received_at, created_at)
+                 spam_score, tags, metadata, received_at, created_at, keywords, sent_date, snippet)
                  SELECT $1, tenant_id, $2, message_id_header, subject, from_address, to_addresses,
                  cc_addresses, headers, body_preview, body_size, has_attachments, storage_path,
-                 seen, answered, flagged, false, draft, spam_score, tags, metadata, received_at, NOW()
-                 FROM messages WHERE id = $3",
+                 seen, answered, flagged, false, draft, spam_score, tags, metadata, received_at, NOW(),
+                 keywords, sent_date, snippet
+                 FROM messages WHERE id = $3
+                 RETURNING uid",
             )
             .bind(new_id)
             .bind(dest_id)
             .bind(msg.id)
-            .execute(pool)
+            .fetch_one(pool)
             .await;
 
             match copy_result {
-                Ok(_) => {
+                Ok((new_uid,)) => {
                     source_uids.push(msg_uid.to_string());
                     dest_uids.push(new_uid.to_string());
                 }
@@ -1679,7 +3015,7 @@ impl ImapServer {
         }
 
         let copyuid = ImapResponse::copyuid(
-            selected.uid_validity,
+            dest_uid_validity,
             &source_uids.join(","),
             &dest_uids.join(","),
         );
@@ -1720,75 +3056,63 @@ impl ImapServer {
         };
         drop(sess);
 
-        let pool = db_pool.pool();
+        // Moving out of a mailbox deletes and expunges there
+        if !(selected.rights.has('t') && selected.rights.has('e')) {
+            return ImapResponse::no(tag, "[NOPERM] Permission denied");
+        }
 
-        // Find destination mailbox (filtered by tenant_id AND user_id to prevent cross-user access)
-        let dest_mailbox_query = if dest_mailbox.to_uppercase() == "INBOX" {
-            sqlx::query_as::<_, (Uuid,)>(
-                "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 LIMIT 1",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-        } else {
-            sqlx::query_as::<_, (Uuid,)>(
-                "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-            .bind(dest_mailbox)
-        };
+        let pool = db_pool.pool();
 
-        let dest_id = match dest_mailbox_query.fetch_optional(pool).await {
-            Ok(Some((id,))) => id,
+        // Find destination mailbox among the user's own and shared mailboxes
+        let dest = Self::resolve_mailbox(dest_mailbox, tenant_id, user_id, db_pool).await;
+        let (dest_id, dest_uid_validity) = match dest {
+            Ok(Some(mailbox)) if !mailbox.rights.has('i') => {
+                return ImapResponse::no(tag, "[NOPERM] Permission denied")
+            }
+            Ok(Some(mailbox)) => (mailbox.id, mailbox.uid_validity as u32),
             Ok(None) => {
                 return ImapResponse::no(tag, "[TRYCREATE] Destination mailbox does not exist")
             }
             Err(e) => {
                 error!("Failed to find destination mailbox: {}", e);
-                return ImapResponse::no(tag, "Failed to find destination mailbox");
+                return failure(tag, e, "Failed to find destination mailbox");
             }
         };
 
         // Get source messages
-        let messages: Vec<Message> =
-            sqlx::query_as("SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC")
-                .bind(selected.id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
+        let ranges = selected.uid_ranges(sequence, uid_mode);
+        let messages = match Self::messages_in(db_pool, selected.id, &ranges).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("MOVE from mailbox {} failed: {}", selected.id, e);
+                return failure(tag, e, "MOVE failed");
+            }
+        };
 
-        let max_seq = messages.len() as u32;
         let mut response = String::new();
         let mut source_uids = Vec::new();
         let mut dest_uids = Vec::new();
-        let mut expunged_seqs = Vec::new();
 
-        for (idx, msg) in messages.iter().enumerate() {
-            let seq = (idx + 1) as u32;
-            let msg_uid = Self::message_id_to_uid(&msg.id);
+        for msg in &messages {
+            let msg_uid = Self::message_uid(msg);
 
-            let in_set = if uid_mode {
-                sequence.contains(msg_uid, msg_uid)
-            } else {
-                sequence.contains(seq, max_seq)
-            };
-
-            if !in_set {
+            if selected.get_seq_by_uid(msg_uid).is_none() {
                 continue;
             }
 
-            // Move the message (update mailbox_id)
-            let move_result = sqlx::query("UPDATE messages SET mailbox_id = $2 WHERE id = $1")
-                .bind(msg.id)
-                .bind(dest_id)
-                .execute(pool)
-                .await;
+            // Move the message (update mailbox_id); a new UID is allocated in the destination
+            let move_result = sqlx::query_as::<_, (i64,)>(
+                "UPDATE messages SET mailbox_id = $2 WHERE id = $1 RETURNING uid",
+            )
+            .bind(msg.id)
+            .bind(dest_id)
+            .fetch_one(pool)
+            .await;
 
             match move_result {
-                Ok(_) => {
-                    source_uids.push(msg_uid.to_string());
-                    dest_uids.push(msg_uid.to_string()); // UID doesn't change on move
-                    expunged_seqs.push(seq);
+                Ok((new_uid,)) => {
+                    source_uids.push(msg_uid);
+                    dest_uids.push(new_uid.to_string());
                 }
                 Err(e) => {
                     error!("Failed to move message: {}", e);
@@ -1796,16 +3120,22 @@ impl ImapServer {
             }
         }
 
-        // Send EXPUNGE responses for moved messages (in reverse order to maintain sequence numbers)
-        for seq in expunged_seqs.iter().rev() {
-            response.push_str(&ImapResponse::expunge(*seq));
+        // Send EXPUNGE responses for moved messages; each sequence number
+        // accounts for the ones sent before it
+        if let Some(selected) = session.lock().await.selected_mailbox.as_mut() {
+            for uid in &source_uids {
+                if let Some(seq) = selected.expunge(*uid) {
+                    response.push_str(&ImapResponse::expunge(seq));
+                }
+            }
         }
+        let source_uids: Vec<String> = source_uids.iter().map(u32::to_string).collect();
 
         if source_uids.is_empty() {
             response.push_str(&ImapResponse::ok(tag, "MOVE completed (no messages)"));
         } else {
             let copyuid = ImapResponse::copyuid(
-                selected.uid_validity,
+                dest_uid_validity,
                 &source_uids.join(","),
                 &dest_uids.join(","),
             );
@@ -1839,50 +3169,44 @@ impl ImapServer {
         };
         drop(sess);
 
-        let pool = db_pool.pool();
-
-        // Get messages marked for deletion
-        let messages: Vec<Message> =
-            sqlx::query_as("SELECT * FROM messages WHERE mailbox_id = $1 ORDER BY received_at ASC")
-                .bind(selected.id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
-
-        let mut response = String::new();
-        let mut deleted_count = 0;
-
-        // Process in reverse order to maintain correct sequence numbers
-        let mut seq_to_delete = Vec::new();
-        for (idx, msg) in messages.iter().enumerate() {
-            if msg.deleted {
-                seq_to_delete.push((idx + 1) as u32);
-            }
+        if !selected.rights.has('e') {
+            return ImapResponse::no(tag, "[NOPERM] Permission denied");
         }
 
-        // Delete messages and send EXPUNGE responses
-        for (offset, (idx, msg)) in messages
-            .iter()
-            .enumerate()
-            .filter(|(_, m)| m.deleted)
-            .enumerate()
-        {
-            let seq = (idx + 1 - offset) as u32; // Adjust for already deleted messages
+        let pool = db_pool.pool();
 
-            let delete_result = sqlx::query("DELETE FROM messages WHERE id = $1")
-                .bind(msg.id)
-                .execute(pool)
-                .await;
+        // Delete the messages marked for deletion
+        let deleted: Vec<i64> = match sqlx::query_scalar(
+            "DELETE FROM messages WHERE mailbox_id = $1 AND deleted RETURNING uid",
+        )
+        .bind(selected.id)
+        .fetch_all(pool)
+        .await
+        {
+            Ok(uids) => uids,
+            Err(e) => {
+                error!("Failed to expunge mailbox {}: {}", selected.name, e);
+                return failure(tag, e, "EXPUNGE failed");
+            }
+        };
+        let mut deleted: Vec<u32> = deleted.into_iter().map(|uid| uid as u32).collect();
+        deleted.sort_unstable();
 
-            if delete_result.is_ok() {
-                response.push_str(&ImapResponse::expunge(seq));
-                deleted_count += 1;
+        // Send EXPUNGE responses; each sequence number accounts for the ones
+        // sent before it
+        let mut response = String::new();
+        if let Some(selected) = session.lock().await.selected_mailbox.as_mut() {
+            for uid in &deleted {
+                if let Some(seq) = selected.expunge(*uid) {
+                    response.push_str(&ImapResponse::expunge(seq));
+                }
             }
         }
 
         info!(
             "Expunged {} messages from mailbox {}",
-            deleted_count, selected.name
+            deleted.len(),
+            selected.name
         );
         response.push_str(&ImapResponse::ok(tag, "EXPUNGE completed"));
         response
@@ -1892,6 +3216,7 @@ impl ImapServer {
     const MAX_APPEND_SIZE: usize = 50 * 1024 * 1024;
 
     /// Handle APPEND command
+    #[allow(clippy::too_many_arguments)]
     async fn handle_append(
         tag: &str,
         mailbox_name: &str,
@@ -1901,6 +3226,7 @@ impl ImapServer {
         session: &Arc<Mutex<ImapSession>>,
         db_pool: &DatabasePool,
         storage_path_base: &PathBuf,
+        storage_layout: &StorageLayout,
     ) -> String {
         // Enforce message size limit to prevent disk exhaustion DoS
         if message.len() > Self::MAX_APPEND_SIZE {
@@ -1932,28 +3258,17 @@ impl ImapServer {
 
         let pool = db_pool.pool();
 
-        // Find the mailbox (filtered by tenant_id AND user_id to prevent cross-user access)
-        let mailbox_query = if mailbox_name.to_uppercase() == "INBOX" {
-            sqlx::query_as::<_, (Uuid,)>(
-                "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 LIMIT 1",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-        } else {
-            sqlx::query_as::<_, (Uuid,)>(
-                "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND address = $3",
-            )
-            .bind(tenant_id)
-            .bind(user_id)
-            .bind(mailbox_name)
-        };
-
-        let mailbox_id = match mailbox_query.fetch_optional(pool).await {
-            Ok(Some((id,))) => id,
+        // Find the mailbox among the user's own and shared mailboxes
+        let mailbox = Self::resolve_mailbox(mailbox_name, tenant_id, user_id, db_pool).await;
+        let (mailbox_id, uid_validity) = match mailbox {
+            Ok(Some(mailbox)) if !mailbox.rights.has('i') => {
+                return ImapResponse::no(tag, "[NOPERM] Permission denied")
+            }
+            Ok(Some(mailbox)) => (mailbox.id, mailbox.uid_validity as u32),
             Ok(None) => return ImapResponse::no(tag, "[TRYCREATE] Mailbox does not exist"),
             Err(e) => {
                 error!("Failed to find mailbox: {}", e);
-                return ImapResponse::no(tag, "Failed to find mailbox");
+                return failure(tag, e, "Failed to find mailbox");
             }
         };
 
@@ -1963,22 +3278,20 @@ impl ImapServer {
         let flagged = flags.iter().any(|f| f.to_uppercase() == "\\FLAGGED");
         let deleted = flags.iter().any(|f| f.to_uppercase() == "\\DELETED");
         let draft = flags.iter().any(|f| f.to_uppercase() == "\\DRAFT");
+        let keywords = keywords_in(flags);
 
         // Create the message
         let message_id = Uuid::new_v4();
-        // Create preview from first 500 characters of message for quick display
-        let body_preview = String::from_utf8_lossy(message)
-            .chars()
-            .take(500)
-            .collect::<String>();
-        let storage_path = format!("{}/{}/{}.eml", tenant_id, mailbox_id, message_id);
+        // Preview and snippet from the decoded body text
+        let preview = MessagePreview::of_raw(message);
+        let storage_path = storage_layout.message_path(tenant_id, mailbox_id, message_id);
 
         // Initialize file storage and store the FULL message
         let storage = match LocalStorage::from_path(storage_path_base) {
-            Ok(s) => s,
+            Ok(s) => s.with_layout(storage_layout.clone()),
             Err(e) => {
                 error!("Failed to initialize storage for APPEND: {}", e);
-                return ImapResponse::no(tag, "Failed to initialize storage");
+                return failure(tag, e, "Failed to initialize storage");
             }
         };
 
@@ -1988,16 +3301,18 @@ impl ImapServer {
             return ImapResponse::no(tag, "Failed to store message");
         }
 
-        let insert_result = sqlx::query(
+        let insert_result = sqlx::query_as::<_, (i64,)>(
             "INSERT INTO messages (id, tenant_id, mailbox_id, body_preview, body_size, storage_path,
              seen, answered, flagged, deleted, draft, to_addresses, headers, tags, metadata,
-             received_at, created_at)
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, '[]', '{}', '[]', '{}', NOW(), NOW())",
+             received_at, created_at, keywords, sent_date, snippet)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, '[]', $12, '[]', '{}', NOW(), NOW(),
+             $13, $14, $15)
+             RETURNING uid",
         )
         .bind(message_id)
         .bind(tenant_id)
         .bind(mailbox_id)
-        .bind(&body_preview)
+        .bind(&preview.body_preview)
         .bind(message.len() as i64)
         .bind(&storage_path)
         .bind(seen)
@@ -2005,13 +3320,16 @@ impl ImapServer {
         .bind(flagged)
         .bind(deleted)
         .bind(draft)
-        .execute(pool)
+        .bind(search::stored_headers(message))
+        .bind(serde_json::json!(keywords))
+        .bind(search::sent_date(message))
+        .bind(&preview.snippet)
+        .fetch_one(pool)
         .await;
 
         match insert_result {
-            Ok(_) => {
-                let uid = Self::message_id_to_uid(&message_id);
-                let appenduid = ImapResponse::appenduid(1, uid);
+            Ok((uid,)) => {
+                let appenduid = ImapResponse::appenduid(uid_validity, uid as u32);
                 info!(
                     "Appended message {} to mailbox {}",
                     message_id, mailbox_name
@@ -2020,21 +3338,1273 @@ impl ImapServer {
             }
             Err(e) => {
                 error!("Failed to append message: {}", e);
-                ImapResponse::no(tag, "Failed to append message")
+                failure(tag, e, "Failed to append message")
+            }
+        }
+    }
+
+    /// Resolve the target of a metadata command
+    ///
+    /// Returns `Some(None)` for server metadata (empty mailbox name),
+    /// `Some(Some(id))` for a mailbox and `None` if the mailbox does not exist.
+    async fn metadata_target(
+        mailbox_name: &str,
+        tenant_id: Uuid,
+        user_id: Uuid,
+        db_pool: &DatabasePool,
+    ) -> Option<Option<Uuid>> {
+        if mailbox_name.is_empty() {
+            return Some(None);
+        }
+
+        // SECURITY: Must filter by user_id to prevent cross-user mailbox access
+        let query = if mailbox_name.to_uppercase() == "INBOX" {
+            sqlx::query_as::<_, (Uuid,)>(
+                "SELECT id FROM mailboxes
+                 WHERE tenant_id = $1 AND user_id = $2 AND quarantine_of IS NULL
+                 ORDER BY folder_path IS NOT NULL, created_at LIMIT 1",
+            )
+            .bind(tenant_id)
+            .bind(user_id)
+        } else {
+            sqlx::query_as::<_, (Uuid,)>(
+                "SELECT id FROM mailboxes
+                 WHERE tenant_id = $1 AND user_id = $2 AND folder_path = $3",
+            )
+            .bind(tenant_id)
+            .bind(user_id)
+            .bind(canonical_name(mailbox_name))
+        };
+
+        query
+            .fetch_optional(db_pool.pool())
+            .await
+            .ok()
+            .flatten()
+            .map(|(id,)| Some(id))
+    }
+
+    /// Handle GETMETADATA command (RFC 5464)
+    async fn handle_getmetadata(
+        tag: &str,
+        mailbox_name: &str,
+        entries: &[String],
+        options: &MetadataOptions,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (tenant_id, user_id) = {
+            let sess = session.lock().await;
+            if !sess.is_authenticated() {
+                return ImapResponse::no(tag, "Not authenticated");
+            }
+            match (sess.tenant_id, sess.user_id) {
+                (Some(tenant_id), Some(user_id)) => (tenant_id, user_id),
+                _ => return ImapResponse::no(tag, "No user context"),
+            }
+        };
+
+        let requested: Vec<String> = match entries.iter().map(|e| normalize_entry(e)).collect() {
+            Some(requested) => requested,
+            None => return ImapResponse::bad(tag, "Invalid metadata entry name"),
+        };
+
+        let mailbox_id =
+            match Self::metadata_target(mailbox_name, tenant_id, user_id, db_pool).await {
+                Some(target) => target,
+                None => return ImapResponse::no(tag, "[NONEXISTENT] Mailbox does not exist"),
+            };
+
+        let repo = MetadataRepository::new(db_pool.clone());
+        let stored = match repo.list(tenant_id, Some(user_id), mailbox_id).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                error!("Failed to load metadata: {}", e);
+                return failure(tag, e, "Failed to load metadata");
+            }
+        };
+
+        let mut found: Vec<(String, Option<String>)> = Vec::new();
+        let mut too_large = Vec::new();
+        let mut longest = 0;
+        for entry in stored {
+            if !requested
+                .iter()
+                .any(|r| metadata_entry_matches(r, &entry.entry, options.depth))
+            {
+                continue;
+            }
+            if options.maxsize.is_some_and(|max| entry.value.len() > max) {
+                longest = longest.max(entry.value.len());
+                too_large.push(entry.entry);
+                continue;
+            }
+            found.push((entry.entry, Some(entry.value)));
+        }
+
+        // Requested entries that do not exist are returned as NIL
+        for entry in requested {
+            if !found.iter().any(|(e, _)| *e == entry) && !too_large.contains(&entry) {
+                found.push((entry, None));
+            }
+        }
+
+        let mut response = if found.is_empty() {
+            String::new()
+        } else {
+            ImapResponse::metadata(mailbox_name, &found)
+        };
+        if longest > 0 {
+            response.push_str(&ImapResponse::ok(
+                tag,
+                &format!("[METADATA LONGENTRIES {}] GETMETADATA completed", longest),
+            ));
+        } else {
+            response.push_str(&ImapResponse::ok(tag, "GETMETADATA completed"));
+        }
+        response
+    }
+
+    /// Handle SETMETADATA command (RFC 5464)
+    async fn handle_setmetadata(
+        tag: &str,
+        mailbox_name: &str,
+        entries: &[(String, Option<String>)],
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (tenant_id, user_id) = {
+            let sess = session.lock().await;
+            if !sess.is_authenticated() {
+                return ImapResponse::no(tag, "Not authenticated");
+            }
+            match (sess.tenant_id, sess.user_id) {
+                (Some(tenant_id), Some(user_id)) => (tenant_id, user_id),
+                _ => return ImapResponse::no(tag, "No user context"),
+            }
+        };
+
+        let mut normalized = Vec::with_capacity(entries.len());
+        for (entry, value) in entries {
+            let entry = match normalize_entry(entry) {
+                Some(entry) => entry,
+                None => return ImapResponse::bad(tag, "Invalid metadata entry name"),
+            };
+            if value
+                .as_ref()
+                .is_some_and(|v| v.len() > METADATA_MAX_VALUE_SIZE)
+            {
+                return ImapResponse::no(
+                    tag,
+                    &format!(
+                        "[METADATA MAXSIZE {}] Value too large",
+                        METADATA_MAX_VALUE_SIZE
+                    ),
+                );
+            }
+            normalized.push((entry, value.clone()));
+        }
+
+        let mailbox_id =
+            match Self::metadata_target(mailbox_name, tenant_id, user_id, db_pool).await {
+                Some(target) => target,
+                None => return ImapResponse::no(tag, "[NONEXISTENT] Mailbox does not exist"),
+            };
+
+        // Shared server annotations are administered by the server, not by users
+        if mailbox_id.is_none() && normalized.iter().any(|(e, _)| !is_private_entry(e)) {
+            return ImapResponse::no(tag, "[NOPERM] Shared server metadata is read-only");
+        }
+
+        let repo = MetadataRepository::new(db_pool.clone());
+        match repo
+            .set_entries(tenant_id, Some(user_id), mailbox_id, &normalized)
+            .await
+        {
+            Ok(true) => ImapResponse::ok(tag, "SETMETADATA completed"),
+            Ok(false) => ImapResponse::no(tag, "[METADATA TOOMANY] Too many metadata entries"),
+            Err(e) => {
+                error!("Failed to store metadata: {}", e);
+                failure(tag, e, "Failed to store metadata")
+            }
+        }
+    }
+
+    // ========================================================================
+    // Access Control Lists (RFC 4314)
+    // ========================================================================
+
+    /// Resolve the mailbox of an ACL command, requiring `right` unless `None`
+    ///
+    /// Returns the tenant and mailbox, or the response to send on failure.
+    async fn acl_target(
+        tag: &str,
+        mailbox_name: &str,
+        right: Option<char>,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> std::result::Result<(Uuid, AccessibleMailbox), String> {
+        let (tenant_id, user_id) = {
+            let sess = session.lock().await;
+            if !sess.is_authenticated() {
+                return Err(ImapResponse::no(tag, "Not authenticated"));
+            }
+            match (sess.tenant_id, sess.user_id) {
+                (Some(tenant_id), Some(user_id)) => (tenant_id, user_id),
+                _ => return Err(ImapResponse::no(tag, "No user context")),
+            }
+        };
+
+        match Self::resolve_mailbox(mailbox_name, tenant_id, user_id, db_pool).await {
+            Ok(Some(mailbox)) if mailbox.rights.is_empty() => Err(ImapResponse::no(
+                tag,
+                "[NONEXISTENT] Mailbox does not exist",
+            )),
+            Ok(Some(mailbox)) if right.is_some_and(|r| !mailbox.rights.has(r)) => {
+                Err(ImapResponse::no(tag, "[NOPERM] Permission denied"))
+            }
+            Ok(Some(mailbox)) => Ok((tenant_id, mailbox)),
+            Ok(None) => Err(ImapResponse::no(
+                tag,
+                "[NONEXISTENT] Mailbox does not exist",
+            )),
+            Err(e) => {
+                error!("Failed to resolve mailbox for ACL command: {}", e);
+                Err(ImapResponse::no(tag, "Internal server error"))
+            }
+        }
+    }
+
+    /// Look up the user an ACL identifier (email address) refers to within a tenant
+    async fn acl_grantee(
+        identifier: &str,
+        tenant_id: Uuid,
+        db_pool: &DatabasePool,
+    ) -> Result<Option<Uuid>> {
+        let user: Option<(Uuid,)> = sqlx::query_as(
+            "SELECT id FROM users WHERE tenant_id = $1 AND LOWER(email) = LOWER($2)",
+        )
+        .bind(tenant_id)
+        .bind(identifier)
+        .fetch_optional(db_pool.pool())
+        .await?;
+
+        Ok(user.map(|(id,)| id))
+    }
+
+    /// Handle SETACL command
+    async fn handle_setacl(
+        tag: &str,
+        mailbox_name: &str,
+        identifier: &str,
+        modification: &str,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (tenant_id, mailbox) =
+            match Self::acl_target(tag, mailbox_name, Some('a'), session, db_pool).await {
+                Ok(target) => target,
+                Err(response) => return response,
+            };
+
+        let grantee = match Self::acl_grantee(identifier, tenant_id, db_pool).await {
+            Ok(Some(id)) => id,
+            Ok(None) => {
+                return ImapResponse::no(tag, "[CANNOT] Rights can only be granted to users")
+            }
+            Err(e) => {
+                error!("Failed to look up ACL identifier: {}", e);
+                return failure(tag, e, "Internal server error");
+            }
+        };
+        if grantee == mailbox.owner_id {
+            return ImapResponse::no(tag, "[CANNOT] The owner's rights cannot be changed");
+        }
+
+        let repo = MailboxAclRepository::new(db_pool.clone());
+        let current = match repo.list(tenant_id, mailbox.id).await {
+            Ok(entries) => entries
+                .iter()
+                .find(|entry| entry.user_id == grantee)
+                .and_then(|entry| Rights::parse(&entry.rights))
+                .unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to load mailbox ACL: {}", e);
+                return failure(tag, e, "Failed to update ACL");
+            }
+        };
+
+        let rights = match current.apply(modification) {
+            Some(rights) => rights,
+            None => return ImapResponse::bad(tag, "Invalid rights"),
+        };
+
+        let result = if rights.is_empty() {
+            repo.remove(tenant_id, mailbox.id, grantee)
+                .await
+                .map(|_| ())
+        } else {
+            repo.set(tenant_id, mailbox.id, grantee, &rights.to_string())
+                .await
+        };
+
+        match result {
+            Ok(()) => {
+                info!(
+                    "Set rights \"{}\" on mailbox {} for {}",
+                    rights, mailbox.id, identifier
+                );
+                ImapResponse::ok(tag, "SETACL completed")
+            }
+            Err(e) => {
+                error!("Failed to update mailbox ACL: {}", e);
+                failure(tag, e, "Failed to update ACL")
+            }
+        }
+    }
+
+    /// Handle DELETEACL command
+    async fn handle_deleteacl(
+        tag: &str,
+        mailbox_name: &str,
+        identifier: &str,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (tenant_id, mailbox) =
+            match Self::acl_target(tag, mailbox_name, Some('a'), session, db_pool).await {
+                Ok(target) => target,
+                Err(response) => return response,
+            };
+
+        let grantee = match Self::acl_grantee(identifier, tenant_id, db_pool).await {
+            Ok(Some(id)) => id,
+            // Nothing can have been granted to an unknown identifier
+            Ok(None) => return ImapResponse::ok(tag, "DELETEACL completed"),
+            Err(e) => {
+                error!("Failed to look up ACL identifier: {}", e);
+                return failure(tag, e, "Internal server error");
+            }
+        };
+        if grantee == mailbox.owner_id {
+            return ImapResponse::no(tag, "[CANNOT] The owner's rights cannot be changed");
+        }
+
+        match MailboxAclRepository::new(db_pool.clone())
+            .remove(tenant_id, mailbox.id, grantee)
+            .await
+        {
+            Ok(_) => ImapResponse::ok(tag, "DELETEACL completed"),
+            Err(e) => {
+                error!("Failed to update mailbox ACL: {}", e);
+                failure(tag, e, "Failed to update ACL")
+            }
+        }
+    }
+
+    /// Handle GETACL command
+    async fn handle_getacl(
+        tag: &str,
+        mailbox_name: &str,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (tenant_id, mailbox) =
+            match Self::acl_target(tag, mailbox_name, Some('a'), session, db_pool).await {
+                Ok(target) => target,
+                Err(response) => return response,
+            };
+
+        let owner: Option<(String,)> =
+            match sqlx::query_as::<_, (String,)>("SELECT email FROM users WHERE id = $1")
+                .bind(mailbox.owner_id)
+                .fetch_optional(db_pool.pool())
+                .await
+            {
+                Ok(owner) => owner,
+                Err(e) => {
+                    error!("Failed to look up mailbox owner: {}", e);
+                    return failure(tag, e, "Internal server error");
+                }
+            };
+
+        let grants = match MailboxAclRepository::new(db_pool.clone())
+            .list(tenant_id, mailbox.id)
+            .await
+        {
+            Ok(grants) => grants,
+            Err(e) => {
+                error!("Failed to load mailbox ACL: {}", e);
+                return failure(tag, e, "Failed to load ACL");
+            }
+        };
+
+        let mut entries = Vec::with_capacity(grants.len() + 1);
+        if let Some((email,)) = owner {
+            entries.push((email, ALL_RIGHTS.to_string()));
+        }
+        entries.extend(grants.into_iter().map(|grant| {
+            let rights = Rights::parse(&grant.rights).unwrap_or_default();
+            (grant.identifier, rights.to_string())
+        }));
+
+        format!(
+            "{}{}",
+            ImapResponse::acl(mailbox_name, &entries),
+            ImapResponse::ok(tag, "GETACL completed")
+        )
+    }
+
+    /// Handle LISTRIGHTS command
+    async fn handle_listrights(
+        tag: &str,
+        mailbox_name: &str,
+        identifier: &str,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (tenant_id, mailbox) =
+            match Self::acl_target(tag, mailbox_name, Some('a'), session, db_pool).await {
+                Ok(target) => target,
+                Err(response) => return response,
+            };
+
+        let grantee = match Self::acl_grantee(identifier, tenant_id, db_pool).await {
+            Ok(grantee) => grantee,
+            Err(e) => {
+                error!("Failed to look up ACL identifier: {}", e);
+                return failure(tag, e, "Internal server error");
+            }
+        };
+
+        // The owner always holds every right; anyone else may be granted any of them
+        let listrights = if grantee == Some(mailbox.owner_id) {
+            ImapResponse::listrights(mailbox_name, identifier, ALL_RIGHTS, &[])
+        } else {
+            let optional: Vec<&str> = (0..ALL_RIGHTS.len())
+                .map(|i| &ALL_RIGHTS[i..i + 1])
+                .collect();
+            ImapResponse::listrights(mailbox_name, identifier, "", &optional)
+        };
+
+        format!(
+            "{}{}",
+            listrights,
+            ImapResponse::ok(tag, "LISTRIGHTS completed")
+        )
+    }
+
+    /// Handle MYRIGHTS command
+    async fn handle_myrights(
+        tag: &str,
+        mailbox_name: &str,
+        session: &Arc<Mutex<ImapSession>>,
+        db_pool: &DatabasePool,
+    ) -> String {
+        let (_, mailbox) = match Self::acl_target(tag, mailbox_name, None, session, db_pool).await {
+            Ok(target) => target,
+            Err(response) => return response,
+        };
+
+        format!(
+            "{}{}",
+            ImapResponse::myrights(mailbox_name, &mailbox.rights.to_string()),
+            ImapResponse::ok(tag, "MYRIGHTS completed")
+        )
+    }
+}
+
+/// Discard input up to and including the next LF; false if the
+/// connection ends first
+async fn skip_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<bool> {
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(false);
+        }
+        if let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            reader.consume(end + 1);
+            return Ok(true);
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+/// NO response to a command that failed: MaiRust errors get their response
+/// code (RFC 5530), anything else is reported as the server being
+/// unavailable
+fn failure(tag: &str, error: impl Into<anyhow::Error>, text: &str) -> String {
+    let error = error.into();
+    let code = error
+        .downcast_ref::<mairust_common::Error>()
+        .map_or("UNAVAILABLE", mairust_common::Error::imap_code);
+    ImapResponse::no(tag, &format!("[{}] {}", code, text))
+}
+
+/// Whether a stored metadata entry is selected by a requested entry and depth
+fn metadata_entry_matches(requested: &str, entry: &str, depth: MetadataDepth) -> bool {
+    if entry == requested {
+        return true;
+    }
+    let descendant = match entry
+        .strip_prefix(requested)
+        .and_then(|rest| rest.strip_prefix('/'))
+    {
+        Some(descendant) => descendant,
+        None => return false,
+    };
+    match depth {
+        MetadataDepth::Zero => false,
+        MetadataDepth::One => !descendant.contains('/'),
+        MetadataDepth::Infinity => true,
+    }
+}
+
+/// Keywords registered for common client features (RFC 5788), always
+/// announced in FLAGS
+const COMMON_KEYWORDS: &[&str] = &["$Forwarded", "$MDNSent", "$Junk", "$NotJunk", "$Phishing"];
+
+/// Most keywords a single message can carry
+const MAX_KEYWORDS_PER_MESSAGE: usize = 100;
+
+/// Longest keyword accepted
+const MAX_KEYWORD_LEN: usize = 128;
+
+/// Whether `flag` is a keyword: an atom not starting with a backslash
+fn is_keyword(flag: &str) -> bool {
+    !flag.is_empty()
+        && flag.len() <= MAX_KEYWORD_LEN
+        && flag
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !b"(){%*\"\\]".contains(&b))
+}
+
+/// Add `keyword` unless it is present already (keywords are case-insensitive)
+fn push_keyword(keywords: &mut Vec<String>, keyword: &str) {
+    if keywords.len() < MAX_KEYWORDS_PER_MESSAGE
+        && !keywords.iter().any(|k| k.eq_ignore_ascii_case(keyword))
+    {
+        keywords.push(keyword.to_string());
+    }
+}
+
+/// Keywords among the flags of an APPEND or STORE
+fn keywords_in(flags: &[String]) -> Vec<String> {
+    let mut keywords = Vec::new();
+    for flag in flags.iter().filter(|f| is_keyword(f)) {
+        push_keyword(&mut keywords, flag);
+    }
+    keywords
+}
+
+/// Apply the keyword part of a STORE to a message's keywords
+fn apply_keyword_changes(mut current: Vec<String>, flags: &StoreFlags) -> Vec<String> {
+    let requested = keywords_in(&flags.flags);
+    match flags.operation {
+        StoreOperation::Replace => requested,
+        StoreOperation::Add => {
+            for keyword in &requested {
+                push_keyword(&mut current, keyword);
+            }
+            current
+        }
+        StoreOperation::Remove => {
+            current.retain(|k| !requested.iter().any(|r| r.eq_ignore_ascii_case(k)));
+            current
+        }
+    }
+}
+
+/// Create a folder and the parents it is missing in one transaction
+async fn create_folders(
+    pool: &PgPool,
+    tenant_id: Uuid,
+    domain_id: Uuid,
+    user_id: Uuid,
+    path: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for ancestor in folders::ancestor_folders(path) {
+        if ancestor != "INBOX" {
+            create_folder(&mut tx, tenant_id, domain_id, user_id, ancestor).await?;
+        }
+    }
+    create_folder(&mut tx, tenant_id, domain_id, user_id, path).await?;
+    tx.commit().await
+}
+
+/// Create a folder unless the user already has it
+async fn create_folder(
+    conn: &mut PgConnection,
+    tenant_id: Uuid,
+    domain_id: Uuid,
+    user_id: Uuid,
+    path: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO mailboxes (id, tenant_id, domain_id, user_id, address, folder_path, parent_path,
+         created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $5, $6, NOW(), NOW())
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(Uuid::new_v4())
+    .bind(tenant_id)
+    .bind(domain_id)
+    .bind(user_id)
+    .bind(path)
+    .bind(folders::parent_folder(path))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Rename a folder and its subfolders, creating the missing parents of the
+/// new name; returns the number of folders renamed
+async fn rename_folder(
+    pool: &PgPool,
+    tenant_id: Uuid,
+    domain_id: Uuid,
+    user_id: Uuid,
+    old_path: &str,
+    new_path: &str,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for ancestor in folders::ancestor_folders(new_path) {
+        if ancestor != "INBOX" {
+            create_folder(&mut tx, tenant_id, domain_id, user_id, ancestor).await?;
+        }
+    }
+
+    // The address of a folder is its full name as well
+    let result = sqlx::query(
+        "UPDATE mailboxes SET
+             folder_path = $4 || substr(folder_path, length($3) + 1),
+             address = $4 || substr(folder_path, length($3) + 1),
+             parent_path = CASE WHEN folder_path = $3 THEN $5
+                 ELSE $4 || substr(parent_path, length($3) + 1) END,
+             updated_at = NOW()
+         WHERE tenant_id = $1 AND user_id = $2
+           AND (folder_path = $3 OR left(folder_path, length($3) + 1) = $3 || '/')",
+    )
+    .bind(tenant_id)
+    .bind(user_id)
+    .bind(old_path)
+    .bind(new_path)
+    .bind(folders::parent_folder(new_path))
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+/// Mailbox name with INBOX, which is case-insensitive, spelled in capitals,
+/// also as the first level of a folder name
+fn canonical_name(name: &str) -> String {
+    match name.get(..5) {
+        Some(inbox)
+            if inbox.eq_ignore_ascii_case("INBOX")
+                && (name.len() == 5 || name[5..].starts_with(FOLDER_DELIMITER)) =>
+        {
+            format!("INBOX{}", &name[5..])
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// A name in a LIST response
+#[derive(Debug, PartialEq)]
+struct ListEntry {
+    name: String,
+    special_use: Option<SpecialUse>,
+    has_children: bool,
+    /// The name only exists as the parent of other names
+    noselect: bool,
+}
+
+/// LIST entries for mailbox names: INBOX first, then the others sorted,
+/// with the parents that only exist as part of other names added
+fn list_entries(names: Vec<(String, Option<SpecialUse>)>) -> Vec<ListEntry> {
+    let parents: HashSet<String> = names
+        .iter()
+        .flat_map(|(name, _)| folders::ancestor_folders(name))
+        .map(str::to_string)
+        .collect();
+
+    let mut entries: Vec<ListEntry> = parents
+        .iter()
+        .filter(|parent| !names.iter().any(|(name, _)| name == *parent))
+        .map(|parent| ListEntry {
+            name: parent.clone(),
+            special_use: None,
+            has_children: true,
+            noselect: true,
+        })
+        .collect();
+    entries.extend(names.into_iter().map(|(name, special_use)| ListEntry {
+        has_children: parents.contains(&name),
+        name,
+        special_use,
+        noselect: false,
+    }));
+
+    entries.sort_by(|a, b| (a.name != "INBOX", &a.name).cmp(&(b.name != "INBOX", &b.name)));
+    entries.dedup_by(|a, b| a.name == b.name);
+    entries
+}
+
+/// Whether a mailbox name matches a LIST pattern: `*` matches any
+/// characters, `%` any characters but the hierarchy delimiter
+fn list_pattern_matches(pattern: &str, name: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+
+    // matched[i]: the pattern so far matches the first i characters
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    for p in pattern.chars() {
+        let mut next = vec![false; name.len() + 1];
+        for start in (0..=name.len()).filter(|&i| matched[i]) {
+            match p {
+                '*' => next[start..].iter_mut().for_each(|m| *m = true),
+                '%' => {
+                    let level_end = name[start..]
+                        .iter()
+                        .position(|&c| c == FOLDER_DELIMITER)
+                        .map_or(name.len(), |pos| start + pos);
+                    next[start..=level_end].iter_mut().for_each(|m| *m = true);
+                }
+                c => {
+                    if name.get(start) == Some(&c) {
+                        next[start + 1] = true;
+                    }
+                }
+            }
+        }
+        matched = next;
+    }
+    matched[name.len()]
+}
+
+/// LSUB entries for the subscribed names matching `pattern`, with whether
+/// each is \Noselect
+///
+/// A parent that is not subscribed itself is returned as \Noselect when
+/// the pattern matches it but none of its subscribed descendants, as with
+/// `%` (RFC 3501 section 6.3.9).
+fn lsub_entries(subscribed: &[String], pattern: &str) -> Vec<(String, bool)> {
+    let mut entries: Vec<(String, bool)> = subscribed
+        .iter()
+        .filter(|name| list_pattern_matches(pattern, name))
+        .map(|name| (name.clone(), false))
+        .collect();
+
+    for parent in subscribed
+        .iter()
+        .flat_map(|name| folders::ancestor_folders(name))
+    {
+        let prefix = format!("{}{}", parent, FOLDER_DELIMITER);
+        let descendant_matches = entries
+            .iter()
+            .any(|(name, noselect)| !noselect && name.starts_with(&prefix));
+        if !subscribed.iter().any(|name| name == parent)
+            && !descendant_matches
+            && list_pattern_matches(pattern, parent)
+        {
+            entries.push((parent.to_string(), true));
+        }
+    }
+
+    entries.sort_by(|a, b| (a.0 != "INBOX", &a.0).cmp(&(b.0 != "INBOX", &b.0)));
+    entries.dedup_by(|a, b| a.0 == b.0);
+    entries
+}
+
+/// Keywords to announce for a mailbox: the common ones plus those in use
+fn mailbox_keywords(in_use: Vec<String>) -> Vec<String> {
+    let mut keywords: Vec<String> = COMMON_KEYWORDS.iter().map(|k| k.to_string()).collect();
+    for keyword in in_use {
+        if !keywords.iter().any(|k| k.eq_ignore_ascii_case(&keyword)) {
+            keywords.push(keyword);
+        }
+    }
+    keywords
+}
+
+/// Chunk size for streaming message literals from storage
+const LITERAL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Value of a FETCH data item
+enum FetchValue {
+    /// Atom, number, list or quoted string
+    Text(String),
+    /// Literal held in memory
+    Literal(Vec<u8>),
+    /// Literal streamed from the stored message file
+    Stored { offset: u64, len: u64 },
+}
+
+/// Offset and length to send for a `<origin.count>` partial range of a
+/// literal of `len` octets; an origin past the end yields an empty literal
+fn partial_range(len: u64, partial: Option<(u32, u32)>) -> (u64, u64) {
+    match partial {
+        Some((origin, count)) => {
+            let start = u64::from(origin).min(len);
+            (start, u64::from(count).min(len - start))
+        }
+        None => (0, len),
+    }
+}
+
+fn slice_partial(data: &[u8], partial: Option<(u32, u32)>) -> Vec<u8> {
+    let (offset, len) = partial_range(data.len() as u64, partial);
+    data[offset as usize..(offset + len) as usize].to_vec()
+}
+
+/// Write one untagged FETCH response, streaming stored literals in chunks
+async fn write_fetch<W>(
+    w: &mut W,
+    seq: u32,
+    items: &[(String, FetchValue)],
+    storage: Option<&LocalStorage>,
+    path: &str,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = format!("* {} FETCH (", seq).into_bytes();
+
+    for (i, (key, value)) in items.iter().enumerate() {
+        if i > 0 {
+            buf.push(b' ');
+        }
+        buf.extend_from_slice(key.as_bytes());
+        buf.push(b' ');
+
+        match value {
+            FetchValue::Text(text) => buf.extend_from_slice(text.as_bytes()),
+            FetchValue::Literal(data) => {
+                buf.extend_from_slice(format!("{{{}}}\r\n", data.len()).as_bytes());
+                buf.extend_from_slice(data);
+            }
+            FetchValue::Stored { offset, len } => {
+                let storage = storage.ok_or_else(|| anyhow!("Message storage unavailable"))?;
+                buf.extend_from_slice(format!("{{{}}}\r\n", len).as_bytes());
+                w.write_all(&buf).await?;
+                buf.clear();
+
+                let mut sent = 0u64;
+                while sent < *len {
+                    let chunk_len = (len - sent).min(LITERAL_CHUNK_SIZE as u64) as usize;
+                    let chunk = storage.read_range(path, offset + sent, chunk_len).await?;
+                    if chunk.is_empty() {
+                        // The announced literal size can no longer be honoured
+                        return Err(anyhow!("Message {} truncated while streaming", path));
+                    }
+                    w.write_all(&chunk).await?;
+                    sent += chunk.len() as u64;
+                }
             }
         }
     }
+
+    buf.extend_from_slice(b")\r\n");
+    w.write_all(&buf).await?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_canonical_name() {
+        assert_eq!(canonical_name("inbox"), "INBOX");
+        assert_eq!(canonical_name("Inbox/Receipts"), "INBOX/Receipts");
+        assert_eq!(canonical_name("Inboxes"), "Inboxes");
+        assert_eq!(canonical_name("Archive/inbox"), "Archive/inbox");
+        assert_eq!(canonical_name("受信"), "受信");
+    }
+
+    #[test]
+    fn test_list_pattern_matches() {
+        assert!(list_pattern_matches("*", "Archive/2024/Q1"));
+        assert!(list_pattern_matches("%", "Archive"));
+        assert!(!list_pattern_matches("%", "Archive/2024"));
+        assert!(list_pattern_matches("Archive/%", "Archive/2024"));
+        assert!(!list_pattern_matches("Archive/%", "Archive/2024/Q1"));
+        assert!(!list_pattern_matches("Archive/%", "Archive"));
+        assert!(list_pattern_matches("Archive*", "Archive"));
+        assert!(list_pattern_matches("Archive*", "Archive/2024/Q1"));
+        assert!(list_pattern_matches("A%e", "Archive"));
+        assert!(list_pattern_matches("%/2024", "Archive/2024"));
+        assert!(!list_pattern_matches("%/2024", "Old/Archive/2024"));
+        assert!(list_pattern_matches("*/2024", "Old/Archive/2024"));
+        assert!(list_pattern_matches("INBOX", "INBOX"));
+        assert!(!list_pattern_matches("Sent", "Sent Items"));
+        assert!(!list_pattern_matches("", "INBOX"));
+    }
+
+    #[test]
+    fn test_list_entries() {
+        let entries = list_entries(vec![
+            ("INBOX".to_string(), None),
+            ("Sent".to_string(), Some(SpecialUse::Sent)),
+            ("Archive".to_string(), None),
+            ("Archive/2024".to_string(), None),
+            ("INBOX/Receipts".to_string(), None),
+            ("Shared/bob@example.com/INBOX".to_string(), None),
+        ]);
+
+        let summary: Vec<(&str, bool, bool)> = entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.has_children, e.noselect))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("INBOX", true, false),
+                ("Archive", true, false),
+                ("Archive/2024", false, false),
+                ("INBOX/Receipts", false, false),
+                ("Sent", false, false),
+                ("Shared", true, true),
+                ("Shared/bob@example.com", true, true),
+                ("Shared/bob@example.com/INBOX", false, false),
+            ]
+        );
+        assert_eq!(entries[4].special_use, Some(SpecialUse::Sent));
+    }
+
+    #[test]
+    fn test_lsub_entries() {
+        let subscribed: Vec<String> = ["INBOX", "Archive/2024", "Sent", "Lists/rust/users"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+
+        assert_eq!(
+            lsub_entries(&subscribed, "%"),
+            vec![
+                ("INBOX".to_string(), false),
+                ("Archive".to_string(), true),
+                ("Lists".to_string(), true),
+                ("Sent".to_string(), false),
+            ]
+        );
+        assert_eq!(
+            lsub_entries(&subscribed, "*"),
+            vec![
+                ("INBOX".to_string(), false),
+                ("Archive/2024".to_string(), false),
+                ("Lists/rust/users".to_string(), false),
+                ("Sent".to_string(), false),
+            ]
+        );
+        assert_eq!(
+            lsub_entries(&subscribed, "Lists/%"),
+            vec![("Lists/rust".to_string(), true)]
+        );
+        assert!(lsub_entries(&subscribed, "Drafts").is_empty());
+    }
+
+    #[test]
+    fn test_metadata_entry_matches() {
+        let requested = "/private/vendor";
+        assert!(metadata_entry_matches(
+            requested,
+            "/private/vendor",
+            MetadataDepth::Zero
+        ));
+        assert!(!metadata_entry_matches(
+            requested,
+            "/private/vendor/a",
+            MetadataDepth::Zero
+        ));
+        assert!(metadata_entry_matches(
+            requested,
+            "/private/vendor/a",
+            MetadataDepth::One
+        ));
+        assert!(!metadata_entry_matches(
+            requested,
+            "/private/vendor/a/b",
+            MetadataDepth::One
+        ));
+        assert!(metadata_entry_matches(
+            requested,
+            "/private/vendor/a/b",
+            MetadataDepth::Infinity
+        ));
+        assert!(!metadata_entry_matches(
+            requested,
+            "/private/vendorx",
+            MetadataDepth::Infinity
+        ));
+    }
+
     #[test]
     fn test_config_default() {
         let config = ImapConfig::default();
         assert_eq!(config.bind, "0.0.0.0:143");
         assert!(!config.starttls);
         assert_eq!(config.timeout_minutes, 30);
+        assert!(config.tls_bind.is_none());
+    }
+
+    #[test]
+    fn test_keyword_changes() {
+        assert!(is_keyword("$Forwarded"));
+        assert!(is_keyword("work"));
+        assert!(!is_keyword("\\Seen"));
+        assert!(!is_keyword("two words"));
+        assert!(!is_keyword("bad]"));
+        assert!(!is_keyword(""));
+
+        let store = |operation, flags: &[&str]| StoreFlags {
+            operation,
+            silent: false,
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+        };
+        let current = vec!["$Forwarded".to_string()];
+
+        let added = apply_keyword_changes(
+            current.clone(),
+            &store(StoreOperation::Add, &["\\Seen", "$forwarded", "$Phishing"]),
+        );
+        assert_eq!(added, vec!["$Forwarded", "$Phishing"]);
+
+        let removed = apply_keyword_changes(added, &store(StoreOperation::Remove, &["$FORWARDED"]));
+        assert_eq!(removed, vec!["$Phishing"]);
+
+        let replaced = apply_keyword_changes(
+            current,
+            &store(StoreOperation::Replace, &["\\Flagged", "todo"]),
+        );
+        assert_eq!(replaced, vec!["todo"]);
+    }
+
+    #[test]
+    fn test_partial_range() {
+        assert_eq!(partial_range(100, None), (0, 100));
+        assert_eq!(partial_range(100, Some((10, 20))), (10, 20));
+        assert_eq!(partial_range(100, Some((90, 20))), (90, 10));
+        assert_eq!(partial_range(100, Some((200, 20))), (100, 0));
+        assert_eq!(slice_partial(b"Hello, World!", Some((7, 5))), b"World");
+    }
+
+    #[tokio::test]
+    async fn test_write_fetch_streams_stored_literal() {
+        let dir = std::env::temp_dir().join(format!("mairust-fetch-{}", Uuid::now_v7()));
+        let storage = LocalStorage::from_path(&dir).unwrap();
+        let message = vec![b'x'; LITERAL_CHUNK_SIZE * 2 + 10];
+        storage.store("t/m.eml", &message).await.unwrap();
+
+        let items = vec![
+            ("UID".to_string(), FetchValue::Text("7".to_string())),
+            (
+                "BODY[]<5>".to_string(),
+                FetchValue::Stored {
+                    offset: 5,
+                    len: LITERAL_CHUNK_SIZE as u64 + 3,
+                },
+            ),
+            (
+                "BODY[1]".to_string(),
+                FetchValue::Literal(b"\xffab".to_vec()),
+            ),
+        ];
+        let mut out = Vec::new();
+        write_fetch(&mut out, 3, &items, Some(&storage), "t/m.eml")
+            .await
+            .unwrap();
+
+        let mut expected = format!(
+            "* 3 FETCH (UID 7 BODY[]<5> {{{}}}\r\n",
+            LITERAL_CHUNK_SIZE + 3
+        )
+        .into_bytes();
+        expected.resize(expected.len() + LITERAL_CHUNK_SIZE + 3, b'x');
+        expected.extend_from_slice(b" BODY[1] {3}\r\n\xffab)\r\n");
+        assert_eq!(out, expected);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_command_literals() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let mut reader = BufReader::new(server_read);
+        let writer = Arc::new(Mutex::new(server_write));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+
+        let client_task = tokio::spawn(async move {
+            client_write.write_all(b"A1 LOGIN {4}\r\n").await.unwrap();
+            // The literal is only sent after the continuation request
+            let mut continuation = [0u8; 4];
+            client_read.read_exact(&mut continuation).await.unwrap();
+            assert_eq!(&continuation, b"+ \r\n");
+            client_write
+                .write_all(b"user {4+}\r\npass\r\nA2 APPEND INBOX {999999999}\r\n")
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+            BufReader::new(client_read)
+                .read_line(&mut response)
+                .await
+                .unwrap();
+            response
+        });
+
+        let read = ImapServer::read_command(&mut reader, &writer, false)
+            .await
+            .unwrap();
+        assert!(
+            matches!(read, CommandRead::Command(ref c) if c == b"A1 LOGIN {4}\r\nuser {4+}\r\npass\r\n")
+        );
+
+        // An oversized synchronizing literal is refused before its data is sent
+        let read = ImapServer::read_command(&mut reader, &writer, true)
+            .await
+            .unwrap();
+        assert!(matches!(read, CommandRead::Rejected));
+        assert_eq!(
+            client_task.await.unwrap(),
+            "A2 NO [TOOBIG] Literal too large\r\n"
+        );
+    }
+
+    async fn next_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        line
+    }
+
+    #[tokio::test]
+    async fn test_read_command_limits() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let mut reader = BufReader::new(server_read);
+        let writer = Arc::new(Mutex::new(server_write));
+        let (client_read, mut client_write) = tokio::io::split(client);
+
+        let client_task = tokio::spawn(async move {
+            let mut client_read = BufReader::new(client_read);
+            let mut replies = Vec::new();
+
+            // A marker whose length overflows the command size
+            client_write
+                .write_all(b"A1 LOGIN {18446744073709551615}\r\n")
+                .await
+                .unwrap();
+            replies.push(next_line(&mut client_read).await);
+
+            // A synchronizing literal above the pre-authentication limit
+            client_write.write_all(b"A2 LOGIN {5000}\r\n").await.unwrap();
+            replies.push(next_line(&mut client_read).await);
+
+            // The same literal once authenticated
+            client_write
+                .write_all(b"A3 APPEND INBOX {5000}\r\n")
+                .await
+                .unwrap();
+            replies.push(next_line(&mut client_read).await);
+            client_write.write_all(&[b'x'; 5000]).await.unwrap();
+            client_write.write_all(b"\r\n").await.unwrap();
+
+            // A line above the line length limit, then a valid command
+            let mut line = b"A4 NOOP ".to_vec();
+            line.resize(MAX_LINE_LENGTH + 100, b'a');
+            line.extend_from_slice(b"\r\nA5 NOOP\r\n");
+            client_write.write_all(&line).await.unwrap();
+            replies.push(next_line(&mut client_read).await);
+
+            // A non-synchronizing literal above the LITERAL- limit
+            client_write.write_all(b"A6 LOGIN {5000+}\r\n").await.unwrap();
+            replies.push(next_line(&mut client_read).await);
+            replies.push(next_line(&mut client_read).await);
+            replies
+        });
+
+        let read = ImapServer::read_command(&mut reader, &writer, true)
+            .await
+            .unwrap();
+        assert!(matches!(read, CommandRead::Rejected));
+
+        let read = ImapServer::read_command(&mut reader, &writer, false)
+            .await
+            .unwrap();
+        assert!(matches!(read, CommandRead::Rejected));
+
+        let read = ImapServer::read_command(&mut reader, &writer, true)
+            .await
+            .unwrap();
+        let header = b"A3 APPEND INBOX {5000}\r\n";
+        assert!(
+            matches!(read, CommandRead::Command(ref c) if c.len() == header.len() + 5002 && c.starts_with(header))
+        );
+
+        let read = ImapServer::read_command(&mut reader, &writer, true)
+            .await
+            .unwrap();
+        assert!(matches!(read, CommandRead::Rejected));
+        let read = ImapServer::read_command(&mut reader, &writer, true)
+            .await
+            .unwrap();
+        assert!(matches!(read, CommandRead::Command(ref c) if c == b"A5 NOOP\r\n"));
+
+        let read = ImapServer::read_command(&mut reader, &writer, true)
+            .await
+            .unwrap();
+        assert!(matches!(read, CommandRead::Closed));
+
+        assert_eq!(
+            client_task.await.unwrap(),
+            vec![
+                "A1 NO [TOOBIG] Literal too large\r\n",
+                "A2 NO [TOOBIG] Literal too large\r\n",
+                "+ \r\n",
+                "A4 BAD [TOOBIG] Command line too long\r\n",
+                "A6 BAD [TOOBIG] Literal too large\r\n",
+                "* BYE Literal too large\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bearer_key_user() {
+        let user_id = Uuid::new_v4();
+        let key = |scopes: serde_json::Value| ApiKey {
+            id: Uuid::new_v4(),
+            tenant_id: Uuid::new_v4(),
+            user_id: Some(user_id),
+            name: "client".to_string(),
+            key_hash: String::new(),
+            key_prefix: "mairust_".to_string(),
+            scopes,
+            expires_at: None,
+            last_used_at: None,
+            created_at: chrono::Utc::now(),
+        };
+
+        let imap = key(serde_json::json!(["mail:imap"]));
+        assert_eq!(ImapServer::bearer_key_user(&imap), Ok(user_id));
+        let all = key(serde_json::json!(["*"]));
+        assert_eq!(ImapServer::bearer_key_user(&all), Ok(user_id));
+
+        let read_only = key(serde_json::json!(["read", "webhooks"]));
+        assert!(ImapServer::bearer_key_user(&read_only).is_err());
+        let none = key(serde_json::json!([]));
+        assert!(ImapServer::bearer_key_user(&none).is_err());
+
+        let mut expired = key(serde_json::json!(["mail:imap"]));
+        expired.expires_at = Some(chrono::Utc::now() - chrono::Duration::hours(1));
+        assert_eq!(ImapServer::bearer_key_user(&expired), Err("Token expired"));
+
+        let mut unbound = key(serde_json::json!(["mail:imap"]));
+        unbound.user_id = None;
+        assert!(ImapServer::bearer_key_user(&unbound).is_err());
     }
 }
@@ -3,10 +3,11 @@
 //! Manages the state of an IMAP connection including authentication
 //! and selected mailbox state.
 
+use super::acl::Rights;
+use super::command::SequenceSet;
 use chrono::{DateTime, Utc};
 use mairust_common::types::{MailboxId, TenantId, UserId};
-use mairust_storage::models::Message;
-use std::collections::HashMap;
+use std::path::PathBuf;
 use uuid::Uuid;
 
 /// IMAP session state
@@ -24,6 +25,19 @@ pub enum SessionState {
     Logout,
 }
 
+impl SessionState {
+    /// Name of the state in session listings
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::NotAuthenticated => "not_authenticated",
+            SessionState::Authenticated => "authenticated",
+            SessionState::Selected => "selected",
+            SessionState::ReadOnly => "read_only",
+            SessionState::Logout => "logout",
+        }
+    }
+}
+
 /// Selected mailbox information
 #[derive(Debug, Clone)]
 pub struct SelectedMailbox {
@@ -37,16 +51,17 @@ pub struct SelectedMailbox {
     pub recent: u32,
     /// First unseen message sequence number
     pub first_unseen: Option<u32>,
-    /// UID validity value
+    /// UID validity value (stored per mailbox)
     pub uid_validity: u32,
-    /// Next UID value
+    /// Next UID value (stored per mailbox)
     pub uid_next: u32,
     /// Available flags
     pub flags: Vec<String>,
-    /// Message UID to sequence number mapping
-    pub uid_map: HashMap<u32, u32>,
-    /// Sequence number to message ID mapping
-    pub seq_to_id: HashMap<u32, Uuid>,
+    /// UIDs of the messages known to the client in ascending order; the
+    /// sequence number of a message is its index plus one
+    pub uids: Vec<u32>,
+    /// The session user's rights on the mailbox
+    pub rights: Rights,
 }
 
 impl SelectedMailbox {
@@ -67,71 +82,155 @@ impl SelectedMailbox {
                 "\\Deleted".to_string(),
                 "\\Draft".to_string(),
             ],
-            uid_map: HashMap::new(),
-            seq_to_id: HashMap::new(),
+            uids: Vec::new(),
+            rights: Rights::all(),
         }
     }
 
-    /// Update mailbox with messages
-    pub fn update_with_messages(&mut self, messages: &[Message]) {
-        self.exists = messages.len() as u32;
-        self.uid_map.clear();
-        self.seq_to_id.clear();
+    /// Update mailbox with the state of its messages in UID order
+    pub fn update_with_messages(&mut self, messages: &[MessageState]) {
+        self.uids = messages.iter().map(|m| m.uid).collect();
+        self.exists = self.uids.len() as u32;
+        self.recent = messages.iter().filter(|m| m.recent).count() as u32;
+        self.first_unseen = messages
+            .iter()
+            .position(|m| !m.seen)
+            .map(|idx| (idx + 1) as u32);
+        self.uid_next = self.last_uid().saturating_add(1);
+    }
 
-        let mut first_unseen = None;
-        let mut recent_count = 0u32;
-        let mut max_uid = 0u32;
+    /// UID of the highest known message, 0 when the mailbox is empty
+    pub fn last_uid(&self) -> u32 {
+        self.uids.last().copied().unwrap_or_default()
+    }
 
-        for (idx, msg) in messages.iter().enumerate() {
-            let seq = (idx + 1) as u32;
-            // Use message ID bytes as UID (simplified)
-            let uid = Self::message_id_to_uid(&msg.id);
+    /// Get UID by sequence number
+    pub fn get_uid(&self, seq: u32) -> Option<u32> {
+        let idx = (seq as usize).checked_sub(1)?;
+        self.uids.get(idx).copied()
+    }
 
-            self.uid_map.insert(uid, seq);
-            self.seq_to_id.insert(seq, msg.id);
+    /// Get sequence number by UID
+    pub fn get_seq_by_uid(&self, uid: u32) -> Option<u32> {
+        self.uids
+            .binary_search(&uid)
+            .ok()
+            .map(|idx| (idx + 1) as u32)
+    }
 
-            if !msg.seen && first_unseen.is_none() {
-                first_unseen = Some(seq);
-            }
+    /// UID ranges covering a sequence set, merged and in ascending order
+    ///
+    /// Sequence numbers are resolved against the cached UIDs, so a query
+    /// for the returned ranges only touches the requested messages.
+    pub fn uid_ranges(&self, sequence: &SequenceSet, uid_mode: bool) -> Vec<(u32, u32)> {
+        let max = if uid_mode {
+            self.last_uid()
+        } else {
+            self.exists
+        };
 
-            // Count recent messages (simplified: check if created today)
-            let now = Utc::now();
-            if (now - msg.created_at).num_hours() < 24 {
-                recent_count += 1;
-            }
+        let mut ranges = Vec::new();
+        collect_ranges(sequence, max, &mut ranges);
+        let ranges = merge_ranges(ranges);
 
-            if uid > max_uid {
-                max_uid = uid;
-            }
+        if uid_mode {
+            return ranges;
         }
+        ranges
+            .into_iter()
+            .filter_map(|(start, end)| {
+                let start = start.max(1);
+                let end = end.min(self.exists);
+                if start > end {
+                    return None;
+                }
+                Some((self.get_uid(start)?, self.get_uid(end)?))
+            })
+            .collect()
+    }
 
-        self.first_unseen = first_unseen;
-        self.recent = recent_count;
-        self.uid_next = max_uid.saturating_add(1);
+    /// Add messages that arrived since the UIDs were loaded, returning
+    /// whether any were added
+    pub fn append_uids(&mut self, uids: &[u32]) -> bool {
+        let last = self.last_uid();
+        let before = self.uids.len();
+        self.uids
+            .extend(uids.iter().copied().filter(|&uid| uid > last));
+        self.exists = self.uids.len() as u32;
+        self.uid_next = self.uid_next.max(self.last_uid().saturating_add(1));
+        self.uids.len() > before
     }
 
-    /// Convert message ID to UID
-    fn message_id_to_uid(id: &Uuid) -> u32 {
-        // Use first 4 bytes of UUID as UID
-        let bytes = id.as_bytes();
-        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    /// Remove an expunged message, returning the sequence number it had
+    pub fn expunge(&mut self, uid: u32) -> Option<u32> {
+        let idx = self.uids.binary_search(&uid).ok()?;
+        self.uids.remove(idx);
+        self.exists = self.uids.len() as u32;
+        Some((idx + 1) as u32)
     }
 
-    /// Get message ID by sequence number
-    pub fn get_message_id(&self, seq: u32) -> Option<Uuid> {
-        self.seq_to_id.get(&seq).copied()
+    /// Remove the messages that are no longer in the mailbox, given all of
+    /// its current UIDs in ascending order
+    ///
+    /// Returns the sequence numbers to announce in EXPUNGE responses, each
+    /// valid after the ones before it have been announced.
+    pub fn expunge_missing(&mut self, current: &[u32]) -> Vec<u32> {
+        let missing: Vec<u32> = self
+            .uids
+            .iter()
+            .copied()
+            .filter(|uid| current.binary_search(uid).is_err())
+            .collect();
+        missing
+            .into_iter()
+            .filter_map(|uid| self.expunge(uid))
+            .collect()
     }
+}
 
-    /// Get message ID by UID
-    pub fn get_message_id_by_uid(&self, uid: u32) -> Option<Uuid> {
-        let seq = self.uid_map.get(&uid)?;
-        self.seq_to_id.get(seq).copied()
+/// State of a message loaded on SELECT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageState {
+    /// Message UID
+    pub uid: u32,
+    /// Whether the message has been seen
+    pub seen: bool,
+    /// Whether the message is recent
+    pub recent: bool,
+}
+
+/// Collect the ranges of a sequence set, resolving `*` to `max`
+fn collect_ranges(sequence: &SequenceSet, max: u32, ranges: &mut Vec<(u32, u32)>) {
+    let resolve = |n: u32| if n == u32::MAX { max } else { n };
+    match sequence {
+        SequenceSet::Single(n) => ranges.push((resolve(*n), resolve(*n))),
+        SequenceSet::Range(start, end) => {
+            let (start, end) = (resolve(*start), resolve(*end));
+            // 4:2 is the same range as 2:4
+            ranges.push((start.min(end), start.max(end)));
+        }
+        SequenceSet::All => ranges.push((1, max)),
+        SequenceSet::Multiple(sets) => {
+            for set in sets {
+                collect_ranges(set, max, ranges);
+            }
+        }
     }
+}
 
-    /// Get sequence number by UID
-    pub fn get_seq_by_uid(&self, uid: u32) -> Option<u32> {
-        self.uid_map.get(&uid).copied()
+/// Sort ranges and merge the overlapping and adjacent ones
+fn merge_ranges(mut ranges: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    ranges.retain(|(start, end)| start <= end);
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
     }
+    merged
 }
 
 /// IMAP Session
@@ -153,6 +252,13 @@ pub struct ImapSession {
     pub started_at: DateTime<Utc>,
     /// Last activity time
     pub last_activity: DateTime<Utc>,
+    /// The ALERT for the current maintenance window was sent
+    pub maintenance_alerted: bool,
+    /// Username given in the last LOGIN or AUTHENTICATE
+    pub login_username: Option<String>,
+    /// Storage root of the region the user's tenant is pinned to, when it
+    /// is this server's region
+    pub storage_path: Option<PathBuf>,
 }
 
 impl ImapSession {
@@ -168,6 +274,9 @@ impl ImapSession {
             selected_mailbox: None,
             started_at: now,
             last_activity: now,
+            maintenance_alerted: false,
+            login_username: None,
+            storage_path: None,
         }
     }
 
@@ -286,6 +395,99 @@ mod tests {
         assert!(session.is_readonly());
     }
 
+    fn state(uid: u32, seen: bool) -> MessageState {
+        MessageState {
+            uid,
+            seen,
+            recent: false,
+        }
+    }
+
+    fn mailbox_with_uids(uids: &[u32]) -> SelectedMailbox {
+        let messages: Vec<MessageState> = uids.iter().map(|&uid| state(uid, true)).collect();
+        let mut mailbox = SelectedMailbox::new(Uuid::new_v4(), "INBOX".to_string());
+        mailbox.update_with_messages(&messages);
+        mailbox
+    }
+
+    #[test]
+    fn test_update_with_messages_uses_stored_uids() {
+        let mut mailbox = SelectedMailbox::new(Uuid::new_v4(), "INBOX".to_string());
+        mailbox.update_with_messages(&[
+            state(3, true),
+            MessageState {
+                uid: 7,
+                seen: false,
+                recent: true,
+            },
+        ]);
+
+        assert_eq!(mailbox.exists, 2);
+        assert_eq!(mailbox.recent, 1);
+        assert_eq!(mailbox.first_unseen, Some(2));
+        assert_eq!(mailbox.get_seq_by_uid(3), Some(1));
+        assert_eq!(mailbox.get_seq_by_uid(7), Some(2));
+        assert_eq!(mailbox.get_seq_by_uid(5), None);
+        assert_eq!(mailbox.get_uid(2), Some(7));
+        assert_eq!(mailbox.get_uid(0), None);
+        assert_eq!(mailbox.uid_next, 8);
+    }
+
+    #[test]
+    fn test_uid_ranges() {
+        let mailbox = mailbox_with_uids(&[2, 3, 5, 8, 9, 12]);
+
+        // Sequence numbers resolve to the UIDs of the requested messages
+        let set = SequenceSet::Range(1, 3);
+        assert_eq!(mailbox.uid_ranges(&set, false), vec![(2, 5)]);
+        let set = SequenceSet::Multiple(vec![
+            SequenceSet::Single(5),
+            SequenceSet::Single(1),
+            SequenceSet::Range(2, 3),
+        ]);
+        assert_eq!(mailbox.uid_ranges(&set, false), vec![(2, 5), (9, 9)]);
+        let set = SequenceSet::Range(4, u32::MAX);
+        assert_eq!(mailbox.uid_ranges(&set, false), vec![(8, 12)]);
+        let set = SequenceSet::Range(5, 20);
+        assert_eq!(mailbox.uid_ranges(&set, false), vec![(9, 12)]);
+        assert!(mailbox
+            .uid_ranges(&SequenceSet::Single(7), false)
+            .is_empty());
+
+        // UIDs are used as given, with * as the highest UID
+        let set = SequenceSet::Multiple(vec![
+            SequenceSet::Range(10, 4),
+            SequenceSet::Range(11, u32::MAX),
+        ]);
+        assert_eq!(mailbox.uid_ranges(&set, true), vec![(4, 12)]);
+        let set = SequenceSet::Range(20, u32::MAX);
+        assert_eq!(mailbox.uid_ranges(&set, true), vec![(12, 20)]);
+        assert_eq!(mailbox.uid_ranges(&SequenceSet::All, true), vec![(1, 12)]);
+
+        let empty = mailbox_with_uids(&[]);
+        assert!(empty.uid_ranges(&SequenceSet::All, false).is_empty());
+    }
+
+    #[test]
+    fn test_sync_uids() {
+        let mut mailbox = mailbox_with_uids(&[2, 3, 5, 8]);
+
+        assert!(!mailbox.append_uids(&[5, 8]));
+        assert!(mailbox.append_uids(&[8, 10, 11]));
+        assert_eq!(mailbox.uids, vec![2, 3, 5, 8, 10, 11]);
+        assert_eq!(mailbox.exists, 6);
+        assert_eq!(mailbox.uid_next, 12);
+
+        // Each sequence number accounts for the expunges announced before it
+        assert_eq!(mailbox.expunge_missing(&[2, 8, 11]), vec![2, 2, 3]);
+        assert_eq!(mailbox.uids, vec![2, 8, 11]);
+        assert_eq!(mailbox.exists, 3);
+
+        assert_eq!(mailbox.expunge(8), Some(2));
+        assert_eq!(mailbox.expunge(8), None);
+        assert_eq!(mailbox.get_seq_by_uid(11), Some(2));
+    }
+
     #[test]
     fn test_selected_mailbox() {
         let mut mailbox = SelectedMailbox::new(Uuid::new_v4(), "INBOX".to_string());
@@ -0,0 +1,151 @@
+//! Modified UTF-7 mailbox names (RFC 3501 section 5.1.3)
+//!
+//! Mailbox names are stored as UTF-8 and converted at the protocol
+//! boundary: the parser decodes names sent by clients and responses encode
+//! the names they return, so "送信済み" is sent as "&kAFP4W4IMH8-".
+
+/// Modified base64 alphabet (`,` instead of `/`)
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,";
+
+/// Encode a UTF-8 mailbox name in modified UTF-7
+pub fn encode(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut pending: Vec<u16> = Vec::new();
+
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut out, &mut pending);
+            if c == '&' {
+                out.push_str("&-");
+            } else {
+                out.push(c);
+            }
+        } else {
+            let mut units = [0u16; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut units));
+        }
+    }
+    flush(&mut out, &mut pending);
+    out
+}
+
+/// Write pending UTF-16 units as a `&...-` shifted sequence
+fn flush(out: &mut String, pending: &mut Vec<u16>) {
+    if pending.is_empty() {
+        return;
+    }
+    let bytes: Vec<u8> = pending.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+    out.push('&');
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..chunk.len() + 1 {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out.push('-');
+    pending.clear();
+}
+
+/// Decode a modified UTF-7 mailbox name
+///
+/// Names that are not valid modified UTF-7 (e.g. raw UTF-8 from clients
+/// that do not encode) are returned unchanged.
+pub fn decode(name: &str) -> String {
+    try_decode(name).unwrap_or_else(|| name.to_string())
+}
+
+fn try_decode(name: &str) -> Option<String> {
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let shifted = &rest[start + 1..];
+        let end = shifted.find('-')?;
+        let encoded = &shifted[..end];
+        if encoded.is_empty() {
+            out.push('&');
+        } else {
+            out.push_str(&decode_shifted(encoded)?);
+        }
+        rest = &shifted[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// Decode the modified base64 between `&` and `-`
+fn decode_shifted(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut nbits = 0;
+    for c in encoded.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits = bits << 6 | value;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            bytes.push((bits >> nbits) as u8);
+        }
+    }
+    if bytes.len() % 2 != 0 || bits & ((1 << nbits) - 1) != 0 {
+        return None;
+    }
+
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let decoded = String::from_utf16(&units).ok()?;
+    // Printable ASCII must not be encoded
+    if decoded.chars().any(|c| (' '..='~').contains(&c)) {
+        return None;
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("INBOX"), "INBOX");
+        assert_eq!(encode("Tom & Jerry"), "Tom &- Jerry");
+        assert_eq!(encode("送信済み"), "&kAFP4W4IMH8-");
+        assert_eq!(encode("Entwürfe"), "Entw&APw-rfe");
+        assert_eq!(
+            encode("~peter/mail/台北/日本語"),
+            "~peter/mail/&U,BTFw-/&ZeVnLIqe-"
+        );
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        for name in [
+            "INBOX",
+            "Tom & Jerry",
+            "送信済み",
+            "Entwürfe",
+            "보낸편지함",
+            "a😀b",
+        ] {
+            assert_eq!(decode(&encode(name)), name);
+        }
+        assert_eq!(
+            decode("~peter/mail/&U,BTFw-/&ZeVnLIqe-"),
+            "~peter/mail/台北/日本語"
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_is_unchanged() {
+        assert_eq!(decode("送信済み"), "送信済み");
+        assert_eq!(decode("&unterminated"), "&unterminated");
+        assert_eq!(decode("&AGE-"), "&AGE-");
+        assert_eq!(decode("&!!-"), "&!!-");
+    }
+}
@@ -0,0 +1,386 @@
+//! Display-name impersonation protection
+//!
+//! "CEO fraud" mail shows the name of someone in the organisation in its
+//! From header, but comes from an outside address. Tenants that turn the
+//! check on have inbound mail compared against their protected names (and,
+//! unless turned off, the names of their users); a match from an address
+//! outside the tenant's domains gets a warning for the web UI to show as a
+//! banner, and optionally the `impersonation` tag.
+//!
+//! Tenant settings:
+//! - `display_name_protection`: `"warn"` or `"tag"`; off when unset
+//! - `display_name_protect_users`: also protect user names (default true)
+
+use anyhow::Result;
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::{ProtectedNameRepository, TenantRepository};
+use serde::Serialize;
+
+/// Tag added to impersonating messages in `tag` mode
+pub const IMPERSONATION_TAG: &str = "impersonation";
+
+/// What happens to a message that impersonates a protected name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImpersonationAction {
+    /// Record a warning for the web UI
+    Warn,
+    /// Record a warning and tag the message
+    Tag,
+}
+
+/// Display-name protection settings of a tenant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayNameProtection {
+    pub action: ImpersonationAction,
+    /// Also protect the names of the tenant's users
+    pub protect_users: bool,
+}
+
+impl DisplayNameProtection {
+    /// Read `display_name_protection` and `display_name_protect_users` from
+    /// tenant settings; `None` when the check is off
+    pub fn from_tenant_settings(settings: &serde_json::Value) -> Option<Self> {
+        let action = match settings.get("display_name_protection")?.as_str()? {
+            "warn" => ImpersonationAction::Warn,
+            "tag" => ImpersonationAction::Tag,
+            _ => return None,
+        };
+        Some(Self {
+            action,
+            protect_users: settings
+                .get("display_name_protect_users")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true),
+        })
+    }
+}
+
+/// A From header impersonating a protected name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Impersonation {
+    /// Display name of the From header
+    pub display_name: String,
+    /// Address of the From header
+    pub from_address: String,
+    /// The protected name it matches
+    pub protected_name: String,
+    #[serde(skip)]
+    pub action: ImpersonationAction,
+}
+
+impl Impersonation {
+    /// Warning recorded in the message metadata (`metadata.warnings`)
+    pub fn warning(&self) -> serde_json::Value {
+        serde_json::json!({
+            "kind": "display_name_impersonation",
+            "message": format!(
+                "This message appears to be from {}, but was sent from an external address ({}).",
+                self.display_name, self.from_address
+            ),
+            "display_name": self.display_name,
+            "from_address": self.from_address,
+            "protected_name": self.protected_name,
+        })
+    }
+}
+
+/// A name to protect, with the address allowed to use it
+#[derive(Debug, Clone)]
+struct Protected {
+    name: String,
+    email: Option<String>,
+}
+
+/// Fold lookalike characters and split a name into lowercase words
+///
+/// Punctuation and word order do not matter, so "Doe, John" and
+/// "john.doe" are the same name as "John Doe".
+fn name_words(name: &str) -> Vec<String> {
+    let folded: String = name
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(fold_lookalike)
+        .collect();
+    let mut words: Vec<String> = folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect();
+    words.sort();
+    words
+}
+
+/// Latin letter a Cyrillic or Greek lookalike stands for
+pub(crate) fn fold_lookalike(c: char) -> char {
+    match c {
+        'а' | 'α' => 'a',
+        'с' => 'c',
+        'е' | 'ε' => 'e',
+        'і' | 'ι' => 'i',
+        'ј' => 'j',
+        'о' | 'ο' => 'o',
+        'р' | 'ρ' => 'p',
+        'ѕ' => 's',
+        'х' | 'χ' => 'x',
+        'у' => 'y',
+        _ => c,
+    }
+}
+
+/// Whether a display name shows a protected name
+///
+/// Every word of the protected name has to appear. Single-word names only
+/// match a display name of that word alone, to keep common first names
+/// from matching everything.
+pub(crate) fn shows_name(display_name: &str, protected: &str) -> bool {
+    let shown = name_words(display_name);
+    let wanted = name_words(protected);
+    match wanted.len() {
+        0 => false,
+        1 => shown == wanted,
+        _ => wanted.iter().all(|w| shown.contains(w)),
+    }
+}
+
+/// Domain of an address, lowercased
+pub(crate) fn domain_of(address: &str) -> Option<String> {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+/// Whether a domain or domain alias belongs to the tenant
+pub(crate) async fn is_tenant_domain(
+    db_pool: &DatabasePool,
+    tenant_id: TenantId,
+    domain: &str,
+) -> Result<bool> {
+    let internal: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM domains WHERE tenant_id = $1 AND LOWER(name) = $2
+            UNION ALL
+            SELECT 1 FROM domain_aliases WHERE tenant_id = $1 AND LOWER(alias_domain) = $2
+        )
+        "#,
+    )
+    .bind(tenant_id)
+    .bind(domain)
+    .fetch_one(db_pool.pool())
+    .await?;
+    Ok(internal)
+}
+
+/// First protected name the From header impersonates
+fn find_impersonation(
+    display_name: &str,
+    from_address: &str,
+    protected: &[Protected],
+) -> Option<String> {
+    let from = from_address.trim().to_lowercase();
+    protected
+        .iter()
+        .filter(|p| {
+            !p.email
+                .as_deref()
+                .is_some_and(|email| email.eq_ignore_ascii_case(&from))
+        })
+        .find(|p| shows_name(display_name, &p.name))
+        .map(|p| p.name.clone())
+}
+
+/// Checks inbound From headers against a tenant's protected names
+pub struct ImpersonationChecker {
+    db_pool: DatabasePool,
+}
+
+impl ImpersonationChecker {
+    /// Create a checker
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Check the From header of a message delivered to a tenant
+    ///
+    /// `None` when the tenant has the check off, the sender is inside the
+    /// tenant's domains or no protected name matches.
+    pub async fn check(
+        &self,
+        tenant_id: TenantId,
+        display_name: Option<&str>,
+        from_address: Option<&str>,
+    ) -> Result<Option<Impersonation>> {
+        let (Some(display_name), Some(from_address)) = (display_name, from_address) else {
+            return Ok(None);
+        };
+        if display_name.trim().is_empty() {
+            return Ok(None);
+        }
+        let Some(protection) = TenantRepository::new(self.db_pool.clone())
+            .find_by_id(tenant_id)
+            .await?
+            .and_then(|tenant| DisplayNameProtection::from_tenant_settings(&tenant.settings))
+        else {
+            return Ok(None);
+        };
+        let Some(domain) = domain_of(from_address) else {
+            return Ok(None);
+        };
+        if is_tenant_domain(&self.db_pool, tenant_id, &domain).await? {
+            return Ok(None);
+        }
+
+        let protected = self
+            .protected_names(tenant_id, protection.protect_users)
+            .await?;
+        Ok(
+            find_impersonation(display_name, from_address, &protected).map(|protected_name| {
+                Impersonation {
+                    display_name: display_name.to_string(),
+                    from_address: from_address.to_string(),
+                    protected_name,
+                    action: protection.action,
+                }
+            }),
+        )
+    }
+
+    /// Protected names of a tenant, with its user names if they are protected
+    async fn protected_names(
+        &self,
+        tenant_id: TenantId,
+        protect_users: bool,
+    ) -> Result<Vec<Protected>> {
+        let mut protected: Vec<Protected> = ProtectedNameRepository::new(self.db_pool.clone())
+            .list(tenant_id)
+            .await?
+            .into_iter()
+            .map(|p| Protected {
+                name: p.display_name,
+                email: p.email,
+            })
+            .collect();
+
+        if protect_users {
+            let users: Vec<(String, String)> = sqlx::query_as(
+                r#"
+                SELECT name, email FROM users
+                WHERE tenant_id = $1 AND active = true AND name IS NOT NULL AND name <> ''
+                "#,
+            )
+            .bind(tenant_id)
+            .fetch_all(self.db_pool.pool())
+            .await?;
+            protected.extend(users.into_iter().map(|(name, email)| Protected {
+                name,
+                email: Some(email),
+            }));
+        }
+        Ok(protected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_tenant_settings() {
+        assert_eq!(
+            DisplayNameProtection::from_tenant_settings(&serde_json::json!({})),
+            None
+        );
+        assert_eq!(
+            DisplayNameProtection::from_tenant_settings(
+                &serde_json::json!({"display_name_protection": "off"})
+            ),
+            None
+        );
+        assert_eq!(
+            DisplayNameProtection::from_tenant_settings(
+                &serde_json::json!({"display_name_protection": "warn"})
+            ),
+            Some(DisplayNameProtection {
+                action: ImpersonationAction::Warn,
+                protect_users: true,
+            })
+        );
+        assert_eq!(
+            DisplayNameProtection::from_tenant_settings(&serde_json::json!({
+                "display_name_protection": "tag",
+                "display_name_protect_users": false
+            })),
+            Some(DisplayNameProtection {
+                action: ImpersonationAction::Tag,
+                protect_users: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_shows_name() {
+        assert!(shows_name("John Doe", "John Doe"));
+        assert!(shows_name("\"Doe, John\"", "John Doe"));
+        assert!(shows_name("john.doe", "John Doe"));
+        assert!(shows_name("John Doe (CEO)", "John Doe"));
+        // Cyrillic о and е
+        assert!(shows_name("Jоhn Dое", "John Doe"));
+        assert!(!shows_name("John Smith", "John Doe"));
+        assert!(!shows_name("Johnny Doe", "John Doe"));
+
+        assert!(shows_name("Accounting", "accounting"));
+        assert!(!shows_name("Accounting Newsletter", "Accounting"));
+        assert!(!shows_name("John Doe", ""));
+    }
+
+    #[test]
+    fn test_find_impersonation() {
+        let protected = vec![
+            Protected {
+                name: "Jane Roe".to_string(),
+                email: Some("jane.roe@gmail.com".to_string()),
+            },
+            Protected {
+                name: "John Doe".to_string(),
+                email: None,
+            },
+        ];
+
+        assert_eq!(
+            find_impersonation("John Doe", "ceo.office@evil.example", &protected),
+            Some("John Doe".to_string())
+        );
+        assert_eq!(
+            find_impersonation("Jane Roe", "jane@evil.example", &protected),
+            Some("Jane Roe".to_string())
+        );
+        // Her own outside address may use her name
+        assert_eq!(
+            find_impersonation("Jane Roe", "Jane.Roe@gmail.com", &protected),
+            None
+        );
+        assert_eq!(
+            find_impersonation("Newsletter", "news@example.org", &protected),
+            None
+        );
+    }
+
+    #[test]
+    fn test_warning() {
+        let impersonation = Impersonation {
+            display_name: "John Doe".to_string(),
+            from_address: "john@evil.example".to_string(),
+            protected_name: "John Doe".to_string(),
+            action: ImpersonationAction::Warn,
+        };
+        let warning = impersonation.warning();
+        assert_eq!(warning["kind"], "display_name_impersonation");
+        assert_eq!(warning["protected_name"], "John Doe");
+        assert!(warning["message"]
+            .as_str()
+            .unwrap()
+            .contains("john@evil.example"));
+    }
+}
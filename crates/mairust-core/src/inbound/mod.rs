@@ -0,0 +1,18 @@
+//! Inbound parse - post received messages to tenant webhooks
+//!
+//! A tenant maps an address, or a whole domain, to a webhook with an inbound
+//! route. Messages for a routed recipient are kept under the route, queued,
+//! parsed into JSON and posted with retries; delivery to the recipient's
+//! mailbox is optional per route.
+
+mod payload;
+mod worker;
+
+pub use payload::{
+    attachment, attachment_signature, inbound_storage_path, payload_signature,
+    verify_attachment_signature, AttachmentLinks, InboundAddress, InboundAttachment, InboundAuth,
+    InboundEnvelope, InboundHeader, InboundPayload, ATTACHMENT_URL_TTL_DAYS,
+};
+pub use worker::{InboundJob, InboundWebhookWorker, INBOUND_QUEUE};
+
+pub(crate) use payload::content_type;
@@ -0,0 +1,465 @@
+//! Inbound parse payloads
+//!
+//! A matching message is posted as JSON: envelope, raw headers, the parsed
+//! address fields, the text and HTML bodies, and attachment metadata. The
+//! attachment contents are not inlined; each attachment gets a link signed
+//! with the route secret that expires after `ATTACHMENT_URL_TTL_DAYS`.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use mail_parser::{MessageParser, MimeHeaders};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Lifetime of attachment links in days, counted from the delivery attempt
+pub const ATTACHMENT_URL_TTL_DAYS: i64 = 7;
+
+/// Authentication results recorded at reception
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InboundAuth {
+    pub spf: Option<String>,
+    pub dkim: Option<String>,
+    pub dmarc: Option<String>,
+}
+
+/// SMTP envelope of the delivery
+#[derive(Debug, Clone, Serialize)]
+pub struct InboundEnvelope {
+    pub from: Option<String>,
+    /// The recipient that matched the route
+    pub to: String,
+}
+
+/// A mailbox from an address header
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InboundAddress {
+    pub name: Option<String>,
+    pub address: Option<String>,
+}
+
+/// A header field as it appears in the message, unfolded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InboundHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Attachment metadata and its signed download link
+#[derive(Debug, Clone, Serialize)]
+pub struct InboundAttachment {
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub content_id: Option<String>,
+    pub size: usize,
+    pub url: String,
+}
+
+/// JSON body posted to an inbound route's webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct InboundPayload {
+    /// Message ID assigned at reception
+    pub id: Uuid,
+    pub route_id: Uuid,
+    pub received_at: DateTime<Utc>,
+    pub envelope: InboundEnvelope,
+    pub message_id: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<String>,
+    pub from: Vec<InboundAddress>,
+    pub to: Vec<InboundAddress>,
+    pub cc: Vec<InboundAddress>,
+    pub reply_to: Vec<InboundAddress>,
+    pub headers: Vec<InboundHeader>,
+    pub text: Option<String>,
+    pub html: Option<String>,
+    pub attachments: Vec<InboundAttachment>,
+    pub auth: InboundAuth,
+    pub spam_score: Option<f64>,
+}
+
+/// Where and how attachment links are signed
+#[derive(Debug, Clone)]
+pub struct AttachmentLinks<'a> {
+    /// Public base URL of the API
+    pub base_url: &'a str,
+    pub route_id: Uuid,
+    pub secret: &'a str,
+    pub expires: DateTime<Utc>,
+}
+
+impl AttachmentLinks<'_> {
+    /// Signed link to attachment `index` of a message
+    pub fn url(&self, message_id: Uuid, index: usize) -> String {
+        let expires = self.expires.timestamp();
+        format!(
+            "{}/api/v1/inbound/attachments/{}/{}/{}?expires={}&signature={}",
+            self.base_url.trim_end_matches('/'),
+            self.route_id,
+            message_id,
+            index,
+            expires,
+            attachment_signature(self.secret, self.route_id, message_id, index, expires)
+        )
+    }
+}
+
+impl InboundPayload {
+    /// Parse a raw message into a payload
+    ///
+    /// Returns `None` when the message cannot be parsed.
+    pub fn parse(
+        raw: &[u8],
+        id: Uuid,
+        received_at: DateTime<Utc>,
+        envelope: InboundEnvelope,
+        links: &AttachmentLinks<'_>,
+    ) -> Option<Self> {
+        let parsed = MessageParser::default().parse(raw)?;
+
+        let text = join_bodies(parsed.text_bodies().filter(|p| !is_html(p)));
+        let html = join_bodies(parsed.html_bodies().filter(|p| is_html(p)));
+
+        let attachments = parsed
+            .attachments()
+            .enumerate()
+            .map(|(index, part)| InboundAttachment {
+                filename: part.attachment_name().map(str::to_string),
+                content_type: content_type(part),
+                content_id: part.content_id().map(str::to_string),
+                size: part.contents().len(),
+                url: links.url(id, index),
+            })
+            .collect();
+
+        Some(Self {
+            id,
+            route_id: links.route_id,
+            received_at,
+            envelope,
+            message_id: parsed.message_id().map(str::to_string),
+            subject: parsed.subject().map(str::to_string),
+            date: parsed.date().map(|d| d.to_rfc3339()),
+            from: addresses(parsed.from()),
+            to: addresses(parsed.to()),
+            cc: addresses(parsed.cc()),
+            reply_to: addresses(parsed.reply_to()),
+            headers: raw_headers(raw),
+            text,
+            html,
+            attachments,
+            auth: InboundAuth::default(),
+            spam_score: None,
+        })
+    }
+}
+
+/// Content type and contents of attachment `index` of a raw message
+pub fn attachment(raw: &[u8], index: usize) -> Option<(String, Option<String>, Vec<u8>)> {
+    let parsed = MessageParser::default().parse(raw)?;
+    let part = parsed.attachments().nth(index)?;
+    Some((
+        content_type(part),
+        part.attachment_name().map(str::to_string),
+        part.contents().to_vec(),
+    ))
+}
+
+/// Storage path of the copy kept for a route
+///
+/// The webhook job and attachment links read the message from here, so it
+/// does not depend on the mailbox copy.
+pub fn inbound_storage_path(tenant_id: Uuid, route_id: Uuid, message_id: Uuid) -> String {
+    format!("{}/inbound/{}/{}.eml", tenant_id, route_id, message_id)
+}
+
+/// `X-Webhook-Signature` value for a request body
+pub fn payload_signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Signature of an attachment link
+pub fn attachment_signature(
+    secret: &str,
+    route_id: Uuid,
+    message_id: Uuid,
+    index: usize,
+    expires: i64,
+) -> String {
+    let mac = attachment_mac(secret, route_id, message_id, index, expires);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check an attachment link's signature and expiry
+pub fn verify_attachment_signature(
+    secret: &str,
+    route_id: Uuid,
+    message_id: Uuid,
+    index: usize,
+    expires: i64,
+    signature: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    if expires < now.timestamp() {
+        return false;
+    }
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    attachment_mac(secret, route_id, message_id, index, expires)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn attachment_mac(
+    secret: &str,
+    route_id: Uuid,
+    message_id: Uuid,
+    index: usize,
+    expires: i64,
+) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}/{}/{}/{}", route_id, message_id, index, expires).as_bytes());
+    mac
+}
+
+/// Media type of a part, `application/octet-stream` when it has none
+pub(crate) fn content_type(part: &mail_parser::MessagePart<'_>) -> String {
+    part.content_type()
+        .map(|ct| match ct.subtype() {
+            Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+            None => ct.ctype().to_string(),
+        })
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+        .to_lowercase()
+}
+
+fn is_html(part: &mail_parser::MessagePart<'_>) -> bool {
+    part.content_type()
+        .and_then(|ct| ct.subtype())
+        .map(|subtype| subtype.eq_ignore_ascii_case("html"))
+        .unwrap_or(false)
+}
+
+/// Concatenated text of body parts, `None` when there is none
+fn join_bodies<'a>(
+    parts: impl Iterator<Item = &'a mail_parser::MessagePart<'a>>,
+) -> Option<String> {
+    let text: Vec<&str> = parts.filter_map(|p| p.text_contents()).collect();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.join("\n"))
+    }
+}
+
+fn addresses(address: Option<&mail_parser::Address<'_>>) -> Vec<InboundAddress> {
+    address
+        .map(|a| {
+            a.iter()
+                .map(|addr| InboundAddress {
+                    name: addr.name().map(str::to_string),
+                    address: addr.address().map(str::to_string),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Header fields of a raw message in order, with folding removed
+fn raw_headers(raw: &[u8]) -> Vec<InboundHeader> {
+    let text = String::from_utf8_lossy(raw);
+    let mut headers: Vec<InboundHeader> = Vec::new();
+
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(last) = headers.last_mut() {
+                last.value.push(' ');
+                last.value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push(InboundHeader {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            });
+        }
+    }
+
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    const MESSAGE: &[u8] = b"From: Alice <alice@example.net>\r\n\
+To: orders@example.com, \"Bob\" <bob@example.com>\r\n\
+Subject: Order\r\n\
+\x20#42\r\n\
+Message-ID: <42@example.net>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b1\"\r\n\
+\r\n\
+--b1\r\n\
+Content-Type: multipart/alternative; boundary=\"b2\"\r\n\
+\r\n\
+--b2\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Two widgets, please.\r\n\
+--b2\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>Two widgets, please.</p>\r\n\
+--b2--\r\n\
+--b1\r\n\
+Content-Type: application/pdf; name=\"po.pdf\"\r\n\
+Content-Disposition: attachment; filename=\"po.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQK\r\n\
+--b1--\r\n";
+
+    fn links(expires: DateTime<Utc>) -> AttachmentLinks<'static> {
+        AttachmentLinks {
+            base_url: "https://mail.example.com/",
+            route_id: Uuid::nil(),
+            secret: "whsec_test",
+            expires,
+        }
+    }
+
+    #[test]
+    fn test_parse_payload() {
+        let id = Uuid::now_v7();
+        let envelope = InboundEnvelope {
+            from: Some("alice@example.net".to_string()),
+            to: "orders@example.com".to_string(),
+        };
+        let payload =
+            InboundPayload::parse(MESSAGE, id, Utc::now(), envelope, &links(Utc::now())).unwrap();
+
+        assert_eq!(payload.subject.as_deref(), Some("Order #42"));
+        assert_eq!(payload.message_id.as_deref(), Some("42@example.net"));
+        assert_eq!(
+            payload.from,
+            vec![InboundAddress {
+                name: Some("Alice".to_string()),
+                address: Some("alice@example.net".to_string()),
+            }]
+        );
+        assert_eq!(payload.to.len(), 2);
+        assert_eq!(
+            payload.text.as_deref().map(str::trim),
+            Some("Two widgets, please.")
+        );
+        assert_eq!(
+            payload.html.as_deref().map(str::trim),
+            Some("<p>Two widgets, please.</p>")
+        );
+
+        assert_eq!(payload.attachments.len(), 1);
+        let attachment = &payload.attachments[0];
+        assert_eq!(attachment.filename.as_deref(), Some("po.pdf"));
+        assert_eq!(attachment.content_type, "application/pdf");
+        assert_eq!(attachment.size, 9);
+        assert!(attachment.url.starts_with(&format!(
+            "https://mail.example.com/api/v1/inbound/attachments/{}/{}/0?expires=",
+            Uuid::nil(),
+            id
+        )));
+    }
+
+    #[test]
+    fn test_plain_message_has_no_html() {
+        let raw = b"From: a@example.net\r\nSubject: Hi\r\n\r\nJust text\r\n";
+        let envelope = InboundEnvelope {
+            from: None,
+            to: "x@example.com".to_string(),
+        };
+        let payload =
+            InboundPayload::parse(raw, Uuid::nil(), Utc::now(), envelope, &links(Utc::now()))
+                .unwrap();
+        assert_eq!(payload.text.as_deref().map(str::trim), Some("Just text"));
+        assert!(payload.html.is_none());
+        assert!(payload.attachments.is_empty());
+    }
+
+    #[test]
+    fn test_attachment() {
+        let (content_type, filename, contents) = attachment(MESSAGE, 0).unwrap();
+        assert_eq!(content_type, "application/pdf");
+        assert_eq!(filename.as_deref(), Some("po.pdf"));
+        assert_eq!(contents, b"%PDF-1.4\n");
+        assert!(attachment(MESSAGE, 1).is_none());
+    }
+
+    #[test]
+    fn test_raw_headers() {
+        let headers = raw_headers(MESSAGE);
+        assert_eq!(headers[0].name, "From");
+        assert_eq!(headers[2].value, "Order #42");
+        assert_eq!(headers.len(), 6);
+    }
+
+    #[test]
+    fn test_attachment_signature() {
+        let now = Utc::now();
+        let route = Uuid::now_v7();
+        let message = Uuid::now_v7();
+        let expires = now.timestamp() + 60;
+        let signature = attachment_signature("s", route, message, 0, expires);
+
+        assert!(verify_attachment_signature(
+            "s", route, message, 0, expires, &signature, now
+        ));
+        assert!(!verify_attachment_signature(
+            "t", route, message, 0, expires, &signature, now
+        ));
+        assert!(!verify_attachment_signature(
+            "s", route, message, 1, expires, &signature, now
+        ));
+        assert!(!verify_attachment_signature(
+            "s",
+            route,
+            message,
+            0,
+            expires + 1,
+            &signature,
+            now
+        ));
+        assert!(!verify_attachment_signature(
+            "s",
+            route,
+            message,
+            0,
+            expires,
+            &signature,
+            now + Duration::seconds(61)
+        ));
+        assert!(!verify_attachment_signature(
+            "s", route, message, 0, expires, "zz", now
+        ));
+    }
+
+    #[test]
+    fn test_payload_signature() {
+        assert_eq!(
+            payload_signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
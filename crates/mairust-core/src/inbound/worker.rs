@@ -0,0 +1,315 @@
+//! Inbound webhook delivery
+//!
+//! Matching messages are queued as `inbound_webhook` jobs at reception and
+//! posted by `InboundWebhookWorker`, which retries failed deliveries with
+//! the delivery queue's backoff.
+
+use super::payload::{
+    payload_signature, AttachmentLinks, InboundAuth, InboundEnvelope, InboundPayload,
+    ATTACHMENT_URL_TTL_DAYS,
+};
+use crate::hooks::validate_webhook_url;
+use crate::queue::calculate_backoff;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use mairust_storage::models::Job;
+use mairust_storage::repository::InboundRouteRepository;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use tokio::time::{interval, Duration as TokioDuration};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Job queue for webhook deliveries
+pub const INBOUND_QUEUE: &str = "inbound_webhook";
+
+/// Delivery attempts before a job is marked failed (about 4 hours of retries)
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Timeout for one webhook request
+const WEBHOOK_TIMEOUT_SECS: u64 = 30;
+
+/// Jobs claimed per poll
+const BATCH_SIZE: i64 = 10;
+
+/// Job payload for a webhook delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboundJob {
+    pub route_id: Uuid,
+    pub tenant_id: Uuid,
+    pub message_id: Uuid,
+    /// Path of the route's copy of the message in file storage
+    pub storage_path: String,
+    pub envelope_from: Option<String>,
+    pub recipient: String,
+    #[serde(default)]
+    pub auth: InboundAuth,
+    #[serde(default)]
+    pub spam_score: Option<f64>,
+    pub received_at: DateTime<Utc>,
+}
+
+impl InboundJob {
+    /// Queue the delivery
+    pub async fn enqueue(&self, db_pool: &DatabasePool) -> Result<Uuid> {
+        let job_id = Uuid::now_v7();
+
+        sqlx::query(
+            r#"
+            INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at,
+                              tenant_id)
+            VALUES ($1, $2, $3, 'pending', 0, $4, NOW(), NOW(), $5)
+            "#,
+        )
+        .bind(job_id)
+        .bind(INBOUND_QUEUE)
+        .bind(serde_json::to_value(self)?)
+        .bind(MAX_ATTEMPTS)
+        .bind(self.tenant_id)
+        .execute(db_pool.pool())
+        .await?;
+
+        debug!(
+            "Queued inbound webhook job {} for message {} (route {})",
+            job_id, self.message_id, self.route_id
+        );
+        Ok(job_id)
+    }
+}
+
+/// Why a delivery attempt failed
+#[derive(Debug)]
+enum WebhookError {
+    /// Retrying cannot succeed
+    Permanent(String),
+    /// Worth retrying later
+    Temporary(String),
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Permanent(e) | WebhookError::Temporary(e) => f.write_str(e),
+        }
+    }
+}
+
+/// Posts queued inbound messages to their routes' webhooks
+pub struct InboundWebhookWorker<S: FileStorage> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+    http_client: Client,
+    /// Public base URL of the API, for attachment links
+    base_url: String,
+}
+
+impl<S: FileStorage + Send + Sync + 'static> InboundWebhookWorker<S> {
+    /// Create a new worker
+    pub fn new(db_pool: DatabasePool, file_storage: Arc<S>, base_url: String) -> Self {
+        let http_client = Client::builder()
+            .timeout(std::time::Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            db_pool,
+            file_storage,
+            http_client,
+            base_url,
+        }
+    }
+
+    /// Run the worker
+    pub async fn run(&self) {
+        let mut ticker = interval(TokioDuration::from_secs(5));
+
+        info!("Inbound webhook worker started");
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = self.process_due_jobs().await {
+                error!("Error processing inbound webhooks: {}", e);
+            }
+        }
+    }
+
+    /// Claim and deliver due jobs
+    async fn process_due_jobs(&self) -> Result<()> {
+        let jobs: Vec<Job> = sqlx::query_as(
+            r#"
+            UPDATE jobs SET status = 'processing', started_at = NOW()
+            WHERE id IN (
+                SELECT id FROM jobs
+                WHERE queue = $1 AND status = 'pending' AND scheduled_at <= NOW()
+                ORDER BY scheduled_at ASC
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(INBOUND_QUEUE)
+        .bind(BATCH_SIZE)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        for job in jobs {
+            self.process_job(job).await;
+        }
+
+        Ok(())
+    }
+
+    /// Deliver one job and record the outcome
+    async fn process_job(&self, job: Job) {
+        let result = match serde_json::from_value::<InboundJob>(job.payload) {
+            Ok(inbound) => self.deliver(job.id, &inbound).await,
+            Err(e) => Err(WebhookError::Permanent(format!(
+                "Invalid job payload: {}",
+                e
+            ))),
+        };
+
+        let attempts = job.attempts + 1;
+        let outcome = match result {
+            Ok(()) => {
+                info!("Inbound webhook job {} delivered", job.id);
+                sqlx::query(
+                    "UPDATE jobs SET status = 'completed', attempts = $2, completed_at = NOW()
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .execute(self.db_pool.pool())
+                .await
+            }
+            Err(WebhookError::Temporary(e)) if attempts < job.max_attempts => {
+                let scheduled_at = Utc::now() + calculate_backoff(attempts);
+                warn!(
+                    "Inbound webhook job {} failed, retrying at {}: {}",
+                    job.id, scheduled_at, e
+                );
+                sqlx::query(
+                    "UPDATE jobs SET status = 'pending', attempts = $2, last_error = $3,
+                                     scheduled_at = $4
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .bind(&e)
+                .bind(scheduled_at)
+                .execute(self.db_pool.pool())
+                .await
+            }
+            Err(e) => {
+                error!("Inbound webhook job {} failed: {}", job.id, e);
+                sqlx::query(
+                    "UPDATE jobs SET status = 'failed', attempts = $2, last_error = $3,
+                                     completed_at = NOW()
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .bind(e.to_string())
+                .execute(self.db_pool.pool())
+                .await
+            }
+        };
+
+        if let Err(e) = outcome {
+            error!("Failed to record inbound webhook job {}: {}", job.id, e);
+        }
+    }
+
+    /// Post a message to its route's webhook
+    ///
+    /// The job ID is sent as `X-Webhook-Id`, so receivers can recognize a
+    /// retried delivery.
+    async fn deliver(&self, job_id: Uuid, job: &InboundJob) -> Result<(), WebhookError> {
+        let route = InboundRouteRepository::new(self.db_pool.clone())
+            .get_by_id(job.route_id)
+            .await
+            .map_err(|e| WebhookError::Temporary(format!("Failed to load route: {}", e)))?
+            .filter(|route| route.enabled)
+            .ok_or_else(|| {
+                WebhookError::Permanent(format!("Route {} deleted or disabled", job.route_id))
+            })?;
+
+        validate_webhook_url(&route.webhook_url)
+            .map_err(|e| WebhookError::Permanent(e.to_string()))?;
+
+        let raw = self
+            .file_storage
+            .retrieve(&job.storage_path)
+            .await
+            .map_err(|e| WebhookError::Temporary(format!("Failed to read message: {}", e)))?;
+
+        let links = AttachmentLinks {
+            base_url: &self.base_url,
+            route_id: route.id,
+            secret: &route.secret,
+            expires: Utc::now() + Duration::days(ATTACHMENT_URL_TTL_DAYS),
+        };
+        let envelope = InboundEnvelope {
+            from: job.envelope_from.clone(),
+            to: job.recipient.clone(),
+        };
+        let mut payload =
+            InboundPayload::parse(&raw, job.message_id, job.received_at, envelope, &links)
+                .ok_or_else(|| WebhookError::Permanent("Failed to parse message".to_string()))?;
+        payload.auth = job.auth.clone();
+        payload.spam_score = job.spam_score;
+
+        let body = serde_json::to_vec(&payload)
+            .map_err(|e| WebhookError::Permanent(format!("Failed to encode payload: {}", e)))?;
+
+        let response = self
+            .http_client
+            .post(&route.webhook_url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Id", job_id.to_string())
+            .header(
+                "X-Webhook-Signature",
+                payload_signature(&route.secret, &body),
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| WebhookError::Temporary(format!("Request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(WebhookError::Temporary(format!(
+                "Webhook returned status {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inbound_job_defaults() {
+        let job: InboundJob = serde_json::from_value(serde_json::json!({
+            "route_id": Uuid::nil(),
+            "tenant_id": Uuid::nil(),
+            "message_id": Uuid::nil(),
+            "storage_path": "t/inbound/r/m.eml",
+            "envelope_from": null,
+            "recipient": "orders@example.com",
+            "received_at": "2024-01-16T00:00:00Z"
+        }))
+        .unwrap();
+        assert_eq!(job.auth, InboundAuth::default());
+        assert!(job.spam_score.is_none());
+    }
+}
@@ -0,0 +1,18 @@
+//! Envelope journaling - export a copy of every message to an archive
+//!
+//! Inbound and outbound messages are recorded with their envelope: the
+//! sender, each recipient with what happened to the message for it, and
+//! the verdicts of the authentication and spam checks. A worker wraps each
+//! record in a journal report and sends it to an external archive by SMTP
+//! or stores it in an S3 bucket.
+
+mod report;
+mod s3;
+mod worker;
+
+pub use report::{
+    render_report, JournalDirection, JournalRecipient, JournalVerdicts, RecipientDisposition,
+};
+pub use worker::{
+    journal_storage_path, retry_failed_exports, Journal, JournalJob, JournalWorker, JOURNAL_QUEUE,
+};
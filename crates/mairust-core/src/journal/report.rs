@@ -0,0 +1,320 @@
+//! Envelope journal reports
+//!
+//! A report is a `multipart/mixed` message: a plain text part lists the
+//! envelope sender, each recipient with what happened to the message for
+//! it, and the verdicts of the checks, followed by the original message as
+//! a `message/rfc822` attachment.
+
+use super::worker::JournalJob;
+use serde::{Deserialize, Serialize};
+
+/// Whether a journaled message came in or went out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalDirection {
+    /// Received for local recipients
+    Inbound,
+    /// Delivered to remote recipients
+    Outbound,
+}
+
+impl JournalDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalDirection::Inbound => "inbound",
+            JournalDirection::Outbound => "outbound",
+        }
+    }
+}
+
+/// What happened to a journaled message for one recipient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipientDisposition {
+    /// Stored in the recipient's mailbox
+    Mailbox,
+    /// Stored in the recipient's Junk folder
+    Junk,
+    /// Posted to an inbound route's webhook
+    Webhook,
+    /// Processed as a bounce of a campaign message
+    Bounce,
+    /// Parsed as DMARC or TLS-RPT reports
+    Report,
+    /// No mailbox exists for the recipient
+    NoMailbox,
+    /// Deleted by one of the recipient's filters
+    Discarded,
+    /// Held in quarantine by the virus scan, the spam filter or a policy
+    Quarantined,
+    /// Accepted by the recipient's mail server
+    Delivered,
+    /// Delivery failed permanently
+    Failed,
+}
+
+impl RecipientDisposition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecipientDisposition::Mailbox => "mailbox",
+            RecipientDisposition::Junk => "junk",
+            RecipientDisposition::Webhook => "webhook",
+            RecipientDisposition::Bounce => "bounce",
+            RecipientDisposition::Report => "report",
+            RecipientDisposition::NoMailbox => "no_mailbox",
+            RecipientDisposition::Discarded => "discarded",
+            RecipientDisposition::Quarantined => "quarantined",
+            RecipientDisposition::Delivered => "delivered",
+            RecipientDisposition::Failed => "failed",
+        }
+    }
+}
+
+/// A recipient listed in a journal report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalRecipient {
+    pub address: String,
+    pub disposition: RecipientDisposition,
+    /// Delivering host or failure reason
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+impl JournalRecipient {
+    pub fn new(address: impl Into<String>, disposition: RecipientDisposition) -> Self {
+        Self {
+            address: address.into(),
+            disposition,
+            detail: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// Verdicts recorded with a journaled message
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JournalVerdicts {
+    #[serde(default)]
+    pub spf: Option<String>,
+    #[serde(default)]
+    pub dkim: Option<String>,
+    #[serde(default)]
+    pub dmarc: Option<String>,
+    #[serde(default)]
+    pub spam_score: Option<f64>,
+    #[serde(default)]
+    pub spam_action: Option<String>,
+    /// RFC 8689 REQUIRETLS was requested
+    #[serde(default)]
+    pub require_tls: bool,
+}
+
+/// Build the journal report of `job` wrapping `original`
+///
+/// `to` is the journal mailbox when the report is sent by SMTP.
+pub fn render_report(
+    job: &JournalJob,
+    hostname: &str,
+    from: &str,
+    to: Option<&str>,
+    original: &[u8],
+) -> Vec<u8> {
+    let boundary = format!("=_journal_{}", job.journal_id.simple());
+    let parsed = mail_parser::MessageParser::default().parse(original);
+    let subject = parsed
+        .as_ref()
+        .and_then(|m| m.subject())
+        .map(header_value)
+        .unwrap_or_default();
+    let message_id = parsed
+        .as_ref()
+        .and_then(|m| m.message_id())
+        .map(header_value);
+
+    let mut msg = String::new();
+    msg.push_str(&format!("From: MaiRust Journal <{}>\r\n", from));
+    if let Some(to) = to {
+        msg.push_str(&format!("To: <{}>\r\n", to));
+    }
+    msg.push_str(&format!("Subject: Journal report: {}\r\n", subject));
+    msg.push_str(&format!("Date: {}\r\n", job.recorded_at.to_rfc2822()));
+    msg.push_str(&format!(
+        "Message-ID: <{}@{}>\r\n",
+        job.journal_id, hostname
+    ));
+    msg.push_str(&format!(
+        "X-MaiRust-Journal-Report: {}\r\n",
+        job.direction.as_str()
+    ));
+    msg.push_str("Auto-Submitted: auto-generated\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+        boundary
+    ));
+
+    // Envelope and verdicts
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+    msg.push_str(&envelope_text(job, &subject, message_id.as_deref()));
+    msg.push_str("\r\n");
+
+    // Original message
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str("Content-Type: message/rfc822\r\n");
+    msg.push_str("Content-Disposition: attachment; filename=\"original.eml\"\r\n\r\n");
+
+    let mut report = msg.into_bytes();
+    report.extend_from_slice(original);
+    if !original.ends_with(b"\n") {
+        report.extend_from_slice(b"\r\n");
+    }
+    report.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    report
+}
+
+/// The envelope part: one `Field: value` line per fact
+fn envelope_text(job: &JournalJob, subject: &str, message_id: Option<&str>) -> String {
+    let mut text = String::new();
+    let mut line = |name: &str, value: &str| {
+        text.push_str(&format!("{}: {}\r\n", name, header_value(value)));
+    };
+
+    line("Direction", job.direction.as_str());
+    line("Sender", job.sender.as_deref().unwrap_or("<>"));
+    line("Subject", subject);
+    if let Some(message_id) = message_id {
+        line("Message-Id", &format!("<{}>", message_id));
+    }
+    line("Recorded-At", &job.recorded_at.to_rfc3339());
+    if let Some(tenant_id) = job.tenant_id {
+        line("Tenant", &tenant_id.to_string());
+    }
+    for recipient in &job.recipients {
+        let value = match &recipient.detail {
+            Some(detail) => format!(
+                "{} ({}: {})",
+                recipient.address,
+                recipient.disposition.as_str(),
+                detail
+            ),
+            None => format!("{} ({})", recipient.address, recipient.disposition.as_str()),
+        };
+        line("Recipient", &value);
+    }
+
+    let verdicts = &job.verdicts;
+    if let Some(score) = verdicts.spam_score {
+        line("Spam-Score", &format!("{:.2}", score));
+    }
+    if let Some(action) = &verdicts.spam_action {
+        line("Spam-Action", action);
+    }
+    if let Some(spf) = &verdicts.spf {
+        line("SPF", spf);
+    }
+    if let Some(dkim) = &verdicts.dkim {
+        line("DKIM", dkim);
+    }
+    if let Some(dmarc) = &verdicts.dmarc {
+        line("DMARC", dmarc);
+    }
+    if verdicts.require_tls {
+        line("Require-TLS", "yes");
+    }
+
+    text
+}
+
+/// A value safe to put on a single header-style line
+fn header_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn job() -> JournalJob {
+        JournalJob::new(
+            JournalDirection::Inbound,
+            Uuid::now_v7(),
+            None,
+            Some("alice@example.net".to_string()),
+            vec![
+                JournalRecipient::new("bob@example.com", RecipientDisposition::Mailbox),
+                JournalRecipient::new("eve@example.com", RecipientDisposition::Junk),
+            ],
+            JournalVerdicts {
+                spf: Some("pass".to_string()),
+                spam_score: Some(6.5),
+                ..JournalVerdicts::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_render_report() {
+        let original =
+            b"Subject: Quarterly\r\n results\r\nMessage-ID: <q1@example.net>\r\n\r\nbody";
+        let report = render_report(
+            &job(),
+            "mail.example.com",
+            "journal@mail.example.com",
+            Some("archive@vault.example.org"),
+            original,
+        );
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(report.contains("To: <archive@vault.example.org>\r\n"));
+        assert!(report.contains("X-MaiRust-Journal-Report: inbound\r\n"));
+        assert!(report.contains("Sender: alice@example.net\r\n"));
+        assert!(report.contains("Message-Id: <q1@example.net>\r\n"));
+        assert!(report.contains("Recipient: bob@example.com (mailbox)\r\n"));
+        assert!(report.contains("Recipient: eve@example.com (junk)\r\n"));
+        assert!(report.contains("Spam-Score: 6.50\r\n"));
+        assert!(report.contains("SPF: pass\r\n"));
+        assert!(report.contains("Content-Type: message/rfc822\r\n"));
+        assert!(report.contains("\r\n\r\nbody\r\n--=_journal_"));
+        assert!(report.ends_with("--\r\n"));
+    }
+
+    #[test]
+    fn test_header_value() {
+        assert_eq!(header_value("a\r\nBcc: x"), "a  Bcc: x");
+        assert_eq!(header_value(" plain "), "plain");
+    }
+
+    #[test]
+    fn test_recipient_detail() {
+        let mut job = job();
+        job.recipients =
+            vec![
+                JournalRecipient::new("carol@example.org", RecipientDisposition::Failed)
+                    .with_detail("550 no such user"),
+            ];
+        job.sender = None;
+        let report = String::from_utf8(render_report(
+            &job,
+            "mail.example.com",
+            "journal@mail.example.com",
+            None,
+            b"Subject: x\r\n\r\n",
+        ))
+        .unwrap();
+
+        assert!(!report.contains("\r\nTo: "));
+        assert!(report.contains("Sender: <>\r\n"));
+        assert!(report.contains("Recipient: carol@example.org (failed: 550 no such user)\r\n"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Upload of journal reports to S3
+//!
+//! Objects are written with a single `PUT` signed with AWS Signature
+//! Version 4, using path-style URLs so that S3-compatible stores such as
+//! MinIO work with a custom endpoint.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use mairust_common::config::S3Config;
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Headers included in the signature
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// Store `body` under `key` in the configured bucket
+///
+/// Credentials not set in the configuration are taken from
+/// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+pub(crate) async fn put_object(
+    client: &Client,
+    config: &S3Config,
+    key: &str,
+    body: Vec<u8>,
+) -> Result<()> {
+    let access_key = config
+        .access_key_id
+        .clone()
+        .or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())
+        .ok_or_else(|| anyhow!("No S3 access key configured"))?;
+    let secret_key = config
+        .secret_access_key
+        .clone()
+        .or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())
+        .ok_or_else(|| anyhow!("No S3 secret key configured"))?;
+
+    let url = object_url(config, key)?;
+    let payload_hash = hex::encode(Sha256::digest(&body));
+    let now = Utc::now();
+    let authorization = authorization(
+        &url,
+        &payload_hash,
+        now,
+        &config.region,
+        &access_key,
+        &secret_key,
+    )?;
+
+    let response = client
+        .put(url)
+        .header("x-amz-content-sha256", &payload_hash)
+        .header("x-amz-date", amz_date(now))
+        .header("Authorization", authorization)
+        .header("Content-Type", "message/rfc822")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| anyhow!("S3 request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let detail = response.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "S3 returned status {}: {}",
+            status,
+            detail.chars().take(200).collect::<String>()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Path-style URL of an object
+fn object_url(config: &S3Config, key: &str) -> Result<Url> {
+    let endpoint = config
+        .endpoint
+        .clone()
+        .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.region));
+    let url = format!(
+        "{}/{}/{}",
+        endpoint.trim_end_matches('/'),
+        uri_encode(&config.bucket, false),
+        uri_encode(key, true)
+    );
+    Url::parse(&url).map_err(|e| anyhow!("Invalid S3 URL {}: {}", url, e))
+}
+
+/// `Authorization` header for a `PUT` of `url`
+fn authorization(
+    url: &Url,
+    payload_hash: &str,
+    now: DateTime<Utc>,
+    region: &str,
+    access_key: &str,
+    secret_key: &str,
+) -> Result<String> {
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(anyhow!("S3 URL without host: {}", url)),
+    };
+    let date = now.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+
+    let canonical_request = format!(
+        "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        url.path(),
+        host,
+        payload_hash,
+        amz_date(now),
+        SIGNED_HEADERS,
+        payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date(now),
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(secret_key, &date, region, "s3");
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+    Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, SIGNED_HEADERS, signature
+    ))
+}
+
+fn amz_date(now: DateTime<Utc>) -> String {
+    now.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Signing key derived from the secret key for one day, region and service
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+/// Percent-encode everything but unreserved characters (and `/` in keys)
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn config(endpoint: Option<&str>) -> S3Config {
+        S3Config {
+            bucket: "archive".to_string(),
+            region: "eu-west-1".to_string(),
+            endpoint: endpoint.map(str::to_string),
+            access_key_id: None,
+            secret_access_key: None,
+            multipart_threshold_mb: 8,
+        }
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_object_url() {
+        let url = object_url(&config(None), "journal/2026/10/16/a b.eml").unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://s3.eu-west-1.amazonaws.com/archive/journal/2026/10/16/a%20b.eml"
+        );
+
+        let url = object_url(&config(Some("http://minio:9000/")), "j/x.eml").unwrap();
+        assert_eq!(url.as_str(), "http://minio:9000/archive/j/x.eml");
+    }
+
+    #[test]
+    fn test_authorization() {
+        let url = object_url(&config(Some("http://minio:9000")), "j/x.eml").unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let header = authorization(&url, "e3b0", now, "eu-west-1", "AKID", "secret").unwrap();
+
+        assert!(header.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKID/20261016/eu-west-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="
+        ));
+        let signature = header.rsplit('=').next().unwrap();
+        assert_eq!(signature.len(), 64);
+    }
+}
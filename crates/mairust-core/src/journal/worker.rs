@@ -0,0 +1,595 @@
+//! Journal export
+//!
+//! Messages are recorded as `journal` jobs with a copy of the message in
+//! file storage. `JournalWorker` renders each job's report and exports it
+//! to the archive, retrying with the delivery queue's backoff while the
+//! archive is unreachable, so reports back up in the queue instead of being
+//! lost. Failed exports and a growing backlog raise alerts.
+
+use super::report::{render_report, JournalDirection, JournalRecipient, JournalVerdicts};
+use super::s3;
+use crate::queue::calculate_backoff;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use lettre::address::{Address, Envelope};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use mairust_common::config::{JournalConfig, JournalTarget};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use mairust_storage::models::Job;
+use mairust_storage::repository::JobRepository;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Job queue for journal exports
+pub const JOURNAL_QUEUE: &str = "journal";
+
+/// Jobs claimed per poll
+const BATCH_SIZE: i64 = 20;
+
+/// Timeout for one S3 upload or alert request
+const HTTP_TIMEOUT_SECS: u64 = 60;
+
+/// Timeout for one SMTP command to the journal mailbox's server
+const SMTP_TIMEOUT_SECS: u64 = 60;
+
+/// How often the backlog is checked
+const BACKLOG_CHECK_SECS: u64 = 60;
+
+/// Path of a journaled copy in file storage
+pub fn journal_storage_path(journal_id: Uuid) -> String {
+    format!("journal/{}.eml", journal_id)
+}
+
+/// Job payload for a journal export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalJob {
+    pub journal_id: Uuid,
+    pub message_id: Uuid,
+    pub direction: JournalDirection,
+    #[serde(default)]
+    pub tenant_id: Option<Uuid>,
+    /// Envelope sender (None for the null sender)
+    pub sender: Option<String>,
+    pub recipients: Vec<JournalRecipient>,
+    #[serde(default)]
+    pub verdicts: JournalVerdicts,
+    /// Path of the journaled copy in file storage
+    pub storage_path: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl JournalJob {
+    /// Describe a message to journal
+    pub fn new(
+        direction: JournalDirection,
+        message_id: Uuid,
+        tenant_id: Option<Uuid>,
+        sender: Option<String>,
+        recipients: Vec<JournalRecipient>,
+        verdicts: JournalVerdicts,
+    ) -> Self {
+        let journal_id = Uuid::now_v7();
+        Self {
+            journal_id,
+            message_id,
+            direction,
+            tenant_id,
+            sender: sender.filter(|s| !s.is_empty()),
+            recipients,
+            verdicts,
+            storage_path: journal_storage_path(journal_id),
+            recorded_at: Utc::now(),
+        }
+    }
+
+    /// Keep a copy of the message and queue the export
+    pub async fn enqueue<S: FileStorage + ?Sized>(
+        &self,
+        db_pool: &DatabasePool,
+        file_storage: &S,
+        raw_message: &[u8],
+        max_attempts: i32,
+    ) -> Result<Uuid> {
+        file_storage.store(&self.storage_path, raw_message).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at,
+                              tenant_id)
+            VALUES ($1, $2, $3, 'pending', 0, $4, NOW(), NOW(), $5)
+            "#,
+        )
+        .bind(self.journal_id)
+        .bind(JOURNAL_QUEUE)
+        .bind(serde_json::to_value(self)?)
+        .bind(max_attempts)
+        .bind(self.tenant_id)
+        .execute(db_pool.pool())
+        .await?;
+
+        debug!(
+            "Queued {} journal report {} for message {}",
+            self.direction.as_str(),
+            self.journal_id,
+            self.message_id
+        );
+        Ok(self.journal_id)
+    }
+}
+
+/// Shared journaling settings for the servers that record messages
+///
+/// The default handle journals nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    config: Option<Arc<JournalConfig>>,
+}
+
+impl Journal {
+    /// Create a handle; journaling stays off unless the configuration enables it
+    pub fn new(config: JournalConfig) -> Self {
+        Self {
+            config: config.enabled.then(|| Arc::new(config)),
+        }
+    }
+
+    /// Whether messages going in `direction` are journaled
+    pub fn records(&self, direction: JournalDirection) -> bool {
+        match (&self.config, direction) {
+            (Some(config), JournalDirection::Inbound) => config.journals_inbound(),
+            (Some(config), JournalDirection::Outbound) => config.journals_outbound(),
+            (None, _) => false,
+        }
+    }
+
+    /// Queue `job` for export when its direction is journaled
+    ///
+    /// A failure is logged; the message itself has already been handled.
+    pub async fn record<S: FileStorage + ?Sized>(
+        &self,
+        db_pool: &DatabasePool,
+        file_storage: &S,
+        job: JournalJob,
+        raw_message: &[u8],
+    ) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        if !self.records(job.direction) || job.recipients.is_empty() {
+            return;
+        }
+
+        if let Err(e) = job
+            .enqueue(db_pool, file_storage, raw_message, config.max_attempts)
+            .await
+        {
+            error!(
+                "Failed to journal {} message {}: {}",
+                job.direction.as_str(),
+                job.message_id,
+                e
+            );
+        }
+    }
+}
+
+/// Kinds of alerts, throttled separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum AlertKind {
+    ExportFailed,
+    Backlog,
+}
+
+impl AlertKind {
+    fn event(&self) -> &'static str {
+        match self {
+            AlertKind::ExportFailed => "journal.export_failed",
+            AlertKind::Backlog => "journal.backlog",
+        }
+    }
+}
+
+/// Exports queued journal reports to the archive
+pub struct JournalWorker<S: FileStorage> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+    config: JournalConfig,
+    /// Server hostname, for report Message-IDs and the default sender
+    hostname: String,
+    http_client: Client,
+    last_alerts: Mutex<HashMap<AlertKind, Instant>>,
+}
+
+impl<S: FileStorage + Send + Sync + 'static> JournalWorker<S> {
+    /// Create a new worker
+    pub fn new(
+        db_pool: DatabasePool,
+        file_storage: Arc<S>,
+        config: JournalConfig,
+        hostname: String,
+    ) -> Self {
+        let http_client = Client::builder()
+            .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            db_pool,
+            file_storage,
+            config,
+            hostname,
+            http_client,
+            last_alerts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run the worker
+    pub async fn run(&self) {
+        let mut ticker = interval(Duration::from_secs(5));
+        let mut backlog_ticker = interval(Duration::from_secs(BACKLOG_CHECK_SECS));
+
+        info!(
+            "Journal worker started (exporting to {})",
+            self.target_description()
+        );
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(e) = self.process_due_jobs().await {
+                        error!("Error processing journal exports: {}", e);
+                    }
+                }
+                _ = backlog_ticker.tick() => {
+                    if let Err(e) = self.check_backlog().await {
+                        error!("Error checking journal backlog: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn target_description(&self) -> String {
+        match self.config.target {
+            JournalTarget::Smtp => format!(
+                "{} via {}:{}",
+                self.config.recipient.as_deref().unwrap_or_default(),
+                self.config.smtp_host.as_deref().unwrap_or_default(),
+                self.config.smtp_port
+            ),
+            JournalTarget::S3 => format!(
+                "s3://{}/{}",
+                self.config
+                    .s3
+                    .as_ref()
+                    .map(|s3| s3.bucket.as_str())
+                    .unwrap_or_default(),
+                self.config.s3_prefix
+            ),
+        }
+    }
+
+    /// Envelope sender and From address of the reports
+    fn sender(&self) -> String {
+        self.config
+            .sender
+            .clone()
+            .unwrap_or_else(|| format!("journal@{}", self.hostname))
+    }
+
+    /// Claim and export due jobs, oldest first
+    async fn process_due_jobs(&self) -> Result<()> {
+        let jobs: Vec<Job> = sqlx::query_as(
+            r#"
+            UPDATE jobs SET status = 'processing', started_at = NOW()
+            WHERE id IN (
+                SELECT id FROM jobs
+                WHERE queue = $1 AND status = 'pending' AND scheduled_at <= NOW()
+                ORDER BY scheduled_at ASC
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(JOURNAL_QUEUE)
+        .bind(BATCH_SIZE)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        for job in jobs {
+            self.process_job(job).await;
+        }
+
+        Ok(())
+    }
+
+    /// Export one job and record the outcome
+    async fn process_job(&self, job: Job) {
+        let journal = serde_json::from_value::<JournalJob>(job.payload);
+        let result = match &journal {
+            Ok(journal) => self.export(journal).await,
+            Err(e) => Err(anyhow!("Invalid job payload: {}", e)),
+        };
+
+        let attempts = job.attempts + 1;
+        let outcome = match result {
+            Ok(()) => {
+                debug!("Journal report {} exported", job.id);
+                if let Ok(journal) = &journal {
+                    if let Err(e) = self.file_storage.delete(&journal.storage_path).await {
+                        warn!(
+                            "Failed to remove journaled copy {}: {}",
+                            journal.storage_path, e
+                        );
+                    }
+                }
+                sqlx::query(
+                    "UPDATE jobs SET status = 'completed', attempts = $2, completed_at = NOW()
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .execute(self.db_pool.pool())
+                .await
+            }
+            Err(e) if journal.is_ok() && attempts < job.max_attempts => {
+                let scheduled_at = Utc::now() + calculate_backoff(attempts);
+                warn!(
+                    "Journal export {} failed, retrying at {}: {}",
+                    job.id, scheduled_at, e
+                );
+                sqlx::query(
+                    "UPDATE jobs SET status = 'pending', attempts = $2, last_error = $3,
+                                     scheduled_at = $4
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .bind(e.to_string())
+                .bind(scheduled_at)
+                .execute(self.db_pool.pool())
+                .await
+            }
+            Err(e) => {
+                // The copy is kept so that the export can be retried
+                self.alert(
+                    AlertKind::ExportFailed,
+                    &format!(
+                        "Journal report {} could not be exported after {} attempts: {}",
+                        job.id, attempts, e
+                    ),
+                )
+                .await;
+                sqlx::query(
+                    "UPDATE jobs SET status = 'failed', attempts = $2, last_error = $3,
+                                     completed_at = NOW()
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .bind(e.to_string())
+                .execute(self.db_pool.pool())
+                .await
+            }
+        };
+
+        if let Err(e) = outcome {
+            error!("Failed to record journal export {}: {}", job.id, e);
+        }
+    }
+
+    /// Render a report and hand it to the archive
+    async fn export(&self, job: &JournalJob) -> Result<()> {
+        let original = self
+            .file_storage
+            .retrieve(&job.storage_path)
+            .await
+            .map_err(|e| anyhow!("Failed to read journaled copy: {}", e))?;
+        let sender = self.sender();
+
+        match self.config.target {
+            JournalTarget::Smtp => {
+                let host = self
+                    .config
+                    .smtp_host
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("No journal SMTP host configured"))?;
+                let recipient = self
+                    .config
+                    .recipient
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("No journal recipient configured"))?;
+                let report =
+                    render_report(job, &self.hostname, &sender, Some(recipient), &original);
+
+                let envelope = Envelope::new(
+                    Some(sender.parse::<Address>()?),
+                    vec![recipient.parse::<Address>()?],
+                )?;
+                // STARTTLS when the archive offers it
+                let tls = Tls::Opportunistic(TlsParameters::new(host.to_string())?);
+                let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+                    .port(self.config.smtp_port)
+                    .tls(tls)
+                    .hello_name(ClientId::Domain(self.hostname.clone()))
+                    .timeout(Some(Duration::from_secs(SMTP_TIMEOUT_SECS)))
+                    .build();
+                transport
+                    .send_raw(&envelope, &report)
+                    .await
+                    .map_err(|e| anyhow!("Journal delivery to {} failed: {}", host, e))?;
+            }
+            JournalTarget::S3 => {
+                let s3_config = self
+                    .config
+                    .s3
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No journal S3 bucket configured"))?;
+                let report = render_report(job, &self.hostname, &sender, None, &original);
+                let key = object_key(&self.config.s3_prefix, job);
+                s3::put_object(&self.http_client, s3_config, &key, report).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Alert when reports pile up because the archive is not keeping up
+    async fn check_backlog(&self) -> Result<()> {
+        let stats = JobRepository::new(self.db_pool.clone())
+            .queue_stats(JOURNAL_QUEUE, None)
+            .await?;
+        let pending = stats.total("pending") + stats.total("processing");
+        let oldest_minutes = stats
+            .oldest_pending_at
+            .map(|t| (Utc::now() - t).num_minutes())
+            .unwrap_or(0);
+
+        if pending > self.config.backlog_alert_threshold
+            || oldest_minutes > self.config.backlog_alert_minutes
+        {
+            self.alert(
+                AlertKind::Backlog,
+                &format!(
+                    "{} journal reports are waiting for export; the oldest was queued {} minutes ago",
+                    pending, oldest_minutes
+                ),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    /// Log an alert and notify the alert webhook, at most once per interval
+    async fn alert(&self, kind: AlertKind, message: &str) {
+        error!("{}", message);
+
+        let Some(url) = &self.config.alert_webhook_url else {
+            return;
+        };
+        {
+            let throttle = Duration::from_secs(self.config.alert_interval_minutes * 60);
+            let mut last_alerts = self.last_alerts.lock().unwrap();
+            if last_alerts
+                .get(&kind)
+                .is_some_and(|at| at.elapsed() < throttle)
+            {
+                return;
+            }
+            last_alerts.insert(kind, Instant::now());
+        }
+
+        let body = serde_json::json!({
+            "event": kind.event(),
+            "message": message,
+            "hostname": self.hostname,
+            "timestamp": Utc::now(),
+        });
+        match self.http_client.post(url).json(&body).send().await {
+            Ok(response) if !response.status().is_success() => {
+                warn!(
+                    "Journal alert webhook returned status {}",
+                    response.status()
+                );
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to send journal alert: {}", e),
+        }
+    }
+}
+
+/// Object key of a report: `<prefix>YYYY/MM/DD/<journal id>.eml`
+fn object_key(prefix: &str, job: &JournalJob) -> String {
+    format!(
+        "{}{}/{}.eml",
+        prefix,
+        job.recorded_at.format("%Y/%m/%d"),
+        job.journal_id
+    )
+}
+
+/// Requeue failed exports, returning how many were requeued
+///
+/// Used once the archive is reachable again after exports ran out of
+/// attempts; the journaled copies are kept until a report is exported.
+pub async fn retry_failed_exports(db_pool: &DatabasePool) -> Result<u64> {
+    let result = sqlx::query(
+        "UPDATE jobs SET status = 'pending', attempts = 0, scheduled_at = NOW(),
+                         completed_at = NULL
+         WHERE queue = $1 AND status = 'failed'",
+    )
+    .bind(JOURNAL_QUEUE)
+    .execute(db_pool.pool())
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::report::RecipientDisposition;
+    use super::*;
+    use chrono::TimeZone;
+
+    fn job() -> JournalJob {
+        JournalJob::new(
+            JournalDirection::Outbound,
+            Uuid::now_v7(),
+            Some(Uuid::nil()),
+            Some(String::new()),
+            vec![JournalRecipient::new(
+                "bob@example.net",
+                RecipientDisposition::Delivered,
+            )],
+            JournalVerdicts::default(),
+        )
+    }
+
+    #[test]
+    fn test_journal_job_roundtrip() {
+        let job = job();
+        assert!(job.sender.is_none());
+        assert_eq!(job.storage_path, journal_storage_path(job.journal_id));
+
+        let value = serde_json::to_value(&job).unwrap();
+        assert_eq!(value["direction"], "outbound");
+        assert_eq!(value["recipients"][0]["disposition"], "delivered");
+        let parsed: JournalJob = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.recipients, job.recipients);
+    }
+
+    #[test]
+    fn test_object_key() {
+        let mut job = job();
+        job.recorded_at = Utc.with_ymd_and_hms(2026, 10, 6, 9, 30, 0).unwrap();
+        assert_eq!(
+            object_key("journal/", &job),
+            format!("journal/2026/10/06/{}.eml", job.journal_id)
+        );
+    }
+
+    #[test]
+    fn test_journal_handle() {
+        let disabled = Journal::default();
+        assert!(!disabled.records(JournalDirection::Inbound));
+
+        let journal = Journal::new(JournalConfig {
+            enabled: true,
+            outbound: false,
+            ..JournalConfig::default()
+        });
+        assert!(journal.records(JournalDirection::Inbound));
+        assert!(!journal.records(JournalDirection::Outbound));
+    }
+}
@@ -1,28 +1,98 @@
-//! MaiRust Core - SMTP/IMAP/POP3 server and mail processing
+//! MaiRust Core - SMTP/IMAP/POP3/ManageSieve server and mail processing
 //!
 //! This crate provides the core mail server functionality for MaiRust,
 //! including message reception, hook execution, queue management, and plugin system.
 
+pub mod antivirus;
+pub mod attachments;
+pub mod banner;
+pub mod bundle;
 pub mod email_auth;
+pub mod filters;
+pub mod folders;
+pub mod geoip;
 pub mod hooks;
 pub mod imap;
+pub mod impersonation;
+pub mod inbound;
+pub mod journal;
+pub mod limits;
+pub mod maintenance;
+pub mod managesieve;
+pub mod mta_sts;
+pub mod onboarding;
 pub mod plugins;
 pub mod policy;
+pub mod preview;
 pub mod pop3;
+pub mod print;
+pub mod provisioning;
+pub mod proxy_protocol;
+pub mod quarantine;
 pub mod queue;
+pub mod quota;
+pub mod relocation;
+pub mod replication;
+pub mod reports;
+pub mod reprocess;
+pub mod sanitize;
 pub mod scheduled;
 pub mod search;
+pub mod secure_message;
+pub mod security;
+pub mod sent_archive;
+pub mod services;
+pub mod sessions;
+pub mod share;
+pub mod sieve;
 pub mod smtp;
 pub mod spam;
+pub mod subaddress;
+pub mod subdomains;
+pub mod tracking;
 
-pub use email_auth::{AuthenticationResult, DkimResult, DkimSigner, DmarcResult, SpfResult};
+pub use antivirus::{Antivirus, AntivirusRejection, ScanVerdict};
+pub use banner::{BannerStamper, BannerTemplate, SenderBanners};
+pub use bundle::{BundleError, BundleFormat, ChangeAction, ConfigBundle, ConfigBundleManager, ImportOptions, ImportReport};
+pub use email_auth::{
+    ArcResult, AuthenticationResult, DkimKeyring, DkimResult, DkimRotationWorker, DkimSigner,
+    DmarcReportWorker, DmarcResult, SpfResult,
+};
+pub use filters::{FilterAction, FilterOutcome, MailboxFilters};
+pub use folders::{FolderProvisioner, FolderSettings, SpecialUse};
+pub use geoip::{GeoIp, GeoIpInfo, MmdbReader};
 pub use hooks::HookManager;
 pub use imap::{ImapConfig, ImapServer};
+pub use impersonation::{DisplayNameProtection, Impersonation, ImpersonationAction, ImpersonationChecker};
+pub use inbound::{InboundJob, InboundPayload, InboundWebhookWorker};
+pub use journal::{Journal, JournalDirection, JournalJob, JournalWorker};
+pub use limits::{ConnectionLimiter, ConnectionRefusal};
+pub use maintenance::{MaintenanceMode, MaintenanceStatus};
+pub use managesieve::{ManageSieveConfig, ManageSieveServer};
+pub use mta_sts::{MtaStsHost, MtaStsMode, MtaStsPolicy};
+pub use onboarding::{BootstrapRequest, BootstrapResult, DkimKey, OnboardingError, TenantBootstrapper};
 pub use plugins::{PluginManager, PluginManagerConfig, AiCategorizationPlugin, CategorizationInput, CategorizationOutput};
-pub use policy::{PolicyContext, PolicyEngine, PolicyEvaluation, PolicyEvaluationResult, PolicyMatch};
+pub use policy::{PolicyContext, PolicyEngine, PolicyEvaluation, PolicyEvaluationResult, PolicyMatch, PolicySimulator};
 pub use pop3::{Pop3Config, Pop3Server};
+pub use preview::{MessagePreview, PreviewBackfillWorker};
+pub use print::{PrintableMessage, PrintedAttachment};
+pub use provisioning::{BulkMailboxRow, BulkProvisioner, MailboxProvisioningWorker, PasswordMode, ProvisioningError};
+pub use proxy_protocol::TrustedProxies;
+pub use quarantine::{QuarantineDigestWorker, QuarantineReason};
 pub use queue::QueueManager;
-pub use scheduled::{CampaignManager, CampaignError, ScheduledDeliveryWorker, DeliveryResult, RateLimiter, RemainingQuota, TemplateRenderer, SmtpConfig};
+pub use quota::{QuotaChecker, QuotaVerdict};
+pub use relocation::{RelocationOptions, RelocationSummary, StorageRelocator};
+pub use replication::{FileSync, Replication, ReplicationRole, ReplicationStatus};
+pub use reprocess::{MessageReprocessor, ReprocessOptions, ReprocessSelection, ReprocessStep, ReprocessSummary};
+pub use scheduled::{BounceProcessor, CampaignManager, CampaignError, ScheduledDeliveryWorker, DeliveryResult, RateLimiter, RemainingQuota, TemplateRenderer, SmtpConfig};
 pub use search::{MeilisearchClient, MeilisearchConfig, MessageDocument, MessageIndexer, MessageSearchHit, SearchOptions, SearchResult};
+pub use security::{SecurityEventType, SecurityNotificationSettings, SecurityNotifier};
+pub use sent_archive::{ArchiveTarget, SentArchiver};
+pub use services::{ServicePolicy, ServiceRefusal};
+pub use sessions::{SessionFilter, SessionInfo, SessionProtocol, SessionRegistry};
+pub use share::SharedMessage;
 pub use smtp::SmtpServer;
-pub use spam::{RspamdClient, RspamdConfig, SpamAction, SpamCheckResult, SpamFilter};
+pub use spam::{JunkFiler, RspamdClient, RspamdConfig, SpamAction, SpamCheckResult, SpamFilter, SpamRejection};
+pub use subaddress::SubaddressFiler;
+pub use subdomains::{SigningDomain, SubdomainPolicy};
+pub use tracking::{AcmeWorker, ClickTarget, ClickTracker, TrackingCertificates};
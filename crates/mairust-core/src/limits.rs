@@ -0,0 +1,346 @@
+//! Connection limits of the IMAP and POP3 servers
+//!
+//! A `ConnectionLimiter` caps the connections a server holds open at once
+//! and the connections of each client IP address. An address that opens
+//! too many connections within the window, or fails authentication too
+//! often, is banned for a while. All listeners of a protocol share one
+//! limiter, so the limits apply across plaintext and TLS ports.
+
+use mairust_common::config::ConnectionLimitConfig;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Why a connection was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRefusal {
+    /// The server holds its maximum number of connections
+    ServerFull,
+    /// The address holds its maximum number of connections
+    TooManyFromIp,
+    /// The address is banned
+    Banned,
+}
+
+impl ConnectionRefusal {
+    /// Text sent to the refused client
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ConnectionRefusal::ServerFull => "Too many connections, try again later",
+            ConnectionRefusal::TooManyFromIp => "Too many connections from your address",
+            ConnectionRefusal::Banned => {
+                "Too many connection attempts or failed logins, try again later"
+            }
+        }
+    }
+}
+
+/// Connections, recent attempts and failed logins of one address
+#[derive(Debug, Default)]
+struct IpState {
+    active: usize,
+    attempts: VecDeque<Instant>,
+    auth_failures: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl IpState {
+    fn is_idle(&self, now: Instant) -> bool {
+        self.active == 0
+            && self.attempts.is_empty()
+            && self.auth_failures.is_empty()
+            && !matches!(self.banned_until, Some(until) if now < until)
+    }
+}
+
+/// Per-address bookkeeping; times are passed in so it can be tested
+#[derive(Debug)]
+pub(crate) struct IpTracker {
+    config: ConnectionLimitConfig,
+    ips: HashMap<IpAddr, IpState>,
+    last_prune: Option<Instant>,
+}
+
+/// Whether `count` is above `limit` (a limit of 0 is disabled)
+fn exceeds(count: usize, limit: usize) -> bool {
+    limit > 0 && count > limit
+}
+
+/// Drop the entries older than the window
+fn trim(events: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while events
+        .front()
+        .is_some_and(|at| now.duration_since(*at) >= window)
+    {
+        events.pop_front();
+    }
+}
+
+impl IpTracker {
+    pub fn new(config: ConnectionLimitConfig) -> Self {
+        Self {
+            config,
+            ips: HashMap::new(),
+            last_prune: None,
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs)
+    }
+
+    fn ban(&self, state: &mut IpState, now: Instant) {
+        state.banned_until = Some(now + Duration::from_secs(self.config.ban_secs));
+        state.attempts.clear();
+        state.auth_failures.clear();
+    }
+
+    /// Count a new connection from `ip`, refusing it when a limit is hit
+    pub fn admit(&mut self, ip: IpAddr, now: Instant) -> Result<(), ConnectionRefusal> {
+        self.prune(now);
+        let window = self.window();
+        let mut state = self.ips.remove(&ip).unwrap_or_default();
+
+        let result = if state.banned_until.is_some_and(|until| now < until) {
+            Err(ConnectionRefusal::Banned)
+        } else {
+            state.banned_until = None;
+            trim(&mut state.attempts, now, window);
+            state.attempts.push_back(now);
+
+            if exceeds(state.attempts.len(), self.config.max_attempts_per_ip) {
+                self.ban(&mut state, now);
+                Err(ConnectionRefusal::Banned)
+            } else if exceeds(state.active + 1, self.config.max_connections_per_ip) {
+                Err(ConnectionRefusal::TooManyFromIp)
+            } else {
+                state.active += 1;
+                Ok(())
+            }
+        };
+
+        self.ips.insert(ip, state);
+        result
+    }
+
+    /// Count the end of a connection admitted for `ip`
+    pub fn release(&mut self, ip: IpAddr) {
+        if let Some(state) = self.ips.get_mut(&ip) {
+            state.active = state.active.saturating_sub(1);
+        }
+    }
+
+    /// Count a failed login from `ip`; true when the address is now banned
+    pub fn auth_failed(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let window = self.window();
+        let mut state = self.ips.remove(&ip).unwrap_or_default();
+        trim(&mut state.auth_failures, now, window);
+        state.auth_failures.push_back(now);
+
+        let limit = self.config.max_auth_failures_per_ip;
+        let banned = limit > 0 && state.auth_failures.len() >= limit;
+        if banned {
+            self.ban(&mut state, now);
+        }
+
+        self.ips.insert(ip, state);
+        banned
+    }
+
+    /// Forget idle addresses, at most once per window
+    fn prune(&mut self, now: Instant) {
+        let window = self.window();
+        if self
+            .last_prune
+            .is_some_and(|at| now.duration_since(at) < window)
+        {
+            return;
+        }
+        self.last_prune = Some(now);
+
+        self.ips.retain(|_, state| {
+            trim(&mut state.attempts, now, window);
+            trim(&mut state.auth_failures, now, window);
+            !state.is_idle(now)
+        });
+    }
+
+    #[cfg(test)]
+    fn tracked(&self) -> usize {
+        self.ips.len()
+    }
+}
+
+/// Shared connection limits of one protocol
+#[derive(Debug, Clone)]
+pub struct ConnectionLimiter {
+    semaphore: Arc<Semaphore>,
+    tracker: Arc<Mutex<IpTracker>>,
+}
+
+impl ConnectionLimiter {
+    /// Create a limiter for at most `max_connections` connections
+    pub fn new(max_connections: usize, config: ConnectionLimitConfig) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_connections)),
+            tracker: Arc::new(Mutex::new(IpTracker::new(config))),
+        }
+    }
+
+    /// Admit a connection from `ip`
+    ///
+    /// The returned permit holds the connection's slot until it is dropped.
+    pub fn try_acquire(&self, ip: IpAddr) -> Result<ConnectionPermit, ConnectionRefusal> {
+        self.tracker.lock().unwrap().admit(ip, Instant::now())?;
+
+        match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Ok(ConnectionPermit {
+                ip,
+                tracker: self.tracker.clone(),
+                _permit: permit,
+            }),
+            Err(_) => {
+                self.tracker.lock().unwrap().release(ip);
+                Err(ConnectionRefusal::ServerFull)
+            }
+        }
+    }
+
+    /// Record a failed login from `ip`; true when the address is now banned
+    pub fn auth_failed(&self, ip: IpAddr) -> bool {
+        let banned = self.tracker.lock().unwrap().auth_failed(ip, Instant::now());
+        if banned {
+            warn!("Banning {} after repeated authentication failures", ip);
+        }
+        banned
+    }
+}
+
+/// A connection admitted by a `ConnectionLimiter`
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    ip: IpAddr,
+    tracker: Arc<Mutex<IpTracker>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Ok(mut tracker) = self.tracker.lock() {
+            tracker.release(self.ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConnectionLimitConfig {
+        ConnectionLimitConfig {
+            max_connections_per_ip: 2,
+            max_attempts_per_ip: 5,
+            max_auth_failures_per_ip: 3,
+            window_secs: 60,
+            ban_secs: 600,
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn test_connections_per_ip() {
+        let mut tracker = IpTracker::new(config());
+        let now = Instant::now();
+
+        assert!(tracker.admit(ip(1), now).is_ok());
+        assert!(tracker.admit(ip(1), now).is_ok());
+        assert_eq!(
+            tracker.admit(ip(1), now),
+            Err(ConnectionRefusal::TooManyFromIp)
+        );
+        assert!(tracker.admit(ip(2), now).is_ok());
+
+        tracker.release(ip(1));
+        assert!(tracker.admit(ip(1), now).is_ok());
+    }
+
+    #[test]
+    fn test_attempts_ban() {
+        let mut tracker = IpTracker::new(config());
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            assert!(tracker.admit(ip(1), now).is_ok());
+            tracker.release(ip(1));
+        }
+        assert_eq!(tracker.admit(ip(1), now), Err(ConnectionRefusal::Banned));
+
+        let later = now + Duration::from_secs(599);
+        assert_eq!(tracker.admit(ip(1), later), Err(ConnectionRefusal::Banned));
+        let after_ban = now + Duration::from_secs(600);
+        assert!(tracker.admit(ip(1), after_ban).is_ok());
+    }
+
+    #[test]
+    fn test_attempts_outside_window() {
+        let mut tracker = IpTracker::new(config());
+        let now = Instant::now();
+
+        for n in 0..10 {
+            let at = now + Duration::from_secs(n * 20);
+            assert!(tracker.admit(ip(1), at).is_ok());
+            tracker.release(ip(1));
+        }
+    }
+
+    #[test]
+    fn test_auth_failure_ban() {
+        let mut tracker = IpTracker::new(config());
+        let now = Instant::now();
+
+        assert!(!tracker.auth_failed(ip(1), now));
+        assert!(!tracker.auth_failed(ip(1), now));
+        assert!(tracker.auth_failed(ip(1), now));
+        assert_eq!(tracker.admit(ip(1), now), Err(ConnectionRefusal::Banned));
+        assert!(tracker.admit(ip(2), now).is_ok());
+
+        // Failures outside the window are forgotten
+        let mut tracker = IpTracker::new(config());
+        assert!(!tracker.auth_failed(ip(1), now));
+        assert!(!tracker.auth_failed(ip(1), now));
+        assert!(!tracker.auth_failed(ip(1), now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_prune_idle_addresses() {
+        let mut tracker = IpTracker::new(config());
+        let now = Instant::now();
+
+        assert!(tracker.admit(ip(1), now).is_ok());
+        assert!(tracker.admit(ip(2), now).is_ok());
+        tracker.release(ip(2));
+
+        let later = now + Duration::from_secs(61);
+        assert!(tracker.admit(ip(3), later).is_ok());
+        assert_eq!(tracker.tracked(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_limiter_server_full() {
+        let limiter = ConnectionLimiter::new(1, config());
+
+        let permit = limiter.try_acquire(ip(1)).unwrap();
+        assert_eq!(
+            limiter.try_acquire(ip(2)).unwrap_err(),
+            ConnectionRefusal::ServerFull
+        );
+        drop(permit);
+        assert!(limiter.try_acquire(ip(2)).is_ok());
+    }
+}
@@ -0,0 +1,195 @@
+//! Maintenance mode
+//!
+//! While a maintenance window is active the system is read-only: SMTP
+//! refuses sessions with 421, IMAP announces an ALERT and refuses changes,
+//! and POP3 refuses deletions. Servers check a shared `MaintenanceMode`
+//! handle whose windows are reloaded from the database by `run`, so a
+//! scheduled window starts on time between reloads.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::maintenance::MaintenanceWindow;
+use mairust_storage::repository::MaintenanceRepository;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::time::{interval, Duration};
+use tracing::{error, info};
+
+/// Retry hint for windows without an end
+pub const DEFAULT_RETRY_AFTER_SECS: i64 = 300;
+
+/// How often windows are reloaded
+const REFRESH_INTERVAL_SECS: u64 = 10;
+
+/// The maintenance in effect at some moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceStatus {
+    /// Message of the most recently started active window
+    pub message: String,
+    /// When the last overlapping window ends, `None` if one has no end
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceStatus {
+    /// The status at `now`, `None` when no window is active
+    pub fn from_windows(windows: &[MaintenanceWindow], now: DateTime<Utc>) -> Option<Self> {
+        let active: Vec<&MaintenanceWindow> = windows.iter().filter(|w| w.is_active(now)).collect();
+        let latest = active.iter().max_by_key(|w| w.starts_at)?;
+
+        let until = if active.iter().any(|w| w.ends_at.is_none()) {
+            None
+        } else {
+            active.iter().filter_map(|w| w.ends_at).max()
+        };
+
+        Some(Self {
+            message: latest.message.clone(),
+            until,
+        })
+    }
+
+    /// Seconds a client should wait before retrying
+    pub fn retry_after_secs(&self, now: DateTime<Utc>) -> i64 {
+        match self.until {
+            Some(until) => (until - now).num_seconds().max(1),
+            None => DEFAULT_RETRY_AFTER_SECS,
+        }
+    }
+}
+
+/// Shared view of the maintenance windows
+///
+/// The default handle has no windows and is never in maintenance.
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceMode {
+    windows: Arc<RwLock<Vec<MaintenanceWindow>>>,
+}
+
+impl MaintenanceMode {
+    /// Create a handle with no windows
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maintenance in effect now
+    pub async fn status(&self) -> Option<MaintenanceStatus> {
+        MaintenanceStatus::from_windows(&self.windows.read().await, Utc::now())
+    }
+
+    /// Whether the system is read-only now
+    pub async fn is_active(&self) -> bool {
+        self.status().await.is_some()
+    }
+
+    /// Reload the open windows
+    pub async fn refresh(&self, db_pool: &DatabasePool) -> Result<()> {
+        let windows = MaintenanceRepository::new(db_pool.clone())
+            .list_open()
+            .await?;
+        *self.windows.write().await = windows;
+        Ok(())
+    }
+
+    /// Reload the windows periodically
+    pub async fn run(self, db_pool: DatabasePool) {
+        let mut ticker = interval(Duration::from_secs(REFRESH_INTERVAL_SECS));
+        let mut active = false;
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = self.refresh(&db_pool).await {
+                error!("Failed to load maintenance windows: {}", e);
+                continue;
+            }
+
+            match (self.status().await, active) {
+                (Some(status), false) => {
+                    info!("Maintenance mode started: {}", status.message);
+                    active = true;
+                }
+                (None, true) => {
+                    info!("Maintenance mode ended");
+                    active = false;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    fn window(
+        message: &str,
+        starts_at: DateTime<Utc>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> MaintenanceWindow {
+        MaintenanceWindow {
+            id: Uuid::now_v7(),
+            starts_at,
+            ends_at,
+            message: message.to_string(),
+            created_by: None,
+            created_at: starts_at,
+        }
+    }
+
+    #[test]
+    fn test_status_from_windows() {
+        let now = Utc::now();
+        let upcoming = window("Later", now + Duration::hours(1), None);
+        assert_eq!(
+            MaintenanceStatus::from_windows(std::slice::from_ref(&upcoming), now),
+            None
+        );
+
+        let first = window(
+            "Storage migration",
+            now - Duration::minutes(10),
+            Some(now + Duration::minutes(20)),
+        );
+        let second = window(
+            "Index rebuild",
+            now - Duration::minutes(5),
+            Some(now + Duration::minutes(30)),
+        );
+        let status =
+            MaintenanceStatus::from_windows(&[first.clone(), second, upcoming], now).unwrap();
+        assert_eq!(status.message, "Index rebuild");
+        assert_eq!(status.until, Some(now + Duration::minutes(30)));
+        assert_eq!(status.retry_after_secs(now), 30 * 60);
+
+        let open = window("Until further notice", now - Duration::minutes(1), None);
+        let status = MaintenanceStatus::from_windows(&[first, open], now).unwrap();
+        assert_eq!(status.until, None);
+        assert_eq!(status.retry_after_secs(now), DEFAULT_RETRY_AFTER_SECS);
+    }
+
+    #[test]
+    fn test_retry_after_is_positive() {
+        let now = Utc::now();
+        let status = MaintenanceStatus {
+            message: "Almost done".to_string(),
+            until: Some(now),
+        };
+        assert_eq!(status.retry_after_secs(now), 1);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode() {
+        let mode = MaintenanceMode::new();
+        assert!(!mode.is_active().await);
+
+        // Clones share the windows
+        let now = Utc::now();
+        *mode.clone().windows.write().await = vec![window("Storage migration", now, None)];
+        assert!(mode.is_active().await);
+        assert_eq!(mode.status().await.unwrap().message, "Storage migration");
+    }
+}
@@ -0,0 +1,392 @@
+//! ManageSieve command parsing
+//!
+//! Commands are an atom followed by atoms, quoted strings and literals
+//! (`{n+}` or `{n}` followed by n octets). Literals larger than allowed
+//! are read past rather than kept, so that the client and the server stay
+//! in step.
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Longest command line, not counting literals
+const MAX_LINE: u64 = 8 * 1024;
+
+/// An argument of a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Argument {
+    /// Command name, number or other bare word
+    Atom(String),
+    /// Quoted string or literal
+    String(Vec<u8>),
+    /// Literal of this size that was too large to keep
+    TooLarge(u64),
+}
+
+/// What a line ended with
+#[derive(Debug, PartialEq, Eq)]
+enum LineEnd {
+    Complete,
+    Literal(u64),
+}
+
+/// A line read from the client
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    /// The connection was closed
+    Closed,
+    Arguments(Vec<Argument>),
+    /// The line could not be parsed
+    Invalid(String),
+}
+
+/// Read a command line with its literals
+pub async fn read_line<R>(reader: &mut R, max_literal: u64) -> Result<Line>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut arguments = Vec::new();
+    let mut error = None;
+    loop {
+        let mut line = Vec::new();
+        let read = (&mut *reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if read == 0 {
+            return Ok(Line::Closed);
+        }
+        if !line.ends_with(b"\n") {
+            return Err(anyhow!("ManageSieve command line too long"));
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let end = if error.is_some() {
+            // Skip the literals of a line that is already refused
+            literal_at_end(line)
+        } else {
+            match parse_line(line, &mut arguments) {
+                Ok(end) => end,
+                Err(e) => {
+                    let end = literal_at_end(line);
+                    error = Some(e);
+                    end
+                }
+            }
+        };
+        let LineEnd::Literal(size) = end else {
+            break;
+        };
+        if size > max_literal || error.is_some() {
+            let skipped =
+                tokio::io::copy(&mut (&mut *reader).take(size), &mut tokio::io::sink()).await?;
+            if skipped < size {
+                return Ok(Line::Closed);
+            }
+            arguments.push(Argument::TooLarge(size));
+        } else {
+            let mut data = vec![0; size as usize];
+            reader.read_exact(&mut data).await?;
+            arguments.push(Argument::String(data));
+        }
+    }
+
+    Ok(match error {
+        Some(e) => Line::Invalid(e),
+        None => Line::Arguments(arguments),
+    })
+}
+
+/// Parse the arguments of a line without its line ending
+fn parse_line(line: &[u8], arguments: &mut Vec<Argument>) -> Result<LineEnd, String> {
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            b' ' | b'\t' => i += 1,
+            b'"' => {
+                i += 1;
+                let mut value = Vec::new();
+                loop {
+                    match line.get(i) {
+                        None => return Err("Unterminated string".to_string()),
+                        Some(b'"') => break,
+                        Some(b'\\') => match line.get(i + 1) {
+                            Some(&c @ (b'"' | b'\\')) => {
+                                value.push(c);
+                                i += 1;
+                            }
+                            _ => return Err("Invalid escape in string".to_string()),
+                        },
+                        Some(&c) => value.push(c),
+                    }
+                    i += 1;
+                }
+                arguments.push(Argument::String(value));
+                i += 1;
+            }
+            b'{' => {
+                return match parse_literal(&line[i..]) {
+                    Some(size) => Ok(LineEnd::Literal(size)),
+                    None => Err("Invalid literal".to_string()),
+                };
+            }
+            _ => {
+                let start = i;
+                while i < line.len() && !matches!(line[i], b' ' | b'\t' | b'"' | b'{') {
+                    i += 1;
+                }
+                let atom = std::str::from_utf8(&line[start..i])
+                    .map_err(|_| "Invalid characters in atom".to_string())?;
+                arguments.push(Argument::Atom(atom.to_string()));
+            }
+        }
+    }
+    Ok(LineEnd::Complete)
+}
+
+/// Size of a `{n+}` or `{n}` literal that makes up the rest of a line
+fn parse_literal(rest: &[u8]) -> Option<u64> {
+    let inner = rest.strip_prefix(b"{")?.strip_suffix(b"}")?;
+    let digits = inner.strip_suffix(b"+").unwrap_or(inner);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// The literal a line ends with, if any
+fn literal_at_end(line: &[u8]) -> LineEnd {
+    line.iter()
+        .rposition(|&c| c == b'{')
+        .and_then(|at| parse_literal(&line[at..]))
+        .map_or(LineEnd::Complete, LineEnd::Literal)
+}
+
+/// Script content sent by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptContent {
+    Text(String),
+    /// A script larger than the server accepts
+    TooLarge,
+}
+
+/// ManageSieve command (RFC 5804)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManageSieveCommand {
+    /// AUTHENTICATE mechanism [initial-response]
+    Authenticate {
+        mechanism: String,
+        initial_response: Option<String>,
+    },
+    /// STARTTLS - Upgrade the connection to TLS
+    StartTls,
+    /// LOGOUT - End session
+    Logout,
+    /// CAPABILITY - List server capabilities
+    Capability,
+    /// HAVESPACE name size - Whether a script of this size can be stored
+    HaveSpace { name: String, size: u64 },
+    /// PUTSCRIPT name content - Store a script
+    PutScript {
+        name: String,
+        content: ScriptContent,
+    },
+    /// LISTSCRIPTS - List the user's scripts
+    ListScripts,
+    /// SETACTIVE name - Activate a script, or none with ""
+    SetActive { name: String },
+    /// GETSCRIPT name - Fetch a script
+    GetScript { name: String },
+    /// DELETESCRIPT name - Delete a script
+    DeleteScript { name: String },
+    /// RENAMESCRIPT old new - Rename a script
+    RenameScript { name: String, new_name: String },
+    /// CHECKSCRIPT content - Check a script without storing it
+    CheckScript { content: ScriptContent },
+    /// NOOP [tag] - No operation
+    Noop { tag: Option<String> },
+    /// UNAUTHENTICATE - Log out but keep the connection
+    Unauthenticate,
+}
+
+impl ManageSieveCommand {
+    /// Parse the arguments of a command line
+    pub fn parse(arguments: Vec<Argument>) -> Result<Self, String> {
+        let mut arguments = arguments.into_iter();
+        let name = match arguments.next() {
+            Some(Argument::Atom(name)) => name.to_ascii_uppercase(),
+            Some(_) => return Err("Expected a command".to_string()),
+            None => return Err("Empty command".to_string()),
+        };
+        let arguments: Vec<Argument> = arguments.collect();
+
+        let command = match (name.as_str(), arguments.as_slice()) {
+            ("AUTHENTICATE", [mechanism]) => ManageSieveCommand::Authenticate {
+                mechanism: string(mechanism)?,
+                initial_response: None,
+            },
+            ("AUTHENTICATE", [mechanism, initial_response]) => ManageSieveCommand::Authenticate {
+                mechanism: string(mechanism)?,
+                initial_response: Some(string(initial_response)?),
+            },
+            ("STARTTLS", []) => ManageSieveCommand::StartTls,
+            ("LOGOUT", []) => ManageSieveCommand::Logout,
+            ("CAPABILITY", []) => ManageSieveCommand::Capability,
+            ("HAVESPACE", [name, Argument::Atom(size)]) => ManageSieveCommand::HaveSpace {
+                name: string(name)?,
+                size: size
+                    .parse()
+                    .map_err(|_| "Invalid script size".to_string())?,
+            },
+            ("PUTSCRIPT", [name, content]) => ManageSieveCommand::PutScript {
+                name: string(name)?,
+                content: script(content)?,
+            },
+            ("LISTSCRIPTS", []) => ManageSieveCommand::ListScripts,
+            ("SETACTIVE", [name]) => ManageSieveCommand::SetActive {
+                name: string(name)?,
+            },
+            ("GETSCRIPT", [name]) => ManageSieveCommand::GetScript {
+                name: string(name)?,
+            },
+            ("DELETESCRIPT", [name]) => ManageSieveCommand::DeleteScript {
+                name: string(name)?,
+            },
+            ("RENAMESCRIPT", [name, new_name]) => ManageSieveCommand::RenameScript {
+                name: string(name)?,
+                new_name: string(new_name)?,
+            },
+            ("CHECKSCRIPT", [content]) => ManageSieveCommand::CheckScript {
+                content: script(content)?,
+            },
+            ("NOOP", []) => ManageSieveCommand::Noop { tag: None },
+            ("NOOP", [tag]) => ManageSieveCommand::Noop {
+                tag: Some(string(tag)?),
+            },
+            ("UNAUTHENTICATE", []) => ManageSieveCommand::Unauthenticate,
+            (
+                "AUTHENTICATE" | "STARTTLS" | "LOGOUT" | "CAPABILITY" | "HAVESPACE" | "PUTSCRIPT"
+                | "LISTSCRIPTS" | "SETACTIVE" | "GETSCRIPT" | "DELETESCRIPT" | "RENAMESCRIPT"
+                | "CHECKSCRIPT" | "NOOP" | "UNAUTHENTICATE",
+                _,
+            ) => return Err(format!("Invalid arguments for {}", name)),
+            _ => return Err(format!("Unknown command {}", name)),
+        };
+        Ok(command)
+    }
+}
+
+/// A string argument as UTF-8
+fn string(argument: &Argument) -> Result<String, String> {
+    match argument {
+        Argument::String(value) => {
+            String::from_utf8(value.clone()).map_err(|_| "Strings must be UTF-8".to_string())
+        }
+        Argument::Atom(_) => Err("Expected a quoted string or literal".to_string()),
+        Argument::TooLarge(_) => Err("String too long".to_string()),
+    }
+}
+
+fn script(argument: &Argument) -> Result<ScriptContent, String> {
+    match argument {
+        Argument::TooLarge(_) => Ok(ScriptContent::TooLarge),
+        other => string(other).map(ScriptContent::Text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(input: &[u8], max_literal: u64) -> Line {
+        let mut reader = input;
+        read_line(&mut reader, max_literal).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_line() {
+        let line = read(b"PUTSCRIPT \"my \\\"script\\\"\" {6+}\r\nkeep;\n\r\n", 100).await;
+        assert_eq!(
+            line,
+            Line::Arguments(vec![
+                Argument::Atom("PUTSCRIPT".to_string()),
+                Argument::String(b"my \"script\"".to_vec()),
+                Argument::String(b"keep;\n".to_vec()),
+            ])
+        );
+
+        let line = read(b"CHECKSCRIPT {10}\r\n0123456789\r\nNOOP\r\n", 4).await;
+        assert_eq!(
+            line,
+            Line::Arguments(vec![
+                Argument::Atom("CHECKSCRIPT".to_string()),
+                Argument::TooLarge(10),
+            ])
+        );
+
+        assert!(matches!(
+            read(b"GETSCRIPT \"open\r\n", 100).await,
+            Line::Invalid(_)
+        ));
+        assert_eq!(read(b"", 100).await, Line::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_line_skips_literal() {
+        let mut reader: &[u8] = b"PUTSCRIPT \"a\\x\" {4+}\r\nstop\r\nLOGOUT\r\n";
+        assert!(matches!(
+            read_line(&mut reader, 100).await.unwrap(),
+            Line::Invalid(_)
+        ));
+        assert_eq!(
+            read_line(&mut reader, 100).await.unwrap(),
+            Line::Arguments(vec![Argument::Atom("LOGOUT".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let parse = |line: &str| {
+            let mut arguments = Vec::new();
+            assert_eq!(
+                parse_line(line.as_bytes(), &mut arguments),
+                Ok(LineEnd::Complete)
+            );
+            ManageSieveCommand::parse(arguments)
+        };
+
+        assert_eq!(
+            parse("authenticate \"PLAIN\" \"AGEAYg==\""),
+            Ok(ManageSieveCommand::Authenticate {
+                mechanism: "PLAIN".to_string(),
+                initial_response: Some("AGEAYg==".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("HAVESPACE \"vacation\" 2048"),
+            Ok(ManageSieveCommand::HaveSpace {
+                name: "vacation".to_string(),
+                size: 2048,
+            })
+        );
+        assert_eq!(
+            parse("SETACTIVE \"\""),
+            Ok(ManageSieveCommand::SetActive {
+                name: String::new()
+            })
+        );
+        assert_eq!(parse("Logout"), Ok(ManageSieveCommand::Logout));
+        assert!(parse("GETSCRIPT vacation").is_err());
+        assert!(parse("LISTSCRIPTS \"x\"").is_err());
+        assert!(parse("FROB").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(parse_literal(b"{12+}"), Some(12));
+        assert_eq!(parse_literal(b"{0}"), Some(0));
+        assert_eq!(parse_literal(b"{+}"), None);
+        assert_eq!(parse_literal(b"{12+} x"), None);
+    }
+}
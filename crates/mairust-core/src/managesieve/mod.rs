@@ -0,0 +1,10 @@
+//! ManageSieve Server Module
+//!
+//! Implements the ManageSieve protocol (RFC 5804) for managing Sieve
+//! scripts.
+
+mod command;
+mod response;
+mod server;
+
+pub use server::{ManageSieveConfig, ManageSieveServer};
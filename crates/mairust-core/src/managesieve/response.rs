@@ -0,0 +1,136 @@
+//! ManageSieve Response generation
+//!
+//! Responses are `OK`, `NO` or `BYE`, each with an optional response code
+//! in parentheses and a human-readable string.
+
+/// SASL mechanisms accepted by AUTHENTICATE
+pub const SASL_MECHANISMS: [&str; 2] = ["PLAIN", "LOGIN"];
+
+/// ManageSieve Response builder
+pub struct ManageSieveResponse;
+
+impl ManageSieveResponse {
+    /// Positive response
+    pub fn ok(message: &str) -> String {
+        format!("OK {}\r\n", Self::string(message))
+    }
+
+    /// Positive response with a response code
+    pub fn ok_code(code: &str, message: &str) -> String {
+        format!("OK ({}) {}\r\n", code, Self::string(message))
+    }
+
+    /// Negative response
+    pub fn no(message: &str) -> String {
+        format!("NO {}\r\n", Self::string(message))
+    }
+
+    /// Negative response with a response code, e.g. `NONEXISTENT`
+    pub fn no_code(code: &str, message: &str) -> String {
+        format!("NO ({}) {}\r\n", code, Self::string(message))
+    }
+
+    /// The server is closing the connection
+    pub fn bye(message: &str) -> String {
+        format!("BYE {}\r\n", Self::string(message))
+    }
+
+    /// A string, quoted when it is short and on one line and sent as a
+    /// literal otherwise
+    pub fn string(value: &str) -> String {
+        if value.len() <= 1024 && !value.contains(['\r', '\n', '\0']) {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            format!("{{{}}}\r\n{}", value.len(), value)
+        }
+    }
+
+    /// Capability listing, sent as the greeting and for CAPABILITY
+    ///
+    /// `sasl` is empty while authentication is refused, e.g. until STARTTLS.
+    pub fn capabilities(
+        implementation: &str,
+        sasl: &[&str],
+        extensions: &[&str],
+        starttls: bool,
+    ) -> String {
+        let mut response = format!(
+            "\"IMPLEMENTATION\" {}\r\n\"SASL\" {}\r\n\"SIEVE\" {}\r\n",
+            Self::string(implementation),
+            Self::string(&sasl.join(" ")),
+            Self::string(&extensions.join(" ")),
+        );
+        if starttls {
+            response.push_str("\"STARTTLS\"\r\n");
+        }
+        response.push_str("\"UNAUTHENTICATE\"\r\n\"VERSION\" \"1.0\"\r\nOK\r\n");
+        response
+    }
+
+    /// LISTSCRIPTS response
+    pub fn script_list<'a>(scripts: impl IntoIterator<Item = (&'a str, bool)>) -> String {
+        let mut response = String::new();
+        for (name, active) in scripts {
+            response.push_str(&Self::string(name));
+            if active {
+                response.push_str(" ACTIVE");
+            }
+            response.push_str("\r\n");
+        }
+        response.push_str("OK\r\n");
+        response
+    }
+
+    /// GETSCRIPT response
+    pub fn script(content: &str) -> String {
+        format!("{{{}}}\r\n{}\r\nOK\r\n", content.len(), content)
+    }
+
+    /// SASL challenge
+    pub fn challenge(data: &str) -> String {
+        format!("{}\r\n", Self::string(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responses() {
+        assert_eq!(ManageSieveResponse::ok("Done"), "OK \"Done\"\r\n");
+        assert_eq!(
+            ManageSieveResponse::no_code("NONEXISTENT", "No \"x\""),
+            "NO (NONEXISTENT) \"No \\\"x\\\"\"\r\n"
+        );
+        assert_eq!(
+            ManageSieveResponse::no("line 2\r\nbad"),
+            "NO {11}\r\nline 2\r\nbad\r\n"
+        );
+        assert_eq!(
+            ManageSieveResponse::script("keep;\r\n"),
+            "{7}\r\nkeep;\r\n\r\nOK\r\n"
+        );
+        assert_eq!(
+            ManageSieveResponse::script_list([("a", false), ("b", true)]),
+            "\"a\"\r\n\"b\" ACTIVE\r\nOK\r\n"
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let caps = ManageSieveResponse::capabilities(
+            "MaiRust",
+            &SASL_MECHANISMS,
+            &["fileinto", "vacation"],
+            true,
+        );
+        assert!(caps.starts_with("\"IMPLEMENTATION\" \"MaiRust\"\r\n\"SASL\" \"PLAIN LOGIN\"\r\n"));
+        assert!(caps.contains("\"SIEVE\" \"fileinto vacation\"\r\n\"STARTTLS\"\r\n"));
+        assert!(caps.ends_with("\"VERSION\" \"1.0\"\r\nOK\r\n"));
+
+        let caps = ManageSieveResponse::capabilities("MaiRust", &[], &[], false);
+        assert!(caps.contains("\"SASL\" \"\"\r\n"));
+        assert!(!caps.contains("STARTTLS"));
+    }
+}
@@ -0,0 +1,848 @@
+//! ManageSieve Server
+//!
+//! Lets mail clients and the web UI upload, check, list and activate a
+//! user's Sieve scripts (RFC 5804). Users log in with their IMAP
+//! credentials, and IMAP being switched off for them or their tenant
+//! also refuses ManageSieve.
+
+use super::command::{read_line, Argument, Line, ManageSieveCommand, ScriptContent};
+use super::response::{ManageSieveResponse, SASL_MECHANISMS};
+use crate::imap::sasl::{self, SaslCredentials, SaslMechanism};
+use crate::limits::ConnectionLimiter;
+use crate::proxy_protocol::TrustedProxies;
+use crate::security::SecurityNotifier;
+use crate::services::ServicePolicy;
+use crate::sessions::{ActiveSession, SessionProtocol, SessionRegistry};
+use crate::sieve;
+use crate::smtp::SmtpAuthenticator;
+
+use anyhow::{anyhow, Result};
+use mairust_common::config::{ConnectionLimitConfig, TlsConfig};
+use mairust_common::types::MailService;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::SieveScriptRepository;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Timeout for the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 30;
+
+/// Largest literal read for commands other than script uploads
+const MIN_LITERAL: usize = 8 * 1024;
+
+/// ManageSieve server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManageSieveConfig {
+    /// Listen address and port
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// Enable STARTTLS
+    #[serde(default)]
+    pub starttls: bool,
+    /// Refuse AUTHENTICATE on connections that are not encrypted
+    #[serde(default = "default_require_tls_for_auth")]
+    pub require_tls_for_auth: bool,
+    /// Session timeout in minutes
+    #[serde(default = "default_timeout")]
+    pub timeout_minutes: i64,
+    /// Maximum connections
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// Scripts a user may store
+    #[serde(default = "default_max_scripts")]
+    pub max_scripts: usize,
+    /// Largest script in bytes
+    #[serde(default = "default_max_script_size")]
+    pub max_script_size: usize,
+    /// Implementation name announced to clients
+    #[serde(default = "default_server_name")]
+    pub server_name: String,
+    /// Connection limits (set at startup; created from `max_connections`
+    /// with the default per-IP limits when unset)
+    #[serde(skip)]
+    pub limiter: Option<ConnectionLimiter>,
+    /// Records blocked logins and logins from new networks (set at startup)
+    #[serde(skip)]
+    pub security: Option<SecurityNotifier>,
+    /// Load balancers whose PROXY header gives the client address (set at
+    /// startup for `proxy_protocol` listeners)
+    #[serde(skip)]
+    pub proxy: Option<TrustedProxies>,
+    /// IMAP access by plan, tenant and user (set at startup)
+    #[serde(skip)]
+    pub services: ServicePolicy,
+    /// Live sessions listed to administrators (set at startup)
+    #[serde(skip)]
+    pub sessions: SessionRegistry,
+}
+
+fn default_bind() -> String {
+    "0.0.0.0:4190".to_string()
+}
+
+fn default_require_tls_for_auth() -> bool {
+    true
+}
+
+fn default_timeout() -> i64 {
+    10
+}
+
+fn default_max_connections() -> usize {
+    100
+}
+
+fn default_max_scripts() -> usize {
+    16
+}
+
+fn default_max_script_size() -> usize {
+    64 * 1024
+}
+
+fn default_server_name() -> String {
+    "MaiRust".to_string()
+}
+
+impl Default for ManageSieveConfig {
+    fn default() -> Self {
+        Self {
+            bind: default_bind(),
+            starttls: false,
+            require_tls_for_auth: default_require_tls_for_auth(),
+            timeout_minutes: default_timeout(),
+            max_connections: default_max_connections(),
+            max_scripts: default_max_scripts(),
+            max_script_size: default_max_script_size(),
+            server_name: default_server_name(),
+            limiter: None,
+            security: None,
+            proxy: None,
+            services: ServicePolicy::default(),
+            sessions: SessionRegistry::default(),
+        }
+    }
+}
+
+/// The user a session is logged in as
+#[derive(Debug, Clone)]
+struct SieveUser {
+    user_id: Uuid,
+    tenant_id: Uuid,
+    email: String,
+}
+
+/// State of a connection
+#[derive(Debug, Default)]
+struct Session {
+    tls: bool,
+    /// User named in the last login attempt
+    username: Option<String>,
+    user: Option<SieveUser>,
+}
+
+/// How a command loop ended
+enum Outcome {
+    Closed,
+    StartTls,
+}
+
+/// ManageSieve Server
+pub struct ManageSieveServer {
+    config: ManageSieveConfig,
+    db_pool: DatabasePool,
+    tls_acceptor: Option<Arc<TlsAcceptor>>,
+}
+
+impl ManageSieveServer {
+    /// Create a new ManageSieve server
+    pub fn new(config: ManageSieveConfig, db_pool: DatabasePool) -> Self {
+        Self {
+            config: Self::with_limiter(config),
+            db_pool,
+            tls_acceptor: None,
+        }
+    }
+
+    pub fn with_tls(
+        config: ManageSieveConfig,
+        db_pool: DatabasePool,
+        tls: Option<&TlsConfig>,
+    ) -> Self {
+        let tls_acceptor =
+            tls.and_then(
+                |tls_config| match crate::smtp::create_tls_acceptor(tls_config) {
+                    Ok(acceptor) => Some(Arc::new(acceptor)),
+                    Err(e) => {
+                        warn!("Failed to initialize ManageSieve TLS acceptor: {}", e);
+                        None
+                    }
+                },
+            );
+
+        Self {
+            config: Self::with_limiter(config),
+            db_pool,
+            tls_acceptor,
+        }
+    }
+
+    /// Give the configuration a limiter of its own unless one is shared
+    fn with_limiter(mut config: ManageSieveConfig) -> ManageSieveConfig {
+        if config.limiter.is_none() {
+            config.limiter = Some(ConnectionLimiter::new(
+                config.max_connections,
+                ConnectionLimitConfig::default(),
+            ));
+        }
+        config
+    }
+
+    /// Start a single listener: plaintext (with STARTTLS when enabled) or
+    /// implicit TLS
+    pub async fn run_listener(&self, bind: &str, implicit_tls: bool) -> Result<()> {
+        if implicit_tls && self.tls_acceptor.is_none() {
+            return Err(anyhow!(
+                "Implicit TLS ManageSieve listener on {} requires TLS to be configured",
+                bind
+            ));
+        }
+
+        let listener = TcpListener::bind(bind).await?;
+        if implicit_tls {
+            info!("ManageSieve server listening on {} (implicit TLS)", bind);
+        } else if self.config.starttls && self.tls_acceptor.is_some() {
+            info!(
+                "ManageSieve server listening on {} (STARTTLS enabled)",
+                bind
+            );
+        } else {
+            info!(
+                "ManageSieve server listening on {} (STARTTLS disabled)",
+                bind
+            );
+        }
+
+        self.accept_loop(listener, implicit_tls).await
+    }
+
+    /// Accept connections on a listener
+    async fn accept_loop(&self, listener: TcpListener, implicit_tls: bool) -> Result<()> {
+        loop {
+            let (mut stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("ManageSieve accept error: {}", e);
+                    continue;
+                }
+            };
+            let connection = Connection {
+                db_pool: self.db_pool.clone(),
+                config: self.config.clone(),
+                tls_acceptor: self.tls_acceptor.clone(),
+                addr,
+            };
+
+            tokio::spawn(async move {
+                let mut connection = connection;
+                // Behind a load balancer the PROXY header names the client
+                if let Some(proxy) = &connection.config.proxy {
+                    match proxy.client_addr(&mut stream, addr).await {
+                        Ok(client_addr) => connection.addr = client_addr,
+                        Err(e) => {
+                            warn!("Refusing ManageSieve connection: {}", e);
+                            return;
+                        }
+                    }
+                }
+                let addr = connection.addr;
+
+                let limiter = connection.config.limiter.as_ref();
+                let _permit = match limiter.map(|l| l.try_acquire(addr.ip())).transpose() {
+                    Ok(permit) => permit,
+                    Err(refusal) => {
+                        warn!(
+                            "Refusing ManageSieve connection from {}: {}",
+                            addr,
+                            refusal.reason()
+                        );
+                        if !implicit_tls {
+                            // A new connection's send buffer has room for the line
+                            let bye = ManageSieveResponse::bye(refusal.reason());
+                            let _ = stream.try_write(bye.as_bytes());
+                        }
+                        return;
+                    }
+                };
+
+                let registration = connection
+                    .config
+                    .sessions
+                    .register(SessionProtocol::ManageSieve, addr);
+                let live = registration.session().clone();
+                let result = live
+                    .run(connection.handle(stream, implicit_tls, live.clone()))
+                    .await;
+                match result {
+                    Some(Err(e)) => error!("ManageSieve connection error from {}: {}", addr, e),
+                    Some(Ok(())) => {}
+                    None => info!("Terminated ManageSieve session from {}", addr),
+                }
+            });
+        }
+    }
+}
+
+/// A client connection
+struct Connection {
+    db_pool: DatabasePool,
+    config: ManageSieveConfig,
+    tls_acceptor: Option<Arc<TlsAcceptor>>,
+    addr: SocketAddr,
+}
+
+impl Connection {
+    /// Serve a connection, upgrading it to TLS on connect or on STARTTLS
+    async fn handle(
+        self,
+        stream: TcpStream,
+        implicit_tls: bool,
+        live: Arc<ActiveSession>,
+    ) -> Result<()> {
+        info!("New ManageSieve connection from {}", self.addr);
+        let mut session = Session::default();
+
+        if implicit_tls {
+            let tls_stream = self.accept_tls(stream).await?;
+            session.tls = true;
+            let mut stream = BufReader::new(live.meter(tls_stream));
+            self.send(&mut stream, &self.capabilities(&session)).await?;
+            self.serve(&mut stream, &mut session, &live).await?;
+            return Ok(());
+        }
+
+        let mut stream = BufReader::new(live.meter(stream));
+        self.send(&mut stream, &self.capabilities(&session)).await?;
+        if let Outcome::StartTls = self.serve(&mut stream, &mut session, &live).await? {
+            // Anything the client sent after STARTTLS is dropped with the buffer
+            let tls_stream = self.accept_tls(stream.into_inner().into_inner()).await?;
+            info!(
+                "ManageSieve STARTTLS negotiation completed for {}",
+                self.addr
+            );
+            session.tls = true;
+            let mut stream = BufReader::new(live.meter(tls_stream));
+            // Capabilities are sent again once TLS is active (RFC 5804 section 2.2)
+            self.send(&mut stream, &self.capabilities(&session)).await?;
+            self.serve(&mut stream, &mut session, &live).await?;
+        }
+        info!("ManageSieve connection closed for {}", self.addr);
+        Ok(())
+    }
+
+    async fn accept_tls(
+        &self,
+        stream: TcpStream,
+    ) -> Result<tokio_rustls::server::TlsStream<TcpStream>> {
+        let acceptor = self
+            .tls_acceptor
+            .clone()
+            .ok_or_else(|| anyhow!("ManageSieve TLS requested without configured acceptor"))?;
+        tokio::time::timeout(
+            Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS),
+            acceptor.accept(stream),
+        )
+        .await
+        .map_err(|_| anyhow!("TLS handshake timed out for {}", self.addr))?
+        .map_err(Into::into)
+    }
+
+    async fn send<S: AsyncWrite + Unpin>(&self, stream: &mut S, response: &str) -> Result<()> {
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    fn starttls_available(&self, session: &Session) -> bool {
+        !session.tls && self.config.starttls && self.tls_acceptor.is_some()
+    }
+
+    fn capabilities(&self, session: &Session) -> String {
+        let sasl: &[&str] = if self.config.require_tls_for_auth && !session.tls {
+            &[]
+        } else {
+            &SASL_MECHANISMS
+        };
+        ManageSieveResponse::capabilities(
+            &self.config.server_name,
+            sasl,
+            sieve::EXTENSIONS,
+            self.starttls_available(session),
+        )
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs((self.config.timeout_minutes * 60) as u64)
+    }
+
+    /// Read and answer commands until the client logs out, the connection
+    /// closes or STARTTLS is accepted
+    async fn serve<S>(
+        &self,
+        stream: &mut S,
+        session: &mut Session,
+        live: &ActiveSession,
+    ) -> Result<Outcome>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin,
+    {
+        let max_literal = self.config.max_script_size.max(MIN_LITERAL) as u64;
+        loop {
+            let line =
+                match tokio::time::timeout(self.timeout(), read_line(stream, max_literal)).await {
+                    Ok(line) => line?,
+                    Err(_) => {
+                        warn!("ManageSieve connection timeout for {}", self.addr);
+                        self.send(stream, &ManageSieveResponse::bye("Session timeout"))
+                            .await?;
+                        return Ok(Outcome::Closed);
+                    }
+                };
+            let arguments = match line {
+                Line::Closed => return Ok(Outcome::Closed),
+                Line::Invalid(e) => {
+                    self.send(stream, &ManageSieveResponse::no(&e)).await?;
+                    continue;
+                }
+                Line::Arguments(arguments) if arguments.is_empty() => continue,
+                Line::Arguments(arguments) => arguments,
+            };
+            let command = match ManageSieveCommand::parse(arguments) {
+                Ok(command) => command,
+                Err(e) => {
+                    self.send(stream, &ManageSieveResponse::no(&e)).await?;
+                    continue;
+                }
+            };
+
+            let response = match command {
+                ManageSieveCommand::Capability => self.capabilities(session),
+                ManageSieveCommand::Logout => {
+                    self.send(stream, &ManageSieveResponse::ok("Logout completed"))
+                        .await?;
+                    return Ok(Outcome::Closed);
+                }
+                ManageSieveCommand::Noop { tag: Some(tag) } => ManageSieveResponse::ok_code(
+                    &format!("TAG {}", ManageSieveResponse::string(&tag)),
+                    "Done",
+                ),
+                ManageSieveCommand::Noop { tag: None } => ManageSieveResponse::ok("Done"),
+                ManageSieveCommand::StartTls => {
+                    if session.tls {
+                        ManageSieveResponse::no("TLS already active")
+                    } else if !self.starttls_available(session) {
+                        ManageSieveResponse::no("STARTTLS not available")
+                    } else if session.user.is_some() {
+                        ManageSieveResponse::no("STARTTLS only allowed before authentication")
+                    } else {
+                        self.send(stream, &ManageSieveResponse::ok("Begin TLS negotiation"))
+                            .await?;
+                        return Ok(Outcome::StartTls);
+                    }
+                }
+                ManageSieveCommand::Authenticate { .. } if session.user.is_some() => {
+                    ManageSieveResponse::no("Already authenticated")
+                }
+                ManageSieveCommand::Authenticate { .. }
+                    if self.config.require_tls_for_auth && !session.tls =>
+                {
+                    ManageSieveResponse::no_code(
+                        "ENCRYPT-NEEDED",
+                        if self.starttls_available(session) {
+                            "Plaintext authentication disabled, use STARTTLS first"
+                        } else {
+                            "Plaintext authentication disabled"
+                        },
+                    )
+                }
+                ManageSieveCommand::Authenticate {
+                    mechanism,
+                    initial_response,
+                } => {
+                    let response = self
+                        .authenticate(stream, session, &mechanism, initial_response)
+                        .await?;
+                    self.report_session(session, live);
+                    if self.check_login(&response, session) {
+                        self.send(stream, &response).await?;
+                        let bye =
+                            ManageSieveResponse::bye("Too many failed logins, try again later");
+                        self.send(stream, &bye).await?;
+                        return Ok(Outcome::Closed);
+                    }
+                    response
+                }
+                ManageSieveCommand::Unauthenticate => match session.user.take() {
+                    Some(_) => {
+                        self.report_session(session, live);
+                        ManageSieveResponse::ok("Logged out")
+                    }
+                    None => ManageSieveResponse::no("Not authenticated"),
+                },
+                command => match &session.user {
+                    Some(user) => self
+                        .script_command(user, command)
+                        .await
+                        .unwrap_or_else(|e| {
+                            error!("ManageSieve command failed for {}: {}", user.email, e);
+                            ManageSieveResponse::no_code("TRYLATER", "Temporary server error")
+                        }),
+                    None => ManageSieveResponse::no("Authenticate first"),
+                },
+            };
+            self.send(stream, &response).await?;
+        }
+    }
+
+    /// Run the SASL exchange of AUTHENTICATE and log the user in
+    ///
+    /// Credentials are checked like SMTP AUTH; bearer token mechanisms are
+    /// only offered by IMAP.
+    async fn authenticate<S>(
+        &self,
+        stream: &mut S,
+        session: &mut Session,
+        mechanism: &str,
+        initial_response: Option<String>,
+    ) -> Result<String>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin,
+    {
+        let mechanism = match SaslMechanism::parse(mechanism) {
+            Some(m) if !m.is_bearer() => m,
+            _ => {
+                return Ok(ManageSieveResponse::no(
+                    "Unsupported authentication mechanism",
+                ))
+            }
+        };
+
+        // The first response is either sent inline or after an initial challenge
+        let first = match initial_response {
+            Some(response) => response,
+            None => {
+                let prompt = if mechanism == SaslMechanism::Login {
+                    sasl::LOGIN_USERNAME_CHALLENGE
+                } else {
+                    ""
+                };
+                match self.exchange(stream, prompt).await? {
+                    Some(response) => response,
+                    None => return Ok(ManageSieveResponse::no("Authentication cancelled")),
+                }
+            }
+        };
+        let Some(data) = sasl::decode_response(&first) else {
+            return Ok(ManageSieveResponse::no("Invalid base64 in SASL response"));
+        };
+
+        let credentials = match mechanism {
+            SaslMechanism::Login => {
+                let password = match self
+                    .exchange(stream, sasl::LOGIN_PASSWORD_CHALLENGE)
+                    .await?
+                {
+                    Some(response) => {
+                        sasl::decode_response(&response).and_then(|p| String::from_utf8(p).ok())
+                    }
+                    None => return Ok(ManageSieveResponse::no("Authentication cancelled")),
+                };
+                String::from_utf8(data)
+                    .ok()
+                    .filter(|u| !u.is_empty())
+                    .zip(password)
+                    .map(|(username, password)| SaslCredentials::Password { username, password })
+            }
+            _ => sasl::parse_plain(&data),
+        };
+        let Some(SaslCredentials::Password { username, password }) = credentials else {
+            return Ok(ManageSieveResponse::no("Malformed SASL response"));
+        };
+        session.username = Some(username.clone());
+
+        let result = SmtpAuthenticator::new(self.db_pool.clone())
+            .verify_credentials(&username, &password)
+            .await;
+        let user = match result.user {
+            Some(user) if result.success => user,
+            _ => {
+                return Ok(ManageSieveResponse::no(
+                    result.error.as_deref().unwrap_or("Authentication failed"),
+                ))
+            }
+        };
+
+        match self
+            .config
+            .services
+            .check(&self.db_pool, user.tenant_id, user.id, MailService::Imap)
+            .await
+        {
+            Ok(None) => {}
+            Ok(Some(refusal)) => {
+                info!("ManageSieve login of {} refused: {:?}", username, refusal);
+                return Ok(ManageSieveResponse::no(&refusal.message(MailService::Imap)));
+            }
+            Err(e) => {
+                error!("Failed to check IMAP access of {}: {}", username, e);
+                return Ok(ManageSieveResponse::no_code(
+                    "TRYLATER",
+                    "Temporary authentication error",
+                ));
+            }
+        }
+
+        session.user = Some(SieveUser {
+            user_id: user.id,
+            tenant_id: user.tenant_id,
+            email: user.email,
+        });
+        Ok(ManageSieveResponse::ok("Logged in"))
+    }
+
+    /// Send a SASL challenge and read the client's response; `None` when
+    /// the client cancels with `"*"` or does not answer with a string
+    async fn exchange<S>(&self, stream: &mut S, challenge: &str) -> Result<Option<String>>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin,
+    {
+        self.send(stream, &ManageSieveResponse::challenge(challenge))
+            .await?;
+        let line = tokio::time::timeout(self.timeout(), read_line(stream, MIN_LITERAL as u64))
+            .await
+            .map_err(|_| anyhow!("SASL exchange timed out"))??;
+        let mut arguments = match line {
+            Line::Closed => return Err(anyhow!("Connection closed during SASL exchange")),
+            Line::Invalid(_) => return Ok(None),
+            Line::Arguments(arguments) => arguments,
+        };
+        match (arguments.pop(), arguments.is_empty()) {
+            (Some(Argument::String(response)), true) => {
+                Ok(String::from_utf8(response).ok().filter(|r| r != "*"))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Show the session's state and user in the session registry
+    fn report_session(&self, session: &Session, live: &ActiveSession) {
+        match &session.user {
+            Some(user) => {
+                live.set_state("authenticated");
+                live.set_user(&user.email, Some(user.tenant_id));
+            }
+            None => live.set_state("not authenticated"),
+        }
+    }
+
+    /// Report the outcome of a login to the security notifier and count a
+    /// failure towards the per-IP ban; true when the address is now banned
+    /// and the connection has to be closed
+    fn check_login(&self, response: &str, session: &Session) -> bool {
+        let ip = self.addr.ip();
+        if let Some(user) = &session.user {
+            if let Some(security) = &self.config.security {
+                security.login_succeeded(
+                    &self.db_pool,
+                    user.user_id,
+                    user.tenant_id,
+                    ip,
+                    "managesieve",
+                );
+            }
+            return false;
+        }
+
+        let Some(limiter) = &self.config.limiter else {
+            return false;
+        };
+        if !response.starts_with("NO") || !limiter.auth_failed(ip) {
+            return false;
+        }
+        if let (Some(security), Some(username)) = (&self.config.security, &session.username) {
+            security.login_blocked(&self.db_pool, username, ip, "managesieve");
+        }
+        true
+    }
+
+    /// Answer a command that manages the user's scripts
+    async fn script_command(
+        &self,
+        user: &SieveUser,
+        command: ManageSieveCommand,
+    ) -> Result<String> {
+        let repo = SieveScriptRepository::new(self.db_pool.clone());
+        let response = match command {
+            ManageSieveCommand::HaveSpace { name, size } => {
+                if !is_valid_script_name(&name) {
+                    return Ok(ManageSieveResponse::no("Invalid script name"));
+                }
+                match self.check_space(&repo, user, &name, size).await? {
+                    Some(refusal) => refusal,
+                    None => ManageSieveResponse::ok("Putscript would succeed"),
+                }
+            }
+            ManageSieveCommand::PutScript { name, content } => {
+                if !is_valid_script_name(&name) {
+                    return Ok(ManageSieveResponse::no("Invalid script name"));
+                }
+                let content = match content {
+                    ScriptContent::Text(content) => content,
+                    ScriptContent::TooLarge => return Ok(self.too_large()),
+                };
+                if let Some(refusal) = self
+                    .check_space(&repo, user, &name, content.len() as u64)
+                    .await?
+                {
+                    return Ok(refusal);
+                }
+                if let Err(e) = sieve::validate(&content) {
+                    return Ok(ManageSieveResponse::no(&e.to_string()));
+                }
+                repo.put(user.tenant_id, user.user_id, &name, &content)
+                    .await?;
+                info!("Stored Sieve script {:?} of {}", name, user.email);
+                ManageSieveResponse::ok("Script stored")
+            }
+            ManageSieveCommand::ListScripts => {
+                let scripts = repo.list(user.user_id).await?;
+                ManageSieveResponse::script_list(
+                    scripts
+                        .iter()
+                        .map(|script| (script.name.as_str(), script.active)),
+                )
+            }
+            ManageSieveCommand::SetActive { name } => {
+                let name = (!name.is_empty()).then_some(name.as_str());
+                if repo.set_active(user.user_id, name).await? {
+                    ManageSieveResponse::ok(match name {
+                        Some(_) => "Script activated",
+                        None => "All scripts deactivated",
+                    })
+                } else {
+                    nonexistent()
+                }
+            }
+            ManageSieveCommand::GetScript { name } => match repo.get(user.user_id, &name).await? {
+                Some(script) => ManageSieveResponse::script(&script.content),
+                None => nonexistent(),
+            },
+            ManageSieveCommand::DeleteScript { name } => {
+                match repo.get(user.user_id, &name).await? {
+                    None => nonexistent(),
+                    Some(script) if script.active => ManageSieveResponse::no_code(
+                        "ACTIVE",
+                        "Deactivate the script before deleting it",
+                    ),
+                    Some(_) => {
+                        repo.delete(user.user_id, &name).await?;
+                        ManageSieveResponse::ok("Script deleted")
+                    }
+                }
+            }
+            ManageSieveCommand::RenameScript { name, new_name } => {
+                if !is_valid_script_name(&new_name) {
+                    ManageSieveResponse::no("Invalid script name")
+                } else if repo.get(user.user_id, &new_name).await?.is_some() {
+                    ManageSieveResponse::no_code(
+                        "ALREADYEXISTS",
+                        "A script with that name already exists",
+                    )
+                } else if repo.rename(user.user_id, &name, &new_name).await? {
+                    ManageSieveResponse::ok("Script renamed")
+                } else {
+                    nonexistent()
+                }
+            }
+            ManageSieveCommand::CheckScript { content } => match content {
+                ScriptContent::TooLarge => self.too_large(),
+                ScriptContent::Text(content) => match sieve::validate(&content) {
+                    Ok(()) => ManageSieveResponse::ok("Script is valid"),
+                    Err(e) => ManageSieveResponse::no(&e.to_string()),
+                },
+            },
+            _ => ManageSieveResponse::no("Command not available"),
+        };
+        Ok(response)
+    }
+
+    /// Refusal when storing a script of `size` bytes under `name` would
+    /// exceed the user's limits
+    async fn check_space(
+        &self,
+        repo: &SieveScriptRepository,
+        user: &SieveUser,
+        name: &str,
+        size: u64,
+    ) -> Result<Option<String>> {
+        if size > self.config.max_script_size as u64 {
+            return Ok(Some(self.too_large()));
+        }
+        let usage = repo.usage(user.user_id).await?;
+        if usage.scripts as usize >= self.config.max_scripts
+            && repo.get(user.user_id, name).await?.is_none()
+        {
+            return Ok(Some(ManageSieveResponse::no_code(
+                "QUOTA/MAXSCRIPTS",
+                &format!("At most {} scripts can be stored", self.config.max_scripts),
+            )));
+        }
+        Ok(None)
+    }
+
+    fn too_large(&self) -> String {
+        ManageSieveResponse::no_code(
+            "QUOTA/MAXSIZE",
+            &format!(
+                "Scripts are limited to {} bytes",
+                self.config.max_script_size
+            ),
+        )
+    }
+}
+
+fn nonexistent() -> String {
+    ManageSieveResponse::no_code("NONEXISTENT", "No script with that name")
+}
+
+/// Whether a script name is allowed (RFC 5804 section 1.6): not empty,
+/// at most 255 characters and without control characters
+fn is_valid_script_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= 255
+        && !name
+            .chars()
+            .any(|c| c.is_control() || c == '\u{2028}' || c == '\u{2029}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_names() {
+        assert!(is_valid_script_name("vacation"));
+        assert!(is_valid_script_name("Ferien – Büro"));
+        assert!(!is_valid_script_name(""));
+        assert!(!is_valid_script_name("a\tb"));
+        assert!(!is_valid_script_name("a\u{2028}b"));
+        assert!(!is_valid_script_name(&"x".repeat(256)));
+    }
+}
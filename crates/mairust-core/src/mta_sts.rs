@@ -0,0 +1,309 @@
+//! MTA-STS policies and TLS-RPT of hosted domains
+//!
+//! MTA-STS (RFC 8461) tells sending servers to deliver to a domain only
+//! over TLS to the listed MX hosts. The policy is fetched from
+//! `https://mta-sts.<domain>/.well-known/mta-sts.txt`, which the web UI
+//! serves from the domain's settings when `mta-sts.<domain>` points at it.
+//! Domain settings (`extra_settings.mta_sts`):
+//!
+//! ```json
+//! {"mode": "enforce", "mx": ["mail.example.com"], "max_age": 604800}
+//! ```
+//!
+//! - `mode`: `enforce`, `testing` (the default) or `none`.
+//! - `mx`: MX hosts senders may deliver to, `*.example.com` matching one
+//!   level of subdomains. Defaults to `MAIRUST_HOSTNAME`.
+//! - `max_age`: seconds senders cache the policy (default one week).
+//!
+//! Senders only fetch the policy again when the id in the domain's
+//! `_mta-sts` TXT record changes; the id is the time the settings were
+//! last updated.
+//!
+//! TLS-RPT (RFC 8460) reports about the domain can be posted to the web UI
+//! at `https://mta-sts.<domain>` and the configured report path; they are
+//! stored like reports sent by mail.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Domain;
+use mairust_storage::repository::{
+    DomainRepository, DomainSettingsRepository, DomainSettingsRepositoryTrait,
+};
+
+/// Path of the policy on the policy host
+pub const POLICY_PATH: &str = "/.well-known/mta-sts.txt";
+
+/// Label that prefixes a domain in the name of its policy host
+const POLICY_HOST_PREFIX: &str = "mta-sts.";
+
+/// Default `max_age`: one week
+const DEFAULT_MAX_AGE: u64 = 604_800;
+
+/// Largest `max_age` senders honor (RFC 8461 section 3.2)
+const MAX_MAX_AGE: u64 = 31_557_600;
+
+/// What senders do when delivery does not satisfy the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MtaStsMode {
+    /// Refuse to deliver
+    Enforce,
+    /// Deliver anyway, and report the failure through TLS-RPT
+    #[default]
+    Testing,
+    /// Forget the domain's policy
+    None,
+}
+
+impl MtaStsMode {
+    /// Parse a `mode` setting
+    pub fn from_setting(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "enforce" => Some(MtaStsMode::Enforce),
+            "testing" => Some(MtaStsMode::Testing),
+            "none" => Some(MtaStsMode::None),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MtaStsMode::Enforce => "enforce",
+            MtaStsMode::Testing => "testing",
+            MtaStsMode::None => "none",
+        }
+    }
+}
+
+/// MTA-STS policy of a domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtaStsPolicy {
+    pub mode: MtaStsMode,
+    /// Lowercased MX host patterns
+    pub mx: Vec<String>,
+    /// Seconds senders cache the policy
+    pub max_age: u64,
+}
+
+impl MtaStsPolicy {
+    /// Read `mta_sts` from domain settings, with `default_mx` when it lists
+    /// no MX hosts; `None` when the domain has no policy, or one that
+    /// delivers nowhere
+    pub fn from_domain_settings(
+        extra_settings: &serde_json::Value,
+        default_mx: Option<&str>,
+    ) -> Option<Self> {
+        let settings = extra_settings.get("mta_sts")?.as_object()?;
+        let mode = settings
+            .get("mode")
+            .and_then(|v| v.as_str())
+            .and_then(MtaStsMode::from_setting)
+            .unwrap_or_default();
+        let mut mx: Vec<String> = settings
+            .get("mx")
+            .and_then(|v| v.as_array())
+            .map(|hosts| {
+                hosts
+                    .iter()
+                    .filter_map(|host| host.as_str())
+                    .filter_map(normalize_mx)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if mx.is_empty() {
+            mx.extend(default_mx.and_then(normalize_mx));
+        }
+        mx.dedup();
+        if mx.is_empty() && mode != MtaStsMode::None {
+            return None;
+        }
+        let max_age = settings
+            .get("max_age")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(DEFAULT_MAX_AGE)
+            .min(MAX_MAX_AGE);
+        Some(Self { mode, mx, max_age })
+    }
+
+    /// The policy file
+    pub fn to_text(&self) -> String {
+        let mut text = format!("version: STSv1\r\nmode: {}\r\n", self.mode.as_str());
+        for mx in &self.mx {
+            text.push_str(&format!("mx: {}\r\n", mx));
+        }
+        text.push_str(&format!("max_age: {}\r\n", self.max_age));
+        text
+    }
+}
+
+/// Lowercased MX host pattern, without a trailing dot
+fn normalize_mx(host: &str) -> Option<String> {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+    let name = host.strip_prefix("*.").unwrap_or(&host);
+    let valid = !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    valid.then_some(host)
+}
+
+/// Policy id of settings last updated at `updated_at`
+pub fn policy_id(updated_at: DateTime<Utc>) -> String {
+    updated_at.format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Value of the `_mta-sts` TXT record of settings last updated at
+/// `updated_at`
+pub fn sts_record(updated_at: DateTime<Utc>) -> String {
+    format!("v=STSv1; id={}", policy_id(updated_at))
+}
+
+/// Domain whose policy host `host` (a Host header) is
+pub fn policy_domain(host: &str) -> Option<String> {
+    let host = host.trim().to_lowercase();
+    // Drop the port
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host,
+    };
+    let domain = host
+        .trim_end_matches('.')
+        .strip_prefix(POLICY_HOST_PREFIX)?;
+    (!domain.is_empty() && domain.contains('.')).then(|| domain.to_string())
+}
+
+/// Looks up the hosted domains behind policy hosts
+pub struct MtaStsHost {
+    db_pool: DatabasePool,
+}
+
+impl MtaStsHost {
+    /// Create a policy host lookup
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Hosted domain whose policy host `host` is
+    pub async fn domain(&self, host: &str) -> Result<Option<Domain>> {
+        let Some(name) = policy_domain(host) else {
+            return Ok(None);
+        };
+        Ok(DomainRepository::new(self.db_pool.clone())
+            .find_by_name(&name)
+            .await?)
+    }
+
+    /// Policy file of the domain whose policy host `host` is
+    pub async fn policy(&self, host: &str) -> Result<Option<String>> {
+        let Some(domain) = self.domain(host).await? else {
+            return Ok(None);
+        };
+        let Some(settings) = DomainSettingsRepository::new(self.db_pool.clone())
+            .get(domain.id)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let default_mx = std::env::var("MAIRUST_HOSTNAME").ok();
+        Ok(
+            MtaStsPolicy::from_domain_settings(&settings.extra_settings, default_mx.as_deref())
+                .map(|policy| policy.to_text()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_policy_from_domain_settings() {
+        assert_eq!(
+            MtaStsPolicy::from_domain_settings(&serde_json::json!({}), Some("mx.example.net")),
+            None
+        );
+
+        let policy = MtaStsPolicy::from_domain_settings(
+            &serde_json::json!({"mta_sts": {}}),
+            Some("MX.Example.NET."),
+        )
+        .unwrap();
+        assert_eq!(policy.mode, MtaStsMode::Testing);
+        assert_eq!(policy.mx, vec!["mx.example.net".to_string()]);
+        assert_eq!(policy.max_age, DEFAULT_MAX_AGE);
+
+        let policy = MtaStsPolicy::from_domain_settings(
+            &serde_json::json!({"mta_sts": {
+                "mode": "Enforce",
+                "mx": ["mail.example.com", "*.mx.example.com", "bad host", 7],
+                "max_age": 99_999_999
+            }}),
+            Some("mx.example.net"),
+        )
+        .unwrap();
+        assert_eq!(policy.mode, MtaStsMode::Enforce);
+        assert_eq!(
+            policy.mx,
+            vec![
+                "mail.example.com".to_string(),
+                "*.mx.example.com".to_string()
+            ]
+        );
+        assert_eq!(policy.max_age, MAX_MAX_AGE);
+
+        // A policy without MX hosts delivers nowhere
+        assert_eq!(
+            MtaStsPolicy::from_domain_settings(
+                &serde_json::json!({"mta_sts": {"mode": "enforce"}}),
+                None
+            ),
+            None
+        );
+        let none = MtaStsPolicy::from_domain_settings(
+            &serde_json::json!({"mta_sts": {"mode": "none"}}),
+            None,
+        )
+        .unwrap();
+        assert_eq!(none.mode, MtaStsMode::None);
+    }
+
+    #[test]
+    fn test_policy_text() {
+        let policy = MtaStsPolicy {
+            mode: MtaStsMode::Enforce,
+            mx: vec!["mail.example.com".to_string(), "*.example.net".to_string()],
+            max_age: 86_400,
+        };
+        assert_eq!(
+            policy.to_text(),
+            "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.example.net\r\nmax_age: 86400\r\n"
+        );
+    }
+
+    #[test]
+    fn test_sts_record() {
+        let updated_at = Utc.with_ymd_and_hms(2024, 2, 8, 9, 30, 5).unwrap();
+        assert_eq!(policy_id(updated_at), "20240208093005");
+        assert_eq!(sts_record(updated_at), "v=STSv1; id=20240208093005");
+    }
+
+    #[test]
+    fn test_policy_domain() {
+        assert_eq!(
+            policy_domain("mta-sts.example.com").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            policy_domain("MTA-STS.Example.com:443").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            policy_domain("mta-sts.example.com.").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(policy_domain("example.com"), None);
+        assert_eq!(policy_domain("mail.example.com"), None);
+        assert_eq!(policy_domain("mta-sts.localhost"), None);
+    }
+}
@@ -0,0 +1,417 @@
+//! Tenant onboarding
+//!
+//! Bootstrapping creates what a new tenant needs to send and receive mail
+//! in one transaction: the tenant, its first domain with a DKIM key, default
+//! domain settings, an admin user with a mailbox and the default folders,
+//! an optional starter configuration bundle and an API key for the tenant.
+//! A failing step rolls everything back, so a bootstrap can simply be
+//! retried.
+//!
+//! The DKIM key is generated by the caller, which also publishes its DNS
+//! record; the API key is only ever returned here, in plain text.
+
+use crate::bundle::{apply_bundle, BundleError, ConfigBundle, ImportOptions, ImportReport};
+use crate::folders::{provision_folders, FolderSettings};
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Argon2, PasswordHasher};
+use mairust_common::types::UserRole;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{Domain, Mailbox, Tenant, User};
+use mairust_storage::repository::api_keys::{generate_api_key, hash_new_api_key, key_prefix};
+use mairust_storage::repository::PolicyActor;
+use serde::Deserialize;
+use sqlx::PgConnection;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+/// Shortest admin password accepted
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// Scopes of the API key issued to a new tenant
+///
+/// Tenant routes only check that the key belongs to the tenant; system
+/// administration scopes (`admin:*`) are never granted.
+pub const TENANT_KEY_SCOPES: &[&str] = &["read", "write", "send", "admin"];
+
+/// Onboarding errors
+#[derive(Error, Debug)]
+pub enum OnboardingError {
+    #[error("Invalid request: {0}")]
+    Invalid(String),
+
+    #[error("{0} already exists")]
+    Conflict(String),
+
+    #[error("Invalid configuration bundle: {0}")]
+    Bundle(#[from] BundleError),
+
+    #[error("Failed to hash password: {0}")]
+    Password(String),
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// A new tenant with its first domain and admin
+#[derive(Debug, Clone, Deserialize)]
+pub struct BootstrapRequest {
+    pub name: String,
+    pub slug: String,
+    pub plan: Option<String>,
+    pub settings: Option<serde_json::Value>,
+    /// First domain of the tenant
+    pub domain: String,
+    /// Address of the admin user, at `domain`
+    pub admin_email: String,
+    pub admin_password: String,
+    pub admin_name: Option<String>,
+    /// Policies, hooks and domain settings to start from
+    pub config: Option<ConfigBundle>,
+}
+
+/// DKIM key of the first domain
+#[derive(Debug, Clone)]
+pub struct DkimKey {
+    pub selector: String,
+    /// PKCS#8 PEM private key
+    pub private_key: String,
+}
+
+/// Everything created by a bootstrap
+#[derive(Debug, Clone)]
+pub struct BootstrapResult {
+    pub tenant: Tenant,
+    pub domain: Domain,
+    pub admin: User,
+    pub mailbox: Mailbox,
+    /// Names of the folders provisioned for the admin
+    pub folders: Vec<String>,
+    /// Result of applying the starter bundle, if one was given
+    pub config: Option<ImportReport>,
+    pub api_key_id: Uuid,
+    /// The API key in plain text; only its hash is stored
+    pub api_key: String,
+}
+
+/// Creates tenants ready for use in one step
+pub struct TenantBootstrapper {
+    db_pool: DatabasePool,
+}
+
+impl TenantBootstrapper {
+    /// Create a new bootstrapper
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Create the tenant, its domain, admin, folders, settings and API key
+    pub async fn bootstrap(
+        &self,
+        input: &BootstrapRequest,
+        dkim: DkimKey,
+    ) -> Result<BootstrapResult, OnboardingError> {
+        validate(input)?;
+        if let Some(bundle) = &input.config {
+            bundle
+                .validate()
+                .map_err(|e| BundleError::Invalid(e.to_string()))?;
+        }
+
+        let domain_name = input.domain.trim().to_lowercase();
+        let admin_email = input.admin_email.trim().to_lowercase();
+        let password_hash = hash_password(&input.admin_password)?;
+        let api_key = generate_api_key();
+        let api_key_hash =
+            hash_new_api_key(&api_key).map_err(|e| OnboardingError::Password(e.to_string()))?;
+
+        let mut tx = self.db_pool.pool().begin().await?;
+        check_available(&mut tx, &input.slug, &domain_name, &admin_email).await?;
+
+        let tenant = sqlx::query_as::<_, Tenant>(
+            r#"
+            INSERT INTO tenants (id, name, slug, status, plan, settings, created_at, updated_at)
+            VALUES ($1, $2, $3, 'active', $4, $5, NOW(), NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(input.name.trim())
+        .bind(&input.slug)
+        .bind(input.plan.as_deref().unwrap_or("free"))
+        .bind(input.settings.clone().unwrap_or(serde_json::json!({})))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let domain = sqlx::query_as::<_, Domain>(
+            r#"
+            INSERT INTO domains (id, tenant_id, name, verified, dkim_selector, dkim_private_key,
+                                 created_at, updated_at)
+            VALUES ($1, $2, $3, false, $4, $5, NOW(), NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant.id)
+        .bind(&domain_name)
+        .bind(&dkim.selector)
+        .bind(&dkim.private_key)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO dkim_keys (id, domain_id, selector, algorithm, private_key, status,
+                                   activated_at)
+            VALUES ($1, $2, $3, 'rsa-sha256', $4, 'active', NOW())
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(domain.id)
+        .bind(&dkim.selector)
+        .bind(&dkim.private_key)
+        .execute(&mut *tx)
+        .await?;
+
+        // Default settings; a starter bundle may override them below
+        sqlx::query("INSERT INTO domain_settings (domain_id, updated_at) VALUES ($1, NOW())")
+            .bind(domain.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let admin = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (id, tenant_id, email, password_hash, name, role, active,
+                               created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, true, NOW(), NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant.id)
+        .bind(&admin_email)
+        .bind(&password_hash)
+        .bind(&input.admin_name)
+        .bind(format!("{:?}", UserRole::TenantAdmin).to_lowercase())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mailbox = sqlx::query_as::<_, Mailbox>(
+            r#"
+            INSERT INTO mailboxes (id, tenant_id, domain_id, user_id, address, display_name,
+                                   used_bytes, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, 0, NOW(), NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant.id)
+        .bind(domain.id)
+        .bind(admin.id)
+        .bind(&admin_email)
+        .bind(&input.admin_name)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let folder_settings = FolderSettings::from_tenant_settings(&tenant.settings);
+        let folders = provision_folders(&mut tx, &mailbox, &folder_settings).await?;
+
+        let config = match &input.config {
+            Some(bundle) => Some(
+                apply_bundle(
+                    &mut tx,
+                    tenant.id,
+                    bundle,
+                    ImportOptions::default(),
+                    &PolicyActor::system(),
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        let api_key_id = Uuid::now_v7();
+        sqlx::query(
+            r#"
+            INSERT INTO api_keys (id, tenant_id, user_id, name, key_hash, key_prefix, scopes, created_at)
+            VALUES ($1, $2, $3, 'Initial admin key', $4, $5, $6, NOW())
+            "#,
+        )
+        .bind(api_key_id)
+        .bind(tenant.id)
+        .bind(admin.id)
+        .bind(&api_key_hash)
+        .bind(key_prefix(&api_key))
+        .bind(serde_json::json!(TENANT_KEY_SCOPES))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        info!(
+            "Bootstrapped tenant {} ({}) with domain {} and admin {}",
+            tenant.slug, tenant.id, domain.name, admin.email
+        );
+
+        Ok(BootstrapResult {
+            tenant,
+            domain,
+            admin,
+            mailbox,
+            folders,
+            config,
+            api_key_id,
+            api_key,
+        })
+    }
+}
+
+/// Check the parts of a request that do not need the database
+fn validate(input: &BootstrapRequest) -> Result<(), OnboardingError> {
+    let invalid = |reason: &str| Err(OnboardingError::Invalid(reason.to_string()));
+
+    if input.name.trim().is_empty() {
+        return invalid("tenant name is empty");
+    }
+    if !is_valid_slug(&input.slug) {
+        return invalid("slug must be 1-63 lowercase letters, digits or hyphens");
+    }
+    let domain = input.domain.trim().to_lowercase();
+    if domain.is_empty() || !domain.contains('.') || domain.contains('@') {
+        return invalid("domain is not a domain name");
+    }
+    let admin_domain = input
+        .admin_email
+        .trim()
+        .rsplit_once('@')
+        .filter(|(local, _)| !local.is_empty())
+        .map(|(_, domain)| domain.to_lowercase());
+    if admin_domain.as_deref() != Some(domain.as_str()) {
+        return invalid("admin_email must be an address at the domain");
+    }
+    if input.admin_password.chars().count() < MIN_PASSWORD_LEN {
+        return invalid("admin_password is too short");
+    }
+    Ok(())
+}
+
+/// Lowercase letters, digits and inner hyphens, at most 63 characters
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 63
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Report taken names as conflicts rather than constraint violations
+async fn check_available(
+    conn: &mut PgConnection,
+    slug: &str,
+    domain: &str,
+    admin_email: &str,
+) -> Result<(), OnboardingError> {
+    let (slug_taken, domain_taken, email_taken): (bool, bool, bool) = sqlx::query_as(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM tenants WHERE slug = $1),
+               EXISTS (SELECT 1 FROM domains WHERE name = $2),
+               EXISTS (SELECT 1 FROM users WHERE email = $3)
+                   OR EXISTS (SELECT 1 FROM mailboxes WHERE address = $3)
+        "#,
+    )
+    .bind(slug)
+    .bind(domain)
+    .bind(admin_email)
+    .fetch_one(conn)
+    .await?;
+
+    if slug_taken {
+        return Err(OnboardingError::Conflict(format!("Tenant '{}'", slug)));
+    }
+    if domain_taken {
+        return Err(OnboardingError::Conflict(format!("Domain '{}'", domain)));
+    }
+    if email_taken {
+        return Err(OnboardingError::Conflict(format!(
+            "Address '{}'",
+            admin_email
+        )));
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, OnboardingError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| OnboardingError::Password(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> BootstrapRequest {
+        BootstrapRequest {
+            name: "Example".to_string(),
+            slug: "example".to_string(),
+            plan: None,
+            settings: None,
+            domain: "Example.com".to_string(),
+            admin_email: "admin@example.com".to_string(),
+            admin_password: "correct horse".to_string(),
+            admin_name: None,
+            config: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&request()).is_ok());
+
+        let mut input = request();
+        input.admin_email = "admin@example.net".to_string();
+        assert!(validate(&input).is_err());
+
+        let mut input = request();
+        input.admin_email = "@example.com".to_string();
+        assert!(validate(&input).is_err());
+
+        let mut input = request();
+        input.admin_password = "short".to_string();
+        assert!(validate(&input).is_err());
+
+        let mut input = request();
+        input.name = " ".to_string();
+        assert!(validate(&input).is_err());
+    }
+
+    #[test]
+    fn test_is_valid_slug() {
+        assert!(is_valid_slug("acme-corp2"));
+        assert!(!is_valid_slug(""));
+        assert!(!is_valid_slug("Acme"));
+        assert!(!is_valid_slug("-acme"));
+        assert!(!is_valid_slug("acme_corp"));
+        assert!(!is_valid_slug(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_tenant_key_scopes() {
+        assert!(!TENANT_KEY_SCOPES.contains(&"*"));
+        assert!(TENANT_KEY_SCOPES.iter().all(|s| !s.starts_with("admin:")));
+    }
+
+    #[test]
+    fn test_hash_password() {
+        use argon2::{PasswordHash, PasswordVerifier};
+
+        let hash = hash_password("correct horse").unwrap();
+        let parsed = PasswordHash::new(&hash).unwrap();
+        assert!(Argon2::default()
+            .verify_password(b"correct horse", &parsed)
+            .is_ok());
+    }
+}
@@ -3,14 +3,20 @@
 //! The policy engine loads applicable policies and evaluates their conditions
 //! against message data to determine what actions should be taken.
 
+use super::rate_limit::PolicyRateLimit;
+use crate::attachments::{AttachmentDisposition, AttachmentRemoval};
+use crate::email_auth::AuthenticationResult;
 use anyhow::Result;
 use chrono::{DateTime, Datelike, Timelike, Utc};
 use mairust_common::types::{DomainId, TenantId};
 use mairust_storage::db::DatabasePool;
-use mairust_storage::models::{PolicyAction, PolicyCondition, PolicyConditionType, PolicyRule};
+use mairust_storage::models::{
+    PolicyAction, PolicyCondition, PolicyConditionGroup, PolicyConditionNode, PolicyConditionType,
+    PolicyRule,
+};
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Context for policy evaluation
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,12 +41,28 @@ pub struct PolicyContext {
     pub headers: serde_json::Value,
     /// Message size in bytes
     pub message_size: i64,
-    /// Attachment content types
+    /// Attachment content types and file name extensions (`.exe`)
     pub attachment_types: Vec<String>,
     /// Spam score (if available)
     pub spam_score: Option<f64>,
     /// Client IP address
     pub client_ip: Option<String>,
+    /// Reputation of the sender domain, from 0 to 100 (if tracked)
+    pub sender_reputation: Option<f64>,
+    /// ISO 3166-1 code of the client's country (if known)
+    pub country: Option<String>,
+    /// Autonomous system number of the client (if known)
+    pub asn: Option<u32>,
+    /// SPF result (`pass`, `fail`, `softfail`, ...), if checked
+    pub spf_result: Option<String>,
+    /// DKIM result (`pass`, `fail`, `none`, ...), if checked
+    pub dkim_result: Option<String>,
+    /// DMARC result (`pass`, `fail`, `none`, ...), if checked and not
+    /// overridden by a trusted ARC chain
+    pub dmarc_result: Option<String>,
+    /// Published policy of the sender domain (`none`, `quarantine` or
+    /// `reject`) when DMARC failed
+    pub dmarc_policy: Option<String>,
     /// Current time (for time-based policies)
     pub current_time: DateTime<Utc>,
 }
@@ -76,6 +98,13 @@ impl PolicyContext {
             attachment_types: Vec::new(),
             spam_score: None,
             client_ip: None,
+            sender_reputation: None,
+            country: None,
+            asn: None,
+            spf_result: None,
+            dkim_result: None,
+            dmarc_result: None,
+            dmarc_policy: None,
             current_time: Utc::now(),
         }
     }
@@ -127,6 +156,59 @@ impl PolicyContext {
         self.client_ip = ip;
         self
     }
+
+    /// Set the sender domain reputation
+    pub fn with_sender_reputation(mut self, reputation: Option<f64>) -> Self {
+        self.sender_reputation = reputation;
+        self
+    }
+
+    /// Set the client's country
+    pub fn with_country(mut self, country: Option<String>) -> Self {
+        self.country = country;
+        self
+    }
+
+    /// Set the client's autonomous system number
+    pub fn with_asn(mut self, asn: Option<u32>) -> Self {
+        self.asn = asn;
+        self
+    }
+
+    /// Set the email authentication results
+    ///
+    /// A DMARC failure overridden by a trusted ARC chain leaves the DMARC
+    /// result unset, so conditions on it do not match.
+    pub fn with_authentication(mut self, auth: &AuthenticationResult) -> Self {
+        self.spf_result = Some(auth.spf.as_header_value().to_string());
+        self.dkim_result = Some(auth.dkim.as_header_value().to_string());
+        if !auth.dmarc_overridden {
+            self.dmarc_result = Some(auth.dmarc.as_header_value().to_string());
+            self.dmarc_policy = auth
+                .dmarc
+                .policy()
+                .map(|policy| policy.as_str().to_string());
+        }
+        self
+    }
+
+    /// Set the email authentication results stored in the metadata of a
+    /// message
+    pub fn with_stored_authentication(mut self, metadata: &serde_json::Value) -> Self {
+        let result = |key: &str| {
+            metadata
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        self.spf_result = result("spf");
+        self.dkim_result = result("dkim");
+        if metadata.get("dmarc_overridden").and_then(|v| v.as_bool()) != Some(true) {
+            self.dmarc_result = result("dmarc");
+            self.dmarc_policy = result("dmarc_policy");
+        }
+        self
+    }
 }
 
 /// Result of evaluating a single policy rule
@@ -165,6 +247,12 @@ pub struct PolicyEvaluationResult {
     pub quarantine: bool,
     /// Redirect address (if redirecting)
     pub redirect_to: Option<String>,
+    /// Relay only over verified TLS (RFC 8689 REQUIRETLS)
+    pub require_tls: bool,
+    /// Attachments to take out of delivered copies
+    pub attachments: Option<AttachmentRemoval>,
+    /// Rate limits the message counts against
+    pub rate_limits: Vec<PolicyRateLimit>,
 }
 
 impl Default for PolicyEvaluationResult {
@@ -180,10 +268,50 @@ impl Default for PolicyEvaluationResult {
             headers_to_add: Vec::new(),
             quarantine: false,
             redirect_to: None,
+            require_tls: false,
+            attachments: None,
+            rate_limits: Vec::new(),
         }
     }
 }
 
+impl PolicyEvaluationResult {
+    /// Refusal of the message by a matched `reject` or `tempfail` action
+    pub fn rejection(&self) -> Option<PolicyRejection> {
+        if !self.reject {
+            return None;
+        }
+        let code = self
+            .smtp_code
+            .filter(|code| (400..600).contains(code))
+            .unwrap_or(550);
+        let status = if code < 500 { "4.7.1" } else { "5.7.1" };
+        let message = match &self.smtp_message {
+            Some(message) if message.starts_with(|c: char| c.is_ascii_digit()) => message.clone(),
+            Some(message) => format!("{} {}", status, message),
+            None => format!("{} Message refused by policy", status),
+        };
+        Some(PolicyRejection { code, message })
+    }
+}
+
+/// A message refused by a matched policy
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("message refused by policy: {code} {message}")]
+pub struct PolicyRejection {
+    /// SMTP reply code, 4xx or 5xx
+    pub code: u16,
+    /// Reply text, starting with an enhanced status code
+    pub message: String,
+}
+
+impl PolicyRejection {
+    /// SMTP reply for the refused message
+    pub fn reply(&self) -> (u16, String) {
+        (self.code, self.message.clone())
+    }
+}
+
 /// Evaluation context for a single condition
 #[derive(Debug, Clone)]
 pub struct PolicyEvaluation {
@@ -216,6 +344,23 @@ impl PolicyEngine {
 
         debug!("Evaluating {} policies", policies.len());
 
+        let result = self.evaluate_rules(&policies, context);
+        for policy_match in &result.matches {
+            info!(
+                "Policy '{}' (priority {}) matched for tenant {:?}",
+                policy_match.policy_name, policy_match.priority, context.tenant_id
+            );
+        }
+
+        Ok(result)
+    }
+
+    /// Evaluate a given set of policies, e.g. one with rules not saved yet
+    pub fn evaluate_rules(
+        &self,
+        policies: &[PolicyRule],
+        context: &PolicyContext,
+    ) -> PolicyEvaluationResult {
         let mut result = PolicyEvaluationResult::default();
         let mut matches = Vec::new();
 
@@ -230,9 +375,16 @@ impl PolicyEngine {
                 continue;
             }
 
-            // Evaluate conditions
-            let conditions: Vec<PolicyCondition> =
-                serde_json::from_value(policy.conditions.clone()).unwrap_or_default();
+            // Evaluate conditions; a policy whose conditions cannot be read
+            // would otherwise match all mail
+            let conditions: Vec<PolicyConditionNode> =
+                match serde_json::from_value(policy.conditions.clone()) {
+                    Ok(conditions) => conditions,
+                    Err(e) => {
+                        warn!("Skipping policy {} with invalid conditions: {}", policy.name, e);
+                        continue;
+                    }
+                };
 
             let all_conditions_match = self.evaluate_conditions(&conditions, context);
 
@@ -240,11 +392,6 @@ impl PolicyEngine {
                 let actions: Vec<PolicyAction> =
                     serde_json::from_value(policy.actions.clone()).unwrap_or_default();
 
-                info!(
-                    "Policy '{}' (priority {}) matched for tenant {:?}",
-                    policy.name, policy.priority, context.tenant_id
-                );
-
                 matches.push(PolicyMatch {
                     policy_id: policy.id,
                     policy_name: policy.name.clone(),
@@ -260,7 +407,7 @@ impl PolicyEngine {
         // Process actions from all matching policies
         for policy_match in &matches {
             for action in &policy_match.actions {
-                self.apply_action(action, &mut result);
+                self.apply_action(policy_match.policy_id, action, &mut result);
             }
         }
 
@@ -270,7 +417,7 @@ impl PolicyEngine {
             .first()
             .and_then(|m| m.actions.first().cloned());
 
-        Ok(result)
+        result
     }
 
     /// Load applicable policies from the database
@@ -302,26 +449,24 @@ impl PolicyEngine {
         Ok(policies)
     }
 
-    /// Evaluate all conditions (AND logic)
-    fn evaluate_conditions(
+    /// Evaluate a conditions array: every entry has to match, and groups
+    /// in it combine their entries with all-of, any-of or none-of logic
+    ///
+    /// Mailbox filters use the same conditions as policies.
+    pub fn evaluate_conditions(
         &self,
-        conditions: &[PolicyCondition],
+        conditions: &[PolicyConditionNode],
         context: &PolicyContext,
     ) -> bool {
-        if conditions.is_empty() {
-            return true; // No conditions means always match
-        }
-
-        for condition in conditions {
+        // No conditions means always match
+        conditions_match(conditions, &|condition| {
             let eval = self.evaluate_condition(condition, context);
-            let matched = if condition.negate { !eval.matched } else { eval.matched };
-
-            if !matched {
-                return false;
+            if condition.negate {
+                !eval.matched
+            } else {
+                eval.matched
             }
-        }
-
-        true
+        })
     }
 
     /// Evaluate a single condition
@@ -374,6 +519,34 @@ impl PolicyEngine {
             PolicyConditionType::TimeOfDay => {
                 self.evaluate_time_condition(&condition.operator, &context.current_time, &condition.value)
             }
+            PolicyConditionType::SenderReputation => {
+                if let Some(reputation) = context.sender_reputation {
+                    self.evaluate_numeric_condition(&condition.operator, reputation, &condition.value)
+                } else {
+                    PolicyEvaluation {
+                        matched: false,
+                        debug_info: Some("No sender reputation available".to_string()),
+                    }
+                }
+            }
+            PolicyConditionType::Country => {
+                self.evaluate_string_condition(&condition.operator, &context.country, &condition.value)
+            }
+            PolicyConditionType::Asn => {
+                self.evaluate_asn_condition(&condition.operator, context.asn, &condition.value)
+            }
+            PolicyConditionType::SpfResult => {
+                self.evaluate_string_condition(&condition.operator, &context.spf_result, &condition.value)
+            }
+            PolicyConditionType::DkimResult => {
+                self.evaluate_string_condition(&condition.operator, &context.dkim_result, &condition.value)
+            }
+            PolicyConditionType::DmarcResult => {
+                self.evaluate_string_condition(&condition.operator, &context.dmarc_result, &condition.value)
+            }
+            PolicyConditionType::DmarcPolicy => {
+                self.evaluate_string_condition(&condition.operator, &context.dmarc_policy, &condition.value)
+            }
         }
     }
 
@@ -651,6 +824,43 @@ impl PolicyEngine {
         }
     }
 
+    /// Evaluate autonomous system condition; numbers are given as `64500`
+    /// or `"AS64500"`
+    fn evaluate_asn_condition(
+        &self,
+        operator: &str,
+        asn: Option<u32>,
+        expected: &serde_json::Value,
+    ) -> PolicyEvaluation {
+        let asn = match asn {
+            Some(asn) => asn,
+            None => {
+                return PolicyEvaluation {
+                    matched: false,
+                    debug_info: Some("No ASN available".to_string()),
+                }
+            }
+        };
+
+        let matched = match operator {
+            "eq" | "equals" => asn_number(expected) == Some(asn),
+            "ne" | "not_equals" => asn_number(expected) != Some(asn),
+            "in" => {
+                if let Some(arr) = expected.as_array() {
+                    arr.iter().any(|v| asn_number(v) == Some(asn))
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        };
+
+        PolicyEvaluation {
+            matched,
+            debug_info: Some(format!("AS{} {} {:?}", asn, operator, expected)),
+        }
+    }
+
     /// Check if IP is in CIDR range
     fn ip_in_cidr(&self, ip_str: &str, cidr_str: &str) -> bool {
         // Parse the IP address
@@ -745,7 +955,12 @@ impl PolicyEngine {
     }
 
     /// Apply an action to the evaluation result
-    fn apply_action(&self, action: &PolicyAction, result: &mut PolicyEvaluationResult) {
+    fn apply_action(
+        &self,
+        policy_id: uuid::Uuid,
+        action: &PolicyAction,
+        result: &mut PolicyEvaluationResult,
+    ) {
         let params = &action.parameters;
 
         match &action.action_type {
@@ -804,17 +1019,82 @@ impl PolicyEngine {
                 }
             }
             mairust_storage::models::PolicyActionType::RateLimit => {
-                // Rate limiting would be handled by a separate rate limiter
-                // Here we just add a tag
-                result.tags.push("rate_limited".to_string());
+                // Counted and enforced by the SMTP handler
+                match PolicyRateLimit::from_parameters(policy_id, params) {
+                    Some(limit) => result.rate_limits.push(limit),
+                    None => warn!(
+                        "Ignoring rate_limit action of policy {} without a valid limit",
+                        policy_id
+                    ),
+                }
             }
             mairust_storage::models::PolicyActionType::RequireTls => {
+                // The header carries the requirement to the outbound queue
+                result.require_tls = true;
                 result.headers_to_add.push(("X-Require-TLS".to_string(), "true".to_string()));
             }
+            mairust_storage::models::PolicyActionType::StripAttachments => {
+                // The highest priority policy decides
+                if result.attachments.is_none() {
+                    result.attachments = Some(AttachmentRemoval::from_parameters(
+                        AttachmentDisposition::Strip,
+                        params,
+                    ));
+                }
+            }
+            mairust_storage::models::PolicyActionType::ReplaceAttachments => {
+                if result.attachments.is_none() {
+                    result.attachments = Some(AttachmentRemoval::from_parameters(
+                        AttachmentDisposition::Replace,
+                        params,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Whether every entry of a conditions array matches, with `matches`
+/// deciding single conditions
+fn conditions_match(
+    nodes: &[PolicyConditionNode],
+    matches: &dyn Fn(&PolicyCondition) -> bool,
+) -> bool {
+    nodes.iter().all(|node| node_matches(node, matches))
+}
+
+/// Whether a condition or group matches; an empty `any_of` never does
+fn node_matches(node: &PolicyConditionNode, matches: &dyn Fn(&PolicyCondition) -> bool) -> bool {
+    match node {
+        PolicyConditionNode::Condition(condition) => matches(condition),
+        PolicyConditionNode::Group(PolicyConditionGroup::AllOf(nodes)) => {
+            conditions_match(nodes, matches)
+        }
+        PolicyConditionNode::Group(PolicyConditionGroup::AnyOf(nodes)) => {
+            nodes.iter().any(|node| node_matches(node, matches))
+        }
+        PolicyConditionNode::Group(PolicyConditionGroup::NoneOf(nodes)) => {
+            !nodes.iter().any(|node| node_matches(node, matches))
         }
     }
 }
 
+/// Autonomous system number of a condition value, `64500` or `"AS64500"`
+fn asn_number(value: &serde_json::Value) -> Option<u32> {
+    match value {
+        serde_json::Value::Number(number) => number.as_u64().and_then(|n| u32::try_from(n).ok()),
+        serde_json::Value::String(text) => {
+            let text = text.trim();
+            let digits = text
+                .get(..2)
+                .filter(|prefix| prefix.eq_ignore_ascii_case("as"))
+                .map_or(text, |_| &text[2..]);
+            digits.parse().ok()
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -996,6 +1276,70 @@ mod tests {
         assert!(ip_in_cidr_test("10.0.0.1", "10.0.0.0/8"));
     }
 
+    #[test]
+    fn test_country_condition() {
+        let context = create_test_context().with_country(Some("NL".to_string()));
+        let result =
+            evaluate_string_condition_test("eq", &context.country, &serde_json::json!("nl"));
+        assert!(result.matched);
+
+        let unknown = create_test_context();
+        let result =
+            evaluate_string_condition_test("eq", &unknown.country, &serde_json::json!("NL"));
+        assert!(!result.matched);
+    }
+
+    #[test]
+    fn test_authentication_results() {
+        use crate::email_auth::{DkimResult, DmarcPolicy, DmarcResult, SpfResult};
+
+        let auth = AuthenticationResult::new(
+            SpfResult::SoftFail,
+            DkimResult::None,
+            DmarcResult::Fail(DmarcPolicy::None),
+        );
+        let context = create_test_context().with_authentication(&auth);
+        assert_eq!(context.spf_result.as_deref(), Some("softfail"));
+        assert_eq!(context.dkim_result.as_deref(), Some("none"));
+        assert_eq!(context.dmarc_policy.as_deref(), Some("none"));
+        let result =
+            evaluate_string_condition_test("eq", &context.dmarc_result, &serde_json::json!("fail"));
+        assert!(result.matched);
+
+        let overridden = AuthenticationResult {
+            dmarc_overridden: true,
+            ..auth
+        };
+        let context = create_test_context().with_authentication(&overridden);
+        assert_eq!(context.dmarc_result, None);
+        assert_eq!(context.dmarc_policy, None);
+
+        let stored = create_test_context().with_stored_authentication(&serde_json::json!({
+            "spf": "pass",
+            "dkim": "pass",
+            "dmarc": "fail",
+            "dmarc_policy": "quarantine"
+        }));
+        assert_eq!(stored.spf_result.as_deref(), Some("pass"));
+        assert_eq!(stored.dmarc_result.as_deref(), Some("fail"));
+        assert_eq!(stored.dmarc_policy.as_deref(), Some("quarantine"));
+        let stored = create_test_context().with_stored_authentication(
+            &serde_json::json!({"dmarc": "fail", "dmarc_overridden": true}),
+        );
+        assert_eq!(stored.dmarc_result, None);
+    }
+
+    #[test]
+    fn test_asn_number() {
+        assert_eq!(asn_number(&serde_json::json!(64500)), Some(64500));
+        assert_eq!(asn_number(&serde_json::json!("AS64500")), Some(64500));
+        assert_eq!(asn_number(&serde_json::json!("as64500")), Some(64500));
+        assert_eq!(asn_number(&serde_json::json!("64500")), Some(64500));
+        assert_eq!(asn_number(&serde_json::json!("ASX")), None);
+        assert_eq!(asn_number(&serde_json::json!(-1)), None);
+        assert_eq!(asn_number(&serde_json::json!(5_000_000_000u64)), None);
+    }
+
     #[test]
     fn test_negate_condition() {
         let context = create_test_context();
@@ -1013,6 +1357,104 @@ mod tests {
         assert!(negated);
     }
 
+    #[test]
+    fn test_condition_groups() {
+        // (sender domain a.example OR b.example) AND attachment, NOT spam
+        let conditions: Vec<PolicyConditionNode> = serde_json::from_value(serde_json::json!([
+            {"any_of": [
+                {"condition_type": "sender_domain", "operator": "eq", "value": "a.example"},
+                {"condition_type": "sender_domain", "operator": "eq", "value": "b.example"}
+            ]},
+            {"condition_type": "attachment_type", "operator": "any_in", "value": [".pdf"]},
+            {"none_of": [
+                {"condition_type": "spam_score", "operator": "gt", "value": 5}
+            ]}
+        ]))
+        .unwrap();
+        let matching = |domain: &'static str, attachment: bool, spam: bool| {
+            move |condition: &PolicyCondition| match condition.condition_type {
+                PolicyConditionType::SenderDomain => condition.value == domain,
+                PolicyConditionType::AttachmentType => attachment,
+                PolicyConditionType::SpamScore => spam,
+                _ => false,
+            }
+        };
+
+        assert!(conditions_match(
+            &conditions,
+            &matching("a.example", true, false)
+        ));
+        assert!(conditions_match(
+            &conditions,
+            &matching("b.example", true, false)
+        ));
+        assert!(!conditions_match(
+            &conditions,
+            &matching("c.example", true, false)
+        ));
+        assert!(!conditions_match(
+            &conditions,
+            &matching("a.example", false, false)
+        ));
+        assert!(!conditions_match(
+            &conditions,
+            &matching("a.example", true, true)
+        ));
+        assert_eq!(conditions[0].conditions().len(), 2);
+    }
+
+    #[test]
+    fn test_empty_condition_groups() {
+        let matches = |_: &PolicyCondition| true;
+        assert!(conditions_match(&[], &matches));
+        let all_of: Vec<PolicyConditionNode> =
+            serde_json::from_value(serde_json::json!([{"all_of": []}])).unwrap();
+        assert!(conditions_match(&all_of, &matches));
+        let any_of: Vec<PolicyConditionNode> =
+            serde_json::from_value(serde_json::json!([{"any_of": []}])).unwrap();
+        assert!(!conditions_match(&any_of, &matches));
+        assert!(serde_json::from_value::<Vec<PolicyConditionNode>>(
+            serde_json::json!([{"one_of": []}])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_rejection() {
+        assert_eq!(PolicyEvaluationResult::default().rejection(), None);
+
+        let rejected = PolicyEvaluationResult {
+            reject: true,
+            smtp_message: Some("Not accepted from your network".to_string()),
+            ..PolicyEvaluationResult::default()
+        };
+        assert_eq!(
+            rejected.rejection().unwrap().reply(),
+            (550, "5.7.1 Not accepted from your network".to_string())
+        );
+
+        let deferred = PolicyEvaluationResult {
+            reject: true,
+            smtp_code: Some(451),
+            smtp_message: Some("4.7.0 Try again later".to_string()),
+            ..PolicyEvaluationResult::default()
+        };
+        assert_eq!(
+            deferred.rejection().unwrap().reply(),
+            (451, "4.7.0 Try again later".to_string())
+        );
+
+        let invalid_code = PolicyEvaluationResult {
+            reject: true,
+            smtp_code: Some(250),
+            ..PolicyEvaluationResult::default()
+        };
+        assert_eq!(
+            invalid_code.rejection().unwrap().reply(),
+            (550, "5.7.1 Message refused by policy".to_string())
+        );
+    }
+
     #[test]
     fn test_policy_context_creation() {
         let context = create_test_context();
@@ -1,10 +1,19 @@
 //! Policy Engine Module
 //!
 //! Evaluates policy rules against messages to determine actions for
-//! inbound and outbound email processing.
+//! inbound and outbound email processing, enforces the rate limits of
+//! matched policies, and replays stored mail through proposed policies
+//! before they are enabled.
 
 mod engine;
+mod rate_limit;
+mod simulator;
 
 pub use engine::{
     PolicyContext, PolicyEngine, PolicyEvaluation, PolicyEvaluationResult, PolicyMatch,
+    PolicyRejection,
+};
+pub use rate_limit::{PolicyRateLimit, PolicyRateLimited, PolicyRateLimiter, RateLimitSubject};
+pub use simulator::{
+    PolicySimulator, SimulatedMessage, SimulatedOutcome, SimulationOptions, SimulationReport,
 };
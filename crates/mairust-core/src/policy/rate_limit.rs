@@ -0,0 +1,293 @@
+//! Rate limits of the `rate_limit` policy action
+//!
+//! A matching policy with a `rate_limit` action counts the message against
+//! the limit of its sender, sender domain or client IP address:
+//!
+//! ```json
+//! {"action_type": "rate_limit", "parameters": {"limit": 100, "window": 3600, "per": "sender_domain"}}
+//! ```
+//!
+//! `window` is in seconds (default 3600) and `per` is `sender` (the
+//! default), `sender_domain` or `client_ip`. Messages over the limit are
+//! deferred with a 450 reply until the window ends.
+//!
+//! Counts are kept in `rate_limit_counters` in fixed windows, so that all
+//! server instances share them. They have no tenant; the scope is `policy`
+//! and the scope key the policy and what is counted.
+
+use super::PolicyContext;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_storage::db::DatabasePool;
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Scope of the stored counters
+const SCOPE: &str = "policy";
+
+/// Window of a limit without `window`
+const DEFAULT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Longest window of a limit, well within the two days old counters are
+/// kept for
+const MAX_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// What a policy rate limit counts messages of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitSubject {
+    Sender,
+    SenderDomain,
+    ClientIp,
+}
+
+/// Rate limit of a matched policy
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct PolicyRateLimit {
+    pub policy_id: Uuid,
+    /// Messages allowed in a window
+    pub limit: u32,
+    /// Length of a window, serialized in seconds
+    #[serde(serialize_with = "serialize_secs")]
+    pub window: Duration,
+    pub per: RateLimitSubject,
+    /// Text of the deferral reply
+    pub message: Option<String>,
+}
+
+impl PolicyRateLimit {
+    /// Read `limit`, `window`, `per` and `message` from the parameters of a
+    /// `rate_limit` action; `None` without a valid `limit`
+    pub fn from_parameters(policy_id: Uuid, params: &serde_json::Value) -> Option<Self> {
+        let limit = params
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .filter(|limit| *limit > 0)?;
+        let window = params
+            .get("window")
+            .and_then(|v| v.as_u64())
+            .filter(|secs| *secs > 0)
+            .map_or(DEFAULT_WINDOW, Duration::from_secs)
+            .min(MAX_WINDOW);
+        let per = match params.get("per").and_then(|v| v.as_str()) {
+            None | Some("sender") => RateLimitSubject::Sender,
+            Some("sender_domain") => RateLimitSubject::SenderDomain,
+            Some("client_ip") => RateLimitSubject::ClientIp,
+            Some(_) => return None,
+        };
+        Some(Self {
+            policy_id,
+            limit: u32::try_from(limit).unwrap_or(u32::MAX),
+            window,
+            per,
+            message: params
+                .get("message")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+        })
+    }
+
+    /// Scope key of the counter of a message; `None` when the message has
+    /// nothing to count by
+    fn key(&self, context: &PolicyContext) -> Option<String> {
+        let (kind, value) = match self.per {
+            RateLimitSubject::Sender => ("sender", context.sender_address.as_deref()?),
+            RateLimitSubject::SenderDomain => ("domain", context.sender_domain.as_deref()?),
+            RateLimitSubject::ClientIp => ("ip", context.client_ip.as_deref()?),
+        };
+        Some(format!(
+            "{}:{}:{}",
+            self.policy_id,
+            kind,
+            value.to_lowercase()
+        ))
+    }
+
+    /// Window type of the stored counters
+    fn window_type(&self) -> String {
+        format!("{}s", self.window.as_secs())
+    }
+
+    /// Start of the window `at` falls in
+    fn window_start(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = self.window.as_secs() as i64;
+        let start = at.timestamp().div_euclid(secs) * secs;
+        DateTime::from_timestamp(start, 0).unwrap_or(at)
+    }
+
+    /// Seconds from `at` until the window ends
+    fn retry_after(&self, at: DateTime<Utc>) -> i64 {
+        (self.window_start(at).timestamp() + self.window.as_secs() as i64 - at.timestamp()).max(1)
+    }
+}
+
+fn serialize_secs<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_secs())
+}
+
+/// A message deferred because a policy rate limit is exceeded
+#[derive(Debug, thiserror::Error)]
+#[error("policy rate limit exceeded, retry after {retry_after} seconds")]
+pub struct PolicyRateLimited {
+    pub retry_after: i64,
+    pub message: Option<String>,
+}
+
+impl PolicyRateLimited {
+    /// SMTP reply for the deferred message
+    pub fn reply(&self) -> (u16, String) {
+        let text = match &self.message {
+            Some(message) => format!("4.7.1 {}", message),
+            None => format!(
+                "4.7.1 Rate limit exceeded, please try again in {} seconds",
+                self.retry_after
+            ),
+        };
+        (450, text)
+    }
+}
+
+/// Counts messages against policy rate limits
+pub struct PolicyRateLimiter {
+    db_pool: DatabasePool,
+}
+
+impl PolicyRateLimiter {
+    /// Create a rate limiter
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Count a message against a limit
+    ///
+    /// Messages over the limit are not counted, and give the deferral.
+    pub async fn check(
+        &self,
+        limit: &PolicyRateLimit,
+        context: &PolicyContext,
+    ) -> Result<Option<PolicyRateLimited>> {
+        let Some(key) = limit.key(context) else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        let counted: Option<i32> = sqlx::query_scalar(
+            r#"
+            INSERT INTO rate_limit_counters
+                (id, tenant_id, scope, scope_key, window_type, window_start, count, limit_value)
+            VALUES (gen_random_uuid(), NULL, $1, $2, $3, $4, 1, $5)
+            ON CONFLICT (scope, scope_key, window_type, window_start)
+                WHERE tenant_id IS NULL
+            DO UPDATE SET count = rate_limit_counters.count + 1, updated_at = NOW()
+                WHERE rate_limit_counters.count < EXCLUDED.limit_value
+            RETURNING count
+            "#,
+        )
+        .bind(SCOPE)
+        .bind(&key)
+        .bind(limit.window_type())
+        .bind(limit.window_start(now))
+        .bind(limit.limit.min(i32::MAX as u32) as i32)
+        .fetch_optional(self.db_pool.pool())
+        .await?;
+
+        Ok(match counted {
+            Some(_) => None,
+            None => Some(PolicyRateLimited {
+                retry_after: limit.retry_after(now),
+                message: limit.message.clone(),
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_parameters() {
+        let id = Uuid::nil();
+        assert_eq!(
+            PolicyRateLimit::from_parameters(id, &json!({"limit": 10})),
+            Some(PolicyRateLimit {
+                policy_id: id,
+                limit: 10,
+                window: DEFAULT_WINDOW,
+                per: RateLimitSubject::Sender,
+                message: None,
+            })
+        );
+        let limit = PolicyRateLimit::from_parameters(
+            id,
+            &json!({"limit": 5, "window": 60, "per": "sender_domain", "message": "Slow down"}),
+        )
+        .unwrap();
+        assert_eq!(limit.window, Duration::from_secs(60));
+        assert_eq!(limit.per, RateLimitSubject::SenderDomain);
+        assert_eq!(limit.message.as_deref(), Some("Slow down"));
+
+        assert_eq!(
+            PolicyRateLimit::from_parameters(id, &json!({"limit": 5, "window": 10_000_000}))
+                .unwrap()
+                .window,
+            MAX_WINDOW
+        );
+        assert_eq!(PolicyRateLimit::from_parameters(id, &json!({})), None);
+        assert_eq!(
+            PolicyRateLimit::from_parameters(id, &json!({"limit": 0})),
+            None
+        );
+        assert_eq!(
+            PolicyRateLimit::from_parameters(id, &json!({"limit": 5, "per": "recipient"})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let id = Uuid::nil();
+        let context = PolicyContext::for_inbound(
+            Uuid::nil(),
+            None,
+            Some("Sender@Example.com".to_string()),
+            vec!["user@mydomain.com".to_string()],
+        );
+        let limit = |per| PolicyRateLimit {
+            policy_id: id,
+            limit: 1,
+            window: DEFAULT_WINDOW,
+            per,
+            message: None,
+        };
+        assert_eq!(
+            limit(RateLimitSubject::Sender).key(&context).as_deref(),
+            Some("00000000-0000-0000-0000-000000000000:sender:sender@example.com")
+        );
+        assert_eq!(
+            limit(RateLimitSubject::SenderDomain)
+                .key(&context)
+                .as_deref(),
+            Some("00000000-0000-0000-0000-000000000000:domain:example.com")
+        );
+        assert_eq!(limit(RateLimitSubject::ClientIp).key(&context), None);
+    }
+
+    #[test]
+    fn test_window() {
+        let limit = PolicyRateLimit::from_parameters(Uuid::nil(), &json!({"limit": 1})).unwrap();
+        let at = DateTime::parse_from_rfc3339("2024-01-28T10:15:42Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            limit.window_start(at),
+            DateTime::parse_from_rfc3339("2024-01-28T10:00:00Z").unwrap()
+        );
+        assert_eq!(limit.retry_after(at), 44 * 60 + 18);
+        assert_eq!(limit.window_type(), "3600s");
+    }
+}
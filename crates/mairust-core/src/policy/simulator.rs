@@ -0,0 +1,499 @@
+//! Policy simulator - replays stored mail through a proposed policy
+//!
+//! Before a rule is enabled, administrators can see what it would have
+//! done to recent mail. The metadata of the messages stored in the last
+//! days is evaluated twice, against the tenant's current policies and
+//! against the same policies with the proposed rule, and the messages
+//! whose outcome changes are counted and listed.
+
+use crate::policy::{PolicyContext, PolicyEngine, PolicyEvaluationResult};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{Message, PolicyConditionNode, PolicyConditionType, PolicyRule};
+use mairust_storage::{PolicyRepository, PolicyRepositoryTrait};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::info;
+use uuid::Uuid;
+
+/// Default number of days replayed
+const DEFAULT_DAYS: i64 = 7;
+
+/// Longest period that can be replayed
+const MAX_DAYS: i64 = 90;
+
+/// Most messages replayed by one simulation, the most recent first
+const MAX_MESSAGES: i64 = 10_000;
+
+/// Most affected messages listed in a report
+const MAX_SAMPLES: usize = 100;
+
+/// Tag of the copies kept by sent-mail archiving
+const SENT_ARCHIVE_TAG: &str = "sent-archive";
+
+/// What to replay
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimulationOptions {
+    /// Days of mail to replay (default 7, at most 90)
+    pub days: Option<i64>,
+    /// Existing policy the proposed rule would replace
+    pub replaces: Option<Uuid>,
+}
+
+/// What a set of policies does to a message
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SimulatedOutcome {
+    pub reject: bool,
+    pub smtp_code: Option<u16>,
+    pub quarantine: bool,
+    pub tags: Vec<String>,
+}
+
+impl From<&PolicyEvaluationResult> for SimulatedOutcome {
+    fn from(result: &PolicyEvaluationResult) -> Self {
+        Self {
+            reject: result.reject,
+            smtp_code: result.smtp_code,
+            quarantine: result.quarantine,
+            tags: result.tags.clone(),
+        }
+    }
+}
+
+/// A message whose outcome the proposed rule changes
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulatedMessage {
+    pub message_id: Uuid,
+    pub received_at: DateTime<Utc>,
+    pub from_address: Option<String>,
+    pub subject: Option<String>,
+    /// Outcome under the current policies
+    pub before: SimulatedOutcome,
+    /// Outcome with the proposed rule
+    pub after: SimulatedOutcome,
+}
+
+/// What the proposed rule would have done
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Stored messages replayed
+    pub examined: usize,
+    /// More messages were received in the period than could be replayed
+    pub truncated: bool,
+    /// Messages the proposed rule matches
+    pub matched: usize,
+    /// Messages that would be rejected and were not
+    pub rejected: usize,
+    /// Messages that would be quarantined and were not
+    pub quarantined: usize,
+    /// Messages that would get tags they did not get
+    pub tagged: usize,
+    /// Messages per added tag
+    pub tags: BTreeMap<String, usize>,
+    /// Messages the proposed rule no longer rejects (when it replaces a rule)
+    pub no_longer_rejected: usize,
+    /// Affected messages, the most recent first
+    pub samples: Vec<SimulatedMessage>,
+    /// Conditions that cannot be evaluated from stored metadata
+    pub warnings: Vec<String>,
+}
+
+/// Tallies outcomes as messages are replayed
+#[derive(Debug, Default)]
+struct Tally {
+    examined: usize,
+    matched: usize,
+    rejected: usize,
+    quarantined: usize,
+    tagged: usize,
+    tags: BTreeMap<String, usize>,
+    no_longer_rejected: usize,
+    samples: Vec<SimulatedMessage>,
+}
+
+impl Tally {
+    fn record(
+        &mut self,
+        message: &Message,
+        matched: bool,
+        before: SimulatedOutcome,
+        after: SimulatedOutcome,
+    ) {
+        self.examined += 1;
+        if matched {
+            self.matched += 1;
+        }
+        if before == after {
+            return;
+        }
+
+        if after.reject && !before.reject {
+            self.rejected += 1;
+        }
+        if before.reject && !after.reject {
+            self.no_longer_rejected += 1;
+        }
+        if after.quarantine && !before.quarantine {
+            self.quarantined += 1;
+        }
+        let added: Vec<&String> = after
+            .tags
+            .iter()
+            .filter(|tag| !before.tags.contains(tag))
+            .collect();
+        if !added.is_empty() {
+            self.tagged += 1;
+            for tag in added {
+                *self.tags.entry(tag.clone()).or_default() += 1;
+            }
+        }
+
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(SimulatedMessage {
+                message_id: message.id,
+                received_at: message.received_at,
+                from_address: message.from_address.clone(),
+                subject: message.subject.clone(),
+                before,
+                after,
+            });
+        }
+    }
+}
+
+/// Replays stored mail through proposed policies
+pub struct PolicySimulator {
+    db_pool: DatabasePool,
+    engine: PolicyEngine,
+}
+
+impl PolicySimulator {
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self {
+            engine: PolicyEngine::new(db_pool.clone()),
+            db_pool,
+        }
+    }
+
+    /// Replay a tenant's recent mail through `proposed`
+    ///
+    /// The proposed rule is evaluated as enabled, together with the
+    /// tenant's enabled tenant-wide and global policies.
+    pub async fn simulate(
+        &self,
+        tenant_id: TenantId,
+        proposed: &PolicyRule,
+        options: &SimulationOptions,
+    ) -> Result<SimulationReport> {
+        let days = options.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+        let to = Utc::now();
+        let from = to - Duration::days(days);
+
+        let current: Vec<PolicyRule> = PolicyRepository::new(self.db_pool.clone())
+            .list_effective(tenant_id, None)
+            .await?
+            .into_iter()
+            .filter(|policy| Some(policy.id) != options.replaces)
+            .collect();
+        let proposed = PolicyRule {
+            enabled: true,
+            ..proposed.clone()
+        };
+        let mut with_proposed = current.clone();
+        with_proposed.push(proposed.clone());
+
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT * FROM messages
+            WHERE tenant_id = $1 AND received_at >= $2 AND received_at < $3
+            ORDER BY received_at DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(from)
+        .bind(to)
+        .bind(MAX_MESSAGES + 1)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+        let truncated = messages.len() as i64 > MAX_MESSAGES;
+        messages.truncate(MAX_MESSAGES as usize);
+
+        let mut tally = Tally::default();
+        for message in &messages {
+            let context = replay_context(message);
+            let before = self.engine.evaluate_rules(&current, &context);
+            let after = self.engine.evaluate_rules(&with_proposed, &context);
+            let matched = after.matches.iter().any(|m| m.policy_id == proposed.id);
+            tally.record(message, matched, (&before).into(), (&after).into());
+        }
+
+        info!(
+            "Simulated policy '{}' for tenant {} over {} messages: {} matched",
+            proposed.name, tenant_id, tally.examined, tally.matched
+        );
+
+        Ok(SimulationReport {
+            from,
+            to,
+            examined: tally.examined,
+            truncated,
+            matched: tally.matched,
+            rejected: tally.rejected,
+            quarantined: tally.quarantined,
+            tagged: tally.tagged,
+            tags: tally.tags,
+            no_longer_rejected: tally.no_longer_rejected,
+            samples: tally.samples,
+            warnings: unsupported_conditions(&proposed),
+        })
+    }
+}
+
+/// Policy context of a stored message, as it was when received
+///
+/// Copies kept by sent-mail archiving are replayed as outbound mail.
+fn replay_context(message: &Message) -> PolicyContext {
+    let recipients: Vec<String> =
+        serde_json::from_value(message.to_addresses.clone()).unwrap_or_default();
+    let sender = message
+        .metadata
+        .get("envelope_from")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .or_else(|| message.from_address.clone());
+
+    let context = if message.tags_vec().iter().any(|t| t == SENT_ARCHIVE_TAG) {
+        PolicyContext::for_outbound(message.tenant_id, None, sender, recipients)
+    } else {
+        PolicyContext::for_inbound(message.tenant_id, None, sender, recipients)
+    };
+    PolicyContext {
+        current_time: message.received_at,
+        ..context
+            .with_subject(message.subject.clone())
+            .with_headers(message.headers.clone())
+            .with_message_size(message.body_size)
+            .with_spam_score(message.spam_score)
+            .with_sender_reputation(
+                message
+                    .metadata
+                    .get("sender_reputation")
+                    .and_then(|v| v.as_f64()),
+            )
+            .with_country(
+                message
+                    .metadata
+                    .get("country")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string),
+            )
+            .with_asn(
+                message
+                    .metadata
+                    .get("asn")
+                    .and_then(|v| v.as_u64())
+                    .and_then(|asn| u32::try_from(asn).ok()),
+            )
+            .with_stored_authentication(&message.metadata)
+    }
+}
+
+/// Conditions of a rule that stored messages cannot answer
+fn unsupported_conditions(policy: &PolicyRule) -> Vec<String> {
+    let conditions: Vec<PolicyConditionNode> =
+        serde_json::from_value(policy.conditions.clone()).unwrap_or_default();
+    let mut warnings = Vec::new();
+    for condition in conditions.iter().flat_map(PolicyConditionNode::conditions) {
+        let warning = match condition.condition_type {
+            PolicyConditionType::ClientIp => {
+                "client_ip is not stored with messages; the condition never matches"
+            }
+            PolicyConditionType::AttachmentType => {
+                "attachment types are not stored with messages; the condition never matches"
+            }
+            _ => continue,
+        };
+        if !warnings.iter().any(|w| w == warning) {
+            warnings.push(warning.to_string());
+        }
+    }
+    if policy.domain_id.is_some() {
+        warnings.push(
+            "the domain restriction is not simulated; all of the tenant's mail is replayed"
+                .to_string(),
+        );
+    }
+    if !policy.policy_type.eq_ignore_ascii_case("inbound") {
+        warnings.push(
+            "only outbound mail kept by sent-mail archiving can be replayed as outbound"
+                .to_string(),
+        );
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(tags: serde_json::Value) -> Message {
+        let now = Utc::now();
+        Message {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            mailbox_id: Uuid::now_v7(),
+            message_id_header: None,
+            subject: Some("Invoice".to_string()),
+            from_address: Some("Billing <billing@example.com>".to_string()),
+            to_addresses: serde_json::json!(["user@example.org"]),
+            cc_addresses: None,
+            headers: serde_json::json!({"X-Mailer": "test"}),
+            body_preview: None,
+            body_size: 2048,
+            has_attachments: false,
+            storage_path: String::new(),
+            seen: false,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: Some(3.5),
+            tags,
+            metadata: serde_json::json!({
+                "envelope_from": "bounce@example.com",
+                "sender_reputation": 42.5,
+                "country": "NL",
+                "asn": 64500,
+                "spf": "pass",
+                "dmarc": "fail",
+                "dmarc_policy": "none"
+            }),
+            received_at: now - Duration::hours(30),
+            created_at: now,
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: None,
+            snippet: None,
+        }
+    }
+
+    fn outcome(reject: bool, quarantine: bool, tags: &[&str]) -> SimulatedOutcome {
+        SimulatedOutcome {
+            reject,
+            smtp_code: reject.then_some(550),
+            quarantine,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_replay_context() {
+        let stored = message(serde_json::json!([]));
+        let context = replay_context(&stored);
+        assert_eq!(context.policy_type, "inbound");
+        assert_eq!(
+            context.sender_address.as_deref(),
+            Some("bounce@example.com")
+        );
+        assert_eq!(context.sender_domain.as_deref(), Some("example.com"));
+        assert_eq!(context.recipient_domains, vec!["example.org".to_string()]);
+        assert_eq!(context.message_size, 2048);
+        assert_eq!(context.spam_score, Some(3.5));
+        assert_eq!(context.sender_reputation, Some(42.5));
+        assert_eq!(context.country.as_deref(), Some("NL"));
+        assert_eq!(context.asn, Some(64500));
+        assert_eq!(context.spf_result.as_deref(), Some("pass"));
+        assert_eq!(context.dmarc_result.as_deref(), Some("fail"));
+        assert_eq!(context.dmarc_policy.as_deref(), Some("none"));
+        assert_eq!(context.current_time, stored.received_at);
+
+        let archived = message(serde_json::json!(["sent-archive"]));
+        assert_eq!(replay_context(&archived).policy_type, "outbound");
+    }
+
+    #[test]
+    fn test_tally() {
+        let stored = message(serde_json::json!([]));
+        let mut tally = Tally::default();
+        tally.record(
+            &stored,
+            false,
+            outcome(false, false, &[]),
+            outcome(false, false, &[]),
+        );
+        tally.record(
+            &stored,
+            true,
+            outcome(false, false, &[]),
+            outcome(true, false, &[]),
+        );
+        tally.record(
+            &stored,
+            true,
+            outcome(false, false, &["bulk"]),
+            outcome(false, true, &["bulk", "invoice"]),
+        );
+        tally.record(
+            &stored,
+            false,
+            outcome(true, false, &[]),
+            outcome(false, false, &[]),
+        );
+
+        assert_eq!(tally.examined, 4);
+        assert_eq!(tally.matched, 2);
+        assert_eq!(tally.rejected, 1);
+        assert_eq!(tally.quarantined, 1);
+        assert_eq!(tally.tagged, 1);
+        assert_eq!(tally.tags.get("invoice"), Some(&1));
+        assert!(!tally.tags.contains_key("bulk"));
+        assert_eq!(tally.no_longer_rejected, 1);
+        assert_eq!(tally.samples.len(), 3);
+    }
+
+    #[test]
+    fn test_unsupported_conditions() {
+        let now = Utc::now();
+        let policy = PolicyRule {
+            id: Uuid::now_v7(),
+            tenant_id: None,
+            domain_id: None,
+            name: "Block".to_string(),
+            description: None,
+            policy_type: "inbound".to_string(),
+            priority: 100,
+            enabled: false,
+            conditions: serde_json::json!([
+                {"condition_type": "client_ip", "operator": "in", "value": ["192.0.2.0/24"]},
+                {"condition_type": "client_ip", "operator": "eq", "value": "192.0.2.1"},
+                {"condition_type": "sender_domain", "operator": "eq", "value": "example.com"}
+            ]),
+            actions: serde_json::json!([{"action_type": "reject"}]),
+            created_at: now,
+            updated_at: now,
+        };
+        let warnings = unsupported_conditions(&policy);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("client_ip"));
+
+        let outbound = PolicyRule {
+            policy_type: "outbound".to_string(),
+            conditions: serde_json::json!([]),
+            ..policy.clone()
+        };
+        assert_eq!(unsupported_conditions(&outbound).len(), 1);
+
+        let domain = PolicyRule {
+            domain_id: Some(Uuid::now_v7()),
+            conditions: serde_json::json!([]),
+            ..policy
+        };
+        assert_eq!(unsupported_conditions(&domain).len(), 1);
+    }
+}
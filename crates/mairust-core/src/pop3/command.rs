@@ -19,6 +19,11 @@ pub enum Pop3Command {
         name: String,
         digest: String,
     },
+    /// AUTH [mechanism [initial-response]] - SASL authentication (RFC 5034)
+    Auth {
+        mechanism: Option<String>,
+        initial_response: Option<String>,
+    },
 
     // Transaction state commands
     /// STAT - Get mailbox status
@@ -98,6 +103,13 @@ impl Pop3Parser {
                     Pop3Command::Unknown { command: cmd }
                 }
             }
+            "AUTH" => {
+                let mut parts = args.split_whitespace();
+                Pop3Command::Auth {
+                    mechanism: parts.next().map(|m| m.to_uppercase()),
+                    initial_response: parts.next().map(str::to_string),
+                }
+            }
             "STAT" => Pop3Command::Stat,
             "LIST" => {
                 let msg = if args.is_empty() {
@@ -171,6 +183,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_auth() {
+        match Pop3Parser::parse("AUTH plain AGFsaWNlAHNlY3JldA==") {
+            Pop3Command::Auth {
+                mechanism,
+                initial_response,
+            } => {
+                assert_eq!(mechanism.as_deref(), Some("PLAIN"));
+                assert_eq!(initial_response.as_deref(), Some("AGFsaWNlAHNlY3JldA=="));
+            }
+            _ => panic!("Expected AUTH command"),
+        }
+
+        assert!(matches!(
+            Pop3Parser::parse("AUTH"),
+            Pop3Command::Auth {
+                mechanism: None,
+                initial_response: None
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_apop() {
+        match Pop3Parser::parse("APOP mrose c4c9334bac560ecc979e58001b3e22fb") {
+            Pop3Command::Apop { name, digest } => {
+                assert_eq!(name, "mrose");
+                assert_eq!(digest, "c4c9334bac560ecc979e58001b3e22fb");
+            }
+            _ => panic!("Expected APOP command"),
+        }
+    }
+
     #[test]
     fn test_parse_stat() {
         assert!(matches!(Pop3Parser::parse("STAT"), Pop3Command::Stat));
@@ -2,6 +2,9 @@
 //!
 //! Generates POP3 response strings for client communication.
 
+/// SASL mechanisms accepted by AUTH
+pub const SASL_MECHANISMS: [&str; 2] = ["PLAIN", "LOGIN"];
+
 /// POP3 Response builder
 pub struct Pop3Response;
 
@@ -11,6 +14,21 @@ impl Pop3Response {
         format!("+OK {} POP3 server ready\r\n", server_name)
     }
 
+    /// Server greeting with a notice for the user
+    pub fn greeting_with_notice(server_name: &str, notice: &str) -> String {
+        format!("+OK {} POP3 server ready, {}\r\n", server_name, notice)
+    }
+
+    /// Greeting line with the APOP timestamp appended (RFC 1939 section 7)
+    pub fn with_apop_timestamp(greeting: &str, timestamp: &str) -> String {
+        format!("{} {}\r\n", greeting.trim_end(), timestamp)
+    }
+
+    /// Negative response for a change refused during maintenance (RFC 3206)
+    pub fn maintenance(message: &str) -> String {
+        Self::err(&format!("[SYS/TEMP] {}", message))
+    }
+
     /// Positive response
     pub fn ok(message: &str) -> String {
         format!("+OK {}\r\n", message)
@@ -78,12 +96,18 @@ impl Pop3Response {
 
     /// CAPA response with optional STLS extension
     pub fn capabilities_with_starttls(starttls_enabled: bool) -> String {
-        let mut lines = vec![
-            "+OK Capability list follows".to_string(),
-            "USER".to_string(),
-            "TOP".to_string(),
-            "UIDL".to_string(),
-        ];
+        Self::capability_list(starttls_enabled, true)
+    }
+
+    /// CAPA response; USER and SASL are left out while plaintext logins
+    /// are refused (RFC 2595 section 4)
+    pub fn capability_list(starttls_enabled: bool, user_enabled: bool) -> String {
+        let mut lines = vec!["+OK Capability list follows".to_string()];
+        if user_enabled {
+            lines.push("USER".to_string());
+            lines.push(format!("SASL {}", SASL_MECHANISMS.join(" ")));
+        }
+        lines.extend(["TOP", "UIDL", "RESP-CODES"].map(String::from));
         if starttls_enabled {
             lines.push("STLS".to_string());
         }
@@ -92,6 +116,17 @@ impl Pop3Response {
         format!("{}\r\n", lines.join("\r\n"))
     }
 
+    /// AUTH response without a mechanism: the supported mechanisms
+    pub fn sasl_mechanisms() -> String {
+        let mut response = "+OK Supported mechanisms follow\r\n".to_string();
+        for mechanism in SASL_MECHANISMS {
+            response.push_str(mechanism);
+            response.push_str("\r\n");
+        }
+        response.push_str(&Self::terminator());
+        response
+    }
+
     /// Multi-line terminator
     pub fn terminator() -> String {
         ".\r\n".to_string()
@@ -123,6 +158,45 @@ mod tests {
         assert_eq!(Pop3Response::ok("Success"), "+OK Success\r\n");
     }
 
+    #[test]
+    fn test_maintenance() {
+        assert_eq!(
+            Pop3Response::maintenance("Storage migration"),
+            "-ERR [SYS/TEMP] Storage migration\r\n"
+        );
+        assert!(Pop3Response::capabilities().contains("RESP-CODES\r\n"));
+    }
+
+    #[test]
+    fn test_capability_list() {
+        let plaintext = Pop3Response::capability_list(true, false);
+        assert!(plaintext.contains("\r\nSTLS\r\n"));
+        assert!(!plaintext.contains("USER"));
+        assert!(!plaintext.contains("SASL"));
+        assert!(plaintext.ends_with(".\r\n"));
+
+        let tls = Pop3Response::capabilities_with_starttls(false);
+        assert!(tls.contains("\r\nUSER\r\n"));
+        assert!(tls.contains("\r\nSASL PLAIN LOGIN\r\n"));
+        assert!(!tls.contains("STLS"));
+
+        assert_eq!(
+            Pop3Response::sasl_mechanisms(),
+            "+OK Supported mechanisms follow\r\nPLAIN\r\nLOGIN\r\n.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_apop_greeting() {
+        assert_eq!(
+            Pop3Response::with_apop_timestamp(
+                &Pop3Response::greeting("MaiRust"),
+                "<1896.697170952@dbc.mtview.ca.us>"
+            ),
+            "+OK MaiRust POP3 server ready <1896.697170952@dbc.mtview.ca.us>\r\n"
+        );
+    }
+
     #[test]
     fn test_err() {
         assert_eq!(Pop3Response::err("Failed"), "-ERR Failed\r\n");
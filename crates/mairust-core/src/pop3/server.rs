@@ -5,24 +5,38 @@
 use super::command::{Pop3Command, Pop3Parser};
 use super::response::Pop3Response;
 use super::session::{MessageInfo, Pop3Session, SessionState};
+use crate::imap::sasl::{self, SaslCredentials, SaslMechanism};
+use crate::limits::ConnectionLimiter;
+use crate::maintenance::MaintenanceMode;
+use crate::proxy_protocol::TrustedProxies;
+use crate::security::SecurityNotifier;
+use crate::services::ServicePolicy;
+use crate::sessions::{ActiveSession, SessionProtocol, SessionRegistry};
+use crate::smtp::SmtpAuthenticator;
 
 use anyhow::{anyhow, Result};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use mairust_common::config::TlsConfig;
+use mairust_common::config::{ConnectionLimitConfig, TlsConfig};
+use mairust_common::types::MailService;
 use mairust_storage::db::DatabasePool;
 use mairust_storage::models::Message;
-use mairust_storage::{FileStorage, LocalStorage};
+use mairust_storage::repository::users::{DbUserRepository, UserRepository};
+use mairust_storage::{FileStorage, LocalStorage, Residency};
+use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Timeout for the TLS handshake on the POP3S listener
+const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 30;
+
 /// POP3 server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pop3Config {
@@ -32,6 +46,15 @@ pub struct Pop3Config {
     /// Enable STARTTLS
     #[serde(default)]
     pub starttls: bool,
+    /// Implicit TLS (POP3S) listen address and port
+    #[serde(default)]
+    pub tls_bind: Option<String>,
+    /// Refuse USER/PASS and AUTH on connections that are not encrypted
+    #[serde(default = "default_require_tls_for_auth")]
+    pub require_tls_for_auth: bool,
+    /// Offer APOP to users with an APOP secret
+    #[serde(default)]
+    pub apop: bool,
     /// Session timeout in minutes
     #[serde(default = "default_timeout")]
     pub timeout_minutes: i64,
@@ -44,6 +67,29 @@ pub struct Pop3Config {
     /// Storage path for message files
     #[serde(default = "default_storage_path")]
     pub storage_path: PathBuf,
+    /// Maintenance windows during which deletions are refused
+    #[serde(skip)]
+    pub maintenance: MaintenanceMode,
+    /// Connection limits (set at startup; created from `max_connections`
+    /// with the default per-IP limits when unset)
+    #[serde(skip)]
+    pub limiter: Option<ConnectionLimiter>,
+    /// Records blocked logins and logins from new networks (set at startup)
+    #[serde(skip)]
+    pub security: Option<SecurityNotifier>,
+    /// Load balancers whose PROXY header gives the client address (set at
+    /// startup for `proxy_protocol` listeners)
+    #[serde(skip)]
+    pub proxy: Option<TrustedProxies>,
+    /// POP3 access by plan, tenant and user (set at startup)
+    #[serde(skip)]
+    pub services: ServicePolicy,
+    /// Regions tenant data is pinned to (set at startup)
+    #[serde(skip)]
+    pub residency: Option<Arc<Residency>>,
+    /// Live sessions listed to administrators (set at startup)
+    #[serde(skip)]
+    pub sessions: SessionRegistry,
 }
 
 fn default_storage_path() -> PathBuf {
@@ -54,6 +100,10 @@ fn default_bind() -> String {
     "0.0.0.0:110".to_string()
 }
 
+fn default_require_tls_for_auth() -> bool {
+    true
+}
+
 fn default_timeout() -> i64 {
     10
 }
@@ -71,10 +121,20 @@ impl Default for Pop3Config {
         Self {
             bind: default_bind(),
             starttls: false,
+            tls_bind: None,
+            require_tls_for_auth: default_require_tls_for_auth(),
+            apop: false,
             timeout_minutes: default_timeout(),
             max_connections: default_max_connections(),
             server_name: default_server_name(),
             storage_path: default_storage_path(),
+            maintenance: MaintenanceMode::default(),
+            limiter: None,
+            security: None,
+            proxy: None,
+            services: ServicePolicy::default(),
+            residency: None,
+            sessions: SessionRegistry::default(),
         }
     }
 }
@@ -90,7 +150,7 @@ impl Pop3Server {
     /// Create a new POP3 server
     pub fn new(config: Pop3Config, db_pool: DatabasePool) -> Self {
         Self {
-            config,
+            config: Self::with_limiter(config),
             db_pool,
             tls_acceptor: None,
         }
@@ -102,19 +162,30 @@ impl Pop3Server {
                 |tls_config| match crate::smtp::create_tls_acceptor(tls_config) {
                     Ok(acceptor) => Some(Arc::new(acceptor)),
                     Err(e) => {
-                        warn!("Failed to initialize POP3 STLS acceptor: {}", e);
+                        warn!("Failed to initialize POP3 TLS acceptor: {}", e);
                         None
                     }
                 },
             );
 
         Self {
-            config,
+            config: Self::with_limiter(config),
             db_pool,
             tls_acceptor,
         }
     }
 
+    /// Give the configuration a limiter of its own unless one is shared
+    fn with_limiter(mut config: Pop3Config) -> Pop3Config {
+        if config.limiter.is_none() {
+            config.limiter = Some(ConnectionLimiter::new(
+                config.max_connections,
+                ConnectionLimitConfig::default(),
+            ));
+        }
+        config
+    }
+
     /// Start the POP3 server
     pub async fn run(&self) -> Result<()> {
         let listener = TcpListener::bind(&self.config.bind).await?;
@@ -128,6 +199,56 @@ impl Pop3Server {
             self.config.bind, tls_status
         );
 
+        let tls_listener = match (&self.config.tls_bind, &self.tls_acceptor) {
+            (Some(bind), Some(_)) => {
+                let listener = TcpListener::bind(bind).await?;
+                info!("POP3S server listening on {} (implicit TLS)", bind);
+                Some(listener)
+            }
+            (Some(bind), None) => {
+                warn!("POP3S listener on {} disabled: TLS is not configured", bind);
+                None
+            }
+            (None, _) => None,
+        };
+
+        match tls_listener {
+            Some(tls_listener) => {
+                tokio::try_join!(
+                    self.accept_loop(listener, false),
+                    self.accept_loop(tls_listener, true)
+                )?;
+            }
+            None => self.accept_loop(listener, false).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Start a single listener: plaintext (with STLS when enabled) or
+    /// implicit TLS
+    pub async fn run_listener(&self, bind: &str, implicit_tls: bool) -> Result<()> {
+        if implicit_tls && self.tls_acceptor.is_none() {
+            return Err(anyhow!(
+                "POP3S listener on {} requires TLS to be configured",
+                bind
+            ));
+        }
+
+        let listener = TcpListener::bind(bind).await?;
+        if implicit_tls {
+            info!("POP3S server listening on {} (implicit TLS)", bind);
+        } else if self.config.starttls && self.tls_acceptor.is_some() {
+            info!("POP3 server listening on {} (STLS enabled)", bind);
+        } else {
+            info!("POP3 server listening on {} (STLS disabled)", bind);
+        }
+
+        self.accept_loop(listener, implicit_tls).await
+    }
+
+    /// Accept connections on a listener
+    async fn accept_loop(&self, listener: TcpListener, implicit_tls: bool) -> Result<()> {
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
@@ -136,11 +257,71 @@ impl Pop3Server {
                     let tls_acceptor = self.tls_acceptor.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) =
-                            Self::handle_connection(stream, addr, db_pool, config, tls_acceptor)
-                                .await
-                        {
-                            error!("POP3 connection error from {}: {}", addr, e);
+                        let mut stream = stream;
+                        // Behind a load balancer the PROXY header names the client
+                        let addr = match &config.proxy {
+                            Some(proxy) => match proxy.client_addr(&mut stream, addr).await {
+                                Ok(client_addr) => client_addr,
+                                Err(e) => {
+                                    warn!("Refusing POP3 connection: {}", e);
+                                    return;
+                                }
+                            },
+                            None => addr,
+                        };
+
+                        let limiter = config.limiter.as_ref();
+                        let _permit = match limiter.map(|l| l.try_acquire(addr.ip())).transpose() {
+                            Ok(permit) => permit,
+                            Err(refusal) => {
+                                warn!(
+                                    "Refusing POP3 connection from {}: {}",
+                                    addr,
+                                    refusal.reason()
+                                );
+                                if !implicit_tls {
+                                    // A new connection's send buffer has room for the line
+                                    let err = Pop3Response::err(&format!(
+                                        "[SYS/TEMP] {}",
+                                        refusal.reason()
+                                    ));
+                                    let _ = stream.try_write(err.as_bytes());
+                                }
+                                return;
+                            }
+                        };
+
+                        let registration = config.sessions.register(SessionProtocol::Pop3, addr);
+                        let live = registration.session().clone();
+                        let result = live
+                            .run(async {
+                                if implicit_tls {
+                                    Self::handle_implicit_tls_connection(
+                                        stream,
+                                        addr,
+                                        db_pool,
+                                        config,
+                                        tls_acceptor,
+                                        live.clone(),
+                                    )
+                                    .await
+                                } else {
+                                    Self::handle_connection(
+                                        stream,
+                                        addr,
+                                        db_pool,
+                                        config,
+                                        tls_acceptor,
+                                        live.clone(),
+                                    )
+                                    .await
+                                }
+                            })
+                            .await;
+                        match result {
+                            Some(Err(e)) => error!("POP3 connection error from {}: {}", addr, e),
+                            Some(Ok(())) => {}
+                            None => info!("Terminated POP3 session from {}", addr),
                         }
                     });
                 }
@@ -151,6 +332,61 @@ impl Pop3Server {
         }
     }
 
+    /// Handle a connection on the implicit TLS (POP3S) listener
+    async fn handle_implicit_tls_connection(
+        stream: TcpStream,
+        addr: SocketAddr,
+        db_pool: DatabasePool,
+        config: Pop3Config,
+        tls_acceptor: Option<Arc<TlsAcceptor>>,
+        live: Arc<ActiveSession>,
+    ) -> Result<()> {
+        let acceptor =
+            tls_acceptor.ok_or_else(|| anyhow!("POP3S connection without configured acceptor"))?;
+
+        info!("New POP3S connection from {}", addr);
+        let tls_stream = tokio::time::timeout(
+            std::time::Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS),
+            acceptor.accept(stream),
+        )
+        .await
+        .map_err(|_| anyhow!("TLS handshake timed out for {}", addr))??;
+
+        let session = Arc::new(Mutex::new(Pop3Session::new()));
+        let mut tls_stream = live.meter(tls_stream);
+        tls_stream
+            .write_all(Self::greeting(&config, &session).await.as_bytes())
+            .await?;
+        tls_stream.flush().await?;
+
+        Self::handle_tls_connection(
+            tls_stream.into_inner(),
+            addr,
+            db_pool,
+            config,
+            session,
+            live,
+        )
+        .await
+    }
+
+    /// Greeting, announcing maintenance in its text and ending with the
+    /// APOP timestamp when APOP is offered
+    async fn greeting(config: &Pop3Config, session: &Arc<Mutex<Pop3Session>>) -> String {
+        let greeting = match config.maintenance.status().await {
+            Some(status) => Pop3Response::greeting_with_notice(
+                &config.server_name,
+                &format!("{} (read-only, deletions are refused)", status.message),
+            ),
+            None => Pop3Response::greeting(&config.server_name),
+        };
+        if !config.apop {
+            return greeting;
+        }
+        let timestamp = session.lock().await.offer_apop(&config.server_name);
+        Pop3Response::with_apop_timestamp(&greeting, &timestamp)
+    }
+
     /// Handle a single POP3 connection
     async fn handle_connection(
         stream: TcpStream,
@@ -158,19 +394,20 @@ impl Pop3Server {
         db_pool: DatabasePool,
         config: Pop3Config,
         tls_acceptor: Option<Arc<TlsAcceptor>>,
+        live: Arc<ActiveSession>,
     ) -> Result<()> {
         info!("New POP3 connection from {}", addr);
 
         let (reader, writer) = stream.into_split();
-        let mut reader = BufReader::new(reader);
-        let writer = Arc::new(Mutex::new(writer));
+        let mut reader = BufReader::new(live.meter(reader));
+        let writer = Arc::new(Mutex::new(live.meter(writer)));
         let session = Arc::new(Mutex::new(Pop3Session::new()));
 
         // Send greeting
         {
+            let greeting = Self::greeting(&config, &session).await;
             let mut w = writer.lock().await;
-            w.write_all(Pop3Response::greeting(&config.server_name).as_bytes())
-                .await?;
+            w.write_all(greeting.as_bytes()).await?;
             w.flush().await?;
         }
 
@@ -197,8 +434,28 @@ impl Pop3Server {
 
                     // Parse and handle command
                     let cmd = Pop3Parser::parse(&line);
+                    // A login refused for lack of TLS is not a failed login
+                    let is_login = match cmd {
+                        Pop3Command::Apop { .. } => true,
+                        Pop3Command::Pass { .. }
+                        | Pop3Command::Auth {
+                            mechanism: Some(_), ..
+                        } => !config.require_tls_for_auth,
+                        _ => false,
+                    };
                     let mut upgrade_tls = false;
                     let response = match cmd {
+                        Pop3Command::User { .. }
+                        | Pop3Command::Pass { .. }
+                        | Pop3Command::Auth { .. }
+                            if config.require_tls_for_auth =>
+                        {
+                            Pop3Response::err(if config.starttls && tls_acceptor.is_some() {
+                                "Plaintext authentication disabled, use STLS first"
+                            } else {
+                                "Plaintext authentication disabled, use POP3S"
+                            })
+                        }
                         Pop3Command::Stls => {
                             let sess = session.lock().await;
                             if !sess.is_authorization() {
@@ -210,17 +467,28 @@ impl Pop3Server {
                                 Pop3Response::ok("Begin TLS negotiation")
                             }
                         }
-                        Pop3Command::Capa => Pop3Response::capabilities_with_starttls(
+                        Pop3Command::Capa => Pop3Response::capability_list(
                             config.starttls && tls_acceptor.is_some(),
+                            !config.require_tls_for_auth,
                         ),
-                        other => {
-                            let (resp, should_quit) = Self::handle_command(
-                                other,
+                        Pop3Command::Auth {
+                            mechanism,
+                            initial_response,
+                        } => {
+                            Self::handle_auth(
+                                mechanism,
+                                initial_response,
+                                &mut reader,
+                                &writer,
                                 &session,
                                 &db_pool,
-                                &config.storage_path,
+                                &config,
                             )
-                            .await;
+                            .await?
+                        }
+                        other => {
+                            let (resp, should_quit) =
+                                Self::handle_command(other, &session, &db_pool, &config).await;
                             if should_quit {
                                 let mut w = writer.lock().await;
                                 w.write_all(resp.as_bytes()).await?;
@@ -237,14 +505,25 @@ impl Pop3Server {
                         w.write_all(response.as_bytes()).await?;
                         w.flush().await?;
                     }
+                    Self::report_session(&session, &live).await;
+
+                    if is_login
+                        && Self::check_login(&response, &session, &db_pool, &config, addr).await
+                    {
+                        let mut w = writer.lock().await;
+                        w.write_all(Self::banned_response().as_bytes()).await?;
+                        w.flush().await?;
+                        break;
+                    }
 
                     if upgrade_tls {
                         let acceptor = tls_acceptor.clone().ok_or_else(|| {
                             anyhow!("POP3 STLS requested without configured acceptor")
                         })?;
-                        let read_half = reader.into_inner();
+                        let read_half = reader.into_inner().into_inner();
                         let writer_half = Arc::try_unwrap(writer)
                             .map_err(|_| anyhow!("Failed to unwrap POP3 writer during STLS"))?
+                            .into_inner()
                             .into_inner();
                         let tcp_stream = read_half.reunite(writer_half).map_err(|_| {
                             anyhow!("Failed to reunite POP3 stream halves during STLS")
@@ -252,7 +531,7 @@ impl Pop3Server {
                         let tls_stream = acceptor.accept(tcp_stream).await?;
                         info!("POP3 STLS negotiation completed for {}", addr);
                         return Self::handle_tls_connection(
-                            tls_stream, addr, db_pool, config, session,
+                            tls_stream, addr, db_pool, config, session, live,
                         )
                         .await;
                     }
@@ -282,8 +561,9 @@ impl Pop3Server {
         db_pool: DatabasePool,
         config: Pop3Config,
         session: Arc<Mutex<Pop3Session>>,
+        live: Arc<ActiveSession>,
     ) -> Result<()> {
-        let (reader, writer) = tokio::io::split(tls_stream);
+        let (reader, writer) = tokio::io::split(live.meter(tls_stream));
         let mut reader = BufReader::new(reader);
         let writer = Arc::new(Mutex::new(writer));
         let mut line = String::new();
@@ -300,17 +580,36 @@ impl Pop3Server {
                 Ok(Ok(0)) => break,
                 Ok(Ok(_)) => {
                     let cmd = Pop3Parser::parse(&line);
+                    let is_login = matches!(
+                        cmd,
+                        Pop3Command::Pass { .. }
+                            | Pop3Command::Apop { .. }
+                            | Pop3Command::Auth {
+                                mechanism: Some(_),
+                                ..
+                            }
+                    );
                     let response = match cmd {
                         Pop3Command::Stls => Pop3Response::err("TLS already active"),
                         Pop3Command::Capa => Pop3Response::capabilities_with_starttls(false),
-                        other => {
-                            let (resp, should_quit) = Self::handle_command(
-                                other,
+                        Pop3Command::Auth {
+                            mechanism,
+                            initial_response,
+                        } => {
+                            Self::handle_auth(
+                                mechanism,
+                                initial_response,
+                                &mut reader,
+                                &writer,
                                 &session,
                                 &db_pool,
-                                &config.storage_path,
+                                &config,
                             )
-                            .await;
+                            .await?
+                        }
+                        other => {
+                            let (resp, should_quit) =
+                                Self::handle_command(other, &session, &db_pool, &config).await;
                             if should_quit {
                                 let mut w = writer.lock().await;
                                 w.write_all(resp.as_bytes()).await?;
@@ -324,6 +623,15 @@ impl Pop3Server {
                     let mut w = writer.lock().await;
                     w.write_all(response.as_bytes()).await?;
                     w.flush().await?;
+                    Self::report_session(&session, &live).await;
+
+                    if is_login
+                        && Self::check_login(&response, &session, &db_pool, &config, addr).await
+                    {
+                        w.write_all(Self::banned_response().as_bytes()).await?;
+                        w.flush().await?;
+                        break;
+                    }
                 }
                 Ok(Err(e)) => {
                     error!("POP3 TLS read error from {}: {}", addr, e);
@@ -343,12 +651,60 @@ impl Pop3Server {
         Ok(())
     }
 
+    /// Show the session's state and user in the session registry
+    async fn report_session(session: &Arc<Mutex<Pop3Session>>, live: &ActiveSession) {
+        let session = session.lock().await;
+        live.set_state(session.state.as_str());
+        if let (Some(username), Some(_)) = (&session.username, session.user_id) {
+            live.set_user(username, session.tenant_id);
+        }
+    }
+
+    /// Report the outcome of a login to the security notifier and count a
+    /// failure towards the per-IP ban; true when the address is now banned
+    /// and the connection has to be closed
+    async fn check_login(
+        response: &str,
+        session: &Arc<Mutex<Pop3Session>>,
+        db_pool: &DatabasePool,
+        config: &Pop3Config,
+        addr: SocketAddr,
+    ) -> bool {
+        let sess = session.lock().await;
+        if !sess.is_authorization() {
+            if let (true, Some(security), Some(user_id), Some(tenant_id)) = (
+                response.starts_with("+OK"),
+                &config.security,
+                sess.user_id,
+                sess.tenant_id,
+            ) {
+                security.login_succeeded(db_pool, user_id, tenant_id, addr.ip(), "pop3");
+            }
+            return false;
+        }
+
+        let Some(limiter) = &config.limiter else {
+            return false;
+        };
+        if !response.starts_with("-ERR") || !limiter.auth_failed(addr.ip()) {
+            return false;
+        }
+        if let (Some(security), Some(username)) = (&config.security, &sess.username) {
+            security.login_blocked(db_pool, username, addr.ip(), "pop3");
+        }
+        true
+    }
+
+    fn banned_response() -> String {
+        Pop3Response::err("[SYS/TEMP] Too many failed logins, try again later")
+    }
+
     /// Handle a parsed POP3 command
     async fn handle_command(
         cmd: Pop3Command,
         session: &Arc<Mutex<Pop3Session>>,
         db_pool: &DatabasePool,
-        storage_path: &PathBuf,
+        config: &Pop3Config,
     ) -> (String, bool) {
         match cmd {
             // Authorization state commands
@@ -361,11 +717,17 @@ impl Pop3Server {
                 (Pop3Response::ok("Send password"), false)
             }
 
-            Pop3Command::Pass { password } => Self::handle_pass(&password, session, db_pool).await,
+            Pop3Command::Pass { password } => {
+                Self::handle_pass(&password, session, db_pool, config).await
+            }
 
             Pop3Command::Apop { name, digest } => {
-                // APOP not implemented yet
-                (Pop3Response::err("APOP not supported"), false)
+                Self::handle_apop(&name, &digest, session, db_pool, config).await
+            }
+
+            Pop3Command::Auth { .. } => {
+                // The SASL exchange needs the connection and is handled by the connection loop
+                (Pop3Response::err("AUTH not available"), false)
             }
 
             // Transaction state commands
@@ -382,13 +744,18 @@ impl Pop3Server {
 
             Pop3Command::List { msg } => Self::handle_list(msg, session).await,
 
-            Pop3Command::Retr { msg } => Self::handle_retr(msg, session, storage_path).await,
+            Pop3Command::Retr { msg } => {
+                Self::handle_retr(msg, session, &config.storage_path).await
+            }
 
             Pop3Command::Dele { msg } => {
                 let mut sess = session.lock().await;
                 if !sess.is_transaction() {
                     return (Pop3Response::err("Not authenticated"), false);
                 }
+                if let Some(status) = config.maintenance.status().await {
+                    return (Pop3Response::maintenance(&status.message), false);
+                }
                 if sess.mark_deleted(msg) {
                     (Pop3Response::ok(&format!("Message {} deleted", msg)), false)
                 } else {
@@ -417,13 +784,13 @@ impl Pop3Server {
             }
 
             Pop3Command::Top { msg, lines } => {
-                Self::handle_top(msg, lines, session, storage_path).await
+                Self::handle_top(msg, lines, session, &config.storage_path).await
             }
 
             Pop3Command::Uidl { msg } => Self::handle_uidl(msg, session).await,
 
             // Any state commands
-            Pop3Command::Quit => Self::handle_quit(session, db_pool).await,
+            Pop3Command::Quit => Self::handle_quit(session, db_pool, &config.maintenance).await,
 
             Pop3Command::Capa => (Pop3Response::capabilities(), false),
 
@@ -441,6 +808,7 @@ impl Pop3Server {
         password: &str,
         session: &Arc<Mutex<Pop3Session>>,
         db_pool: &DatabasePool,
+        config: &Pop3Config,
     ) -> (String, bool) {
         let mut sess = session.lock().await;
 
@@ -458,14 +826,19 @@ impl Pop3Server {
         let pool = db_pool.pool();
 
         // Query user by email
-        let user: Option<(Uuid, Uuid, String, bool)> = sqlx::query_as(
+        let user: Option<(Uuid, Uuid, String, bool)> = match sqlx::query_as(
             "SELECT id, tenant_id, password_hash, active FROM users WHERE email = $1",
         )
         .bind(&username)
         .fetch_optional(pool)
         .await
-        .ok()
-        .flatten();
+        {
+            Ok(user) => user,
+            Err(e) => {
+                error!("POP3 PASS: Database error: {}", e);
+                return (failure(e, "Temporary authentication error"), false);
+            }
+        };
 
         match user {
             Some((user_id, tenant_id, password_hash, active)) => {
@@ -486,59 +859,269 @@ impl Pop3Server {
                     return (Pop3Response::err("Invalid password"), false);
                 }
 
-                // Get user's primary mailbox
-                let mailbox: Option<(Uuid,)> = sqlx::query_as(
-                    "SELECT id FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 LIMIT 1",
+                (
+                    Self::open_maildrop(user_id, tenant_id, &username, session, db_pool, config)
+                        .await,
+                    false,
                 )
-                .bind(tenant_id)
-                .bind(user_id)
-                .fetch_optional(pool)
-                .await
-                .ok()
-                .flatten();
-
-                let mailbox_id = match mailbox {
-                    Some((id,)) => id,
-                    None => return (Pop3Response::err("No mailbox"), false),
-                };
+            }
+            None => (Pop3Response::err("Invalid user"), false),
+        }
+    }
 
-                // Load messages
-                let messages: Vec<Message> = sqlx::query_as(
-                    "SELECT * FROM messages WHERE mailbox_id = $1 AND deleted = false ORDER BY received_at ASC",
-                )
-                .bind(mailbox_id)
-                .fetch_all(pool)
-                .await
-                .unwrap_or_default();
-
-                let message_infos: Vec<MessageInfo> = messages
-                    .iter()
-                    .map(|m| MessageInfo {
-                        id: m.id,
-                        size: m.body_size as u64,
-                        uid: m.id.to_string(),
-                        body_preview: m.body_preview.clone(),
-                        storage_path: m.storage_path.clone(),
-                    })
-                    .collect();
-
-                let count = message_infos.len();
+    /// Handle APOP command (RFC 1939 section 7)
+    async fn handle_apop(
+        name: &str,
+        digest: &str,
+        session: &Arc<Mutex<Pop3Session>>,
+        db_pool: &DatabasePool,
+        config: &Pop3Config,
+    ) -> (String, bool) {
+        let mut sess = session.lock().await;
+        if !sess.is_authorization() {
+            return (Pop3Response::err("Already authenticated"), false);
+        }
+        let Some(timestamp) = sess.apop_timestamp.clone() else {
+            return (Pop3Response::err("APOP not supported"), false);
+        };
+        sess.set_username(name.to_string());
+        drop(sess);
 
-                let mut sess = session.lock().await;
-                sess.authenticate(user_id, tenant_id, mailbox_id);
-                sess.load_messages(message_infos);
+        let repo = DbUserRepository::new(db_pool.clone());
+        let user = match repo.get_by_email(name).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return (Pop3Response::err("[AUTH] Authentication failed"), false),
+            Err(e) => {
+                warn!("POP3 APOP: Database error: {}", e);
+                return (failure(e, "Temporary authentication error"), false);
+            }
+        };
 
-                info!("POP3 user {} authenticated, {} messages", username, count);
+        // Users without a secret cannot use APOP
+        let secret = match repo.apop_secret(user.id).await {
+            Ok(secret) => secret,
+            Err(e) => {
+                warn!("POP3 APOP: Database error: {}", e);
+                return (failure(e, "Temporary authentication error"), false);
+            }
+        };
+        if !secret.is_some_and(|secret| apop_matches(&timestamp, &secret, digest)) {
+            debug!("POP3 APOP: Digest mismatch for {}", name);
+            return (Pop3Response::err("[AUTH] Authentication failed"), false);
+        }
 
-                (
-                    Pop3Response::ok(&format!("Maildrop has {} messages", count)),
-                    false,
-                )
+        (
+            Self::open_maildrop(
+                user.id,
+                user.tenant_id,
+                &user.email,
+                session,
+                db_pool,
+                config,
+            )
+            .await,
+            false,
+        )
+    }
+
+    /// Handle AUTH, running the SASL exchange (RFC 5034)
+    ///
+    /// Credentials are checked like SMTP AUTH; bearer token mechanisms are
+    /// only offered by IMAP.
+    async fn handle_auth<R, W>(
+        mechanism: Option<String>,
+        initial_response: Option<String>,
+        reader: &mut R,
+        writer: &Arc<Mutex<W>>,
+        session: &Arc<Mutex<Pop3Session>>,
+        db_pool: &DatabasePool,
+        config: &Pop3Config,
+    ) -> Result<String>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        if !session.lock().await.is_authorization() {
+            return Ok(Pop3Response::err("Already authenticated"));
+        }
+        let Some(mechanism) = mechanism else {
+            return Ok(Pop3Response::sasl_mechanisms());
+        };
+        let mechanism = match SaslMechanism::parse(&mechanism) {
+            Some(m) if !m.is_bearer() => m,
+            _ => return Ok(Pop3Response::err("Unsupported authentication mechanism")),
+        };
+        let timeout = std::time::Duration::from_secs((config.timeout_minutes * 60) as u64);
+
+        // The first response is either sent inline or after an initial challenge
+        let first = match initial_response {
+            Some(response) => response,
+            None => {
+                let prompt = if mechanism == SaslMechanism::Login {
+                    sasl::LOGIN_USERNAME_CHALLENGE
+                } else {
+                    ""
+                };
+                match sasl::exchange(reader, writer, &sasl::challenge(prompt), timeout).await? {
+                    Some(response) => response,
+                    None => return Ok(Pop3Response::err("AUTH cancelled")),
+                }
             }
-            None => (Pop3Response::err("Invalid user"), false),
+        };
+        let Some(data) = sasl::decode_response(&first) else {
+            return Ok(Pop3Response::err("Invalid base64 in SASL response"));
+        };
+
+        let credentials = match mechanism {
+            SaslMechanism::Login => {
+                let challenge = sasl::challenge(sasl::LOGIN_PASSWORD_CHALLENGE);
+                let password = match sasl::exchange(reader, writer, &challenge, timeout).await? {
+                    Some(response) => {
+                        sasl::decode_response(&response).and_then(|p| String::from_utf8(p).ok())
+                    }
+                    None => return Ok(Pop3Response::err("AUTH cancelled")),
+                };
+                String::from_utf8(data)
+                    .ok()
+                    .filter(|u| !u.is_empty())
+                    .zip(password)
+                    .map(|(username, password)| SaslCredentials::Password { username, password })
+            }
+            _ => sasl::parse_plain(&data),
+        };
+        let Some(SaslCredentials::Password { username, password }) = credentials else {
+            return Ok(Pop3Response::err("Malformed SASL response"));
+        };
+        session.lock().await.set_username(username.clone());
+
+        let result = SmtpAuthenticator::new(db_pool.clone())
+            .verify_credentials(&username, &password)
+            .await;
+        match result.user {
+            Some(user) if result.success => Ok(Self::open_maildrop(
+                user.id,
+                user.tenant_id,
+                &user.email,
+                session,
+                db_pool,
+                config,
+            )
+            .await),
+            _ => Ok(Pop3Response::err(&format!(
+                "[AUTH] {}",
+                result.error.as_deref().unwrap_or("Authentication failed")
+            ))),
         }
     }
 
+    /// Open the user's maildrop after a successful login, unless POP3 is
+    /// switched off for them or their tenant
+    async fn open_maildrop(
+        user_id: Uuid,
+        tenant_id: Uuid,
+        username: &str,
+        session: &Arc<Mutex<Pop3Session>>,
+        db_pool: &DatabasePool,
+        config: &Pop3Config,
+    ) -> String {
+        match config
+            .services
+            .check(db_pool, tenant_id, user_id, MailService::Pop3)
+            .await
+        {
+            Ok(None) => {}
+            Ok(Some(refusal)) => {
+                info!("POP3 login of {} refused: {:?}", username, refusal);
+                return Pop3Response::err(&format!(
+                    "[SYS/PERM] {}",
+                    refusal.message(MailService::Pop3)
+                ));
+            }
+            Err(e) => {
+                error!("Failed to check POP3 access of {}: {}", username, e);
+                return failure(e, "Temporary authentication error");
+            }
+        }
+
+        // Tenants pinned to another region are served by its servers
+        let storage_path = match &config.residency {
+            Some(residency) => match residency.storage_for(tenant_id).await {
+                Ok(storage) => storage.map(|storage| storage.path.clone()),
+                Err(e @ mairust_common::Error::Residency(_)) => {
+                    info!("POP3 login of {} refused: {}", username, e);
+                    return Pop3Response::err(&format!("[SYS/PERM] {}", e.public_message()));
+                }
+                Err(e) => {
+                    error!("Failed to check the data region of {}: {}", username, e);
+                    return failure(e, "Temporary authentication error");
+                }
+            },
+            None => None,
+        };
+
+        let pool = db_pool.pool();
+
+        // Get user's primary mailbox
+        let mailbox: Option<(Uuid,)> = match sqlx::query_as(
+            "SELECT id FROM mailboxes
+             WHERE tenant_id = $1 AND user_id = $2 AND quarantine_of IS NULL LIMIT 1",
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        {
+            Ok(mailbox) => mailbox,
+            Err(e) => {
+                error!("Failed to find the maildrop of {}: {}", username, e);
+                return failure(e, "Failed to open maildrop");
+            }
+        };
+
+        let mailbox_id = match mailbox {
+            Some((id,)) => id,
+            None => return Pop3Response::err("No mailbox"),
+        };
+
+        // Load messages; an empty listing on a database error would look
+        // like an empty maildrop
+        let messages: Vec<Message> = match sqlx::query_as(
+            "SELECT * FROM messages WHERE mailbox_id = $1 AND deleted = false ORDER BY received_at ASC",
+        )
+        .bind(mailbox_id)
+        .fetch_all(pool)
+        .await
+        {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("Failed to load the maildrop of {}: {}", username, e);
+                return failure(e, "Failed to open maildrop");
+            }
+        };
+
+        let message_infos: Vec<MessageInfo> = messages
+            .iter()
+            .map(|m| MessageInfo {
+                id: m.id,
+                size: m.body_size as u64,
+                uid: m.id.to_string(),
+                body_preview: m.body_preview.clone(),
+                storage_path: m.storage_path.clone(),
+            })
+            .collect();
+
+        let count = message_infos.len();
+
+        let mut sess = session.lock().await;
+        sess.authenticate(user_id, tenant_id, mailbox_id);
+        sess.storage_path = storage_path;
+        sess.load_messages(message_infos);
+
+        info!("POP3 user {} authenticated, {} messages", username, count);
+
+        Pop3Response::ok(&format!("Maildrop has {} messages", count))
+    }
+
     /// Handle LIST command
     async fn handle_list(msg: Option<u32>, session: &Arc<Mutex<Pop3Session>>) -> (String, bool) {
         let sess = session.lock().await;
@@ -585,15 +1168,16 @@ impl Pop3Server {
             Some(m) => m.clone(),
             None => return (Pop3Response::err("No such message"), false),
         };
+        let storage_path = sess.storage_path.as_ref().unwrap_or(storage_path).clone();
 
         drop(sess);
 
         // Initialize file storage and read the full message
-        let storage = match LocalStorage::from_path(storage_path) {
+        let storage = match LocalStorage::from_path(&storage_path) {
             Ok(s) => s,
             Err(e) => {
                 error!("Failed to initialize storage for RETR: {}", e);
-                return (Pop3Response::err("Failed to read message"), false);
+                return (failure(e, "Failed to read message"), false);
             }
         };
 
@@ -655,15 +1239,16 @@ impl Pop3Server {
             Some(m) => m.clone(),
             None => return (Pop3Response::err("No such message"), false),
         };
+        let storage_path = sess.storage_path.as_ref().unwrap_or(storage_path).clone();
 
         drop(sess);
 
         // Initialize file storage and read the full message
-        let storage = match LocalStorage::from_path(storage_path) {
+        let storage = match LocalStorage::from_path(&storage_path) {
             Ok(s) => s,
             Err(e) => {
                 error!("Failed to initialize storage for TOP: {}", e);
-                return (Pop3Response::err("Failed to read message"), false);
+                return (failure(e, "Failed to read message"), false);
             }
         };
 
@@ -740,10 +1325,19 @@ impl Pop3Server {
     async fn handle_quit(
         session: &Arc<Mutex<Pop3Session>>,
         db_pool: &DatabasePool,
+        maintenance: &MaintenanceMode,
     ) -> (String, bool) {
         let mut sess = session.lock().await;
 
         if sess.is_transaction() {
+            // Deletions marked before a window started are kept; RFC 1939 lets
+            // QUIT answer -ERR when marked messages could not be removed
+            if !sess.deleted.is_empty() {
+                if let Some(status) = maintenance.status().await {
+                    return (Pop3Response::maintenance(&status.message), true);
+                }
+            }
+
             sess.enter_update();
 
             // Delete marked messages
@@ -762,6 +1356,35 @@ impl Pop3Server {
     }
 }
 
+/// -ERR response to a command that failed: MaiRust errors get their
+/// response code (RFC 2449, RFC 3206), anything else is a temporary system
+/// error
+fn failure(error: impl Into<anyhow::Error>, text: &str) -> String {
+    let error = error.into();
+    let code = match error.downcast_ref::<mairust_common::Error>() {
+        Some(error) => error.pop3_code(),
+        None => Some("SYS/TEMP"),
+    };
+    match code {
+        Some(code) => Pop3Response::err(&format!("[{}] {}", code, text)),
+        None => Pop3Response::err(text),
+    }
+}
+
+/// Whether an APOP digest is the MD5 of the greeting timestamp followed by
+/// the shared secret
+fn apop_matches(timestamp: &str, secret: &str, digest: &str) -> bool {
+    let expected = hex::encode(Md5::digest(format!("{}{}", timestamp, secret).as_bytes()));
+    let digest = digest.to_ascii_lowercase();
+    // Compare without short-circuiting
+    digest.len() == expected.len()
+        && digest
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -772,5 +1395,29 @@ mod tests {
         assert_eq!(config.bind, "0.0.0.0:110");
         assert!(!config.starttls);
         assert_eq!(config.timeout_minutes, 10);
+        assert!(config.require_tls_for_auth);
+        assert!(!config.apop);
+    }
+
+    #[test]
+    fn test_apop_matches() {
+        // Example from RFC 1939 section 7
+        let timestamp = "<1896.697170952@dbc.mtview.ca.us>";
+        assert!(apop_matches(
+            timestamp,
+            "tanstaaf",
+            "c4c9334bac560ecc979e58001b3e22fb"
+        ));
+        assert!(apop_matches(
+            timestamp,
+            "tanstaaf",
+            "C4C9334BAC560ECC979E58001B3E22FB"
+        ));
+        assert!(!apop_matches(
+            timestamp,
+            "tanstaafl",
+            "c4c9334bac560ecc979e58001b3e22fb"
+        ));
+        assert!(!apop_matches(timestamp, "tanstaaf", "c4c9334b"));
     }
 }
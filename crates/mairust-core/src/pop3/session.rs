@@ -6,6 +6,7 @@
 use chrono::{DateTime, Utc};
 use mairust_common::types::{MailboxId, TenantId, UserId};
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use uuid::Uuid;
 
 /// POP3 session state
@@ -19,6 +20,17 @@ pub enum SessionState {
     Update,
 }
 
+impl SessionState {
+    /// Name of the state in session listings
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::Authorization => "authorization",
+            SessionState::Transaction => "transaction",
+            SessionState::Update => "update",
+        }
+    }
+}
+
 /// Message info for POP3 session
 #[derive(Debug, Clone)]
 pub struct MessageInfo {
@@ -41,8 +53,10 @@ pub struct Pop3Session {
     pub id: String,
     /// Current state
     pub state: SessionState,
-    /// Username (provided via USER command)
+    /// Username (provided via USER, APOP or AUTH)
     pub username: Option<String>,
+    /// Timestamp sent in the greeting when APOP is offered
+    pub apop_timestamp: Option<String>,
     /// Authenticated user ID
     pub user_id: Option<UserId>,
     /// Authenticated tenant ID
@@ -57,6 +71,9 @@ pub struct Pop3Session {
     pub started_at: DateTime<Utc>,
     /// Last activity time
     pub last_activity: DateTime<Utc>,
+    /// Storage root of the region the user's tenant is pinned to, when it
+    /// is this server's region
+    pub storage_path: Option<PathBuf>,
 }
 
 impl Pop3Session {
@@ -67,6 +84,7 @@ impl Pop3Session {
             id: Uuid::new_v4().to_string(),
             state: SessionState::Authorization,
             username: None,
+            apop_timestamp: None,
             user_id: None,
             tenant_id: None,
             mailbox_id: None,
@@ -74,6 +92,7 @@ impl Pop3Session {
             deleted: HashSet::new(),
             started_at: now,
             last_activity: now,
+            storage_path: None,
         }
     }
 
@@ -93,6 +112,19 @@ impl Pop3Session {
         self.update_activity();
     }
 
+    /// Offer APOP, returning the greeting timestamp the digest is computed
+    /// over (RFC 1939 section 7)
+    pub fn offer_apop(&mut self, server_name: &str) -> String {
+        let timestamp = format!(
+            "<{}.{}@{}>",
+            self.id.replace('-', ""),
+            self.started_at.timestamp(),
+            server_name
+        );
+        self.apop_timestamp = Some(timestamp.clone());
+        timestamp
+    }
+
     /// Authenticate the session
     pub fn authenticate(
         &mut self,
@@ -215,6 +247,18 @@ mod tests {
         assert_eq!(session.state, SessionState::Authorization);
         assert!(session.is_authorization());
         assert!(!session.is_transaction());
+        assert!(session.apop_timestamp.is_none());
+    }
+
+    #[test]
+    fn test_offer_apop() {
+        let mut session = Pop3Session::new();
+        let timestamp = session.offer_apop("mail.example.com");
+        assert!(timestamp.starts_with('<'));
+        assert!(timestamp.ends_with("@mail.example.com>"));
+        assert_eq!(session.apop_timestamp.as_deref(), Some(timestamp.as_str()));
+        // Each session has its own timestamp
+        assert_ne!(Pop3Session::new().offer_apop("mail.example.com"), timestamp);
     }
 
     #[test]
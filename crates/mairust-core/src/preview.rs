@@ -0,0 +1,302 @@
+//! Message previews
+//!
+//! `body_preview` holds the start of the decoded body text and `snippet` a
+//! single-line excerpt of it for message lists. Both come from the first
+//! text body part: text/plain when the message has one, otherwise the HTML
+//! part with its markup, style sheets and scripts removed. Attachments and
+//! transfer encodings never end up in either.
+//!
+//! Messages stored before snippets existed are filled in by
+//! [`PreviewBackfillWorker`].
+
+use anyhow::Result;
+use mail_parser::{MessageParser, PartType};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Longest body preview, in characters
+pub const PREVIEW_MAX_CHARS: usize = 500;
+
+/// Longest snippet, in bytes
+pub const SNIPPET_MAX_BYTES: usize = 200;
+
+/// Messages backfilled per batch
+const BACKFILL_BATCH_SIZE: i64 = 200;
+
+/// Pause between backfill batches, to leave the database to live traffic
+const BACKFILL_PAUSE: Duration = Duration::from_millis(200);
+
+/// Preview and snippet of a message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessagePreview {
+    /// Start of the body text; `None` when the message has no text
+    pub body_preview: Option<String>,
+    /// Body text on one line; empty when the message has no text
+    pub snippet: String,
+}
+
+impl MessagePreview {
+    /// Preview of a parsed message
+    pub fn of_message(parsed: &mail_parser::Message) -> Self {
+        match body_text(parsed) {
+            Some(text) => Self {
+                snippet: snippet(&text),
+                body_preview: Some(text.chars().take(PREVIEW_MAX_CHARS).collect()),
+            },
+            None => Self::default(),
+        }
+    }
+
+    /// Preview of a raw message
+    pub fn of_raw(data: &[u8]) -> Self {
+        MessageParser::default()
+            .parse(data)
+            .map(|parsed| Self::of_message(&parsed))
+            .unwrap_or_default()
+    }
+}
+
+/// Decoded text of the first text body part, `None` when it has no text
+///
+/// HTML bodies are converted to text. Control characters are dropped and
+/// runs of blank lines collapsed.
+pub(crate) fn body_text(parsed: &mail_parser::Message) -> Option<String> {
+    parsed
+        .text_part(0)
+        .and_then(|part| match &part.body {
+            PartType::Text(text) => Some(clean_text(text)),
+            PartType::Html(html) => Some(clean_text(&html_to_text(html))),
+            _ => None,
+        })
+        .filter(|text| !text.is_empty())
+}
+
+/// Text of an HTML body without style sheets, scripts and comments
+fn html_to_text(html: &str) -> String {
+    let mut html = html.to_string();
+    for (open, close) in [
+        ("<!--", "-->"),
+        ("<style", "</style>"),
+        ("<script", "</script>"),
+    ] {
+        html = remove_blocks(&html, open, close);
+    }
+    mail_parser::decoders::html::html_to_text(&html)
+}
+
+/// Remove every `open ... close` block, matching ASCII case-insensitively
+///
+/// An unclosed block runs to the end of the text.
+fn remove_blocks(html: &str, open: &str, close: &str) -> String {
+    // ASCII lowercasing keeps byte offsets
+    let lower = html.to_ascii_lowercase();
+    let mut result = String::with_capacity(html.len());
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find(open).map(|i| pos + i) {
+        result.push_str(&html[pos..start]);
+        match lower[start + open.len()..].find(close) {
+            Some(end) => pos = start + open.len() + end + close.len(),
+            None => return result,
+        }
+    }
+    result.push_str(&html[pos..]);
+    result
+}
+
+/// Drop control characters and trailing spaces, and collapse runs of blank
+/// lines
+fn clean_text(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line: String = line
+            .chars()
+            .map(|c| if c == '\t' { ' ' } else { c })
+            .filter(|c| !c.is_control())
+            .collect();
+        let line = line.trim_end().to_string();
+        if line.is_empty() && lines.last().map(String::is_empty).unwrap_or(true) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Body text on one line, cut to [`SNIPPET_MAX_BYTES`]
+fn snippet(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    truncate_on_char_boundary(&line, SNIPPET_MAX_BYTES).to_string()
+}
+
+/// Longest prefix of `text` of at most `max_bytes` bytes that does not
+/// split a character
+pub fn truncate_on_char_boundary(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Fills in previews and snippets of messages stored before snippets
+/// existed
+///
+/// Previews of these messages were cut from the raw message and often
+/// show MIME boundaries or base64, so both are rebuilt from the stored
+/// file.
+pub struct PreviewBackfillWorker<S: FileStorage> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+}
+
+impl<S: FileStorage> PreviewBackfillWorker<S> {
+    /// Create a worker reading messages from `file_storage`
+    pub fn new(db_pool: DatabasePool, file_storage: Arc<S>) -> Self {
+        Self {
+            db_pool,
+            file_storage,
+        }
+    }
+
+    /// Backfill every message without a snippet, returning how many were
+    /// updated
+    pub async fn run(&self) -> Result<u64> {
+        let mut after = Uuid::nil();
+        let mut updated = 0;
+
+        loop {
+            let rows: Vec<(Uuid, String)> = sqlx::query_as(
+                r#"
+                SELECT id, storage_path FROM messages
+                WHERE snippet IS NULL AND id > $1
+                ORDER BY id
+                LIMIT $2
+                "#,
+            )
+            .bind(after)
+            .bind(BACKFILL_BATCH_SIZE)
+            .fetch_all(self.db_pool.pool())
+            .await?;
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            after = *last;
+
+            for (id, storage_path) in rows {
+                // Unreadable files keep their preview; the empty snippet
+                // stops them from being retried
+                let preview = match self.file_storage.read(&storage_path).await {
+                    Ok(data) => Some(MessagePreview::of_raw(&data)),
+                    Err(e) => {
+                        warn!("Failed to read {} for its preview: {}", storage_path, e);
+                        None
+                    }
+                };
+                sqlx::query(
+                    r#"
+                    UPDATE messages
+                    SET body_preview = CASE WHEN $2 THEN $3 ELSE body_preview END,
+                        snippet = $4
+                    WHERE id = $1 AND snippet IS NULL
+                    "#,
+                )
+                .bind(id)
+                .bind(preview.is_some())
+                .bind(preview.as_ref().and_then(|p| p.body_preview.as_deref()))
+                .bind(preview.as_ref().map(|p| p.snippet.as_str()).unwrap_or(""))
+                .execute(self.db_pool.pool())
+                .await?;
+                updated += 1;
+            }
+
+            debug!("Backfilled previews of {} messages", updated);
+            tokio::time::sleep(BACKFILL_PAUSE).await;
+        }
+
+        if updated > 0 {
+            info!("Backfilled previews of {} messages", updated);
+        }
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_preview() {
+        let raw = b"From: a@example.com\r\nSubject: Hi\r\nContent-Type: text/plain; charset=utf-8\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\r\nHello =E3=81=93=E3=82=93=E3=81=AB=E3=81=A1=E3=81=AF,\r\n\r\n\r\n\r\n\
+see  you\ttomorrow.\r\n";
+        let preview = MessagePreview::of_raw(raw);
+        assert_eq!(
+            preview.body_preview.as_deref(),
+            Some("Hello こんにちは,\n\nsee  you tomorrow.")
+        );
+        assert_eq!(preview.snippet, "Hello こんにちは, see you tomorrow.");
+    }
+
+    #[test]
+    fn test_attachments_are_not_previewed() {
+        let raw = b"From: a@example.com\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+--b\r\nContent-Type: text/plain\r\n\r\nReport attached.\r\n\
+--b\r\nContent-Type: application/pdf\r\nContent-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQKJcfs\r\n\
+--b--\r\n";
+        let preview = MessagePreview::of_raw(raw);
+        assert_eq!(preview.body_preview.as_deref(), Some("Report attached."));
+        assert!(!preview.snippet.contains("JVBER"));
+        assert!(!preview.snippet.contains("--b"));
+    }
+
+    #[test]
+    fn test_html_preview() {
+        let raw = b"From: a@example.com\r\nContent-Type: text/html\r\n\r\n\
+<html><head><title>T</title></head><body><style>p { color: red; }</style>\
+<script>alert(1)</script><!-- tracking --><p>Your <b>order</b> has shipped</p></body></html>\r\n";
+        let preview = MessagePreview::of_raw(raw);
+        let text = preview.body_preview.unwrap();
+        assert!(text.contains("Your order has shipped"), "{}", text);
+        assert!(!text.contains("color"));
+        assert!(!text.contains("alert"));
+        assert!(!text.contains("tracking"));
+        assert!(!text.contains('<'));
+    }
+
+    #[test]
+    fn test_no_text() {
+        let raw = b"From: a@example.com\r\nContent-Type: image/png\r\n\r\niVBORw0KGgo=\r\n";
+        assert_eq!(MessagePreview::of_raw(raw), MessagePreview::default());
+    }
+
+    #[test]
+    fn test_snippet_is_cut_on_char_boundary() {
+        let text = "あ".repeat(100);
+        let snippet = snippet(&text);
+        assert!(snippet.len() <= SNIPPET_MAX_BYTES);
+        assert_eq!(snippet.len(), 198);
+        assert_eq!(truncate_on_char_boundary("héllo", 2), "h");
+        assert_eq!(truncate_on_char_boundary("héllo", 3), "hé");
+        assert_eq!(truncate_on_char_boundary("abc", 10), "abc");
+    }
+
+    #[test]
+    fn test_remove_blocks() {
+        assert_eq!(
+            remove_blocks("a<STYLE x>b</Style>c", "<style", "</style>"),
+            "ac"
+        );
+        assert_eq!(remove_blocks("a<!-- b", "<!--", "-->"), "a");
+        assert_eq!(remove_blocks("abc", "<script", "</script>"), "abc");
+    }
+}
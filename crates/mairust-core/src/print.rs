@@ -0,0 +1,559 @@
+//! Print views and portable copies of messages
+//!
+//! A printed message shows its main header fields, its body text and the
+//! list of its attachments. It is rendered either as a self-contained HTML
+//! page for the web UI's print button or as a PDF. HTML bodies are printed
+//! as text, so a print view never loads remote content or runs the sender's
+//! markup.
+//!
+//! The PDF uses the standard Courier fonts, which only cover Latin-1 (and
+//! the few extra characters of WinAnsiEncoding); other characters come out
+//! as `?`. The HTML view, printed from a browser, covers every script.
+
+use crate::inbound::content_type;
+use crate::preview::body_text;
+use mail_parser::{MessageParser, MimeHeaders};
+use std::fmt::Write as _;
+
+/// An attachment as listed on a printed message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintedAttachment {
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub size: usize,
+}
+
+impl PrintedAttachment {
+    /// One-line description, e.g. `report.pdf (application/pdf, 1.2 KB)`
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({}, {})",
+            self.filename.as_deref().unwrap_or("(unnamed)"),
+            self.content_type,
+            format_size(self.size)
+        )
+    }
+}
+
+/// The parts of a message that are printed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrintableMessage {
+    pub subject: Option<String>,
+    /// `Name <address>` or a bare address, per mailbox
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    /// Date header in RFC 822 form
+    pub date: Option<String>,
+    /// Body text; empty when the message has none
+    pub body: String,
+    pub attachments: Vec<PrintedAttachment>,
+}
+
+impl PrintableMessage {
+    /// Parse a raw message; `None` when it cannot be parsed
+    pub fn of_raw(raw: &[u8]) -> Option<Self> {
+        MessageParser::default()
+            .parse(raw)
+            .map(|parsed| Self::of_parsed(&parsed))
+    }
+
+    /// Printed parts of a parsed message
+    pub(crate) fn of_parsed(parsed: &mail_parser::Message<'_>) -> Self {
+        Self {
+            subject: parsed.subject().map(str::to_string),
+            from: mailboxes(parsed.from()),
+            to: mailboxes(parsed.to()),
+            cc: mailboxes(parsed.cc()),
+            date: parsed.date().map(|date| date.to_rfc822()),
+            body: body_text(parsed).unwrap_or_default(),
+            attachments: parsed
+                .attachments()
+                .map(|part| PrintedAttachment {
+                    filename: part.attachment_name().map(str::to_string),
+                    content_type: content_type(part),
+                    size: part.contents().len(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Header fields to print, in order, leaving out empty ones
+    pub fn header_fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        for (name, value) in [
+            ("From", self.from.join(", ")),
+            ("To", self.to.join(", ")),
+            ("Cc", self.cc.join(", ")),
+            ("Date", self.date.clone().unwrap_or_default()),
+            ("Subject", self.subject.clone().unwrap_or_default()),
+        ] {
+            if !value.is_empty() {
+                fields.push((name, value));
+            }
+        }
+        fields
+    }
+
+    fn title(&self) -> &str {
+        self.subject
+            .as_deref()
+            .filter(|subject| !subject.trim().is_empty())
+            .unwrap_or("(no subject)")
+    }
+
+    /// Print-friendly HTML page
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>\n<table class=\"headers\">\n",
+            escape_html(self.title()),
+            PRINT_CSS,
+            escape_html(self.title())
+        );
+        for (name, value) in self.header_fields() {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}:</th><td>{}</td></tr>",
+                name,
+                escape_html(&value)
+            );
+        }
+        let _ = write!(
+            html,
+            "</table>\n<hr>\n<pre class=\"body\">{}</pre>\n",
+            escape_html(&self.body)
+        );
+        if !self.attachments.is_empty() {
+            let _ = write!(
+                html,
+                "<hr>\n<h2>Attachments ({})</h2>\n<ul class=\"attachments\">\n",
+                self.attachments.len()
+            );
+            for attachment in &self.attachments {
+                let _ = writeln!(html, "<li>{}</li>", escape_html(&attachment.describe()));
+            }
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// PDF document, A4, one or more pages
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut lines = vec![PdfLine::bold(self.title())];
+        lines.push(PdfLine::blank());
+        for (name, value) in self.header_fields() {
+            lines.push(PdfLine::regular(&format!("{}: {}", name, value)));
+        }
+        lines.push(PdfLine::regular(&"-".repeat(PDF_COLUMNS)));
+        for line in self.body.lines() {
+            lines.push(PdfLine::regular(line));
+        }
+        if !self.attachments.is_empty() {
+            lines.push(PdfLine::blank());
+            lines.push(PdfLine::bold(&format!(
+                "Attachments ({})",
+                self.attachments.len()
+            )));
+            for attachment in &self.attachments {
+                lines.push(PdfLine::regular(&format!("- {}", attachment.describe())));
+            }
+        }
+        write_pdf(self.title(), &lines)
+    }
+}
+
+/// File name for a downloaded copy of a message, from its subject
+///
+/// Characters that are unsafe in file names or in a quoted
+/// `Content-Disposition` value become `_`.
+pub fn export_filename(subject: Option<&str>, extension: &str) -> String {
+    let stem: String = subject
+        .unwrap_or("")
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '"' | '\\' | '/' | ':' | '*' | '?' | '<' | '>' | '|') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .take(100)
+        .collect();
+    let stem = stem.trim().trim_matches('.');
+    let stem = if stem.is_empty() { "message" } else { stem };
+    format!("{}.{}", stem, extension)
+}
+
+/// `Name <address>` per mailbox of an address header
+fn mailboxes(address: Option<&mail_parser::Address<'_>>) -> Vec<String> {
+    address
+        .map(|address| {
+            address
+                .iter()
+                .filter_map(|addr| match (addr.name(), addr.address()) {
+                    (Some(name), Some(address)) => Some(format!("{} <{}>", name, address)),
+                    (None, Some(address)) => Some(address.to_string()),
+                    (Some(name), None) => Some(name.to_string()),
+                    (None, None) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Size in B, KB or MB, as the web UI shows it
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const PRINT_CSS: &str = "body { font-family: sans-serif; margin: 2em; color: #000; }
+h1 { font-size: 1.4em; }
+h2 { font-size: 1.1em; }
+table.headers { border-collapse: collapse; }
+table.headers th { text-align: left; vertical-align: top; padding-right: 1em; }
+pre.body { white-space: pre-wrap; word-wrap: break-word; font-family: inherit; }
+@media print { body { margin: 0; } }
+";
+
+/// A4 page size in points
+const PDF_PAGE_WIDTH: u32 = 595;
+const PDF_PAGE_HEIGHT: u32 = 842;
+const PDF_MARGIN: u32 = 56;
+const PDF_FONT_SIZE: u32 = 10;
+const PDF_LEADING: u32 = 12;
+/// Characters per line; Courier glyphs are 0.6 em wide
+const PDF_COLUMNS: usize = 80;
+const PDF_LINES_PER_PAGE: usize = ((PDF_PAGE_HEIGHT - 2 * PDF_MARGIN) / PDF_LEADING) as usize;
+
+/// One printed line of a PDF, before wrapping
+struct PdfLine {
+    bold: bool,
+    text: String,
+}
+
+impl PdfLine {
+    fn regular(text: &str) -> Self {
+        Self {
+            bold: false,
+            text: text.to_string(),
+        }
+    }
+
+    fn bold(text: &str) -> Self {
+        Self {
+            bold: true,
+            text: text.to_string(),
+        }
+    }
+
+    fn blank() -> Self {
+        Self::regular("")
+    }
+}
+
+/// Split a line into rows of at most `columns` characters, breaking after
+/// the last space of a row when there is one
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut rest: Vec<char> = text.chars().collect();
+    while rest.len() > columns {
+        let split = rest[..=columns]
+            .iter()
+            .rposition(|c| *c == ' ')
+            .filter(|&i| i > 0)
+            .unwrap_or(columns);
+        rows.push(
+            rest[..split]
+                .iter()
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+        );
+        rest.drain(..split);
+        while rest.first() == Some(&' ') {
+            rest.remove(0);
+        }
+    }
+    rows.push(rest.into_iter().collect());
+    rows
+}
+
+/// Text as a PDF literal string in WinAnsiEncoding
+fn pdf_string(text: &str) -> String {
+    let mut literal = String::from("(");
+    for c in text.chars() {
+        let byte = match c {
+            ' '..='~' => c as u8,
+            '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '€' => 0x80,
+            '‚' => 0x82,
+            '„' => 0x84,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            '™' => 0x99,
+            _ => b'?',
+        };
+        match byte {
+            b'(' | b')' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            0x20..=0x7e => literal.push(byte as char),
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            }
+        }
+    }
+    literal.push(')');
+    literal
+}
+
+/// Lay lines out on pages and write the PDF file
+fn write_pdf(title: &str, lines: &[PdfLine]) -> Vec<u8> {
+    let rows: Vec<(bool, String)> = lines
+        .iter()
+        .flat_map(|line| {
+            wrap(&line.text, PDF_COLUMNS)
+                .into_iter()
+                .map(move |row| (line.bold, row))
+        })
+        .collect();
+    let pages: Vec<&[(bool, String)]> = rows.chunks(PDF_LINES_PER_PAGE).collect();
+
+    // 1 catalog, 2 page tree, 3-4 fonts, 5 info, then a page and its
+    // contents per page
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| 6 + 2 * i).collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids
+                .iter()
+                .map(|id| format!("{} 0 R", id))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+        format!("<< /Title {} /Producer (MaiRust) >>", pdf_string(title)),
+    ];
+    for (number, page) in pages.iter().enumerate() {
+        let content_id = page_ids[number] + 1;
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            PDF_PAGE_WIDTH, PDF_PAGE_HEIGHT, content_id
+        ));
+
+        let mut content = format!(
+            "BT\n{} TL\n{} {} Td\n",
+            PDF_LEADING,
+            PDF_MARGIN,
+            PDF_PAGE_HEIGHT - PDF_MARGIN - PDF_FONT_SIZE
+        );
+        let mut bold = None;
+        for (row_bold, row) in page.iter() {
+            if bold != Some(*row_bold) {
+                let font = if *row_bold { "F2" } else { "F1" };
+                let _ = writeln!(content, "/{} {} Tf", font, PDF_FONT_SIZE);
+                bold = Some(*row_bold);
+            }
+            let _ = writeln!(content, "{} Tj T*", pdf_string(row));
+        }
+        let _ = write!(
+            content,
+            "ET\nBT\n/F1 8 Tf\n{} {} Td\n{} Tj\nET\n",
+            PDF_MARGIN,
+            PDF_MARGIN / 2,
+            pdf_string(&format!("Page {} of {}", number + 1, pages.len()))
+        );
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+    }
+
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref = pdf.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R /Info 5 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    pdf.extend_from_slice(trailer.as_bytes());
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: Alice Example <alice@example.com>\r\n\
+To: bob@example.org, Carol <carol@example.org>\r\n\
+Subject: Quarterly report\r\n\
+Date: Tue, 1 Oct 2024 10:00:00 +0000\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+\r\n\
+<html><body><p>Numbers are <b>up</b> &amp; costs down.</p><script>alert(1)</script></body></html>\r\n\
+--b\r\n\
+Content-Type: application/pdf; name=\"report.pdf\"\r\n\
+Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQK\r\n\
+--b--\r\n";
+
+    #[test]
+    fn test_of_raw() {
+        let printable = PrintableMessage::of_raw(MESSAGE).unwrap();
+        assert_eq!(printable.subject.as_deref(), Some("Quarterly report"));
+        assert_eq!(printable.from, vec!["Alice Example <alice@example.com>"]);
+        assert_eq!(
+            printable.to,
+            vec!["bob@example.org", "Carol <carol@example.org>"]
+        );
+        assert!(printable.cc.is_empty());
+        assert!(printable.date.is_some());
+        assert!(printable.body.contains("Numbers are up & costs down."));
+        assert!(!printable.body.contains("alert"));
+        assert_eq!(
+            printable.attachments,
+            vec![PrintedAttachment {
+                filename: Some("report.pdf".to_string()),
+                content_type: "application/pdf".to_string(),
+                size: 9,
+            }]
+        );
+
+        let fields: Vec<&str> = printable
+            .header_fields()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(fields, vec!["From", "To", "Date", "Subject"]);
+    }
+
+    #[test]
+    fn test_to_html() {
+        let printable = PrintableMessage {
+            subject: Some("<script>x</script>".to_string()),
+            from: vec!["Alice <alice@example.com>".to_string()],
+            body: "a < b & c".to_string(),
+            attachments: vec![PrintedAttachment {
+                filename: None,
+                content_type: "image/png".to_string(),
+                size: 2048,
+            }],
+            ..Default::default()
+        };
+        let html = printable.to_html();
+        assert!(html.contains("<title>&lt;script&gt;x&lt;/script&gt;</title>"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<td>Alice &lt;alice@example.com&gt;</td>"));
+        assert!(html.contains("<pre class=\"body\">a &lt; b &amp; c</pre>"));
+        assert!(html.contains("<li>(unnamed) (image/png, 2.0 KB)</li>"));
+    }
+
+    #[test]
+    fn test_to_pdf() {
+        let printable = PrintableMessage {
+            subject: Some("Café (draft)".to_string()),
+            body: "line\n".repeat(150),
+            ..Default::default()
+        };
+        let pdf = printable.to_pdf();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("/Count 3"));
+        assert!(text.contains("(Caf\\351 \\(draft\\)) Tj"));
+        assert!(text.contains("(Page 3 of 3) Tj"));
+
+        // Every xref entry points at its object
+        let xref = pdf.windows(6).rposition(|w| w == b"\nxref\n").unwrap() + 1;
+        let trailer = std::str::from_utf8(&pdf[xref..]).unwrap();
+        let entries: Vec<usize> = trailer
+            .lines()
+            .skip(3)
+            .take_while(|line| line.ends_with(" n "))
+            .map(|line| line[..10].parse().unwrap())
+            .collect();
+        assert_eq!(entries.len(), 5 + 2 * 3);
+        for (i, offset) in entries.into_iter().enumerate() {
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj\n", i + 1).as_bytes()));
+        }
+        let startxref: usize = trailer
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(startxref, xref);
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("", 10), vec![""]);
+        assert_eq!(wrap("short", 10), vec!["short"]);
+        assert_eq!(wrap("aaaa bbbb cccc", 10), vec!["aaaa bbbb", "cccc"]);
+        assert_eq!(wrap("aaaaaaaaaaaa", 5), vec!["aaaaa", "aaaaa", "aa"]);
+    }
+
+    #[test]
+    fn test_pdf_string() {
+        assert_eq!(pdf_string("a(b)\\"), "(a\\(b\\)\\\\)");
+        assert_eq!(pdf_string("é–日"), "(\\351\\226?)");
+    }
+
+    #[test]
+    fn test_export_filename() {
+        assert_eq!(
+            export_filename(Some("Re: Q3 \"numbers\""), "pdf"),
+            "Re_ Q3 _numbers_.pdf"
+        );
+        assert_eq!(export_filename(None, "eml"), "message.eml");
+        assert_eq!(export_filename(Some(" .. "), "eml"), "message.eml");
+    }
+}
@@ -0,0 +1,964 @@
+//! Bulk mailbox provisioning
+//!
+//! A batch of users with mailboxes is submitted in one call, as JSON or
+//! CSV, and created by `MailboxProvisioningWorker` in the background. Each
+//! row is provisioned in its own transaction and records its result, so a
+//! batch is never rolled back as a whole: invalid rows fail on their own,
+//! and addresses that already have a mailbox in the tenant are reported as
+//! existing and left untouched. Submitting the same rows again is therefore
+//! safe, and a batch submitted again with the same idempotency key returns
+//! the first one.
+//!
+//! Users without a password in the batch either get a generated one,
+//! returned only by the submitting call, or an invite emailed to another
+//! address, with a link to choose a password on the web UI.
+
+use crate::folders::{provision_folders, FolderSettings};
+use crate::queue::{calculate_backoff, DeliveryJob};
+use crate::secure_message::web_base_url;
+use anyhow::{anyhow, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::types::{DeliveryLane, UserRole};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{Domain, Job, Mailbox, Tenant, User};
+use mairust_storage::repository::message_shares::hash_share_token;
+use mairust_storage::{
+    ProvisioningBatch, ProvisioningOutcome, ProvisioningRepository, ProvisioningRow,
+    TenantRepository,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use thiserror::Error;
+use tokio::time::{interval, Duration as TokioDuration};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Job queue for provisioning batches
+pub const PROVISIONING_QUEUE: &str = "mailbox_provisioning";
+
+/// Most rows accepted in one batch
+pub const MAX_BULK_ROWS: usize = 5000;
+
+/// Shortest password accepted for a user
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// Length of generated passwords
+pub const GENERATED_PASSWORD_LEN: usize = 16;
+
+/// Days an invite link stays valid
+pub const INVITE_VALID_DAYS: i64 = 7;
+
+/// Password hash of invited users until they choose a password; it is not
+/// a valid hash, so no password matches it
+pub const INVITE_PENDING_HASH: &str = "!invite";
+
+/// Attempts before a batch job is marked failed
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Pending rows loaded at a time
+const ROWS_PER_QUERY: i64 = 100;
+
+/// Characters of generated passwords; no look-alikes such as `0`/`O` or
+/// `1`/`l`
+const PASSWORD_CHARS: &[u8] = b"abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Provisioning errors
+#[derive(Error, Debug)]
+pub enum ProvisioningError {
+    #[error("Invalid request: {0}")]
+    Invalid(String),
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("{0}")]
+    Other(#[from] anyhow::Error),
+}
+
+/// What users without a password in the batch get
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordMode {
+    /// A random password, returned by the submitting call
+    #[default]
+    Generate,
+    /// An emailed link to choose a password
+    Invite,
+}
+
+impl PasswordMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PasswordMode::Generate => "generate",
+            PasswordMode::Invite => "invite",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "generate" => Some(PasswordMode::Generate),
+            "invite" => Some(PasswordMode::Invite),
+            _ => None,
+        }
+    }
+}
+
+/// A user with a mailbox to provision
+///
+/// CSV batches have a header line naming these columns; only `email` is
+/// required.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkMailboxRow {
+    /// Address of the user and the mailbox
+    pub email: String,
+    /// Display name
+    pub name: Option<String>,
+    /// Password; without one, the batch's password mode applies
+    pub password: Option<String>,
+    /// `user` (default), `domain_admin` or `tenant_admin`
+    pub role: Option<String>,
+    pub quota_bytes: Option<i64>,
+    /// Where to email the invite, in `invite` mode
+    pub invite_email: Option<String>,
+}
+
+/// Parse a CSV batch with a header line
+pub fn parse_csv(text: &str) -> Result<Vec<BulkMailboxRow>, ProvisioningError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+
+    let mut rows = Vec::new();
+    for (index, record) in reader.deserialize::<BulkMailboxRow>().enumerate() {
+        let row = record
+            .map_err(|e| ProvisioningError::Invalid(format!("CSV line {}: {}", index + 2, e)))?;
+        rows.push(row);
+        if rows.len() > MAX_BULK_ROWS {
+            break;
+        }
+    }
+    Ok(rows)
+}
+
+/// Random password of `GENERATED_PASSWORD_LEN` letters and digits
+pub fn generate_password() -> String {
+    let mut rng = OsRng;
+    (0..GENERATED_PASSWORD_LEN)
+        .map(|_| {
+            let index = rng.next_u32() as usize % PASSWORD_CHARS.len();
+            PASSWORD_CHARS[index] as char
+        })
+        .collect()
+}
+
+/// Argon2 hash of a user password
+pub fn hash_user_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+/// Check that a password is long enough for a user
+pub fn validate_user_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// A row checked and normalized for storing
+#[derive(Debug, Clone, PartialEq)]
+struct ValidRow {
+    email: String,
+    name: Option<String>,
+    password: Option<String>,
+    /// Role as stored in `users.role`
+    role: String,
+    quota_bytes: Option<i64>,
+    invite_email: Option<String>,
+}
+
+/// Check the parts of a row that do not need the database
+fn validate_row(row: &BulkMailboxRow, mode: PasswordMode) -> Result<ValidRow, String> {
+    let email = row.email.trim().to_lowercase();
+    if !is_address(&email) {
+        return Err("email is not an address".to_string());
+    }
+
+    let role = match row.role.as_deref().map(str::trim).unwrap_or("") {
+        "" | "user" => UserRole::User,
+        "domain_admin" => UserRole::DomainAdmin,
+        "tenant_admin" => UserRole::TenantAdmin,
+        other => return Err(format!("unknown role '{}'", other)),
+    };
+
+    if row.quota_bytes.is_some_and(|quota| quota < 0) {
+        return Err("quota_bytes is negative".to_string());
+    }
+
+    let password = row.password.clone().filter(|p| !p.is_empty());
+    if let Some(password) = &password {
+        validate_user_password(password)?;
+    }
+
+    let invite_email = row
+        .invite_email
+        .as_deref()
+        .map(|address| address.trim().to_lowercase())
+        .filter(|address| !address.is_empty());
+    if let Some(address) = &invite_email {
+        if !is_address(address) {
+            return Err("invite_email is not an address".to_string());
+        }
+        if *address == email {
+            return Err("invite_email must be another address than email".to_string());
+        }
+    }
+    if mode == PasswordMode::Invite && password.is_none() && invite_email.is_none() {
+        return Err("invite_email is required without a password".to_string());
+    }
+
+    Ok(ValidRow {
+        email,
+        name: row
+            .name
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+        password,
+        role: format!("{:?}", role).to_lowercase(),
+        quota_bytes: row.quota_bytes,
+        invite_email,
+    })
+}
+
+/// A plausible `local@domain` address
+fn is_address(address: &str) -> bool {
+    address.len() <= 255
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control())
+        && address.rsplit_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && !domain.starts_with('.') && domain.contains('.')
+        })
+}
+
+/// Password generated for a row
+#[derive(Debug, Clone, Serialize)]
+pub struct GeneratedPassword {
+    pub row_number: i32,
+    pub email: String,
+    pub password: String,
+}
+
+/// Result of submitting a batch
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub batch: ProvisioningBatch,
+    /// False when the idempotency key matched an earlier batch, which is
+    /// returned instead
+    pub created: bool,
+    /// Generated passwords; only returned here, and empty for an earlier
+    /// batch
+    pub passwords: Vec<GeneratedPassword>,
+}
+
+/// Job payload of a provisioning batch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvisioningJob {
+    pub batch_id: Uuid,
+    pub tenant_id: Uuid,
+}
+
+/// Submits provisioning batches
+pub struct BulkProvisioner {
+    db_pool: DatabasePool,
+}
+
+impl BulkProvisioner {
+    /// Create a new provisioner
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Store a batch and queue its job
+    ///
+    /// Rows that are invalid on their own are stored as failed right away.
+    pub async fn submit(
+        &self,
+        tenant_id: Uuid,
+        rows: &[BulkMailboxRow],
+        mode: PasswordMode,
+        idempotency_key: Option<&str>,
+    ) -> Result<Submission, ProvisioningError> {
+        if rows.is_empty() {
+            return Err(ProvisioningError::Invalid(
+                "the batch has no rows".to_string(),
+            ));
+        }
+        if rows.len() > MAX_BULK_ROWS {
+            return Err(ProvisioningError::Invalid(format!(
+                "a batch has at most {} rows",
+                MAX_BULK_ROWS
+            )));
+        }
+        let idempotency_key = idempotency_key.map(str::trim).filter(|key| !key.is_empty());
+        if idempotency_key.is_some_and(|key| key.len() > 255) {
+            return Err(ProvisioningError::Invalid(
+                "idempotency_key is too long".to_string(),
+            ));
+        }
+
+        let mut tx = self.db_pool.pool().begin().await?;
+        let batch = sqlx::query_as::<_, ProvisioningBatch>(
+            r#"
+            INSERT INTO provisioning_batches (id, tenant_id, idempotency_key, password_mode,
+                                              status, total_rows, created_at)
+            VALUES ($1, $2, $3, $4, 'pending', $5, NOW())
+            ON CONFLICT (tenant_id, idempotency_key) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant_id)
+        .bind(idempotency_key)
+        .bind(mode.as_str())
+        .bind(rows.len() as i32)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(batch) = batch else {
+            tx.rollback().await?;
+            let key = idempotency_key.unwrap_or_default();
+            let batch = ProvisioningRepository::new(self.db_pool.clone())
+                .find_by_key(tenant_id, key)
+                .await?
+                .ok_or_else(|| anyhow!("Batch with idempotency key '{}' disappeared", key))?;
+            debug!(
+                "Idempotency key '{}' matched provisioning batch {}",
+                key, batch.id
+            );
+            return Ok(Submission {
+                batch,
+                created: false,
+                passwords: Vec::new(),
+            });
+        };
+
+        let mut passwords = Vec::new();
+        let mut failed = 0;
+        for (index, row) in rows.iter().enumerate() {
+            let row_number = index as i32 + 1;
+            match validate_row(row, mode) {
+                Ok(mut valid) => {
+                    if valid.password.is_none() && mode == PasswordMode::Generate {
+                        let password = generate_password();
+                        passwords.push(GeneratedPassword {
+                            row_number,
+                            email: valid.email.clone(),
+                            password: password.clone(),
+                        });
+                        valid.password = Some(password);
+                    }
+                    sqlx::query(
+                        r#"
+                        INSERT INTO provisioning_rows (batch_id, row_number, email, name, role,
+                                                       quota_bytes, invite_email, password, status)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'pending')
+                        "#,
+                    )
+                    .bind(batch.id)
+                    .bind(row_number)
+                    .bind(&valid.email)
+                    .bind(&valid.name)
+                    .bind(&valid.role)
+                    .bind(valid.quota_bytes)
+                    .bind(&valid.invite_email)
+                    .bind(&valid.password)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                Err(reason) => {
+                    failed += 1;
+                    sqlx::query(
+                        r#"
+                        INSERT INTO provisioning_rows (batch_id, row_number, email, name,
+                                                       status, error, processed_at)
+                        VALUES ($1, $2, $3, $4, 'failed', $5, NOW())
+                        "#,
+                    )
+                    .bind(batch.id)
+                    .bind(row_number)
+                    .bind(row.email.chars().take(255).collect::<String>())
+                    .bind(
+                        row.name
+                            .as_deref()
+                            .map(|name| name.chars().take(255).collect::<String>()),
+                    )
+                    .bind(&reason)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+        }
+
+        let job = ProvisioningJob {
+            batch_id: batch.id,
+            tenant_id,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at,
+                              tenant_id)
+            VALUES ($1, $2, $3, 'pending', 0, $4, NOW(), NOW(), $5)
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(PROVISIONING_QUEUE)
+        .bind(serde_json::to_value(&job).map_err(anyhow::Error::from)?)
+        .bind(MAX_ATTEMPTS)
+        .bind(tenant_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        info!(
+            "Queued provisioning batch {} for tenant {}: {} rows, {} invalid",
+            batch.id,
+            tenant_id,
+            rows.len(),
+            failed
+        );
+
+        Ok(Submission {
+            batch,
+            created: true,
+            passwords,
+        })
+    }
+}
+
+/// Result of provisioning one row
+struct RowResult {
+    outcome: ProvisioningOutcome,
+    user_id: Option<Uuid>,
+    mailbox_id: Option<Uuid>,
+    error: Option<String>,
+}
+
+impl RowResult {
+    fn failed(error: impl Into<String>) -> Self {
+        Self {
+            outcome: ProvisioningOutcome::Failed,
+            user_id: None,
+            mailbox_id: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// Provisions queued batches
+pub struct MailboxProvisioningWorker {
+    db_pool: DatabasePool,
+}
+
+impl MailboxProvisioningWorker {
+    /// Create a new worker
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Run the worker
+    pub async fn run(&self) {
+        let mut ticker = interval(TokioDuration::from_secs(5));
+
+        info!("Mailbox provisioning worker started");
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = self.process_due_jobs().await {
+                error!("Error processing provisioning batches: {}", e);
+            }
+        }
+    }
+
+    /// Claim and run due jobs, one batch at a time
+    async fn process_due_jobs(&self) -> Result<()> {
+        let jobs: Vec<Job> = sqlx::query_as(
+            r#"
+            UPDATE jobs SET status = 'processing', started_at = NOW()
+            WHERE id IN (
+                SELECT id FROM jobs
+                WHERE queue = $1 AND status = 'pending' AND scheduled_at <= NOW()
+                ORDER BY scheduled_at ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(PROVISIONING_QUEUE)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        for job in jobs {
+            self.process_job(job).await;
+        }
+
+        Ok(())
+    }
+
+    /// Run one job and record the outcome
+    async fn process_job(&self, job: Job) {
+        let result = match serde_json::from_value::<ProvisioningJob>(job.payload) {
+            Ok(payload) => self.provision_batch(&payload).await,
+            Err(e) => Err(anyhow!("Invalid job payload: {}", e)),
+        };
+
+        let attempts = job.attempts + 1;
+        let outcome = match result {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE jobs SET status = 'completed', attempts = $2, completed_at = NOW()
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .execute(self.db_pool.pool())
+                .await
+            }
+            Err(e) if attempts < job.max_attempts => {
+                let scheduled_at = Utc::now() + calculate_backoff(attempts);
+                warn!(
+                    "Provisioning job {} failed, resuming at {}: {}",
+                    job.id, scheduled_at, e
+                );
+                sqlx::query(
+                    "UPDATE jobs SET status = 'pending', attempts = $2, last_error = $3,
+                                     scheduled_at = $4
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .bind(e.to_string())
+                .bind(scheduled_at)
+                .execute(self.db_pool.pool())
+                .await
+            }
+            Err(e) => {
+                error!("Provisioning job {} failed: {}", job.id, e);
+                sqlx::query(
+                    "UPDATE jobs SET status = 'failed', attempts = $2, last_error = $3,
+                                     completed_at = NOW()
+                     WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(attempts)
+                .bind(e.to_string())
+                .execute(self.db_pool.pool())
+                .await
+            }
+        };
+
+        if let Err(e) = outcome {
+            error!("Failed to record provisioning job {}: {}", job.id, e);
+        }
+    }
+
+    /// Provision the pending rows of a batch; rows done by an earlier
+    /// attempt are skipped
+    async fn provision_batch(&self, job: &ProvisioningJob) -> Result<()> {
+        let repository = ProvisioningRepository::new(self.db_pool.clone());
+        let Some(batch) = repository.get(job.tenant_id, job.batch_id).await? else {
+            warn!("Provisioning batch {} no longer exists", job.batch_id);
+            return Ok(());
+        };
+        let tenant = TenantRepository::new(self.db_pool.clone())
+            .find_by_id(batch.tenant_id)
+            .await?
+            .ok_or_else(|| anyhow!("Tenant {} no longer exists", batch.tenant_id))?;
+        let mode = PasswordMode::parse(&batch.password_mode).unwrap_or_default();
+        let folder_settings = FolderSettings::from_tenant_settings(&tenant.settings);
+
+        repository.start(batch.id).await?;
+        loop {
+            let rows = repository
+                .rows(batch.id, Some("pending"), ROWS_PER_QUERY, 0)
+                .await?;
+            if rows.is_empty() {
+                break;
+            }
+            for row in rows {
+                let result = self
+                    .provision_row(&tenant, &folder_settings, mode, &row)
+                    .await?;
+                repository
+                    .finish_row(
+                        batch.id,
+                        row.row_number,
+                        result.outcome,
+                        result.user_id,
+                        result.mailbox_id,
+                        result.error.as_deref(),
+                    )
+                    .await?;
+            }
+        }
+
+        let batch = repository.complete(batch.id).await?;
+        info!(
+            "Provisioning batch {} completed: {} created, {} existing, {} failed",
+            batch.id, batch.created_count, batch.existing_count, batch.failed_count
+        );
+        Ok(())
+    }
+
+    /// Provision one row in a transaction
+    ///
+    /// Problems with the row fail it; database errors are returned, so the
+    /// job retries the row later.
+    async fn provision_row(
+        &self,
+        tenant: &Tenant,
+        folder_settings: &FolderSettings,
+        mode: PasswordMode,
+        row: &ProvisioningRow,
+    ) -> Result<RowResult> {
+        let domain_name = row.email.rsplit_once('@').map_or("", |(_, domain)| domain);
+        let mut tx = self.db_pool.pool().begin().await?;
+
+        let domain =
+            sqlx::query_as::<_, Domain>("SELECT * FROM domains WHERE tenant_id = $1 AND name = $2")
+                .bind(tenant.id)
+                .bind(domain_name)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some(domain) = domain else {
+            return Ok(RowResult::failed(format!(
+                "{} is not a domain of the tenant",
+                domain_name
+            )));
+        };
+
+        let mailbox = sqlx::query_as::<_, Mailbox>(
+            "SELECT * FROM mailboxes WHERE address = $1 AND folder_path IS NULL",
+        )
+        .bind(&row.email)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(mailbox) = mailbox {
+            if mailbox.tenant_id != tenant.id {
+                return Ok(RowResult::failed("the address is already in use"));
+            }
+            return Ok(RowResult {
+                outcome: ProvisioningOutcome::Exists,
+                user_id: mailbox.user_id,
+                mailbox_id: Some(mailbox.id),
+                error: None,
+            });
+        }
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(&row.email)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let (user, invite) = match user {
+            Some(user) if user.tenant_id != tenant.id => {
+                return Ok(RowResult::failed("the address is already in use"));
+            }
+            // A user without a mailbox keeps its password
+            Some(user) => (user, None),
+            None => {
+                let invite = match (&row.password, mode) {
+                    (None, PasswordMode::Invite) => row.invite_email.clone(),
+                    _ => None,
+                };
+                let password_hash = match &row.password {
+                    Some(password) => hash_user_password(password)?,
+                    None if invite.is_some() => INVITE_PENDING_HASH.to_string(),
+                    None => return Ok(RowResult::failed("no password and no invite_email")),
+                };
+                let user = sqlx::query_as::<_, User>(
+                    r#"
+                    INSERT INTO users (id, tenant_id, email, password_hash, name, role, active,
+                                       created_at, updated_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, true, NOW(), NOW())
+                    RETURNING *
+                    "#,
+                )
+                .bind(Uuid::now_v7())
+                .bind(tenant.id)
+                .bind(&row.email)
+                .bind(&password_hash)
+                .bind(&row.name)
+                .bind(&row.role)
+                .fetch_one(&mut *tx)
+                .await?;
+                (user, invite)
+            }
+        };
+
+        let mailbox = sqlx::query_as::<_, Mailbox>(
+            r#"
+            INSERT INTO mailboxes (id, tenant_id, domain_id, user_id, address, display_name,
+                                   quota_bytes, used_bytes, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, 0, NOW(), NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant.id)
+        .bind(domain.id)
+        .bind(user.id)
+        .bind(&row.email)
+        .bind(&row.name)
+        .bind(row.quota_bytes)
+        .fetch_one(&mut *tx)
+        .await?;
+        provision_folders(&mut tx, &mailbox, folder_settings).await?;
+
+        if let Some(invite_email) = invite {
+            send_invite(&mut tx, &user, &domain, &invite_email).await?;
+        }
+
+        tx.commit().await?;
+        debug!("Provisioned {} for tenant {}", mailbox.address, tenant.id);
+
+        Ok(RowResult {
+            outcome: ProvisioningOutcome::Created,
+            user_id: Some(user.id),
+            mailbox_id: Some(mailbox.id),
+            error: None,
+        })
+    }
+}
+
+/// Store an invite for a new user and queue its email to `invite_email`
+async fn send_invite(
+    conn: &mut PgConnection,
+    user: &User,
+    domain: &Domain,
+    invite_email: &str,
+) -> Result<()> {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let expires_at = Utc::now() + Duration::days(INVITE_VALID_DAYS);
+    sqlx::query(
+        r#"
+        INSERT INTO user_invites (id, tenant_id, user_id, token_hash, email, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW())
+        "#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(user.tenant_id)
+    .bind(user.id)
+    .bind(hash_share_token(&token))
+    .bind(invite_email)
+    .bind(expires_at)
+    .execute(&mut *conn)
+    .await?;
+
+    let from = format!("postmaster@{}", domain.name);
+    let url = invite_url(&web_base_url(), &token);
+    let raw = build_invite_message(&from, invite_email, &user.email, &url, expires_at);
+    let job = DeliveryJob {
+        message_id: Uuid::now_v7(),
+        tenant_id: user.tenant_id,
+        from: from.clone(),
+        to: vec![invite_email.to_string()],
+        storage_path: String::new(),
+        raw_message_base64: Some(base64::engine::general_purpose::STANDARD.encode(&raw)),
+        require_tls: false,
+        priority: 0,
+        lane: DeliveryLane::Transactional,
+        archived: true,
+        dkim_signed: false,
+        forwarded: false,
+        queued_at: None,
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at,
+                          tenant_id)
+        VALUES ($1, 'delivery', $2, 'pending', 0, 5, NOW(), NOW(), $3)
+        "#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(serde_json::to_value(&job)?)
+    .bind(user.tenant_id)
+    .execute(&mut *conn)
+    .await?;
+
+    debug!("Queued invite for {} to {}", user.email, invite_email);
+    Ok(())
+}
+
+/// Link of an invite on the web UI
+pub fn invite_url(base_url: &str, token: &str) -> String {
+    format!("{}/invite/{}", base_url.trim_end_matches('/'), token)
+}
+
+/// Email inviting the owner of `address` to choose a password
+pub fn build_invite_message(
+    from: &str,
+    to: &str,
+    address: &str,
+    url: &str,
+    expires_at: DateTime<Utc>,
+) -> Vec<u8> {
+    let domain = from
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+
+    let mut msg = String::new();
+    msg.push_str(&format!("From: <{}>\r\n", from));
+    msg.push_str(&format!("To: <{}>\r\n", to));
+    msg.push_str(&format!("Subject: Your new mailbox {}\r\n", address));
+    msg.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+    msg.push_str(&format!("Message-ID: <{}@{}>\r\n", Uuid::now_v7(), domain));
+    msg.push_str("Auto-Submitted: auto-generated\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+    msg.push_str(&format!(
+        "A mailbox was created for you: {}\r\n\r\n\
+         Choose its password here:\r\n\
+         {}\r\n\r\n\
+         The link can be used once and expires on {}.\r\n",
+        address,
+        url,
+        expires_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+
+    msg.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(email: &str) -> BulkMailboxRow {
+        BulkMailboxRow {
+            email: email.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let rows = parse_csv(
+            "email,name,role,quota_bytes,invite_email\n\
+             alice@example.com, Alice ,tenant_admin,1048576,alice@home.example\n\
+             bob@example.com,,,,\n",
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].name.as_deref(), Some("Alice"));
+        assert_eq!(rows[0].quota_bytes, Some(1048576));
+        assert_eq!(rows[1].email, "bob@example.com");
+        assert_eq!(rows[1].name, None);
+        assert_eq!(rows[1].quota_bytes, None);
+
+        assert!(parse_csv("email,nmae\nalice@example.com,Alice\n").is_err());
+        assert!(parse_csv("email,quota_bytes\nalice@example.com,lots\n").is_err());
+        assert!(parse_csv("email\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_row() {
+        let valid = validate_row(&row(" Alice@Example.com "), PasswordMode::Generate).unwrap();
+        assert_eq!(valid.email, "alice@example.com");
+        assert_eq!(valid.role, "user");
+        assert_eq!(valid.password, None);
+
+        let mut input = row("alice@example.com");
+        input.role = Some("tenant_admin".to_string());
+        assert_eq!(
+            validate_row(&input, PasswordMode::Generate).unwrap().role,
+            "tenantadmin"
+        );
+        input.role = Some("super_admin".to_string());
+        assert!(validate_row(&input, PasswordMode::Generate).is_err());
+
+        assert!(validate_row(&row("alice"), PasswordMode::Generate).is_err());
+        assert!(validate_row(&row("alice@localhost"), PasswordMode::Generate).is_err());
+        assert!(validate_row(&row("al ice@example.com"), PasswordMode::Generate).is_err());
+
+        let mut input = row("alice@example.com");
+        input.password = Some("short".to_string());
+        assert!(validate_row(&input, PasswordMode::Generate).is_err());
+
+        let mut input = row("alice@example.com");
+        input.quota_bytes = Some(-1);
+        assert!(validate_row(&input, PasswordMode::Generate).is_err());
+    }
+
+    #[test]
+    fn test_validate_row_invite() {
+        let mut input = row("alice@example.com");
+        assert!(validate_row(&input, PasswordMode::Invite).is_err());
+
+        input.invite_email = Some("Alice@Home.example".to_string());
+        let valid = validate_row(&input, PasswordMode::Invite).unwrap();
+        assert_eq!(valid.invite_email.as_deref(), Some("alice@home.example"));
+
+        input.invite_email = Some("alice@example.com".to_string());
+        assert!(validate_row(&input, PasswordMode::Invite).is_err());
+
+        // A password makes the invite unnecessary
+        input.invite_email = None;
+        input.password = Some("correct horse".to_string());
+        assert!(validate_row(&input, PasswordMode::Invite).is_ok());
+    }
+
+    #[test]
+    fn test_generate_password() {
+        let password = generate_password();
+        assert_eq!(password.len(), GENERATED_PASSWORD_LEN);
+        assert!(password.bytes().all(|c| PASSWORD_CHARS.contains(&c)));
+        assert!(validate_user_password(&password).is_ok());
+        assert_ne!(password, generate_password());
+    }
+
+    #[test]
+    fn test_password_mode() {
+        assert_eq!(PasswordMode::parse("invite"), Some(PasswordMode::Invite));
+        assert_eq!(
+            PasswordMode::parse("generate"),
+            Some(PasswordMode::Generate)
+        );
+        assert_eq!(PasswordMode::parse("none"), None);
+        assert_eq!(PasswordMode::default().as_str(), "generate");
+    }
+
+    #[test]
+    fn test_invite_pending_hash_is_not_a_hash() {
+        assert!(argon2::PasswordHash::new(INVITE_PENDING_HASH).is_err());
+    }
+
+    #[test]
+    fn test_build_invite_message() {
+        let url = invite_url("https://mail.example.com/", "abc");
+        assert_eq!(url, "https://mail.example.com/invite/abc");
+
+        let raw = build_invite_message(
+            "postmaster@example.com",
+            "alice@home.example",
+            "alice@example.com",
+            &url,
+            Utc::now(),
+        );
+        let text = String::from_utf8(raw).unwrap();
+        assert!(text.starts_with("From: <postmaster@example.com>\r\nTo: <alice@home.example>\r\n"));
+        assert!(text.contains("Subject: Your new mailbox alice@example.com\r\n"));
+        assert!(text.contains("\r\nhttps://mail.example.com/invite/abc\r\n"));
+    }
+}
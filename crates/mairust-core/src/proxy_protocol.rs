@@ -0,0 +1,251 @@
+//! PROXY protocol (v1 and v2)
+//!
+//! Behind a load balancer every connection comes from the balancer's
+//! address. On listeners with `proxy_protocol = true` the balancer sends a
+//! PROXY header before any protocol data, carrying the client's address;
+//! that address is then used for connection limits, policies, SPF and the
+//! logs. Only peers in `server.trusted_proxies` may send the header, and a
+//! connection from any other peer is refused, so clients cannot claim an
+//! address of their choosing.
+//!
+//! A header without an address (v1 `UNKNOWN`, the v2 `LOCAL` command used
+//! by health checks, or an address family other than TCP over IPv4/IPv6)
+//! keeps the balancer's address.
+
+use anyhow::{anyhow, bail, Result};
+use ipnet::IpNet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Time allowed for the balancer to send the header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest v1 header, CRLF included
+const V1_MAX_LEN: usize = 107;
+
+/// Signature opening a v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Load balancers allowed to send PROXY headers
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Parse addresses and CIDR ranges
+    pub fn parse(networks: &[String]) -> Result<Self> {
+        let networks = networks
+            .iter()
+            .map(|network| {
+                let network = network.trim();
+                network
+                    .parse::<IpNet>()
+                    .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow!("'{}' is not an IP address or CIDR range", network))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { networks })
+    }
+
+    /// Whether `ip` is a trusted proxy
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Read the PROXY header of a connection from `peer` and return the
+    /// client's address
+    ///
+    /// Fails for untrusted peers, malformed headers and balancers that do
+    /// not send the header in time; the connection should then be closed.
+    pub async fn client_addr<R: AsyncRead + Unpin>(
+        &self,
+        stream: &mut R,
+        peer: SocketAddr,
+    ) -> Result<SocketAddr> {
+        if !self.contains(peer.ip()) {
+            bail!("{} is not a trusted proxy", peer);
+        }
+        let source = tokio::time::timeout(HEADER_TIMEOUT, read_header(stream))
+            .await
+            .map_err(|_| anyhow!("No PROXY header from {}", peer))??;
+        Ok(source.unwrap_or(peer))
+    }
+}
+
+/// Read a v1 or v2 header, returning the source address it carries
+///
+/// Exactly the header is consumed, so the protocol data that follows is
+/// left for the session.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>> {
+    // Both versions are longer than the v2 signature
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed).await?;
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut addresses = vec![0u8; len];
+        stream.read_exact(&mut addresses).await?;
+        return parse_v2(fixed[0], fixed[1], &addresses);
+    }
+
+    if !start.starts_with(b"PROXY ") {
+        bail!("Connection did not start with a PROXY header");
+    }
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            bail!("PROXY v1 header too long");
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| anyhow!("PROXY v1 header is not ASCII"))?;
+    parse_v1(line)
+}
+
+/// Parse a v1 header line without its CRLF
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| anyhow!("Invalid PROXY source address '{}'", source))?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                bail!("PROXY source address '{}' is not {}", source, family);
+            }
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| anyhow!("Invalid PROXY source port '{}'", source_port))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("Malformed PROXY v1 header '{}'", line),
+    }
+}
+
+/// Parse the version/command and family bytes and the address block of a
+/// v2 header
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        bail!("Unsupported PROXY version {}", version_command >> 4);
+    }
+    match version_command & 0x0f {
+        // LOCAL: sent by the balancer itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        command => bail!("Unknown PROXY v2 command {}", command),
+    }
+
+    // Source address, destination address, source port, destination port
+    match family {
+        // TCP over IPv4
+        0x11 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let ip = Ipv6Addr::from(octets);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(ip), port)))
+        }
+        0x11 | 0x21 => bail!("PROXY v2 address block too short"),
+        // UNSPEC, UDP and UNIX sockets carry no usable client address
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.2:40000".parse().unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::parse(&["10.0.0.0/8".to_string(), "::1".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let proxies = proxies();
+        assert!(proxies.contains("10.1.2.3".parse().unwrap()));
+        assert!(proxies.contains("::1".parse().unwrap()));
+        assert!(!proxies.contains("192.0.2.1".parse().unwrap()));
+        assert!(TrustedProxies::parse(&["10.0.0.0/40".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_v1_header() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.10 10.0.0.1 51234 25\r\nEHLO client\r\n";
+        let addr = proxies().client_addr(&mut stream, peer()).await.unwrap();
+        assert_eq!(addr, "192.0.2.10:51234".parse().unwrap());
+        // The protocol data is left unread
+        assert_eq!(stream, b"EHLO client\r\n");
+
+        let mut stream: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 443 993\r\n";
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some("[2001:db8::1]:443".parse().unwrap())
+        );
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        let addr = proxies().client_addr(&mut stream, peer()).await.unwrap();
+        assert_eq!(addr, peer());
+    }
+
+    #[tokio::test]
+    async fn test_v2_header() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0c]);
+        header.extend_from_slice(&[192, 0, 2, 10, 10, 0, 0, 1]);
+        header.extend_from_slice(&51234u16.to_be_bytes());
+        header.extend_from_slice(&143u16.to_be_bytes());
+        header.extend_from_slice(b"a1 CAPABILITY\r\n");
+
+        let mut stream = header.as_slice();
+        let addr = proxies().client_addr(&mut stream, peer()).await.unwrap();
+        assert_eq!(addr, "192.0.2.10:51234".parse().unwrap());
+        assert_eq!(stream, b"a1 CAPABILITY\r\n");
+
+        // LOCAL (health checks) keeps the balancer's address
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        let mut stream = local.as_slice();
+        let addr = proxies().client_addr(&mut stream, peer()).await.unwrap();
+        assert_eq!(addr, peer());
+    }
+
+    #[tokio::test]
+    async fn test_refused_headers() {
+        // Untrusted peers may not send a header
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.10 10.0.0.1 51234 25\r\n";
+        let untrusted = "192.0.2.99:40000".parse().unwrap();
+        assert!(proxies().client_addr(&mut stream, untrusted).await.is_err());
+
+        for header in [
+            &b"EHLO client.example.com\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 10.0.0.1 51234 25\r\n",
+            b"PROXY TCP4 192.0.2.10 10.0.0.1 51234\r\n",
+            b"PROXY TCP4 192.0.2.10 10.0.0.1 70000 25\r\n",
+        ] {
+            let mut stream = header;
+            assert!(read_header(&mut stream).await.is_err(), "{:?}", header);
+        }
+
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.extend_from_slice(&[b'x'; 200]);
+        let mut stream = long.as_slice();
+        assert!(read_header(&mut stream).await.is_err());
+    }
+}
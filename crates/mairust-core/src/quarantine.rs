@@ -0,0 +1,380 @@
+//! Quarantine - messages held out of mailboxes
+//!
+//! The virus scan, the spam filter and inbound policies can hold a message
+//! instead of delivering it. A held message is stored in a hidden folder of
+//! the mailbox it was addressed to and flagged as quarantined, with the
+//! reason in `metadata.quarantine`. Administrators list, preview, release
+//! and delete held messages through the API; releasing moves a message
+//! into the mailbox as if it had just been delivered.
+//!
+//! Users who turn on the `quarantine_digest` setting get a daily email in
+//! their inbox listing the messages held for their mailboxes.
+
+use crate::policy::PolicyEvaluationResult;
+use crate::preview::MessagePreview;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{Message, PolicyActionType};
+use mairust_storage::{
+    FileStorage, MailboxRepository, MessageRepository, QuarantineDigestRecipient,
+    QuarantineRepository, QuarantinedMessage,
+};
+use std::sync::Arc;
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Tag of quarantine digest emails
+pub const QUARANTINE_DIGEST_TAG: &str = "quarantine-digest";
+
+/// How often the digest worker looks for digests that are due
+const DIGEST_CHECK_SECS: u64 = 3600;
+
+/// Time between two digests of a user
+const DIGEST_INTERVAL_HOURS: i64 = 24;
+
+/// Most messages listed in one digest
+const DIGEST_MAX_ITEMS: usize = 100;
+
+/// Why a message was quarantined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuarantineReason {
+    /// The virus scan found a virus
+    Virus,
+    /// The spam filter asked for quarantine
+    Spam,
+    /// An inbound policy has a quarantine action
+    Policy,
+}
+
+impl QuarantineReason {
+    /// Value recorded in `metadata.quarantine.reason`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuarantineReason::Virus => "virus",
+            QuarantineReason::Spam => "spam",
+            QuarantineReason::Policy => "policy",
+        }
+    }
+}
+
+/// Metadata recorded on a quarantined message (`metadata.quarantine`)
+pub fn quarantine_metadata(
+    reason: QuarantineReason,
+    detail: &str,
+    original_mailbox_id: Uuid,
+) -> serde_json::Value {
+    serde_json::json!({
+        "reason": reason.as_str(),
+        "detail": detail,
+        "original_mailbox_id": original_mailbox_id,
+        "quarantined_at": Utc::now(),
+    })
+}
+
+/// Name of the highest priority policy that quarantines the message
+pub fn quarantining_policy(result: &PolicyEvaluationResult) -> Option<&str> {
+    if !result.quarantine {
+        return None;
+    }
+    result
+        .matches
+        .iter()
+        .find(|m| {
+            m.actions
+                .iter()
+                .any(|action| matches!(action.action_type, PolicyActionType::Quarantine))
+        })
+        .map(|m| m.policy_name.as_str())
+}
+
+/// Build the digest email of the messages held for a user, returning the
+/// raw message
+pub fn build_digest(hostname: &str, to: &str, messages: &[QuarantinedMessage]) -> Vec<u8> {
+    let now = Utc::now();
+
+    let mut msg = String::new();
+    msg.push_str(&format!("From: Quarantine <postmaster@{}>\r\n", hostname));
+    msg.push_str(&format!("To: <{}>\r\n", to));
+    msg.push_str(&format!("Subject: {}\r\n", digest_subject(messages.len())));
+    msg.push_str(&format!("Date: {}\r\n", now.to_rfc2822()));
+    msg.push_str(&format!(
+        "Message-ID: <{}@{}>\r\n",
+        Uuid::now_v7(),
+        hostname
+    ));
+    msg.push_str("Auto-Submitted: auto-generated\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+
+    msg.push_str(
+        "These messages for your mailboxes were held in quarantine and not\r\n\
+         delivered:\r\n\r\n",
+    );
+    for message in messages.iter().take(DIGEST_MAX_ITEMS) {
+        msg.push_str(&format!(
+            "{}  {}\r\n",
+            message.received_at.format("%Y-%m-%d %H:%M UTC"),
+            message.address
+        ));
+        msg.push_str(&format!(
+            "  From: {}\r\n",
+            message.from_address.as_deref().unwrap_or("(unknown)")
+        ));
+        msg.push_str(&format!(
+            "  Subject: {}\r\n",
+            message.subject.as_deref().unwrap_or("(no subject)")
+        ));
+        let reason = match (message.reason.as_deref(), message.detail.as_deref()) {
+            (Some(reason), Some(detail)) if !detail.is_empty() => {
+                format!("{} ({})", reason, detail)
+            }
+            (Some(reason), _) => reason.to_string(),
+            (None, _) => "unknown".to_string(),
+        };
+        msg.push_str(&format!("  Reason: {}\r\n\r\n", reason));
+    }
+    if messages.len() > DIGEST_MAX_ITEMS {
+        msg.push_str(&format!(
+            "... and {} more.\r\n\r\n",
+            messages.len() - DIGEST_MAX_ITEMS
+        ));
+    }
+    msg.push_str(
+        "If you were expecting one of them, ask your administrator to release\r\n\
+         it to your inbox.\r\n",
+    );
+
+    msg.into_bytes()
+}
+
+/// Subject of a digest listing `count` messages
+fn digest_subject(count: usize) -> String {
+    match count {
+        1 => "1 message held in quarantine".to_string(),
+        n => format!("{} messages held in quarantine", n),
+    }
+}
+
+/// Emails users a daily digest of their quarantined messages
+pub struct QuarantineDigestWorker<S: FileStorage> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+    /// Host name digests are sent from
+    hostname: String,
+}
+
+impl<S: FileStorage + Send + Sync + 'static> QuarantineDigestWorker<S> {
+    /// Create a worker delivering digests into `file_storage`
+    pub fn new(db_pool: DatabasePool, file_storage: Arc<S>, hostname: String) -> Self {
+        Self {
+            db_pool,
+            file_storage,
+            hostname,
+        }
+    }
+
+    /// Send digests as they fall due
+    pub async fn run(self) {
+        let mut ticker = interval(std::time::Duration::from_secs(DIGEST_CHECK_SECS));
+        info!("Quarantine digest worker started");
+
+        loop {
+            ticker.tick().await;
+            match self.send_due_digests(Utc::now()).await {
+                Ok(0) => {}
+                Ok(sent) => info!("Sent {} quarantine digests", sent),
+                Err(e) => error!("Failed to send quarantine digests: {}", e),
+            }
+        }
+    }
+
+    /// Send the digests due at `now`; returns how many were delivered
+    ///
+    /// Users with nothing held since their last digest get none, but their
+    /// next one is still a day away.
+    pub async fn send_due_digests(&self, now: DateTime<Utc>) -> Result<usize> {
+        let repo = QuarantineRepository::new(self.db_pool.clone());
+        let period = Duration::hours(DIGEST_INTERVAL_HOURS);
+
+        let mut sent = 0;
+        for recipient in repo.due_digests(now - period).await? {
+            let since = recipient.sent_at.unwrap_or(now - period);
+            match self.send_digest(&repo, &recipient, since).await {
+                Ok(true) => sent += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!(
+                        "Failed to send the quarantine digest of {}: {}",
+                        recipient.email, e
+                    );
+                    continue;
+                }
+            }
+            repo.digest_sent(recipient.user_id, now).await?;
+        }
+        Ok(sent)
+    }
+
+    /// Deliver a user's digest to their inbox; false when nothing was held
+    /// for them or they have no mailbox
+    async fn send_digest(
+        &self,
+        repo: &QuarantineRepository,
+        recipient: &QuarantineDigestRecipient,
+        since: DateTime<Utc>,
+    ) -> Result<bool> {
+        let messages = repo
+            .list_since(recipient.tenant_id, recipient.user_id, since)
+            .await?;
+        if messages.is_empty() {
+            return Ok(false);
+        }
+
+        let Some(mailbox) = MailboxRepository::new(self.db_pool.clone())
+            .find_by_address(&recipient.email)
+            .await?
+        else {
+            debug!(
+                "No mailbox to send the quarantine digest of {} to",
+                recipient.email
+            );
+            return Ok(false);
+        };
+
+        let raw = build_digest(&self.hostname, &recipient.email, &messages);
+        let preview = MessagePreview::of_raw(&raw);
+        let message_id = Uuid::now_v7();
+        let storage_path =
+            self.file_storage
+                .message_path(mailbox.tenant_id, mailbox.id, message_id);
+        self.file_storage.store(&storage_path, &raw).await?;
+
+        let message = Message {
+            id: message_id,
+            tenant_id: mailbox.tenant_id,
+            mailbox_id: mailbox.id,
+            message_id_header: None,
+            subject: Some(digest_subject(messages.len())),
+            from_address: Some(format!("postmaster@{}", self.hostname)),
+            to_addresses: serde_json::json!([recipient.email]),
+            cc_addresses: None,
+            headers: serde_json::json!({}),
+            body_preview: preview.body_preview,
+            body_size: raw.len() as i64,
+            has_attachments: false,
+            storage_path,
+            seen: false,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: None,
+            tags: serde_json::json!([QUARANTINE_DIGEST_TAG]),
+            metadata: serde_json::json!({
+                "quarantine_digest": messages.len(),
+            }),
+            received_at: Utc::now(),
+            created_at: Utc::now(),
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: None,
+            snippet: Some(preview.snippet),
+        };
+        MessageRepository::new(self.db_pool.clone())
+            .create(&message)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyMatch;
+    use mairust_storage::models::PolicyAction;
+
+    fn held(subject: &str, reason: &str, detail: &str) -> QuarantinedMessage {
+        QuarantinedMessage {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            mailbox_id: Uuid::now_v7(),
+            address: "alice@example.com".to_string(),
+            user_id: None,
+            subject: Some(subject.to_string()),
+            from_address: Some("sender@example.net".to_string()),
+            snippet: None,
+            body_size: 100,
+            has_attachments: true,
+            spam_score: None,
+            reason: Some(reason.to_string()),
+            detail: Some(detail.to_string()),
+            received_at: Utc::now(),
+            storage_path: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_quarantine_metadata() {
+        let mailbox_id = Uuid::now_v7();
+        let metadata =
+            quarantine_metadata(QuarantineReason::Virus, "Eicar-Test-Signature", mailbox_id);
+        assert_eq!(metadata["reason"], "virus");
+        assert_eq!(metadata["detail"], "Eicar-Test-Signature");
+        assert_eq!(metadata["original_mailbox_id"], mailbox_id.to_string());
+        assert!(metadata["quarantined_at"].is_string());
+    }
+
+    #[test]
+    fn test_quarantining_policy() {
+        let action = |action_type| PolicyAction {
+            action_type,
+            parameters: serde_json::json!({}),
+        };
+        let policy_match = |name: &str, priority, actions| PolicyMatch {
+            policy_id: Uuid::now_v7(),
+            policy_name: name.to_string(),
+            priority,
+            actions,
+        };
+        let mut result = PolicyEvaluationResult {
+            matches: vec![
+                policy_match("Tag newsletters", 20, vec![action(PolicyActionType::Tag)]),
+                policy_match(
+                    "Hold executables",
+                    10,
+                    vec![action(PolicyActionType::Quarantine)],
+                ),
+            ],
+            quarantine: true,
+            ..Default::default()
+        };
+        assert_eq!(quarantining_policy(&result), Some("Hold executables"));
+
+        result.quarantine = false;
+        assert_eq!(quarantining_policy(&result), None);
+    }
+
+    #[test]
+    fn test_build_digest() {
+        let messages = vec![
+            held("Invoice", "virus", "Eicar-Test-Signature"),
+            held("Cheap watches", "spam", "score 12.5"),
+        ];
+        let raw = String::from_utf8(build_digest(
+            "mx.example.com",
+            "alice@example.com",
+            &messages,
+        ))
+        .unwrap();
+        assert!(raw.contains("Subject: 2 messages held in quarantine\r\n"));
+        assert!(raw.contains("To: <alice@example.com>\r\n"));
+        assert!(raw.contains("Auto-Submitted: auto-generated\r\n"));
+        assert!(raw.contains("  Subject: Invoice\r\n"));
+        assert!(raw.contains("  Reason: virus (Eicar-Test-Signature)\r\n"));
+        assert!(raw.contains("  Reason: spam (score 12.5)\r\n"));
+        assert_eq!(digest_subject(1), "1 message held in quarantine");
+    }
+}
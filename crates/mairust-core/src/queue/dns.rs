@@ -0,0 +1,513 @@
+//! DNS cache of outbound destination domains
+//!
+//! The MX hosts of a destination, the TLSA records of those hosts
+//! (`_25._tcp.<host>`) and the domain's `_mta-sts` TXT record are resolved
+//! together and cached for the shortest of their TTLs. Without a cache every
+//! delivery pays for cold lookups; with a plain cache the first delivery
+//! after each expiry still does. The prefetcher therefore keeps the most
+//! frequent destinations warm by resolving them again shortly before their
+//! records expire. On startup it is seeded with the destinations of the
+//! last day's deliveries.
+
+use anyhow::Result;
+use mairust_common::config::DnsPrefetchConfig;
+use mairust_storage::db::DatabasePool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::op::ResponseCode;
+use trust_dns_resolver::proto::rr::RecordType;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Usage counts are halved this often, so they follow recent traffic
+const USAGE_HALF_LIFE: Duration = Duration::from_secs(600);
+
+/// Domains refreshed at once by the prefetcher
+const PREFETCH_CONCURRENCY: usize = 16;
+
+/// DNS data of one destination domain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DomainRecords {
+    /// MX hosts by preference; the domain itself when it has no MX (RFC 5321
+    /// section 5.1), none when the domain does not exist
+    pub mx_hosts: Vec<String>,
+    /// TLSA records of each MX host, in presentation format
+    pub tlsa: HashMap<String, Vec<String>>,
+    /// The `v=STSv1` TXT record of `_mta-sts.<domain>`
+    pub mta_sts: Option<String>,
+}
+
+impl DomainRecords {
+    /// Records used when the domain could not be resolved
+    fn fallback(domain: &str) -> Self {
+        Self {
+            mx_hosts: vec![domain.to_string()],
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    records: Arc<DomainRecords>,
+    expires_at: Instant,
+}
+
+/// Cached records and usage counts; times are passed in so it can be tested
+#[derive(Debug, Default)]
+pub(crate) struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    usage: HashMap<String, u64>,
+    last_decay: Option<Instant>,
+}
+
+impl CacheState {
+    /// Count a delivery to `domain` and return its records if still fresh
+    pub fn lookup(&mut self, domain: &str, now: Instant) -> Option<Arc<DomainRecords>> {
+        *self.usage.entry(domain.to_string()).or_default() += 1;
+        self.entries
+            .get(domain)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.records.clone())
+    }
+
+    /// Records of `domain`, even if expired
+    pub fn stale(&self, domain: &str) -> Option<Arc<DomainRecords>> {
+        self.entries.get(domain).map(|entry| entry.records.clone())
+    }
+
+    pub fn insert(&mut self, domain: &str, records: Arc<DomainRecords>, expires_at: Instant) {
+        self.entries.insert(
+            domain.to_string(),
+            CacheEntry {
+                records,
+                expires_at,
+            },
+        );
+    }
+
+    /// Count past deliveries to `domain`
+    pub fn seed(&mut self, domain: &str, count: u64) {
+        *self.usage.entry(domain.to_string()).or_default() += count;
+    }
+
+    /// The most used `limit` domains whose records are missing or expire
+    /// within `ahead`
+    pub fn due_for_refresh(&self, limit: usize, ahead: Duration, now: Instant) -> Vec<String> {
+        let mut domains: Vec<(&String, u64)> = self.usage.iter().map(|(d, n)| (d, *n)).collect();
+        domains.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        domains
+            .into_iter()
+            .take(limit)
+            .filter(|(domain, _)| {
+                !matches!(self.entries.get(*domain), Some(entry) if entry.expires_at > now + ahead)
+            })
+            .map(|(domain, _)| domain.clone())
+            .collect()
+    }
+
+    /// Halve the usage counts once per half-life and forget expired records
+    /// of domains no longer in use
+    pub fn decay(&mut self, now: Instant) {
+        if self
+            .last_decay
+            .is_some_and(|at| now.duration_since(at) < USAGE_HALF_LIFE)
+        {
+            return;
+        }
+        self.last_decay = Some(now);
+
+        self.usage.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+        let usage = &self.usage;
+        self.entries
+            .retain(|domain, entry| entry.expires_at > now || usage.contains_key(domain));
+    }
+}
+
+/// Domain names are cached lower-case and without the trailing dot
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Time left before a lookup expires
+fn ttl_of(valid_until: Instant) -> Duration {
+    valid_until.saturating_duration_since(Instant::now())
+}
+
+/// Mail hosts of a domain whose MX lookup found no records, with their
+/// negative-caching TTL: the domain itself when it exists, none when it
+/// does not (NXDOMAIN), so that mail to it fails at once
+fn hosts_without_mx(domain: &str, e: &ResolveError) -> Option<(Vec<String>, Duration)> {
+    let ttl = negative_ttl(e)?;
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NXDomain,
+            ..
+        } => Some((Vec::new(), ttl)),
+        _ => Some((vec![domain.to_string()], ttl)),
+    }
+}
+
+/// Negative-caching TTL of a lookup that found no records
+fn negative_ttl(e: &ResolveError) -> Option<Duration> {
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound { negative_ttl, .. } => Some(Duration::from_secs(
+            negative_ttl.map(u64::from).unwrap_or_default(),
+        )),
+        _ => None,
+    }
+}
+
+/// Shared DNS cache of the delivery queue
+pub struct DnsCache {
+    resolver: TokioAsyncResolver,
+    config: DnsPrefetchConfig,
+    state: Mutex<CacheState>,
+}
+
+impl DnsCache {
+    /// Create a cache
+    pub fn new(config: DnsPrefetchConfig) -> Self {
+        // The resolver's own cache would hand the prefetcher the records it
+        // is trying to refresh, so this cache is the only one
+        let mut opts = ResolverOpts::default();
+        opts.cache_size = 0;
+
+        Self {
+            resolver: TokioAsyncResolver::tokio(ResolverConfig::default(), opts),
+            config,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Records of a destination domain, from the cache when fresh
+    ///
+    /// When the domain cannot be resolved, expired records are used if there
+    /// are any, the domain itself as its mail host otherwise.
+    pub async fn records(&self, domain: &str) -> Arc<DomainRecords> {
+        let domain = normalize_domain(domain);
+        match self.lookup(&domain).await {
+            Ok(records) => records,
+            Err(e) => {
+                warn!("DNS lookup for {} failed: {}", domain, e);
+                self.state
+                    .lock()
+                    .unwrap()
+                    .stale(&domain)
+                    .unwrap_or_else(|| Arc::new(DomainRecords::fallback(&domain)))
+            }
+        }
+    }
+
+    /// Records of a destination domain, from the cache when fresh, or the
+    /// error of resolving it
+    pub async fn lookup(&self, domain: &str) -> Result<Arc<DomainRecords>> {
+        let domain = normalize_domain(domain);
+        if let Some(records) = self.state.lock().unwrap().lookup(&domain, Instant::now()) {
+            return Ok(records);
+        }
+        self.resolve(&domain).await
+    }
+
+    /// MX hosts of a destination domain, by preference
+    pub async fn mx_hosts(&self, domain: &str) -> Vec<String> {
+        self.records(domain).await.mx_hosts.clone()
+    }
+
+    /// Resolve a domain and cache its records
+    async fn resolve(&self, domain: &str) -> Result<Arc<DomainRecords>> {
+        let (mx_hosts, mut ttl) = match self.resolver.mx_lookup(domain).await {
+            Ok(mx) => {
+                let mut hosts: Vec<(u16, String)> = mx
+                    .iter()
+                    .map(|r| (r.preference(), r.exchange().to_string()))
+                    .collect();
+                // Sort by preference (lower is better)
+                hosts.sort_by_key(|(pref, _)| *pref);
+                (
+                    hosts.into_iter().map(|(_, host)| host).collect(),
+                    ttl_of(mx.valid_until()),
+                )
+            }
+            Err(e) => match hosts_without_mx(domain, &e) {
+                Some((hosts, ttl)) => {
+                    if hosts.is_empty() {
+                        debug!("{} does not exist", domain);
+                    } else {
+                        debug!("No MX records for {}, using its A record", domain);
+                    }
+                    (hosts, ttl)
+                }
+                None => return Err(e.into()),
+            },
+        };
+
+        // TLSA records of every MX host and the MTA-STS record, all at once
+        let mut tlsa_lookups = JoinSet::new();
+        for host in &mx_hosts {
+            let resolver = self.resolver.clone();
+            let host = host.clone();
+            tlsa_lookups.spawn(async move {
+                let name = format!("_25._tcp.{}", host);
+                (host, resolver.lookup(name, RecordType::TLSA).await)
+            });
+        }
+        let mta_sts_lookup = self.resolver.txt_lookup(format!("_mta-sts.{}", domain));
+
+        let mut records = DomainRecords {
+            mx_hosts,
+            ..DomainRecords::default()
+        };
+        match mta_sts_lookup.await {
+            Ok(txt) => {
+                records.mta_sts = txt
+                    .iter()
+                    .map(|r| {
+                        r.txt_data()
+                            .iter()
+                            .map(|part| String::from_utf8_lossy(part))
+                            .collect::<String>()
+                    })
+                    .find(|text| text.starts_with("v=STSv1"));
+                ttl = ttl.min(ttl_of(txt.valid_until()));
+            }
+            Err(e) => {
+                if let Some(negative) = negative_ttl(&e) {
+                    ttl = ttl.min(negative);
+                }
+            }
+        }
+        while let Some(joined) = tlsa_lookups.join_next().await {
+            let Ok((host, lookup)) = joined else {
+                continue;
+            };
+            match lookup {
+                Ok(tlsa) => {
+                    ttl = ttl.min(ttl_of(tlsa.valid_until()));
+                    records
+                        .tlsa
+                        .insert(host, tlsa.iter().map(|r| r.to_string()).collect());
+                }
+                Err(e) => {
+                    if let Some(negative) = negative_ttl(&e) {
+                        ttl = ttl.min(negative);
+                    }
+                }
+            }
+        }
+
+        let ttl = ttl.clamp(
+            Duration::from_secs(self.config.min_ttl_secs),
+            Duration::from_secs(self.config.max_ttl_secs.max(self.config.min_ttl_secs)),
+        );
+        let records = Arc::new(records);
+        self.state
+            .lock()
+            .unwrap()
+            .insert(domain, records.clone(), Instant::now() + ttl);
+        debug!("Cached DNS records of {} for {}s", domain, ttl.as_secs());
+
+        Ok(records)
+    }
+
+    /// Count the destinations of the last day's deliveries and resolve the
+    /// most frequent ones
+    pub async fn warm_up(self: &Arc<Self>, db_pool: &DatabasePool) -> Result<usize> {
+        let domains: Vec<(String, i64)> = sqlx::query_as(
+            r#"
+            SELECT lower(split_part(rcpt, '@', 2)) AS domain, COUNT(*) AS deliveries
+            FROM jobs, jsonb_array_elements_text(payload->'to') AS rcpt
+            WHERE queue = 'delivery' AND created_at > NOW() - INTERVAL '1 day'
+            GROUP BY 1
+            ORDER BY 2 DESC
+            LIMIT $1
+            "#,
+        )
+        .bind(self.config.prefetch_domains as i64)
+        .fetch_all(db_pool.pool())
+        .await?;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            for (domain, deliveries) in &domains {
+                if !domain.is_empty() {
+                    state.seed(&normalize_domain(domain), *deliveries as u64);
+                }
+            }
+        }
+        self.prefetch().await;
+
+        Ok(domains.len())
+    }
+
+    /// Refresh the records of frequent destinations before they expire
+    pub async fn run(self: Arc<Self>, db_pool: DatabasePool) {
+        match self.warm_up(&db_pool).await {
+            Ok(count) => info!("DNS cache warmed up with {} destination domains", count),
+            Err(e) => warn!("Failed to warm up the DNS cache: {}", e),
+        }
+
+        let mut ticker = interval(Duration::from_secs(self.config.interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            self.prefetch().await;
+            self.state.lock().unwrap().decay(Instant::now());
+        }
+    }
+
+    /// Resolve the frequent destinations that are due
+    async fn prefetch(self: &Arc<Self>) {
+        let due = self.state.lock().unwrap().due_for_refresh(
+            self.config.prefetch_domains,
+            Duration::from_secs(self.config.refresh_ahead_secs),
+            Instant::now(),
+        );
+        if due.is_empty() {
+            return;
+        }
+        debug!("Prefetching DNS records of {} domains", due.len());
+
+        for batch in due.chunks(PREFETCH_CONCURRENCY) {
+            let mut refreshes = JoinSet::new();
+            for domain in batch {
+                let cache = Arc::clone(self);
+                let domain = domain.clone();
+                refreshes.spawn(async move {
+                    // Expired records stay in use until a refresh succeeds
+                    if let Err(e) = cache.resolve(&domain).await {
+                        debug!("DNS prefetch for {} failed: {}", domain, e);
+                    }
+                });
+            }
+            while refreshes.join_next().await.is_some() {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(host: &str) -> Arc<DomainRecords> {
+        Arc::new(DomainRecords {
+            mx_hosts: vec![host.to_string()],
+            ..DomainRecords::default()
+        })
+    }
+
+    #[test]
+    fn test_lookup_freshness() {
+        let mut state = CacheState::default();
+        let now = Instant::now();
+
+        assert!(state.lookup("example.com", now).is_none());
+        state.insert(
+            "example.com",
+            records("mx1."),
+            now + Duration::from_secs(60),
+        );
+        assert_eq!(
+            state.lookup("example.com", now).unwrap().mx_hosts,
+            vec!["mx1."]
+        );
+
+        let later = now + Duration::from_secs(60);
+        assert!(state.lookup("example.com", later).is_none());
+        assert!(state.stale("example.com").is_some());
+    }
+
+    #[test]
+    fn test_due_for_refresh() {
+        let mut state = CacheState::default();
+        let now = Instant::now();
+        let ahead = Duration::from_secs(60);
+
+        state.seed("busy.example", 100);
+        state.seed("steady.example", 50);
+        state.seed("rare.example", 1);
+        state.insert("busy.example", records("a."), now + Duration::from_secs(30));
+        state.insert(
+            "steady.example",
+            records("b."),
+            now + Duration::from_secs(600),
+        );
+
+        // Expiring soon or missing, among the two most used domains
+        assert_eq!(state.due_for_refresh(2, ahead, now), vec!["busy.example"]);
+        assert_eq!(
+            state.due_for_refresh(3, ahead, now),
+            vec!["busy.example", "rare.example"]
+        );
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut state = CacheState::default();
+        let now = Instant::now();
+
+        state.seed("busy.example", 8);
+        state.seed("once.example", 1);
+        state.insert("once.example", records("a."), now + Duration::from_secs(10));
+        state.insert("gone.example", records("b."), now);
+
+        state.decay(now);
+        assert_eq!(state.usage.get("busy.example"), Some(&4));
+        assert!(!state.usage.contains_key("once.example"));
+        // Fresh records are kept, expired ones of unused domains dropped
+        assert!(state.entries.contains_key("once.example"));
+        assert!(!state.entries.contains_key("gone.example"));
+
+        // At most once per half-life
+        state.decay(now + Duration::from_secs(1));
+        assert_eq!(state.usage.get("busy.example"), Some(&4));
+    }
+
+    #[test]
+    fn test_hosts_without_mx() {
+        use trust_dns_resolver::proto::op::Query;
+        use trust_dns_resolver::proto::rr::Name;
+
+        let no_records = |response_code| {
+            ResolveError::from(ResolveErrorKind::NoRecordsFound {
+                query: Box::new(Query::query(
+                    Name::from_ascii("example.com.").unwrap(),
+                    RecordType::MX,
+                )),
+                soa: None,
+                negative_ttl: Some(300),
+                response_code,
+                trusted: true,
+            })
+        };
+
+        // The domain exists without MX records: its A record is used
+        assert_eq!(
+            hosts_without_mx("example.com", &no_records(ResponseCode::NoError)),
+            Some((vec!["example.com".to_string()], Duration::from_secs(300)))
+        );
+        // The domain does not exist
+        assert_eq!(
+            hosts_without_mx("example.com", &no_records(ResponseCode::NXDomain)),
+            Some((Vec::new(), Duration::from_secs(300)))
+        );
+        // Other failures are not cached
+        assert_eq!(
+            hosts_without_mx("example.com", &ResolveError::from("timed out")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(normalize_domain("Example.COM."), "example.com");
+    }
+}
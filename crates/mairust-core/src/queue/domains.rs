@@ -0,0 +1,95 @@
+//! Per-domain delivery concurrency
+//!
+//! Receiving servers throttle or temporarily reject senders that open many
+//! connections at once, so deliveries to one destination domain share a
+//! small number of slots across all tenants and jobs. A delivery waits for
+//! a slot of its domain; the slots of other domains are not affected.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Connection slots per destination domain
+#[derive(Debug)]
+pub(crate) struct DomainSlots {
+    limit: usize,
+    domains: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl DomainSlots {
+    /// Allow `limit` deliveries in progress per domain (at least 1)
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            domains: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a slot of `domain`, held until the permit is dropped
+    pub async fn acquire(&self, domain: &str) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut domains = self.domains.lock().unwrap();
+            // Domains nobody holds or waits for are forgotten
+            domains.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            domains
+                .entry(domain.trim_end_matches('.').to_ascii_lowercase())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+                .clone()
+        };
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("domain semaphores are never closed")
+    }
+
+    /// Domains with deliveries in progress or waiting
+    #[cfg(test)]
+    fn tracked(&self) -> usize {
+        let mut domains = self.domains.lock().unwrap();
+        domains.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+        domains.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_domain_limit() {
+        let slots = DomainSlots::new(2);
+        let first = slots.acquire("example.com").await;
+        let _second = slots.acquire("EXAMPLE.com.").await;
+
+        // The third delivery to the domain waits for a slot
+        let blocked = timeout(Duration::from_millis(50), slots.acquire("example.com")).await;
+        assert!(blocked.is_err());
+
+        // Other domains are not held up
+        let other = timeout(Duration::from_millis(50), slots.acquire("example.org")).await;
+        assert!(other.is_ok());
+
+        drop(first);
+        let freed = timeout(Duration::from_millis(50), slots.acquire("example.com")).await;
+        assert!(freed.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_idle_domains_are_forgotten() {
+        let slots = DomainSlots::new(0);
+        let permit = slots.acquire("example.com").await;
+        assert_eq!(slots.tracked(), 1);
+
+        // A limit of 0 still allows one delivery
+        assert!(
+            timeout(Duration::from_millis(50), slots.acquire("example.com"))
+                .await
+                .is_err()
+        );
+
+        drop(permit);
+        assert_eq!(slots.tracked(), 0);
+    }
+}
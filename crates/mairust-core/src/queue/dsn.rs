@@ -0,0 +1,172 @@
+//! Delivery Status Notifications (RFC 3464)
+
+use super::outbound::DeliveryError;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Build a failure DSN for `failures`, returning the raw message
+///
+/// Only the original headers are returned (`text/rfc822-headers`), which is
+/// what RFC 8689 requires when the original message carried REQUIRETLS and
+/// keeps bounces small otherwise.
+pub fn build_failure_dsn(
+    reporting_mta: &str,
+    original_sender: &str,
+    failures: &[(String, DeliveryError)],
+    original: &[u8],
+    arrival_date: DateTime<Utc>,
+) -> Vec<u8> {
+    let boundary = format!("=_dsn_{}", Uuid::now_v7().simple());
+    let now = Utc::now();
+
+    let mut msg = String::new();
+    msg.push_str(&format!("From: Mail Delivery System <MAILER-DAEMON@{}>\r\n", reporting_mta));
+    msg.push_str(&format!("To: <{}>\r\n", original_sender));
+    msg.push_str("Subject: Undelivered Mail Returned to Sender\r\n");
+    msg.push_str(&format!("Date: {}\r\n", now.to_rfc2822()));
+    msg.push_str(&format!("Message-ID: <{}@{}>\r\n", Uuid::now_v7(), reporting_mta));
+    msg.push_str("Auto-Submitted: auto-replied\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str(&format!(
+        "Content-Type: multipart/report; report-type=delivery-status; boundary=\"{}\"\r\n\r\n",
+        boundary
+    ));
+
+    // Human readable part
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+    msg.push_str(&format!(
+        "This is the mail system at host {}.\r\n\r\n\
+         Your message could not be delivered to one or more recipients.\r\n\r\n",
+        reporting_mta
+    ));
+    for (rcpt, error) in failures {
+        msg.push_str(&format!("<{}>: {}\r\n", rcpt, error));
+    }
+    msg.push_str("\r\n");
+
+    // Machine readable part
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str("Content-Type: message/delivery-status\r\n\r\n");
+    msg.push_str(&format!("Reporting-MTA: dns; {}\r\n", reporting_mta));
+    msg.push_str(&format!("Arrival-Date: {}\r\n", arrival_date.to_rfc2822()));
+    for (rcpt, error) in failures {
+        msg.push_str("\r\n");
+        msg.push_str(&format!("Final-Recipient: rfc822; {}\r\n", rcpt));
+        msg.push_str("Action: failed\r\n");
+        msg.push_str(&format!("Status: {}\r\n", error.status_code()));
+        // The remote server's own words, when it gave any
+        if let Some(reply) = error.remote_reply() {
+            msg.push_str(&format!("Remote-MTA: dns; {}\r\n", reply.host));
+            msg.push_str(&format!(
+                "Diagnostic-Code: smtp; {} {}\r\n",
+                reply.code,
+                reply.text.replace(['\r', '\n'], " ")
+            ));
+        }
+    }
+    msg.push_str("\r\n");
+
+    // Original headers
+    msg.push_str(&format!("--{}\r\n", boundary));
+    msg.push_str("Content-Type: text/rfc822-headers\r\n\r\n");
+    msg.push_str(&String::from_utf8_lossy(header_block(original)));
+    msg.push_str(&format!("\r\n--{}--\r\n", boundary));
+
+    msg.into_bytes()
+}
+
+/// Header section of a raw message (up to the first empty line)
+fn header_block(data: &[u8]) -> &[u8] {
+    if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+        return &data[..pos + 2];
+    }
+    if let Some(pos) = data.windows(2).position(|w| w == b"\n\n") {
+        return &data[..pos + 1];
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::RemoteReply;
+
+    #[test]
+    fn test_header_block() {
+        assert_eq!(header_block(b"Subject: a\r\n\r\nbody"), b"Subject: a\r\n");
+        assert_eq!(header_block(b"Subject: a\n\nbody"), b"Subject: a\n");
+        assert_eq!(header_block(b"Subject: a"), b"Subject: a");
+    }
+
+    #[test]
+    fn test_build_failure_dsn() {
+        let failures = vec![(
+            "bob@example.net".to_string(),
+            DeliveryError::TlsRequired("mx.example.net does not offer STARTTLS".to_string()),
+        )];
+        let dsn = build_failure_dsn(
+            "mail.example.com",
+            "alice@example.com",
+            &failures,
+            b"Subject: secret\r\n\r\nconfidential body",
+            Utc::now(),
+        );
+        let dsn = String::from_utf8(dsn).unwrap();
+
+        assert!(dsn.contains("report-type=delivery-status"));
+        assert!(dsn.contains("Final-Recipient: rfc822; bob@example.net"));
+        assert!(dsn.contains("Status: 5.7.30"));
+        assert!(dsn.contains("Subject: secret"));
+        assert!(!dsn.contains("confidential body"));
+        assert!(!dsn.contains("Remote-MTA"));
+    }
+
+    #[test]
+    fn test_dsn_reports_remote_reply() {
+        let rejected = DeliveryError::Rejected(RemoteReply {
+            host: "mx.example.net".to_string(),
+            stage: "RCPT TO".to_string(),
+            code: 550,
+            text: "5.1.1 <bob@example.net>: user unknown".to_string(),
+        });
+        let deferred = DeliveryError::Rejected(RemoteReply {
+            host: "mx.example.org".to_string(),
+            stage: "DATA".to_string(),
+            code: 452,
+            text: "4.2.2 mailbox full".to_string(),
+        });
+        let failures = vec![
+            ("bob@example.net".to_string(), rejected),
+            (
+                "carol@example.org".to_string(),
+                DeliveryError::Expired(Box::new(deferred)),
+            ),
+        ];
+        let dsn = build_failure_dsn(
+            "mail.example.com",
+            "alice@example.com",
+            &failures,
+            b"Subject: hello\r\nMessage-ID: <1@example.com>\r\n\r\nbody",
+            Utc::now(),
+        );
+        let dsn = String::from_utf8(dsn).unwrap();
+
+        assert!(dsn.contains(
+            "Final-Recipient: rfc822; bob@example.net\r\n\
+             Action: failed\r\n\
+             Status: 5.1.1\r\n\
+             Remote-MTA: dns; mx.example.net\r\n\
+             Diagnostic-Code: smtp; 550 5.1.1 <bob@example.net>: user unknown\r\n"
+        ));
+        // Retries ran out: delivery time expired, with the last reply
+        assert!(dsn.contains(
+            "Final-Recipient: rfc822; carol@example.org\r\n\
+             Action: failed\r\n\
+             Status: 4.4.7\r\n\
+             Remote-MTA: dns; mx.example.org\r\n\
+             Diagnostic-Code: smtp; 452 4.2.2 mailbox full\r\n"
+        ));
+        assert!(dsn.contains("Message-ID: <1@example.com>"));
+    }
+}
@@ -0,0 +1,225 @@
+//! Fair scheduling of deliveries across tenants
+//!
+//! Due jobs are dispatched weighted round-robin over tenants: each turn a
+//! tenant gets up to its weight in jobs, as long as it stays below its
+//! concurrency cap. The next dispatch pass starts after the tenant served
+//! last, so a tenant with a large backlog cannot take every free slot.
+
+use mairust_common::config::QueueConfig;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use uuid::Uuid;
+
+/// Tenant a job is scheduled under (None for jobs without a tenant)
+pub(crate) type TenantKey = Option<Uuid>;
+
+/// Round-robin state and deliveries in progress per tenant
+#[derive(Debug, Default)]
+pub(crate) struct FairScheduler {
+    in_flight: HashMap<TenantKey, usize>,
+    last_served: Option<TenantKey>,
+}
+
+impl FairScheduler {
+    /// Deliveries in progress across all tenants
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.values().sum()
+    }
+
+    /// Record that a delivery of the tenant ended (or was never started)
+    pub fn finished(&mut self, tenant: TenantKey) {
+        if let Some(count) = self.in_flight.get_mut(&tenant) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.in_flight.remove(&tenant);
+            }
+        }
+    }
+
+    /// Choose the candidates to dispatch now and count them as in progress
+    ///
+    /// Candidates of one tenant are taken in the order given. Every chosen
+    /// job must be released with [`FairScheduler::finished`].
+    #[cfg(test)]
+    pub fn select<T>(
+        &mut self,
+        candidates: Vec<(TenantKey, T)>,
+        config: &QueueConfig,
+    ) -> Vec<(TenantKey, T)> {
+        let slots = config
+            .max_concurrent_deliveries
+            .saturating_sub(self.in_flight());
+        self.select_up_to(candidates, config, slots)
+    }
+
+    /// Choose at most `slots` candidates to dispatch now, as
+    /// [`FairScheduler::select`] does
+    pub fn select_up_to<T>(
+        &mut self,
+        candidates: Vec<(TenantKey, T)>,
+        config: &QueueConfig,
+        mut slots: usize,
+    ) -> Vec<(TenantKey, T)> {
+        let mut queues: BTreeMap<TenantKey, VecDeque<T>> = BTreeMap::new();
+        for (tenant, job) in candidates {
+            queues.entry(tenant).or_default().push_back(job);
+        }
+
+        // Rotate so that the pass starts after the tenant served last
+        let mut order: Vec<TenantKey> = queues.keys().copied().collect();
+        if let Some(last) = self.last_served {
+            let start = order.partition_point(|tenant| *tenant <= last);
+            order.rotate_left(start);
+        }
+
+        let mut selected = Vec::new();
+
+        while slots > 0 {
+            let mut progressed = false;
+            for tenant in &order {
+                let Some(queue) = queues.get_mut(tenant) else {
+                    continue;
+                };
+                let running = self.in_flight.entry(*tenant).or_insert(0);
+                let cap = config.tenant_concurrency(*tenant);
+                let mut turn = config.tenant_weight(*tenant) as usize;
+
+                while turn > 0 && slots > 0 && *running < cap {
+                    let Some(job) = queue.pop_front() else {
+                        break;
+                    };
+                    selected.push((*tenant, job));
+                    *running += 1;
+                    turn -= 1;
+                    slots -= 1;
+                    progressed = true;
+                    self.last_served = Some(*tenant);
+                }
+                if *running == 0 {
+                    self.in_flight.remove(tenant);
+                }
+                if slots == 0 {
+                    break;
+                }
+            }
+            if !progressed {
+                break;
+            }
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mairust_common::config::TenantQueueConfig;
+
+    fn tenant(n: u128) -> TenantKey {
+        Some(Uuid::from_u128(n))
+    }
+
+    fn config(max_concurrent: usize, tenant_concurrency: usize) -> QueueConfig {
+        QueueConfig {
+            max_concurrent_deliveries: max_concurrent,
+            tenant_concurrency,
+            ..QueueConfig::default()
+        }
+    }
+
+    fn jobs(tenant_key: TenantKey, count: usize) -> Vec<(TenantKey, usize)> {
+        (0..count).map(|n| (tenant_key, n)).collect()
+    }
+
+    #[test]
+    fn test_round_robin_over_tenants() {
+        let mut scheduler = FairScheduler::default();
+        let mut candidates = jobs(tenant(1), 10);
+        candidates.extend(jobs(tenant(2), 2));
+        candidates.extend(jobs(None, 1));
+
+        let selected = scheduler.select(candidates, &config(4, 10));
+        let tenants: Vec<TenantKey> = selected.iter().map(|(t, _)| *t).collect();
+        assert_eq!(tenants, vec![None, tenant(1), tenant(2), tenant(1)]);
+        assert_eq!(scheduler.in_flight(), 4);
+
+        // The next pass starts after the tenant served last
+        let mut candidates = jobs(tenant(1), 5);
+        candidates.extend(jobs(tenant(2), 1));
+        let selected = scheduler.select(candidates, &config(6, 10));
+        let tenants: Vec<TenantKey> = selected.iter().map(|(t, _)| *t).collect();
+        assert_eq!(tenants, vec![tenant(2), tenant(1)]);
+    }
+
+    #[test]
+    fn test_tenant_concurrency_cap() {
+        let mut scheduler = FairScheduler::default();
+        let selected = scheduler.select(jobs(tenant(1), 10), &config(20, 3));
+        assert_eq!(selected.len(), 3);
+        assert_eq!(
+            selected.iter().map(|(_, n)| *n).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+
+        // No slot for the tenant until one of its deliveries ends
+        assert!(scheduler
+            .select(jobs(tenant(1), 10), &config(20, 3))
+            .is_empty());
+        scheduler.finished(tenant(1));
+        assert_eq!(
+            scheduler.select(jobs(tenant(1), 10), &config(20, 3)).len(),
+            1
+        );
+
+        // Other tenants are not held up by the busy one
+        assert_eq!(
+            scheduler.select(jobs(tenant(2), 10), &config(20, 3)).len(),
+            3
+        );
+        assert_eq!(scheduler.in_flight(), 6);
+    }
+
+    #[test]
+    fn test_tenant_weights() {
+        let mut queue_config = config(8, 10);
+        queue_config.tenants.push(TenantQueueConfig {
+            tenant_id: Uuid::from_u128(1),
+            weight: Some(3),
+            max_concurrent: None,
+        });
+
+        let mut scheduler = FairScheduler::default();
+        let mut candidates = jobs(tenant(1), 10);
+        candidates.extend(jobs(tenant(2), 10));
+        let selected = scheduler.select(candidates, &queue_config);
+        let tenants: Vec<TenantKey> = selected.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            tenants,
+            vec![
+                tenant(1),
+                tenant(1),
+                tenant(1),
+                tenant(2),
+                tenant(1),
+                tenant(1),
+                tenant(1),
+                tenant(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_finished_releases_slots() {
+        let mut scheduler = FairScheduler::default();
+        assert_eq!(scheduler.select(jobs(tenant(1), 5), &config(2, 5)).len(), 2);
+        assert!(scheduler
+            .select(jobs(tenant(2), 5), &config(2, 5))
+            .is_empty());
+
+        scheduler.finished(tenant(1));
+        scheduler.finished(tenant(1));
+        scheduler.finished(tenant(1));
+        assert_eq!(scheduler.in_flight(), 0);
+        assert_eq!(scheduler.select(jobs(tenant(2), 5), &config(2, 5)).len(), 2);
+    }
+}
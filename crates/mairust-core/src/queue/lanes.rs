@@ -0,0 +1,182 @@
+//! Priority lanes of the delivery queue
+//!
+//! Due jobs are dispatched lane by lane: transactional mail first, then
+//! campaign mail, then bulk mail. Each lane has its own concurrency budget
+//! and its own round-robin over tenants, so a large campaign neither takes
+//! every delivery slot nor uses up its tenant's cap for transactional mail.
+
+use super::fair::{FairScheduler, TenantKey};
+use mairust_common::config::QueueConfig;
+use mairust_common::types::DeliveryLane;
+use std::collections::HashMap;
+
+/// Fair schedulers of the delivery lanes
+#[derive(Debug, Default)]
+pub(crate) struct LaneScheduler {
+    lanes: HashMap<DeliveryLane, FairScheduler>,
+}
+
+impl LaneScheduler {
+    /// Deliveries in progress across all lanes
+    pub fn in_flight(&self) -> usize {
+        self.lanes.values().map(FairScheduler::in_flight).sum()
+    }
+
+    /// Deliveries in progress in a lane
+    #[cfg(test)]
+    fn lane_in_flight(&self, lane: DeliveryLane) -> usize {
+        self.lanes.get(&lane).map_or(0, FairScheduler::in_flight)
+    }
+
+    /// Record that a delivery of the lane and tenant ended (or was never
+    /// started)
+    pub fn finished(&mut self, lane: DeliveryLane, tenant: TenantKey) {
+        if let Some(scheduler) = self.lanes.get_mut(&lane) {
+            scheduler.finished(tenant);
+        }
+    }
+
+    /// Choose the candidates to dispatch now and count them as in progress
+    ///
+    /// Lanes are served in order, each up to its budget and the slots left
+    /// by the lanes before it. Every chosen job must be released with
+    /// [`LaneScheduler::finished`].
+    pub fn select<T>(
+        &mut self,
+        candidates: Vec<(DeliveryLane, TenantKey, T)>,
+        config: &QueueConfig,
+    ) -> Vec<(DeliveryLane, TenantKey, T)> {
+        let mut by_lane: HashMap<DeliveryLane, Vec<(TenantKey, T)>> = HashMap::new();
+        for (lane, tenant, job) in candidates {
+            by_lane.entry(lane).or_default().push((tenant, job));
+        }
+
+        let mut free = config
+            .max_concurrent_deliveries
+            .saturating_sub(self.in_flight());
+        let mut selected = Vec::new();
+
+        for lane in DeliveryLane::ALL {
+            if free == 0 {
+                break;
+            }
+            let Some(jobs) = by_lane.remove(&lane) else {
+                continue;
+            };
+
+            let scheduler = self.lanes.entry(lane).or_default();
+            let slots = config
+                .lane_concurrency(lane)
+                .saturating_sub(scheduler.in_flight())
+                .min(free);
+            let chosen = scheduler.select_up_to(jobs, config, slots);
+            free -= chosen.len();
+            selected.extend(chosen.into_iter().map(|(tenant, job)| (lane, tenant, job)));
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn tenant(n: u128) -> TenantKey {
+        Some(Uuid::from_u128(n))
+    }
+
+    fn config(max_concurrent: usize, campaign: usize, bulk: usize) -> QueueConfig {
+        let mut config = QueueConfig {
+            max_concurrent_deliveries: max_concurrent,
+            tenant_concurrency: 100,
+            ..QueueConfig::default()
+        };
+        config.lanes.transactional = max_concurrent;
+        config.lanes.campaign = campaign;
+        config.lanes.bulk = bulk;
+        config
+    }
+
+    fn jobs(
+        lane: DeliveryLane,
+        tenant_key: TenantKey,
+        count: usize,
+    ) -> Vec<(DeliveryLane, TenantKey, usize)> {
+        (0..count).map(|n| (lane, tenant_key, n)).collect()
+    }
+
+    fn lanes(selected: &[(DeliveryLane, TenantKey, usize)]) -> Vec<DeliveryLane> {
+        selected.iter().map(|(lane, _, _)| *lane).collect()
+    }
+
+    #[test]
+    fn test_transactional_first() {
+        let mut scheduler = LaneScheduler::default();
+        let mut candidates = jobs(DeliveryLane::Bulk, tenant(1), 5);
+        candidates.extend(jobs(DeliveryLane::Campaign, tenant(1), 10));
+        candidates.extend(jobs(DeliveryLane::Transactional, tenant(2), 2));
+
+        let selected = scheduler.select(candidates, &config(5, 10, 10));
+        assert_eq!(
+            lanes(&selected),
+            vec![
+                DeliveryLane::Transactional,
+                DeliveryLane::Transactional,
+                DeliveryLane::Campaign,
+                DeliveryLane::Campaign,
+                DeliveryLane::Campaign,
+            ]
+        );
+        assert_eq!(scheduler.in_flight(), 5);
+    }
+
+    #[test]
+    fn test_lane_budgets() {
+        let queue_config = config(10, 3, 1);
+        let mut scheduler = LaneScheduler::default();
+        let mut candidates = jobs(DeliveryLane::Campaign, tenant(1), 20);
+        candidates.extend(jobs(DeliveryLane::Bulk, tenant(1), 20));
+        let selected = scheduler.select(candidates, &queue_config);
+        assert_eq!(selected.len(), 4);
+        assert_eq!(scheduler.lane_in_flight(DeliveryLane::Campaign), 3);
+        assert_eq!(scheduler.lane_in_flight(DeliveryLane::Bulk), 1);
+
+        // A campaign at its budget leaves the other slots to transactional
+        // mail
+        assert!(scheduler
+            .select(jobs(DeliveryLane::Campaign, tenant(1), 20), &queue_config)
+            .is_empty());
+        let selected = scheduler.select(
+            jobs(DeliveryLane::Transactional, tenant(1), 20),
+            &queue_config,
+        );
+        assert_eq!(selected.len(), 6);
+
+        scheduler.finished(DeliveryLane::Campaign, tenant(1));
+        assert_eq!(scheduler.lane_in_flight(DeliveryLane::Campaign), 2);
+        assert_eq!(scheduler.in_flight(), 9);
+    }
+
+    #[test]
+    fn test_tenant_caps_per_lane() {
+        let mut queue_config = config(20, 20, 20);
+        queue_config.tenant_concurrency = 2;
+
+        let mut scheduler = LaneScheduler::default();
+        assert_eq!(
+            scheduler
+                .select(jobs(DeliveryLane::Campaign, tenant(1), 10), &queue_config)
+                .len(),
+            2
+        );
+
+        // The tenant's campaign does not hold up its transactional mail
+        let selected = scheduler.select(
+            jobs(DeliveryLane::Transactional, tenant(1), 10),
+            &queue_config,
+        );
+        assert_eq!(selected.len(), 2);
+    }
+}
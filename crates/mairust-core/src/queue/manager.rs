@@ -1,17 +1,43 @@
 //! Queue Manager - Handles outbound mail queue and delivery
 
+use super::dns::DnsCache;
+use super::domains::DomainSlots;
+use super::dsn::build_failure_dsn;
+use super::fair::TenantKey;
+use super::lanes::LaneScheduler;
+use super::outbound::{
+    load_tls_connector, DeliveryError, DeliveryReport, OutboundClient, OutboundEnvelope,
+    TlsRequirement,
+};
+use crate::email_auth::{DkimKeyring, Srs};
 use crate::hooks::HookManager;
+use crate::journal::{
+    Journal, JournalDirection, JournalJob, JournalRecipient, JournalVerdicts, RecipientDisposition,
+};
+use crate::preview::MessagePreview;
+use crate::sent_archive::SentArchiver;
 use anyhow::Result;
-use chrono::{Duration, Utc};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::config::{QueueConfig, SmtpConfig};
+use mairust_common::types::DeliveryLane;
 use mairust_storage::db::DatabasePool;
 use mairust_storage::file::FileStorage;
-use mairust_storage::models::Job;
+use mairust_storage::models::{Job, Message};
+use mairust_storage::repository::{MailboxRepository, MessageRepository};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::time::{interval, Duration as TokioDuration};
+use sqlx::postgres::PgListener;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration as TokioDuration};
+use tokio_rustls::TlsConnector;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Channel notified when a pending delivery job is queued or rescheduled
+const DELIVERY_QUEUE_CHANNEL: &str = "delivery_queue";
+
 /// Job payload for outbound mail delivery
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeliveryJob {
@@ -19,7 +45,36 @@ pub struct DeliveryJob {
     pub tenant_id: Uuid,
     pub from: String,
     pub to: Vec<String>,
+    /// Path of the raw message in file storage
+    #[serde(default)]
     pub storage_path: String,
+    /// Raw message, for jobs enqueued without a stored copy
+    #[serde(default)]
+    pub raw_message_base64: Option<String>,
+    /// RFC 8689 REQUIRETLS: never relay this message without verified TLS
+    #[serde(default)]
+    pub require_tls: bool,
+    /// Delivery priority within the lane (higher first)
+    #[serde(default)]
+    pub priority: i32,
+    /// Priority lane; transactional mail is delivered first
+    #[serde(default)]
+    pub lane: DeliveryLane,
+    /// Sent-mail archive copies were already filed
+    #[serde(default)]
+    pub archived: bool,
+    /// The message was DKIM-signed before it was queued; others are
+    /// signed at each delivery attempt
+    #[serde(default)]
+    pub dkim_signed: bool,
+    /// The message is forwarded for a sender of another domain; its
+    /// envelope sender is rewritten with SRS when that is configured
+    #[serde(default)]
+    pub forwarded: bool,
+    /// When the message was first queued. Its queue lifetime runs from
+    /// then, also for the retries of part of its recipients.
+    #[serde(default)]
+    pub queued_at: Option<DateTime<Utc>>,
 }
 
 /// Queue Manager for handling mail delivery
@@ -28,6 +83,17 @@ pub struct QueueManager<S: FileStorage> {
     file_storage: Arc<S>,
     #[allow(dead_code)]
     hook_manager: Arc<HookManager>,
+    smtp_config: SmtpConfig,
+    queue_config: QueueConfig,
+    scheduler: Mutex<LaneScheduler>,
+    /// Connections in progress per lane and destination domain
+    domain_slots: HashMap<DeliveryLane, DomainSlots>,
+    /// Woken when a delivery ends so that its slot is refilled right away
+    slot_freed: Notify,
+    journal: Journal,
+    dns: Arc<DnsCache>,
+    /// Connector of outbound STARTTLS, built once from the system CA bundle
+    tls_connector: Option<TlsConnector>,
 }
 
 impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
@@ -41,47 +107,166 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
             db_pool,
             file_storage,
             hook_manager,
+            smtp_config: SmtpConfig::default(),
+            queue_config: QueueConfig::default(),
+            scheduler: Mutex::new(LaneScheduler::default()),
+            domain_slots: lane_domain_slots(QueueConfig::default().domain_concurrency),
+            slot_freed: Notify::new(),
+            journal: Journal::default(),
+            dns: Arc::new(DnsCache::new(Default::default())),
+            tls_connector: load_tls_connector(),
         }
     }
 
+    /// Use the SMTP configuration for outbound HELO names and the reporting
+    /// MTA name of DSNs
+    pub fn with_smtp_config(mut self, smtp_config: SmtpConfig) -> Self {
+        self.smtp_config = smtp_config;
+        self
+    }
+
+    /// Use the queue configuration for concurrency caps, lane budgets and
+    /// tenant weights
+    pub fn with_queue_config(mut self, queue_config: QueueConfig) -> Self {
+        self.domain_slots = lane_domain_slots(queue_config.domain_concurrency);
+        self.queue_config = queue_config;
+        self
+    }
+
+    /// Journal the final outcome of each outbound recipient
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    /// Resolve destinations through a shared DNS cache, e.g. one kept warm
+    /// by its prefetcher
+    pub fn with_dns_cache(mut self, dns: Arc<DnsCache>) -> Self {
+        self.dns = dns;
+        self
+    }
+
     /// Run the queue processor
-    pub async fn run(&self) {
-        let mut ticker = interval(TokioDuration::from_secs(5));
+    ///
+    /// Dispatches due jobs, then sleeps until the next job comes due, a
+    /// delivery slot is freed or a job is queued.
+    pub async fn run(self: Arc<Self>) {
+        let mut listener = self.listen().await;
 
-        info!("Queue processor started");
+        info!(
+            "Queue processor started (max {} concurrent deliveries, {} per tenant)",
+            self.queue_config.max_concurrent_deliveries, self.queue_config.tenant_concurrency
+        );
 
         loop {
-            ticker.tick().await;
-
-            if let Err(e) = self.process_pending_jobs().await {
+            if let Err(e) = self.dispatch_pending_jobs().await {
                 error!("Error processing queue: {}", e);
             }
+
+            let wait = self.next_wakeup().await;
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = self.slot_freed.notified() => {}
+                result = next_notification(&mut listener) => {
+                    if let Err(e) = result {
+                        warn!("Lost delivery queue notifications, polling instead: {}", e);
+                        listener = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Listen for delivery jobs queued by any process; without it the
+    /// queue is only scanned at the poll interval
+    async fn listen(&self) -> Option<PgListener> {
+        let result = async {
+            let mut listener = PgListener::connect_with(self.db_pool.pool()).await?;
+            listener.listen(DELIVERY_QUEUE_CHANNEL).await?;
+            Ok::<_, sqlx::Error>(listener)
+        }
+        .await;
+
+        match result {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                warn!("Failed to listen for delivery queue notifications: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Time until the next pending job comes due, at most the poll interval
+    ///
+    /// Jobs that are already due but could not be dispatched wait for a
+    /// free slot instead.
+    async fn next_wakeup(&self) -> TokioDuration {
+        let poll = TokioDuration::from_secs(self.queue_config.poll_interval_secs.max(1));
+
+        let next: Result<Option<DateTime<Utc>>, sqlx::Error> = sqlx::query_scalar(
+            r#"
+            SELECT MIN(scheduled_at) FROM jobs
+            WHERE status = 'pending'
+            AND queue = 'delivery'
+            AND scheduled_at > NOW()
+            "#,
+        )
+        .fetch_one(self.db_pool.pool())
+        .await;
+
+        match next {
+            Ok(Some(at)) => (at - Utc::now()).to_std().unwrap_or_default().min(poll),
+            Ok(None) => poll,
+            Err(e) => {
+                error!("Failed to find the next due delivery: {}", e);
+                poll
+            }
         }
     }
 
     /// Enqueue a delivery job
     pub async fn enqueue_delivery(&self, job: DeliveryJob) -> Result<Uuid> {
-        let job_id = Uuid::now_v7();
+        let job_id = self
+            .insert_job(&job, "pending", 0, Utc::now(), None)
+            .await?;
+
+        info!("Enqueued delivery job {}", job_id);
+        Ok(job_id)
+    }
 
+    /// Insert a delivery job with a status and its next attempt time
+    async fn insert_job(
+        &self,
+        job: &DeliveryJob,
+        status: &str,
+        attempts: i32,
+        scheduled_at: DateTime<Utc>,
+        last_error: Option<&str>,
+    ) -> Result<Uuid> {
         let db_job = Job {
-            id: job_id,
+            id: Uuid::now_v7(),
             queue: "delivery".to_string(),
-            payload: serde_json::to_value(&job)?,
-            status: "pending".to_string(),
-            attempts: 0,
+            payload: serde_json::to_value(job)?,
+            status: status.to_string(),
+            attempts,
             max_attempts: 5,
-            last_error: None,
-            scheduled_at: Utc::now(),
+            last_error: last_error.map(str::to_string),
+            scheduled_at,
             started_at: None,
             completed_at: None,
             created_at: Utc::now(),
+            tenant_id: Some(job.tenant_id),
+            priority: job.priority,
+            lane: job.lane.to_string(),
         };
 
         let pool = self.db_pool.pool();
         sqlx::query(
             r#"
-            INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, last_error,
+                              scheduled_at, completed_at, created_at, tenant_id, priority, lane)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8,
+                    CASE WHEN $4 = 'dead_letter' THEN NOW() END, $9, $10, $11, $12)
             "#,
         )
         .bind(db_job.id)
@@ -90,54 +275,119 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
         .bind(&db_job.status)
         .bind(db_job.attempts)
         .bind(db_job.max_attempts)
+        .bind(&db_job.last_error)
         .bind(db_job.scheduled_at)
         .bind(db_job.created_at)
+        .bind(db_job.tenant_id)
+        .bind(db_job.priority)
+        .bind(&db_job.lane)
         .execute(pool)
         .await?;
 
-        info!("Enqueued delivery job {}", job_id);
-        Ok(job_id)
+        Ok(db_job.id)
     }
 
-    /// Process pending jobs
-    async fn process_pending_jobs(&self) -> Result<()> {
-        let pool = self.db_pool.pool();
+    /// Dispatch due jobs lane by lane, round-robin over tenants, into free
+    /// delivery slots
+    async fn dispatch_pending_jobs(self: &Arc<Self>) -> Result<()> {
+        let free = {
+            let scheduler = self.scheduler.lock().unwrap();
+            self.queue_config
+                .max_concurrent_deliveries
+                .saturating_sub(scheduler.in_flight())
+        };
+        if free == 0 {
+            return Ok(());
+        }
+
+        // The first jobs of every tenant with due mail in each lane; no
+        // tenant can be given more than its cap, so a deeper look is not
+        // needed
+        let per_tenant = self
+            .queue_config
+            .tenants
+            .iter()
+            .filter_map(|t| t.max_concurrent)
+            .fold(self.queue_config.tenant_concurrency, usize::max)
+            .min(self.queue_config.max_concurrent_deliveries);
 
-        // Fetch pending jobs that are due
+        let pool = self.db_pool.pool();
         let jobs: Vec<Job> = sqlx::query_as(
             r#"
-            SELECT * FROM jobs
-            WHERE status = 'pending'
-            AND queue = 'delivery'
-            AND scheduled_at <= NOW()
-            ORDER BY scheduled_at ASC
-            LIMIT 10
-            FOR UPDATE SKIP LOCKED
+            SELECT * FROM (
+                SELECT jobs.*,
+                       ROW_NUMBER() OVER (
+                           PARTITION BY lane, tenant_id ORDER BY priority DESC, scheduled_at ASC
+                       ) AS tenant_rank
+                FROM jobs
+                WHERE status = 'pending'
+                AND queue = 'delivery'
+                AND scheduled_at <= NOW()
+            ) AS due
+            WHERE tenant_rank <= $1
+            ORDER BY priority DESC, scheduled_at ASC
             "#,
         )
+        .bind(per_tenant as i64)
         .fetch_all(pool)
         .await?;
 
-        for job in jobs {
-            self.process_job(job).await;
+        let selected = {
+            let mut scheduler = self.scheduler.lock().unwrap();
+            scheduler.select(
+                jobs.into_iter()
+                    .map(|job| (DeliveryLane::from_name(&job.lane), job.tenant_id, job))
+                    .collect(),
+                &self.queue_config,
+            )
+        };
+
+        for (lane, tenant, job) in selected {
+            match self.mark_job_started(job.id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    // Claimed by another queue processor
+                    self.release_slot(lane, tenant);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Failed to mark job {} as started: {}", job.id, e);
+                    self.release_slot(lane, tenant);
+                    continue;
+                }
+            }
+
+            debug!(
+                "Dispatching {} job {} for tenant {:?} after {}s in queue",
+                lane,
+                job.id,
+                tenant,
+                (Utc::now() - job.scheduled_at).num_seconds().max(0)
+            );
+
+            let manager = Arc::clone(self);
+            tokio::spawn(async move {
+                manager.process_job(job).await;
+                manager.release_slot(lane, tenant);
+                manager.slot_freed.notify_one();
+            });
         }
 
         Ok(())
     }
 
-    /// Process a single job
+    /// Give a delivery slot of a lane and tenant back to the scheduler
+    fn release_slot(&self, lane: DeliveryLane, tenant: TenantKey) {
+        self.scheduler.lock().unwrap().finished(lane, tenant);
+    }
+
+    /// Process a single job that has been marked as started
     async fn process_job(&self, job: Job) {
         let job_id = job.id;
         debug!("Processing job {}", job_id);
 
-        // Mark job as in progress
-        if let Err(e) = self.mark_job_started(job_id).await {
-            error!("Failed to mark job {} as started: {}", job_id, e);
-            return;
-        }
-
         // Parse job payload
-        let delivery_job: DeliveryJob = match serde_json::from_value(job.payload) {
+        let mut delivery_job: DeliveryJob = match serde_json::from_value(job.payload) {
             Ok(j) => j,
             Err(e) => {
                 error!("Failed to parse job {} payload: {}", job_id, e);
@@ -146,8 +396,23 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
             }
         };
 
+        // Jobs queued before lifetimes were tracked count from their creation
+        delivery_job.queued_at.get_or_insert(job.created_at);
+        let attempts = job.attempts + 1;
+
+        // Mail sent through the API is archived on its first attempt;
+        // bounces (null sender) are not
+        if !delivery_job.archived && !delivery_job.from.is_empty() {
+            if let Err(e) = self.archive_sent(job_id, &mut delivery_job).await {
+                warn!(
+                    "Failed to archive message {}: {}",
+                    delivery_job.message_id, e
+                );
+            }
+        }
+
         // Execute delivery
-        match self.deliver_message(&delivery_job).await {
+        match self.deliver_message(&delivery_job, attempts).await {
             Ok(()) => {
                 info!("Job {} completed successfully", job_id);
                 if let Err(e) = self.mark_job_completed(job_id).await {
@@ -157,27 +422,185 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
             Err(e) => {
                 warn!("Job {} failed: {}", job_id, e);
 
-                let attempts = job.attempts + 1;
-                if attempts >= job.max_attempts {
-                    error!("Job {} exceeded max attempts, marking as failed", job_id);
-                    let _ = self.mark_job_failed(job_id, &e.to_string()).await;
-                } else {
-                    // Schedule retry with exponential backoff
-                    let delay = calculate_backoff(attempts);
-                    let _ = self.schedule_retry(job_id, attempts, &e.to_string(), delay).await;
+                match self.next_attempt_at(&delivery_job, attempts) {
+                    Some(next_attempt_at) => {
+                        let _ = self
+                            .schedule_retry(job_id, attempts, &e.to_string(), next_attempt_at)
+                            .await;
+                    }
+                    None => {
+                        error!(
+                            "Job {} exceeded its queue lifetime, moving it to dead letters",
+                            job_id
+                        );
+                        let _ = self
+                            .mark_job_dead_letter(job_id, attempts, &e.to_string())
+                            .await;
+                        let last = match e.downcast_ref::<DeliveryError>() {
+                            Some(last) => last.clone(),
+                            None => DeliveryError::Temporary(e.to_string()),
+                        };
+                        self.expire_job(&delivery_job, last).await;
+                    }
                 }
             }
         }
     }
 
-    /// Deliver a message
-    async fn deliver_message(&self, job: &DeliveryJob) -> Result<()> {
-        // Read message from storage
-        let _data = self.file_storage.retrieve(&job.storage_path).await?;
+    /// When to retry a job after a failed attempt; None once the retry
+    /// would fall beyond the message's queue lifetime
+    fn next_attempt_at(&self, job: &DeliveryJob, attempts: i32) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        let delay = retry_delay(
+            &self.queue_config.retry_schedule_secs,
+            attempts,
+            self.queue_config.retry_jitter_percent,
+            jitter_roll(),
+        );
+        let expires_at = job.queued_at.unwrap_or(now)
+            + Duration::hours(self.queue_config.max_lifetime_hours as i64);
+        Some(now + delay).filter(|at| *at <= expires_at)
+    }
+
+    /// File the sender's archive copies and add remote archives to the
+    /// recipients, recording in the job that this was done
+    async fn archive_sent(&self, job_id: Uuid, job: &mut DeliveryJob) -> Result<()> {
+        let data = self.load_message(job).await?;
+        let bcc = SentArchiver::new(self.db_pool.clone())
+            .archive(
+                self.file_storage.as_ref(),
+                job.tenant_id,
+                &job.from,
+                &job.to,
+                &data,
+            )
+            .await?;
+        job.to.extend(bcc);
+        job.archived = true;
+
+        sqlx::query("UPDATE jobs SET payload = $2 WHERE id = $1")
+            .bind(job_id)
+            .bind(serde_json::to_value(&*job)?)
+            .execute(self.db_pool.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Bounce and journal the recipients of a job that exceeded its queue
+    /// lifetime
+    async fn expire_job(&self, job: &DeliveryJob, last_error: DeliveryError) {
+        let data = match self.load_message(job).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to load message {} to bounce: {}", job.message_id, e);
+                return;
+            }
+        };
+
+        let failures: Vec<(String, DeliveryError)> = job
+            .to
+            .iter()
+            .map(|rcpt| {
+                let error = DeliveryError::Expired(Box::new(last_error.clone()));
+                (rcpt.clone(), error)
+            })
+            .collect();
+        if let Err(e) = self.send_bounce(job, &data, &failures).await {
+            error!("Failed to send DSN for message {}: {}", job.message_id, e);
+        }
+
+        if self.journal.records(JournalDirection::Outbound) {
+            let failed = failures
+                .iter()
+                .map(|(rcpt, e)| {
+                    JournalRecipient::new(rcpt.as_str(), RecipientDisposition::Failed)
+                        .with_detail(e.to_string())
+                })
+                .collect();
+            self.journal_outbound(job, &data, failed).await;
+        }
+    }
+
+    /// Read the message of a job
+    async fn load_message(&self, job: &DeliveryJob) -> Result<Vec<u8>> {
+        match &job.raw_message_base64 {
+            Some(encoded) => Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?),
+            None => Ok(self.file_storage.retrieve(&job.storage_path).await?),
+        }
+    }
+
+    /// Journal the outcome for recipients that will not be retried
+    async fn journal_outbound(
+        &self,
+        job: &DeliveryJob,
+        data: &[u8],
+        recipients: Vec<JournalRecipient>,
+    ) {
+        let verdicts = JournalVerdicts {
+            require_tls: job.require_tls,
+            ..JournalVerdicts::default()
+        };
+        let journal_job = JournalJob::new(
+            JournalDirection::Outbound,
+            job.message_id,
+            Some(job.tenant_id),
+            Some(job.from.clone()),
+            recipients,
+            verdicts,
+        );
+        self.journal
+            .record(&self.db_pool, self.file_storage.as_ref(), journal_job, data)
+            .await;
+    }
+
+    /// Sign a message for its From domain; it goes out unsigned when it
+    /// cannot be
+    async fn dkim_sign(&self, job: &DeliveryJob, data: Vec<u8>) -> Vec<u8> {
+        match DkimKeyring::new(self.db_pool.clone())
+            .sign(job.tenant_id, &data)
+            .await
+        {
+            Ok(Some(signed)) => signed,
+            Ok(None) => data,
+            Err(e) => {
+                warn!("Failed to DKIM-sign message {}: {}", job.message_id, e);
+                data
+            }
+        }
+    }
+
+    /// Envelope sender of a delivery; forwarded mail gets an SRS address
+    fn return_path(&self, job: &DeliveryJob) -> String {
+        if !job.forwarded {
+            return job.from.clone();
+        }
+        match Srs::from_config(&self.smtp_config.srs, &self.smtp_config.hostname) {
+            Some(srs) => srs.forward(&job.from, Utc::now()),
+            None => job.from.clone(),
+        }
+    }
+
+    /// Deliver a message; `attempts` counts this attempt
+    async fn deliver_message(&self, job: &DeliveryJob, attempts: i32) -> Result<()> {
+        let data = self.load_message(job).await?;
+        let data = if job.dkim_signed {
+            data
+        } else {
+            self.dkim_sign(job, data).await
+        };
 
         // Execute pre_send hooks
         // Note: In production, we'd load the full message and execute hooks
 
+        let tls = if job.require_tls || requests_tls(&data) {
+            TlsRequirement::Required
+        } else {
+            TlsRequirement::Opportunistic
+        };
+
+        let return_path = self.return_path(job);
+
         // Group recipients by domain
         let mut by_domain: std::collections::HashMap<String, Vec<&str>> =
             std::collections::HashMap::new();
@@ -191,10 +614,105 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
             }
         }
 
+        let mut failures: Vec<(String, DeliveryError)> = Vec::new();
+        let mut deferred: Vec<String> = Vec::new();
+        let mut last_temporary: Option<DeliveryError> = None;
+        let mut delivered: Vec<JournalRecipient> = Vec::new();
+
         // Deliver to each domain
         for (domain, recipients) in by_domain {
-            self.deliver_to_domain(&domain, &recipients, &job.from, job)
+            match self
+                .deliver_to_domain(job.lane, &domain, &recipients, &return_path, &data, tls)
+                .await
+            {
+                Ok(report) => {
+                    delivered.extend(report.accepted.iter().map(|rcpt| {
+                        JournalRecipient::new(rcpt.as_str(), RecipientDisposition::Delivered)
+                            .with_detail(report.host.clone())
+                    }));
+                    for (rcpt, e) in report.rejected {
+                        if e.is_permanent() {
+                            failures.push((rcpt, e));
+                        } else {
+                            deferred.push(rcpt);
+                            last_temporary = Some(e);
+                        }
+                    }
+                }
+                Err(e) if e.is_permanent() => {
+                    warn!("Delivery to {} failed permanently: {}", domain, e);
+                    failures.extend(recipients.iter().map(|r| (r.to_string(), e.clone())));
+                }
+                Err(e) => {
+                    deferred.extend(recipients.iter().map(|r| r.to_string()));
+                    last_temporary = Some(e);
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            if let Err(e) = self.send_bounce(job, &data, &failures).await {
+                error!("Failed to send DSN for message {}: {}", job.message_id, e);
+            }
+        }
+
+        // Deferred recipients are journaled once their retries end
+        if self.journal.records(JournalDirection::Outbound)
+            && (!delivered.is_empty() || !failures.is_empty())
+        {
+            delivered.extend(failures.iter().map(|(rcpt, e)| {
+                JournalRecipient::new(rcpt.as_str(), RecipientDisposition::Failed)
+                    .with_detail(e.to_string())
+            }));
+            self.journal_outbound(job, &data, delivered).await;
+        }
+
+        if deferred.is_empty() {
+            return Ok(());
+        }
+
+        let e = last_temporary
+            .unwrap_or_else(|| DeliveryError::Temporary("Delivery deferred".to_string()));
+
+        // Nothing was delivered: retry the whole job, keeping the last
+        // error for the DSN if its lifetime runs out
+        if deferred.len() == job.to.len() {
+            return Err(e.into());
+        }
+
+        // Partial delivery: retry only the deferred recipients, on the
+        // schedule of this job
+        let retry = DeliveryJob {
+            to: deferred,
+            ..job.clone()
+        };
+        match self.next_attempt_at(job, attempts) {
+            Some(next_attempt_at) => {
+                self.insert_job(
+                    &retry,
+                    "pending",
+                    attempts,
+                    next_attempt_at,
+                    Some(&e.to_string()),
+                )
                 .await?;
+            }
+            None => {
+                let job_id = self
+                    .insert_job(
+                        &retry,
+                        "dead_letter",
+                        attempts,
+                        Utc::now(),
+                        Some(&e.to_string()),
+                    )
+                    .await?;
+                warn!(
+                    "Deferred recipients of message {} exceeded the queue lifetime (job {})",
+                    job.message_id, job_id
+                );
+                self.expire_job(&retry, e).await;
+            }
         }
 
         Ok(())
@@ -203,75 +721,182 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
     /// Deliver message to a specific domain
     async fn deliver_to_domain(
         &self,
+        lane: DeliveryLane,
         domain: &str,
-        _recipients: &[&str],
-        _from: &str,
-        _job: &DeliveryJob,
-    ) -> Result<()> {
-        // Resolve MX records
-        let _mx_hosts = self.resolve_mx(domain).await?;
+        recipients: &[&str],
+        from: &str,
+        data: &[u8],
+        tls: TlsRequirement,
+    ) -> Result<DeliveryReport, DeliveryError> {
+        let mx_hosts = self
+            .resolve_mx(domain)
+            .await
+            .map_err(|e| DeliveryError::Temporary(e.to_string()))?;
+
+        // A single MX of "." says the domain accepts no mail (RFC 7505)
+        if is_null_mx(&mx_hosts) {
+            return Err(DeliveryError::Permanent(format!(
+                "{} does not accept mail (null MX)",
+                domain
+            )));
+        }
+        // No hosts at all: the domain does not exist (NXDOMAIN)
+        if mx_hosts.is_empty() {
+            return Err(DeliveryError::Permanent(format!(
+                "{} does not exist",
+                domain
+            )));
+        }
 
-        // For Phase 1, we'll just log the delivery attempt
-        // In production, we'd connect to the MX and deliver via SMTP
-        info!("Would deliver to domain {} (MX resolution done)", domain);
+        let sender_domain = from.rsplit('@').next().unwrap_or_default();
+        let client = OutboundClient::with_tls_connector(
+            self.smtp_config.outbound_helo(sender_domain),
+            self.tls_connector.clone(),
+        );
+        let envelope = OutboundEnvelope {
+            from,
+            recipients,
+            data,
+            tls,
+        };
 
-        // TODO: Implement actual SMTP delivery
-        // 1. Connect to MX host
-        // 2. Send EHLO
-        // 3. STARTTLS if supported
-        // 4. MAIL FROM / RCPT TO / DATA
-        // 5. Handle response codes
+        // Wait for a connection slot of the domain in the lane, so that
+        // transactional mail does not queue behind a campaign to the domain
+        let _slot = self.domain_slots[&lane].acquire(domain).await;
 
-        Ok(())
+        // Every MX is tried in turn, as one host's rejection may not be
+        // shared by the others
+        let mut failures = Vec::with_capacity(mx_hosts.len());
+        for host in &mx_hosts {
+            match client.send(host, &envelope).await {
+                Ok(report) => return Ok(report),
+                Err(e) => {
+                    warn!("Delivery to {} via {} failed: {}", domain, host, e);
+                    failures.push(e);
+                }
+            }
+        }
+
+        Err(mx_failure(failures))
     }
 
-    /// Resolve MX records for a domain
-    async fn resolve_mx(&self, domain: &str) -> Result<Vec<String>> {
-        use trust_dns_resolver::config::*;
-        use trust_dns_resolver::TokioAsyncResolver;
+    /// Return a failure DSN to the sender
+    pub(crate) async fn send_bounce(
+        &self,
+        job: &DeliveryJob,
+        original: &[u8],
+        failures: &[(String, DeliveryError)],
+    ) -> Result<()> {
+        // Never bounce a bounce
+        if job.from.is_empty() {
+            warn!(
+                "Dropping DSN for message {} with null sender",
+                job.message_id
+            );
+            return Ok(());
+        }
 
-        let resolver = TokioAsyncResolver::tokio(
-            ResolverConfig::default(),
-            ResolverOpts::default(),
+        let dsn = build_failure_dsn(
+            &self.smtp_config.hostname,
+            &job.from,
+            failures,
+            original,
+            Utc::now(),
         );
+        let dsn_id = Uuid::now_v7();
+
+        let mailbox_repo = MailboxRepository::new(self.db_pool.clone());
+        let Some(mailbox) = mailbox_repo.find_by_address(&job.from).await? else {
+            // Remote sender: relay the DSN with a null return path
+            self.enqueue_delivery(DeliveryJob {
+                message_id: dsn_id,
+                tenant_id: job.tenant_id,
+                from: String::new(),
+                to: vec![job.from.clone()],
+                storage_path: String::new(),
+                raw_message_base64: Some(base64::engine::general_purpose::STANDARD.encode(&dsn)),
+                require_tls: job.require_tls,
+                priority: job.priority,
+                lane: job.lane,
+                archived: true,
+                dkim_signed: false,
+                forwarded: false,
+                queued_at: None,
+            })
+            .await?;
+            return Ok(());
+        };
 
-        let mx_response = resolver.mx_lookup(domain).await;
+        let storage_path = self
+            .file_storage
+            .message_path(mailbox.tenant_id, mailbox.id, dsn_id);
+        self.file_storage.store(&storage_path, &dsn).await?;
+        let preview = MessagePreview::of_raw(&dsn);
 
-        match mx_response {
-            Ok(mx) => {
-                let mut hosts: Vec<(u16, String)> = mx
-                    .iter()
-                    .map(|r| (r.preference(), r.exchange().to_string()))
-                    .collect();
+        let message = Message {
+            id: dsn_id,
+            tenant_id: mailbox.tenant_id,
+            mailbox_id: mailbox.id,
+            message_id_header: None,
+            subject: Some("Undelivered Mail Returned to Sender".to_string()),
+            from_address: Some(format!("MAILER-DAEMON@{}", self.smtp_config.hostname)),
+            to_addresses: serde_json::json!([job.from]),
+            cc_addresses: None,
+            headers: serde_json::json!({}),
+            body_preview: preview.body_preview,
+            body_size: dsn.len() as i64,
+            has_attachments: false,
+            storage_path,
+            seen: false,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: None,
+            tags: serde_json::json!(["dsn"]),
+            metadata: serde_json::json!({
+                "dsn_for": job.message_id,
+            }),
+            received_at: Utc::now(),
+            created_at: Utc::now(),
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: None,
+            snippet: Some(preview.snippet),
+        };
 
-                // Sort by preference (lower is better)
-                hosts.sort_by_key(|(pref, _)| *pref);
+        MessageRepository::new(self.db_pool.clone())
+            .create(&message)
+            .await?;
 
-                Ok(hosts.into_iter().map(|(_, host)| host).collect())
-            }
-            Err(e) => {
-                // If no MX, try A record
-                warn!("No MX records for {}, falling back to A record: {}", domain, e);
-                Ok(vec![domain.to_string()])
-            }
-        }
+        info!(
+            "Delivered DSN {} for message {} to {}",
+            dsn_id, job.message_id, job.from
+        );
+
+        Ok(())
+    }
+
+    /// Resolve MX records for a domain
+    async fn resolve_mx(&self, domain: &str) -> Result<Vec<String>> {
+        Ok(self.dns.mx_hosts(domain).await)
     }
 
-    /// Mark a job as started
-    async fn mark_job_started(&self, job_id: Uuid) -> Result<()> {
+    /// Mark a pending job as started; false if another processor claimed it
+    async fn mark_job_started(&self, job_id: Uuid) -> Result<bool> {
         let pool = self.db_pool.pool();
-        sqlx::query(
+        let result = sqlx::query(
             r#"
             UPDATE jobs
             SET status = 'processing', started_at = NOW()
-            WHERE id = $1
+            WHERE id = $1 AND status = 'pending'
             "#,
         )
         .bind(job_id)
         .execute(pool)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
     /// Mark a job as completed
@@ -309,16 +934,34 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
         Ok(())
     }
 
+    /// Move a job that exceeded its queue lifetime to the dead-letter state
+    async fn mark_job_dead_letter(&self, job_id: Uuid, attempts: i32, error: &str) -> Result<()> {
+        let pool = self.db_pool.pool();
+        sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = 'dead_letter', attempts = $2, last_error = $3, completed_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(job_id)
+        .bind(attempts)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
     /// Schedule a job retry
     async fn schedule_retry(
         &self,
         job_id: Uuid,
         attempts: i32,
         error: &str,
-        delay: Duration,
+        scheduled_at: DateTime<Utc>,
     ) -> Result<()> {
         let pool = self.db_pool.pool();
-        let scheduled_at = Utc::now() + delay;
 
         sqlx::query(
             r#"
@@ -367,27 +1010,111 @@ impl<S: FileStorage + Send + Sync + 'static> QueueManager<S> {
         .fetch_one(pool)
         .await?;
 
+        let dead_letter: (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM jobs WHERE status = 'dead_letter' AND queue = 'delivery'",
+        )
+        .fetch_one(pool)
+        .await?;
+
         Ok(QueueStats {
             pending: pending.0 as u64,
             processing: processing.0 as u64,
             failed: failed.0 as u64,
+            dead_letter: dead_letter.0 as u64,
+        })
+    }
+}
+
+/// Whether the MX hosts are a null MX record
+pub(crate) fn is_null_mx(mx_hosts: &[String]) -> bool {
+    matches!(mx_hosts, [host] if host.trim_end_matches('.').is_empty())
+}
+
+/// Failure of a delivery that every MX host of the domain failed, given in
+/// MX order
+///
+/// The delivery fails permanently with the first permanent failure, once
+/// every host has been tried; otherwise it is retried with the last
+/// temporary one.
+fn mx_failure(failures: Vec<DeliveryError>) -> DeliveryError {
+    let mut last_temporary = None;
+    for failure in failures {
+        if failure.is_permanent() {
+            return failure;
+        }
+        last_temporary = Some(failure);
+    }
+    last_temporary.unwrap_or_else(|| DeliveryError::Temporary("No MX hosts".to_string()))
+}
+
+/// Whether the message asks for REQUIRETLS handling via its headers
+///
+/// `X-Require-TLS` is set by the RequireTls policy action and may be set by
+/// submitting clients that cannot pass the REQUIRETLS MAIL parameter.
+fn requests_tls(data: &[u8]) -> bool {
+    mail_parser::MessageParser::default()
+        .parse_headers(data)
+        .and_then(|parsed| {
+            parsed
+                .header_raw("X-Require-TLS")
+                .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "true" | "yes" | "1"))
         })
+        .unwrap_or(false)
+}
+
+/// Connection slots per destination domain for each lane
+fn lane_domain_slots(limit: usize) -> HashMap<DeliveryLane, DomainSlots> {
+    DeliveryLane::ALL
+        .into_iter()
+        .map(|lane| (lane, DomainSlots::new(limit)))
+        .collect()
+}
+
+/// Next delivery notification; never resolves without a listener
+async fn next_notification(listener: &mut Option<PgListener>) -> Result<(), sqlx::Error> {
+    match listener {
+        Some(listener) => listener.recv().await.map(|_| ()),
+        None => std::future::pending().await,
     }
 }
 
 /// Calculate exponential backoff delay
-fn calculate_backoff(attempts: i32) -> Duration {
+pub(crate) fn calculate_backoff(attempts: i32) -> Duration {
     // Base: 1 minute, max: 4 hours
     let minutes = std::cmp::min(2_i64.pow(attempts as u32), 240);
     Duration::minutes(minutes)
 }
 
+/// Delay before the retry after a number of failed attempts
+///
+/// Takes the interval of the attempt from the schedule, repeating its last
+/// one, and spreads it by up to `jitter_percent` either way; `roll` picks
+/// the spread, from 0 (shortest) to 1 (longest). An empty schedule falls
+/// back to exponential backoff.
+fn retry_delay(schedule_secs: &[u64], attempts: i32, jitter_percent: u32, roll: f64) -> Duration {
+    let index = (attempts.max(1) - 1) as usize;
+    let Some(&secs) = schedule_secs.get(index).or(schedule_secs.last()) else {
+        return calculate_backoff(attempts);
+    };
+
+    let spread = f64::from(jitter_percent.min(100)) / 100.0;
+    let factor = 1.0 + spread * (roll.clamp(0.0, 1.0) * 2.0 - 1.0);
+    Duration::milliseconds((secs as f64 * 1000.0 * factor) as i64)
+}
+
+/// A random number in [0, 1) for retry jitter
+fn jitter_roll() -> f64 {
+    (Uuid::new_v4().as_u128() >> 75) as f64 / (1u64 << 53) as f64
+}
+
 /// Queue statistics
 #[derive(Debug, Clone)]
 pub struct QueueStats {
     pub pending: u64,
     pub processing: u64,
     pub failed: u64,
+    /// Jobs that exceeded their queue lifetime
+    pub dead_letter: u64,
 }
 
 #[cfg(test)]
@@ -402,4 +1129,97 @@ mod tests {
         assert_eq!(calculate_backoff(3), Duration::minutes(8));
         assert_eq!(calculate_backoff(10), Duration::minutes(240)); // Max capped at 4 hours
     }
+
+    #[test]
+    fn test_retry_delay() {
+        let schedule = [60, 300, 900, 3600, 14400];
+        assert_eq!(retry_delay(&schedule, 1, 0, 0.7), Duration::minutes(1));
+        assert_eq!(retry_delay(&schedule, 2, 0, 0.7), Duration::minutes(5));
+        assert_eq!(retry_delay(&schedule, 5, 0, 0.7), Duration::hours(4));
+        // The last interval repeats
+        assert_eq!(retry_delay(&schedule, 12, 0, 0.7), Duration::hours(4));
+
+        // Jitter spreads the interval either way
+        assert_eq!(retry_delay(&schedule, 2, 10, 0.0), Duration::seconds(270));
+        assert_eq!(retry_delay(&schedule, 2, 10, 0.5), Duration::seconds(300));
+        assert_eq!(retry_delay(&schedule, 2, 10, 1.0), Duration::seconds(330));
+
+        // Without a schedule, exponential backoff
+        assert_eq!(retry_delay(&[], 3, 10, 0.0), calculate_backoff(3));
+    }
+
+    #[test]
+    fn test_jitter_roll() {
+        for _ in 0..100 {
+            let roll = jitter_roll();
+            assert!((0.0..1.0).contains(&roll));
+        }
+    }
+
+    #[test]
+    fn test_is_null_mx() {
+        assert!(is_null_mx(&[".".to_string()]));
+        assert!(!is_null_mx(&["mx.example.com.".to_string()]));
+        assert!(!is_null_mx(&[".".to_string(), "mx.example.com.".to_string()]));
+        assert!(!is_null_mx(&[]));
+    }
+
+    #[test]
+    fn test_mx_failure() {
+        use crate::queue::RemoteReply;
+
+        let rejected = |host: &str, code: u16| {
+            DeliveryError::Rejected(RemoteReply {
+                host: host.to_string(),
+                stage: "MAIL FROM".to_string(),
+                code,
+                text: "No".to_string(),
+            })
+        };
+
+        // The first permanent rejection, whatever the hosts after it said
+        let failure = mx_failure(vec![
+            DeliveryError::Temporary("Connection refused".to_string()),
+            rejected("mx1.example.com", 554),
+            rejected("mx2.example.com", 550),
+        ]);
+        assert_eq!(failure.remote_reply().unwrap().host, "mx1.example.com");
+
+        // Only temporary failures: retried with the last one
+        let failure = mx_failure(vec![
+            rejected("mx1.example.com", 421),
+            DeliveryError::Temporary("Connection refused".to_string()),
+        ]);
+        assert!(!failure.is_permanent());
+        assert!(failure.remote_reply().is_none());
+
+        assert!(!mx_failure(Vec::new()).is_permanent());
+    }
+
+    #[test]
+    fn test_requests_tls() {
+        assert!(requests_tls(b"X-Require-TLS: true\r\nSubject: a\r\n\r\nbody"));
+        assert!(requests_tls(b"x-require-tls: Yes\r\n\r\nbody"));
+        assert!(!requests_tls(b"X-Require-TLS: no\r\n\r\nbody"));
+        assert!(!requests_tls(b"Subject: a\r\n\r\nX-Require-TLS: true"));
+    }
+
+    #[test]
+    fn test_delivery_job_defaults() {
+        let job: DeliveryJob = serde_json::from_value(serde_json::json!({
+            "message_id": Uuid::nil(),
+            "tenant_id": Uuid::nil(),
+            "from": "a@example.com",
+            "to": ["b@example.net"],
+            "storage_path": "t/m/x.eml"
+        }))
+        .unwrap();
+        assert!(!job.require_tls);
+        assert!(job.raw_message_base64.is_none());
+        assert!(!job.archived);
+        assert_eq!(job.lane, DeliveryLane::Transactional);
+        assert!(!job.dkim_signed);
+        assert!(!job.forwarded);
+        assert!(job.queued_at.is_none());
+    }
 }
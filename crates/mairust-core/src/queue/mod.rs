@@ -1,5 +1,19 @@
 //! Queue management module
 
+mod dns;
+mod domains;
+mod dsn;
+mod fair;
+mod lanes;
 mod manager;
+mod outbound;
+mod preflight;
 
-pub use manager::QueueManager;
+pub use dns::{DnsCache, DomainRecords};
+pub use manager::{DeliveryJob, QueueManager};
+pub(crate) use manager::calculate_backoff;
+pub use outbound::{
+    load_tls_connector, DeliveryError, DeliveryReport, OutboundClient, OutboundEnvelope,
+    RemoteReply, TlsRequirement,
+};
+pub use preflight::{DeliveryPreflight, PreflightReport, PreflightRequest};
@@ -0,0 +1,609 @@
+//! Outbound SMTP client used by the delivery queue
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use rustls_pemfile::certs;
+use std::fs::File;
+use std::io::BufReader as StdBufReader;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+
+/// Well-known locations of the system CA bundle
+const CA_BUNDLE_PATHS: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/cert.pem",
+];
+
+/// Default SMTP relay port
+const SMTP_PORT: u16 = 25;
+
+/// Timeout for establishing a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeout for a single command/reply exchange (RFC 5321 4.5.3.2)
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// TLS requirement for an outbound transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsRequirement {
+    /// Use STARTTLS when offered, fall back to plaintext otherwise
+    Opportunistic,
+    /// RFC 8689 REQUIRETLS: verified TLS and REQUIRETLS support on every hop
+    Required,
+}
+
+/// Outbound delivery failure
+#[derive(Debug, Clone, Error)]
+pub enum DeliveryError {
+    #[error("Temporary failure: {0}")]
+    Temporary(String),
+
+    #[error("Permanent failure: {0}")]
+    Permanent(String),
+
+    #[error("TLS requirements not met: {0}")]
+    TlsRequired(String),
+
+    /// Negative reply of the remote server
+    #[error("Rejected by {0}")]
+    Rejected(RemoteReply),
+
+    /// Temporary failures went on until the job ran out of attempts; holds
+    /// the last of them
+    #[error("Delivery time expired: {0}")]
+    Expired(Box<DeliveryError>),
+}
+
+impl DeliveryError {
+    /// Whether retrying the delivery cannot succeed
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            DeliveryError::Temporary(_) => false,
+            DeliveryError::Rejected(reply) => reply.is_permanent(),
+            _ => true,
+        }
+    }
+
+    /// Enhanced status code to report in a DSN
+    pub fn status_code(&self) -> String {
+        match self {
+            DeliveryError::Temporary(_) => "4.0.0".to_string(),
+            DeliveryError::Permanent(_) => "5.0.0".to_string(),
+            DeliveryError::TlsRequired(_) => "5.7.30".to_string(),
+            DeliveryError::Rejected(reply) => reply.status_code(),
+            DeliveryError::Expired(_) => "4.4.7".to_string(),
+        }
+    }
+
+    /// Reply of the remote server behind the failure, if one gave it
+    pub fn remote_reply(&self) -> Option<&RemoteReply> {
+        match self {
+            DeliveryError::Rejected(reply) => Some(reply),
+            DeliveryError::Expired(last) => last.remote_reply(),
+            _ => None,
+        }
+    }
+}
+
+/// Negative reply that ended a delivery, as reported in a DSN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteReply {
+    /// Host that replied
+    pub host: String,
+    /// Command the reply answered
+    pub stage: String,
+    /// Reply code
+    pub code: u16,
+    /// Reply text, lines joined by spaces
+    pub text: String,
+}
+
+impl RemoteReply {
+    /// Whether the reply is a permanent (5xx) rejection
+    pub fn is_permanent(&self) -> bool {
+        self.code >= 500
+    }
+
+    /// Enhanced status code given in the reply text (RFC 3463), or the
+    /// generic one of the reply's class
+    pub fn status_code(&self) -> String {
+        let class = (self.code / 100).to_string();
+        self.text
+            .split_whitespace()
+            .next()
+            .filter(|token| {
+                let parts: Vec<&str> = token.split('.').collect();
+                parts.len() == 3
+                    && parts[0] == class
+                    && parts[1..].iter().all(|p| {
+                        (1..=3).contains(&p.len()) && p.bytes().all(|b| b.is_ascii_digit())
+                    })
+            })
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}.0.0", class))
+    }
+}
+
+impl std::fmt::Display for RemoteReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} after {}: {}", self.host, self.code, self.stage, self.text)
+    }
+}
+
+/// Message to relay to a single next hop
+#[derive(Debug, Clone)]
+pub struct OutboundEnvelope<'a> {
+    pub from: &'a str,
+    pub recipients: &'a [&'a str],
+    pub data: &'a [u8],
+    pub tls: TlsRequirement,
+}
+
+/// Result of a completed SMTP transaction
+#[derive(Debug, Clone)]
+pub struct DeliveryReport {
+    /// Host the message was handed to
+    pub host: String,
+    /// Whether the transaction ran over TLS
+    pub tls: bool,
+    /// Recipients accepted by the next hop
+    pub accepted: Vec<String>,
+    /// Recipients rejected at RCPT time
+    pub rejected: Vec<(String, DeliveryError)>,
+}
+
+/// SMTP reply
+#[derive(Debug, Clone)]
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    fn is_positive(&self) -> bool {
+        (200..400).contains(&self.code)
+    }
+
+    fn has_extension(&self, name: &str) -> bool {
+        self.lines.iter().skip(1).any(|line| {
+            line.split_whitespace()
+                .next()
+                .map(|kw| kw.eq_ignore_ascii_case(name))
+                .unwrap_or(false)
+        })
+    }
+
+    fn into_error(self, host: &str, stage: &str) -> DeliveryError {
+        DeliveryError::Rejected(RemoteReply {
+            host: host.to_string(),
+            stage: stage.to_string(),
+            code: self.code,
+            text: self.lines.join(" "),
+        })
+    }
+}
+
+/// Outcome of one connection attempt
+enum Attempt {
+    Done(Result<DeliveryReport, DeliveryError>),
+    HandshakeFailed(String),
+}
+
+/// Outbound SMTP client
+pub struct OutboundClient {
+    helo_name: String,
+    port: u16,
+    tls_connector: Option<TlsConnector>,
+}
+
+impl OutboundClient {
+    /// Create a client announcing `helo_name`, trusting the system CA bundle
+    pub fn new(helo_name: impl Into<String>) -> Self {
+        Self::with_tls_connector(helo_name, load_tls_connector())
+    }
+
+    /// Create a client announcing `helo_name` that upgrades connections
+    /// with `tls_connector`, e.g. one built once with
+    /// [`load_tls_connector`]; without one, STARTTLS is not used
+    pub fn with_tls_connector(
+        helo_name: impl Into<String>,
+        tls_connector: Option<TlsConnector>,
+    ) -> Self {
+        Self {
+            helo_name: helo_name.into(),
+            port: SMTP_PORT,
+            tls_connector,
+        }
+    }
+
+    /// Use a non-standard destination port
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Relay a message to `host`
+    pub async fn send(
+        &self,
+        host: &str,
+        envelope: &OutboundEnvelope<'_>,
+    ) -> Result<DeliveryReport, DeliveryError> {
+        let host = host.trim_end_matches('.');
+
+        match self.attempt(host, envelope, true).await? {
+            Attempt::Done(result) => result,
+            Attempt::HandshakeFailed(reason) => {
+                if envelope.tls == TlsRequirement::Required {
+                    return Err(DeliveryError::TlsRequired(reason));
+                }
+                // Opportunistic TLS: retry in plaintext on a fresh connection
+                warn!("{}; retrying {} without TLS", reason, host);
+                match self.attempt(host, envelope, false).await? {
+                    Attempt::Done(result) => result,
+                    Attempt::HandshakeFailed(reason) => Err(DeliveryError::Temporary(reason)),
+                }
+            }
+        }
+    }
+
+    async fn attempt(
+        &self,
+        host: &str,
+        envelope: &OutboundEnvelope<'_>,
+        use_starttls: bool,
+    ) -> Result<Attempt, DeliveryError> {
+        let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((host, self.port)))
+            .await
+            .map_err(|_| DeliveryError::Temporary(format!("Connection to {} timed out", host)))?
+            .map_err(|e| DeliveryError::Temporary(format!("Connection to {} failed: {}", host, e)))?;
+        let mut conn = BufReader::new(stream);
+
+        let greeting = read_reply(&mut conn).await?;
+        if !greeting.is_positive() {
+            return Err(greeting.into_error(host, "greeting"));
+        }
+
+        let ehlo = command(&mut conn, &format!("EHLO {}", self.helo_name)).await?;
+        if !ehlo.is_positive() {
+            if envelope.tls == TlsRequirement::Required {
+                return Err(DeliveryError::TlsRequired(format!(
+                    "{} does not support ESMTP",
+                    host
+                )));
+            }
+            let helo = command(&mut conn, &format!("HELO {}", self.helo_name)).await?;
+            if !helo.is_positive() {
+                return Err(helo.into_error(host, "HELO"));
+            }
+            return Ok(Attempt::Done(transact(&mut conn, host, envelope, false).await));
+        }
+
+        let connector = match (&self.tls_connector, use_starttls && ehlo.has_extension("STARTTLS")) {
+            (Some(connector), true) => connector,
+            _ => {
+                if envelope.tls == TlsRequirement::Required {
+                    let _ = command(&mut conn, "QUIT").await;
+                    return Err(DeliveryError::TlsRequired(format!(
+                        "{} does not offer STARTTLS",
+                        host
+                    )));
+                }
+                return Ok(Attempt::Done(transact(&mut conn, host, envelope, false).await));
+            }
+        };
+
+        let reply = command(&mut conn, "STARTTLS").await?;
+        if reply.code != 220 {
+            return Ok(Attempt::HandshakeFailed(format!(
+                "{} refused STARTTLS: {} {}",
+                host,
+                reply.code,
+                reply.lines.join(" ")
+            )));
+        }
+
+        let server_name = match ServerName::try_from(host.to_string()) {
+            Ok(name) => name,
+            Err(e) => {
+                return Ok(Attempt::HandshakeFailed(format!(
+                    "Invalid TLS server name {}: {}",
+                    host, e
+                )))
+            }
+        };
+
+        // Certificates are verified against the MX hostname (RFC 8689 section 4.2)
+        let tls_stream = match connector.connect(server_name, conn.into_inner()).await {
+            Ok(s) => s,
+            Err(e) => {
+                return Ok(Attempt::HandshakeFailed(format!(
+                    "TLS handshake with {} failed: {}",
+                    host, e
+                )))
+            }
+        };
+        let mut conn = BufReader::new(tls_stream);
+
+        let ehlo = command(&mut conn, &format!("EHLO {}", self.helo_name)).await?;
+        if !ehlo.is_positive() {
+            return Err(ehlo.into_error(host, "EHLO"));
+        }
+
+        if envelope.tls == TlsRequirement::Required && !ehlo.has_extension("REQUIRETLS") {
+            let _ = command(&mut conn, "QUIT").await;
+            return Err(DeliveryError::TlsRequired(format!(
+                "{} does not advertise REQUIRETLS",
+                host
+            )));
+        }
+
+        Ok(Attempt::Done(transact(&mut conn, host, envelope, true).await))
+    }
+}
+
+/// Run MAIL/RCPT/DATA on an established session
+async fn transact<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut BufReader<S>,
+    host: &str,
+    envelope: &OutboundEnvelope<'_>,
+    tls: bool,
+) -> Result<DeliveryReport, DeliveryError> {
+    let mail = match envelope.tls {
+        TlsRequirement::Required => format!("MAIL FROM:<{}> REQUIRETLS", envelope.from),
+        TlsRequirement::Opportunistic => format!("MAIL FROM:<{}>", envelope.from),
+    };
+    let reply = command(conn, &mail).await?;
+    if !reply.is_positive() {
+        return Err(reply.into_error(host, "MAIL FROM"));
+    }
+
+    let mut report = DeliveryReport {
+        host: host.to_string(),
+        tls,
+        accepted: Vec::new(),
+        rejected: Vec::new(),
+    };
+
+    for rcpt in envelope.recipients {
+        let reply = command(conn, &format!("RCPT TO:<{}>", rcpt)).await?;
+        if reply.is_positive() {
+            report.accepted.push(rcpt.to_string());
+        } else {
+            report
+                .rejected
+                .push((rcpt.to_string(), reply.into_error(host, "RCPT TO")));
+        }
+    }
+
+    if report.accepted.is_empty() {
+        let _ = command(conn, "QUIT").await;
+        return Ok(report);
+    }
+
+    let reply = command(conn, "DATA").await?;
+    if reply.code != 354 {
+        return Err(reply.into_error(host, "DATA"));
+    }
+
+    let mut payload = dot_stuff(envelope.data);
+    payload.extend_from_slice(b".\r\n");
+    write_all(conn, &payload).await?;
+
+    let reply = read_reply(conn).await?;
+    if !reply.is_positive() {
+        return Err(reply.into_error(host, "end of data"));
+    }
+
+    info!(
+        "Relayed message to {} for {} recipient(s){}",
+        host,
+        report.accepted.len(),
+        if tls { " over TLS" } else { "" }
+    );
+
+    let _ = command(conn, "QUIT").await;
+    Ok(report)
+}
+
+/// Send a command line and read the reply
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut BufReader<S>,
+    line: &str,
+) -> Result<Reply, DeliveryError> {
+    debug!("SMTP out: {}", line);
+    write_all(conn, format!("{}\r\n", line).as_bytes()).await?;
+    read_reply(conn).await
+}
+
+async fn write_all<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut BufReader<S>,
+    data: &[u8],
+) -> Result<(), DeliveryError> {
+    let stream = conn.get_mut();
+    timeout(COMMAND_TIMEOUT, async {
+        stream.write_all(data).await?;
+        stream.flush().await
+    })
+    .await
+    .map_err(|_| DeliveryError::Temporary("Write timed out".to_string()))?
+    .map_err(|e| DeliveryError::Temporary(format!("Write failed: {}", e)))
+}
+
+/// Read a (possibly multi-line) reply
+async fn read_reply<R: AsyncBufRead + Unpin>(conn: &mut R) -> Result<Reply, DeliveryError> {
+    let mut lines = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        let n = timeout(COMMAND_TIMEOUT, conn.read_line(&mut line))
+            .await
+            .map_err(|_| DeliveryError::Temporary("Timed out waiting for reply".to_string()))?
+            .map_err(|e| DeliveryError::Temporary(format!("Read failed: {}", e)))?;
+
+        if n == 0 {
+            return Err(DeliveryError::Temporary(
+                "Connection closed by remote host".to_string(),
+            ));
+        }
+
+        let (code, last, text) = parse_reply_line(line.trim_end()).ok_or_else(|| {
+            DeliveryError::Temporary(format!("Malformed reply: {}", line.trim_end()))
+        })?;
+        lines.push(text.to_string());
+
+        if last {
+            return Ok(Reply { code, lines });
+        }
+    }
+}
+
+/// Split a reply line into code, final-line flag and text
+fn parse_reply_line(line: &str) -> Option<(u16, bool, &str)> {
+    if line.len() < 3 || !line.is_char_boundary(3) {
+        return None;
+    }
+    let code = line[..3].parse().ok()?;
+    match line.as_bytes().get(3) {
+        None => Some((code, true, "")),
+        Some(b' ') => Some((code, true, &line[4..])),
+        Some(b'-') => Some((code, false, &line[4..])),
+        Some(_) => None,
+    }
+}
+
+/// Normalize line endings to CRLF and escape leading dots (RFC 5321 4.5.2)
+fn dot_stuff(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 64);
+    let mut at_line_start = true;
+    let mut prev = 0u8;
+
+    for &b in data {
+        if at_line_start && b == b'.' {
+            out.push(b'.');
+        }
+        if b == b'\n' && prev != b'\r' {
+            out.push(b'\r');
+        }
+        out.push(b);
+        at_line_start = b == b'\n';
+        prev = b;
+    }
+
+    if !out.ends_with(b"\r\n") {
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Build a TLS connector trusting the system CA bundle
+pub fn load_tls_connector() -> Option<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+
+    for path in CA_BUNDLE_PATHS {
+        let Ok(file) = File::open(path) else {
+            continue;
+        };
+        let mut reader = StdBufReader::new(file);
+        for cert in certs(&mut reader).flatten() {
+            let _ = roots.add(cert);
+        }
+        if !roots.is_empty() {
+            break;
+        }
+    }
+
+    if roots.is_empty() {
+        warn!("No CA bundle found, outbound STARTTLS is disabled");
+        return None;
+    }
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Some(TlsConnector::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reply_line() {
+        assert_eq!(parse_reply_line("250-SIZE 1000"), Some((250, false, "SIZE 1000")));
+        assert_eq!(parse_reply_line("250 OK"), Some((250, true, "OK")));
+        assert_eq!(parse_reply_line("354"), Some((354, true, "")));
+        assert_eq!(parse_reply_line("25"), None);
+        assert_eq!(parse_reply_line("abc OK"), None);
+    }
+
+    #[test]
+    fn test_has_extension() {
+        let reply = Reply {
+            code: 250,
+            lines: vec![
+                "mx.example.com".to_string(),
+                "STARTTLS".to_string(),
+                "requiretls".to_string(),
+            ],
+        };
+        assert!(reply.has_extension("STARTTLS"));
+        assert!(reply.has_extension("REQUIRETLS"));
+        assert!(!reply.has_extension("mx.example.com"));
+        assert!(!reply.has_extension("SMTPUTF8"));
+    }
+
+    #[test]
+    fn test_dot_stuff() {
+        assert_eq!(dot_stuff(b".hidden\nline\n"), b"..hidden\r\nline\r\n".to_vec());
+        assert_eq!(dot_stuff(b"a\r\n.\r\nb"), b"a\r\n..\r\nb\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_delivery_error_classification() {
+        assert!(!DeliveryError::Temporary("x".into()).is_permanent());
+        assert!(DeliveryError::Permanent("x".into()).is_permanent());
+        assert!(DeliveryError::TlsRequired("x".into()).is_permanent());
+        assert_eq!(DeliveryError::TlsRequired("x".into()).status_code(), "5.7.30");
+        let expired = DeliveryError::Expired(Box::new(DeliveryError::Temporary("x".into())));
+        assert!(expired.is_permanent());
+        assert_eq!(expired.status_code(), "4.4.7");
+        assert!(expired.remote_reply().is_none());
+    }
+
+    #[test]
+    fn test_remote_reply_classification() {
+        let reply = |code: u16, text: &str| Reply {
+            code,
+            lines: vec![text.to_string()],
+        };
+
+        let rejected = reply(550, "5.1.1 <bob@example.net>: user unknown")
+            .into_error("mx.example.net", "RCPT TO");
+        assert!(rejected.is_permanent());
+        assert_eq!(rejected.status_code(), "5.1.1");
+        assert_eq!(
+            rejected.to_string(),
+            "Rejected by mx.example.net 550 after RCPT TO: 5.1.1 <bob@example.net>: user unknown"
+        );
+
+        let deferred = reply(451, "Try again later").into_error("mx.example.net", "DATA");
+        assert!(!deferred.is_permanent());
+        assert_eq!(deferred.status_code(), "4.0.0");
+
+        // Retries ran out: the last reply is still reported
+        let expired = DeliveryError::Expired(Box::new(deferred));
+        assert_eq!(expired.remote_reply().map(|r| r.code), Some(451));
+
+        // An enhanced code of another class is not trusted
+        let mismatched = reply(554, "4.7.1 policy").into_error("mx.example.net", "end of data");
+        assert_eq!(mismatched.status_code(), "5.0.0");
+    }
+}
@@ -0,0 +1,334 @@
+//! Delivery preflight
+//!
+//! Reports what the queue would do with a message to one recipient without
+//! sending anything: the MX hosts it would be relayed to, the MTA-STS and
+//! DANE records published for them, whether the tenant has suppressed the
+//! address and how much of the tenant's sending quota is left.
+//!
+//! MTA-STS and TLSA records are reported as published; relaying uses
+//! STARTTLS when offered and only insists on verified TLS for REQUIRETLS
+//! messages. Suppressions and quotas apply to campaign sends.
+
+use super::dns::DnsCache;
+use super::manager::is_null_mx;
+use crate::scheduled::{RateLimiter, RemainingQuota};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::MailboxRepository;
+use mairust_storage::UnsubscribeRepository;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Port outbound connections are made to
+const SMTP_PORT: u16 = 25;
+
+/// Recipient to check
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreflightRequest {
+    pub recipient: String,
+    /// Check as for a message sent with REQUIRETLS
+    #[serde(default)]
+    pub require_tls: bool,
+}
+
+/// What would happen to a message to the recipient
+#[derive(Debug, Clone, Serialize)]
+pub struct PreflightReport {
+    pub recipient: String,
+    pub domain: String,
+    /// The message would be handed to an MX host
+    pub deliverable: bool,
+    /// Reasons the message would fail, be held up or be skipped
+    pub problems: Vec<String>,
+    /// The recipient is a mailbox of this server
+    pub local_mailbox: bool,
+    pub mx: MxReport,
+    pub tls: TlsReport,
+    pub suppression: SuppressionReport,
+    pub rate_limit: RateLimitReport,
+    pub route: RouteReport,
+}
+
+/// MX resolution of the recipient's domain
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MxReport {
+    /// Hosts tried in order
+    pub hosts: Vec<String>,
+    /// The domain has no MX record and its address record is used
+    pub implicit: bool,
+    /// The domain publishes a null MX (RFC 7505)
+    pub null_mx: bool,
+    /// Why the domain could not be resolved
+    pub error: Option<String>,
+}
+
+/// TLS policies of the destination
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TlsReport {
+    /// Verified TLS is required (REQUIRETLS)
+    pub required: bool,
+    /// The `_mta-sts` TXT record of the domain
+    pub mta_sts: Option<String>,
+    /// TLSA records (DANE) by MX host
+    pub tlsa: HashMap<String, Vec<String>>,
+}
+
+/// Suppressions of the address in the tenant
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SuppressionReport {
+    pub unsubscribed: bool,
+    pub unsubscribe_source: Option<String>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+    /// Bounced or complained statuses in the tenant's recipient lists
+    pub list_statuses: Vec<String>,
+}
+
+/// Sending quota of the tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitReport {
+    pub enabled: bool,
+    pub per_minute: i32,
+    pub per_hour: i32,
+    pub per_day: i32,
+    pub remaining: RemainingQuota,
+}
+
+/// How the message would leave the server
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteReport {
+    /// Relayed directly to the MX hosts; there is no smarthost
+    pub method: &'static str,
+    /// Name the server introduces itself with
+    pub helo: String,
+    pub port: u16,
+}
+
+/// Runs preflight checks for a tenant's recipients
+pub struct DeliveryPreflight {
+    db_pool: DatabasePool,
+    dns: Arc<DnsCache>,
+    hostname: String,
+}
+
+impl DeliveryPreflight {
+    /// Create a preflight checker that resolves through `dns`
+    pub fn new(db_pool: DatabasePool, dns: Arc<DnsCache>, hostname: impl Into<String>) -> Self {
+        Self {
+            db_pool,
+            dns,
+            hostname: hostname.into(),
+        }
+    }
+
+    /// Check a recipient; nothing is queued or sent
+    pub async fn check(
+        &self,
+        tenant_id: TenantId,
+        request: &PreflightRequest,
+    ) -> Result<PreflightReport> {
+        let recipient = request.recipient.trim().to_lowercase();
+        let domain = match recipient.rsplit_once('@') {
+            Some((local, domain)) if !local.is_empty() && !domain.is_empty() => {
+                domain.trim_end_matches('.').to_string()
+            }
+            _ => anyhow::bail!("'{}' is not an email address", request.recipient),
+        };
+
+        let local_mailbox = MailboxRepository::new(self.db_pool.clone())
+            .find_by_address(&recipient)
+            .await?
+            .is_some();
+
+        let mut mx = MxReport::default();
+        let mut tls = TlsReport {
+            required: request.require_tls,
+            ..TlsReport::default()
+        };
+        match self.dns.lookup(&domain).await {
+            Ok(records) => {
+                mx.null_mx = is_null_mx(&records.mx_hosts);
+                mx.implicit = records.mx_hosts == [domain.clone()];
+                if !mx.null_mx {
+                    mx.hosts = records.mx_hosts.clone();
+                }
+                tls.mta_sts = records.mta_sts.clone();
+                tls.tlsa = records.tlsa.clone();
+            }
+            Err(e) => mx.error = Some(e.to_string()),
+        }
+
+        let suppression = self.suppression(tenant_id, &recipient).await?;
+        let rate_limit = self.rate_limit(tenant_id).await?;
+
+        let problems = problems(&mx, &tls, &suppression, &rate_limit);
+        Ok(PreflightReport {
+            recipient,
+            domain,
+            deliverable: mx.error.is_none() && !mx.null_mx,
+            problems,
+            local_mailbox,
+            mx,
+            tls,
+            suppression,
+            rate_limit,
+            route: RouteReport {
+                method: "mx",
+                helo: self.hostname.clone(),
+                port: SMTP_PORT,
+            },
+        })
+    }
+
+    async fn suppression(&self, tenant_id: TenantId, recipient: &str) -> Result<SuppressionReport> {
+        let unsubscribe = UnsubscribeRepository::new(self.db_pool.pool().clone())
+            .get_by_email(tenant_id, recipient)
+            .await?;
+        let list_statuses: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT r.status
+            FROM recipients r
+            JOIN recipient_lists l ON l.id = r.recipient_list_id
+            WHERE l.tenant_id = $1 AND lower(r.email) = $2
+              AND r.status IN ('bounced', 'complained')
+            ORDER BY r.status
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(recipient)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        Ok(SuppressionReport {
+            unsubscribed: unsubscribe.is_some(),
+            unsubscribe_source: unsubscribe.as_ref().map(|u| u.source.clone()),
+            unsubscribed_at: unsubscribe.map(|u| u.unsubscribed_at),
+            list_statuses,
+        })
+    }
+
+    async fn rate_limit(&self, tenant_id: TenantId) -> Result<RateLimitReport> {
+        let limiter = RateLimiter::new(self.db_pool.clone());
+        let limits = limiter.get_rate_limits(tenant_id).await?;
+        let remaining = limiter.get_remaining(tenant_id).await?;
+
+        Ok(RateLimitReport {
+            enabled: limits.enabled,
+            per_minute: limits.per_minute,
+            per_hour: limits.per_hour,
+            per_day: limits.per_day,
+            remaining,
+        })
+    }
+}
+
+/// Everything in a report that would stop, hold up or skip the message
+fn problems(
+    mx: &MxReport,
+    tls: &TlsReport,
+    suppression: &SuppressionReport,
+    rate_limit: &RateLimitReport,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Some(error) = &mx.error {
+        problems.push(format!("The domain could not be resolved: {}", error));
+    }
+    if mx.null_mx {
+        problems.push("The domain does not accept mail (null MX)".to_string());
+    }
+    if tls.required && tls.tlsa.is_empty() && tls.mta_sts.is_none() {
+        problems.push(
+            "REQUIRETLS: the domain publishes no MTA-STS or DANE policy; delivery depends on \
+             the MX hosts offering a verifiable certificate"
+                .to_string(),
+        );
+    }
+    if suppression.unsubscribed {
+        problems.push("The address has unsubscribed; campaign sends skip it".to_string());
+    }
+    for status in &suppression.list_statuses {
+        problems.push(format!(
+            "The address is marked {} in a recipient list",
+            status
+        ));
+    }
+    if rate_limit.enabled && rate_limit.remaining.min() == 0 {
+        problems.push("The tenant's sending quota is used up; campaign sends wait".to_string());
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limit(remaining: i32) -> RateLimitReport {
+        RateLimitReport {
+            enabled: true,
+            per_minute: 10,
+            per_hour: 100,
+            per_day: 1000,
+            remaining: RemainingQuota {
+                per_minute: remaining,
+                per_hour: 100,
+                per_day: 1000,
+            },
+        }
+    }
+
+    #[test]
+    fn test_no_problems() {
+        let mx = MxReport {
+            hosts: vec!["mx1.example.com.".to_string()],
+            ..MxReport::default()
+        };
+        let problems = problems(
+            &mx,
+            &TlsReport::default(),
+            &SuppressionReport::default(),
+            &rate_limit(10),
+        );
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn test_problems() {
+        let mx = MxReport {
+            null_mx: true,
+            ..MxReport::default()
+        };
+        let tls = TlsReport {
+            required: true,
+            ..TlsReport::default()
+        };
+        let suppression = SuppressionReport {
+            unsubscribed: true,
+            list_statuses: vec!["bounced".to_string()],
+            ..SuppressionReport::default()
+        };
+        let problems = problems(&mx, &tls, &suppression, &rate_limit(0));
+        assert_eq!(problems.len(), 5);
+        assert!(problems[0].contains("null MX"));
+        assert!(problems[3].contains("bounced"));
+    }
+
+    #[test]
+    fn test_tls_policy_satisfies_requiretls_check() {
+        let tls = TlsReport {
+            required: true,
+            mta_sts: Some("v=STSv1; id=20240101".to_string()),
+            ..TlsReport::default()
+        };
+        let problems = problems(
+            &MxReport::default(),
+            &tls,
+            &SuppressionReport::default(),
+            &rate_limit(10),
+        );
+        assert!(problems.is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+//! Mailbox quotas at delivery
+//!
+//! `used_bytes` of each mailbox row is kept by the database from the sizes
+//! of its messages. The quota of an address mailbox covers the mailbox and
+//! its owner's folders, so mail filed into Junk or a folder counts too.
+//!
+//! Mail that does not fit is refused at RCPT TO, using the size declared
+//! with `SIZE=` when there is one, and again after DATA with the actual
+//! size. Within `smtp.quota_grace_percent` over the quota the refusal is
+//! temporary (452 4.2.2), so the sender retries while the user frees space;
+//! beyond it, it is permanent (552 5.2.2).
+
+use anyhow::Result;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Mailbox;
+use mairust_storage::MailboxRepository;
+
+/// Whether a message fits in a mailbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuotaVerdict {
+    /// No quota, or the message fits
+    Within,
+    /// Over the quota, but within the grace margin: defer
+    Grace,
+    /// Over the quota and the grace margin: reject
+    Exceeded,
+}
+
+impl QuotaVerdict {
+    /// Verdict for `incoming` bytes on a mailbox using `used` of `quota`
+    pub fn of(quota: Option<i64>, used: i64, incoming: i64, grace_percent: u32) -> Self {
+        let Some(quota) = quota else {
+            return QuotaVerdict::Within;
+        };
+        let total = used.saturating_add(incoming.max(0));
+        if total <= quota {
+            return QuotaVerdict::Within;
+        }
+        let grace = quota.max(0).saturating_mul(i64::from(grace_percent)) / 100;
+        if total <= quota.saturating_add(grace) {
+            QuotaVerdict::Grace
+        } else {
+            QuotaVerdict::Exceeded
+        }
+    }
+
+    /// SMTP reply for a refused delivery; `None` when it fits
+    pub fn reply(self) -> Option<(u16, &'static str)> {
+        match self {
+            QuotaVerdict::Within => None,
+            QuotaVerdict::Grace => Some((452, "4.2.2 Mailbox full, try again later")),
+            QuotaVerdict::Exceeded => Some((552, "5.2.2 Mailbox full")),
+        }
+    }
+}
+
+/// A message refused after DATA because a recipient's mailbox is full
+#[derive(Debug, thiserror::Error)]
+#[error("mailbox over quota ({0:?})")]
+pub struct QuotaRejection(pub QuotaVerdict);
+
+/// Checks mailbox quotas before delivery
+pub struct QuotaChecker {
+    db_pool: DatabasePool,
+    grace_percent: u32,
+}
+
+impl QuotaChecker {
+    /// Create a quota checker with the grace margin in percent of the quota
+    pub fn new(db_pool: DatabasePool, grace_percent: u32) -> Self {
+        Self {
+            db_pool,
+            grace_percent,
+        }
+    }
+
+    /// Whether `incoming` more bytes fit in a mailbox
+    pub async fn check(&self, mailbox: &Mailbox, incoming: i64) -> Result<QuotaVerdict> {
+        if mailbox.quota_bytes.is_none() {
+            return Ok(QuotaVerdict::Within);
+        }
+        let used = MailboxRepository::new(self.db_pool.clone())
+            .quota_usage(mailbox)
+            .await?;
+        Ok(QuotaVerdict::of(
+            mailbox.quota_bytes,
+            used,
+            incoming,
+            self.grace_percent,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict() {
+        assert_eq!(
+            QuotaVerdict::of(None, 5_000, 1_000, 10),
+            QuotaVerdict::Within
+        );
+        assert_eq!(
+            QuotaVerdict::of(Some(1_000), 400, 600, 10),
+            QuotaVerdict::Within
+        );
+        assert_eq!(
+            QuotaVerdict::of(Some(1_000), 400, 601, 10),
+            QuotaVerdict::Grace
+        );
+        assert_eq!(
+            QuotaVerdict::of(Some(1_000), 1_000, 100, 10),
+            QuotaVerdict::Grace
+        );
+        assert_eq!(
+            QuotaVerdict::of(Some(1_000), 1_000, 101, 10),
+            QuotaVerdict::Exceeded
+        );
+        assert_eq!(
+            QuotaVerdict::of(Some(1_000), 1_000, 1, 0),
+            QuotaVerdict::Exceeded
+        );
+        assert_eq!(
+            QuotaVerdict::of(Some(1_000), 2_000, 0, 10),
+            QuotaVerdict::Exceeded
+        );
+        assert_eq!(QuotaVerdict::of(Some(0), 0, 1, 10), QuotaVerdict::Exceeded);
+        assert_eq!(
+            QuotaVerdict::of(Some(i64::MAX), i64::MAX, 1, 10),
+            QuotaVerdict::Within
+        );
+    }
+
+    #[test]
+    fn test_reply() {
+        assert_eq!(QuotaVerdict::Within.reply(), None);
+        assert_eq!(QuotaVerdict::Grace.reply().map(|(code, _)| code), Some(452));
+        assert_eq!(
+            QuotaVerdict::Exceeded.reply().map(|(code, _)| code),
+            Some(552)
+        );
+        assert!(QuotaVerdict::Exceeded > QuotaVerdict::Grace);
+    }
+}
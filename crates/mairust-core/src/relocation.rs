@@ -0,0 +1,276 @@
+//! Storage relocation
+//!
+//! Moves message files written under an earlier storage layout to the
+//! configured one and rewrites their `storage_path`.
+//!
+//! Each file is copied and checked before any row points at the copy, and
+//! the old file is only removed once no message refers to it any more, so
+//! an interrupted run leaves every message readable and can simply be
+//! started again. Messages copied by IMAP share one file, which moves
+//! once for all of them. Files other than messages (inbound route payloads,
+//! journal reports) are left alone, and so are the files of tenants kept
+//! in another region, which that region's servers relocate.
+
+use anyhow::{bail, Result};
+use mairust_common::Error;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use mairust_storage::StorageLayout;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Rows read per batch by default
+const DEFAULT_BATCH_SIZE: i64 = 500;
+
+/// Times references added while a file moves are chased before giving up
+const MAX_REWRITE_ROUNDS: usize = 3;
+
+/// Which messages to relocate
+#[derive(Debug, Clone)]
+pub struct RelocationOptions {
+    /// Only messages of this tenant
+    pub tenant_id: Option<Uuid>,
+    /// Rows read per batch
+    pub batch_size: i64,
+    /// Count what would move without touching anything
+    pub dry_run: bool,
+}
+
+impl Default for RelocationOptions {
+    fn default() -> Self {
+        Self {
+            tenant_id: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            dry_run: false,
+        }
+    }
+}
+
+/// Outcome of a relocation run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RelocationSummary {
+    pub dry_run: bool,
+    /// Messages looked at
+    pub scanned: u64,
+    /// Files moved (or that would be moved)
+    pub relocated: u64,
+    /// Messages already stored under the layout
+    pub in_place: u64,
+    /// Messages whose path is not a message file path
+    pub skipped: u64,
+    /// Messages whose file is missing
+    pub missing: u64,
+    /// Messages of tenants kept in another region
+    pub other_region: u64,
+    /// Files that could not be moved
+    pub failed: u64,
+}
+
+/// Where a stored file belongs under the target layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Placement {
+    /// Already there
+    InPlace,
+    /// Not a message file path
+    Foreign,
+    /// To be moved to the path
+    Move(String),
+}
+
+fn placement(layout: &StorageLayout, path: &str) -> Placement {
+    match layout.relocate(path) {
+        Some(target) if target == path => Placement::InPlace,
+        Some(target) => Placement::Move(target),
+        None => Placement::Foreign,
+    }
+}
+
+/// Moves message files to a storage layout
+pub struct StorageRelocator<S: FileStorage> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+    layout: StorageLayout,
+}
+
+impl<S: FileStorage> StorageRelocator<S> {
+    /// Create a relocator moving files of `file_storage` to `layout`
+    pub fn new(db_pool: DatabasePool, file_storage: Arc<S>, layout: StorageLayout) -> Self {
+        Self {
+            db_pool,
+            file_storage,
+            layout,
+        }
+    }
+
+    /// Relocate every message that is not stored under the layout
+    pub async fn run(&self, options: &RelocationOptions) -> Result<RelocationSummary> {
+        let mut summary = RelocationSummary {
+            dry_run: options.dry_run,
+            ..RelocationSummary::default()
+        };
+        let mut after = Uuid::nil();
+
+        loop {
+            let rows: Vec<(Uuid, String)> = sqlx::query_as(
+                r#"
+                SELECT id, storage_path FROM messages
+                WHERE id > $1 AND ($2::uuid IS NULL OR tenant_id = $2)
+                ORDER BY id
+                LIMIT $3
+                "#,
+            )
+            .bind(after)
+            .bind(options.tenant_id)
+            .bind(options.batch_size.max(1))
+            .fetch_all(self.db_pool.pool())
+            .await?;
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            after = *last;
+
+            for (id, path) in rows {
+                summary.scanned += 1;
+                let target = match placement(&self.layout, &path) {
+                    Placement::InPlace => {
+                        summary.in_place += 1;
+                        continue;
+                    }
+                    Placement::Foreign => {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    Placement::Move(target) => target,
+                };
+                match self.file_storage.exists(&path).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("File {} of message {} is missing", path, id);
+                        summary.missing += 1;
+                        continue;
+                    }
+                    Err(Error::Residency(_)) => {
+                        summary.other_region += 1;
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                if options.dry_run {
+                    summary.relocated += 1;
+                    continue;
+                }
+                match self.relocate(&path, &target).await {
+                    Ok(()) => summary.relocated += 1,
+                    Err(e) => {
+                        warn!("Failed to relocate {} to {}: {}", path, target, e);
+                        summary.failed += 1;
+                    }
+                }
+            }
+        }
+
+        info!(
+            "Storage relocation{}: {} scanned, {} relocated, {} in place, {} skipped, {} missing, \
+             {} in another region, {} failed",
+            if options.dry_run { " (dry run)" } else { "" },
+            summary.scanned,
+            summary.relocated,
+            summary.in_place,
+            summary.skipped,
+            summary.missing,
+            summary.other_region,
+            summary.failed
+        );
+        Ok(summary)
+    }
+
+    /// Move one file and the references to it
+    async fn relocate(&self, old: &str, new: &str) -> Result<()> {
+        let data = self.file_storage.read(old).await?;
+        self.file_storage.store(new, &data).await?;
+        let stored = self.file_storage.size(new).await?;
+        if stored != data.len() as u64 {
+            bail!("copy has {} of {} bytes", stored, data.len());
+        }
+
+        // IMAP COPY may add a row with the old path while it is rewritten
+        for _ in 0..MAX_REWRITE_ROUNDS {
+            self.rewrite(old, new).await?;
+            if !self.referenced(old).await? {
+                self.file_storage.delete(old).await?;
+                return Ok(());
+            }
+        }
+        bail!("{} is still referenced; the old file was kept", old)
+    }
+
+    /// Point messages and attachment scans at the new path
+    async fn rewrite(&self, old: &str, new: &str) -> Result<()> {
+        let mut tx = self.db_pool.pool().begin().await?;
+        sqlx::query("UPDATE messages SET storage_path = $2 WHERE storage_path = $1")
+            .bind(old)
+            .bind(new)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE attachment_scans SET storage_path = $2
+            WHERE storage_path = $1
+              AND NOT EXISTS (SELECT 1 FROM attachment_scans WHERE storage_path = $2)
+            "#,
+        )
+        .bind(old)
+        .bind(new)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM attachment_scans WHERE storage_path = $1")
+            .bind(old)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn referenced(&self, path: &str) -> Result<bool> {
+        let referenced: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM messages WHERE storage_path = $1)")
+                .bind(path)
+                .fetch_one(self.db_pool.pool())
+                .await?;
+        Ok(referenced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mairust_common::config::{StorageLayoutConfig, StorageLayoutScheme};
+
+    #[test]
+    fn test_placement() {
+        let layout = StorageLayout::from(&StorageLayoutConfig {
+            scheme: StorageLayoutScheme::Hashed,
+            fanout: 2,
+            generation: 1,
+        });
+        let (tenant, mailbox, message) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::now_v7());
+        let flat = StorageLayout::default().message_path(tenant, mailbox, message);
+        let hashed = layout.message_path(tenant, mailbox, message);
+
+        assert_eq!(placement(&layout, &flat), Placement::Move(hashed.clone()));
+        assert_eq!(placement(&layout, &hashed), Placement::InPlace);
+        assert_eq!(
+            placement(&StorageLayout::default(), &hashed),
+            Placement::Move(flat)
+        );
+        assert_eq!(
+            placement(
+                &layout,
+                &format!("{}/inbound/{}/{}.eml", tenant, mailbox, message)
+            ),
+            Placement::Foreign
+        );
+    }
+}
@@ -0,0 +1,745 @@
+//! Warm standby replication
+//!
+//! A standby server follows a primary so that it can take over when the
+//! primary is lost:
+//!
+//! - **Database:** PostgreSQL logical replication. `mairust replication
+//!   publish` on the primary publishes its tables, and `mairust replication
+//!   subscribe` on the standby subscribes to them. Each server keeps its
+//!   own migrations, so both must run the same version.
+//! - **Message files:** replicated messages queue their files on the
+//!   standby (a trigger that only fires while replication applies changes).
+//!   [`FileSync`] copies them from the primary's API, checking their SHA-256
+//!   checksum, and deletes the files of messages deleted on the primary.
+//! - **Lag:** the primary records a heartbeat every few seconds; the
+//!   standby's lag is the age of the last heartbeat it has replicated.
+//!
+//! The standby serves only the API (read-only) while it is subscribed.
+//! `mairust replication promote` drops the subscription, and the standby
+//! then starts the mail services as a primary.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::config::ReplicationConfig;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use mairust_storage::{FileQueueStats, QueuedFile, ReplicationRepository, SubscriberLag};
+use reqwest::{Client, StatusCode, Url};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::sync::Arc;
+use tokio::time::{interval, Duration as TokioDuration};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Response header with the SHA-256 checksum (hex) of a message file
+pub const CHECKSUM_HEADER: &str = "x-checksum-sha256";
+
+/// Path of the message files on the primary's API
+const FILES_PATH: [&str; 6] = ["api", "v1", "admin", "system", "replication", "files"];
+
+/// Seconds between two checks for promotion
+const PROMOTION_POLL_SECS: u64 = 5;
+
+/// Timeout of a file request to the primary
+const FILE_TIMEOUT_SECS: u64 = 120;
+
+/// Longest wait before a failed file is tried again
+const MAX_RETRY_MINUTES: i64 = 60;
+
+/// Role of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationRole {
+    Primary,
+    Standby,
+}
+
+impl ReplicationRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplicationRole::Primary => "primary",
+            ReplicationRole::Standby => "standby",
+        }
+    }
+}
+
+/// Replication state of a server
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationStatus {
+    pub enabled: bool,
+    pub role: ReplicationRole,
+    /// Last heartbeat of the primary in this database
+    pub heartbeat_at: Option<DateTime<Utc>>,
+    /// Seconds the standby is behind the primary
+    pub lag_secs: Option<f64>,
+    /// Lag above which a standby is unhealthy
+    pub max_lag_secs: u64,
+    /// Message files the standby has yet to copy or delete
+    pub files: FileQueueStats,
+    /// Subscribers of a primary, with their replay lag
+    pub subscribers: Vec<SubscriberLag>,
+}
+
+impl ReplicationStatus {
+    /// Status of a server with replication disabled
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            role: ReplicationRole::Primary,
+            heartbeat_at: None,
+            lag_secs: None,
+            max_lag_secs: 0,
+            files: FileQueueStats::default(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Whether this is a standby that has fallen too far behind, or has
+    /// not heard from its primary yet
+    pub fn is_lagging(&self) -> bool {
+        self.role == ReplicationRole::Standby
+            && !matches!(self.lag_secs, Some(lag) if lag <= self.max_lag_secs as f64)
+    }
+
+    /// Seconds the longest waiting message file has waited at `now`
+    pub fn file_lag_secs(&self, now: DateTime<Utc>) -> f64 {
+        self.files
+            .oldest_queued_at
+            .map(|queued_at| seconds_between(queued_at, now))
+            .unwrap_or(0.0)
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn metrics(&self, now: DateTime<Utc>) -> String {
+        let mut out = String::new();
+        let unlabelled = |value: f64| vec![(String::new(), value)];
+
+        write_gauge(
+            &mut out,
+            "mairust_replication_enabled",
+            "Whether replication is enabled",
+            &unlabelled(f64::from(u8::from(self.enabled))),
+        );
+        if !self.enabled {
+            return out;
+        }
+        write_gauge(
+            &mut out,
+            "mairust_replication_standby",
+            "Whether this server is a standby",
+            &unlabelled(f64::from(u8::from(self.role == ReplicationRole::Standby))),
+        );
+        if let Some(heartbeat_at) = self.heartbeat_at {
+            write_gauge(
+                &mut out,
+                "mairust_replication_heartbeat_timestamp_seconds",
+                "Time of the last heartbeat of the primary",
+                &unlabelled(heartbeat_at.timestamp() as f64),
+            );
+        }
+        if self.role == ReplicationRole::Standby {
+            if let Some(lag) = self.lag_secs {
+                write_gauge(
+                    &mut out,
+                    "mairust_replication_lag_seconds",
+                    "Age of the last replicated heartbeat of the primary",
+                    &unlabelled(lag),
+                );
+            }
+            write_gauge(
+                &mut out,
+                "mairust_replication_files_pending",
+                "Message files waiting to be copied or deleted",
+                &unlabelled(self.files.pending as f64),
+            );
+            write_gauge(
+                &mut out,
+                "mairust_replication_files_failing",
+                "Message files whose last copy or deletion failed",
+                &unlabelled(self.files.failing as f64),
+            );
+            write_gauge(
+                &mut out,
+                "mairust_replication_files_lag_seconds",
+                "Time the longest waiting message file has waited",
+                &unlabelled(self.file_lag_secs(now)),
+            );
+        }
+        let lags: Vec<_> = self
+            .subscribers
+            .iter()
+            .filter_map(|subscriber| {
+                subscriber.replay_lag_secs.map(|lag| {
+                    (
+                        format!("{{subscription=\"{}\"}}", escape_label(&subscriber.name)),
+                        lag,
+                    )
+                })
+            })
+            .collect();
+        if !lags.is_empty() {
+            write_gauge(
+                &mut out,
+                "mairust_replication_subscriber_lag_seconds",
+                "Replay lag of the standbys of this primary",
+                &lags,
+            );
+        }
+        out
+    }
+}
+
+/// Append a gauge and its samples (label set, value)
+fn write_gauge(out: &mut String, name: &str, help: &str, samples: &[(String, f64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    ((to - from).num_milliseconds() as f64 / 1000.0).max(0.0)
+}
+
+/// SHA-256 checksum (hex) of a message file
+pub fn checksum(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Wait before the next attempt after `attempts` failed ones: a minute,
+/// doubling up to an hour
+fn retry_delay(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(0, 6) as u32;
+    Duration::minutes((1i64 << exponent).min(MAX_RETRY_MINUTES))
+}
+
+/// URL of a message file on the primary's API
+fn file_url(primary_api_url: &str, storage_path: &str) -> Result<Url> {
+    let mut url = Url::parse(primary_api_url)?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid primary API URL {}", primary_api_url))?
+        .pop_if_empty()
+        .extend(FILES_PATH)
+        .extend(storage_path.split('/'));
+    Ok(url)
+}
+
+/// Replication role, status and promotion of a server
+#[derive(Clone)]
+pub struct Replication {
+    db_pool: DatabasePool,
+    config: ReplicationConfig,
+}
+
+impl Replication {
+    /// Create a replication handle
+    pub fn new(db_pool: DatabasePool, config: ReplicationConfig) -> Self {
+        Self { db_pool, config }
+    }
+
+    fn repository(&self) -> ReplicationRepository {
+        ReplicationRepository::new(self.db_pool.clone())
+    }
+
+    /// Role of this server: a standby while it is subscribed to a primary
+    pub async fn role(&self) -> Result<ReplicationRole> {
+        if !self.config.enabled {
+            return Ok(ReplicationRole::Primary);
+        }
+        Ok(
+            if self
+                .repository()
+                .has_subscription(&self.config.subscription)
+                .await?
+            {
+                ReplicationRole::Standby
+            } else {
+                ReplicationRole::Primary
+            },
+        )
+    }
+
+    /// Current replication state
+    pub async fn status(&self) -> Result<ReplicationStatus> {
+        if !self.config.enabled {
+            return Ok(ReplicationStatus::disabled());
+        }
+        let repository = self.repository();
+        let role = self.role().await?;
+        let heartbeat_at = repository.heartbeat().await?;
+        let now = Utc::now();
+        let (files, subscribers) = match role {
+            ReplicationRole::Standby => (repository.file_queue_stats().await?, Vec::new()),
+            ReplicationRole::Primary => (
+                FileQueueStats::default(),
+                repository.subscriber_lags().await?,
+            ),
+        };
+        Ok(ReplicationStatus {
+            enabled: true,
+            role,
+            heartbeat_at,
+            lag_secs: match role {
+                ReplicationRole::Standby => heartbeat_at.map(|at| seconds_between(at, now)),
+                ReplicationRole::Primary => None,
+            },
+            max_lag_secs: self.config.max_lag_secs,
+            files,
+            subscribers,
+        })
+    }
+
+    /// Record heartbeats while this server is a primary
+    pub async fn run_heartbeat(self) {
+        let mut ticker = interval(TokioDuration::from_secs(self.config.heartbeat_secs.max(1)));
+
+        info!("Replication heartbeat started");
+
+        loop {
+            ticker.tick().await;
+
+            match self.role().await {
+                Ok(ReplicationRole::Primary) => {
+                    if let Err(e) = self.repository().beat().await {
+                        error!("Failed to record replication heartbeat: {}", e);
+                    }
+                }
+                // Heartbeats of a standby would conflict with its primary's
+                Ok(ReplicationRole::Standby) => {}
+                Err(e) => error!("Failed to read replication role: {}", e),
+            }
+        }
+    }
+
+    /// Wait until this standby has been promoted
+    pub async fn wait_for_promotion(&self) {
+        let mut ticker = interval(TokioDuration::from_secs(PROMOTION_POLL_SECS));
+        loop {
+            ticker.tick().await;
+            match self.role().await {
+                Ok(ReplicationRole::Primary) => return,
+                Ok(ReplicationRole::Standby) => {}
+                Err(e) => warn!("Failed to read replication role: {}", e),
+            }
+        }
+    }
+
+    /// Make this standby a primary by dropping its subscription
+    ///
+    /// The standby keeps whatever it had replicated; message files still
+    /// queued stay queued and are reported by [`Replication::status`].
+    pub async fn promote(&self) -> Result<ReplicationStatus> {
+        if !self.config.enabled {
+            bail!("Replication is not enabled");
+        }
+        if self.role().await? != ReplicationRole::Standby {
+            bail!(
+                "This server is not a standby (no subscription '{}')",
+                self.config.subscription
+            );
+        }
+        let before = self.status().await?;
+        self.repository()
+            .unsubscribe(&self.config.subscription)
+            .await?;
+        info!(
+            "Promoted to primary, {} message files were not copied",
+            before.files.pending
+        );
+        Ok(before)
+    }
+}
+
+/// Copies message files from the primary to a standby
+pub struct FileSync<S: FileStorage> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+    config: ReplicationConfig,
+    client: Client,
+}
+
+impl<S: FileStorage> FileSync<S> {
+    /// Create a file sync of a standby; fails without the primary's API
+    pub fn new(
+        db_pool: DatabasePool,
+        file_storage: Arc<S>,
+        config: ReplicationConfig,
+    ) -> Result<Self> {
+        let Some(primary_api_url) = config.primary_api_url.as_deref() else {
+            bail!("replication.primary_api_url is required on a standby");
+        };
+        file_url(primary_api_url, "")?;
+        if config.primary_api_key.is_none() {
+            bail!("replication.primary_api_key is required on a standby");
+        }
+        let client = Client::builder()
+            .timeout(std::time::Duration::from_secs(FILE_TIMEOUT_SECS))
+            .build()?;
+        Ok(Self {
+            db_pool,
+            file_storage,
+            config,
+            client,
+        })
+    }
+
+    fn repository(&self) -> ReplicationRepository {
+        ReplicationRepository::new(self.db_pool.clone())
+    }
+
+    /// Queue the files the standby lacks, then copy queued files as they
+    /// come
+    pub async fn run(self) {
+        match self.scan().await {
+            Ok(queued) => info!("Replication file scan queued {} files", queued),
+            Err(e) => error!("Replication file scan failed: {}", e),
+        }
+
+        let mut ticker = interval(TokioDuration::from_secs(
+            self.config.sync_interval_secs.max(1),
+        ));
+
+        info!("Replication file sync started");
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = self.sync_due().await {
+                error!("Error syncing replicated files: {}", e);
+            }
+        }
+    }
+
+    /// Queue the files of messages that are missing here, or that differ
+    /// from the primary's when `verify_existing` is set
+    ///
+    /// Catches up on messages copied when the subscription was created,
+    /// which do not go through the queue trigger.
+    pub async fn scan(&self) -> Result<u64> {
+        let repository = self.repository();
+        let mut after = Uuid::nil();
+        let mut queued = 0;
+        loop {
+            let rows = repository
+                .message_files(after, self.config.sync_batch_size.max(1))
+                .await?;
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            after = *last;
+
+            for (_, path) in rows {
+                let stale = if !self.file_storage.exists(&path).await? {
+                    true
+                } else if self.config.verify_existing {
+                    match self.differs(&path).await {
+                        Ok(differs) => differs,
+                        Err(e) => {
+                            warn!("Failed to verify replicated file {}: {}", path, e);
+                            false
+                        }
+                    }
+                } else {
+                    false
+                };
+                if stale {
+                    repository.queue_copy(&path).await?;
+                    queued += 1;
+                }
+            }
+        }
+        Ok(queued)
+    }
+
+    /// Copy or delete the queued files that are due
+    pub async fn sync_due(&self) -> Result<usize> {
+        let repository = self.repository();
+        let files = repository
+            .due_files(self.config.sync_batch_size.max(1))
+            .await?;
+        let mut done = 0;
+        for file in files {
+            let result = if file.is_delete() {
+                self.delete(&file).await
+            } else {
+                self.copy(&file.storage_path).await
+            };
+            match result {
+                Ok(()) => {
+                    repository.file_done(&file).await?;
+                    done += 1;
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to {} replicated file {}: {}",
+                        file.action, file.storage_path, e
+                    );
+                    let retry_at = Utc::now() + retry_delay(file.attempts);
+                    repository
+                        .file_failed(&file, &e.to_string(), retry_at)
+                        .await?;
+                }
+            }
+        }
+        if done > 0 {
+            debug!("Synced {} replicated files", done);
+        }
+        Ok(done)
+    }
+
+    /// Copy a file from the primary, checking its checksum
+    async fn copy(&self, storage_path: &str) -> Result<()> {
+        let response = self
+            .request(reqwest::Method::GET, storage_path)?
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            // The message was deleted on the primary since; its deletion
+            // reaches this standby through replication
+            if !self.repository().is_message_file(storage_path).await? {
+                return Ok(());
+            }
+            bail!("the primary does not have the file");
+        }
+        let response = response.error_for_status()?;
+        let expected = expected_checksum(&response)?;
+        let data = response.bytes().await?;
+        let actual = checksum(&data);
+        if actual != expected {
+            bail!("checksum {} does not match {}", actual, expected);
+        }
+        self.file_storage.store(storage_path, &data).await?;
+        Ok(())
+    }
+
+    /// Delete the file of a message deleted on the primary, unless another
+    /// message still uses it
+    async fn delete(&self, file: &QueuedFile) -> Result<()> {
+        if self
+            .repository()
+            .is_message_file(&file.storage_path)
+            .await?
+        {
+            return Ok(());
+        }
+        if self.file_storage.exists(&file.storage_path).await? {
+            self.file_storage.delete(&file.storage_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the local file differs from the primary's
+    async fn differs(&self, storage_path: &str) -> Result<bool> {
+        let response = self
+            .request(reqwest::Method::HEAD, storage_path)?
+            .send()
+            .await?
+            .error_for_status()?;
+        let expected = expected_checksum(&response)?;
+        let local = checksum(&self.file_storage.read(storage_path).await?);
+        Ok(local != expected)
+    }
+
+    fn request(
+        &self,
+        method: reqwest::Method,
+        storage_path: &str,
+    ) -> Result<reqwest::RequestBuilder> {
+        let url = file_url(
+            self.config.primary_api_url.as_deref().unwrap_or_default(),
+            storage_path,
+        )?;
+        Ok(self.client.request(method, url).header(
+            "X-API-Key",
+            self.config.primary_api_key.as_deref().unwrap_or_default(),
+        ))
+    }
+}
+
+/// Checksum the primary sent with a file
+fn expected_checksum(response: &reqwest::Response) -> Result<String> {
+    response
+        .headers()
+        .get(CHECKSUM_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_lowercase())
+        .ok_or_else(|| anyhow!("the primary sent no checksum"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mairust_common::config::{DatabaseConfig, StorageLayoutConfig, StorageLayoutScheme};
+    use mairust_storage::{LocalStorage, StorageLayout};
+    use wiremock::matchers::{header, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn standby_status(lag_secs: Option<f64>) -> ReplicationStatus {
+        ReplicationStatus {
+            enabled: true,
+            role: ReplicationRole::Standby,
+            heartbeat_at: None,
+            lag_secs,
+            max_lag_secs: 300,
+            files: FileQueueStats {
+                pending: 3,
+                failing: 1,
+                oldest_queued_at: None,
+            },
+            subscribers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(
+            checksum(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::minutes(1));
+        assert_eq!(retry_delay(3), Duration::minutes(8));
+        assert_eq!(retry_delay(6), Duration::minutes(60));
+        assert_eq!(retry_delay(50), Duration::minutes(60));
+    }
+
+    #[test]
+    fn test_file_url() {
+        let path = "0b6c/inbox/018d.eml";
+        assert_eq!(
+            file_url("https://mail.example.com:8080", path)
+                .unwrap()
+                .as_str(),
+            "https://mail.example.com:8080/api/v1/admin/system/replication/files/0b6c/inbox/018d.eml"
+        );
+        assert_eq!(
+            file_url("https://mail.example.com/mairust/", "a b/c.eml")
+                .unwrap()
+                .as_str(),
+            "https://mail.example.com/mairust/api/v1/admin/system/replication/files/a%20b/c.eml"
+        );
+        assert!(file_url("not a url", path).is_err());
+    }
+
+    #[test]
+    fn test_is_lagging() {
+        assert!(!standby_status(Some(10.0)).is_lagging());
+        assert!(standby_status(Some(301.0)).is_lagging());
+        // A standby that has not heard from its primary yet
+        assert!(standby_status(None).is_lagging());
+
+        let mut primary = standby_status(None);
+        primary.role = ReplicationRole::Primary;
+        assert!(!primary.is_lagging());
+        assert!(!ReplicationStatus::disabled().is_lagging());
+    }
+
+    #[test]
+    fn test_metrics() {
+        assert_eq!(
+            ReplicationStatus::disabled().metrics(Utc::now()),
+            "# HELP mairust_replication_enabled Whether replication is enabled\n\
+             # TYPE mairust_replication_enabled gauge\n\
+             mairust_replication_enabled 0\n"
+        );
+
+        let now = Utc::now();
+        let mut status = standby_status(Some(12.5));
+        status.files.oldest_queued_at = Some(now - Duration::seconds(30));
+        let metrics = status.metrics(now);
+        assert!(metrics.contains("mairust_replication_standby 1\n"));
+        assert!(metrics.contains("mairust_replication_lag_seconds 12.5\n"));
+        assert!(metrics.contains("mairust_replication_files_pending 3\n"));
+        assert!(metrics.contains("mairust_replication_files_failing 1\n"));
+        assert!(metrics.contains("mairust_replication_files_lag_seconds 30\n"));
+
+        let mut primary = standby_status(None);
+        primary.role = ReplicationRole::Primary;
+        primary.subscribers = vec![
+            SubscriberLag {
+                name: "mairust_standby".to_string(),
+                replay_lag_secs: Some(0.25),
+            },
+            SubscriberLag {
+                name: "idle".to_string(),
+                replay_lag_secs: None,
+            },
+        ];
+        let metrics = primary.metrics(now);
+        assert!(metrics.contains("mairust_replication_standby 0\n"));
+        assert!(!metrics.contains("mairust_replication_lag_seconds"));
+        assert!(metrics.contains(
+            "mairust_replication_subscriber_lag_seconds{subscription=\"mairust_standby\"} 0.25\n"
+        ));
+        assert!(!metrics.contains("idle"));
+    }
+
+    #[tokio::test]
+    async fn test_copy_into_configured_storage() {
+        // A standby whose storage uses a hashed layout and its own root
+        let root = std::env::temp_dir().join(format!("mairust-standby-{}", Uuid::new_v4()));
+        let storage = Arc::new(LocalStorage::from_path(&root).unwrap().with_layout(
+            StorageLayout::from(&StorageLayoutConfig {
+                scheme: StorageLayoutScheme::Hashed,
+                fanout: 2,
+                generation: 3,
+            }),
+        ));
+        let storage_path = storage.message_path(Uuid::new_v4(), Uuid::new_v4(), Uuid::now_v7());
+        assert!(storage_path.starts_with("g3/"));
+        let data = b"Subject: replicated\r\n\r\nbody\r\n";
+
+        let primary = MockServer::start().await;
+        Mock::given(path(format!(
+            "/api/v1/admin/system/replication/files/{}",
+            storage_path
+        )))
+        .and(header("X-API-Key", "standby-key"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(CHECKSUM_HEADER, checksum(data).as_str())
+                .set_body_bytes(data.to_vec()),
+        )
+        .mount(&primary)
+        .await;
+
+        let db_pool = DatabasePool::connect_lazy(&DatabaseConfig {
+            backend: "postgres".to_string(),
+            url: Some("postgres://localhost/mairust".to_string()),
+            path: None,
+            max_connections: 1,
+            min_connections: 0,
+        })
+        .unwrap();
+        let sync = FileSync::new(
+            db_pool,
+            storage.clone(),
+            ReplicationConfig {
+                primary_api_url: Some(primary.uri()),
+                primary_api_key: Some("standby-key".to_string()),
+                ..ReplicationConfig::default()
+            },
+        )
+        .unwrap();
+
+        sync.copy(&storage_path).await.unwrap();
+        assert_eq!(std::fs::read(root.join(&storage_path)).unwrap(), data);
+        assert!(!sync.differs(&storage_path).await.unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
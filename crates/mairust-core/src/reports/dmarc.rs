@@ -0,0 +1,284 @@
+//! DMARC aggregate report parsing (RFC 7489, appendix C)
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use mairust_storage::repository::received_reports::NewDmarcRecord;
+use roxmltree::{Document, Node};
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// Policy the reporter found published for the domain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReportedPolicy {
+    pub domain: String,
+    pub adkim: Option<String>,
+    pub aspf: Option<String>,
+    pub p: String,
+    pub sp: Option<String>,
+    pub pct: Option<u8>,
+}
+
+/// A DMARC aggregate report
+#[derive(Debug, Clone)]
+pub struct AggregateReport {
+    pub org_name: String,
+    pub email: Option<String>,
+    pub report_id: String,
+    pub begin: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub policy: ReportedPolicy,
+    pub records: Vec<NewDmarcRecord>,
+}
+
+/// Parse the XML of an aggregate report
+pub fn parse_aggregate_report(xml: &str) -> Result<AggregateReport> {
+    let document = Document::parse(xml).context("Invalid report XML")?;
+    let feedback = document.root_element();
+    if feedback.tag_name().name() != "feedback" {
+        bail!("Not a DMARC aggregate report");
+    }
+
+    let metadata = required_child(feedback, "report_metadata")?;
+    let date_range = required_child(metadata, "date_range")?;
+    let published = required_child(feedback, "policy_published")?;
+
+    let policy = ReportedPolicy {
+        domain: required_text(published, "domain")?.to_lowercase(),
+        adkim: text(published, "adkim"),
+        aspf: text(published, "aspf"),
+        p: required_text(published, "p")?,
+        sp: text(published, "sp"),
+        pct: text(published, "pct").and_then(|pct| pct.parse().ok()),
+    };
+
+    let records = children(feedback, "record")
+        .map(parse_record)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(AggregateReport {
+        org_name: required_text(metadata, "org_name")?,
+        email: text(metadata, "email"),
+        report_id: required_text(metadata, "report_id")?,
+        begin: timestamp(&required_text(date_range, "begin")?)?,
+        end: timestamp(&required_text(date_range, "end")?)?,
+        policy,
+        records,
+    })
+}
+
+/// One `<record>`: messages from a source with the same results
+fn parse_record(record: Node) -> Result<NewDmarcRecord> {
+    let row = required_child(record, "row")?;
+    let evaluated = required_child(row, "policy_evaluated")?;
+    let identifiers = required_child(record, "identifiers")?;
+
+    let source_ip = required_text(row, "source_ip")?;
+    let source_ip: IpAddr = source_ip
+        .parse()
+        .map_err(|_| anyhow!("Invalid source_ip {}", source_ip))?;
+    let count = required_text(row, "count")?;
+    let message_count: i64 = count
+        .parse()
+        .map_err(|_| anyhow!("Invalid count {}", count))?;
+
+    let reasons: Vec<serde_json::Value> = children(evaluated, "reason")
+        .map(|reason| {
+            serde_json::json!({
+                "type": text(reason, "type"),
+                "comment": text(reason, "comment"),
+            })
+        })
+        .collect();
+    let (dkim_results, spf_results) = match child(record, "auth_results") {
+        Some(auth) => (
+            children(auth, "dkim")
+                .map(|dkim| {
+                    serde_json::json!({
+                        "domain": text(dkim, "domain"),
+                        "selector": text(dkim, "selector"),
+                        "result": text(dkim, "result"),
+                    })
+                })
+                .collect(),
+            children(auth, "spf")
+                .map(|spf| {
+                    serde_json::json!({
+                        "domain": text(spf, "domain"),
+                        "scope": text(spf, "scope"),
+                        "result": text(spf, "result"),
+                    })
+                })
+                .collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+
+    Ok(NewDmarcRecord {
+        source_ip: source_ip.to_string(),
+        message_count,
+        disposition: text(evaluated, "disposition").unwrap_or_else(|| "none".to_string()),
+        dkim: text(evaluated, "dkim").unwrap_or_else(|| "fail".to_string()),
+        spf: text(evaluated, "spf").unwrap_or_else(|| "fail".to_string()),
+        reasons: serde_json::Value::Array(reasons),
+        header_from: required_text(identifiers, "header_from")?.to_lowercase(),
+        envelope_from: text(identifiers, "envelope_from"),
+        dkim_results: serde_json::Value::Array(dkim_results),
+        spf_results: serde_json::Value::Array(spf_results),
+    })
+}
+
+/// Child elements named `name`, in any namespace
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+fn required_child<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> Result<Node<'a, 'input>> {
+    child(node, name).ok_or_else(|| anyhow!("Report has no <{}>", name))
+}
+
+/// Trimmed text of a child element, `None` when missing or empty
+fn text(node: Node, name: &'static str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
+
+fn required_text(node: Node, name: &'static str) -> Result<String> {
+    text(node, name).ok_or_else(|| anyhow!("Report has no <{}>", name))
+}
+
+fn timestamp(value: &str) -> Result<DateTime<Utc>> {
+    value
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .ok_or_else(|| anyhow!("Invalid timestamp {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<feedback>
+  <report_metadata>
+    <org_name>google.com</org_name>
+    <email>noreply-dmarc-support@google.com</email>
+    <report_id>17390251723948562</report_id>
+    <date_range>
+      <begin>1760572800</begin>
+      <end>1760659199</end>
+    </date_range>
+  </report_metadata>
+  <policy_published>
+    <domain>Example.com</domain>
+    <adkim>r</adkim>
+    <aspf>r</aspf>
+    <p>reject</p>
+    <sp>reject</sp>
+    <pct>100</pct>
+  </policy_published>
+  <record>
+    <row>
+      <source_ip>192.0.2.10</source_ip>
+      <count>12</count>
+      <policy_evaluated>
+        <disposition>none</disposition>
+        <dkim>pass</dkim>
+        <spf>pass</spf>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <dkim>
+        <domain>example.com</domain>
+        <selector>mail</selector>
+        <result>pass</result>
+      </dkim>
+      <spf>
+        <domain>example.com</domain>
+        <result>pass</result>
+      </spf>
+    </auth_results>
+  </record>
+  <record>
+    <row>
+      <source_ip>203.0.113.66</source_ip>
+      <count>3</count>
+      <policy_evaluated>
+        <disposition>reject</disposition>
+        <dkim>fail</dkim>
+        <spf>fail</spf>
+        <reason>
+          <type>local_policy</type>
+        </reason>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <header_from>example.com</header_from>
+      <envelope_from>spoofer.example</envelope_from>
+    </identifiers>
+    <auth_results>
+      <spf>
+        <domain>spoofer.example</domain>
+        <result>softfail</result>
+      </spf>
+    </auth_results>
+  </record>
+</feedback>"#;
+
+    #[test]
+    fn test_parse_aggregate_report() {
+        let report = parse_aggregate_report(REPORT).unwrap();
+        assert_eq!(report.org_name, "google.com");
+        assert_eq!(report.report_id, "17390251723948562");
+        assert_eq!(report.begin.timestamp(), 1760572800);
+        assert_eq!(report.end.timestamp(), 1760659199);
+        assert_eq!(report.policy.domain, "example.com");
+        assert_eq!(report.policy.p, "reject");
+        assert_eq!(report.policy.pct, Some(100));
+        assert_eq!(report.records.len(), 2);
+
+        let spoofed = &report.records[1];
+        assert_eq!(spoofed.source_ip, "203.0.113.66");
+        assert_eq!(spoofed.message_count, 3);
+        assert_eq!(spoofed.disposition, "reject");
+        assert!(!spoofed.passed());
+        assert_eq!(spoofed.envelope_from.as_deref(), Some("spoofer.example"));
+        assert_eq!(spoofed.reasons[0]["type"], "local_policy");
+        assert_eq!(spoofed.spf_results[0]["result"], "softfail");
+        assert!(report.records[0].passed());
+    }
+
+    #[test]
+    fn test_parse_namespaced_report() {
+        let xml = REPORT.replace(
+            "<feedback>",
+            r#"<feedback xmlns="urn:ietf:params:xml:ns:dmarc-2.0">"#,
+        );
+        assert_eq!(parse_aggregate_report(&xml).unwrap().records.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_invalid_report() {
+        assert!(parse_aggregate_report("<feedback>").is_err());
+        assert!(parse_aggregate_report("<html></html>").is_err());
+        let xml = REPORT.replace("192.0.2.10", "not an address");
+        assert!(parse_aggregate_report(&xml).is_err());
+    }
+}
@@ -0,0 +1,473 @@
+//! Received DMARC and TLS-RPT reports
+//!
+//! Mail to a domain's report addresses is not delivered to a mailbox; its
+//! DMARC aggregate reports (XML, plain, gzip or zip) and TLS-RPT reports
+//! (JSON, plain or gzip) are parsed into the received report tables. Domain
+//! settings (`extra_settings.report_addresses`) name the local parts:
+//!
+//! ```json
+//! ["dmarc-reports", "tls-reports"]
+//! ```
+//!
+//! A report is stored for the tenant domain it is about, or the nearest
+//! parent domain of it, and only when that domain belongs to the tenant of
+//! the address it was sent to.
+
+pub mod dmarc;
+pub mod tls;
+
+pub use dmarc::{parse_aggregate_report, AggregateReport, ReportedPolicy};
+pub use tls::{parse_tls_report, TlsReport};
+
+use crate::inbound::content_type;
+use crate::subdomains::parent_domains;
+use anyhow::{anyhow, bail, Result};
+use flate2::read::{DeflateDecoder, GzDecoder};
+use mail_parser::{MessageParser, MimeHeaders};
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Domain;
+use mairust_storage::repository::received_reports::{NewDmarcReport, NewTlsReport};
+use mairust_storage::repository::{DomainRepository, ReceivedReportRepository};
+use std::io::Read;
+use tracing::{info, warn};
+
+/// Largest report accepted after decompression
+const MAX_REPORT_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Report addresses of a domain (`extra_settings.report_addresses`), as
+/// lowercased local parts
+pub fn report_addresses(extra_settings: &serde_json::Value) -> Vec<String> {
+    extra_settings
+        .get("report_addresses")
+        .and_then(|v| v.as_array())
+        .map(|addresses| {
+            addresses
+                .iter()
+                .filter_map(|a| a.as_str())
+                .map(|a| a.trim().to_lowercase())
+                .filter(|a| !a.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Kind of report found in a message part
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    Dmarc,
+    Tls,
+}
+
+/// How a report is packaged in its part
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Packaging {
+    Plain,
+    Gzip,
+    Zip,
+}
+
+/// Kind and packaging of a part, from its media type and file name
+fn classify(content_type: &str, filename: &str) -> Option<(ReportKind, Packaging)> {
+    let filename = filename.to_lowercase();
+    match content_type {
+        "application/tlsrpt+gzip" => return Some((ReportKind::Tls, Packaging::Gzip)),
+        "application/tlsrpt+json" => return Some((ReportKind::Tls, Packaging::Plain)),
+        _ => {}
+    }
+    if filename.ends_with(".json.gz") {
+        Some((ReportKind::Tls, Packaging::Gzip))
+    } else if filename.ends_with(".json") {
+        Some((ReportKind::Tls, Packaging::Plain))
+    } else if matches!(
+        content_type,
+        "application/zip" | "application/x-zip-compressed"
+    ) || filename.ends_with(".zip")
+    {
+        Some((ReportKind::Dmarc, Packaging::Zip))
+    } else if matches!(content_type, "application/gzip" | "application/x-gzip")
+        || filename.ends_with(".gz")
+    {
+        Some((ReportKind::Dmarc, Packaging::Gzip))
+    } else if matches!(content_type, "text/xml" | "application/xml") || filename.ends_with(".xml") {
+        Some((ReportKind::Dmarc, Packaging::Plain))
+    } else {
+        None
+    }
+}
+
+/// Reports in a message, decompressed
+pub fn extract_reports(message: &[u8]) -> Vec<(ReportKind, Vec<u8>)> {
+    let Some(parsed) = MessageParser::default().parse(message) else {
+        return Vec::new();
+    };
+    parsed
+        .parts
+        .iter()
+        .filter_map(|part| {
+            let content_type = content_type(part);
+            let (kind, packaging) =
+                classify(&content_type, part.attachment_name().unwrap_or_default())?;
+            let contents = part.contents();
+            let report = match packaging {
+                Packaging::Plain => Ok(contents.to_vec()),
+                Packaging::Gzip => read_limited(GzDecoder::new(contents)),
+                Packaging::Zip => unzip(contents),
+            };
+            match report {
+                Ok(report) => Some((kind, report)),
+                Err(e) => {
+                    warn!("Failed to unpack report: {}", e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// TLS report posted over HTTPS (RFC 8460 section 5.4), decompressed;
+/// `None` when the media type is not a TLS report's
+pub fn unpack_posted_tls_report(content_type: &str, body: &[u8]) -> Option<Result<Vec<u8>>> {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    match media_type.as_str() {
+        "application/tlsrpt+gzip" => Some(read_limited(GzDecoder::new(body))),
+        "application/tlsrpt+json" => Some(Ok(body.to_vec())),
+        _ => None,
+    }
+}
+
+/// Read a decompressed stream of at most `MAX_REPORT_SIZE` bytes
+fn read_limited(reader: impl Read) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(MAX_REPORT_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_REPORT_SIZE {
+        bail!("Report is larger than {} bytes", MAX_REPORT_SIZE);
+    }
+    Ok(data)
+}
+
+/// First file of a zip archive
+fn unzip(data: &[u8]) -> Result<Vec<u8>> {
+    let u16_at = |at: usize| {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| anyhow!("Truncated zip archive"))
+    };
+    let u32_at = |at: usize| {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| anyhow!("Truncated zip archive"))
+    };
+
+    // The end of central directory record is followed by at most a 64 KiB
+    // comment
+    let end = (0..data.len().saturating_sub(21))
+        .rev()
+        .take(65_536)
+        .find(|&at| data[at..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| anyhow!("Not a zip archive"))?;
+    let central = u32_at(end + 16)?;
+    if !data[central.min(data.len())..].starts_with(b"PK\x01\x02") {
+        bail!("Damaged zip archive");
+    }
+    let method = u16_at(central + 10)?;
+    let compressed_size = u32_at(central + 20)?;
+    let local = u32_at(central + 42)?;
+    if !data[local.min(data.len())..].starts_with(b"PK\x03\x04") {
+        bail!("Damaged zip archive");
+    }
+    let start = local + 30 + u16_at(local + 26)? + u16_at(local + 28)?;
+    let compressed = data
+        .get(start..start + compressed_size)
+        .ok_or_else(|| anyhow!("Truncated zip archive"))?;
+
+    match method {
+        0 => read_limited(compressed),
+        8 => read_limited(DeflateDecoder::new(compressed)),
+        _ => bail!("Unsupported zip compression method {}", method),
+    }
+}
+
+/// Reports stored from a message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestSummary {
+    pub dmarc_reports: usize,
+    pub tls_reports: usize,
+    /// Reports that could not be parsed or are about no domain of the tenant
+    pub skipped: usize,
+}
+
+/// Stores the reports sent to report addresses
+pub struct ReportIngester {
+    db_pool: DatabasePool,
+}
+
+impl ReportIngester {
+    /// Create an ingester
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Store the reports of a message sent to a report address of `domain`
+    pub async fn ingest(&self, domain: &Domain, message: &[u8]) -> Result<IngestSummary> {
+        let repository = ReceivedReportRepository::new(self.db_pool.clone());
+        let mut summary = IngestSummary::default();
+
+        for (kind, report) in extract_reports(message) {
+            match kind {
+                ReportKind::Dmarc => {
+                    let report = match std::str::from_utf8(&report)
+                        .map_err(anyhow::Error::from)
+                        .and_then(parse_aggregate_report)
+                    {
+                        Ok(report) => report,
+                        Err(e) => {
+                            warn!("Invalid DMARC report sent to {}: {}", domain.name, e);
+                            summary.skipped += 1;
+                            continue;
+                        }
+                    };
+                    let Some(about) = self
+                        .tenant_domain(domain.tenant_id, &report.policy.domain)
+                        .await?
+                    else {
+                        warn!(
+                            "DMARC report from {} is about {}, not a domain of the tenant",
+                            report.org_name, report.policy.domain
+                        );
+                        summary.skipped += 1;
+                        continue;
+                    };
+                    let stored = repository
+                        .insert_dmarc_report(&NewDmarcReport {
+                            tenant_id: about.tenant_id,
+                            domain_id: about.id,
+                            org_name: report.org_name.clone(),
+                            email: report.email,
+                            report_id: report.report_id.clone(),
+                            begin_at: report.begin,
+                            end_at: report.end,
+                            policy: serde_json::to_value(&report.policy)?,
+                            records: report.records,
+                        })
+                        .await?;
+                    match stored {
+                        Some(_) => summary.dmarc_reports += 1,
+                        None => info!(
+                            "DMARC report {} from {} was already stored",
+                            report.report_id, report.org_name
+                        ),
+                    }
+                }
+                ReportKind::Tls => {
+                    self.store_tls_report(domain, &report, &mut summary).await?;
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Store a TLS report about `domain` that was posted over HTTPS
+    pub async fn ingest_tls_report(&self, domain: &Domain, report: &[u8]) -> Result<IngestSummary> {
+        let mut summary = IngestSummary::default();
+        self.store_tls_report(domain, report, &mut summary).await?;
+        Ok(summary)
+    }
+
+    /// Store a TLS report received for `domain`, one row per policy of a
+    /// domain of its tenant
+    async fn store_tls_report(
+        &self,
+        domain: &Domain,
+        report: &[u8],
+        summary: &mut IngestSummary,
+    ) -> Result<()> {
+        let report = match parse_tls_report(report) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("Invalid TLS report sent to {}: {}", domain.name, e);
+                summary.skipped += 1;
+                return Ok(());
+            }
+        };
+        let repository = ReceivedReportRepository::new(self.db_pool.clone());
+        for policy in report.policies {
+            let Some(about) = self
+                .tenant_domain(domain.tenant_id, &policy.policy.policy_domain)
+                .await?
+            else {
+                warn!(
+                    "TLS report from {} is about {}, not a domain of the tenant",
+                    report.organization_name, policy.policy.policy_domain
+                );
+                summary.skipped += 1;
+                continue;
+            };
+            let stored = repository
+                .insert_tls_report(&NewTlsReport {
+                    tenant_id: about.tenant_id,
+                    domain_id: about.id,
+                    org_name: report.organization_name.clone(),
+                    contact_info: report.contact_info.clone(),
+                    report_id: report.report_id.clone(),
+                    begin_at: report.date_range.start_datetime,
+                    end_at: report.date_range.end_datetime,
+                    policy_type: policy.policy.policy_type,
+                    policy_domain: policy.policy.policy_domain.to_lowercase(),
+                    policy_strings: serde_json::json!(policy.policy.policy_string),
+                    mx_hosts: serde_json::json!(policy.policy.mx_host),
+                    successful_sessions: saturating_i64(
+                        policy.summary.total_successful_session_count,
+                    ),
+                    failed_sessions: saturating_i64(policy.summary.total_failure_session_count),
+                    failure_details: serde_json::json!(policy.failure_details),
+                })
+                .await?;
+            if stored.is_some() {
+                summary.tls_reports += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// The tenant's domain a report about `name` belongs to: the domain
+    /// itself, or its nearest parent
+    async fn tenant_domain(&self, tenant_id: TenantId, name: &str) -> Result<Option<Domain>> {
+        let name = name.trim().trim_end_matches('.').to_lowercase();
+        let domains = DomainRepository::new(self.db_pool.clone());
+        for candidate in std::iter::once(name.as_str()).chain(parent_domains(&name)) {
+            if let Some(domain) = domains
+                .find_by_name_for_tenant(tenant_id, candidate)
+                .await?
+            {
+                return Ok(Some(domain));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn saturating_i64(count: u64) -> i64 {
+    i64::try_from(count).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_auth::dmarc_report::package_report;
+    use base64::Engine;
+    use chrono::Utc;
+    use mairust_common::config::DmarcReportCompression;
+
+    #[test]
+    fn test_report_addresses() {
+        assert!(report_addresses(&serde_json::json!({})).is_empty());
+        assert_eq!(
+            report_addresses(&serde_json::json!({
+                "report_addresses": ["DMARC-Reports", " ", 7, "tls-reports"]
+            })),
+            vec!["dmarc-reports", "tls-reports"]
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify("application/tlsrpt+gzip", "report.gz"),
+            Some((ReportKind::Tls, Packaging::Gzip))
+        );
+        assert_eq!(
+            classify(
+                "application/octet-stream",
+                "google.com!example.com!1!2.json.gz"
+            ),
+            Some((ReportKind::Tls, Packaging::Gzip))
+        );
+        assert_eq!(
+            classify("application/zip", "google.com!example.com!1!2.zip"),
+            Some((ReportKind::Dmarc, Packaging::Zip))
+        );
+        assert_eq!(
+            classify("application/octet-stream", "report.XML.GZ"),
+            Some((ReportKind::Dmarc, Packaging::Gzip))
+        );
+        assert_eq!(
+            classify("text/xml", ""),
+            Some((ReportKind::Dmarc, Packaging::Plain))
+        );
+        assert_eq!(classify("text/plain", ""), None);
+        assert_eq!(classify("application/pdf", "invoice.pdf"), None);
+    }
+
+    #[test]
+    fn test_unzip() {
+        let xml = b"<feedback></feedback>".repeat(50);
+        let archive =
+            package_report(&xml, "report.xml", DmarcReportCompression::Zip, Utc::now()).unwrap();
+        assert_eq!(unzip(&archive).unwrap(), xml);
+        assert!(unzip(b"PK not really").is_err());
+        assert!(unzip(&archive[..archive.len() - 30]).is_err());
+    }
+
+    #[test]
+    fn test_extract_reports() {
+        let xml = b"<feedback></feedback>";
+        let gzip =
+            package_report(xml, "report.xml", DmarcReportCompression::Gzip, Utc::now()).unwrap();
+        let message = format!(
+            "From: noreply-dmarc-support@google.com\r\n\
+             To: dmarc-reports@example.com\r\n\
+             Subject: Report domain: example.com\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+             \r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             This is an aggregate report.\r\n\
+             --b\r\n\
+             Content-Type: application/gzip; name=\"google.com!example.com!1!2.xml.gz\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             Content-Disposition: attachment; filename=\"google.com!example.com!1!2.xml.gz\"\r\n\
+             \r\n\
+             {}\r\n\
+             --b--\r\n",
+            base64::engine::general_purpose::STANDARD.encode(&gzip)
+        );
+        assert_eq!(
+            extract_reports(message.as_bytes()),
+            vec![(ReportKind::Dmarc, xml.to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_unpack_posted_tls_report() {
+        let json = br#"{"organization-name":"Example"}"#;
+        let gzip = package_report(
+            json,
+            "report.json",
+            DmarcReportCompression::Gzip,
+            Utc::now(),
+        )
+        .unwrap();
+        assert_eq!(
+            unpack_posted_tls_report("application/tlsrpt+gzip", &gzip).map(Result::unwrap),
+            Some(json.to_vec())
+        );
+        assert_eq!(
+            unpack_posted_tls_report("Application/TLSRPT+JSON; charset=utf-8", json)
+                .map(Result::unwrap),
+            Some(json.to_vec())
+        );
+        assert!(unpack_posted_tls_report("application/tlsrpt+gzip", json)
+            .unwrap()
+            .is_err());
+        assert!(unpack_posted_tls_report("application/json", json).is_none());
+    }
+}
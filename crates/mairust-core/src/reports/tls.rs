@@ -0,0 +1,118 @@
+//! TLS-RPT report parsing (RFC 8460, section 4)
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+/// A TLS-RPT report
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsReport {
+    pub organization_name: String,
+    pub date_range: TlsDateRange,
+    pub contact_info: Option<String>,
+    pub report_id: String,
+    #[serde(default)]
+    pub policies: Vec<TlsPolicyReport>,
+}
+
+/// Period a TLS-RPT report covers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsDateRange {
+    pub start_datetime: DateTime<Utc>,
+    pub end_datetime: DateTime<Utc>,
+}
+
+/// Sessions under one policy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsPolicyReport {
+    pub policy: TlsPolicy,
+    pub summary: TlsSummary,
+    /// Failure details, kept as sent
+    #[serde(default)]
+    pub failure_details: Vec<serde_json::Value>,
+}
+
+/// Policy the sender applied
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsPolicy {
+    /// `sts`, `tlsa` or `no-policy-found`
+    pub policy_type: String,
+    #[serde(default)]
+    pub policy_string: Vec<String>,
+    pub policy_domain: String,
+    /// MX patterns of an MTA-STS policy; some reporters send objects
+    #[serde(default)]
+    pub mx_host: Vec<serde_json::Value>,
+}
+
+/// Session counts under a policy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsSummary {
+    pub total_successful_session_count: u64,
+    pub total_failure_session_count: u64,
+}
+
+/// Parse the JSON of a TLS-RPT report
+pub fn parse_tls_report(json: &[u8]) -> Result<TlsReport> {
+    serde_json::from_slice(json).context("Invalid TLS-RPT report")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tls_report() {
+        let json = br#"{
+            "organization-name": "Company-X",
+            "date-range": {
+                "start-datetime": "2016-04-01T00:00:00Z",
+                "end-datetime": "2016-04-01T23:59:59Z"
+            },
+            "contact-info": "sts-reporting@company-x.example",
+            "report-id": "5065427c-23d3-47ca-b6e0-946ea0e8c4be",
+            "policies": [{
+                "policy": {
+                    "policy-type": "sts",
+                    "policy-string": ["version: STSv1", "mode: testing",
+                        "mx: *.mail.company-y.example", "max_age: 86400"],
+                    "policy-domain": "company-y.example",
+                    "mx-host": ["*.mail.company-y.example"]
+                },
+                "summary": {
+                    "total-successful-session-count": 5326,
+                    "total-failure-session-count": 303
+                },
+                "failure-details": [{
+                    "result-type": "certificate-expired",
+                    "sending-mta-ip": "2001:db8:abcd:0012::1",
+                    "receiving-mx-hostname": "mx1.mail.company-y.example",
+                    "failed-session-count": 100
+                }]
+            }]
+        }"#;
+        let report = parse_tls_report(json).unwrap();
+        assert_eq!(report.organization_name, "Company-X");
+        assert_eq!(report.date_range.end_datetime.timestamp(), 1459555199);
+        let policy = &report.policies[0];
+        assert_eq!(policy.policy.policy_type, "sts");
+        assert_eq!(policy.policy.policy_domain, "company-y.example");
+        assert_eq!(policy.policy.policy_string.len(), 4);
+        assert_eq!(policy.summary.total_failure_session_count, 303);
+        assert_eq!(
+            policy.failure_details[0]["result-type"],
+            "certificate-expired"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_tls_report() {
+        assert!(parse_tls_report(b"{}").is_err());
+        assert!(parse_tls_report(b"<feedback/>").is_err());
+    }
+}
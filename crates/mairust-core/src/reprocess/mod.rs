@@ -0,0 +1,12 @@
+//! Message reprocessing module
+//!
+//! Re-runs the inbound processing pipeline (spam filtering, policies,
+//! hooks, categorization and search indexing) for messages that are
+//! already stored, e.g. after a policy has been fixed or a plugin added.
+
+mod pipeline;
+
+pub use pipeline::{
+    MessageReprocessor, ReprocessOptions, ReprocessOutcome, ReprocessSelection, ReprocessStep,
+    ReprocessSummary, MAX_BATCH_SIZE,
+};
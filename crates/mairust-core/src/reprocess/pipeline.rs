@@ -0,0 +1,746 @@
+//! Reprocessing pipeline - re-runs inbound processing for stored messages
+
+use crate::hooks::HookManager;
+use crate::plugins::{
+    AiCategorizationPlugin, CategorizationInput, PluginContext, RuleBasedCategorizer,
+};
+use crate::policy::{PolicyContext, PolicyEngine};
+use crate::quarantine::{quarantine_metadata, quarantining_policy, QuarantineReason};
+use crate::search::{body_text, MessageDocument, MessageIndexer};
+use crate::spam::SpamFilter;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use mairust_storage::models::Message;
+use mairust_storage::repository::{
+    CategoryRepository, MailboxRepository, MailboxRepositoryTrait, QuarantineRepository,
+    QUARANTINE_SPECIAL_USE,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Maximum number of messages processed by a single request
+pub const MAX_BATCH_SIZE: i64 = 1000;
+
+/// Metadata key of the tags each stage added on the last run
+const STEP_TAGS_KEY: &str = "reprocess_tags";
+
+/// Tags added by each stage that adds tags
+type StepTags = HashMap<ReprocessStep, Vec<String>>;
+
+/// A single stage of the inbound pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReprocessStep {
+    Spam,
+    Policies,
+    Hooks,
+    Categorization,
+    Indexing,
+}
+
+impl ReprocessStep {
+    /// All pipeline stages in execution order
+    pub fn all() -> Vec<ReprocessStep> {
+        vec![
+            ReprocessStep::Spam,
+            ReprocessStep::Policies,
+            ReprocessStep::Hooks,
+            ReprocessStep::Categorization,
+            ReprocessStep::Indexing,
+        ]
+    }
+}
+
+impl std::fmt::Display for ReprocessStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReprocessStep::Spam => write!(f, "spam"),
+            ReprocessStep::Policies => write!(f, "policies"),
+            ReprocessStep::Hooks => write!(f, "hooks"),
+            ReprocessStep::Categorization => write!(f, "categorization"),
+            ReprocessStep::Indexing => write!(f, "indexing"),
+        }
+    }
+}
+
+/// Which stored messages to reprocess
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReprocessSelection {
+    /// Restrict to a tenant (None = all tenants, super admin only)
+    pub tenant_id: Option<Uuid>,
+    /// Explicit message IDs (capped at 1000); takes precedence over the
+    /// date range
+    #[serde(default)]
+    pub message_ids: Vec<Uuid>,
+    /// Restrict to a mailbox
+    pub mailbox_id: Option<Uuid>,
+    /// Received at or after
+    pub from: Option<DateTime<Utc>>,
+    /// Received before
+    pub to: Option<DateTime<Utc>>,
+    /// Maximum number of messages (capped at 1000)
+    pub limit: Option<i64>,
+}
+
+/// Options controlling a reprocessing run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReprocessOptions {
+    /// Stages to run (defaults to all)
+    #[serde(default = "ReprocessStep::all")]
+    pub steps: Vec<ReprocessStep>,
+    /// Evaluate without writing results or calling external hooks
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl Default for ReprocessOptions {
+    fn default() -> Self {
+        Self {
+            steps: ReprocessStep::all(),
+            dry_run: false,
+        }
+    }
+}
+
+impl ReprocessOptions {
+    fn runs(&self, step: ReprocessStep) -> bool {
+        self.steps.contains(&step)
+    }
+}
+
+/// Result of reprocessing a single message
+#[derive(Debug, Clone, Serialize)]
+pub struct ReprocessOutcome {
+    pub message_id: Uuid,
+    pub previous_spam_score: Option<f64>,
+    pub spam_score: Option<f64>,
+    pub tags: Vec<String>,
+    pub matched_policies: Vec<String>,
+    pub quarantine: bool,
+    pub category: Option<String>,
+    pub indexed: bool,
+    pub errors: Vec<String>,
+}
+
+/// Aggregate result of a reprocessing run
+#[derive(Debug, Clone, Serialize)]
+pub struct ReprocessSummary {
+    pub requested_steps: Vec<ReprocessStep>,
+    pub dry_run: bool,
+    pub processed: usize,
+    pub failed: usize,
+    pub outcomes: Vec<ReprocessOutcome>,
+}
+
+/// Re-runs the inbound processing pipeline against stored messages
+pub struct MessageReprocessor<S: FileStorage + ?Sized> {
+    db_pool: DatabasePool,
+    file_storage: Arc<S>,
+    hook_manager: Arc<HookManager>,
+    spam_filter: SpamFilter,
+    policy_engine: PolicyEngine,
+    indexer: Option<MessageIndexer>,
+}
+
+impl<S: FileStorage + ?Sized + Send + Sync + 'static> MessageReprocessor<S> {
+    /// Create a new reprocessor using rule-based spam filtering
+    pub fn new(
+        db_pool: DatabasePool,
+        file_storage: Arc<S>,
+        hook_manager: Arc<HookManager>,
+    ) -> Self {
+        Self {
+            policy_engine: PolicyEngine::new(db_pool.clone()),
+            db_pool,
+            file_storage,
+            hook_manager,
+            spam_filter: SpamFilter::rules_only(),
+            indexer: None,
+        }
+    }
+
+    /// Use a specific spam filter (e.g. one backed by rspamd)
+    pub fn with_spam_filter(mut self, spam_filter: SpamFilter) -> Self {
+        self.spam_filter = spam_filter;
+        self
+    }
+
+    /// Enable re-indexing through Meilisearch
+    pub fn with_indexer(mut self, indexer: MessageIndexer) -> Self {
+        self.indexer = Some(indexer);
+        self
+    }
+
+    /// Load the messages matching a selection
+    pub async fn select_messages(&self, selection: &ReprocessSelection) -> Result<Vec<Message>> {
+        let pool = self.db_pool.pool();
+
+        if !selection.message_ids.is_empty() {
+            let mut message_ids = selection.message_ids.as_slice();
+            if message_ids.len() > MAX_BATCH_SIZE as usize {
+                warn!(
+                    "Reprocessing the first {} of {} requested messages",
+                    MAX_BATCH_SIZE,
+                    message_ids.len()
+                );
+                message_ids = &message_ids[..MAX_BATCH_SIZE as usize];
+            }
+            let messages: Vec<Message> = sqlx::query_as(
+                r#"
+                SELECT * FROM messages
+                WHERE id = ANY($1)
+                  AND ($2::uuid IS NULL OR tenant_id = $2)
+                ORDER BY received_at ASC
+                "#,
+            )
+            .bind(message_ids)
+            .bind(selection.tenant_id)
+            .fetch_all(pool)
+            .await?;
+            return Ok(messages);
+        }
+
+        let limit = selection
+            .limit
+            .unwrap_or(MAX_BATCH_SIZE)
+            .clamp(1, MAX_BATCH_SIZE);
+
+        let messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT * FROM messages
+            WHERE ($1::uuid IS NULL OR tenant_id = $1)
+              AND ($2::uuid IS NULL OR mailbox_id = $2)
+              AND ($3::timestamptz IS NULL OR received_at >= $3)
+              AND ($4::timestamptz IS NULL OR received_at < $4)
+            ORDER BY received_at ASC
+            LIMIT $5
+            "#,
+        )
+        .bind(selection.tenant_id)
+        .bind(selection.mailbox_id)
+        .bind(selection.from)
+        .bind(selection.to)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// Reprocess every message matching a selection
+    pub async fn reprocess(
+        &self,
+        selection: &ReprocessSelection,
+        options: &ReprocessOptions,
+    ) -> Result<ReprocessSummary> {
+        let messages = self.select_messages(selection).await?;
+
+        info!(
+            "Reprocessing {} messages (steps: {:?}, dry_run: {})",
+            messages.len(),
+            options.steps,
+            options.dry_run
+        );
+
+        let mut outcomes = Vec::with_capacity(messages.len());
+        for message in &messages {
+            outcomes.push(self.reprocess_message(message, options).await);
+        }
+
+        let failed = outcomes.iter().filter(|o| !o.errors.is_empty()).count();
+
+        Ok(ReprocessSummary {
+            requested_steps: options.steps.clone(),
+            dry_run: options.dry_run,
+            processed: outcomes.len(),
+            failed,
+            outcomes,
+        })
+    }
+
+    /// Reprocess a single stored message
+    ///
+    /// The tags of the stages that run are rebuilt from scratch, so a
+    /// message no longer matching a policy loses the policy's tags. Tags set
+    /// by users and by stages that do not run are kept.
+    pub async fn reprocess_message(
+        &self,
+        message: &Message,
+        options: &ReprocessOptions,
+    ) -> ReprocessOutcome {
+        let (tags, mut step_tags) = retained_tags(message, options);
+        let mut outcome = ReprocessOutcome {
+            message_id: message.id,
+            previous_spam_score: message.spam_score,
+            spam_score: message.spam_score,
+            tags,
+            matched_policies: Vec::new(),
+            quarantine: false,
+            category: None,
+            indexed: false,
+            errors: Vec::new(),
+        };
+
+        let recipients: Vec<String> =
+            serde_json::from_value(message.to_addresses.clone()).unwrap_or_default();
+
+        // The raw message is only needed by spam filtering, hooks and indexing
+        let indexing =
+            options.runs(ReprocessStep::Indexing) && !options.dry_run && self.indexer.is_some();
+        let raw = if options.runs(ReprocessStep::Spam)
+            || options.runs(ReprocessStep::Hooks)
+            || indexing
+        {
+            match self.file_storage.read(&message.storage_path).await {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    warn!("Failed to read message {} from storage: {}", message.id, e);
+                    outcome
+                        .errors
+                        .push(format!("failed to read stored message: {}", e));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        if options.runs(ReprocessStep::Spam) {
+            if let Some(ref raw) = raw {
+                let rcpt: Vec<&str> = recipients.iter().map(|s| s.as_str()).collect();
+                let result = self
+                    .spam_filter
+                    .check(raw, message.from_address.as_deref(), &rcpt, None, None)
+                    .await;
+                outcome.spam_score = Some(result.score);
+                if result.is_spam {
+                    add_step_tag(
+                        &mut outcome.tags,
+                        &mut step_tags,
+                        ReprocessStep::Spam,
+                        "spam",
+                    );
+                }
+            }
+        }
+
+        let mut quarantined_by = None;
+        if options.runs(ReprocessStep::Policies) {
+            let context = PolicyContext::for_inbound(
+                message.tenant_id,
+                None,
+                message.from_address.clone(),
+                recipients.clone(),
+            )
+            .with_subject(message.subject.clone())
+            .with_headers(message.headers.clone())
+            .with_message_size(message.body_size)
+            .with_spam_score(outcome.spam_score)
+            .with_sender_reputation(
+                message
+                    .metadata
+                    .get("sender_reputation")
+                    .and_then(|v| v.as_f64()),
+            )
+            .with_country(
+                message
+                    .metadata
+                    .get("country")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string),
+            )
+            .with_asn(
+                message
+                    .metadata
+                    .get("asn")
+                    .and_then(|v| v.as_u64())
+                    .and_then(|asn| u32::try_from(asn).ok()),
+            )
+            .with_stored_authentication(&message.metadata);
+
+            match self.policy_engine.evaluate(&context).await {
+                Ok(result) => {
+                    outcome.matched_policies =
+                        result.matches.iter().map(|m| m.policy_name.clone()).collect();
+                    outcome.quarantine = result.quarantine;
+                    quarantined_by = quarantining_policy(&result).map(str::to_string);
+                    for tag in &result.tags {
+                        add_step_tag(
+                            &mut outcome.tags,
+                            &mut step_tags,
+                            ReprocessStep::Policies,
+                            tag,
+                        );
+                    }
+                }
+                Err(e) => outcome
+                    .errors
+                    .push(format!("policy evaluation failed: {}", e)),
+            }
+        }
+
+        // Hooks call out to external plugins, so they never run on a dry run
+        if options.runs(ReprocessStep::Hooks) && !options.dry_run {
+            let raw_data = raw.as_deref().unwrap_or_default();
+            match self
+                .hook_manager
+                .execute_post_receive(message.tenant_id, message, raw_data)
+                .await
+            {
+                Ok(results) => {
+                    for tag in self.hook_manager.collect_tags(&results) {
+                        add_step_tag(
+                            &mut outcome.tags,
+                            &mut step_tags,
+                            ReprocessStep::Hooks,
+                            &tag,
+                        );
+                    }
+                    if let Some(score) = self.hook_manager.aggregate_spam_score(&results) {
+                        outcome.spam_score =
+                            Some(outcome.spam_score.map_or(score, |s| s.max(score)));
+                    }
+                }
+                Err(e) => outcome.errors.push(format!("hook execution failed: {}", e)),
+            }
+        }
+
+        if options.runs(ReprocessStep::Categorization) {
+            if let Err(e) = self.categorize(message, &mut outcome, options.dry_run).await {
+                outcome.errors.push(format!("categorization failed: {}", e));
+            }
+        }
+
+        if !options.dry_run {
+            if let Err(e) = self.persist(message, &outcome, &step_tags, options).await {
+                outcome
+                    .errors
+                    .push(format!("failed to save results: {}", e));
+            }
+            if let Some(policy) = quarantined_by.filter(|_| outcome.quarantine) {
+                if let Err(e) = self.quarantine(message, &policy).await {
+                    outcome.errors.push(format!("quarantine failed: {}", e));
+                }
+            }
+        }
+
+        if options.runs(ReprocessStep::Indexing) && !options.dry_run {
+            if let Some(ref indexer) = self.indexer {
+                let document =
+                    build_document(message, &outcome).with_body(raw.as_deref().and_then(body_text));
+                match indexer.index_message(document).await {
+                    Ok(_) => outcome.indexed = true,
+                    Err(e) => outcome.errors.push(format!("indexing failed: {}", e)),
+                }
+            } else {
+                debug!("Search indexer not configured, skipping indexing");
+            }
+        }
+
+        outcome
+    }
+
+    /// Run the rule-based categorizer and store the assigned category
+    async fn categorize(
+        &self,
+        message: &Message,
+        outcome: &mut ReprocessOutcome,
+        dry_run: bool,
+    ) -> Result<()> {
+        let category_repo = CategoryRepository::new(self.db_pool.clone());
+        let category_ids: HashMap<String, Uuid> = category_repo
+            .list(message.tenant_id)
+            .await?
+            .into_iter()
+            .map(|c| (c.name, c.id))
+            .collect();
+
+        let headers: HashMap<String, String> = message
+            .headers
+            .as_object()
+            .map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let input = CategorizationInput {
+            message_id: message.id,
+            from_address: message.from_address.clone(),
+            to_addresses: serde_json::from_value(message.to_addresses.clone())
+                .unwrap_or_default(),
+            subject: message.subject.clone(),
+            body_preview: message.body_preview.clone(),
+            headers,
+            spam_score: outcome.spam_score,
+            tags: outcome.tags.clone(),
+        };
+
+        let categorizer = RuleBasedCategorizer::new().with_category_ids(category_ids.clone());
+        let result = categorizer
+            .categorize(&PluginContext::new(message.tenant_id), &input)
+            .await?;
+
+        outcome.category = Some(result.category_name.clone());
+
+        // Only assign categories that exist for this tenant
+        if !dry_run && category_ids.get(&result.category_name) == Some(&result.category_id) {
+            category_repo
+                .assign_to_message(
+                    message.id,
+                    result.category_id,
+                    result.confidence,
+                    result.summary.as_deref(),
+                    result.metadata,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Move a message a policy quarantines into the quarantine folder of
+    /// its mailbox, as inbound delivery does
+    async fn quarantine(&self, message: &Message, policy: &str) -> Result<()> {
+        let Some(mailbox) = MailboxRepository::new(self.db_pool.clone())
+            .get(message.tenant_id, message.mailbox_id)
+            .await?
+        else {
+            return Ok(());
+        };
+        // Already held
+        if mailbox.special_use.as_deref() == Some(QUARANTINE_SPECIAL_USE) {
+            return Ok(());
+        }
+
+        let quarantine_repo = QuarantineRepository::new(self.db_pool.clone());
+        let folder_id = quarantine_repo.folder(&mailbox).await?;
+        let metadata = quarantine_metadata(QuarantineReason::Policy, policy, mailbox.id);
+        if quarantine_repo
+            .hold(message.tenant_id, message.id, folder_id, metadata)
+            .await?
+        {
+            info!(
+                "Message {} quarantined on reprocessing (policy: {})",
+                message.id, policy
+            );
+        }
+        Ok(())
+    }
+
+    /// Write spam score, tags and reprocessing metadata back to the message
+    async fn persist(
+        &self,
+        message: &Message,
+        outcome: &ReprocessOutcome,
+        step_tags: &StepTags,
+        options: &ReprocessOptions,
+    ) -> Result<()> {
+        let metadata = serde_json::json!({
+            "reprocessed_at": Utc::now().to_rfc3339(),
+            "reprocess_steps": options.steps,
+            "reprocess_policies": outcome.matched_policies,
+            STEP_TAGS_KEY: step_tags,
+        });
+
+        sqlx::query(
+            r#"
+            UPDATE messages
+            SET spam_score = $2, tags = $3, metadata = metadata || $4
+            WHERE id = $1
+            "#,
+        )
+        .bind(message.id)
+        .bind(outcome.spam_score)
+        .bind(serde_json::json!(outcome.tags))
+        .bind(metadata)
+        .execute(self.db_pool.pool())
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Add a tag if it is not already present
+fn add_tag(tags: &mut Vec<String>, tag: &str) {
+    if !tags.iter().any(|t| t == tag) {
+        tags.push(tag.to_string());
+    }
+}
+
+/// Add a tag on behalf of a stage, remembering that the stage added it
+fn add_step_tag(tags: &mut Vec<String>, step_tags: &mut StepTags, step: ReprocessStep, tag: &str) {
+    add_tag(tags, tag);
+    add_tag(step_tags.entry(step).or_default(), tag);
+}
+
+/// Tags of a message before the stages that run add theirs, and the tags
+/// each stage added on the last run, without those of the running stages
+///
+/// Inbound delivery tags spam as `spam`, so that tag belongs to the spam
+/// stage until the message has been reprocessed.
+fn retained_tags(message: &Message, options: &ReprocessOptions) -> (Vec<String>, StepTags) {
+    let mut tags = message.tags_vec();
+    let mut step_tags: StepTags = message
+        .metadata
+        .get(STEP_TAGS_KEY)
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+    if !step_tags.contains_key(&ReprocessStep::Spam) && tags.iter().any(|t| t == "spam") {
+        step_tags.insert(ReprocessStep::Spam, vec!["spam".to_string()]);
+    }
+
+    let (rebuilt, kept): (StepTags, StepTags) = step_tags
+        .into_iter()
+        .partition(|(step, _)| options.runs(*step));
+    tags.retain(|tag| {
+        !rebuilt.values().flatten().any(|t| t == tag) || kept.values().flatten().any(|t| t == tag)
+    });
+    (tags, kept)
+}
+
+/// Build a search document reflecting the reprocessed state
+fn build_document(message: &Message, outcome: &ReprocessOutcome) -> MessageDocument {
+    MessageDocument::new(
+        message.id,
+        message.tenant_id,
+        message.mailbox_id,
+        message.message_id_header.clone(),
+        message.subject.clone(),
+        message.from_address.clone(),
+        serde_json::from_value(message.to_addresses.clone()).unwrap_or_default(),
+        message
+            .cc_addresses
+            .clone()
+            .and_then(|v| serde_json::from_value(v).ok()),
+        message.body_preview.clone(),
+        message.has_attachments,
+        message.seen,
+        message.flagged,
+        outcome.tags.clone(),
+        outcome.spam_score,
+        message.received_at,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_options_run_all_steps() {
+        let options = ReprocessOptions::default();
+        assert!(!options.dry_run);
+        for step in ReprocessStep::all() {
+            assert!(options.runs(step));
+        }
+    }
+
+    #[test]
+    fn test_options_deserialize_subset() {
+        let options: ReprocessOptions =
+            serde_json::from_str(r#"{"steps": ["spam", "indexing"], "dry_run": true}"#).unwrap();
+        assert!(options.dry_run);
+        assert!(options.runs(ReprocessStep::Spam));
+        assert!(options.runs(ReprocessStep::Indexing));
+        assert!(!options.runs(ReprocessStep::Hooks));
+    }
+
+    fn stored_message(tags: serde_json::Value, metadata: serde_json::Value) -> Message {
+        Message {
+            id: Uuid::new_v4(),
+            tenant_id: Uuid::new_v4(),
+            mailbox_id: Uuid::new_v4(),
+            message_id_header: None,
+            subject: None,
+            from_address: None,
+            to_addresses: serde_json::json!([]),
+            cc_addresses: None,
+            headers: serde_json::json!({}),
+            body_preview: None,
+            body_size: 0,
+            has_attachments: false,
+            storage_path: String::new(),
+            seen: false,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: None,
+            tags,
+            metadata,
+            received_at: Utc::now(),
+            created_at: Utc::now(),
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: None,
+            snippet: None,
+        }
+    }
+
+    #[test]
+    fn test_retained_tags() {
+        let message = stored_message(
+            serde_json::json!(["spam", "urgent", "billing", "work"]),
+            serde_json::json!({
+                STEP_TAGS_KEY: { "policies": ["urgent", "billing"], "hooks": ["billing"] }
+            }),
+        );
+
+        // Every stage runs: only the user's tag is left
+        let (tags, step_tags) = retained_tags(&message, &ReprocessOptions::default());
+        assert_eq!(tags, vec!["work"]);
+        assert!(step_tags.is_empty());
+
+        // A tag a stage that does not run added as well is kept
+        let options = ReprocessOptions {
+            steps: vec![ReprocessStep::Policies],
+            dry_run: false,
+        };
+        let (tags, step_tags) = retained_tags(&message, &options);
+        assert_eq!(tags, vec!["spam", "billing", "work"]);
+        assert_eq!(step_tags[&ReprocessStep::Spam], vec!["spam"]);
+        assert_eq!(step_tags[&ReprocessStep::Hooks], vec!["billing"]);
+        assert!(!step_tags.contains_key(&ReprocessStep::Policies));
+
+        // Without stage tags, inbound spam tagging belongs to the spam stage
+        let message = stored_message(serde_json::json!(["spam", "work"]), serde_json::json!({}));
+        let options = ReprocessOptions {
+            steps: vec![ReprocessStep::Spam],
+            dry_run: false,
+        };
+        let (tags, _) = retained_tags(&message, &options);
+        assert_eq!(tags, vec!["work"]);
+    }
+
+    #[test]
+    fn test_add_step_tag() {
+        let mut tags = vec!["work".to_string()];
+        let mut step_tags = StepTags::new();
+        add_step_tag(&mut tags, &mut step_tags, ReprocessStep::Policies, "urgent");
+        add_step_tag(&mut tags, &mut step_tags, ReprocessStep::Hooks, "urgent");
+        assert_eq!(tags, vec!["work", "urgent"]);
+        assert_eq!(step_tags[&ReprocessStep::Policies], vec!["urgent"]);
+        assert_eq!(step_tags[&ReprocessStep::Hooks], vec!["urgent"]);
+
+        // Stage tags are stored by stage name
+        let stored = serde_json::json!(step_tags);
+        assert_eq!(stored["policies"], serde_json::json!(["urgent"]));
+        let loaded: StepTags = serde_json::from_value(stored).unwrap();
+        assert_eq!(loaded, step_tags);
+    }
+
+    #[test]
+    fn test_add_tag_deduplicates() {
+        let mut tags = vec!["spam".to_string()];
+        add_tag(&mut tags, "spam");
+        add_tag(&mut tags, "newsletter");
+        assert_eq!(tags, vec!["spam", "newsletter"]);
+    }
+}
@@ -0,0 +1,443 @@
+//! HTML sanitizing for message bodies shown outside the mail client
+//!
+//! The sanitizer is allowlist based: only simple formatting and table tags
+//! are kept, and all of their attributes are dropped except for `href` on
+//! links (http, https and mailto only) and `colspan`/`rowspan` on table
+//! cells. Scripts, style sheets, embedded documents and forms are removed
+//! with their contents; other tags are removed but their text is kept.
+//! Images are dropped, so a sanitized body never loads remote content.
+//!
+//! The output is rebuilt from the tags that are kept, so it is always
+//! balanced and never contains markup that was not on the allowlist.
+
+use std::fmt::Write as _;
+
+/// Tags that are kept, without attributes unless noted above
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "code",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+/// Allowed tags without contents or closing tag
+const VOID_TAGS: &[&str] = &["br", "hr"];
+
+/// Tags that are removed together with everything up to their closing tag
+const DROPPED_BLOCKS: &[&str] = &[
+    "applet", "embed", "frameset", "head", "iframe", "math", "noembed", "noframes", "noscript",
+    "object", "script", "select", "style", "svg", "template", "textarea", "title", "xmp",
+];
+
+/// URL schemes links may use
+const ALLOWED_SCHEMES: &[&str] = &["http:", "https:", "mailto:"];
+
+/// Sanitize an HTML body for display
+pub fn sanitize_html(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut rest = html;
+
+    while let Some(lt) = rest.find('<') {
+        output.push_str(&rest[..lt]);
+        rest = &rest[lt..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+        let Some(tag) = Tag::parse(rest) else {
+            output.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.len..];
+
+        if !tag.closing && DROPPED_BLOCKS.contains(&tag.name.as_str()) {
+            rest = skip_block(rest, &tag.name);
+            continue;
+        }
+        let Some(name) = ALLOWED_TAGS.iter().copied().find(|name| *name == tag.name) else {
+            continue;
+        };
+
+        if tag.closing {
+            if let Some(at) = open.iter().rposition(|open| *open == name) {
+                for open in open.drain(at..).rev() {
+                    let _ = write!(output, "</{}>", open);
+                }
+            }
+            continue;
+        }
+
+        output.push('<');
+        output.push_str(name);
+        match name {
+            "a" => {
+                if let Some(href) = tag.attribute("href").and_then(safe_href) {
+                    let _ = write!(
+                        output,
+                        " href=\"{}\" rel=\"noopener noreferrer\" target=\"_blank\"",
+                        escape_attribute(&href)
+                    );
+                }
+            }
+            "td" | "th" => {
+                for attribute in ["colspan", "rowspan"] {
+                    if let Some(span) = tag
+                        .attribute(attribute)
+                        .and_then(|value| value.trim().parse::<u16>().ok())
+                        .filter(|span| (1..=1000).contains(span))
+                    {
+                        let _ = write!(output, " {}=\"{}\"", attribute, span);
+                    }
+                }
+            }
+            _ => {}
+        }
+        output.push('>');
+        if !VOID_TAGS.contains(&name) {
+            open.push(name);
+        }
+    }
+    output.push_str(rest);
+
+    for name in open.into_iter().rev() {
+        let _ = write!(output, "</{}>", name);
+    }
+    output
+}
+
+/// A start or end tag
+struct Tag<'a> {
+    /// Lowercase tag name
+    name: String,
+    closing: bool,
+    attributes: Vec<(&'a str, &'a str)>,
+    /// Length of the tag in the input, including `<` and `>`
+    len: usize,
+}
+
+impl<'a> Tag<'a> {
+    /// Parse the tag at the start of `input`, which starts with `<`
+    ///
+    /// `None` when the `<` does not start a tag. A tag without `>` runs to
+    /// the end of the input.
+    fn parse(input: &'a str) -> Option<Self> {
+        let bytes = input.as_bytes();
+        let mut pos = 1;
+        let closing = bytes.get(pos) == Some(&b'/');
+        if closing {
+            pos += 1;
+        }
+        if !bytes.get(pos).is_some_and(u8::is_ascii_alphabetic) {
+            return None;
+        }
+        let name_start = pos;
+        while pos < bytes.len() && !is_tag_delimiter(bytes[pos]) {
+            pos += 1;
+        }
+        let name = input[name_start..pos].to_ascii_lowercase();
+
+        let mut attributes = Vec::new();
+        loop {
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+                pos += 1;
+            }
+            match bytes.get(pos) {
+                None => break,
+                Some(b'>') => {
+                    pos += 1;
+                    break;
+                }
+                Some(_) => {}
+            }
+
+            // The first character always belongs to the name, even `=`
+            let attribute_start = pos;
+            pos += input[pos..].chars().next().map_or(1, char::len_utf8);
+            while pos < bytes.len() && !is_tag_delimiter(bytes[pos]) && bytes[pos] != b'=' {
+                pos += 1;
+            }
+            let attribute = &input[attribute_start..pos];
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+
+            let mut value = "";
+            if bytes.get(pos) == Some(&b'=') {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                match bytes.get(pos) {
+                    Some(&quote @ (b'"' | b'\'')) => {
+                        let value_start = pos + 1;
+                        let value_end = input[value_start..]
+                            .find(quote as char)
+                            .map_or(input.len(), |end| value_start + end);
+                        value = &input[value_start..value_end];
+                        pos = (value_end + 1).min(input.len());
+                    }
+                    _ => {
+                        let value_start = pos;
+                        while pos < bytes.len()
+                            && !bytes[pos].is_ascii_whitespace()
+                            && bytes[pos] != b'>'
+                        {
+                            pos += 1;
+                        }
+                        value = &input[value_start..pos];
+                    }
+                }
+            }
+            attributes.push((attribute, value));
+        }
+
+        Some(Self {
+            name,
+            closing,
+            attributes,
+            len: pos,
+        })
+    }
+
+    /// Raw value of an attribute, by case-insensitive name
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+}
+
+fn is_tag_delimiter(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || byte == b'/' || byte == b'>'
+}
+
+/// Input after the closing tag of a dropped block; empty when unclosed
+fn skip_block<'a>(input: &'a str, name: &str) -> &'a str {
+    // ASCII lowercasing keeps byte offsets
+    let lower = input.to_ascii_lowercase();
+    let Some(start) = lower.find(&format!("</{}", name)) else {
+        return "";
+    };
+    input[start..]
+        .find('>')
+        .map_or("", |end| &input[start + end + 1..])
+}
+
+/// Link target if it uses an allowed scheme
+///
+/// Character references are decoded and tabs and newlines removed first,
+/// as browsers do, so the checked URL is the one a browser would follow.
+fn safe_href(value: &str) -> Option<String> {
+    let url: String = decode_references(value)
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    let url = url.trim_matches(|c: char| c.is_ascii_control() || c == ' ');
+    let lower = url.to_ascii_lowercase();
+    ALLOWED_SCHEMES
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+        .then(|| url.to_string())
+}
+
+/// Decode numeric and the basic named character references
+///
+/// Other references are left as they are.
+fn decode_references(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match decode_reference(rest) {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Character and length of the reference at the start of `input`
+fn decode_reference(input: &str) -> Option<(char, usize)> {
+    if let Some(number) = input.strip_prefix("&#") {
+        let (digits, radix, prefix) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 3),
+            None => (number, 10, 2),
+        };
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let code = u32::from_str_radix(&digits[..len], radix).ok()?;
+        let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+        let semicolon = usize::from(digits[len..].starts_with(';'));
+        return Some((c, prefix + len + semicolon));
+    }
+    [
+        ("&amp;", '&'),
+        ("&lt;", '<'),
+        ("&gt;", '>'),
+        ("&quot;", '"'),
+        ("&apos;", '\''),
+        ("&colon;", ':'),
+        ("&tab;", '\t'),
+        ("&newline;", '\n'),
+    ]
+    .iter()
+    .find(|(name, _)| {
+        input
+            .get(..name.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+    })
+    .map(|(name, c)| (*c, name.len()))
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_formatting() {
+        assert_eq!(
+            sanitize_html("<p>Hello <b>world</b> &amp; you</p>"),
+            "<p>Hello <b>world</b> &amp; you</p>"
+        );
+        assert_eq!(
+            sanitize_html("<P CLASS=\"x\" style='color:red'>Hi<BR/>there</P>"),
+            "<p>Hi<br>there</p>"
+        );
+        assert_eq!(
+            sanitize_html("<table><tr><td colspan=\"2\" onclick=\"x()\">a</td></tr></table>"),
+            "<table><tr><td colspan=\"2\">a</td></tr></table>"
+        );
+    }
+
+    #[test]
+    fn test_removes_active_content() {
+        assert_eq!(sanitize_html("a<script>alert(1)</script>b"), "ab");
+        assert_eq!(sanitize_html("a<SCRIPT src=x>alert(1)</Script >b"), "ab");
+        assert_eq!(sanitize_html("a<style>p{}</style>b<!-- <b> -->c"), "abc");
+        assert_eq!(
+            sanitize_html("<html><head><title>t</title></head><body>x</body></html>"),
+            "x"
+        );
+        assert_eq!(
+            sanitize_html("<img src=\"http://tracker/x.gif\" onerror=\"x()\">"),
+            ""
+        );
+        assert_eq!(
+            sanitize_html("<div onmouseover=\"x()\">a</div>"),
+            "<div>a</div>"
+        );
+        assert_eq!(sanitize_html("a<script>never closed"), "a");
+        assert_eq!(sanitize_html("<scr<script>x</script>ipt>"), "xipt>");
+        assert_eq!(
+            sanitize_html("<iframe src=\"x\"></iframe><svg><script>x</script></svg>"),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(
+            sanitize_html("<a href=\"https://example.com/?a=1&amp;b=2\">x</a>"),
+            "<a href=\"https://example.com/?a=1&amp;b=2\" rel=\"noopener noreferrer\" target=\"_blank\">x</a>"
+        );
+        assert_eq!(
+            sanitize_html("<a href=\"javascript:alert(1)\">x</a>"),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize_html("<a href=\"java&#x09;script&colon;alert(1)\">x</a>"),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize_html("<a href=\" &#106;avascript:alert(1)\">x</a>"),
+            "<a>x</a>"
+        );
+        assert_eq!(sanitize_html("<a href=/relative>x</a>"), "<a>x</a>");
+        assert_eq!(
+            sanitize_html("<a href=mailto:a@example.com\">x</a>"),
+            "<a href=\"mailto:a@example.com&quot;\" rel=\"noopener noreferrer\" target=\"_blank\">x</a>"
+        );
+    }
+
+    #[test]
+    fn test_balances_tags() {
+        assert_eq!(sanitize_html("<b><i>x</b>y"), "<b><i>x</i></b>y");
+        assert_eq!(sanitize_html("x</div></p>"), "x");
+        assert_eq!(
+            sanitize_html("<ul><li>one<li>two"),
+            "<ul><li>one<li>two</li></li></ul>"
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(sanitize_html("1 < 2 > 0"), "1 &lt; 2 > 0");
+        assert_eq!(sanitize_html("a </ b"), "a &lt;/ b");
+        assert_eq!(
+            sanitize_html("日本語 <b title=\"日本\">テキスト</b>"),
+            "日本語 <b>テキスト</b>"
+        );
+        assert_eq!(sanitize_html("<unknown attr='<b>'>text</unknown>"), "text");
+    }
+}
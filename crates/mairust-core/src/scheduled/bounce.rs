@@ -0,0 +1,331 @@
+//! Bounce Processing - VERP return paths and inbound bounce handling
+//!
+//! Every scheduled message is sent with a per-recipient return path of the
+//! form `bounce+<message id>-<tag>@<sender domain>`. A bounce addressed to
+//! such a path names the scheduled message it belongs to, so it can be
+//! attributed without trusting the recipient listed in the DSN body.
+//!
+//! The tag is a truncated HMAC-SHA256 of the message ID, so that someone
+//! who learns a message ID cannot make up return paths for it.
+
+use hmac::{Hmac, Mac};
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::RecipientStatus;
+use mairust_storage::repository::{RecipientRepository, ScheduledMessageRepository};
+use sha2::Sha256;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Local-part prefix of VERP return paths
+pub const VERP_PREFIX: &str = "bounce+";
+
+/// Hex characters of the return path tag
+const TAG_LENGTH: usize = 16;
+
+/// Bounce processing errors
+#[derive(Error, Debug)]
+pub enum BounceError {
+    #[error("Scheduled message not found")]
+    NotFound,
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Build the VERP return path for a scheduled message, tagged with `secret`
+pub fn verp_address(message_id: Uuid, domain: &str, secret: &str) -> String {
+    format!(
+        "{}{}-{}@{}",
+        VERP_PREFIX,
+        message_id.simple(),
+        verp_tag(secret, message_id),
+        domain
+    )
+}
+
+/// Extract the scheduled message ID from a VERP local part whose tag was
+/// made with one of `secrets`
+///
+/// Intermediate servers may change the case of local parts, so the tag is
+/// compared case-insensitively.
+pub fn parse_verp(local: &str, secrets: &[String]) -> Option<Uuid> {
+    let prefix = local.get(..VERP_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(VERP_PREFIX) {
+        return None;
+    }
+    let (id, tag) = local[VERP_PREFIX.len()..].split_once('-')?;
+    let message_id = Uuid::try_parse(id).ok()?;
+    secrets
+        .iter()
+        .any(|secret| verp_tag(secret, message_id).eq_ignore_ascii_case(tag))
+        .then_some(message_id)
+}
+
+/// Truncated HMAC of a scheduled message ID
+fn verp_tag(secret: &str, message_id: Uuid) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(message_id.simple().to_string().as_bytes());
+    let mut tag = hex::encode(mac.finalize().into_bytes());
+    tag.truncate(TAG_LENGTH);
+    tag
+}
+
+/// Severity of a bounce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BounceType {
+    /// Permanent failure (5.x.x); the recipient should not be mailed again
+    Hard,
+    /// Transient failure (4.x.x) that the reporting MTA gave up on
+    Soft,
+}
+
+impl BounceType {
+    /// Value stored in `scheduled_messages.bounce_type`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BounceType::Hard => "hard",
+            BounceType::Soft => "soft",
+        }
+    }
+}
+
+/// Failure reported by a delivery status notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BounceReport {
+    pub bounce_type: BounceType,
+    /// Enhanced status code (e.g. `5.1.1`), if given
+    pub status: Option<String>,
+    /// Diagnostic-Code text, if given
+    pub diagnostic: Option<String>,
+}
+
+impl BounceReport {
+    /// Human-readable reason stored with the bounce
+    pub fn reason(&self) -> String {
+        match (&self.status, &self.diagnostic) {
+            (Some(status), Some(diagnostic)) => format!("{} {}", status, diagnostic),
+            (Some(status), None) => status.clone(),
+            (None, Some(diagnostic)) => diagnostic.clone(),
+            (None, None) => "Delivery failed".to_string(),
+        }
+    }
+}
+
+/// Read the per-recipient fields of a DSN (RFC 3464)
+///
+/// Returns `None` for delay and success notifications, and for messages
+/// without delivery status fields (e.g. auto-replies sent to the return path).
+pub fn parse_dsn(data: &[u8]) -> Option<BounceReport> {
+    let text = String::from_utf8_lossy(data);
+    // Skip the human-readable part when the report is properly structured
+    let fields = match text.to_ascii_lowercase().find("message/delivery-status") {
+        Some(pos) => &text[pos..],
+        None => &text[..],
+    };
+
+    let mut action = None;
+    let mut status = None;
+    let mut diagnostic = None;
+    for line in fields.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "action" if action.is_none() => action = Some(value.to_ascii_lowercase()),
+            "status" if status.is_none() => status = Some(value.to_string()),
+            "diagnostic-code" if diagnostic.is_none() => {
+                // Drop the diagnostic type (e.g. "smtp; 550 ...")
+                let text = value.split_once(';').map(|(_, t)| t).unwrap_or(value);
+                diagnostic = Some(text.trim().to_string());
+            }
+            _ => {}
+        }
+    }
+
+    let bounce_type = match (action.as_deref(), status.as_deref()) {
+        (Some("delayed" | "delivered" | "relayed" | "expanded"), _) => return None,
+        (_, Some(status)) if status.starts_with('5') => BounceType::Hard,
+        (_, Some(status)) if status.starts_with('4') => BounceType::Soft,
+        (Some("failed"), _) => BounceType::Hard,
+        _ => return None,
+    };
+
+    Some(BounceReport {
+        bounce_type,
+        status,
+        diagnostic,
+    })
+}
+
+/// Records bounces received on VERP return paths
+pub struct BounceProcessor {
+    scheduled_message_repo: ScheduledMessageRepository,
+    recipient_repo: RecipientRepository,
+}
+
+impl BounceProcessor {
+    /// Create a new bounce processor
+    pub fn new(db_pool: DatabasePool) -> Self {
+        let pool = db_pool.pool().clone();
+        Self {
+            scheduled_message_repo: ScheduledMessageRepository::new(pool.clone()),
+            recipient_repo: RecipientRepository::new(pool),
+        }
+    }
+
+    /// Process a bounce for the scheduled message `message_id`, received at
+    /// a domain of `tenant_id`
+    ///
+    /// Returns the recorded report, or `None` when the message was not a
+    /// failure notification. Hard bounces also mark the campaign recipient
+    /// as bounced so later campaigns skip the address. A message of another
+    /// tenant is not found.
+    pub async fn process(
+        &self,
+        message_id: Uuid,
+        tenant_id: TenantId,
+        data: &[u8],
+    ) -> Result<Option<BounceReport>, BounceError> {
+        let Some(report) = parse_dsn(data) else {
+            debug!(
+                "Ignoring non-failure message sent to the return path of {}",
+                message_id
+            );
+            return Ok(None);
+        };
+
+        let message = self
+            .scheduled_message_repo
+            .mark_bounced(
+                message_id,
+                tenant_id,
+                report.bounce_type.as_str(),
+                &report.reason(),
+            )
+            .await?
+            .ok_or(BounceError::NotFound)?;
+
+        info!(
+            "Scheduled message {} to {} bounced ({}): {}",
+            message.id,
+            message.to_address,
+            report.bounce_type.as_str(),
+            report.reason()
+        );
+
+        if report.bounce_type == BounceType::Hard {
+            if let Some(recipient_id) = message.recipient_id {
+                if self
+                    .recipient_repo
+                    .update_status(recipient_id, RecipientStatus::Bounced)
+                    .await?
+                    .is_none()
+                {
+                    warn!(
+                        "Recipient {} of bounced message no longer exists",
+                        recipient_id
+                    );
+                }
+            }
+        }
+
+        Ok(Some(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verp_round_trip() {
+        let id = Uuid::new_v4();
+        let secrets = vec!["new".to_string(), "old".to_string()];
+        let address = verp_address(id, "example.com", "new");
+        let (local, domain) = address.split_once('@').unwrap();
+        assert_eq!(domain, "example.com");
+        assert_eq!(parse_verp(local, &secrets), Some(id));
+        assert_eq!(parse_verp(&local.to_uppercase(), &secrets), Some(id));
+
+        // Return paths of a rotated key are still accepted
+        let address = verp_address(id, "example.com", "old");
+        let (local, _) = address.split_once('@').unwrap();
+        assert_eq!(parse_verp(local, &secrets), Some(id));
+    }
+
+    #[test]
+    fn test_parse_verp_rejects_forged_tags() {
+        let id = Uuid::new_v4();
+        let secrets = vec!["secret".to_string()];
+        let address = verp_address(id, "example.com", "other");
+        let (local, _) = address.split_once('@').unwrap();
+        assert_eq!(parse_verp(local, &secrets), None);
+        assert_eq!(parse_verp(local, &[]), None);
+
+        // The tag of one message does not work for another
+        let (_, tag) = local.rsplit_once('-').unwrap();
+        let forged = format!("{}{}-{}", VERP_PREFIX, Uuid::new_v4().simple(), tag);
+        assert_eq!(parse_verp(&forged, &secrets), None);
+
+        // Untagged return paths are not accepted
+        assert_eq!(
+            parse_verp(&format!("{}{}", VERP_PREFIX, id.simple()), &secrets),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_verp_rejects_other_addresses() {
+        let secrets = vec!["secret".to_string()];
+        assert_eq!(parse_verp("alice", &secrets), None);
+        assert_eq!(parse_verp("bounce+", &secrets), None);
+        assert_eq!(parse_verp("bounce+not-a-uuid", &secrets), None);
+        assert_eq!(parse_verp("alice+bounce", &secrets), None);
+    }
+
+    #[test]
+    fn test_parse_dsn_hard_bounce() {
+        let dsn =
+            b"Content-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            Status: this human-readable line is not a status field\r\n\
+            --b\r\n\
+            Content-Type: message/delivery-status\r\n\r\n\
+            Reporting-MTA: dns; mx.example.net\r\n\r\n\
+            Final-Recipient: rfc822; bob@example.net\r\n\
+            Action: failed\r\n\
+            Status: 5.1.1\r\n\
+            Diagnostic-Code: smtp; 550 5.1.1 User unknown\r\n\
+            --b--\r\n";
+
+        let report = parse_dsn(dsn).unwrap();
+        assert_eq!(report.bounce_type, BounceType::Hard);
+        assert_eq!(report.status.as_deref(), Some("5.1.1"));
+        assert_eq!(report.diagnostic.as_deref(), Some("550 5.1.1 User unknown"));
+    }
+
+    #[test]
+    fn test_parse_dsn_soft_and_ignored() {
+        let soft = b"Content-Type: message/delivery-status\r\n\r\n\
+            Final-Recipient: rfc822; bob@example.net\r\n\
+            Action: failed\r\n\
+            Status: 4.2.2\r\n\
+            Diagnostic-Code: smtp; 452 4.2.2 Mailbox full\r\n";
+        let report = parse_dsn(soft).unwrap();
+        assert_eq!(report.bounce_type, BounceType::Soft);
+        assert_eq!(report.reason(), "4.2.2 452 4.2.2 Mailbox full");
+
+        let delayed = b"Action: delayed\r\nStatus: 4.4.7\r\n";
+        assert_eq!(parse_dsn(delayed), None);
+
+        let auto_reply = b"Subject: Out of office\r\n\r\nI am away until Monday.";
+        assert_eq!(parse_dsn(auto_reply), None);
+    }
+}
@@ -2,6 +2,7 @@
 
 use super::rate_limiter::RateLimiter;
 use super::template::TemplateRenderer;
+use crate::tracking::{link_base_url, ClickTracker};
 use anyhow::Result;
 use chrono::{Duration, Utc};
 use mairust_common::types::TenantId;
@@ -12,7 +13,7 @@ use mairust_storage::models::{
 };
 use mairust_storage::repository::{
     CampaignMessageCounts, CampaignRepository, RecipientListRepository, RecipientRepository,
-    ScheduledMessageRepository, UnsubscribeRepository,
+    ScheduledMessageRepository, TrackingDomainRepository, UnsubscribeRepository,
 };
 use std::sync::Arc;
 use thiserror::Error;
@@ -44,6 +45,21 @@ pub enum CampaignError {
     Internal(#[from] anyhow::Error),
 }
 
+impl From<CampaignError> for mairust_common::Error {
+    fn from(error: CampaignError) -> Self {
+        use mairust_common::Error;
+        match error {
+            CampaignError::NotFound => Error::NotFound(error.to_string()),
+            CampaignError::NotDraft
+            | CampaignError::NotScheduledOrSending
+            | CampaignError::NoRecipientList
+            | CampaignError::EmptyRecipientList => Error::Validation(error.to_string()),
+            CampaignError::Database(e) => mairust_storage::db::db_error(e),
+            CampaignError::Internal(e) => Error::Other(e),
+        }
+    }
+}
+
 /// Campaign Manager - Manages campaign lifecycle
 pub struct CampaignManager {
     db_pool: DatabasePool,
@@ -54,6 +70,8 @@ pub struct CampaignManager {
     unsubscribe_repo: UnsubscribeRepository,
     rate_limiter: Arc<RateLimiter>,
     template_renderer: TemplateRenderer,
+    click_tracker: Option<ClickTracker>,
+    tracking_default_url: Option<String>,
 }
 
 impl CampaignManager {
@@ -69,9 +87,20 @@ impl CampaignManager {
             unsubscribe_repo: UnsubscribeRepository::new(pool.clone()),
             rate_limiter: Arc::new(RateLimiter::new(db_pool)),
             template_renderer: TemplateRenderer::new(unsubscribe_base_url),
+            click_tracker: None,
+            tracking_default_url: None,
         }
     }
 
+    /// Rewrite the links of campaign mail to tracked links, on the tenant's
+    /// tracking domain aligned with the From address or else on
+    /// `default_url`
+    pub fn with_click_tracking(mut self, tracker: ClickTracker, default_url: Option<String>) -> Self {
+        self.click_tracker = Some(tracker);
+        self.tracking_default_url = default_url;
+        self
+    }
+
     /// Get the rate limiter
     pub fn rate_limiter(&self) -> Arc<RateLimiter> {
         Arc::clone(&self.rate_limiter)
@@ -341,6 +370,21 @@ impl CampaignManager {
         let mut current_time = start_time;
         let mut minute_count = 0usize;
 
+        // Base URL of tracked links; links are left as they are without one
+        let link_base = match self.click_tracker {
+            Some(_) => {
+                let domains = TrackingDomainRepository::new(self.db_pool.clone())
+                    .list_active(campaign.tenant_id)
+                    .await?;
+                let from_domain = campaign
+                    .from_address
+                    .rsplit_once('@')
+                    .map_or("", |(_, domain)| domain);
+                link_base_url(&domains, from_domain, self.tracking_default_url.as_deref())
+            }
+            None => None,
+        };
+
         loop {
             // Fetch batch of active recipients
             let recipients = self
@@ -376,7 +420,17 @@ impl CampaignManager {
                 );
 
                 let html_body = campaign.html_body.as_ref().map(|body| {
-                    self.template_renderer.render(body, &recipient, Some(campaign.id))
+                    let html = self.template_renderer.render(body, &recipient, Some(campaign.id));
+                    match (&self.click_tracker, &link_base) {
+                        (Some(tracker), Some(base_url)) => tracker.rewrite_html(
+                            &html,
+                            base_url,
+                            campaign.id,
+                            recipient.id,
+                            &[self.template_renderer.unsubscribe_base_url()],
+                        ),
+                        _ => html,
+                    }
                 });
 
                 let text_body = campaign.text_body.as_ref().map(|body| {
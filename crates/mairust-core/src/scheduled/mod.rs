@@ -1,10 +1,12 @@
 //! Scheduled Email Module - Campaign management and scheduled delivery
 
+mod bounce;
 mod manager;
 mod scheduler;
 mod rate_limiter;
 mod template;
 
+pub use bounce::{parse_dsn, parse_verp, verp_address, BounceError, BounceProcessor, BounceReport, BounceType};
 pub use manager::{CampaignManager, CampaignError};
 pub use scheduler::{ScheduledDeliveryWorker, DeliveryResult, SmtpConfig};
 pub use rate_limiter::{RateLimiter, RemainingQuota};
@@ -5,6 +5,7 @@ use chrono::{Duration, Timelike, Utc};
 use mairust_common::types::TenantId;
 use mairust_storage::db::DatabasePool;
 use mairust_storage::models::TenantRateLimit;
+use serde::Serialize;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{debug, warn};
@@ -105,7 +106,7 @@ impl RateLimiter {
     }
 
     /// Get rate limits for a tenant (with caching)
-    async fn get_rate_limits(&self, tenant_id: TenantId) -> Result<TenantRateLimit> {
+    pub async fn get_rate_limits(&self, tenant_id: TenantId) -> Result<TenantRateLimit> {
         // Check cache first
         {
             let cache = self.cache.read().await;
@@ -253,7 +254,7 @@ impl RateLimiter {
 }
 
 /// Remaining quota for a tenant
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RemainingQuota {
     pub per_minute: i32,
     pub per_hour: i32,
@@ -1,13 +1,15 @@
 //! Scheduled Delivery Worker - Processes and sends scheduled messages
 
+use super::bounce::verp_address;
 use super::manager::CampaignManager;
 use super::rate_limiter::RateLimiter;
 use anyhow::Result;
 use chrono::{Duration, Utc};
 use lettre::{
+    address::Envelope,
     message::{header::ContentType, Mailbox, MultiPart, SinglePart},
-    transport::smtp::authentication::Credentials,
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    transport::smtp::{authentication::Credentials, extension::ClientId},
+    Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
 use mairust_storage::db::DatabasePool;
 use mairust_storage::models::{ScheduledMessage, ScheduledMessageStatus};
@@ -41,6 +43,14 @@ pub struct SmtpConfig {
     pub password: Option<String>,
     pub use_tls: bool,
     pub use_starttls: bool,
+    /// Hostname announced in EHLO (see `SmtpConfig::outbound_helo`)
+    pub helo_name: Option<String>,
+    /// Send with a per-message VERP return path so bounces reach the
+    /// bounce processor
+    pub verp: bool,
+    /// Key of the VERP return path tags (the first of `smtp.verp.secrets`);
+    /// without one, mail is sent without VERP
+    pub verp_secret: Option<String>,
 }
 
 impl Default for SmtpConfig {
@@ -52,6 +62,9 @@ impl Default for SmtpConfig {
             password: None,
             use_tls: false,
             use_starttls: true,
+            helo_name: None,
+            verp: true,
+            verp_secret: None,
         }
     }
 }
@@ -229,11 +242,39 @@ impl ScheduledDeliveryWorker {
             }
         };
 
+        // Per-message return path, so a bounce names the message it belongs to
+        let verp_secret = smtp_config
+            .verp_secret
+            .as_deref()
+            .filter(|_| smtp_config.verp);
+        let envelope = if let Some(secret) = verp_secret {
+            let envelope = verp_address(message.id, from.email.domain(), secret)
+                .parse::<Address>()
+                .map_err(|e| e.to_string())
+                .and_then(|return_path| {
+                    Envelope::new(Some(return_path), vec![to.email.clone()])
+                        .map_err(|e| e.to_string())
+                });
+            match envelope {
+                Ok(e) => Some(e),
+                Err(e) => {
+                    return DeliveryResult::PermanentFailure {
+                        error: format!("Invalid return path: {}", e),
+                    };
+                }
+            }
+        } else {
+            None
+        };
+
         // Build message
         let mut email_builder = Message::builder()
             .from(from)
             .to(to)
             .subject(&message.subject);
+        if let Some(envelope) = envelope {
+            email_builder = email_builder.envelope(envelope);
+        }
 
         // Add custom headers
         if let Some(headers) = message.headers.as_object() {
@@ -295,6 +336,10 @@ impl ScheduledDeliveryWorker {
             }
         };
 
+        if let Some(helo) = &smtp_config.helo_name {
+            transport = transport.hello_name(ClientId::Domain(helo.clone()));
+        }
+
         // Add credentials if configured
         if let (Some(username), Some(password)) = (&smtp_config.username, &smtp_config.password) {
             transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
@@ -342,6 +387,10 @@ impl ScheduledDeliveryWorker {
         let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp_config.host)
             .port(smtp_config.port);
 
+        if let Some(helo) = &smtp_config.helo_name {
+            transport = transport.hello_name(ClientId::Domain(helo.clone()));
+        }
+
         if let (Some(username), Some(password)) = (&smtp_config.username, &smtp_config.password) {
             transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
         }
@@ -423,7 +472,10 @@ impl ScheduledDeliveryWorker {
                     message.id, bounce_type, reason
                 );
 
-                if let Err(e) = repo.mark_bounced(message.id, &bounce_type, &reason).await {
+                if let Err(e) = repo
+                    .mark_bounced(message.id, message.tenant_id, &bounce_type, &reason)
+                    .await
+                {
                     error!("Failed to mark message {} as bounced: {}", message.id, e);
                 }
             }
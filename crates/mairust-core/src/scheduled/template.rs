@@ -17,6 +17,11 @@ impl TemplateRenderer {
         Self { unsubscribe_base_url }
     }
 
+    /// Base URL of unsubscribe links
+    pub fn unsubscribe_base_url(&self) -> &str {
+        &self.unsubscribe_base_url
+    }
+
     /// Render a template with recipient data
     pub fn render(&self, template: &str, recipient: &Recipient, campaign_id: Option<uuid::Uuid>) -> String {
         let mut result = template.to_string();
@@ -60,6 +60,10 @@ pub struct SearchRequest {
     pub attributes_to_highlight: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sort: Option<Vec<String>>,
+    /// Restrict matching to these attributes (all searchable attributes if unset)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "attributesToSearchOn")]
+    pub attributes_to_search_on: Option<Vec<String>>,
 }
 
 impl Default for SearchRequest {
@@ -72,6 +76,7 @@ impl Default for SearchRequest {
             attributes_to_retrieve: None,
             attributes_to_highlight: Some(vec!["subject".to_string(), "body_preview".to_string()]),
             sort: Some(vec!["received_at:desc".to_string()]),
+            attributes_to_search_on: None,
         }
     }
 }
@@ -99,14 +104,26 @@ pub struct IndexSettings {
     pub sortable_attributes: Vec<String>,
     #[serde(rename = "displayedAttributes")]
     pub displayed_attributes: Vec<String>,
+    pub pagination: PaginationSettings,
 }
 
+/// Limit on the number of hits a search can return
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaginationSettings {
+    #[serde(rename = "maxTotalHits")]
+    pub max_total_hits: u64,
+}
+
+/// Hits a single search can return; IMAP SEARCH needs every match in a mailbox
+pub const MAX_TOTAL_HITS: u64 = 100_000;
+
 impl Default for IndexSettings {
     fn default() -> Self {
         Self {
             searchable_attributes: vec![
                 "subject".to_string(),
                 "body_preview".to_string(),
+                "body".to_string(),
                 "from_address".to_string(),
                 "to_addresses".to_string(),
             ],
@@ -122,6 +139,9 @@ impl Default for IndexSettings {
             ],
             sortable_attributes: vec!["received_at".to_string(), "subject".to_string()],
             displayed_attributes: vec!["*".to_string()],
+            pagination: PaginationSettings {
+                max_total_hits: MAX_TOTAL_HITS,
+            },
         }
     }
 }
@@ -408,6 +428,7 @@ mod tests {
         assert_eq!(json["q"], "test query");
         assert_eq!(json["limit"], 10);
         assert_eq!(json["filter"], "tenant_id = 'abc'");
+        assert!(json.get("attributesToSearchOn").is_none());
     }
 
     #[test]
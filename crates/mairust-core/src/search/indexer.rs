@@ -33,6 +33,9 @@ pub struct MessageDocument {
     /// Body preview (first 4KB of text)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body_preview: Option<String>,
+    /// Full body text, so BODY/TEXT searches are not limited to the preview
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
     /// Has attachments flag
     pub has_attachments: bool,
     /// Read/seen flag
@@ -79,6 +82,7 @@ impl MessageDocument {
             to_addresses,
             cc_addresses,
             body_preview,
+            body: None,
             has_attachments,
             seen,
             flagged,
@@ -88,6 +92,34 @@ impl MessageDocument {
             received_at_iso: received_at.to_rfc3339(),
         }
     }
+
+    /// Set the full body text (see [`body_text`])
+    pub fn with_body(mut self, body: Option<String>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+/// Body text indexed per message; longer bodies are truncated
+pub const MAX_INDEXED_BODY_CHARS: usize = 100_000;
+
+/// Text of all text parts of a raw message, for the `body` field
+pub fn body_text(raw: &[u8]) -> Option<String> {
+    let parsed = mail_parser::MessageParser::default().parse(raw)?;
+    let text = (0..parsed.text_body_count())
+        .filter_map(|i| parsed.body_text(i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.trim().is_empty() {
+        return None;
+    }
+    Some(text.chars().take(MAX_INDEXED_BODY_CHARS).collect())
+}
+
+/// Search hit carrying only the document ID
+#[derive(Debug, Deserialize)]
+struct IdHit {
+    id: String,
 }
 
 /// Search hit result with highlights
@@ -265,11 +297,64 @@ impl MessageIndexer {
             attributes_to_retrieve: None,
             attributes_to_highlight: Some(vec!["subject".to_string(), "body_preview".to_string()]),
             sort: Some(vec!["received_at:desc".to_string()]),
+            attributes_to_search_on: None,
         };
 
         self.client.search(request).await
     }
 
+    /// IDs of the indexed messages of a mailbox containing `phrase` in one
+    /// of `attributes`
+    ///
+    /// The phrase is matched exactly (no typo tolerance); an empty phrase
+    /// lists every indexed message of the mailbox. Fails rather than
+    /// returning a partial set when `limit` or more messages match.
+    pub async fn find_message_ids(
+        &self,
+        tenant_id: Uuid,
+        mailbox_id: Uuid,
+        phrase: &str,
+        attributes: &[&str],
+        limit: u64,
+    ) -> Result<Vec<Uuid>, String> {
+        let q = if phrase.trim().is_empty() {
+            String::new()
+        } else {
+            format!("\"{}\"", phrase.replace('"', " "))
+        };
+
+        let request = SearchRequest {
+            q,
+            offset: None,
+            limit: Some(limit),
+            filter: Some(format!(
+                "tenant_id = '{}' AND mailbox_id = '{}'",
+                tenant_id, mailbox_id
+            )),
+            attributes_to_retrieve: Some(vec!["id".to_string()]),
+            attributes_to_highlight: None,
+            sort: None,
+            attributes_to_search_on: (!attributes.is_empty())
+                .then(|| attributes.iter().map(|a| a.to_string()).collect()),
+        };
+
+        let result: SearchResult<IdHit> = self.client.search(request).await?;
+        let total = result.estimated_total_hits.unwrap_or(0);
+        if total > result.hits.len() as u64 || result.hits.len() as u64 >= limit {
+            return Err(format!(
+                "Search in mailbox {} returned a partial result ({} hits)",
+                mailbox_id,
+                result.hits.len()
+            ));
+        }
+
+        Ok(result
+            .hits
+            .iter()
+            .filter_map(|hit| Uuid::parse_str(&hit.id).ok())
+            .collect())
+    }
+
     /// Update message flags in the index
     pub async fn update_message_flags(
         &self,
@@ -330,6 +415,16 @@ mod tests {
         assert_eq!(doc.received_at, now.timestamp());
     }
 
+    #[test]
+    fn test_body_text() {
+        let raw = b"From: a@example.com\r\nSubject: Hi\r\n\r\nThe full body text.\r\n";
+        let body = body_text(raw).unwrap();
+        assert!(body.contains("The full body text."));
+        assert!(!body.contains("Subject"));
+
+        assert_eq!(body_text(b"Subject: Empty\r\n\r\n"), None);
+    }
+
     #[test]
     fn test_search_options_default() {
         let options = SearchOptions::default();
@@ -6,5 +6,5 @@
 pub mod client;
 pub mod indexer;
 
-pub use client::{MeilisearchClient, MeilisearchConfig, SearchResult};
-pub use indexer::{MessageDocument, MessageIndexer, MessageSearchHit, SearchOptions};
+pub use client::{MeilisearchClient, MeilisearchConfig, SearchResult, MAX_TOTAL_HITS};
+pub use indexer::{body_text, MessageDocument, MessageIndexer, MessageSearchHit, SearchOptions};
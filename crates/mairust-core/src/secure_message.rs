@@ -0,0 +1,445 @@
+//! Secure messages
+//!
+//! Instead of a message, the recipient of a secure message is sent a link
+//! to the web UI, where the message opens after entering a passcode. The
+//! sender either shares a passcode with the recipient some other way, or
+//! lets the recipient have a one-time code emailed to them. The raw message
+//! is kept encrypted with AES-256-GCM under the key in
+//! `MAIRUST_SECURE_MESSAGE_KEY`; messages expire, can be revoked, and every
+//! code sent, open and wrong passcode is logged. Messages are stored by
+//! `SecureMessageRepository`.
+
+use crate::queue::DeliveryJob;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Result};
+use argon2::password_hash::{rand_core::RngCore, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::types::DeliveryLane;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::{SecureMessage, SecureMessageRepository};
+use tracing::debug;
+use uuid::Uuid;
+
+/// Lifetime of a secure message when none is requested (7 days)
+pub const DEFAULT_SECURE_MESSAGE_HOURS: i64 = 24 * 7;
+
+/// Longest lifetime of a secure message (30 days)
+pub const MAX_SECURE_MESSAGE_HOURS: i64 = 24 * 30;
+
+/// Shortest pre-shared passcode
+pub const MIN_PASSCODE_LENGTH: usize = 8;
+
+/// Minutes an emailed code stays valid
+pub const CODE_VALID_MINUTES: i64 = 15;
+
+/// Seconds before another code can be emailed
+pub const CODE_RESEND_SECONDS: i64 = 60;
+
+/// Length of the AES-GCM nonce in front of a sealed message
+const NONCE_LEN: usize = 12;
+
+/// Lifetime of a new secure message; `None` when the requested number of
+/// hours is out of range
+pub fn secure_message_lifetime(hours: Option<i64>) -> Option<Duration> {
+    let hours = hours.unwrap_or(DEFAULT_SECURE_MESSAGE_HOURS);
+    (1..=MAX_SECURE_MESSAGE_HOURS)
+        .contains(&hours)
+        .then(|| Duration::hours(hours))
+}
+
+/// Key secure messages are encrypted with
+#[derive(Clone)]
+pub struct SecureMessageKey {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for SecureMessageKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecureMessageKey(..)")
+    }
+}
+
+impl SecureMessageKey {
+    /// Key from 32 bytes in base64
+    pub fn from_base64(key: &str) -> Result<Self> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(key.trim())
+            .map_err(|_| anyhow!("Secure message key is not base64"))?;
+        let cipher = Aes256Gcm::new_from_slice(&bytes)
+            .map_err(|_| anyhow!("Secure message key must be 32 bytes"))?;
+        Ok(Self { cipher })
+    }
+
+    /// Key from `MAIRUST_SECURE_MESSAGE_KEY`; `None` when it is not set,
+    /// which disables secure messages
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("MAIRUST_SECURE_MESSAGE_KEY") {
+            Ok(key) if !key.trim().is_empty() => Self::from_base64(&key).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Encrypt the raw message of the secure message `id`
+    pub fn seal(&self, id: Uuid, raw: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: raw,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt secure message"))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt the raw message of the secure message `id`; `None` when it
+    /// was sealed with another key or for another message
+    pub fn open(&self, id: Uuid, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: id.as_bytes(),
+                },
+            )
+            .ok()
+    }
+}
+
+/// Check a pre-shared passcode chosen by the sender
+pub fn validate_passcode(passcode: &str) -> Result<()> {
+    if passcode.chars().count() < MIN_PASSCODE_LENGTH {
+        bail!(
+            "Passcode must be at least {} characters",
+            MIN_PASSCODE_LENGTH
+        );
+    }
+    Ok(())
+}
+
+/// Argon2 hash of a passcode or code
+pub fn hash_passcode(passcode: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(passcode.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash passcode: {}", e))
+}
+
+fn verify_passcode(hash: &str, passcode: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(passcode.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Random six-digit one-time code
+pub fn generate_code() -> String {
+    format!("{:06}", OsRng.next_u32() % 1_000_000)
+}
+
+/// Whether a passcode opens the message: its pre-shared passcode, or the
+/// last emailed code while it is valid
+pub fn check_passcode(message: &SecureMessage, passcode: &str, now: DateTime<Utc>) -> bool {
+    let passcode = passcode.trim();
+    if passcode.is_empty() {
+        return false;
+    }
+    if let Some(hash) = &message.passcode_hash {
+        return verify_passcode(hash, passcode);
+    }
+    match (&message.code_hash, message.code_sent_at) {
+        (Some(hash), Some(sent_at)) if now < sent_at + Duration::minutes(CODE_VALID_MINUTES) => {
+            verify_passcode(hash, passcode)
+        }
+        _ => false,
+    }
+}
+
+/// Base URL of the web UI in links, from `MAIRUST_WEB_URL`
+pub fn web_base_url() -> String {
+    match std::env::var("MAIRUST_WEB_URL") {
+        Ok(url) if !url.trim().is_empty() => url.trim().trim_end_matches('/').to_string(),
+        _ => {
+            let hostname = std::env::var("MAIRUST_HOSTNAME")
+                .unwrap_or_else(|_| "mail.example.com".to_string());
+            format!("https://{}", hostname)
+        }
+    }
+}
+
+/// Link to a secure message
+pub fn secure_message_url(base_url: &str, token: &str) -> String {
+    format!("{}/secure/{}", base_url.trim_end_matches('/'), token)
+}
+
+/// Address with all but the first letter of its local part hidden, to
+/// show where a code went without giving the address away
+pub fn mask_address(address: &str) -> String {
+    match address.rsplit_once('@') {
+        Some((local, domain)) => {
+            let first: String = local.chars().take(1).collect();
+            format!("{}***@{}", first, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+/// Body of the notification sent to the recipient in place of the message
+pub fn notification_text(
+    sender: &str,
+    url: &str,
+    expires_at: DateTime<Utc>,
+    emailed_codes: bool,
+) -> String {
+    let mut text = format!(
+        "{} sent you a secure message.\r\n\r\n\
+         Open it here:\r\n{}\r\n\r\n",
+        sender, url
+    );
+    if emailed_codes {
+        text.push_str(
+            "On that page you can have a one-time code sent to this address\r\n\
+             to open the message.\r\n",
+        );
+    } else {
+        text.push_str(
+            "You need the passcode the sender gave you to open the message.\r\n\
+             It is not included in this email.\r\n",
+        );
+    }
+    text.push_str(&format!(
+        "\r\nThe link expires on {}.\r\n",
+        expires_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+    text
+}
+
+/// Build the email with a one-time code, returning the raw message
+pub fn build_code_message(message: &SecureMessage, code: &str) -> Vec<u8> {
+    let domain = message
+        .sender
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+
+    let mut msg = String::new();
+    msg.push_str(&format!("From: <{}>\r\n", message.sender));
+    msg.push_str(&format!("To: <{}>\r\n", message.recipient));
+    msg.push_str("Subject: Your code for a secure message\r\n");
+    msg.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+    msg.push_str(&format!("Message-ID: <{}@{}>\r\n", Uuid::now_v7(), domain));
+    msg.push_str("Auto-Submitted: auto-generated\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+    msg.push_str(&format!(
+        "Your code to open the secure message from {} is:\r\n\r\n\
+         {}\r\n\r\n\
+         It is valid for {} minutes and can be used once.\r\n\
+         If you did not ask for it, you can ignore this email.\r\n",
+        message.sender, code, CODE_VALID_MINUTES
+    ));
+
+    msg.into_bytes()
+}
+
+/// Email a new one-time code to the recipient; false when a code was sent
+/// too recently to send another
+pub async fn send_code(db_pool: &DatabasePool, message: &SecureMessage) -> Result<bool> {
+    let code = generate_code();
+    let sent = SecureMessageRepository::new(db_pool.clone())
+        .set_code(
+            message.id,
+            &hash_passcode(&code)?,
+            Utc::now() - Duration::seconds(CODE_RESEND_SECONDS),
+        )
+        .await?;
+    if !sent {
+        debug!("Kept the recent code of secure message {}", message.id);
+        return Ok(false);
+    }
+
+    let raw = build_code_message(message, &code);
+    let job = DeliveryJob {
+        message_id: Uuid::now_v7(),
+        tenant_id: message.tenant_id,
+        from: message.sender.clone(),
+        to: vec![message.recipient.clone()],
+        storage_path: String::new(),
+        raw_message_base64: Some(base64::engine::general_purpose::STANDARD.encode(&raw)),
+        require_tls: false,
+        priority: 0,
+        lane: DeliveryLane::Transactional,
+        archived: true,
+        dkim_signed: false,
+        forwarded: false,
+        queued_at: None,
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO jobs (id, queue, payload, status, attempts, max_attempts, scheduled_at, created_at,
+                          tenant_id)
+        VALUES ($1, 'delivery', $2, 'pending', 0, 5, NOW(), NOW(), $3)
+        "#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(serde_json::to_value(&job)?)
+    .bind(message.tenant_id)
+    .execute(db_pool.pool())
+    .await?;
+
+    debug!("Queued a code for secure message {}", message.id);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    fn secure_message() -> SecureMessage {
+        let now = Utc::now();
+        SecureMessage {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            token_hash: String::new(),
+            sender: "alice@example.com".to_string(),
+            recipient: "bob@example.net".to_string(),
+            sealed_message: Vec::new(),
+            passcode_hash: None,
+            code_hash: None,
+            code_sent_at: None,
+            failed_attempts: 0,
+            created_by: None,
+            expires_at: now + Duration::days(7),
+            revoked_at: None,
+            open_count: 0,
+            last_opened_at: None,
+            created_at: now,
+        }
+    }
+
+    #[test]
+    fn test_secure_message_lifetime() {
+        assert_eq!(secure_message_lifetime(None), Some(Duration::days(7)));
+        assert_eq!(secure_message_lifetime(Some(1)), Some(Duration::hours(1)));
+        assert_eq!(secure_message_lifetime(Some(720)), Some(Duration::days(30)));
+        assert_eq!(secure_message_lifetime(Some(721)), None);
+        assert_eq!(secure_message_lifetime(Some(0)), None);
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = SecureMessageKey::from_base64(KEY).unwrap();
+        let id = Uuid::now_v7();
+        let raw = b"Subject: Contract\r\n\r\nSigned copy attached.\r\n";
+
+        let sealed = key.seal(id, raw).unwrap();
+        assert!(!sealed.windows(8).any(|w| w == b"Contract"));
+        assert_ne!(key.seal(id, raw).unwrap(), sealed);
+        assert_eq!(key.open(id, &sealed).unwrap(), raw);
+
+        // Bound to the message it was sealed for
+        assert!(key.open(Uuid::now_v7(), &sealed).is_none());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(id, &tampered).is_none());
+        assert!(key.open(id, &sealed[..4]).is_none());
+
+        let other = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+        let other = SecureMessageKey::from_base64(&other).unwrap();
+        assert!(other.open(id, &sealed).is_none());
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(SecureMessageKey::from_base64("not base64!").is_err());
+        assert!(SecureMessageKey::from_base64("AAECAwQF").is_err());
+    }
+
+    #[test]
+    fn test_pre_shared_passcode() {
+        assert!(validate_passcode("short").is_err());
+        assert!(validate_passcode("correct horse").is_ok());
+
+        let mut message = secure_message();
+        message.passcode_hash = Some(hash_passcode("correct horse").unwrap());
+        assert!(!message.uses_emailed_codes());
+        let now = Utc::now();
+        assert!(check_passcode(&message, "correct horse", now));
+        assert!(check_passcode(&message, " correct horse\n", now));
+        assert!(!check_passcode(&message, "wrong horse", now));
+        assert!(!check_passcode(&message, "", now));
+    }
+
+    #[test]
+    fn test_emailed_code() {
+        let code = generate_code();
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+
+        let mut message = secure_message();
+        let now = Utc::now();
+        assert!(!check_passcode(&message, &code, now));
+
+        message.code_hash = Some(hash_passcode(&code).unwrap());
+        message.code_sent_at = Some(now);
+        assert!(check_passcode(&message, &code, now));
+        assert!(!check_passcode(&message, "not the code", now));
+        let later = now + Duration::minutes(CODE_VALID_MINUTES);
+        assert!(!check_passcode(&message, &code, later));
+    }
+
+    #[test]
+    fn test_notification_text() {
+        let expires_at = DateTime::parse_from_rfc3339("2026-10-23T09:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let url = secure_message_url("https://mail.example.com/", "abc");
+        assert_eq!(url, "https://mail.example.com/secure/abc");
+
+        let text = notification_text("alice@example.com", &url, expires_at, true);
+        assert!(text.contains("alice@example.com sent you a secure message"));
+        assert!(text.contains(&url));
+        assert!(text.contains("one-time code"));
+        assert!(text.contains("2026-10-23 09:30 UTC"));
+        let text = notification_text("alice@example.com", &url, expires_at, false);
+        assert!(text.contains("passcode the sender gave you"));
+    }
+
+    #[test]
+    fn test_mask_address() {
+        assert_eq!(mask_address("bob@example.net"), "b***@example.net");
+        assert_eq!(mask_address("élise@example.fr"), "é***@example.fr");
+        assert_eq!(mask_address("@example.net"), "***@example.net");
+        assert_eq!(mask_address("postmaster"), "***");
+    }
+
+    #[test]
+    fn test_build_code_message() {
+        let message = secure_message();
+        let raw = String::from_utf8(build_code_message(&message, "042917")).unwrap();
+        assert!(raw.contains("From: <alice@example.com>\r\n"));
+        assert!(raw.contains("To: <bob@example.net>\r\n"));
+        assert!(raw.contains("@example.com>\r\n"));
+        assert!(raw.contains("\r\n\r\n042917\r\n"));
+    }
+}
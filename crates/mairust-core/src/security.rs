@@ -0,0 +1,467 @@
+//! Account security events - blocked logins and logins from new networks
+//!
+//! When the brute-force protection bans an address after failed logins to
+//! an account, or a user logs in from a network they have not logged in
+//! from before, a security event is recorded for the user. Events are
+//! always recorded; the tenant decides which of them are also sent to the
+//! user as a notification email in their inbox.
+//!
+//! Tenant settings:
+//! - `notify_login_blocked`: email users about blocked logins (default on)
+//! - `notify_new_login_location`: email users about logins from a new
+//!   network (default on)
+
+use crate::preview::MessagePreview;
+use anyhow::Result;
+use chrono::Utc;
+use mairust_common::types::{TenantId, UserId};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::{Message, User};
+use mairust_storage::repository::users::{DbUserRepository, UserRepository};
+use mairust_storage::repository::{
+    MailboxRepository, MessageRepository, NewSecurityEvent, SecurityEvent, SecurityEventRepository,
+};
+use mairust_storage::{FileStorage, LocalStorage, Residency, StorageLayout, TenantRepository};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Kind of security event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityEventType {
+    /// Failed logins got the client address banned
+    LoginBlocked,
+    /// Successful login from a network the user has not used before
+    NewLoginLocation,
+}
+
+impl SecurityEventType {
+    /// Value stored in `security_events.event_type`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityEventType::LoginBlocked => "login_blocked",
+            SecurityEventType::NewLoginLocation => "new_login_location",
+        }
+    }
+
+    /// Subject of the notification email
+    fn subject(&self) -> &'static str {
+        match self {
+            SecurityEventType::LoginBlocked => "Logins to your account were blocked",
+            SecurityEventType::NewLoginLocation => "New login to your account",
+        }
+    }
+}
+
+/// Security notification settings of a tenant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityNotificationSettings {
+    pub login_blocked: bool,
+    pub new_login_location: bool,
+}
+
+impl Default for SecurityNotificationSettings {
+    fn default() -> Self {
+        Self {
+            login_blocked: true,
+            new_login_location: true,
+        }
+    }
+}
+
+impl SecurityNotificationSettings {
+    /// Read `notify_login_blocked` and `notify_new_login_location` from
+    /// tenant settings
+    pub fn from_tenant_settings(settings: &serde_json::Value) -> Self {
+        let flag = |key: &str| settings.get(key).and_then(|v| v.as_bool());
+        let defaults = Self::default();
+        Self {
+            login_blocked: flag("notify_login_blocked").unwrap_or(defaults.login_blocked),
+            new_login_location: flag("notify_new_login_location")
+                .unwrap_or(defaults.new_login_location),
+        }
+    }
+
+    /// Whether users are emailed about events of this kind
+    pub fn notifies(&self, event_type: SecurityEventType) -> bool {
+        match event_type {
+            SecurityEventType::LoginBlocked => self.login_blocked,
+            SecurityEventType::NewLoginLocation => self.new_login_location,
+        }
+    }
+}
+
+/// Network a login comes from: the /24 of an IPv4 address, the /64 of an
+/// IPv6 address
+pub fn login_source(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => login_source(IpAddr::V4(v4)),
+            None => {
+                let s = v6.segments();
+                format!("{:x}:{:x}:{:x}:{:x}::/64", s[0], s[1], s[2], s[3])
+            }
+        },
+    }
+}
+
+/// Build the notification email for an event, returning the raw message
+pub fn build_notification(
+    hostname: &str,
+    to: &str,
+    event_type: SecurityEventType,
+    ip: IpAddr,
+    protocol: &str,
+) -> Vec<u8> {
+    let now = Utc::now();
+
+    let mut msg = String::new();
+    msg.push_str(&format!(
+        "From: Account Security <postmaster@{}>\r\n",
+        hostname
+    ));
+    msg.push_str(&format!("To: <{}>\r\n", to));
+    msg.push_str(&format!("Subject: {}\r\n", event_type.subject()));
+    msg.push_str(&format!("Date: {}\r\n", now.to_rfc2822()));
+    msg.push_str(&format!(
+        "Message-ID: <{}@{}>\r\n",
+        Uuid::now_v7(),
+        hostname
+    ));
+    msg.push_str("Auto-Submitted: auto-generated\r\n");
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+
+    let protocol = protocol.to_uppercase();
+    match event_type {
+        SecurityEventType::LoginBlocked => msg.push_str(&format!(
+            "Repeated failed {} logins to your account {} came from {}.\r\n\
+             Logins from that address are blocked for a while.\r\n\r\n\
+             If these were not your attempts, someone may be trying to guess\r\n\
+             your password. Consider changing it.\r\n",
+            protocol, to, ip
+        )),
+        SecurityEventType::NewLoginLocation => msg.push_str(&format!(
+            "Your account {} was used to log in over {} from {},\r\n\
+             a network it has not logged in from before.\r\n\r\n\
+             If this was not you, change your password right away.\r\n",
+            to, protocol, ip
+        )),
+    }
+    msg.push_str(&format!("\r\nTime: {}\r\n", now.to_rfc2822()));
+
+    msg.into_bytes()
+}
+
+/// Records security events of logins and notifies users
+///
+/// The methods return right away; the work runs in the background so that
+/// logins are not slowed down.
+#[derive(Debug, Clone)]
+pub struct SecurityNotifier {
+    /// Host name notification emails are sent from
+    hostname: String,
+    /// Storage path for message files
+    storage_path: PathBuf,
+    /// Layout of the notification message files
+    storage_layout: StorageLayout,
+    /// Regions tenant data is pinned to
+    residency: Option<Arc<Residency>>,
+}
+
+impl SecurityNotifier {
+    /// Create a notifier delivering into the message storage at `storage_path`
+    pub fn new(hostname: impl Into<String>, storage_path: impl Into<PathBuf>) -> Self {
+        Self {
+            hostname: hostname.into(),
+            storage_path: storage_path.into(),
+            storage_layout: StorageLayout::default(),
+            residency: None,
+        }
+    }
+
+    /// Store notifications under `layout` instead of the flat layout
+    pub fn with_storage_layout(mut self, layout: StorageLayout) -> Self {
+        self.storage_layout = layout;
+        self
+    }
+
+    /// Store notifications of tenants pinned to this server's region in the
+    /// region's storage
+    pub fn with_residency(mut self, residency: Arc<Residency>) -> Self {
+        self.residency = Some(residency);
+        self
+    }
+
+    /// Failed logins to `username` from `ip` got the address banned
+    pub fn login_blocked(
+        &self,
+        db_pool: &DatabasePool,
+        username: &str,
+        ip: IpAddr,
+        protocol: &str,
+    ) {
+        let notifier = self.clone();
+        let db_pool = db_pool.clone();
+        let username = username.to_string();
+        let protocol = protocol.to_string();
+        tokio::spawn(async move {
+            let user = match DbUserRepository::new(db_pool.clone())
+                .get_by_email(&username)
+                .await
+            {
+                Ok(Some(user)) => user,
+                // Guessed names of users that do not exist concern no one
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Failed to look up {} for a blocked login: {}", username, e);
+                    return;
+                }
+            };
+            if let Err(e) = notifier
+                .record(
+                    &db_pool,
+                    &user,
+                    SecurityEventType::LoginBlocked,
+                    ip,
+                    &protocol,
+                )
+                .await
+            {
+                warn!("Failed to record blocked login of {}: {}", username, e);
+            }
+        });
+    }
+
+    /// The user logged in from `ip`
+    pub fn login_succeeded(
+        &self,
+        db_pool: &DatabasePool,
+        user_id: UserId,
+        tenant_id: TenantId,
+        ip: IpAddr,
+        protocol: &str,
+    ) {
+        let notifier = self.clone();
+        let db_pool = db_pool.clone();
+        let protocol = protocol.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = notifier
+                .check_login_source(&db_pool, user_id, tenant_id, ip, &protocol)
+                .await
+            {
+                warn!("Failed to check login source of user {}: {}", user_id, e);
+            }
+        });
+    }
+
+    async fn check_login_source(
+        &self,
+        db_pool: &DatabasePool,
+        user_id: UserId,
+        tenant_id: TenantId,
+        ip: IpAddr,
+        protocol: &str,
+    ) -> Result<()> {
+        let is_new = SecurityEventRepository::new(db_pool.clone())
+            .record_login_source(user_id, &login_source(ip))
+            .await?;
+        if !is_new {
+            return Ok(());
+        }
+
+        let Some(user) = DbUserRepository::new(db_pool.clone())
+            .get(tenant_id, user_id)
+            .await?
+        else {
+            return Ok(());
+        };
+        self.record(
+            db_pool,
+            &user,
+            SecurityEventType::NewLoginLocation,
+            ip,
+            protocol,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Record an event and notify the user if the tenant wants it
+    async fn record(
+        &self,
+        db_pool: &DatabasePool,
+        user: &User,
+        event_type: SecurityEventType,
+        ip: IpAddr,
+        protocol: &str,
+    ) -> Result<SecurityEvent> {
+        let repo = SecurityEventRepository::new(db_pool.clone());
+        let event = repo
+            .record(&NewSecurityEvent {
+                tenant_id: user.tenant_id,
+                user_id: user.id,
+                event_type: event_type.as_str().to_string(),
+                ip_address: Some(ip.to_string()),
+                protocol: Some(protocol.to_string()),
+                details: serde_json::json!({ "source": login_source(ip) }),
+            })
+            .await?;
+        info!(
+            "Security event {} for {} from {} ({})",
+            event_type.as_str(),
+            user.email,
+            ip,
+            protocol
+        );
+
+        let settings = TenantRepository::new(db_pool.clone())
+            .find_by_id(user.tenant_id)
+            .await?
+            .map(|tenant| SecurityNotificationSettings::from_tenant_settings(&tenant.settings))
+            .unwrap_or_default();
+        if !settings.notifies(event_type) {
+            debug!(
+                "Tenant {} does not notify users of {}",
+                user.tenant_id,
+                event_type.as_str()
+            );
+            return Ok(event);
+        }
+
+        if self
+            .deliver(db_pool, user, event_type, ip, protocol)
+            .await?
+        {
+            repo.mark_notified(event.id).await?;
+        }
+        Ok(event)
+    }
+
+    /// Deliver the notification email to the user's inbox; false when the
+    /// user has no mailbox
+    async fn deliver(
+        &self,
+        db_pool: &DatabasePool,
+        user: &User,
+        event_type: SecurityEventType,
+        ip: IpAddr,
+        protocol: &str,
+    ) -> Result<bool> {
+        let Some(mailbox) = MailboxRepository::new(db_pool.clone())
+            .find_by_address(&user.email)
+            .await?
+        else {
+            debug!("No mailbox to notify {} in", user.email);
+            return Ok(false);
+        };
+
+        let raw = build_notification(&self.hostname, &user.email, event_type, ip, protocol);
+        let preview = MessagePreview::of_raw(&raw);
+        let message_id = Uuid::now_v7();
+        let storage_path =
+            self.storage_layout
+                .message_path(mailbox.tenant_id, mailbox.id, message_id);
+        let pinned = match &self.residency {
+            Some(residency) => residency.storage_for(mailbox.tenant_id).await?,
+            None => None,
+        };
+        LocalStorage::from_path(pinned.map_or(&self.storage_path, |storage| &storage.path))?
+            .store(&storage_path, &raw)
+            .await?;
+
+        let message = Message {
+            id: message_id,
+            tenant_id: mailbox.tenant_id,
+            mailbox_id: mailbox.id,
+            message_id_header: None,
+            subject: Some(event_type.subject().to_string()),
+            from_address: Some(format!("postmaster@{}", self.hostname)),
+            to_addresses: serde_json::json!([user.email]),
+            cc_addresses: None,
+            headers: serde_json::json!({}),
+            body_preview: preview.body_preview,
+            body_size: raw.len() as i64,
+            has_attachments: false,
+            storage_path,
+            seen: false,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: None,
+            tags: serde_json::json!(["security"]),
+            metadata: serde_json::json!({
+                "security_event": event_type.as_str(),
+            }),
+            received_at: Utc::now(),
+            created_at: Utc::now(),
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: None,
+            snippet: Some(preview.snippet),
+        };
+        MessageRepository::new(db_pool.clone())
+            .create(&message)
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_source() {
+        assert_eq!(login_source("192.0.2.77".parse().unwrap()), "192.0.2.0/24");
+        assert_eq!(
+            login_source("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(
+            login_source("::ffff:198.51.100.9".parse().unwrap()),
+            "198.51.100.0/24"
+        );
+    }
+
+    #[test]
+    fn test_notification_settings() {
+        assert_eq!(
+            SecurityNotificationSettings::from_tenant_settings(&serde_json::json!({})),
+            SecurityNotificationSettings::default()
+        );
+
+        let settings = SecurityNotificationSettings::from_tenant_settings(&serde_json::json!({
+            "notify_login_blocked": false,
+            "notify_new_login_location": "yes",
+        }));
+        assert!(!settings.notifies(SecurityEventType::LoginBlocked));
+        assert!(settings.notifies(SecurityEventType::NewLoginLocation));
+    }
+
+    #[test]
+    fn test_build_notification() {
+        let raw = build_notification(
+            "mx.example.com",
+            "alice@example.com",
+            SecurityEventType::LoginBlocked,
+            "192.0.2.1".parse().unwrap(),
+            "imap",
+        );
+        let text = String::from_utf8(raw).unwrap();
+        assert!(text.starts_with("From: Account Security <postmaster@mx.example.com>\r\n"));
+        assert!(text.contains("To: <alice@example.com>\r\n"));
+        assert!(text.contains("Subject: Logins to your account were blocked\r\n"));
+        assert!(text.contains("Auto-Submitted: auto-generated\r\n"));
+        assert!(text.contains("failed IMAP logins"));
+        assert!(text.contains("192.0.2.1"));
+    }
+}
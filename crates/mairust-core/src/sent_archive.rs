@@ -0,0 +1,308 @@
+//! Sent-mail archiving
+//!
+//! A copy of every message a user sends, through the submission port or
+//! the send API, is kept in an archive separate from the Sent folder. The
+//! archive is either a folder of the sender or an email address: local
+//! addresses get the copy filed into their mailbox, remote ones receive it
+//! as an added BCC recipient.
+//!
+//! Archiving happens once per message, on the first delivery attempt. A
+//! copy is not filed into a mailbox that already holds the message
+//! (same Message-ID), and the Sent folder itself is never an archive:
+//! clients already save their own copy there.
+//!
+//! Settings:
+//! - tenant setting `sent_archive`: archive of the mail of every user
+//! - user setting `sent_archive`: the user's own archive, kept as well
+
+use crate::folders::{normalize_folder_path, SpecialUse};
+use crate::imap::search;
+use crate::preview::MessagePreview;
+use anyhow::Result;
+use chrono::Utc;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::file::FileStorage;
+use mairust_storage::models::{Mailbox, Message};
+use mairust_storage::repository::{MailboxRepository, MessageRepository, UserSettingsRepository};
+use mairust_storage::TenantRepository;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Where sent mail is archived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveTarget {
+    /// Folder of the sender (`Archive/Outgoing`)
+    Folder(String),
+    /// Mailbox address, local or remote
+    Address(String),
+}
+
+impl ArchiveTarget {
+    /// Parse a setting value: an address when it contains `@`, otherwise a
+    /// folder path
+    ///
+    /// Invalid values and the Sent folder, under any of its names, are
+    /// refused.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.contains('@') {
+            let (local, domain) = value.rsplit_once('@')?;
+            if local.is_empty() || !domain.contains('.') || value.contains(char::is_whitespace) {
+                return None;
+            }
+            return Some(Self::Address(value.to_lowercase()));
+        }
+
+        let path = normalize_folder_path(value)?;
+        if SpecialUse::from_folder_name(&path) == Some(SpecialUse::Sent) {
+            return None;
+        }
+        Some(Self::Folder(path))
+    }
+
+    /// Read the `sent_archive` tenant setting
+    pub fn from_tenant_settings(settings: &serde_json::Value) -> Option<Self> {
+        settings
+            .get("sent_archive")
+            .and_then(|v| v.as_str())
+            .and_then(Self::parse)
+    }
+}
+
+/// Files archive copies of outbound mail
+pub struct SentArchiver {
+    db_pool: DatabasePool,
+}
+
+impl SentArchiver {
+    /// Create an archiver
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Archive a message sent from `from`, returning the addresses to add
+    /// to its recipients as BCC
+    ///
+    /// Senders without a local mailbox of the tenant have no archive.
+    pub async fn archive<S: FileStorage>(
+        &self,
+        file_storage: &S,
+        tenant_id: Uuid,
+        from: &str,
+        recipients: &[String],
+        data: &[u8],
+    ) -> Result<Vec<String>> {
+        let mailboxes = MailboxRepository::new(self.db_pool.clone());
+        let Some(sender) = mailboxes
+            .find_by_address_for_tenant(tenant_id, &from.to_lowercase())
+            .await?
+        else {
+            return Ok(Vec::new());
+        };
+
+        let mut targets = Vec::new();
+        if let Some(tenant) = TenantRepository::new(self.db_pool.clone())
+            .find_by_id(tenant_id)
+            .await?
+        {
+            targets.extend(ArchiveTarget::from_tenant_settings(&tenant.settings));
+        }
+        if let Some(user_id) = sender.user_id {
+            let settings = UserSettingsRepository::new(self.db_pool.clone())
+                .get(tenant_id, user_id)
+                .await?;
+            targets.extend(
+                settings
+                    .sent_archive
+                    .as_deref()
+                    .and_then(ArchiveTarget::parse),
+            );
+        }
+        targets.dedup();
+
+        let headers = search::stored_headers(data);
+        let message_id_header = headers
+            .get("message-id")
+            .and_then(|v| v.as_str())
+            .map(|v| v.trim().to_string());
+
+        let mut bcc = Vec::new();
+        for target in targets {
+            let mailbox = match &target {
+                ArchiveTarget::Folder(path) => match self.sender_folder(&sender, path).await? {
+                    Some(folder) => folder,
+                    None => {
+                        warn!("Archive folder {} of {} does not exist", path, from);
+                        continue;
+                    }
+                },
+                ArchiveTarget::Address(address) => {
+                    match mailboxes
+                        .find_by_address_for_tenant(tenant_id, address)
+                        .await?
+                    {
+                        Some(mailbox) => mailbox,
+                        None => {
+                            // Remote archive; recipients already get the message
+                            if !recipients.iter().any(|r| r.eq_ignore_ascii_case(address)) {
+                                bcc.push(address.clone());
+                            }
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            if SpecialUse::of_folder(
+                mailbox.special_use.as_deref(),
+                mailbox.folder_path.as_deref().unwrap_or_default(),
+            ) == Some(SpecialUse::Sent)
+            {
+                debug!("Not archiving {} into a Sent folder", from);
+                continue;
+            }
+            if let Some(message_id) = &message_id_header {
+                if self.holds(mailbox.id, message_id).await? {
+                    debug!("Archive {} already holds {}", mailbox.address, message_id);
+                    continue;
+                }
+            }
+
+            self.file(file_storage, &mailbox, from, &headers, data)
+                .await?;
+        }
+
+        Ok(bcc)
+    }
+
+    /// Folder of the sender's user by full name
+    async fn sender_folder(&self, sender: &Mailbox, path: &str) -> Result<Option<Mailbox>> {
+        let Some(user_id) = sender.user_id else {
+            return Ok(None);
+        };
+        let folder = sqlx::query_as::<_, Mailbox>(
+            "SELECT * FROM mailboxes WHERE tenant_id = $1 AND user_id = $2 AND folder_path = $3",
+        )
+        .bind(sender.tenant_id)
+        .bind(user_id)
+        .bind(path)
+        .fetch_optional(self.db_pool.pool())
+        .await?;
+        Ok(folder)
+    }
+
+    /// Whether a mailbox already holds the message with `message_id`
+    async fn holds(&self, mailbox_id: Uuid, message_id: &str) -> Result<bool> {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE mailbox_id = $1 AND message_id_header = $2)",
+        )
+        .bind(mailbox_id)
+        .bind(message_id)
+        .fetch_one(self.db_pool.pool())
+        .await?;
+        Ok(exists)
+    }
+
+    /// Store a copy of the message in `mailbox`, marked as seen
+    async fn file<S: FileStorage>(
+        &self,
+        file_storage: &S,
+        mailbox: &Mailbox,
+        from: &str,
+        headers: &serde_json::Value,
+        data: &[u8],
+    ) -> Result<()> {
+        let message_id = Uuid::now_v7();
+        let storage_path = file_storage.message_path(mailbox.tenant_id, mailbox.id, message_id);
+        file_storage.store(&storage_path, data).await?;
+        let preview = MessagePreview::of_raw(data);
+
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        let message = Message {
+            id: message_id,
+            tenant_id: mailbox.tenant_id,
+            mailbox_id: mailbox.id,
+            message_id_header: header("message-id"),
+            subject: header("subject"),
+            from_address: header("from").or_else(|| Some(from.to_string())),
+            to_addresses: serde_json::json!(header("to").into_iter().collect::<Vec<_>>()),
+            cc_addresses: None,
+            headers: headers.clone(),
+            body_preview: preview.body_preview,
+            body_size: data.len() as i64,
+            has_attachments: false,
+            storage_path,
+            seen: true,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: None,
+            tags: serde_json::json!(["sent-archive"]),
+            metadata: serde_json::json!({}),
+            received_at: Utc::now(),
+            created_at: Utc::now(),
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: search::sent_date(data),
+            snippet: Some(preview.snippet),
+        };
+        MessageRepository::new(self.db_pool.clone())
+            .create(&message)
+            .await?;
+
+        info!("Archived message from {} into {}", from, mailbox.address);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_archive_target() {
+        assert_eq!(
+            ArchiveTarget::parse(" Archive@Example.com "),
+            Some(ArchiveTarget::Address("archive@example.com".to_string()))
+        );
+        assert_eq!(
+            ArchiveTarget::parse("Archive/Outgoing/"),
+            Some(ArchiveTarget::Folder("Archive/Outgoing".to_string()))
+        );
+        assert_eq!(ArchiveTarget::parse("@example.com"), None);
+        assert_eq!(ArchiveTarget::parse("archive@localhost"), None);
+        assert_eq!(ArchiveTarget::parse(""), None);
+        assert_eq!(ArchiveTarget::parse("Archive//Outgoing"), None);
+    }
+
+    #[test]
+    fn test_sent_folder_is_not_an_archive() {
+        assert_eq!(ArchiveTarget::parse("Sent"), None);
+        assert_eq!(ArchiveTarget::parse("Sent Items"), None);
+        assert_eq!(ArchiveTarget::parse("送信済み"), None);
+        assert!(ArchiveTarget::parse("Sent/Archive").is_some());
+    }
+
+    #[test]
+    fn test_from_tenant_settings() {
+        let settings = serde_json::json!({"sent_archive": "compliance@example.com"});
+        assert_eq!(
+            ArchiveTarget::from_tenant_settings(&settings),
+            Some(ArchiveTarget::Address("compliance@example.com".to_string()))
+        );
+        assert_eq!(
+            ArchiveTarget::from_tenant_settings(&serde_json::json!({})),
+            None
+        );
+        assert_eq!(
+            ArchiveTarget::from_tenant_settings(&serde_json::json!({"sent_archive": "Sent"})),
+            None
+        );
+    }
+}
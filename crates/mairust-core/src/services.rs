@@ -0,0 +1,144 @@
+//! IMAP and POP3 access of tenants and users
+//!
+//! A protocol can be switched off at three levels: for a plan in the server
+//! configuration (`[services.plans.<plan>]`), for a tenant
+//! (`services_enabled` in its settings) and for a user (their
+//! `services_enabled` column). A login is refused when any level switches
+//! the protocol off, so users can be restricted to webmail and the API.
+
+use anyhow::Result;
+use mairust_common::types::{MailService, ServicesEnabled, TenantId, UserId};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::tenants::DbTenantRepository;
+use mairust_storage::repository::users::DbUserRepository;
+use std::collections::HashMap;
+
+/// Why a login to a protocol is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceRefusal {
+    /// Switched off for the tenant or its plan
+    Tenant,
+    /// Switched off for the user
+    User,
+}
+
+impl ServiceRefusal {
+    /// Text sent to the refused client
+    pub fn message(&self, service: MailService) -> String {
+        match self {
+            ServiceRefusal::Tenant => {
+                format!(
+                    "{} access is disabled for your organization",
+                    service.name()
+                )
+            }
+            ServiceRefusal::User => format!(
+                "{} access is disabled for your account, use webmail instead",
+                service.name()
+            ),
+        }
+    }
+}
+
+/// Protocol access by plan, checked against tenant and user settings at
+/// login
+#[derive(Debug, Clone, Default)]
+pub struct ServicePolicy {
+    plans: HashMap<String, ServicesEnabled>,
+}
+
+impl ServicePolicy {
+    /// Create a policy with the protocols of each plan
+    pub fn new(plans: HashMap<String, ServicesEnabled>) -> Self {
+        Self { plans }
+    }
+
+    /// Decide whether a login is allowed, from the tenant's plan and the
+    /// tenant and user settings
+    pub fn decide(
+        &self,
+        service: MailService,
+        plan: &str,
+        tenant: &ServicesEnabled,
+        user: &ServicesEnabled,
+    ) -> Option<ServiceRefusal> {
+        let plan_services = self.plans.get(plan).copied().unwrap_or_default();
+        if !plan_services.allows(service) || !tenant.allows(service) {
+            Some(ServiceRefusal::Tenant)
+        } else if !user.allows(service) {
+            Some(ServiceRefusal::User)
+        } else {
+            None
+        }
+    }
+
+    /// Check whether a user who has logged in may use a protocol
+    pub async fn check(
+        &self,
+        db_pool: &DatabasePool,
+        tenant_id: TenantId,
+        user_id: UserId,
+        service: MailService,
+    ) -> Result<Option<ServiceRefusal>> {
+        let tenant = DbTenantRepository::new(db_pool.clone())
+            .find_by_id(tenant_id)
+            .await?;
+        let (plan, tenant_services) = match &tenant {
+            Some(tenant) => (
+                tenant.plan.as_str(),
+                tenant
+                    .settings
+                    .get("services_enabled")
+                    .map(ServicesEnabled::from_json)
+                    .unwrap_or_default(),
+            ),
+            None => ("", ServicesEnabled::default()),
+        };
+        let user_services = DbUserRepository::new(db_pool.clone())
+            .services_enabled(user_id)
+            .await?;
+        Ok(self.decide(service, plan, &tenant_services, &user_services))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decide() {
+        let policy = ServicePolicy::new(HashMap::from([(
+            "free".to_string(),
+            ServicesEnabled {
+                imap: true,
+                pop3: false,
+            },
+        )]));
+        let enabled = ServicesEnabled::default();
+        let no_imap = ServicesEnabled {
+            imap: false,
+            pop3: true,
+        };
+
+        assert_eq!(
+            policy.decide(MailService::Pop3, "pro", &enabled, &enabled),
+            None
+        );
+        assert_eq!(
+            policy.decide(MailService::Pop3, "free", &enabled, &enabled),
+            Some(ServiceRefusal::Tenant)
+        );
+        assert_eq!(
+            policy.decide(MailService::Imap, "free", &enabled, &enabled),
+            None
+        );
+        assert_eq!(
+            policy.decide(MailService::Imap, "pro", &no_imap, &enabled),
+            Some(ServiceRefusal::Tenant)
+        );
+        assert_eq!(
+            policy.decide(MailService::Imap, "pro", &enabled, &no_imap),
+            Some(ServiceRefusal::User)
+        );
+    }
+}
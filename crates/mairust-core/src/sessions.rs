@@ -0,0 +1,400 @@
+//! Registry of live protocol sessions
+//!
+//! SMTP, LMTP, IMAP, POP3 and ManageSieve servers register each connection
+//! in a shared [`SessionRegistry`] for as long as it lasts, with its client
+//! address, user, protocol state and traffic. Administrators list the
+//! sessions and terminate them through the API; the connection of a
+//! terminated session is closed at once, whatever the session is doing.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Protocol of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionProtocol {
+    Smtp,
+    Submission,
+    Lmtp,
+    Imap,
+    Pop3,
+    ManageSieve,
+}
+
+/// What a session is known to be doing
+#[derive(Debug, Clone)]
+struct SessionDetails {
+    user: Option<String>,
+    tenant_id: Option<Uuid>,
+    state: &'static str,
+}
+
+/// A connection being served
+#[derive(Debug)]
+pub struct ActiveSession {
+    id: Uuid,
+    protocol: SessionProtocol,
+    peer_addr: SocketAddr,
+    started_at: DateTime<Utc>,
+    details: Mutex<SessionDetails>,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    terminate: CancellationToken,
+}
+
+impl ActiveSession {
+    /// A session that is not registered (yet)
+    pub fn new(protocol: SessionProtocol, peer_addr: SocketAddr) -> Arc<Self> {
+        Arc::new(Self {
+            id: Uuid::now_v7(),
+            protocol,
+            peer_addr,
+            started_at: Utc::now(),
+            details: Mutex::new(SessionDetails {
+                user: None,
+                tenant_id: None,
+                state: "connected",
+            }),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            terminate: CancellationToken::new(),
+        })
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Record the user the session authenticated as
+    pub fn set_user(&self, user: &str, tenant_id: Option<Uuid>) {
+        let mut details = self.details.lock().unwrap();
+        details.user = Some(user.to_string());
+        details.tenant_id = tenant_id;
+    }
+
+    /// Record the protocol state of the session
+    pub fn set_state(&self, state: &'static str) {
+        self.details.lock().unwrap().state = state;
+    }
+
+    /// Wrap a stream of the session so that its traffic is counted
+    pub fn meter<S>(self: &Arc<Self>, stream: S) -> Metered<S> {
+        Metered {
+            inner: stream,
+            session: self.clone(),
+        }
+    }
+
+    /// Close the session's connection
+    pub fn terminate(&self) {
+        self.terminate.cancel();
+    }
+
+    /// Run the session until it ends, or `None` when it is terminated first
+    pub async fn run<T>(&self, session: impl Future<Output = T>) -> Option<T> {
+        tokio::select! {
+            biased;
+            _ = self.terminate.cancelled() => None,
+            result = session => Some(result),
+        }
+    }
+
+    /// Snapshot of the session
+    pub fn info(&self) -> SessionInfo {
+        let details = self.details.lock().unwrap().clone();
+        SessionInfo {
+            id: self.id,
+            protocol: self.protocol,
+            peer_ip: self.peer_addr.ip(),
+            peer_port: self.peer_addr.port(),
+            user: details.user,
+            tenant_id: details.tenant_id,
+            state: details.state.to_string(),
+            started_at: self.started_at,
+            duration_secs: (Utc::now() - self.started_at).num_seconds(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of a live session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub protocol: SessionProtocol,
+    pub peer_ip: IpAddr,
+    pub peer_port: u16,
+    /// User the session authenticated as
+    pub user: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    /// Protocol state (e.g. "authenticated", "selected", "data")
+    pub state: String,
+    pub started_at: DateTime<Utc>,
+    pub duration_secs: i64,
+    /// Bytes received from the client
+    pub bytes_in: u64,
+    /// Bytes sent to the client
+    pub bytes_out: u64,
+}
+
+/// Sessions to list or terminate; empty fields match any session
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionFilter {
+    pub protocol: Option<SessionProtocol>,
+    /// User, case-insensitive
+    pub user: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub tenant_id: Option<Uuid>,
+}
+
+impl SessionFilter {
+    /// Whether the filter matches every session
+    pub fn is_empty(&self) -> bool {
+        self.protocol.is_none()
+            && self.user.is_none()
+            && self.ip.is_none()
+            && self.tenant_id.is_none()
+    }
+
+    pub fn matches(&self, info: &SessionInfo) -> bool {
+        let user_matches = match (&self.user, &info.user) {
+            (Some(user), Some(session_user)) => session_user.eq_ignore_ascii_case(user),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        user_matches
+            && !matches!(self.protocol, Some(protocol) if protocol != info.protocol)
+            && !matches!(self.ip, Some(ip) if ip != info.peer_ip)
+            && !matches!(self.tenant_id, Some(tenant_id) if info.tenant_id != Some(tenant_id))
+    }
+}
+
+/// Live sessions of all servers of this process
+///
+/// The default registry is empty and shared by nobody.
+#[derive(Debug, Clone, Default)]
+pub struct SessionRegistry {
+    sessions: Arc<Mutex<HashMap<Uuid, Arc<ActiveSession>>>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new connection; it stays listed until the registration
+    /// is dropped
+    pub fn register(&self, protocol: SessionProtocol, peer_addr: SocketAddr) -> Registration {
+        let session = ActiveSession::new(protocol, peer_addr);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id, session.clone());
+        Registration {
+            registry: self.clone(),
+            session,
+        }
+    }
+
+    /// Sessions matching `filter`, oldest first
+    pub fn list(&self, filter: &SessionFilter) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|session| session.info())
+            .filter(|info| filter.matches(info))
+            .collect();
+        sessions.sort_by_key(|info| (info.started_at, info.id));
+        sessions
+    }
+
+    /// Terminate a session; false when there is no such session
+    pub fn terminate(&self, id: Uuid) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(session) => {
+                session.terminate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Terminate the sessions matching `filter`; returns how many
+    pub fn terminate_matching(&self, filter: &SessionFilter) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let mut terminated = 0;
+        for session in sessions.values() {
+            if filter.matches(&session.info()) {
+                session.terminate();
+                terminated += 1;
+            }
+        }
+        terminated
+    }
+}
+
+/// Listing of a session in a registry, removed when dropped
+#[derive(Debug)]
+pub struct Registration {
+    registry: SessionRegistry,
+    session: Arc<ActiveSession>,
+}
+
+impl Registration {
+    pub fn session(&self) -> &Arc<ActiveSession> {
+        &self.session
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(&self.session.id);
+    }
+}
+
+/// Stream whose traffic is counted in a session
+#[derive(Debug)]
+pub struct Metered<S> {
+    inner: S,
+    session: Arc<ActiveSession>,
+}
+
+impl<S> Metered<S> {
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = (buf.filled().len() - filled) as u64;
+            this.session.bytes_in.fetch_add(read, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.session
+                .bytes_out
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn addr(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 40000)
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = SessionRegistry::new();
+        let imap = registry.register(SessionProtocol::Imap, addr("192.0.2.1"));
+        let smtp = registry.register(SessionProtocol::Smtp, addr("192.0.2.2"));
+        imap.session().set_user("alice@example.com", None);
+        imap.session().set_state("selected");
+
+        let sessions = registry.list(&SessionFilter::default());
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, imap.session().id());
+        assert_eq!(sessions[0].state, "selected");
+        assert_eq!(sessions[1].state, "connected");
+
+        let by_user = SessionFilter {
+            user: Some("Alice@Example.com".to_string()),
+            ..SessionFilter::default()
+        };
+        assert_eq!(registry.list(&by_user).len(), 1);
+        let by_ip = SessionFilter {
+            ip: Some("192.0.2.2".parse().unwrap()),
+            ..SessionFilter::default()
+        };
+        assert_eq!(registry.list(&by_ip)[0].protocol, SessionProtocol::Smtp);
+
+        assert_eq!(registry.terminate_matching(&by_user), 1);
+        assert!(imap.session().terminate.is_cancelled());
+        assert!(!smtp.session().terminate.is_cancelled());
+        assert!(!registry.terminate(Uuid::now_v7()));
+
+        drop(imap);
+        assert_eq!(registry.list(&SessionFilter::default()).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_terminated_session() {
+        let registry = SessionRegistry::new();
+        let registration = registry.register(SessionProtocol::Pop3, addr("192.0.2.1"));
+        let session = registration.session().clone();
+        assert!(registry.terminate(session.id()));
+        let result = session
+            .run(std::future::pending::<std::io::Result<()>>())
+            .await;
+        assert!(result.is_none());
+        assert_eq!(session.run(async { 42 }).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_metered() {
+        let session = ActiveSession::new(SessionProtocol::Smtp, addr("192.0.2.1"));
+        let (client, server) = tokio::io::duplex(64);
+        let mut server = session.meter(server);
+        let mut client = client;
+
+        client.write_all(b"EHLO client\r\n").await.unwrap();
+        let mut line = [0u8; 13];
+        server.read_exact(&mut line).await.unwrap();
+        server.write_all(b"250 OK\r\n").await.unwrap();
+
+        let info = session.info();
+        assert_eq!(info.bytes_in, 13);
+        assert_eq!(info.bytes_out, 8);
+    }
+}
@@ -0,0 +1,102 @@
+//! Read-only share links for messages
+//!
+//! A user can share one message through a link that works without signing
+//! in, to pass its context on to someone outside email. The shared page
+//! shows the message's header fields, its body as sanitized HTML and links
+//! to its attachments. Links expire, can be revoked at any time, and count
+//! their views; shares are stored by `MessageShareRepository`.
+
+use crate::print::{escape_html, PrintableMessage};
+use crate::sanitize::sanitize_html;
+use chrono::Duration;
+use mail_parser::{MessageParser, PartType};
+
+/// Lifetime of a share link when none is requested
+pub const DEFAULT_SHARE_HOURS: i64 = 72;
+
+/// Longest lifetime of a share link (30 days)
+pub const MAX_SHARE_HOURS: i64 = 24 * 30;
+
+/// Lifetime of a new share link; `None` when the requested number of hours
+/// is out of range
+pub fn share_lifetime(hours: Option<i64>) -> Option<Duration> {
+    let hours = hours.unwrap_or(DEFAULT_SHARE_HOURS);
+    (1..=MAX_SHARE_HOURS)
+        .contains(&hours)
+        .then(|| Duration::hours(hours))
+}
+
+/// A message as shown on its share page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedMessage {
+    /// Header fields, body text and attachments
+    pub message: PrintableMessage,
+    /// Body as sanitized HTML; a text body is preformatted
+    pub body_html: String,
+}
+
+impl SharedMessage {
+    /// Parse a raw message; `None` when it cannot be parsed
+    pub fn of_raw(raw: &[u8]) -> Option<Self> {
+        let parsed = MessageParser::default().parse(raw)?;
+        let message = PrintableMessage::of_parsed(&parsed);
+        let body_html = match parsed.html_part(0).map(|part| &part.body) {
+            Some(PartType::Html(html)) => sanitize_html(html),
+            _ => format!("<pre>{}</pre>", escape_html(&message.body)),
+        };
+        Some(Self { message, body_html })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_lifetime() {
+        assert_eq!(
+            share_lifetime(None),
+            Some(Duration::hours(DEFAULT_SHARE_HOURS))
+        );
+        assert_eq!(share_lifetime(Some(1)), Some(Duration::hours(1)));
+        assert_eq!(share_lifetime(Some(720)), Some(Duration::days(30)));
+        assert_eq!(share_lifetime(Some(721)), None);
+        assert_eq!(share_lifetime(Some(0)), None);
+        assert_eq!(share_lifetime(Some(-5)), None);
+    }
+
+    #[test]
+    fn test_html_body() {
+        let raw = b"From: Alice <alice@example.com>\r\n\
+To: bob@example.com\r\n\
+Subject: Plans\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/alternative; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+See you\r\n\
+--b\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p onclick=\"x()\">See <b>you</b></p><script>alert(1)</script>\r\n\
+--b--\r\n";
+        let shared = SharedMessage::of_raw(raw).unwrap();
+        assert_eq!(shared.message.subject.as_deref(), Some("Plans"));
+        assert_eq!(shared.body_html.trim(), "<p>See <b>you</b></p>");
+    }
+
+    #[test]
+    fn test_text_body() {
+        let raw = b"From: alice@example.com\r\n\
+Subject: Note\r\n\
+\r\n\
+1 < 2 & <b>not bold</b>\r\n";
+        let shared = SharedMessage::of_raw(raw).unwrap();
+        assert_eq!(
+            shared.body_html,
+            "<pre>1 &lt; 2 &amp; &lt;b&gt;not bold&lt;/b&gt;</pre>"
+        );
+    }
+}
@@ -0,0 +1,1052 @@
+//! Sieve script checking (RFC 5228)
+//!
+//! Scripts uploaded over ManageSieve are checked before they are stored:
+//! they have to parse, use only known commands, tests and tags with the
+//! right arguments, and `require` every extension they use. Supported
+//! extensions are listed in [`EXTENSIONS`] and announced to clients.
+//!
+//! Scripts are not run at delivery yet; this module only checks them.
+
+use std::collections::HashSet;
+
+/// Extensions scripts may require
+pub const EXTENSIONS: &[&str] = &[
+    "fileinto",
+    "reject",
+    "ereject",
+    "envelope",
+    "body",
+    "copy",
+    "imap4flags",
+    "variables",
+    "vacation",
+    "relational",
+    "subaddress",
+    "regex",
+    "encoded-character",
+    "comparator-i;ascii-numeric",
+];
+
+/// Why a script was refused
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}: {message}")]
+pub struct SieveError {
+    /// Line of the script the error was found on, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl SieveError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// Check a script
+pub fn validate(script: &str) -> Result<(), SieveError> {
+    let tokens = lex(script)?;
+    let end_line = script.matches('\n').count() + 1;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end_line,
+        required: HashSet::new(),
+    };
+    parser.commands(true)?;
+    match parser.peek() {
+        None => Ok(()),
+        Some(token) => Err(SieveError::new(
+            token.line,
+            format!("unexpected {}", token.kind.describe()),
+        )),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Identifier(String),
+    Tag(String),
+    Number(u64),
+    String(String),
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Semicolon,
+}
+
+impl TokenKind {
+    fn describe(&self) -> String {
+        match self {
+            TokenKind::Identifier(name) => format!("\"{}\"", name),
+            TokenKind::Tag(name) => format!("tag :{}", name),
+            TokenKind::Number(_) => "number".to_string(),
+            TokenKind::String(_) => "string".to_string(),
+            TokenKind::LeftBracket => "\"[\"".to_string(),
+            TokenKind::RightBracket => "\"]\"".to_string(),
+            TokenKind::LeftParen => "\"(\"".to_string(),
+            TokenKind::RightParen => "\")\"".to_string(),
+            TokenKind::LeftBrace => "\"{\"".to_string(),
+            TokenKind::RightBrace => "\"}\"".to_string(),
+            TokenKind::Comma => "\",\"".to_string(),
+            TokenKind::Semicolon => "\";\"".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+}
+
+/// Split a script into tokens, dropping comments
+fn lex(script: &str) -> Result<Vec<Token>, SieveError> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = line;
+        let kind = match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+            ' ' | '\t' | '\r' => {
+                i += 1;
+                continue;
+            }
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(SieveError::new(start, "unterminated comment")),
+                        Some('*') if chars.get(i + 1) == Some(&'/') => break,
+                        Some('\n') => line += 1,
+                        Some(_) => {}
+                    }
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            '[' => TokenKind::LeftBracket,
+            ']' => TokenKind::RightBracket,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '{' => TokenKind::LeftBrace,
+            '}' => TokenKind::RightBrace,
+            ',' => TokenKind::Comma,
+            ';' => TokenKind::Semicolon,
+            '"' => {
+                i += 1;
+                let mut value = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => return Err(SieveError::new(start, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            // Unknown escapes lose their backslash
+                            i += 1;
+                            match chars.get(i) {
+                                Some(&c) => value.push(c),
+                                None => return Err(SieveError::new(start, "unterminated string")),
+                            }
+                        }
+                        Some(&c) => value.push(c),
+                    }
+                    if chars[i] == '\n' {
+                        line += 1;
+                    }
+                    i += 1;
+                }
+                TokenKind::String(value)
+            }
+            ':' => {
+                let name = identifier(&chars, i + 1);
+                if name.is_empty() {
+                    return Err(SieveError::new(line, "tag without a name"));
+                }
+                i += name.len();
+                TokenKind::Tag(name.to_ascii_lowercase())
+            }
+            '0'..='9' => {
+                let mut value: u64 = 0;
+                let mut overflow = false;
+                while let Some(d) = chars.get(i).and_then(|c| c.to_digit(10)) {
+                    value = match value.checked_mul(10).and_then(|v| v.checked_add(d.into())) {
+                        Some(value) => value,
+                        None => {
+                            overflow = true;
+                            0
+                        }
+                    };
+                    i += 1;
+                }
+                let shift = match chars.get(i).map(|c| c.to_ascii_uppercase()) {
+                    Some('K') => 10,
+                    Some('M') => 20,
+                    Some('G') => 30,
+                    _ => 0,
+                };
+                if shift == 0 {
+                    i -= 1;
+                }
+                let value = value
+                    .checked_mul(1 << shift)
+                    .filter(|_| !overflow)
+                    .ok_or_else(|| SieveError::new(line, "number too large"))?;
+                TokenKind::Number(value)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let name = identifier(&chars, i);
+                i += name.len();
+                if name.eq_ignore_ascii_case("text") && chars.get(i) == Some(&':') {
+                    let (value, end, lines) = multiline(&chars, i + 1, start)?;
+                    i = end;
+                    line += lines;
+                    tokens.push(Token {
+                        kind: TokenKind::String(value),
+                        line: start,
+                    });
+                    continue;
+                }
+                i -= 1;
+                TokenKind::Identifier(name.to_ascii_lowercase())
+            }
+            c => {
+                return Err(SieveError::new(
+                    line,
+                    format!("unexpected character {:?}", c),
+                ))
+            }
+        };
+        tokens.push(Token { kind, line: start });
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// The identifier starting at `start`, empty if there is none
+fn identifier(chars: &[char], start: usize) -> String {
+    let mut name = String::new();
+    for (n, &c) in chars[start.min(chars.len())..].iter().enumerate() {
+        let valid = c.is_ascii_alphabetic() || c == '_' || (n > 0 && c.is_ascii_digit());
+        if !valid {
+            break;
+        }
+        name.push(c);
+    }
+    name
+}
+
+/// A `text:` string starting after the colon; returns its value, the
+/// index after its closing dot line and the number of lines it spans
+fn multiline(
+    chars: &[char],
+    mut i: usize,
+    line: usize,
+) -> Result<(String, usize, usize), SieveError> {
+    // Only blanks and a comment may follow `text:` on its line
+    while matches!(chars.get(i), Some(' ' | '\t')) {
+        i += 1;
+    }
+    match chars.get(i) {
+        Some('#') => {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        }
+        Some('\r') if chars.get(i + 1) == Some(&'\n') => i += 1,
+        Some('\n') => {}
+        _ => {
+            return Err(SieveError::new(
+                line,
+                "text: must be followed by a new line",
+            ))
+        }
+    }
+    i += 1;
+
+    let mut value = String::new();
+    let mut lines = 1;
+    loop {
+        if i >= chars.len() {
+            return Err(SieveError::new(line, "unterminated text: string"));
+        }
+        let end = chars[i..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(chars.len(), |n| i + n);
+        let text: String = chars[i..end].iter().collect();
+        let text = text.strip_suffix('\r').unwrap_or(&text);
+        i = end + 1;
+        lines += 1;
+        if text == "." {
+            return Ok((value, i, lines));
+        }
+        value.push_str(text.strip_prefix('.').unwrap_or(text));
+        value.push_str("\r\n");
+    }
+}
+
+/// Kinds of positional arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    /// A single string
+    String,
+    /// A string or a list of strings
+    StringList,
+}
+
+impl Kind {
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Number => "a number",
+            Kind::String => "a string",
+            Kind::StringList => "a string list",
+        }
+    }
+}
+
+struct TagSpec {
+    name: &'static str,
+    value: Option<Kind>,
+    /// Tags of a group exclude each other
+    group: Option<&'static str>,
+    extension: Option<&'static str>,
+}
+
+const fn tag(
+    name: &'static str,
+    value: Option<Kind>,
+    group: Option<&'static str>,
+    extension: Option<&'static str>,
+) -> TagSpec {
+    TagSpec {
+        name,
+        value,
+        group,
+        extension,
+    }
+}
+
+const COMPARATOR: &[TagSpec] = &[tag(
+    "comparator",
+    Some(Kind::String),
+    Some("comparator"),
+    None,
+)];
+
+const MATCH_TYPE: &[TagSpec] = &[
+    tag("is", None, Some("match type"), None),
+    tag("contains", None, Some("match type"), None),
+    tag("matches", None, Some("match type"), None),
+    tag("regex", None, Some("match type"), Some("regex")),
+    tag(
+        "count",
+        Some(Kind::String),
+        Some("match type"),
+        Some("relational"),
+    ),
+    tag(
+        "value",
+        Some(Kind::String),
+        Some("match type"),
+        Some("relational"),
+    ),
+];
+
+const ADDRESS_PART: &[TagSpec] = &[
+    tag("all", None, Some("address part"), None),
+    tag("localpart", None, Some("address part"), None),
+    tag("domain", None, Some("address part"), None),
+    tag("user", None, Some("address part"), Some("subaddress")),
+    tag("detail", None, Some("address part"), Some("subaddress")),
+];
+
+const BODY_TRANSFORM: &[TagSpec] = &[
+    tag("raw", None, Some("body transform"), None),
+    tag(
+        "content",
+        Some(Kind::StringList),
+        Some("body transform"),
+        None,
+    ),
+    tag("text", None, Some("body transform"), None),
+];
+
+const SIZE: &[TagSpec] = &[
+    tag("over", Some(Kind::Number), Some("size"), None),
+    tag("under", Some(Kind::Number), Some("size"), None),
+];
+
+const COPY: &[TagSpec] = &[tag("copy", None, None, Some("copy"))];
+
+const FLAGS: &[TagSpec] = &[tag(
+    "flags",
+    Some(Kind::StringList),
+    None,
+    Some("imap4flags"),
+)];
+
+const VACATION: &[TagSpec] = &[
+    tag("days", Some(Kind::Number), None, None),
+    tag("subject", Some(Kind::String), None, None),
+    tag("from", Some(Kind::String), None, None),
+    tag("addresses", Some(Kind::StringList), None, None),
+    tag("mime", None, None, None),
+    tag("handle", Some(Kind::String), None, None),
+];
+
+const MODIFIERS: &[TagSpec] = &[
+    tag("lower", None, None, None),
+    tag("upper", None, None, None),
+    tag("lowerfirst", None, None, None),
+    tag("upperfirst", None, None, None),
+    tag("quotewildcard", None, None, None),
+    tag("length", None, None, None),
+];
+
+/// Nested tests a test takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Nested {
+    None,
+    One,
+    List,
+}
+
+/// Arguments of an action or test
+struct Spec {
+    name: &'static str,
+    extension: Option<&'static str>,
+    tags: &'static [&'static [TagSpec]],
+    positional: &'static [Kind],
+    /// The first positional argument may be left out
+    optional_first: bool,
+    /// A tag of this group is required
+    required_group: Option<&'static str>,
+    nested: Nested,
+}
+
+const fn spec(
+    name: &'static str,
+    extension: Option<&'static str>,
+    tags: &'static [&'static [TagSpec]],
+    positional: &'static [Kind],
+) -> Spec {
+    Spec {
+        name,
+        extension,
+        tags,
+        positional,
+        optional_first: false,
+        required_group: None,
+        nested: Nested::None,
+    }
+}
+
+const ACTIONS: &[Spec] = &[
+    spec("keep", None, &[FLAGS], &[]),
+    spec("discard", None, &[], &[]),
+    spec("stop", None, &[], &[]),
+    spec("redirect", None, &[COPY], &[Kind::String]),
+    spec(
+        "fileinto",
+        Some("fileinto"),
+        &[COPY, FLAGS],
+        &[Kind::String],
+    ),
+    spec("reject", Some("reject"), &[], &[Kind::String]),
+    spec("ereject", Some("ereject"), &[], &[Kind::String]),
+    spec("vacation", Some("vacation"), &[VACATION], &[Kind::String]),
+    Spec {
+        optional_first: true,
+        ..spec(
+            "setflag",
+            Some("imap4flags"),
+            &[],
+            &[Kind::String, Kind::StringList],
+        )
+    },
+    Spec {
+        optional_first: true,
+        ..spec(
+            "addflag",
+            Some("imap4flags"),
+            &[],
+            &[Kind::String, Kind::StringList],
+        )
+    },
+    Spec {
+        optional_first: true,
+        ..spec(
+            "removeflag",
+            Some("imap4flags"),
+            &[],
+            &[Kind::String, Kind::StringList],
+        )
+    },
+    spec(
+        "set",
+        Some("variables"),
+        &[MODIFIERS],
+        &[Kind::String, Kind::String],
+    ),
+];
+
+const TESTS: &[Spec] = &[
+    spec(
+        "address",
+        None,
+        &[COMPARATOR, MATCH_TYPE, ADDRESS_PART],
+        &[Kind::StringList, Kind::StringList],
+    ),
+    spec(
+        "envelope",
+        Some("envelope"),
+        &[COMPARATOR, MATCH_TYPE, ADDRESS_PART],
+        &[Kind::StringList, Kind::StringList],
+    ),
+    spec(
+        "header",
+        None,
+        &[COMPARATOR, MATCH_TYPE],
+        &[Kind::StringList, Kind::StringList],
+    ),
+    spec("exists", None, &[], &[Kind::StringList]),
+    Spec {
+        required_group: Some("size"),
+        ..spec("size", None, &[SIZE], &[])
+    },
+    spec("true", None, &[], &[]),
+    spec("false", None, &[], &[]),
+    Spec {
+        nested: Nested::One,
+        ..spec("not", None, &[], &[])
+    },
+    Spec {
+        nested: Nested::List,
+        ..spec("allof", None, &[], &[])
+    },
+    Spec {
+        nested: Nested::List,
+        ..spec("anyof", None, &[], &[])
+    },
+    spec(
+        "body",
+        Some("body"),
+        &[COMPARATOR, MATCH_TYPE, BODY_TRANSFORM],
+        &[Kind::StringList],
+    ),
+    Spec {
+        optional_first: true,
+        ..spec(
+            "hasflag",
+            Some("imap4flags"),
+            &[COMPARATOR, MATCH_TYPE],
+            &[Kind::StringList, Kind::StringList],
+        )
+    },
+    spec(
+        "string",
+        Some("variables"),
+        &[COMPARATOR, MATCH_TYPE],
+        &[Kind::StringList, Kind::StringList],
+    ),
+];
+
+/// An argument as written
+#[derive(Debug)]
+enum Argument {
+    Tag(String),
+    Number,
+    Strings { values: Vec<String>, list: bool },
+}
+
+impl Argument {
+    fn is(&self, kind: Kind) -> bool {
+        match self {
+            Argument::Tag(_) => false,
+            Argument::Number => kind == Kind::Number,
+            Argument::Strings { list, .. } => {
+                kind == Kind::StringList || (kind == Kind::String && !list)
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    end_line: usize,
+    required: HashSet<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn line(&self) -> usize {
+        self.peek().map_or(self.end_line, |token| token.line)
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), SieveError> {
+        match self.next() {
+            Some(token) if token.kind == kind => Ok(()),
+            Some(token) => Err(SieveError::new(
+                token.line,
+                format!(
+                    "expected {} but found {}",
+                    kind.describe(),
+                    token.kind.describe()
+                ),
+            )),
+            None => Err(SieveError::new(
+                self.end_line,
+                format!("expected {} at the end of the script", kind.describe()),
+            )),
+        }
+    }
+
+    fn take(&mut self, kind: &TokenKind) -> bool {
+        let found = self.peek().is_some_and(|token| &token.kind == kind);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Commands up to the end of the script or block
+    fn commands(&mut self, top_level: bool) -> Result<(), SieveError> {
+        let mut require_allowed = top_level;
+        let mut after_if = false;
+        while let Some(token) = self.peek() {
+            let line = token.line;
+            let name = match &token.kind {
+                TokenKind::RightBrace if !top_level => return Ok(()),
+                TokenKind::Identifier(name) => name.clone(),
+                other => {
+                    return Err(SieveError::new(
+                        line,
+                        format!("expected a command but found {}", other.describe()),
+                    ))
+                }
+            };
+            self.pos += 1;
+
+            match name.as_str() {
+                "require" => {
+                    if !require_allowed {
+                        return Err(SieveError::new(
+                            line,
+                            "require must come before other commands",
+                        ));
+                    }
+                    self.require(line)?;
+                }
+                "if" | "elsif" | "else" => {
+                    if name != "if" && !after_if {
+                        return Err(SieveError::new(
+                            line,
+                            format!("{} without a preceding if", name),
+                        ));
+                    }
+                    if name != "else" {
+                        self.test()?;
+                    }
+                    self.block()?;
+                    require_allowed = false;
+                    after_if = name != "else";
+                    continue;
+                }
+                _ => {
+                    let Some(spec) = ACTIONS.iter().find(|spec| spec.name == name) else {
+                        return Err(SieveError::new(line, format!("unknown command {}", name)));
+                    };
+                    let arguments = self.arguments()?;
+                    self.check(spec, &arguments, line)?;
+                    if matches!(
+                        self.peek().map(|token| &token.kind),
+                        Some(TokenKind::Identifier(_) | TokenKind::LeftParen)
+                    ) {
+                        return Err(SieveError::new(
+                            line,
+                            format!("{} does not take a test", name),
+                        ));
+                    }
+                    self.expect(TokenKind::Semicolon)?;
+                    require_allowed = false;
+                }
+            }
+            after_if = false;
+        }
+        if top_level {
+            Ok(())
+        } else {
+            Err(SieveError::new(self.end_line, "unterminated block"))
+        }
+    }
+
+    fn require(&mut self, line: usize) -> Result<(), SieveError> {
+        let arguments = self.arguments()?;
+        let [Argument::Strings { values, .. }] = arguments.as_slice() else {
+            return Err(SieveError::new(line, "require takes a string list"));
+        };
+        for value in values {
+            let extension = value.to_ascii_lowercase();
+            if !EXTENSIONS.contains(&extension.as_str()) {
+                return Err(SieveError::new(
+                    line,
+                    format!("unsupported extension \"{}\"", value),
+                ));
+            }
+            self.required.insert(extension);
+        }
+        self.expect(TokenKind::Semicolon)
+    }
+
+    fn block(&mut self) -> Result<(), SieveError> {
+        self.expect(TokenKind::LeftBrace)?;
+        self.commands(false)?;
+        self.expect(TokenKind::RightBrace)
+    }
+
+    fn test(&mut self) -> Result<(), SieveError> {
+        let line = self.line();
+        let name = match self.next() {
+            Some(Token {
+                kind: TokenKind::Identifier(name),
+                ..
+            }) => name,
+            Some(token) => {
+                return Err(SieveError::new(
+                    token.line,
+                    format!("expected a test but found {}", token.kind.describe()),
+                ))
+            }
+            None => return Err(SieveError::new(self.end_line, "expected a test")),
+        };
+        let Some(spec) = TESTS.iter().find(|spec| spec.name == name) else {
+            return Err(SieveError::new(line, format!("unknown test {}", name)));
+        };
+        let arguments = self.arguments()?;
+        self.check(spec, &arguments, line)?;
+        match spec.nested {
+            Nested::None => {}
+            Nested::One => {
+                self.test()?;
+            }
+            Nested::List => {
+                self.expect(TokenKind::LeftParen)?;
+                loop {
+                    self.test()?;
+                    if !self.take(&TokenKind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(TokenKind::RightParen)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tags, numbers and strings up to a test, block or semicolon
+    fn arguments(&mut self) -> Result<Vec<Argument>, SieveError> {
+        let mut arguments = Vec::new();
+        loop {
+            let Some(token) = self.peek() else {
+                return Ok(arguments);
+            };
+            let argument = match &token.kind {
+                TokenKind::Tag(name) => Argument::Tag(name.clone()),
+                TokenKind::Number(_) => Argument::Number,
+                TokenKind::String(value) => Argument::Strings {
+                    values: vec![value.clone()],
+                    list: false,
+                },
+                TokenKind::LeftBracket => {
+                    self.pos += 1;
+                    let mut values = Vec::new();
+                    loop {
+                        match self.next() {
+                            Some(Token {
+                                kind: TokenKind::String(value),
+                                ..
+                            }) => values.push(value),
+                            Some(token) => {
+                                return Err(SieveError::new(
+                                    token.line,
+                                    format!(
+                                        "expected a string but found {}",
+                                        token.kind.describe()
+                                    ),
+                                ))
+                            }
+                            None => {
+                                return Err(SieveError::new(
+                                    self.end_line,
+                                    "unterminated string list",
+                                ))
+                            }
+                        }
+                        if !self.take(&TokenKind::Comma) {
+                            break;
+                        }
+                    }
+                    self.expect(TokenKind::RightBracket)?;
+                    arguments.push(Argument::Strings { values, list: true });
+                    continue;
+                }
+                _ => return Ok(arguments),
+            };
+            self.pos += 1;
+            arguments.push(argument);
+        }
+    }
+
+    /// Check the arguments of an action or test against its spec
+    fn check(&self, spec: &Spec, arguments: &[Argument], line: usize) -> Result<(), SieveError> {
+        if let Some(extension) = spec.extension {
+            self.check_required(extension, spec.name, line)?;
+        }
+
+        let mut groups = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut positional = Vec::new();
+        let mut iter = arguments.iter();
+        while let Some(argument) = iter.next() {
+            let Argument::Tag(name) = argument else {
+                positional.push(argument);
+                continue;
+            };
+            if !positional.is_empty() {
+                return Err(SieveError::new(
+                    line,
+                    format!("tag :{} of {} after its arguments", name, spec.name),
+                ));
+            }
+            let Some(tag) = spec
+                .tags
+                .iter()
+                .flat_map(|tags| tags.iter())
+                .find(|tag| tag.name == name)
+            else {
+                return Err(SieveError::new(
+                    line,
+                    format!("unknown tag :{} for {}", name, spec.name),
+                ));
+            };
+            if let Some(extension) = tag.extension {
+                self.check_required(extension, &format!(":{}", name), line)?;
+            }
+            if !seen.insert(tag.name) {
+                return Err(SieveError::new(line, format!("duplicate tag :{}", name)));
+            }
+            if let Some(group) = tag.group {
+                if !groups.insert(group) {
+                    return Err(SieveError::new(
+                        line,
+                        format!("more than one {} for {}", group, spec.name),
+                    ));
+                }
+            }
+            if let Some(kind) = tag.value {
+                match iter.next() {
+                    Some(value) if value.is(kind) => self.check_tag_value(name, value, line)?,
+                    _ => {
+                        return Err(SieveError::new(
+                            line,
+                            format!("tag :{} takes {}", name, kind.describe()),
+                        ))
+                    }
+                }
+            }
+        }
+
+        if let Some(group) = spec.required_group {
+            if !groups.contains(group) {
+                return Err(SieveError::new(
+                    line,
+                    format!("{} requires a {} tag", spec.name, group),
+                ));
+            }
+        }
+
+        let expected = if spec.optional_first && positional.len() + 1 == spec.positional.len() {
+            &spec.positional[1..]
+        } else {
+            spec.positional
+        };
+        if positional.len() != expected.len() {
+            return Err(SieveError::new(
+                line,
+                format!(
+                    "{} takes {} argument{} but was given {}",
+                    spec.name,
+                    expected.len(),
+                    if expected.len() == 1 { "" } else { "s" },
+                    positional.len()
+                ),
+            ));
+        }
+        for (argument, &kind) in positional.iter().zip(expected) {
+            if !argument.is(kind) {
+                return Err(SieveError::new(
+                    line,
+                    format!("{} expects {}", spec.name, kind.describe()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the values of comparator and relational tags
+    fn check_tag_value(&self, name: &str, value: &Argument, line: usize) -> Result<(), SieveError> {
+        let Argument::Strings { values, .. } = value else {
+            return Ok(());
+        };
+        let value = values[0].to_ascii_lowercase();
+        match name {
+            "comparator" => match value.as_str() {
+                "i;octet" | "i;ascii-casemap" => Ok(()),
+                "i;ascii-numeric" => {
+                    self.check_required("comparator-i;ascii-numeric", "i;ascii-numeric", line)
+                }
+                _ => Err(SieveError::new(
+                    line,
+                    format!("unknown comparator \"{}\"", values[0]),
+                )),
+            },
+            "count" | "value" => match value.as_str() {
+                "gt" | "ge" | "lt" | "le" | "eq" | "ne" => Ok(()),
+                _ => Err(SieveError::new(
+                    line,
+                    format!("unknown relational operator \"{}\"", values[0]),
+                )),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn check_required(&self, extension: &str, used: &str, line: usize) -> Result<(), SieveError> {
+        if self.required.contains(extension) {
+            Ok(())
+        } else {
+            Err(SieveError::new(
+                line,
+                format!("{} requires the \"{}\" extension", used, extension),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_scripts() {
+        assert!(validate("").is_ok());
+        assert!(validate("# nothing to do\nkeep;").is_ok());
+        let script = r#"
+            require ["fileinto", "imap4flags", "copy", "relational",
+                     "comparator-i;ascii-numeric"];
+            /* file lists */
+            if header :contains "List-Id" "rust" {
+                fileinto :copy "Lists/Rust";
+            } elsif anyof (size :over 1M,
+                           address :domain :is "from" ["example.com", "example.org"]) {
+                addflag "\\Flagged";
+                stop;
+            } elsif header :value "ge" :comparator "i;ascii-numeric" "X-Spam-Score" "5" {
+                fileinto :flags ["\\Seen"] "Junk";
+            } else {
+                keep;
+            }
+        "#;
+        assert_eq!(validate(script), Ok(()));
+
+        let vacation = "require \"vacation\";\n\
+                        vacation :days 7 :subject \"Away\" text:\n\
+                        Back soon.\n..and later\n.\n;\n";
+        assert_eq!(validate(vacation), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_scripts() {
+        let err = validate("fileinto \"Junk\";").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(err.message.contains("\"fileinto\" extension"));
+
+        let err = validate("keep;\nrequire \"fileinto\";").unwrap_err();
+        assert_eq!(err.line, 2);
+
+        let err = validate("require \"enotify\";").unwrap_err();
+        assert!(err.message.contains("unsupported extension"));
+
+        assert!(validate("keep").is_err());
+        assert!(validate("frobnicate;").is_err());
+        assert!(validate("if true { keep;").is_err());
+        assert!(validate("else { keep; }").is_err());
+        assert!(validate("if size 10 { keep; }").is_err());
+        assert!(validate("if header :is :contains \"a\" \"b\" { keep; }").is_err());
+        assert!(validate("if header :is \"a\" { keep; }").is_err());
+        assert!(validate("redirect [\"a@example.com\"];").is_err());
+        assert!(validate("if exists \"x\" keep;").is_err());
+        assert!(validate("keep;\n\n\"unterminated").unwrap_err().line == 3);
+        assert!(validate("if address :user \"to\" \"a\" { keep; }").is_err());
+    }
+
+    #[test]
+    fn test_lex() {
+        let tokens = lex("a :Is 2K \"q\\\"\\x\" [ ] ;").unwrap();
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Identifier("a".to_string()),
+                TokenKind::Tag("is".to_string()),
+                TokenKind::Number(2048),
+                TokenKind::String("q\"x".to_string()),
+                TokenKind::LeftBracket,
+                TokenKind::RightBracket,
+                TokenKind::Semicolon,
+            ]
+        );
+
+        let tokens = lex("text: # note\r\nline\r\n..dot\r\n.\r\n;").unwrap();
+        assert_eq!(
+            tokens[0].kind,
+            TokenKind::String("line\r\n.dot\r\n".to_string())
+        );
+        assert_eq!(tokens[1].line, 5);
+        assert!(lex("/* open").is_err());
+        assert!(lex("99999999999999999999").is_err());
+    }
+}
@@ -110,8 +110,8 @@ impl SmtpAuthenticator {
         self.verify_credentials(&username, &password).await
     }
 
-    /// Verify credentials against the database
-    async fn verify_credentials(&self, email: &str, password: &str) -> AuthResult {
+    /// Verify credentials against the database (also used by POP3 AUTH)
+    pub(crate) async fn verify_credentials(&self, email: &str, password: &str) -> AuthResult {
         let user_repo = DbUserRepository::new(self.db_pool.clone());
 
         // Find user by email
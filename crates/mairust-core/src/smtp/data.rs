@@ -0,0 +1,228 @@
+//! Reading of message data after DATA (RFC 5321 section 4.5.2)
+//!
+//! Message data is read as bytes and stored as received: bodies need not be
+//! UTF-8 and line endings are kept, so DKIM signatures of stored messages
+//! can still be verified. Only `<CRLF>.<CRLF>` ends the data; a dot line
+//! ended by a bare LF does not, so a message cannot smuggle a second one
+//! past the end of data. A leading dot is removed from lines that start
+//! with one.
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Message data that was read to its end but is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum DataError {
+    /// The message is larger than the maximum message size
+    #[error("message too large")]
+    TooLarge,
+    /// A line is longer than the maximum line length
+    #[error("message line too long")]
+    LineTooLong,
+}
+
+impl DataError {
+    /// SMTP reply code and text
+    pub fn reply(&self) -> (u16, &'static str) {
+        match self {
+            DataError::TooLarge => (552, "5.3.4 Message size exceeds fixed maximum message size"),
+            DataError::LineTooLong => (500, "5.5.2 Message line too long"),
+        }
+    }
+}
+
+/// Where the scanner is within a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    /// At the start of a line
+    LineStart,
+    /// After a dot at the start of a line
+    Dot,
+    /// After a dot and CR at the start of a line
+    DotCr,
+    /// Within a line
+    Text,
+    /// After a CR within a line
+    Cr,
+}
+
+/// Scans message data for its end, removing dot-stuffing
+pub struct DataScanner {
+    data: Vec<u8>,
+    state: ScanState,
+    line_length: usize,
+    max_size: usize,
+    max_line_length: usize,
+    error: Option<DataError>,
+}
+
+impl DataScanner {
+    /// Create a scanner for messages of up to `max_size` bytes with lines of
+    /// up to `max_line_length` bytes, not counting the line ending
+    pub fn new(max_size: usize, max_line_length: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            state: ScanState::LineStart,
+            line_length: 0,
+            max_size,
+            max_line_length,
+            error: None,
+        }
+    }
+
+    /// Scan received bytes. Returns how many were part of the message data,
+    /// and whether they include its end; bytes after the end are left for
+    /// the next command.
+    pub fn feed(&mut self, input: &[u8]) -> (usize, bool) {
+        for (i, &byte) in input.iter().enumerate() {
+            match self.state {
+                ScanState::LineStart if byte == b'.' => self.state = ScanState::Dot,
+                ScanState::Dot if byte == b'\r' => self.state = ScanState::DotCr,
+                ScanState::DotCr if byte == b'\n' => return (i + 1, true),
+                ScanState::DotCr => {
+                    // ".\r" not followed by LF: the dot was stuffing
+                    self.state = ScanState::Text;
+                    self.text(b'\r');
+                    self.text(byte);
+                }
+                _ => self.text(byte),
+            }
+        }
+        (input.len(), false)
+    }
+
+    /// The message data, once its end has been scanned
+    pub fn finish(self) -> Result<Vec<u8>, DataError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.data),
+        }
+    }
+
+    /// Take a byte of line content or line ending
+    fn text(&mut self, byte: u8) {
+        self.state = match (self.state, byte) {
+            (ScanState::Cr, b'\n') => ScanState::LineStart,
+            (_, b'\r') => ScanState::Cr,
+            _ => ScanState::Text,
+        };
+        if byte == b'\n' {
+            self.line_length = 0;
+        } else if byte != b'\r' {
+            self.line_length += 1;
+            if self.line_length > self.max_line_length {
+                self.error.get_or_insert(DataError::LineTooLong);
+            }
+        }
+
+        // Refused messages are still read to their end, but not kept
+        if self.error.is_some() {
+            return;
+        }
+        if self.data.len() >= self.max_size {
+            self.error = Some(DataError::TooLarge);
+            self.data = Vec::new();
+            return;
+        }
+        self.data.push(byte);
+    }
+}
+
+/// Read message data up to `<CRLF>.<CRLF>`
+///
+/// A message over the size or line length limit is read to its end and
+/// then refused with a [`DataError`], so the session stays in sync.
+pub async fn read_data<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+    max_line_length: usize,
+) -> Result<Vec<u8>> {
+    let mut scanner = DataScanner::new(max_size, max_line_length);
+    loop {
+        let input = reader.fill_buf().await?;
+        if input.is_empty() {
+            return Err(anyhow!("Connection closed during DATA"));
+        }
+        let (consumed, done) = scanner.feed(input);
+        reader.consume(consumed);
+        if done {
+            return Ok(scanner.finish()?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(input: &[u8]) -> (Result<Vec<u8>, DataError>, usize) {
+        let mut scanner = DataScanner::new(100, 20);
+        let (consumed, done) = scanner.feed(input);
+        assert!(done);
+        (scanner.finish(), consumed)
+    }
+
+    #[test]
+    fn test_end_of_data() {
+        let (data, consumed) = scan(b"Subject: hi\r\n\r\nbody\r\n.\r\nQUIT\r\n");
+        assert_eq!(data.unwrap(), b"Subject: hi\r\n\r\nbody\r\n");
+        assert_eq!(consumed, 24);
+
+        // Empty message
+        assert_eq!(scan(b".\r\n").0.unwrap(), b"");
+
+        // Dot lines ended by a bare LF do not end the data
+        let mut scanner = DataScanner::new(100, 20);
+        assert_eq!(scanner.feed(b"a\n.\nb\r\n.\nc\n.\r\n"), (14, false));
+        assert_eq!(scanner.feed(b"\r\n.\r\n"), (5, true));
+        assert_eq!(scanner.finish().unwrap(), b"a\n.\nb\r\n\nc\n.\r\n\r\n");
+    }
+
+    #[test]
+    fn test_dot_stuffing() {
+        let (data, _) = scan(b"..\r\n..leading\r\n.x\r\nmid.dle\r\n.\r\r\n.\r\n");
+        assert_eq!(data.unwrap(), b".\r\n.leading\r\nx\r\nmid.dle\r\n\r\r\n");
+    }
+
+    #[test]
+    fn test_split_input() {
+        let input: &[u8] = b"caf\xc3\xa9 \xff\r\n..x\r\n.\r\n";
+        let mut scanner = DataScanner::new(100, 20);
+        for (i, byte) in input.iter().enumerate() {
+            let (consumed, done) = scanner.feed(std::slice::from_ref(byte));
+            assert_eq!(consumed, 1);
+            assert_eq!(done, i == input.len() - 1);
+        }
+        assert_eq!(scanner.finish().unwrap(), b"caf\xc3\xa9 \xff\r\n.x\r\n");
+    }
+
+    #[test]
+    fn test_limits() {
+        let line = [b'a'; 20];
+        let mut input = line.to_vec();
+        input.extend_from_slice(b"\r\n.\r\n");
+        assert_eq!(scan(&input).0.unwrap().len(), 22);
+
+        input.insert(0, b'a');
+        assert_eq!(scan(&input), (Err(DataError::LineTooLong), 26));
+
+        let mut input = Vec::new();
+        for _ in 0..10 {
+            input.extend_from_slice(&line[..10]);
+            input.extend_from_slice(b"\r\n");
+        }
+        input.extend_from_slice(b".\r\n");
+        assert_eq!(scan(&input), (Err(DataError::TooLarge), 123));
+    }
+
+    #[tokio::test]
+    async fn test_read_data() {
+        let mut reader = &b"Subject: hi\r\n\r\nbody\r\n.\r\nQUIT\r\n"[..];
+        let data = read_data(&mut reader, 100, 20).await.unwrap();
+        assert_eq!(data, b"Subject: hi\r\n\r\nbody\r\n");
+        assert_eq!(reader, b"QUIT\r\n");
+
+        let mut reader = &b"body\r\n"[..];
+        assert!(read_data(&mut reader, 100, 20).await.is_err());
+    }
+}
@@ -0,0 +1,410 @@
+//! Greylisting
+//!
+//! Mail from unauthenticated clients is deferred at RCPT TO the first time a
+//! (client network, sender, recipient) triplet is seen. Legitimate servers
+//! retry, and a retry after the configured delay passes the triplet for
+//! good; most spam software does not retry. Client networks are the /24 of
+//! IPv4 addresses and the /64 of IPv6 addresses, so retries from another
+//! host of the same sending cluster count.
+//!
+//! Clients are not greylisted when their network has passed enough
+//! triplets (auto-whitelisting) or the sender passes SPF for them.
+//!
+//! Messages the spam filter soft-rejects are greylisted after DATA with the
+//! digest of the message in place of the recipient, so that the same
+//! message is accepted when it is retried after the delay.
+
+use crate::security::login_source;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use mairust_common::config::GreylistConfig;
+use mairust_storage::db::DatabasePool;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+/// How often expired entries are deleted
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Outcome of a greylisting check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GreylistVerdict {
+    /// Accept
+    Pass,
+    /// Defer with a 451; the client may retry after this many seconds
+    Defer(i64),
+}
+
+impl GreylistVerdict {
+    /// Text of the 451 reply for a deferral
+    pub fn reply(retry_after: i64) -> String {
+        format!(
+            "4.7.1 Greylisted, please try again in {} seconds",
+            retry_after.max(1)
+        )
+    }
+}
+
+/// A message deferred because the spam filter soft-rejected it
+#[derive(Debug, thiserror::Error)]
+#[error("message greylisted, retry after {0} seconds")]
+pub struct GreylistDeferral(pub i64);
+
+/// Stored state of a triplet
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct Triplet {
+    first_seen_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    passed_at: Option<DateTime<Utc>>,
+}
+
+/// What an attempt does to its triplet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    /// The triplet passed before
+    Pass,
+    /// A retry after the delay; the triplet passes from now on
+    FirstPass,
+    /// Too early; retry after this many seconds
+    Defer(i64),
+    /// New, expired or retried too late; the delay starts now
+    Start,
+}
+
+/// Decide an attempt at `now` for a triplet in its stored state
+fn decide(triplet: Option<&Triplet>, now: DateTime<Utc>, config: &GreylistConfig) -> Decision {
+    let delay = Duration::seconds(config.delay_secs as i64);
+    let retry_window = Duration::seconds(config.retry_window_secs as i64);
+    let expiry = Duration::seconds(config.expiry_secs as i64);
+
+    let Some(triplet) = triplet else {
+        return Decision::Start;
+    };
+    if triplet.passed_at.is_some() {
+        return if now - triplet.last_seen_at <= expiry {
+            Decision::Pass
+        } else {
+            Decision::Start
+        };
+    }
+
+    let waited = now - triplet.first_seen_at;
+    if waited > retry_window {
+        Decision::Start
+    } else if waited < delay {
+        Decision::Defer((delay - waited).num_seconds())
+    } else {
+        Decision::FirstPass
+    }
+}
+
+/// Triplet key of a message the spam filter soft-rejected
+fn message_key(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(data)))
+}
+
+/// Greylisting state, shared by the sessions of an SMTP server
+pub struct Greylist {
+    db_pool: DatabasePool,
+    config: GreylistConfig,
+    last_purge: Mutex<Option<Instant>>,
+}
+
+impl Greylist {
+    /// Create a greylist
+    pub fn new(db_pool: DatabasePool, config: GreylistConfig) -> Self {
+        Self {
+            db_pool,
+            config,
+            last_purge: Mutex::new(None),
+        }
+    }
+
+    /// Whether recipients of unauthenticated sessions are greylisted
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Check a recipient of an unauthenticated session
+    ///
+    /// `spf_pass` is only awaited when the triplet has not passed before.
+    pub async fn check<F>(
+        &self,
+        client_ip: IpAddr,
+        sender: &str,
+        recipient: &str,
+        spf_pass: F,
+    ) -> Result<GreylistVerdict>
+    where
+        F: Future<Output = bool>,
+    {
+        let network = login_source(client_ip);
+        if self.is_whitelisted(&network).await? {
+            return Ok(GreylistVerdict::Pass);
+        }
+
+        let now = Utc::now();
+        let triplet = self.triplet(&network, sender, recipient).await?;
+        let decision = decide(triplet.as_ref(), now, &self.config);
+        if decision != Decision::Pass && spf_pass.await {
+            debug!("Not greylisting {} from {}: SPF pass", sender, network);
+            return Ok(GreylistVerdict::Pass);
+        }
+
+        self.record(&network, sender, recipient, decision, now)
+            .await
+    }
+
+    /// Check a message the spam filter soft-rejected, whatever the
+    /// client's history
+    pub async fn check_message(
+        &self,
+        client_ip: IpAddr,
+        sender: &str,
+        data: &[u8],
+    ) -> Result<GreylistVerdict> {
+        let network = login_source(client_ip);
+        let key = message_key(data);
+        let now = Utc::now();
+        let triplet = self.triplet(&network, sender, &key).await?;
+        let decision = decide(triplet.as_ref(), now, &self.config);
+        self.record(&network, sender, &key, decision, now).await
+    }
+
+    /// Whether a client network passed enough triplets recently
+    async fn is_whitelisted(&self, network: &str) -> Result<bool> {
+        if self.config.auto_whitelist_after == 0 {
+            return Ok(false);
+        }
+        let whitelisted: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM greylist_clients
+                WHERE client_network = $1 AND passed >= $2
+                  AND last_passed_at > NOW() - make_interval(secs => $3)
+            )
+            "#,
+        )
+        .bind(network)
+        .bind(self.config.auto_whitelist_after as i32)
+        .bind(self.config.expiry_secs as f64)
+        .fetch_one(self.db_pool.pool())
+        .await?;
+        Ok(whitelisted)
+    }
+
+    /// Stored state of a triplet
+    async fn triplet(
+        &self,
+        network: &str,
+        sender: &str,
+        recipient: &str,
+    ) -> Result<Option<Triplet>> {
+        let triplet = sqlx::query_as::<_, Triplet>(
+            r#"
+            SELECT first_seen_at, last_seen_at, passed_at
+            FROM greylist_triplets
+            WHERE client_network = $1 AND sender = $2 AND recipient = $3
+            "#,
+        )
+        .bind(network)
+        .bind(sender)
+        .bind(recipient)
+        .fetch_optional(self.db_pool.pool())
+        .await?;
+        Ok(triplet)
+    }
+
+    /// Store the outcome of an attempt and turn it into a verdict
+    async fn record(
+        &self,
+        network: &str,
+        sender: &str,
+        recipient: &str,
+        decision: Decision,
+        now: DateTime<Utc>,
+    ) -> Result<GreylistVerdict> {
+        let pool = self.db_pool.pool();
+        match decision {
+            Decision::Start => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO greylist_triplets
+                        (client_network, sender, recipient, first_seen_at, last_seen_at)
+                    VALUES ($1, $2, $3, $4, $4)
+                    ON CONFLICT (client_network, sender, recipient) DO UPDATE SET
+                        first_seen_at = EXCLUDED.first_seen_at,
+                        last_seen_at = EXCLUDED.last_seen_at,
+                        passed_at = NULL,
+                        attempts = 1
+                    "#,
+                )
+                .bind(network)
+                .bind(sender)
+                .bind(recipient)
+                .bind(now)
+                .execute(pool)
+                .await?;
+                self.purge_expired().await;
+
+                info!("Greylisted {} -> {} from {}", sender, recipient, network);
+                Ok(GreylistVerdict::Defer(self.config.delay_secs as i64))
+            }
+            Decision::Defer(retry_after) => {
+                sqlx::query(
+                    r#"
+                    UPDATE greylist_triplets
+                    SET last_seen_at = $4, attempts = attempts + 1
+                    WHERE client_network = $1 AND sender = $2 AND recipient = $3
+                    "#,
+                )
+                .bind(network)
+                .bind(sender)
+                .bind(recipient)
+                .bind(now)
+                .execute(pool)
+                .await?;
+                Ok(GreylistVerdict::Defer(retry_after))
+            }
+            Decision::FirstPass | Decision::Pass => {
+                let first = decision == Decision::FirstPass;
+                sqlx::query(
+                    r#"
+                    UPDATE greylist_triplets
+                    SET last_seen_at = $4, attempts = attempts + 1,
+                        passed_at = COALESCE(passed_at, $4)
+                    WHERE client_network = $1 AND sender = $2 AND recipient = $3
+                    "#,
+                )
+                .bind(network)
+                .bind(sender)
+                .bind(recipient)
+                .bind(now)
+                .execute(pool)
+                .await?;
+
+                if first {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO greylist_clients (client_network, passed, last_passed_at)
+                        VALUES ($1, 1, $2)
+                        ON CONFLICT (client_network) DO UPDATE SET
+                            passed = greylist_clients.passed + 1,
+                            last_passed_at = EXCLUDED.last_passed_at
+                        "#,
+                    )
+                    .bind(network)
+                    .bind(now)
+                    .execute(pool)
+                    .await?;
+                    debug!("Greylisting passed for {} -> {}", sender, recipient);
+                }
+                Ok(GreylistVerdict::Pass)
+            }
+        }
+    }
+
+    /// Delete triplets and clients that expired, at most once per interval
+    async fn purge_expired(&self) {
+        {
+            let mut last_purge = self.last_purge.lock().unwrap();
+            if last_purge.is_some_and(|at| at.elapsed() < PURGE_INTERVAL) {
+                return;
+            }
+            *last_purge = Some(Instant::now());
+        }
+
+        let result = sqlx::query(
+            r#"
+            WITH triplets AS (
+                DELETE FROM greylist_triplets
+                WHERE (passed_at IS NULL AND first_seen_at < NOW() - make_interval(secs => $1))
+                   OR last_seen_at < NOW() - make_interval(secs => $2)
+            )
+            DELETE FROM greylist_clients
+            WHERE last_passed_at < NOW() - make_interval(secs => $2)
+            "#,
+        )
+        .bind(self.config.retry_window_secs as f64)
+        .bind(self.config.expiry_secs as f64)
+        .execute(self.db_pool.pool())
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to purge greylist: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GreylistConfig {
+        GreylistConfig {
+            enabled: true,
+            delay_secs: 300,
+            retry_window_secs: 4 * 3600,
+            expiry_secs: 35 * 86400,
+            auto_whitelist_after: 5,
+        }
+    }
+
+    fn triplet(first_seen_secs_ago: i64, passed: bool, now: DateTime<Utc>) -> Triplet {
+        let first_seen_at = now - Duration::seconds(first_seen_secs_ago);
+        Triplet {
+            first_seen_at,
+            last_seen_at: first_seen_at,
+            passed_at: passed.then_some(first_seen_at),
+        }
+    }
+
+    #[test]
+    fn test_new_triplet_is_deferred() {
+        assert_eq!(decide(None, Utc::now(), &config()), Decision::Start);
+    }
+
+    #[test]
+    fn test_retry_before_delay_is_deferred() {
+        let now = Utc::now();
+        let early = triplet(60, false, now);
+        assert_eq!(decide(Some(&early), now, &config()), Decision::Defer(240));
+    }
+
+    #[test]
+    fn test_retry_after_delay_passes() {
+        let now = Utc::now();
+        let retry = triplet(600, false, now);
+        assert_eq!(decide(Some(&retry), now, &config()), Decision::FirstPass);
+
+        let passed = triplet(86400, true, now);
+        assert_eq!(decide(Some(&passed), now, &config()), Decision::Pass);
+    }
+
+    #[test]
+    fn test_late_or_expired_triplets_start_over() {
+        let now = Utc::now();
+        let late = triplet(5 * 3600, false, now);
+        assert_eq!(decide(Some(&late), now, &config()), Decision::Start);
+
+        let expired = triplet(40 * 86400, true, now);
+        assert_eq!(decide(Some(&expired), now, &config()), Decision::Start);
+    }
+
+    #[test]
+    fn test_reply() {
+        assert_eq!(
+            GreylistVerdict::reply(300),
+            "4.7.1 Greylisted, please try again in 300 seconds"
+        );
+    }
+
+    #[test]
+    fn test_message_key() {
+        assert_eq!(message_key(b"data"), message_key(b"data"));
+        assert_ne!(message_key(b"data"), message_key(b"other"));
+        assert!(message_key(b"data").starts_with("sha256:"));
+    }
+}
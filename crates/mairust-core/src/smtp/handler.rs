@@ -1,20 +1,73 @@
 //! SMTP session handler
 
+use crate::antivirus::{Antivirus, AntivirusRejection, ScanVerdict, VIRUS_TAG};
+use crate::attachments::{
+    attachment_types, remove_attachments, AttachmentDisposition, AttachmentRemoval,
+};
+use crate::banner::BannerStamper;
+use crate::email_auth::dmarc_report::{DmarcObservation, PolicyOverride, PublishedPolicy};
 use crate::email_auth::{
-    dkim::DkimVerifier, dmarc::DmarcVerifier, spf::SpfVerifier, AuthenticationResult,
+    bimi, dkim::DkimVerifier, dmarc::DmarcVerifier, spf::SpfVerifier, srs::is_srs,
+    ArcVerification, ArcVerifier, AuthenticationResult, Bimi, DkimKeyring, DmarcEvaluation,
+    DmarcPolicy, DmarcReportRecorder, DmarcResult, SpfResult, Srs, SrsError,
 };
+use crate::filters::{FilterOutcome, MailboxFilters};
+use crate::geoip::{GeoIp, GeoIpInfo};
 use crate::hooks::HookManager;
-use crate::queue::QueueManager;
+use crate::impersonation::{
+    Impersonation, ImpersonationAction, ImpersonationChecker, IMPERSONATION_TAG,
+};
+use crate::inbound::{inbound_storage_path, InboundAuth, InboundJob};
+use crate::journal::{
+    Journal, JournalDirection, JournalJob, JournalRecipient, JournalVerdicts, RecipientDisposition,
+};
+use crate::maintenance::{MaintenanceMode, MaintenanceStatus};
+use crate::policy::{
+    PolicyContext, PolicyEngine, PolicyMatch, PolicyRateLimit, PolicyRateLimited,
+    PolicyRateLimiter, PolicyRejection,
+};
+use crate::preview::MessagePreview;
+use crate::quarantine::{quarantine_metadata, quarantining_policy, QuarantineReason};
+use crate::queue::{DeliveryError, DeliveryJob, QueueManager, RemoteReply};
+use crate::quota::{QuotaChecker, QuotaRejection, QuotaVerdict};
+use crate::reports::{report_addresses, ReportIngester};
+use crate::scheduled::{parse_verp, BounceProcessor};
+use crate::sent_archive::SentArchiver;
+use crate::sessions::{ActiveSession, SessionProtocol};
 use crate::smtp::auth::{login_challenge_password, login_challenge_username, SmtpAuthenticator};
+use crate::smtp::data::{read_data, DataError};
+use crate::smtp::greylist::{Greylist, GreylistDeferral, GreylistVerdict};
+use crate::smtp::milter::{apply_header_changes, MilterSession, MilterVerdict};
+use crate::smtp::rate_limit::{RateLimitClient, RateLimitKind, SmtpRateLimiter};
+use crate::smtp::sender::{SenderVerdict, SenderVerifier, NOT_OWNED};
+use crate::smtp::stream::SmtpStream;
+use crate::spam::junk::junk_metadata;
+use crate::spam::reputation::CLEAN_SCORE;
+use crate::spam::{
+    Dnsbl, JunkFiler, PhishingAnalyzer, PhishingReport, PhishingSignal, SenderReputation,
+    SpamAction, SpamCheckResult, SpamFilter, SpamRejection, Uribl,
+};
+use crate::subaddress::{split_subaddress, SubaddressFiler};
+use crate::subdomains::find_parent_domain;
 use anyhow::Result;
-use chrono::Utc;
-use mairust_common::config::SmtpConfig;
-use mairust_common::types::{EmailAddress, Envelope};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use mairust_common::config::{AntivirusAction, SmtpConfig};
+use mairust_common::types::{DeliveryLane, DomainId, EmailAddress, Envelope, TenantId};
 use mairust_storage::db::DatabasePool;
 use mairust_storage::file::FileStorage;
-use mairust_storage::models::{Message, User};
-use mairust_storage::repository::{DomainRepository, MailboxRepository, MessageRepository};
-use std::net::SocketAddr;
+use mairust_storage::models::{Domain, DomainSettings, Mailbox, Message, User};
+use mairust_storage::repository::inbound_routes::InboundRoute;
+use mairust_storage::repository::{
+    CorrespondentRepository, DomainRepository, DomainSettingsRepository,
+    DomainSettingsRepositoryTrait, InboundRouteRepository, MailboxRepository,
+    MailboxRepositoryTrait, MessageRepository, NewPolicyMatch, PolicyHistoryRepository,
+    QuarantineRepository,
+};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::TcpStream;
@@ -22,6 +75,15 @@ use tokio_rustls::TlsAcceptor;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+/// Tag added to messages delivered to a domain's catch-all mailbox
+const CATCH_ALL_TAG: &str = "catch-all";
+
+/// Maximum message size when the configuration sets none (50MB)
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 52_428_800;
+
+/// Reply to recipients whose domain takes smaller messages
+const DOMAIN_SIZE_REPLY: &str = "5.3.4 Message size exceeds maximum message size of the domain";
+
 /// SMTP session state
 #[derive(Debug, Clone, PartialEq)]
 enum SessionState {
@@ -32,6 +94,188 @@ enum SessionState {
     Data,
 }
 
+impl SessionState {
+    /// Name of the state in session listings
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::Connected => "connected",
+            SessionState::Greeted => "greeted",
+            SessionState::MailFrom => "mail_from",
+            SessionState::RcptTo => "rcpt_to",
+            SessionState::Data => "data",
+        }
+    }
+}
+
+/// Message size and recipient count a transaction is held to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MessageLimits {
+    max_size: usize,
+    max_recipients: usize,
+}
+
+impl MessageLimits {
+    /// Limits of the server configuration
+    fn from_config(config: &SmtpConfig) -> Self {
+        Self {
+            max_size: config.max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
+            max_recipients: config.max_recipients,
+        }
+    }
+
+    /// Apply the limits of a recipient domain. A domain can only lower the
+    /// server limits; values that are unset or not positive are ignored.
+    fn restrict(self, settings: &DomainSettings) -> Self {
+        let lower = |limit: usize, value: Option<i64>| match value
+            .filter(|value| *value > 0)
+            .and_then(|value| usize::try_from(value).ok())
+        {
+            Some(value) => limit.min(value),
+            None => limit,
+        };
+        Self {
+            max_size: lower(self.max_size, settings.max_message_size),
+            max_recipients: lower(self.max_recipients, settings.max_recipients.map(i64::from)),
+        }
+    }
+}
+
+/// Largest message a recipient of a transaction accepts
+#[derive(Debug, Clone, PartialEq)]
+struct RecipientSizeLimit {
+    recipient: EmailAddress,
+    max_size: usize,
+    /// Tenant of the recipient's domain, for local domains
+    tenant_id: Option<TenantId>,
+}
+
+/// What the inbound policies of a tenant and domain do to a received message
+#[derive(Debug, Clone, Default)]
+struct DeliveryPolicy {
+    /// Policies that matched the message
+    matches: Vec<PolicyMatch>,
+    /// Refusal of the whole message
+    rejection: Option<PolicyRejection>,
+    /// Name of the policy that quarantines the message
+    quarantine: Option<String>,
+    /// Attachments taken out of the copy
+    attachments: Option<AttachmentRemoval>,
+    /// Rate limits the message counts against
+    rate_limits: Vec<PolicyRateLimit>,
+}
+
+/// Inbound policies of a tenant and domain, evaluated for the message to
+/// some of its recipients
+struct MatchedPolicies {
+    key: (TenantId, DomainId),
+    policy: DeliveryPolicy,
+    recipients: Vec<String>,
+}
+
+/// A received message, as recorded with the policies that matched it
+struct MatchedMessage<'a> {
+    /// Message-ID header
+    message_id: Option<&'a str>,
+    sender: Option<&'a str>,
+    client_ip: &'a str,
+}
+
+/// A received message, after the checks shared by all its recipients
+struct InboundMessage<'a> {
+    message_id: Uuid,
+    envelope: &'a Envelope,
+    /// The message with this server's Received and BIMI headers
+    data: &'a [u8],
+    authenticated_user: Option<&'a User>,
+    sender: Option<String>,
+    recipients: Vec<String>,
+    client_ip: String,
+    subject: Option<String>,
+    /// Address of the From header
+    from_header: Option<String>,
+    from_name: Option<String>,
+    reply_to: Option<String>,
+    /// Message-ID header
+    message_id_header: Option<String>,
+    attachment_count: usize,
+    preview: MessagePreview,
+    auth_result: AuthenticationResult,
+    /// Spam verdict before the spam rules of the recipients' tenants
+    spam: SpamCheckResult,
+    /// Reputation of the sender domain before this message
+    sender_reputation: Option<f64>,
+    virus_scan: Option<ScanVerdict>,
+    /// What the tenants that scan their mail do with viruses
+    antivirus_actions: HashMap<TenantId, AntivirusAction>,
+    geo: GeoIpInfo,
+    /// Context the inbound policies and mailbox filters match
+    filter_context: PolicyContext,
+    received_at: DateTime<Utc>,
+}
+
+impl InboundMessage<'_> {
+    /// Metadata shared by the stored copies of the message
+    fn metadata(&self, hostname: &str) -> serde_json::Value {
+        let auth_result = &self.auth_result;
+        let mut metadata = serde_json::json!({
+            "spf": auth_result.spf.as_header_value(),
+            "dkim": auth_result.dkim.as_header_value(),
+            "dmarc": auth_result.dmarc.as_header_value(),
+            "arc": auth_result.arc.result.as_header_value(),
+            "auth_results_header": auth_result.to_header(hostname),
+            "require_tls": self.envelope.require_tls,
+            "envelope_from": self.sender
+        });
+        if let Some(policy) = auth_result.dmarc.policy() {
+            metadata["dmarc_policy"] = serde_json::json!(policy.as_str());
+        }
+        if auth_result.dmarc_overridden {
+            metadata["dmarc_overridden"] = serde_json::json!(true);
+        }
+        if let Some(bimi) = &auth_result.bimi {
+            metadata["bimi"] = serde_json::json!(bimi.result.as_header_value());
+        }
+        if let Some(reputation) = self.sender_reputation {
+            metadata["sender_reputation"] = serde_json::json!(reputation);
+        }
+        if let Some(country) = &self.geo.country {
+            metadata["country"] = serde_json::json!(country);
+        }
+        if let Some(asn) = self.geo.asn {
+            metadata["asn"] = serde_json::json!(asn);
+        }
+        metadata
+    }
+}
+
+/// What the recipients of a message share while it is delivered to them
+struct RecipientDeliveries {
+    /// Inbound policies of each tenant and domain
+    policies: HashMap<(TenantId, DomainId), DeliveryPolicy>,
+    /// Spam verdict and phishing signals of each tenant
+    tenant_verdicts: HashMap<TenantId, (SpamCheckResult, PhishingReport)>,
+    /// Display name impersonation of each tenant
+    impersonations: HashMap<TenantId, Option<Impersonation>>,
+    /// Catch-all mailboxes that already have a copy
+    catch_all_delivered: HashSet<Uuid>,
+    banner_stamper: BannerStamper,
+    /// What happened for each recipient, for the journal
+    journaled: Vec<JournalRecipient>,
+}
+
+impl RecipientDeliveries {
+    fn new(db_pool: DatabasePool) -> Self {
+        Self {
+            policies: HashMap::new(),
+            tenant_verdicts: HashMap::new(),
+            impersonations: HashMap::new(),
+            catch_all_delivered: HashSet::new(),
+            banner_stamper: BannerStamper::new(db_pool),
+            journaled: Vec::new(),
+        }
+    }
+}
+
 /// Result of command processing
 enum CommandResult {
     Continue,
@@ -45,9 +289,21 @@ pub struct SmtpHandler<S: FileStorage> {
     db_pool: DatabasePool,
     file_storage: Arc<S>,
     hook_manager: Arc<HookManager>,
-    #[allow(dead_code)]
     queue_manager: Arc<QueueManager<S>>,
+    spam_filter: Arc<SpamFilter>,
     peer_addr: SocketAddr,
+    maintenance: MaintenanceMode,
+    journal: Journal,
+    greylist: Option<Arc<Greylist>>,
+    rate_limiter: Option<Arc<SmtpRateLimiter>>,
+    dnsbl: Option<Arc<Dnsbl>>,
+    uribl: Option<Arc<Uribl>>,
+    sender_reputation: Option<Arc<SenderReputation>>,
+    bimi: Option<Arc<Bimi>>,
+    antivirus: Option<Arc<Antivirus>>,
+    geoip: Option<Arc<GeoIp>>,
+    session: Arc<ActiveSession>,
+    lmtp: bool,
 }
 
 impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
@@ -58,6 +314,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         file_storage: Arc<S>,
         hook_manager: Arc<HookManager>,
         queue_manager: Arc<QueueManager<S>>,
+        spam_filter: Arc<SpamFilter>,
         peer_addr: SocketAddr,
     ) -> Self {
         Self {
@@ -66,63 +323,182 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
             file_storage,
             hook_manager,
             queue_manager,
+            spam_filter,
             peer_addr,
+            maintenance: MaintenanceMode::default(),
+            journal: Journal::default(),
+            greylist: None,
+            rate_limiter: None,
+            dnsbl: None,
+            uribl: None,
+            sender_reputation: None,
+            bimi: None,
+            antivirus: None,
+            geoip: None,
+            session: ActiveSession::new(SessionProtocol::Smtp, peer_addr),
+            lmtp: false,
         }
     }
 
+    /// Refuse the session with 421 while a maintenance window is active
+    pub fn with_maintenance(mut self, maintenance: MaintenanceMode) -> Self {
+        self.maintenance = maintenance;
+        self
+    }
+
+    /// Journal received messages
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    /// Greylist unauthenticated clients and enforce spam soft rejects
+    pub fn with_greylist(mut self, greylist: Arc<Greylist>) -> Self {
+        self.greylist = Some(greylist);
+        self
+    }
+
+    /// Limit the connection, message and recipient rates of clients
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<SmtpRateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Look up unauthenticated clients in DNS blocklists
+    pub fn with_dnsbl(mut self, dnsbl: Arc<Dnsbl>) -> Self {
+        self.dnsbl = Some(dnsbl);
+        self
+    }
+
+    /// Look up the links of messages from unauthenticated clients in URI
+    /// blocklists
+    pub fn with_uribl(mut self, uribl: Arc<Uribl>) -> Self {
+        self.uribl = Some(uribl);
+        self
+    }
+
+    /// Track the reputation of the sender domains of unauthenticated clients
+    pub fn with_sender_reputation(mut self, sender_reputation: Arc<SenderReputation>) -> Self {
+        self.sender_reputation = Some(sender_reputation);
+        self
+    }
+
+    /// Add the BIMI logos of senders whose DMARC policy is enforced
+    pub fn with_bimi(mut self, bimi: Arc<Bimi>) -> Self {
+        self.bimi = Some(bimi);
+        self
+    }
+
+    /// Scan received messages for viruses
+    pub fn with_antivirus(mut self, antivirus: Arc<Antivirus>) -> Self {
+        self.antivirus = Some(antivirus);
+        self
+    }
+
+    /// Look up the country and autonomous system of clients for policies
+    pub fn with_geoip(mut self, geoip: Arc<GeoIp>) -> Self {
+        self.geoip = Some(geoip);
+        self
+    }
+
+    /// Report the session's user, state and traffic to a session registry
+    pub fn with_session(mut self, session: Arc<ActiveSession>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Speak LMTP (RFC 2033): LHLO instead of HELO/EHLO, a reply for each
+    /// recipient after DATA, and XFORWARD from the MTA in front
+    pub fn with_lmtp(mut self) -> Self {
+        self.lmtp = true;
+        self
+    }
+
+    /// Treat the session as coming from `peer_addr` (the client address of
+    /// a PROXY protocol header)
+    pub fn with_peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.peer_addr = peer_addr;
+        self
+    }
+
     /// Handle an SMTP session (legacy method without TLS)
     pub async fn handle(self, stream: TcpStream) -> Result<()> {
         self.handle_with_tls(stream, None).await
     }
 
+    /// Handle an SMTP session on an implicit TLS listener (e.g. port 465)
+    pub async fn handle_implicit_tls(
+        self,
+        stream: TcpStream,
+        tls_acceptor: Arc<TlsAcceptor>,
+    ) -> Result<()> {
+        let stream = SmtpStream::Plain(stream)
+            .upgrade(&tls_acceptor)
+            .await
+            .map_err(|e| anyhow::anyhow!("{} for {}", e, self.peer_addr))?;
+        self.serve(stream, None).await
+    }
+
     /// Handle an SMTP session with optional TLS support
     pub async fn handle_with_tls(
         self,
         stream: TcpStream,
         tls_acceptor: Option<Arc<TlsAcceptor>>,
     ) -> Result<()> {
-        // Start with plain text session
-        let (reader, writer) = stream.into_split();
-        let mut reader = BufReader::new(reader);
-        let mut writer = BufWriter::new(writer);
-
-        // Run plaintext phase
-        let result = self
-            .run_session(&mut reader, &mut writer, false, true)
-            .await?;
+        self.serve(SmtpStream::Plain(stream), tls_acceptor).await
+    }
 
-        match result {
-            CommandResult::Quit => Ok(()),
-            CommandResult::Continue => Ok(()),
-            CommandResult::StartTls => {
-                let acceptor = tls_acceptor.ok_or_else(|| {
-                    anyhow::anyhow!("STARTTLS requested without configured acceptor")
-                })?;
-
-                // Make sure response is flushed before upgrading.
-                writer.flush().await?;
-
-                // Reunite stream halves so rustls can perform handshake.
-                let reader = reader.into_inner();
-                let writer = writer.into_inner();
-                let tcp_stream = reader.reunite(writer).map_err(|_| {
-                    anyhow::anyhow!("Failed to reunite TCP stream halves during STARTTLS")
-                })?;
-
-                info!("Upgrading connection to TLS for {}", self.peer_addr);
-                let tls_stream = acceptor.accept(tcp_stream).await.map_err(|e| {
-                    anyhow::anyhow!("TLS handshake failed for {}: {}", self.peer_addr, e)
-                })?;
-
-                let (tls_reader, tls_writer) = tokio::io::split(tls_stream);
-                let mut tls_reader = BufReader::new(tls_reader);
-                let mut tls_writer = BufWriter::new(tls_writer);
-
-                // Continue same SMTP session over TLS without sending a second greeting.
-                self.run_session(&mut tls_reader, &mut tls_writer, true, false)
-                    .await?;
-                Ok(())
-            }
+    /// Serve a session, upgrading its connection in place on STARTTLS
+    ///
+    /// After the upgrade the session starts over without a greeting: the
+    /// client has to send EHLO again, and nothing said before TLS (HELO,
+    /// AUTH, the envelope) is kept (RFC 3207).
+    async fn serve(
+        &self,
+        mut stream: SmtpStream,
+        tls_acceptor: Option<Arc<TlsAcceptor>>,
+    ) -> Result<()> {
+        let mut send_greeting = true;
+        // The MTA in front of LMTP has run its own milters
+        let mut milters = MilterSession::new(if self.lmtp { &[] } else { &self.config.milters });
+        loop {
+            let tls_established = stream.is_tls();
+            let (reader, writer) = tokio::io::split(self.session.meter(stream));
+            let mut reader = BufReader::new(reader);
+            let mut writer = BufWriter::new(writer);
+
+            let result = self
+                .run_session(
+                    &mut reader,
+                    &mut writer,
+                    &mut milters,
+                    tls_established,
+                    send_greeting,
+                )
+                .await?;
+            writer.flush().await?;
+            let CommandResult::StartTls = result else {
+                milters.quit().await;
+                return Ok(());
+            };
+            let acceptor = tls_acceptor
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("STARTTLS requested without configured acceptor"))?;
+
+            // The 220 reply went out before the handshake. Commands the
+            // client pipelined behind STARTTLS are still buffered in the
+            // reader and are dropped with it.
+            stream = reader
+                .into_inner()
+                .unsplit(writer.into_inner())
+                .into_inner();
+
+            info!("Upgrading connection to TLS for {}", self.peer_addr);
+            stream = stream
+                .upgrade(acceptor)
+                .await
+                .map_err(|e| anyhow::anyhow!("{} for {}", e, self.peer_addr))?;
+            send_greeting = false;
         }
     }
 
@@ -131,6 +507,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         &self,
         reader: &mut BufReader<R>,
         writer: &mut BufWriter<W>,
+        milters: &mut MilterSession,
         tls_established: bool,
         send_greeting: bool,
     ) -> Result<CommandResult>
@@ -144,6 +521,10 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
             to: Vec::new(),
             client_ip: Some(self.peer_addr.ip().to_string()),
             helo: None,
+            require_tls: false,
+            size: None,
+            relay_to: Vec::new(),
+            srs_to: Vec::new(),
         };
         let mut authenticated = false;
         #[allow(unused_assignments)]
@@ -151,11 +532,55 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         let authenticator = SmtpAuthenticator::new(self.db_pool.clone());
 
         if send_greeting {
+            if let Some(status) = self.maintenance.status().await {
+                self.send_maintenance_response(writer, &status).await?;
+                return Ok(CommandResult::Quit);
+            }
+
+            if let Some(rate_limiter) = &self.rate_limiter {
+                if !rate_limiter.check_connection(self.peer_addr.ip()).await {
+                    self.send_response(
+                        writer,
+                        421,
+                        &format!(
+                            "{} 4.7.0 Too many connections from your address, try again later",
+                            self.config.hostname
+                        ),
+                    )
+                    .await?;
+                    return Ok(CommandResult::Quit);
+                }
+            }
+
+            if let MilterVerdict::Reply(code, reply) =
+                milters.connect(&self.config.hostname, self.peer_addr).await
+            {
+                info!(
+                    "Milter refused connection from {}: {}",
+                    self.peer_addr, reply
+                );
+                let code = if code >= 500 { 554 } else { 421 };
+                self.send_response(writer, code, &format!("{} {}", self.config.hostname, reply))
+                    .await?;
+                return Ok(CommandResult::Quit);
+            }
+
+            // Start the blocklist lookups, to have their answers by MAIL FROM
+            if let Some(dnsbl) = self.dnsbl.as_ref().filter(|dnsbl| dnsbl.enabled()) {
+                if !self.config.auth_required.unwrap_or(false) {
+                    let dnsbl = dnsbl.clone();
+                    let client_ip = self.peer_addr.ip();
+                    tokio::spawn(async move {
+                        dnsbl.check(client_ip).await;
+                    });
+                }
+            }
+
             // Send greeting
             self.send_response(
                 writer,
                 220,
-                &format!("{} ESMTP MaiRust", self.config.hostname),
+                &format!("{} {}", self.config.hostname, self.config.banner),
             )
             .await?;
         }
@@ -163,6 +588,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         let mut line = String::new();
 
         loop {
+            flush_if_idle(reader, writer).await?;
             line.clear();
             let bytes_read = reader.read_line(&mut line).await?;
 
@@ -178,6 +604,14 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
             let command = command.to_string();
             let args = args.to_string();
 
+            // Sessions open when a window starts are closed at their next command
+            if !command.eq_ignore_ascii_case("QUIT") {
+                if let Some(status) = self.maintenance.status().await {
+                    self.send_maintenance_response(writer, &status).await?;
+                    return Ok(CommandResult::Quit);
+                }
+            }
+
             let result = self
                 .process_command(
                     &command,
@@ -187,12 +621,14 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                     &mut authenticated,
                     &mut authenticated_user,
                     &authenticator,
+                    milters,
                     tls_established,
                     reader,
                     writer,
                     &mut line,
                 )
                 .await?;
+            self.session.set_state(state.as_str());
 
             match result {
                 CommandResult::Continue => continue,
@@ -215,6 +651,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         authenticated: &mut bool,
         authenticated_user: &mut Option<User>,
         authenticator: &SmtpAuthenticator,
+        milters: &mut MilterSession,
         tls_established: bool,
         reader: &mut R,
         writer: &mut W,
@@ -225,21 +662,46 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         W: AsyncWrite + Unpin,
     {
         match command.to_uppercase().as_str() {
+            "HELO" | "EHLO" if self.lmtp => {
+                self.send_response(writer, 500, "5.5.1 Use LHLO with LMTP")
+                    .await?;
+            }
+
+            "LHLO" if !self.lmtp => {
+                self.send_response(writer, 500, "5.5.2 Command not recognized")
+                    .await?;
+            }
+
             "HELO" => {
+                if let MilterVerdict::Reply(code, reply) = milters.helo(args).await {
+                    self.send_response(writer, code, &reply).await?;
+                    return Ok(CommandResult::Continue);
+                }
                 envelope.helo = Some(args.to_string());
                 *state = SessionState::Greeted;
-                self.send_response(writer, 250, &format!("Hello {}", args))
-                    .await?;
+                self.send_response(
+                    writer,
+                    250,
+                    &format!("{} Hello {}", self.config.hostname, args),
+                )
+                .await?;
             }
 
-            "EHLO" => {
+            "EHLO" | "LHLO" => {
+                if let MilterVerdict::Reply(code, reply) = milters.helo(args).await {
+                    self.send_response(writer, code, &reply).await?;
+                    return Ok(CommandResult::Continue);
+                }
                 envelope.helo = Some(args.to_string());
                 *state = SessionState::Greeted;
 
-                // Send EHLO response with extensions
+                // Send EHLO response with extensions; AUTH is offered on the
+                // sessions that can relay
+                let offers_auth = self.config.auth_required.unwrap_or(false) || *authenticated;
+                let max_size = self.session_size_limit(offers_auth).await;
                 let mut responses = vec![
                     format!("{} Hello {}", self.config.hostname, args),
-                    "SIZE 52428800".to_string(), // 50MB
+                    format!("SIZE {}", max_size),
                     "8BITMIME".to_string(),
                     "PIPELINING".to_string(),
                     "ENHANCEDSTATUSCODES".to_string(),
@@ -250,11 +712,21 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                     responses.push("STARTTLS".to_string());
                 }
 
+                // REQUIRETLS is only offered on protected sessions (RFC 8689 section 4)
+                if tls_established {
+                    responses.push("REQUIRETLS".to_string());
+                }
+
                 // Advertise AUTH
-                if self.config.auth_required.unwrap_or(false) || *authenticated {
+                if offers_auth {
                     responses.push("AUTH PLAIN LOGIN".to_string());
                 }
 
+                // The MTA in front passes on its client
+                if self.lmtp {
+                    responses.push("XFORWARD NAME ADDR PROTO HELO".to_string());
+                }
+
                 for (i, resp) in responses.iter().enumerate() {
                     if i == responses.len() - 1 {
                         self.send_response(writer, 250, resp).await?;
@@ -289,6 +761,11 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                 return Ok(CommandResult::StartTls);
             }
 
+            "AUTH" if self.lmtp => {
+                self.send_response(writer, 502, "5.5.1 AUTH not supported")
+                    .await?;
+            }
+
             "AUTH" => {
                 if *state != SessionState::Greeted {
                     self.send_response(writer, 503, "5.5.1 Bad sequence of commands")
@@ -318,7 +795,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                             initial_response.to_string()
                         } else {
                             // Request credentials
-                            self.send_response(writer, 334, "").await?;
+                            self.send_intermediate_response(writer, 334, "").await?;
 
                             // Read credentials
                             line.clear();
@@ -341,6 +818,9 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                         if result.success {
                             *authenticated = true;
                             *authenticated_user = result.user;
+                            if let Some(user) = authenticated_user.as_ref() {
+                                self.session.set_user(&user.email, Some(user.tenant_id));
+                            }
                             info!(
                                 "SMTP AUTH PLAIN successful for {:?} from {}",
                                 authenticated_user.as_ref().map(|u| &u.email),
@@ -364,7 +844,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                     Some("LOGIN") => {
                         // AUTH LOGIN flow - challenge/response
                         // Send username challenge
-                        self.send_response(writer, 334, &login_challenge_username())
+                        self.send_intermediate_response(writer, 334, &login_challenge_username())
                             .await?;
 
                         // Read username
@@ -383,7 +863,7 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                         }
 
                         // Send password challenge
-                        self.send_response(writer, 334, &login_challenge_password())
+                        self.send_intermediate_response(writer, 334, &login_challenge_password())
                             .await?;
 
                         // Read password
@@ -406,6 +886,9 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                         if result.success {
                             *authenticated = true;
                             *authenticated_user = result.user;
+                            if let Some(user) = authenticated_user.as_ref() {
+                                self.session.set_user(&user.email, Some(user.tenant_id));
+                            }
                             info!(
                                 "SMTP AUTH LOGIN successful for {:?} from {}",
                                 authenticated_user.as_ref().map(|u| &u.email),
@@ -437,6 +920,21 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                 }
             }
 
+            "XFORWARD" if self.lmtp => {
+                if *state != SessionState::Greeted {
+                    self.send_response(writer, 503, "5.5.1 Bad sequence of commands")
+                        .await?;
+                    return Ok(CommandResult::Continue);
+                }
+                match apply_xforward(envelope, args) {
+                    Ok(()) => self.send_response(writer, 250, "2.0.0 OK").await?,
+                    Err(e) => {
+                        self.send_response(writer, 501, &format!("5.5.4 {}", e))
+                            .await?
+                    }
+                }
+            }
+
             "MAIL" => {
                 if *state != SessionState::Greeted {
                     self.send_response(writer, 503, "5.5.1 Bad sequence of commands")
@@ -451,9 +949,105 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                     return Ok(CommandResult::Continue);
                 }
 
+                let require_tls = has_mail_parameter(args, "REQUIRETLS");
+                if require_tls && !tls_established {
+                    self.send_response(
+                        writer,
+                        530,
+                        "5.7.10 REQUIRETLS not permitted on an unencrypted connection",
+                    )
+                    .await?;
+                    return Ok(CommandResult::Continue);
+                }
+
+                if !self
+                    .within_rate_limit(RateLimitKind::Message, authenticated_user.as_ref())
+                    .await
+                {
+                    self.send_response(writer, 450, "4.7.1 Too many messages, try again later")
+                        .await?;
+                    return Ok(CommandResult::Continue);
+                }
+
+                if !*authenticated {
+                    if let Some(reply) = self.dnsbl_rejection().await {
+                        self.send_response(writer, 550, &reply).await?;
+                        return Ok(CommandResult::Continue);
+                    }
+                }
+
                 // Parse MAIL FROM:<address>
                 if let Some(from_addr) = parse_mail_from(args) {
+                    let size = mail_parameter(args, "SIZE").and_then(|v| v.parse::<u64>().ok());
+                    let max_size = self.session_size_limit(*authenticated).await;
+                    if size.is_some_and(|size| size > max_size as u64) {
+                        self.send_response(
+                            writer,
+                            552,
+                            "5.3.4 Message size exceeds fixed maximum message size",
+                        )
+                        .await?;
+                        return Ok(CommandResult::Continue);
+                    }
+                    let from_addr = match (authenticated_user.as_ref(), from_addr) {
+                        (Some(user), Some(address)) => {
+                            let verdict = SenderVerifier::new(self.db_pool.clone(), &self.config)
+                                .check(user, &address)
+                                .await;
+                            match verdict {
+                                Ok(SenderVerdict::Accept) => Some(address),
+                                Ok(SenderVerdict::Rewrite(own)) => {
+                                    info!(
+                                        "Rewriting sender {} of {} to {}",
+                                        address, user.email, own
+                                    );
+                                    Some(own)
+                                }
+                                Ok(SenderVerdict::Reject) => {
+                                    info!("Refusing sender {} of {}", address, user.email);
+                                    self.send_response(
+                                        writer,
+                                        553,
+                                        &format!("5.7.1 {}", NOT_OWNED),
+                                    )
+                                    .await?;
+                                    return Ok(CommandResult::Continue);
+                                }
+                                Err(e) => {
+                                    warn!("Database error checking sender: {}", e);
+                                    let (code, reply) = error_reply(&e);
+                                    self.send_response(writer, code, &reply).await?;
+                                    return Ok(CommandResult::Continue);
+                                }
+                            }
+                        }
+                        (_, from_addr) => from_addr,
+                    };
+                    if let (false, Some(address)) = (*authenticated, from_addr.as_ref()) {
+                        if let Some(reply) = self.sender_domain_deferral(&address.domain).await {
+                            self.send_response(writer, 450, &reply).await?;
+                            return Ok(CommandResult::Continue);
+                        }
+                    }
+                    // LMTP mail was accepted by the MTA in front; refusing it
+                    // here would make that MTA bounce it
+                    if !*authenticated && !self.lmtp {
+                        if let Some(reply) = self.spf_rejection(envelope, from_addr.as_ref()).await
+                        {
+                            self.send_response(writer, 550, &reply).await?;
+                            return Ok(CommandResult::Continue);
+                        }
+                    }
+                    let user = authenticated_user.as_ref().map(|user| user.email.as_str());
+                    if let MilterVerdict::Reply(code, reply) =
+                        milters.mail(from_addr.as_ref(), args, user).await
+                    {
+                        self.send_response(writer, code, &reply).await?;
+                        return Ok(CommandResult::Continue);
+                    }
                     envelope.from = from_addr;
+                    envelope.require_tls = require_tls;
+                    envelope.size = size;
                     *state = SessionState::MailFrom;
                     self.send_response(writer, 250, "2.1.0 OK").await?;
                 } else {
@@ -471,16 +1065,133 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
 
                 // Parse RCPT TO:<address>
                 if let Some(to_addr) = parse_rcpt_to(args) {
+                    // Recipients accepted so far in this transaction
+                    let recipients =
+                        envelope.to.len() + envelope.relay_to.len() + envelope.srs_to.len();
+                    if recipients >= MessageLimits::from_config(&self.config).max_recipients {
+                        self.send_response(writer, 452, "4.5.3 Too many recipients")
+                            .await?;
+                        return Ok(CommandResult::Continue);
+                    }
+
+                    if !self
+                        .within_rate_limit(RateLimitKind::Recipient, authenticated_user.as_ref())
+                        .await
+                    {
+                        self.send_response(
+                            writer,
+                            450,
+                            "4.7.1 Too many recipients, try again later",
+                        )
+                        .await?;
+                        return Ok(CommandResult::Continue);
+                    }
+
+                    // Mail to SRS addresses goes back to the original sender.
+                    // LMTP only delivers to mailboxes.
+                    match self.srs_return(&to_addr).filter(|_| !self.lmtp) {
+                        Some(Ok(original)) => {
+                            if let MilterVerdict::Reply(code, reply) =
+                                milters.rcpt(&to_addr, args).await
+                            {
+                                self.send_response(writer, code, &reply).await?;
+                                return Ok(CommandResult::Continue);
+                            }
+                            envelope.srs_to.push(original);
+                            *state = SessionState::RcptTo;
+                            self.send_response(writer, 250, "2.1.5 OK").await?;
+                            return Ok(CommandResult::Continue);
+                        }
+                        Some(Err(e)) => {
+                            info!("Refusing mail for {}: {}", to_addr, e);
+                            let reply = format!("5.1.1 Recipient address rejected: {}", e);
+                            self.send_response(writer, 550, &reply).await?;
+                            return Ok(CommandResult::Continue);
+                        }
+                        None => {}
+                    }
+
                     // Check if we handle this domain
-                    let domain_repo = DomainRepository::new(self.db_pool.clone());
-                    match domain_repo.find_by_name(&to_addr.domain).await {
-                        Ok(Some(_domain)) => {
+                    match self.local_domain(&to_addr.domain).await {
+                        Ok(Some(domain)) => {
+                            let limits = self.domain_limits(&domain).await;
+                            if recipients >= limits.max_recipients {
+                                self.send_response(writer, 452, "4.5.3 Too many recipients")
+                                    .await?;
+                                return Ok(CommandResult::Continue);
+                            }
+                            if envelope
+                                .size
+                                .is_some_and(|size| size > limits.max_size as u64)
+                            {
+                                self.send_response(writer, 552, DOMAIN_SIZE_REPLY).await?;
+                                return Ok(CommandResult::Continue);
+                            }
+                            match self.is_deliverable(&to_addr).await {
+                                Ok(true) => {}
+                                Ok(false) => {
+                                    self.record_refused_recipient(envelope, *authenticated)
+                                        .await;
+                                    self.send_response(
+                                        writer,
+                                        550,
+                                        "5.1.1 Recipient address rejected: User unknown",
+                                    )
+                                    .await?;
+                                    return Ok(CommandResult::Continue);
+                                }
+                                Err(e) => {
+                                    warn!("Database error checking recipient: {}", e);
+                                    let (code, reply) = error_reply(&e);
+                                    self.send_response(writer, code, &reply).await?;
+                                    return Ok(CommandResult::Continue);
+                                }
+                            }
+                            let declared = envelope.size.unwrap_or(0);
+                            if let Some((code, reply)) =
+                                self.quota_verdict(&to_addr, declared).await.reply()
+                            {
+                                info!("Refusing mail for {}: mailbox over quota", to_addr);
+                                self.send_response(writer, code, reply).await?;
+                                return Ok(CommandResult::Continue);
+                            }
+                            if let Some(retry_after) = self
+                                .greylist_deferral(envelope, &to_addr, *authenticated)
+                                .await
+                            {
+                                self.send_response(
+                                    writer,
+                                    451,
+                                    &GreylistVerdict::reply(retry_after),
+                                )
+                                .await?;
+                                return Ok(CommandResult::Continue);
+                            }
+                            if let MilterVerdict::Reply(code, reply) =
+                                milters.rcpt(&to_addr, args).await
+                            {
+                                self.send_response(writer, code, &reply).await?;
+                                return Ok(CommandResult::Continue);
+                            }
                             envelope.to.push(to_addr);
                             *state = SessionState::RcptTo;
                             self.send_response(writer, 250, "2.1.5 OK").await?;
                         }
+                        Ok(None) if self.config.allow_relay && *authenticated => {
+                            if let MilterVerdict::Reply(code, reply) =
+                                milters.rcpt(&to_addr, args).await
+                            {
+                                self.send_response(writer, code, &reply).await?;
+                                return Ok(CommandResult::Continue);
+                            }
+                            envelope.relay_to.push(to_addr);
+                            *state = SessionState::RcptTo;
+                            self.send_response(writer, 250, "2.1.5 OK").await?;
+                        }
                         Ok(None) => {
                             // We don't handle this domain - relay not allowed
+                            self.record_refused_recipient(envelope, *authenticated)
+                                .await;
                             self.send_response(
                                 writer,
                                 550,
@@ -490,8 +1201,8 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                         }
                         Err(e) => {
                             warn!("Database error checking domain: {}", e);
-                            self.send_response(writer, 451, "4.3.0 Temporary error")
-                                .await?;
+                            let (code, reply) = error_reply(&e);
+                            self.send_response(writer, code, &reply).await?;
                         }
                     }
                 } else {
@@ -507,44 +1218,93 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                     return Ok(CommandResult::Continue);
                 }
 
-                if envelope.to.is_empty() {
+                if envelope.to.is_empty()
+                    && envelope.relay_to.is_empty()
+                    && envelope.srs_to.is_empty()
+                {
                     self.send_response(writer, 503, "5.5.1 No recipients specified")
                         .await?;
                     return Ok(CommandResult::Continue);
                 }
 
+                if let MilterVerdict::Reply(code, reply) = milters.data().await {
+                    self.send_response(writer, code, &reply).await?;
+                    return Ok(CommandResult::Continue);
+                }
+
                 let _ = SessionState::Data;
-                self.send_response(writer, 354, "Start mail input; end with <CRLF>.<CRLF>")
-                    .await?;
+                self.send_intermediate_response(
+                    writer,
+                    354,
+                    "Start mail input; end with <CRLF>.<CRLF>",
+                )
+                .await?;
 
-                // Read message data
-                match self.read_data(reader).await {
+                // Read message data, as much as the recipient that takes the
+                // largest messages accepts
+                let size_limits = self.recipient_size_limits(envelope).await;
+                let max_size = data_size_limit(
+                    &size_limits,
+                    envelope,
+                    MessageLimits::from_config(&self.config).max_size,
+                );
+                match self.read_data(reader, max_size).await {
+                    Ok(data) if self.lmtp => {
+                        let protocol = if tls_established { "LMTPS" } else { "LMTP" };
+                        let replies = self
+                            .lmtp_delivery(envelope, &data, protocol, &size_limits)
+                            .await;
+                        for (code, reply) in replies {
+                            self.send_response(writer, code, &reply).await?;
+                        }
+                    }
                     Ok(data) => {
-                        // Process the message
-                        match self.process_message(envelope, &data).await {
-                            Ok(message_id) => {
+                        let (verdict, changes) = milters.end_of_message(&data).await;
+                        let data = apply_header_changes(&data, &changes);
+                        match verdict {
+                            MilterVerdict::Reply(code, reply) => {
                                 info!(
-                                    "Message {} accepted from {} for {:?}",
-                                    message_id, self.peer_addr, envelope.to
+                                    "Message from {} refused by a milter: {} {}",
+                                    self.peer_addr, code, reply
                                 );
-                                self.send_response(
-                                    writer,
-                                    250,
-                                    &format!("2.0.0 OK: queued as {}", message_id),
-                                )
-                                .await?;
+                                self.send_response(writer, code, &reply).await?;
                             }
-                            Err(e) => {
-                                warn!("Failed to process message: {}", e);
-                                self.send_response(writer, 451, "4.3.0 Temporary error")
-                                    .await?;
+                            MilterVerdict::Discard => {
+                                info!("Message from {} discarded by a milter", self.peer_addr);
+                                self.send_response(writer, 250, "2.0.0 OK").await?;
+                            }
+                            MilterVerdict::Continue => {
+                                let protocol = received_protocol(tls_established, *authenticated);
+                                let (code, reply) = self
+                                    .accept_message(
+                                        envelope,
+                                        &data,
+                                        protocol,
+                                        authenticated_user.as_ref(),
+                                        &size_limits,
+                                    )
+                                    .await;
+                                self.send_response(writer, code, &reply).await?;
                             }
                         }
                     }
                     Err(e) => {
-                        warn!("Failed to read message data: {}", e);
-                        self.send_response(writer, 451, "4.3.0 Error reading message")
-                            .await?;
+                        milters.abort().await;
+                        let (code, message) = match e.downcast_ref::<DataError>() {
+                            Some(rejection) => {
+                                info!("Message from {} refused: {}", self.peer_addr, rejection);
+                                rejection.reply()
+                            }
+                            None => {
+                                warn!("Failed to read message data: {}", e);
+                                (451, "4.3.0 Error reading message")
+                            }
+                        };
+                        // LMTP answers for each recipient (RFC 2033 section 4.2)
+                        let replies = if self.lmtp { envelope.to.len() } else { 1 };
+                        for _ in 0..replies {
+                            self.send_response(writer, code, message).await?;
+                        }
                     }
                 }
 
@@ -552,11 +1312,20 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                 *state = SessionState::Greeted;
                 envelope.from = None;
                 envelope.to.clear();
+                envelope.require_tls = false;
+                envelope.size = None;
+                envelope.relay_to.clear();
+                envelope.srs_to.clear();
             }
 
             "RSET" => {
+                milters.abort().await;
                 envelope.from = None;
                 envelope.to.clear();
+                envelope.require_tls = false;
+                envelope.size = None;
+                envelope.relay_to.clear();
+                envelope.srs_to.clear();
                 if *state != SessionState::Connected {
                     *state = SessionState::Greeted;
                 }
@@ -591,143 +1360,1986 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         Ok(CommandResult::Continue)
     }
 
-    /// Read message data until <CRLF>.<CRLF>
-    async fn read_data<R: AsyncBufRead + Unpin>(&self, reader: &mut R) -> Result<Vec<u8>> {
-        let mut data = Vec::new();
-        let mut line = String::new();
-        let max_size = self.config.max_message_size.unwrap_or(52_428_800); // 50MB default
+    /// Read message data of up to `max_size` bytes until <CRLF>.<CRLF>
+    async fn read_data<R: AsyncBufRead + Unpin>(
+        &self,
+        reader: &mut R,
+        max_size: usize,
+    ) -> Result<Vec<u8>> {
+        read_data(reader, max_size, self.config.max_line_length).await
+    }
 
-        loop {
-            line.clear();
-            let bytes_read = reader.read_line(&mut line).await?;
+    /// Accept a message received over SMTP, with the reply to its data
+    ///
+    /// Recipients whose domain takes smaller messages than this one are
+    /// returned to the sender in a DSN; the others get the message. The
+    /// message is refused when no recipient is left.
+    async fn accept_message(
+        &self,
+        envelope: &Envelope,
+        data: &[u8],
+        protocol: &str,
+        authenticated_user: Option<&User>,
+        size_limits: &[RecipientSizeLimit],
+    ) -> (u16, String) {
+        let oversized = oversized_recipients(size_limits, data.len());
+        let within_limits;
+        let envelope = if oversized.is_empty() {
+            envelope
+        } else {
+            within_limits = Envelope {
+                to: envelope
+                    .to
+                    .iter()
+                    .filter(|recipient| !is_oversized(&oversized, recipient))
+                    .cloned()
+                    .collect(),
+                ..envelope.clone()
+            };
+            &within_limits
+        };
+        if envelope.to.is_empty() && envelope.relay_to.is_empty() && envelope.srs_to.is_empty() {
+            info!(
+                "Message from {} refused: larger than the domains of its recipients accept",
+                self.peer_addr
+            );
+            return (552, DOMAIN_SIZE_REPLY.to_string());
+        }
 
-            if bytes_read == 0 {
-                return Err(anyhow::anyhow!("Connection closed during DATA"));
+        match self
+            .process_message(envelope, data, protocol, authenticated_user)
+            .await
+        {
+            Ok(message_id) => {
+                info!(
+                    "Message {} accepted from {} for {:?}",
+                    message_id, self.peer_addr, envelope.to
+                );
+                if !oversized.is_empty() {
+                    self.bounce_oversized(envelope, message_id, data, &oversized)
+                        .await;
+                }
+                (250, format!("2.0.0 OK: queued as {}", message_id))
             }
+            Err(e) => message_error_reply(&e),
+        }
+    }
 
-            // Check for end of data
-            if line.trim() == "." {
-                break;
+    /// Return a DSN for the recipients whose domain refused the size of an
+    /// accepted message
+    ///
+    /// Each tenant returns the message for its own recipients, so the DSN is
+    /// queued and counted like the tenant's other mail.
+    async fn bounce_oversized(
+        &self,
+        envelope: &Envelope,
+        message_id: Uuid,
+        data: &[u8],
+        oversized: &[&RecipientSizeLimit],
+    ) {
+        let failure = DeliveryError::Rejected(RemoteReply {
+            host: self.config.hostname.clone(),
+            stage: "DATA".to_string(),
+            code: 552,
+            text: DOMAIN_SIZE_REPLY.to_string(),
+        });
+        // Only recipients of local domains have a limit below the server's
+        let mut tenants: Vec<(TenantId, Vec<(String, DeliveryError)>)> = Vec::new();
+        for limit in oversized {
+            let Some(tenant_id) = limit.tenant_id else {
+                continue;
+            };
+            let failure = (limit.recipient.to_string(), failure.clone());
+            match tenants.iter_mut().find(|(tenant, _)| *tenant == tenant_id) {
+                Some((_, failures)) => failures.push(failure),
+                None => tenants.push((tenant_id, vec![failure])),
+            }
+        }
+
+        for (tenant_id, failures) in tenants {
+            info!(
+                "Message {} too large for {:?}, returned to the sender",
+                message_id,
+                failures.iter().map(|(rcpt, _)| rcpt).collect::<Vec<_>>()
+            );
+            let job = DeliveryJob {
+                message_id,
+                tenant_id,
+                from: envelope
+                    .from
+                    .as_ref()
+                    .map(|from| from.to_string())
+                    .unwrap_or_default(),
+                to: failures.iter().map(|(rcpt, _)| rcpt.clone()).collect(),
+                storage_path: String::new(),
+                raw_message_base64: None,
+                require_tls: envelope.require_tls,
+                priority: 0,
+                lane: DeliveryLane::Transactional,
+                archived: true,
+                dkim_signed: false,
+                forwarded: false,
+                queued_at: None,
+            };
+            if let Err(e) = self.queue_manager.send_bounce(&job, data, &failures).await {
+                warn!(
+                    "Failed to return message {} to its sender: {}",
+                    message_id, e
+                );
+            }
+        }
+    }
+
+    /// Deliver a message received over LMTP, with a reply for each
+    /// recipient in RCPT order
+    ///
+    /// Recipients whose domain takes smaller messages, or whose mailbox has
+    /// run out of room, are refused on their own; the message is stored
+    /// once for the others.
+    async fn lmtp_delivery(
+        &self,
+        envelope: &Envelope,
+        data: &[u8],
+        protocol: &str,
+        size_limits: &[RecipientSizeLimit],
+    ) -> Vec<(u16, String)> {
+        let mut deliverable = envelope.clone();
+        deliverable.to.clear();
+        let mut refusals = Vec::with_capacity(envelope.to.len());
+        let oversized = oversized_recipients(size_limits, data.len());
+        for recipient in &envelope.to {
+            if is_oversized(&oversized, recipient) {
+                info!("Refusing mail for {}: message too large", recipient);
+                refusals.push(Some((552, DOMAIN_SIZE_REPLY.to_string())));
+                continue;
             }
+            let verdict = self.quota_verdict(recipient, data.len() as u64).await;
+            match verdict.reply() {
+                Some((code, reply)) => {
+                    info!("Refusing mail for {}: mailbox over quota", recipient);
+                    refusals.push(Some((code, reply.to_string())));
+                }
+                None => {
+                    deliverable.to.push(recipient.clone());
+                    refusals.push(None);
+                }
+            }
+        }
+
+        if deliverable.to.is_empty() {
+            return refusals.into_iter().flatten().collect();
+        }
 
-            // Dot-stuffing: remove leading dot if followed by another dot
-            let line_bytes = if line.starts_with("..") {
-                &line.as_bytes()[1..]
+        let delivered = match self
+            .process_message(&deliverable, data, protocol, None)
+            .await
+        {
+            Ok(message_id) => {
+                info!(
+                    "Message {} delivered from {} for {:?}",
+                    message_id, self.peer_addr, deliverable.to
+                );
+                (250, format!("2.0.0 OK: delivered as {}", message_id))
+            }
+            Err(e) => message_error_reply(&e),
+        };
+        refusals
+            .into_iter()
+            .map(|refusal| refusal.unwrap_or_else(|| delivered.clone()))
+            .collect()
+    }
+
+    /// Process and store a received message
+    async fn process_message(
+        &self,
+        envelope: &Envelope,
+        data: &[u8],
+        protocol: &str,
+        authenticated_user: Option<&User>,
+    ) -> Result<Uuid> {
+        let message_id = Uuid::now_v7();
+
+        // Authenticated users may only send from their own addresses
+        let checked = match authenticated_user {
+            Some(user) => {
+                SenderVerifier::new(self.db_pool.clone(), &self.config)
+                    .check_message(user, data)
+                    .await?
+            }
+            None => Cow::Borrowed(data),
+        };
+        // BIMI headers are only ever this server's
+        let stripped = bimi::strip_headers(&checked);
+        let data: &[u8] = &stripped;
+
+        // Trace the hop with a Received header naming this listener's host
+        let received = received_header(
+            envelope.helo.as_deref(),
+            self.peer_addr,
+            &self.config.hostname,
+            protocol,
+            message_id,
+            Utc::now(),
+        );
+        let unstamped = data;
+        let mut stamped = Vec::with_capacity(received.len() + data.len());
+        stamped.extend_from_slice(received.as_bytes());
+        stamped.extend_from_slice(data);
+        let data = stamped.as_slice();
+
+        // Parse message headers
+        let parsed = mail_parser::MessageParser::default()
+            .parse(data)
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse message"))?;
+
+        let subject = parsed.subject().map(|s| s.to_string());
+        let from_header = parsed.from().and_then(|a| a.first()).map(|a| {
+            if let Some(email) = a.address() {
+                email.to_string()
             } else {
-                line.as_bytes()
+                String::new()
+            }
+        });
+        let from_name = parsed
+            .from()
+            .and_then(|a| a.first())
+            .and_then(|a| a.name())
+            .map(|s| s.to_string());
+        let reply_to = parsed
+            .reply_to()
+            .and_then(|a| a.first())
+            .and_then(|a| a.address())
+            .map(|s| s.to_string());
+        let message_id_header = parsed.message_id().map(|s| s.to_string());
+
+        // Perform email authentication checks
+        let auth_result = self
+            .verify_email_authentication(envelope, data, authenticated_user.is_none())
+            .await;
+
+        // The sender's logo goes below the Received header
+        let bimi_headers = auth_result
+            .bimi
+            .as_ref()
+            .map(|bimi| bimi.headers())
+            .unwrap_or_default();
+        let with_bimi;
+        let data = if bimi_headers.is_empty() {
+            data
+        } else {
+            with_bimi = [received.as_bytes(), bimi_headers.as_bytes(), unstamped].concat();
+            with_bimi.as_slice()
+        };
+
+        // Preview and snippet from the decoded body text
+        let preview = MessagePreview::of_message(&parsed);
+
+        // Spam verdict, shared by all recipients. Behind LMTP, the client
+        // is the one the MTA in front passed on with XFORWARD.
+        let client_ip = envelope
+            .client_ip
+            .clone()
+            .unwrap_or_else(|| self.peer_addr.ip().to_string());
+        let sender = envelope.from.as_ref().map(|f| f.to_string());
+        let recipients: Vec<String> = envelope.to.iter().map(|r| r.to_string()).collect();
+        let (spam, sender_reputation) = self
+            .spam_verdict(
+                envelope,
+                data,
+                unstamped,
+                &recipients,
+                &client_ip,
+                authenticated_user,
+            )
+            .await?;
+
+        self.check_quotas(envelope, data.len() as u64).await?;
+
+        // Virus scan, shared by the recipients whose tenant scans its mail
+        let (virus_scan, antivirus_actions) = self.virus_scan(envelope, unstamped).await?;
+
+        // Country and autonomous system of the client, for policies
+        let geo = match (&self.geoip, client_ip.parse::<IpAddr>()) {
+            (Some(geoip), Ok(ip)) => geoip.lookup(ip),
+            _ => GeoIpInfo::default(),
+        };
+        // Filters match the From header as the sender, like users see it
+        let filter_context = PolicyContext::for_inbound(
+            Uuid::nil(),
+            None,
+            from_header
+                .clone()
+                .filter(|from| !from.is_empty())
+                .or_else(|| sender.clone()),
+            recipients.clone(),
+        )
+        .with_subject(subject.clone())
+        .with_headers(crate::imap::search::stored_headers(data))
+        .with_message_size(data.len() as i64)
+        .with_spam_score(Some(spam.score))
+        .with_client_ip(Some(client_ip.clone()))
+        .with_country(geo.country.clone())
+        .with_asn(geo.asn)
+        .with_authentication(&auth_result)
+        .with_attachment_types(attachment_types(data));
+
+        let message = InboundMessage {
+            message_id,
+            envelope,
+            data,
+            authenticated_user,
+            sender,
+            recipients,
+            client_ip,
+            subject,
+            from_header,
+            from_name,
+            reply_to,
+            message_id_header,
+            attachment_count: parsed.attachment_count(),
+            preview,
+            auth_result,
+            spam,
+            sender_reputation,
+            virus_scan,
+            antivirus_actions,
+            geo,
+            filter_context,
+            received_at: Utc::now(),
+        };
+        let mut deliveries = RecipientDeliveries::new(self.db_pool.clone());
+        self.apply_inbound_policies(&message, &mut deliveries.policies)
+            .await?;
+
+        // For each recipient, store the message
+        for recipient in &envelope.to {
+            self.deliver_to_recipient(&message, recipient, &mut deliveries)
+                .await?;
+        }
+
+        self.journal_inbound(&message, deliveries.journaled).await;
+        self.relay_message(&message).await?;
+        self.return_to_srs_senders(&message).await?;
+
+        Ok(message_id)
+    }
+
+    /// Spam verdict shared by all recipients, and the reputation of the
+    /// sender domain before this message
+    ///
+    /// Soft rejects are enforced by greylisting the message, and rejected
+    /// spam is refused before any copy is stored.
+    async fn spam_verdict(
+        &self,
+        envelope: &Envelope,
+        data: &[u8],
+        unstamped: &[u8],
+        recipients: &[String],
+        client_ip: &str,
+        authenticated_user: Option<&User>,
+    ) -> Result<(SpamCheckResult, Option<f64>)> {
+        let sender = envelope.from.as_ref().map(|f| f.to_string());
+        let recipient_refs: Vec<&str> = recipients.iter().map(String::as_str).collect();
+        let mut spam = self
+            .spam_filter
+            .check(
+                data,
+                sender.as_deref(),
+                &recipient_refs,
+                Some(client_ip),
+                envelope.helo.as_deref(),
+            )
+            .await;
+        if let (Some(dnsbl), None) = (&self.dnsbl, authenticated_user) {
+            dnsbl.check(self.peer_addr.ip()).await.add_to(&mut spam);
+        }
+        if let (Some(uribl), None) = (&self.uribl, authenticated_user) {
+            uribl.check(data).await.add_to(&mut spam);
+        }
+
+        // Reputation of the sender domain before this message; domains not
+        // seen yet have a clean one
+        let sender_domain = envelope
+            .from
+            .as_ref()
+            .filter(|_| authenticated_user.is_none())
+            .map(|from| from.domain.as_str());
+        let sender_reputation = match (&self.sender_reputation, sender_domain) {
+            (Some(tracker), Some(domain)) if tracker.enabled() => {
+                let reputation = tracker.score(domain).await;
+                if let Some(reputation) = &reputation {
+                    tracker.add_to(reputation, &mut spam);
+                }
+                Some(reputation.map_or(CLEAN_SCORE, |reputation| reputation.score))
+            }
+            _ => None,
+        };
+
+        // Soft rejects are enforced by greylisting the message
+        if let (SpamAction::SoftReject, Some(greylist), None) =
+            (spam.action, &self.greylist, authenticated_user)
+        {
+            let verdict = greylist
+                .check_message(
+                    self.peer_addr.ip(),
+                    sender.as_deref().unwrap_or_default(),
+                    unstamped,
+                )
+                .await?;
+            if let GreylistVerdict::Defer(retry_after) = verdict {
+                info!(
+                    "Deferring message from {} soft-rejected by the spam filter (score {:.1})",
+                    self.peer_addr, spam.score
+                );
+                return Err(GreylistDeferral(retry_after).into());
+            }
+        }
+
+        if let (Some(tracker), Some(domain)) = (&self.sender_reputation, sender_domain) {
+            tracker.record_message(domain, spam.is_spam).await;
+        }
+
+        // Rejected spam is refused before any copy is stored
+        if spam.action == SpamAction::Reject {
+            info!(
+                "Refusing message from {} rejected by the spam filter (score {:.1})",
+                self.peer_addr, spam.score
+            );
+            return Err(SpamRejection(spam.score).into());
+        }
+
+        Ok((spam, sender_reputation))
+    }
+
+    /// Refuse a message unless every local mailbox has room for it
+    ///
+    /// A permanent refusal after DATA applies to all recipients, so it is
+    /// only given when the full mailbox is the only one.
+    async fn check_quotas(&self, envelope: &Envelope, size: u64) -> Result<()> {
+        let mut over_quota = QuotaVerdict::Within;
+        for recipient in &envelope.to {
+            over_quota = over_quota.max(self.quota_verdict(recipient, size).await);
+        }
+        if over_quota == QuotaVerdict::Within {
+            return Ok(());
+        }
+        let sole_recipient =
+            envelope.to.len() == 1 && envelope.relay_to.is_empty() && envelope.srs_to.is_empty();
+        let verdict = if sole_recipient {
+            over_quota
+        } else {
+            QuotaVerdict::Grace
+        };
+        info!(
+            "Refusing message from {}: a recipient's mailbox is over quota",
+            self.peer_addr
+        );
+        Err(QuotaRejection(verdict).into())
+    }
+
+    /// Evaluate the inbound policies of the recipients' tenants and domains
+    ///
+    /// Policies may refuse the whole message, and count it once against
+    /// their rate limits, before any copy is stored; a limit that is
+    /// exceeded defers the message. The policies that matched are recorded
+    /// with what happened to the message.
+    async fn apply_inbound_policies(
+        &self,
+        message: &InboundMessage<'_>,
+        delivery_policies: &mut HashMap<(TenantId, DomainId), DeliveryPolicy>,
+    ) -> Result<()> {
+        let mut policy_rate_limits: Vec<PolicyRateLimit> = Vec::new();
+        let mut matched_policies: Vec<MatchedPolicies> = Vec::new();
+        let matched_message = MatchedMessage {
+            message_id: message.message_id_header.as_deref(),
+            sender: message.sender.as_deref(),
+            client_ip: &message.client_ip,
+        };
+        for recipient in &message.envelope.to {
+            let domain = match self.local_domain(&recipient.domain).await {
+                Ok(Some(domain)) => domain,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Failed to look up domain of {}: {}", recipient, e);
+                    continue;
+                }
+            };
+            let policy = self
+                .delivery_policy(
+                    delivery_policies,
+                    domain.tenant_id,
+                    domain.id,
+                    &message.filter_context,
+                )
+                .await;
+            let key = (domain.tenant_id, domain.id);
+            match matched_policies
+                .iter_mut()
+                .find(|matched| matched.key == key)
+            {
+                Some(matched) => matched.recipients.push(recipient.to_string()),
+                None => matched_policies.push(MatchedPolicies {
+                    key,
+                    policy: policy.clone(),
+                    recipients: vec![recipient.to_string()],
+                }),
+            }
+            if let Some(rejection) = policy.rejection {
+                info!(
+                    "Refusing message from {} for {}: {}",
+                    self.peer_addr, recipient, rejection.message
+                );
+                self.record_policy_matches(
+                    &matched_policies,
+                    &matched_message,
+                    Some(rejection.reply()),
+                )
+                .await;
+                return Err(rejection.into());
+            }
+            for limit in policy.rate_limits {
+                if !policy_rate_limits.contains(&limit) {
+                    policy_rate_limits.push(limit);
+                }
+            }
+        }
+        let policy_rate_limiter = PolicyRateLimiter::new(self.db_pool.clone());
+        for limit in &policy_rate_limits {
+            match policy_rate_limiter
+                .check(limit, &message.filter_context)
+                .await
+            {
+                Ok(None) => {}
+                Ok(Some(deferral)) => {
+                    info!(
+                        "Deferring message from {}: rate limit of policy {} exceeded",
+                        self.peer_addr, limit.policy_id
+                    );
+                    self.record_policy_matches(
+                        &matched_policies,
+                        &matched_message,
+                        Some(deferral.reply()),
+                    )
+                    .await;
+                    return Err(deferral.into());
+                }
+                Err(e) => warn!(
+                    "Rate limit check of policy {} failed: {}",
+                    limit.policy_id, e
+                ),
+            }
+        }
+        self.record_policy_matches(&matched_policies, &matched_message, None)
+            .await;
+        Ok(())
+    }
+
+    /// Deliver a message to one local recipient: a VERP bounce goes to the
+    /// bounce processor, mail to a report address to the received reports,
+    /// and other mail to the recipient's inbound route and mailbox
+    async fn deliver_to_recipient(
+        &self,
+        message: &InboundMessage<'_>,
+        recipient: &EmailAddress,
+        deliveries: &mut RecipientDeliveries,
+    ) -> Result<()> {
+        // Bounces to a VERP return path go to the bounce processor
+        if let Some(scheduled_id) = self.verp_message_id(recipient) {
+            self.process_bounce(recipient, scheduled_id, message.data)
+                .await?;
+            deliveries.journaled.push(JournalRecipient::new(
+                recipient.to_string(),
+                RecipientDisposition::Bounce,
+            ));
+            return Ok(());
+        }
+
+        // Mail to a report address is parsed into the received reports
+        if let Some(domain) = self.report_domain(recipient).await? {
+            self.ingest_reports(message, recipient, &domain).await;
+            deliveries.journaled.push(JournalRecipient::new(
+                recipient.to_string(),
+                RecipientDisposition::Report,
+            ));
+            return Ok(());
+        }
+
+        // Recipients with an inbound route are posted to its webhook
+        if let Some(route) = InboundRouteRepository::new(self.db_pool.clone())
+            .find_for_address(&recipient.local, &recipient.domain)
+            .await?
+        {
+            self.post_to_inbound_route(message, recipient, &route)
+                .await?;
+            deliveries.journaled.push(
+                JournalRecipient::new(recipient.to_string(), RecipientDisposition::Webhook)
+                    .with_detail(route.id.to_string()),
+            );
+            if !route.store_message {
+                return Ok(());
+            }
+        }
+
+        // Find the mailbox for this recipient; unknown local parts go to
+        // the domain's catch-all mailbox, if any
+        let (mailbox, subaddress, catch_all) = match self.find_mailbox(recipient).await? {
+            Some((mb, subaddress)) => (mb, subaddress, false),
+            None => match self.catch_all_mailbox(recipient).await? {
+                Some(mb) => (mb, None, true),
+                None => {
+                    warn!("Mailbox not found for {}", recipient);
+                    deliveries.journaled.push(JournalRecipient::new(
+                        recipient.to_string(),
+                        RecipientDisposition::NoMailbox,
+                    ));
+                    return Ok(());
+                }
+            },
+        };
+        if catch_all {
+            // Several unknown recipients of one message get a single copy
+            if !deliveries.catch_all_delivered.insert(mailbox.id) {
+                deliveries.journaled.push(
+                    JournalRecipient::new(recipient.to_string(), RecipientDisposition::Mailbox)
+                        .with_detail(CATCH_ALL_TAG.to_string()),
+                );
+                return Ok(());
+            }
+            info!(
+                "Delivering message {} for {} to catch-all mailbox {}",
+                message.message_id, recipient, mailbox.address
+            );
+        }
+
+        self.store_in_mailbox(
+            message, recipient, &mailbox, subaddress, catch_all, deliveries,
+        )
+        .await
+    }
+
+    /// Record a bounce received on the VERP return path of a scheduled
+    /// message, for the tenant of the receiving domain
+    async fn process_bounce(
+        &self,
+        recipient: &EmailAddress,
+        scheduled_id: Uuid,
+        data: &[u8],
+    ) -> Result<()> {
+        match self.local_domain(&recipient.domain).await? {
+            Some(domain) => {
+                if let Err(e) = BounceProcessor::new(self.db_pool.clone())
+                    .process(scheduled_id, domain.tenant_id, data)
+                    .await
+                {
+                    warn!("Failed to process bounce for {}: {}", recipient, e);
+                }
+            }
+            None => warn!("Bounce for {} received at an unknown domain", recipient),
+        }
+        Ok(())
+    }
+
+    /// Store the DMARC and TLS reports of a message sent to a domain's
+    /// report address
+    async fn ingest_reports(
+        &self,
+        message: &InboundMessage<'_>,
+        recipient: &EmailAddress,
+        domain: &Domain,
+    ) {
+        match ReportIngester::new(self.db_pool.clone())
+            .ingest(domain, message.data)
+            .await
+        {
+            Ok(summary) => info!(
+                "Stored {} DMARC and {} TLS reports from message {} for {} ({} skipped)",
+                summary.dmarc_reports,
+                summary.tls_reports,
+                message.message_id,
+                recipient,
+                summary.skipped
+            ),
+            Err(e) => warn!("Failed to store reports for {}: {}", recipient, e),
+        }
+    }
+
+    /// Queue a message for the webhook of a recipient's inbound route
+    async fn post_to_inbound_route(
+        &self,
+        message: &InboundMessage<'_>,
+        recipient: &EmailAddress,
+        route: &InboundRoute,
+    ) -> Result<()> {
+        let auth_result = &message.auth_result;
+        let storage_path = inbound_storage_path(route.tenant_id, route.id, message.message_id);
+        self.file_storage.store(&storage_path, message.data).await?;
+        let job = InboundJob {
+            route_id: route.id,
+            tenant_id: route.tenant_id,
+            message_id: message.message_id,
+            storage_path,
+            envelope_from: message.sender.clone(),
+            recipient: recipient.to_string(),
+            auth: InboundAuth {
+                spf: Some(auth_result.spf.as_header_value().to_string()),
+                dkim: Some(auth_result.dkim.as_header_value().to_string()),
+                dmarc: Some(auth_result.dmarc.as_header_value().to_string()),
+            },
+            spam_score: Some(message.spam.score),
+            received_at: message.received_at,
+        };
+        job.enqueue(&self.db_pool).await?;
+        info!(
+            "Message {} for {} queued for inbound route {}",
+            message.message_id, recipient, route.id
+        );
+        Ok(())
+    }
+
+    /// Store a copy of a message in a recipient's mailbox, after the
+    /// owner's filters and the verdicts of the owner's tenant
+    async fn store_in_mailbox(
+        &self,
+        message: &InboundMessage<'_>,
+        recipient: &EmailAddress,
+        mailbox: &Mailbox,
+        subaddress: Option<String>,
+        catch_all: bool,
+        deliveries: &mut RecipientDeliveries,
+    ) -> Result<()> {
+        let message_id = message.message_id;
+        let (spam, phishing) = self
+            .tenant_verdict(message, mailbox.tenant_id, deliveries)
+            .await;
+
+        // The owner's filters
+        let filtered = MailboxFilters::new(self.db_pool.clone())
+            .run(mailbox, &message.filter_context)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Filters of {} failed for message {}: {}",
+                    recipient, message_id, e
+                );
+                FilterOutcome::default()
+            });
+        // The virus verdict, when the owner's tenant scans its mail
+        let virus = match (
+            &message.virus_scan,
+            message.antivirus_actions.get(&mailbox.tenant_id),
+        ) {
+            (Some(verdict), Some(action)) => Some((verdict, *action)),
+            _ => None,
+        };
+        let infected = virus.is_some_and(|(verdict, _)| verdict.is_infected());
+
+        // Inbound policies of the owner's tenant and domain
+        let policy = self
+            .delivery_policy(
+                &mut deliveries.policies,
+                mailbox.tenant_id,
+                mailbox.domain_id,
+                &message.filter_context,
+            )
+            .await;
+
+        // Messages held by the virus scan, the spam filter or an inbound
+        // policy go to the mailbox's quarantine
+        let quarantine = match virus {
+            Some((ScanVerdict::Infected(virus_name), AntivirusAction::Quarantine)) => {
+                Some((QuarantineReason::Virus, virus_name.clone()))
+            }
+            _ if spam.action.quarantines() => {
+                Some((QuarantineReason::Spam, format!("score {:.1}", spam.score)))
+            }
+            _ => policy
+                .quarantine
+                .clone()
+                .map(|policy| (QuarantineReason::Policy, policy)),
+        };
+
+        // Forwarded copies are sent as received, but spam, viruses and
+        // quarantined messages are not forwarded
+        if !filtered.forward_to.is_empty() && !spam.is_spam && !infected && quarantine.is_none() {
+            self.forward_copy(message, recipient, mailbox, &filtered.forward_to)
+                .await;
+        }
+        if filtered.delete {
+            info!(
+                "Message {} for {} deleted by a filter",
+                message_id, recipient
+            );
+            deliveries.journaled.push(JournalRecipient::new(
+                recipient.to_string(),
+                RecipientDisposition::Discarded,
+            ));
+            return Ok(());
+        }
+
+        let (target_mailbox_id, junk_mailbox_id) = self
+            .target_folder(
+                recipient,
+                mailbox,
+                quarantine.is_some(),
+                spam.action.files_as_junk(),
+                filtered.folder.as_deref(),
+                subaddress.as_deref(),
+            )
+            .await?;
+
+        let mut metadata = message.metadata(&self.config.hostname);
+        if let Some(tag) = &subaddress {
+            metadata["subaddress"] = serde_json::json!(tag);
+        }
+        if catch_all {
+            metadata["original_recipient"] = serde_json::json!(recipient.to_string());
+        }
+        if !filtered.matched.is_empty() {
+            metadata["filters"] = serde_json::json!(filtered.matched);
+        }
+        if let Some((verdict, action)) = virus {
+            metadata["antivirus"] = verdict.metadata(action);
+        }
+        if let Some((reason, detail)) = &quarantine {
+            metadata["quarantine"] = quarantine_metadata(*reason, detail, mailbox.id);
+        }
+        if junk_mailbox_id.is_some() {
+            metadata["junk"] = junk_metadata(&spam, mailbox.id);
+            info!(
+                "Filing message {} for {} as junk (score {:.1})",
+                message_id, recipient, spam.score
+            );
+        }
+
+        let mut tags = Vec::new();
+        if spam.is_spam {
+            tags.push("spam");
+        }
+        if catch_all {
+            tags.push(CATCH_ALL_TAG);
+        }
+        if infected {
+            tags.push(VIRUS_TAG);
+        }
+        tags.extend(filtered.tags.iter().map(String::as_str));
+        let warnings = self
+            .copy_warnings(
+                message,
+                recipient,
+                mailbox.tenant_id,
+                &phishing,
+                deliveries,
+                &mut tags,
+            )
+            .await;
+        if !warnings.is_empty() {
+            metadata["warnings"] = serde_json::json!(warnings);
+        }
+
+        let (delivered, stripped) = self
+            .mailbox_copy(
+                message,
+                recipient,
+                mailbox,
+                &policy,
+                &phishing,
+                catch_all,
+                deliveries,
+                &mut metadata,
+            )
+            .await;
+        let delivered: &[u8] = &delivered;
+
+        // Store the raw message to file storage
+        let storage_path =
+            self.file_storage
+                .message_path(mailbox.tenant_id, target_mailbox_id, message_id);
+
+        self.file_storage.store(&storage_path, delivered).await?;
+
+        // Create message record
+        let stored = Message {
+            id: message_id,
+            tenant_id: mailbox.tenant_id,
+            mailbox_id: target_mailbox_id,
+            message_id_header: message.message_id_header.clone(),
+            subject: message.subject.clone(),
+            from_address: message.from_header.clone(),
+            to_addresses: serde_json::to_value(&message.envelope.to)?,
+            cc_addresses: None,
+            headers: crate::imap::search::stored_headers(delivered),
+            body_preview: message.preview.body_preview.clone(),
+            body_size: delivered.len() as i64,
+            has_attachments: message.attachment_count > stripped,
+            storage_path: storage_path.clone(),
+            seen: filtered.mark_read,
+            answered: false,
+            flagged: false,
+            deleted: false,
+            draft: false,
+            spam_score: Some(spam.score),
+            tags: serde_json::json!(tags),
+            metadata,
+            received_at: Utc::now(),
+            created_at: Utc::now(),
+            uid: None,
+            keywords: serde_json::json!([]),
+            sent_date: crate::imap::search::sent_date(delivered),
+            snippet: Some(message.preview.snippet.clone()),
+        };
+
+        // Store in database
+        let message_repo = MessageRepository::new(self.db_pool.clone());
+        message_repo.create(&stored).await?;
+
+        // Held messages are not delivered, so nothing else happens to them
+        if let Some((reason, detail)) = quarantine {
+            QuarantineRepository::new(self.db_pool.clone())
+                .mark(mailbox.tenant_id, message_id)
+                .await?;
+            info!(
+                "Message {} for {} quarantined ({}: {})",
+                message_id,
+                recipient,
+                reason.as_str(),
+                detail
+            );
+            deliveries.journaled.push(
+                JournalRecipient::new(recipient.to_string(), RecipientDisposition::Quarantined)
+                    .with_detail(detail),
+            );
+            return Ok(());
+        }
+
+        let disposition = if junk_mailbox_id.is_some() {
+            RecipientDisposition::Junk
+        } else {
+            RecipientDisposition::Mailbox
+        };
+        deliveries
+            .journaled
+            .push(JournalRecipient::new(recipient.to_string(), disposition));
+
+        // Remember the sender for the owner's composer autocomplete,
+        // but not from spam
+        let learn_from = if spam.is_spam {
+            None
+        } else {
+            message.from_header.as_deref()
+        };
+        if let (Some(user_id), Some(from)) = (mailbox.user_id, learn_from) {
+            if let Err(e) = CorrespondentRepository::new(self.db_pool.clone())
+                .record(
+                    mailbox.tenant_id,
+                    user_id,
+                    from,
+                    message.from_name.as_deref(),
+                )
+                .await
+            {
+                warn!("Failed to record correspondent {}: {}", from, e);
+            }
+        }
+
+        // Execute post_receive hooks
+        if let Err(e) = self
+            .hook_manager
+            .execute_post_receive(mailbox.tenant_id, &stored, delivered)
+            .await
+        {
+            warn!("Hook execution failed for message {}: {}", message_id, e);
+        }
+        Ok(())
+    }
+
+    /// The spam verdict with the spam rules of a tenant, and the phishing
+    /// signals against it
+    async fn tenant_verdict(
+        &self,
+        message: &InboundMessage<'_>,
+        tenant_id: TenantId,
+        deliveries: &mut RecipientDeliveries,
+    ) -> (SpamCheckResult, PhishingReport) {
+        if let Some(verdict) = deliveries.tenant_verdicts.get(&tenant_id) {
+            return verdict.clone();
+        }
+        let mut verdict = self
+            .spam_filter
+            .apply_tenant_rules(&message.spam, tenant_id, message.data)
+            .await;
+        let phishing = match message.authenticated_user {
+            None => PhishingAnalyzer::new(self.db_pool.clone())
+                .check(
+                    tenant_id,
+                    message.from_name.as_deref(),
+                    message.from_header.as_deref(),
+                    message.reply_to.as_deref(),
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Phishing check for message {} failed: {}",
+                        message.message_id, e
+                    );
+                    PhishingReport::default()
+                }),
+            Some(_) => PhishingReport::default(),
+        };
+        phishing.add_to(&mut verdict);
+        deliveries
+            .tenant_verdicts
+            .insert(tenant_id, (verdict.clone(), phishing.clone()));
+        (verdict, phishing)
+    }
+
+    /// Forward a copy to the addresses a filter of the recipient's mailbox
+    /// chose, sent as received and sealed with ARC by the recipient's domain
+    async fn forward_copy(
+        &self,
+        message: &InboundMessage<'_>,
+        recipient: &EmailAddress,
+        mailbox: &Mailbox,
+        forward_to: &[String],
+    ) {
+        let sealed = DkimKeyring::new(self.db_pool.clone())
+            .try_arc_seal(
+                mailbox.tenant_id,
+                &recipient.domain,
+                message.data,
+                &message.auth_result.to_header(&self.config.hostname),
+                message.auth_result.arc.result,
+            )
+            .await;
+        let job = forward_job(
+            message.message_id,
+            mailbox.tenant_id,
+            message.sender.clone().unwrap_or_default(),
+            forward_to.to_vec(),
+            message.data,
+            sealed,
+            message.envelope.require_tls,
+        );
+        match self.queue_manager.enqueue_delivery(job).await {
+            Ok(_) => info!(
+                "Message {} for {} forwarded to {:?} by a filter",
+                message.message_id, recipient, forward_to
+            ),
+            Err(e) => warn!(
+                "Failed to queue message {} for {} to {:?}: {}",
+                message.message_id, recipient, forward_to, e
+            ),
+        }
+    }
+
+    /// Folder a recipient's copy is stored in, and the Junk folder when it
+    /// is filed as junk
+    ///
+    /// Quarantined copies go to the mailbox's quarantine, flagged ones to
+    /// the owner's Junk folder if they have one, then to the folder a
+    /// filter chose and the folder named after the subaddress tag, if the
+    /// owner has them.
+    async fn target_folder(
+        &self,
+        recipient: &EmailAddress,
+        mailbox: &Mailbox,
+        quarantined: bool,
+        files_as_junk: bool,
+        filter_folder: Option<&str>,
+        subaddress: Option<&str>,
+    ) -> Result<(Uuid, Option<Uuid>)> {
+        let quarantine_folder_id = if quarantined {
+            Some(
+                QuarantineRepository::new(self.db_pool.clone())
+                    .folder(mailbox)
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let junk_mailbox_id = if files_as_junk && !quarantined {
+            match JunkFiler::new(self.db_pool.clone())
+                .junk_folder(mailbox)
+                .await
+            {
+                Ok(junk) => junk,
+                Err(e) => {
+                    warn!("Failed to look up Junk folder for {}: {}", recipient, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let filter_folder_id = match (filter_folder, junk_mailbox_id.or(quarantine_folder_id)) {
+            (Some(folder), None) => MailboxFilters::new(self.db_pool.clone())
+                .folder(mailbox, folder)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Failed to look up folder {} for {}: {}",
+                        folder, recipient, e
+                    );
+                    None
+                }),
+            _ => None,
+        };
+
+        let filed_id = quarantine_folder_id
+            .or(junk_mailbox_id)
+            .or(filter_folder_id);
+        let tag_folder_id = match (subaddress, filed_id) {
+            (Some(tag), None) if !tag.is_empty() => SubaddressFiler::new(self.db_pool.clone())
+                .folder(mailbox, tag)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to look up folder {} for {}: {}", tag, recipient, e);
+                    None
+                }),
+            _ => None,
+        };
+        Ok((
+            filed_id.or(tag_folder_id).unwrap_or(mailbox.id),
+            junk_mailbox_id,
+        ))
+    }
+
+    /// Warnings shown with a recipient's copy: outside senders showing a
+    /// protected display name, and phishing signals
+    ///
+    /// Tags the copy when the tenant tags impersonations.
+    async fn copy_warnings(
+        &self,
+        message: &InboundMessage<'_>,
+        recipient: &EmailAddress,
+        tenant_id: TenantId,
+        phishing: &PhishingReport,
+        deliveries: &mut RecipientDeliveries,
+        tags: &mut Vec<&str>,
+    ) -> Vec<serde_json::Value> {
+        let message_id = message.message_id;
+        if let Entry::Vacant(entry) = deliveries.impersonations.entry(tenant_id) {
+            let impersonation = ImpersonationChecker::new(self.db_pool.clone())
+                .check(
+                    tenant_id,
+                    message.from_name.as_deref(),
+                    message.from_header.as_deref(),
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Display name check for message {} failed: {}",
+                        message_id, e
+                    );
+                    None
+                });
+            entry.insert(impersonation);
+        }
+        let mut warnings = Vec::new();
+        if let Some(impersonation) = &deliveries.impersonations[&tenant_id] {
+            warnings.push(impersonation.warning());
+            if impersonation.action == ImpersonationAction::Tag {
+                tags.push(IMPERSONATION_TAG);
+            }
+            info!(
+                "Message {} for {} shows protected name '{}' from {}",
+                message_id, recipient, impersonation.protected_name, impersonation.from_address
+            );
+        }
+        // Phishing signals, without repeating the display name warning
+        for signal in &phishing.signals {
+            if matches!(signal, PhishingSignal::InternalName { .. }) && !warnings.is_empty() {
+                continue;
+            }
+            warnings.push(signal.warning());
+        }
+        if !phishing.is_empty() {
+            info!(
+                "Message {} for {} has phishing signals {:?}",
+                message_id,
+                recipient,
+                phishing
+                    .signals
+                    .iter()
+                    .map(PhishingSignal::kind)
+                    .collect::<Vec<_>>()
+            );
+        }
+        warnings
+    }
+
+    /// Content of a recipient's copy, and the number of attachments
+    /// stripped from it
+    ///
+    /// The attachments the policies take out, the banner for outside
+    /// senders and the headers for the web UI only change this copy.
+    #[allow(clippy::too_many_arguments)]
+    async fn mailbox_copy<'m>(
+        &self,
+        message: &InboundMessage<'m>,
+        recipient: &EmailAddress,
+        mailbox: &Mailbox,
+        policy: &DeliveryPolicy,
+        phishing: &PhishingReport,
+        catch_all: bool,
+        deliveries: &mut RecipientDeliveries,
+        metadata: &mut serde_json::Value,
+    ) -> (Cow<'m, [u8]>, usize) {
+        let message_id = message.message_id;
+        let data = message.data;
+
+        // Attachments the policies take out, in this mailbox's copy only
+        let removed = policy
+            .attachments
+            .as_ref()
+            .and_then(|removal| remove_attachments(data, removal));
+        let stripped = match (&removed, &policy.attachments) {
+            (Some(removed), Some(removal)) => {
+                info!(
+                    "Removed attachments {:?} from message {} for {}",
+                    removed.filenames, message_id, recipient
+                );
+                metadata["attachments_removed"] = serde_json::json!(removed.filenames);
+                if removal.disposition == AttachmentDisposition::Strip {
+                    removed.filenames.len()
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+        let content = removed
+            .as_ref()
+            .map_or(data, |removed| removed.raw.as_slice());
+
+        // Banner for outside senders, in this mailbox's copy only
+        let from_trusted = !message.auth_result.dmarc_failed();
+        let stamped = match (message.authenticated_user, message.from_header.as_deref()) {
+            (None, Some(from)) => deliveries
+                .banner_stamper
+                .stamp(
+                    mailbox.tenant_id,
+                    mailbox.user_id,
+                    from,
+                    message.from_name.as_deref(),
+                    from_trusted,
+                    content,
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Sender banner for message {} failed: {}", message_id, e);
+                    None
+                }),
+            _ => None,
+        };
+        let mut delivered = match (stamped, removed) {
+            (Some(stamped), _) => Cow::Owned(stamped),
+            (None, Some(removed)) => Cow::Owned(removed.raw),
+            (None, None) => Cow::Borrowed(data),
+        };
+        if let Some(header) = phishing.header() {
+            // For the web UI to warn about the message
+            let mut copy = header.into_bytes();
+            copy.extend_from_slice(&delivered);
+            delivered = Cow::Owned(copy);
+        }
+        if catch_all {
+            // Keep the address the sender used visible in the catch-all copy
+            let mut copy = format!("X-Original-To: {}\r\n", recipient).into_bytes();
+            copy.extend_from_slice(&delivered);
+            delivered = Cow::Owned(copy);
+        }
+        (delivered, stripped)
+    }
+
+    /// Journal what happened for each local recipient
+    async fn journal_inbound(
+        &self,
+        message: &InboundMessage<'_>,
+        journaled: Vec<JournalRecipient>,
+    ) {
+        if !self.journal.records(JournalDirection::Inbound) || journaled.is_empty() {
+            return;
+        }
+        let auth_result = &message.auth_result;
+        let verdicts = JournalVerdicts {
+            spf: Some(auth_result.spf.as_header_value().to_string()),
+            dkim: Some(auth_result.dkim.as_header_value().to_string()),
+            dmarc: Some(auth_result.dmarc.as_header_value().to_string()),
+            spam_score: Some(message.spam.score),
+            spam_action: serde_json::to_value(message.spam.action)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string)),
+            require_tls: message.envelope.require_tls,
+        };
+        let job = JournalJob::new(
+            JournalDirection::Inbound,
+            message.message_id,
+            None,
+            message.sender.clone(),
+            journaled,
+            verdicts,
+        );
+        self.journal
+            .record(&self.db_pool, self.file_storage.as_ref(), job, message.data)
+            .await;
+    }
+
+    /// Archive what authenticated users send, and queue the remote
+    /// recipients accepted for relay; remote archives are relayed as
+    /// hidden recipients
+    async fn relay_message(&self, message: &InboundMessage<'_>) -> Result<()> {
+        let envelope = message.envelope;
+        let data = message.data;
+        let mut relay_to: Vec<String> = envelope.relay_to.iter().map(|r| r.to_string()).collect();
+        if let (Some(user), Some(from)) = (message.authenticated_user, message.sender.as_deref()) {
+            let sent_to: Vec<String> = message
+                .recipients
+                .iter()
+                .chain(&relay_to)
+                .cloned()
+                .collect();
+            match SentArchiver::new(self.db_pool.clone())
+                .archive(
+                    self.file_storage.as_ref(),
+                    user.tenant_id,
+                    from,
+                    &sent_to,
+                    data,
+                )
+                .await
+            {
+                Ok(bcc) => relay_to.extend(bcc),
+                Err(e) => warn!(
+                    "Failed to archive message {} from {}: {}",
+                    message.message_id, from, e
+                ),
+            }
+        }
+
+        if relay_to.is_empty() {
+            return Ok(());
+        }
+        let user = message
+            .authenticated_user
+            .ok_or_else(|| anyhow::anyhow!("Relay recipients without an authenticated user"))?;
+        // Sign once, so that every attempt sends the same signature
+        let signed = DkimKeyring::new(self.db_pool.clone())
+            .try_sign(user.tenant_id, data)
+            .await;
+        let job = DeliveryJob {
+            message_id: message.message_id,
+            tenant_id: user.tenant_id,
+            from: message.sender.clone().unwrap_or_default(),
+            to: relay_to,
+            storage_path: String::new(),
+            raw_message_base64: Some(
+                base64::engine::general_purpose::STANDARD.encode(signed.as_deref().unwrap_or(data)),
+            ),
+            require_tls: envelope.require_tls,
+            priority: 0,
+            lane: DeliveryLane::Transactional,
+            archived: true,
+            dkim_signed: signed.is_some(),
+            forwarded: false,
+            queued_at: None,
+        };
+        self.queue_manager.enqueue_delivery(job).await?;
+        info!(
+            "Message {} queued for relay to {:?} by {}",
+            message.message_id, envelope.relay_to, user.email
+        );
+        Ok(())
+    }
+
+    /// Return mail to SRS addresses to the original senders, as it was
+    /// received; it belongs to no tenant
+    async fn return_to_srs_senders(&self, message: &InboundMessage<'_>) -> Result<()> {
+        let envelope = message.envelope;
+        if envelope.srs_to.is_empty() {
+            return Ok(());
+        }
+        let to: Vec<String> = envelope.srs_to.iter().map(|r| r.to_string()).collect();
+        let job = DeliveryJob {
+            message_id: message.message_id,
+            tenant_id: Uuid::nil(),
+            from: message.sender.clone().unwrap_or_default(),
+            to,
+            storage_path: String::new(),
+            raw_message_base64: Some(
+                base64::engine::general_purpose::STANDARD.encode(message.data),
+            ),
+            require_tls: envelope.require_tls,
+            priority: 0,
+            lane: DeliveryLane::Transactional,
+            archived: true,
+            dkim_signed: false,
+            forwarded: false,
+            queued_at: None,
+        };
+        self.queue_manager.enqueue_delivery(job).await?;
+        info!(
+            "Message {} to SRS addresses returned to {:?}",
+            message.message_id, envelope.srs_to
+        );
+        Ok(())
+    }
+
+    /// Count a message or recipient against the limit of the authenticated
+    /// user, or of the client address for unauthenticated sessions
+    async fn within_rate_limit(&self, kind: RateLimitKind, user: Option<&User>) -> bool {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return true;
+        };
+        let client = match user {
+            Some(user) => RateLimitClient::User(&user.email),
+            None => RateLimitClient::Ip(self.peer_addr.ip()),
+        };
+        rate_limiter.check(kind, client).await
+    }
+
+    /// Reply refusing MAIL FROM when the client's blocklist score reaches
+    /// the reject score
+    async fn dnsbl_rejection(&self) -> Option<String> {
+        let dnsbl = self.dnsbl.as_ref().filter(|dnsbl| dnsbl.enabled())?;
+        let client_ip = self.peer_addr.ip();
+        let result = dnsbl.check(client_ip).await;
+        if !dnsbl.rejects(&result) {
+            return None;
+        }
+        info!(
+            "Refusing mail from {}, listed with score {:.1}",
+            self.peer_addr,
+            result.score()
+        );
+        Some(result.reply(client_ip))
+    }
+
+    /// Reply refusing MAIL FROM of an unauthenticated client when the
+    /// sender's SPF policy fails it and `smtp.spf.reject_fail` is set
+    ///
+    /// Bounces are checked against the HELO name. The reply carries the
+    /// explanation the domain publishes, if any.
+    async fn spf_rejection(
+        &self,
+        envelope: &Envelope,
+        from: Option<&EmailAddress>,
+    ) -> Option<String> {
+        if !self.config.spf.reject_fail {
+            return None;
+        }
+        let client_ip = match &envelope.client_ip {
+            Some(ip) => ip.parse().ok()?,
+            None => self.peer_addr.ip(),
+        };
+        let mail_from = from.map(|addr| addr.to_string()).unwrap_or_default();
+        let verifier = match SpfVerifier::new().await {
+            Ok(verifier) => verifier.with_hostname(&self.config.hostname),
+            Err(e) => {
+                warn!("Failed to create SPF verifier: {}", e);
+                return None;
+            }
+        };
+        let evaluation = verifier
+            .check(&mail_from, envelope.helo.as_deref(), client_ip)
+            .await;
+        if evaluation.result != SpfResult::Fail {
+            return None;
+        }
+        info!(
+            "Refusing mail from {} for {}, SPF failed",
+            self.peer_addr,
+            evaluation.domain.as_deref().unwrap_or("unknown")
+        );
+        Some(evaluation.reply())
+    }
+
+    /// Reply deferring MAIL FROM of an unauthenticated client when its
+    /// sender domain is over its rate limit or has a poor reputation
+    async fn sender_domain_deferral(&self, domain: &str) -> Option<String> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let client = RateLimitClient::SenderDomain(domain);
+            if !rate_limiter.check(RateLimitKind::Message, client).await {
+                return Some(
+                    "4.7.1 Too many messages from this sender domain, try again later".to_string(),
+                );
+            }
+        }
+        let tracker = self.sender_reputation.as_ref()?;
+        let reputation = tracker.score(domain).await?;
+        if !tracker.defers(&reputation) {
+            return None;
+        }
+        info!(
+            "Deferring mail from {} of {}, with a reputation of {:.1}",
+            self.peer_addr, domain, reputation.score
+        );
+        Some(format!(
+            "4.7.1 Too much unwanted mail from {}, try again later",
+            domain
+        ))
+    }
+
+    /// Count a refused recipient against the sender domain of an
+    /// unauthenticated client
+    async fn record_refused_recipient(&self, envelope: &Envelope, authenticated: bool) {
+        if let (Some(tracker), Some(from), false) =
+            (&self.sender_reputation, &envelope.from, authenticated)
+        {
+            tracker.record_error(&from.domain).await;
+        }
+    }
+
+    /// Whether mail for an address at one of our domains has somewhere to go:
+    /// a VERP bounce address, a report address, an inbound route, a mailbox
+    /// or the domain's catch-all mailbox
+    async fn is_deliverable(&self, recipient: &EmailAddress) -> Result<bool> {
+        if self.verp_message_id(recipient).is_some() {
+            return Ok(true);
+        }
+        if self.report_domain(recipient).await?.is_some() {
+            return Ok(true);
+        }
+        if InboundRouteRepository::new(self.db_pool.clone())
+            .find_for_address(&recipient.local, &recipient.domain)
+            .await?
+            .is_some()
+        {
+            return Ok(true);
+        }
+        if self.find_mailbox(recipient).await?.is_some() {
+            return Ok(true);
+        }
+        Ok(self.catch_all_mailbox(recipient).await?.is_some())
+    }
+
+    /// Scheduled message named by a recipient that is a VERP return path
+    /// with a valid tag
+    fn verp_message_id(&self, recipient: &EmailAddress) -> Option<Uuid> {
+        parse_verp(&recipient.local, &self.config.verp.secrets)
+    }
+
+    /// Domain of a recipient that is one of its domain's report addresses
+    async fn report_domain(&self, recipient: &EmailAddress) -> Result<Option<Domain>> {
+        let Some(domain) = self.local_domain(&recipient.domain).await? else {
+            return Ok(None);
+        };
+        let local = recipient.local.to_lowercase();
+        let is_report_address = DomainSettingsRepository::new(self.db_pool.clone())
+            .get(domain.id)
+            .await?
+            .is_some_and(|settings| report_addresses(&settings.extra_settings).contains(&local));
+        Ok(is_report_address.then_some(domain))
+    }
+
+    /// Original sender of mail to an SRS address, or `None` when SRS is off
+    /// or the recipient is not an SRS address
+    fn srs_return(&self, recipient: &EmailAddress) -> Option<Result<EmailAddress, SrsError>> {
+        let srs = Srs::from_config(&self.config.srs, &self.config.hostname)?;
+        if !recipient.domain.eq_ignore_ascii_case(srs.domain()) || !is_srs(&recipient.local) {
+            return None;
+        }
+        Some(
+            srs.reverse(&recipient.to_string(), Utc::now())
+                .and_then(|original| EmailAddress::parse(&original).ok_or(SrsError::Malformed)),
+        )
+    }
+
+    /// Mailbox of a recipient, with the subaddress tag when it was found
+    /// by its base address (`user` for `user+tag`)
+    ///
+    /// A mailbox with the full address wins over the base address. Addresses
+    /// at a subdomain that accepts mail go to the same local part at the
+    /// domain, unless a mailbox has the subdomain address itself.
+    async fn find_mailbox(
+        &self,
+        recipient: &EmailAddress,
+    ) -> Result<Option<(Mailbox, Option<String>)>> {
+        if let Some(found) = self.find_mailbox_at(recipient).await? {
+            return Ok(Some(found));
+        }
+        match self.local_domain(&recipient.domain).await? {
+            Some(domain) if !domain.name.eq_ignore_ascii_case(&recipient.domain) => {
+                let address = EmailAddress::new(recipient.local.as_str(), domain.name.as_str());
+                self.find_mailbox_at(&address).await
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Mailbox of an address, by its full or base address
+    async fn find_mailbox_at(
+        &self,
+        recipient: &EmailAddress,
+    ) -> Result<Option<(Mailbox, Option<String>)>> {
+        let mailbox_repo = MailboxRepository::new(self.db_pool.clone());
+        if let Some(mailbox) = mailbox_repo.find_by_address(&recipient.to_string()).await? {
+            return Ok(Some((mailbox, None)));
+        }
+        let Some((base, tag)) =
+            split_subaddress(&recipient.local, &self.config.recipient_delimiter)
+        else {
+            return Ok(None);
+        };
+        let base = EmailAddress::new(base, recipient.domain.as_str());
+        Ok(mailbox_repo
+            .find_by_address(&base.to_string())
+            .await?
+            .map(|mailbox| (mailbox, Some(tag.to_string()))))
+    }
+
+    /// Tenant domain mail for a domain name is delivered to: the domain
+    /// itself, or the nearest domain above it when that accepts mail for
+    /// its subdomains
+    async fn local_domain(&self, name: &str) -> Result<Option<Domain>> {
+        let name = name.to_lowercase();
+        if let Some(domain) = DomainRepository::new(self.db_pool.clone())
+            .find_by_name(&name)
+            .await?
+        {
+            return Ok(Some(domain));
+        }
+        Ok(find_parent_domain(&self.db_pool, &name)
+            .await?
+            .filter(|(_, policy)| policy.accept_mail)
+            .map(|(domain, _)| domain))
+    }
+
+    /// Limits of mail to a tenant domain: the server limits, lowered by the
+    /// domain settings
+    ///
+    /// The server limits apply when the settings cannot be loaded.
+    async fn domain_limits(&self, domain: &Domain) -> MessageLimits {
+        let limits = MessageLimits::from_config(&self.config);
+        match DomainSettingsRepository::new(self.db_pool.clone())
+            .get(domain.id)
+            .await
+        {
+            Ok(Some(settings)) => limits.restrict(&settings),
+            Ok(None) => limits,
+            Err(e) => {
+                warn!("Failed to load the settings of {}: {}", domain.name, e);
+                limits
+            }
+        }
+    }
+
+    /// Largest message each recipient of a transaction accepts
+    async fn recipient_size_limits(&self, envelope: &Envelope) -> Vec<RecipientSizeLimit> {
+        let server_max = MessageLimits::from_config(&self.config).max_size;
+        let mut domains: HashMap<String, (usize, Option<TenantId>)> = HashMap::new();
+        let mut limits = Vec::with_capacity(envelope.to.len());
+        for recipient in &envelope.to {
+            let name = recipient.domain.to_lowercase();
+            let (max_size, tenant_id) = match domains.get(&name) {
+                Some(limit) => *limit,
+                None => {
+                    let limit = match self.local_domain(&name).await {
+                        Ok(Some(domain)) => (
+                            self.domain_limits(&domain).await.max_size,
+                            Some(domain.tenant_id),
+                        ),
+                        Ok(None) => (server_max, None),
+                        Err(e) => {
+                            warn!("Failed to look up domain {}: {}", name, e);
+                            (server_max, None)
+                        }
+                    };
+                    domains.insert(name, limit);
+                    limit
+                }
             };
+            limits.push(RecipientSizeLimit {
+                recipient: recipient.clone(),
+                max_size,
+                tenant_id,
+            });
+        }
+        limits
+    }
 
-            data.extend_from_slice(line_bytes);
+    /// Largest message a session accepts, advertised with SIZE in EHLO and
+    /// checked against the SIZE declared on MAIL FROM
+    ///
+    /// Sessions that can authenticate may relay, so they take messages of
+    /// the server limit. Other sessions only deliver to the tenant domains,
+    /// and take no message above the largest limit among them; the limit of
+    /// each recipient's domain is checked at RCPT.
+    async fn session_size_limit(&self, can_relay: bool) -> usize {
+        let server_max = MessageLimits::from_config(&self.config).max_size;
+        if can_relay {
+            return server_max;
+        }
+        match DomainSettingsRepository::new(self.db_pool.clone())
+            .largest_message_size()
+            .await
+        {
+            Ok(largest) => domains_size_limit(largest, server_max),
+            Err(e) => {
+                warn!(
+                    "Failed to load the message size limits of the domains: {}",
+                    e
+                );
+                server_max
+            }
+        }
+    }
 
-            if data.len() > max_size {
-                return Err(anyhow::anyhow!("Message too large"));
+    /// Scan a message for the tenants of its local recipients that scan
+    /// their mail
+    ///
+    /// Returns the verdict, `None` when no tenant scans, and the action of
+    /// each tenant that does. An infected message is refused when one of
+    /// them rejects infected mail.
+    async fn virus_scan(
+        &self,
+        envelope: &Envelope,
+        data: &[u8],
+    ) -> Result<(Option<ScanVerdict>, HashMap<TenantId, AntivirusAction>)> {
+        let mut actions = HashMap::new();
+        let Some(antivirus) = self.antivirus.as_ref().filter(|av| av.enabled()) else {
+            return Ok((None, actions));
+        };
+        let names: HashSet<String> = envelope
+            .to
+            .iter()
+            .map(|recipient| recipient.domain.to_lowercase())
+            .collect();
+        for name in names {
+            let Some(domain) = self.local_domain(&name).await? else {
+                continue;
+            };
+            if actions.contains_key(&domain.tenant_id) {
+                continue;
+            }
+            if let Some(action) = antivirus.tenant_action(domain.tenant_id).await {
+                actions.insert(domain.tenant_id, action);
             }
         }
+        if actions.is_empty() {
+            return Ok((None, actions));
+        }
 
-        Ok(data)
+        let verdict = antivirus.scan(data).await?;
+        if let ScanVerdict::Infected(virus) = &verdict {
+            if actions
+                .values()
+                .any(|action| *action == AntivirusAction::Reject)
+            {
+                info!(
+                    "Rejecting message from {}: virus {} found",
+                    self.peer_addr, virus
+                );
+                return Err(AntivirusRejection::Infected(virus.clone()).into());
+            }
+        }
+        Ok((Some(verdict), actions))
     }
 
-    /// Process and store a received message
-    async fn process_message(&self, envelope: &Envelope, data: &[u8]) -> Result<Uuid> {
-        let message_id = Uuid::now_v7();
+    /// Inbound policies applying to the copy for `mailbox`, evaluated once
+    /// per tenant and domain
+    ///
+    /// Only the reject, tempfail, quarantine, attachment and rate limit
+    /// actions of policies are applied at delivery.
+    async fn delivery_policy(
+        &self,
+        verdicts: &mut HashMap<(TenantId, DomainId), DeliveryPolicy>,
+        tenant_id: TenantId,
+        domain_id: DomainId,
+        context: &PolicyContext,
+    ) -> DeliveryPolicy {
+        let key = (tenant_id, domain_id);
+        if let Some(verdict) = verdicts.get(&key) {
+            return verdict.clone();
+        }
 
-        // Parse message headers
-        let parsed = mail_parser::MessageParser::default()
-            .parse(data)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse message"))?;
+        let mut context = context.clone();
+        context.tenant_id = tenant_id;
+        context.domain_id = Some(domain_id);
+        let verdict = match PolicyEngine::new(self.db_pool.clone())
+            .evaluate(&context)
+            .await
+        {
+            Ok(result) => DeliveryPolicy {
+                matches: result.matches.clone(),
+                rejection: result.rejection(),
+                quarantine: quarantining_policy(&result).map(str::to_string),
+                attachments: result.attachments,
+                rate_limits: result.rate_limits,
+            },
+            Err(e) => {
+                warn!("Policy evaluation for domain {} failed: {}", domain_id, e);
+                DeliveryPolicy::default()
+            }
+        };
+        verdicts.insert(key, verdict.clone());
+        verdict
+    }
 
-        let subject = parsed.subject().map(|s| s.to_string());
-        let from_header = parsed.from().and_then(|a| a.first()).map(|a| {
-            if let Some(email) = a.address() {
-                email.to_string()
-            } else {
-                String::new()
+    /// Record the inbound policies that matched a message
+    ///
+    /// `reply` is the refusal of the message; without one, the message was
+    /// quarantined or accepted for the recipients of each tenant and domain.
+    async fn record_policy_matches(
+        &self,
+        matched: &[MatchedPolicies],
+        message: &MatchedMessage<'_>,
+        reply: Option<(u16, String)>,
+    ) {
+        let history = PolicyHistoryRepository::new(self.db_pool.clone());
+        for matched in matched {
+            let outcome = match (&reply, &matched.policy.quarantine) {
+                (Some((code, _)), _) if *code >= 500 => "rejected",
+                (Some(_), _) => "deferred",
+                (None, Some(_)) => "quarantined",
+                (None, None) => "accepted",
+            };
+            for policy_match in &matched.policy.matches {
+                let input = NewPolicyMatch {
+                    tenant_id: matched.key.0,
+                    policy_id: policy_match.policy_id,
+                    policy_name: policy_match.policy_name.clone(),
+                    message_id: message.message_id.map(str::to_string),
+                    sender: message.sender.map(str::to_string),
+                    recipients: matched.recipients.clone(),
+                    client_ip: Some(message.client_ip.to_string()),
+                    actions: policy_match
+                        .actions
+                        .iter()
+                        .filter_map(|action| {
+                            serde_json::to_value(&action.action_type)
+                                .ok()
+                                .and_then(|value| value.as_str().map(str::to_string))
+                        })
+                        .collect(),
+                    outcome: outcome.to_string(),
+                    smtp_reply: reply
+                        .as_ref()
+                        .map(|(code, text)| format!("{} {}", code, text)),
+                };
+                if let Err(e) = history.record_match(&input).await {
+                    warn!(
+                        "Failed to record match of policy {}: {}",
+                        policy_match.policy_id, e
+                    );
+                }
             }
-        });
-        let message_id_header = parsed.message_id().map(|s| s.to_string());
+        }
+    }
 
-        // Perform email authentication checks
-        let auth_result = self.verify_email_authentication(envelope, data).await;
+    /// Catch-all mailbox of the recipient's domain, when its domain settings
+    /// enable one
+    async fn catch_all_mailbox(&self, recipient: &EmailAddress) -> Result<Option<Mailbox>> {
+        let Some(domain) = self.local_domain(&recipient.domain).await? else {
+            return Ok(None);
+        };
+        let settings = DomainSettingsRepository::new(self.db_pool.clone())
+            .get(domain.id)
+            .await?;
+        let Some(mailbox_id) = settings
+            .filter(|settings| settings.catch_all_enabled)
+            .and_then(|settings| settings.catch_all_mailbox_id)
+        else {
+            return Ok(None);
+        };
+        // Only a mailbox of the domain's own tenant can be its catch-all
+        Ok(MailboxRepository::new(self.db_pool.clone())
+            .get(domain.tenant_id, mailbox_id)
+            .await?)
+    }
 
-        // Get body preview
-        let body_preview = parsed
-            .body_text(0)
-            .map(|s| s.chars().take(500).collect::<String>());
+    /// Whether `incoming` bytes fit in the mailbox a recipient's mail is
+    /// stored in
+    ///
+    /// Mail is let through when the quota cannot be checked.
+    async fn quota_verdict(&self, recipient: &EmailAddress, incoming: u64) -> QuotaVerdict {
+        let checker = QuotaChecker::new(self.db_pool.clone(), self.config.quota_grace_percent);
+        let incoming = i64::try_from(incoming).unwrap_or(i64::MAX);
+        let verdict = match self.stored_mailbox(recipient).await {
+            Ok(Some(mailbox)) => checker.check(&mailbox, incoming).await,
+            Ok(None) => Ok(QuotaVerdict::Within),
+            Err(e) => Err(e),
+        };
+        verdict.unwrap_or_else(|e| {
+            warn!("Failed to check the quota of {}: {}", recipient, e);
+            QuotaVerdict::Within
+        })
+    }
 
-        // For each recipient, store the message
-        for recipient in &envelope.to {
-            // Find the mailbox for this recipient
-            let mailbox_repo = MailboxRepository::new(self.db_pool.clone());
+    /// Mailbox a recipient's mail is stored in; `None` for VERP bounces and
+    /// inbound routes that only post to a webhook
+    async fn stored_mailbox(&self, recipient: &EmailAddress) -> Result<Option<Mailbox>> {
+        if self.verp_message_id(recipient).is_some() {
+            return Ok(None);
+        }
+        let route = InboundRouteRepository::new(self.db_pool.clone())
+            .find_for_address(&recipient.local, &recipient.domain)
+            .await?;
+        if route.is_some_and(|route| !route.store_message) {
+            return Ok(None);
+        }
+        match self.find_mailbox(recipient).await? {
+            Some((mailbox, _)) => Ok(Some(mailbox)),
+            None => self.catch_all_mailbox(recipient).await,
+        }
+    }
 
-            let mailbox = match mailbox_repo.find_by_address(&recipient.to_string()).await? {
-                Some(mb) => mb,
-                None => {
-                    warn!("Mailbox not found for {}", recipient);
-                    continue;
+    /// Seconds an unauthenticated client has to wait before a recipient
+    /// is accepted, when greylisting defers it
+    ///
+    /// Clients are let through when the greylist cannot be checked.
+    async fn greylist_deferral(
+        &self,
+        envelope: &Envelope,
+        recipient: &EmailAddress,
+        authenticated: bool,
+    ) -> Option<i64> {
+        let greylist = self
+            .greylist
+            .as_ref()
+            .filter(|greylist| greylist.enabled() && !authenticated)?;
+        let sender = envelope
+            .from
+            .as_ref()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let client_ip = self.peer_addr.ip();
+        let spf_pass = async {
+            match SpfVerifier::new().await {
+                Ok(verifier) => verifier.verify(&sender, client_ip).await == SpfResult::Pass,
+                Err(e) => {
+                    warn!("Failed to create SPF verifier: {}", e);
+                    false
                 }
-            };
-
-            // Store the raw message to file storage
-            let storage_path = format!("{}/{}/{}.eml", mailbox.tenant_id, mailbox.id, message_id);
-
-            self.file_storage.store(&storage_path, data).await?;
-
-            // Create message record
-            let message = Message {
-                id: message_id,
-                tenant_id: mailbox.tenant_id,
-                mailbox_id: mailbox.id,
-                message_id_header: message_id_header.clone(),
-                subject: subject.clone(),
-                from_address: from_header.clone(),
-                to_addresses: serde_json::to_value(&envelope.to)?,
-                cc_addresses: None,
-                headers: serde_json::json!({}),
-                body_preview: body_preview.clone(),
-                body_size: data.len() as i64,
-                has_attachments: parsed.attachment_count() > 0,
-                storage_path: storage_path.clone(),
-                seen: false,
-                answered: false,
-                flagged: false,
-                deleted: false,
-                draft: false,
-                spam_score: None,
-                tags: serde_json::json!([]),
-                metadata: serde_json::json!({
-                    "spf": auth_result.spf.as_header_value(),
-                    "dkim": auth_result.dkim.as_header_value(),
-                    "dmarc": auth_result.dmarc.as_header_value(),
-                    "auth_results_header": auth_result.to_header(&self.config.hostname)
-                }),
-                received_at: Utc::now(),
-                created_at: Utc::now(),
-            };
-
-            // Store in database
-            let message_repo = MessageRepository::new(self.db_pool.clone());
-            message_repo.create(&message).await?;
+            }
+        };
 
-            // Execute post_receive hooks
-            if let Err(e) = self
-                .hook_manager
-                .execute_post_receive(mailbox.tenant_id, &message, data)
-                .await
-            {
-                warn!("Hook execution failed for message {}: {}", message_id, e);
+        match greylist
+            .check(client_ip, &sender, &recipient.to_string(), spf_pass)
+            .await
+        {
+            Ok(GreylistVerdict::Pass) => None,
+            Ok(GreylistVerdict::Defer(retry_after)) => Some(retry_after),
+            Err(e) => {
+                warn!("Greylist check for {} failed: {}", recipient, e);
+                None
             }
         }
-
-        Ok(message_id)
     }
 
-    /// Verify email authentication (SPF, DKIM, DMARC)
+    /// Verify email authentication (SPF, DKIM, DMARC, ARC)
+    ///
+    /// With `report` (mail from outside), the DMARC result is counted for
+    /// the aggregate reports the From domain asks for, and the sender's
+    /// BIMI logo is looked up.
     async fn verify_email_authentication(
         &self,
         envelope: &Envelope,
         message_data: &[u8],
+        report: bool,
     ) -> AuthenticationResult {
-        use crate::email_auth::{DkimResult, DmarcResult, SpfResult};
+        use crate::email_auth::DkimResult;
 
         // Get client IP and mail from address
         let client_ip = match &envelope.client_ip {
@@ -747,7 +3359,13 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         // SPF verification
         let spf_result = if let Some(ip) = client_ip {
             match SpfVerifier::new().await {
-                Ok(verifier) => verifier.verify(&mail_from, ip).await,
+                Ok(verifier) => {
+                    verifier
+                        .with_hostname(&self.config.hostname)
+                        .check(&mail_from, envelope.helo.as_deref(), ip)
+                        .await
+                        .result
+                }
                 Err(e) => {
                     warn!("Failed to create SPF verifier: {}", e);
                     SpfResult::TempError
@@ -777,16 +3395,16 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
         );
 
         // DMARC verification
-        let dmarc_result = if let Some(ref from_domain) = from_domain {
-            let mail_from_domain = envelope.from.as_ref().map(|addr| addr.domain.clone());
+        let mail_from_domain = envelope.from.as_ref().map(|addr| addr.domain.clone());
 
-            // Extract DKIM domain from the message
-            let dkim_domain = self.extract_dkim_domain(message_data);
+        // Extract DKIM domain from the message
+        let dkim_domain = self.extract_dkim_domain(message_data);
 
+        let dmarc_evaluation = if let Some(ref from_domain) = from_domain {
             match DmarcVerifier::new().await {
                 Ok(verifier) => {
                     verifier
-                        .verify(
+                        .evaluate(
                             from_domain,
                             mail_from_domain.as_deref(),
                             dkim_domain.as_deref(),
@@ -797,19 +3415,87 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
                 }
                 Err(e) => {
                     warn!("Failed to create DMARC verifier: {}", e);
-                    DmarcResult::TempError
+                    DmarcEvaluation::without_record(DmarcResult::TempError)
                 }
             }
         } else {
-            DmarcResult::None
+            DmarcEvaluation::without_record(DmarcResult::None)
         };
+        let dmarc_result = dmarc_evaluation.result.clone();
 
         info!(
             "DMARC result for message from {}: {:?}",
             self.peer_addr, dmarc_result
         );
 
-        AuthenticationResult::new(spf_result, dkim_result, dmarc_result)
+        // ARC verification, which can override a DMARC failure of mail
+        // forwarded by a trusted sealer
+        let arc = match ArcVerifier::new().await {
+            Ok(verifier) => verifier.verify(message_data).await,
+            Err(e) => {
+                warn!("Failed to create ARC verifier: {}", e);
+                ArcVerification::default()
+            }
+        };
+
+        // Logo of senders whose DMARC policy is enforced
+        let bimi = match (&self.bimi, from_domain.as_deref()) {
+            (Some(bimi), Some(from_domain)) if report && bimi.enabled() => Some(
+                bimi.evaluate(message_data, from_domain, &dmarc_evaluation)
+                    .await,
+            ),
+            _ => None,
+        };
+
+        let auth_result = AuthenticationResult::new(spf_result, dkim_result, dmarc_result)
+            .with_arc(arc, &self.config.arc.trusted_sealers)
+            .with_bimi(bimi);
+        info!(
+            "ARC result for message from {}: {:?}{}",
+            self.peer_addr,
+            auth_result.arc.result,
+            if auth_result.dmarc_overridden {
+                " (DMARC failure overridden)"
+            } else {
+                ""
+            }
+        );
+
+        // Count the result for the aggregate reports the domain asks for
+        let policy = dmarc_evaluation
+            .policy_domain
+            .as_deref()
+            .zip(dmarc_evaluation.record.as_ref())
+            .and_then(|(domain, record)| PublishedPolicy::new(domain, record));
+        if let (true, Some(policy), Some(source_ip), Some(header_from)) = (
+            report && self.config.dmarc_reports.enabled,
+            policy,
+            client_ip,
+            from_domain,
+        ) {
+            let observation = DmarcObservation {
+                source_ip,
+                header_from,
+                spf_domain: mail_from_domain.clone().or_else(|| envelope.helo.clone()),
+                envelope_from: mail_from_domain,
+                dkim_domain,
+                dkim_result: auth_result.dkim.clone(),
+                spf_result: auth_result.spf.clone(),
+                dkim_aligned: dmarc_evaluation.dkim_aligned,
+                spf_aligned: dmarc_evaluation.spf_aligned,
+                // Failing mail is delivered, with its sender marked untrusted
+                disposition: DmarcPolicy::None,
+                policy_override: dmarc_override(&auth_result),
+            };
+            if let Err(e) = DmarcReportRecorder::new(self.db_pool.clone())
+                .record(&policy, &observation, Utc::now())
+                .await
+            {
+                warn!("Failed to record DMARC result for {}: {}", policy.domain, e);
+            }
+        }
+
+        auth_result
     }
 
     /// Extract the From header domain from message data
@@ -845,6 +3531,9 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
     }
 
     /// Send an SMTP response
+    ///
+    /// The reply is buffered; the session loop flushes it together with the
+    /// replies to the other commands of a pipelined group.
     async fn send_response<W: AsyncWrite + Unpin>(
         &self,
         writer: &mut W,
@@ -853,11 +3542,41 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
     ) -> Result<()> {
         let response = format!("{} {}\r\n", code, message);
         writer.write_all(response.as_bytes()).await?;
-        writer.flush().await?;
         debug!("SMTP to {}: {}", self.peer_addr, response.trim());
         Ok(())
     }
 
+    /// Send a 334 or 354 reply, which the client waits for before it sends
+    /// more data
+    async fn send_intermediate_response<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        code: u16,
+        message: &str,
+    ) -> Result<()> {
+        self.send_response(writer, code, message).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Close the session for maintenance (RFC 5321 section 3.8)
+    async fn send_maintenance_response<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        status: &MaintenanceStatus,
+    ) -> Result<()> {
+        info!(
+            "Refusing SMTP session from {} for maintenance",
+            self.peer_addr
+        );
+        self.send_response(
+            writer,
+            421,
+            &maintenance_reply(&self.config.hostname, status, Utc::now()),
+        )
+        .await
+    }
+
     /// Send a multi-line response (intermediate line)
     async fn send_response_continue<W: AsyncWrite + Unpin>(
         &self,
@@ -867,12 +3586,244 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpHandler<S> {
     ) -> Result<()> {
         let response = format!("{}-{}\r\n", code, message);
         writer.write_all(response.as_bytes()).await?;
-        writer.flush().await?;
         debug!("SMTP to {}: {}", self.peer_addr, response.trim());
         Ok(())
     }
 }
 
+/// Flush the buffered replies unless the client has already sent another
+/// complete command
+///
+/// A client pipelining commands (RFC 2920) sends a group of them and then
+/// waits for all their replies. The replies are sent together once the group
+/// is read, instead of one write per command.
+async fn flush_if_idle<R, W>(reader: &BufReader<R>, writer: &mut W) -> std::io::Result<()>
+where
+    R: tokio::io::AsyncRead,
+    W: AsyncWrite + Unpin,
+{
+    if reader.buffer().contains(&b'\n') {
+        return Ok(());
+    }
+    writer.flush().await
+}
+
+/// Bytes read at DATA: the largest message one of the recipients accepts
+///
+/// Remote recipients (relay and SRS) take messages of the server limit.
+fn data_size_limit(limits: &[RecipientSizeLimit], envelope: &Envelope, server_max: usize) -> usize {
+    let remote = !envelope.relay_to.is_empty() || !envelope.srs_to.is_empty();
+    limits
+        .iter()
+        .map(|limit| limit.max_size)
+        .chain(remote.then_some(server_max))
+        .max()
+        .unwrap_or(server_max)
+}
+
+/// Recipients whose limit is below the size of a message
+fn oversized_recipients(limits: &[RecipientSizeLimit], size: usize) -> Vec<&RecipientSizeLimit> {
+    limits
+        .iter()
+        .filter(|limit| size > limit.max_size)
+        .collect()
+}
+
+/// Whether a recipient is one of the oversized ones
+fn is_oversized(oversized: &[&RecipientSizeLimit], recipient: &EmailAddress) -> bool {
+    oversized.iter().any(|limit| limit.recipient == *recipient)
+}
+
+/// Largest message the tenant domains accept, from the largest limit in
+/// their settings (`None` when one of them has none)
+fn domains_size_limit(largest: Option<i64>, server_max: usize) -> usize {
+    largest
+        .filter(|largest| *largest > 0)
+        .and_then(|largest| usize::try_from(largest).ok())
+        .map_or(server_max, |largest| largest.min(server_max))
+}
+
+/// Delivery of a copy forwarded by a mailbox filter
+///
+/// The copy is the ARC-sealed message, or the message as received when the
+/// forwarding domain could not seal it.
+fn forward_job(
+    message_id: Uuid,
+    tenant_id: Uuid,
+    from: String,
+    to: Vec<String>,
+    message: &[u8],
+    sealed: Option<Vec<u8>>,
+    require_tls: bool,
+) -> DeliveryJob {
+    let message = sealed.as_deref().unwrap_or(message);
+    DeliveryJob {
+        message_id,
+        tenant_id,
+        from,
+        to,
+        storage_path: String::new(),
+        raw_message_base64: Some(base64::engine::general_purpose::STANDARD.encode(message)),
+        require_tls,
+        priority: 0,
+        lane: DeliveryLane::Transactional,
+        archived: true,
+        dkim_signed: false,
+        forwarded: true,
+        queued_at: None,
+    }
+}
+
+/// Why a DMARC failure was not acted on, as given in aggregate reports
+fn dmarc_override(auth_result: &AuthenticationResult) -> Option<PolicyOverride> {
+    if auth_result.dmarc_overridden {
+        return Some(PolicyOverride {
+            reason: "trusted_forwarder",
+            comment: format!(
+                "arc=pass as={}",
+                auth_result.arc.latest_sealer().unwrap_or_default()
+            ),
+        });
+    }
+    match auth_result.dmarc {
+        DmarcResult::Fail(DmarcPolicy::Quarantine | DmarcPolicy::Reject) => Some(PolicyOverride {
+            reason: "local_policy",
+            comment: "delivered, sender marked untrusted".to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Protocol keyword for the Received header (RFC 3848)
+fn received_protocol(tls: bool, authenticated: bool) -> &'static str {
+    match (tls, authenticated) {
+        (true, true) => "ESMTPSA",
+        (true, false) => "ESMTPS",
+        (false, true) => "ESMTPA",
+        (false, false) => "ESMTP",
+    }
+}
+
+/// Build a Received trace header for an accepted message
+fn received_header(
+    helo: Option<&str>,
+    peer_addr: SocketAddr,
+    hostname: &str,
+    protocol: &str,
+    message_id: Uuid,
+    received_at: chrono::DateTime<Utc>,
+) -> String {
+    format!(
+        "Received: from {} ([{}])\r\n\tby {} with {} id {};\r\n\t{}\r\n",
+        helo.unwrap_or("unknown"),
+        peer_addr.ip(),
+        hostname,
+        protocol,
+        message_id,
+        received_at.to_rfc2822()
+    )
+}
+
+/// Reply to a command that failed: MaiRust errors get their SMTP mapping,
+/// anything else is a temporary failure
+fn error_reply(error: &anyhow::Error) -> (u16, String) {
+    match error.downcast_ref::<mairust_common::Error>() {
+        Some(error) => error.smtp_reply(),
+        None => (451, "4.3.0 Temporary error".to_string()),
+    }
+}
+
+/// Reply to a message that could not be accepted after DATA
+fn message_error_reply(error: &anyhow::Error) -> (u16, String) {
+    if let Some(GreylistDeferral(retry_after)) = error.downcast_ref::<GreylistDeferral>() {
+        return (451, GreylistVerdict::reply(*retry_after));
+    }
+    if let Some(QuotaRejection(verdict)) = error.downcast_ref::<QuotaRejection>() {
+        let (code, reply) = verdict
+            .reply()
+            .unwrap_or((452, "4.2.2 Mailbox full, try again later"));
+        return (code, reply.to_string());
+    }
+    if let Some(rejection) = error.downcast_ref::<SpamRejection>() {
+        return rejection.reply();
+    }
+    if let Some(rejection) = error.downcast_ref::<AntivirusRejection>() {
+        return rejection.reply();
+    }
+    if let Some(rejection) = error.downcast_ref::<PolicyRejection>() {
+        return rejection.reply();
+    }
+    if let Some(deferral) = error.downcast_ref::<PolicyRateLimited>() {
+        return deferral.reply();
+    }
+    warn!("Failed to process message: {}", error);
+    error_reply(error)
+}
+
+/// Apply an XFORWARD command (Postfix XFORWARD_README) to the envelope
+///
+/// ADDR and HELO replace the client address and HELO name used by the
+/// spam filter and authentication checks; NAME and PROTO are accepted
+/// and ignored. Values the MTA does not know are left as they are.
+fn apply_xforward(envelope: &mut Envelope, args: &str) -> Result<(), String> {
+    let mut attributes = Vec::new();
+    for attribute in args.split_whitespace() {
+        let (name, value) = attribute
+            .split_once('=')
+            .ok_or_else(|| format!("Bad XFORWARD attribute: {}", attribute))?;
+        let name = name.to_ascii_uppercase();
+        if !matches!(name.as_str(), "NAME" | "ADDR" | "PROTO" | "HELO") {
+            return Err(format!("Bad XFORWARD attribute name: {}", name));
+        }
+        let value = decode_xtext(value)
+            .ok_or_else(|| format!("Bad XFORWARD attribute value: {}", attribute))?;
+        attributes.push((name, value));
+    }
+    if attributes.is_empty() {
+        return Err("No XFORWARD attributes".to_string());
+    }
+
+    for (name, value) in attributes {
+        if value == "[UNAVAILABLE]" || value == "[TEMPUNAVAIL]" {
+            continue;
+        }
+        match name.as_str() {
+            "ADDR" => {
+                let addr = value
+                    .strip_prefix("IPV6:")
+                    .or_else(|| value.strip_prefix("ipv6:"))
+                    .unwrap_or(&value);
+                let ip: IpAddr = addr
+                    .parse()
+                    .map_err(|_| format!("Bad XFORWARD address: {}", value))?;
+                envelope.client_ip = Some(ip.to_string());
+            }
+            "HELO" => envelope.helo = Some(value),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Decode an xtext value (RFC 3461 section 4)
+fn decode_xtext(value: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'+' {
+            let hex = [bytes.next()?, bytes.next()?];
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
 /// Parse an SMTP command line into command and arguments
 fn parse_command(line: &str) -> (&str, &str) {
     let parts: Vec<&str> = line.splitn(2, ' ').collect();
@@ -912,6 +3863,34 @@ fn parse_mail_from(args: &str) -> Option<Option<EmailAddress>> {
     }
 }
 
+/// Text of the 421 reply sent during maintenance, with a retry hint
+fn maintenance_reply(hostname: &str, status: &MaintenanceStatus, now: DateTime<Utc>) -> String {
+    format!(
+        "4.3.2 {} {}, try again in {} seconds",
+        hostname,
+        status.message,
+        status.retry_after_secs(now)
+    )
+}
+
+/// Check whether MAIL FROM carries an ESMTP parameter (e.g. `REQUIRETLS`)
+fn has_mail_parameter(args: &str, name: &str) -> bool {
+    mail_parameter(args, name).is_some()
+}
+
+/// Value of an ESMTP parameter on MAIL FROM (e.g. `SIZE=1024`); empty for
+/// a parameter without a value
+fn mail_parameter<'a>(args: &'a str, name: &str) -> Option<&'a str> {
+    let params = match args.find('>') {
+        Some(end) => &args[end + 1..],
+        None => args.split_once(char::is_whitespace).map(|(_, p)| p).unwrap_or(""),
+    };
+    params.split_whitespace().find_map(|p| {
+        let (key, value) = p.split_once('=').unwrap_or((p, ""));
+        key.eq_ignore_ascii_case(name).then_some(value)
+    })
+}
+
 /// Parse RCPT TO:<address>
 fn parse_rcpt_to(args: &str) -> Option<EmailAddress> {
     let args = args.trim();
@@ -971,4 +3950,304 @@ mod tests {
 
         assert_eq!(parse_rcpt_to("TO:<>"), None);
     }
+
+    #[test]
+    fn test_has_mail_parameter() {
+        assert!(has_mail_parameter("FROM:<user@example.com> REQUIRETLS", "REQUIRETLS"));
+        assert!(has_mail_parameter("FROM:<> SIZE=100 requiretls", "REQUIRETLS"));
+        assert!(!has_mail_parameter("FROM:<user@example.com> SIZE=100", "REQUIRETLS"));
+        assert!(!has_mail_parameter("FROM:<requiretls@example.com>", "REQUIRETLS"));
+    }
+
+    #[test]
+    fn test_mail_parameter() {
+        assert_eq!(
+            mail_parameter("FROM:<user@example.com> SIZE=1024", "SIZE"),
+            Some("1024")
+        );
+        assert_eq!(
+            mail_parameter("FROM:<> BODY=8BITMIME size=7", "SIZE"),
+            Some("7")
+        );
+        assert_eq!(mail_parameter("FROM:<> REQUIRETLS", "REQUIRETLS"), Some(""));
+        assert_eq!(mail_parameter("FROM:<size=1@example.com>", "SIZE"), None);
+    }
+
+    #[test]
+    fn test_maintenance_reply() {
+        let now = Utc::now();
+        let status = MaintenanceStatus {
+            message: "Storage migration".to_string(),
+            until: Some(now + chrono::Duration::minutes(15)),
+        };
+        assert_eq!(
+            maintenance_reply("mx.example.com", &status, now),
+            "4.3.2 mx.example.com Storage migration, try again in 900 seconds"
+        );
+    }
+
+    #[test]
+    fn test_received_header() {
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let id = Uuid::nil();
+        let header = received_header(
+            Some("client.example"),
+            peer,
+            "mx1.example.com",
+            received_protocol(true, false),
+            id,
+            Utc::now(),
+        );
+        assert!(header.starts_with("Received: from client.example ([192.0.2.1])\r\n"));
+        assert!(header.contains("by mx1.example.com with ESMTPS id "));
+        assert!(header.ends_with("\r\n"));
+    }
+
+    #[test]
+    fn test_error_reply() {
+        let error = anyhow::Error::from(mairust_common::Error::QuotaExceeded(
+            "Mailbox full".to_string(),
+        ));
+        assert_eq!(error_reply(&error), (552, "5.2.2 Mailbox full".to_string()));
+
+        let error = anyhow::Error::from(mairust_common::Error::Database("timeout".to_string()));
+        assert_eq!(
+            error_reply(&error),
+            (451, "4.3.0 Temporary error".to_string())
+        );
+
+        let error = anyhow::anyhow!("connection reset");
+        assert_eq!(
+            error_reply(&error),
+            (451, "4.3.0 Temporary error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_xforward() {
+        let mut envelope = Envelope {
+            from: None,
+            to: Vec::new(),
+            client_ip: Some("127.0.0.1".to_string()),
+            helo: Some("mta.example.com".to_string()),
+            require_tls: false,
+            size: None,
+            relay_to: Vec::new(),
+            srs_to: Vec::new(),
+        };
+
+        apply_xforward(
+            &mut envelope,
+            "NAME=client.example ADDR=192.0.2.1 PROTO=ESMTP HELO=client+2Eexample",
+        )
+        .unwrap();
+        assert_eq!(envelope.client_ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(envelope.helo.as_deref(), Some("client.example"));
+
+        apply_xforward(&mut envelope, "addr=IPV6:2001:db8::1 HELO=[UNAVAILABLE]").unwrap();
+        assert_eq!(envelope.client_ip.as_deref(), Some("2001:db8::1"));
+        assert_eq!(envelope.helo.as_deref(), Some("client.example"));
+
+        assert!(apply_xforward(&mut envelope, "").is_err());
+        assert!(apply_xforward(&mut envelope, "ADDR").is_err());
+        assert!(apply_xforward(&mut envelope, "PORT=25").is_err());
+        assert!(apply_xforward(&mut envelope, "ADDR=not-an-ip").is_err());
+        assert!(apply_xforward(&mut envelope, "HELO=bad+4").is_err());
+        assert_eq!(envelope.client_ip.as_deref(), Some("2001:db8::1"));
+    }
+
+    #[test]
+    fn test_message_limits() {
+        let config = SmtpConfig {
+            max_message_size: Some(1000),
+            max_recipients: 10,
+            ..SmtpConfig::default()
+        };
+        let limits = MessageLimits::from_config(&config);
+        assert_eq!(
+            limits,
+            MessageLimits {
+                max_size: 1000,
+                max_recipients: 10
+            }
+        );
+
+        let mut settings = DomainSettings::default();
+        assert_eq!(limits.restrict(&settings), limits);
+
+        settings.max_message_size = Some(500);
+        settings.max_recipients = Some(3);
+        assert_eq!(
+            limits.restrict(&settings),
+            MessageLimits {
+                max_size: 500,
+                max_recipients: 3
+            }
+        );
+
+        // Domains cannot raise the server limits
+        settings.max_message_size = Some(5000);
+        settings.max_recipients = Some(0);
+        assert_eq!(limits.restrict(&settings), limits);
+    }
+
+    #[test]
+    fn test_recipient_size_limits() {
+        let small = EmailAddress::new("user", "small.example");
+        let large = EmailAddress::new("user", "large.example");
+        let tenant_id = Uuid::new_v4();
+        let limits = vec![
+            RecipientSizeLimit {
+                recipient: small.clone(),
+                max_size: 500,
+                tenant_id: Some(tenant_id),
+            },
+            RecipientSizeLimit {
+                recipient: large.clone(),
+                max_size: 800,
+                tenant_id: Some(tenant_id),
+            },
+        ];
+        let mut envelope = Envelope {
+            from: None,
+            to: vec![small.clone(), large.clone()],
+            client_ip: None,
+            helo: None,
+            require_tls: false,
+            size: None,
+            relay_to: Vec::new(),
+            srs_to: Vec::new(),
+        };
+
+        // One small domain does not shrink the message for the others
+        assert_eq!(data_size_limit(&limits, &envelope, 1000), 800);
+        let oversized = oversized_recipients(&limits, 600);
+        assert_eq!(oversized, vec![&limits[0]]);
+        assert!(is_oversized(&oversized, &small));
+        assert!(!is_oversized(&oversized, &large));
+        assert!(oversized_recipients(&limits, 500).is_empty());
+        assert_eq!(oversized_recipients(&limits, 900).len(), 2);
+
+        envelope
+            .relay_to
+            .push(EmailAddress::new("user", "remote.example"));
+        assert_eq!(data_size_limit(&limits, &envelope, 1000), 1000);
+        assert_eq!(data_size_limit(&[], &envelope, 1000), 1000);
+    }
+
+    #[test]
+    fn test_domains_size_limit() {
+        // Sessions take no message above the largest domain limit
+        assert_eq!(domains_size_limit(Some(800), 1000), 800);
+        assert_eq!(domains_size_limit(Some(5000), 1000), 1000);
+        // A domain without a limit takes messages of the server limit
+        assert_eq!(domains_size_limit(None, 1000), 1000);
+        assert_eq!(domains_size_limit(Some(0), 1000), 1000);
+    }
+
+    #[test]
+    fn test_dmarc_override() {
+        use crate::email_auth::DkimResult;
+
+        let result = AuthenticationResult::new(
+            SpfResult::Fail,
+            DkimResult::None,
+            DmarcResult::Fail(DmarcPolicy::Reject),
+        );
+        let policy_override = dmarc_override(&result).unwrap();
+        assert_eq!(policy_override.reason, "local_policy");
+
+        let result = AuthenticationResult::new(
+            SpfResult::Fail,
+            DkimResult::None,
+            DmarcResult::Fail(DmarcPolicy::None),
+        );
+        assert!(dmarc_override(&result).is_none());
+
+        let result =
+            AuthenticationResult::new(SpfResult::Pass, DkimResult::None, DmarcResult::Pass);
+        assert!(dmarc_override(&result).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flush_if_idle() {
+        let input = b"MAIL FROM:<a@example.com>\r\nRCPT TO:<b@example.com>\r\n";
+        let mut reader = BufReader::new(&input[..]);
+        let mut writer = BufWriter::new(Vec::new());
+        let mut line = String::new();
+
+        // The RCPT command is already buffered, so the MAIL reply waits
+        reader.read_line(&mut line).await.unwrap();
+        writer.write_all(b"250 2.1.0 OK\r\n").await.unwrap();
+        flush_if_idle(&reader, &mut writer).await.unwrap();
+        assert!(writer.get_ref().is_empty());
+
+        reader.read_line(&mut line).await.unwrap();
+        writer.write_all(b"250 2.1.5 OK\r\n").await.unwrap();
+        flush_if_idle(&reader, &mut writer).await.unwrap();
+        assert_eq!(writer.get_ref(), b"250 2.1.0 OK\r\n250 2.1.5 OK\r\n");
+    }
+
+    #[test]
+    fn test_forward_job_is_sealed() {
+        use crate::email_auth::arc::{verify_chain, Keys};
+        use crate::email_auth::{ArcResult, SigningAlgorithm, SigningIdentity};
+        use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+
+        let private_key = rsa::RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+        let public_key = rsa::RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .unwrap();
+        let identity = SigningIdentity {
+            domain: "example.net".to_string(),
+            selector: "fwd".to_string(),
+            private_key_pem: private_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string(),
+            algorithm: SigningAlgorithm::RsaSha256,
+        };
+        let message = b"From: Alice <alice@example.com>\r\nTo: bob@example.net\r\nSubject: Hi\r\n\r\nHello\r\n";
+        let sealed = identity
+            .arc_seal(message, "mx.example.net; spf=pass dkim=pass", ArcResult::None)
+            .unwrap()
+            .unwrap();
+
+        // Unsealed, the message is forwarded as received
+        let job = forward_job(
+            Uuid::now_v7(),
+            Uuid::now_v7(),
+            "alice@example.com".to_string(),
+            vec!["bob@example.org".to_string()],
+            message,
+            None,
+            false,
+        );
+        assert!(job.forwarded);
+        let queued = base64::engine::general_purpose::STANDARD
+            .decode(job.raw_message_base64.unwrap())
+            .unwrap();
+        assert_eq!(queued, message);
+
+        let job = forward_job(
+            Uuid::now_v7(),
+            Uuid::now_v7(),
+            "alice@example.com".to_string(),
+            vec!["bob@example.org".to_string()],
+            message,
+            Some(sealed),
+            false,
+        );
+        assert!(job.forwarded);
+        let queued = base64::engine::general_purpose::STANDARD
+            .decode(job.raw_message_base64.unwrap())
+            .unwrap();
+        assert!(queued.starts_with(b"ARC-Seal: i=1; a=rsa-sha256;"));
+        assert!(String::from_utf8_lossy(&queued).contains("ARC-Message-Signature: i=1;"));
+
+        let keys = Keys::from([(
+            ("fwd".to_string(), "example.net".to_string()),
+            base64::engine::general_purpose::STANDARD.encode(public_key.as_bytes()),
+        )]);
+        let verification = verify_chain(&queued, &keys);
+        assert_eq!(verification.result, ArcResult::Pass);
+        assert_eq!(verification.sealers, ["example.net"]);
+    }
 }
@@ -0,0 +1,1086 @@
+//! Milter client - the Sendmail milter protocol, version 6
+//!
+//! External filters such as rspamd's milter proxy or clamav-milter are
+//! asked about each stage of an inbound SMTP session: the connection, HELO,
+//! MAIL, every accepted RCPT, DATA and finally the headers and body of the
+//! message. Each session keeps one connection to every configured milter.
+//!
+//! The first milter to refuse decides; a milter that accepts is not asked
+//! again for the message (or the connection, when it accepts at connect or
+//! HELO). At the end of the message milters may add, insert and change
+//! headers; they are not offered other modifications. A milter that cannot
+//! be reached, times out or breaks the protocol is dropped for the session
+//! when it fails open, and defers every command with 451 when it fails
+//! closed.
+
+use anyhow::{anyhow, bail, Result};
+use mairust_common::config::MilterConfig;
+use mairust_common::types::EmailAddress;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+/// Protocol version offered
+const VERSION: u32 = 6;
+
+/// Largest body chunk sent at once
+const BODY_CHUNK: usize = 65535;
+
+/// Largest packet accepted from a milter
+const MAX_PACKET: usize = 1024 * 1024;
+
+// Commands
+const SMFIC_ABORT: u8 = b'A';
+const SMFIC_BODY: u8 = b'B';
+const SMFIC_CONNECT: u8 = b'C';
+const SMFIC_MACRO: u8 = b'D';
+const SMFIC_BODYEOB: u8 = b'E';
+const SMFIC_HELO: u8 = b'H';
+const SMFIC_HEADER: u8 = b'L';
+const SMFIC_MAIL: u8 = b'M';
+const SMFIC_EOH: u8 = b'N';
+const SMFIC_OPTNEG: u8 = b'O';
+const SMFIC_QUIT: u8 = b'Q';
+const SMFIC_RCPT: u8 = b'R';
+const SMFIC_DATA: u8 = b'T';
+
+// Replies
+const SMFIR_ACCEPT: u8 = b'a';
+const SMFIR_CONTINUE: u8 = b'c';
+const SMFIR_DISCARD: u8 = b'd';
+const SMFIR_ADDHEADER: u8 = b'h';
+const SMFIR_INSHEADER: u8 = b'i';
+const SMFIR_CHGHEADER: u8 = b'm';
+const SMFIR_PROGRESS: u8 = b'p';
+const SMFIR_REJECT: u8 = b'r';
+const SMFIR_SKIP: u8 = b's';
+const SMFIR_TEMPFAIL: u8 = b't';
+const SMFIR_REPLYCODE: u8 = b'y';
+
+// Modifications offered: adding and changing headers
+const SMFIF_ADDHDRS: u32 = 0x01;
+const SMFIF_CHGHDRS: u32 = 0x10;
+
+// Steps a milter may skip or not reply to
+const SMFIP_NOCONNECT: u32 = 0x1;
+const SMFIP_NOHELO: u32 = 0x2;
+const SMFIP_NOMAIL: u32 = 0x4;
+const SMFIP_NORCPT: u32 = 0x8;
+const SMFIP_NOBODY: u32 = 0x10;
+const SMFIP_NOHDRS: u32 = 0x20;
+const SMFIP_NOEOH: u32 = 0x40;
+const SMFIP_NR_HDR: u32 = 0x80;
+const SMFIP_NOUNKNOWN: u32 = 0x100;
+const SMFIP_NODATA: u32 = 0x200;
+const SMFIP_SKIP: u32 = 0x400;
+const SMFIP_NR_CONN: u32 = 0x1000;
+const SMFIP_NR_HELO: u32 = 0x2000;
+const SMFIP_NR_MAIL: u32 = 0x4000;
+const SMFIP_NR_RCPT: u32 = 0x8000;
+const SMFIP_NR_DATA: u32 = 0x10000;
+const SMFIP_NR_EOH: u32 = 0x40000;
+const SMFIP_NR_BODY: u32 = 0x80000;
+
+/// Protocol flags offered
+const PROTOCOL_FLAGS: u32 = SMFIP_NOCONNECT
+    | SMFIP_NOHELO
+    | SMFIP_NOMAIL
+    | SMFIP_NORCPT
+    | SMFIP_NOBODY
+    | SMFIP_NOHDRS
+    | SMFIP_NOEOH
+    | SMFIP_NR_HDR
+    | SMFIP_NOUNKNOWN
+    | SMFIP_NODATA
+    | SMFIP_SKIP
+    | SMFIP_NR_CONN
+    | SMFIP_NR_HELO
+    | SMFIP_NR_MAIL
+    | SMFIP_NR_RCPT
+    | SMFIP_NR_DATA
+    | SMFIP_NR_EOH
+    | SMFIP_NR_BODY;
+
+/// What the milters decided at a stage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MilterVerdict {
+    /// Go on with the session
+    Continue,
+    /// Refuse the command with this reply
+    Reply(u16, String),
+    /// Accept the message, then throw it away
+    Discard,
+}
+
+impl MilterVerdict {
+    fn reject() -> Self {
+        MilterVerdict::Reply(550, "5.7.1 Command rejected".to_string())
+    }
+
+    fn tempfail() -> Self {
+        MilterVerdict::Reply(
+            451,
+            "4.7.1 Service unavailable, try again later".to_string(),
+        )
+    }
+}
+
+/// A header change asked for by a milter at the end of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderChange {
+    /// Add a header after the others
+    Add { name: String, value: String },
+    /// Insert a header at a position (0 is the first)
+    Insert {
+        index: usize,
+        name: String,
+        value: String,
+    },
+    /// Replace the nth (from 1) header of a name; an empty value removes it
+    Change {
+        index: usize,
+        name: String,
+        value: String,
+    },
+}
+
+/// A stage of the session a milter is asked about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Connect,
+    Helo,
+    Mail,
+    Rcpt,
+    Data,
+    Header,
+    EndOfHeaders,
+    Body,
+}
+
+impl Stage {
+    /// Protocol flag of a milter that does not want the stage
+    fn skip_flag(self) -> u32 {
+        match self {
+            Stage::Connect => SMFIP_NOCONNECT,
+            Stage::Helo => SMFIP_NOHELO,
+            Stage::Mail => SMFIP_NOMAIL,
+            Stage::Rcpt => SMFIP_NORCPT,
+            Stage::Data => SMFIP_NODATA,
+            Stage::Header => SMFIP_NOHDRS,
+            Stage::EndOfHeaders => SMFIP_NOEOH,
+            Stage::Body => SMFIP_NOBODY,
+        }
+    }
+
+    /// Protocol flag of a milter that does not reply to the stage
+    fn no_reply_flag(self) -> u32 {
+        match self {
+            Stage::Connect => SMFIP_NR_CONN,
+            Stage::Helo => SMFIP_NR_HELO,
+            Stage::Mail => SMFIP_NR_MAIL,
+            Stage::Rcpt => SMFIP_NR_RCPT,
+            Stage::Data => SMFIP_NR_DATA,
+            Stage::Header => SMFIP_NR_HDR,
+            Stage::EndOfHeaders => SMFIP_NR_EOH,
+            Stage::Body => SMFIP_NR_BODY,
+        }
+    }
+
+    /// Whether accepting at this stage accepts the whole connection
+    fn is_connection(self) -> bool {
+        matches!(self, Stage::Connect | Stage::Helo)
+    }
+}
+
+/// A milter's answer to a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Response {
+    Continue,
+    Accept,
+    Reject,
+    Tempfail,
+    Discard,
+    Skip,
+    ReplyCode(u16, String),
+}
+
+trait MilterStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MilterStream for T {}
+
+/// A negotiated connection to a milter
+struct MilterConnection {
+    stream: Box<dyn MilterStream>,
+    timeout: Duration,
+    /// Protocol flags the milter asked for
+    protocol: u32,
+}
+
+impl MilterConnection {
+    /// Connect to a milter and negotiate the protocol
+    async fn open(config: &MilterConfig) -> Result<Self> {
+        let connect_timeout = Duration::from_secs(config.connect_timeout_secs.max(1));
+        let stream: Box<dyn MilterStream> = match config.address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Box::new(
+                tokio::time::timeout(connect_timeout, tokio::net::UnixStream::connect(path))
+                    .await
+                    .map_err(|_| anyhow!("connection timed out"))??,
+            ),
+            #[cfg(not(unix))]
+            Some(_) => bail!("unix sockets are not supported on this platform"),
+            None => Box::new(
+                tokio::time::timeout(connect_timeout, TcpStream::connect(&config.address))
+                    .await
+                    .map_err(|_| anyhow!("connection timed out"))??,
+            ),
+        };
+
+        let mut connection = Self {
+            stream,
+            timeout: Duration::from_secs(config.timeout_secs.max(1)),
+            protocol: 0,
+        };
+        let mut offer = Vec::with_capacity(12);
+        offer.extend_from_slice(&VERSION.to_be_bytes());
+        offer.extend_from_slice(&(SMFIF_ADDHDRS | SMFIF_CHGHDRS).to_be_bytes());
+        offer.extend_from_slice(&PROTOCOL_FLAGS.to_be_bytes());
+        connection.send(SMFIC_OPTNEG, &offer).await?;
+
+        let (command, data) = connection.read_packet().await?;
+        if command != SMFIC_OPTNEG || data.len() < 12 {
+            bail!("unexpected reply to option negotiation");
+        }
+        let version = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        if version < 2 {
+            bail!("unsupported protocol version {}", version);
+        }
+        connection.protocol =
+            u32::from_be_bytes([data[8], data[9], data[10], data[11]]) & PROTOCOL_FLAGS;
+        Ok(connection)
+    }
+
+    /// Send a packet
+    async fn send(&mut self, command: u8, data: &[u8]) -> Result<()> {
+        let mut packet = Vec::with_capacity(5 + data.len());
+        packet.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+        packet.push(command);
+        packet.extend_from_slice(data);
+        tokio::time::timeout(self.timeout, self.stream.write_all(&packet))
+            .await
+            .map_err(|_| anyhow!("write timed out"))??;
+        Ok(())
+    }
+
+    /// Read a packet
+    async fn read_packet(&mut self) -> Result<(u8, Vec<u8>)> {
+        let read = async {
+            let mut length = [0u8; 4];
+            self.stream.read_exact(&mut length).await?;
+            let length = u32::from_be_bytes(length) as usize;
+            if length == 0 || length > MAX_PACKET {
+                bail!("bad packet length {}", length);
+            }
+            let mut packet = vec![0u8; length];
+            self.stream.read_exact(&mut packet).await?;
+            let data = packet.split_off(1);
+            Ok((packet[0], data))
+        };
+        tokio::time::timeout(self.timeout, read)
+            .await
+            .map_err(|_| anyhow!("reply timed out"))?
+    }
+
+    /// Read the answer to a command, waiting out progress notices
+    async fn response(&mut self) -> Result<Response> {
+        loop {
+            let (command, data) = self.read_packet().await?;
+            return Ok(match command {
+                SMFIR_PROGRESS => continue,
+                SMFIR_CONTINUE => Response::Continue,
+                SMFIR_ACCEPT => Response::Accept,
+                SMFIR_REJECT => Response::Reject,
+                SMFIR_TEMPFAIL => Response::Tempfail,
+                SMFIR_DISCARD => Response::Discard,
+                SMFIR_SKIP => Response::Skip,
+                SMFIR_REPLYCODE => {
+                    let (code, text) = parse_reply_code(&data)
+                        .ok_or_else(|| anyhow!("bad reply code from milter"))?;
+                    Response::ReplyCode(code, text)
+                }
+                other => bail!("unexpected reply '{}'", other as char),
+            });
+        }
+    }
+
+    /// Send a stage's macros and command, and read the answer
+    async fn ask(
+        &mut self,
+        stage: Stage,
+        macros: &[(&str, &str)],
+        command: u8,
+        data: &[u8],
+    ) -> Result<Response> {
+        if self.protocol & stage.skip_flag() != 0 {
+            return Ok(Response::Continue);
+        }
+        if !macros.is_empty() {
+            self.send(SMFIC_MACRO, &macro_packet(command, macros))
+                .await?;
+        }
+        self.send(command, data).await?;
+        if self.protocol & stage.no_reply_flag() != 0 {
+            return Ok(Response::Continue);
+        }
+        self.response().await
+    }
+}
+
+/// A milter within a session
+struct MilterState {
+    config: MilterConfig,
+    connection: Option<MilterConnection>,
+    /// Failed while failing closed: every command is deferred
+    failed: bool,
+    /// Accepted the connection; not asked again
+    accepted_connection: bool,
+    /// Accepted or discarded the current message; not asked again for it
+    done_message: bool,
+}
+
+impl MilterState {
+    /// Drop the connection after an error
+    fn fail(&mut self, error: anyhow::Error) -> MilterVerdict {
+        warn!("Milter {} failed: {}", self.config.name, error);
+        self.connection = None;
+        if self.config.fail_open {
+            MilterVerdict::Continue
+        } else {
+            self.failed = true;
+            MilterVerdict::tempfail()
+        }
+    }
+
+    /// Turn an answer into a verdict, remembering acceptance
+    fn verdict(&mut self, stage: Stage, response: Response) -> MilterVerdict {
+        let verdict = match response {
+            Response::Continue | Response::Skip => MilterVerdict::Continue,
+            Response::Accept if stage.is_connection() => {
+                self.accepted_connection = true;
+                MilterVerdict::Continue
+            }
+            Response::Accept => {
+                self.done_message = true;
+                MilterVerdict::Continue
+            }
+            Response::Discard => {
+                self.done_message = true;
+                MilterVerdict::Discard
+            }
+            Response::Reject => MilterVerdict::reject(),
+            Response::Tempfail => MilterVerdict::tempfail(),
+            Response::ReplyCode(code, text) => MilterVerdict::Reply(code, text),
+        };
+        if let MilterVerdict::Reply(code, _) = &verdict {
+            debug!(
+                "Milter {} replied {} at {:?}",
+                self.config.name, code, stage
+            );
+        }
+        verdict
+    }
+
+    /// Whether the milter is out of the current message
+    fn idle(&self) -> bool {
+        self.connection.is_none() || self.accepted_connection || self.done_message
+    }
+
+    /// Ask the milter about a stage
+    async fn ask(
+        &mut self,
+        stage: Stage,
+        macros: &[(&str, &str)],
+        command: u8,
+        data: &[u8],
+    ) -> MilterVerdict {
+        if self.failed {
+            return MilterVerdict::tempfail();
+        }
+        if self.idle() {
+            return MilterVerdict::Continue;
+        }
+        let Some(connection) = self.connection.as_mut() else {
+            return MilterVerdict::Continue;
+        };
+        match connection.ask(stage, macros, command, data).await {
+            Ok(response) => self.verdict(stage, response),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Send the message and collect the milter's header changes
+    async fn end_of_message(
+        &mut self,
+        headers: &[(String, String)],
+        body: &[u8],
+        changes: &mut Vec<HeaderChange>,
+    ) -> MilterVerdict {
+        if self.failed {
+            return MilterVerdict::tempfail();
+        }
+        for (name, value) in headers {
+            let data = [name.as_bytes(), b"\0", value.as_bytes(), b"\0"].concat();
+            let verdict = self.ask(Stage::Header, &[], SMFIC_HEADER, &data).await;
+            if verdict != MilterVerdict::Continue || self.idle() {
+                return verdict;
+            }
+        }
+        let verdict = self.ask(Stage::EndOfHeaders, &[], SMFIC_EOH, &[]).await;
+        if verdict != MilterVerdict::Continue || self.idle() {
+            return verdict;
+        }
+        for chunk in body.chunks(BODY_CHUNK) {
+            let Some(connection) = self.connection.as_mut() else {
+                break;
+            };
+            let response = match connection.ask(Stage::Body, &[], SMFIC_BODY, chunk).await {
+                Ok(response) => response,
+                Err(e) => return self.fail(e),
+            };
+            let skip = response == Response::Skip;
+            let verdict = self.verdict(Stage::Body, response);
+            if verdict != MilterVerdict::Continue || self.idle() {
+                return verdict;
+            }
+            if skip {
+                break;
+            }
+        }
+
+        let Some(connection) = self.connection.as_mut() else {
+            return MilterVerdict::Continue;
+        };
+        match end_of_body(connection, changes).await {
+            Ok(response) => self.verdict(Stage::Body, response),
+            Err(e) => self.fail(e),
+        }
+    }
+}
+
+/// Send the end of the body and read header changes up to the answer
+async fn end_of_body(
+    connection: &mut MilterConnection,
+    changes: &mut Vec<HeaderChange>,
+) -> Result<Response> {
+    connection.send(SMFIC_BODYEOB, &[]).await?;
+    loop {
+        let (command, data) = connection.read_packet().await?;
+        let change = match command {
+            SMFIR_ADDHEADER => {
+                let (name, value) = header_fields(&data)?;
+                HeaderChange::Add { name, value }
+            }
+            SMFIR_INSHEADER | SMFIR_CHGHEADER if data.len() >= 4 => {
+                let index = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                let (name, value) = header_fields(&data[4..])?;
+                if command == SMFIR_INSHEADER {
+                    HeaderChange::Insert { index, name, value }
+                } else {
+                    HeaderChange::Change { index, name, value }
+                }
+            }
+            SMFIR_PROGRESS => continue,
+            SMFIR_CONTINUE => return Ok(Response::Continue),
+            SMFIR_ACCEPT => return Ok(Response::Accept),
+            SMFIR_REJECT => return Ok(Response::Reject),
+            SMFIR_TEMPFAIL => return Ok(Response::Tempfail),
+            SMFIR_DISCARD => return Ok(Response::Discard),
+            SMFIR_REPLYCODE => {
+                let (code, text) =
+                    parse_reply_code(&data).ok_or_else(|| anyhow!("bad reply code from milter"))?;
+                return Ok(Response::ReplyCode(code, text));
+            }
+            other => bail!("unexpected reply '{}' at end of message", other as char),
+        };
+        changes.push(change);
+    }
+}
+
+/// The milters of one SMTP session
+pub struct MilterSession {
+    milters: Vec<MilterState>,
+    /// A milter discarded the current message
+    discard: bool,
+    /// The milters were told about a message
+    in_message: bool,
+}
+
+impl MilterSession {
+    /// Milters to connect to; none when `configs` is empty
+    pub fn new(configs: &[MilterConfig]) -> Self {
+        Self {
+            milters: configs
+                .iter()
+                .map(|config| MilterState {
+                    config: config.clone(),
+                    connection: None,
+                    failed: false,
+                    accepted_connection: false,
+                    done_message: false,
+                })
+                .collect(),
+            discard: false,
+            in_message: false,
+        }
+    }
+
+    /// Ask every milter in turn, stopping at the first refusal
+    async fn ask(
+        &mut self,
+        stage: Stage,
+        macros: &[(&str, &str)],
+        command: u8,
+        data: &[u8],
+    ) -> MilterVerdict {
+        for milter in &mut self.milters {
+            match milter.ask(stage, macros, command, data).await {
+                MilterVerdict::Continue => {}
+                MilterVerdict::Discard => self.discard = true,
+                reply => return reply,
+            }
+        }
+        MilterVerdict::Continue
+    }
+
+    /// Connect to the milters and tell them about the client
+    pub async fn connect(&mut self, hostname: &str, peer_addr: SocketAddr) -> MilterVerdict {
+        for milter in &mut self.milters {
+            match MilterConnection::open(&milter.config).await {
+                Ok(connection) => milter.connection = Some(connection),
+                Err(e) => {
+                    milter.fail(e);
+                }
+            }
+        }
+
+        let client_addr = peer_addr.ip().to_string();
+        let family = if peer_addr.is_ipv4() { b'4' } else { b'6' };
+        let mut data = Vec::new();
+        data.extend_from_slice(format!("[{}]\0", client_addr).as_bytes());
+        data.push(family);
+        data.extend_from_slice(&peer_addr.port().to_be_bytes());
+        data.extend_from_slice(client_addr.as_bytes());
+        data.push(0);
+        let macros = [
+            ("j", hostname),
+            ("{daemon_name}", "MaiRust"),
+            ("{client_addr}", client_addr.as_str()),
+        ];
+        self.ask(Stage::Connect, &macros, SMFIC_CONNECT, &data)
+            .await
+    }
+
+    /// Tell the milters the HELO name
+    pub async fn helo(&mut self, name: &str) -> MilterVerdict {
+        let data = [name.as_bytes(), b"\0"].concat();
+        self.ask(Stage::Helo, &[], SMFIC_HELO, &data).await
+    }
+
+    /// Tell the milters the sender and MAIL parameters (`args` is the
+    /// argument of MAIL)
+    pub async fn mail(
+        &mut self,
+        from: Option<&EmailAddress>,
+        args: &str,
+        authenticated_user: Option<&str>,
+    ) -> MilterVerdict {
+        let from = from.map(|from| from.to_string()).unwrap_or_default();
+        let data = command_arguments(&format!("<{}>", from), args);
+        let mut macros = vec![("{mail_addr}", from.as_str())];
+        if let Some(user) = authenticated_user {
+            macros.push(("{auth_authen}", user));
+        }
+        self.in_message = true;
+        self.ask(Stage::Mail, &macros, SMFIC_MAIL, &data).await
+    }
+
+    /// Tell the milters an accepted recipient (`args` is the argument of
+    /// RCPT)
+    pub async fn rcpt(&mut self, to: &EmailAddress, args: &str) -> MilterVerdict {
+        let to = to.to_string();
+        let data = command_arguments(&format!("<{}>", to), args);
+        let macros = [("{rcpt_addr}", to.as_str())];
+        self.ask(Stage::Rcpt, &macros, SMFIC_RCPT, &data).await
+    }
+
+    /// Tell the milters that the client is about to send the message
+    pub async fn data(&mut self) -> MilterVerdict {
+        self.ask(Stage::Data, &[], SMFIC_DATA, &[]).await
+    }
+
+    /// Send the message to the milters, and collect their header changes
+    ///
+    /// Every milter sees the message as received; the changes of all of
+    /// them are returned in order. The milters are ready for the next
+    /// message afterwards.
+    pub async fn end_of_message(&mut self, data: &[u8]) -> (MilterVerdict, Vec<HeaderChange>) {
+        let mut changes = Vec::new();
+        let mut verdict = MilterVerdict::Continue;
+        if !self.milters.is_empty() {
+            let (headers, body) = split_message(data);
+            for milter in &mut self.milters {
+                match milter.end_of_message(&headers, body, &mut changes).await {
+                    MilterVerdict::Continue => {}
+                    MilterVerdict::Discard => self.discard = true,
+                    reply => {
+                        verdict = reply;
+                        break;
+                    }
+                }
+            }
+        }
+        if verdict == MilterVerdict::Continue && self.discard {
+            verdict = MilterVerdict::Discard;
+        }
+        self.reset();
+        (verdict, changes)
+    }
+
+    /// Tell the milters that the message was given up (RSET, refused DATA)
+    pub async fn abort(&mut self) {
+        if self.in_message {
+            for milter in &mut self.milters {
+                if milter.accepted_connection {
+                    continue;
+                }
+                if let Some(connection) = milter.connection.as_mut() {
+                    if let Err(e) = connection.send(SMFIC_ABORT, &[]).await {
+                        milter.fail(e);
+                    }
+                }
+            }
+        }
+        self.reset();
+    }
+
+    /// Close the connections to the milters
+    pub async fn quit(&mut self) {
+        for milter in &mut self.milters {
+            if let Some(mut connection) = milter.connection.take() {
+                let _ = connection.send(SMFIC_QUIT, &[]).await;
+            }
+        }
+    }
+
+    /// Forget the state of the current message
+    fn reset(&mut self) {
+        self.discard = false;
+        self.in_message = false;
+        for milter in &mut self.milters {
+            milter.done_message = false;
+        }
+    }
+}
+
+/// Build a macro packet for a command: the command, then names and values
+fn macro_packet(command: u8, macros: &[(&str, &str)]) -> Vec<u8> {
+    let mut data = vec![command];
+    for (name, value) in macros {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+    }
+    data
+}
+
+/// The address and ESMTP parameters of MAIL or RCPT, NUL-terminated
+fn command_arguments(address: &str, args: &str) -> Vec<u8> {
+    let parameters = args.split_once('>').map(|(_, rest)| rest).unwrap_or("");
+    let mut data = Vec::new();
+    for argument in std::iter::once(address).chain(parameters.split_whitespace()) {
+        data.extend_from_slice(argument.as_bytes());
+        data.push(0);
+    }
+    data
+}
+
+/// Split a NUL-terminated header name and value
+fn header_fields(data: &[u8]) -> Result<(String, String)> {
+    let mut fields = data.split(|b| *b == 0);
+    match (fields.next(), fields.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => Ok((
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(value).into_owned(),
+        )),
+        _ => bail!("bad header in milter reply"),
+    }
+}
+
+/// Parse the "code text" of a reply code answer
+fn parse_reply_code(data: &[u8]) -> Option<(u16, String)> {
+    let reply = String::from_utf8_lossy(data);
+    let reply = reply.trim_end_matches('\0');
+    // Only the first line of a multiline reply is used
+    let line = reply.lines().next()?.trim();
+    let code: u16 = line.get(..3)?.parse().ok()?;
+    let text = &line[3..];
+    if !(400..600).contains(&code) {
+        return None;
+    }
+    let text = text.trim_start_matches(['-', ' ']).trim();
+    let text = if text.is_empty() {
+        if code >= 500 {
+            "5.7.1 Command rejected"
+        } else {
+            "4.7.1 Service unavailable, try again later"
+        }
+    } else {
+        text
+    };
+    Some((code, text.to_string()))
+}
+
+/// Byte ranges of the header fields of a message (continuation lines
+/// included), and where the rest of the message starts
+fn header_ranges(data: &[u8]) -> (Vec<(usize, usize)>, usize) {
+    let mut fields: Vec<(usize, usize)> = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let end = data[offset..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(data.len(), |i| offset + i + 1);
+        let line = &data[offset..end];
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        match (line[0], fields.last_mut()) {
+            (b' ' | b'\t', Some(field)) => field.1 = end,
+            _ if line.contains(&b':') => fields.push((offset, end)),
+            _ => break,
+        }
+        offset = end;
+    }
+    (fields, offset)
+}
+
+/// Headers of a message as milters expect them (name and value, line
+/// breaks within values as LF), and its body
+fn split_message(data: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let (ranges, end) = header_ranges(data);
+    let headers = ranges
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let field = String::from_utf8_lossy(&data[start..end]);
+            let (name, value) = field.split_once(':')?;
+            let value = value.trim_start_matches([' ', '\t']);
+            let value = value.trim_end_matches(['\r', '\n']).replace("\r\n", "\n");
+            Some((name.trim_end().to_string(), value))
+        })
+        .collect();
+    // The body starts after the empty line
+    let body = match &data[end..] {
+        [b'\r', b'\n', body @ ..] | [b'\n', body @ ..] => body,
+        body => body,
+    };
+    (headers, body)
+}
+
+/// Format a header field, with CRLF line breaks
+fn format_header(name: &str, value: &str) -> Vec<u8> {
+    let value = value.replace("\r\n", "\n").replace('\n', "\r\n");
+    let separator = if value.starts_with([' ', '\t']) {
+        ""
+    } else {
+        " "
+    };
+    format!("{}:{}{}\r\n", name, separator, value).into_bytes()
+}
+
+/// Apply the header changes of milters to a message
+pub fn apply_header_changes<'a>(data: &'a [u8], changes: &[HeaderChange]) -> Cow<'a, [u8]> {
+    if changes.is_empty() {
+        return Cow::Borrowed(data);
+    }
+    let (ranges, end) = header_ranges(data);
+    let mut fields: Vec<Vec<u8>> = ranges
+        .into_iter()
+        .map(|(start, end)| data[start..end].to_vec())
+        .collect();
+    let has_name = |field: &[u8], name: &str| {
+        field.len() > name.len()
+            && field[..name.len()].eq_ignore_ascii_case(name.as_bytes())
+            && matches!(field[name.len()], b':' | b' ' | b'\t')
+    };
+
+    for change in changes {
+        match change {
+            HeaderChange::Add { name, value } => fields.push(format_header(name, value)),
+            HeaderChange::Insert { index, name, value } => {
+                let index = (*index).min(fields.len());
+                fields.insert(index, format_header(name, value));
+            }
+            HeaderChange::Change { index, name, value } => {
+                let position = fields
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, field)| has_name(field, name))
+                    .nth(index.saturating_sub(1))
+                    .map(|(i, _)| i);
+                match (position, value.is_empty()) {
+                    (Some(i), true) => {
+                        fields.remove(i);
+                    }
+                    (Some(i), false) => fields[i] = format_header(name, value),
+                    (None, true) => {}
+                    (None, false) => fields.push(format_header(name, value)),
+                }
+            }
+        }
+    }
+
+    let mut message = fields.concat();
+    message.extend_from_slice(&data[end..]);
+    Cow::Owned(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: a@example.com\r\n\
+Subject: Hello\r\n\
+\tworld\r\n\
+X-Spam: no\r\n\
+\r\n\
+Body\r\n";
+
+    #[test]
+    fn test_split_message() {
+        let (headers, body) = split_message(MESSAGE);
+        assert_eq!(
+            headers,
+            vec![
+                ("From".to_string(), "a@example.com".to_string()),
+                ("Subject".to_string(), "Hello\n\tworld".to_string()),
+                ("X-Spam".to_string(), "no".to_string()),
+            ]
+        );
+        assert_eq!(body, b"Body\r\n");
+    }
+
+    #[test]
+    fn test_apply_header_changes() {
+        assert!(matches!(
+            apply_header_changes(MESSAGE, &[]),
+            Cow::Borrowed(_)
+        ));
+
+        let changed = apply_header_changes(
+            MESSAGE,
+            &[
+                HeaderChange::Add {
+                    name: "X-Virus".to_string(),
+                    value: "clean".to_string(),
+                },
+                HeaderChange::Insert {
+                    index: 0,
+                    name: "X-First".to_string(),
+                    value: "1".to_string(),
+                },
+                HeaderChange::Change {
+                    index: 1,
+                    name: "x-spam".to_string(),
+                    value: "yes\n\tscore=9".to_string(),
+                },
+                HeaderChange::Change {
+                    index: 1,
+                    name: "Subject".to_string(),
+                    value: String::new(),
+                },
+            ],
+        );
+        assert_eq!(
+            &changed[..],
+            &b"X-First: 1\r\n\
+From: a@example.com\r\n\
+x-spam: yes\r\n\
+\tscore=9\r\n\
+X-Virus: clean\r\n\
+\r\n\
+Body\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_parse_reply_code() {
+        assert_eq!(
+            parse_reply_code(b"550 5.7.1 Virus found\0"),
+            Some((550, "5.7.1 Virus found".to_string()))
+        );
+        assert_eq!(
+            parse_reply_code(b"451"),
+            Some((
+                451,
+                "4.7.1 Service unavailable, try again later".to_string()
+            ))
+        );
+        assert_eq!(parse_reply_code(b"250 OK"), None);
+        assert_eq!(parse_reply_code(b"oops"), None);
+    }
+
+    #[test]
+    fn test_command_arguments() {
+        assert_eq!(
+            command_arguments(
+                "<a@example.com>",
+                "FROM:<a@example.com> SIZE=100 BODY=8BITMIME"
+            ),
+            b"<a@example.com>\0SIZE=100\0BODY=8BITMIME\0".to_vec()
+        );
+        assert_eq!(command_arguments("<>", "FROM:<>"), b"<>\0".to_vec());
+        assert_eq!(
+            macro_packet(SMFIC_RCPT, &[("{rcpt_addr}", "b@example.com")]),
+            b"R{rcpt_addr}\0b@example.com\0".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_milter_session() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // A milter that rejects one recipient and adds a header
+        let milter = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut seen = Vec::new();
+            loop {
+                let mut length = [0u8; 4];
+                if stream.read_exact(&mut length).await.is_err() {
+                    break;
+                }
+                let mut packet = vec![0u8; u32::from_be_bytes(length) as usize];
+                stream.read_exact(&mut packet).await.unwrap();
+                let command = packet[0];
+                seen.push(command);
+                let replies: Vec<(u8, Vec<u8>)> = match command {
+                    SMFIC_OPTNEG => {
+                        let mut data = VERSION.to_be_bytes().to_vec();
+                        data.extend_from_slice(&SMFIF_ADDHDRS.to_be_bytes());
+                        data.extend_from_slice(&(SMFIP_NOHELO | SMFIP_NR_HDR).to_be_bytes());
+                        vec![(SMFIC_OPTNEG, data)]
+                    }
+                    SMFIC_MACRO | SMFIC_HEADER | SMFIC_ABORT => vec![],
+                    SMFIC_RCPT if packet.starts_with(b"R<spam@") => vec![(SMFIR_REJECT, vec![])],
+                    SMFIC_BODYEOB => vec![
+                        (SMFIR_PROGRESS, vec![]),
+                        (SMFIR_ADDHEADER, b"X-Milter\0checked\0".to_vec()),
+                        (SMFIR_CONTINUE, vec![]),
+                    ],
+                    SMFIC_QUIT => break,
+                    _ => vec![(SMFIR_CONTINUE, vec![])],
+                };
+                for (reply, data) in replies {
+                    let mut packet = (data.len() as u32 + 1).to_be_bytes().to_vec();
+                    packet.push(reply);
+                    packet.extend_from_slice(&data);
+                    stream.write_all(&packet).await.unwrap();
+                }
+            }
+            seen
+        });
+
+        let config = MilterConfig {
+            name: "test".to_string(),
+            address,
+            fail_open: false,
+            connect_timeout_secs: 5,
+            timeout_secs: 5,
+        };
+        let mut session = MilterSession::new(&[config]);
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        assert_eq!(
+            session.connect("mx.example.com", peer).await,
+            MilterVerdict::Continue
+        );
+        assert_eq!(
+            session.helo("client.example").await,
+            MilterVerdict::Continue
+        );
+        let from = EmailAddress::new("a", "example.com");
+        assert_eq!(
+            session
+                .mail(Some(&from), "FROM:<a@example.com>", None)
+                .await,
+            MilterVerdict::Continue
+        );
+        let spam = EmailAddress::new("spam", "example.org");
+        assert_eq!(
+            session.rcpt(&spam, "TO:<spam@example.org>").await,
+            MilterVerdict::reject()
+        );
+        let rcpt = EmailAddress::new("b", "example.org");
+        assert_eq!(
+            session.rcpt(&rcpt, "TO:<b@example.org>").await,
+            MilterVerdict::Continue
+        );
+        assert_eq!(session.data().await, MilterVerdict::Continue);
+        let (verdict, changes) = session.end_of_message(MESSAGE).await;
+        assert_eq!(verdict, MilterVerdict::Continue);
+        assert_eq!(
+            changes,
+            vec![HeaderChange::Add {
+                name: "X-Milter".to_string(),
+                value: "checked".to_string(),
+            }]
+        );
+        session.quit().await;
+
+        let seen = milter.await.unwrap();
+        // HELO was skipped, as the milter asked
+        assert!(!seen.contains(&SMFIC_HELO));
+        assert_eq!(seen.iter().filter(|c| **c == SMFIC_HEADER).count(), 3);
+        assert!(seen.contains(&SMFIC_BODY));
+        assert_eq!(seen.last(), Some(&SMFIC_QUIT));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_milter() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let config = MilterConfig {
+            name: "gone".to_string(),
+            address,
+            fail_open: true,
+            connect_timeout_secs: 1,
+            timeout_secs: 1,
+        };
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+
+        let mut session = MilterSession::new(std::slice::from_ref(&config));
+        assert_eq!(
+            session.connect("mx.example.com", peer).await,
+            MilterVerdict::Continue
+        );
+        assert_eq!(session.data().await, MilterVerdict::Continue);
+
+        let config = MilterConfig {
+            fail_open: false,
+            ..config
+        };
+        let mut session = MilterSession::new(&[config]);
+        assert_eq!(
+            session.connect("mx.example.com", peer).await,
+            MilterVerdict::tempfail()
+        );
+        assert_eq!(session.data().await, MilterVerdict::tempfail());
+    }
+}
@@ -1,11 +1,23 @@
 //! SMTP server module
 
 mod auth;
+mod data;
+mod greylist;
 mod handler;
+mod milter;
+mod rate_limit;
+mod sender;
 mod server;
+mod stream;
 mod tls;
 
 pub use auth::{AuthResult, SmtpAuthenticator};
+pub use data::{DataError, DataScanner};
+pub use greylist::{Greylist, GreylistDeferral, GreylistVerdict};
 pub use handler::SmtpHandler;
+pub use milter::{apply_header_changes, HeaderChange, MilterSession, MilterVerdict};
+pub use rate_limit::{RateLimitClient, RateLimitKind, SmtpRateLimiter};
+pub use sender::{rewrite_from, SenderVerdict, SenderVerifier};
 pub use server::{SmtpServer, SmtpServiceType};
+pub use stream::SmtpStream;
 pub use tls::{create_tls_acceptor, is_tls_configured};
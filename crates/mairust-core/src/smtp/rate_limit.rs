@@ -0,0 +1,374 @@
+//! SMTP rate limiting
+//!
+//! Connections are limited per client IP address, and messages (MAIL FROM)
+//! and recipients (RCPT TO) per authenticated user or, for unauthenticated
+//! sessions, per client IP address. Messages of unauthenticated sessions
+//! are also limited per envelope sender domain. Each limit is a token
+//! bucket holding a minute's allowance that refills continuously, so
+//! clients may send in bursts but not exceed the rate over time.
+//!
+//! Buckets live in memory. Their use is also counted in
+//! `rate_limit_counters`, per minute, and a new bucket starts with what was
+//! already used in the current minute, so a restart or another server
+//! instance does not hand out a fresh allowance.
+
+use chrono::{DateTime, DurationRound, Utc};
+use mairust_common::config::SmtpRateLimitConfig;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::RateLimitCounter;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// How often counts are written to `rate_limit_counters`
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Window type of the stored counters
+const WINDOW_TYPE: &str = "minute";
+
+/// What a limit counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKind {
+    Connection,
+    Message,
+    Recipient,
+}
+
+impl RateLimitKind {
+    /// Scope of the stored counters
+    fn scope(&self) -> &'static str {
+        match self {
+            RateLimitKind::Connection => "smtp_connection",
+            RateLimitKind::Message => "smtp_message",
+            RateLimitKind::Recipient => "smtp_recipient",
+        }
+    }
+}
+
+/// Who a limit applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitClient<'a> {
+    /// An unauthenticated client
+    Ip(IpAddr),
+    /// An authenticated user, by email address
+    User(&'a str),
+    /// The envelope sender domain of an unauthenticated client
+    SenderDomain(&'a str),
+}
+
+impl RateLimitClient<'_> {
+    /// Scope key of the stored counters
+    fn key(&self) -> String {
+        match self {
+            RateLimitClient::Ip(ip) => format!("ip:{}", ip),
+            RateLimitClient::User(email) => format!("user:{}", email.to_lowercase()),
+            RateLimitClient::SenderDomain(domain) => format!("domain:{}", domain.to_lowercase()),
+        }
+    }
+}
+
+/// Per-minute limit of a client; 0 when unlimited
+fn limit(config: &SmtpRateLimitConfig, kind: RateLimitKind, client: &RateLimitClient) -> u32 {
+    match (kind, client) {
+        (RateLimitKind::Connection, RateLimitClient::Ip(_)) => config.connections_per_ip,
+        // Connections are counted before anyone authenticates
+        (RateLimitKind::Connection, RateLimitClient::User(_)) => 0,
+        (RateLimitKind::Message, RateLimitClient::Ip(_)) => config.messages_per_ip,
+        (RateLimitKind::Message, RateLimitClient::User(_)) => config.messages_per_user,
+        (RateLimitKind::Recipient, RateLimitClient::Ip(_)) => config.recipients_per_ip,
+        (RateLimitKind::Recipient, RateLimitClient::User(_)) => config.recipients_per_user,
+        (RateLimitKind::Message, RateLimitClient::SenderDomain(_)) => {
+            config.messages_per_sender_domain
+        }
+        // Only messages are counted per sender domain
+        (_, RateLimitClient::SenderDomain(_)) => 0,
+    }
+}
+
+/// Token bucket holding a minute's allowance
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket for `limit` per minute, of which `used` are taken
+    fn new(limit: u32, used: u32, now: Instant) -> Self {
+        Self {
+            capacity: limit as f64,
+            tokens: limit.saturating_sub(used) as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.capacity / 60.0).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Take a token if one is left
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket refilled completely, and can be dropped
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+type BucketKey = (RateLimitKind, String);
+
+/// Rate limits, shared by the sessions of an SMTP server
+pub struct SmtpRateLimiter {
+    db_pool: DatabasePool,
+    config: SmtpRateLimitConfig,
+    buckets: Mutex<HashMap<BucketKey, TokenBucket>>,
+    /// Tokens taken since the last flush, with the limit they count against
+    pending: Mutex<HashMap<BucketKey, (u32, u32)>>,
+    last_flush: Mutex<Instant>,
+}
+
+impl SmtpRateLimiter {
+    /// Create a rate limiter
+    pub fn new(db_pool: DatabasePool, config: SmtpRateLimitConfig) -> Self {
+        Self {
+            db_pool,
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            last_flush: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Whether the limits are enforced
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Count a connection from `client_ip`, returning whether it is within
+    /// the limit
+    pub async fn check_connection(&self, client_ip: IpAddr) -> bool {
+        self.check(RateLimitKind::Connection, RateLimitClient::Ip(client_ip))
+            .await
+    }
+
+    /// Count a message or recipient of `client`, returning whether it is
+    /// within the limit
+    pub async fn check(&self, kind: RateLimitKind, client: RateLimitClient<'_>) -> bool {
+        if !self.enabled() {
+            return true;
+        }
+        let limit = limit(&self.config, kind, &client);
+        if limit == 0 {
+            return true;
+        }
+
+        let key = (kind, client.key());
+        let known = self.buckets.lock().unwrap().contains_key(&key);
+        let used = if known {
+            0
+        } else {
+            self.used_this_minute(kind, &key.1).await
+        };
+
+        let allowed = {
+            let mut buckets = self.buckets.lock().unwrap();
+            buckets
+                .entry(key.clone())
+                .or_insert_with(|| TokenBucket::new(limit, used, Instant::now()))
+                .try_take(Instant::now())
+        };
+        if allowed {
+            self.pending
+                .lock()
+                .unwrap()
+                .entry(key)
+                .or_insert((0, limit))
+                .0 += 1;
+        } else {
+            info!(
+                "SMTP {} rate limit of {} per minute exceeded by {}",
+                kind.scope(),
+                limit,
+                key.1
+            );
+        }
+
+        self.flush_due();
+        allowed
+    }
+
+    /// What a client used of a limit in the current minute, across
+    /// restarts and server instances
+    async fn used_this_minute(&self, kind: RateLimitKind, key: &str) -> u32 {
+        let counter = sqlx::query_as::<_, RateLimitCounter>(
+            r#"
+            SELECT * FROM rate_limit_counters
+            WHERE tenant_id IS NULL AND scope = $1 AND scope_key = $2
+              AND window_type = $3 AND window_start = $4
+            "#,
+        )
+        .bind(kind.scope())
+        .bind(key)
+        .bind(WINDOW_TYPE)
+        .bind(window_start(Utc::now()))
+        .fetch_optional(self.db_pool.pool())
+        .await;
+        match counter {
+            Ok(counter) => counter.map_or(0, |c| c.count.max(0) as u32),
+            Err(e) => {
+                warn!("Failed to read SMTP rate limit counter of {}: {}", key, e);
+                0
+            }
+        }
+    }
+
+    /// Write pending counts and drop idle buckets, at most once per interval
+    fn flush_due(&self) {
+        {
+            let mut last_flush = self.last_flush.lock().unwrap();
+            if last_flush.elapsed() < FLUSH_INTERVAL {
+                return;
+            }
+            *last_flush = Instant::now();
+        }
+
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| !bucket.is_full(now));
+
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return;
+        }
+        let db_pool = self.db_pool.clone();
+        tokio::spawn(async move {
+            let window_start = window_start(Utc::now());
+            for ((kind, key), (count, limit)) in pending {
+                let result = sqlx::query(
+                    r#"
+                    INSERT INTO rate_limit_counters
+                        (id, tenant_id, scope, scope_key, window_type, window_start, count, limit_value)
+                    VALUES (gen_random_uuid(), NULL, $1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (scope, scope_key, window_type, window_start)
+                        WHERE tenant_id IS NULL
+                    DO UPDATE SET count = rate_limit_counters.count + EXCLUDED.count,
+                        updated_at = NOW()
+                    "#,
+                )
+                .bind(kind.scope())
+                .bind(&key)
+                .bind(WINDOW_TYPE)
+                .bind(window_start)
+                .bind(count as i32)
+                .bind(limit as i32)
+                .execute(db_pool.pool())
+                .await;
+                if let Err(e) = result {
+                    warn!("Failed to store SMTP rate limit counter of {}: {}", key, e);
+                }
+            }
+            debug!("Stored SMTP rate limit counters");
+        });
+    }
+}
+
+/// Start of the minute `at` falls in
+fn window_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    at.duration_trunc(chrono::Duration::minutes(1))
+        .unwrap_or(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3, 0, start);
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+
+        // One token every 20 seconds
+        assert!(!bucket.try_take(start + Duration::from_secs(10)));
+        assert!(bucket.try_take(start + Duration::from_secs(20)));
+        assert!(!bucket.try_take(start + Duration::from_secs(21)));
+
+        // Never more than the capacity
+        let later = start + Duration::from_secs(3600);
+        assert!(bucket.is_full(later));
+        for _ in 0..3 {
+            assert!(bucket.try_take(later));
+        }
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn test_token_bucket_starts_with_used_tokens() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, 1, start);
+        assert!(!bucket.is_full(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+
+        let mut bucket = TokenBucket::new(2, 5, start);
+        assert!(!bucket.try_take(start));
+    }
+
+    #[test]
+    fn test_limits() {
+        let config = SmtpRateLimitConfig::default();
+        let ip = RateLimitClient::Ip("192.0.2.1".parse().unwrap());
+        let user = RateLimitClient::User("Alice@Example.com");
+
+        assert_eq!(limit(&config, RateLimitKind::Connection, &ip), 60);
+        assert_eq!(limit(&config, RateLimitKind::Connection, &user), 0);
+        assert_eq!(limit(&config, RateLimitKind::Message, &ip), 30);
+        assert_eq!(limit(&config, RateLimitKind::Message, &user), 100);
+        assert_eq!(limit(&config, RateLimitKind::Recipient, &ip), 300);
+        assert_eq!(limit(&config, RateLimitKind::Recipient, &user), 1000);
+
+        assert_eq!(ip.key(), "ip:192.0.2.1");
+        assert_eq!(user.key(), "user:alice@example.com");
+
+        let domain = RateLimitClient::SenderDomain("Example.COM");
+        assert_eq!(limit(&config, RateLimitKind::Message, &domain), 0);
+        let config = SmtpRateLimitConfig {
+            messages_per_sender_domain: 120,
+            ..config
+        };
+        assert_eq!(limit(&config, RateLimitKind::Message, &domain), 120);
+        assert_eq!(limit(&config, RateLimitKind::Recipient, &domain), 0);
+        assert_eq!(domain.key(), "domain:example.com");
+    }
+
+    #[test]
+    fn test_window_start() {
+        let at = DateTime::parse_from_rfc3339("2024-01-28T10:15:42.5Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            window_start(at),
+            DateTime::parse_from_rfc3339("2024-01-28T10:15:00Z").unwrap()
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Sender addresses of authenticated users
+//!
+//! An authenticated user may send from their login address, the address
+//! mailboxes assigned to them, the same local parts at the aliases of these
+//! mailboxes' domains, and subaddresses of all of them. `smtp.sender_policy`
+//! decides what happens to MAIL FROM and From header addresses outside this
+//! set: the sender or message is refused, or the address is replaced with
+//! the user's login address.
+
+use crate::subaddress::split_subaddress;
+use anyhow::Result;
+use mairust_common::config::{SenderPolicy, SmtpConfig};
+use mairust_common::types::EmailAddress;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::User;
+use std::borrow::Cow;
+
+/// Reply text for senders the user does not own
+pub const NOT_OWNED: &str = "Sender address not owned by authenticated user";
+
+/// What to do with an address used by an authenticated user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SenderVerdict {
+    /// The user owns the address, or the policy allows any address
+    Accept,
+    /// Send with this address instead
+    Rewrite(EmailAddress),
+    /// Refuse the address
+    Reject,
+}
+
+/// Checks the addresses authenticated users send from
+pub struct SenderVerifier {
+    db_pool: DatabasePool,
+    policy: SenderPolicy,
+    recipient_delimiter: String,
+}
+
+impl SenderVerifier {
+    pub fn new(db_pool: DatabasePool, config: &SmtpConfig) -> Self {
+        Self {
+            db_pool,
+            policy: config.sender_policy,
+            recipient_delimiter: config.recipient_delimiter.clone(),
+        }
+    }
+
+    /// Verdict for an envelope sender (MAIL FROM)
+    pub async fn check(&self, user: &User, address: &EmailAddress) -> Result<SenderVerdict> {
+        if self.policy == SenderPolicy::Allow || self.owns(user, address).await? {
+            return Ok(SenderVerdict::Accept);
+        }
+        Ok(match (self.policy, EmailAddress::parse(&user.email)) {
+            (SenderPolicy::Rewrite, Some(own)) => SenderVerdict::Rewrite(own),
+            _ => SenderVerdict::Reject,
+        })
+    }
+
+    /// The message to send after checking its From header
+    ///
+    /// Every From address must be owned by the user. With the rewrite
+    /// policy, a From header with other addresses is replaced by one with
+    /// the user's address and name; otherwise the message is refused with
+    /// [`mairust_common::Error::PermissionDenied`]. Messages without a From
+    /// header are left alone.
+    pub async fn check_message<'a>(&self, user: &User, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if self.policy == SenderPolicy::Allow {
+            return Ok(Cow::Borrowed(data));
+        }
+        let addresses: Vec<Option<EmailAddress>> = {
+            let message = mail_parser::MessageParser::default().parse_headers(data);
+            match message.as_ref().and_then(|message| message.from()) {
+                Some(from) => from
+                    .iter()
+                    .map(|address| address.address().and_then(EmailAddress::parse))
+                    .collect(),
+                None => return Ok(Cow::Borrowed(data)),
+            }
+        };
+
+        let mut owned = true;
+        for address in addresses {
+            owned = match address {
+                Some(address) => self.owns(user, &address).await?,
+                None => false,
+            };
+            if !owned {
+                break;
+            }
+        }
+        if owned {
+            return Ok(Cow::Borrowed(data));
+        }
+        match self.policy {
+            SenderPolicy::Rewrite => Ok(Cow::Owned(rewrite_from(
+                data,
+                user.name.as_deref(),
+                &user.email,
+            ))),
+            _ => Err(mairust_common::Error::PermissionDenied(NOT_OWNED.to_string()).into()),
+        }
+    }
+
+    /// Whether `address` is one of the user's addresses
+    pub async fn owns(&self, user: &User, address: &EmailAddress) -> Result<bool> {
+        let local = split_subaddress(&address.local, &self.recipient_delimiter)
+            .map_or(address.local.as_str(), |(base, _)| base)
+            .to_lowercase();
+        let domain = address.domain.to_lowercase();
+        let full = format!("{}@{}", local, domain);
+        if user.email.eq_ignore_ascii_case(&full) {
+            return Ok(true);
+        }
+
+        let owned: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM mailboxes
+                WHERE user_id = $1 AND folder_path IS NULL AND LOWER(address) = $2
+                UNION ALL
+                SELECT 1 FROM mailboxes m
+                JOIN domain_aliases a ON a.primary_domain_id = m.domain_id AND a.enabled = true
+                JOIN domains d ON d.id = m.domain_id
+                WHERE m.user_id = $1 AND m.folder_path IS NULL
+                  AND LOWER(a.alias_domain) = $4
+                  AND LOWER(m.address) = $3 || '@' || LOWER(d.name)
+            )
+            "#,
+        )
+        .bind(user.id)
+        .bind(&full)
+        .bind(&local)
+        .bind(&domain)
+        .fetch_one(self.db_pool.pool())
+        .await?;
+        Ok(owned)
+    }
+}
+
+/// Replace the From header of a message with `address` and `name`
+///
+/// The header is written where the first From header was; other From
+/// headers are removed. The name is left out unless it can be sent as a
+/// plain quoted string.
+pub fn rewrite_from(data: &[u8], name: Option<&str>, address: &str) -> Vec<u8> {
+    let from = match name.filter(|name| is_plain_name(name)) {
+        Some(name) => format!("From: \"{}\" <{}>\r\n", name, address),
+        None => format!("From: <{}>\r\n", address),
+    };
+
+    let mut rewritten = Vec::with_capacity(data.len() + from.len());
+    let mut written = false;
+    let mut in_from = false;
+    let mut offset = 0;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        offset += line.len();
+        if line == b"\r\n" || line == b"\n" {
+            // End of the header
+            if !written {
+                rewritten.extend_from_slice(from.as_bytes());
+            }
+            rewritten.extend_from_slice(line);
+            rewritten.extend_from_slice(&data[offset..]);
+            return rewritten;
+        }
+        if in_from && matches!(line.first(), Some(b' ' | b'\t')) {
+            continue;
+        }
+        in_from = line.len() > 5 && line[..5].eq_ignore_ascii_case(b"from:");
+        if !in_from {
+            rewritten.extend_from_slice(line);
+        } else if !written {
+            rewritten.extend_from_slice(from.as_bytes());
+            written = true;
+        }
+    }
+    // Header only
+    if !written {
+        rewritten.extend_from_slice(from.as_bytes());
+    }
+    rewritten
+}
+
+/// Whether a display name can be written as a quoted string as is
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_from() {
+        let data = b"Subject: Hi\r\nFrom: \"Boss\" <ceo@example.com>,\r\n <cfo@example.com>\r\nTo: a@example.com\r\n\r\nFrom: body\r\n";
+        let rewritten = rewrite_from(data, Some("Alice Smith"), "alice@example.com");
+        assert_eq!(
+            rewritten,
+            b"Subject: Hi\r\nFrom: \"Alice Smith\" <alice@example.com>\r\nTo: a@example.com\r\n\r\nFrom: body\r\n"
+        );
+
+        let data = b"Subject: Hi\r\n\r\nBody\r\n";
+        let rewritten = rewrite_from(data, Some("\"Quoted\""), "alice@example.com");
+        assert_eq!(
+            rewritten,
+            b"Subject: Hi\r\nFrom: <alice@example.com>\r\n\r\nBody\r\n"
+        );
+    }
+}
@@ -1,16 +1,25 @@
 //! SMTP server implementation
 
+use crate::antivirus::Antivirus;
+use crate::email_auth::Bimi;
+use crate::geoip::GeoIp;
 use crate::hooks::HookManager;
+use crate::journal::Journal;
+use crate::maintenance::MaintenanceMode;
+use crate::proxy_protocol::TrustedProxies;
 use crate::queue::QueueManager;
+use crate::sessions::{SessionProtocol, SessionRegistry};
 use crate::smtp::tls::create_tls_acceptor;
-use crate::smtp::SmtpHandler;
-use anyhow::Result;
-use mairust_common::config::{Config, SmtpConfig};
+use crate::smtp::{Greylist, SmtpHandler, SmtpRateLimiter};
+use crate::spam::{Dnsbl, SenderReputation, SpamFilter, Uribl};
+use anyhow::{anyhow, bail, Result};
+use mairust_common::config::{Config, ListenerConfig, ListenerProtocol, ListenerTls, SmtpConfig};
 use mairust_storage::db::DatabasePool;
 use mairust_storage::file::FileStorage;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
 use tracing::{error, info, warn};
 
@@ -21,6 +30,19 @@ pub enum SmtpServiceType {
     Smtp,
     /// Port 587 - mail submission (requires auth)
     Submission,
+    /// Port 24 - local delivery from another MTA (RFC 2033)
+    Lmtp,
+}
+
+impl SmtpServiceType {
+    /// Service name used in configuration
+    pub fn config_key(&self) -> &'static str {
+        match self {
+            SmtpServiceType::Smtp => "smtp",
+            SmtpServiceType::Submission => "submission",
+            SmtpServiceType::Lmtp => "lmtp",
+        }
+    }
 }
 
 impl std::fmt::Display for SmtpServiceType {
@@ -28,6 +50,7 @@ impl std::fmt::Display for SmtpServiceType {
         match self {
             SmtpServiceType::Smtp => write!(f, "SMTP"),
             SmtpServiceType::Submission => write!(f, "Submission"),
+            SmtpServiceType::Lmtp => write!(f, "LMTP"),
         }
     }
 }
@@ -41,6 +64,19 @@ pub struct SmtpServer<S: FileStorage> {
     queue_manager: Arc<QueueManager<S>>,
     connection_semaphore: Arc<Semaphore>,
     tls_acceptor: Option<Arc<TlsAcceptor>>,
+    spam_filter: Arc<SpamFilter>,
+    maintenance: MaintenanceMode,
+    journal: Journal,
+    trusted_proxies: TrustedProxies,
+    greylist: Arc<Greylist>,
+    rate_limiter: Arc<SmtpRateLimiter>,
+    dnsbl: Arc<Dnsbl>,
+    uribl: Arc<Uribl>,
+    sender_reputation: Arc<SenderReputation>,
+    bimi: Arc<Bimi>,
+    antivirus: Arc<Antivirus>,
+    geoip: Arc<GeoIp>,
+    sessions: SessionRegistry,
 }
 
 impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
@@ -53,6 +89,21 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
         queue_manager: Arc<QueueManager<S>>,
     ) -> Self {
         let max_connections = config.max_connections.unwrap_or(100);
+        let greylist = Arc::new(Greylist::new(db_pool.clone(), config.greylisting.clone()));
+        let rate_limiter = Arc::new(SmtpRateLimiter::new(
+            db_pool.clone(),
+            config.rate_limits.clone(),
+        ));
+        let dnsbl = Arc::new(Dnsbl::new(db_pool.clone(), config.dnsbl.clone()));
+        let uribl = Arc::new(Uribl::new(db_pool.clone(), config.uribl.clone()));
+        let spam_filter = Arc::new(SpamFilter::rules_only().with_tenant_rules(db_pool.clone()));
+        let sender_reputation = Arc::new(SenderReputation::new(
+            db_pool.clone(),
+            config.sender_reputation.clone(),
+        ));
+        let bimi = Arc::new(Bimi::new(config.bimi.clone()));
+        let antivirus = Arc::new(Antivirus::new(db_pool.clone(), config.antivirus.clone()));
+        let geoip = Arc::new(GeoIp::new(&config.geoip));
         Self {
             config,
             db_pool,
@@ -61,6 +112,19 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
             queue_manager,
             connection_semaphore: Arc::new(Semaphore::new(max_connections)),
             tls_acceptor: None,
+            spam_filter,
+            maintenance: MaintenanceMode::default(),
+            journal: Journal::default(),
+            trusted_proxies: TrustedProxies::default(),
+            greylist,
+            rate_limiter,
+            dnsbl,
+            uribl,
+            sender_reputation,
+            bimi,
+            antivirus,
+            geoip,
+            sessions: SessionRegistry::default(),
         }
     }
 
@@ -90,6 +154,28 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
             None
         };
 
+        let greylist = Arc::new(Greylist::new(
+            db_pool.clone(),
+            full_config.smtp.greylisting.clone(),
+        ));
+        let rate_limiter = Arc::new(SmtpRateLimiter::new(
+            db_pool.clone(),
+            full_config.smtp.rate_limits.clone(),
+        ));
+        let dnsbl = Arc::new(Dnsbl::new(db_pool.clone(), full_config.smtp.dnsbl.clone()));
+        let uribl = Arc::new(Uribl::new(db_pool.clone(), full_config.smtp.uribl.clone()));
+        let spam_filter = Arc::new(SpamFilter::rules_only().with_tenant_rules(db_pool.clone()));
+        let sender_reputation = Arc::new(SenderReputation::new(
+            db_pool.clone(),
+            full_config.smtp.sender_reputation.clone(),
+        ));
+        let bimi = Arc::new(Bimi::new(full_config.smtp.bimi.clone()));
+        let antivirus = Arc::new(Antivirus::new(
+            db_pool.clone(),
+            full_config.smtp.antivirus.clone(),
+        ));
+        let geoip = Arc::new(GeoIp::new(&full_config.smtp.geoip));
+
         Self {
             config: full_config.smtp.clone(),
             db_pool,
@@ -98,69 +184,154 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
             queue_manager,
             connection_semaphore: Arc::new(Semaphore::new(max_connections)),
             tls_acceptor,
+            spam_filter,
+            maintenance: MaintenanceMode::default(),
+            journal: Journal::default(),
+            trusted_proxies: TrustedProxies::default(),
+            greylist,
+            rate_limiter,
+            dnsbl,
+            uribl,
+            sender_reputation,
+            bimi,
+            antivirus,
+            geoip,
+            sessions: SessionRegistry::default(),
         }
     }
 
+    /// Use a specific spam filter for inbound messages (e.g. one backed by rspamd)
+    pub fn with_spam_filter(mut self, spam_filter: SpamFilter) -> Self {
+        self.spam_filter = Arc::new(spam_filter);
+        self
+    }
+
+    /// Refuse sessions with 421 while a maintenance window is active
+    pub fn with_maintenance(mut self, maintenance: MaintenanceMode) -> Self {
+        self.maintenance = maintenance;
+        self
+    }
+
+    /// Journal received messages
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    /// Load balancers allowed to send PROXY headers on `proxy_protocol`
+    /// listeners
+    pub fn with_trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// List sessions in `sessions`, where administrators can terminate them
+    pub fn with_sessions(mut self, sessions: SessionRegistry) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
     /// Run both SMTP (port 25) and Submission (port 587) servers
     pub async fn run_dual_port(self: Arc<Self>) -> Result<()> {
-        let smtp_server = self.clone();
-        let submission_server = self.clone();
-
-        // Start both listeners concurrently
-        let smtp_handle = tokio::spawn(async move {
-            smtp_server.run_service(SmtpServiceType::Smtp).await
-        });
+        let listeners = self.config.default_listeners();
+        self.run_listeners(listeners).await
+    }
 
-        let submission_handle = tokio::spawn(async move {
-            submission_server.run_service(SmtpServiceType::Submission).await
-        });
+    /// Run a set of SMTP/Submission/LMTP listeners until one of them stops
+    pub async fn run_listeners(self: Arc<Self>, listeners: Vec<ListenerConfig>) -> Result<()> {
+        let mut tasks = JoinSet::new();
+        for listener in listeners {
+            let server = self.clone();
+            tasks.spawn(async move {
+                let result = server.run_listener(&listener).await;
+                (listener.label(), result)
+            });
+        }
 
-        // Wait for both to complete (they shouldn't unless there's an error)
-        tokio::select! {
-            result = smtp_handle => {
-                match result {
-                    Ok(Ok(())) => info!("SMTP service stopped"),
-                    Ok(Err(e)) => error!("SMTP service error: {}", e),
-                    Err(e) => error!("SMTP task panicked: {}", e),
-                }
-            }
-            result = submission_handle => {
-                match result {
-                    Ok(Ok(())) => info!("Submission service stopped"),
-                    Ok(Err(e)) => error!("Submission service error: {}", e),
-                    Err(e) => error!("Submission task panicked: {}", e),
-                }
+        // Listeners run forever unless there's an error
+        if let Some(result) = tasks.join_next().await {
+            match result {
+                Ok((label, Ok(()))) => info!("{} service stopped", label),
+                Ok((label, Err(e))) => error!("{} service error: {}", label, e),
+                Err(e) => error!("SMTP listener task panicked: {}", e),
             }
         }
 
         Ok(())
     }
 
-    /// Run a specific SMTP service (SMTP or Submission)
+    /// Run a specific SMTP service (SMTP or Submission) on its configured port
     pub async fn run_service(&self, service_type: SmtpServiceType) -> Result<()> {
-        let (port, auth_required) = match service_type {
-            SmtpServiceType::Smtp => (self.config.port, self.config.auth_required.unwrap_or(false)),
-            SmtpServiceType::Submission => (self.config.submission_port, true), // Submission always requires auth
+        let listener = self
+            .config
+            .default_listeners()
+            .into_iter()
+            .find(|l| l.protocol.as_str() == service_type.config_key())
+            .ok_or_else(|| anyhow!("No default listener for {}", service_type))?;
+        self.run_listener(&listener).await
+    }
+
+    /// Accept connections on a single listener
+    pub async fn run_listener(&self, listener: &ListenerConfig) -> Result<()> {
+        let service_type = match listener.protocol {
+            ListenerProtocol::Smtp => SmtpServiceType::Smtp,
+            ListenerProtocol::Submission => SmtpServiceType::Submission,
+            ListenerProtocol::Lmtp => SmtpServiceType::Lmtp,
+            other => bail!("{} is not an SMTP listener", other.as_str()),
+        };
+        let label = listener.label();
+        let protocol = match service_type {
+            SmtpServiceType::Smtp => SessionProtocol::Smtp,
+            SmtpServiceType::Submission => SessionProtocol::Submission,
+            SmtpServiceType::Lmtp => SessionProtocol::Lmtp,
         };
 
-        let addr = format!("{}:{}", self.config.host, port);
-        let listener = TcpListener::bind(&addr).await?;
+        let tls_mode = listener.tls_mode();
+        if tls_mode == ListenerTls::Implicit && self.tls_acceptor.is_none() {
+            bail!("{}: implicit TLS requires a [tls] certificate", label);
+        }
+        // Clients are identified by the PROXY header of the load balancer
+        let proxy = listener
+            .proxy_protocol
+            .then(|| self.trusted_proxies.clone());
 
-        let tls_status = if self.tls_acceptor.is_some() {
-            "STARTTLS enabled"
-        } else {
-            "STARTTLS disabled"
+        let auth_required = listener.auth_required.unwrap_or(match service_type {
+            SmtpServiceType::Smtp => self.config.auth_required.unwrap_or(false),
+            SmtpServiceType::Submission => true, // Submission always requires auth
+            SmtpServiceType::Lmtp => false,
+        });
+        // LMTP is for local delivery only
+        let allow_relay = service_type != SmtpServiceType::Lmtp
+            && listener.relay.unwrap_or(self.config.allow_relay);
+        // STARTTLS is only offered if we have an acceptor
+        let tls_acceptor = match tls_mode {
+            ListenerTls::None => None,
+            ListenerTls::Starttls | ListenerTls::Implicit => self.tls_acceptor.clone(),
+        };
+
+        let tcp_listener = TcpListener::bind(&listener.bind).await?;
+
+        let tls_status = match (tls_mode, &tls_acceptor) {
+            (ListenerTls::Implicit, _) => "implicit TLS",
+            (ListenerTls::Starttls, Some(_)) => "STARTTLS enabled",
+            _ => "STARTTLS disabled",
         };
-        info!("{} server listening on {} ({})", service_type, addr, tls_status);
+        info!(
+            "{} server listening on {} ({})",
+            service_type, listener.bind, tls_status
+        );
 
         loop {
-            match listener.accept().await {
+            match tcp_listener.accept().await {
                 Ok((stream, peer_addr)) => {
                     // Acquire semaphore permit
                     let permit = match self.connection_semaphore.clone().try_acquire_owned() {
                         Ok(permit) => permit,
                         Err(_) => {
-                            warn!("{}: Max connections reached, rejecting {}", service_type, peer_addr);
+                            warn!(
+                                "{}: Max connections reached, rejecting {}",
+                                label, peer_addr
+                            );
                             continue;
                         }
                     };
@@ -168,8 +339,17 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
                     // Create config for this connection
                     let mut handler_config = self.config.clone();
                     handler_config.auth_required = Some(auth_required);
-                    // Enable TLS in config only if we have an acceptor
-                    handler_config.tls_enabled = Some(self.tls_acceptor.is_some());
+                    handler_config.allow_relay = allow_relay;
+                    handler_config.tls_enabled =
+                        Some(tls_mode == ListenerTls::Starttls && tls_acceptor.is_some());
+
+                    // Present the identity configured for this listener
+                    let local_ip = stream.local_addr().ok().map(|a| a.ip().to_string());
+                    let (hostname, banner) = self
+                        .config
+                        .listener_identity(service_type.config_key(), local_ip.as_deref());
+                    handler_config.hostname = hostname;
+                    handler_config.banner = banner;
 
                     let handler = SmtpHandler::new(
                         handler_config,
@@ -177,21 +357,74 @@ impl<S: FileStorage + Send + Sync + 'static> SmtpServer<S> {
                         self.file_storage.clone(),
                         self.hook_manager.clone(),
                         self.queue_manager.clone(),
+                        self.spam_filter.clone(),
                         peer_addr,
-                    );
+                    )
+                    .with_maintenance(self.maintenance.clone())
+                    .with_journal(self.journal.clone())
+                    .with_bimi(self.bimi.clone())
+                    .with_uribl(self.uribl.clone())
+                    .with_antivirus(self.antivirus.clone())
+                    .with_geoip(self.geoip.clone());
+                    // The MTA in front of an LMTP listener screens its clients
+                    let handler = match service_type {
+                        SmtpServiceType::Lmtp => handler.with_lmtp(),
+                        _ => handler
+                            .with_greylist(self.greylist.clone())
+                            .with_rate_limiter(self.rate_limiter.clone())
+                            .with_dnsbl(self.dnsbl.clone())
+                            .with_sender_reputation(self.sender_reputation.clone()),
+                    };
 
-                    let service_name = service_type.to_string();
-                    let tls_acceptor = self.tls_acceptor.clone();
+                    let service_name = label.clone();
+                    let tls_acceptor = tls_acceptor.clone();
+                    let proxy = proxy.clone();
+                    let sessions = self.sessions.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) = handler.handle_with_tls(stream, tls_acceptor).await {
-                            error!("{} session error from {}: {}", service_name, peer_addr, e);
+                        let mut stream = stream;
+                        let (handler, peer_addr) = match proxy {
+                            Some(proxy) => match proxy.client_addr(&mut stream, peer_addr).await {
+                                Ok(client_addr) => {
+                                    (handler.with_peer_addr(client_addr), client_addr)
+                                }
+                                Err(e) => {
+                                    warn!("{}: Refusing connection: {}", service_name, e);
+                                    return;
+                                }
+                            },
+                            None => (handler, peer_addr),
+                        };
+                        let registration = sessions.register(protocol, peer_addr);
+                        let live = registration.session().clone();
+                        let handler = handler.with_session(live.clone());
+                        let result = live
+                            .run(async move {
+                                match (tls_mode, tls_acceptor) {
+                                    (ListenerTls::Implicit, Some(acceptor)) => {
+                                        handler.handle_implicit_tls(stream, acceptor).await
+                                    }
+                                    (_, tls_acceptor) => {
+                                        handler.handle_with_tls(stream, tls_acceptor).await
+                                    }
+                                }
+                            })
+                            .await;
+                        match result {
+                            Some(Err(e)) => {
+                                error!("{} session error from {}: {}", service_name, peer_addr, e);
+                            }
+                            Some(Ok(())) => {}
+                            None => {
+                                info!("{}: Terminated session from {}", service_name, peer_addr);
+                            }
                         }
+                        drop(registration);
                         drop(permit);
                     });
                 }
                 Err(e) => {
-                    error!("{}: Failed to accept connection: {}", service_type, e);
+                    error!("{}: Failed to accept connection: {}", label, e);
                 }
             }
         }
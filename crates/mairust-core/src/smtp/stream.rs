@@ -0,0 +1,118 @@
+//! Connection of an SMTP session
+//!
+//! A session starts on a plain TCP connection (ports 25 and 587) or on TLS
+//! (implicit TLS listeners). STARTTLS upgrades a plain connection in place:
+//! the handshake runs on the same socket and the session continues on the
+//! TLS stream.
+
+use anyhow::{anyhow, bail, Result};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Plain or TLS connection of an SMTP session
+pub enum SmtpStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl SmtpStream {
+    /// Whether the connection is encrypted
+    pub fn is_tls(&self) -> bool {
+        matches!(self, SmtpStream::Tls(_))
+    }
+
+    /// Run the TLS handshake on a plain connection
+    pub async fn upgrade(self, acceptor: &TlsAcceptor) -> Result<Self> {
+        match self {
+            SmtpStream::Plain(stream) => {
+                let tls_stream = acceptor
+                    .accept(stream)
+                    .await
+                    .map_err(|e| anyhow!("TLS handshake failed: {}", e))?;
+                Ok(SmtpStream::Tls(Box::new(tls_stream)))
+            }
+            SmtpStream::Tls(_) => bail!("TLS is already active"),
+        }
+    }
+}
+
+impl AsyncRead for SmtpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SmtpStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            SmtpStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SmtpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SmtpStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            SmtpStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SmtpStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            SmtpStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SmtpStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            SmtpStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_plain_stream_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"EHLO client\r\n").await.unwrap();
+            let mut reply = String::new();
+            BufReader::new(client).read_line(&mut reply).await.unwrap();
+            reply
+        });
+
+        let (server, _) = listener.accept().await.unwrap();
+        let stream = SmtpStream::Plain(server);
+        assert!(!stream.is_tls());
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut line = String::new();
+        let mut reader = BufReader::new(reader);
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "EHLO client\r\n");
+        writer.write_all(b"250 OK\r\n").await.unwrap();
+        writer.flush().await.unwrap();
+
+        // The halves join back into the stream STARTTLS upgrades
+        let stream = reader.into_inner().unsplit(writer);
+        assert!(!stream.is_tls());
+        assert_eq!(client.await.unwrap(), "250 OK\r\n");
+    }
+}
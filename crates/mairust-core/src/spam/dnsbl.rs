@@ -0,0 +1,518 @@
+//! DNS blocklist (DNSBL/RBL) lookups
+//!
+//! The address of an unauthenticated client is looked up in the configured
+//! blocklists: `192.0.2.1` is listed in `zen.spamhaus.org` when
+//! `1.2.0.192.zen.spamhaus.org` has an A record in 127.0.0.0/8 (IPv6
+//! addresses are queried nibble by nibble). The scores of the lists the
+//! client is on add up; the sum is added to the spam score of its messages,
+//! and MAIL FROM is refused once it reaches the configured reject score.
+//!
+//! The lookups start when the client connects, so their answers are
+//! usually in by MAIL FROM. Results are cached per address. Addresses and
+//! networks on the DNSBL allowlist are never looked up.
+//!
+//! rspamd runs its own blocklist checks; enable these only for lists it
+//! does not query, or without rspamd.
+
+use super::SpamCheckResult;
+use ipnet::IpNet;
+use mairust_common::config::{DnsblConfig, DnsblListConfig};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::DnsblAllowlistRepository;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// How often the allowlist is read again
+const ALLOWLIST_REFRESH: Duration = Duration::from_secs(60);
+
+/// How long results with a failed lookup are cached
+const FAILURE_CACHE: Duration = Duration::from_secs(60);
+
+/// Cached results above which expired ones are dropped
+const MAX_CACHED: usize = 10_000;
+
+/// A blocklist a client is on
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsblListing {
+    /// Zone of the list
+    pub zone: String,
+    /// The list's answer, telling why the client is listed
+    pub code: Ipv4Addr,
+    /// Score of the list
+    pub score: f64,
+}
+
+impl DnsblListing {
+    /// Spam symbol of the listing (e.g. `DNSBL_ZEN_SPAMHAUS_ORG`)
+    pub fn symbol(&self) -> String {
+        zone_symbol("DNSBL", &self.zone)
+    }
+}
+
+/// Spam symbol of a list: the prefix and the zone in upper case, with
+/// underscores for dots
+pub(super) fn zone_symbol(prefix: &str, zone: &str) -> String {
+    let zone: String = zone
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}_{}", prefix, zone)
+}
+
+/// Blocklists a client is on
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DnsblResult {
+    pub listings: Vec<DnsblListing>,
+}
+
+impl DnsblResult {
+    /// Whether the client is on any list
+    pub fn is_listed(&self) -> bool {
+        !self.listings.is_empty()
+    }
+
+    /// Sum of the scores of the lists the client is on
+    pub fn score(&self) -> f64 {
+        self.listings.iter().map(|listing| listing.score).sum()
+    }
+
+    /// Add the listings to the spam verdict of a message
+    pub fn add_to(&self, spam: &mut SpamCheckResult) {
+        for listing in &self.listings {
+            spam.add_score(listing.symbol(), listing.score);
+        }
+    }
+
+    /// Text of the 550 reply refusing a listed client
+    pub fn reply(&self, client_ip: IpAddr) -> String {
+        let zones: Vec<&str> = self
+            .listings
+            .iter()
+            .map(|listing| listing.zone.as_str())
+            .collect();
+        format!(
+            "5.7.1 Service unavailable; client [{}] blocked using {}",
+            client_ip,
+            zones.join(", ")
+        )
+    }
+}
+
+/// Canonical form of an allowlist network: a CIDR range without host
+/// bits, or a single address as /32 or /128
+pub fn normalize_network(network: &str) -> Option<String> {
+    let network = network.trim();
+    network
+        .parse::<IpNet>()
+        .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+        .ok()
+        .map(|network| network.trunc().to_string())
+}
+
+/// Name to query for `ip` in `zone`
+pub(super) fn query_name(ip: IpAddr, zone: &str) -> String {
+    let zone = zone.trim_end_matches('.');
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.{}.", d, c, b, a, zone)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(64 + zone.len() + 1);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str(zone);
+            name.push('.');
+            name
+        }
+    }
+}
+
+/// Whether an answer of a list counts as a listing
+///
+/// Answers outside 127.0.0.0/8 are wildcard or hijacked records, and
+/// 127.255.255.0/24 answers are errors (Spamhaus uses them to refuse
+/// queries from public resolvers).
+pub(super) fn is_listing(code: Ipv4Addr, list: &DnsblListConfig) -> bool {
+    let octets = code.octets();
+    if octets[0] != 127 || octets[..3] == [127, 255, 255] {
+        return false;
+    }
+    list.codes.is_empty()
+        || list
+            .codes
+            .iter()
+            .any(|c| c.trim().parse::<Ipv4Addr>() == Ok(code))
+}
+
+/// Address to look up, or `None` for addresses no list covers (loopback,
+/// private and link-local ranges)
+pub(super) fn lookup_address(ip: IpAddr) -> Option<IpAddr> {
+    match ip {
+        IpAddr::V4(v4) => {
+            let public = !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast());
+            public.then_some(ip)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return lookup_address(IpAddr::V4(v4));
+            }
+            let segment = v6.segments()[0];
+            let local = v6.is_loopback()
+                || v6.is_unspecified()
+                // fc00::/7 unique local and fe80::/10 link local
+                || (segment & 0xfe00) == 0xfc00
+                || (segment & 0xffc0) == 0xfe80;
+            (!local).then_some(ip)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    result: Arc<DnsblResult>,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Allowlist {
+    networks: Vec<IpNet>,
+    loaded_at: Option<Instant>,
+}
+
+/// DNS blocklist lookups, shared by the sessions of an SMTP server
+pub struct Dnsbl {
+    db_pool: DatabasePool,
+    config: DnsblConfig,
+    resolver: TokioAsyncResolver,
+    cache: Mutex<HashMap<IpAddr, CacheEntry>>,
+    allowlist: Mutex<Allowlist>,
+}
+
+impl Dnsbl {
+    /// Create a blocklist checker
+    ///
+    /// Lookups go to the system's resolver: public resolvers are refused by
+    /// several lists.
+    pub fn new(db_pool: DatabasePool, config: DnsblConfig) -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            if config.enabled {
+                warn!("Failed to read the system resolver configuration: {}", e);
+            }
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+
+        Self {
+            db_pool,
+            config,
+            resolver,
+            cache: Mutex::new(HashMap::new()),
+            allowlist: Mutex::new(Allowlist::default()),
+        }
+    }
+
+    /// Whether clients are looked up
+    pub fn enabled(&self) -> bool {
+        self.config.enabled && !self.config.lists.is_empty()
+    }
+
+    /// Whether a client with these listings is refused at MAIL FROM
+    pub fn rejects(&self, result: &DnsblResult) -> bool {
+        self.config.reject_score > 0.0
+            && result.is_listed()
+            && result.score() >= self.config.reject_score
+    }
+
+    /// Blocklists `client_ip` is on, from the cache when fresh
+    pub async fn check(&self, client_ip: IpAddr) -> Arc<DnsblResult> {
+        let Some(ip) = lookup_address(client_ip).filter(|_| self.enabled()) else {
+            return Arc::default();
+        };
+        if self.is_allowlisted(ip).await {
+            return Arc::default();
+        }
+
+        let now = Instant::now();
+        if let Some(entry) = self.cache.lock().unwrap().get(&ip) {
+            if entry.expires_at > now {
+                return entry.result.clone();
+            }
+        }
+
+        let (result, failed) = self.lookup(ip).await;
+        let result = Arc::new(result);
+        if result.is_listed() {
+            info!(
+                "Client {} is listed in {} (score {:.1})",
+                ip,
+                result
+                    .listings
+                    .iter()
+                    .map(|listing| format!("{} ({})", listing.zone, listing.code))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                result.score()
+            );
+        }
+
+        let ttl = Duration::from_secs(self.config.cache_secs);
+        let ttl = if failed { ttl.min(FAILURE_CACHE) } else { ttl };
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, entry| entry.expires_at > now);
+        }
+        cache.insert(
+            ip,
+            CacheEntry {
+                result: result.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        result
+    }
+
+    /// Query every list at once, returning the listings and whether any
+    /// lookup failed
+    async fn lookup(&self, ip: IpAddr) -> (DnsblResult, bool) {
+        let timeout = Duration::from_secs(self.config.timeout_secs.max(1));
+        let mut lookups = JoinSet::new();
+        for (index, list) in self.config.lists.iter().enumerate() {
+            let resolver = self.resolver.clone();
+            let name = query_name(ip, &list.zone);
+            lookups.spawn(async move {
+                let answer = tokio::time::timeout(timeout, resolver.ipv4_lookup(name)).await;
+                (index, answer)
+            });
+        }
+
+        let mut listings = Vec::new();
+        let mut failed = false;
+        while let Some(joined) = lookups.join_next().await {
+            let Ok((index, answer)) = joined else {
+                failed = true;
+                continue;
+            };
+            let list = &self.config.lists[index];
+            match answer {
+                Ok(Ok(records)) => {
+                    let code = records
+                        .iter()
+                        .map(|record| record.0)
+                        .find(|code| is_listing(*code, list));
+                    if let Some(code) = code {
+                        listings.push((
+                            index,
+                            DnsblListing {
+                                zone: list.zone.clone(),
+                                code,
+                                score: list.score,
+                            },
+                        ));
+                    }
+                }
+                Ok(Err(e)) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {}
+                Ok(Err(e)) => {
+                    debug!("DNSBL lookup of {} in {} failed: {}", ip, list.zone, e);
+                    failed = true;
+                }
+                Err(_) => {
+                    debug!("DNSBL lookup of {} in {} timed out", ip, list.zone);
+                    failed = true;
+                }
+            }
+        }
+
+        // Keep the configured order of the lists
+        listings.sort_by_key(|(index, _)| *index);
+        let listings = listings.into_iter().map(|(_, listing)| listing).collect();
+        (DnsblResult { listings }, failed)
+    }
+
+    /// Whether `ip` is on the allowlist, reading it again when it is due
+    async fn is_allowlisted(&self, ip: IpAddr) -> bool {
+        let due = self
+            .allowlist
+            .lock()
+            .unwrap()
+            .loaded_at
+            .map(|loaded_at| loaded_at.elapsed() >= ALLOWLIST_REFRESH)
+            .unwrap_or(true);
+        if due {
+            // A failed read keeps the previous list
+            let networks = match DnsblAllowlistRepository::new(self.db_pool.clone())
+                .list()
+                .await
+            {
+                Ok(entries) => Some(
+                    entries
+                        .iter()
+                        .filter_map(|entry| entry.network.parse::<IpNet>().ok())
+                        .collect(),
+                ),
+                Err(e) => {
+                    warn!("Failed to read the DNSBL allowlist: {}", e);
+                    None
+                }
+            };
+            let mut allowlist = self.allowlist.lock().unwrap();
+            if let Some(networks) = networks {
+                allowlist.networks = networks;
+            }
+            allowlist.loaded_at = Some(Instant::now());
+        }
+
+        self.allowlist
+            .lock()
+            .unwrap()
+            .networks
+            .iter()
+            .any(|network| network.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(zone: &str, codes: &[&str]) -> DnsblListConfig {
+        DnsblListConfig {
+            zone: zone.to_string(),
+            score: 5.0,
+            codes: codes.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_query_name() {
+        assert_eq!(
+            query_name("192.0.2.1".parse().unwrap(), "zen.spamhaus.org"),
+            "1.2.0.192.zen.spamhaus.org."
+        );
+        assert_eq!(
+            query_name("192.0.2.1".parse().unwrap(), "bl.example."),
+            "1.2.0.192.bl.example."
+        );
+        assert_eq!(
+            query_name("2001:db8::567:89ab".parse().unwrap(), "bl.example"),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.bl.example."
+        );
+    }
+
+    #[test]
+    fn test_normalize_network() {
+        assert_eq!(
+            normalize_network(" 192.0.2.7 ").as_deref(),
+            Some("192.0.2.7/32")
+        );
+        assert_eq!(
+            normalize_network("192.0.2.7/24").as_deref(),
+            Some("192.0.2.0/24")
+        );
+        assert_eq!(
+            normalize_network("2001:db8::1/48").as_deref(),
+            Some("2001:db8::/48")
+        );
+        assert_eq!(
+            normalize_network("2001:db8::1").as_deref(),
+            Some("2001:db8::1/128")
+        );
+        assert_eq!(normalize_network("192.0.2.0/33"), None);
+        assert_eq!(normalize_network("mail.example.com"), None);
+    }
+
+    #[test]
+    fn test_is_listing() {
+        let any = list("bl.example", &[]);
+        assert!(is_listing(Ipv4Addr::new(127, 0, 0, 2), &any));
+        assert!(is_listing(Ipv4Addr::new(127, 0, 0, 11), &any));
+        // Refused query and wildcard answers
+        assert!(!is_listing(Ipv4Addr::new(127, 255, 255, 254), &any));
+        assert!(!is_listing(Ipv4Addr::new(198, 51, 100, 7), &any));
+
+        let sbl = list("zen.spamhaus.org", &["127.0.0.2", " 127.0.0.3"]);
+        assert!(is_listing(Ipv4Addr::new(127, 0, 0, 2), &sbl));
+        assert!(is_listing(Ipv4Addr::new(127, 0, 0, 3), &sbl));
+        assert!(!is_listing(Ipv4Addr::new(127, 0, 0, 11), &sbl));
+    }
+
+    #[test]
+    fn test_lookup_address() {
+        assert_eq!(
+            lookup_address("198.51.100.7".parse().unwrap()),
+            Some("198.51.100.7".parse().unwrap())
+        );
+        assert_eq!(
+            lookup_address("::ffff:198.51.100.7".parse().unwrap()),
+            Some("198.51.100.7".parse().unwrap())
+        );
+        assert!(lookup_address("2001:db8::1".parse().unwrap()).is_some());
+        for local in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.1",
+            "169.254.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert_eq!(lookup_address(local.parse().unwrap()), None, "{}", local);
+        }
+    }
+
+    #[test]
+    fn test_result() {
+        let result = DnsblResult {
+            listings: vec![
+                DnsblListing {
+                    zone: "zen.spamhaus.org".to_string(),
+                    code: Ipv4Addr::new(127, 0, 0, 4),
+                    score: 4.0,
+                },
+                DnsblListing {
+                    zone: "bl.spamcop.net".to_string(),
+                    code: Ipv4Addr::new(127, 0, 0, 2),
+                    score: 2.5,
+                },
+            ],
+        };
+        assert!(result.is_listed());
+        assert_eq!(result.score(), 6.5);
+        assert_eq!(result.listings[0].symbol(), "DNSBL_ZEN_SPAMHAUS_ORG");
+        assert_eq!(
+            result.reply("192.0.2.1".parse().unwrap()),
+            "5.7.1 Service unavailable; client [192.0.2.1] blocked using \
+             zen.spamhaus.org, bl.spamcop.net"
+        );
+
+        let mut spam = SpamCheckResult::default();
+        result.add_to(&mut spam);
+        assert_eq!(spam.score, 6.5);
+        assert!(spam.is_spam);
+        assert_eq!(
+            spam.symbols,
+            vec!["DNSBL_ZEN_SPAMHAUS_ORG", "DNSBL_BL_SPAMCOP_NET"]
+        );
+
+        assert!(!DnsblResult::default().is_listed());
+        assert_eq!(DnsblResult::default().score(), 0.0);
+    }
+}
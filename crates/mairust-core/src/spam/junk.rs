@@ -0,0 +1,238 @@
+//! Junk folder filing
+//!
+//! Messages the spam filter marks for a header or subject rewrite are
+//! delivered to the recipient's Junk folder when one exists and the user has
+//! not turned filing off. The decision is recorded under `metadata.junk` so
+//! a "not spam" verdict can move the message back to where it was headed.
+
+use super::SpamCheckResult;
+use crate::folders::SpecialUse;
+use anyhow::Result;
+use chrono::Utc;
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Mailbox;
+use mairust_storage::UserSettingsRepository;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Whether a folder name denotes a Junk folder, in any supported locale
+pub fn is_junk_folder_name(name: &str) -> bool {
+    SpecialUse::from_folder_name(name) == Some(SpecialUse::Junk)
+}
+
+/// Metadata recorded on a message filed into the Junk folder
+pub fn junk_metadata(result: &SpamCheckResult, original_mailbox_id: Uuid) -> serde_json::Value {
+    serde_json::json!({
+        "filed": true,
+        "original_mailbox_id": original_mailbox_id,
+        "score": result.score,
+        "action": result.action,
+        "filed_at": Utc::now(),
+    })
+}
+
+/// Result of a "not spam" verdict
+#[derive(Debug, Clone, Serialize)]
+pub struct NotSpamOutcome {
+    pub message_id: Uuid,
+    /// Mailbox the message is in after the verdict
+    pub mailbox_id: Uuid,
+    /// Whether the message was moved out of the Junk folder
+    pub moved: bool,
+    /// Storage path of the raw message, for learning
+    #[serde(skip)]
+    pub storage_path: String,
+}
+
+/// Files spam into Junk folders and reverses the decision
+pub struct JunkFiler {
+    db_pool: DatabasePool,
+}
+
+impl JunkFiler {
+    /// Create a new junk filer
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Junk folder to deliver to instead of `mailbox`
+    ///
+    /// Returns `None` when the mailbox has no owner, the owner disabled junk
+    /// filing or has no Junk folder.
+    pub async fn junk_folder(&self, mailbox: &Mailbox) -> Result<Option<Uuid>> {
+        let user_id = match mailbox.user_id {
+            Some(user_id) => user_id,
+            None => return Ok(None),
+        };
+
+        let settings = UserSettingsRepository::new(self.db_pool.clone())
+            .get(mailbox.tenant_id, user_id)
+            .await?;
+        if !settings.junk_filing {
+            return Ok(None);
+        }
+
+        let names: Vec<String> = SpecialUse::Junk
+            .folder_names()
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        let folders: Vec<(Uuid, String, Option<String>)> = sqlx::query_as(
+            "SELECT id, address, special_use FROM mailboxes
+             WHERE tenant_id = $1 AND user_id = $2 AND id <> $3
+               AND (special_use = 'junk' OR LOWER(address) = ANY($4))",
+        )
+        .bind(mailbox.tenant_id)
+        .bind(user_id)
+        .bind(mailbox.id)
+        .bind(&names)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        Ok(preferred_junk_folder(&folders))
+    }
+
+    /// Record a "not spam" verdict for a message
+    ///
+    /// Messages filed by [`JunkFiler`] are moved back to their original
+    /// mailbox; the `spam` tag is removed either way. Returns `None` if the
+    /// message does not exist in the tenant.
+    pub async fn mark_not_spam(
+        &self,
+        tenant_id: TenantId,
+        message_id: Uuid,
+    ) -> Result<Option<NotSpamOutcome>> {
+        let pool = self.db_pool.pool();
+
+        let message: Option<(Uuid, Option<String>)> = sqlx::query_as(
+            "SELECT mailbox_id, metadata->'junk'->>'original_mailbox_id'
+             FROM messages WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .fetch_optional(pool)
+        .await?;
+
+        let (current_mailbox_id, original) = match message {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+
+        // Only move back into a mailbox that still exists in the tenant
+        let original = original.and_then(|id| id.parse::<Uuid>().ok());
+        let target = match original {
+            Some(original_id) if original_id != current_mailbox_id => {
+                let exists: Option<(Uuid,)> =
+                    sqlx::query_as("SELECT id FROM mailboxes WHERE tenant_id = $1 AND id = $2")
+                        .bind(tenant_id)
+                        .bind(original_id)
+                        .fetch_optional(pool)
+                        .await?;
+                exists.map_or(current_mailbox_id, |(id,)| id)
+            }
+            _ => current_mailbox_id,
+        };
+
+        // Moving assigns a new UID in the destination (see assign_message_uid)
+        let (mailbox_id, storage_path): (Uuid, String) = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET mailbox_id = $3,
+                tags = tags - 'spam',
+                metadata = jsonb_set(
+                    metadata,
+                    '{junk}',
+                    COALESCE(metadata->'junk', '{}'::jsonb)
+                        || jsonb_build_object('user_verdict', 'ham', 'verdict_at', NOW())
+                )
+            WHERE tenant_id = $1 AND id = $2
+            RETURNING mailbox_id, storage_path
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .bind(target)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Some(NotSpamOutcome {
+            message_id,
+            mailbox_id,
+            moved: mailbox_id != current_mailbox_id,
+            storage_path,
+        }))
+    }
+}
+
+/// Pick the Junk folder: a provisioned one first, then by name preference
+fn preferred_junk_folder(folders: &[(Uuid, String, Option<String>)]) -> Option<Uuid> {
+    let names = SpecialUse::Junk.folder_names();
+    folders
+        .iter()
+        .filter(|(_, address, special_use)| {
+            SpecialUse::of_folder(special_use.as_deref(), address) == Some(SpecialUse::Junk)
+        })
+        .min_by_key(|(_, address, special_use)| {
+            let rank = names
+                .iter()
+                .position(|name| name.to_lowercase() == address.to_lowercase())
+                .unwrap_or(names.len());
+            (special_use.is_none(), rank)
+        })
+        .map(|(id, _, _)| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spam::SpamAction;
+
+    #[test]
+    fn test_is_junk_folder_name() {
+        assert!(is_junk_folder_name("Junk"));
+        assert!(is_junk_folder_name("SPAM"));
+        assert!(is_junk_folder_name("Junk E-mail"));
+        assert!(is_junk_folder_name("迷惑メール"));
+        assert!(!is_junk_folder_name("INBOX"));
+    }
+
+    #[test]
+    fn test_preferred_junk_folder() {
+        let spam = Uuid::now_v7();
+        let junk = Uuid::now_v7();
+        let folders = vec![
+            (spam, "Spam".to_string(), None),
+            (junk, "Junk".to_string(), None),
+        ];
+        assert_eq!(preferred_junk_folder(&folders), Some(junk));
+        assert_eq!(preferred_junk_folder(&folders[..1]), Some(spam));
+        assert_eq!(preferred_junk_folder(&[]), None);
+
+        // A provisioned folder wins whatever it is called
+        let provisioned = Uuid::now_v7();
+        let mut folders = folders;
+        folders.push((
+            provisioned,
+            "迷惑メール".to_string(),
+            Some("junk".to_string()),
+        ));
+        assert_eq!(preferred_junk_folder(&folders), Some(provisioned));
+    }
+
+    #[test]
+    fn test_junk_metadata() {
+        let result = SpamCheckResult {
+            score: 7.5,
+            is_spam: true,
+            action: SpamAction::AddHeader,
+            ..Default::default()
+        };
+        let original = Uuid::now_v7();
+        let metadata = junk_metadata(&result, original);
+
+        assert_eq!(metadata["filed"], true);
+        assert_eq!(metadata["original_mailbox_id"], original.to_string());
+        assert_eq!(metadata["action"], "addheader");
+    }
+}
@@ -3,13 +3,37 @@
 //! Provides spam detection through:
 //! - rspamd integration for advanced spam filtering
 //! - Rule-based filtering as a fallback
+//! - Spam rules of each tenant, kept in the database
+//! - DNS blocklist lookups of the connecting client
+//! - URI blocklist lookups of the links in a message
+//! - Local reputation of sender domains
+//! - Phishing heuristics on the From and Reply-To headers
+//! - Junk folder filing of flagged messages
+//! - Quarantine of messages rspamd gives the custom `quarantine` action
+//! - Rescans of stored messages before attachments are downloaded
 
+pub mod dnsbl;
+pub mod junk;
+pub mod phishing;
+pub mod reputation;
+pub mod rescan;
 pub mod rspamd;
 pub mod rules;
+pub mod tenant_rules;
+pub mod uribl;
 
+pub use dnsbl::{normalize_network, Dnsbl, DnsblListing, DnsblResult};
+pub use junk::{JunkFiler, NotSpamOutcome};
+pub use phishing::{PhishingAnalyzer, PhishingReport, PhishingSignal, PHISHING_HEADER};
+pub use reputation::{ReputationScore, SenderReputation};
+pub use rescan::{AttachmentRescanner, RescanVerdict};
 pub use rspamd::{RspamdClient, RspamdConfig, RspamdResult};
-pub use rules::{RuleBasedFilter, RuleResult, SpamRule};
+pub use rules::{extract_urls, RuleBasedFilter, RuleResult, RuleType, SpamRule};
+pub use tenant_rules::TenantSpamRules;
+pub use uribl::{normalize_domain, Uribl, UriblListing, UriblResult};
 
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
 use serde::{Deserialize, Serialize};
 
 /// Overall spam check result
@@ -46,6 +70,40 @@ impl Default for SpamCheckResult {
     }
 }
 
+impl SpamCheckResult {
+    /// Add the score of a check made outside the filter, such as a DNS
+    /// blocklist listing of the client
+    ///
+    /// A message pushed over the threshold counts as spam and gets the spam
+    /// headers; it is never rejected for it here. A negative score that
+    /// brings a message filed as junk below the threshold accepts it.
+    pub fn add_score(&mut self, symbol: impl Into<String>, score: f64) {
+        self.symbols.push(symbol.into());
+        self.adjust_score(score);
+    }
+
+    /// Take back the score of a matched symbol
+    fn remove_score(&mut self, symbol: &str, score: f64) {
+        if let Some(pos) = self.symbols.iter().position(|matched| matched == symbol) {
+            self.symbols.remove(pos);
+            self.adjust_score(-score);
+        }
+    }
+
+    fn adjust_score(&mut self, score: f64) {
+        self.score += score;
+        if !self.is_spam && self.score >= self.threshold {
+            self.is_spam = true;
+            if self.action == SpamAction::Accept {
+                self.action = SpamAction::AddHeader;
+            }
+        } else if self.is_spam && self.score < self.threshold && self.action.files_as_junk() {
+            self.is_spam = false;
+            self.action = SpamAction::Accept;
+        }
+    }
+}
+
 /// Action to take based on spam check
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -60,6 +118,8 @@ pub enum SpamAction {
     SoftReject,
     /// Reject the message
     Reject,
+    /// Accept the message but hold it in quarantine
+    Quarantine,
 }
 
 impl Default for SpamAction {
@@ -68,10 +128,39 @@ impl Default for SpamAction {
     }
 }
 
+impl SpamAction {
+    /// Whether the message is accepted but should land in the Junk folder
+    pub fn files_as_junk(self) -> bool {
+        matches!(self, SpamAction::AddHeader | SpamAction::RewriteSubject)
+    }
+
+    /// Whether the message is held in quarantine instead of delivered
+    ///
+    /// Messages the spam rules of a tenant reject are quarantined: by the
+    /// time those rules run, the message may have been accepted for the
+    /// other recipients.
+    pub fn quarantines(self) -> bool {
+        matches!(self, SpamAction::Quarantine | SpamAction::Reject)
+    }
+}
+
+/// A message refused after DATA because the spam filter rejected it
+#[derive(Debug, thiserror::Error)]
+#[error("message rejected as spam (score {0:.1})")]
+pub struct SpamRejection(pub f64);
+
+impl SpamRejection {
+    /// SMTP reply for the refused message
+    pub fn reply(&self) -> (u16, String) {
+        (550, "5.7.1 Message rejected as spam".to_string())
+    }
+}
+
 /// Combined spam filter that uses rspamd with rule-based fallback
 pub struct SpamFilter {
     rspamd: Option<RspamdClient>,
     rules: RuleBasedFilter,
+    tenant_rules: Option<TenantSpamRules>,
 }
 
 impl SpamFilter {
@@ -81,17 +170,38 @@ impl SpamFilter {
         Self {
             rspamd,
             rules: RuleBasedFilter::new(),
+            tenant_rules: None,
         }
     }
 
+    /// Create a spam filter from the environment
+    ///
+    /// Uses rspamd when `RSPAMD_URL` is set (with optional `RSPAMD_PASSWORD`),
+    /// rule-based filtering otherwise.
+    pub fn from_env() -> Self {
+        let rspamd_config = std::env::var("RSPAMD_URL").ok().map(|url| RspamdConfig {
+            url,
+            password: std::env::var("RSPAMD_PASSWORD").ok(),
+            ..Default::default()
+        });
+        Self::new(rspamd_config)
+    }
+
     /// Create a new spam filter with only rule-based filtering
     pub fn rules_only() -> Self {
         Self {
             rspamd: None,
             rules: RuleBasedFilter::new(),
+            tenant_rules: None,
         }
     }
 
+    /// Apply the spam rules tenants keep in the database
+    pub fn with_tenant_rules(mut self, db_pool: DatabasePool) -> Self {
+        self.tenant_rules = Some(TenantSpamRules::new(db_pool));
+        self
+    }
+
     /// Check a message for spam
     ///
     /// # Arguments
@@ -123,6 +233,7 @@ impl SpamFilter {
                             "soft reject" | "greylist" => SpamAction::SoftReject,
                             "rewrite subject" => SpamAction::RewriteSubject,
                             "add header" => SpamAction::AddHeader,
+                            "quarantine" => SpamAction::Quarantine,
                             _ => SpamAction::Accept,
                         },
                         metadata: serde_json::json!({
@@ -158,6 +269,71 @@ impl SpamFilter {
         }
     }
 
+    /// The verdict of a message for the recipients of a tenant
+    ///
+    /// Adds the scores of the tenant's matching rules. When the verdict came
+    /// from the built-in rules, a tenant rule of the same name replaces the
+    /// built-in one, taking its score back.
+    pub async fn apply_tenant_rules(
+        &self,
+        verdict: &SpamCheckResult,
+        tenant_id: TenantId,
+        raw_message: &[u8],
+    ) -> SpamCheckResult {
+        let mut verdict = verdict.clone();
+        let Some(tenant_rules) = &self.tenant_rules else {
+            return verdict;
+        };
+        let rules = tenant_rules.rules(tenant_id).await;
+        if rules.get_rules().is_empty() {
+            return verdict;
+        }
+
+        if verdict.metadata["source"] == "rules" {
+            for rule in rules.get_rules() {
+                if let Some(builtin) = self
+                    .rules
+                    .get_rules()
+                    .iter()
+                    .find(|builtin| builtin.name == rule.name)
+                {
+                    verdict.remove_score(&builtin.name, builtin.score);
+                }
+            }
+        }
+        for matched in rules.check(raw_message).matches {
+            verdict.add_score(matched.rule_name, matched.score);
+        }
+        verdict
+    }
+
+    /// Train the filter with a user verdict
+    ///
+    /// Returns `false` when no trainable backend (rspamd) is configured.
+    pub async fn learn(&self, raw_message: &[u8], is_spam: bool) -> anyhow::Result<bool> {
+        match self.rspamd {
+            Some(ref rspamd) => {
+                rspamd.learn(raw_message, is_spam).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Check a message with the antivirus and URL reputation checks again
+    ///
+    /// Returns the matched symbols, or `None` when no rspamd is configured;
+    /// the rule-based filter does not scan attachments or URLs.
+    pub async fn scan_threats(&self, raw_message: &[u8]) -> anyhow::Result<Option<Vec<String>>> {
+        match self.rspamd {
+            Some(ref rspamd) => {
+                let result = rspamd.check(raw_message, None, &[], None, None).await?;
+                Ok(Some(result.threat_symbols()))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Check if rspamd is available
     pub fn has_rspamd(&self) -> bool {
         self.rspamd.is_some()
@@ -173,6 +349,15 @@ impl SpamFilter {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_spam_actions() {
+        assert!(SpamAction::Reject.quarantines());
+        assert!(SpamAction::Quarantine.quarantines());
+        assert!(!SpamAction::AddHeader.quarantines());
+        assert!(!SpamAction::Reject.files_as_junk());
+        assert_eq!(SpamRejection(12.0).reply().0, 550);
+    }
+
     #[test]
     fn test_spam_check_result_default() {
         let result = SpamCheckResult::default();
@@ -180,4 +365,68 @@ mod tests {
         assert!(!result.is_reject);
         assert_eq!(result.action, SpamAction::Accept);
     }
+
+    #[test]
+    fn test_add_score() {
+        let mut result = SpamCheckResult::default();
+        result.add_score("DNSBL_BL_SPAMCOP_NET", 3.0);
+        assert!(!result.is_spam);
+        assert_eq!(result.action, SpamAction::Accept);
+
+        result.add_score("DNSBL_ZEN_SPAMHAUS_ORG", 4.0);
+        assert_eq!(result.score, 7.0);
+        assert!(result.is_spam);
+        assert!(!result.is_reject);
+        assert_eq!(result.action, SpamAction::AddHeader);
+        assert_eq!(
+            result.symbols,
+            vec!["DNSBL_BL_SPAMCOP_NET", "DNSBL_ZEN_SPAMHAUS_ORG"]
+        );
+    }
+
+    #[test]
+    fn test_negative_score() {
+        let mut result = SpamCheckResult::default();
+        result.add_score("SUBJECT_ADULT", 6.0);
+        assert!(result.is_spam);
+
+        result.add_score("TENANT_PARTNER", -2.0);
+        assert_eq!(result.score, 4.0);
+        assert!(!result.is_spam);
+        assert_eq!(result.action, SpamAction::Accept);
+
+        // Quarantined and rejected messages stay so
+        let mut result = SpamCheckResult {
+            score: 8.0,
+            is_spam: true,
+            action: SpamAction::Quarantine,
+            ..Default::default()
+        };
+        result.add_score("TENANT_PARTNER", -5.0);
+        assert!(result.is_spam);
+        assert_eq!(result.action, SpamAction::Quarantine);
+    }
+
+    #[test]
+    fn test_remove_score() {
+        let mut result = SpamCheckResult::default();
+        result.add_score("SUBJECT_ALL_CAPS", 2.0);
+        result.add_score("SUBJECT_MONEY", 4.0);
+        assert!(result.is_spam);
+
+        result.remove_score("SUBJECT_MONEY", 4.0);
+        result.remove_score("BODY_EMPTY", 2.0);
+        assert_eq!(result.score, 2.0);
+        assert_eq!(result.symbols, vec!["SUBJECT_ALL_CAPS"]);
+        assert!(!result.is_spam);
+    }
+
+    #[test]
+    fn test_files_as_junk() {
+        assert!(SpamAction::AddHeader.files_as_junk());
+        assert!(SpamAction::RewriteSubject.files_as_junk());
+        assert!(!SpamAction::Accept.files_as_junk());
+        assert!(!SpamAction::Reject.files_as_junk());
+        assert!(!SpamAction::Quarantine.files_as_junk());
+    }
 }
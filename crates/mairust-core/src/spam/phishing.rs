@@ -0,0 +1,478 @@
+//! Phishing heuristics
+//!
+//! Looks at the From and Reply-To headers of inbound mail for the usual
+//! tricks against a tenant:
+//! - the display name of one of the tenant's users on an outside address
+//! - a From domain that looks like one of the tenant's domains, spelled
+//!   with lookalike characters (`exarnple.com`, `examp1e.com`) or in
+//!   punycode (`xn--exmple-cua.com`)
+//! - a Reply-To address in another domain than the From address
+//!
+//! Each signal adds its score to the spam score of the message for the
+//! tenant's recipients, and is recorded as a warning in the message
+//! metadata and in the `X-MaiRust-Phishing` header of the stored copy, for
+//! the web UI to show.
+
+use super::uribl::registered_domain;
+use super::SpamCheckResult;
+use crate::impersonation::{domain_of, fold_lookalike, shows_name};
+use anyhow::Result;
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+
+/// Header listing the kinds of phishing signals of a stored message
+pub const PHISHING_HEADER: &str = "X-MaiRust-Phishing";
+
+/// Score of a user's display name on an outside address
+const INTERNAL_NAME_SCORE: f64 = 3.0;
+
+/// Score of a lookalike of one of the tenant's domains
+const LOOKALIKE_DOMAIN_SCORE: f64 = 6.0;
+
+/// Score of a Reply-To address in another domain
+const REPLY_TO_MISMATCH_SCORE: f64 = 1.0;
+
+/// Something in the headers that looks like phishing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhishingSignal {
+    /// The From display name shows a user of the tenant, or one of its
+    /// addresses, but the address is outside the tenant's domains
+    InternalName {
+        display_name: String,
+        from_address: String,
+    },
+    /// The From domain looks like a domain of the tenant
+    LookalikeDomain { domain: String, resembles: String },
+    /// Replies go to another domain than the From address
+    ReplyToMismatch {
+        from_domain: String,
+        reply_to: String,
+    },
+}
+
+impl PhishingSignal {
+    /// Kind of the signal, as in the header and the warnings
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PhishingSignal::InternalName { .. } => "internal_name_external_address",
+            PhishingSignal::LookalikeDomain { .. } => "lookalike_domain",
+            PhishingSignal::ReplyToMismatch { .. } => "reply_to_mismatch",
+        }
+    }
+
+    /// Spam symbol of the signal
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            PhishingSignal::InternalName { .. } => "PHISHING_INTERNAL_NAME",
+            PhishingSignal::LookalikeDomain { .. } => "PHISHING_LOOKALIKE_DOMAIN",
+            PhishingSignal::ReplyToMismatch { .. } => "PHISHING_REPLY_TO_MISMATCH",
+        }
+    }
+
+    /// Spam score of the signal
+    pub fn score(&self) -> f64 {
+        match self {
+            PhishingSignal::InternalName { .. } => INTERNAL_NAME_SCORE,
+            PhishingSignal::LookalikeDomain { .. } => LOOKALIKE_DOMAIN_SCORE,
+            PhishingSignal::ReplyToMismatch { .. } => REPLY_TO_MISMATCH_SCORE,
+        }
+    }
+
+    /// Warning recorded in the message metadata (`metadata.warnings`)
+    pub fn warning(&self) -> serde_json::Value {
+        let message = match self {
+            PhishingSignal::InternalName {
+                display_name,
+                from_address,
+            } => format!(
+                "This message shows the name {} of someone in your organisation, but was sent from an external address ({}).",
+                display_name, from_address
+            ),
+            PhishingSignal::LookalikeDomain { domain, resembles } => format!(
+                "This message was sent from {}, a domain that looks like {}.",
+                domain, resembles
+            ),
+            PhishingSignal::ReplyToMismatch { from_domain, reply_to } => format!(
+                "Replies to this message go to {}, outside the sender's domain {}.",
+                reply_to, from_domain
+            ),
+        };
+        serde_json::json!({
+            "kind": self.kind(),
+            "message": message,
+        })
+    }
+}
+
+/// Phishing signals of a message for a tenant
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhishingReport {
+    pub signals: Vec<PhishingSignal>,
+}
+
+impl PhishingReport {
+    /// Whether nothing looks like phishing
+    pub fn is_empty(&self) -> bool {
+        self.signals.is_empty()
+    }
+
+    /// Add the signals to the spam verdict of the message
+    pub fn add_to(&self, spam: &mut SpamCheckResult) {
+        for signal in &self.signals {
+            spam.add_score(signal.symbol(), signal.score());
+        }
+    }
+
+    /// `X-MaiRust-Phishing` header line, `None` without signals
+    pub fn header(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let kinds: Vec<&str> = self.signals.iter().map(PhishingSignal::kind).collect();
+        Some(format!("{}: {}\r\n", PHISHING_HEADER, kinds.join(", ")))
+    }
+}
+
+/// Look for phishing signals in the From and Reply-To headers
+///
+/// `tenant_domains` are the lowercase domains and domain aliases of the
+/// tenant, `user_names` the display names of its users.
+pub fn analyze(
+    from_name: Option<&str>,
+    from_address: &str,
+    reply_to: Option<&str>,
+    tenant_domains: &[String],
+    user_names: &[String],
+) -> PhishingReport {
+    let mut report = PhishingReport::default();
+    let Some(from_domain) = domain_of(from_address) else {
+        return report;
+    };
+    if !is_within(&from_domain, tenant_domains) {
+        if let Some(name) = from_name.map(str::trim).filter(|name| !name.is_empty()) {
+            let shows_address = domain_of(name)
+                .map(|domain| domain.trim_end_matches(['>', '"', '\'']).to_string())
+                .is_some_and(|domain| tenant_domains.contains(&domain));
+            if shows_address || user_names.iter().any(|user| shows_name(name, user)) {
+                report.signals.push(PhishingSignal::InternalName {
+                    display_name: name.to_string(),
+                    from_address: from_address.to_string(),
+                });
+            }
+        }
+        if let Some(resembles) = lookalike_of(&from_domain, tenant_domains) {
+            report.signals.push(PhishingSignal::LookalikeDomain {
+                domain: from_domain.clone(),
+                resembles: resembles.to_string(),
+            });
+        }
+    }
+
+    let reply_domain = reply_to.and_then(domain_of);
+    if let (Some(reply_to), Some(reply_domain)) = (reply_to, reply_domain) {
+        if registered_domain(&reply_domain) != registered_domain(&from_domain) {
+            report.signals.push(PhishingSignal::ReplyToMismatch {
+                from_domain,
+                reply_to: reply_to.trim().to_string(),
+            });
+        }
+    }
+    report
+}
+
+/// Whether a domain is one of the tenant's domains or a subdomain of one
+fn is_within(domain: &str, tenant_domains: &[String]) -> bool {
+    tenant_domains.iter().any(|tenant_domain| {
+        domain
+            .strip_suffix(tenant_domain.as_str())
+            .is_some_and(|sub| sub.is_empty() || sub.ends_with('.'))
+    })
+}
+
+/// Tenant domain a domain is a lookalike of
+///
+/// The domain, or the domain it is registered under, has to read the same
+/// as the tenant domain once lookalike characters are folded, without
+/// being inside it.
+fn lookalike_of<'a>(domain: &str, tenant_domains: &'a [String]) -> Option<&'a str> {
+    if is_within(domain, tenant_domains) {
+        return None;
+    }
+    let candidates = [skeleton(domain), skeleton(registered_domain(domain))];
+    tenant_domains
+        .iter()
+        .find(|tenant_domain| candidates.contains(&skeleton(tenant_domain)))
+        .map(String::as_str)
+}
+
+/// How a domain reads: punycode labels decoded, accents dropped and
+/// lookalike letters and digits folded together
+fn skeleton(domain: &str) -> String {
+    let decoded: Vec<String> = domain
+        .split('.')
+        .map(|label| {
+            let label = label.to_lowercase();
+            label
+                .strip_prefix("xn--")
+                .and_then(decode_punycode)
+                .unwrap_or(label)
+        })
+        .collect();
+    let folded: String = decoded
+        .join(".")
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(fold_lookalike)
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ñ' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | '0' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            // Letters that read as a lowercase l
+            'i' | 'ì' | 'í' | 'î' | 'ï' | '1' | '|' | 'ӏ' => 'l',
+            _ => c,
+        })
+        .collect();
+    folded.replace("rn", "m").replace("vv", "w")
+}
+
+/// Punycode parameters (RFC 3492, section 5)
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+
+/// Decode the part of a punycode label after `xn--` (RFC 3492)
+fn decode_punycode(input: &str) -> Option<String> {
+    let (basic, extended) = match input.rfind('-') {
+        Some(pos) => (&input[..pos], &input[pos + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut digits = extended.chars().peekable();
+    let (mut n, mut i, mut bias) = (128u32, 0u32, 72u32);
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut weight = 1u32;
+        let mut k = BASE;
+        loop {
+            let digit = match digits.next()? {
+                c @ 'a'..='z' => c as u32 - 'a' as u32,
+                c @ 'A'..='Z' => c as u32 - 'A' as u32,
+                c @ '0'..='9' => c as u32 - '0' as u32 + 26,
+                _ => return None,
+            };
+            i = i.checked_add(digit.checked_mul(weight)?)?;
+            let t = if k <= bias {
+                T_MIN
+            } else if k >= bias + T_MAX {
+                T_MAX
+            } else {
+                k - bias
+            };
+            if digit < t {
+                break;
+            }
+            weight = weight.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt_bias(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+/// Bias adaptation of RFC 3492, section 6.1
+fn adapt_bias(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + ((BASE - T_MIN + 1) * delta) / (delta + SKEW)
+}
+
+/// Looks for phishing signals in mail to a tenant
+pub struct PhishingAnalyzer {
+    db_pool: DatabasePool,
+}
+
+impl PhishingAnalyzer {
+    /// Create an analyzer
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Check the headers of a message delivered to a tenant
+    pub async fn check(
+        &self,
+        tenant_id: TenantId,
+        from_name: Option<&str>,
+        from_address: Option<&str>,
+        reply_to: Option<&str>,
+    ) -> Result<PhishingReport> {
+        let Some(from_address) = from_address else {
+            return Ok(PhishingReport::default());
+        };
+        let tenant_domains: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT LOWER(name) FROM domains WHERE tenant_id = $1
+            UNION
+            SELECT LOWER(alias_domain) FROM domain_aliases WHERE tenant_id = $1
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+        let user_names: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT name FROM users
+            WHERE tenant_id = $1 AND active = true AND name IS NOT NULL AND name <> ''
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+        Ok(analyze(
+            from_name,
+            from_address,
+            reply_to,
+            &tenant_domains,
+            &user_names,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains() -> Vec<String> {
+        vec!["example.com".to_string(), "example.org".to_string()]
+    }
+
+    fn kinds(report: &PhishingReport) -> Vec<&'static str> {
+        report.signals.iter().map(PhishingSignal::kind).collect()
+    }
+
+    #[test]
+    fn test_decode_punycode() {
+        assert_eq!(decode_punycode("bcher-kva").as_deref(), Some("bücher"));
+        assert_eq!(decode_punycode("80ak6aa92e").as_deref(), Some("аррӏе"));
+        assert_eq!(decode_punycode("!!"), None);
+    }
+
+    #[test]
+    fn test_lookalike_of() {
+        let domains = domains();
+        assert_eq!(lookalike_of("example.com", &domains), None);
+        assert_eq!(lookalike_of("exarnple.com", &domains), Some("example.com"));
+        assert_eq!(lookalike_of("examp1e.org", &domains), Some("example.org"));
+        // Cyrillic а
+        assert_eq!(lookalike_of("exаmple.com", &domains), Some("example.com"));
+        assert_eq!(
+            lookalike_of("xn--exmple-cua.com", &domains),
+            Some("example.com")
+        );
+        assert_eq!(
+            lookalike_of("mail.examp1e.com", &domains),
+            Some("example.com")
+        );
+        assert_eq!(lookalike_of("sample.com", &domains), None);
+        assert_eq!(lookalike_of("mail.example.com", &domains), None);
+    }
+
+    #[test]
+    fn test_internal_name() {
+        let users = vec!["Jane Roe".to_string()];
+        let report = analyze(
+            Some("Jane Roe"),
+            "jane.roe@freemail.test",
+            None,
+            &domains(),
+            &users,
+        );
+        assert_eq!(kinds(&report), vec!["internal_name_external_address"]);
+
+        let report = analyze(
+            Some("ceo@example.com"),
+            "ceo@freemail.test",
+            None,
+            &domains(),
+            &users,
+        );
+        assert_eq!(kinds(&report), vec!["internal_name_external_address"]);
+
+        // The user's own domain
+        let report = analyze(
+            Some("Jane Roe"),
+            "jane@example.com",
+            None,
+            &domains(),
+            &users,
+        );
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_reply_to_mismatch() {
+        let report = analyze(
+            Some("Billing"),
+            "billing@shop.test",
+            Some("refunds@elsewhere.test"),
+            &domains(),
+            &[],
+        );
+        assert_eq!(kinds(&report), vec!["reply_to_mismatch"]);
+        assert_eq!(
+            report.header().as_deref(),
+            Some("X-MaiRust-Phishing: reply_to_mismatch\r\n")
+        );
+
+        let report = analyze(
+            None,
+            "news@shop.test",
+            Some("support@mail.shop.test"),
+            &domains(),
+            &[],
+        );
+        assert!(report.is_empty());
+        assert_eq!(report.header(), None);
+    }
+
+    #[test]
+    fn test_add_to() {
+        let report = analyze(
+            Some("Jane Roe"),
+            "jane@exarnple.com",
+            Some("jane@freemail.test"),
+            &domains(),
+            &["Jane Roe".to_string()],
+        );
+        let mut spam = SpamCheckResult::default();
+        report.add_to(&mut spam);
+        assert_eq!(
+            spam.symbols,
+            vec![
+                "PHISHING_INTERNAL_NAME",
+                "PHISHING_LOOKALIKE_DOMAIN",
+                "PHISHING_REPLY_TO_MISMATCH"
+            ]
+        );
+        assert_eq!(spam.score, 10.0);
+        assert!(spam.is_spam);
+    }
+}
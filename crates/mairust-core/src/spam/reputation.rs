@@ -0,0 +1,255 @@
+//! Local reputation of sender domains
+//!
+//! Mail from unauthenticated sessions is counted per envelope sender
+//! domain: messages, messages the spam filter flagged, and recipients that
+//! were refused (unknown users, unknown domains). The counts decay
+//! exponentially, halving every configured half-life, so a domain recovers
+//! once its mail gets better and an old burst is forgotten.
+//!
+//! The reputation is a score from 0 to 100: the share of clean messages
+//! among messages and refused recipients, with a few clean ones assumed so
+//! that a domain is not condemned by its first spam. Below 50 it adds to
+//! the spam score of the domain's messages, and below the configured
+//! threshold MAIL FROM is deferred. Policies can match on it.
+//!
+//! The envelope sender is not authenticated at MAIL FROM, so a forged
+//! domain takes the blame for the forger's mail; keep the spam weight
+//! modest and the deferral threshold low.
+
+use super::SpamCheckResult;
+use chrono::{DateTime, Utc};
+use mairust_common::config::SenderReputationConfig;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::{SenderReputationRecord, SenderReputationRepository};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// Spam symbol of the reputation score factor
+pub const REPUTATION_SYMBOL: &str = "SENDER_REPUTATION";
+
+/// Reputation of a domain without mail, or whose counts have decayed away
+pub const CLEAN_SCORE: f64 = 100.0;
+
+/// Clean events assumed for every domain
+const PRIOR_EVENTS: f64 = 5.0;
+
+/// Reputation below which the spam score is raised
+const NEUTRAL_SCORE: f64 = 50.0;
+
+/// Shortest half-life, so that counts never decay instantly
+const MIN_HALF_LIFE_SECS: f64 = 60.0;
+
+/// Share of a count left after `elapsed_secs`
+pub fn decay_factor(elapsed_secs: f64, half_life_secs: f64) -> f64 {
+    0.5f64.powf(elapsed_secs.max(0.0) / half_life_secs.max(MIN_HALF_LIFE_SECS))
+}
+
+/// Reputation from 0 (only spam and errors) to 100 (clean)
+pub fn reputation_score(messages: f64, spam: f64, errors: f64) -> f64 {
+    let bad = spam + errors;
+    let events = messages + errors;
+    (CLEAN_SCORE * (1.0 - bad / (events + PRIOR_EVENTS))).clamp(0.0, CLEAN_SCORE)
+}
+
+/// Spam score added for a reputation, up to `weight` at 0
+pub fn spam_penalty(score: f64, weight: f64) -> f64 {
+    if score >= NEUTRAL_SCORE {
+        0.0
+    } else {
+        weight * (NEUTRAL_SCORE - score) / NEUTRAL_SCORE
+    }
+}
+
+/// Reputation of a sender domain, with its counts decayed to a point in
+/// time
+#[derive(Debug, Clone, Serialize)]
+pub struct ReputationScore {
+    pub domain: String,
+    /// From 0 (only spam and errors) to 100 (clean)
+    pub score: f64,
+    pub messages: f64,
+    pub spam: f64,
+    pub errors: f64,
+    pub total_messages: i64,
+    pub total_spam: i64,
+    pub total_errors: i64,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl ReputationScore {
+    /// Reputation of stored counts at `now`
+    pub fn of(record: &SenderReputationRecord, now: DateTime<Utc>) -> Self {
+        let elapsed = (now - record.decayed_at).num_milliseconds() as f64 / 1000.0;
+        let factor = decay_factor(elapsed, record.half_life_secs);
+        let messages = record.messages * factor;
+        let spam = record.spam * factor;
+        let errors = record.errors * factor;
+        Self {
+            domain: record.domain.clone(),
+            score: reputation_score(messages, spam, errors),
+            messages,
+            spam,
+            errors,
+            total_messages: record.total_messages,
+            total_spam: record.total_spam,
+            total_errors: record.total_errors,
+            first_seen_at: record.first_seen_at,
+            last_seen_at: record.last_seen_at,
+        }
+    }
+
+    /// Messages and refused recipients, after decay
+    pub fn volume(&self) -> f64 {
+        self.messages + self.errors
+    }
+}
+
+/// Sender domain reputations, shared by the sessions of an SMTP server
+pub struct SenderReputation {
+    db_pool: DatabasePool,
+    config: SenderReputationConfig,
+}
+
+impl SenderReputation {
+    /// Create a sender reputation tracker
+    pub fn new(db_pool: DatabasePool, config: SenderReputationConfig) -> Self {
+        Self { db_pool, config }
+    }
+
+    /// Whether sender domains are tracked
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    fn half_life_secs(&self) -> f64 {
+        (self.config.half_life_hours * 3600.0).max(MIN_HALF_LIFE_SECS)
+    }
+
+    /// Current reputation of a domain; `None` when it is not known or
+    /// cannot be read
+    pub async fn score(&self, domain: &str) -> Option<ReputationScore> {
+        if !self.enabled() {
+            return None;
+        }
+        match SenderReputationRepository::new(self.db_pool.clone())
+            .get(domain)
+            .await
+        {
+            Ok(record) => record.map(|record| ReputationScore::of(&record, Utc::now())),
+            Err(e) => {
+                warn!("Failed to read the reputation of {}: {}", domain, e);
+                None
+            }
+        }
+    }
+
+    /// Whether MAIL FROM of a domain with this reputation is deferred
+    pub fn defers(&self, reputation: &ReputationScore) -> bool {
+        self.config.reject_below > 0.0
+            && reputation.score < self.config.reject_below
+            && reputation.volume() >= self.config.min_messages
+    }
+
+    /// Add the reputation to the spam verdict of a message
+    pub fn add_to(&self, reputation: &ReputationScore, spam: &mut SpamCheckResult) {
+        let penalty = spam_penalty(reputation.score, self.config.spam_weight);
+        if penalty > 0.0 {
+            spam.add_score(REPUTATION_SYMBOL, penalty);
+        }
+    }
+
+    /// Count a received message of a domain
+    pub async fn record_message(&self, domain: &str, is_spam: bool) {
+        self.record(domain, 1, i64::from(is_spam), 0).await;
+    }
+
+    /// Count a refused recipient of a domain
+    pub async fn record_error(&self, domain: &str) {
+        self.record(domain, 0, 0, 1).await;
+    }
+
+    async fn record(&self, domain: &str, messages: i64, spam: i64, errors: i64) {
+        if !self.enabled() || domain.is_empty() {
+            return;
+        }
+        match SenderReputationRepository::new(self.db_pool.clone())
+            .record(domain, messages, spam, errors, self.half_life_secs())
+            .await
+        {
+            Ok(record) => debug!(
+                "Reputation of {} is now {:.1}",
+                domain,
+                ReputationScore::of(&record, record.decayed_at).score
+            ),
+            Err(e) => warn!("Failed to record the reputation of {}: {}", domain, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_decay_factor() {
+        assert_eq!(decay_factor(0.0, 3600.0), 1.0);
+        assert!((decay_factor(3600.0, 3600.0) - 0.5).abs() < 1e-9);
+        assert!((decay_factor(7200.0, 3600.0) - 0.25).abs() < 1e-9);
+        // Clock skew does not grow counts, and the half-life has a floor
+        assert_eq!(decay_factor(-10.0, 3600.0), 1.0);
+        assert!((decay_factor(60.0, 0.0) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reputation_score() {
+        assert_eq!(reputation_score(0.0, 0.0, 0.0), 100.0);
+        assert_eq!(reputation_score(100.0, 0.0, 0.0), 100.0);
+        // One spam of a new domain is not enough to condemn it
+        let first = reputation_score(1.0, 1.0, 0.0);
+        assert!(first > 80.0 && first < 90.0);
+        assert!(reputation_score(1000.0, 1000.0, 0.0) < 1.0);
+        // Refused recipients count as errors
+        assert!(reputation_score(0.0, 0.0, 95.0) < 5.5);
+        assert!(reputation_score(50.0, 0.0, 50.0) < reputation_score(100.0, 0.0, 0.0));
+        // Spam counted with more messages never goes below 0
+        assert_eq!(reputation_score(0.0, 10.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_spam_penalty() {
+        assert_eq!(spam_penalty(100.0, 3.0), 0.0);
+        assert_eq!(spam_penalty(50.0, 3.0), 0.0);
+        assert!((spam_penalty(25.0, 3.0) - 1.5).abs() < 1e-9);
+        assert_eq!(spam_penalty(0.0, 3.0), 3.0);
+    }
+
+    #[test]
+    fn test_score_of_record_decays() {
+        let decayed_at = Utc::now();
+        let record = SenderReputationRecord {
+            domain: "example.com".to_string(),
+            messages: 40.0,
+            spam: 40.0,
+            errors: 0.0,
+            half_life_secs: 3600.0,
+            decayed_at,
+            total_messages: 40,
+            total_spam: 40,
+            total_errors: 0,
+            first_seen_at: decayed_at,
+            last_seen_at: decayed_at,
+        };
+
+        let now = ReputationScore::of(&record, decayed_at);
+        assert_eq!(now.messages, 40.0);
+        assert!(now.score < 15.0);
+
+        // A day later the burst is mostly forgotten
+        let later = ReputationScore::of(&record, decayed_at + Duration::hours(24));
+        assert!(later.messages < 0.01);
+        assert!(later.score > 99.0);
+        assert_eq!(later.total_spam, 40);
+    }
+}
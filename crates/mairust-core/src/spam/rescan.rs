@@ -0,0 +1,200 @@
+//! Rescan of attachments on download
+//!
+//! Messages are scanned when they are received, but antivirus signatures
+//! and URL reputation lists keep changing. With rescanning enabled, a stored
+//! message whose last scan is older than the rescan interval is checked with
+//! rspamd again before one of its attachments is served. Downloads from a
+//! message flagged by the antivirus or URL reputation checks are refused.
+
+use super::SpamFilter;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::AttachmentScanRepository;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Rescan interval when `MAIRUST_ATTACHMENT_RESCAN_AFTER_MINUTES` is not set
+pub const DEFAULT_RESCAN_AFTER_MINUTES: i64 = 60;
+
+/// Whether an attachment may be downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum RescanVerdict {
+    /// The attachment may be served
+    Allowed,
+    /// The message was flagged with the given symbols
+    Blocked(Vec<String>),
+}
+
+impl RescanVerdict {
+    /// Explanation returned with a refused download
+    pub fn reason(&self) -> Option<String> {
+        match self {
+            RescanVerdict::Allowed => None,
+            RescanVerdict::Blocked(symbols) => Some(format!(
+                "This message was flagged as harmful when it was scanned again ({}). \
+                 Its attachments can no longer be downloaded.",
+                symbols.join(", ")
+            )),
+        }
+    }
+}
+
+/// Rescans stored messages before their attachments are served
+pub struct AttachmentRescanner {
+    spam_filter: SpamFilter,
+    scans: AttachmentScanRepository,
+    /// `None` when rescanning is disabled
+    rescan_after: Option<Duration>,
+}
+
+impl AttachmentRescanner {
+    /// Create a rescanner; `rescan_after` of `None` disables rescanning
+    pub fn new(
+        db_pool: DatabasePool,
+        spam_filter: SpamFilter,
+        rescan_after: Option<Duration>,
+    ) -> Self {
+        Self {
+            spam_filter,
+            scans: AttachmentScanRepository::new(db_pool),
+            rescan_after,
+        }
+    }
+
+    /// Create a rescanner from the environment
+    ///
+    /// Rescanning is enabled by `MAIRUST_ATTACHMENT_RESCAN=true` and repeats
+    /// scans older than `MAIRUST_ATTACHMENT_RESCAN_AFTER_MINUTES` (default
+    /// 60). It uses the rspamd configured for the spam filter.
+    pub fn from_env(db_pool: DatabasePool) -> Self {
+        let enabled = std::env::var("MAIRUST_ATTACHMENT_RESCAN")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "true" | "yes" | "1"))
+            .unwrap_or(false);
+        let minutes = std::env::var("MAIRUST_ATTACHMENT_RESCAN_AFTER_MINUTES")
+            .ok()
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .unwrap_or(DEFAULT_RESCAN_AFTER_MINUTES)
+            .max(0);
+
+        Self::new(
+            db_pool,
+            SpamFilter::from_env(),
+            enabled.then_some(Duration::minutes(minutes)),
+        )
+    }
+
+    /// Check a stored message before one of its attachments is served
+    ///
+    /// `received_at` is the time of the scan at reception, when known. A
+    /// failed rescan does not block the download.
+    pub async fn check(
+        &self,
+        tenant_id: Uuid,
+        storage_path: &str,
+        received_at: Option<DateTime<Utc>>,
+        raw_message: &[u8],
+    ) -> RescanVerdict {
+        let Some(rescan_after) = self.rescan_after else {
+            return RescanVerdict::Allowed;
+        };
+        if !self.spam_filter.has_rspamd() {
+            return RescanVerdict::Allowed;
+        }
+
+        let recorded = match self.scans.get(storage_path).await {
+            Ok(scan) => scan,
+            Err(e) => {
+                warn!("Failed to load attachment scan of {}: {}", storage_path, e);
+                None
+            }
+        };
+        let last_scan = recorded.as_ref().map(|s| s.scanned_at).or(received_at);
+
+        if !needs_rescan(last_scan, rescan_after, Utc::now()) {
+            return match recorded.map(|s| s.flagged_symbols_vec()) {
+                Some(symbols) if !symbols.is_empty() => RescanVerdict::Blocked(symbols),
+                _ => RescanVerdict::Allowed,
+            };
+        }
+
+        let symbols = match self.spam_filter.scan_threats(raw_message).await {
+            Ok(Some(symbols)) => symbols,
+            Ok(None) => return RescanVerdict::Allowed,
+            Err(e) => {
+                warn!(
+                    "Attachment rescan of {} failed, serving it: {}",
+                    storage_path, e
+                );
+                return RescanVerdict::Allowed;
+            }
+        };
+
+        if let Err(e) = self.scans.record(tenant_id, storage_path, &symbols).await {
+            warn!(
+                "Failed to record attachment scan of {}: {}",
+                storage_path, e
+            );
+        }
+
+        if symbols.is_empty() {
+            RescanVerdict::Allowed
+        } else {
+            info!(
+                "Rescan flagged {} ({}), blocking attachment downloads",
+                storage_path,
+                symbols.join(", ")
+            );
+            RescanVerdict::Blocked(symbols)
+        }
+    }
+}
+
+/// Whether a scan made at `last_scan` has to be repeated at `now`
+fn needs_rescan(
+    last_scan: Option<DateTime<Utc>>,
+    rescan_after: Duration,
+    now: DateTime<Utc>,
+) -> bool {
+    match last_scan {
+        Some(scanned_at) => now - scanned_at >= rescan_after,
+        None => true,
+    }
+}
+
+/// Reception time encoded in a time-ordered (v7) message ID
+pub fn received_at_from_id(message_id: Uuid) -> Option<DateTime<Utc>> {
+    let (secs, nanos) = message_id.get_timestamp()?.to_unix();
+    Utc.timestamp_opt(secs as i64, nanos).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_rescan() {
+        let now = Utc::now();
+        let hour = Duration::minutes(60);
+        assert!(needs_rescan(None, hour, now));
+        assert!(needs_rescan(Some(now - Duration::minutes(61)), hour, now));
+        assert!(!needs_rescan(Some(now - Duration::minutes(59)), hour, now));
+        assert!(needs_rescan(Some(now), Duration::zero(), now));
+    }
+
+    #[test]
+    fn test_received_at_from_id() {
+        let before = Utc::now() - Duration::seconds(1);
+        let received_at = received_at_from_id(Uuid::now_v7()).unwrap();
+        assert!(received_at >= before && received_at <= Utc::now());
+        assert!(received_at_from_id(Uuid::new_v4()).is_none());
+    }
+
+    #[test]
+    fn test_verdict_reason() {
+        assert!(RescanVerdict::Allowed.reason().is_none());
+        let reason = RescanVerdict::Blocked(vec!["CLAM_VIRUS".to_string()])
+            .reason()
+            .unwrap();
+        assert!(reason.contains("CLAM_VIRUS"));
+    }
+}
@@ -64,6 +64,35 @@ pub struct RspamdResult {
     pub time_real: Option<f64>,
 }
 
+impl RspamdResult {
+    /// Matched symbols of the antivirus and URL reputation checks
+    pub fn threat_symbols(&self) -> Vec<String> {
+        self.symbols
+            .iter()
+            .filter(|s| s.score > 0.0 && is_threat_symbol(&s.name))
+            .map(|s| s.name.clone())
+            .collect()
+    }
+}
+
+/// URL reputation symbol prefixes of the rspamd SURBL/RBL modules
+const URL_REPUTATION_PREFIXES: &[&str] = &["URIBL_", "SURBL_", "DBL_", "SEM_URIBL", "PH_SURBL"];
+
+/// Whether a symbol reports a virus, malware or a URL with bad reputation
+///
+/// `*_FAIL` symbols report that a scanner could not be reached and
+/// `*_BLOCKED` ones that a lookup was refused; neither is a finding.
+fn is_threat_symbol(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    if name.ends_with("_FAIL") || name.ends_with("_BLOCKED") {
+        return false;
+    }
+    name.contains("VIRUS")
+        || name.contains("MALWARE")
+        || name == "PHISHING"
+        || URL_REPUTATION_PREFIXES.iter().any(|p| name.starts_with(p))
+}
+
 /// rspamd symbol/rule match
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RspamdSymbol {
@@ -377,4 +406,41 @@ mod tests {
         let json = serde_json::to_string(&result).unwrap();
         assert!(json.contains("BAYES_SPAM"));
     }
+
+    #[test]
+    fn test_threat_symbols() {
+        let symbol = |name: &str, score: f64| RspamdSymbol {
+            name: name.to_string(),
+            score,
+            description: None,
+            options: vec![],
+        };
+        let result = RspamdResult {
+            score: 30.0,
+            required_score: 15.0,
+            is_spam: true,
+            action: "reject".to_string(),
+            symbols: vec![
+                symbol("BAYES_SPAM", 5.0),
+                symbol("CLAM_VIRUS", 20.0),
+                symbol("CLAM_VIRUS_FAIL", 0.0),
+                symbol("URIBL_BLACK", 7.5),
+                symbol("URIBL_BLOCKED", 0.0),
+                symbol("DBL_PHISH", 7.5),
+                symbol("PHISHING", 4.0),
+                symbol("SURBL_MULTI", 0.0),
+            ],
+            message_id: None,
+            time_real: None,
+        };
+
+        let mut threats = result.threat_symbols();
+        threats.sort();
+        assert_eq!(
+            threats,
+            vec!["CLAM_VIRUS", "DBL_PHISH", "PHISHING", "URIBL_BLACK"]
+        );
+        assert!(!is_threat_symbol("ClamAV_virus_fail"));
+        assert!(is_threat_symbol("sem_uribl_fresh15"));
+    }
 }
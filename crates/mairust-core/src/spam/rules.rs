@@ -2,7 +2,11 @@
 //!
 //! Provides basic spam detection using simple rules.
 //! This serves as a fallback when rspamd is not available.
+//!
+//! URL rules are matched against each link of the message, taken from its
+//! decoded text and HTML parts (see [`extract_urls`]).
 
+use mail_parser::{MessageParser, PartType};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -31,6 +35,24 @@ fn default_true() -> bool {
     true
 }
 
+impl SpamRule {
+    /// Check that the pattern fits the rule type
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rule_type.is_regex() {
+            Regex::new(&self.pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+        }
+        match self.rule_type {
+            RuleType::HasHeader | RuleType::MissingHeader if self.pattern.trim().is_empty() => {
+                Err("a header name is required".to_string())
+            }
+            RuleType::BodyLength if self.pattern.parse::<usize>().is_err() => {
+                Err("the pattern must be a length in bytes".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Type of spam rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -49,10 +71,52 @@ pub enum RuleType {
     MissingHeader,
     /// Check body length
     BodyLength,
-    /// URL pattern matching
+    /// Match pattern against each link in the body
     Url,
 }
 
+impl RuleType {
+    /// Name of the rule type, as used in the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleType::Header => "header",
+            RuleType::Body => "body",
+            RuleType::From => "from",
+            RuleType::Subject => "subject",
+            RuleType::HasHeader => "has_header",
+            RuleType::MissingHeader => "missing_header",
+            RuleType::BodyLength => "body_length",
+            RuleType::Url => "url",
+        }
+    }
+
+    /// Rule type of a name
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "header" => Some(RuleType::Header),
+            "body" => Some(RuleType::Body),
+            "from" => Some(RuleType::From),
+            "subject" => Some(RuleType::Subject),
+            "has_header" => Some(RuleType::HasHeader),
+            "missing_header" => Some(RuleType::MissingHeader),
+            "body_length" => Some(RuleType::BodyLength),
+            "url" => Some(RuleType::Url),
+            _ => None,
+        }
+    }
+
+    /// Whether the pattern is a regular expression
+    fn is_regex(&self) -> bool {
+        matches!(
+            self,
+            RuleType::Header | RuleType::Body | RuleType::From | RuleType::Subject | RuleType::Url
+        )
+    }
+}
+
+/// Most links taken from a message
+const MAX_URLS: usize = 200;
+
 /// Result of rule-based spam check
 #[derive(Debug, Clone, Default)]
 pub struct RuleResult {
@@ -81,16 +145,21 @@ pub struct RuleBasedFilter {
 impl RuleBasedFilter {
     /// Create a new rule-based filter with default rules
     pub fn new() -> Self {
-        let mut filter = Self {
-            rules: Vec::new(),
-            compiled_patterns: HashMap::new(),
-        };
+        let mut filter = Self::empty();
 
         // Add default spam rules
         filter.add_default_rules();
         filter
     }
 
+    /// Create a rule-based filter without rules
+    pub fn empty() -> Self {
+        Self {
+            rules: Vec::new(),
+            compiled_patterns: HashMap::new(),
+        }
+    }
+
     /// Add default spam detection rules
     fn add_default_rules(&mut self) {
         let default_rules = vec![
@@ -183,7 +252,7 @@ impl RuleBasedFilter {
                 name: "URL_SHORTENER".to_string(),
                 description: "Contains URL shortener links".to_string(),
                 rule_type: RuleType::Url,
-                pattern: r"(?i)(bit\.ly|tinyurl\.com|goo\.gl|t\.co|ow\.ly|is\.gd|buff\.ly)".to_string(),
+                pattern: r"(?i)^(?:[a-z]+://)?(?:www\.)?(bit\.ly|tinyurl\.com|goo\.gl|t\.co|ow\.ly|is\.gd|buff\.ly)(?:[/?#:]|$)".to_string(),
                 score: 1.5,
                 enabled: true,
             },
@@ -191,7 +260,7 @@ impl RuleBasedFilter {
                 name: "URL_IP_ADDRESS".to_string(),
                 description: "Contains URL with IP address".to_string(),
                 rule_type: RuleType::Url,
-                pattern: r"https?://\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}".to_string(),
+                pattern: r"(?i)^https?://\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}(?:[/?#:]|$)".to_string(),
                 score: 3.0,
                 enabled: true,
             },
@@ -251,14 +320,7 @@ impl RuleBasedFilter {
     /// Add a custom rule
     pub fn add_rule(&mut self, rule: SpamRule) {
         // Compile regex pattern if applicable
-        if matches!(
-            rule.rule_type,
-            RuleType::Header
-                | RuleType::Body
-                | RuleType::From
-                | RuleType::Subject
-                | RuleType::Url
-        ) {
+        if rule.rule_type.is_regex() {
             if let Ok(regex) = Regex::new(&rule.pattern) {
                 self.compiled_patterns.insert(rule.name.clone(), regex);
             }
@@ -288,6 +350,7 @@ impl RuleBasedFilter {
         // Get specific header values
         let subject = headers.get("subject").map(|s| s.as_str()).unwrap_or("");
         let from = headers.get("from").map(|s| s.as_str()).unwrap_or("");
+        let urls = extract_urls(raw_message);
 
         // Apply each rule
         for rule in &self.rules {
@@ -326,7 +389,7 @@ impl RuleBasedFilter {
                 }
                 RuleType::Url => {
                     if let Some(regex) = self.compiled_patterns.get(&rule.name) {
-                        regex.is_match(&message_str)
+                        urls.iter().any(|url| regex.is_match(url))
                     } else {
                         false
                     }
@@ -380,6 +443,98 @@ impl Default for RuleBasedFilter {
     }
 }
 
+/// Links in the body of a message, each once, in order
+///
+/// Text and HTML parts are decoded first, so links in quoted-printable or
+/// base64 bodies are found too. A link starts with `http://`, `https://`
+/// or `www.` and ends at whitespace, a quote or an angle bracket.
+pub fn extract_urls(raw_message: &[u8]) -> Vec<String> {
+    let texts: Vec<String> = match MessageParser::default().parse(raw_message) {
+        Some(message) => message
+            .parts
+            .iter()
+            .filter_map(|part| match &part.body {
+                PartType::Text(text) | PartType::Html(text) => Some(text.to_string()),
+                _ => None,
+            })
+            .collect(),
+        None => vec![String::from_utf8_lossy(raw_message).into_owned()],
+    };
+
+    let mut urls: Vec<String> = Vec::new();
+    for text in &texts {
+        for url in urls_in(text) {
+            if urls.len() >= MAX_URLS {
+                return urls;
+            }
+            if !urls.iter().any(|seen| seen == url) {
+                urls.push(url.to_string());
+            }
+        }
+    }
+    urls
+}
+
+/// Links in a text
+fn urls_in(text: &str) -> Vec<&str> {
+    // ASCII lowercasing keeps byte offsets
+    let lower = text.to_ascii_lowercase();
+    let bytes = lower.as_bytes();
+    let mut urls = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &lower[pos..];
+        // `www.` only starts a link on its own, not in `me@www.example.com`
+        let starts = rest.starts_with("http://")
+            || rest.starts_with("https://")
+            || (rest.starts_with("www.")
+                && (pos == 0
+                    || !(bytes[pos - 1].is_ascii_alphanumeric()
+                        || matches!(bytes[pos - 1], b'.' | b'@' | b'-' | b'_'))));
+        if !starts {
+            pos += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+
+        let end = rest
+            .find(|c: char| {
+                c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | '`' | '\\' | '^' | '|')
+            })
+            .map_or(lower.len(), |len| pos + len);
+        let url = text[pos..end].trim_end_matches(|c: char| {
+            matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | ')' | ']' | '}')
+        });
+        if url_host(url).is_some() {
+            urls.push(url);
+        }
+        pos = end;
+    }
+    urls
+}
+
+/// Lowercase host a link points to, without port or user name; IPv6
+/// addresses keep their brackets
+pub fn url_host(url: &str) -> Option<String> {
+    let rest = match url.find("://") {
+        Some(scheme_end) => &url[scheme_end + 3..],
+        None => url,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit('@').next().unwrap_or("");
+    let host = if host_port.starts_with('[') {
+        host_port.find(']').map(|end| &host_port[..=end])?
+    } else {
+        host_port.split(':').next().unwrap_or("")
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let valid = host.starts_with('[')
+        || (host.contains('.')
+            && host
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '.' || c == '-' || c == '_'));
+    (valid && !host.is_empty()).then_some(host)
+}
+
 /// Parse email headers into a map
 fn parse_headers(headers_str: &str) -> HashMap<String, String> {
     let mut headers = HashMap::new();
@@ -455,6 +610,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_extract_urls() {
+        let message = b"From: user@example.com\r\nSubject: Links\r\n\r\n\
+            See https://Example.com/a?b=c, and www.example.org.\r\n\
+            <a href=\"http://192.0.2.1/login\">log in</a> https://Example.com/a?b=c\r\n\
+            Mail me at me@www.example.net";
+        assert_eq!(
+            extract_urls(message),
+            vec![
+                "https://Example.com/a?b=c",
+                "www.example.org",
+                "http://192.0.2.1/login",
+            ]
+        );
+
+        // Links in an encoded body are found too
+        let encoded = b"From: user@example.com\r\nSubject: Encoded\r\n\
+            Content-Type: text/html\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n\
+            <a href=3D\"https://spam.example/offer\">Offer</a>";
+        assert_eq!(extract_urls(encoded), vec!["https://spam.example/offer"]);
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(
+            url_host("https://user:pw@WWW.Example.com:8443/path?q#f").as_deref(),
+            Some("www.example.com")
+        );
+        assert_eq!(
+            url_host("www.example.org.").as_deref(),
+            Some("www.example.org")
+        );
+        assert_eq!(url_host("http://192.0.2.1/").as_deref(), Some("192.0.2.1"));
+        assert_eq!(
+            url_host("http://[2001:db8::1]:80/").as_deref(),
+            Some("[2001:db8::1]")
+        );
+        assert_eq!(url_host("http://localhost/"), None);
+        assert_eq!(url_host("http:///path"), None);
+    }
+
+    #[test]
+    fn test_url_rules() {
+        let filter = RuleBasedFilter::new();
+
+        let message = b"From: user@example.com\r\nSubject: Test\r\n\r\n\
+            Short link https://bit.ly/abc and https://t.co/xyz";
+        let result = filter.check(message);
+        assert!(result.matched_rules.contains(&"URL_SHORTENER".to_string()));
+
+        // Look-alike domains and bare mentions do not count
+        let message = b"From: user@example.com\r\nSubject: Test\r\n\r\n\
+            Visit https://hobbit.ly.example/ and https://list.com/, or type bit.ly";
+        let result = filter.check(message);
+        assert!(!result.matched_rules.contains(&"URL_SHORTENER".to_string()));
+
+        let message = b"From: user@example.com\r\nSubject: Test\r\n\r\n\
+            Sign in at http://198.51.100.7/bank";
+        let result = filter.check(message);
+        assert!(result.matched_rules.contains(&"URL_IP_ADDRESS".to_string()));
+    }
+
     #[test]
     fn test_add_custom_rule() {
         let mut filter = RuleBasedFilter::new();
@@ -474,4 +691,40 @@ mod tests {
         assert!(result.matched_rules.contains(&"CUSTOM_PATTERN".to_string()));
         assert!(result.score >= 10.0);
     }
+
+    #[test]
+    fn test_rule_type_names() {
+        for name in [
+            "header",
+            "body",
+            "from",
+            "subject",
+            "has_header",
+            "missing_header",
+            "body_length",
+            "url",
+        ] {
+            assert_eq!(RuleType::parse(name).unwrap().as_str(), name);
+        }
+        assert!(RuleType::parse("Body").is_none());
+    }
+
+    #[test]
+    fn test_validate_rule() {
+        let rule = |rule_type, pattern: &str| SpamRule {
+            name: "TENANT_RULE".to_string(),
+            description: String::new(),
+            rule_type,
+            pattern: pattern.to_string(),
+            score: 1.0,
+            enabled: true,
+        };
+
+        assert!(rule(RuleType::Subject, r"(?i)invoice").validate().is_ok());
+        assert!(rule(RuleType::Body, "(unclosed").validate().is_err());
+        assert!(rule(RuleType::HasHeader, "X-Mailer").validate().is_ok());
+        assert!(rule(RuleType::MissingHeader, " ").validate().is_err());
+        assert!(rule(RuleType::BodyLength, "100").validate().is_ok());
+        assert!(rule(RuleType::BodyLength, "short").validate().is_err());
+    }
 }
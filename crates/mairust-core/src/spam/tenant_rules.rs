@@ -0,0 +1,98 @@
+//! Spam rules of a tenant
+//!
+//! Tenants add rules to the rule-based filter through the API. They are
+//! checked per recipient, after the message-wide check: a matching rule adds
+//! its score to the verdict of the recipients of its tenant. A rule named
+//! like a built-in rule replaces it when the verdict came from the built-in
+//! rules, so a tenant can change the score of a built-in rule or, with a
+//! disabled rule, turn it off.
+//!
+//! The rules of a tenant are cached for a minute.
+
+use super::rules::{RuleBasedFilter, RuleType, SpamRule};
+use mairust_common::types::TenantId;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::{SpamRuleRecord, SpamRuleRepository};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How long the rules of a tenant are cached
+const RULES_REFRESH: Duration = Duration::from_secs(60);
+
+/// Filter rule of a stored rule, or `None` when the record is invalid
+///
+/// Records are validated by the API; one written some other way is skipped.
+pub fn tenant_rule(record: &SpamRuleRecord) -> Option<SpamRule> {
+    let rule = SpamRule {
+        name: record.name.clone(),
+        description: record.description.clone().unwrap_or_default(),
+        rule_type: RuleType::parse(&record.scope)?,
+        pattern: record.pattern.clone(),
+        score: record.score,
+        enabled: record.enabled,
+    };
+    rule.validate().ok()?;
+    Some(rule)
+}
+
+/// Spam rules of the tenants, shared by the sessions of an SMTP server
+pub struct TenantSpamRules {
+    db_pool: DatabasePool,
+    cache: Mutex<HashMap<TenantId, (Arc<RuleBasedFilter>, Instant)>>,
+}
+
+impl TenantSpamRules {
+    /// Create a tenant rule cache
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self {
+            db_pool,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Rules of a tenant, reading them again when they are due
+    ///
+    /// A failed read keeps the cached rules, or uses none.
+    pub async fn rules(&self, tenant_id: TenantId) -> Arc<RuleBasedFilter> {
+        let cached = self.cache.lock().unwrap().get(&tenant_id).cloned();
+        if let Some((rules, loaded_at)) = &cached {
+            if loaded_at.elapsed() < RULES_REFRESH {
+                return rules.clone();
+            }
+        }
+
+        let rules = match SpamRuleRepository::new(self.db_pool.clone())
+            .list(tenant_id)
+            .await
+        {
+            Ok(records) => {
+                let mut rules = RuleBasedFilter::empty();
+                for record in &records {
+                    match tenant_rule(record) {
+                        Some(rule) => rules.add_rule(rule),
+                        None => warn!(
+                            "Skipping invalid spam rule {} of tenant {}",
+                            record.name, tenant_id
+                        ),
+                    }
+                }
+                Arc::new(rules)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to read the spam rules of tenant {}: {}",
+                    tenant_id, e
+                );
+                cached.map_or_else(|| Arc::new(RuleBasedFilter::empty()), |(rules, _)| rules)
+            }
+        };
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(tenant_id, (rules.clone(), Instant::now()));
+        rules
+    }
+}
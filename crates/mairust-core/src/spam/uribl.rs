@@ -0,0 +1,558 @@
+//! URI blocklist (URIBL/SURBL) lookups
+//!
+//! The links of a received message are looked up by the domain or address
+//! they point to: a link to `https://www.spam.example/offer` is listed in
+//! `multi.surbl.org` when `spam.example.multi.surbl.org` has an A record in
+//! 127.0.0.0/8, and a link to `http://192.0.2.1/` when
+//! `1.2.0.192.multi.surbl.org` has one. Domains are looked up by their
+//! registered domain, as the lists expect. The local bad-domain table is
+//! checked as well; its entries also cover their subdomains.
+//!
+//! Each list, and the bad-domain table, adds its score once to the spam
+//! score of a message, however many of its links are listed. Results are
+//! cached per domain.
+//!
+//! rspamd runs its own URL checks; enable these only for lists it does not
+//! query, or without rspamd.
+
+use super::dnsbl::{is_listing as is_dnsbl_listing, lookup_address, query_name, zone_symbol};
+use super::rules::{extract_urls, url_host};
+use super::SpamCheckResult;
+use mairust_common::config::{DnsblListConfig, UriblConfig};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::BadDomainRepository;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// How often the bad-domain table is read again
+const BAD_DOMAINS_REFRESH: Duration = Duration::from_secs(60);
+
+/// How long results with a failed lookup are cached
+const FAILURE_CACHE: Duration = Duration::from_secs(60);
+
+/// Cached results above which expired ones are dropped
+const MAX_CACHED: usize = 10_000;
+
+/// Symbol of links to a domain of the bad-domain table
+pub const BAD_DOMAIN_SYMBOL: &str = "URIBL_BAD_DOMAIN";
+
+/// Second-level labels under which country code TLDs register domains
+/// (`example.co.uk`)
+const SECOND_LEVEL_LABELS: &[&str] = &["ac", "co", "com", "edu", "gov", "ne", "net", "or", "org"];
+
+/// A list a link of the message is on
+#[derive(Debug, Clone, PartialEq)]
+pub struct UriblListing {
+    /// Zone of the list, `None` for the bad-domain table
+    pub zone: Option<String>,
+    /// Domain or address of the first listed link
+    pub domain: String,
+    /// Score of the list or table entry
+    pub score: f64,
+}
+
+impl UriblListing {
+    /// Spam symbol of the listing (e.g. `URIBL_MULTI_SURBL_ORG`)
+    pub fn symbol(&self) -> String {
+        match &self.zone {
+            Some(zone) => zone_symbol("URIBL", zone),
+            None => BAD_DOMAIN_SYMBOL.to_string(),
+        }
+    }
+}
+
+/// Lists the links of a message are on
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UriblResult {
+    pub listings: Vec<UriblListing>,
+}
+
+impl UriblResult {
+    /// Whether a link is on any list
+    pub fn is_listed(&self) -> bool {
+        !self.listings.is_empty()
+    }
+
+    /// Sum of the scores of the lists
+    pub fn score(&self) -> f64 {
+        self.listings.iter().map(|listing| listing.score).sum()
+    }
+
+    /// Add the listings to the spam verdict of the message
+    pub fn add_to(&self, spam: &mut SpamCheckResult) {
+        for listing in &self.listings {
+            spam.add_score(listing.symbol(), listing.score);
+        }
+    }
+}
+
+/// Canonical form of a bad domain: lowercase, without trailing dot
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    valid.then_some(domain)
+}
+
+/// Domain under which a host is registered
+///
+/// Without the public suffix list this is an approximation: the last two
+/// labels, or three under the usual second-level domains of country code
+/// TLDs (`example.co.uk`, `example.com.au`).
+pub(super) fn registered_domain(host: &str) -> &str {
+    let labels: Vec<&str> = host.rsplitn(4, '.').collect();
+    let count = match labels.as_slice() {
+        [tld, second, _, ..] if tld.len() == 2 && SECOND_LEVEL_LABELS.contains(second) => 3,
+        _ => 2,
+    };
+    if labels.len() <= count {
+        return host;
+    }
+    let suffix_len: usize = labels[..count].iter().map(|label| label.len() + 1).sum();
+    &host[host.len() + 1 - suffix_len..]
+}
+
+/// What a link host is looked up as in the lists: its registered domain,
+/// or its address when it is one. `None` for private addresses.
+fn lookup_target(host: &str) -> Option<String> {
+    let unbracketed = host.trim_start_matches('[').trim_end_matches(']');
+    match unbracketed.parse::<IpAddr>() {
+        Ok(ip) => lookup_address(ip).map(|ip| ip.to_string()),
+        Err(_) => Some(registered_domain(host).to_string()),
+    }
+}
+
+/// Name to query for a lookup target in `zone`
+fn target_query_name(target: &str, zone: &str) -> String {
+    match target.parse::<IpAddr>() {
+        Ok(ip) => query_name(ip, zone),
+        Err(_) => format!("{}.{}.", target, zone.trim_end_matches('.')),
+    }
+}
+
+/// Whether an answer of a list counts as a listing
+///
+/// URIBL answers 127.0.0.1 to queries it refuses.
+fn is_listing(code: Ipv4Addr, list: &DnsblListConfig) -> bool {
+    code != Ipv4Addr::new(127, 0, 0, 1) && is_dnsbl_listing(code, list)
+}
+
+/// Highest-scoring bad domain that one of the hosts is, or is a subdomain
+/// of
+fn bad_domain_of<'a>(hosts: &'a [String], bad_domains: &[(String, f64)]) -> Option<(&'a str, f64)> {
+    let mut best: Option<(&str, f64)> = None;
+    for host in hosts {
+        for (domain, score) in bad_domains {
+            let covered = host == domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'));
+            if covered && !best.is_some_and(|(_, best_score)| *score <= best_score) {
+                best = Some((host, *score));
+            }
+        }
+    }
+    best
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// Lists (by index) the target is on, with their answers
+    listed: Vec<(usize, Ipv4Addr)>,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct BadDomains {
+    /// Domains with their scores
+    entries: Vec<(String, f64)>,
+    loaded_at: Option<Instant>,
+}
+
+/// URI blocklist lookups, shared by the sessions of an SMTP server
+pub struct Uribl {
+    db_pool: DatabasePool,
+    config: UriblConfig,
+    resolver: TokioAsyncResolver,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+    bad_domains: Mutex<BadDomains>,
+}
+
+impl Uribl {
+    /// Create a URI blocklist checker
+    ///
+    /// Lookups go to the system's resolver: public resolvers are refused by
+    /// several lists.
+    pub fn new(db_pool: DatabasePool, config: UriblConfig) -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            if config.enabled {
+                warn!("Failed to read the system resolver configuration: {}", e);
+            }
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+
+        Self {
+            db_pool,
+            config,
+            resolver,
+            cache: Mutex::new(HashMap::new()),
+            bad_domains: Mutex::new(BadDomains::default()),
+        }
+    }
+
+    /// Whether links are looked up
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Lists the links of a message are on
+    pub async fn check(&self, raw_message: &[u8]) -> UriblResult {
+        if !self.enabled() {
+            return UriblResult::default();
+        }
+
+        let mut hosts: Vec<String> = Vec::new();
+        for host in extract_urls(raw_message)
+            .iter()
+            .filter_map(|url| url_host(url))
+        {
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        if hosts.is_empty() {
+            return UriblResult::default();
+        }
+
+        let mut targets: Vec<String> = Vec::new();
+        for target in hosts.iter().filter_map(|host| lookup_target(host)) {
+            if targets.len() >= self.config.max_domains {
+                break;
+            }
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+
+        // Each list counts once, for the first of the message's links on it
+        let listed = self.lookup(&targets).await;
+        let mut listings: Vec<UriblListing> = self
+            .config
+            .lists
+            .iter()
+            .enumerate()
+            .filter_map(|(index, list)| {
+                let target = targets.iter().find(|target| {
+                    listed
+                        .get(*target)
+                        .is_some_and(|lists| lists.iter().any(|(i, _)| *i == index))
+                })?;
+                Some(UriblListing {
+                    zone: Some(list.zone.clone()),
+                    domain: target.clone(),
+                    score: list.score,
+                })
+            })
+            .collect();
+
+        let bad_domains = self.bad_domains().await;
+        if let Some((host, score)) = bad_domain_of(&hosts, &bad_domains) {
+            listings.push(UriblListing {
+                zone: None,
+                domain: host.to_string(),
+                score,
+            });
+        }
+
+        let result = UriblResult { listings };
+        if result.is_listed() {
+            info!(
+                "Message links are listed in {} (score {:.1})",
+                result
+                    .listings
+                    .iter()
+                    .map(|listing| format!(
+                        "{} ({})",
+                        listing.zone.as_deref().unwrap_or("bad domains"),
+                        listing.domain
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                result.score()
+            );
+        }
+        result
+    }
+
+    /// Lists each target is on, from the cache when fresh; the targets
+    /// not in the cache are looked up in every list at once
+    async fn lookup(&self, targets: &[String]) -> HashMap<String, Vec<(usize, Ipv4Addr)>> {
+        let mut listed = HashMap::new();
+        if self.config.lists.is_empty() {
+            return listed;
+        }
+
+        let now = Instant::now();
+        let mut uncached = Vec::new();
+        {
+            let cache = self.cache.lock().unwrap();
+            for target in targets {
+                match cache.get(target) {
+                    Some(entry) if entry.expires_at > now => {
+                        listed.insert(target.clone(), entry.listed.clone());
+                    }
+                    _ => uncached.push(target.clone()),
+                }
+            }
+        }
+        if uncached.is_empty() {
+            return listed;
+        }
+
+        let timeout = Duration::from_secs(self.config.timeout_secs.max(1));
+        let mut lookups = JoinSet::new();
+        for target in &uncached {
+            for (index, list) in self.config.lists.iter().enumerate() {
+                let resolver = self.resolver.clone();
+                let name = target_query_name(target, &list.zone);
+                let target = target.clone();
+                lookups.spawn(async move {
+                    let answer = tokio::time::timeout(timeout, resolver.ipv4_lookup(name)).await;
+                    (target, index, answer)
+                });
+            }
+        }
+
+        let mut answers: HashMap<String, Vec<(usize, Ipv4Addr)>> = HashMap::new();
+        let mut failed: Vec<String> = Vec::new();
+        while let Some(joined) = lookups.join_next().await {
+            let Ok((target, index, answer)) = joined else {
+                continue;
+            };
+            let list = &self.config.lists[index];
+            match answer {
+                Ok(Ok(records)) => {
+                    let code = records
+                        .iter()
+                        .map(|record| record.0)
+                        .find(|code| is_listing(*code, list));
+                    if let Some(code) = code {
+                        debug!("{} is listed in {} ({})", target, list.zone, code);
+                        answers.entry(target).or_default().push((index, code));
+                    }
+                }
+                Ok(Err(e)) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {}
+                Ok(Err(e)) => {
+                    debug!("URIBL lookup of {} in {} failed: {}", target, list.zone, e);
+                    failed.push(target);
+                }
+                Err(_) => {
+                    debug!("URIBL lookup of {} in {} timed out", target, list.zone);
+                    failed.push(target);
+                }
+            }
+        }
+
+        let ttl = Duration::from_secs(self.config.cache_secs);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, entry| entry.expires_at > now);
+        }
+        for target in uncached {
+            let mut found = answers.remove(&target).unwrap_or_default();
+            found.sort_by_key(|(index, _)| *index);
+            let ttl = if failed.contains(&target) {
+                ttl.min(FAILURE_CACHE)
+            } else {
+                ttl
+            };
+            cache.insert(
+                target.clone(),
+                CacheEntry {
+                    listed: found.clone(),
+                    expires_at: now + ttl,
+                },
+            );
+            listed.insert(target, found);
+        }
+        listed
+    }
+
+    /// The bad-domain table with scores, reading it again when it is due
+    async fn bad_domains(&self) -> Vec<(String, f64)> {
+        let due = match self.bad_domains.lock().unwrap().loaded_at {
+            Some(loaded_at) => loaded_at.elapsed() >= BAD_DOMAINS_REFRESH,
+            None => true,
+        };
+        if due {
+            // A failed read keeps the previous table
+            let entries = match BadDomainRepository::new(self.db_pool.clone()).list().await {
+                Ok(entries) => Some(
+                    entries
+                        .into_iter()
+                        .map(|entry| {
+                            let score = entry.score.unwrap_or(self.config.bad_domain_score);
+                            (entry.domain, score)
+                        })
+                        .collect(),
+                ),
+                Err(e) => {
+                    warn!("Failed to read the bad-domain table: {}", e);
+                    None
+                }
+            };
+            let mut bad_domains = self.bad_domains.lock().unwrap();
+            if let Some(entries) = entries {
+                bad_domains.entries = entries;
+            }
+            bad_domains.loaded_at = Some(Instant::now());
+        }
+
+        self.bad_domains.lock().unwrap().entries.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registered_domain() {
+        assert_eq!(registered_domain("example.com"), "example.com");
+        assert_eq!(registered_domain("www.spam.example.com"), "example.com");
+        assert_eq!(registered_domain("shop.example.co.uk"), "example.co.uk");
+        assert_eq!(registered_domain("example.co.uk"), "example.co.uk");
+        assert_eq!(registered_domain("a.b.example.com.au"), "example.com.au");
+        // Only under two-letter TLDs
+        assert_eq!(registered_domain("mail.co.example"), "co.example");
+    }
+
+    #[test]
+    fn test_lookup_target() {
+        assert_eq!(
+            lookup_target("www.spam.example").as_deref(),
+            Some("spam.example")
+        );
+        assert_eq!(
+            lookup_target("198.51.100.7").as_deref(),
+            Some("198.51.100.7")
+        );
+        assert_eq!(
+            lookup_target("[2001:db8::1]").as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(lookup_target("10.0.0.1"), None);
+        assert_eq!(lookup_target("[::1]"), None);
+    }
+
+    #[test]
+    fn test_target_query_name() {
+        assert_eq!(
+            target_query_name("spam.example", "multi.surbl.org"),
+            "spam.example.multi.surbl.org."
+        );
+        assert_eq!(
+            target_query_name("198.51.100.7", "multi.surbl.org."),
+            "7.100.51.198.multi.surbl.org."
+        );
+    }
+
+    #[test]
+    fn test_is_listing() {
+        let list = DnsblListConfig {
+            zone: "multi.uribl.com".to_string(),
+            score: 5.0,
+            codes: Vec::new(),
+        };
+        assert!(is_listing(Ipv4Addr::new(127, 0, 0, 2), &list));
+        assert!(!is_listing(Ipv4Addr::new(127, 0, 0, 1), &list));
+        assert!(!is_listing(Ipv4Addr::new(127, 255, 255, 255), &list));
+    }
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(
+            normalize_domain(" Spam.Example. ").as_deref(),
+            Some("spam.example")
+        );
+        assert_eq!(normalize_domain("localhost"), None);
+        assert_eq!(normalize_domain("spam..example"), None);
+        assert_eq!(normalize_domain("-spam.example"), None);
+        assert_eq!(normalize_domain("https://spam.example/"), None);
+    }
+
+    #[test]
+    fn test_bad_domain_of() {
+        let bad_domains = vec![
+            ("spam.example".to_string(), 5.0),
+            ("phish.example".to_string(), 8.0),
+        ];
+        let hosts = vec!["www.spam.example".to_string()];
+        assert_eq!(
+            bad_domain_of(&hosts, &bad_domains),
+            Some(("www.spam.example", 5.0))
+        );
+
+        let hosts = vec![
+            "spam.example".to_string(),
+            "login.phish.example".to_string(),
+        ];
+        assert_eq!(
+            bad_domain_of(&hosts, &bad_domains),
+            Some(("login.phish.example", 8.0))
+        );
+
+        let hosts = vec![
+            "notspam.example".to_string(),
+            "spam.example.org".to_string(),
+        ];
+        assert_eq!(bad_domain_of(&hosts, &bad_domains), None);
+    }
+
+    #[test]
+    fn test_result() {
+        let result = UriblResult {
+            listings: vec![
+                UriblListing {
+                    zone: Some("multi.surbl.org".to_string()),
+                    domain: "spam.example".to_string(),
+                    score: 4.0,
+                },
+                UriblListing {
+                    zone: None,
+                    domain: "www.phish.example".to_string(),
+                    score: 3.0,
+                },
+            ],
+        };
+        assert!(result.is_listed());
+        assert_eq!(result.score(), 7.0);
+
+        let mut spam = SpamCheckResult::default();
+        result.add_to(&mut spam);
+        assert_eq!(spam.score, 7.0);
+        assert!(spam.is_spam);
+        assert_eq!(
+            spam.symbols,
+            vec!["URIBL_MULTI_SURBL_ORG", BAD_DOMAIN_SYMBOL]
+        );
+
+        assert!(!UriblResult::default().is_listed());
+    }
+}
@@ -0,0 +1,121 @@
+//! Subaddressing - `user+tag@domain` delivery (RFC 5233)
+//!
+//! Mail for `user+tag@example.com` is delivered to the mailbox of
+//! `user@example.com` when no mailbox has the full address. The tag is
+//! recorded in the message metadata (`metadata.subaddress`), and users who
+//! turn on `subaddress_filing` get the message filed into their folder of
+//! the same name.
+//!
+//! Settings:
+//! - `smtp.recipient_delimiter`: characters that start a subaddress
+//!   (default `+`); empty turns subaddressing off
+//! - user setting `subaddress_filing`: file by tag (default off)
+
+use crate::folders::{normalize_folder_path, SpecialUse};
+use anyhow::Result;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Mailbox;
+use mairust_storage::UserSettingsRepository;
+use uuid::Uuid;
+
+/// Split a local part into its base and its subaddress tag
+///
+/// The local part is split at the first delimiter character. `None` when
+/// it has none, or nothing before it.
+pub fn split_subaddress<'a>(local: &'a str, delimiters: &str) -> Option<(&'a str, &'a str)> {
+    let at = local.find(|c| delimiters.contains(c))?;
+    let delimiter_len = local[at..].chars().next()?.len_utf8();
+    (at > 0).then(|| (&local[..at], &local[at + delimiter_len..]))
+}
+
+/// Whether a folder can receive messages filed by tag
+///
+/// Special-use folders other than Archive are left out, so that a tag
+/// chosen by the sender cannot put mail into Trash, Junk, Sent or Drafts.
+fn is_filing_folder(special_use: Option<&str>, path: &str) -> bool {
+    matches!(
+        SpecialUse::of_folder(special_use, path),
+        None | Some(SpecialUse::Archive)
+    )
+}
+
+/// Files subaddressed mail into the folder named after its tag
+pub struct SubaddressFiler {
+    db_pool: DatabasePool,
+}
+
+impl SubaddressFiler {
+    /// Create a new subaddress filer
+    pub fn new(db_pool: DatabasePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Folder of the mailbox's owner to deliver a message tagged `tag` to
+    ///
+    /// `None` when the owner has filing off or has no folder of that name
+    /// (ignoring case). Folders are never created for a tag.
+    pub async fn folder(&self, mailbox: &Mailbox, tag: &str) -> Result<Option<Uuid>> {
+        let Some(user_id) = mailbox.user_id else {
+            return Ok(None);
+        };
+        let Some(path) = normalize_folder_path(tag) else {
+            return Ok(None);
+        };
+
+        let settings = UserSettingsRepository::new(self.db_pool.clone())
+            .get(mailbox.tenant_id, user_id)
+            .await?;
+        if !settings.subaddress_filing {
+            return Ok(None);
+        }
+
+        let folders: Vec<(Uuid, String, Option<String>)> = sqlx::query_as(
+            "SELECT id, folder_path, special_use FROM mailboxes
+             WHERE tenant_id = $1 AND user_id = $2 AND id <> $3
+               AND LOWER(folder_path) = LOWER($4)
+             ORDER BY folder_path = $4 DESC",
+        )
+        .bind(mailbox.tenant_id)
+        .bind(user_id)
+        .bind(mailbox.id)
+        .bind(&path)
+        .fetch_all(self.db_pool.pool())
+        .await?;
+
+        Ok(folders
+            .into_iter()
+            .find(|(_, path, special_use)| is_filing_folder(special_use.as_deref(), path))
+            .map(|(id, _, _)| id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_subaddress() {
+        assert_eq!(split_subaddress("user+news", "+"), Some(("user", "news")));
+        assert_eq!(
+            split_subaddress("user+lists+rust", "+"),
+            Some(("user", "lists+rust"))
+        );
+        assert_eq!(split_subaddress("user+", "+"), Some(("user", "")));
+        assert_eq!(
+            split_subaddress("first-last", "+-"),
+            Some(("first", "last"))
+        );
+        assert_eq!(split_subaddress("user", "+"), None);
+        assert_eq!(split_subaddress("+news", "+"), None);
+        assert_eq!(split_subaddress("user+news", ""), None);
+    }
+
+    #[test]
+    fn test_is_filing_folder() {
+        assert!(is_filing_folder(None, "Newsletters"));
+        assert!(is_filing_folder(Some("archive"), "Archive"));
+        assert!(!is_filing_folder(Some("trash"), "Trash"));
+        assert!(!is_filing_folder(None, "Junk"));
+        assert!(!is_filing_folder(None, "送信済み"));
+    }
+}
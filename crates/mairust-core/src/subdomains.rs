@@ -0,0 +1,224 @@
+//! Subdomains of tenant domains
+//!
+//! A domain's settings can extend it to its subdomains. Domain settings
+//! (`extra_settings.subdomains`):
+//!
+//! ```json
+//! {
+//!   "sign": true,
+//!   "signing_domain": "parent",
+//!   "selectors": {"news.example.com": "news"},
+//!   "accept_mail": true
+//! }
+//! ```
+//!
+//! - `sign`: DKIM-sign mail from subdomain addresses with the domain's key.
+//! - `signing_domain`: `parent` (the default) signs with `d=` the domain
+//!   itself, which DMARC's relaxed alignment accepts for its subdomains;
+//!   `subdomain` signs with `d=` the sender's subdomain, which needs the
+//!   public key published under that subdomain.
+//! - `selectors`: selectors used for particular subdomains instead of the
+//!   domain's own; each needs its own DNS record.
+//! - `accept_mail`: accept mail for addresses at subdomains. It is delivered
+//!   to the mailbox with the same local part at the domain.
+//!
+//! A subdomain that is a domain of its own is never covered by its parent.
+
+use anyhow::Result;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::models::Domain;
+use mairust_storage::repository::{
+    DomainRepository, DomainSettingsRepository, DomainSettingsRepositoryTrait,
+};
+use std::collections::HashMap;
+
+/// Which domain subdomain mail is signed for (`d=`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SigningDomain {
+    /// The parent domain
+    #[default]
+    Parent,
+    /// The sender's subdomain
+    Subdomain,
+}
+
+impl SigningDomain {
+    /// Parse a `signing_domain` setting
+    pub fn from_setting(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "parent" => Some(SigningDomain::Parent),
+            "subdomain" => Some(SigningDomain::Subdomain),
+            _ => None,
+        }
+    }
+}
+
+/// Subdomain settings of a domain
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SubdomainPolicy {
+    /// Sign mail from subdomain addresses
+    pub sign: bool,
+    /// Domain the signatures are made for
+    pub signing_domain: SigningDomain,
+    /// Selectors of particular subdomains, by lowercased subdomain
+    pub selectors: HashMap<String, String>,
+    /// Accept mail for subdomain addresses
+    pub accept_mail: bool,
+}
+
+impl SubdomainPolicy {
+    /// Read `subdomains` from domain settings; `None` when subdomains are
+    /// neither signed nor accepted
+    pub fn from_domain_settings(extra_settings: &serde_json::Value) -> Option<Self> {
+        let subdomains = extra_settings.get("subdomains")?;
+        let flag = |name: &str| {
+            subdomains
+                .get(name)
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false)
+        };
+        let policy = Self {
+            sign: flag("sign"),
+            signing_domain: subdomains
+                .get("signing_domain")
+                .and_then(|v| v.as_str())
+                .and_then(SigningDomain::from_setting)
+                .unwrap_or_default(),
+            selectors: subdomains
+                .get("selectors")
+                .and_then(|v| v.as_object())
+                .map(|selectors| {
+                    selectors
+                        .iter()
+                        .filter_map(|(name, selector)| {
+                            let selector = selector.as_str()?.trim();
+                            (!selector.is_empty())
+                                .then(|| (name.trim().to_lowercase(), selector.to_string()))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            accept_mail: flag("accept_mail"),
+        };
+        (policy.sign || policy.accept_mail).then_some(policy)
+    }
+
+    /// `d=` and `s=` for mail from `subdomain` of `parent`, whose own
+    /// selector is `selector`
+    pub fn signing_tags(&self, parent: &str, selector: &str, subdomain: &str) -> (String, String) {
+        let subdomain = subdomain.to_lowercase();
+        let selector = self
+            .selectors
+            .get(&subdomain)
+            .map(String::as_str)
+            .unwrap_or(selector)
+            .to_string();
+        match self.signing_domain {
+            SigningDomain::Parent => (parent.to_lowercase(), selector),
+            SigningDomain::Subdomain => (subdomain, selector),
+        }
+    }
+}
+
+/// Parent domains of a domain name, nearest first
+pub fn parent_domains(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices('.')
+        .map(move |(i, _)| &name[i + 1..])
+        .filter(|parent| !parent.is_empty())
+}
+
+/// The nearest tenant domain above a domain name, with its subdomain
+/// policy
+///
+/// Returns `None` when no parent is a tenant domain, or when the nearest
+/// one does not extend to its subdomains.
+pub async fn find_parent_domain(
+    db_pool: &DatabasePool,
+    name: &str,
+) -> Result<Option<(Domain, SubdomainPolicy)>> {
+    let domains = DomainRepository::new(db_pool.clone());
+    for parent in parent_domains(name) {
+        let Some(domain) = domains.find_by_name(parent).await? else {
+            continue;
+        };
+        let policy = DomainSettingsRepository::new(db_pool.clone())
+            .get(domain.id)
+            .await?
+            .and_then(|settings| SubdomainPolicy::from_domain_settings(&settings.extra_settings));
+        return Ok(policy.map(|policy| (domain, policy)));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_domain_settings() {
+        assert_eq!(
+            SubdomainPolicy::from_domain_settings(&serde_json::json!({})),
+            None
+        );
+        assert_eq!(
+            SubdomainPolicy::from_domain_settings(&serde_json::json!({
+                "subdomains": {"sign": false, "selectors": {"a.example.com": "a"}}
+            })),
+            None
+        );
+
+        let policy = SubdomainPolicy::from_domain_settings(&serde_json::json!({
+            "subdomains": {
+                "sign": true,
+                "signing_domain": "Subdomain",
+                "selectors": {"News.Example.com": "news", "blank.example.com": " "}
+            }
+        }))
+        .unwrap();
+        assert!(policy.sign);
+        assert!(!policy.accept_mail);
+        assert_eq!(policy.signing_domain, SigningDomain::Subdomain);
+        assert_eq!(policy.selectors.len(), 1);
+        assert_eq!(policy.selectors["news.example.com"], "news");
+
+        let policy = SubdomainPolicy::from_domain_settings(&serde_json::json!({
+            "subdomains": {"accept_mail": true, "signing_domain": "other"}
+        }))
+        .unwrap();
+        assert!(policy.accept_mail);
+        assert!(!policy.sign);
+        assert_eq!(policy.signing_domain, SigningDomain::Parent);
+    }
+
+    #[test]
+    fn test_signing_tags() {
+        let mut policy = SubdomainPolicy {
+            sign: true,
+            selectors: HashMap::from([("news.example.com".to_string(), "news".to_string())]),
+            ..SubdomainPolicy::default()
+        };
+        assert_eq!(
+            policy.signing_tags("example.com", "mairust", "News.example.com"),
+            ("example.com".to_string(), "news".to_string())
+        );
+        assert_eq!(
+            policy.signing_tags("example.com", "mairust", "shop.example.com"),
+            ("example.com".to_string(), "mairust".to_string())
+        );
+
+        policy.signing_domain = SigningDomain::Subdomain;
+        assert_eq!(
+            policy.signing_tags("example.com", "mairust", "shop.example.com"),
+            ("shop.example.com".to_string(), "mairust".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parent_domains() {
+        assert_eq!(
+            parent_domains("a.b.example.com").collect::<Vec<_>>(),
+            vec!["b.example.com", "example.com", "com"]
+        );
+        assert_eq!(parent_domains("localhost").count(), 0);
+    }
+}
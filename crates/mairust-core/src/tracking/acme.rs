@@ -0,0 +1,206 @@
+//! Tracking domain verification and certificates
+//!
+//! The worker checks the CNAME of new tracking domains and requests
+//! certificates of verified ones over ACME (RFC 8555). The HTTP-01
+//! challenges are stored in the database and answered by the web server on
+//! `/.well-known/acme-challenge/<token>`, so any node behind the tracking
+//! domain can answer them. Certificates are renewed after
+//! `renew_after_days`.
+
+use super::domains::CnameChecker;
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus,
+};
+use mairust_common::config::TrackingConfig;
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::TrackingDomainRepository;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Longest wait between two polls of an order
+const MAX_POLL_DELAY: Duration = Duration::from_secs(32);
+
+/// Verifies tracking domains and keeps their certificates
+pub struct AcmeWorker {
+    db_pool: DatabasePool,
+    config: TrackingConfig,
+    checker: CnameChecker,
+}
+
+impl AcmeWorker {
+    /// Create a worker; `hostname` is the CNAME target when the
+    /// configuration has none
+    pub fn new(db_pool: DatabasePool, config: TrackingConfig, hostname: &str) -> Self {
+        let checker = CnameChecker::new(config.cname_target(hostname));
+        Self {
+            db_pool,
+            config,
+            checker,
+        }
+    }
+
+    /// Run until the task is aborted
+    pub async fn run(&self) {
+        let interval = Duration::from_secs(self.config.acme.check_interval_secs.max(60));
+        loop {
+            if let Err(e) = self.run_once().await {
+                warn!("Tracking domain check failed: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Verify pending domains and order missing and expiring certificates
+    pub async fn run_once(&self) -> Result<()> {
+        let repo = TrackingDomainRepository::new(self.db_pool.clone());
+
+        for domain in repo.list_unverified().await? {
+            match self.checker.check(&domain.domain).await {
+                Ok(()) => {
+                    repo.mark_verified(domain.id).await?;
+                    info!("Tracking domain {} verified", domain.domain);
+                }
+                Err(e) => {
+                    debug!("Tracking domain {} not verified: {}", domain.domain, e);
+                    repo.record_check_error(domain.id, &e).await?;
+                }
+            }
+        }
+
+        if self.config.acme.enabled {
+            let issued_before =
+                Utc::now() - chrono::Duration::days(self.config.acme.renew_after_days);
+            let domains = repo.list_needing_certificate(issued_before).await?;
+            if !domains.is_empty() {
+                let account = self.account(&repo).await?;
+                for domain in domains {
+                    let result = self
+                        .order_certificate(&account, &repo, &domain.domain)
+                        .await;
+                    repo.delete_challenges(&domain.domain).await?;
+                    match result {
+                        Ok((certificate_pem, private_key_pem)) => {
+                            repo.store_certificate(domain.id, &certificate_pem, &private_key_pem)
+                                .await?;
+                            info!("Certificate of tracking domain {} issued", domain.domain);
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Certificate order of tracking domain {} failed: {}",
+                                domain.domain, e
+                            );
+                            repo.mark_failed(domain.id, &e.to_string()).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The ACME account at the directory, created on first use
+    async fn account(&self, repo: &TrackingDomainRepository) -> Result<Account> {
+        let directory_url = &self.config.acme.directory_url;
+        if let Some(credentials) = repo.find_account(directory_url).await? {
+            let credentials: AccountCredentials = serde_json::from_value(credentials)?;
+            return Ok(Account::from_credentials(credentials).await?);
+        }
+
+        let contact = self
+            .config
+            .acme
+            .contact_email
+            .as_ref()
+            .map(|email| format!("mailto:{}", email));
+        let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            directory_url,
+            None,
+        )
+        .await?;
+        repo.store_account(directory_url, &serde_json::to_value(&credentials)?)
+            .await?;
+        info!("Created ACME account at {}", directory_url);
+        Ok(account)
+    }
+
+    /// Order a certificate of a domain over HTTP-01; returns the PEM
+    /// certificate chain and private key
+    async fn order_certificate(
+        &self,
+        account: &Account,
+        repo: &TrackingDomainRepository,
+        domain: &str,
+    ) -> Result<(String, String)> {
+        let identifiers = [Identifier::Dns(domain.to_string())];
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await?;
+
+        let mut ready = Vec::new();
+        for authorization in order.authorizations().await? {
+            match authorization.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => bail!("authorization is {:?}", status),
+            }
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|challenge| challenge.r#type == ChallengeType::Http01)
+                .ok_or_else(|| anyhow!("no HTTP-01 challenge offered"))?;
+            let key_authorization = order.key_authorization(challenge);
+            repo.put_challenge(&challenge.token, key_authorization.as_str(), domain)
+                .await?;
+            ready.push(challenge.url.clone());
+        }
+        for url in &ready {
+            order.set_challenge_ready(url).await?;
+        }
+
+        let mut delay = Duration::from_secs(1);
+        loop {
+            tokio::time::sleep(delay).await;
+            let state = order.refresh().await?;
+            match state.status {
+                OrderStatus::Ready => break,
+                OrderStatus::Invalid => match state.error {
+                    Some(ref problem) => bail!("order is invalid: {}", problem),
+                    None => bail!("order is invalid"),
+                },
+                OrderStatus::Valid => bail!("order was already finalized"),
+                _ if delay < MAX_POLL_DELAY => delay *= 2,
+                _ => bail!("challenges were not validated in time"),
+            }
+        }
+
+        let key_pair = rcgen::KeyPair::generate()?;
+        let mut params = rcgen::CertificateParams::new(vec![domain.to_string()])?;
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let csr = params.serialize_request(&key_pair)?;
+        order.finalize(csr.der()).await?;
+
+        let mut delay = Duration::from_secs(1);
+        loop {
+            if let Some(certificate_pem) = order.certificate().await? {
+                return Ok((certificate_pem, key_pair.serialize_pem()));
+            }
+            if delay >= MAX_POLL_DELAY {
+                bail!("certificate was not issued in time");
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
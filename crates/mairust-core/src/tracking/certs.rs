@@ -0,0 +1,168 @@
+//! Certificates of tracking domains
+//!
+//! The HTTPS listener of tracking domains picks the certificate by the SNI
+//! name of the client. Certificates are loaded from the database, where the
+//! ACME worker stores them, and reloaded periodically, so that every node
+//! serves the certificates ordered by any of them.
+
+use anyhow::{anyhow, Result};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::repository::{TrackingDomain, TrackingDomainRepository};
+use rustls::pki_types::CertificateDer;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use rustls_pemfile::{certs, private_key};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+/// Certificates of tracking domains, by domain
+#[derive(Debug, Clone, Default)]
+pub struct TrackingCertificates {
+    keys: Arc<RwLock<HashMap<String, Arc<CertifiedKey>>>>,
+}
+
+impl TrackingCertificates {
+    /// Create an empty set of certificates
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reload the certificates every `interval` until the task is aborted
+    pub async fn run(self, db_pool: DatabasePool, interval: Duration) {
+        loop {
+            if let Err(e) = self.reload(&db_pool).await {
+                warn!("Failed to load tracking domain certificates: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Replace the certificates with those stored in the database
+    pub async fn reload(&self, db_pool: &DatabasePool) -> Result<()> {
+        let domains = TrackingDomainRepository::new(db_pool.clone())
+            .list_with_certificates()
+            .await?;
+        self.load(&domains);
+        Ok(())
+    }
+
+    /// Replace the certificates with those of `domains`
+    pub fn load(&self, domains: &[TrackingDomain]) {
+        let mut keys = HashMap::new();
+        for domain in domains {
+            let (Some(certificate), Some(private_key)) =
+                (&domain.certificate_pem, &domain.private_key_pem)
+            else {
+                continue;
+            };
+            match certified_key(certificate, private_key) {
+                Ok(key) => {
+                    keys.insert(domain.domain.to_lowercase(), Arc::new(key));
+                }
+                Err(e) => warn!(
+                    "Invalid certificate of tracking domain {}: {}",
+                    domain.domain, e
+                ),
+            }
+        }
+        let count = keys.len();
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = keys;
+        debug!("Loaded {} tracking domain certificate(s)", count);
+    }
+
+    /// Whether a domain has a certificate
+    pub fn contains(&self, domain: &str) -> bool {
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&domain.to_lowercase())
+    }
+
+    /// TLS acceptor of the HTTPS listener
+    pub fn acceptor(&self) -> TlsAcceptor {
+        let mut server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(self.clone()));
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(server_config))
+    }
+}
+
+impl ResolvesServerCert for TrackingCertificates {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name()?.to_lowercase();
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&name)
+            .cloned()
+    }
+}
+
+/// Certificate chain and key of PEM files
+fn certified_key(certificate_pem: &str, private_key_pem: &str) -> Result<CertifiedKey> {
+    let chain: Vec<CertificateDer<'static>> = certs(&mut certificate_pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Failed to parse certificates: {}", e))?;
+    if chain.is_empty() {
+        return Err(anyhow!("No certificates found"));
+    }
+    let key = private_key(&mut private_key_pem.as_bytes())
+        .map_err(|e| anyhow!("Failed to read private key: {}", e))?
+        .ok_or_else(|| anyhow!("No private key found"))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| anyhow!("Unsupported private key: {}", e))?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn tracking_domain(
+        domain: &str,
+        certificate_pem: &str,
+        private_key_pem: &str,
+    ) -> TrackingDomain {
+        TrackingDomain {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            domain: domain.to_string(),
+            status: "active".to_string(),
+            verified_at: Some(Utc::now()),
+            certificate_pem: Some(certificate_pem.to_string()),
+            private_key_pem: Some(private_key_pem.to_string()),
+            certificate_issued_at: Some(Utc::now()),
+            last_error: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let certificate = rcgen::CertificateParams::new(vec!["links.example.com".to_string()])
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+
+        let certificates = TrackingCertificates::new();
+        certificates.load(&[
+            tracking_domain(
+                "Links.example.com",
+                &certificate.pem(),
+                &key_pair.serialize_pem(),
+            ),
+            tracking_domain("broken.example.com", "not a certificate", "not a key"),
+        ]);
+        assert!(certificates.contains("links.example.com"));
+        assert!(!certificates.contains("broken.example.com"));
+    }
+}
@@ -0,0 +1,241 @@
+//! Tracked links
+//!
+//! Links in the HTML of campaign mail are rewritten to `<base>/c/<token>`.
+//! The token carries the campaign, the recipient and the original URL, and
+//! is signed with HMAC-SHA256 so that the redirect cannot be turned into an
+//! open redirect. The web server counts the click and redirects to the
+//! original URL.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use mairust_common::config::TrackingConfig;
+use regex::{Captures, Regex};
+use sha2::Sha256;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes of the MAC kept in a token
+const MAC_LENGTH: usize = 16;
+
+/// Where a tracked link leads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClickTarget {
+    pub campaign_id: Uuid,
+    pub recipient_id: Uuid,
+    pub url: String,
+}
+
+/// Signs and checks tracked links
+#[derive(Debug, Clone)]
+pub struct ClickTracker {
+    secrets: Vec<String>,
+}
+
+impl ClickTracker {
+    /// Create a tracker; the first secret signs new links, and every secret
+    /// is accepted when checking them
+    pub fn new(secrets: Vec<String>) -> Self {
+        Self { secrets }
+    }
+
+    /// Tracker of the `[tracking]` configuration, or `None` when click
+    /// tracking is disabled or has no secret
+    pub fn from_config(config: &TrackingConfig) -> Option<Self> {
+        if !config.enabled || config.secrets.is_empty() {
+            return None;
+        }
+        Some(Self::new(config.secrets.clone()))
+    }
+
+    /// Token of a tracked link
+    pub fn token(&self, target: &ClickTarget) -> String {
+        let payload = payload(target);
+        let mac = mac(&self.secrets[0], &payload);
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(&mac[..MAC_LENGTH])
+        )
+    }
+
+    /// Target of a token, or `None` when it was not signed by this server
+    pub fn verify(&self, token: &str) -> Option<ClickTarget> {
+        let (payload, tag) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        if tag.len() != MAC_LENGTH {
+            return None;
+        }
+        let signed = self.secrets.iter().any(|secret| {
+            let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(&payload);
+            mac.verify_truncated_left(&tag).is_ok()
+        });
+        if !signed || payload.len() < 32 {
+            return None;
+        }
+
+        let url = String::from_utf8(payload[32..].to_vec()).ok()?;
+        if !is_web_url(&url) {
+            return None;
+        }
+        Some(ClickTarget {
+            campaign_id: Uuid::from_slice(&payload[..16]).ok()?,
+            recipient_id: Uuid::from_slice(&payload[16..32]).ok()?,
+            url,
+        })
+    }
+
+    /// The HTML with its web links replaced by tracked links on `base_url`
+    ///
+    /// Links starting with one of `untracked` (such as the unsubscribe
+    /// link) are kept as they are.
+    pub fn rewrite_html(
+        &self,
+        html: &str,
+        base_url: &str,
+        campaign_id: Uuid,
+        recipient_id: Uuid,
+        untracked: &[&str],
+    ) -> String {
+        static HREF: OnceLock<Regex> = OnceLock::new();
+        let href = HREF.get_or_init(|| {
+            Regex::new(r#"(?i)\bhref\s*=\s*(?:"(https?://[^"]*)"|'(https?://[^']*)')"#)
+                .expect("valid regex")
+        });
+        let base_url = base_url.trim_end_matches('/');
+
+        href.replace_all(html, |captures: &Captures| {
+            let quoted = captures
+                .get(1)
+                .or_else(|| captures.get(2))
+                .map_or("", |m| m.as_str());
+            // Attribute values are HTML-escaped
+            let url = quoted.replace("&amp;", "&");
+            if untracked.iter().any(|prefix| url.starts_with(prefix)) {
+                return captures[0].to_string();
+            }
+            let token = self.token(&ClickTarget {
+                campaign_id,
+                recipient_id,
+                url,
+            });
+            format!("href=\"{}/c/{}\"", base_url, token)
+        })
+        .into_owned()
+    }
+}
+
+/// Signed bytes of a target: campaign, recipient, then the URL
+fn payload(target: &ClickTarget) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(32 + target.url.len());
+    payload.extend_from_slice(target.campaign_id.as_bytes());
+    payload.extend_from_slice(target.recipient_id.as_bytes());
+    payload.extend_from_slice(target.url.as_bytes());
+    payload
+}
+
+fn mac(secret: &str, payload: &[u8]) -> Vec<u8> {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Whether a URL may be redirected to
+fn is_web_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> ClickTarget {
+        ClickTarget {
+            campaign_id: Uuid::from_u128(1),
+            recipient_id: Uuid::from_u128(2),
+            url: "https://shop.example.com/sale?ref=mail&id=7".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token_roundtrip() {
+        let tracker = ClickTracker::new(vec!["secret".to_string()]);
+        let token = tracker.token(&target());
+        assert!(!token.contains('/'));
+        assert_eq!(tracker.verify(&token), Some(target()));
+    }
+
+    #[test]
+    fn test_token_forgery() {
+        let tracker = ClickTracker::new(vec!["secret".to_string()]);
+        let token = tracker.token(&target());
+
+        // Signed with another secret
+        let other = ClickTracker::new(vec!["other".to_string()]);
+        assert_eq!(other.verify(&token), None);
+
+        // Another URL with the same MAC
+        let (_, tag) = token.split_once('.').unwrap();
+        let forged = ClickTarget {
+            url: "https://evil.example.net/".to_string(),
+            ..target()
+        };
+        let payload = URL_SAFE_NO_PAD.encode(payload(&forged));
+        assert_eq!(tracker.verify(&format!("{}.{}", payload, tag)), None);
+        assert_eq!(tracker.verify("garbage"), None);
+    }
+
+    #[test]
+    fn test_secret_rotation() {
+        let old = ClickTracker::new(vec!["old".to_string()]);
+        let rotated = ClickTracker::new(vec!["new".to_string(), "old".to_string()]);
+        assert_eq!(rotated.verify(&old.token(&target())), Some(target()));
+    }
+
+    #[test]
+    fn test_rewrite_html() {
+        let tracker = ClickTracker::new(vec!["secret".to_string()]);
+        let html = r#"<a href="https://shop.example.com/sale?ref=mail&amp;id=7">Sale</a>
+<a HREF='http://blog.example.com/'>Blog</a>
+<a href="mailto:help@example.com">Help</a>
+<a href="https://mail.example.com/unsubscribe/abc">Unsubscribe</a>"#;
+        let rewritten = tracker.rewrite_html(
+            html,
+            "https://links.example.com/",
+            Uuid::from_u128(1),
+            Uuid::from_u128(2),
+            &["https://mail.example.com/unsubscribe"],
+        );
+
+        let links: Vec<&str> = rewritten
+            .split("href=\"https://links.example.com/c/")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect();
+        assert_eq!(links.len(), 2);
+        assert_eq!(tracker.verify(links[0]), Some(target()));
+        assert_eq!(
+            tracker.verify(links[1]).unwrap().url,
+            "http://blog.example.com/"
+        );
+        assert!(rewritten.contains(r#"href="mailto:help@example.com""#));
+        assert!(rewritten.contains(r#"href="https://mail.example.com/unsubscribe/abc""#));
+    }
+
+    #[test]
+    fn test_from_config() {
+        let mut config = TrackingConfig::default();
+        assert!(ClickTracker::from_config(&config).is_none());
+        config.enabled = true;
+        assert!(ClickTracker::from_config(&config).is_none());
+        config.secrets = vec!["secret".to_string()];
+        assert!(ClickTracker::from_config(&config).is_some());
+    }
+}
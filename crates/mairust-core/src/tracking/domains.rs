@@ -0,0 +1,127 @@
+//! Tracking domains of tenants
+//!
+//! A tracking domain serves the links of campaigns sent from a domain with
+//! the same organizational domain, so that the links match the domain that
+//! SPF and DKIM authenticate. It is verified once its CNAME points at the
+//! server.
+
+use crate::email_auth::dmarc::get_organizational_domain;
+use mairust_storage::repository::TrackingDomain;
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::proto::rr::RecordType;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Whether two domains have the same organizational domain
+pub fn is_aligned(tracking_domain: &str, domain: &str) -> bool {
+    let organizational = |domain: &str| {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        get_organizational_domain(&domain).unwrap_or(domain)
+    };
+    organizational(tracking_domain) == organizational(domain)
+}
+
+/// Base URL of the tracked links of mail from `from_domain`: the first
+/// active tracking domain aligned with it, else `default_url`
+pub fn link_base_url(
+    domains: &[TrackingDomain],
+    from_domain: &str,
+    default_url: Option<&str>,
+) -> Option<String> {
+    domains
+        .iter()
+        .find(|domain| domain.is_active() && is_aligned(&domain.domain, from_domain))
+        .map(|domain| format!("https://{}", domain.domain))
+        .or_else(|| default_url.map(|url| url.trim_end_matches('/').to_string()))
+}
+
+/// Checks that tracking domains point at the server
+pub struct CnameChecker {
+    resolver: TokioAsyncResolver,
+    target: String,
+}
+
+impl CnameChecker {
+    /// Create a checker of CNAMEs to `target`
+    pub fn new(target: &str) -> Self {
+        Self {
+            resolver: TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()),
+            target: target.trim_end_matches('.').to_lowercase(),
+        }
+    }
+
+    /// Whether `domain` has a CNAME to the server; the error says what the
+    /// tenant has to fix
+    pub async fn check(&self, domain: &str) -> Result<(), String> {
+        let lookup = self
+            .resolver
+            .lookup(format!("{}.", domain), RecordType::CNAME)
+            .await
+            .map_err(|e| format!("CNAME lookup of {} failed: {}", domain, e))?;
+        let names: Vec<String> = lookup
+            .iter()
+            .filter_map(|rdata| rdata.as_cname())
+            .map(|name| name.0.to_utf8().trim_end_matches('.').to_lowercase())
+            .collect();
+        if names.contains(&self.target) {
+            return Ok(());
+        }
+        if names.is_empty() {
+            return Err(format!("{} has no CNAME record", domain));
+        }
+        Err(format!(
+            "{} has a CNAME to {} instead of {}",
+            domain,
+            names.join(", "),
+            self.target
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn tracking_domain(domain: &str, status: &str) -> TrackingDomain {
+        TrackingDomain {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            domain: domain.to_string(),
+            status: status.to_string(),
+            verified_at: None,
+            certificate_pem: None,
+            private_key_pem: None,
+            certificate_issued_at: None,
+            last_error: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_is_aligned() {
+        assert!(is_aligned("links.example.com", "example.com"));
+        assert!(is_aligned("links.example.com", "news.example.com"));
+        assert!(is_aligned("Links.Example.com.", "example.com"));
+        assert!(!is_aligned("links.example.net", "example.com"));
+    }
+
+    #[test]
+    fn test_link_base_url() {
+        let domains = vec![
+            tracking_domain("go.example.org", "active"),
+            tracking_domain("links.example.com", "verified"),
+            tracking_domain("click.example.com", "active"),
+        ];
+        assert_eq!(
+            link_base_url(&domains, "news.example.com", None).as_deref(),
+            Some("https://click.example.com")
+        );
+        assert_eq!(
+            link_base_url(&domains, "example.net", Some("https://mail.example.net/")).as_deref(),
+            Some("https://mail.example.net")
+        );
+        assert_eq!(link_base_url(&domains, "example.net", None), None);
+    }
+}
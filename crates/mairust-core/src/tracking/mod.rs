@@ -0,0 +1,16 @@
+//! Click tracking of campaign mail
+//!
+//! Links of campaign mail are rewritten to signed links on the web server,
+//! which counts the click and redirects. Tenants can serve the links from
+//! their own tracking domains, verified by CNAME and given certificates
+//! over ACME.
+
+mod acme;
+mod certs;
+mod click;
+mod domains;
+
+pub use acme::AcmeWorker;
+pub use certs::TrackingCertificates;
+pub use click::{ClickTarget, ClickTracker};
+pub use domains::{is_aligned, link_base_url, CnameChecker};
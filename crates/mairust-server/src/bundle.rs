@@ -0,0 +1,182 @@
+//! `mairust bundle` subcommand
+//!
+//! Exports and imports tenant configuration bundles from the command line,
+//! for use in deployment pipelines.
+
+use anyhow::{anyhow, bail, Context, Result};
+use mairust_common::config::Config;
+use mairust_core::{BundleFormat, ChangeAction, ConfigBundle, ConfigBundleManager, ImportOptions};
+use mairust_storage::db::DatabasePool;
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+const USAGE: &str = "\
+Usage:
+  mairust bundle export --tenant <id> [--format json|yaml] [--output <file>]
+  mairust bundle import --tenant <id> [--format json|yaml] [--dry-run] [--prune] <file>
+
+Use '-' as the file to read from stdin or write to stdout (the export default).
+The format defaults to the file extension, then JSON.";
+
+/// Parsed command line of the subcommand
+struct BundleArgs {
+    command: String,
+    tenant_id: Uuid,
+    format: Option<BundleFormat>,
+    file: Option<String>,
+    dry_run: bool,
+    prune: bool,
+}
+
+impl BundleArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut iter = args.iter();
+        let command = iter.next().cloned().ok_or_else(|| anyhow!(USAGE))?;
+        if command != "export" && command != "import" {
+            bail!("Unknown bundle command '{}'\n\n{}", command, USAGE);
+        }
+
+        let mut tenant_id = None;
+        let mut format = None;
+        let mut file = None;
+        let mut dry_run = false;
+        let mut prune = false;
+
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--tenant" => {
+                    let value = value()?;
+                    tenant_id = Some(
+                        Uuid::parse_str(&value)
+                            .with_context(|| format!("Invalid tenant ID '{}'", value))?,
+                    );
+                }
+                "--format" => {
+                    format = Some(match value()?.to_lowercase().as_str() {
+                        "json" => BundleFormat::Json,
+                        "yaml" | "yml" => BundleFormat::Yaml,
+                        other => bail!("Unknown format '{}'", other),
+                    });
+                }
+                "--output" if command == "export" => file = Some(value()?),
+                "--dry-run" if command == "import" => dry_run = true,
+                "--prune" if command == "import" => prune = true,
+                "-h" | "--help" => bail!(USAGE),
+                other if command == "import" && file.is_none() && !other.starts_with("--") => {
+                    file = Some(other.to_string());
+                }
+                other => bail!("Unexpected argument '{}'\n\n{}", other, USAGE),
+            }
+        }
+
+        let tenant_id = tenant_id.ok_or_else(|| anyhow!("--tenant is required\n\n{}", USAGE))?;
+        if command == "import" && file.is_none() {
+            bail!("No bundle file given\n\n{}", USAGE);
+        }
+
+        Ok(Self {
+            command,
+            tenant_id,
+            format,
+            file,
+            dry_run,
+            prune,
+        })
+    }
+
+    /// Format given explicitly or implied by the file name
+    fn format(&self) -> BundleFormat {
+        self.format.unwrap_or_else(|| match self.file.as_deref() {
+            Some(file) if file != "-" => BundleFormat::from_path(file),
+            _ => BundleFormat::Json,
+        })
+    }
+}
+
+/// Run `mairust bundle <args>`
+pub async fn run(args: &[String]) -> Result<()> {
+    let args = BundleArgs::parse(args)?;
+
+    let config = Config::load()?;
+    let db_pool = DatabasePool::new(&config.database).await?;
+    let manager = ConfigBundleManager::new(db_pool);
+
+    if args.command == "export" {
+        export(&manager, &args).await
+    } else {
+        import(&manager, &args).await
+    }
+}
+
+async fn export(manager: &ConfigBundleManager, args: &BundleArgs) -> Result<()> {
+    let bundle = manager.export(args.tenant_id).await?;
+    let rendered = bundle.render(args.format())?;
+
+    match args.file.as_deref() {
+        Some(file) if file != "-" => {
+            std::fs::write(file, rendered).with_context(|| format!("Failed to write {}", file))?;
+            eprintln!(
+                "Exported {} policies, {} hooks and {} domain settings to {}",
+                bundle.policies.len(),
+                bundle.hooks.len(),
+                bundle.domain_settings.len(),
+                file
+            );
+        }
+        _ => std::io::stdout().write_all(rendered.as_bytes())?,
+    }
+    Ok(())
+}
+
+async fn import(manager: &ConfigBundleManager, args: &BundleArgs) -> Result<()> {
+    let file = args.file.as_deref().unwrap_or("-");
+    let input = if file == "-" {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        input
+    } else {
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?
+    };
+
+    let bundle = ConfigBundle::parse(&input, args.format())
+        .with_context(|| format!("Failed to parse {}", file))?;
+    let options = ImportOptions {
+        dry_run: args.dry_run,
+        prune: args.prune,
+    };
+    let report = manager.import(args.tenant_id, &bundle, options).await?;
+
+    for change in &report.changes {
+        let action = match change.action {
+            ChangeAction::Create => "create",
+            ChangeAction::Update => "update",
+            ChangeAction::Delete => "delete",
+            ChangeAction::Unchanged => continue,
+        };
+        if change.fields.is_empty() {
+            println!("{} {} {}", action, change.kind, change.name);
+        } else {
+            println!(
+                "{} {} {} ({})",
+                action,
+                change.kind,
+                change.name,
+                change.fields.join(", ")
+            );
+        }
+    }
+    println!(
+        "{}: {} created, {} updated, {} deleted, {} unchanged",
+        if report.dry_run { "Dry run" } else { "Applied" },
+        report.created,
+        report.updated,
+        report.deleted,
+        report.unchanged
+    );
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! `mairust config` subcommand and the configuration flags
+//!
+//! `--config`, `--profile` and `--set` choose the configuration layers of
+//! the server and of `mairust config dump` (see [`ConfigLoader`]).
+
+use anyhow::{anyhow, bail, Result};
+use mairust_common::ConfigLoader;
+
+const USAGE: &str = "\
+Usage:
+  mairust config dump [--resolved] [--config <file>] [--profile <name>] [--set <key>=<value>]...
+
+Prints the configuration with secrets redacted: the values set by the
+configuration file, the profile overlay, MAIRUST__<SECTION>__<KEY> environment
+variables and --set flags, in that order of precedence. With --resolved,
+prints the effective configuration including built-in defaults.";
+
+/// Read the configuration flags; returns the loader and the other arguments
+pub fn parse_flags(args: &[String]) -> Result<(ConfigLoader, Vec<String>)> {
+    let mut loader = ConfigLoader::from_env();
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--config" => loader = loader.with_file(value()?),
+            "--profile" => loader = loader.with_profile(value()?),
+            "--set" => loader = loader.with_override(&value()?)?,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((loader, rest))
+}
+
+/// Run `mairust config <args>`
+pub fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("dump") => {}
+        Some("-h" | "--help") | None => bail!(USAGE),
+        Some(other) => bail!("Unknown config command '{}'\n\n{}", other, USAGE),
+    }
+
+    let (loader, rest) = parse_flags(&args[1..])?;
+    let mut resolved = false;
+    for arg in rest {
+        match arg.as_str() {
+            "--resolved" => resolved = true,
+            "-h" | "--help" => bail!(USAGE),
+            other => bail!("Unexpected argument '{}'\n\n{}", other, USAGE),
+        }
+    }
+
+    print!("{}", loader.dump(resolved)?);
+    Ok(())
+}
@@ -1,25 +1,59 @@
 //! MaiRust - Mail server entry point
 
 use anyhow::Result;
-use mairust_common::config::Config;
+use mairust_common::config::{ListenerProtocol, ListenerTls};
+use mairust_core::queue::DnsCache;
 use mairust_core::{
-    HookManager, ImapServer, PluginManager, PluginManagerConfig, Pop3Config, Pop3Server,
-    QueueManager, SmtpServer,
+    AcmeWorker, ConnectionLimiter, DkimRotationWorker, DmarcReportWorker, FileSync, HookManager,
+    ImapServer, InboundWebhookWorker, Journal, JournalWorker, MailboxProvisioningWorker,
+    MaintenanceMode, ManageSieveConfig, ManageSieveServer, MeilisearchClient, MeilisearchConfig,
+    MessageIndexer, PluginManager, PluginManagerConfig, Pop3Config, Pop3Server,
+    PreviewBackfillWorker, QuarantineDigestWorker, QueueManager, Replication, ReplicationRole,
+    SecurityNotifier, ServicePolicy, SessionRegistry, SmtpServer, SpamFilter, TrustedProxies,
+};
+use mairust_storage::{
+    db::DatabasePool, file::LocalStorage, Residency, ResidentStorage, StorageLayout,
 };
-use mairust_storage::{db::DatabasePool, file::LocalStorage};
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
+mod bundle;
+mod config;
+mod replication;
+mod storage;
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Subcommands run against the database without starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bundle") {
+        return bundle::run(&args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("storage") {
+        return storage::run(&args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("config") {
+        return config::run(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("replication") {
+        return replication::run(&args[1..]).await;
+    }
+    let (config_loader, rest) = config::parse_flags(&args)?;
+    if let Some(arg) = rest.first() {
+        anyhow::bail!("Unexpected argument '{}'", arg);
+    }
+
     // Initialize logging
     init_logging();
 
     info!("Starting MaiRust mail server...");
 
     // Load configuration
-    let config = Config::load()?;
+    let config = config_loader.load()?;
+    if let Some(profile) = config_loader.profile() {
+        info!("Using configuration profile '{}'", profile);
+    }
 
     // Initialize database
     let db_pool = DatabasePool::new(&config.database).await?;
@@ -29,8 +63,87 @@ async fn main() -> Result<()> {
     db_pool.migrate().await?;
     info!("Database migrations completed");
 
-    // Initialize file storage
-    let file_storage = Arc::new(LocalStorage::new(&config.storage)?);
+    // Initialize file storage; files of tenants pinned to a region are kept
+    // in the region's storage
+    let residency = Arc::new(Residency::new(db_pool.clone(), config.residency.clone()));
+    if let (true, Some(region)) = (residency.is_enabled(), residency.local_region()) {
+        info!("Data residency enforced in region {}", region);
+    }
+    let file_storage = Arc::new(ResidentStorage::new(
+        Box::new(LocalStorage::new(&config.storage)?),
+        residency.clone(),
+    )?);
+
+    // Listeners from [[listener]], or the fixed ports of each section
+    let listeners = config.listeners();
+    // Live sessions of all listeners, listed and terminated through the API
+    let sessions = SessionRegistry::new();
+    let mut server_handles = Vec::new();
+
+    // Start API listeners
+    for listener in listeners
+        .iter()
+        .filter(|l| l.protocol == ListenerProtocol::Api)
+    {
+        let db_pool = db_pool.clone();
+        let file_storage = file_storage.clone();
+        let sessions = sessions.clone();
+        let replication = config.replication.clone();
+        let tracking = config.tracking.clone();
+        let hostname = config.server.hostname.clone();
+        let bind = listener.bind.clone();
+        server_handles.push(tokio::spawn(async move {
+            let app = mairust_api::create_router(
+                db_pool,
+                file_storage,
+                sessions,
+                replication,
+                tracking,
+                &hostname,
+            );
+            let listener = tokio::net::TcpListener::bind(&bind)
+                .await
+                .expect("Failed to bind API server");
+            info!("Starting API server on {}", bind);
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("API server error: {}", e);
+            }
+        }));
+    }
+
+    // A replication standby serves only the API and copies message files
+    // from its primary, until it is promoted
+    let replication = Replication::new(db_pool.clone(), config.replication.clone());
+    if replication.role().await? == ReplicationRole::Standby {
+        info!("Running as a replication standby");
+        let file_sync = FileSync::new(
+            db_pool.clone(),
+            file_storage.clone(),
+            config.replication.clone(),
+        )?;
+        let sync_handle = tokio::spawn(file_sync.run());
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                info!("Shutdown signal received");
+                sync_handle.abort();
+                for handle in server_handles {
+                    handle.abort();
+                }
+                return Ok(());
+            }
+            _ = replication.wait_for_promotion() => {
+                info!("Promoted to primary, starting mail services");
+                sync_handle.abort();
+            }
+        }
+    }
+    let replication_handle = if config.replication.enabled {
+        Some(tokio::spawn(replication.run_heartbeat()))
+    } else {
+        None
+    };
 
     // Initialize hook manager
     let hook_manager = Arc::new(HookManager::new(db_pool.clone()));
@@ -50,12 +163,25 @@ async fn main() -> Result<()> {
     }
     let plugin_manager = Arc::new(tokio::sync::RwLock::new(plugin_manager));
 
+    // Envelope journaling, shared by the SMTP server and the queue
+    let journal = Journal::new(config.journal.clone());
+
+    // DNS cache of destination domains, kept warm for frequent ones
+    let dns_cache = Arc::new(DnsCache::new(config.queue.dns.clone()));
+    let dns_handle = if config.queue.dns.prefetch {
+        Some(tokio::spawn(dns_cache.clone().run(db_pool.clone())))
+    } else {
+        None
+    };
+
     // Initialize queue manager
-    let queue_manager = Arc::new(QueueManager::new(
-        db_pool.clone(),
-        file_storage.clone(),
-        hook_manager.clone(),
-    ));
+    let queue_manager = Arc::new(
+        QueueManager::new(db_pool.clone(), file_storage.clone(), hook_manager.clone())
+            .with_smtp_config(config.smtp.clone())
+            .with_queue_config(config.queue.clone())
+            .with_journal(journal.clone())
+            .with_dns_cache(dns_cache),
+    );
 
     // Start queue processor
     let queue_handle = {
@@ -65,90 +191,290 @@ async fn main() -> Result<()> {
         })
     };
 
-    // Initialize SMTP server
-    let smtp_server = Arc::new(SmtpServer::new(
-        config.smtp.clone(),
-        db_pool.clone(),
-        file_storage.clone(),
-        hook_manager.clone(),
-        queue_manager.clone(),
-    ));
-
-    info!(
-        "Starting SMTP server on {}:{} (SMTP) and {}:{} (Submission)",
-        config.smtp.host, config.smtp.port, config.smtp.host, config.smtp.submission_port
-    );
+    // Start inbound webhook worker
+    let inbound_handle = {
+        let worker = InboundWebhookWorker::new(
+            db_pool.clone(),
+            file_storage.clone(),
+            config.api.public_base_url(&config.server.hostname),
+        );
+        tokio::spawn(async move {
+            worker.run().await;
+        })
+    };
 
-    // Start SMTP server
-    let smtp_handle = {
-        let smtp_server = smtp_server.clone();
+    // Rebuild previews of messages stored before snippets existed
+    let preview_handle = {
+        let worker = PreviewBackfillWorker::new(db_pool.clone(), file_storage.clone());
         tokio::spawn(async move {
-            if let Err(e) = smtp_server.run_dual_port().await {
-                tracing::error!("SMTP server error: {}", e);
+            if let Err(e) = worker.run().await {
+                warn!("Preview backfill failed: {}", e);
             }
         })
     };
 
-    // Start IMAP server if enabled
-    let imap_handle = if config.imap.enabled {
+    // Start bulk mailbox provisioning worker
+    let provisioning_handle = {
+        let worker = MailboxProvisioningWorker::new(db_pool.clone());
+        tokio::spawn(async move {
+            worker.run().await;
+        })
+    };
+
+    // Start journal export worker
+    let journal_handle = if config.journal.enabled {
+        let worker = JournalWorker::new(
+            db_pool.clone(),
+            file_storage.clone(),
+            config.journal.clone(),
+            config.server.hostname.clone(),
+        );
+        Some(tokio::spawn(async move {
+            worker.run().await;
+        }))
+    } else {
+        None
+    };
+
+    // Start DMARC aggregate report sender
+    let dmarc_report_handle = if config.smtp.dmarc_reports.enabled {
+        let worker = DmarcReportWorker::new(db_pool.clone(), queue_manager.clone(), &config.smtp);
+        Some(tokio::spawn(async move {
+            worker.run().await;
+        }))
+    } else {
+        None
+    };
+
+    // Start quarantine digest sender
+    let quarantine_digest_handle = {
+        let worker = QuarantineDigestWorker::new(
+            db_pool.clone(),
+            file_storage.clone(),
+            config.server.hostname.clone(),
+        );
+        tokio::spawn(async move {
+            worker.run().await;
+        })
+    };
+
+    // Start DKIM key rotation; pending keys are activated even when
+    // rotation itself is off
+    let dkim_rotation_handle = {
+        let worker = DkimRotationWorker::new(db_pool.clone(), &config.smtp);
+        tokio::spawn(async move {
+            worker.run().await;
+        })
+    };
+
+    // Verify tracking domains and order their certificates
+    let acme_handle = if config.tracking.enabled {
+        let worker = AcmeWorker::new(
+            db_pool.clone(),
+            config.tracking.clone(),
+            &config.server.hostname,
+        );
+        Some(tokio::spawn(async move {
+            worker.run().await;
+        }))
+    } else {
+        None
+    };
+
+    // Maintenance windows, shared by the mail listeners
+    let maintenance = MaintenanceMode::new();
+    if let Err(e) = maintenance.refresh(&db_pool).await {
+        warn!("Failed to load maintenance windows: {}", e);
+    }
+    let maintenance_handle = tokio::spawn(maintenance.clone().run(db_pool.clone()));
+
+    // Full-text search index, used by IMAP SEARCH
+    let search_config = if config.meilisearch.enabled {
+        let search_config = MeilisearchConfig {
+            url: config.meilisearch.url.clone(),
+            api_key: config.meilisearch.api_key.clone(),
+            timeout_secs: config.meilisearch.timeout_secs,
+            messages_index: config.meilisearch.messages_index.clone(),
+        };
+        let indexer = MessageIndexer::new(MeilisearchClient::new(search_config.clone()));
+        match indexer.initialize().await {
+            Ok(()) => info!("Search index initialized"),
+            Err(e) => warn!("Search index setup failed: {}", e),
+        }
+        Some(search_config)
+    } else {
+        None
+    };
+
+    let trusted_proxies = TrustedProxies::parse(&config.server.trusted_proxies)?;
+
+    // Initialize SMTP server
+    let smtp_listeners: Vec<_> = listeners
+        .iter()
+        .filter(|l| l.protocol.is_smtp())
+        .cloned()
+        .collect();
+    if smtp_listeners.is_empty() {
+        info!("SMTP server disabled");
+    } else {
+        let smtp_server = Arc::new(
+            SmtpServer::with_config(
+                &config,
+                db_pool.clone(),
+                file_storage.clone(),
+                hook_manager.clone(),
+                queue_manager.clone(),
+            )
+            .with_spam_filter(SpamFilter::from_env().with_tenant_rules(db_pool.clone()))
+            .with_maintenance(maintenance.clone())
+            .with_journal(journal.clone())
+            .with_trusted_proxies(trusted_proxies.clone())
+            .with_sessions(sessions.clone()),
+        );
+
+        for listener in &smtp_listeners {
+            info!("Starting SMTP listener {}", listener.label());
+        }
+
+        // Start SMTP server
+        server_handles.push(tokio::spawn(async move {
+            if let Err(e) = smtp_server.run_listeners(smtp_listeners).await {
+                tracing::error!("SMTP server error: {}", e);
+            }
+        }));
+    }
+
+    // Blocked logins and logins from new networks are reported to users
+    let security =
+        SecurityNotifier::new(config.server.hostname.clone(), config.storage.path.clone())
+            .with_storage_layout(StorageLayout::from(&config.storage.layout))
+            .with_residency(residency.clone());
+
+    // IMAP and POP3 access by plan, tenant and user; ManageSieve follows IMAP
+    let service_policy = ServicePolicy::new(config.services.plans.clone());
+
+    // Start IMAP listeners
+    let imap_listeners: Vec<_> = listeners
+        .iter()
+        .filter(|l| l.protocol == ListenerProtocol::Imap)
+        .collect();
+    if imap_listeners.is_empty() {
+        info!("IMAP server disabled");
+    }
+    // Connection limits apply across all IMAP listeners
+    let imap_limiter =
+        ConnectionLimiter::new(config.imap.max_connections, config.imap.limits.clone());
+    for listener in imap_listeners {
         let imap_config = mairust_core::imap::ImapConfig {
-            bind: config.imap.bind.clone(),
-            starttls: config.imap.starttls,
+            bind: listener.bind.clone(),
+            starttls: listener.tls_mode() == ListenerTls::Starttls,
+            tls_bind: None,
             timeout_minutes: config.imap.timeout_minutes,
             max_connections: config.imap.max_connections,
             storage_path: config.storage.path.clone(),
+            storage_layout: StorageLayout::from(&config.storage.layout),
+            search: search_config.clone(),
+            maintenance: maintenance.clone(),
+            limiter: Some(imap_limiter.clone()),
+            security: Some(security.clone()),
+            proxy: listener.proxy_protocol.then(|| trusted_proxies.clone()),
+            services: service_policy.clone(),
+            residency: Some(residency.clone()),
+            sessions: sessions.clone(),
         };
         let imap_server = ImapServer::with_tls(imap_config, db_pool.clone(), config.tls.as_ref());
-        info!("Starting IMAP server on {}", config.imap.bind);
+        let implicit_tls = listener.tls_mode() == ListenerTls::Implicit;
+        let bind = listener.bind.clone();
+        info!("Starting IMAP listener {}", listener.label());
 
-        Some(tokio::spawn(async move {
-            if let Err(e) = imap_server.run().await {
+        server_handles.push(tokio::spawn(async move {
+            if let Err(e) = imap_server.run_listener(&bind, implicit_tls).await {
                 tracing::error!("IMAP server error: {}", e);
             }
-        }))
-    } else {
-        info!("IMAP server disabled");
-        None
-    };
+        }));
+    }
 
-    // Start POP3 server if enabled
-    let pop3_handle = if config.pop3.enabled {
+    // Start POP3 listeners
+    let pop3_listeners: Vec<_> = listeners
+        .iter()
+        .filter(|l| l.protocol == ListenerProtocol::Pop3)
+        .collect();
+    if pop3_listeners.is_empty() {
+        info!("POP3 server disabled");
+    }
+    let pop3_limiter =
+        ConnectionLimiter::new(config.pop3.max_connections, config.pop3.limits.clone());
+    for listener in pop3_listeners {
         let pop3_config = Pop3Config {
-            bind: config.pop3.bind.clone(),
-            starttls: config.pop3.starttls,
+            bind: listener.bind.clone(),
+            starttls: listener.tls_mode() == ListenerTls::Starttls,
+            tls_bind: None,
+            require_tls_for_auth: config.pop3.require_tls_for_auth,
+            apop: config.pop3.apop,
             timeout_minutes: config.pop3.timeout_minutes,
             max_connections: config.pop3.max_connections,
             server_name: config.server.hostname.clone(),
             storage_path: config.storage.path.clone(),
+            maintenance: maintenance.clone(),
+            limiter: Some(pop3_limiter.clone()),
+            security: Some(security.clone()),
+            proxy: listener.proxy_protocol.then(|| trusted_proxies.clone()),
+            services: service_policy.clone(),
+            residency: Some(residency.clone()),
+            sessions: sessions.clone(),
         };
         let pop3_server = Pop3Server::with_tls(pop3_config, db_pool.clone(), config.tls.as_ref());
-        info!("Starting POP3 server on {}", config.pop3.bind);
+        let implicit_tls = listener.tls_mode() == ListenerTls::Implicit;
+        let bind = listener.bind.clone();
+        info!("Starting POP3 listener {}", listener.label());
 
-        Some(tokio::spawn(async move {
-            if let Err(e) = pop3_server.run().await {
+        server_handles.push(tokio::spawn(async move {
+            if let Err(e) = pop3_server.run_listener(&bind, implicit_tls).await {
                 tracing::error!("POP3 server error: {}", e);
             }
-        }))
-    } else {
-        info!("POP3 server disabled");
-        None
-    };
+        }));
+    }
 
-    // Start API server
-    let api_handle = {
-        let db_pool = db_pool.clone();
-        let api_port = config.api.port;
-        tokio::spawn(async move {
-            let app = mairust_api::create_router(db_pool);
-            let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", api_port))
-                .await
-                .expect("Failed to bind API server");
-            info!("Starting API server on port {}", api_port);
-            if let Err(e) = axum::serve(listener, app).await {
-                tracing::error!("API server error: {}", e);
+    // Start ManageSieve listeners
+    let managesieve_listeners: Vec<_> = listeners
+        .iter()
+        .filter(|l| l.protocol == ListenerProtocol::ManageSieve)
+        .collect();
+    if managesieve_listeners.is_empty() {
+        info!("ManageSieve server disabled");
+    }
+    let managesieve_limiter = ConnectionLimiter::new(
+        config.managesieve.max_connections,
+        config.managesieve.limits.clone(),
+    );
+    for listener in managesieve_listeners {
+        let managesieve_config = ManageSieveConfig {
+            bind: listener.bind.clone(),
+            starttls: listener.tls_mode() == ListenerTls::Starttls,
+            require_tls_for_auth: config.managesieve.require_tls_for_auth,
+            timeout_minutes: config.managesieve.timeout_minutes,
+            max_connections: config.managesieve.max_connections,
+            max_scripts: config.managesieve.max_scripts,
+            max_script_size: config.managesieve.max_script_size,
+            server_name: config.server.hostname.clone(),
+            limiter: Some(managesieve_limiter.clone()),
+            security: Some(security.clone()),
+            proxy: listener.proxy_protocol.then(|| trusted_proxies.clone()),
+            services: service_policy.clone(),
+            sessions: sessions.clone(),
+        };
+        let managesieve_server =
+            ManageSieveServer::with_tls(managesieve_config, db_pool.clone(), config.tls.as_ref());
+        let implicit_tls = listener.tls_mode() == ListenerTls::Implicit;
+        let bind = listener.bind.clone();
+        info!("Starting ManageSieve listener {}", listener.label());
+
+        server_handles.push(tokio::spawn(async move {
+            if let Err(e) = managesieve_server.run_listener(&bind, implicit_tls).await {
+                tracing::error!("ManageSieve server error: {}", e);
             }
-        })
-    };
+        }));
+    }
 
     // Start Web UI server if enabled
     let web_handle = if config.web.enabled {
@@ -156,12 +482,15 @@ async fn main() -> Result<()> {
             bind: config.web.bind.clone(),
             api_url: config.web.api_url.clone(),
             debug: config.web.debug,
+            tls_report_path: config.web.tls_report_path.clone(),
+            tracking: config.tracking.clone(),
         };
         let db_pool = db_pool.clone();
+        let file_storage = file_storage.clone();
         info!("Starting Web UI server on {}", config.web.bind);
 
         Some(tokio::spawn(async move {
-            if let Err(e) = mairust_web::run(web_config, db_pool).await {
+            if let Err(e) = mairust_web::run(web_config, db_pool, file_storage).await {
                 tracing::error!("Web UI server error: {}", e);
             }
         }))
@@ -177,14 +506,30 @@ async fn main() -> Result<()> {
     info!("Shutdown signal received");
 
     // Cleanup
-    smtp_handle.abort();
     queue_handle.abort();
-    api_handle.abort();
-
-    if let Some(handle) = imap_handle {
+    if let Some(handle) = dns_handle {
+        handle.abort();
+    }
+    inbound_handle.abort();
+    preview_handle.abort();
+    provisioning_handle.abort();
+    quarantine_digest_handle.abort();
+    if let Some(handle) = journal_handle {
+        handle.abort();
+    }
+    if let Some(handle) = dmarc_report_handle {
+        handle.abort();
+    }
+    dkim_rotation_handle.abort();
+    if let Some(handle) = acme_handle {
+        handle.abort();
+    }
+    maintenance_handle.abort();
+    if let Some(handle) = replication_handle {
         handle.abort();
     }
-    if let Some(handle) = pop3_handle {
+
+    for handle in server_handles {
         handle.abort();
     }
     if let Some(handle) = web_handle {
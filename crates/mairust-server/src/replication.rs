@@ -0,0 +1,141 @@
+//! `mairust replication` subcommand
+//!
+//! Sets up warm standby replication and promotes a standby to primary.
+
+use anyhow::{bail, Result};
+use mairust_common::config::Config;
+use mairust_core::{Replication, ReplicationStatus};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::ReplicationRepository;
+
+const USAGE: &str = "\
+Usage:
+  mairust replication publish
+  mairust replication subscribe <conninfo>
+  mairust replication status
+  mairust replication promote
+
+'publish' runs on the primary and publishes its tables; run it again after
+upgrades that add tables. 'subscribe' runs on the standby, after its
+migrations, with the primary's PostgreSQL connection string. 'promote'
+makes the standby a primary; a running standby server then starts its mail
+services. [replication] must be enabled on both servers.";
+
+/// A replication command
+enum Command {
+    Publish,
+    Subscribe(String),
+    Status,
+    Promote,
+}
+
+fn parse(args: &[String]) -> Result<Command> {
+    let command = match args.first().map(String::as_str) {
+        Some("publish") => Command::Publish,
+        Some("subscribe") => match args.get(1) {
+            Some(conninfo) => Command::Subscribe(conninfo.clone()),
+            None => bail!("Missing connection string\n\n{}", USAGE),
+        },
+        Some("status") => Command::Status,
+        Some("promote") => Command::Promote,
+        Some("-h" | "--help") | None => bail!(USAGE),
+        Some(other) => bail!("Unknown replication command '{}'\n\n{}", other, USAGE),
+    };
+    let expected = if matches!(command, Command::Subscribe(_)) {
+        2
+    } else {
+        1
+    };
+    if let Some(arg) = args.get(expected) {
+        bail!("Unexpected argument '{}'\n\n{}", arg, USAGE);
+    }
+    Ok(command)
+}
+
+/// Run `mairust replication <args>`
+pub async fn run(args: &[String]) -> Result<()> {
+    let command = parse(args)?;
+
+    let config = Config::load()?;
+    let replication = config.replication.clone();
+    if !replication.enabled {
+        bail!("Replication is not enabled in [replication]");
+    }
+    let db_pool = DatabasePool::new(&config.database).await?;
+    let repository = ReplicationRepository::new(db_pool.clone());
+
+    match command {
+        Command::Publish => {
+            let added = repository.publish(&replication.publication).await?;
+            repository.beat().await?;
+            if added.is_empty() {
+                println!("Publication '{}' is up to date", replication.publication);
+            } else {
+                println!(
+                    "Published {} tables in '{}': {}",
+                    added.len(),
+                    replication.publication,
+                    added.join(", ")
+                );
+            }
+        }
+        Command::Subscribe(conninfo) => {
+            let created = repository
+                .subscribe(
+                    &replication.subscription,
+                    &conninfo,
+                    &replication.publication,
+                )
+                .await?;
+            if created {
+                println!(
+                    "Subscription '{}' created; the primary's data is being copied",
+                    replication.subscription
+                );
+            } else {
+                println!(
+                    "Subscription '{}' refreshed with the primary's new tables",
+                    replication.subscription
+                );
+            }
+        }
+        Command::Status => {
+            let status = Replication::new(db_pool, replication).status().await?;
+            print_status(&status);
+        }
+        Command::Promote => {
+            let status = Replication::new(db_pool, replication).promote().await?;
+            println!("Promoted to primary");
+            if status.files.pending > 0 {
+                println!(
+                    "{} message files had not been copied from the old primary",
+                    status.files.pending
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_status(status: &ReplicationStatus) {
+    println!("Role: {}", status.role.as_str());
+    match status.heartbeat_at {
+        Some(at) => println!("Last heartbeat: {}", at.to_rfc3339()),
+        None => println!("Last heartbeat: none"),
+    }
+    if let Some(lag) = status.lag_secs {
+        println!("Lag: {:.1}s (max {}s)", lag, status.max_lag_secs);
+    }
+    if status.files.pending > 0 || status.files.failing > 0 {
+        println!(
+            "Message files: {} pending, {} failing",
+            status.files.pending, status.files.failing
+        );
+    }
+    for subscriber in &status.subscribers {
+        match subscriber.replay_lag_secs {
+            Some(lag) => println!("Subscriber {}: {:.1}s behind", subscriber.name, lag),
+            None => println!("Subscriber {}: up to date", subscriber.name),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! `mairust storage` subcommand
+//!
+//! Moves message files to the layout configured in `[storage.layout]`,
+//! after the layout has been changed. Files of tenants kept in another
+//! region are left to the servers of that region.
+
+use anyhow::{anyhow, bail, Context, Result};
+use mairust_common::config::Config;
+use mairust_core::{RelocationOptions, StorageRelocator};
+use mairust_storage::db::DatabasePool;
+use mairust_storage::{LocalStorage, Residency, ResidentStorage, StorageLayout};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const USAGE: &str = "\
+Usage:
+  mairust storage relocate [--tenant <id>] [--batch <size>] [--dry-run]
+
+Moves message files written under an earlier layout to the one configured
+in [storage.layout] and updates their paths. Files of tenants kept in
+another region are left alone. The server may keep running; an interrupted
+run can be started again.";
+
+/// Parse `relocate` arguments
+fn parse(args: &[String]) -> Result<RelocationOptions> {
+    let mut iter = args.iter();
+    match iter.next().map(String::as_str) {
+        Some("relocate") => {}
+        Some("-h" | "--help") | None => bail!(USAGE),
+        Some(other) => bail!("Unknown storage command '{}'\n\n{}", other, USAGE),
+    }
+
+    let mut options = RelocationOptions::default();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--tenant" => {
+                let value = value()?;
+                options.tenant_id = Some(
+                    Uuid::parse_str(&value)
+                        .with_context(|| format!("Invalid tenant ID '{}'", value))?,
+                );
+            }
+            "--batch" => {
+                let value = value()?;
+                options.batch_size = value
+                    .parse()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or_else(|| anyhow!("Invalid batch size '{}'", value))?;
+            }
+            "--dry-run" => options.dry_run = true,
+            "-h" | "--help" => bail!(USAGE),
+            other => bail!("Unexpected argument '{}'\n\n{}", other, USAGE),
+        }
+    }
+    Ok(options)
+}
+
+/// Run `mairust storage <args>`
+pub async fn run(args: &[String]) -> Result<()> {
+    let options = parse(args)?;
+
+    let config = Config::load()?;
+    if config.storage.backend != "fs" {
+        bail!("Relocation needs the fs storage backend");
+    }
+    let db_pool = DatabasePool::new(&config.database).await?;
+    let residency = Arc::new(Residency::new(db_pool.clone(), config.residency.clone()));
+    let file_storage = Arc::new(ResidentStorage::new(
+        Box::new(LocalStorage::new(&config.storage)?),
+        residency,
+    )?);
+    let relocator = StorageRelocator::new(
+        db_pool,
+        file_storage,
+        StorageLayout::from(&config.storage.layout),
+    );
+
+    let summary = relocator.run(&options).await?;
+    println!(
+        "{}: {} messages scanned, {} files relocated, {} in place, {} skipped, {} missing, \
+         {} in another region, {} failed",
+        if summary.dry_run {
+            "Dry run"
+        } else {
+            "Relocated"
+        },
+        summary.scanned,
+        summary.relocated,
+        summary.in_place,
+        summary.skipped,
+        summary.missing,
+        summary.other_region,
+        summary.failed
+    );
+    if summary.failed > 0 {
+        bail!("{} files could not be relocated", summary.failed);
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+-- Persistent per-mailbox IMAP UIDs (RFC 3501 section 2.3.1.1)
+--
+-- UIDs are allocated from mailboxes.uid_next when a message is inserted
+-- into, or moved to, a mailbox. Locking the mailbox row while allocating
+-- keeps UIDs strictly ascending within a mailbox.
+
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS uid_validity BIGINT NOT NULL
+    DEFAULT (EXTRACT(EPOCH FROM NOW())::BIGINT);
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS uid_next BIGINT NOT NULL DEFAULT 1;
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS uid BIGINT;
+
+-- Backfill existing messages in arrival order
+WITH numbered AS (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY mailbox_id ORDER BY received_at, id) AS rn
+    FROM messages
+)
+UPDATE messages m SET uid = n.rn
+FROM numbered n
+WHERE m.id = n.id AND m.uid IS NULL;
+
+UPDATE mailboxes mb
+SET uid_next = COALESCE((SELECT MAX(uid) FROM messages WHERE mailbox_id = mb.id), 0) + 1;
+
+CREATE OR REPLACE FUNCTION assign_message_uid()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' AND NEW.uid IS NOT NULL THEN
+        RETURN NEW;
+    END IF;
+    IF TG_OP = 'UPDATE' AND NEW.mailbox_id IS NOT DISTINCT FROM OLD.mailbox_id THEN
+        RETURN NEW;
+    END IF;
+
+    UPDATE mailboxes
+    SET uid_next = uid_next + 1
+    WHERE id = NEW.mailbox_id
+    RETURNING uid_next - 1 INTO NEW.uid;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_assign_message_uid
+BEFORE INSERT OR UPDATE OF mailbox_id ON messages
+FOR EACH ROW EXECUTE FUNCTION assign_message_uid();
+
+ALTER TABLE messages ALTER COLUMN uid SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_mailbox_uid ON messages(mailbox_id, uid);
@@ -0,0 +1,25 @@
+-- Queue statistics support
+--
+-- tenant_id and priority are promoted out of the JSON payload so that
+-- queue monitoring can be answered from indexes instead of scanning
+-- and parsing every job payload.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS tenant_id UUID;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS priority INTEGER NOT NULL DEFAULT 0;
+
+UPDATE jobs
+SET tenant_id = (payload->>'tenant_id')::uuid
+WHERE tenant_id IS NULL
+  AND payload->>'tenant_id' ~* '^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$';
+
+-- Counts by status and priority
+CREATE INDEX IF NOT EXISTS idx_jobs_queue_status_priority ON jobs(queue, status, priority);
+CREATE INDEX IF NOT EXISTS idx_jobs_tenant_queue_status_priority ON jobs(tenant_id, queue, status, priority);
+
+-- Oldest pending job
+CREATE INDEX IF NOT EXISTS idx_jobs_queue_status_created ON jobs(queue, status, created_at);
+CREATE INDEX IF NOT EXISTS idx_jobs_tenant_queue_status_created ON jobs(tenant_id, queue, status, created_at);
+
+-- Throughput and recent failures
+CREATE INDEX IF NOT EXISTS idx_jobs_queue_status_completed ON jobs(queue, status, completed_at);
+CREATE INDEX IF NOT EXISTS idx_jobs_tenant_queue_status_completed ON jobs(tenant_id, queue, status, completed_at);
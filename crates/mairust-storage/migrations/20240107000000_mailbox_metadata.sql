@@ -0,0 +1,24 @@
+-- Mailbox metadata (RFC 5464)
+--
+-- Entries under /private/ belong to a single user (user_id set); entries
+-- under /shared/ are visible to everyone with access to the mailbox
+-- (user_id NULL). A NULL mailbox_id holds server-level entries.
+
+CREATE TABLE IF NOT EXISTS mailbox_metadata (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    mailbox_id UUID REFERENCES mailboxes(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    entry TEXT NOT NULL,
+    value TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_mailbox_metadata_entry ON mailbox_metadata (
+    tenant_id,
+    COALESCE(mailbox_id, '00000000-0000-0000-0000-000000000000'::uuid),
+    COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::uuid),
+    entry
+);
+CREATE INDEX IF NOT EXISTS idx_mailbox_metadata_mailbox ON mailbox_metadata(mailbox_id);
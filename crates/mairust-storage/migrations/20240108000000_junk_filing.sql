@@ -0,0 +1,18 @@
+-- Per-user settings
+--
+-- Starts with the junk filing preference: when enabled (the default),
+-- messages the spam filter flags are delivered to the user's Junk folder
+-- instead of the inbox.
+
+CREATE TABLE IF NOT EXISTS user_settings (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    junk_filing BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_settings_tenant ON user_settings(tenant_id);
+
+-- Junk folder lookup by owner and folder name
+CREATE INDEX IF NOT EXISTS idx_mailboxes_user_address_lower ON mailboxes(user_id, LOWER(address));
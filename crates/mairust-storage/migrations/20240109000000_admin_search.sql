@@ -0,0 +1,14 @@
+-- Admin global search
+--
+-- Lookups by envelope sender and Message-ID across all tenants. Received
+-- messages record the SMTP envelope sender in metadata; delivery jobs carry
+-- it in their payload.
+
+CREATE INDEX IF NOT EXISTS idx_messages_envelope_from
+    ON messages(LOWER(metadata->>'envelope_from'));
+
+CREATE INDEX IF NOT EXISTS idx_jobs_delivery_from
+    ON jobs(LOWER(payload->>'from')) WHERE queue = 'delivery';
+
+CREATE INDEX IF NOT EXISTS idx_jobs_delivery_message_id_header
+    ON jobs((payload->>'message_id_header')) WHERE queue = 'delivery';
@@ -0,0 +1,18 @@
+-- Mailbox access control lists (RFC 4314)
+--
+-- A mailbox owner always holds every right; rows here grant rights on a
+-- mailbox to other users of the same tenant. Rights are stored as the
+-- RFC 4314 right letters, e.g. 'lrs' for read-only access.
+
+CREATE TABLE IF NOT EXISTS mailbox_acl (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    mailbox_id UUID NOT NULL REFERENCES mailboxes(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    rights VARCHAR(32) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (mailbox_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_mailbox_acl_user ON mailbox_acl(tenant_id, user_id);
@@ -0,0 +1,51 @@
+-- Webmail notification preferences and unread counters
+--
+-- Notification preferences live next to the other per-user settings.
+-- Mailboxes keep message and unread counts up to date through a trigger
+-- so the web UI badge does not have to COUNT(*) the messages table.
+
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS desktop_notifications BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS notification_sound BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS digest_frequency VARCHAR(20) NOT NULL DEFAULT 'never'
+    CHECK (digest_frequency IN ('never', 'hourly', 'daily', 'weekly'));
+
+-- message_count includes messages flagged \Deleted (like IMAP EXISTS);
+-- unread_count only counts unseen messages that are not flagged \Deleted.
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS message_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS unread_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE mailboxes mb
+SET message_count = c.total, unread_count = c.unread
+FROM (
+    SELECT mailbox_id,
+           COUNT(*) AS total,
+           COUNT(*) FILTER (WHERE NOT seen AND NOT deleted) AS unread
+    FROM messages
+    GROUP BY mailbox_id
+) c
+WHERE mb.id = c.mailbox_id;
+
+CREATE OR REPLACE FUNCTION maintain_mailbox_counters()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE mailboxes
+        SET message_count = message_count - 1,
+            unread_count = unread_count - CASE WHEN NOT OLD.seen AND NOT OLD.deleted THEN 1 ELSE 0 END
+        WHERE id = OLD.mailbox_id;
+    END IF;
+
+    IF TG_OP IN ('UPDATE', 'INSERT') THEN
+        UPDATE mailboxes
+        SET message_count = message_count + 1,
+            unread_count = unread_count + CASE WHEN NOT NEW.seen AND NOT NEW.deleted THEN 1 ELSE 0 END
+        WHERE id = NEW.mailbox_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_maintain_mailbox_counters
+AFTER INSERT OR DELETE OR UPDATE OF mailbox_id, seen, deleted ON messages
+FOR EACH ROW EXECUTE FUNCTION maintain_mailbox_counters();
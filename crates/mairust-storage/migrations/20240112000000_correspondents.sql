@@ -0,0 +1,25 @@
+-- Recent correspondents for composer autocomplete
+--
+-- One row per user and address, updated whenever the user sends to or
+-- receives from the address. Suggestions are ranked by frequency and
+-- recency, independent of any address book.
+
+CREATE TABLE IF NOT EXISTS correspondents (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Lowercased email address
+    address VARCHAR(255) NOT NULL,
+    display_name VARCHAR(255),
+    frequency INTEGER NOT NULL DEFAULT 0,
+    last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, address)
+);
+
+-- Prefix matches on the address and display name
+CREATE INDEX IF NOT EXISTS idx_correspondents_user_address
+    ON correspondents(user_id, address varchar_pattern_ops);
+CREATE INDEX IF NOT EXISTS idx_correspondents_user_name
+    ON correspondents(user_id, LOWER(display_name) varchar_pattern_ops);
+CREATE INDEX IF NOT EXISTS idx_correspondents_tenant ON correspondents(tenant_id);
@@ -0,0 +1,40 @@
+-- Tables managed by configuration bundles
+--
+-- Policy rules and domain settings were used by their repositories without
+-- a migration creating them. Bundle import upserts policies by name and
+-- hooks by name, so those lookups get indexes too.
+
+CREATE TABLE IF NOT EXISTS policy_rules (
+    id UUID PRIMARY KEY,
+    tenant_id UUID REFERENCES tenants(id) ON DELETE CASCADE,
+    domain_id UUID REFERENCES domains(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    policy_type VARCHAR(20) NOT NULL DEFAULT 'inbound',
+    priority INTEGER NOT NULL DEFAULT 100,
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    conditions JSONB NOT NULL DEFAULT '[]',
+    actions JSONB NOT NULL DEFAULT '[]',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_policy_rules_tenant ON policy_rules(tenant_id, name);
+CREATE INDEX IF NOT EXISTS idx_policy_rules_domain ON policy_rules(domain_id);
+
+CREATE TABLE IF NOT EXISTS domain_settings (
+    domain_id UUID PRIMARY KEY REFERENCES domains(id) ON DELETE CASCADE,
+    catch_all_enabled BOOLEAN NOT NULL DEFAULT false,
+    catch_all_mailbox_id UUID REFERENCES mailboxes(id) ON DELETE SET NULL,
+    max_message_size BIGINT,
+    max_recipients INTEGER,
+    rate_limit_per_hour INTEGER,
+    require_tls_inbound BOOLEAN NOT NULL DEFAULT false,
+    require_tls_outbound BOOLEAN NOT NULL DEFAULT false,
+    spf_policy VARCHAR(20) NOT NULL DEFAULT 'neutral',
+    dmarc_policy VARCHAR(20) NOT NULL DEFAULT 'none',
+    extra_settings JSONB NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_hooks_tenant_name ON hooks(tenant_id, name);
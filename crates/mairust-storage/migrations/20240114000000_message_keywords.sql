@@ -0,0 +1,9 @@
+-- IMAP keywords (user-defined flags such as $Forwarded, $Phishing or labels)
+--
+-- Stored per message as a JSON array of keyword atoms, next to the system
+-- flag columns.
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS keywords JSONB NOT NULL DEFAULT '[]';
+
+-- SEARCH KEYWORD / UNKEYWORD
+CREATE INDEX IF NOT EXISTS idx_messages_keywords ON messages USING GIN (keywords);
@@ -0,0 +1,18 @@
+-- Default folders with localized names
+--
+-- IMAP folders are mailbox rows owned by a user whose address is the folder
+-- name. Email addresses stay globally unique, but folder names only need to
+-- be unique per user, so every user can have their own "Sent" or "送信済み".
+
+ALTER TABLE mailboxes DROP CONSTRAINT IF EXISTS mailboxes_address_key;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_mailboxes_address_unique
+    ON mailboxes(address) WHERE position('@' IN address) > 0;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_mailboxes_user_folder
+    ON mailboxes(user_id, address) WHERE position('@' IN address) = 0;
+
+-- Special-use role of a folder (RFC 6154): sent, drafts, trash, junk or archive.
+-- Set on provisioned folders so the role survives a rename; folders created
+-- by clients are matched by name instead.
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS special_use VARCHAR(16);
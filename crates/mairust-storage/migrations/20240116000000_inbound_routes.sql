@@ -0,0 +1,27 @@
+-- Inbound parse routes
+--
+-- A route maps one address, or every address of a domain, to a webhook.
+-- Matching inbound messages are parsed to JSON and posted to the webhook
+-- through the inbound_webhook job queue. Payloads and attachment links are
+-- signed with the route secret.
+
+CREATE TABLE IF NOT EXISTS inbound_routes (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    domain_id UUID NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    -- NULL matches every address of the domain
+    local_part VARCHAR(255),
+    webhook_url TEXT NOT NULL,
+    secret VARCHAR(128) NOT NULL,
+    -- Also deliver to the recipient's mailbox
+    store_message BOOLEAN NOT NULL DEFAULT true,
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_inbound_routes_address
+    ON inbound_routes(domain_id, LOWER(local_part)) WHERE local_part IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_inbound_routes_domain
+    ON inbound_routes(domain_id) WHERE local_part IS NULL;
+CREATE INDEX IF NOT EXISTS idx_inbound_routes_tenant ON inbound_routes(tenant_id);
@@ -0,0 +1,19 @@
+-- Maintenance windows
+--
+-- While a window is active the system is read-only: SMTP refuses
+-- connections with 421, IMAP and POP3 refuse changes, and API mutations
+-- return 503.
+
+CREATE TABLE IF NOT EXISTS maintenance_windows (
+    id UUID PRIMARY KEY,
+    starts_at TIMESTAMPTZ NOT NULL,
+    -- NULL for a window that lasts until an admin ends it
+    ends_at TIMESTAMPTZ,
+    message TEXT NOT NULL,
+    -- API key that scheduled the window
+    created_by VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (ends_at IS NULL OR ends_at >= starts_at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_maintenance_windows_starts_at ON maintenance_windows(starts_at);
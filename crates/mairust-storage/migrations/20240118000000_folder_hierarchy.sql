@@ -0,0 +1,22 @@
+-- Folder hierarchy
+--
+-- Folders form a hierarchy separated by "/" that is independent of the
+-- email addresses of mailboxes. folder_path is the full name of a folder
+-- ("Archive/2024") and parent_path the full name of its parent, NULL at the
+-- top level. Address mailboxes have no folder_path; a user's address
+-- mailbox is their INBOX. The address column of a folder keeps its full
+-- name for lookups by address.
+
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS folder_path VARCHAR(255);
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS parent_path VARCHAR(255);
+
+UPDATE mailboxes SET folder_path = address
+WHERE folder_path IS NULL AND position('@' IN address) = 0;
+
+UPDATE mailboxes SET parent_path = regexp_replace(folder_path, '/[^/]*$', '')
+WHERE parent_path IS NULL AND position('/' IN folder_path) > 0;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_mailboxes_user_folder_path
+    ON mailboxes(user_id, folder_path) WHERE folder_path IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_mailboxes_user_parent_path ON mailboxes(user_id, parent_path);
@@ -0,0 +1,10 @@
+-- Fair queue scheduling
+--
+-- The delivery worker takes the first due jobs of every tenant, ordered
+-- by priority and due time, and the queue statistics report per-tenant
+-- waits of the deliveries started in the last hour.
+
+CREATE INDEX IF NOT EXISTS idx_jobs_queue_status_tenant_due
+    ON jobs(queue, status, tenant_id, priority DESC, scheduled_at);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_queue_started ON jobs(queue, started_at);
@@ -0,0 +1,16 @@
+-- Attachment rescans
+--
+-- Messages are scanned when they are received. With rescanning on
+-- download enabled, a stored message whose scan is older than the rescan
+-- interval is checked again before one of its attachments is served; the
+-- latest result is kept here per stored message.
+
+CREATE TABLE IF NOT EXISTS attachment_scans (
+    storage_path TEXT PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    scanned_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Antivirus and URL reputation symbols that matched, empty when clean
+    flagged_symbols JSONB NOT NULL DEFAULT '[]'
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachment_scans_tenant ON attachment_scans(tenant_id);
@@ -0,0 +1,7 @@
+-- APOP shared secrets
+--
+-- APOP (RFC 1939) proves knowledge of a secret with an MD5 digest, so the
+-- server needs the secret itself rather than a password hash. Users who
+-- want APOP set a separate secret; NULL disables APOP for the user.
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS apop_secret TEXT;
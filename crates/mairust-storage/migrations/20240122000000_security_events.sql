@@ -0,0 +1,32 @@
+-- Account security events
+--
+-- Events that concern the security of a user's account (logins blocked by
+-- the brute-force protection, logins from a new network), shown to the
+-- user and sent as notification emails when the tenant enables them.
+
+CREATE TABLE IF NOT EXISTS security_events (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- login_blocked, new_login_location
+    event_type TEXT NOT NULL,
+    ip_address TEXT,
+    -- Protocol of the login (imap, pop3)
+    protocol TEXT,
+    details JSONB NOT NULL DEFAULT '{}',
+    -- A notification email was delivered to the user
+    notified BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_security_events_user ON security_events(user_id, created_at DESC);
+
+-- Networks each user has logged in from, to recognize new ones
+CREATE TABLE IF NOT EXISTS user_login_sources (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Network of the client address (IPv4 /24, IPv6 /64)
+    source TEXT NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, source)
+);
@@ -0,0 +1,7 @@
+-- IMAP SEARCH fields
+--
+-- SENTBEFORE, SENTON and SENTSINCE compare the day of the Date header,
+-- stored at delivery. Messages stored before this column, or without a
+-- parseable Date header, are searched by their internal date instead.
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS sent_date DATE;
@@ -0,0 +1,41 @@
+-- Unseen counter for IMAP STATUS
+--
+-- IMAP UNSEEN counts every message without \Seen, including those flagged
+-- \Deleted, so it cannot be answered from unread_count. The counter is kept
+-- by the same trigger as the others, in the transaction that changes the
+-- message, so STATUS and the poll of a selected mailbox read the mailbox
+-- row instead of counting its messages.
+
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS unseen_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE mailboxes mb
+SET unseen_count = c.unseen
+FROM (
+    SELECT mailbox_id, COUNT(*) FILTER (WHERE NOT seen) AS unseen
+    FROM messages
+    GROUP BY mailbox_id
+) c
+WHERE mb.id = c.mailbox_id;
+
+CREATE OR REPLACE FUNCTION maintain_mailbox_counters()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE mailboxes
+        SET message_count = message_count - 1,
+            unread_count = unread_count - CASE WHEN NOT OLD.seen AND NOT OLD.deleted THEN 1 ELSE 0 END,
+            unseen_count = unseen_count - CASE WHEN NOT OLD.seen THEN 1 ELSE 0 END
+        WHERE id = OLD.mailbox_id;
+    END IF;
+
+    IF TG_OP IN ('UPDATE', 'INSERT') THEN
+        UPDATE mailboxes
+        SET message_count = message_count + 1,
+            unread_count = unread_count + CASE WHEN NOT NEW.seen AND NOT NEW.deleted THEN 1 ELSE 0 END,
+            unseen_count = unseen_count + CASE WHEN NOT NEW.seen THEN 1 ELSE 0 END
+        WHERE id = NEW.mailbox_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+-- Sent-mail archive per user
+--
+-- A copy of every message the user sends is filed into this archive, a
+-- folder of the user or an email address. NULL keeps no archive beyond
+-- the tenant-wide one (tenant setting `sent_archive`).
+
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS sent_archive VARCHAR(320);
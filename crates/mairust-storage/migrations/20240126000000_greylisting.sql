@@ -0,0 +1,27 @@
+-- Greylisting of unauthenticated SMTP clients
+--
+-- A triplet is the network of the client address (IPv4 /24, IPv6 /64), the
+-- envelope sender and the recipient. Its first attempt is deferred; a retry
+-- after the configured delay passes it. Client networks that passed enough
+-- triplets are no longer greylisted.
+
+CREATE TABLE IF NOT EXISTS greylist_triplets (
+    client_network TEXT NOT NULL,
+    sender VARCHAR(320) NOT NULL,
+    recipient VARCHAR(320) NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Set once a retry was accepted
+    passed_at TIMESTAMPTZ,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (client_network, sender, recipient)
+);
+
+CREATE INDEX IF NOT EXISTS idx_greylist_triplets_last_seen ON greylist_triplets(last_seen_at);
+
+CREATE TABLE IF NOT EXISTS greylist_clients (
+    client_network TEXT PRIMARY KEY,
+    -- Triplets of the network that passed greylisting
+    passed INTEGER NOT NULL DEFAULT 0,
+    last_passed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
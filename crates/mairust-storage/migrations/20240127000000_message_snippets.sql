@@ -0,0 +1,10 @@
+-- Message snippets
+--
+-- A single-line excerpt of the decoded body text for message lists. NULL
+-- marks messages stored before snippets existed; the preview backfill
+-- rebuilds their snippet and body_preview from the stored file.
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS snippet TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_snippet_backfill
+    ON messages(id) WHERE snippet IS NULL;
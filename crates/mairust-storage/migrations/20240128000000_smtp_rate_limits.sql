@@ -0,0 +1,16 @@
+-- SMTP rate limit counters
+--
+-- Counters of the SMTP rate limits are kept per client IP address or
+-- authenticated user rather than per tenant. They have no tenant, and
+-- `scope` / `scope_key` say what they count (e.g. 'smtp_message' for
+-- 'user:alice@example.com'). Tenant counters keep scope 'tenant'.
+
+ALTER TABLE rate_limit_counters ALTER COLUMN tenant_id DROP NOT NULL;
+
+ALTER TABLE rate_limit_counters
+    ADD COLUMN IF NOT EXISTS scope VARCHAR(20) NOT NULL DEFAULT 'tenant',
+    ADD COLUMN IF NOT EXISTS scope_key VARCHAR(320) NOT NULL DEFAULT '';
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_rate_limit_counters_scope_window
+    ON rate_limit_counters(scope, scope_key, window_type, window_start)
+    WHERE tenant_id IS NULL;
@@ -0,0 +1,18 @@
+-- Protected display names
+--
+-- Names of executives and other people a tenant wants protected from
+-- display-name impersonation: inbound mail showing one of these names in
+-- its From header but sent from an outside address is marked with a
+-- warning. `email` is the person's own address, which may use the name
+-- even when it is outside the tenant's domains.
+
+CREATE TABLE IF NOT EXISTS protected_display_names (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    display_name VARCHAR(255) NOT NULL,
+    email VARCHAR(320),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_protected_display_names_unique
+    ON protected_display_names(tenant_id, LOWER(display_name), COALESCE(LOWER(email), ''));
@@ -0,0 +1,14 @@
+-- DNSBL allowlist
+--
+-- Client addresses and networks that are never looked up in DNS
+-- blocklists, for partners that end up listed (shared hosting, dynamic
+-- ranges) but whose mail has to get through. `network` is a CIDR range in
+-- canonical form; single addresses are stored as /32 or /128.
+
+CREATE TABLE IF NOT EXISTS dnsbl_allowlist (
+    id UUID PRIMARY KEY,
+    network VARCHAR(50) NOT NULL UNIQUE,
+    description TEXT,
+    created_by VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+-- Filing of subaddressed mail per user
+--
+-- Users who turn this on get mail for user+tag@domain filed into their
+-- folder named "tag", when they have one.
+
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS subaddress_filing BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,20 @@
+-- Read-only share links for messages
+--
+-- A share lets anyone with its link read one message, with its
+-- attachments, until it expires or is revoked. Only the SHA-256 hash of
+-- the link's token is stored.
+
+CREATE TABLE IF NOT EXISTS message_shares (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    view_count INTEGER NOT NULL DEFAULT 0,
+    last_viewed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_shares_message ON message_shares(message_id);
@@ -0,0 +1,42 @@
+-- Mailbox quota usage
+--
+-- used_bytes was never maintained. It is now kept by the mailbox counters
+-- trigger from the size of the stored messages, in the transaction that
+-- stores, moves or deletes a message, so every path (SMTP delivery, IMAP
+-- APPEND/COPY/MOVE/EXPUNGE, POP3, the API, retention) keeps it right.
+
+UPDATE mailboxes
+SET used_bytes = COALESCE(
+    (SELECT SUM(body_size) FROM messages WHERE messages.mailbox_id = mailboxes.id),
+    0
+);
+
+CREATE OR REPLACE FUNCTION maintain_mailbox_counters()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE mailboxes
+        SET message_count = message_count - 1,
+            unread_count = unread_count - CASE WHEN NOT OLD.seen AND NOT OLD.deleted THEN 1 ELSE 0 END,
+            unseen_count = unseen_count - CASE WHEN NOT OLD.seen THEN 1 ELSE 0 END,
+            used_bytes = used_bytes - OLD.body_size
+        WHERE id = OLD.mailbox_id;
+    END IF;
+
+    IF TG_OP IN ('UPDATE', 'INSERT') THEN
+        UPDATE mailboxes
+        SET message_count = message_count + 1,
+            unread_count = unread_count + CASE WHEN NOT NEW.seen AND NOT NEW.deleted THEN 1 ELSE 0 END,
+            unseen_count = unseen_count + CASE WHEN NOT NEW.seen THEN 1 ELSE 0 END,
+            used_bytes = used_bytes + NEW.body_size
+        WHERE id = NEW.mailbox_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trigger_maintain_mailbox_counters ON messages;
+CREATE TRIGGER trigger_maintain_mailbox_counters
+AFTER INSERT OR DELETE OR UPDATE OF mailbox_id, seen, deleted, body_size ON messages
+FOR EACH ROW EXECUTE FUNCTION maintain_mailbox_counters();
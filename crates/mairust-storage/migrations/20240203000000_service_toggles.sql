@@ -0,0 +1,7 @@
+-- IMAP and POP3 access per user
+--
+-- A user can be restricted to webmail and the API by switching off the
+-- protocols here, e.g. '{"imap": false, "pop3": false}'. Protocols that are
+-- not listed are enabled. Tenants use `services_enabled` in their settings.
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS services_enabled JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,32 @@
+-- DMARC results of received mail, counted for aggregate reports
+--
+-- One row per report day, policy domain and distinct result; messages with
+-- the same result from the same source add to `message_count`. Optional
+-- values are stored as '' so that they can be part of the key. The rows of a
+-- day are deleted when its report is sent.
+
+CREATE TABLE IF NOT EXISTS dmarc_report_records (
+    report_date DATE NOT NULL,
+    policy_domain TEXT NOT NULL,
+    source_ip TEXT NOT NULL,
+    header_from TEXT NOT NULL,
+    envelope_from TEXT NOT NULL DEFAULT '',
+    dkim_domain TEXT NOT NULL DEFAULT '',
+    dkim_result TEXT NOT NULL,
+    spf_domain TEXT NOT NULL DEFAULT '',
+    spf_result TEXT NOT NULL,
+    dkim_aligned BOOLEAN NOT NULL,
+    spf_aligned BOOLEAN NOT NULL,
+    disposition TEXT NOT NULL,
+    -- Why the disposition differs from the published policy (RFC 7489
+    -- PolicyOverrideType), and a comment
+    override_reason TEXT NOT NULL DEFAULT '',
+    override_comment TEXT NOT NULL DEFAULT '',
+    message_count INTEGER NOT NULL DEFAULT 1,
+    -- Published policy (p, sp, pct, adkim, aspf, rua) when last seen
+    policy JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (report_date, policy_domain, source_ip, header_from, envelope_from,
+                 dkim_domain, dkim_result, spf_domain, spf_result, dkim_aligned,
+                 spf_aligned, disposition, override_reason, override_comment)
+);
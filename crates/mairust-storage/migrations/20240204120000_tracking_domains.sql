@@ -0,0 +1,44 @@
+-- Tracking domains of click-tracked campaign links
+--
+-- A tenant points a domain (e.g. `links.example.com`) at the server with a
+-- CNAME. Once the CNAME resolves, the domain is verified and a certificate
+-- is requested for it over ACME; with the certificate it becomes active and
+-- the tracked links of the tenant's campaigns use it.
+
+CREATE TABLE IF NOT EXISTS tracking_domains (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    domain VARCHAR(255) NOT NULL UNIQUE,
+    -- pending, verified, active or failed
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    verified_at TIMESTAMPTZ,
+    certificate_pem TEXT,
+    private_key_pem TEXT,
+    certificate_issued_at TIMESTAMPTZ,
+    -- Why verification or the last certificate order failed
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_tracking_domains_tenant ON tracking_domains(tenant_id);
+
+-- Pending ACME HTTP-01 challenges, answered on
+-- /.well-known/acme-challenge/<token> by the web server
+CREATE TABLE IF NOT EXISTS acme_challenges (
+    token VARCHAR(255) PRIMARY KEY,
+    key_authorization TEXT NOT NULL,
+    domain VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- ACME accounts, one per directory
+CREATE TABLE IF NOT EXISTS acme_accounts (
+    directory_url TEXT PRIMARY KEY,
+    credentials JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- First click on a tracked link of each campaign message, so that a
+-- campaign counts each clicking recipient once
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS clicked_at TIMESTAMPTZ;
@@ -0,0 +1,77 @@
+-- DMARC aggregate and TLS-RPT reports received about tenant domains
+--
+-- Reports sent to a domain's report address are parsed into these tables so
+-- that the sources sending mail as the domain can be charted. A report is
+-- stored once per domain, however many times its reporter sends it.
+
+CREATE TABLE IF NOT EXISTS received_dmarc_reports (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    domain_id UUID NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    org_name TEXT NOT NULL,
+    email TEXT,
+    report_id TEXT NOT NULL,
+    begin_at TIMESTAMPTZ NOT NULL,
+    end_at TIMESTAMPTZ NOT NULL,
+    -- Policy the reporter found published (domain, p, sp, pct, adkim, aspf)
+    policy JSONB NOT NULL,
+    -- Messages in the report, and those that passed neither aligned DKIM
+    -- nor aligned SPF
+    message_count BIGINT NOT NULL,
+    fail_count BIGINT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (domain_id, org_name, report_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_received_dmarc_reports_domain
+    ON received_dmarc_reports(domain_id, end_at DESC);
+
+-- Rows of a DMARC aggregate report: messages from one source with the same
+-- identifiers and results
+CREATE TABLE IF NOT EXISTS received_dmarc_report_records (
+    id UUID PRIMARY KEY,
+    report_id UUID NOT NULL REFERENCES received_dmarc_reports(id) ON DELETE CASCADE,
+    source_ip TEXT NOT NULL,
+    message_count BIGINT NOT NULL,
+    -- none, quarantine or reject
+    disposition TEXT NOT NULL,
+    -- Aligned DKIM and SPF results (pass or fail)
+    dkim TEXT NOT NULL,
+    spf TEXT NOT NULL,
+    -- Policy overrides: [{"type": ..., "comment": ...}]
+    reasons JSONB NOT NULL DEFAULT '[]',
+    header_from TEXT NOT NULL,
+    envelope_from TEXT,
+    -- Raw DKIM and SPF results: [{"domain": ..., "result": ...}]
+    dkim_results JSONB NOT NULL DEFAULT '[]',
+    spf_results JSONB NOT NULL DEFAULT '[]'
+);
+
+CREATE INDEX IF NOT EXISTS idx_received_dmarc_report_records_report
+    ON received_dmarc_report_records(report_id);
+
+-- Policies of a TLS-RPT report (RFC 8460), one row per policy
+CREATE TABLE IF NOT EXISTS received_tls_reports (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    domain_id UUID NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    org_name TEXT NOT NULL,
+    contact_info TEXT,
+    report_id TEXT NOT NULL,
+    begin_at TIMESTAMPTZ NOT NULL,
+    end_at TIMESTAMPTZ NOT NULL,
+    -- sts, tlsa or no-policy-found
+    policy_type TEXT NOT NULL,
+    policy_domain TEXT NOT NULL,
+    policy_strings JSONB NOT NULL DEFAULT '[]',
+    mx_hosts JSONB NOT NULL DEFAULT '[]',
+    successful_sessions BIGINT NOT NULL,
+    failed_sessions BIGINT NOT NULL,
+    -- failure-details of the report, as sent
+    failure_details JSONB NOT NULL DEFAULT '[]',
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (domain_id, org_name, report_id, policy_type, policy_domain)
+);
+
+CREATE INDEX IF NOT EXISTS idx_received_tls_reports_domain
+    ON received_tls_reports(domain_id, end_at DESC);
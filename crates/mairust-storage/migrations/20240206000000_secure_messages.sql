@@ -0,0 +1,45 @@
+-- Secure messages
+--
+-- A secure message is not delivered itself: the recipient is sent a link,
+-- and the message opens on the web UI after entering a passcode. The raw
+-- message is stored encrypted with AES-256-GCM. Passcodes are either
+-- pre-shared by the sender or one-time codes emailed to the recipient on
+-- request; only their Argon2 hashes are stored, as is only the SHA-256
+-- hash of the link's token.
+
+CREATE TABLE IF NOT EXISTS secure_messages (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    sender VARCHAR(255) NOT NULL,
+    recipient VARCHAR(255) NOT NULL,
+    -- Raw message: 12-byte nonce followed by the ciphertext
+    sealed_message BYTEA NOT NULL,
+    -- Pre-shared passcode; NULL when codes are emailed to the recipient
+    passcode_hash TEXT,
+    -- Last emailed one-time code, cleared once used
+    code_hash TEXT,
+    code_sent_at TIMESTAMPTZ,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    open_count INTEGER NOT NULL DEFAULT 0,
+    last_opened_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_secure_messages_tenant ON secure_messages(tenant_id, created_at DESC);
+
+-- Access log of secure messages: codes sent, opens and failed passcodes
+CREATE TABLE IF NOT EXISTS secure_message_access (
+    id UUID PRIMARY KEY,
+    secure_message_id UUID NOT NULL REFERENCES secure_messages(id) ON DELETE CASCADE,
+    event VARCHAR(32) NOT NULL,
+    ip_address VARCHAR(45),
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_secure_message_access_message
+    ON secure_message_access(secure_message_id, created_at);
@@ -0,0 +1,61 @@
+-- Bulk mailbox provisioning
+--
+-- A batch of users with mailboxes, submitted in one call and created by a
+-- background job row by row. Each row records its own result, so a batch
+-- interrupted halfway resumes where it stopped, and rows whose address
+-- already exists in the tenant are reported rather than failed. A batch
+-- submitted again with the same idempotency key returns the first one.
+
+CREATE TABLE IF NOT EXISTS provisioning_batches (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    idempotency_key VARCHAR(255),
+    -- 'generate' (random passwords) or 'invite' (emailed invite links)
+    password_mode VARCHAR(16) NOT NULL,
+    -- pending, running, completed
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    total_rows INTEGER NOT NULL,
+    created_count INTEGER NOT NULL DEFAULT 0,
+    existing_count INTEGER NOT NULL DEFAULT 0,
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ,
+    UNIQUE (tenant_id, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_provisioning_batches_tenant
+    ON provisioning_batches(tenant_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS provisioning_rows (
+    batch_id UUID NOT NULL REFERENCES provisioning_batches(id) ON DELETE CASCADE,
+    row_number INTEGER NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    name VARCHAR(255),
+    role VARCHAR(50) NOT NULL DEFAULT 'user',
+    quota_bytes BIGINT,
+    invite_email VARCHAR(255),
+    -- Password to set, cleared once the row is processed
+    password TEXT,
+    -- pending, created, exists, failed
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    mailbox_id UUID REFERENCES mailboxes(id) ON DELETE SET NULL,
+    error TEXT,
+    processed_at TIMESTAMPTZ,
+    PRIMARY KEY (batch_id, row_number)
+);
+
+-- Invites to choose a password on the web UI, for users created without one
+CREATE TABLE IF NOT EXISTS user_invites (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    email VARCHAR(255) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_invites_user ON user_invites(user_id);
@@ -0,0 +1,24 @@
+-- Sender domain reputation
+--
+-- Mail received from unauthenticated sessions, per envelope sender domain.
+-- `messages`, `spam` and `errors` decay by half every `half_life_secs`;
+-- they were last decayed at `decayed_at`, and readers decay them further to
+-- the current time. The `total_` columns count everything since the domain
+-- was first seen or last reset.
+
+CREATE TABLE IF NOT EXISTS sender_reputation (
+    domain VARCHAR(255) PRIMARY KEY,
+    messages DOUBLE PRECISION NOT NULL DEFAULT 0,
+    spam DOUBLE PRECISION NOT NULL DEFAULT 0,
+    errors DOUBLE PRECISION NOT NULL DEFAULT 0,
+    half_life_secs DOUBLE PRECISION NOT NULL,
+    decayed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    total_messages BIGINT NOT NULL DEFAULT 0,
+    total_spam BIGINT NOT NULL DEFAULT 0,
+    total_errors BIGINT NOT NULL DEFAULT 0,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_sender_reputation_last_seen
+    ON sender_reputation(last_seen_at DESC);
@@ -0,0 +1,54 @@
+-- Warm standby replication
+--
+-- `replication_heartbeat` holds one row that the primary updates every few
+-- seconds; it is replicated to standbys, which measure their lag from it.
+--
+-- `replication_file_queue` is local to a standby and never published: it
+-- lists message files the standby has yet to copy from the primary, or to
+-- delete because their messages are gone. Rows are queued by a trigger
+-- that only fires while logical replication applies changes
+-- (ENABLE REPLICA), so it stays empty on the primary.
+
+CREATE TABLE IF NOT EXISTS replication_heartbeat (
+    id SMALLINT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    beat_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS replication_file_queue (
+    storage_path TEXT PRIMARY KEY,
+    -- 'copy' or 'delete'
+    action VARCHAR(16) NOT NULL DEFAULT 'copy',
+    queued_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_replication_file_queue_due
+    ON replication_file_queue(next_attempt_at);
+
+CREATE OR REPLACE FUNCTION queue_replicated_message_file() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' OR (TG_OP = 'UPDATE' AND OLD.storage_path <> NEW.storage_path) THEN
+        INSERT INTO replication_file_queue (storage_path, action)
+        VALUES (OLD.storage_path, 'delete')
+        ON CONFLICT (storage_path) DO UPDATE SET
+            action = 'delete', queued_at = NOW(), attempts = 0, last_error = NULL,
+            next_attempt_at = NOW();
+    END IF;
+    IF TG_OP = 'INSERT' OR (TG_OP = 'UPDATE' AND OLD.storage_path <> NEW.storage_path) THEN
+        INSERT INTO replication_file_queue (storage_path, action)
+        VALUES (NEW.storage_path, 'copy')
+        ON CONFLICT (storage_path) DO UPDATE SET
+            action = 'copy', queued_at = NOW(), attempts = 0, last_error = NULL,
+            next_attempt_at = NOW();
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS messages_replicated_file ON messages;
+CREATE TRIGGER messages_replicated_file
+    AFTER INSERT OR UPDATE OF storage_path OR DELETE ON messages
+    FOR EACH ROW EXECUTE FUNCTION queue_replicated_message_file();
+ALTER TABLE messages ENABLE REPLICA TRIGGER messages_replicated_file;
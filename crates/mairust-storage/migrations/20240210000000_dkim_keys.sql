@@ -0,0 +1,39 @@
+-- DKIM signing keys
+--
+-- A domain can have several DKIM keys, each under its own selector. A key
+-- is 'pending' while its DNS record propagates, 'active' once messages are
+-- signed with it, and 'retired' when a newer key of the same algorithm
+-- takes over; retired keys stay listed until their records can be removed.
+-- Several active keys of different algorithms (rsa-sha256 and
+-- ed25519-sha256) sign each message together.
+--
+-- The single key stored on the domain row is copied in as an active key;
+-- the domain columns are kept for domains that have no keys here.
+
+CREATE TABLE IF NOT EXISTS dkim_keys (
+    id UUID PRIMARY KEY,
+    domain_id UUID NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    selector VARCHAR(63) NOT NULL,
+    -- 'rsa-sha256' or 'ed25519-sha256'
+    algorithm VARCHAR(16) NOT NULL DEFAULT 'rsa-sha256',
+    private_key TEXT NOT NULL,
+    -- 'pending', 'active' or 'retired'
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    activate_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    activated_at TIMESTAMPTZ,
+    retired_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (domain_id, selector)
+);
+
+CREATE INDEX IF NOT EXISTS idx_dkim_keys_domain ON dkim_keys(domain_id, status);
+CREATE INDEX IF NOT EXISTS idx_dkim_keys_pending
+    ON dkim_keys(activate_at) WHERE status = 'pending';
+
+INSERT INTO dkim_keys (id, domain_id, selector, algorithm, private_key, status,
+                       activate_at, activated_at, created_at)
+SELECT gen_random_uuid(), id, dkim_selector, 'rsa-sha256', dkim_private_key, 'active',
+       created_at, created_at, created_at
+FROM domains
+WHERE dkim_selector IS NOT NULL AND dkim_private_key IS NOT NULL
+ON CONFLICT (domain_id, selector) DO NOTHING;
@@ -0,0 +1,19 @@
+-- Sieve scripts
+--
+-- Scripts users manage over ManageSieve (RFC 5804), by name. At most one
+-- script of a user is active; it is the one meant to filter their mail.
+
+CREATE TABLE IF NOT EXISTS sieve_scripts (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    content TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, name)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sieve_scripts_active
+    ON sieve_scripts(user_id) WHERE active;
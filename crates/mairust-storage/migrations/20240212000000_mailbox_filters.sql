@@ -0,0 +1,28 @@
+-- Mailbox filters
+--
+-- Simple server-side rules for users who do not write Sieve. Each filter
+-- has policy-style conditions on the sender, recipients, subject and
+-- headers, and actions that file, tag, mark read, forward or delete the
+-- message. Filters run in position order when mail is delivered to the
+-- mailbox.
+
+CREATE TABLE IF NOT EXISTS mailbox_filters (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    mailbox_id UUID NOT NULL REFERENCES mailboxes(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    -- Filters run in ascending position
+    position INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    -- Array of policy conditions, all of which must match
+    conditions JSONB NOT NULL DEFAULT '[]',
+    -- Array of filter actions
+    actions JSONB NOT NULL DEFAULT '[]',
+    -- Later filters are skipped when this one matches
+    stop_processing BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_mailbox_filters_mailbox
+    ON mailbox_filters(mailbox_id, position);
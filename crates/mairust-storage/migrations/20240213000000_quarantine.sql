@@ -0,0 +1,23 @@
+-- Quarantine
+--
+-- Messages held by the virus scan, the spam filter or a policy are stored
+-- in a hidden system folder of the mailbox they were addressed to: a
+-- mailbox row with special use 'quarantine' and no folder path, so IMAP,
+-- POP3 and the folder lists never show it. `quarantine_of` links the
+-- folder to its mailbox, which releasing puts messages back into.
+-- Quarantined messages are flagged so they can be listed per tenant.
+
+ALTER TABLE mailboxes ADD COLUMN IF NOT EXISTS quarantine_of UUID
+    REFERENCES mailboxes(id) ON DELETE CASCADE;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_mailboxes_quarantine_of
+    ON mailboxes(quarantine_of) WHERE quarantine_of IS NOT NULL;
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS quarantined BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_messages_quarantined
+    ON messages(tenant_id, received_at DESC) WHERE quarantined;
+
+-- Daily digest of quarantined messages, sent to users who turn it on
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS quarantine_digest BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS quarantine_digest_sent_at TIMESTAMPTZ;
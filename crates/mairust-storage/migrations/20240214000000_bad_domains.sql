@@ -0,0 +1,15 @@
+-- Bad domains
+--
+-- Local URI blocklist: domains whose links mark a message as spam, for
+-- campaigns the public lists do not know yet. An entry also covers the
+-- subdomains of its domain. `score` replaces the configured bad-domain
+-- score when set.
+
+CREATE TABLE IF NOT EXISTS bad_domains (
+    id UUID PRIMARY KEY,
+    domain VARCHAR(255) NOT NULL UNIQUE,
+    score DOUBLE PRECISION,
+    description TEXT,
+    created_by VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
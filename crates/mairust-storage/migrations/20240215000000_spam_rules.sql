@@ -0,0 +1,24 @@
+-- Spam rules
+--
+-- Rules a tenant adds to the rule-based spam filter. `scope` is the rule
+-- type: `header`, `body`, `from`, `subject` and `url` match `pattern` as a
+-- regular expression; `has_header` and `missing_header` name a header;
+-- `body_length` is the longest body that matches. A rule named like a
+-- built-in rule replaces it for the tenant, so a disabled one turns the
+-- built-in rule off.
+
+CREATE TABLE IF NOT EXISTS spam_rules (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    description TEXT,
+    scope VARCHAR(32) NOT NULL,
+    pattern TEXT NOT NULL,
+    score DOUBLE PRECISION NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (tenant_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_spam_rules_tenant ON spam_rules(tenant_id);
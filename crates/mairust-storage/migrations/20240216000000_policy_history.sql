@@ -0,0 +1,58 @@
+-- Policy history
+--
+-- `policy_versions` keeps a snapshot of a policy rule after every change,
+-- with who made it, so that the rule in force at any time can be looked
+-- up. Versions are kept after the policy is deleted; the last one has the
+-- change `deleted`.
+--
+-- `policy_matches` records every inbound policy that matched a received
+-- message, the actions it has and what happened to the message.
+
+CREATE TABLE IF NOT EXISTS policy_versions (
+    id UUID PRIMARY KEY,
+    policy_id UUID NOT NULL,
+    tenant_id UUID REFERENCES tenants(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    -- created, updated, enabled, disabled or deleted
+    change VARCHAR(20) NOT NULL,
+    policy JSONB NOT NULL,
+    actor_type VARCHAR(50) NOT NULL,
+    actor_id VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (policy_id, version)
+);
+
+CREATE INDEX IF NOT EXISTS idx_policy_versions_tenant
+    ON policy_versions(tenant_id, created_at DESC);
+
+-- Existing policies start at version 1
+INSERT INTO policy_versions (id, policy_id, tenant_id, version, change, policy, actor_type,
+                             created_at)
+SELECT gen_random_uuid(), p.id, p.tenant_id, 1, 'created', to_jsonb(p), 'system', p.updated_at
+FROM policy_rules p
+ON CONFLICT (policy_id, version) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS policy_matches (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    policy_id UUID NOT NULL,
+    policy_name VARCHAR(255) NOT NULL,
+    -- Message-ID header of the message
+    message_id TEXT,
+    sender TEXT,
+    recipients JSONB NOT NULL DEFAULT '[]',
+    client_ip VARCHAR(45),
+    -- Action types of the policy
+    actions JSONB NOT NULL DEFAULT '[]',
+    -- rejected, deferred, quarantined or accepted
+    outcome VARCHAR(20) NOT NULL,
+    smtp_reply TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_policy_matches_tenant
+    ON policy_matches(tenant_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_policy_matches_policy
+    ON policy_matches(policy_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_policy_matches_message
+    ON policy_matches(tenant_id, message_id);
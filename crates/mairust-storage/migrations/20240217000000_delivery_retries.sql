@@ -0,0 +1,24 @@
+-- Delivery retries
+--
+-- Delivery jobs are retried on a configurable schedule until the message
+-- reaches its maximum queue lifetime; it is then bounced and the job is
+-- kept with the status `dead_letter`. `scheduled_at` of a pending job is
+-- the time of its next attempt.
+--
+-- The queue processor sleeps until the next attempt is due. A pending
+-- delivery job that is queued or rescheduled, by any process, wakes it
+-- through a notification on the `delivery_queue` channel.
+
+CREATE OR REPLACE FUNCTION notify_delivery_queue() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('delivery_queue', NEW.id::TEXT);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS jobs_notify_delivery_queue ON jobs;
+CREATE TRIGGER jobs_notify_delivery_queue
+    AFTER INSERT OR UPDATE OF status, scheduled_at ON jobs
+    FOR EACH ROW
+    WHEN (NEW.queue = 'delivery' AND NEW.status = 'pending')
+    EXECUTE FUNCTION notify_delivery_queue();
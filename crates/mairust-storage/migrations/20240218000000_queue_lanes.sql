@@ -0,0 +1,18 @@
+-- Queue lanes
+--
+-- Delivery jobs are queued in a priority lane: `transactional`, `campaign`
+-- or `bulk`. The queue processor drains transactional mail first, and
+-- gives each lane its own concurrency budget, so that a large campaign
+-- cannot hold up mail such as password resets. `priority` still orders
+-- the jobs within a lane.
+
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS lane VARCHAR(20) NOT NULL DEFAULT 'transactional';
+
+UPDATE jobs
+SET lane = payload->>'lane'
+WHERE queue = 'delivery'
+  AND payload->>'lane' IN ('campaign', 'bulk');
+
+-- Due jobs of a lane
+CREATE INDEX IF NOT EXISTS idx_jobs_queue_status_lane_scheduled
+    ON jobs(queue, status, lane, scheduled_at);
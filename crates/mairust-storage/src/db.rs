@@ -2,6 +2,7 @@
 
 use mairust_common::config::DatabaseConfig;
 use mairust_common::{Error, Result};
+use sqlx::error::ErrorKind;
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use std::time::Duration;
 use tracing::info;
@@ -35,6 +36,18 @@ impl DatabasePool {
         Ok(Self { pool })
     }
 
+    /// Create a pool that connects on first use
+    pub fn connect_lazy(config: &DatabaseConfig) -> Result<Self> {
+        let url = Self::build_url(config)?;
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(Duration::from_secs(30))
+            .connect_lazy(&url)
+            .map_err(|e| Error::Config(format!("Invalid database URL: {}", e)))?;
+        Ok(Self { pool })
+    }
+
     /// Build database URL from configuration
     fn build_url(config: &DatabaseConfig) -> Result<String> {
         match config.backend.as_str() {
@@ -88,3 +101,71 @@ impl Database for DatabasePool {
         &self.pool
     }
 }
+
+/// Convert a query error into a MaiRust error
+///
+/// Constraint violations and missing rows are reported to API clients as
+/// what the request did wrong (a duplicate name, a reference to a missing
+/// row), but are never permanent SMTP failures; see
+/// [`Error::DatabaseRefused`]. An exhausted pool is temporary. Anything
+/// else is a database error.
+pub fn db_error(e: sqlx::Error) -> Error {
+    let refused = |error| Error::DatabaseRefused(Box::new(error));
+    match &e {
+        sqlx::Error::Database(db) => match db.kind() {
+            ErrorKind::UniqueViolation => {
+                refused(Error::Conflict("Resource already exists".to_string()))
+            }
+            ErrorKind::ForeignKeyViolation => refused(Error::Validation(
+                "Referenced resource does not exist".to_string(),
+            )),
+            ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                refused(Error::Validation("Invalid value".to_string()))
+            }
+            _ => Error::Database(e.to_string()),
+        },
+        sqlx::Error::RowNotFound => refused(Error::NotFound("Resource not found".to_string())),
+        sqlx::Error::PoolTimedOut => {
+            Error::Unavailable("Database temporarily unavailable".to_string())
+        }
+        _ => Error::Database(e.to_string()),
+    }
+}
+
+/// Convert the error of a repository returning `anyhow::Result` into a
+/// MaiRust error, classifying query errors like [`db_error`]
+pub fn storage_error(e: anyhow::Error) -> Error {
+    match e.downcast::<sqlx::Error>() {
+        Ok(e) => db_error(e),
+        Err(e) => match e.downcast::<Error>() {
+            Ok(error) => error,
+            Err(e) => Error::Other(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_error() {
+        let error = db_error(sqlx::Error::RowNotFound);
+        assert_eq!(error.code(), "NOT_FOUND");
+        assert_eq!(error.smtp_reply().0, 451);
+        assert_eq!(db_error(sqlx::Error::PoolTimedOut).status_code(), 503);
+        assert_eq!(db_error(sqlx::Error::PoolClosed).code(), "DATABASE_ERROR");
+    }
+
+    #[test]
+    fn test_storage_error() {
+        let error = storage_error(anyhow::Error::new(sqlx::Error::RowNotFound));
+        assert_eq!(error.status_code(), 404);
+        let error = storage_error(anyhow::Error::new(sqlx::Error::PoolTimedOut));
+        assert_eq!(error.status_code(), 503);
+        let error = storage_error(Error::Conflict("Exists".to_string()).into());
+        assert_eq!(error.status_code(), 409);
+        let error = storage_error(anyhow::anyhow!("invalid key"));
+        assert_eq!(error.code(), "INTERNAL_ERROR");
+    }
+}
@@ -1,11 +1,12 @@
 //! File storage abstraction
 
+use crate::layout::StorageLayout;
 use async_trait::async_trait;
 use mairust_common::config::StorageConfig;
 use mairust_common::{Error, Result};
 use std::path::PathBuf;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tracing::{debug, info};
 
 /// File storage trait
@@ -17,6 +18,19 @@ pub trait FileStorage: Send + Sync {
     /// Read a file
     async fn read(&self, path: &str) -> Result<Vec<u8>>;
 
+    /// Read up to `len` bytes starting at `offset`
+    ///
+    /// Returns fewer bytes when the file ends first. Used to stream large
+    /// files in chunks without loading them whole.
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let data = self.read(path).await?;
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let end = start.saturating_add(len).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
     /// Retrieve a file (alias for read)
     async fn retrieve(&self, path: &str) -> Result<Vec<u8>> {
         self.read(path).await
@@ -30,17 +44,30 @@ pub trait FileStorage: Send + Sync {
 
     /// Get file size
     async fn size(&self, path: &str) -> Result<u64>;
+
+    /// Path a new message file is stored at
+    ///
+    /// Follows the flat layout unless the storage has another one.
+    fn message_path(
+        &self,
+        tenant_id: uuid::Uuid,
+        mailbox_id: uuid::Uuid,
+        message_id: uuid::Uuid,
+    ) -> String {
+        StorageLayout::default().message_path(tenant_id, mailbox_id, message_id)
+    }
 }
 
 /// Local filesystem storage
 pub struct LocalStorage {
     base_path: PathBuf,
+    layout: StorageLayout,
 }
 
 impl LocalStorage {
     /// Create a new local storage instance from config
     pub fn new(config: &StorageConfig) -> Result<Self> {
-        Self::from_path(&config.path)
+        Ok(Self::from_path(&config.path)?.with_layout(StorageLayout::from(&config.layout)))
     }
 
     /// Create a new local storage instance from a path string
@@ -58,9 +85,16 @@ impl LocalStorage {
 
         Ok(Self {
             base_path: path.to_path_buf(),
+            layout: StorageLayout::default(),
         })
     }
 
+    /// Store new message files under `layout`
+    pub fn with_layout(mut self, layout: StorageLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
     /// Get full path for a relative path, with path traversal protection
     fn full_path(&self, path: &str) -> std::result::Result<PathBuf, Error> {
         // Reject paths containing traversal sequences
@@ -142,6 +176,10 @@ impl FileStorage for LocalStorage {
         file.write_all(data)
             .await
             .map_err(|e| Error::Storage(format!("Failed to write file: {}", e)))?;
+        // Tokio completes writes in the background; finish before returning
+        file.flush()
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write file: {}", e)))?;
 
         debug!(path = %path, size = data.len(), "Stored file");
 
@@ -165,6 +203,25 @@ impl FileStorage for LocalStorage {
         Ok(data)
     }
 
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let full_path = self.full_path(path)?;
+
+        let mut file = fs::File::open(&full_path)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to open file: {}", e)))?;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to seek file: {}", e)))?;
+
+        let mut data = Vec::new();
+        file.take(len as u64)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to read file: {}", e)))?;
+
+        Ok(data)
+    }
+
     async fn delete(&self, path: &str) -> Result<()> {
         let full_path = self.full_path(path)?;
 
@@ -191,6 +248,15 @@ impl FileStorage for LocalStorage {
 
         Ok(metadata.len())
     }
+
+    fn message_path(
+        &self,
+        tenant_id: uuid::Uuid,
+        mailbox_id: uuid::Uuid,
+        message_id: uuid::Uuid,
+    ) -> String {
+        self.layout.message_path(tenant_id, mailbox_id, message_id)
+    }
 }
 
 /// Message storage helper
@@ -204,18 +270,13 @@ impl MessageStorage {
         Self { storage }
     }
 
-    /// Generate storage path for a message
+    /// Generate storage path for a message in the flat layout
     pub fn generate_path(
         tenant_id: &uuid::Uuid,
         mailbox_id: &uuid::Uuid,
         message_id: &uuid::Uuid,
     ) -> String {
-        format!(
-            "{}/{}/{}.eml",
-            tenant_id,
-            mailbox_id,
-            message_id
-        )
+        StorageLayout::default().message_path(*tenant_id, *mailbox_id, *message_id)
     }
 
     /// Store a message
@@ -226,7 +287,9 @@ impl MessageStorage {
         message_id: &uuid::Uuid,
         data: &[u8],
     ) -> Result<String> {
-        let path = Self::generate_path(tenant_id, mailbox_id, message_id);
+        let path = self
+            .storage
+            .message_path(*tenant_id, *mailbox_id, *message_id);
         self.storage.store(&path, data).await
     }
 
@@ -268,6 +331,7 @@ mod tests {
             backend: "fs".to_string(),
             path: temp_dir.path().to_path_buf(),
             s3: None,
+            layout: Default::default(),
         };
 
         let storage = LocalStorage::new(&config).unwrap();
@@ -285,6 +349,20 @@ mod tests {
         let read_data = storage.read("test/message.eml").await.unwrap();
         assert_eq!(read_data, data);
 
+        // Test ranged read
+        let range = storage.read_range("test/message.eml", 7, 5).await.unwrap();
+        assert_eq!(range, b"World");
+        let range = storage
+            .read_range("test/message.eml", 7, 100)
+            .await
+            .unwrap();
+        assert_eq!(range, b"World!");
+        let range = storage
+            .read_range("test/message.eml", 100, 5)
+            .await
+            .unwrap();
+        assert!(range.is_empty());
+
         // Test size
         let size = storage.size("test/message.eml").await.unwrap();
         assert_eq!(size, data.len() as u64);
@@ -301,6 +379,7 @@ mod tests {
             backend: "fs".to_string(),
             path: temp_dir.path().to_path_buf(),
             s3: None,
+            layout: Default::default(),
         };
 
         let storage = LocalStorage::new(&config).unwrap();
@@ -318,4 +397,40 @@ mod tests {
         // Normal paths should work
         assert!(storage.store("safe/path/file.eml", b"ok").await.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_message_path_follows_layout() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = StorageConfig {
+            backend: "fs".to_string(),
+            path: temp_dir.path().to_path_buf(),
+            s3: None,
+            layout: Default::default(),
+        };
+        let (tenant, mailbox, message) = (
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        );
+
+        let flat = LocalStorage::new(&config).unwrap();
+        assert_eq!(
+            flat.message_path(tenant, mailbox, message),
+            MessageStorage::generate_path(&tenant, &mailbox, &message)
+        );
+
+        config.layout.scheme = mairust_common::config::StorageLayoutScheme::Hashed;
+        config.layout.generation = 2;
+        let hashed = LocalStorage::new(&config).unwrap();
+        let path = hashed.message_path(tenant, mailbox, message);
+        assert!(path.starts_with(&format!("g2/{}/{}/", tenant, mailbox)));
+
+        let messages = MessageStorage::new(Box::new(hashed));
+        let stored = messages
+            .store_message(&tenant, &mailbox, &message, b"Subject: hi\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(stored, path);
+        assert!(temp_dir.path().join(&path).exists());
+    }
 }
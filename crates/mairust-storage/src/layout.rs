@@ -0,0 +1,194 @@
+//! Storage path layout of message files
+//!
+//! The flat layout keeps every file of a mailbox in one directory, which
+//! grows to millions of entries on busy servers. The hashed layout fans
+//! files out over directories named after a hash of the message ID, and a
+//! generation directory lets a new layout start in a fresh tree.
+//!
+//! Messages store their path, so changing the layout only affects new
+//! files. [`StorageLayout::parse_message_path`] recognises paths of every
+//! layout, which is what relocation relies on.
+
+use mairust_common::config::{StorageLayoutConfig, StorageLayoutScheme};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Builds the storage paths of message files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageLayout {
+    scheme: StorageLayoutScheme,
+    fanout: u8,
+    generation: u32,
+}
+
+/// Components of a message file path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessagePath {
+    pub tenant_id: Uuid,
+    pub mailbox_id: Uuid,
+    pub message_id: Uuid,
+}
+
+impl From<&StorageLayoutConfig> for StorageLayout {
+    fn from(config: &StorageLayoutConfig) -> Self {
+        Self {
+            scheme: config.scheme,
+            fanout: config.fanout.min(StorageLayoutConfig::MAX_FANOUT),
+            generation: config.generation,
+        }
+    }
+}
+
+impl StorageLayout {
+    /// Path of a message file, relative to the storage root
+    pub fn message_path(&self, tenant_id: Uuid, mailbox_id: Uuid, message_id: Uuid) -> String {
+        let mut path = String::new();
+        if self.generation > 0 {
+            path.push_str(&format!("g{}/", self.generation));
+        }
+        path.push_str(&format!("{}/{}/", tenant_id, mailbox_id));
+        if self.scheme == StorageLayoutScheme::Hashed {
+            let hash = hex::encode(Sha256::digest(message_id.to_string().as_bytes()));
+            for level in 0..self.fanout as usize {
+                path.push_str(&hash[level * 2..level * 2 + 2]);
+                path.push('/');
+            }
+        }
+        path.push_str(&format!("{}.eml", message_id));
+        path
+    }
+
+    /// Split a message file path written under any layout
+    ///
+    /// Returns `None` for files that are not message files, such as
+    /// inbound route payloads and journal reports.
+    pub fn parse_message_path(path: &str) -> Option<MessagePath> {
+        let mut parts: Vec<&str> = path.split('/').collect();
+        if parts.first().is_some_and(|part| is_generation(part)) {
+            parts.remove(0);
+        }
+        let [tenant_id, mailbox_id, fanout @ .., file] = parts.as_slice() else {
+            return None;
+        };
+        if fanout.len() > StorageLayoutConfig::MAX_FANOUT as usize
+            || !fanout
+                .iter()
+                .all(|dir| dir.len() == 2 && dir.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            return None;
+        }
+
+        Some(MessagePath {
+            tenant_id: Uuid::parse_str(tenant_id).ok()?,
+            mailbox_id: Uuid::parse_str(mailbox_id).ok()?,
+            message_id: Uuid::parse_str(file.strip_suffix(".eml")?).ok()?,
+        })
+    }
+
+    /// Path of the same file under this layout
+    ///
+    /// `None` when the path is not a message file path.
+    pub fn relocate(&self, path: &str) -> Option<String> {
+        let parsed = Self::parse_message_path(path)?;
+        Some(self.message_path(parsed.tenant_id, parsed.mailbox_id, parsed.message_id))
+    }
+}
+
+/// Whether a path component is a generation directory (`g<n>`)
+fn is_generation(part: &str) -> bool {
+    part.strip_prefix('g')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids() -> (Uuid, Uuid, Uuid) {
+        (
+            Uuid::parse_str("0191a2b3-0000-7000-8000-000000000001").unwrap(),
+            Uuid::parse_str("0191a2b3-0000-7000-8000-000000000002").unwrap(),
+            Uuid::parse_str("0191a2b3-0000-7000-8000-000000000003").unwrap(),
+        )
+    }
+
+    fn hashed(fanout: u8, generation: u32) -> StorageLayout {
+        StorageLayout::from(&StorageLayoutConfig {
+            scheme: StorageLayoutScheme::Hashed,
+            fanout,
+            generation,
+        })
+    }
+
+    #[test]
+    fn test_flat_layout() {
+        let (tenant, mailbox, message) = ids();
+        assert_eq!(
+            StorageLayout::default().message_path(tenant, mailbox, message),
+            format!("{}/{}/{}.eml", tenant, mailbox, message)
+        );
+    }
+
+    #[test]
+    fn test_hashed_layout() {
+        let (tenant, mailbox, message) = ids();
+        let path = hashed(2, 3).message_path(tenant, mailbox, message);
+        let parts: Vec<&str> = path.split('/').collect();
+        assert_eq!(parts.len(), 6);
+        assert_eq!(parts[0], "g3");
+        assert_eq!(parts[1], tenant.to_string());
+        assert_eq!(parts[2], mailbox.to_string());
+        assert!(parts[3..5].iter().all(|dir| dir.len() == 2));
+        assert_eq!(parts[5], format!("{}.eml", message));
+
+        // The same message always lands in the same directory
+        assert_eq!(path, hashed(2, 3).message_path(tenant, mailbox, message));
+    }
+
+    #[test]
+    fn test_parse_message_path() {
+        let (tenant, mailbox, message) = ids();
+        let expected = MessagePath {
+            tenant_id: tenant,
+            mailbox_id: mailbox,
+            message_id: message,
+        };
+        for layout in [StorageLayout::default(), hashed(1, 0), hashed(4, 12)] {
+            let path = layout.message_path(tenant, mailbox, message);
+            assert_eq!(StorageLayout::parse_message_path(&path), Some(expected));
+        }
+
+        assert_eq!(
+            StorageLayout::parse_message_path(&format!(
+                "{}/inbound/{}/{}.eml",
+                tenant, mailbox, message
+            )),
+            None
+        );
+        assert_eq!(
+            StorageLayout::parse_message_path(&format!("journal/{}.eml", message)),
+            None
+        );
+        assert_eq!(
+            StorageLayout::parse_message_path(&format!(
+                "{}/{}/xyz/{}.eml",
+                tenant, mailbox, message
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_relocate() {
+        let (tenant, mailbox, message) = ids();
+        let flat = StorageLayout::default().message_path(tenant, mailbox, message);
+        let target = hashed(2, 1);
+        let relocated = target.relocate(&flat).unwrap();
+        assert_eq!(relocated, target.message_path(tenant, mailbox, message));
+        // Already in place
+        assert_eq!(target.relocate(&relocated).unwrap(), relocated);
+        // And back
+        assert_eq!(StorageLayout::default().relocate(&relocated).unwrap(), flat);
+        assert_eq!(target.relocate("t/inbound/r/m.eml"), None);
+    }
+}
@@ -5,10 +5,14 @@
 
 pub mod db;
 pub mod file;
+pub mod layout;
 pub mod models;
 pub mod repository;
+pub mod residency;
 
 pub use db::{Database, DatabasePool};
 pub use file::{create_storage, FileStorage, LocalStorage, MessageStorage};
+pub use layout::{MessagePath, StorageLayout};
 pub use models::*;
 pub use repository::*;
+pub use residency::{Residency, ResidentStorage};
@@ -1,6 +1,6 @@
 //! Database models
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use mairust_common::types::{
     DomainAliasId, DomainId, HookId, HookType, MailboxId, MessageFlags, MessageId, PolicyId,
     TenantId, UserId, UserRole,
@@ -61,6 +61,18 @@ pub struct Mailbox {
     pub used_bytes: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Special-use role of a provisioned folder (`sent`, `drafts`, ...)
+    #[sqlx(default)]
+    #[serde(default)]
+    pub special_use: Option<String>,
+    /// Full folder name (`Archive/2024`), `None` for address mailboxes
+    #[sqlx(default)]
+    #[serde(default)]
+    pub folder_path: Option<String>,
+    /// Full name of the parent folder, `None` at the top level
+    #[sqlx(default)]
+    #[serde(default)]
+    pub parent_path: Option<String>,
 }
 
 /// Message model
@@ -89,6 +101,22 @@ pub struct Message {
     pub metadata: serde_json::Value,
     pub received_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    /// IMAP UID within the mailbox, allocated by the database on insert
+    #[sqlx(default)]
+    #[serde(default)]
+    pub uid: Option<i64>,
+    /// IMAP keywords (user-defined flags)
+    #[sqlx(default)]
+    #[serde(default)]
+    pub keywords: serde_json::Value,
+    /// Day of the Date header, used by SENTBEFORE/SENTON/SENTSINCE
+    #[sqlx(default)]
+    #[serde(default)]
+    pub sent_date: Option<NaiveDate>,
+    /// Body text on one line for message lists, `None` until backfilled
+    #[sqlx(default)]
+    #[serde(default)]
+    pub snippet: Option<String>,
 }
 
 impl Message {
@@ -107,6 +135,11 @@ impl Message {
     pub fn tags_vec(&self) -> Vec<String> {
         serde_json::from_value(self.tags.clone()).unwrap_or_default()
     }
+
+    /// Get IMAP keywords as a vector
+    pub fn keywords_vec(&self) -> Vec<String> {
+        serde_json::from_value(self.keywords.clone()).unwrap_or_default()
+    }
 }
 
 /// Hook model
@@ -216,6 +249,18 @@ pub struct Job {
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
+    /// Owning tenant, copied from the payload for indexed lookups
+    #[sqlx(default)]
+    #[serde(default)]
+    pub tenant_id: Option<uuid::Uuid>,
+    /// Delivery priority within the lane (higher first)
+    #[sqlx(default)]
+    #[serde(default)]
+    pub priority: i32,
+    /// Delivery lane: `transactional`, `campaign` or `bulk`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub lane: String,
 }
 
 /// Create tenant input
@@ -267,6 +312,8 @@ pub struct CreateMessage {
     pub cc_addresses: Option<Vec<String>>,
     pub headers: serde_json::Value,
     pub body_preview: Option<String>,
+    #[serde(default)]
+    pub snippet: Option<String>,
     pub body_size: i64,
     pub has_attachments: bool,
     pub storage_path: String,
@@ -363,7 +410,7 @@ pub struct UpdateDomainSettings {
 // ============================================================================
 
 /// Policy condition types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum PolicyConditionType {
     SenderDomain,
@@ -378,6 +425,13 @@ pub enum PolicyConditionType {
     SpamScore,
     ClientIp,
     TimeOfDay,
+    SenderReputation,
+    Country,
+    Asn,
+    SpfResult,
+    DkimResult,
+    DmarcResult,
+    DmarcPolicy,
 }
 
 /// Policy action types
@@ -394,6 +448,8 @@ pub enum PolicyActionType {
     ModifySubject,
     RateLimit,
     RequireTls,
+    StripAttachments,
+    ReplaceAttachments,
 }
 
 /// Policy rule model
@@ -439,10 +495,51 @@ pub struct PolicyCondition {
     pub negate: bool,
 }
 
+/// Entry of a conditions array: a condition, or a group of entries
+///
+/// Groups nest: `{"all_of": [{"any_of": [A, B]}, C]}` matches when A or B
+/// matches, and C does. The top-level array is an implicit `all_of`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PolicyConditionNode {
+    Group(PolicyConditionGroup),
+    Condition(PolicyCondition),
+}
+
+/// Group of condition entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyConditionGroup {
+    /// Every entry matches
+    AllOf(Vec<PolicyConditionNode>),
+    /// At least one entry matches
+    AnyOf(Vec<PolicyConditionNode>),
+    /// No entry matches
+    NoneOf(Vec<PolicyConditionNode>),
+}
+
+impl PolicyConditionNode {
+    /// The conditions of this entry and of the groups in it
+    pub fn conditions(&self) -> Vec<&PolicyCondition> {
+        match self {
+            PolicyConditionNode::Condition(condition) => vec![condition],
+            PolicyConditionNode::Group(
+                PolicyConditionGroup::AllOf(nodes)
+                | PolicyConditionGroup::AnyOf(nodes)
+                | PolicyConditionGroup::NoneOf(nodes),
+            ) => nodes
+                .iter()
+                .flat_map(PolicyConditionNode::conditions)
+                .collect(),
+        }
+    }
+}
+
 /// Policy action
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyAction {
     pub action_type: PolicyActionType,
+    #[serde(default)]
     pub parameters: serde_json::Value,
 }
 
@@ -991,7 +1088,16 @@ pub struct UpsertTenantRateLimit {
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct RateLimitCounter {
     pub id: uuid::Uuid,
-    pub tenant_id: TenantId,
+    /// `None` for counters that are not kept per tenant
+    pub tenant_id: Option<TenantId>,
+    /// What is counted: `tenant`, or an SMTP limit such as `smtp_message`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub scope: String,
+    /// Client the counter belongs to, e.g. `ip:192.0.2.1`; empty for tenants
+    #[sqlx(default)]
+    #[serde(default)]
+    pub scope_key: String,
     pub window_type: String,
     pub window_start: DateTime<Utc>,
     pub count: i32,
@@ -6,18 +6,46 @@ pub mod tenants;
 pub mod users;
 pub mod mailboxes;
 pub mod domains;
+pub mod dkim_keys;
 pub mod hooks;
 pub mod domain_aliases;
 pub mod domain_settings;
 pub mod policies;
+pub mod policy_history;
 pub mod threads;
 pub mod tags;
 pub mod categories;
+pub mod correspondents;
 pub mod campaigns;
+pub mod provisioning;
 pub mod recipient_lists;
 pub mod recipients;
+pub mod replication;
 pub mod scheduled_messages;
 pub mod unsubscribes;
+pub mod jobs;
+pub mod metadata;
+pub mod user_invites;
+pub mod user_settings;
+pub mod audit_logs;
+pub mod mailbox_acl;
+pub mod inbound_routes;
+pub mod maintenance;
+pub mod attachment_scans;
+pub mod subscriptions;
+pub mod security_events;
+pub mod protected_names;
+pub mod dnsbl_allowlist;
+pub mod bad_domains;
+pub mod message_shares;
+pub mod tracking_domains;
+pub mod received_reports;
+pub mod secure_messages;
+pub mod sender_reputation;
+pub mod sieve_scripts;
+pub mod mailbox_filters;
+pub mod quarantine;
+pub mod spam_rules;
 
 // Re-export concrete repository implementations with simple names
 pub use api_keys::DbApiKeyRepository as ApiKeyRepository;
@@ -25,19 +53,47 @@ pub use messages::DbMessageRepository as MessageRepository;
 pub use tenants::DbTenantRepository as TenantRepository;
 pub use users::DbUserRepository as UserRepository;
 pub use mailboxes::DbMailboxRepository as MailboxRepository;
+pub use mailbox_filters::MailboxFilterRepository;
 pub use domains::DbDomainRepository as DomainRepository;
+pub use dkim_keys::DkimKeyRepository;
 pub use hooks::DbHookRepository as HookRepository;
 pub use domain_aliases::DbDomainAliasRepository as DomainAliasRepository;
 pub use domain_settings::DbDomainSettingsRepository as DomainSettingsRepository;
 pub use policies::DbPolicyRepository as PolicyRepository;
+pub use policy_history::PolicyHistoryRepository;
 pub use threads::ThreadRepository;
 pub use tags::TagRepository;
 pub use categories::CategoryRepository;
+pub use correspondents::CorrespondentRepository;
 pub use campaigns::CampaignRepository;
+pub use provisioning::ProvisioningRepository;
 pub use recipient_lists::RecipientListRepository;
 pub use recipients::RecipientRepository;
+pub use replication::ReplicationRepository;
 pub use scheduled_messages::ScheduledMessageRepository;
+pub use sieve_scripts::SieveScriptRepository;
 pub use unsubscribes::UnsubscribeRepository;
+pub use jobs::JobRepository;
+pub use metadata::MetadataRepository;
+pub use user_invites::UserInviteRepository;
+pub use user_settings::UserSettingsRepository;
+pub use audit_logs::AuditLogRepository;
+pub use mailbox_acl::MailboxAclRepository;
+pub use inbound_routes::InboundRouteRepository;
+pub use maintenance::MaintenanceRepository;
+pub use attachment_scans::AttachmentScanRepository;
+pub use subscriptions::MailboxSubscriptionRepository;
+pub use security_events::SecurityEventRepository;
+pub use protected_names::ProtectedNameRepository;
+pub use dnsbl_allowlist::DnsblAllowlistRepository;
+pub use bad_domains::BadDomainRepository;
+pub use message_shares::MessageShareRepository;
+pub use tracking_domains::TrackingDomainRepository;
+pub use received_reports::ReceivedReportRepository;
+pub use secure_messages::SecureMessageRepository;
+pub use sender_reputation::SenderReputationRepository;
+pub use quarantine::QuarantineRepository;
+pub use spam_rules::SpamRuleRepository;
 
 // Re-export repository traits
 pub use api_keys::ApiKeyRepository as ApiKeyRepositoryTrait;
@@ -53,3 +109,76 @@ pub use api_keys::{ApiKey, ApiKeyId};
 
 // Re-export scheduled message types
 pub use scheduled_messages::CampaignMessageCounts;
+
+// Re-export job queue statistics types
+pub use jobs::{QueueCount, QueueFailure, QueueStats, TenantQueueLatency};
+
+// Re-export mailbox counter types
+pub use mailboxes::MailboxCounters;
+
+// Re-export mailbox metadata types
+pub use metadata::MetadataEntry;
+
+// Re-export user settings types
+pub use user_settings::{
+    DigestFrequency, NotificationSettings, UpdateNotificationSettings, UpdateUserSettings,
+    UserSettings,
+};
+
+// Re-export audit log types
+pub use audit_logs::NewAuditLog;
+
+// Re-export mailbox ACL types
+pub use mailbox_acl::{MailboxAclEntry, SharedMailbox};
+
+// Re-export correspondent types
+pub use correspondents::Correspondent;
+
+// Re-export security event types
+pub use security_events::{NewSecurityEvent, SecurityEvent};
+
+// Re-export message share types
+pub use message_shares::{CreateMessageShare, MessageShare};
+
+// Re-export tracking domain types
+pub use tracking_domains::{TrackingDomain, TrackingDomainStatus};
+// Re-export secure message types
+pub use secure_messages::{
+    CreateSecureMessage, SecureMessage, SecureMessageAccess, SecureMessageEvent,
+};
+
+// Re-export mailbox provisioning types
+pub use provisioning::{ProvisioningBatch, ProvisioningOutcome, ProvisioningRow};
+
+// Re-export user invite types
+pub use user_invites::UserInvite;
+
+// Re-export sender reputation types
+pub use sender_reputation::SenderReputationRecord;
+
+// Re-export replication types
+pub use replication::{FileQueueStats, QueuedFile, SubscriberLag};
+
+// Re-export DKIM key types
+pub use dkim_keys::{CreateDkimKey, DkimKey, PendingDkimKey, RotationCandidate};
+
+// Re-export Sieve script types
+pub use sieve_scripts::{SieveScript, SieveUsage};
+
+// Re-export mailbox filter types
+pub use mailbox_filters::{CreateMailboxFilter, MailboxFilter};
+
+// Re-export quarantine types
+pub use quarantine::{
+    quarantine_folder_address, QuarantineDigestRecipient, QuarantinedMessage,
+    QUARANTINE_SPECIAL_USE,
+};
+
+// Re-export spam rule types
+pub use spam_rules::{CreateSpamRule, SpamRuleRecord};
+
+// Re-export policy history types
+pub use policy_history::{
+    policy_changes, record_policy_version, NewPolicyMatch, PolicyActor, PolicyMatchFilter,
+    PolicyMatchRecord, PolicyVersion,
+};
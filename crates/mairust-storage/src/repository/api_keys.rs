@@ -1,11 +1,14 @@
 //! API Key repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mairust_common::types::{TenantId, UserId};
 use mairust_common::{Error, Result};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -47,6 +50,60 @@ impl ApiKey {
         let scopes = self.scopes_vec();
         scopes.contains(&"*".to_string()) || scopes.contains(&scope.to_string())
     }
+
+    /// Check a presented key against the stored hash
+    pub fn verify(&self, api_key: &str) -> bool {
+        verify_api_key(api_key, &self.key_hash)
+    }
+}
+
+/// Extract the lookup prefix from an API key (first 8 characters)
+pub fn key_prefix(api_key: &str) -> Option<&str> {
+    api_key.get(..8)
+}
+
+/// Generate a new random API key
+pub fn generate_api_key() -> String {
+    format!(
+        "mk_live_{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+/// Hash a new API key with Argon2 for storage
+pub fn hash_new_api_key(api_key: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(api_key.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::Internal(format!("Failed to hash API key: {}", e)))
+}
+
+/// Hash an API key with SHA-256 (legacy storage format)
+fn hash_api_key(api_key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(api_key.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Verify an API key against a stored hash.
+///
+/// Supports both modern Argon2 hashes (`$argon2...`) and legacy SHA-256 hex hashes
+/// for backward compatibility during migration.
+pub fn verify_api_key(api_key: &str, stored_hash: &str) -> bool {
+    if stored_hash.starts_with("$argon2") {
+        return PasswordHash::new(stored_hash)
+            .ok()
+            .and_then(|parsed_hash| {
+                Argon2::default()
+                    .verify_password(api_key.as_bytes(), &parsed_hash)
+                    .ok()
+            })
+            .is_some();
+    }
+
+    hash_api_key(api_key) == stored_hash
 }
 
 /// API key repository trait
@@ -89,7 +146,7 @@ impl ApiKeyRepository for DbApiKeyRepository {
         .bind(prefix)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn get(&self, id: ApiKeyId) -> Result<Option<ApiKey>> {
@@ -104,7 +161,7 @@ impl ApiKeyRepository for DbApiKeyRepository {
         .bind(id)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn update_last_used(&self, id: ApiKeyId) -> Result<()> {
@@ -114,7 +171,53 @@ impl ApiKeyRepository for DbApiKeyRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_legacy_sha256_hash() {
+        let api_key = "mk_test_legacy_key";
+        let mut hasher = Sha256::new();
+        hasher.update(api_key.as_bytes());
+        let legacy_hash = hex::encode(hasher.finalize());
+
+        assert!(verify_api_key(api_key, &legacy_hash));
+        assert!(!verify_api_key("wrong_key", &legacy_hash));
+    }
+
+    #[test]
+    fn verifies_argon2_hash() {
+        let api_key = "mk_test_argon2_key";
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(api_key.as_bytes(), &salt)
+            .expect("argon2 hash generation should succeed")
+            .to_string();
+
+        assert!(verify_api_key(api_key, &hash));
+        assert!(!verify_api_key("wrong_key", &hash));
+    }
+
+    #[test]
+    fn test_generate_api_key() {
+        let api_key = generate_api_key();
+        assert!(api_key.starts_with("mk_live_"));
+        assert_eq!(api_key.len(), 8 + 64);
+        assert_ne!(api_key, generate_api_key());
+
+        let hash = hash_new_api_key(&api_key).unwrap();
+        assert!(verify_api_key(&api_key, &hash));
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        assert_eq!(key_prefix("mk_abcdef123"), Some("mk_abcde"));
+        assert_eq!(key_prefix("short"), None);
+    }
+}
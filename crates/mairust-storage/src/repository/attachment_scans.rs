@@ -0,0 +1,77 @@
+//! Attachment scan repository
+//!
+//! Latest rescan result of a stored message, recorded when one of its
+//! attachments is downloaded.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Latest rescan of a stored message
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AttachmentScan {
+    pub storage_path: String,
+    pub tenant_id: Uuid,
+    pub scanned_at: DateTime<Utc>,
+    /// Antivirus and URL reputation symbols that matched
+    pub flagged_symbols: serde_json::Value,
+}
+
+impl AttachmentScan {
+    /// Matched symbols as a vector, empty when the message was clean
+    pub fn flagged_symbols_vec(&self) -> Vec<String> {
+        serde_json::from_value(self.flagged_symbols.clone()).unwrap_or_default()
+    }
+}
+
+/// Attachment scan repository
+pub struct AttachmentScanRepository {
+    pool: DatabasePool,
+}
+
+impl AttachmentScanRepository {
+    /// Create a new attachment scan repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Latest scan of a stored message
+    pub async fn get(&self, storage_path: &str) -> Result<Option<AttachmentScan>> {
+        let scan = sqlx::query_as::<_, AttachmentScan>(
+            "SELECT * FROM attachment_scans WHERE storage_path = $1",
+        )
+        .bind(storage_path)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(scan)
+    }
+
+    /// Record the result of a scan, replacing the previous one
+    pub async fn record(
+        &self,
+        tenant_id: Uuid,
+        storage_path: &str,
+        flagged_symbols: &[String],
+    ) -> Result<AttachmentScan> {
+        let scan = sqlx::query_as::<_, AttachmentScan>(
+            r#"
+            INSERT INTO attachment_scans (storage_path, tenant_id, scanned_at, flagged_symbols)
+            VALUES ($1, $2, NOW(), $3)
+            ON CONFLICT (storage_path) DO UPDATE
+            SET scanned_at = EXCLUDED.scanned_at, flagged_symbols = EXCLUDED.flagged_symbols
+            RETURNING *
+            "#,
+        )
+        .bind(storage_path)
+        .bind(tenant_id)
+        .bind(serde_json::json!(flagged_symbols))
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(scan)
+    }
+}
@@ -0,0 +1,58 @@
+//! Audit log repository
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use mairust_common::types::TenantId;
+use uuid::Uuid;
+
+/// An audit event to record
+#[derive(Debug, Clone)]
+pub struct NewAuditLog {
+    /// Tenant the event belongs to (`None` for system-wide events)
+    pub tenant_id: Option<TenantId>,
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+    pub event_type: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub details: serde_json::Value,
+    pub ip_address: Option<String>,
+}
+
+/// Audit log repository
+pub struct AuditLogRepository {
+    pool: DatabasePool,
+}
+
+impl AuditLogRepository {
+    /// Create a new audit log repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Record an audit event
+    pub async fn record(&self, entry: NewAuditLog) -> Result<Uuid> {
+        let id = Uuid::now_v7();
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_logs (id, tenant_id, actor_type, actor_id, event_type,
+                                    target_type, target_id, details, ip_address)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(id)
+        .bind(entry.tenant_id)
+        .bind(&entry.actor_type)
+        .bind(&entry.actor_id)
+        .bind(&entry.event_type)
+        .bind(&entry.target_type)
+        .bind(&entry.target_id)
+        .bind(&entry.details)
+        .bind(&entry.ip_address)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,85 @@
+//! Bad domain repository
+//!
+//! Domains whose links mark a message as spam.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A domain whose links mark a message as spam
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct BadDomain {
+    pub id: Uuid,
+    /// Lowercase domain, also covering its subdomains
+    pub domain: String,
+    /// Score of a link to the domain; the configured one when `None`
+    pub score: Option<f64>,
+    pub description: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Input for adding a bad domain
+#[derive(Debug, Clone)]
+pub struct CreateBadDomain {
+    pub domain: String,
+    pub score: Option<f64>,
+    pub description: Option<String>,
+    pub created_by: Option<String>,
+}
+
+/// Bad domain repository
+pub struct BadDomainRepository {
+    pool: DatabasePool,
+}
+
+impl BadDomainRepository {
+    /// Create a new bad domain repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Add a bad domain; `None` when it is already listed
+    pub async fn create(&self, input: CreateBadDomain) -> Result<Option<BadDomain>> {
+        let entry = sqlx::query_as::<_, BadDomain>(
+            r#"
+            INSERT INTO bad_domains (id, domain, score, description, created_by, created_at)
+            VALUES ($1, $2, $3, $4, $5, NOW())
+            ON CONFLICT (domain) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(&input.domain)
+        .bind(input.score)
+        .bind(&input.description)
+        .bind(&input.created_by)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(entry)
+    }
+
+    /// List the bad domains
+    pub async fn list(&self) -> Result<Vec<BadDomain>> {
+        let entries = sqlx::query_as::<_, BadDomain>("SELECT * FROM bad_domains ORDER BY domain")
+            .fetch_all(self.pool.pool())
+            .await?;
+
+        Ok(entries)
+    }
+
+    /// Remove a bad domain, returning it; `None` when there is no such entry
+    pub async fn delete(&self, id: Uuid) -> Result<Option<BadDomain>> {
+        let entry =
+            sqlx::query_as::<_, BadDomain>("DELETE FROM bad_domains WHERE id = $1 RETURNING *")
+                .bind(id)
+                .fetch_optional(self.pool.pool())
+                .await?;
+
+        Ok(entry)
+    }
+}
@@ -245,6 +245,35 @@ impl CampaignRepository {
         Ok(())
     }
 
+    /// Record a click on a tracked link of a campaign message; the campaign
+    /// counts the first click of each recipient. Returns whether it was the
+    /// first.
+    pub async fn record_click(
+        &self,
+        id: Uuid,
+        recipient_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let first: (i64,) = sqlx::query_as(
+            r#"
+            WITH first_click AS (
+                UPDATE scheduled_messages SET clicked_at = NOW()
+                WHERE campaign_id = $1 AND recipient_id = $2 AND clicked_at IS NULL
+                RETURNING id
+            ), counted AS (
+                UPDATE campaigns
+                SET clicked_count = clicked_count + (SELECT COUNT(*) FROM first_click)
+                WHERE id = $1
+            )
+            SELECT COUNT(*) FROM first_click
+            "#,
+        )
+        .bind(id)
+        .bind(recipient_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(first.0 > 0)
+    }
+
     /// Delete a campaign
     pub async fn delete(&self, id: Uuid, tenant_id: TenantId) -> Result<bool, sqlx::Error> {
         let result = sqlx::query(
@@ -0,0 +1,147 @@
+//! Correspondents repository
+//!
+//! Addresses a user has exchanged mail with, used for composer
+//! autocomplete. Rows are keyed by user and lowercased address.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Maximum number of suggestions returned at once
+pub const CORRESPONDENT_SUGGEST_MAX_LIMIT: i64 = 50;
+
+/// An address the user has sent to or received from
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Correspondent {
+    pub address: String,
+    pub display_name: Option<String>,
+    /// Number of messages exchanged with the address
+    pub frequency: i32,
+    pub last_used_at: DateTime<Utc>,
+}
+
+/// Correspondents repository
+pub struct CorrespondentRepository {
+    pool: DatabasePool,
+}
+
+impl CorrespondentRepository {
+    /// Create a new correspondents repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Record use of an address by a user
+    ///
+    /// Bumps the frequency and last use; a known display name is kept when
+    /// none is given.
+    pub async fn record(
+        &self,
+        tenant_id: TenantId,
+        user_id: UserId,
+        address: &str,
+        display_name: Option<&str>,
+    ) -> Result<()> {
+        let address = address.trim().to_lowercase();
+        if address.is_empty() {
+            return Ok(());
+        }
+        let display_name = display_name.map(str::trim).filter(|name| !name.is_empty());
+
+        sqlx::query(
+            r#"
+            INSERT INTO correspondents (id, tenant_id, user_id, address, display_name, frequency)
+            VALUES ($1, $2, $3, $4, $5, 1)
+            ON CONFLICT (user_id, address) DO UPDATE SET
+                display_name = COALESCE(EXCLUDED.display_name, correspondents.display_name),
+                frequency = correspondents.frequency + 1,
+                last_used_at = NOW()
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(&address)
+        .bind(display_name)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether a user has sent to or received from an address before
+    pub async fn exists(&self, user_id: UserId, address: &str) -> Result<bool> {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM correspondents WHERE user_id = $1 AND address = $2)",
+        )
+        .bind(user_id)
+        .bind(address.trim().to_lowercase())
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Suggest correspondents whose address or display name starts with `prefix`
+    ///
+    /// Display names also match at the start of any word, so "smi" finds
+    /// "John Smith". Results are ordered by frequency, then recency.
+    pub async fn suggest(
+        &self,
+        tenant_id: TenantId,
+        user_id: UserId,
+        prefix: &str,
+        limit: i64,
+    ) -> Result<Vec<Correspondent>> {
+        let pattern = format!("{}%", escape_like(&prefix.trim().to_lowercase()));
+
+        let suggestions = sqlx::query_as::<_, Correspondent>(
+            r#"
+            SELECT address, display_name, frequency, last_used_at
+            FROM correspondents
+            WHERE tenant_id = $1 AND user_id = $2
+              AND (address LIKE $3
+                   OR LOWER(display_name) LIKE $3
+                   OR LOWER(display_name) LIKE '% ' || $3)
+            ORDER BY frequency DESC, last_used_at DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(&pattern)
+        .bind(limit.clamp(1, CORRESPONDENT_SUGGEST_MAX_LIMIT))
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(suggestions)
+    }
+}
+
+/// Escape LIKE wildcards so user input matches literally
+fn escape_like(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("alice"), "alice");
+        assert_eq!(escape_like("a_b%c"), "a\\_b\\%c");
+        assert_eq!(escape_like("back\\slash"), "back\\\\slash");
+    }
+}
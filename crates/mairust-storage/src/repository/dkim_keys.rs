@@ -0,0 +1,341 @@
+//! DKIM key repository
+//!
+//! The signing keys of a domain, each under its own selector. New keys are
+//! pending until their activation date and their DNS record is published;
+//! activating a key retires the domain's other active key of the same
+//! algorithm, so that a domain signs with at most one RSA and one Ed25519
+//! key at a time. The domain's `dkim_selector` and `dkim_private_key`
+//! follow its active RSA key.
+
+use crate::db::DatabasePool;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A DKIM signing key
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DkimKey {
+    pub id: Uuid,
+    pub domain_id: Uuid,
+    pub selector: String,
+    /// `rsa-sha256` or `ed25519-sha256`
+    pub algorithm: String,
+    #[serde(skip_serializing)]
+    pub private_key: String,
+    /// `pending`, `active` or `retired`
+    pub status: String,
+    /// When a pending key is to be activated
+    pub activate_at: DateTime<Utc>,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DkimKey {
+    /// Whether messages are signed with the key
+    pub fn is_active(&self) -> bool {
+        self.status == "active"
+    }
+
+    /// Whether the key is waiting to be activated
+    pub fn is_pending(&self) -> bool {
+        self.status == "pending"
+    }
+}
+
+/// A pending key with the name of its domain
+#[derive(Debug, Clone, FromRow)]
+pub struct PendingDkimKey {
+    pub domain: String,
+    #[sqlx(flatten)]
+    pub key: DkimKey,
+}
+
+/// A domain that signs, with its newest key of an algorithm
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct RotationCandidate {
+    pub domain_id: Uuid,
+    pub domain: String,
+    /// When the newest active or pending key of the algorithm was
+    /// activated or created; `None` if the domain has none
+    pub newest_at: Option<DateTime<Utc>>,
+    /// Whether a key of the algorithm is pending
+    pub has_pending: bool,
+}
+
+/// Input for creating a DKIM key
+#[derive(Debug, Clone)]
+pub struct CreateDkimKey {
+    pub domain_id: Uuid,
+    pub selector: String,
+    pub algorithm: String,
+    pub private_key: String,
+    /// Activate the key at once rather than at `activate_at`
+    pub active: bool,
+    pub activate_at: DateTime<Utc>,
+}
+
+/// DKIM key repository
+pub struct DkimKeyRepository {
+    pool: DatabasePool,
+}
+
+impl DkimKeyRepository {
+    /// Create a new DKIM key repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Keys of a domain, newest first
+    pub async fn list(&self, domain_id: Uuid) -> Result<Vec<DkimKey>> {
+        let keys = sqlx::query_as::<_, DkimKey>(
+            "SELECT * FROM dkim_keys WHERE domain_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(domain_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(keys)
+    }
+
+    /// Active keys of a domain
+    pub async fn active(&self, domain_id: Uuid) -> Result<Vec<DkimKey>> {
+        let keys = sqlx::query_as::<_, DkimKey>(
+            r#"
+            SELECT * FROM dkim_keys
+            WHERE domain_id = $1 AND status = 'active'
+            ORDER BY activated_at DESC
+            "#,
+        )
+        .bind(domain_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(keys)
+    }
+
+    /// Get a key of a domain
+    pub async fn get(&self, domain_id: Uuid, id: Uuid) -> Result<Option<DkimKey>> {
+        let key = sqlx::query_as::<_, DkimKey>(
+            "SELECT * FROM dkim_keys WHERE domain_id = $1 AND id = $2",
+        )
+        .bind(domain_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(key)
+    }
+
+    /// Whether a domain already has a key under the selector
+    pub async fn selector_exists(&self, domain_id: Uuid, selector: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM dkim_keys WHERE domain_id = $1 AND selector = $2)",
+        )
+        .bind(domain_id)
+        .bind(selector)
+        .fetch_one(self.pool.pool())
+        .await?;
+        Ok(exists)
+    }
+
+    /// Add a key; an active key retires the active key it replaces
+    pub async fn create(&self, input: CreateDkimKey) -> Result<DkimKey> {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            r#"
+            INSERT INTO dkim_keys (id, domain_id, selector, algorithm, private_key, activate_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(id)
+        .bind(input.domain_id)
+        .bind(&input.selector)
+        .bind(&input.algorithm)
+        .bind(&input.private_key)
+        .bind(input.activate_at)
+        .execute(self.pool.pool())
+        .await?;
+
+        if input.active {
+            return self.activate(input.domain_id, id).await;
+        }
+        match self.get(input.domain_id, id).await? {
+            Some(key) => Ok(key),
+            None => bail!("DKIM key {} was not created", id),
+        }
+    }
+
+    /// Add or replace the key under a selector and make it active, as
+    /// when a key is set by hand
+    pub async fn set_active(
+        &self,
+        domain_id: Uuid,
+        selector: &str,
+        algorithm: &str,
+        private_key: &str,
+    ) -> Result<DkimKey> {
+        let id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO dkim_keys (id, domain_id, selector, algorithm, private_key)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (domain_id, selector) DO UPDATE SET
+                algorithm = EXCLUDED.algorithm, private_key = EXCLUDED.private_key,
+                activated_at = NULL
+            RETURNING id
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(domain_id)
+        .bind(selector)
+        .bind(algorithm)
+        .bind(private_key)
+        .fetch_one(self.pool.pool())
+        .await?;
+        self.activate(domain_id, id).await
+    }
+
+    /// Make a key active and retire the domain's other active key of the
+    /// same algorithm
+    pub async fn activate(&self, domain_id: Uuid, id: Uuid) -> Result<DkimKey> {
+        let mut tx = self.pool.pool().begin().await?;
+        let key = sqlx::query_as::<_, DkimKey>(
+            r#"
+            UPDATE dkim_keys
+            SET status = 'active', activated_at = COALESCE(activated_at, NOW()), retired_at = NULL
+            WHERE domain_id = $1 AND id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(domain_id)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(key) = key else {
+            bail!("DKIM key {} not found", id);
+        };
+        sqlx::query(
+            r#"
+            UPDATE dkim_keys SET status = 'retired', retired_at = NOW()
+            WHERE domain_id = $1 AND algorithm = $2 AND status = 'active' AND id <> $3
+            "#,
+        )
+        .bind(domain_id)
+        .bind(&key.algorithm)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sync_domain(&mut tx, domain_id).await?;
+        tx.commit().await?;
+        Ok(key)
+    }
+
+    /// Stop signing with a key; returns whether it was found
+    pub async fn retire(&self, domain_id: Uuid, id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.pool().begin().await?;
+        let result = sqlx::query(
+            r#"
+            UPDATE dkim_keys SET status = 'retired', retired_at = COALESCE(retired_at, NOW())
+            WHERE domain_id = $1 AND id = $2
+            "#,
+        )
+        .bind(domain_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sync_domain(&mut tx, domain_id).await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a key; returns whether it was found
+    pub async fn delete(&self, domain_id: Uuid, id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.pool().begin().await?;
+        let result = sqlx::query("DELETE FROM dkim_keys WHERE domain_id = $1 AND id = $2")
+            .bind(domain_id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sync_domain(&mut tx, domain_id).await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Pending keys whose activation date has passed, oldest first
+    pub async fn due_pending(&self, limit: i64) -> Result<Vec<PendingDkimKey>> {
+        let keys = sqlx::query_as::<_, PendingDkimKey>(
+            r#"
+            SELECT d.name AS domain, k.* FROM dkim_keys k
+            JOIN domains d ON d.id = k.domain_id
+            WHERE k.status = 'pending' AND k.activate_at <= NOW()
+            ORDER BY k.activate_at
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(keys)
+    }
+
+    /// Verified domains that sign with an active key, with their newest
+    /// key of an algorithm, for deciding which domains need a new key
+    pub async fn rotation_candidates(&self, algorithm: &str) -> Result<Vec<RotationCandidate>> {
+        let candidates = sqlx::query_as::<_, RotationCandidate>(
+            r#"
+            SELECT d.id AS domain_id, d.name AS domain,
+                   MAX(COALESCE(k.activated_at, k.created_at))
+                       FILTER (WHERE k.status IN ('active', 'pending')) AS newest_at,
+                   COALESCE(BOOL_OR(k.status = 'pending'), false) AS has_pending
+            FROM domains d
+            LEFT JOIN dkim_keys k ON k.domain_id = d.id AND k.algorithm = $1
+            WHERE d.verified = true
+              AND EXISTS (
+                  SELECT 1 FROM dkim_keys a WHERE a.domain_id = d.id AND a.status = 'active'
+              )
+            GROUP BY d.id, d.name
+            ORDER BY d.name
+            "#,
+        )
+        .bind(algorithm)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(candidates)
+    }
+
+    /// Delete keys retired before `before`; returns how many were deleted
+    pub async fn purge_retired(&self, before: DateTime<Utc>) -> Result<u64> {
+        let result =
+            sqlx::query("DELETE FROM dkim_keys WHERE status = 'retired' AND retired_at < $1")
+                .bind(before)
+                .execute(self.pool.pool())
+                .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// Point the domain's DKIM columns at its newest active RSA key, or clear
+/// them when it has none
+async fn sync_domain(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    domain_id: Uuid,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        WITH current AS (
+            SELECT selector, private_key FROM dkim_keys
+            WHERE domain_id = $1 AND algorithm = 'rsa-sha256' AND status = 'active'
+            ORDER BY activated_at DESC
+            LIMIT 1
+        )
+        UPDATE domains SET
+            dkim_selector = (SELECT selector FROM current),
+            dkim_private_key = (SELECT private_key FROM current),
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(domain_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! DNSBL allowlist repository
+//!
+//! Client networks exempt from DNS blocklist lookups.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A client network exempt from DNS blocklist lookups
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DnsblAllowlistEntry {
+    pub id: Uuid,
+    /// CIDR range in canonical form
+    pub network: String,
+    pub description: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Input for allowlisting a network
+#[derive(Debug, Clone)]
+pub struct CreateDnsblAllowlistEntry {
+    pub network: String,
+    pub description: Option<String>,
+    pub created_by: Option<String>,
+}
+
+/// DNSBL allowlist repository
+pub struct DnsblAllowlistRepository {
+    pool: DatabasePool,
+}
+
+impl DnsblAllowlistRepository {
+    /// Create a new DNSBL allowlist repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Allowlist a network; `None` when it is already allowlisted
+    pub async fn create(
+        &self,
+        input: CreateDnsblAllowlistEntry,
+    ) -> Result<Option<DnsblAllowlistEntry>> {
+        let entry = sqlx::query_as::<_, DnsblAllowlistEntry>(
+            r#"
+            INSERT INTO dnsbl_allowlist (id, network, description, created_by, created_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            ON CONFLICT (network) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(&input.network)
+        .bind(&input.description)
+        .bind(&input.created_by)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(entry)
+    }
+
+    /// List the allowlisted networks
+    pub async fn list(&self) -> Result<Vec<DnsblAllowlistEntry>> {
+        let entries = sqlx::query_as::<_, DnsblAllowlistEntry>(
+            "SELECT * FROM dnsbl_allowlist ORDER BY network",
+        )
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Remove a network, returning it; `None` when there is no such entry
+    pub async fn delete(&self, id: Uuid) -> Result<Option<DnsblAllowlistEntry>> {
+        let entry = sqlx::query_as::<_, DnsblAllowlistEntry>(
+            "DELETE FROM dnsbl_allowlist WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(entry)
+    }
+}
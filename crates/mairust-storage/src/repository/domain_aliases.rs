@@ -1,6 +1,6 @@
 //! Domain alias repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreateDomainAlias, DomainAlias};
 use async_trait::async_trait;
 use mairust_common::types::{DomainAliasId, DomainId, TenantId};
@@ -51,7 +51,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(input.tenant_id, id)
             .await?
@@ -66,7 +66,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
         .bind(id)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn get_by_alias_domain(&self, alias_domain: &str) -> Result<Option<DomainAlias>> {
@@ -76,7 +76,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
         .bind(alias_domain)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list(&self, tenant_id: TenantId) -> Result<Vec<DomainAlias>> {
@@ -86,7 +86,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
         .bind(tenant_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_by_primary_domain(&self, primary_domain_id: DomainId) -> Result<Vec<DomainAlias>> {
@@ -96,7 +96,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
         .bind(primary_domain_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn enable(&self, id: DomainAliasId) -> Result<()> {
@@ -106,7 +106,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -117,7 +117,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -126,7 +126,7 @@ impl DomainAliasRepository for DbDomainAliasRepository {
             .bind(id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
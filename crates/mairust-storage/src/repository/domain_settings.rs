@@ -1,6 +1,6 @@
 //! Domain settings repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{DomainSettings, UpdateDomainSettings};
 use async_trait::async_trait;
 use mairust_common::types::DomainId;
@@ -13,6 +13,9 @@ pub trait DomainSettingsRepository: Send + Sync {
     async fn get_or_create(&self, domain_id: DomainId) -> Result<DomainSettings>;
     async fn update(&self, domain_id: DomainId, input: UpdateDomainSettings) -> Result<DomainSettings>;
     async fn delete(&self, domain_id: DomainId) -> Result<()>;
+    /// Largest message size limit among the domains, `None` when one of
+    /// them has no limit
+    async fn largest_message_size(&self) -> Result<Option<i64>>;
 }
 
 /// Database domain settings repository
@@ -33,7 +36,7 @@ impl DomainSettingsRepository for DbDomainSettingsRepository {
             .bind(domain_id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn get_or_create(&self, domain_id: DomainId) -> Result<DomainSettings> {
@@ -69,7 +72,7 @@ impl DomainSettingsRepository for DbDomainSettingsRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(domain_id)
             .await?
@@ -112,7 +115,7 @@ impl DomainSettingsRepository for DbDomainSettingsRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(domain_id)
             .await?
@@ -124,7 +127,21 @@ impl DomainSettingsRepository for DbDomainSettingsRepository {
             .bind(domain_id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
+
+    async fn largest_message_size(&self) -> Result<Option<i64>> {
+        let (limited, largest): (Option<bool>, Option<i64>) = sqlx::query_as(
+            r#"
+            SELECT bool_and(COALESCE(s.max_message_size, 0) > 0), MAX(s.max_message_size)
+            FROM domains d
+            LEFT JOIN domain_settings s ON s.domain_id = d.id
+            "#,
+        )
+        .fetch_one(self.pool.pool())
+        .await
+        .map_err(db_error)?;
+        Ok(largest.filter(|_| limited.unwrap_or(false)))
+    }
 }
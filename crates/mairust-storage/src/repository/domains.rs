@@ -1,6 +1,6 @@
 //! Domain repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreateDomain, Domain};
 use async_trait::async_trait;
 use mairust_common::types::{DomainId, TenantId};
@@ -35,7 +35,7 @@ impl DbDomainRepository {
             .bind(name)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     /// Find domain by name within a specific tenant (for API handlers)
@@ -51,7 +51,7 @@ impl DbDomainRepository {
         .bind(name)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 }
 
@@ -74,7 +74,7 @@ impl DomainRepository for DbDomainRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(input.tenant_id, id)
             .await?
@@ -87,7 +87,7 @@ impl DomainRepository for DbDomainRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn get_by_name(&self, name: &str) -> Result<Option<Domain>> {
@@ -95,7 +95,7 @@ impl DomainRepository for DbDomainRepository {
             .bind(name)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn list(&self, tenant_id: TenantId) -> Result<Vec<Domain>> {
@@ -105,7 +105,7 @@ impl DomainRepository for DbDomainRepository {
         .bind(tenant_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn verify(&self, id: DomainId) -> Result<()> {
@@ -115,7 +115,7 @@ impl DomainRepository for DbDomainRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -130,7 +130,7 @@ impl DomainRepository for DbDomainRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
         Ok(())
     }
 
@@ -139,7 +139,7 @@ impl DomainRepository for DbDomainRepository {
             .bind(id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
@@ -1,6 +1,6 @@
 //! Hook repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::Hook;
 use async_trait::async_trait;
 use mairust_common::types::{HookId, HookType, TenantId};
@@ -60,7 +60,7 @@ impl DbHookRepository {
         .bind(hook_type)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 }
 
@@ -95,7 +95,7 @@ impl HookRepository for DbHookRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(id)
             .await?
@@ -107,7 +107,7 @@ impl HookRepository for DbHookRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn list(&self, tenant_id: Option<TenantId>) -> Result<Vec<Hook>> {
@@ -118,13 +118,13 @@ impl HookRepository for DbHookRepository {
             .bind(tid)
             .fetch_all(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string())),
+            .map_err(db_error),
             None => sqlx::query_as::<_, Hook>(
                 "SELECT * FROM hooks ORDER BY priority ASC",
             )
             .fetch_all(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string())),
+            .map_err(db_error),
         }
     }
 
@@ -136,7 +136,7 @@ impl HookRepository for DbHookRepository {
         .bind(hook_type_str)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_enabled_by_type(&self, hook_type: HookType) -> Result<Vec<Hook>> {
@@ -147,7 +147,7 @@ impl HookRepository for DbHookRepository {
         .bind(hook_type_str)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn enable(&self, id: HookId) -> Result<()> {
@@ -157,7 +157,7 @@ impl HookRepository for DbHookRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -168,7 +168,7 @@ impl HookRepository for DbHookRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -177,7 +177,7 @@ impl HookRepository for DbHookRepository {
             .bind(id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
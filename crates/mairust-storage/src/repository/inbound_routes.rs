@@ -0,0 +1,241 @@
+//! Inbound route repository
+//!
+//! Routes map an address, or every address of a domain, to a webhook that
+//! receives matching inbound messages as JSON.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{DomainId, TenantId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Columns of a route joined with its domain name
+const ROUTE_COLUMNS: &str = "r.id, r.tenant_id, r.domain_id, d.name AS domain, r.local_part,
+     r.webhook_url, r.secret, r.store_message, r.enabled, r.created_at, r.updated_at";
+
+/// A route from an address or domain to a webhook
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct InboundRoute {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub domain_id: DomainId,
+    /// Domain name
+    pub domain: String,
+    /// Local part, or `None` for every address of the domain
+    pub local_part: Option<String>,
+    pub webhook_url: String,
+    /// Key for payload signatures and attachment links
+    pub secret: String,
+    /// Also deliver matching messages to the recipient's mailbox
+    pub store_message: bool,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl InboundRoute {
+    /// The matched address, `*@domain` for domain routes
+    pub fn address(&self) -> String {
+        format!(
+            "{}@{}",
+            self.local_part.as_deref().unwrap_or("*"),
+            self.domain
+        )
+    }
+}
+
+/// Input for creating a route
+#[derive(Debug, Clone)]
+pub struct CreateInboundRoute {
+    pub tenant_id: TenantId,
+    pub domain_id: DomainId,
+    pub local_part: Option<String>,
+    pub webhook_url: String,
+    pub store_message: bool,
+}
+
+/// Inbound route repository
+pub struct InboundRouteRepository {
+    pool: DatabasePool,
+}
+
+impl InboundRouteRepository {
+    /// Create a new inbound route repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Create a route with a new secret
+    pub async fn create(&self, input: CreateInboundRoute) -> Result<InboundRoute> {
+        let id = Uuid::now_v7();
+        let local_part = input
+            .local_part
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty());
+
+        sqlx::query(
+            r#"
+            INSERT INTO inbound_routes (id, tenant_id, domain_id, local_part, webhook_url, secret,
+                                        store_message)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(id)
+        .bind(input.tenant_id)
+        .bind(input.domain_id)
+        .bind(&local_part)
+        .bind(&input.webhook_url)
+        .bind(generate_secret())
+        .bind(input.store_message)
+        .execute(self.pool.pool())
+        .await?;
+
+        self.get(input.tenant_id, id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Failed to create inbound route"))
+    }
+
+    /// Get a route of a tenant
+    pub async fn get(&self, tenant_id: TenantId, id: Uuid) -> Result<Option<InboundRoute>> {
+        let sql = format!(
+            "SELECT {} FROM inbound_routes r JOIN domains d ON d.id = r.domain_id
+             WHERE r.tenant_id = $1 AND r.id = $2",
+            ROUTE_COLUMNS
+        );
+        let route = sqlx::query_as::<_, InboundRoute>(&sql)
+            .bind(tenant_id)
+            .bind(id)
+            .fetch_optional(self.pool.pool())
+            .await?;
+
+        Ok(route)
+    }
+
+    /// Get a route by ID regardless of tenant
+    pub async fn get_by_id(&self, id: Uuid) -> Result<Option<InboundRoute>> {
+        let sql = format!(
+            "SELECT {} FROM inbound_routes r JOIN domains d ON d.id = r.domain_id
+             WHERE r.id = $1",
+            ROUTE_COLUMNS
+        );
+        let route = sqlx::query_as::<_, InboundRoute>(&sql)
+            .bind(id)
+            .fetch_optional(self.pool.pool())
+            .await?;
+
+        Ok(route)
+    }
+
+    /// List the routes of a tenant
+    pub async fn list(&self, tenant_id: TenantId) -> Result<Vec<InboundRoute>> {
+        let sql = format!(
+            "SELECT {} FROM inbound_routes r JOIN domains d ON d.id = r.domain_id
+             WHERE r.tenant_id = $1
+             ORDER BY d.name ASC, r.local_part ASC NULLS LAST",
+            ROUTE_COLUMNS
+        );
+        let routes = sqlx::query_as::<_, InboundRoute>(&sql)
+            .bind(tenant_id)
+            .fetch_all(self.pool.pool())
+            .await?;
+
+        Ok(routes)
+    }
+
+    /// The enabled route for a recipient address
+    ///
+    /// A route for the exact address takes precedence over a domain route.
+    pub async fn find_for_address(
+        &self,
+        local_part: &str,
+        domain: &str,
+    ) -> Result<Option<InboundRoute>> {
+        let sql = format!(
+            "SELECT {} FROM inbound_routes r JOIN domains d ON d.id = r.domain_id
+             WHERE r.enabled = true AND LOWER(d.name) = LOWER($1)
+               AND (LOWER(r.local_part) = LOWER($2) OR r.local_part IS NULL)
+             ORDER BY r.local_part IS NULL
+             LIMIT 1",
+            ROUTE_COLUMNS
+        );
+        let route = sqlx::query_as::<_, InboundRoute>(&sql)
+            .bind(domain)
+            .bind(local_part)
+            .fetch_optional(self.pool.pool())
+            .await?;
+
+        Ok(route)
+    }
+
+    /// Enable or disable a route
+    pub async fn set_enabled(&self, tenant_id: TenantId, id: Uuid, enabled: bool) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE inbound_routes SET enabled = $3, updated_at = NOW()
+             WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .bind(enabled)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a route
+    pub async fn delete(&self, tenant_id: TenantId, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM inbound_routes WHERE tenant_id = $1 AND id = $2")
+            .bind(tenant_id)
+            .bind(id)
+            .execute(self.pool.pool())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Random route secret
+fn generate_secret() -> String {
+    format!(
+        "whsec_{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(local_part: Option<&str>) -> InboundRoute {
+        InboundRoute {
+            id: Uuid::nil(),
+            tenant_id: Uuid::nil(),
+            domain_id: Uuid::nil(),
+            domain: "example.com".to_string(),
+            local_part: local_part.map(str::to_string),
+            webhook_url: "https://hooks.example.net/inbound".to_string(),
+            secret: generate_secret(),
+            store_message: false,
+            enabled: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_route_address() {
+        assert_eq!(route(Some("orders")).address(), "orders@example.com");
+        assert_eq!(route(None).address(), "*@example.com");
+    }
+
+    #[test]
+    fn test_generate_secret() {
+        let secret = generate_secret();
+        assert!(secret.starts_with("whsec_"));
+        assert_eq!(secret.len(), 6 + 64);
+        assert_ne!(secret, generate_secret());
+    }
+}
@@ -0,0 +1,248 @@
+//! Job queue repository
+//!
+//! Read-side aggregation over the `jobs` table for queue monitoring.
+//! Every query is served by one of the `idx_jobs_*` indexes added in the
+//! queue statistics and fair scheduling migrations.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::TenantId;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Number of recent failures returned with queue statistics
+const RECENT_FAILURES_LIMIT: i64 = 20;
+
+/// Job count for a status, lane and priority
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct QueueCount {
+    pub status: String,
+    pub lane: String,
+    pub priority: i32,
+    pub count: i64,
+}
+
+/// A recently failed job
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct QueueFailure {
+    pub job_id: Uuid,
+    /// `failed`, or `dead_letter` for a delivery that exceeded its queue
+    /// lifetime
+    pub status: String,
+    pub tenant_id: Option<Uuid>,
+    pub message_id: Option<String>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub failed_at: Option<DateTime<Utc>>,
+}
+
+/// Queue latency of one tenant
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TenantQueueLatency {
+    pub tenant_id: Option<Uuid>,
+    pub pending: i64,
+    pub processing: i64,
+    /// Creation time of the tenant's oldest pending job
+    pub oldest_pending_at: Option<DateTime<Utc>>,
+    /// Deliveries started in the last hour
+    pub started_last_hour: i64,
+    /// Average seconds from due to started over the last hour
+    pub avg_wait_secs: Option<f64>,
+    /// Longest wait from due to started over the last hour
+    pub max_wait_secs: Option<f64>,
+}
+
+/// Aggregated queue statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    /// Counts grouped by status, lane and priority
+    pub counts: Vec<QueueCount>,
+    /// Creation time of the oldest pending job
+    pub oldest_pending_at: Option<DateTime<Utc>>,
+    /// Jobs completed in the last minute
+    pub completed_last_minute: i64,
+    /// Jobs completed in the last hour
+    pub completed_last_hour: i64,
+    /// Most recent permanent failures
+    pub recent_failures: Vec<QueueFailure>,
+    /// Per-tenant latency, longest waiting tenants first
+    pub tenant_latency: Vec<TenantQueueLatency>,
+}
+
+impl QueueStats {
+    /// Total jobs with the given status across lanes and priorities
+    pub fn total(&self, status: &str) -> i64 {
+        self.counts
+            .iter()
+            .filter(|c| c.status == status)
+            .map(|c| c.count)
+            .sum()
+    }
+
+    /// Total jobs with the given status in a lane
+    pub fn lane_total(&self, status: &str, lane: &str) -> i64 {
+        self.counts
+            .iter()
+            .filter(|c| c.status == status && c.lane == lane)
+            .map(|c| c.count)
+            .sum()
+    }
+}
+
+/// Job queue repository
+pub struct JobRepository {
+    pool: DatabasePool,
+}
+
+impl JobRepository {
+    /// Create a new job repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Aggregate statistics for a queue, optionally scoped to a tenant
+    pub async fn queue_stats(&self, queue: &str, tenant_id: Option<TenantId>) -> Result<QueueStats> {
+        let pool = self.pool.pool();
+        let tenant_filter = if tenant_id.is_some() {
+            "AND tenant_id = $2"
+        } else {
+            ""
+        };
+
+        let sql = format!(
+            "SELECT status, lane, priority, COUNT(*) AS count FROM jobs
+             WHERE queue = $1 {}
+             GROUP BY status, lane, priority
+             ORDER BY status, lane, priority DESC",
+            tenant_filter
+        );
+        let mut query = sqlx::query_as::<_, QueueCount>(&sql).bind(queue);
+        if let Some(tenant_id) = tenant_id {
+            query = query.bind(tenant_id);
+        }
+        let counts = query.fetch_all(pool).await?;
+
+        let sql = format!(
+            "SELECT created_at FROM jobs
+             WHERE queue = $1 AND status = 'pending' {}
+             ORDER BY created_at ASC
+             LIMIT 1",
+            tenant_filter
+        );
+        let mut query = sqlx::query_as::<_, (DateTime<Utc>,)>(&sql).bind(queue);
+        if let Some(tenant_id) = tenant_id {
+            query = query.bind(tenant_id);
+        }
+        let oldest_pending_at = query.fetch_optional(pool).await?.map(|(t,)| t);
+
+        let sql = format!(
+            "SELECT
+                COUNT(*) FILTER (WHERE completed_at > NOW() - INTERVAL '1 minute'),
+                COUNT(*)
+             FROM jobs
+             WHERE queue = $1 AND status = 'completed' {}
+             AND completed_at > NOW() - INTERVAL '1 hour'",
+            tenant_filter
+        );
+        let mut query = sqlx::query_as::<_, (i64, i64)>(&sql).bind(queue);
+        if let Some(tenant_id) = tenant_id {
+            query = query.bind(tenant_id);
+        }
+        let (completed_last_minute, completed_last_hour) = query.fetch_one(pool).await?;
+
+        let sql = format!(
+            "SELECT id AS job_id, status, tenant_id, payload->>'message_id' AS message_id,
+                    attempts, last_error, completed_at AS failed_at
+             FROM jobs
+             WHERE queue = $1 AND status IN ('failed', 'dead_letter') {}
+             ORDER BY completed_at DESC NULLS LAST
+             LIMIT {}",
+            tenant_filter, RECENT_FAILURES_LIMIT
+        );
+        let mut query = sqlx::query_as::<_, QueueFailure>(&sql).bind(queue);
+        if let Some(tenant_id) = tenant_id {
+            query = query.bind(tenant_id);
+        }
+        let recent_failures = query.fetch_all(pool).await?;
+
+        // Jobs waiting for a retry keep the started_at of their last attempt,
+        // so waits are only measured for jobs that have left the queue
+        let sql = format!(
+            "SELECT tenant_id,
+                    COUNT(*) FILTER (WHERE status = 'pending') AS pending,
+                    COUNT(*) FILTER (WHERE status = 'processing') AS processing,
+                    MIN(created_at) FILTER (WHERE status = 'pending') AS oldest_pending_at,
+                    COUNT(*) FILTER (WHERE {started}) AS started_last_hour,
+                    AVG(EXTRACT(EPOCH FROM started_at - scheduled_at))
+                        FILTER (WHERE {started})::FLOAT8 AS avg_wait_secs,
+                    MAX(EXTRACT(EPOCH FROM started_at - scheduled_at))
+                        FILTER (WHERE {started})::FLOAT8 AS max_wait_secs
+             FROM jobs
+             WHERE queue = $1 {tenant_filter}
+             AND (status IN ('pending', 'processing')
+                  OR started_at > NOW() - INTERVAL '1 hour')
+             GROUP BY tenant_id
+             ORDER BY oldest_pending_at ASC NULLS LAST, max_wait_secs DESC NULLS LAST",
+            started = "status <> 'pending' AND started_at > NOW() - INTERVAL '1 hour'",
+            tenant_filter = tenant_filter
+        );
+        let mut query = sqlx::query_as::<_, TenantQueueLatency>(&sql).bind(queue);
+        if let Some(tenant_id) = tenant_id {
+            query = query.bind(tenant_id);
+        }
+        let tenant_latency = query.fetch_all(pool).await?;
+
+        Ok(QueueStats {
+            counts,
+            oldest_pending_at,
+            completed_last_minute,
+            completed_last_hour,
+            recent_failures,
+            tenant_latency,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_stats_total() {
+        let stats = QueueStats {
+            counts: vec![
+                QueueCount {
+                    status: "pending".to_string(),
+                    lane: "transactional".to_string(),
+                    priority: 10,
+                    count: 3,
+                },
+                QueueCount {
+                    status: "pending".to_string(),
+                    lane: "campaign".to_string(),
+                    priority: 0,
+                    count: 4,
+                },
+                QueueCount {
+                    status: "failed".to_string(),
+                    lane: "campaign".to_string(),
+                    priority: 0,
+                    count: 1,
+                },
+            ],
+            oldest_pending_at: None,
+            completed_last_minute: 0,
+            completed_last_hour: 0,
+            recent_failures: Vec::new(),
+            tenant_latency: Vec::new(),
+        };
+
+        assert_eq!(stats.total("pending"), 7);
+        assert_eq!(stats.total("failed"), 1);
+        assert_eq!(stats.total("completed"), 0);
+        assert_eq!(stats.lane_total("pending", "campaign"), 4);
+        assert_eq!(stats.lane_total("pending", "bulk"), 0);
+    }
+}
@@ -0,0 +1,145 @@
+//! Mailbox ACL repository (RFC 4314)
+//!
+//! Stores rights granted on a mailbox to users other than its owner.
+//! Rights are kept as RFC 4314 right letters; validating them is up to the
+//! protocol layer.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Rights granted to a user on a mailbox
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MailboxAclEntry {
+    pub mailbox_id: Uuid,
+    pub user_id: UserId,
+    /// Grantee email address, used as the ACL identifier
+    pub identifier: String,
+    pub rights: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A mailbox shared with a user by its owner
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SharedMailbox {
+    pub mailbox_id: Uuid,
+    pub address: String,
+    pub owner_id: UserId,
+    pub owner_email: String,
+    pub uid_validity: i64,
+    pub uid_next: i64,
+    pub message_count: i64,
+    pub unseen_count: i64,
+    pub rights: String,
+}
+
+/// Mailbox ACL repository
+pub struct MailboxAclRepository {
+    pool: DatabasePool,
+}
+
+impl MailboxAclRepository {
+    /// Create a new mailbox ACL repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// List the rights granted on a mailbox
+    pub async fn list(
+        &self,
+        tenant_id: TenantId,
+        mailbox_id: Uuid,
+    ) -> Result<Vec<MailboxAclEntry>> {
+        let entries = sqlx::query_as::<_, MailboxAclEntry>(
+            r#"
+            SELECT a.mailbox_id, a.user_id, u.email AS identifier, a.rights,
+                   a.created_at, a.updated_at
+            FROM mailbox_acl a
+            JOIN users u ON u.id = a.user_id
+            WHERE a.tenant_id = $1 AND a.mailbox_id = $2
+            ORDER BY u.email
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Grant rights to a user, replacing any previous grant
+    pub async fn set(
+        &self,
+        tenant_id: TenantId,
+        mailbox_id: Uuid,
+        user_id: UserId,
+        rights: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO mailbox_acl (tenant_id, mailbox_id, user_id, rights)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (mailbox_id, user_id)
+            DO UPDATE SET rights = EXCLUDED.rights, updated_at = NOW()
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(user_id)
+        .bind(rights)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Revoke all rights of a user; returns whether a grant existed
+    pub async fn remove(
+        &self,
+        tenant_id: TenantId,
+        mailbox_id: Uuid,
+        user_id: UserId,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM mailbox_acl WHERE tenant_id = $1 AND mailbox_id = $2 AND user_id = $3",
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(user_id)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Mailboxes other users have shared with a user
+    pub async fn shared_with(
+        &self,
+        tenant_id: TenantId,
+        user_id: UserId,
+    ) -> Result<Vec<SharedMailbox>> {
+        let mailboxes = sqlx::query_as::<_, SharedMailbox>(
+            r#"
+            SELECT m.id AS mailbox_id, m.address, o.id AS owner_id, o.email AS owner_email,
+                   m.uid_validity, m.uid_next, m.message_count, m.unseen_count, a.rights
+            FROM mailbox_acl a
+            JOIN mailboxes m ON m.id = a.mailbox_id
+            JOIN users o ON o.id = m.user_id
+            WHERE a.tenant_id = $1 AND a.user_id = $2 AND m.user_id <> $2
+            ORDER BY o.email, m.address
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(mailboxes)
+    }
+}
@@ -0,0 +1,176 @@
+//! Mailbox filter repository
+//!
+//! Server-side filter rules of a mailbox, for users who do not write
+//! Sieve. Conditions and actions are stored as JSON and interpreted by the
+//! delivery code.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::TenantId;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A filter rule of a mailbox
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MailboxFilter {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub mailbox_id: Uuid,
+    pub name: String,
+    /// Filters run in ascending position
+    pub position: i32,
+    pub enabled: bool,
+    /// Policy conditions, all of which must match
+    pub conditions: serde_json::Value,
+    /// Filter actions
+    pub actions: serde_json::Value,
+    /// Skip later filters when this one matches
+    pub stop_processing: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Input for creating or replacing a filter
+#[derive(Debug, Clone)]
+pub struct CreateMailboxFilter {
+    pub name: String,
+    /// `None` puts a new filter after the existing ones
+    pub position: Option<i32>,
+    pub enabled: bool,
+    pub conditions: serde_json::Value,
+    pub actions: serde_json::Value,
+    pub stop_processing: bool,
+}
+
+/// Mailbox filter repository
+pub struct MailboxFilterRepository {
+    pool: DatabasePool,
+}
+
+impl MailboxFilterRepository {
+    /// Create a new mailbox filter repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Filters of a mailbox, in the order they run
+    pub async fn list(&self, tenant_id: TenantId, mailbox_id: Uuid) -> Result<Vec<MailboxFilter>> {
+        let filters = sqlx::query_as::<_, MailboxFilter>(
+            "SELECT * FROM mailbox_filters WHERE tenant_id = $1 AND mailbox_id = $2
+             ORDER BY position, created_at",
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(filters)
+    }
+
+    /// Enabled filters of a mailbox, in the order they run
+    pub async fn list_enabled(&self, mailbox_id: Uuid) -> Result<Vec<MailboxFilter>> {
+        let filters = sqlx::query_as::<_, MailboxFilter>(
+            "SELECT * FROM mailbox_filters WHERE mailbox_id = $1 AND enabled
+             ORDER BY position, created_at",
+        )
+        .bind(mailbox_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(filters)
+    }
+
+    /// Get a filter of a mailbox
+    pub async fn get(
+        &self,
+        tenant_id: TenantId,
+        mailbox_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<MailboxFilter>> {
+        let filter = sqlx::query_as::<_, MailboxFilter>(
+            "SELECT * FROM mailbox_filters WHERE tenant_id = $1 AND mailbox_id = $2 AND id = $3",
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(filter)
+    }
+
+    /// Create a filter
+    pub async fn create(
+        &self,
+        tenant_id: TenantId,
+        mailbox_id: Uuid,
+        input: CreateMailboxFilter,
+    ) -> Result<MailboxFilter> {
+        let filter = sqlx::query_as::<_, MailboxFilter>(
+            r#"
+            INSERT INTO mailbox_filters (id, tenant_id, mailbox_id, name, position, enabled,
+                                         conditions, actions, stop_processing)
+            VALUES ($1, $2, $3, $4,
+                    COALESCE($5, (SELECT COALESCE(MAX(position) + 1, 0)
+                                  FROM mailbox_filters WHERE mailbox_id = $3)),
+                    $6, $7, $8, $9)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(&input.name)
+        .bind(input.position)
+        .bind(input.enabled)
+        .bind(&input.conditions)
+        .bind(&input.actions)
+        .bind(input.stop_processing)
+        .fetch_one(self.pool.pool())
+        .await?;
+        Ok(filter)
+    }
+
+    /// Replace a filter; `None` when it does not exist
+    pub async fn update(
+        &self,
+        tenant_id: TenantId,
+        mailbox_id: Uuid,
+        id: Uuid,
+        input: CreateMailboxFilter,
+    ) -> Result<Option<MailboxFilter>> {
+        let filter = sqlx::query_as::<_, MailboxFilter>(
+            r#"
+            UPDATE mailbox_filters
+            SET name = $4, position = COALESCE($5, position), enabled = $6, conditions = $7,
+                actions = $8, stop_processing = $9, updated_at = NOW()
+            WHERE tenant_id = $1 AND mailbox_id = $2 AND id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(id)
+        .bind(&input.name)
+        .bind(input.position)
+        .bind(input.enabled)
+        .bind(&input.conditions)
+        .bind(&input.actions)
+        .bind(input.stop_processing)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(filter)
+    }
+
+    /// Delete a filter; false when it does not exist
+    pub async fn delete(&self, tenant_id: TenantId, mailbox_id: Uuid, id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM mailbox_filters WHERE tenant_id = $1 AND mailbox_id = $2 AND id = $3",
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(id)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
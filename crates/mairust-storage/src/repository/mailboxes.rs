@@ -1,10 +1,12 @@
 //! Mailbox repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreateMailbox, Mailbox};
 use async_trait::async_trait;
 use mairust_common::types::{DomainId, MailboxId, TenantId, UserId};
 use mairust_common::{Error, Result};
+use serde::Serialize;
+use sqlx::FromRow;
 use uuid::Uuid;
 
 /// Mailbox repository trait
@@ -17,10 +19,22 @@ pub trait MailboxRepository: Send + Sync {
     async fn list_by_domain(&self, domain_id: DomainId) -> Result<Vec<Mailbox>>;
     async fn list_by_user(&self, user_id: UserId) -> Result<Vec<Mailbox>>;
     async fn update_quota(&self, id: MailboxId, quota_bytes: Option<i64>) -> Result<()>;
+    /// Adjust `used_bytes` by hand; stored messages are already counted by
+    /// the mailbox counters trigger
     async fn update_used_bytes(&self, id: MailboxId, delta: i64) -> Result<()>;
     async fn delete(&self, id: MailboxId) -> Result<()>;
 }
 
+/// Message counters maintained on a mailbox by the database
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MailboxCounters {
+    pub mailbox_id: MailboxId,
+    /// All messages, including those flagged `\Deleted`
+    pub message_count: i64,
+    /// Unseen messages not flagged `\Deleted`
+    pub unread_count: i64,
+}
+
 /// Database mailbox repository
 pub struct DbMailboxRepository {
     pool: DatabasePool,
@@ -37,7 +51,7 @@ impl DbMailboxRepository {
             .bind(address)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     /// Find mailbox by address within a specific tenant (for API handlers)
@@ -53,7 +67,39 @@ impl DbMailboxRepository {
         .bind(address)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
+    }
+
+    /// Read the maintained message counters of a mailbox
+    pub async fn counters(
+        &self,
+        tenant_id: TenantId,
+        id: MailboxId,
+    ) -> Result<Option<MailboxCounters>> {
+        sqlx::query_as::<_, MailboxCounters>(
+            "SELECT id AS mailbox_id, message_count, unread_count FROM mailboxes WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await
+        .map_err(db_error)
+    }
+
+    /// Bytes counted against the quota of a mailbox: its own messages and,
+    /// when it has an owner, those in the owner's folders
+    pub async fn quota_usage(&self, mailbox: &Mailbox) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(SUM(used_bytes), 0)::BIGINT FROM mailboxes
+             WHERE id = $1
+                OR (tenant_id = $2 AND user_id = $3 AND folder_path IS NOT NULL)",
+        )
+        .bind(mailbox.id)
+        .bind(mailbox.tenant_id)
+        .bind(mailbox.user_id)
+        .fetch_one(self.pool.pool())
+        .await
+        .map_err(db_error)
     }
 }
 
@@ -80,7 +126,7 @@ impl MailboxRepository for DbMailboxRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(input.tenant_id, id)
             .await?
@@ -93,7 +139,7 @@ impl MailboxRepository for DbMailboxRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn get_by_address(&self, address: &str) -> Result<Option<Mailbox>> {
@@ -101,39 +147,42 @@ impl MailboxRepository for DbMailboxRepository {
             .bind(address)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn list(&self, tenant_id: TenantId, limit: i64, offset: i64) -> Result<Vec<Mailbox>> {
         sqlx::query_as::<_, Mailbox>(
-            "SELECT * FROM mailboxes WHERE tenant_id = $1 ORDER BY address ASC LIMIT $2 OFFSET $3",
+            "SELECT * FROM mailboxes WHERE tenant_id = $1 AND quarantine_of IS NULL
+             ORDER BY address ASC LIMIT $2 OFFSET $3",
         )
         .bind(tenant_id)
         .bind(limit)
         .bind(offset)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_by_domain(&self, domain_id: DomainId) -> Result<Vec<Mailbox>> {
         sqlx::query_as::<_, Mailbox>(
-            "SELECT * FROM mailboxes WHERE domain_id = $1 ORDER BY address ASC",
+            "SELECT * FROM mailboxes WHERE domain_id = $1 AND quarantine_of IS NULL
+             ORDER BY address ASC",
         )
         .bind(domain_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_by_user(&self, user_id: UserId) -> Result<Vec<Mailbox>> {
         sqlx::query_as::<_, Mailbox>(
-            "SELECT * FROM mailboxes WHERE user_id = $1 ORDER BY address ASC",
+            "SELECT * FROM mailboxes WHERE user_id = $1 AND quarantine_of IS NULL
+             ORDER BY address ASC",
         )
         .bind(user_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn update_quota(&self, id: MailboxId, quota_bytes: Option<i64>) -> Result<()> {
@@ -144,7 +193,7 @@ impl MailboxRepository for DbMailboxRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -158,7 +207,7 @@ impl MailboxRepository for DbMailboxRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
         Ok(())
     }
 
@@ -167,7 +216,7 @@ impl MailboxRepository for DbMailboxRepository {
             .bind(id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
@@ -0,0 +1,132 @@
+//! Maintenance window repository
+//!
+//! While a maintenance window is active the system is read-only. Windows
+//! can be scheduled ahead of time or started immediately and ended by hand.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A scheduled or running maintenance window
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct MaintenanceWindow {
+    pub id: Uuid,
+    pub starts_at: DateTime<Utc>,
+    /// `None` for a window that lasts until an admin ends it
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Shown to clients while the window is active
+    pub message: String,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    /// Whether the window is active at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now && !matches!(self.ends_at, Some(end) if end <= now)
+    }
+}
+
+/// Input for scheduling a window
+#[derive(Debug, Clone)]
+pub struct CreateMaintenanceWindow {
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub message: String,
+    pub created_by: Option<String>,
+}
+
+/// Maintenance window repository
+pub struct MaintenanceRepository {
+    pool: DatabasePool,
+}
+
+impl MaintenanceRepository {
+    /// Create a new maintenance window repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Schedule a window
+    pub async fn create(&self, input: CreateMaintenanceWindow) -> Result<MaintenanceWindow> {
+        let window = sqlx::query_as::<_, MaintenanceWindow>(
+            r#"
+            INSERT INTO maintenance_windows (id, starts_at, ends_at, message, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(input.starts_at)
+        .bind(input.ends_at)
+        .bind(&input.message)
+        .bind(&input.created_by)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(window)
+    }
+
+    /// Windows that are active or have not started yet, earliest first
+    pub async fn list_open(&self) -> Result<Vec<MaintenanceWindow>> {
+        let windows = sqlx::query_as::<_, MaintenanceWindow>(
+            "SELECT * FROM maintenance_windows
+             WHERE ends_at IS NULL OR ends_at > NOW()
+             ORDER BY starts_at ASC",
+        )
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(windows)
+    }
+
+    /// End the active windows now, returning how many were ended
+    pub async fn end_active(&self) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE maintenance_windows SET ends_at = NOW()
+             WHERE starts_at <= NOW() AND (ends_at IS NULL OR ends_at > NOW())",
+        )
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Cancel a window that has not started yet
+    pub async fn cancel(&self, id: Uuid) -> Result<bool> {
+        let result =
+            sqlx::query("DELETE FROM maintenance_windows WHERE id = $1 AND starts_at > NOW()")
+                .bind(id)
+                .execute(self.pool.pool())
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_window_is_active() {
+        let now = Utc::now();
+        let window = |starts_at, ends_at| MaintenanceWindow {
+            id: Uuid::nil(),
+            starts_at,
+            ends_at,
+            message: "Storage migration".to_string(),
+            created_by: None,
+            created_at: now,
+        };
+
+        assert!(window(now - Duration::minutes(5), None).is_active(now));
+        assert!(window(now, Some(now + Duration::hours(1))).is_active(now));
+        assert!(!window(now + Duration::minutes(5), None).is_active(now));
+        assert!(!window(now - Duration::hours(1), Some(now)).is_active(now));
+    }
+}
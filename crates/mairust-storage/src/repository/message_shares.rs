@@ -0,0 +1,194 @@
+//! Message share repository
+//!
+//! Read-only share links for single messages. The token in a link is only
+//! returned when the share is created; the database keeps its SHA-256 hash.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A share link of a message
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MessageShare {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub message_id: Uuid,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub created_by: Option<UserId>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// Times the shared page was opened
+    pub view_count: i32,
+    pub last_viewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MessageShare {
+    /// Whether the link still opens the message
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at > now
+    }
+}
+
+/// Input for sharing a message
+#[derive(Debug, Clone)]
+pub struct CreateMessageShare {
+    pub tenant_id: TenantId,
+    pub message_id: Uuid,
+    pub created_by: Option<UserId>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Message share repository
+pub struct MessageShareRepository {
+    pool: DatabasePool,
+}
+
+impl MessageShareRepository {
+    /// Create a new message share repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Share a message, returning the share and its token
+    pub async fn create(&self, input: &CreateMessageShare) -> Result<(MessageShare, String)> {
+        let token = generate_share_token();
+        let share = sqlx::query_as::<_, MessageShare>(
+            r#"
+            INSERT INTO message_shares (id, tenant_id, message_id, token_hash, created_by,
+                                        expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(input.tenant_id)
+        .bind(input.message_id)
+        .bind(hash_share_token(&token))
+        .bind(input.created_by)
+        .bind(input.expires_at)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok((share, token))
+    }
+
+    /// Shares of a message, newest first
+    pub async fn list_for_message(
+        &self,
+        tenant_id: TenantId,
+        message_id: Uuid,
+    ) -> Result<Vec<MessageShare>> {
+        let shares = sqlx::query_as::<_, MessageShare>(
+            "SELECT * FROM message_shares WHERE tenant_id = $1 AND message_id = $2
+             ORDER BY created_at DESC",
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(shares)
+    }
+
+    /// Revoke a share; revoking it again keeps the first revocation time.
+    /// `None` when the message has no such share
+    pub async fn revoke(
+        &self,
+        tenant_id: TenantId,
+        message_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<MessageShare>> {
+        let share = sqlx::query_as::<_, MessageShare>(
+            "UPDATE message_shares SET revoked_at = COALESCE(revoked_at, NOW())
+             WHERE tenant_id = $1 AND message_id = $2 AND id = $3
+             RETURNING *",
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Share of a token that is neither revoked nor expired
+    pub async fn find_active(&self, token: &str) -> Result<Option<MessageShare>> {
+        let share = sqlx::query_as::<_, MessageShare>(
+            "SELECT * FROM message_shares
+             WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()",
+        )
+        .bind(hash_share_token(token))
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Count a view of the shared message
+    pub async fn record_view(&self, id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE message_shares SET view_count = view_count + 1, last_viewed_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Random share token, 64 hex characters
+fn generate_share_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Hash of a share token as stored
+pub fn hash_share_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_share_token() {
+        let token = generate_share_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_share_token());
+        assert_eq!(hash_share_token(&token).len(), 64);
+        assert_eq!(hash_share_token(&token), hash_share_token(&token));
+        assert_ne!(hash_share_token(&token), token);
+    }
+
+    #[test]
+    fn test_is_active() {
+        let now = Utc::now();
+        let mut share = MessageShare {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            message_id: Uuid::now_v7(),
+            token_hash: String::new(),
+            created_by: None,
+            expires_at: now + Duration::hours(1),
+            revoked_at: None,
+            view_count: 0,
+            last_viewed_at: None,
+            created_at: now,
+        };
+        assert!(share.is_active(now));
+        assert!(!share.is_active(now + Duration::hours(2)));
+        share.revoked_at = Some(now);
+        assert!(!share.is_active(now));
+    }
+}
@@ -1,6 +1,6 @@
 //! Message repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreateMessage, Message};
 use async_trait::async_trait;
 use mairust_common::types::{MailboxId, MessageId, TenantId};
@@ -77,7 +77,7 @@ impl DbMessageRepository {
         .bind(id)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     /// Find messages by mailbox (simplified version for handlers)
@@ -94,7 +94,7 @@ impl DbMessageRepository {
         .bind(limit as i64)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     /// Create a message directly from Message struct
@@ -105,10 +105,11 @@ impl DbMessageRepository {
                 id, tenant_id, mailbox_id, message_id_header, subject,
                 from_address, to_addresses, cc_addresses, headers, body_preview,
                 body_size, has_attachments, storage_path, seen, answered,
-                flagged, deleted, draft, spam_score, tags, metadata, received_at, created_at
+                flagged, deleted, draft, spam_score, tags, metadata, received_at, created_at,
+                sent_date, snippet
             ) VALUES (
                 $1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
-                $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23
+                $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25
             )
             "#,
         )
@@ -135,9 +136,11 @@ impl DbMessageRepository {
         .bind(&message.metadata)
         .bind(message.received_at)
         .bind(message.created_at)
+        .bind(message.sent_date)
+        .bind(&message.snippet)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         Ok(())
     }
@@ -186,9 +189,7 @@ impl DbMessageRepository {
             q = q.bind(v);
         }
 
-        q.execute(self.pool.pool())
-            .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+        q.execute(self.pool.pool()).await.map_err(db_error)?;
 
         Ok(())
     }
@@ -222,10 +223,10 @@ impl MessageRepository for DbMessageRepository {
                 id, tenant_id, mailbox_id, message_id_header, subject,
                 from_address, to_addresses, cc_addresses, headers, body_preview,
                 body_size, has_attachments, storage_path, seen, answered,
-                flagged, deleted, draft, tags, metadata, received_at, created_at
+                flagged, deleted, draft, tags, metadata, received_at, created_at, snippet
             ) VALUES (
                 $1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
-                $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22
+                $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23
             )
             "#,
         )
@@ -251,9 +252,10 @@ impl MessageRepository for DbMessageRepository {
         .bind(&metadata_json)
         .bind(input.received_at)
         .bind(now)
+        .bind(&input.snippet)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(input.tenant_id, id)
             .await?
@@ -271,7 +273,7 @@ impl MessageRepository for DbMessageRepository {
         .bind(id)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         Ok(message)
     }
@@ -297,7 +299,7 @@ impl MessageRepository for DbMessageRepository {
         .bind(offset)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         Ok(messages)
     }
@@ -347,9 +349,7 @@ impl MessageRepository for DbMessageRepository {
             q = q.bind(v);
         }
 
-        q.execute(self.pool.pool())
-            .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+        q.execute(self.pool.pool()).await.map_err(db_error)?;
 
         Ok(())
     }
@@ -377,7 +377,7 @@ impl MessageRepository for DbMessageRepository {
             .bind(tags_json)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
 
         Ok(())
     }
@@ -408,7 +408,7 @@ impl MessageRepository for DbMessageRepository {
             .bind(tags_json)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
 
         Ok(())
     }
@@ -428,7 +428,7 @@ impl MessageRepository for DbMessageRepository {
         .bind(mailbox_id)
         .fetch_one(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         Ok(row.get::<i64, _>("count"))
     }
@@ -444,7 +444,7 @@ impl MessageRepository for DbMessageRepository {
         .bind(mailbox_id)
         .fetch_one(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         Ok(row.get::<i64, _>("count"))
     }
@@ -0,0 +1,203 @@
+//! Mailbox metadata repository (RFC 5464)
+//!
+//! Stores per-mailbox and server-level annotations for IMAP
+//! GETMETADATA/SETMETADATA and the folder settings API.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Maximum size of a single metadata value in bytes
+pub const METADATA_MAX_VALUE_SIZE: usize = 64 * 1024;
+
+/// Maximum number of entries per mailbox and user
+pub const METADATA_MAX_ENTRIES: i64 = 100;
+
+/// A metadata entry
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MetadataEntry {
+    pub entry: String,
+    pub value: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Normalize an entry name, returning `None` if it is not valid
+///
+/// Entry names are case-insensitive, live under `/private/` or `/shared/`,
+/// and may not contain wildcards, empty components or a trailing slash.
+pub fn normalize_entry(entry: &str) -> Option<String> {
+    let entry = entry.to_lowercase();
+    if !(entry.starts_with("/private/") || entry.starts_with("/shared/")) {
+        return None;
+    }
+    if entry.ends_with('/')
+        || entry.contains("//")
+        || entry.chars().any(|c| c == '*' || c == '%' || c.is_control())
+    {
+        return None;
+    }
+    Some(entry)
+}
+
+/// Whether a (normalized) entry is private to the user
+pub fn is_private_entry(entry: &str) -> bool {
+    entry.starts_with("/private/")
+}
+
+/// Mailbox metadata repository
+pub struct MetadataRepository {
+    pool: DatabasePool,
+}
+
+impl MetadataRepository {
+    /// Create a new metadata repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// List the entries visible to a user: shared entries plus their private ones
+    ///
+    /// `mailbox_id` of `None` selects server-level entries.
+    pub async fn list(
+        &self,
+        tenant_id: TenantId,
+        user_id: Option<UserId>,
+        mailbox_id: Option<Uuid>,
+    ) -> Result<Vec<MetadataEntry>> {
+        let entries = sqlx::query_as::<_, MetadataEntry>(
+            r#"
+            SELECT entry, value, updated_at
+            FROM mailbox_metadata
+            WHERE tenant_id = $1
+              AND mailbox_id IS NOT DISTINCT FROM $2
+              AND (user_id IS NULL OR user_id = $3)
+            ORDER BY entry
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(user_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Set or remove (`None`) entries atomically
+    ///
+    /// Entries must already be normalized; private entries are stored for
+    /// `user_id`. Returns `false` without changing anything if the result
+    /// would exceed [`METADATA_MAX_ENTRIES`].
+    pub async fn set_entries(
+        &self,
+        tenant_id: TenantId,
+        user_id: Option<UserId>,
+        mailbox_id: Option<Uuid>,
+        entries: &[(String, Option<String>)],
+    ) -> Result<bool> {
+        let mut tx = self.pool.pool().begin().await?;
+
+        for (entry, value) in entries {
+            let owner = if is_private_entry(entry) {
+                Some(user_id.ok_or_else(|| anyhow::anyhow!("Private entry without a user"))?)
+            } else {
+                None
+            };
+
+            match value {
+                Some(value) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO mailbox_metadata (tenant_id, mailbox_id, user_id, entry, value)
+                        VALUES ($1, $2, $3, $4, $5)
+                        ON CONFLICT (
+                            tenant_id,
+                            COALESCE(mailbox_id, '00000000-0000-0000-0000-000000000000'::uuid),
+                            COALESCE(user_id, '00000000-0000-0000-0000-000000000000'::uuid),
+                            entry
+                        )
+                        DO UPDATE SET value = EXCLUDED.value, updated_at = NOW()
+                        "#,
+                    )
+                    .bind(tenant_id)
+                    .bind(mailbox_id)
+                    .bind(owner)
+                    .bind(entry)
+                    .bind(value)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                None => {
+                    sqlx::query(
+                        r#"
+                        DELETE FROM mailbox_metadata
+                        WHERE tenant_id = $1
+                          AND mailbox_id IS NOT DISTINCT FROM $2
+                          AND user_id IS NOT DISTINCT FROM $3
+                          AND entry = $4
+                        "#,
+                    )
+                    .bind(tenant_id)
+                    .bind(mailbox_id)
+                    .bind(owner)
+                    .bind(entry)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+        }
+
+        let (count,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM mailbox_metadata
+            WHERE tenant_id = $1
+              AND mailbox_id IS NOT DISTINCT FROM $2
+              AND (user_id IS NULL OR user_id = $3)
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(mailbox_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if count > METADATA_MAX_ENTRIES {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_entry() {
+        assert_eq!(
+            normalize_entry("/private/Comment").as_deref(),
+            Some("/private/comment")
+        );
+        assert_eq!(
+            normalize_entry("/shared/vendor/example/color").as_deref(),
+            Some("/shared/vendor/example/color")
+        );
+        assert_eq!(normalize_entry("/comment"), None);
+        assert_eq!(normalize_entry("/private/"), None);
+        assert_eq!(normalize_entry("/private//comment"), None);
+        assert_eq!(normalize_entry("/shared/*"), None);
+    }
+
+    #[test]
+    fn test_is_private_entry() {
+        assert!(is_private_entry("/private/comment"));
+        assert!(!is_private_entry("/shared/comment"));
+    }
+}
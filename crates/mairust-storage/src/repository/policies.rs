@@ -1,6 +1,6 @@
 //! Policy repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreatePolicyRule, PolicyRule};
 use async_trait::async_trait;
 use mairust_common::types::{DomainId, PolicyId, TenantId};
@@ -62,7 +62,7 @@ impl PolicyRepository for DbPolicyRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(id)
             .await?
@@ -74,7 +74,7 @@ impl PolicyRepository for DbPolicyRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn list_global(&self) -> Result<Vec<PolicyRule>> {
@@ -83,7 +83,7 @@ impl PolicyRepository for DbPolicyRepository {
         )
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_by_tenant(&self, tenant_id: TenantId) -> Result<Vec<PolicyRule>> {
@@ -93,7 +93,7 @@ impl PolicyRepository for DbPolicyRepository {
         .bind(tenant_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_by_domain(&self, domain_id: DomainId) -> Result<Vec<PolicyRule>> {
@@ -103,7 +103,7 @@ impl PolicyRepository for DbPolicyRepository {
         .bind(domain_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list_effective(&self, tenant_id: TenantId, domain_id: Option<DomainId>) -> Result<Vec<PolicyRule>> {
@@ -142,7 +142,7 @@ impl PolicyRepository for DbPolicyRepository {
         query
             .fetch_all(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn update(&self, id: PolicyId, input: CreatePolicyRule) -> Result<PolicyRule> {
@@ -175,11 +175,11 @@ impl PolicyRepository for DbPolicyRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(id)
             .await?
-            .ok_or_else(|| Error::Internal("Policy rule not found".to_string()))
+            .ok_or_else(|| Error::NotFound("Policy rule not found".to_string()))
     }
 
     async fn enable(&self, id: PolicyId) -> Result<()> {
@@ -189,7 +189,7 @@ impl PolicyRepository for DbPolicyRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -200,7 +200,7 @@ impl PolicyRepository for DbPolicyRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -209,7 +209,7 @@ impl PolicyRepository for DbPolicyRepository {
             .bind(id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
@@ -0,0 +1,320 @@
+//! Policy history repository
+//!
+//! Versions of policy rules, recorded after every change, and the inbound
+//! policies that matched received messages.
+
+use crate::db::DatabasePool;
+use crate::models::PolicyRule;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{PolicyId, TenantId};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sqlx::{FromRow, PgExecutor};
+use uuid::Uuid;
+
+/// Fields of a policy snapshot that are not compared between versions
+const UNVERSIONED_FIELDS: &[&str] = &["id", "created_at", "updated_at"];
+
+/// Who changed a policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyActor {
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+}
+
+impl PolicyActor {
+    /// A change made with an API key
+    pub fn api_key(api_key_id: Uuid) -> Self {
+        Self {
+            actor_type: "api_key".to_string(),
+            actor_id: Some(api_key_id.to_string()),
+        }
+    }
+
+    /// A change made by the server itself, such as a bundle applied from
+    /// the command line
+    pub fn system() -> Self {
+        Self {
+            actor_type: "system".to_string(),
+            actor_id: None,
+        }
+    }
+}
+
+/// A version of a policy rule
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PolicyVersion {
+    pub id: Uuid,
+    pub policy_id: PolicyId,
+    pub tenant_id: Option<TenantId>,
+    /// Version number, from 1
+    pub version: i32,
+    /// `created`, `updated`, `enabled`, `disabled` or `deleted`
+    pub change: String,
+    /// The policy rule after the change
+    pub policy: Value,
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Fields changed since the previous version, as `from` and `to`
+    #[sqlx(skip)]
+    pub changes: Value,
+}
+
+/// A policy that matched a received message
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PolicyMatchRecord {
+    pub id: Uuid,
+    /// Tenant of the recipients
+    pub tenant_id: TenantId,
+    pub policy_id: PolicyId,
+    pub policy_name: String,
+    /// Version of the policy when it matched
+    pub policy_version: Option<i32>,
+    /// Message-ID header of the message
+    pub message_id: Option<String>,
+    pub sender: Option<String>,
+    pub recipients: Value,
+    pub client_ip: Option<String>,
+    /// Action types of the policy
+    pub actions: Value,
+    /// `rejected`, `deferred`, `quarantined` or `accepted`
+    pub outcome: String,
+    /// Reply given to the client when the message was refused
+    pub smtp_reply: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Input for recording a policy match
+#[derive(Debug, Clone)]
+pub struct NewPolicyMatch {
+    pub tenant_id: TenantId,
+    pub policy_id: PolicyId,
+    pub policy_name: String,
+    pub message_id: Option<String>,
+    pub sender: Option<String>,
+    pub recipients: Vec<String>,
+    pub client_ip: Option<String>,
+    pub actions: Vec<String>,
+    pub outcome: String,
+    pub smtp_reply: Option<String>,
+}
+
+/// Filters of the policy matches of a tenant
+#[derive(Debug, Clone, Default)]
+pub struct PolicyMatchFilter {
+    pub policy_id: Option<PolicyId>,
+    pub message_id: Option<String>,
+    pub sender: Option<String>,
+    pub recipient: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Record the version of a policy after a change
+///
+/// Takes any executor, so that a change made in a transaction is
+/// versioned in the same transaction.
+pub async fn record_policy_version<'e, E: PgExecutor<'e>>(
+    executor: E,
+    change: &str,
+    policy: &PolicyRule,
+    actor: &PolicyActor,
+) -> sqlx::Result<PolicyVersion> {
+    let snapshot = serde_json::to_value(policy).unwrap_or_default();
+    sqlx::query_as::<_, PolicyVersion>(
+        r#"
+        INSERT INTO policy_versions (id, policy_id, tenant_id, version, change, policy,
+                                     actor_type, actor_id, created_at)
+        SELECT $1, $2, $3, COALESCE(MAX(version), 0) + 1, $4, $5, $6, $7, NOW()
+        FROM policy_versions WHERE policy_id = $2
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(policy.id)
+    .bind(policy.tenant_id)
+    .bind(change)
+    .bind(&snapshot)
+    .bind(&actor.actor_type)
+    .bind(&actor.actor_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Fields that differ between two policy snapshots, as
+/// `{"field": {"from": ..., "to": ...}}`
+pub fn policy_changes(previous: &Value, current: &Value) -> Value {
+    let empty = Map::new();
+    let previous = previous.as_object().unwrap_or(&empty);
+    let current = current.as_object().unwrap_or(&empty);
+    let mut changes = Map::new();
+    for (field, to) in current {
+        if UNVERSIONED_FIELDS.contains(&field.as_str()) {
+            continue;
+        }
+        let from = previous.get(field).unwrap_or(&Value::Null);
+        if from != to {
+            changes.insert(field.clone(), json!({"from": from, "to": to}));
+        }
+    }
+    Value::Object(changes)
+}
+
+/// Policy history repository
+pub struct PolicyHistoryRepository {
+    pool: DatabasePool,
+}
+
+impl PolicyHistoryRepository {
+    /// Create a new policy history repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Record the version of a policy after a change
+    pub async fn record_version(
+        &self,
+        change: &str,
+        policy: &PolicyRule,
+        actor: &PolicyActor,
+    ) -> Result<PolicyVersion> {
+        Ok(record_policy_version(self.pool.pool(), change, policy, actor).await?)
+    }
+
+    /// Versions of a policy of a tenant, newest first, with the changes of
+    /// each
+    pub async fn versions(
+        &self,
+        tenant_id: TenantId,
+        policy_id: PolicyId,
+    ) -> Result<Vec<PolicyVersion>> {
+        let mut versions = sqlx::query_as::<_, PolicyVersion>(
+            r#"
+            SELECT * FROM policy_versions
+            WHERE tenant_id = $1 AND policy_id = $2
+            ORDER BY version
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(policy_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        let mut previous = Value::Null;
+        for version in &mut versions {
+            version.changes = policy_changes(&previous, &version.policy);
+            previous = version.policy.clone();
+        }
+        versions.reverse();
+        Ok(versions)
+    }
+
+    /// Record a policy that matched a message
+    pub async fn record_match(&self, input: &NewPolicyMatch) -> Result<Uuid> {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            r#"
+            INSERT INTO policy_matches (id, tenant_id, policy_id, policy_name, message_id, sender,
+                                        recipients, client_ip, actions, outcome, smtp_reply,
+                                        created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NOW())
+            "#,
+        )
+        .bind(id)
+        .bind(input.tenant_id)
+        .bind(input.policy_id)
+        .bind(&input.policy_name)
+        .bind(&input.message_id)
+        .bind(&input.sender)
+        .bind(json!(input.recipients))
+        .bind(&input.client_ip)
+        .bind(json!(input.actions))
+        .bind(&input.outcome)
+        .bind(&input.smtp_reply)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(id)
+    }
+
+    /// Policy matches of a tenant, newest first
+    pub async fn matches(
+        &self,
+        tenant_id: TenantId,
+        filter: &PolicyMatchFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<PolicyMatchRecord>> {
+        let matches = sqlx::query_as::<_, PolicyMatchRecord>(
+            r#"
+            SELECT m.*,
+                   (SELECT MAX(v.version) FROM policy_versions v
+                    WHERE v.policy_id = m.policy_id AND v.created_at <= m.created_at)
+                       AS policy_version
+            FROM policy_matches m
+            WHERE m.tenant_id = $1
+              AND ($2::UUID IS NULL OR m.policy_id = $2)
+              AND ($3::TEXT IS NULL OR m.message_id = $3)
+              AND ($4::TEXT IS NULL OR LOWER(m.sender) = LOWER($4))
+              AND ($5::TEXT IS NULL OR EXISTS (
+                  SELECT 1 FROM jsonb_array_elements_text(m.recipients) r
+                  WHERE LOWER(r) = LOWER($5)))
+              AND ($6::TEXT IS NULL OR m.outcome = $6)
+              AND ($7::TIMESTAMPTZ IS NULL OR m.created_at >= $7)
+              AND ($8::TIMESTAMPTZ IS NULL OR m.created_at < $8)
+            ORDER BY m.created_at DESC
+            LIMIT $9 OFFSET $10
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(filter.policy_id)
+        .bind(&filter.message_id)
+        .bind(&filter.sender)
+        .bind(&filter.recipient)
+        .bind(&filter.outcome)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_changes() {
+        let previous = json!({
+            "id": "a",
+            "name": "Block",
+            "priority": 100,
+            "enabled": true,
+            "updated_at": "2024-01-01T00:00:00Z",
+        });
+        let current = json!({
+            "id": "a",
+            "name": "Block",
+            "priority": 50,
+            "enabled": false,
+            "updated_at": "2024-01-02T00:00:00Z",
+        });
+        assert_eq!(
+            policy_changes(&previous, &current),
+            json!({
+                "priority": {"from": 100, "to": 50},
+                "enabled": {"from": true, "to": false},
+            })
+        );
+        assert_eq!(policy_changes(&current, &current), json!({}));
+        assert_eq!(
+            policy_changes(&Value::Null, &json!({"id": "a", "name": "Block"})),
+            json!({"name": {"from": null, "to": "Block"}})
+        );
+    }
+}
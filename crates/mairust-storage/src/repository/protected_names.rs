@@ -0,0 +1,91 @@
+//! Protected display name repository
+//!
+//! Names a tenant protects from display-name impersonation.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::TenantId;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A display name protected from impersonation
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ProtectedName {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub display_name: String,
+    /// The person's own address, allowed to use the name
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Input for protecting a display name
+#[derive(Debug, Clone)]
+pub struct CreateProtectedName {
+    pub tenant_id: TenantId,
+    pub display_name: String,
+    pub email: Option<String>,
+}
+
+/// Protected display name repository
+pub struct ProtectedNameRepository {
+    pool: DatabasePool,
+}
+
+impl ProtectedNameRepository {
+    /// Create a new protected display name repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Protect a display name; `None` when it is already protected
+    pub async fn create(&self, input: CreateProtectedName) -> Result<Option<ProtectedName>> {
+        let email = input
+            .email
+            .map(|e| e.trim().to_lowercase())
+            .filter(|e| !e.is_empty());
+
+        let name = sqlx::query_as::<_, ProtectedName>(
+            r#"
+            INSERT INTO protected_display_names (id, tenant_id, display_name, email, created_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            ON CONFLICT DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(input.tenant_id)
+        .bind(input.display_name.trim())
+        .bind(&email)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(name)
+    }
+
+    /// List the protected names of a tenant
+    pub async fn list(&self, tenant_id: TenantId) -> Result<Vec<ProtectedName>> {
+        let names = sqlx::query_as::<_, ProtectedName>(
+            "SELECT * FROM protected_display_names WHERE tenant_id = $1 ORDER BY display_name",
+        )
+        .bind(tenant_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(names)
+    }
+
+    /// Stop protecting a name; false when the tenant has no such entry
+    pub async fn delete(&self, tenant_id: TenantId, id: Uuid) -> Result<bool> {
+        let result =
+            sqlx::query("DELETE FROM protected_display_names WHERE tenant_id = $1 AND id = $2")
+                .bind(tenant_id)
+                .bind(id)
+                .execute(self.pool.pool())
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
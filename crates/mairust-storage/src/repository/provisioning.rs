@@ -0,0 +1,228 @@
+//! Mailbox provisioning repository
+//!
+//! Batches of users with mailboxes created by a background job, with the
+//! result of every row. Batches are inserted by the submitting call
+//! together with their job; this repository follows them.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{MailboxId, TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A bulk provisioning batch
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ProvisioningBatch {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub idempotency_key: Option<String>,
+    /// `generate` or `invite`: what users without a password get
+    pub password_mode: String,
+    /// `pending`, `running` or `completed`
+    pub status: String,
+    pub total_rows: i32,
+    pub created_count: i32,
+    /// Rows whose mailbox already existed
+    pub existing_count: i32,
+    pub failed_count: i32,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A row of a batch with its result
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ProvisioningRow {
+    pub batch_id: Uuid,
+    /// Position in the submitted batch, from 1
+    pub row_number: i32,
+    pub email: String,
+    pub name: Option<String>,
+    pub role: String,
+    pub quota_bytes: Option<i64>,
+    pub invite_email: Option<String>,
+    #[serde(skip)]
+    pub password: Option<String>,
+    /// `pending`, `created`, `exists` or `failed`
+    pub status: String,
+    pub user_id: Option<UserId>,
+    pub mailbox_id: Option<MailboxId>,
+    pub error: Option<String>,
+    pub processed_at: Option<DateTime<Utc>>,
+}
+
+/// Result of provisioning a row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvisioningOutcome {
+    /// The user or the mailbox was created
+    Created,
+    /// The mailbox already existed in the tenant and was left as it is
+    Exists,
+    /// The row could not be provisioned
+    Failed,
+}
+
+impl ProvisioningOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProvisioningOutcome::Created => "created",
+            ProvisioningOutcome::Exists => "exists",
+            ProvisioningOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// Provisioning repository
+pub struct ProvisioningRepository {
+    pool: DatabasePool,
+}
+
+impl ProvisioningRepository {
+    /// Create a new provisioning repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Batches of a tenant, newest first
+    pub async fn list(
+        &self,
+        tenant_id: TenantId,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ProvisioningBatch>> {
+        let batches = sqlx::query_as::<_, ProvisioningBatch>(
+            "SELECT * FROM provisioning_batches WHERE tenant_id = $1
+             ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+        )
+        .bind(tenant_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(batches)
+    }
+
+    /// A batch of a tenant
+    pub async fn get(&self, tenant_id: TenantId, id: Uuid) -> Result<Option<ProvisioningBatch>> {
+        let batch = sqlx::query_as::<_, ProvisioningBatch>(
+            "SELECT * FROM provisioning_batches WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(batch)
+    }
+
+    /// The batch a tenant submitted with an idempotency key
+    pub async fn find_by_key(
+        &self,
+        tenant_id: TenantId,
+        idempotency_key: &str,
+    ) -> Result<Option<ProvisioningBatch>> {
+        let batch = sqlx::query_as::<_, ProvisioningBatch>(
+            "SELECT * FROM provisioning_batches WHERE tenant_id = $1 AND idempotency_key = $2",
+        )
+        .bind(tenant_id)
+        .bind(idempotency_key)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(batch)
+    }
+
+    /// Rows of a batch in submitted order, optionally only those with a
+    /// status
+    pub async fn rows(
+        &self,
+        batch_id: Uuid,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ProvisioningRow>> {
+        let rows = sqlx::query_as::<_, ProvisioningRow>(
+            "SELECT * FROM provisioning_rows
+             WHERE batch_id = $1 AND ($2::TEXT IS NULL OR status = $2)
+             ORDER BY row_number LIMIT $3 OFFSET $4",
+        )
+        .bind(batch_id)
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Mark a batch as running; its counts are kept when it resumes
+    pub async fn start(&self, id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE provisioning_batches
+             SET status = 'running', started_at = COALESCE(started_at, NOW())
+             WHERE id = $1",
+        )
+        .bind(id)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record the result of a row and forget its password
+    pub async fn finish_row(
+        &self,
+        batch_id: Uuid,
+        row_number: i32,
+        outcome: ProvisioningOutcome,
+        user_id: Option<UserId>,
+        mailbox_id: Option<MailboxId>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE provisioning_rows
+             SET status = $3, user_id = $4, mailbox_id = $5, error = $6, password = NULL,
+                 processed_at = NOW()
+             WHERE batch_id = $1 AND row_number = $2",
+        )
+        .bind(batch_id)
+        .bind(row_number)
+        .bind(outcome.as_str())
+        .bind(user_id)
+        .bind(mailbox_id)
+        .bind(error)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Count the results of the rows and mark the batch completed
+    pub async fn complete(&self, id: Uuid) -> Result<ProvisioningBatch> {
+        let batch = sqlx::query_as::<_, ProvisioningBatch>(
+            r#"
+            UPDATE provisioning_batches b
+            SET status = 'completed', completed_at = NOW(),
+                created_count = counts.created, existing_count = counts.existing,
+                failed_count = counts.failed
+            FROM (
+                SELECT COUNT(*) FILTER (WHERE status = 'created')::INTEGER AS created,
+                       COUNT(*) FILTER (WHERE status = 'exists')::INTEGER AS existing,
+                       COUNT(*) FILTER (WHERE status = 'failed')::INTEGER AS failed
+                FROM provisioning_rows WHERE batch_id = $1
+            ) counts
+            WHERE b.id = $1
+            RETURNING b.*
+            "#,
+        )
+        .bind(id)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(batch)
+    }
+}
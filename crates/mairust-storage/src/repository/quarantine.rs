@@ -0,0 +1,330 @@
+//! Quarantine repository
+//!
+//! Quarantined messages stay in the `messages` table, flagged and moved to
+//! a hidden folder of the mailbox they were addressed to. The folder is a
+//! mailbox row linked to that mailbox by `quarantine_of`; it has no folder
+//! path, so clients never see it. Releasing a message moves it back into
+//! the mailbox.
+
+use crate::db::DatabasePool;
+use crate::models::Mailbox;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Special use of quarantine folders
+pub const QUARANTINE_SPECIAL_USE: &str = "quarantine";
+
+/// Address of the quarantine folder of a mailbox
+///
+/// `*` cannot appear in folder names clients create, so the address never
+/// collides with one of the owner's folders.
+pub fn quarantine_folder_address(mailbox_id: Uuid) -> String {
+    format!("*quarantine/{}", mailbox_id)
+}
+
+/// A message held in quarantine
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct QuarantinedMessage {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    /// Mailbox the message was addressed to, where releasing puts it
+    pub mailbox_id: Uuid,
+    /// Address of that mailbox
+    pub address: String,
+    /// Owner of that mailbox
+    pub user_id: Option<UserId>,
+    pub subject: Option<String>,
+    pub from_address: Option<String>,
+    pub snippet: Option<String>,
+    pub body_size: i64,
+    pub has_attachments: bool,
+    pub spam_score: Option<f64>,
+    /// `virus`, `spam` or `policy`
+    pub reason: Option<String>,
+    /// Virus name, spam score or policy name
+    pub detail: Option<String>,
+    pub received_at: DateTime<Utc>,
+    /// Storage path of the raw message
+    #[serde(skip)]
+    pub storage_path: String,
+}
+
+/// A user whose quarantine digest is due
+#[derive(Debug, Clone, FromRow)]
+pub struct QuarantineDigestRecipient {
+    pub tenant_id: TenantId,
+    pub user_id: UserId,
+    pub email: String,
+    /// When the last digest went out, `None` before the first
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+/// Columns of [`QuarantinedMessage`]; `m` is the message, `o` the mailbox
+/// it was addressed to
+const QUARANTINED_COLUMNS: &str = r#"
+    m.id, m.tenant_id, o.id AS mailbox_id, o.address, o.user_id, m.subject,
+    m.from_address, m.snippet, m.body_size, m.has_attachments, m.spam_score,
+    m.metadata->'quarantine'->>'reason' AS reason,
+    m.metadata->'quarantine'->>'detail' AS detail,
+    m.received_at, m.storage_path
+"#;
+
+/// Quarantine repository
+pub struct QuarantineRepository {
+    pool: DatabasePool,
+}
+
+impl QuarantineRepository {
+    /// Create a new quarantine repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Quarantine folder of a mailbox, created on first use
+    pub async fn folder(&self, mailbox: &Mailbox) -> Result<Uuid> {
+        let pool = self.pool.pool();
+        let existing: Option<Uuid> =
+            sqlx::query_scalar("SELECT id FROM mailboxes WHERE quarantine_of = $1")
+                .bind(mailbox.id)
+                .fetch_optional(pool)
+                .await?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+
+        // Concurrent deliveries may both create it; the first one wins
+        sqlx::query(
+            r#"
+            INSERT INTO mailboxes (id, tenant_id, domain_id, user_id, address, special_use,
+                                   quarantine_of, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())
+            ON CONFLICT (quarantine_of) WHERE quarantine_of IS NOT NULL DO NOTHING
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(mailbox.tenant_id)
+        .bind(mailbox.domain_id)
+        .bind(mailbox.user_id)
+        .bind(quarantine_folder_address(mailbox.id))
+        .bind(QUARANTINE_SPECIAL_USE)
+        .bind(mailbox.id)
+        .execute(pool)
+        .await?;
+
+        let id = sqlx::query_scalar("SELECT id FROM mailboxes WHERE quarantine_of = $1")
+            .bind(mailbox.id)
+            .fetch_one(pool)
+            .await?;
+        Ok(id)
+    }
+
+    /// Flag a message stored in a quarantine folder
+    pub async fn mark(&self, tenant_id: TenantId, message_id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE messages SET quarantined = TRUE WHERE tenant_id = $1 AND id = $2")
+            .bind(tenant_id)
+            .bind(message_id)
+            .execute(self.pool.pool())
+            .await?;
+        Ok(())
+    }
+
+    /// Move a stored message into a quarantine folder and flag it, recording
+    /// why in `metadata.quarantine`; returns whether the message was moved
+    pub async fn hold(
+        &self,
+        tenant_id: TenantId,
+        message_id: Uuid,
+        folder_id: Uuid,
+        metadata: serde_json::Value,
+    ) -> Result<bool> {
+        // Moving assigns a new UID in the folder (see assign_message_uid)
+        let result = sqlx::query(
+            r#"
+            UPDATE messages
+            SET mailbox_id = $3,
+                quarantined = TRUE,
+                metadata = jsonb_set(metadata, '{quarantine}', $4)
+            WHERE tenant_id = $1 AND id = $2 AND NOT quarantined
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .bind(folder_id)
+        .bind(metadata)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Quarantined messages of a tenant, newest first, optionally only
+    /// those addressed to a user's mailboxes
+    pub async fn list(
+        &self,
+        tenant_id: TenantId,
+        user_id: Option<UserId>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<QuarantinedMessage>> {
+        let messages = sqlx::query_as::<_, QuarantinedMessage>(&format!(
+            r#"
+            SELECT {}
+            FROM messages m
+            JOIN mailboxes q ON q.id = m.mailbox_id
+            JOIN mailboxes o ON o.id = q.quarantine_of
+            WHERE m.tenant_id = $1 AND m.quarantined
+              AND ($2::uuid IS NULL OR o.user_id = $2)
+            ORDER BY m.received_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+            QUARANTINED_COLUMNS
+        ))
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(messages)
+    }
+
+    /// A quarantined message of a tenant
+    pub async fn get(
+        &self,
+        tenant_id: TenantId,
+        message_id: Uuid,
+    ) -> Result<Option<QuarantinedMessage>> {
+        let message = sqlx::query_as::<_, QuarantinedMessage>(&format!(
+            r#"
+            SELECT {}
+            FROM messages m
+            JOIN mailboxes q ON q.id = m.mailbox_id
+            JOIN mailboxes o ON o.id = q.quarantine_of
+            WHERE m.tenant_id = $1 AND m.id = $2 AND m.quarantined
+            "#,
+            QUARANTINED_COLUMNS
+        ))
+        .bind(tenant_id)
+        .bind(message_id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(message)
+    }
+
+    /// Move a quarantined message back into the mailbox it was addressed
+    /// to; returns that mailbox, `None` if the message is not quarantined
+    pub async fn release(&self, tenant_id: TenantId, message_id: Uuid) -> Result<Option<Uuid>> {
+        // Moving assigns a new UID in the mailbox (see assign_message_uid)
+        let mailbox_id = sqlx::query_scalar(
+            r#"
+            UPDATE messages m
+            SET mailbox_id = q.quarantine_of,
+                quarantined = FALSE,
+                metadata = jsonb_set(
+                    m.metadata,
+                    '{quarantine}',
+                    COALESCE(m.metadata->'quarantine', '{}'::jsonb)
+                        || jsonb_build_object('released_at', NOW())
+                )
+            FROM mailboxes q
+            WHERE q.id = m.mailbox_id AND q.quarantine_of IS NOT NULL
+              AND m.tenant_id = $1 AND m.id = $2 AND m.quarantined
+            RETURNING m.mailbox_id
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(mailbox_id)
+    }
+
+    /// Delete a quarantined message; returns its storage path, `None` if
+    /// the message is not quarantined
+    pub async fn delete(&self, tenant_id: TenantId, message_id: Uuid) -> Result<Option<String>> {
+        let storage_path = sqlx::query_scalar(
+            "DELETE FROM messages WHERE tenant_id = $1 AND id = $2 AND quarantined
+             RETURNING storage_path",
+        )
+        .bind(tenant_id)
+        .bind(message_id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(storage_path)
+    }
+
+    /// Users with the digest on whose last digest went out before `before`
+    pub async fn due_digests(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<QuarantineDigestRecipient>> {
+        let recipients = sqlx::query_as::<_, QuarantineDigestRecipient>(
+            r#"
+            SELECT s.tenant_id, s.user_id, u.email, s.quarantine_digest_sent_at AS sent_at
+            FROM user_settings s
+            JOIN users u ON u.id = s.user_id
+            WHERE s.quarantine_digest AND u.active
+              AND (s.quarantine_digest_sent_at IS NULL OR s.quarantine_digest_sent_at < $1)
+            "#,
+        )
+        .bind(before)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(recipients)
+    }
+
+    /// Messages quarantined for a user's mailboxes since `since`, oldest
+    /// first
+    pub async fn list_since(
+        &self,
+        tenant_id: TenantId,
+        user_id: UserId,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<QuarantinedMessage>> {
+        let messages = sqlx::query_as::<_, QuarantinedMessage>(&format!(
+            r#"
+            SELECT {}
+            FROM messages m
+            JOIN mailboxes q ON q.id = m.mailbox_id
+            JOIN mailboxes o ON o.id = q.quarantine_of
+            WHERE m.tenant_id = $1 AND m.quarantined AND o.user_id = $2
+              AND m.received_at >= $3
+            ORDER BY m.received_at
+            "#,
+            QUARANTINED_COLUMNS
+        ))
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(since)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(messages)
+    }
+
+    /// Record that a user's digest went out at `sent_at`
+    pub async fn digest_sent(&self, user_id: UserId, sent_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE user_settings SET quarantine_digest_sent_at = $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(sent_at)
+            .execute(self.pool.pool())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine_folder_address() {
+        let mailbox_id = Uuid::now_v7();
+        let address = quarantine_folder_address(mailbox_id);
+        assert!(address.starts_with('*'));
+        assert!(!address.contains('@'));
+        assert!(address.ends_with(&mailbox_id.to_string()));
+    }
+}
@@ -0,0 +1,407 @@
+//! Received report repository
+//!
+//! DMARC aggregate and TLS-RPT reports that other mail systems sent about
+//! a tenant's domains, parsed into rows that can be summed per source.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{DomainId, TenantId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// DMARC aggregate report about a domain
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ReceivedDmarcReport {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub domain_id: DomainId,
+    /// Organization that sent the report
+    pub org_name: String,
+    pub email: Option<String>,
+    /// Reporter's identifier of the report
+    pub report_id: String,
+    pub begin_at: DateTime<Utc>,
+    pub end_at: DateTime<Utc>,
+    /// Policy the reporter found published
+    pub policy: serde_json::Value,
+    pub message_count: i64,
+    /// Messages that passed neither aligned DKIM nor aligned SPF
+    pub fail_count: i64,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Row of a DMARC aggregate report
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ReceivedDmarcRecord {
+    #[serde(skip)]
+    pub id: Uuid,
+    #[serde(skip)]
+    pub report_id: Uuid,
+    pub source_ip: String,
+    pub message_count: i64,
+    pub disposition: String,
+    /// Aligned DKIM result
+    pub dkim: String,
+    /// Aligned SPF result
+    pub spf: String,
+    pub reasons: serde_json::Value,
+    pub header_from: String,
+    pub envelope_from: Option<String>,
+    pub dkim_results: serde_json::Value,
+    pub spf_results: serde_json::Value,
+}
+
+/// Input for a row of a DMARC aggregate report
+#[derive(Debug, Clone)]
+pub struct NewDmarcRecord {
+    pub source_ip: String,
+    pub message_count: i64,
+    pub disposition: String,
+    pub dkim: String,
+    pub spf: String,
+    pub reasons: serde_json::Value,
+    pub header_from: String,
+    pub envelope_from: Option<String>,
+    pub dkim_results: serde_json::Value,
+    pub spf_results: serde_json::Value,
+}
+
+impl NewDmarcRecord {
+    /// Whether the messages passed DMARC: aligned DKIM or aligned SPF
+    pub fn passed(&self) -> bool {
+        self.dkim == "pass" || self.spf == "pass"
+    }
+}
+
+/// Input for storing a DMARC aggregate report
+#[derive(Debug, Clone)]
+pub struct NewDmarcReport {
+    pub tenant_id: TenantId,
+    pub domain_id: DomainId,
+    pub org_name: String,
+    pub email: Option<String>,
+    pub report_id: String,
+    pub begin_at: DateTime<Utc>,
+    pub end_at: DateTime<Utc>,
+    pub policy: serde_json::Value,
+    pub records: Vec<NewDmarcRecord>,
+}
+
+/// Mail from one source address across DMARC reports
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DmarcSourceSummary {
+    pub source_ip: String,
+    pub message_count: i64,
+    /// Messages that passed aligned DKIM or aligned SPF
+    pub pass_count: i64,
+    pub fail_count: i64,
+    /// Dispositions receivers applied to failing messages, e.g. `reject`
+    pub dispositions: Vec<String>,
+    /// Organizations that reported the source
+    pub reporters: Vec<String>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// A policy of a TLS-RPT report about a domain
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ReceivedTlsReport {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub domain_id: DomainId,
+    pub org_name: String,
+    pub contact_info: Option<String>,
+    pub report_id: String,
+    pub begin_at: DateTime<Utc>,
+    pub end_at: DateTime<Utc>,
+    /// `sts`, `tlsa` or `no-policy-found`
+    pub policy_type: String,
+    pub policy_domain: String,
+    pub policy_strings: serde_json::Value,
+    pub mx_hosts: serde_json::Value,
+    pub successful_sessions: i64,
+    pub failed_sessions: i64,
+    pub failure_details: serde_json::Value,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Input for storing a policy of a TLS-RPT report
+#[derive(Debug, Clone)]
+pub struct NewTlsReport {
+    pub tenant_id: TenantId,
+    pub domain_id: DomainId,
+    pub org_name: String,
+    pub contact_info: Option<String>,
+    pub report_id: String,
+    pub begin_at: DateTime<Utc>,
+    pub end_at: DateTime<Utc>,
+    pub policy_type: String,
+    pub policy_domain: String,
+    pub policy_strings: serde_json::Value,
+    pub mx_hosts: serde_json::Value,
+    pub successful_sessions: i64,
+    pub failed_sessions: i64,
+    pub failure_details: serde_json::Value,
+}
+
+/// Received report repository
+pub struct ReceivedReportRepository {
+    pool: DatabasePool,
+}
+
+impl ReceivedReportRepository {
+    /// Create a new received report repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Store a DMARC report with its rows; `None` when the reporter already
+    /// sent it
+    pub async fn insert_dmarc_report(
+        &self,
+        report: &NewDmarcReport,
+    ) -> Result<Option<ReceivedDmarcReport>> {
+        let message_count: i64 = report.records.iter().map(|r| r.message_count).sum();
+        let fail_count: i64 = report
+            .records
+            .iter()
+            .filter(|r| !r.passed())
+            .map(|r| r.message_count)
+            .sum();
+
+        let mut tx = self.pool.pool().begin().await?;
+        let stored = sqlx::query_as::<_, ReceivedDmarcReport>(
+            r#"
+            INSERT INTO received_dmarc_reports
+                (id, tenant_id, domain_id, org_name, email, report_id, begin_at, end_at,
+                 policy, message_count, fail_count, received_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NOW())
+            ON CONFLICT (domain_id, org_name, report_id) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(report.tenant_id)
+        .bind(report.domain_id)
+        .bind(&report.org_name)
+        .bind(&report.email)
+        .bind(&report.report_id)
+        .bind(report.begin_at)
+        .bind(report.end_at)
+        .bind(&report.policy)
+        .bind(message_count)
+        .bind(fail_count)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(stored) = stored else {
+            return Ok(None);
+        };
+
+        for record in &report.records {
+            sqlx::query(
+                r#"
+                INSERT INTO received_dmarc_report_records
+                    (id, report_id, source_ip, message_count, disposition, dkim, spf,
+                     reasons, header_from, envelope_from, dkim_results, spf_results)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                "#,
+            )
+            .bind(Uuid::now_v7())
+            .bind(stored.id)
+            .bind(&record.source_ip)
+            .bind(record.message_count)
+            .bind(&record.disposition)
+            .bind(&record.dkim)
+            .bind(&record.spf)
+            .bind(&record.reasons)
+            .bind(&record.header_from)
+            .bind(&record.envelope_from)
+            .bind(&record.dkim_results)
+            .bind(&record.spf_results)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(Some(stored))
+    }
+
+    /// DMARC reports of a domain whose period ended after `since`, newest
+    /// first
+    pub async fn list_dmarc_reports(
+        &self,
+        domain_id: DomainId,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ReceivedDmarcReport>> {
+        let reports = sqlx::query_as::<_, ReceivedDmarcReport>(
+            r#"
+            SELECT * FROM received_dmarc_reports
+            WHERE domain_id = $1 AND end_at > $2
+            ORDER BY end_at DESC, org_name
+            "#,
+        )
+        .bind(domain_id)
+        .bind(since)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(reports)
+    }
+
+    /// A DMARC report of a domain, with its rows
+    pub async fn get_dmarc_report(
+        &self,
+        domain_id: DomainId,
+        id: Uuid,
+    ) -> Result<Option<(ReceivedDmarcReport, Vec<ReceivedDmarcRecord>)>> {
+        let report = sqlx::query_as::<_, ReceivedDmarcReport>(
+            "SELECT * FROM received_dmarc_reports WHERE domain_id = $1 AND id = $2",
+        )
+        .bind(domain_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        let Some(report) = report else {
+            return Ok(None);
+        };
+
+        let records = sqlx::query_as::<_, ReceivedDmarcRecord>(
+            r#"
+            SELECT * FROM received_dmarc_report_records
+            WHERE report_id = $1
+            ORDER BY message_count DESC, source_ip
+            "#,
+        )
+        .bind(report.id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(Some((report, records)))
+    }
+
+    /// Mail from each source address in the DMARC reports of a domain
+    /// whose period ended after `since`, most failing first
+    pub async fn dmarc_sources(
+        &self,
+        domain_id: DomainId,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DmarcSourceSummary>> {
+        let sources = sqlx::query_as::<_, DmarcSourceSummary>(
+            r#"
+            SELECT rec.source_ip,
+                   SUM(rec.message_count)::BIGINT AS message_count,
+                   COALESCE(SUM(rec.message_count)
+                       FILTER (WHERE rec.dkim = 'pass' OR rec.spf = 'pass'), 0)::BIGINT
+                       AS pass_count,
+                   COALESCE(SUM(rec.message_count)
+                       FILTER (WHERE rec.dkim <> 'pass' AND rec.spf <> 'pass'), 0)::BIGINT
+                       AS fail_count,
+                   COALESCE(ARRAY_AGG(DISTINCT rec.disposition)
+                       FILTER (WHERE rec.dkim <> 'pass' AND rec.spf <> 'pass'), '{}')
+                       AS dispositions,
+                   ARRAY_AGG(DISTINCT r.org_name) AS reporters,
+                   MAX(r.end_at) AS last_seen_at
+            FROM received_dmarc_report_records rec
+            JOIN received_dmarc_reports r ON r.id = rec.report_id
+            WHERE r.domain_id = $1 AND r.end_at > $2
+            GROUP BY rec.source_ip
+            ORDER BY fail_count DESC, message_count DESC, rec.source_ip
+            "#,
+        )
+        .bind(domain_id)
+        .bind(since)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(sources)
+    }
+
+    /// Store a policy of a TLS-RPT report; `None` when the reporter already
+    /// sent it
+    pub async fn insert_tls_report(
+        &self,
+        report: &NewTlsReport,
+    ) -> Result<Option<ReceivedTlsReport>> {
+        let stored = sqlx::query_as::<_, ReceivedTlsReport>(
+            r#"
+            INSERT INTO received_tls_reports
+                (id, tenant_id, domain_id, org_name, contact_info, report_id, begin_at,
+                 end_at, policy_type, policy_domain, policy_strings, mx_hosts,
+                 successful_sessions, failed_sessions, failure_details, received_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, NOW())
+            ON CONFLICT (domain_id, org_name, report_id, policy_type, policy_domain)
+                DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(report.tenant_id)
+        .bind(report.domain_id)
+        .bind(&report.org_name)
+        .bind(&report.contact_info)
+        .bind(&report.report_id)
+        .bind(report.begin_at)
+        .bind(report.end_at)
+        .bind(&report.policy_type)
+        .bind(&report.policy_domain)
+        .bind(&report.policy_strings)
+        .bind(&report.mx_hosts)
+        .bind(report.successful_sessions)
+        .bind(report.failed_sessions)
+        .bind(&report.failure_details)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(stored)
+    }
+
+    /// TLS-RPT report policies of a domain whose period ended after
+    /// `since`, newest first
+    pub async fn list_tls_reports(
+        &self,
+        domain_id: DomainId,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ReceivedTlsReport>> {
+        let reports = sqlx::query_as::<_, ReceivedTlsReport>(
+            r#"
+            SELECT * FROM received_tls_reports
+            WHERE domain_id = $1 AND end_at > $2
+            ORDER BY end_at DESC, org_name, policy_domain
+            "#,
+        )
+        .bind(domain_id)
+        .bind(since)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(dkim: &str, spf: &str) -> NewDmarcRecord {
+        NewDmarcRecord {
+            source_ip: "192.0.2.1".to_string(),
+            message_count: 3,
+            disposition: "none".to_string(),
+            dkim: dkim.to_string(),
+            spf: spf.to_string(),
+            reasons: serde_json::json!([]),
+            header_from: "example.com".to_string(),
+            envelope_from: None,
+            dkim_results: serde_json::json!([]),
+            spf_results: serde_json::json!([]),
+        }
+    }
+
+    #[test]
+    fn test_record_passed() {
+        assert!(record("pass", "fail").passed());
+        assert!(record("fail", "pass").passed());
+        assert!(!record("fail", "fail").passed());
+    }
+}
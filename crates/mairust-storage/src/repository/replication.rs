@@ -0,0 +1,382 @@
+//! Replication repository
+//!
+//! Publication and subscription of the tables a warm standby follows, the
+//! primary's heartbeat, and the standby's queue of message files to copy
+//! from the primary. Logical replication is managed with SQL, so the
+//! database user needs the rights to create publications (primary) and
+//! subscriptions (standby).
+
+use crate::db::DatabasePool;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Tables that are never published: each server has its own migrations,
+/// and the file queue is the standby's own
+const LOCAL_TABLES: &[&str] = &["_sqlx_migrations", "replication_file_queue"];
+
+/// A message file the standby has to copy or delete
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct QueuedFile {
+    pub storage_path: String,
+    /// `copy` or `delete`
+    pub action: String,
+    pub queued_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+impl QueuedFile {
+    /// Whether the file is to be deleted rather than copied
+    pub fn is_delete(&self) -> bool {
+        self.action == "delete"
+    }
+}
+
+/// State of the standby's file queue
+#[derive(Debug, Clone, Default, Serialize, FromRow)]
+pub struct FileQueueStats {
+    /// Files waiting to be copied or deleted
+    pub pending: i64,
+    /// Files whose last attempt failed
+    pub failing: i64,
+    /// When the longest waiting file was queued
+    pub oldest_queued_at: Option<DateTime<Utc>>,
+}
+
+/// Replay lag of a subscriber, as seen by the primary
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SubscriberLag {
+    /// Subscription name
+    pub name: String,
+    pub replay_lag_secs: Option<f64>,
+}
+
+/// Replication repository
+pub struct ReplicationRepository {
+    pool: DatabasePool,
+}
+
+impl ReplicationRepository {
+    /// Create a new replication repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Record a heartbeat of the primary
+    pub async fn beat(&self) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO replication_heartbeat (id, beat_at) VALUES (1, NOW())
+            ON CONFLICT (id) DO UPDATE SET beat_at = NOW()
+            "#,
+        )
+        .execute(self.pool.pool())
+        .await?;
+        Ok(())
+    }
+
+    /// Last heartbeat of the primary that reached this database
+    pub async fn heartbeat(&self) -> Result<Option<DateTime<Utc>>> {
+        let beat_at = sqlx::query_scalar("SELECT beat_at FROM replication_heartbeat WHERE id = 1")
+            .fetch_optional(self.pool.pool())
+            .await?;
+        Ok(beat_at)
+    }
+
+    /// Whether this database has the subscription
+    pub async fn has_subscription(&self, subscription: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM pg_subscription s
+                JOIN pg_database d ON d.oid = s.subdbid
+                WHERE s.subname = $1 AND d.datname = current_database()
+            )
+            "#,
+        )
+        .bind(subscription)
+        .fetch_one(self.pool.pool())
+        .await?;
+        Ok(exists)
+    }
+
+    /// Create the publication if needed and add the tables it lacks;
+    /// returns the tables added
+    ///
+    /// Tables without a primary key are published with their full rows as
+    /// replica identity, so that their updates and deletes replicate.
+    pub async fn publish(&self, publication: &str) -> Result<Vec<String>> {
+        check_identifier(publication)?;
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_publication WHERE pubname = $1)")
+                .bind(publication)
+                .fetch_one(self.pool.pool())
+                .await?;
+        if !exists {
+            sqlx::query(&format!("CREATE PUBLICATION {}", publication))
+                .execute(self.pool.pool())
+                .await?;
+        }
+
+        let missing: Vec<(String, bool)> = sqlx::query_as(
+            r#"
+            SELECT c.relname,
+                   EXISTS (SELECT 1 FROM pg_index i WHERE i.indrelid = c.oid AND i.indisprimary)
+            FROM pg_class c
+            WHERE c.relkind = 'r'
+              AND c.relnamespace = current_schema()::regnamespace
+              AND c.relname::text <> ALL($2)
+              AND NOT EXISTS (
+                  SELECT 1 FROM pg_publication_tables p
+                  WHERE p.pubname = $1 AND p.schemaname = current_schema()
+                    AND p.tablename = c.relname
+              )
+            ORDER BY c.relname
+            "#,
+        )
+        .bind(publication)
+        .bind(LOCAL_TABLES)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        let mut added = Vec::new();
+        for (table, has_primary_key) in missing {
+            let quoted = quote_identifier(&table);
+            if !has_primary_key {
+                sqlx::query(&format!("ALTER TABLE {} REPLICA IDENTITY FULL", quoted))
+                    .execute(self.pool.pool())
+                    .await?;
+            }
+            sqlx::query(&format!(
+                "ALTER PUBLICATION {} ADD TABLE {}",
+                publication, quoted
+            ))
+            .execute(self.pool.pool())
+            .await?;
+            added.push(table);
+        }
+        Ok(added)
+    }
+
+    /// Subscribe to the primary's publication, or pick up the tables
+    /// added to it since; returns whether the subscription was created
+    ///
+    /// A new subscription copies the primary's rows first.
+    pub async fn subscribe(
+        &self,
+        subscription: &str,
+        conninfo: &str,
+        publication: &str,
+    ) -> Result<bool> {
+        check_identifier(subscription)?;
+        check_identifier(publication)?;
+        if self.has_subscription(subscription).await? {
+            sqlx::query(&format!(
+                "ALTER SUBSCRIPTION {} REFRESH PUBLICATION",
+                subscription
+            ))
+            .execute(self.pool.pool())
+            .await?;
+            return Ok(false);
+        }
+        sqlx::query(&format!(
+            "CREATE SUBSCRIPTION {} CONNECTION {} PUBLICATION {}",
+            subscription,
+            quote_literal(conninfo),
+            publication
+        ))
+        .execute(self.pool.pool())
+        .await?;
+        Ok(true)
+    }
+
+    /// Stop following the primary and drop the subscription
+    ///
+    /// The replication slot is detached first, so that this works while
+    /// the primary is down; the slot is left on the primary, to be dropped
+    /// there if it comes back.
+    pub async fn unsubscribe(&self, subscription: &str) -> Result<()> {
+        check_identifier(subscription)?;
+        for statement in [
+            format!("ALTER SUBSCRIPTION {} DISABLE", subscription),
+            format!("ALTER SUBSCRIPTION {} SET (slot_name = NONE)", subscription),
+            format!("DROP SUBSCRIPTION {}", subscription),
+        ] {
+            sqlx::query(&statement).execute(self.pool.pool()).await?;
+        }
+        Ok(())
+    }
+
+    /// Replay lag of the subscribers connected to this database
+    pub async fn subscriber_lags(&self) -> Result<Vec<SubscriberLag>> {
+        let lags = sqlx::query_as::<_, SubscriberLag>(
+            r#"
+            SELECT application_name AS name,
+                   EXTRACT(EPOCH FROM replay_lag)::DOUBLE PRECISION AS replay_lag_secs
+            FROM pg_stat_replication
+            ORDER BY application_name
+            "#,
+        )
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(lags)
+    }
+
+    /// Messages after `after` in id order, with their file paths
+    pub async fn message_files(&self, after: Uuid, limit: i64) -> Result<Vec<(Uuid, String)>> {
+        let rows = sqlx::query_as(
+            "SELECT id, storage_path FROM messages WHERE id > $1 ORDER BY id LIMIT $2",
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(rows)
+    }
+
+    /// Whether a message is stored in the file
+    pub async fn is_message_file(&self, storage_path: &str) -> Result<bool> {
+        let referenced =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM messages WHERE storage_path = $1)")
+                .bind(storage_path)
+                .fetch_one(self.pool.pool())
+                .await?;
+        Ok(referenced)
+    }
+
+    /// Queue a file to be copied from the primary
+    pub async fn queue_copy(&self, storage_path: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO replication_file_queue (storage_path, action) VALUES ($1, 'copy')
+            ON CONFLICT (storage_path) DO NOTHING
+            "#,
+        )
+        .bind(storage_path)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(())
+    }
+
+    /// Files due for an attempt, longest waiting first
+    pub async fn due_files(&self, limit: i64) -> Result<Vec<QueuedFile>> {
+        let files = sqlx::query_as::<_, QueuedFile>(
+            r#"
+            SELECT * FROM replication_file_queue
+            WHERE next_attempt_at <= NOW()
+            ORDER BY queued_at
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(files)
+    }
+
+    /// Remove a handled file from the queue, unless it was queued again
+    /// since it was read
+    pub async fn file_done(&self, file: &QueuedFile) -> Result<()> {
+        sqlx::query(
+            "DELETE FROM replication_file_queue WHERE storage_path = $1 AND queued_at = $2",
+        )
+        .bind(&file.storage_path)
+        .bind(file.queued_at)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(())
+    }
+
+    /// Record a failed attempt and when to try again
+    pub async fn file_failed(
+        &self,
+        file: &QueuedFile,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE replication_file_queue
+            SET attempts = attempts + 1, last_error = $3, next_attempt_at = $4
+            WHERE storage_path = $1 AND queued_at = $2
+            "#,
+        )
+        .bind(&file.storage_path)
+        .bind(file.queued_at)
+        .bind(error)
+        .bind(retry_at)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(())
+    }
+
+    /// State of the file queue
+    pub async fn file_queue_stats(&self) -> Result<FileQueueStats> {
+        let stats = sqlx::query_as::<_, FileQueueStats>(
+            r#"
+            SELECT COUNT(*) AS pending,
+                   COUNT(*) FILTER (WHERE attempts > 0) AS failing,
+                   MIN(queued_at) AS oldest_queued_at
+            FROM replication_file_queue
+            "#,
+        )
+        .fetch_one(self.pool.pool())
+        .await?;
+        Ok(stats)
+    }
+}
+
+/// Refuse names that would need quoting; configuration only allows
+/// lowercase identifiers
+fn check_identifier(name: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        bail!("'{}' is not a valid replication name", name);
+    }
+    Ok(())
+}
+
+/// SQL identifier of a table name
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// SQL string literal
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_identifier() {
+        assert!(check_identifier("mairust_standby").is_ok());
+        assert!(check_identifier("_dr2").is_ok());
+        assert!(check_identifier("").is_err());
+        assert!(check_identifier("2dr").is_err());
+        assert!(check_identifier("Standby").is_err());
+        assert!(check_identifier("dr; DROP TABLE users").is_err());
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(quote_identifier("messages"), "\"messages\"");
+        assert_eq!(quote_identifier("a\"b"), "\"a\"\"b\"");
+        assert_eq!(
+            quote_literal("host=db password='x'"),
+            "'host=db password=''x'''"
+        );
+    }
+}
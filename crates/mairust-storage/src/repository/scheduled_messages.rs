@@ -289,10 +289,11 @@ impl ScheduledMessageRepository {
         .await
     }
 
-    /// Mark a message as bounced
+    /// Mark a message of a tenant as bounced
     pub async fn mark_bounced(
         &self,
         id: Uuid,
+        tenant_id: TenantId,
         bounce_type: &str,
         bounce_reason: &str,
     ) -> Result<Option<ScheduledMessage>, sqlx::Error> {
@@ -304,13 +305,14 @@ impl ScheduledMessageRepository {
                 bounce_type = $2,
                 bounce_reason = $3,
                 updated_at = NOW()
-            WHERE id = $1
+            WHERE id = $1 AND tenant_id = $4
             RETURNING *
             "#,
         )
         .bind(id)
         .bind(bounce_type)
         .bind(bounce_reason)
+        .bind(tenant_id)
         .fetch_optional(&self.pool)
         .await
     }
@@ -0,0 +1,356 @@
+//! Secure message repository
+//!
+//! Messages kept encrypted on the server and read on the web UI after
+//! entering a passcode, with a log of every access. The link's token is
+//! only returned when the message is created; the database keeps its
+//! SHA-256 hash.
+
+use crate::db::DatabasePool;
+use crate::repository::message_shares::hash_share_token;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Wrong passcodes after which a secure message no longer opens
+pub const MAX_FAILED_ATTEMPTS: i32 = 5;
+
+/// A secure message
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SecureMessage {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub sender: String,
+    pub recipient: String,
+    #[serde(skip)]
+    pub sealed_message: Vec<u8>,
+    #[serde(skip)]
+    pub passcode_hash: Option<String>,
+    #[serde(skip)]
+    pub code_hash: Option<String>,
+    pub code_sent_at: Option<DateTime<Utc>>,
+    /// Passcode attempts since the last open
+    pub failed_attempts: i32,
+    pub created_by: Option<UserId>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// Times the message was opened
+    pub open_count: i32,
+    pub last_opened_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SecureMessage {
+    /// Whether the recipient chooses a code emailed to them rather than a
+    /// passcode from the sender
+    pub fn uses_emailed_codes(&self) -> bool {
+        self.passcode_hash.is_none()
+    }
+
+    /// Whether the link still opens the message
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none()
+            && self.expires_at > now
+            && self.failed_attempts < MAX_FAILED_ATTEMPTS
+    }
+}
+
+/// Input for storing a secure message
+#[derive(Debug, Clone)]
+pub struct CreateSecureMessage {
+    /// Id the message was sealed for
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub sender: String,
+    pub recipient: String,
+    pub sealed_message: Vec<u8>,
+    pub passcode_hash: Option<String>,
+    pub created_by: Option<UserId>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Access to a secure message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureMessageEvent {
+    /// A one-time code was emailed to the recipient
+    CodeSent,
+    /// The message was opened
+    Opened,
+    /// A wrong passcode was entered
+    Failed,
+    /// Too many wrong passcodes locked the message
+    Locked,
+}
+
+impl SecureMessageEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecureMessageEvent::CodeSent => "code_sent",
+            SecureMessageEvent::Opened => "opened",
+            SecureMessageEvent::Failed => "failed",
+            SecureMessageEvent::Locked => "locked",
+        }
+    }
+}
+
+/// An entry of the access log
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SecureMessageAccess {
+    pub id: Uuid,
+    pub secure_message_id: Uuid,
+    pub event: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Secure message repository
+pub struct SecureMessageRepository {
+    pool: DatabasePool,
+}
+
+impl SecureMessageRepository {
+    /// Create a new secure message repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Store a secure message, returning it and the token of its link
+    pub async fn create(&self, input: &CreateSecureMessage) -> Result<(SecureMessage, String)> {
+        let token = generate_secure_token();
+        let message = sqlx::query_as::<_, SecureMessage>(
+            r#"
+            INSERT INTO secure_messages (id, tenant_id, token_hash, sender, recipient,
+                                         sealed_message, passcode_hash, created_by, expires_at,
+                                         created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(input.id)
+        .bind(input.tenant_id)
+        .bind(hash_share_token(&token))
+        .bind(&input.sender)
+        .bind(&input.recipient)
+        .bind(&input.sealed_message)
+        .bind(&input.passcode_hash)
+        .bind(input.created_by)
+        .bind(input.expires_at)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok((message, token))
+    }
+
+    /// Secure messages of a tenant, newest first
+    pub async fn list(
+        &self,
+        tenant_id: TenantId,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SecureMessage>> {
+        let messages = sqlx::query_as::<_, SecureMessage>(
+            "SELECT * FROM secure_messages WHERE tenant_id = $1
+             ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+        )
+        .bind(tenant_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// A secure message of a tenant
+    pub async fn get(&self, tenant_id: TenantId, id: Uuid) -> Result<Option<SecureMessage>> {
+        let message = sqlx::query_as::<_, SecureMessage>(
+            "SELECT * FROM secure_messages WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Revoke a secure message; revoking it again keeps the first
+    /// revocation time. `None` when the tenant has no such message
+    pub async fn revoke(&self, tenant_id: TenantId, id: Uuid) -> Result<Option<SecureMessage>> {
+        let message = sqlx::query_as::<_, SecureMessage>(
+            "UPDATE secure_messages SET revoked_at = COALESCE(revoked_at, NOW())
+             WHERE tenant_id = $1 AND id = $2
+             RETURNING *",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Secure message of a token that is neither revoked, expired nor
+    /// locked
+    pub async fn find_active(&self, token: &str) -> Result<Option<SecureMessage>> {
+        let message = sqlx::query_as::<_, SecureMessage>(
+            "SELECT * FROM secure_messages
+             WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+               AND failed_attempts < $2",
+        )
+        .bind(hash_share_token(token))
+        .bind(MAX_FAILED_ATTEMPTS)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Replace the one-time code, unless one was sent after `not_sent_since`;
+    /// false when a recent code is kept
+    pub async fn set_code(
+        &self,
+        id: Uuid,
+        code_hash: &str,
+        not_sent_since: DateTime<Utc>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE secure_messages SET code_hash = $2, code_sent_at = NOW()
+             WHERE id = $1 AND (code_sent_at IS NULL OR code_sent_at < $3)",
+        )
+        .bind(id)
+        .bind(code_hash)
+        .bind(not_sent_since)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Take one of the passcode attempts left before the message locks,
+    /// returning the attempts taken since the last open; `None` when the
+    /// message is locked, revoked or expired
+    ///
+    /// The attempt is taken before the passcode is checked, so concurrent
+    /// requests cannot check more passcodes than the limit allows.
+    pub async fn take_attempt(&self, id: Uuid) -> Result<Option<i32>> {
+        let failed_attempts: Option<(i32,)> = sqlx::query_as(
+            "UPDATE secure_messages SET failed_attempts = failed_attempts + 1
+             WHERE id = $1 AND failed_attempts < $2 AND revoked_at IS NULL
+               AND expires_at > NOW()
+             RETURNING failed_attempts",
+        )
+        .bind(id)
+        .bind(MAX_FAILED_ATTEMPTS)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(failed_attempts.map(|(failed_attempts,)| failed_attempts))
+    }
+
+    /// Count an open with the passcode checked against `code_hash`; the
+    /// one-time code is used up and the attempts are forgiven. False when
+    /// the code was used or replaced in the meantime, or the message locked
+    pub async fn record_open(&self, id: Uuid, code_hash: Option<&str>) -> Result<bool> {
+        let opened: Option<(Uuid,)> = sqlx::query_as(
+            "UPDATE secure_messages
+             SET open_count = open_count + 1, last_opened_at = NOW(), code_hash = NULL,
+                 failed_attempts = 0
+             WHERE id = $1 AND code_hash IS NOT DISTINCT FROM $2 AND failed_attempts <= $3
+               AND revoked_at IS NULL AND expires_at > NOW()
+             RETURNING id",
+        )
+        .bind(id)
+        .bind(code_hash)
+        .bind(MAX_FAILED_ATTEMPTS)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(opened.is_some())
+    }
+
+    /// Add an entry to the access log
+    pub async fn log_access(
+        &self,
+        id: Uuid,
+        event: SecureMessageEvent,
+        ip_address: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO secure_message_access (id, secure_message_id, event, ip_address,
+                                                user_agent, created_at)
+             VALUES ($1, $2, $3, $4, $5, NOW())",
+        )
+        .bind(Uuid::now_v7())
+        .bind(id)
+        .bind(event.as_str())
+        .bind(ip_address)
+        .bind(user_agent)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Access log of a secure message, oldest first
+    pub async fn access_log(&self, id: Uuid) -> Result<Vec<SecureMessageAccess>> {
+        let entries = sqlx::query_as::<_, SecureMessageAccess>(
+            "SELECT * FROM secure_message_access WHERE secure_message_id = $1
+             ORDER BY created_at",
+        )
+        .bind(id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+/// Random link token, 64 hex characters
+fn generate_secure_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_is_active() {
+        let now = Utc::now();
+        let mut message = SecureMessage {
+            id: Uuid::now_v7(),
+            tenant_id: Uuid::now_v7(),
+            token_hash: hash_share_token(&generate_secure_token()),
+            sender: "alice@example.com".to_string(),
+            recipient: "bob@example.net".to_string(),
+            sealed_message: Vec::new(),
+            passcode_hash: None,
+            code_hash: None,
+            code_sent_at: None,
+            failed_attempts: 0,
+            created_by: None,
+            expires_at: now + Duration::hours(1),
+            revoked_at: None,
+            open_count: 0,
+            last_opened_at: None,
+            created_at: now,
+        };
+        assert!(message.is_active(now));
+        assert!(message.uses_emailed_codes());
+        assert!(!message.is_active(now + Duration::hours(2)));
+
+        message.failed_attempts = MAX_FAILED_ATTEMPTS;
+        assert!(!message.is_active(now));
+        message.failed_attempts = 0;
+        message.revoked_at = Some(now);
+        assert!(!message.is_active(now));
+    }
+}
@@ -0,0 +1,134 @@
+//! Account security event repository
+//!
+//! Security events of a user's account, and the networks the user has
+//! logged in from so that logins from a new one can be recognized.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Security event of a user's account
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub user_id: UserId,
+    /// `login_blocked` or `new_login_location`
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    /// Protocol of the login (`imap`, `pop3`)
+    pub protocol: Option<String>,
+    pub details: serde_json::Value,
+    /// A notification email was delivered to the user
+    pub notified: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Input for recording a security event
+#[derive(Debug, Clone)]
+pub struct NewSecurityEvent {
+    pub tenant_id: TenantId,
+    pub user_id: UserId,
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    pub protocol: Option<String>,
+    pub details: serde_json::Value,
+}
+
+/// Security event repository
+pub struct SecurityEventRepository {
+    pool: DatabasePool,
+}
+
+impl SecurityEventRepository {
+    /// Create a new security event repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Record an event
+    pub async fn record(&self, event: &NewSecurityEvent) -> Result<SecurityEvent> {
+        let event = sqlx::query_as::<_, SecurityEvent>(
+            r#"
+            INSERT INTO security_events
+                (id, tenant_id, user_id, event_type, ip_address, protocol, details, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(event.tenant_id)
+        .bind(event.user_id)
+        .bind(&event.event_type)
+        .bind(&event.ip_address)
+        .bind(&event.protocol)
+        .bind(&event.details)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(event)
+    }
+
+    /// Mark that the user was notified of an event
+    pub async fn mark_notified(&self, id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE security_events SET notified = true WHERE id = $1")
+            .bind(id)
+            .execute(self.pool.pool())
+            .await?;
+        Ok(())
+    }
+
+    /// Events of a user, newest first
+    pub async fn list(
+        &self,
+        tenant_id: TenantId,
+        user_id: UserId,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SecurityEvent>> {
+        let events = sqlx::query_as::<_, SecurityEvent>(
+            r#"
+            SELECT * FROM security_events
+            WHERE tenant_id = $1 AND user_id = $2
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Record a login from `source`; true when the user has logged in
+    /// before, but never from this source
+    pub async fn record_login_source(&self, user_id: UserId, source: &str) -> Result<bool> {
+        let (inserted, known_sources): (bool, i64) = sqlx::query_as(
+            r#"
+            WITH known AS (
+                SELECT COUNT(*) AS n FROM user_login_sources WHERE user_id = $1
+            ), upsert AS (
+                INSERT INTO user_login_sources (user_id, source, first_seen_at, last_seen_at)
+                VALUES ($1, $2, NOW(), NOW())
+                ON CONFLICT (user_id, source) DO UPDATE SET last_seen_at = NOW()
+                RETURNING (xmax = 0) AS inserted
+            )
+            SELECT upsert.inserted, known.n FROM upsert, known
+            "#,
+        )
+        .bind(user_id)
+        .bind(source)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(inserted && known_sources > 0)
+    }
+}
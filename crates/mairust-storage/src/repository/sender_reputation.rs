@@ -0,0 +1,125 @@
+//! Sender reputation repository
+//!
+//! Decaying counts of the mail received from each envelope sender domain.
+//! Counts are decayed to the current time whenever they are added to; the
+//! reputation score is computed from them by `mairust_core`.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// Counts of a sender domain
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SenderReputationRecord {
+    pub domain: String,
+    /// Messages, decayed to `decayed_at`
+    pub messages: f64,
+    /// Messages flagged as spam, decayed to `decayed_at`
+    pub spam: f64,
+    /// Refused recipients, decayed to `decayed_at`
+    pub errors: f64,
+    /// Seconds after which the counts have decayed to half
+    pub half_life_secs: f64,
+    pub decayed_at: DateTime<Utc>,
+    pub total_messages: i64,
+    pub total_spam: i64,
+    pub total_errors: i64,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// Sender reputation repository
+pub struct SenderReputationRepository {
+    pool: DatabasePool,
+}
+
+impl SenderReputationRepository {
+    /// Create a new sender reputation repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Decay the counts of a domain to now and add to them
+    pub async fn record(
+        &self,
+        domain: &str,
+        messages: i64,
+        spam: i64,
+        errors: i64,
+        half_life_secs: f64,
+    ) -> Result<SenderReputationRecord> {
+        let record = sqlx::query_as::<_, SenderReputationRecord>(
+            r#"
+            INSERT INTO sender_reputation
+                (domain, messages, spam, errors, half_life_secs, decayed_at,
+                 total_messages, total_spam, total_errors, first_seen_at, last_seen_at)
+            VALUES ($1, $2, $3, $4, $5, NOW(), $2, $3, $4, NOW(), NOW())
+            ON CONFLICT (domain) DO UPDATE SET
+                messages = sender_reputation.messages * POWER(0.5::DOUBLE PRECISION,
+                    EXTRACT(EPOCH FROM NOW() - sender_reputation.decayed_at)::DOUBLE PRECISION
+                    / sender_reputation.half_life_secs) + EXCLUDED.messages,
+                spam = sender_reputation.spam * POWER(0.5::DOUBLE PRECISION,
+                    EXTRACT(EPOCH FROM NOW() - sender_reputation.decayed_at)::DOUBLE PRECISION
+                    / sender_reputation.half_life_secs) + EXCLUDED.spam,
+                errors = sender_reputation.errors * POWER(0.5::DOUBLE PRECISION,
+                    EXTRACT(EPOCH FROM NOW() - sender_reputation.decayed_at)::DOUBLE PRECISION
+                    / sender_reputation.half_life_secs) + EXCLUDED.errors,
+                half_life_secs = EXCLUDED.half_life_secs,
+                decayed_at = NOW(),
+                total_messages = sender_reputation.total_messages + EXCLUDED.total_messages,
+                total_spam = sender_reputation.total_spam + EXCLUDED.total_spam,
+                total_errors = sender_reputation.total_errors + EXCLUDED.total_errors,
+                last_seen_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(domain.to_lowercase())
+        .bind(messages)
+        .bind(spam)
+        .bind(errors)
+        .bind(half_life_secs)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(record)
+    }
+
+    /// Counts of a domain
+    pub async fn get(&self, domain: &str) -> Result<Option<SenderReputationRecord>> {
+        let record = sqlx::query_as::<_, SenderReputationRecord>(
+            "SELECT * FROM sender_reputation WHERE domain = $1",
+        )
+        .bind(domain.to_lowercase())
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(record)
+    }
+
+    /// Domains, most recently seen first
+    pub async fn list(&self, limit: i64, offset: i64) -> Result<Vec<SenderReputationRecord>> {
+        let records = sqlx::query_as::<_, SenderReputationRecord>(
+            "SELECT * FROM sender_reputation ORDER BY last_seen_at DESC LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(records)
+    }
+
+    /// Forget a domain, returning its counts; `None` when it is not known
+    pub async fn reset(&self, domain: &str) -> Result<Option<SenderReputationRecord>> {
+        let record = sqlx::query_as::<_, SenderReputationRecord>(
+            "DELETE FROM sender_reputation WHERE domain = $1 RETURNING *",
+        )
+        .bind(domain.to_lowercase())
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(record)
+    }
+}
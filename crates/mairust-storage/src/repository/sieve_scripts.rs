@@ -0,0 +1,185 @@
+//! Sieve script repository
+//!
+//! The Sieve scripts of a user, by name, as managed over ManageSieve
+//! (RFC 5804). At most one script of a user is active.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A Sieve script
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SieveScript {
+    pub id: Uuid,
+    pub tenant_id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub content: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// How many scripts a user has and their total size
+#[derive(Debug, Clone, Copy, Default, Serialize, FromRow)]
+pub struct SieveUsage {
+    pub scripts: i64,
+    pub bytes: i64,
+}
+
+/// Sieve script repository
+pub struct SieveScriptRepository {
+    pool: DatabasePool,
+}
+
+impl SieveScriptRepository {
+    /// Create a new Sieve script repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Scripts of a user, by name
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<SieveScript>> {
+        let scripts = sqlx::query_as::<_, SieveScript>(
+            "SELECT * FROM sieve_scripts WHERE user_id = $1 ORDER BY name",
+        )
+        .bind(user_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(scripts)
+    }
+
+    /// Get a script of a user by name
+    pub async fn get(&self, user_id: Uuid, name: &str) -> Result<Option<SieveScript>> {
+        let script = sqlx::query_as::<_, SieveScript>(
+            "SELECT * FROM sieve_scripts WHERE user_id = $1 AND name = $2",
+        )
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(script)
+    }
+
+    /// The active script of a user
+    pub async fn active(&self, user_id: Uuid) -> Result<Option<SieveScript>> {
+        let script = sqlx::query_as::<_, SieveScript>(
+            "SELECT * FROM sieve_scripts WHERE user_id = $1 AND active",
+        )
+        .bind(user_id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(script)
+    }
+
+    /// Store a script, replacing the script of the same name
+    pub async fn put(
+        &self,
+        tenant_id: Uuid,
+        user_id: Uuid,
+        name: &str,
+        content: &str,
+    ) -> Result<SieveScript> {
+        let script = sqlx::query_as::<_, SieveScript>(
+            r#"
+            INSERT INTO sieve_scripts (id, tenant_id, user_id, name, content)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, name) DO UPDATE SET
+                content = EXCLUDED.content, updated_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant_id)
+        .bind(user_id)
+        .bind(name)
+        .bind(content)
+        .fetch_one(self.pool.pool())
+        .await?;
+        Ok(script)
+    }
+
+    /// Make a script the active one, or deactivate all scripts with
+    /// `None`; returns whether the named script was found
+    pub async fn set_active(&self, user_id: Uuid, name: Option<&str>) -> Result<bool> {
+        let mut tx = self.pool.pool().begin().await?;
+        if let Some(name) = name {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM sieve_scripts WHERE user_id = $1 AND name = $2)",
+            )
+            .bind(user_id)
+            .bind(name)
+            .fetch_one(&mut *tx)
+            .await?;
+            if !exists {
+                return Ok(false);
+            }
+        }
+        sqlx::query(
+            r#"
+            UPDATE sieve_scripts SET active = false, updated_at = NOW()
+            WHERE user_id = $1 AND active
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(name) = name {
+            sqlx::query(
+                r#"
+                UPDATE sieve_scripts SET active = true, updated_at = NOW()
+                WHERE user_id = $1 AND name = $2
+                "#,
+            )
+            .bind(user_id)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Rename a script; returns whether it was found
+    pub async fn rename(&self, user_id: Uuid, name: &str, new_name: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE sieve_scripts SET name = $3, updated_at = NOW()
+            WHERE user_id = $1 AND name = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(new_name)
+        .execute(self.pool.pool())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a script; returns whether it was found
+    pub async fn delete(&self, user_id: Uuid, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM sieve_scripts WHERE user_id = $1 AND name = $2")
+            .bind(user_id)
+            .bind(name)
+            .execute(self.pool.pool())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// How many scripts a user has and their total size in bytes
+    pub async fn usage(&self, user_id: Uuid) -> Result<SieveUsage> {
+        let usage = sqlx::query_as::<_, SieveUsage>(
+            r#"
+            SELECT COUNT(*) AS scripts, COALESCE(SUM(OCTET_LENGTH(content)), 0)::BIGINT AS bytes
+            FROM sieve_scripts WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(self.pool.pool())
+        .await?;
+        Ok(usage)
+    }
+}
@@ -0,0 +1,148 @@
+//! Spam rule repository
+//!
+//! Rules a tenant adds to the rule-based spam filter, or uses to override
+//! the built-in rules of the same name.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::TenantId;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A spam rule of a tenant
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SpamRuleRecord {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    /// Rule name, reported as the symbol of a match
+    pub name: String,
+    pub description: Option<String>,
+    /// Rule type: header, body, from, subject, url, has_header,
+    /// missing_header or body_length
+    pub scope: String,
+    /// Regular expression, header name or length, depending on the scope
+    pub pattern: String,
+    /// Score added when the rule matches; negative scores lower it
+    pub score: f64,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Input for creating or replacing a spam rule
+#[derive(Debug, Clone)]
+pub struct CreateSpamRule {
+    pub name: String,
+    pub description: Option<String>,
+    pub scope: String,
+    pub pattern: String,
+    pub score: f64,
+    pub enabled: bool,
+}
+
+/// Spam rule repository
+pub struct SpamRuleRepository {
+    pool: DatabasePool,
+}
+
+impl SpamRuleRepository {
+    /// Create a new spam rule repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Rules of a tenant, by name
+    pub async fn list(&self, tenant_id: TenantId) -> Result<Vec<SpamRuleRecord>> {
+        let rules = sqlx::query_as::<_, SpamRuleRecord>(
+            "SELECT * FROM spam_rules WHERE tenant_id = $1 ORDER BY name",
+        )
+        .bind(tenant_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+        Ok(rules)
+    }
+
+    /// Get a rule of a tenant
+    pub async fn get(&self, tenant_id: TenantId, id: Uuid) -> Result<Option<SpamRuleRecord>> {
+        let rule = sqlx::query_as::<_, SpamRuleRecord>(
+            "SELECT * FROM spam_rules WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(rule)
+    }
+
+    /// Create a rule; `None` when the tenant has one of that name
+    pub async fn create(
+        &self,
+        tenant_id: TenantId,
+        input: CreateSpamRule,
+    ) -> Result<Option<SpamRuleRecord>> {
+        let rule = sqlx::query_as::<_, SpamRuleRecord>(
+            r#"
+            INSERT INTO spam_rules (id, tenant_id, name, description, scope, pattern, score,
+                                    enabled)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (tenant_id, name) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant_id)
+        .bind(&input.name)
+        .bind(&input.description)
+        .bind(&input.scope)
+        .bind(&input.pattern)
+        .bind(input.score)
+        .bind(input.enabled)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(rule)
+    }
+
+    /// Replace a rule; `None` when it does not exist or another rule of
+    /// the tenant has the new name
+    pub async fn update(
+        &self,
+        tenant_id: TenantId,
+        id: Uuid,
+        input: CreateSpamRule,
+    ) -> Result<Option<SpamRuleRecord>> {
+        let rule = sqlx::query_as::<_, SpamRuleRecord>(
+            r#"
+            UPDATE spam_rules
+            SET name = $3, description = $4, scope = $5, pattern = $6, score = $7,
+                enabled = $8, updated_at = NOW()
+            WHERE tenant_id = $1 AND id = $2
+              AND NOT EXISTS (SELECT 1 FROM spam_rules
+                              WHERE tenant_id = $1 AND name = $3 AND id <> $2)
+            RETURNING *
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .bind(&input.name)
+        .bind(&input.description)
+        .bind(&input.scope)
+        .bind(&input.pattern)
+        .bind(input.score)
+        .bind(input.enabled)
+        .fetch_optional(self.pool.pool())
+        .await?;
+        Ok(rule)
+    }
+
+    /// Delete a rule; false when it does not exist
+    pub async fn delete(&self, tenant_id: TenantId, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM spam_rules WHERE tenant_id = $1 AND id = $2")
+            .bind(tenant_id)
+            .bind(id)
+            .execute(self.pool.pool())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,69 @@
+//! IMAP mailbox subscription repository
+//!
+//! A row records a user's choice for one mailbox. Mailboxes without a row
+//! are subscribed, so existing folders, new folders and mailboxes shared
+//! with the user show up in LSUB until the user unsubscribes them.
+
+use crate::db::DatabasePool;
+use crate::models::MailboxSubscription;
+use anyhow::Result;
+use mairust_common::types::{MailboxId, UserId};
+
+/// Mailbox subscription repository
+pub struct MailboxSubscriptionRepository {
+    pool: DatabasePool,
+}
+
+impl MailboxSubscriptionRepository {
+    /// Create a new subscription repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Subscribe or unsubscribe a user to a mailbox
+    pub async fn set(
+        &self,
+        user_id: UserId,
+        mailbox_id: MailboxId,
+        subscribed: bool,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO mailbox_subscriptions (user_id, mailbox_id, subscribed, created_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (user_id, mailbox_id) DO UPDATE SET subscribed = EXCLUDED.subscribed
+            "#,
+        )
+        .bind(user_id)
+        .bind(mailbox_id)
+        .bind(subscribed)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Subscription choices a user has made
+    pub async fn list(&self, user_id: UserId) -> Result<Vec<MailboxSubscription>> {
+        let subscriptions = sqlx::query_as::<_, MailboxSubscription>(
+            "SELECT user_id, mailbox_id, subscribed, created_at FROM mailbox_subscriptions
+             WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    /// Mailboxes a user has unsubscribed
+    pub async fn unsubscribed(&self, user_id: UserId) -> Result<Vec<MailboxId>> {
+        Ok(self
+            .list(user_id)
+            .await?
+            .into_iter()
+            .filter(|s| !s.subscribed)
+            .map(|s| s.mailbox_id)
+            .collect())
+    }
+}
@@ -1,9 +1,9 @@
 //! Tenant repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreateTenant, Tenant};
 use async_trait::async_trait;
-use mairust_common::types::TenantId;
+use mairust_common::types::{ServicesEnabled, TenantId};
 use mairust_common::{Error, Result};
 use uuid::Uuid;
 
@@ -35,7 +35,7 @@ impl DbTenantRepository {
         )
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     /// Find tenant by ID (alias for get)
@@ -44,7 +44,7 @@ impl DbTenantRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     /// Create a tenant (simplified for handlers)
@@ -69,13 +69,76 @@ impl DbTenantRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.find_by_id(id)
             .await?
             .ok_or_else(|| Error::Internal("Failed to create tenant".to_string()))
     }
 
+    /// Set the mail access protocols of a tenant (`services_enabled` in its
+    /// settings)
+    pub async fn set_services_enabled(
+        &self,
+        id: TenantId,
+        services: &ServicesEnabled,
+    ) -> Result<()> {
+        let services =
+            serde_json::to_value(services).map_err(|e| Error::Internal(e.to_string()))?;
+        let now = chrono::Utc::now();
+        sqlx::query(
+            "UPDATE tenants SET settings = jsonb_set(settings, '{services_enabled}', $2), updated_at = $3 WHERE id = $1",
+        )
+        .bind(id)
+        .bind(services)
+        .bind(now)
+        .execute(self.pool.pool())
+        .await
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Region a tenant's data is pinned to (`data_region` in its settings)
+    pub async fn data_region(&self, id: TenantId) -> Result<Option<String>> {
+        let region: Option<Option<String>> =
+            sqlx::query_scalar("SELECT settings->>'data_region' FROM tenants WHERE id = $1")
+                .bind(id)
+                .fetch_optional(self.pool.pool())
+                .await
+                .map_err(db_error)?;
+        Ok(region.flatten())
+    }
+
+    /// Pin a tenant's data to a region, or unpin it with `None`
+    pub async fn set_data_region(&self, id: TenantId, region: Option<&str>) -> Result<()> {
+        let now = chrono::Utc::now();
+        sqlx::query(
+            r#"
+            UPDATE tenants SET
+                settings = CASE WHEN $2::text IS NULL THEN settings - 'data_region'
+                    ELSE jsonb_set(settings, '{data_region}', to_jsonb($2::text)) END,
+                updated_at = $3
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(region)
+        .bind(now)
+        .execute(self.pool.pool())
+        .await
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Whether a tenant has stored messages
+    pub async fn has_messages(&self, id: TenantId) -> Result<bool> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM messages WHERE tenant_id = $1)")
+            .bind(id)
+            .fetch_one(self.pool.pool())
+            .await
+            .map_err(db_error)
+    }
+
     /// Delete tenant (soft delete)
     pub async fn delete(&self, id: TenantId) -> Result<()> {
         let now = chrono::Utc::now();
@@ -84,7 +147,7 @@ impl DbTenantRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
@@ -112,7 +175,7 @@ impl TenantRepository for DbTenantRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(id)
             .await?
@@ -124,7 +187,7 @@ impl TenantRepository for DbTenantRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn get_by_slug(&self, slug: &str) -> Result<Option<Tenant>> {
@@ -134,7 +197,7 @@ impl TenantRepository for DbTenantRepository {
         .bind(slug)
         .fetch_optional(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn list(&self, limit: i64, offset: i64) -> Result<Vec<Tenant>> {
@@ -145,7 +208,7 @@ impl TenantRepository for DbTenantRepository {
         .bind(offset)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn update(&self, id: TenantId, name: Option<String>, plan: Option<String>) -> Result<()> {
@@ -158,7 +221,7 @@ impl TenantRepository for DbTenantRepository {
                 .bind(now)
                 .execute(self.pool.pool())
                 .await
-                .map_err(|e| Error::Database(e.to_string()))?;
+                .map_err(db_error)?;
         }
 
         if let Some(plan) = plan {
@@ -168,7 +231,7 @@ impl TenantRepository for DbTenantRepository {
                 .bind(now)
                 .execute(self.pool.pool())
                 .await
-                .map_err(|e| Error::Database(e.to_string()))?;
+                .map_err(db_error)?;
         }
 
         Ok(())
@@ -181,7 +244,7 @@ impl TenantRepository for DbTenantRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
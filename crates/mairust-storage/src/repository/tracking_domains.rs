@@ -0,0 +1,321 @@
+//! Tracking domain repository
+//!
+//! Domains a tenant points at the server for the links of its campaigns,
+//! with the certificates issued for them over ACME, and the state of the
+//! ACME client: pending HTTP-01 challenges and accounts.
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::TenantId;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Status of a tracking domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackingDomainStatus {
+    /// Waiting for its CNAME to point at the server
+    Pending,
+    /// The CNAME points at the server; waiting for a certificate
+    Verified,
+    /// Has a certificate and is used in tracked links
+    Active,
+    /// The CNAME or the certificate order failed, see `last_error`
+    Failed,
+}
+
+impl TrackingDomainStatus {
+    /// Name as stored
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Verified => "verified",
+            Self::Active => "active",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// A tracking domain of a tenant
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TrackingDomain {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub domain: String,
+    pub status: String,
+    pub verified_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub certificate_pem: Option<String>,
+    #[serde(skip)]
+    pub private_key_pem: Option<String>,
+    pub certificate_issued_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TrackingDomain {
+    /// Whether tracked links may use the domain
+    pub fn is_active(&self) -> bool {
+        self.status == TrackingDomainStatus::Active.as_str()
+    }
+}
+
+/// Tracking domain repository
+pub struct TrackingDomainRepository {
+    pool: DatabasePool,
+}
+
+impl TrackingDomainRepository {
+    /// Create a new tracking domain repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Add a tracking domain; `None` when the domain is already taken
+    pub async fn create(&self, tenant_id: TenantId, domain: &str) -> Result<Option<TrackingDomain>> {
+        let tracking_domain = sqlx::query_as::<_, TrackingDomain>(
+            r#"
+            INSERT INTO tracking_domains (id, tenant_id, domain, status, created_at, updated_at)
+            VALUES ($1, $2, $3, 'pending', NOW(), NOW())
+            ON CONFLICT (domain) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(tenant_id)
+        .bind(domain.to_lowercase())
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(tracking_domain)
+    }
+
+    /// Tracking domains of a tenant
+    pub async fn list(&self, tenant_id: TenantId) -> Result<Vec<TrackingDomain>> {
+        let domains = sqlx::query_as::<_, TrackingDomain>(
+            "SELECT * FROM tracking_domains WHERE tenant_id = $1 ORDER BY domain",
+        )
+        .bind(tenant_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(domains)
+    }
+
+    /// A tracking domain of a tenant
+    pub async fn get(&self, tenant_id: TenantId, id: Uuid) -> Result<Option<TrackingDomain>> {
+        let domain = sqlx::query_as::<_, TrackingDomain>(
+            "SELECT * FROM tracking_domains WHERE tenant_id = $1 AND id = $2",
+        )
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(domain)
+    }
+
+    /// Remove a tracking domain; false when the tenant has no such domain
+    pub async fn delete(&self, tenant_id: TenantId, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM tracking_domains WHERE tenant_id = $1 AND id = $2")
+            .bind(tenant_id)
+            .bind(id)
+            .execute(self.pool.pool())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Active tracking domains of a tenant
+    pub async fn list_active(&self, tenant_id: TenantId) -> Result<Vec<TrackingDomain>> {
+        let domains = sqlx::query_as::<_, TrackingDomain>(
+            "SELECT * FROM tracking_domains WHERE tenant_id = $1 AND status = 'active'
+             ORDER BY verified_at",
+        )
+        .bind(tenant_id)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(domains)
+    }
+
+    /// Domains with a certificate, of every tenant
+    pub async fn list_with_certificates(&self) -> Result<Vec<TrackingDomain>> {
+        let domains = sqlx::query_as::<_, TrackingDomain>(
+            "SELECT * FROM tracking_domains
+             WHERE certificate_pem IS NOT NULL AND private_key_pem IS NOT NULL",
+        )
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(domains)
+    }
+
+    /// Domains whose CNAME is still to be checked
+    pub async fn list_unverified(&self) -> Result<Vec<TrackingDomain>> {
+        let domains = sqlx::query_as::<_, TrackingDomain>(
+            "SELECT * FROM tracking_domains WHERE status = 'pending' ORDER BY created_at",
+        )
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(domains)
+    }
+
+    /// Verified domains without a certificate or with one issued before
+    /// `issued_before`
+    pub async fn list_needing_certificate(
+        &self,
+        issued_before: DateTime<Utc>,
+    ) -> Result<Vec<TrackingDomain>> {
+        let domains = sqlx::query_as::<_, TrackingDomain>(
+            "SELECT * FROM tracking_domains
+             WHERE verified_at IS NOT NULL
+               AND (certificate_issued_at IS NULL OR certificate_issued_at < $1)
+             ORDER BY verified_at",
+        )
+        .bind(issued_before)
+        .fetch_all(self.pool.pool())
+        .await?;
+
+        Ok(domains)
+    }
+
+    /// Record that the domain's CNAME points at the server
+    pub async fn mark_verified(&self, id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE tracking_domains SET status = 'verified', verified_at = NOW(),
+                    last_error = NULL, updated_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record why the CNAME check of a pending domain failed
+    pub async fn record_check_error(&self, id: Uuid, error: &str) -> Result<()> {
+        sqlx::query("UPDATE tracking_domains SET last_error = $2, updated_at = NOW() WHERE id = $1")
+            .bind(id)
+            .bind(error)
+            .execute(self.pool.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record why a certificate order failed. A domain that still has a
+    /// certificate stays active until it is replaced.
+    pub async fn mark_failed(&self, id: Uuid, error: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE tracking_domains
+             SET status = CASE WHEN status = 'active' THEN status ELSE 'failed' END,
+                 last_error = $2, updated_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(error)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Store a new certificate of the domain and make it active
+    pub async fn store_certificate(
+        &self,
+        id: Uuid,
+        certificate_pem: &str,
+        private_key_pem: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE tracking_domains SET status = 'active', certificate_pem = $2,
+                    private_key_pem = $3, certificate_issued_at = NOW(), last_error = NULL,
+                    updated_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(certificate_pem)
+        .bind(private_key_pem)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Publish the answer to an HTTP-01 challenge
+    pub async fn put_challenge(
+        &self,
+        token: &str,
+        key_authorization: &str,
+        domain: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO acme_challenges (token, key_authorization, domain, created_at)
+             VALUES ($1, $2, $3, NOW())
+             ON CONFLICT (token) DO UPDATE SET key_authorization = EXCLUDED.key_authorization",
+        )
+        .bind(token)
+        .bind(key_authorization)
+        .bind(domain)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Answer to an HTTP-01 challenge
+    pub async fn find_challenge(&self, token: &str) -> Result<Option<String>> {
+        let key_authorization: Option<(String,)> =
+            sqlx::query_as("SELECT key_authorization FROM acme_challenges WHERE token = $1")
+                .bind(token)
+                .fetch_optional(self.pool.pool())
+                .await?;
+
+        Ok(key_authorization.map(|(key_authorization,)| key_authorization))
+    }
+
+    /// Remove the challenges of a domain once its order is done
+    pub async fn delete_challenges(&self, domain: &str) -> Result<()> {
+        sqlx::query("DELETE FROM acme_challenges WHERE domain = $1")
+            .bind(domain)
+            .execute(self.pool.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stored credentials of the ACME account at a directory
+    pub async fn find_account(&self, directory_url: &str) -> Result<Option<serde_json::Value>> {
+        let credentials: Option<(serde_json::Value,)> =
+            sqlx::query_as("SELECT credentials FROM acme_accounts WHERE directory_url = $1")
+                .bind(directory_url)
+                .fetch_optional(self.pool.pool())
+                .await?;
+
+        Ok(credentials.map(|(credentials,)| credentials))
+    }
+
+    /// Store the credentials of a new ACME account
+    pub async fn store_account(
+        &self,
+        directory_url: &str,
+        credentials: &serde_json::Value,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO acme_accounts (directory_url, credentials, created_at)
+             VALUES ($1, $2, NOW())
+             ON CONFLICT (directory_url) DO UPDATE SET credentials = EXCLUDED.credentials",
+        )
+        .bind(directory_url)
+        .bind(credentials)
+        .execute(self.pool.pool())
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+//! User invite repository
+//!
+//! Invites let a user created without a password choose one on the web UI.
+//! As with share links, only the SHA-256 hash of an invite's token is
+//! stored.
+
+use crate::db::DatabasePool;
+use crate::repository::message_shares::hash_share_token;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// An invite to choose a password
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserInvite {
+    pub id: Uuid,
+    pub tenant_id: TenantId,
+    pub user_id: UserId,
+    #[serde(skip)]
+    pub token_hash: String,
+    /// Address the invite was sent to
+    pub email: String,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// User invite repository
+pub struct UserInviteRepository {
+    pool: DatabasePool,
+}
+
+impl UserInviteRepository {
+    /// Create a new user invite repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Invite of a token that is neither accepted nor expired, with the
+    /// address of its user
+    pub async fn find_active(&self, token: &str) -> Result<Option<(UserInvite, String)>> {
+        let invite = sqlx::query_as::<_, UserInvite>(
+            "SELECT * FROM user_invites
+             WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > NOW()",
+        )
+        .bind(hash_share_token(token))
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        let Some(invite) = invite else {
+            return Ok(None);
+        };
+        let (address,): (String,) = sqlx::query_as("SELECT email FROM users WHERE id = $1")
+            .bind(invite.user_id)
+            .fetch_one(self.pool.pool())
+            .await?;
+
+        Ok(Some((invite, address)))
+    }
+
+    /// Set the user's password and use up the invite, along with any other
+    /// open invites of the user; false when the invite was used meanwhile
+    pub async fn accept(&self, invite: &UserInvite, password_hash: &str) -> Result<bool> {
+        let mut tx = self.pool.pool().begin().await?;
+
+        let accepted = sqlx::query(
+            "UPDATE user_invites SET accepted_at = NOW()
+             WHERE id = $1 AND accepted_at IS NULL AND expires_at > NOW()",
+        )
+        .bind(invite.id)
+        .execute(&mut *tx)
+        .await?;
+        if accepted.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "UPDATE user_invites SET accepted_at = NOW()
+             WHERE user_id = $1 AND accepted_at IS NULL",
+        )
+        .bind(invite.user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE users SET password_hash = $2, updated_at = NOW() WHERE id = $1")
+            .bind(invite.user_id)
+            .bind(password_hash)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,228 @@
+//! User settings repository
+
+use crate::db::DatabasePool;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use mairust_common::types::{TenantId, UserId};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Per-user settings
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserSettings {
+    pub user_id: UserId,
+    pub tenant_id: TenantId,
+    /// Deliver messages flagged by the spam filter to the Junk folder
+    pub junk_filing: bool,
+    /// Show desktop notifications for new mail in the web UI
+    pub desktop_notifications: bool,
+    /// Play a sound with new mail notifications
+    pub notification_sound: bool,
+    /// How often to email a digest of unread messages
+    pub digest_frequency: String,
+    /// Folder or address that receives a copy of every message sent
+    #[sqlx(default)]
+    pub sent_archive: Option<String>,
+    /// File mail for `user+tag@domain` into the folder named `tag`
+    #[sqlx(default)]
+    pub subaddress_filing: bool,
+    /// Email a daily digest of messages held in quarantine
+    #[sqlx(default)]
+    pub quarantine_digest: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl UserSettings {
+    /// Settings used for users that have never saved any
+    pub fn defaults(tenant_id: TenantId, user_id: UserId) -> Self {
+        let now = Utc::now();
+        Self {
+            user_id,
+            tenant_id,
+            junk_filing: true,
+            desktop_notifications: false,
+            notification_sound: true,
+            digest_frequency: DigestFrequency::Never.as_str().to_string(),
+            sent_archive: None,
+            subaddress_filing: false,
+            quarantine_digest: false,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Webmail notification preferences, a subset of [`UserSettings`]
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationSettings {
+    pub desktop_notifications: bool,
+    pub notification_sound: bool,
+    pub digest_frequency: String,
+}
+
+impl From<UserSettings> for NotificationSettings {
+    fn from(settings: UserSettings) -> Self {
+        Self {
+            desktop_notifications: settings.desktop_notifications,
+            notification_sound: settings.notification_sound,
+            digest_frequency: settings.digest_frequency,
+        }
+    }
+}
+
+/// How often unread mail digests are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestFrequency {
+    Never,
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl DigestFrequency {
+    /// Value stored in `user_settings.digest_frequency`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Never => "never",
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+}
+
+/// Partial settings update; `None` leaves a setting unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpdateUserSettings {
+    pub junk_filing: Option<bool>,
+    pub desktop_notifications: Option<bool>,
+    pub notification_sound: Option<bool>,
+    pub digest_frequency: Option<DigestFrequency>,
+    /// Sent-mail archive; an empty string removes it
+    pub sent_archive: Option<String>,
+    pub subaddress_filing: Option<bool>,
+    pub quarantine_digest: Option<bool>,
+}
+
+/// Partial notification preferences update; `None` leaves a setting unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpdateNotificationSettings {
+    pub desktop_notifications: Option<bool>,
+    pub notification_sound: Option<bool>,
+    pub digest_frequency: Option<DigestFrequency>,
+}
+
+impl From<UpdateNotificationSettings> for UpdateUserSettings {
+    fn from(input: UpdateNotificationSettings) -> Self {
+        Self {
+            desktop_notifications: input.desktop_notifications,
+            notification_sound: input.notification_sound,
+            digest_frequency: input.digest_frequency,
+            ..Default::default()
+        }
+    }
+}
+
+/// User settings repository
+pub struct UserSettingsRepository {
+    pool: DatabasePool,
+}
+
+impl UserSettingsRepository {
+    /// Create a new user settings repository
+    pub fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Get a user's settings, falling back to the defaults
+    pub async fn get(&self, tenant_id: TenantId, user_id: UserId) -> Result<UserSettings> {
+        let settings = sqlx::query_as::<_, UserSettings>(
+            "SELECT * FROM user_settings WHERE tenant_id = $1 AND user_id = $2",
+        )
+        .bind(tenant_id)
+        .bind(user_id)
+        .fetch_optional(self.pool.pool())
+        .await?;
+
+        Ok(settings.unwrap_or_else(|| UserSettings::defaults(tenant_id, user_id)))
+    }
+
+    /// Apply a partial update, creating the row on first use
+    pub async fn update(
+        &self,
+        tenant_id: TenantId,
+        user_id: UserId,
+        input: &UpdateUserSettings,
+    ) -> Result<UserSettings> {
+        let settings = sqlx::query_as::<_, UserSettings>(
+            r#"
+            INSERT INTO user_settings (user_id, tenant_id, junk_filing, desktop_notifications,
+                                       notification_sound, digest_frequency, sent_archive,
+                                       subaddress_filing, quarantine_digest)
+            VALUES ($1, $2, COALESCE($3, TRUE), COALESCE($4, FALSE), COALESCE($5, TRUE),
+                    COALESCE($6, 'never'), NULLIF($7, ''), COALESCE($8, FALSE),
+                    COALESCE($9, FALSE))
+            ON CONFLICT (user_id) DO UPDATE SET
+                junk_filing = COALESCE($3, user_settings.junk_filing),
+                desktop_notifications = COALESCE($4, user_settings.desktop_notifications),
+                notification_sound = COALESCE($5, user_settings.notification_sound),
+                digest_frequency = COALESCE($6, user_settings.digest_frequency),
+                sent_archive = CASE WHEN $7::text IS NULL THEN user_settings.sent_archive
+                                    ELSE NULLIF($7, '') END,
+                subaddress_filing = COALESCE($8, user_settings.subaddress_filing),
+                quarantine_digest = COALESCE($9, user_settings.quarantine_digest),
+                updated_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(tenant_id)
+        .bind(input.junk_filing)
+        .bind(input.desktop_notifications)
+        .bind(input.notification_sound)
+        .bind(input.digest_frequency.map(DigestFrequency::as_str))
+        .bind(input.sent_archive.as_deref())
+        .bind(input.subaddress_filing)
+        .bind(input.quarantine_digest)
+        .fetch_one(self.pool.pool())
+        .await?;
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let settings = UserSettings::defaults(uuid::Uuid::now_v7(), uuid::Uuid::now_v7());
+        assert!(settings.junk_filing);
+        assert!(!settings.desktop_notifications);
+        assert!(settings.notification_sound);
+        assert_eq!(settings.digest_frequency, "never");
+        assert_eq!(settings.sent_archive, None);
+        assert!(!settings.subaddress_filing);
+        assert!(!settings.quarantine_digest);
+    }
+
+    #[test]
+    fn test_update_notification_settings() {
+        let input: UpdateNotificationSettings =
+            serde_json::from_str(r#"{"digest_frequency": "daily", "notification_sound": false}"#)
+                .unwrap();
+        let update = UpdateUserSettings::from(input);
+        assert_eq!(update.digest_frequency, Some(DigestFrequency::Daily));
+        assert_eq!(update.notification_sound, Some(false));
+        assert_eq!(update.junk_filing, None);
+        assert_eq!(update.sent_archive, None);
+
+        assert!(serde_json::from_str::<UpdateNotificationSettings>(
+            r#"{"digest_frequency": "monthly"}"#
+        )
+        .is_err());
+    }
+}
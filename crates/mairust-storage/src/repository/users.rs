@@ -1,9 +1,9 @@
 //! User repository
 
-use crate::db::DatabasePool;
+use crate::db::{db_error, DatabasePool};
 use crate::models::{CreateUser, User};
 use async_trait::async_trait;
-use mairust_common::types::{TenantId, UserId};
+use mairust_common::types::{ServicesEnabled, TenantId, UserId};
 use mairust_common::{Error, Result};
 use uuid::Uuid;
 
@@ -37,7 +37,7 @@ impl DbUserRepository {
         .bind(tenant_id)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     /// Find user by ID (simplified)
@@ -46,7 +46,7 @@ impl DbUserRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     /// Create user (simplified for handlers - uses placeholder password)
@@ -73,20 +73,72 @@ impl DbUserRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.find_by_id(id)
             .await?
             .ok_or_else(|| Error::Internal("Failed to create user".to_string()))
     }
 
+    /// APOP shared secret of a user, if one is set
+    pub async fn apop_secret(&self, id: UserId) -> Result<Option<String>> {
+        let secret: Option<(Option<String>,)> =
+            sqlx::query_as("SELECT apop_secret FROM users WHERE id = $1")
+                .bind(id)
+                .fetch_optional(self.pool.pool())
+                .await
+                .map_err(db_error)?;
+        Ok(secret.and_then(|(secret,)| secret))
+    }
+
+    /// Set or clear (`None`) a user's APOP shared secret
+    pub async fn set_apop_secret(&self, id: UserId, secret: Option<&str>) -> Result<()> {
+        let now = chrono::Utc::now();
+        sqlx::query("UPDATE users SET apop_secret = $2, updated_at = $3 WHERE id = $1")
+            .bind(id)
+            .bind(secret)
+            .bind(now)
+            .execute(self.pool.pool())
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    /// Mail access protocols a user may use
+    pub async fn services_enabled(&self, id: UserId) -> Result<ServicesEnabled> {
+        let services: Option<(serde_json::Value,)> =
+            sqlx::query_as("SELECT services_enabled FROM users WHERE id = $1")
+                .bind(id)
+                .fetch_optional(self.pool.pool())
+                .await
+                .map_err(db_error)?;
+        Ok(services
+            .map(|(services,)| ServicesEnabled::from_json(&services))
+            .unwrap_or_default())
+    }
+
+    /// Set the mail access protocols a user may use
+    pub async fn set_services_enabled(&self, id: UserId, services: &ServicesEnabled) -> Result<()> {
+        let services =
+            serde_json::to_value(services).map_err(|e| Error::Internal(e.to_string()))?;
+        let now = chrono::Utc::now();
+        sqlx::query("UPDATE users SET services_enabled = $2, updated_at = $3 WHERE id = $1")
+            .bind(id)
+            .bind(services)
+            .bind(now)
+            .execute(self.pool.pool())
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+
     /// Delete user
     pub async fn delete(&self, id: UserId) -> Result<()> {
         sqlx::query("DELETE FROM users WHERE id = $1")
             .bind(id)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
@@ -114,7 +166,7 @@ impl UserRepository for DbUserRepository {
         .bind(now)
         .execute(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))?;
+        .map_err(db_error)?;
 
         self.get(input.tenant_id, id)
             .await?
@@ -127,7 +179,7 @@ impl UserRepository for DbUserRepository {
             .bind(id)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn get_by_email(&self, email: &str) -> Result<Option<User>> {
@@ -135,7 +187,7 @@ impl UserRepository for DbUserRepository {
             .bind(email)
             .fetch_optional(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))
+            .map_err(db_error)
     }
 
     async fn list(&self, tenant_id: TenantId, limit: i64, offset: i64) -> Result<Vec<User>> {
@@ -147,7 +199,7 @@ impl UserRepository for DbUserRepository {
         .bind(offset)
         .fetch_all(self.pool.pool())
         .await
-        .map_err(|e| Error::Database(e.to_string()))
+        .map_err(db_error)
     }
 
     async fn update_password(&self, id: UserId, password_hash: String) -> Result<()> {
@@ -158,7 +210,7 @@ impl UserRepository for DbUserRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -169,7 +221,7 @@ impl UserRepository for DbUserRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 
@@ -180,7 +232,7 @@ impl UserRepository for DbUserRepository {
             .bind(now)
             .execute(self.pool.pool())
             .await
-            .map_err(|e| Error::Database(e.to_string()))?;
+            .map_err(db_error)?;
         Ok(())
     }
 }
@@ -0,0 +1,274 @@
+//! Tenant data residency
+//!
+//! In a multi-region deployment a tenant can be pinned to a region (the
+//! `data_region` of its settings). The message files of a pinned tenant
+//! live in the storage of that region, and only servers running in that
+//! region (`residency.region`) may access them; other servers get
+//! [`Error::Residency`]. Files of tenants that are not pinned, and files
+//! that belong to no tenant (journal reports), stay in `storage`.
+//!
+//! Tenants are found from the storage path, whose first component (after
+//! the generation directory of the hashed layout) is the tenant ID.
+
+use crate::db::DatabasePool;
+use crate::file::{create_storage, FileStorage};
+use crate::repository::tenants::DbTenantRepository;
+use async_trait::async_trait;
+use mairust_common::config::{ResidencyConfig, StorageConfig};
+use mairust_common::{Error, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long the region of a tenant is cached
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Resolves the region holding each tenant's data
+pub struct Residency {
+    db_pool: DatabasePool,
+    config: ResidencyConfig,
+    cache: Mutex<HashMap<Uuid, (Option<String>, Instant)>>,
+}
+
+impl Residency {
+    pub fn new(db_pool: DatabasePool, config: ResidencyConfig) -> Self {
+        Self {
+            db_pool,
+            config,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether tenants can be pinned to regions
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    /// Region this server runs in
+    pub fn local_region(&self) -> Option<&str> {
+        self.config.region.as_deref()
+    }
+
+    /// Region a tenant is pinned to, from the cache when fresh
+    pub async fn region_of(&self, tenant_id: Uuid) -> Result<Option<String>> {
+        let now = Instant::now();
+        if let Some((region, expires_at)) = self.cache.lock().unwrap().get(&tenant_id) {
+            if *expires_at > now {
+                return Ok(region.clone());
+            }
+        }
+
+        let region = DbTenantRepository::new(self.db_pool.clone())
+            .data_region(tenant_id)
+            .await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(tenant_id, (region.clone(), now + CACHE_TTL));
+        Ok(region)
+    }
+
+    /// Storage of a tenant pinned to this server's region
+    ///
+    /// `None` when the tenant is not pinned or residency is not enforced.
+    /// Fails with [`Error::Residency`] when the tenant is pinned to another
+    /// region.
+    pub async fn storage_for(&self, tenant_id: Uuid) -> Result<Option<&StorageConfig>> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let region = self.region_of(tenant_id).await?;
+        pinned_storage(&self.config, tenant_id, region.as_deref())
+    }
+
+    /// Forget the cached region of a tenant after it changed
+    pub fn invalidate(&self, tenant_id: Uuid) {
+        self.cache.lock().unwrap().remove(&tenant_id);
+    }
+
+    /// Cache the region a tenant was just pinned to
+    pub fn set_region(&self, tenant_id: Uuid, region: Option<String>) {
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(tenant_id, (region, Instant::now() + CACHE_TTL));
+    }
+}
+
+impl std::fmt::Debug for Residency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Residency")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Storage for a tenant pinned to `region`, if this server may access it
+fn pinned_storage<'a>(
+    config: &'a ResidencyConfig,
+    tenant_id: Uuid,
+    region: Option<&str>,
+) -> Result<Option<&'a StorageConfig>> {
+    let Some(region) = region else {
+        return Ok(None);
+    };
+    match config.regions.get(region) {
+        Some(pinned) if config.region.as_deref() == Some(region) => Ok(Some(&pinned.storage)),
+        _ => Err(Error::Residency(format!(
+            "Data of tenant {} is stored in region {}",
+            tenant_id, region
+        ))),
+    }
+}
+
+/// Tenant a storage path belongs to
+///
+/// `None` for files of no tenant, such as journal reports.
+pub fn tenant_of_path(path: &str) -> Option<Uuid> {
+    let mut parts = path.split('/');
+    let first = parts.next()?;
+    let is_generation = first
+        .strip_prefix('g')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    let tenant = if is_generation { parts.next()? } else { first };
+    Uuid::parse_str(tenant).ok()
+}
+
+/// File storage that keeps each tenant's files in its region
+///
+/// Wraps the storage of `storage` and, when residency is enforced, the
+/// storage of this server's region. New files follow the layout of the
+/// wrapped storage.
+pub struct ResidentStorage {
+    storage: Box<dyn FileStorage>,
+    regional: Option<Box<dyn FileStorage>>,
+    residency: Arc<Residency>,
+}
+
+impl ResidentStorage {
+    /// Wrap `storage`, creating the storage of the local region
+    pub fn new(storage: Box<dyn FileStorage>, residency: Arc<Residency>) -> Result<Self> {
+        let regional = match residency.local_region() {
+            Some(region) if residency.is_enabled() => {
+                let config = residency.config.regions.get(region).ok_or_else(|| {
+                    Error::Config(format!("Region '{}' is not configured", region))
+                })?;
+                Some(create_storage(&config.storage)?)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            storage,
+            regional,
+            residency,
+        })
+    }
+
+    /// Residency of the tenants whose files are stored
+    pub fn residency(&self) -> &Residency {
+        &self.residency
+    }
+
+    /// Storage holding the file at `path`
+    async fn storage_of(&self, path: &str) -> Result<&dyn FileStorage> {
+        let tenant_id = match tenant_of_path(path) {
+            Some(tenant_id) if self.residency.is_enabled() => tenant_id,
+            _ => return Ok(self.storage.as_ref()),
+        };
+        match (self.residency.storage_for(tenant_id).await?, &self.regional) {
+            (Some(_), Some(regional)) => Ok(regional.as_ref()),
+            _ => Ok(self.storage.as_ref()),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorage for ResidentStorage {
+    async fn store(&self, path: &str, data: &[u8]) -> Result<String> {
+        self.storage_of(path).await?.store(path, data).await
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>> {
+        self.storage_of(path).await?.read(path).await
+    }
+
+    async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.storage_of(path)
+            .await?
+            .read_range(path, offset, len)
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<()> {
+        self.storage_of(path).await?.delete(path).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        self.storage_of(path).await?.exists(path).await
+    }
+
+    async fn size(&self, path: &str) -> Result<u64> {
+        self.storage_of(path).await?.size(path).await
+    }
+
+    fn message_path(&self, tenant_id: Uuid, mailbox_id: Uuid, message_id: Uuid) -> String {
+        self.storage.message_path(tenant_id, mailbox_id, message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mairust_common::config::RegionConfig;
+
+    #[test]
+    fn test_tenant_of_path() {
+        let tenant = Uuid::parse_str("0191a2b3-0000-7000-8000-000000000001").unwrap();
+        let mailbox = Uuid::parse_str("0191a2b3-0000-7000-8000-000000000002").unwrap();
+        assert_eq!(
+            tenant_of_path(&format!("{}/{}/m.eml", tenant, mailbox)),
+            Some(tenant)
+        );
+        assert_eq!(
+            tenant_of_path(&format!("g2/{}/{}/ab/m.eml", tenant, mailbox)),
+            Some(tenant)
+        );
+        assert_eq!(
+            tenant_of_path(&format!("{}/inbound/{}/m.eml", tenant, mailbox)),
+            Some(tenant)
+        );
+        assert_eq!(tenant_of_path("journal/report.eml"), None);
+        assert_eq!(tenant_of_path(""), None);
+    }
+
+    #[test]
+    fn test_pinned_storage() {
+        let region = |path: &str| RegionConfig {
+            storage: StorageConfig {
+                path: path.into(),
+                ..StorageConfig::default()
+            },
+        };
+        let config = ResidencyConfig {
+            region: Some("eu".to_string()),
+            regions: HashMap::from([
+                ("eu".to_string(), region("/srv/eu")),
+                ("us".to_string(), region("/srv/us")),
+            ]),
+        };
+        let tenant = Uuid::now_v7();
+
+        assert!(pinned_storage(&config, tenant, None).unwrap().is_none());
+        assert_eq!(
+            pinned_storage(&config, tenant, Some("eu"))
+                .unwrap()
+                .map(|storage| storage.path.clone()),
+            Some("/srv/eu".into())
+        );
+        for region in ["us", "ap"] {
+            let error = pinned_storage(&config, tenant, Some(region)).unwrap_err();
+            assert_eq!(error.code(), "WRONG_REGION");
+        }
+    }
+}
@@ -5,13 +5,31 @@
 use crate::{AppState, StaticAssets};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
-    extract::{Path, State},
-    http::{header, StatusCode},
+    body::Bytes,
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode},
     response::{Html, IntoResponse, Redirect, Response},
     Form,
 };
 use axum_extra::extract::cookie::{Cookie, CookieJar};
+use mairust_core::inbound::attachment;
+use mairust_core::mta_sts::MtaStsHost;
+use mairust_core::provisioning::{hash_user_password, validate_user_password, MIN_PASSWORD_LEN};
+use mairust_core::reports::{unpack_posted_tls_report, ReportIngester};
+use mairust_core::secure_message::{
+    check_passcode, mask_address, send_code, SecureMessageKey, CODE_VALID_MINUTES,
+};
+use mairust_core::share::SharedMessage;
+use mairust_core::spam::AttachmentRescanner;
+use mairust_storage::repository::messages::MessageRepository as MessageRepositoryTrait;
+use mairust_storage::repository::secure_messages::MAX_FAILED_ATTEMPTS;
+use mairust_storage::repository::{CampaignRepository, TrackingDomainRepository};
+use mairust_storage::{
+    Message, MessageRepository, MessageShare, MessageShareRepository,
+    SecureMessage, SecureMessageEvent, SecureMessageRepository, UserInvite, UserInviteRepository,
+};
 use serde::Deserialize;
+use std::net::SocketAddr;
 use time::Duration;
 use uuid::Uuid;
 
@@ -354,3 +372,748 @@ pub async fn logout(
 
     (jar.remove(cookie), Redirect::to("/login")).into_response()
 }
+
+/// Content-Security-Policy of shared message pages: inline styles only, no
+/// scripts, images or other remote content
+const SHARE_CSP: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; base-uri 'none'; form-action 'none'";
+
+/// Shared message page
+///
+/// Served without signing in to anyone with the link. Unknown, expired and
+/// revoked links all get the same "not available" page.
+pub async fn shared_message(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Response {
+    let Some((share, _, raw)) = find_shared_message(&state, &token).await else {
+        return share_not_available(&state);
+    };
+    let Some(shared) = SharedMessage::of_raw(&raw) else {
+        tracing::warn!("Shared message {} could not be parsed", share.message_id);
+        return share_not_available(&state);
+    };
+
+    if let Err(e) = MessageShareRepository::new(state.db_pool.clone())
+        .record_view(share.id)
+        .await
+    {
+        tracing::warn!("Failed to count view of share {}: {}", share.id, e);
+    }
+
+    let message = &shared.message;
+    let fields: Vec<serde_json::Value> = message
+        .header_fields()
+        .into_iter()
+        .filter(|(name, _)| *name != "Subject")
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+        .collect();
+    let attachments: Vec<serde_json::Value> = message
+        .attachments
+        .iter()
+        .enumerate()
+        .map(|(index, file)| serde_json::json!({ "index": index, "description": file.describe() }))
+        .collect();
+
+    let context = serde_json::json!({
+        "subject": message.subject,
+        "fields": fields,
+        "body_html": shared.body_html,
+        "attachments": attachments,
+        "token": token,
+        "expires_at": share.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+    });
+
+    render_shared(&state, StatusCode::OK, &context)
+}
+
+/// Attachment download from a shared message page
+///
+/// The message is rescanned first when rescanning on download is enabled,
+/// as for downloads through the API.
+pub async fn shared_attachment(
+    State(state): State<AppState>,
+    Path((token, index)): Path<(String, usize)>,
+) -> Response {
+    let Some((share, message, raw)) = find_shared_message(&state, &token).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some((content_type, filename, contents)) = attachment(&raw, index) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let verdict = AttachmentRescanner::from_env(state.db_pool.clone())
+        .check(
+            share.tenant_id,
+            &message.storage_path,
+            Some(message.received_at),
+            &raw,
+        )
+        .await;
+    if let Some(reason) = verdict.reason() {
+        tracing::warn!(
+            "Refusing download of attachment {} of shared message {}",
+            index,
+            share.message_id
+        );
+        return (StatusCode::FORBIDDEN, reason).into_response();
+    }
+
+    let disposition = match filename {
+        Some(name) => format!(
+            "attachment; filename=\"{}\"",
+            name.replace(['"', '\\', '\r', '\n'], "_")
+        ),
+        None => "attachment".to_string(),
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        contents,
+    )
+        .into_response()
+}
+
+/// Active share of a token with the message it shares, raw and as stored
+async fn find_shared_message(
+    state: &AppState,
+    token: &str,
+) -> Option<(MessageShare, Message, Vec<u8>)> {
+    let share = match MessageShareRepository::new(state.db_pool.clone())
+        .find_active(token)
+        .await
+    {
+        Ok(share) => share?,
+        Err(e) => {
+            tracing::error!("Failed to look up message share: {}", e);
+            return None;
+        }
+    };
+
+    let message = match MessageRepository::new(state.db_pool.clone())
+        .get(share.tenant_id, share.message_id)
+        .await
+    {
+        Ok(message) => message?,
+        Err(e) => {
+            tracing::error!("Failed to load shared message {}: {}", share.message_id, e);
+            return None;
+        }
+    };
+
+    let raw = match state.file_storage.read(&message.storage_path).await {
+        Ok(raw) => raw,
+        Err(e) => {
+            tracing::warn!("Failed to read shared message {}: {}", message.id, e);
+            return None;
+        }
+    };
+
+    Some((share, message, raw))
+}
+
+/// Page of an unknown, expired or revoked link
+fn share_not_available(state: &AppState) -> Response {
+    render_shared(
+        state,
+        StatusCode::NOT_FOUND,
+        &serde_json::json!({ "not_found": true }),
+    )
+}
+
+/// Render a shared message page; shared pages are never cached or sent as
+/// a referrer
+fn render_shared(state: &AppState, status: StatusCode, context: &serde_json::Value) -> Response {
+    match state.templates.render("shared_message", context) {
+        Ok(html) => (
+            status,
+            [
+                (header::CONTENT_SECURITY_POLICY, SHARE_CSP),
+                (header::REFERRER_POLICY, "no-referrer"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            Html(html),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Template error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Content-Security-Policy of secure message and invite pages: as for
+/// shared messages, but the forms post back to the page
+const SECURE_CSP: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; base-uri 'none'; form-action 'self'; \
+     frame-ancestors 'none'";
+
+/// Passcode form data
+#[derive(Deserialize)]
+pub struct PasscodeForm {
+    pub passcode: String,
+}
+
+/// Who opened a secure message page, for its access log
+struct Visitor {
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Visitor {
+    fn of(connect_info: Option<ConnectInfo<SocketAddr>>, headers: &HeaderMap) -> Self {
+        Self {
+            ip_address: connect_info.map(|ConnectInfo(addr)| addr.ip().to_string()),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(512).collect()),
+        }
+    }
+}
+
+/// Secure message page: the passcode form
+///
+/// Unknown, expired, revoked and locked links all get the same "not
+/// available" page.
+pub async fn secure_message(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let Some(message) = find_secure_message(&state, &token).await else {
+        return secure_not_available(&state);
+    };
+
+    render_secure(
+        &state,
+        StatusCode::OK,
+        &passcode_context(&message, &token, None, None),
+    )
+}
+
+/// Email a one-time code to the recipient of a secure message
+pub async fn secure_message_code(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> Response {
+    let Some(message) = find_secure_message(&state, &token).await else {
+        return secure_not_available(&state);
+    };
+    if !message.uses_emailed_codes() {
+        return render_secure(
+            &state,
+            StatusCode::OK,
+            &passcode_context(&message, &token, None, None),
+        );
+    }
+
+    let notice = match send_code(&state.db_pool, &message).await {
+        Ok(true) => {
+            log_secure_access(
+                &state,
+                &message,
+                SecureMessageEvent::CodeSent,
+                &Visitor::of(connect_info, &headers),
+            )
+            .await;
+            format!(
+                "A code was sent to {}. It is valid for {} minutes.",
+                mask_address(&message.recipient),
+                CODE_VALID_MINUTES
+            )
+        }
+        Ok(false) => format!(
+            "A code was sent to {} a moment ago. Please check your inbox.",
+            mask_address(&message.recipient)
+        ),
+        Err(e) => {
+            tracing::error!(
+                "Failed to send code of secure message {}: {}",
+                message.id,
+                e
+            );
+            return render_secure(
+                &state,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &passcode_context(
+                    &message,
+                    &token,
+                    None,
+                    Some("The code could not be sent. Please try again later."),
+                ),
+            );
+        }
+    };
+
+    render_secure(
+        &state,
+        StatusCode::OK,
+        &passcode_context(&message, &token, Some(&notice), None),
+    )
+}
+
+/// Open a secure message with its passcode
+pub async fn secure_message_open(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Form(form): Form<PasscodeForm>,
+) -> Response {
+    let Some(message) = find_secure_message(&state, &token).await else {
+        return secure_not_available(&state);
+    };
+    let visitor = Visitor::of(connect_info, &headers);
+    let repository = SecureMessageRepository::new(state.db_pool.clone());
+
+    let failed_attempts = match repository.take_attempt(message.id).await {
+        Ok(Some(failed_attempts)) => failed_attempts,
+        Ok(None) => return secure_not_available(&state),
+        Err(e) => {
+            tracing::error!("Failed to count passcode attempt of {}: {}", message.id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if !check_passcode(&message, &form.passcode, chrono::Utc::now()) {
+        log_secure_access(&state, &message, SecureMessageEvent::Failed, &visitor).await;
+        if failed_attempts >= MAX_FAILED_ATTEMPTS {
+            tracing::warn!(
+                "Secure message {} locked after {} wrong passcodes",
+                message.id,
+                failed_attempts
+            );
+            log_secure_access(&state, &message, SecureMessageEvent::Locked, &visitor).await;
+            return secure_not_available(&state);
+        }
+        let error = if message.uses_emailed_codes() {
+            "The code is wrong or has expired."
+        } else {
+            "The passcode is wrong."
+        };
+        return render_secure(
+            &state,
+            StatusCode::UNAUTHORIZED,
+            &passcode_context(&message, &token, None, Some(error)),
+        );
+    }
+
+    let key = match SecureMessageKey::from_env() {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            tracing::error!("MAIRUST_SECURE_MESSAGE_KEY is not set");
+            return secure_not_available(&state);
+        }
+        Err(e) => {
+            tracing::error!("Invalid secure message key: {}", e);
+            return secure_not_available(&state);
+        }
+    };
+    let Some(shared) = key
+        .open(message.id, &message.sealed_message)
+        .and_then(|raw| SharedMessage::of_raw(&raw))
+    else {
+        tracing::warn!("Secure message {} could not be opened", message.id);
+        return secure_not_available(&state);
+    };
+
+    // The code is used up here, so only one of concurrent requests with it
+    // opens the message
+    match repository
+        .record_open(message.id, message.code_hash.as_deref())
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return render_secure(
+                &state,
+                StatusCode::UNAUTHORIZED,
+                &passcode_context(
+                    &message,
+                    &token,
+                    None,
+                    Some("The code is wrong or has expired."),
+                ),
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to count open of secure message {}: {}",
+                message.id,
+                e
+            );
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    log_secure_access(&state, &message, SecureMessageEvent::Opened, &visitor).await;
+
+    let fields: Vec<serde_json::Value> = shared
+        .message
+        .header_fields()
+        .into_iter()
+        .filter(|(name, _)| *name != "Subject")
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+        .collect();
+    let context = serde_json::json!({
+        "opened": true,
+        "subject": shared.message.subject,
+        "fields": fields,
+        "body_html": shared.body_html,
+        "expires_at": message.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+    });
+
+    render_secure(&state, StatusCode::OK, &context)
+}
+
+/// Active secure message of a token
+async fn find_secure_message(state: &AppState, token: &str) -> Option<SecureMessage> {
+    match SecureMessageRepository::new(state.db_pool.clone())
+        .find_active(token)
+        .await
+    {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::error!("Failed to look up secure message: {}", e);
+            None
+        }
+    }
+}
+
+/// Context of the passcode form
+fn passcode_context(
+    message: &SecureMessage,
+    token: &str,
+    notice: Option<&str>,
+    error: Option<&str>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "token": token,
+        "sender": message.sender,
+        "emailed_codes": message.uses_emailed_codes(),
+        "recipient": mask_address(&message.recipient),
+        "notice": notice,
+        "error": error,
+        "expires_at": message.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+    })
+}
+
+/// Add an entry to the access log of a secure message; a failure is only
+/// logged
+async fn log_secure_access(
+    state: &AppState,
+    message: &SecureMessage,
+    event: SecureMessageEvent,
+    visitor: &Visitor,
+) {
+    if let Err(e) = SecureMessageRepository::new(state.db_pool.clone())
+        .log_access(
+            message.id,
+            event,
+            visitor.ip_address.as_deref(),
+            visitor.user_agent.as_deref(),
+        )
+        .await
+    {
+        tracing::warn!(
+            "Failed to log {} of secure message {}: {}",
+            event.as_str(),
+            message.id,
+            e
+        );
+    }
+}
+
+/// Page of an unknown, expired, revoked or locked link
+fn secure_not_available(state: &AppState) -> Response {
+    render_secure(
+        state,
+        StatusCode::NOT_FOUND,
+        &serde_json::json!({ "not_found": true }),
+    )
+}
+
+/// Render a secure message page; like shared pages, they are never cached
+/// or sent as a referrer
+fn render_secure(state: &AppState, status: StatusCode, context: &serde_json::Value) -> Response {
+    match state.templates.render("secure_message", context) {
+        Ok(html) => (
+            status,
+            [
+                (header::CONTENT_SECURITY_POLICY, SECURE_CSP),
+                (header::REFERRER_POLICY, "no-referrer"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            Html(html),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Template error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Password form of an invite
+#[derive(Deserialize)]
+pub struct InviteForm {
+    pub password: String,
+    pub confirm_password: String,
+}
+
+/// Invite page: choose the password of a new mailbox
+///
+/// Unknown, expired and used invites all get the same "not available"
+/// page.
+pub async fn invite(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let Some((invite, address)) = find_invite(&state, &token).await else {
+        return invite_not_available(&state);
+    };
+
+    render_invite(
+        &state,
+        StatusCode::OK,
+        &invite_context(&invite, &address, &token, None),
+    )
+}
+
+/// Set the password of an invited user
+pub async fn invite_accept(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Form(form): Form<InviteForm>,
+) -> Response {
+    let Some((invite, address)) = find_invite(&state, &token).await else {
+        return invite_not_available(&state);
+    };
+
+    let problem = if form.password != form.confirm_password {
+        Some("The passwords do not match.".to_string())
+    } else {
+        validate_user_password(&form.password)
+            .err()
+            .map(|reason| format!("The {}.", reason))
+    };
+    if let Some(error) = problem {
+        return render_invite(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &invite_context(&invite, &address, &token, Some(&error)),
+        );
+    }
+
+    let password_hash = match hash_user_password(&form.password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!("Failed to hash password of invite {}: {}", invite.id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match UserInviteRepository::new(state.db_pool.clone())
+        .accept(&invite, &password_hash)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return invite_not_available(&state),
+        Err(e) => {
+            tracing::error!("Failed to accept invite {}: {}", invite.id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    tracing::info!("Invite accepted for {}", address);
+
+    render_invite(
+        &state,
+        StatusCode::OK,
+        &serde_json::json!({ "accepted": true, "address": address }),
+    )
+}
+
+/// Active invite of a token with the address of its user
+async fn find_invite(state: &AppState, token: &str) -> Option<(UserInvite, String)> {
+    match UserInviteRepository::new(state.db_pool.clone())
+        .find_active(token)
+        .await
+    {
+        Ok(invite) => invite,
+        Err(e) => {
+            tracing::error!("Failed to look up invite: {}", e);
+            None
+        }
+    }
+}
+
+/// Context of the password form
+fn invite_context(
+    invite: &UserInvite,
+    address: &str,
+    token: &str,
+    error: Option<&str>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "token": token,
+        "address": address,
+        "min_length": MIN_PASSWORD_LEN,
+        "error": error,
+        "expires_at": invite.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+    })
+}
+
+/// Page of an unknown, expired or used invite
+fn invite_not_available(state: &AppState) -> Response {
+    render_invite(
+        state,
+        StatusCode::NOT_FOUND,
+        &serde_json::json!({ "not_found": true }),
+    )
+}
+
+/// Render an invite page; the token in its URL is never cached or sent as
+/// a referrer
+fn render_invite(state: &AppState, status: StatusCode, context: &serde_json::Value) -> Response {
+    match state.templates.render("invite", context) {
+        Ok(html) => (
+            status,
+            [
+                (header::CONTENT_SECURITY_POLICY, SECURE_CSP),
+                (header::REFERRER_POLICY, "no-referrer"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            Html(html),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Template error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Serve the MTA-STS policy of the domain whose policy host was asked for
+pub async fn mta_sts_policy(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    match MtaStsHost::new(state.db_pool.clone()).policy(host).await {
+        Ok(Some(policy)) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "text/plain"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            policy,
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to look up the MTA-STS policy of {}: {}", host, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Receive a TLS-RPT report posted to the policy host of a domain
+pub async fn tls_report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let domain = match MtaStsHost::new(state.db_pool.clone()).domain(host).await {
+        Ok(Some(domain)) => domain,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to look up the domain of {}: {}", host, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let report = match unpack_posted_tls_report(content_type, &body) {
+        Some(Ok(report)) => report,
+        Some(Err(e)) => {
+            tracing::warn!("Failed to unpack TLS report posted to {}: {}", host, e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        None => return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
+    };
+
+    match ReportIngester::new(state.db_pool.clone())
+        .ingest_tls_report(&domain, &report)
+        .await
+    {
+        Ok(summary) if summary.tls_reports == 0 && summary.skipped > 0 => {
+            StatusCode::BAD_REQUEST.into_response()
+        }
+        Ok(summary) => {
+            tracing::info!(
+                "Stored {} TLS report policies posted for {}",
+                summary.tls_reports,
+                domain.name
+            );
+            StatusCode::OK.into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to store TLS report for {}: {}", domain.name, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Tracked link of campaign mail
+///
+/// Counts the click and redirects to the original link. Links that were not
+/// signed by this server are not redirected.
+pub async fn tracked_link(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let Some(target) = state
+        .click_tracker
+        .as_ref()
+        .and_then(|tracker| tracker.verify(&token))
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if let Err(e) = CampaignRepository::new(state.db_pool.pool().clone())
+        .record_click(target.campaign_id, target.recipient_id)
+        .await
+    {
+        tracing::warn!(
+            "Failed to count click of campaign {}: {}",
+            target.campaign_id,
+            e
+        );
+    }
+
+    Redirect::to(&target.url).into_response()
+}
+
+/// Answer to an ACME HTTP-01 challenge of a tracking domain certificate
+pub async fn acme_challenge(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Response {
+    match TrackingDomainRepository::new(state.db_pool.clone())
+        .find_challenge(&token)
+        .await
+    {
+        Ok(Some(key_authorization)) => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            key_authorization,
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::warn!("Failed to look up ACME challenge: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -5,11 +5,16 @@
 mod handlers;
 mod routes;
 mod templates;
+mod tracking;
 
 use axum::Router;
+use mairust_common::config::TrackingConfig;
+use mairust_core::tracking::{ClickTracker, TrackingCertificates};
 use mairust_storage::db::DatabasePool;
+use mairust_storage::FileStorage;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 /// Static files for the web UI
@@ -29,6 +34,12 @@ pub struct WebConfig {
     /// Enable debug mode
     #[serde(default)]
     pub debug: bool,
+    /// Path that receives TLS-RPT reports on the MTA-STS policy hosts
+    #[serde(default = "default_tls_report_path")]
+    pub tls_report_path: String,
+    /// Tracked links of campaign mail and tracking domains
+    #[serde(default)]
+    pub tracking: TrackingConfig,
 }
 
 fn default_bind() -> String {
@@ -39,12 +50,18 @@ fn default_api_url() -> String {
     "/api/v1".to_string()
 }
 
+fn default_tls_report_path() -> String {
+    "/tlsrpt".to_string()
+}
+
 impl Default for WebConfig {
     fn default() -> Self {
         Self {
             bind: default_bind(),
             api_url: default_api_url(),
             debug: false,
+            tls_report_path: default_tls_report_path(),
+            tracking: TrackingConfig::default(),
         }
     }
 }
@@ -54,15 +71,25 @@ impl Default for WebConfig {
 pub struct AppState {
     pub config: WebConfig,
     pub db_pool: DatabasePool,
+    /// Message file storage of this server
+    pub file_storage: Arc<dyn FileStorage>,
     pub templates: Arc<templates::Templates>,
+    /// Checks tracked links; `None` when click tracking is disabled
+    pub click_tracker: Option<ClickTracker>,
 }
 
 impl AppState {
     /// Create a new app state
-    pub fn new(config: WebConfig, db_pool: DatabasePool) -> Self {
+    pub fn new(
+        config: WebConfig,
+        db_pool: DatabasePool,
+        file_storage: Arc<dyn FileStorage>,
+    ) -> Self {
         Self {
+            click_tracker: ClickTracker::from_config(&config.tracking),
             config,
             db_pool,
+            file_storage,
             templates: Arc::new(templates::Templates::new()),
         }
     }
@@ -74,14 +101,43 @@ pub fn create_router(state: AppState) -> Router {
 }
 
 /// Run the web UI server
-pub async fn run(config: WebConfig, db_pool: DatabasePool) -> anyhow::Result<()> {
-    let state = AppState::new(config.clone(), db_pool);
+pub async fn run(
+    config: WebConfig,
+    db_pool: DatabasePool,
+    file_storage: Arc<dyn FileStorage>,
+) -> anyhow::Result<()> {
+    let state = AppState::new(config.clone(), db_pool.clone(), file_storage);
     let app = create_router(state);
 
+    // Tracking domains are served over HTTPS with their ACME certificates
+    let https = match config.tracking.https_bind {
+        Some(ref bind) if config.tracking.enabled => {
+            let certificates = TrackingCertificates::new();
+            let interval =
+                std::time::Duration::from_secs(config.tracking.acme.check_interval_secs.max(60));
+            let reload = tokio::spawn(certificates.clone().run(db_pool, interval));
+            let listener = tokio::net::TcpListener::bind(bind).await?;
+            tracing::info!("Tracking domains listening on {}", bind);
+            let serve = tokio::spawn(tracking::serve_https(listener, app.clone(), certificates));
+            Some((reload, serve))
+        }
+        _ => None,
+    };
+
     let listener = tokio::net::TcpListener::bind(&config.bind).await?;
     tracing::info!("Web UI listening on {}", config.bind);
 
-    axum::serve(listener, app).await?;
+    // Client addresses go into the access log of secure messages
+    let result = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await;
+    if let Some((reload, serve)) = https {
+        reload.abort();
+        serve.abort();
+    }
+    result?;
 
     Ok(())
 }
@@ -8,6 +8,7 @@ use axum::{
     routing::{get, post},
     Router,
 };
+use mairust_core::mta_sts;
 use tower_http::{
     compression::CompressionLayer,
     cors::{Any, CorsLayer},
@@ -21,6 +22,9 @@ pub fn create_router(state: AppState) -> Router {
         .allow_methods(Any)
         .allow_headers(Any);
 
+    // TLS-RPT reports are posted to the policy hosts at a configured path
+    let tls_report_path = format!("/{}", state.config.tls_report_path.trim_start_matches('/'));
+
     Router::new()
         // Static assets
         .route("/static/*path", get(handlers::static_file))
@@ -33,6 +37,32 @@ pub fn create_router(state: AppState) -> Router {
         .route("/login", get(handlers::login_page))
         .route("/login", post(handlers::login_submit))
         .route("/logout", get(handlers::logout))
+        // Shared messages (no sign-in)
+        .route("/share/:token", get(handlers::shared_message))
+        .route(
+            "/share/:token/attachments/:index",
+            get(handlers::shared_attachment),
+        )
+        // Tracked links of campaign mail (no sign-in)
+        .route("/c/:token", get(handlers::tracked_link))
+        .route(
+            "/.well-known/acme-challenge/:token",
+            get(handlers::acme_challenge),
+        )
+        // Secure messages (passcode instead of sign-in)
+        .route(
+            "/secure/:token",
+            get(handlers::secure_message).post(handlers::secure_message_open),
+        )
+        .route("/secure/:token/code", post(handlers::secure_message_code))
+        // Invites to choose a password (no sign-in)
+        .route(
+            "/invite/:token",
+            get(handlers::invite).post(handlers::invite_accept),
+        )
+        // MTA-STS policies and TLS-RPT reports of hosted domains
+        .route(mta_sts::POLICY_PATH, get(handlers::mta_sts_policy))
+        .route(&tls_report_path, post(handlers::tls_report))
         // Health check
         .route("/health", get(handlers::health))
         // Add middleware
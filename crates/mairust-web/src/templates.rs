@@ -28,6 +28,12 @@ impl Templates {
             .expect("Failed to add settings template");
         env.add_template("login", include_str!("../templates/login.html"))
             .expect("Failed to add login template");
+        env.add_template("shared_message", include_str!("../templates/shared_message.html"))
+            .expect("Failed to add shared_message template");
+        env.add_template("secure_message", include_str!("../templates/secure_message.html"))
+            .expect("Failed to add secure_message template");
+        env.add_template("invite", include_str!("../templates/invite.html"))
+            .expect("Failed to add invite template");
 
         Self { env }
     }
@@ -0,0 +1,45 @@
+//! HTTPS listener of tracking domains
+//!
+//! Serves the web UI routes, and so the tracked links, over TLS with the
+//! certificate of the tracking domain the client asks for.
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use mairust_core::tracking::TrackingCertificates;
+use tokio::net::TcpListener;
+use tracing::{debug, warn};
+
+/// Accept connections until the task is aborted
+pub async fn serve_https(listener: TcpListener, app: Router, certificates: TrackingCertificates) {
+    let acceptor = certificates.acceptor();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept tracking domain connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        // Client addresses go into the access log of secure messages
+        let service = TowerToHyperService::new(app.clone().layer(Extension(ConnectInfo(peer))));
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Tracking domain connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}